@@ -0,0 +1,531 @@
+use std::time::Duration;
+
+use ersha_core::query::{PageToken, ReadingQuery};
+use ersha_core::{DeviceStatus, SensorId, SensorKind, SensorReading};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, header};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+use ulid::Ulid;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client for the ersha-prime HTTP API, authenticated with an API key.
+///
+/// Requests that fail in a way a retry may fix are retried according to the
+/// [`RetryPolicy`].
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+    timeout: Duration,
+    retry: RetryPolicy,
+}
+
+/// Which failed requests are retried, how often, and how long to wait
+/// between attempts. The wait doubles from `initial_backoff` up to
+/// `max_backoff`, unless the server asks for longer with `Retry-After`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 turns retrying off
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Also retry requests that aren't idempotent, such as `POST`s, which
+    /// may then take effect twice
+    pub retry_all_methods: bool,
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after the `attempts`th failure.
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+
+    fn retries(&self, method: &Method) -> bool {
+        self.retry_all_methods || matches!(*method, Method::GET | Method::HEAD)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            retry_all_methods: false,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("{status}: {message}")]
+    Api { status: StatusCode, message: String },
+    #[error("unexpected event: {0}")]
+    Event(#[from] serde_json::Error),
+}
+
+/// Problem details returned by every API endpoint.
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    detail: String,
+}
+
+/// A page of results from a list endpoint.
+#[derive(Debug, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub next_cursor: Option<PageToken>,
+}
+
+/// Current conditions at a device.
+#[derive(Debug, Deserialize)]
+pub struct DeviceSnapshot {
+    /// Newest reading of each sensor, ordered by sensor id
+    pub readings: Vec<SensorReading>,
+    pub status: Option<DeviceStatus>,
+}
+
+/// Hourly or daily rollup of one sensor's readings.
+#[derive(Debug, Deserialize)]
+pub struct Aggregate {
+    pub sensor_id: SensorId,
+    pub metric: SensorKind,
+    pub bucket_start: jiff::Timestamp,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Aggregate {
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>, token: impl Into<String>) -> Result<Self, ClientError> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .build()?;
+
+        Ok(Self::with_http(http, base_url, token))
+    }
+
+    /// A client sending requests through `http`, which sets up whatever every
+    /// request needs, such as proxies, TLS roots or default headers.
+    pub fn with_http(
+        http: reqwest::Client,
+        base_url: impl Into<String>,
+        token: impl Into<String>,
+    ) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+            token: token.into(),
+            timeout: DEFAULT_TIMEOUT,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Give each attempt of a request this long to complete. Streams are
+    /// never timed out once open.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{path}", self.base_url))
+            .bearer_auth(&self.token)
+    }
+
+    /// Send `request`, retrying it as the policy allows.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let request = request.build()?;
+        let retries = if self.retry.retries(request.method()) {
+            self.retry.max_retries
+        } else {
+            0
+        };
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            // Requests with streaming bodies can't be cloned, and are sent once.
+            let Some(attempt) = request.try_clone().filter(|_| attempts <= retries) else {
+                return check(self.http.execute(request).await?).await;
+            };
+
+            let wait = match self.http.execute(attempt).await {
+                Ok(response) if retryable(response.status()) => {
+                    retry_after(&response).unwrap_or_else(|| self.retry.backoff(attempts))
+                }
+                Ok(response) => return check(response).await,
+                Err(e) if e.is_connect() || e.is_timeout() => self.retry.backoff(attempts),
+                Err(e) => return Err(e.into()),
+            };
+            tokio::time::sleep(wait.min(self.retry.max_backoff)).await;
+        }
+    }
+
+    fn timed(&self, request: RequestBuilder) -> RequestBuilder {
+        request.timeout(self.timeout)
+    }
+
+    pub async fn get<Q: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &Q,
+    ) -> Result<T, ClientError> {
+        let request = self.timed(self.request(Method::GET, path).query(query));
+        json(self.send(request).await?).await
+    }
+
+    pub async fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, ClientError> {
+        let request = self.timed(self.request(Method::POST, path).json(body));
+        json(self.send(request).await?).await
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<(), ClientError> {
+        let request = self.timed(self.request(Method::DELETE, path));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Open a server-sent event stream. The response has no timeout.
+    pub async fn stream(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<EventStream, ClientError> {
+        let request = self.request(Method::GET, path).query(query);
+        Ok(EventStream {
+            response: self.send(request).await?,
+            buffer: String::new(),
+        })
+    }
+
+    /// A page of `GET /api/readings`, filtered by `query`.
+    pub async fn readings(&self, query: &ReadingQuery) -> Result<Page<SensorReading>, ClientError> {
+        self.get("/api/readings", query).await
+    }
+
+    /// Every reading matching `query`, following cursors page by page.
+    pub async fn export_readings(
+        &self,
+        query: &ReadingQuery,
+    ) -> Result<Vec<SensorReading>, ClientError> {
+        let mut query = query.clone();
+        let mut readings = Vec::new();
+        loop {
+            let page = self.readings(&query).await?;
+            readings.extend(page.items);
+            match page.next_cursor {
+                Some(cursor) => query.after = Some(cursor),
+                None => return Ok(readings),
+            }
+        }
+    }
+
+    /// Hourly or daily rollups of a device's readings.
+    pub async fn aggregates(
+        &self,
+        device: Ulid,
+        query: &[(&str, String)],
+    ) -> Result<Vec<Aggregate>, ClientError> {
+        self.get(&format!("/api/devices/{device}/aggregates"), query)
+            .await
+    }
+
+    /// The newest reading of each of a device's sensors and its latest status.
+    pub async fn latest(&self, device: Ulid) -> Result<DeviceSnapshot, ClientError> {
+        self.get(&format!("/api/devices/{device}/latest"), &())
+            .await
+    }
+
+    /// Follow readings as they are ingested, filtered by `query`.
+    pub async fn stream_readings(
+        &self,
+        query: &[(&str, String)],
+    ) -> Result<ReadingStream, ClientError> {
+        Ok(ReadingStream {
+            events: self.stream("/api/stream/readings", query).await?,
+        })
+    }
+}
+
+/// Failures a load balancer or a busy server answers with, which may be
+/// gone by the next attempt.
+fn retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// How long the server asked to be left alone, in whole seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let secs = response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Turn an error response into a [`ClientError`].
+async fn check(response: Response) -> Result<Response, ClientError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let text = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<ErrorBody>(&text)
+        .map(|body| body.detail)
+        .unwrap_or(text);
+
+    Err(ClientError::Api { status, message })
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> Result<T, ClientError> {
+    Ok(response.json().await?)
+}
+
+/// A server-sent event.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Events read off a `text/event-stream` response as they arrive.
+pub struct EventStream {
+    response: Response,
+    buffer: String,
+}
+
+impl EventStream {
+    /// The next event, or `None` once the server closes the stream.
+    pub async fn next(&mut self) -> Result<Option<Event>, ClientError> {
+        loop {
+            if let Some(event) = take_event(&mut self.buffer) {
+                return Ok(Some(event));
+            }
+
+            match self.response.chunk().await? {
+                Some(chunk) => self
+                    .buffer
+                    .push_str(&String::from_utf8_lossy(&chunk).replace("\r\n", "\n")),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// What the readings stream delivers.
+#[derive(Debug, PartialEq)]
+pub enum ReadingEvent {
+    Reading(Box<SensorReading>),
+    /// Readings dropped because this client fell behind
+    Lagged(u64),
+}
+
+/// Readings decoded off `GET /api/stream/readings`.
+pub struct ReadingStream {
+    events: EventStream,
+}
+
+impl ReadingStream {
+    /// The next reading or gap, or `None` once the server closes the stream.
+    pub async fn next(&mut self) -> Result<Option<ReadingEvent>, ClientError> {
+        while let Some(event) = self.events.next().await? {
+            if let Some(event) = reading_event(&event)? {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Decode a readings stream event, skipping kinds this client doesn't know.
+fn reading_event(event: &Event) -> Result<Option<ReadingEvent>, ClientError> {
+    let event = match event.event.as_deref() {
+        Some("reading") => ReadingEvent::Reading(serde_json::from_str(&event.data)?),
+        Some("lagged") => ReadingEvent::Lagged(serde_json::from_str(&event.data)?),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(event))
+}
+
+/// Remove the first complete event from `buffer`. Comments such as
+/// keep-alives are skipped.
+fn take_event(buffer: &mut String) -> Option<Event> {
+    loop {
+        let end = buffer.find("\n\n")?;
+        let block: String = buffer.drain(..end + 2).collect();
+
+        let mut event = Event::default();
+        let mut has_data = false;
+        for line in block.lines() {
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => event.event = Some(value.to_owned()),
+                "data" => {
+                    if has_data {
+                        event.data.push('\n');
+                    }
+                    event.data.push_str(value);
+                    has_data = true;
+                }
+                _ => {}
+            }
+        }
+
+        if has_data || event.event.is_some() {
+            return Some(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{Client, ClientError, Event, ReadingEvent, RetryPolicy, reading_event, take_event};
+
+    /// Serve `responses` in turn, one per connection, counting requests.
+    async fn serve(responses: &'static [&'static str]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let served = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 4096];
+                let _ = socket.read(&mut buf).await.unwrap();
+                served.fetch_add(1, Ordering::SeqCst);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    const BAD_GATEWAY: &str =
+        "HTTP/1.1 502 Bad Gateway\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+    const OK: &str = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\nconnection: close\r\n\r\n[]";
+
+    fn quick() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn gets_are_retried_through_transient_failures() {
+        let (url, requests) = serve(&[BAD_GATEWAY, BAD_GATEWAY, OK]).await;
+        let client = Client::new(url, "token").unwrap().with_retry(quick());
+
+        let keys: Vec<u32> = client.get("/api/keys", &()).await.unwrap();
+
+        assert!(keys.is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn posts_are_sent_once_by_default() {
+        let (url, requests) = serve(&[BAD_GATEWAY, OK]).await;
+        let client = Client::new(url, "token").unwrap().with_retry(quick());
+
+        let result: Result<Vec<u32>, _> = client.post("/api/keys", &()).await;
+
+        assert!(matches!(result, Err(ClientError::Api { status, .. }) if status == 502));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn events_are_split_on_blank_lines() {
+        let mut buffer = ": keep-alive\n\nevent: reading\ndata: {\"a\":1}\n\nevent: lag".to_owned();
+
+        assert_eq!(
+            take_event(&mut buffer),
+            Some(Event {
+                event: Some("reading".to_owned()),
+                data: "{\"a\":1}".to_owned(),
+            })
+        );
+        assert_eq!(take_event(&mut buffer), None);
+        assert_eq!(buffer, "event: lag");
+    }
+
+    #[test]
+    fn stream_events_decode_to_readings_and_gaps() {
+        let lagged = Event {
+            event: Some("lagged".to_owned()),
+            data: "12".to_owned(),
+        };
+        assert_eq!(
+            reading_event(&lagged).unwrap(),
+            Some(ReadingEvent::Lagged(12))
+        );
+
+        let unknown = Event {
+            event: Some("hello".to_owned()),
+            data: String::new(),
+        };
+        assert_eq!(reading_event(&unknown).unwrap(), None);
+
+        let garbled = Event {
+            event: Some("reading".to_owned()),
+            data: "{".to_owned(),
+        };
+        assert!(reading_event(&garbled).is_err());
+    }
+}
@@ -0,0 +1,682 @@
+mod client;
+
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::eyre;
+use ersha_core::query::{List, Order, PageToken, ReadingQuery};
+use ersha_core::{
+    Device, DeviceId, DeviceKind, Dispatcher, DispatcherId, Percentage, Sensor, SensorId,
+    SensorKind, SensorMetric, SensorReading,
+};
+use ordered_float::NotNan;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ulid::Ulid;
+
+use crate::client::{Client, Page, ReadingEvent, RetryPolicy};
+
+#[derive(Parser)]
+#[command(name = "ersha-admin")]
+#[command(about = "Administer an ersha-prime instance")]
+struct Cli {
+    /// Base URL of the ersha-prime HTTP API
+    #[arg(long, env = "ERSHA_PRIME_URL", default_value = "http://localhost:8080")]
+    url: String,
+    /// API key to authenticate with
+    #[arg(long, env = "ERSHA_API_KEY", hide_env_values = true)]
+    token: String,
+    /// Times to retry reads that fail with a gateway error or timeout
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+    /// Seconds each request may take
+    #[arg(long, default_value_t = 30)]
+    timeout_secs: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage dispatchers
+    #[command(subcommand)]
+    Dispatchers(DispatcherCommand),
+    /// Manage devices
+    #[command(subcommand)]
+    Devices(DeviceCommand),
+    /// Query stored readings, newest first
+    Readings(ReadingsArgs),
+    /// Print readings as they are ingested until interrupted
+    Tail(TailArgs),
+    /// Show a device's newest reading per sensor and its latest status
+    Latest { device: Ulid },
+    /// Show hourly or daily rollups of a device's readings
+    Aggregates(AggregatesArgs),
+    /// Manage API keys
+    #[command(subcommand)]
+    Keys(KeyCommand),
+    /// Purge expired data
+    #[command(subcommand)]
+    Retention(RetentionCommand),
+}
+
+#[derive(Subcommand)]
+enum DispatcherCommand {
+    List(ListArgs),
+    /// Register a dispatcher and issue its secret
+    Register {
+        /// H3 cell of the dispatcher, in hex
+        #[arg(long, value_parser = parse_cell)]
+        location: u64,
+        /// Dispatcher id; a new one is generated when omitted
+        #[arg(long)]
+        id: Option<Ulid>,
+    },
+    /// Issue a new secret for a known dispatcher, replacing the old one
+    Rotate {
+        id: Ulid,
+    },
+    Suspend {
+        id: Ulid,
+    },
+    Reactivate {
+        id: Ulid,
+    },
+    /// Let in a dispatcher awaiting approval after saying hello unannounced
+    Approve {
+        id: Ulid,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeviceCommand {
+    List(ListArgs),
+    Register {
+        /// H3 cell of the device, in hex
+        #[arg(long, value_parser = parse_cell)]
+        location: u64,
+        /// Device id; a new one is generated when omitted
+        #[arg(long)]
+        id: Option<Ulid>,
+        #[arg(long)]
+        manufacturer: Option<String>,
+        /// Attached sensor; repeat for each one
+        #[arg(long = "sensor", value_enum)]
+        sensors: Vec<SensorArg>,
+    },
+    Suspend {
+        id: Ulid,
+    },
+    Reactivate {
+        id: Ulid,
+    },
+    /// Permanently retire a device
+    Decommission {
+        id: Ulid,
+    },
+}
+
+#[derive(Args)]
+struct ListArgs {
+    /// States to include, e.g. `active,suspended`
+    #[arg(long)]
+    state: Option<String>,
+    #[arg(long, default_value_t = 100)]
+    limit: usize,
+    /// Cursor printed after the previous page
+    #[arg(long)]
+    after: Option<String>,
+}
+
+#[derive(Args)]
+struct ReadingsArgs {
+    #[arg(long)]
+    device: Option<Ulid>,
+    #[arg(long)]
+    dispatcher: Option<Ulid>,
+    /// Metric kinds, e.g. `soil_moisture,air_temp`
+    #[arg(long)]
+    metric: Option<List<SensorKind>>,
+    /// Only readings taken at or after this time
+    #[arg(long)]
+    from: Option<jiff::Timestamp>,
+    /// Only readings taken at or before this time
+    #[arg(long)]
+    to: Option<jiff::Timestamp>,
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+    /// Cursor printed after the previous page
+    #[arg(long)]
+    after: Option<String>,
+    /// Fetch every page rather than one
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Args)]
+struct AggregatesArgs {
+    device: Ulid,
+    /// `hour` or `day`
+    #[arg(long, default_value = "hour")]
+    granularity: String,
+    /// Metric kinds, e.g. `soil_moisture,air_temp`
+    #[arg(long)]
+    metric: Option<String>,
+    /// Only buckets starting at or after this time
+    #[arg(long)]
+    from: Option<jiff::Timestamp>,
+    /// Only buckets starting at or before this time
+    #[arg(long)]
+    to: Option<jiff::Timestamp>,
+}
+
+#[derive(Args)]
+struct TailArgs {
+    #[arg(long)]
+    device: Option<Ulid>,
+    /// Metric kinds, e.g. `soil_moisture,air_temp`
+    #[arg(long)]
+    metric: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum KeyCommand {
+    List,
+    /// Issue a key; its token is printed once
+    Create {
+        name: String,
+        #[arg(long, value_enum, default_value_t = ScopeArg::ReadOnly)]
+        scope: ScopeArg,
+        /// Organization the key acts for
+        #[arg(long)]
+        org: Option<Ulid>,
+    },
+    Revoke {
+        id: Ulid,
+    },
+}
+
+#[derive(Subcommand)]
+enum RetentionCommand {
+    /// Run a retention sweep now
+    Run {
+        /// Only count what would be purged
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SensorArg {
+    SoilMoisture,
+    SoilTemp,
+    AirTemp,
+    Humidity,
+    Rainfall,
+}
+
+#[derive(Clone, Copy, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ScopeArg {
+    ReadOnly,
+    Admin,
+    Dispatcher,
+}
+
+#[derive(Debug, Deserialize)]
+struct DispatcherSecret {
+    dispatcher_id: Ulid,
+    secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiKeyInfo {
+    id: Ulid,
+    name: String,
+    scope: String,
+    org_id: Option<Ulid>,
+    created_at: jiff::Timestamp,
+    revoked_at: Option<jiff::Timestamp>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreatedApiKey {
+    #[serde(flatten)]
+    key: ApiKeyInfo,
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SweepReport {
+    readings: usize,
+    statuses: usize,
+}
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let cli = Cli::parse();
+    let client = Client::new(cli.url, cli.token)?
+        .with_timeout(Duration::from_secs(cli.timeout_secs))
+        .with_retry(RetryPolicy {
+            max_retries: cli.retries,
+            ..Default::default()
+        });
+
+    match cli.command {
+        Command::Dispatchers(command) => dispatchers(&client, command).await,
+        Command::Devices(command) => devices(&client, command).await,
+        Command::Readings(args) => readings(&client, args).await,
+        Command::Tail(args) => tail(&client, args).await,
+        Command::Latest { device } => latest(&client, device).await,
+        Command::Aggregates(args) => aggregates(&client, args).await,
+        Command::Keys(command) => keys(&client, command).await,
+        Command::Retention(RetentionCommand::Run { dry_run }) => {
+            let report: SweepReport = client
+                .post(&format!("/api/retention/run?dry_run={dry_run}"), &json!({}))
+                .await?;
+            let verb = if dry_run { "would purge" } else { "purged" };
+            println!(
+                "{verb} {} readings and {} statuses",
+                report.readings, report.statuses
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn dispatchers(client: &Client, command: DispatcherCommand) -> color_eyre::Result<()> {
+    match command {
+        DispatcherCommand::List(args) => {
+            let page: Page<Dispatcher> = client.get("/api/dispatchers", &args.query()).await?;
+            println!(
+                "{:<26}  {:<9}  {:<15}  PROVISIONED",
+                "ID", "STATE", "LOCATION"
+            );
+            for dispatcher in &page.items {
+                println!(
+                    "{:<26}  {:<9}  {:<15x}  {}",
+                    dispatcher.id.0,
+                    format!("{:?}", dispatcher.state),
+                    dispatcher.location.0,
+                    time(dispatcher.provisioned_at),
+                );
+            }
+            print_footer(&page);
+        }
+        DispatcherCommand::Register { location, id } => {
+            let id = id.unwrap_or_else(Ulid::new);
+            print_secret(
+                client
+                    .post(
+                        &format!("/api/dispatchers/{id}/secret"),
+                        &json!({ "location": location }),
+                    )
+                    .await?,
+            );
+        }
+        DispatcherCommand::Rotate { id } => {
+            print_secret(
+                client
+                    .post(&format!("/api/dispatchers/{id}/secret"), &json!({}))
+                    .await?,
+            );
+        }
+        DispatcherCommand::Suspend { id } => {
+            let dispatcher: Dispatcher = client
+                .post(&format!("/api/dispatchers/{id}/suspend"), &json!({}))
+                .await?;
+            println!("{} is now {:?}", dispatcher.id.0, dispatcher.state);
+        }
+        DispatcherCommand::Reactivate { id } => {
+            let dispatcher: Dispatcher = client
+                .post(&format!("/api/dispatchers/{id}/reactivate"), &json!({}))
+                .await?;
+            println!("{} is now {:?}", dispatcher.id.0, dispatcher.state);
+        }
+        DispatcherCommand::Approve { id } => {
+            let dispatcher: Dispatcher = client
+                .post(&format!("/api/dispatchers/{id}/approve"), &json!({}))
+                .await?;
+            println!("{} is now {:?}", dispatcher.id.0, dispatcher.state);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_secret(secret: DispatcherSecret) {
+    println!("dispatcher: {}", secret.dispatcher_id);
+    println!("secret:     {}", secret.secret);
+    println!("Store the secret in the dispatcher's configuration, it will not be shown again.");
+}
+
+async fn devices(client: &Client, command: DeviceCommand) -> color_eyre::Result<()> {
+    let transition = |id: Ulid, action: &'static str| async move {
+        let device: Device = client
+            .post(&format!("/api/devices/{id}/{action}"), &json!({}))
+            .await?;
+        println!("{} is now {:?}", device.id.0, device.state);
+        Ok::<_, color_eyre::Report>(())
+    };
+
+    match command {
+        DeviceCommand::List(args) => {
+            let page: Page<Device> = client.get("/api/devices", &args.query()).await?;
+            println!(
+                "{:<26}  {:<14}  {:<15}  {:<7}  MANUFACTURER",
+                "ID", "STATE", "LOCATION", "SENSORS"
+            );
+            for device in &page.items {
+                println!(
+                    "{:<26}  {:<14}  {:<15x}  {:<7}  {}",
+                    device.id.0,
+                    format!("{:?}", device.state),
+                    device.location.0,
+                    device.sensors.len(),
+                    device.manufacturer.as_deref().unwrap_or("-"),
+                );
+            }
+            print_footer(&page);
+        }
+        DeviceCommand::Register {
+            location,
+            id,
+            manufacturer,
+            sensors,
+        } => {
+            let sensors: Vec<Sensor> = sensors.into_iter().map(sensor).collect();
+            let device: Device = client
+                .post(
+                    "/api/devices",
+                    &json!({
+                        "id": id,
+                        "kind": DeviceKind::Sensor,
+                        "location": location,
+                        "manufacturer": manufacturer,
+                        "sensors": sensors,
+                    }),
+                )
+                .await?;
+            println!("registered device {}", device.id.0);
+            for sensor in device.sensors.iter() {
+                println!("  sensor {} ({:?})", sensor.id.0, sensor.kind);
+            }
+        }
+        DeviceCommand::Suspend { id } => transition(id, "suspend").await?,
+        DeviceCommand::Reactivate { id } => transition(id, "reactivate").await?,
+        DeviceCommand::Decommission { id } => transition(id, "decommission").await?,
+    }
+
+    Ok(())
+}
+
+async fn readings(client: &Client, args: ReadingsArgs) -> color_eyre::Result<()> {
+    let query = ReadingQuery {
+        device_id: args.device.map(DeviceId).into_iter().collect(),
+        dispatcher_id: args.dispatcher.map(DispatcherId).into_iter().collect(),
+        metric: args.metric.unwrap_or_default(),
+        from: args.from,
+        to: args.to,
+        order: Order::Desc,
+        after: args.after.map(PageToken),
+        limit: Some(args.limit),
+        ..Default::default()
+    };
+
+    if args.all {
+        let readings = client.export_readings(&query).await?;
+        print_reading_header();
+        for reading in &readings {
+            print_reading(reading);
+        }
+        println!("{} readings", readings.len());
+        return Ok(());
+    }
+
+    let page = client.readings(&query).await?;
+    print_reading_header();
+    for reading in &page.items {
+        print_reading(reading);
+    }
+    print_footer(&page);
+
+    Ok(())
+}
+
+async fn latest(client: &Client, device: Ulid) -> color_eyre::Result<()> {
+    let snapshot = client.latest(device).await?;
+
+    print_reading_header();
+    for reading in &snapshot.readings {
+        print_reading(reading);
+    }
+    match snapshot.status {
+        Some(status) => println!(
+            "status at {}: battery {}%, signal {} dBm, up {}s",
+            time(status.timestamp),
+            status.battery_percent.0,
+            status.signal_rssi,
+            status.uptime_seconds,
+        ),
+        None => println!("no status reported yet"),
+    }
+
+    Ok(())
+}
+
+async fn aggregates(client: &Client, args: AggregatesArgs) -> color_eyre::Result<()> {
+    let mut query = vec![("granularity", args.granularity)];
+    push(&mut query, "metric", args.metric);
+    push(&mut query, "from", args.from);
+    push(&mut query, "to", args.to);
+
+    let aggregates = client.aggregates(args.device, &query).await?;
+    println!(
+        "{:<20}  {:<26}  {:<14}  {:>6}  {:>10}  {:>10}  {:>10}",
+        "BUCKET", "SENSOR", "METRIC", "COUNT", "MIN", "MEAN", "MAX"
+    );
+    for aggregate in &aggregates {
+        println!(
+            "{:<20}  {:<26}  {:<14}  {:>6}  {:>10.2}  {:>10.2}  {:>10.2}",
+            time(aggregate.bucket_start),
+            aggregate.sensor_id.0,
+            format!("{:?}", aggregate.metric),
+            aggregate.count,
+            aggregate.min,
+            aggregate.mean(),
+            aggregate.max,
+        );
+    }
+
+    Ok(())
+}
+
+async fn tail(client: &Client, args: TailArgs) -> color_eyre::Result<()> {
+    let mut query = Vec::new();
+    push(&mut query, "device_id", args.device);
+    push(&mut query, "metric", args.metric);
+
+    let mut readings = client.stream_readings(&query).await?;
+    eprintln!("Waiting for readings, press Ctrl+C to stop");
+
+    while let Some(event) = readings.next().await? {
+        match event {
+            ReadingEvent::Reading(reading) => print_reading(&reading),
+            ReadingEvent::Lagged(missed) => eprintln!("missed {missed} readings"),
+        }
+    }
+
+    Err(eyre!("ersha-prime closed the stream"))
+}
+
+async fn keys(client: &Client, command: KeyCommand) -> color_eyre::Result<()> {
+    match command {
+        KeyCommand::List => {
+            let keys: Vec<ApiKeyInfo> = client.get("/api/keys", &()).await?;
+            println!(
+                "{:<26}  {:<10}  {:<26}  {:<20}  NAME",
+                "ID", "SCOPE", "ORG", "CREATED"
+            );
+            for key in keys {
+                let name = match key.revoked_at {
+                    Some(at) => format!("{} (revoked {})", key.name, time(at)),
+                    None => key.name,
+                };
+                println!(
+                    "{:<26}  {:<10}  {:<26}  {:<20}  {name}",
+                    key.id,
+                    key.scope,
+                    key.org_id.map_or("-".to_owned(), |org| org.to_string()),
+                    time(key.created_at),
+                );
+            }
+        }
+        KeyCommand::Create { name, scope, org } => {
+            let created: CreatedApiKey = client
+                .post(
+                    "/api/keys",
+                    &json!({ "name": name, "scope": scope, "org_id": org }),
+                )
+                .await?;
+            println!("key:   {} ({})", created.key.id, created.key.scope);
+            println!("token: {}", created.token);
+            println!("Store the token now, it will not be shown again.");
+        }
+        KeyCommand::Revoke { id } => {
+            client.delete(&format!("/api/keys/{id}")).await?;
+            println!("revoked {id}");
+        }
+    }
+
+    Ok(())
+}
+
+impl ListArgs {
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![("limit", self.limit.to_string())];
+        push(&mut query, "state", self.state.clone());
+        push(&mut query, "after", self.after.clone());
+        query
+    }
+}
+
+fn push<T: ToString>(
+    query: &mut Vec<(&'static str, String)>,
+    name: &'static str,
+    value: Option<T>,
+) {
+    if let Some(value) = value {
+        query.push((name, value.to_string()));
+    }
+}
+
+fn print_footer<T>(page: &Page<T>) {
+    println!("{} of {}", page.items.len(), page.total);
+    if let Some(cursor) = &page.next_cursor {
+        println!("more with --after {cursor}");
+    }
+}
+
+fn print_reading_header() {
+    println!(
+        "{:<20}  {:<26}  {:<22}  CONFIDENCE",
+        "TIMESTAMP", "DEVICE", "METRIC"
+    );
+}
+
+fn print_reading(reading: &SensorReading) {
+    println!(
+        "{:<20}  {:<26}  {:<22}  {}%",
+        time(reading.timestamp),
+        reading.device_id.0,
+        metric(&reading.metric),
+        reading.confidence.0,
+    );
+}
+
+/// A timestamp to the second, which is all a table needs.
+fn time(timestamp: jiff::Timestamp) -> String {
+    timestamp
+        .round(jiff::Unit::Second)
+        .unwrap_or(timestamp)
+        .to_string()
+}
+
+fn metric(metric: &SensorMetric) -> String {
+    match metric {
+        SensorMetric::SoilMoisture { value } => format!("soil_moisture {}%", value.0),
+        SensorMetric::SoilTemp { value } => format!("soil_temp {value}°C"),
+        SensorMetric::AirTemp { value } => format!("air_temp {value}°C"),
+        SensorMetric::Humidity { value } => format!("humidity {}%", value.0),
+        SensorMetric::Rainfall { value } => format!("rainfall {value}mm"),
+    }
+}
+
+/// A new sensor of the given kind. Its metric holds a zero value until the
+/// first reading arrives.
+fn sensor(kind: SensorArg) -> Sensor {
+    let zero = NotNan::default();
+    let (kind, metric) = match kind {
+        SensorArg::SoilMoisture => (
+            SensorKind::SoilMoisture,
+            SensorMetric::SoilMoisture {
+                value: Percentage(0),
+            },
+        ),
+        SensorArg::SoilTemp => (SensorKind::SoilTemp, SensorMetric::SoilTemp { value: zero }),
+        SensorArg::AirTemp => (SensorKind::AirTemp, SensorMetric::AirTemp { value: zero }),
+        SensorArg::Humidity => (
+            SensorKind::Humidity,
+            SensorMetric::Humidity {
+                value: Percentage(0),
+            },
+        ),
+        SensorArg::Rainfall => (SensorKind::Rainfall, SensorMetric::Rainfall { value: zero }),
+    };
+
+    Sensor {
+        id: SensorId(Ulid::new()),
+        metric,
+        kind,
+    }
+}
+
+fn parse_cell(s: &str) -> Result<u64, String> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("'{s}' is not an H3 cell in hex"))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::{Cli, SensorArg, parse_cell, sensor};
+
+    #[test]
+    fn cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn sensors_match_their_metric() {
+        for kind in [
+            SensorArg::SoilMoisture,
+            SensorArg::SoilTemp,
+            SensorArg::AirTemp,
+            SensorArg::Humidity,
+            SensorArg::Rainfall,
+        ] {
+            let sensor = sensor(kind);
+            assert_eq!(sensor.metric.kind(), sensor.kind);
+        }
+    }
+
+    #[test]
+    fn cells_are_hex() {
+        assert_eq!(parse_cell("8a2a1072b59ffff"), Ok(0x8a2a1072b59ffff));
+        assert_eq!(parse_cell("0x8a2a1072b59ffff"), Ok(0x8a2a1072b59ffff));
+        assert!(parse_cell("Addis Ababa").is_err());
+    }
+}
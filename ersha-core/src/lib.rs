@@ -1,3 +1,5 @@
+pub mod query;
+
 use ordered_float::NotNan;
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
@@ -10,38 +12,78 @@ type BoxList<T> = Box<[T]>;
 
 /// Unique identifier for an edge device.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct DeviceId(pub Ulid);
 
 /// Unique identifier for a telemetry reading event.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ReadingId(pub Ulid);
 
 /// Unique identifier for a device status report event.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct StatusId(pub Ulid);
 
 /// Unique identifier for a dispatcher device.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct DispatcherId(pub Ulid);
 
+/// Unique identifier for a command sent to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CommandId(pub Ulid);
+
 /// Unique identifier for an upload batch.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct BatchId(pub Ulid);
 
 /// Unique identifier for a sensor
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct SensorId(pub Ulid);
 
 /// H3 cell index (hex-like 64-bit integer) representing a spatial cell.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct H3Cell(pub u64);
 
+impl H3Cell {
+    const RESOLUTION_OFFSET: u32 = 52;
+    const RESOLUTION_MASK: u64 = 0xf << Self::RESOLUTION_OFFSET;
+    const DIGIT_BITS: u32 = 3;
+    const MAX_RESOLUTION: u8 = 15;
+
+    /// Resolution of the cell, from 0 (coarsest) to 15.
+    pub fn resolution(&self) -> u8 {
+        ((self.0 & Self::RESOLUTION_MASK) >> Self::RESOLUTION_OFFSET) as u8
+    }
+
+    /// Whether this cell is `parent` or one of its descendants.
+    pub fn is_within(&self, parent: H3Cell) -> bool {
+        let resolution = parent.resolution();
+        if self.resolution() < resolution {
+            return false;
+        }
+
+        // Compare everything above the digits finer than the parent's resolution.
+        let shift = u32::from(Self::MAX_RESOLUTION - resolution) * Self::DIGIT_BITS;
+        let prefix = |cell: H3Cell| (cell.0 & !Self::RESOLUTION_MASK) >> shift;
+
+        prefix(*self) == prefix(parent)
+    }
+}
+
 /// Percentage value in the range 0–100 (inclusive).
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Percentage(pub u8);
 
 /// A registered edge device in the platform.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Device {
     /// Stable identity of this device.
     pub id: DeviceId,
@@ -52,14 +94,17 @@ pub struct Device {
     /// Canonical location cell for the device.
     pub location: H3Cell,
     /// Manufacturer or vendor string.
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
     pub manufacturer: Option<BoxStr>,
     /// Provisioning timestamp.
     pub provisioned_at: jiff::Timestamp,
     /// Sensors attached to this device.
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<Sensor>))]
     pub sensors: BoxList<Sensor>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Sensor {
     pub id: SensorId,
     pub metric: SensorMetric,
@@ -67,6 +112,7 @@ pub struct Sensor {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct SensorStatus {
     pub sensor_id: SensorId,
     pub state: SensorState,
@@ -74,13 +120,15 @@ pub struct SensorStatus {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum SensorState {
     Active,
     Faulty,
     Inactive,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum SensorKind {
     SoilMoisture,
     SoilTemp,
@@ -92,21 +140,58 @@ pub enum SensorKind {
 /// Device classification.
 /// Actuators can be added later.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum DeviceKind {
     Sensor,
 }
 
 /// Device state.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum DeviceState {
     /// Device is permitted to upload telemetry.
     Active,
-    /// Device is blocked (e.g., compromised, decommissioned, etc.).
+    /// Device is temporarily blocked (e.g., compromised, under maintenance).
     Suspended,
+    /// Device is permanently retired; its data is no longer accepted.
+    Decommissioned,
+}
+
+/// Whether a reading passed the validation rules covering it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum QualityStatus {
+    #[default]
+    Good,
+    /// Below the rule's minimum or above its maximum
+    OutOfRange,
+    /// Changed faster than the rule allows since the sensor's previous reading
+    RateExceeded,
+}
+
+impl QualityStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QualityStatus::Good => "good",
+            QualityStatus::OutOfRange => "out_of_range",
+            QualityStatus::RateExceeded => "rate_exceeded",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "good" => Some(QualityStatus::Good),
+            "out_of_range" => Some(QualityStatus::OutOfRange),
+            "rate_exceeded" => Some(QualityStatus::RateExceeded),
+            _ => None,
+        }
+    }
 }
 
 /// A single sensor reading emitted by an edge device and forwarded by a dispatcher.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct SensorReading {
     /// Unique id for this reading.
     pub id: ReadingId,
@@ -128,17 +213,40 @@ pub struct SensorReading {
 
 /// Supported sensor metrics.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum SensorMetric {
     /// Soil moisture as a percentage.
     SoilMoisture { value: Percentage },
     /// Soil temperature in degrees Celsius.
-    SoilTemp { value: NotNan<f64> },
+    SoilTemp {
+        #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+        value: NotNan<f64>,
+    },
     /// Air temperature in degrees Celsius.
-    AirTemp { value: NotNan<f64> },
+    AirTemp {
+        #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+        value: NotNan<f64>,
+    },
     /// Relative humidity as a percentage.
     Humidity { value: Percentage },
     /// Rainfall in millimeters.
-    Rainfall { value: NotNan<f64> },
+    Rainfall {
+        #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+        value: NotNan<f64>,
+    },
+}
+
+impl SensorMetric {
+    /// Kind of sensor that produces this metric.
+    pub fn kind(&self) -> SensorKind {
+        match self {
+            SensorMetric::SoilMoisture { .. } => SensorKind::SoilMoisture,
+            SensorMetric::SoilTemp { .. } => SensorKind::SoilTemp,
+            SensorMetric::AirTemp { .. } => SensorKind::AirTemp,
+            SensorMetric::Humidity { .. } => SensorKind::Humidity,
+            SensorMetric::Rainfall { .. } => SensorKind::Rainfall,
+        }
+    }
 }
 
 /// Units used by metrics.
@@ -154,6 +262,7 @@ pub enum MetricUnit {
 
 /// A status report emitted by a device.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct DeviceStatus {
     /// Unique id for this status record.
     pub id: StatusId,
@@ -168,24 +277,29 @@ pub struct DeviceStatus {
     /// Received signal strength indicator (RSSI).
     pub signal_rssi: i16,
     /// Any errors reported by device firmware.
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<DeviceError>))]
     pub errors: BoxList<DeviceError>,
     /// Timestamp when status was captured.
     pub timestamp: jiff::Timestamp,
     /// The status of each sensor attached to this device
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<SensorStatus>))]
     pub sensor_statuses: BoxList<SensorStatus>,
 }
 
 /// A structured error from a device.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct DeviceError {
     /// Canonical error category.
     pub code: DeviceErrorCode,
     /// Optional human-readable message from firmware.
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
     pub message: Option<BoxStr>,
 }
 
 /// Device error codes.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum DeviceErrorCode {
     LowBattery,
     SensorFault,
@@ -195,6 +309,7 @@ pub enum DeviceErrorCode {
 
 /// A registered dispatcher in the platform.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Dispatcher {
     /// Stable identity of this dispatcher.
     pub id: DispatcherId,
@@ -208,30 +323,252 @@ pub struct Dispatcher {
 
 /// Dispatcher State
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum DispatcherState {
     /// Dispatcher is permitted to upload data.
     Active,
     /// Dispatcher is blocked (e.g., compromised, decommissioned).
     Suspended,
+    /// Dispatcher said hello unannounced and awaits an operator's approval
+    /// before it may upload data.
+    Pending,
+}
+
+/// Periodic health report sent by a dispatcher.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DispatcherStatus {
+    /// Dispatcher that produced this report.
+    pub dispatcher_id: DispatcherId,
+    /// Readings buffered locally and not yet uploaded.
+    pub pending_readings: u64,
+    /// Device statuses buffered locally and not yet uploaded.
+    pub pending_statuses: u64,
+    /// Quality of the uplink to central.
+    pub link: LinkQuality,
+    /// Dispatcher uptime (seconds since start).
+    pub uptime_seconds: u64,
+    /// Timestamp when the report was produced.
+    pub timestamp: jiff::Timestamp,
+}
+
+/// Uplink quality as observed by a dispatcher.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LinkQuality {
+    /// Round-trip time of the last exchange with central, in milliseconds.
+    pub rtt_ms: Option<u32>,
+    /// Uploads that failed since the previous report.
+    pub failed_uploads: u32,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DispatcherStatusResponse {
+    /// The report was recorded.
+    Accepted,
+    /// The report was not recorded.
+    Rejected { reason: BatchRejectionReason },
+}
+
+/// A device its dispatcher stopped hearing from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeviceDisconnection {
+    pub device_id: DeviceId,
+    /// When the dispatcher last received a reading or status from it.
+    pub last_seen: jiff::Timestamp,
+}
+
+/// Sent by a dispatcher when devices stop reporting, and when devices it
+/// reported disconnected report again.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DeviceDisconnectionRequest {
+    /// Dispatcher the devices report through.
+    pub dispatcher_id: DispatcherId,
+    /// Devices not heard from for too long.
+    pub disconnected: BoxList<DeviceDisconnection>,
+    /// Devices heard from again since being reported disconnected.
+    pub reconnected: BoxList<DeviceId>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DeviceDisconnectionResponse {
+    /// The devices were marked disconnected or reconnected.
+    Accepted,
+    /// Nothing was recorded.
+    Rejected { reason: BatchRejectionReason },
+}
+
+/// An instruction for a device, relayed by a dispatcher.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeviceCommand {
+    /// Stable identity of this command, echoed back when acknowledging it.
+    pub id: CommandId,
+    /// Device the command is meant for.
+    pub device_id: DeviceId,
+    /// What the device should do.
+    pub kind: CommandKind,
+    /// The command must not be applied after this time.
+    pub expires_at: jiff::Timestamp,
+}
+
+/// Actuator and configuration commands understood by devices.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum CommandKind {
+    /// Switch an actuator output, e.g. an irrigation valve, on or off.
+    Actuate { channel: u8, on: bool },
+    /// Change how often the device samples and reports.
+    SetReportInterval { seconds: u32 },
+    /// Set a firmware configuration value.
+    Configure {
+        #[cfg_attr(feature = "openapi", schema(value_type = String))]
+        key: BoxStr,
+        #[cfg_attr(feature = "openapi", schema(value_type = String))]
+        value: BoxStr,
+    },
+    /// Restart the device.
+    Reboot,
+    /// Install a firmware image, downloaded from `url` and checked against
+    /// its SHA-256 digest before flashing.
+    UpdateFirmware {
+        #[cfg_attr(feature = "openapi", schema(value_type = String))]
+        version: BoxStr,
+        #[cfg_attr(feature = "openapi", schema(value_type = String))]
+        url: BoxStr,
+        /// Hex encoded SHA-256 of the image.
+        #[cfg_attr(feature = "openapi", schema(value_type = String))]
+        sha256: BoxStr,
+    },
+}
+
+/// Sent by a dispatcher to fetch commands for its devices.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CommandPoll {
+    /// Dispatcher asking for commands.
+    pub dispatcher_id: DispatcherId,
+    /// Previously received commands that have been passed on to their devices.
+    pub acks: BoxList<CommandId>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum CommandPollResponse {
+    /// Commands now delivered to the dispatcher, oldest first.
+    Accepted { commands: BoxList<DeviceCommand> },
+    /// Nothing was delivered or acknowledged.
+    Rejected { reason: BatchRejectionReason },
+}
+
+/// Pushed by prime to hand a connected dispatcher commands without waiting
+/// for its next poll.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CommandDispatchRequest {
+    /// Commands now delivered to the dispatcher, oldest first.
+    pub commands: BoxList<DeviceCommand>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CommandDispatchResponse {
+    /// Commands passed on to their devices. The others are acknowledged by a
+    /// later poll, or expire.
+    pub acks: BoxList<CommandId>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct BatchUploadRequest {
-    /// Unique id for this batch.
+    /// Unique id for this batch, kept when it is sent again so the retry
+    /// is answered with the first response rather than processed twice.
     pub id: BatchId,
     /// Dispatcher that created and is uploading this batch.
     pub dispatcher_id: DispatcherId,
     /// Telemetry readings included in this batch.
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<SensorReading>))]
     pub readings: BoxList<SensorReading>,
     /// Device status records included in this batch.
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<DeviceStatus>))]
     pub statuses: BoxList<DeviceStatus>,
     /// Timestamp when the batch was created by dispatcher.
     pub timestamp: jiff::Timestamp,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct BatchUploadResponse {
-    pub id: BatchId,
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum BatchUploadResponse {
+    /// The batch was processed; each item reports its own outcome.
+    Accepted {
+        id: BatchId,
+        readings: BoxList<ItemResult<ReadingId>>,
+        statuses: BoxList<ItemResult<StatusId>>,
+    },
+    /// Nothing in the batch was stored.
+    Rejected {
+        id: BatchId,
+        reason: BatchRejectionReason,
+    },
+}
+
+impl BatchUploadResponse {
+    pub fn id(&self) -> BatchId {
+        match self {
+            BatchUploadResponse::Accepted { id, .. } | BatchUploadResponse::Rejected { id, .. } => {
+                *id
+            }
+        }
+    }
+}
+
+/// Reason central refused a whole batch or status report.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum BatchRejectionReason {
+    /// Dispatcher has not said hello or was never registered.
+    UnknownDispatcher,
+    /// Dispatcher has been suspended by an operator.
+    Suspended,
+    /// Central could not store the batch; it may be retried.
+    Unavailable,
+    /// Dispatcher has not been approved by an operator yet.
+    PendingApproval,
+}
+
+/// Outcome of a single reading or status within a batch.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ItemResult<I> {
+    pub id: I,
+    pub outcome: ItemOutcome,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ItemOutcome {
+    /// The item was stored.
+    Stored,
+    /// An item with the same id was already stored; it was not overwritten.
+    Duplicate,
+    /// The item failed validation and will never be accepted.
+    Invalid(InvalidItemReason),
+}
+
+/// Why an item failed validation.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum InvalidItemReason {
+    /// The item names a different dispatcher than the batch.
+    DispatcherMismatch,
+    /// A percentage field is above 100.
+    PercentageOutOfRange,
+    /// The item is timestamped too far in the future.
+    FutureTimestamp,
+    /// The item is timestamped before prime accepts items from, as devices
+    /// whose clock was never set report.
+    StaleTimestamp,
+    /// The item comes from a decommissioned device.
+    DeviceDecommissioned,
+    /// The device is not assigned to the uploading dispatcher.
+    DeviceNotAssigned,
+    /// The device is not registered with prime.
+    UnknownDevice,
+    /// The reading names a sensor the device does not have.
+    UnknownSensor,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -240,9 +577,88 @@ pub struct HelloRequest {
     pub dispatcher_id: DispatcherId,
     /// Dispatcher location cell.
     pub location: H3Cell,
+    /// Proof that the dispatcher holds its shared secret.
+    pub credentials: Option<HelloCredentials>,
+    /// Chunk size, in bytes, the dispatcher would stream large batches in;
+    /// `None` if it only uploads whole batches.
+    pub max_chunk_bytes: Option<u32>,
+    /// Frame compression the dispatcher supports, most preferred first.
+    pub compression: BoxList<Compression>,
+    /// Whether the dispatcher handles requests prime pushes to it, such as
+    /// [`CommandDispatchRequest`]s.
+    pub accepts_push: bool,
+}
+
+/// Signature over a hello, keyed by the dispatcher's shared secret.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HelloCredentials {
+    /// When the hello was signed; stale signatures are rejected.
+    pub timestamp: jiff::Timestamp,
+    /// Random value making each signature unique.
+    pub nonce: u64,
+    /// HMAC-SHA256 over the dispatcher id, location, timestamp and nonce.
+    pub mac: [u8; 32],
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub struct HelloResponse {
-    pub dispatcher_id: DispatcherId,
+pub enum HelloResponse {
+    /// Dispatcher is provisioned and may upload data.
+    Accepted {
+        dispatcher_id: DispatcherId,
+        /// Proof that central knows the dispatcher's secret, present when
+        /// the hello carried credentials.
+        proof: Option<[u8; 32]>,
+        /// How batches may be streamed in chunks, present when the hello
+        /// asked to and central supports it.
+        chunking: Option<ChunkLimits>,
+        /// Compression both ends apply to frames from here on, if any.
+        compression: Option<Compression>,
+    },
+    /// Dispatcher is not permitted to upload data.
+    Rejected {
+        dispatcher_id: DispatcherId,
+        reason: HelloRejectionReason,
+    },
+}
+
+impl HelloResponse {
+    pub fn dispatcher_id(&self) -> DispatcherId {
+        match self {
+            HelloResponse::Accepted { dispatcher_id, .. }
+            | HelloResponse::Rejected { dispatcher_id, .. } => *dispatcher_id,
+        }
+    }
+}
+
+/// Chunked batch upload terms agreed in the hello.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChunkLimits {
+    /// Largest chunk of an encoded batch carried by one message.
+    pub chunk_bytes: u32,
+    /// Largest encoded batch central reassembles.
+    pub max_batch_bytes: u64,
+}
+
+/// Compression applied to RPC frames.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Compression {
+    Lz4,
+    Zstd,
+}
+
+/// Reason a dispatcher's hello was rejected by central.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum HelloRejectionReason {
+    /// Dispatcher has been suspended by an operator.
+    Suspended,
+    /// Central could not verify the dispatcher; the hello may be retried.
+    Unavailable,
+    /// Credentials were missing, stale or did not match.
+    Unauthorized,
+    /// The client certificate was not issued to the dispatcher saying hello,
+    /// or names a dispatcher central does not know.
+    CertificateMismatch,
+    /// Central recorded the dispatcher, but an operator has to approve it
+    /// before it may upload data. The hello may be retried.
+    PendingApproval,
 }
@@ -0,0 +1,357 @@
+//! List queries of the ersha-prime HTTP API, shared by the server and its
+//! clients.
+//!
+//! Each query serializes to, and deserializes from, the query string of its
+//! endpoint: lists are comma separated, timestamps are RFC 3339, and H3
+//! cells are in hex. Clients build them with typed values and the server
+//! receives them already parsed.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use ulid::Ulid;
+
+use crate::{DeviceId, DispatcherId, H3Cell, QualityStatus, SensorId, SensorKind};
+
+/// A value that can appear in a comma separated list parameter.
+pub trait QueryValue: Sized {
+    fn parse(s: &str) -> Option<Self>;
+    fn render(&self) -> String;
+}
+
+macro_rules! ulid_query_value {
+    ($($id:ident),*) => {
+        $(
+            impl QueryValue for $id {
+                fn parse(s: &str) -> Option<Self> {
+                    s.parse::<Ulid>().ok().map($id)
+                }
+
+                fn render(&self) -> String {
+                    self.0.to_string()
+                }
+            }
+        )*
+    };
+}
+
+ulid_query_value!(DeviceId, DispatcherId, SensorId);
+
+impl QueryValue for SensorKind {
+    fn parse(s: &str) -> Option<Self> {
+        let kind = match s {
+            "soil_moisture" => SensorKind::SoilMoisture,
+            "soil_temp" => SensorKind::SoilTemp,
+            "air_temp" => SensorKind::AirTemp,
+            "humidity" => SensorKind::Humidity,
+            "rainfall" => SensorKind::Rainfall,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+
+    fn render(&self) -> String {
+        match self {
+            SensorKind::SoilMoisture => "soil_moisture",
+            SensorKind::SoilTemp => "soil_temp",
+            SensorKind::AirTemp => "air_temp",
+            SensorKind::Humidity => "humidity",
+            SensorKind::Rainfall => "rainfall",
+        }
+        .to_owned()
+    }
+}
+
+impl QueryValue for H3Cell {
+    fn parse(s: &str) -> Option<Self> {
+        u64::from_str_radix(s, 16).ok().map(H3Cell)
+    }
+
+    fn render(&self) -> String {
+        format!("{:x}", self.0)
+    }
+}
+
+impl QueryValue for QualityStatus {
+    fn parse(s: &str) -> Option<Self> {
+        QualityStatus::parse(s)
+    }
+
+    fn render(&self) -> String {
+        self.as_str().to_owned()
+    }
+}
+
+/// A list parameter, sent as comma separated values. Empty lists match
+/// everything and are left out of the query string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T> List<T> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The items, or `None` when there are none to filter by.
+    pub fn into_filter(self) -> Option<Vec<T>> {
+        (!self.0.is_empty()).then_some(self.0)
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T> FromIterator<T> for List<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<T: QueryValue> FromStr for List<T> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| T::parse(item).ok_or_else(|| format!("invalid list item '{item}'")))
+            .collect()
+    }
+}
+
+impl<T: QueryValue> fmt::Display for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(&item.render())?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: QueryValue> Serialize for List<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de, T: QueryValue> Deserialize<'de> for List<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Opaque token marking where a page ended. Pass a page's `next_cursor` as
+/// `after` to fetch the page following it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(transparent)]
+pub struct PageToken(pub String);
+
+impl PageToken {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PageToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// What readings are ordered by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingSort {
+    #[default]
+    Timestamp,
+    Confidence,
+}
+
+/// Query parameters for `GET /api/readings` and the endpoints narrowing it
+/// to a device or region.
+///
+/// List parameters are comma separated. Locations are H3 indexes in hex.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct ReadingQuery {
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub device_id: List<DeviceId>,
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub sensor_id: List<SensorId>,
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub dispatcher_id: List<DispatcherId>,
+    /// Metric kinds, e.g. `soil_moisture,air_temp`
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub metric: List<SensorKind>,
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub location: List<H3Cell>,
+    /// H3 cells in hex; readings inside any of them match
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub within: List<H3Cell>,
+    /// Only readings taken at or after this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<jiff::Timestamp>,
+    /// Only readings taken at or before this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<jiff::Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_confidence: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_confidence: Option<u8>,
+    /// Only readings whose value, in its metric's unit, is at least this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_value: Option<f64>,
+    /// Only readings whose value, in its metric's unit, is at most this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_value: Option<f64>,
+    /// Quality statuses, e.g. `out_of_range,rate_exceeded`
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub quality: List<QualityStatus>,
+    #[serde(default)]
+    pub sort_by: ReadingSort,
+    #[serde(default)]
+    pub order: Order,
+    /// Cursor returned as `next_cursor` by the previous page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub after: Option<PageToken>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+impl ReadingQuery {
+    pub fn devices(mut self, ids: impl IntoIterator<Item = DeviceId>) -> Self {
+        self.device_id = ids.into_iter().collect();
+        self
+    }
+
+    pub fn dispatchers(mut self, ids: impl IntoIterator<Item = DispatcherId>) -> Self {
+        self.dispatcher_id = ids.into_iter().collect();
+        self
+    }
+
+    pub fn metrics(mut self, kinds: impl IntoIterator<Item = SensorKind>) -> Self {
+        self.metric = kinds.into_iter().collect();
+        self
+    }
+
+    pub fn from(mut self, from: jiff::Timestamp) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    pub fn to(mut self, to: jiff::Timestamp) -> Self {
+        self.to = Some(to);
+        self
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn after(mut self, token: PageToken) -> Self {
+        self.after = Some(token);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+/// Query parameters for `GET /api/statuses`.
+///
+/// List parameters are comma separated.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct StatusQuery {
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub device_id: List<DeviceId>,
+    #[serde(default, skip_serializing_if = "List::is_empty")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub dispatcher_id: List<DispatcherId>,
+    /// Only statuses captured at or after this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<jiff::Timestamp>,
+    /// Only statuses captured at or before this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<jiff::Timestamp>,
+    /// Order by capture time
+    #[serde(default)]
+    pub order: Order,
+    /// Cursor returned as `next_cursor` by the previous page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub after: Option<PageToken>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+impl StatusQuery {
+    pub fn devices(mut self, ids: impl IntoIterator<Item = DeviceId>) -> Self {
+        self.device_id = ids.into_iter().collect();
+        self
+    }
+
+    pub fn dispatchers(mut self, ids: impl IntoIterator<Item = DispatcherId>) -> Self {
+        self.dispatcher_id = ids.into_iter().collect();
+        self
+    }
+
+    pub fn from(mut self, from: jiff::Timestamp) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    pub fn to(mut self, to: jiff::Timestamp) -> Self {
+        self.to = Some(to);
+        self
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn after(mut self, token: PageToken) -> Self {
+        self.after = Some(token);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
@@ -0,0 +1,49 @@
+-- Newest reading per sensor and status per device, kept up to date on
+-- insert so the local dashboard reads them by key rather than ranking every
+-- stored row. Timestamps are nanoseconds since the Unix epoch. When cleanup
+-- removes an entry's row, the newest remaining row for that key replaces it.
+CREATE TABLE IF NOT EXISTS latest_readings (
+    device_id TEXT NOT NULL,
+    sensor_id TEXT NOT NULL,
+    id TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    reading_json TEXT NOT NULL,
+    PRIMARY KEY (device_id, sensor_id)
+);
+
+CREATE TABLE IF NOT EXISTS latest_statuses (
+    device_id TEXT PRIMARY KEY,
+    id TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    status_json TEXT NOT NULL
+);
+
+INSERT INTO latest_readings (device_id, sensor_id, id, timestamp, reading_json)
+SELECT device_id, sensor_id, id, timestamp, reading_json FROM (
+    SELECT
+        id,
+        json_extract(reading_json, '$.device_id') AS device_id,
+        json_extract(reading_json, '$.sensor_id') AS sensor_id,
+        CAST((julianday(json_extract(reading_json, '$.timestamp')) - 2440587.5) * 86400000000000 AS INTEGER) AS timestamp,
+        reading_json,
+        ROW_NUMBER() OVER (
+            PARTITION BY json_extract(reading_json, '$.device_id'),
+                         json_extract(reading_json, '$.sensor_id')
+            ORDER BY julianday(json_extract(reading_json, '$.timestamp')) DESC
+        ) AS rn
+    FROM sensor_readings
+) WHERE rn = 1;
+
+INSERT INTO latest_statuses (device_id, id, timestamp, status_json)
+SELECT device_id, id, timestamp, status_json FROM (
+    SELECT
+        id,
+        json_extract(status_json, '$.device_id') AS device_id,
+        CAST((julianday(json_extract(status_json, '$.timestamp')) - 2440587.5) * 86400000000000 AS INTEGER) AS timestamp,
+        status_json,
+        ROW_NUMBER() OVER (
+            PARTITION BY json_extract(status_json, '$.device_id')
+            ORDER BY julianday(json_extract(status_json, '$.timestamp')) DESC
+        ) AS rn
+    FROM device_statuses
+) WHERE rn = 1;
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ersha_core::{DeviceId, DeviceStatus, SensorId, SensorReading};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::config::{OffPeakWindow, UplinkConfig};
+
+/// Approximate per-frame overhead on top of the encoded batch contents:
+/// length prefix, envelope ids and batch header.
+const FRAME_OVERHEAD_BYTES: u64 = 96;
+
+/// Items selected for the next upload.
+#[derive(Debug, Default)]
+pub struct UploadPlan {
+    pub readings: Vec<SensorReading>,
+    pub statuses: Vec<DeviceStatus>,
+    /// Estimated bytes this plan will put on the uplink, including one
+    /// frame overhead per batch.
+    pub estimated_bytes: u64,
+}
+
+impl UploadPlan {
+    pub fn is_empty(&self) -> bool {
+        self.readings.is_empty() && self.statuses.is_empty()
+    }
+
+    /// Split the plan into batches of at most `max_items` readings and
+    /// statuses each, preserving priority order. Statuses fill the first
+    /// batches.
+    pub fn into_batches(self, max_items: usize) -> Vec<UploadPlan> {
+        let max_items = max_items.max(1);
+        let mut batches = Vec::new();
+        let mut current = UploadPlan::default();
+
+        let mut statuses = self.statuses.into_iter();
+        let mut readings = self.readings.into_iter();
+
+        loop {
+            if current.readings.len() + current.statuses.len() == max_items {
+                batches.push(std::mem::take(&mut current));
+            }
+
+            if let Some(status) = statuses.next() {
+                current.estimated_bytes += item_len(&status);
+                current.statuses.push(status);
+            } else if let Some(reading) = readings.next() {
+                current.estimated_bytes += item_len(&reading);
+                current.readings.push(reading);
+            } else {
+                break;
+            }
+        }
+
+        if !current.is_empty() {
+            batches.push(current);
+        }
+
+        for batch in &mut batches {
+            batch.estimated_bytes += FRAME_OVERHEAD_BYTES;
+        }
+
+        batches
+    }
+}
+
+/// Snapshot of the uplink budget.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BudgetStats {
+    /// Configured daily budget, `None` when uploads are unmetered.
+    pub daily_budget_bytes: Option<u64>,
+    /// Bytes uploaded today.
+    pub consumed_bytes: u64,
+    /// Bytes left for today, `None` when uploads are unmetered.
+    pub remaining_bytes: Option<u64>,
+    /// Whether the whole backlog may be uploaded rather than a batch of it
+    /// per upload.
+    pub off_peak: bool,
+}
+
+struct BudgetState {
+    day: jiff::civil::Date,
+    consumed: u64,
+}
+
+/// Decides what to upload given a daily byte budget and an off-peak window.
+///
+/// Device statuses always go first, then the newest reading per sensor,
+/// then the backlog oldest first. Outside the off-peak window the backlog
+/// drains by at most one batch per upload; within it everything pending is
+/// eligible. Either way, until the day's budget runs out.
+#[derive(Clone)]
+pub struct UploadScheduler {
+    daily_budget_bytes: Option<u64>,
+    off_peak: Option<OffPeakWindow>,
+    state: Arc<Mutex<BudgetState>>,
+}
+
+impl UploadScheduler {
+    pub fn new(config: &UplinkConfig) -> Self {
+        Self {
+            daily_budget_bytes: config.daily_budget_bytes,
+            off_peak: config.off_peak,
+            state: Arc::new(Mutex::new(BudgetState {
+                day: jiff::Zoned::now().date(),
+                consumed: 0,
+            })),
+        }
+    }
+
+    /// Select which of the pending items to upload at `now`, charging the
+    /// frame overhead once per batch of at most `max_items`.
+    pub async fn plan(
+        &self,
+        readings: Vec<SensorReading>,
+        statuses: Vec<DeviceStatus>,
+        max_items: usize,
+        now: &jiff::Zoned,
+    ) -> UploadPlan {
+        let mut remaining = {
+            let mut state = self.state.lock().await;
+            roll_over(&mut state, now);
+            self.daily_budget_bytes
+                .map(|budget| budget.saturating_sub(state.consumed))
+        };
+
+        let max_items = max_items.max(1);
+        let mut plan = UploadPlan::default();
+
+        let mut statuses = statuses;
+        statuses.sort_by_key(|s| s.timestamp);
+        for status in statuses {
+            let opens_batch = plan.statuses.len() % max_items == 0;
+            let Some(size) = take(&mut remaining, item_len(&status), opens_batch) else {
+                break;
+            };
+            plan.estimated_bytes += size;
+            plan.statuses.push(status);
+        }
+
+        let sensors = latest_ids(&readings).len();
+        let mut readings = prioritize(readings);
+        if !self.is_off_peak(now) {
+            readings.truncate(sensors + max_items);
+        }
+
+        for reading in readings {
+            let opens_batch = (plan.statuses.len() + plan.readings.len()) % max_items == 0;
+            let Some(size) = take(&mut remaining, item_len(&reading), opens_batch) else {
+                break;
+            };
+            plan.estimated_bytes += size;
+            plan.readings.push(reading);
+        }
+
+        plan
+    }
+
+    /// Record bytes sent on the uplink.
+    pub async fn record(&self, bytes: u64, now: &jiff::Zoned) {
+        let mut state = self.state.lock().await;
+        roll_over(&mut state, now);
+        state.consumed = state.consumed.saturating_add(bytes);
+    }
+
+    pub async fn stats(&self, now: &jiff::Zoned) -> BudgetStats {
+        let mut state = self.state.lock().await;
+        roll_over(&mut state, now);
+
+        BudgetStats {
+            daily_budget_bytes: self.daily_budget_bytes,
+            consumed_bytes: state.consumed,
+            remaining_bytes: self
+                .daily_budget_bytes
+                .map(|budget| budget.saturating_sub(state.consumed)),
+            off_peak: self.is_off_peak(now),
+        }
+    }
+
+    fn is_off_peak(&self, now: &jiff::Zoned) -> bool {
+        match self.off_peak {
+            Some(window) => window.contains(now.hour()),
+            None => true,
+        }
+    }
+}
+
+fn roll_over(state: &mut BudgetState, now: &jiff::Zoned) {
+    if now.date() != state.day {
+        state.day = now.date();
+        state.consumed = 0;
+    }
+}
+
+/// Deduct `size` from the remaining budget, plus the frame overhead if the
+/// item opens a new batch. Returns the bytes charged, or `None` if they
+/// don't fit.
+fn take(remaining: &mut Option<u64>, size: u64, opens_batch: bool) -> Option<u64> {
+    let size = if opens_batch {
+        size + FRAME_OVERHEAD_BYTES
+    } else {
+        size
+    };
+
+    match remaining {
+        Some(left) if *left < size => None,
+        Some(left) => {
+            *left -= size;
+            Some(size)
+        }
+        None => Some(size),
+    }
+}
+
+fn item_len<T: Serialize>(item: &T) -> u64 {
+    ersha_rpc::encoded_len(item).map_or(0, |len| len as u64)
+}
+
+/// Newest reading per sensor first, then everything else oldest first.
+fn prioritize(readings: Vec<SensorReading>) -> Vec<SensorReading> {
+    let latest = latest_ids(&readings);
+    let (mut head, mut tail): (Vec<_>, Vec<_>) = readings
+        .into_iter()
+        .partition(|r| latest.get(&(r.device_id, r.sensor_id)) == Some(&r.id));
+
+    head.sort_by_key(|r| std::cmp::Reverse(r.timestamp));
+    tail.sort_by_key(|r| r.timestamp);
+    head.extend(tail);
+    head
+}
+
+fn latest_ids(readings: &[SensorReading]) -> HashMap<(DeviceId, SensorId), ersha_core::ReadingId> {
+    let mut latest: HashMap<(DeviceId, SensorId), &SensorReading> = HashMap::new();
+    for reading in readings {
+        latest
+            .entry((reading.device_id, reading.sensor_id))
+            .and_modify(|current| {
+                if reading.timestamp > current.timestamp {
+                    *current = reading;
+                }
+            })
+            .or_insert(reading);
+    }
+
+    latest.into_iter().map(|(key, r)| (key, r.id)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UploadScheduler;
+    use crate::config::{OffPeakWindow, UplinkConfig};
+    use ersha_core::*;
+    use ulid::Ulid;
+
+    fn reading(device_id: DeviceId, sensor_id: SensorId, second: i64) -> SensorReading {
+        SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id,
+            dispatcher_id: DispatcherId(Ulid::new()),
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(42),
+            },
+            location: H3Cell(123),
+            confidence: Percentage(95),
+            timestamp: jiff::Timestamp::from_second(second).unwrap(),
+            sensor_id,
+        }
+    }
+
+    fn dummy_status() -> DeviceStatus {
+        DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            battery_percent: Percentage(85),
+            uptime_seconds: 3600,
+            signal_rssi: -65,
+            errors: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+            sensor_statuses: Box::new([]),
+        }
+    }
+
+    fn at_hour(hour: i8) -> jiff::Zoned {
+        jiff::civil::date(2025, 6, 1)
+            .at(hour, 0, 0, 0)
+            .in_tz("UTC")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unmetered_sends_everything() {
+        let scheduler = UploadScheduler::new(&UplinkConfig::default());
+        let device = DeviceId(Ulid::new());
+        let sensor = SensorId(Ulid::new());
+
+        let readings = vec![reading(device, sensor, 1), reading(device, sensor, 2)];
+        let plan = scheduler
+            .plan(readings, vec![dummy_status()], 100, &at_hour(12))
+            .await;
+
+        assert_eq!(plan.readings.len(), 2);
+        assert_eq!(plan.statuses.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn peak_hours_drain_the_backlog_a_batch_at_a_time() {
+        let scheduler = UploadScheduler::new(&UplinkConfig {
+            daily_budget_bytes: None,
+            off_peak: Some(OffPeakWindow {
+                start_hour: 22,
+                end_hour: 6,
+            }),
+        });
+        let device = DeviceId(Ulid::new());
+        let sensor_a = SensorId(Ulid::new());
+        let sensor_b = SensorId(Ulid::new());
+
+        let mut readings: Vec<_> = (1..=5).map(|i| reading(device, sensor_a, i)).collect();
+        readings.push(reading(device, sensor_b, 2));
+        let newest_id = readings[4].id;
+
+        // The newest of each sensor, then a batch of the oldest.
+        let plan = scheduler
+            .plan(readings.clone(), vec![], 2, &at_hour(12))
+            .await;
+        let seconds: Vec<_> = plan
+            .readings
+            .iter()
+            .map(|r| r.timestamp.as_second())
+            .collect();
+        assert_eq!(plan.readings[0].id, newest_id);
+        assert_eq!(seconds, [5, 2, 1, 2]);
+
+        let plan = scheduler.plan(readings, vec![], 2, &at_hour(23)).await;
+        assert_eq!(plan.readings.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn budget_limits_plan_and_resets_daily() {
+        let device = DeviceId(Ulid::new());
+        let sensor = SensorId(Ulid::new());
+        let readings: Vec<_> = (0..10).map(|i| reading(device, sensor, i)).collect();
+        let one = ersha_rpc::encoded_len(&readings[0]).unwrap() as u64;
+
+        // Two batches of at most two readings each
+        let budget = 2 * super::FRAME_OVERHEAD_BYTES + one * 3;
+        let scheduler = UploadScheduler::new(&UplinkConfig {
+            daily_budget_bytes: Some(budget),
+            off_peak: None,
+        });
+
+        let now = at_hour(1);
+        let plan = scheduler.plan(readings.clone(), vec![], 2, &now).await;
+        assert_eq!(plan.readings.len(), 3);
+        assert!(plan.estimated_bytes <= budget);
+
+        let planned = plan.estimated_bytes;
+        let batches = plan.into_batches(2);
+        assert_eq!(batches.len(), 2);
+        let sent: u64 = batches.iter().map(|b| b.estimated_bytes).sum();
+        assert_eq!(sent, planned);
+        assert!(sent <= budget);
+
+        scheduler.record(sent, &now).await;
+        let stats = scheduler.stats(&now).await;
+        assert_eq!(stats.remaining_bytes, Some(0));
+
+        let plan = scheduler.plan(readings.clone(), vec![], 2, &now).await;
+        assert!(plan.is_empty());
+
+        let tomorrow = now.tomorrow().unwrap();
+        let plan = scheduler.plan(readings, vec![], 2, &tomorrow).await;
+        assert_eq!(plan.readings.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn plan_splits_into_bounded_batches() {
+        let scheduler = UploadScheduler::new(&UplinkConfig::default());
+        let device = DeviceId(Ulid::new());
+        let readings: Vec<_> = (0..7)
+            .map(|i| reading(device, SensorId(Ulid::new()), i))
+            .collect();
+        let statuses = vec![dummy_status(), dummy_status()];
+
+        let plan = scheduler.plan(readings, statuses, 4, &at_hour(12)).await;
+        let batches = plan.into_batches(4);
+
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].statuses.len(), 2);
+        assert_eq!(batches[0].readings.len(), 2);
+        assert_eq!(batches[1].readings.len(), 4);
+        assert_eq!(batches[2].readings.len(), 1);
+        assert!(
+            batches
+                .iter()
+                .all(|b| b.estimated_bytes > super::FRAME_OVERHEAD_BYTES)
+        );
+    }
+}
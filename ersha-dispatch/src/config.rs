@@ -1,6 +1,9 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use ersha_rpc::Keepalive;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
@@ -10,14 +13,29 @@ pub struct Config {
     pub storage: StorageConfig,
     pub prime: PrimeConfig,
     pub edge: EdgeConfig,
+    #[serde(default)]
+    pub uplink: UplinkConfig,
+    #[serde(default)]
+    pub decoders: DecoderConfig,
+    #[serde(default)]
+    pub devices: DeviceConfig,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct DispatcherConfig {
-    /// Dispatcher ID (ULID format)
-    pub id: String,
+    /// Dispatcher ID (ULID format). Generated and persisted on first start when unset.
+    pub id: Option<String>,
+    /// Path of the file holding the persisted dispatcher identity
+    #[serde(default = "default_identity_path")]
+    pub identity_path: PathBuf,
     /// H3 cell location
     pub location: u64,
+    /// Shared secret provisioned on ersha-prime, used to sign the hello
+    pub secret: Option<String>,
+}
+
+fn default_identity_path() -> PathBuf {
+    PathBuf::from("dispatcher-identity.json")
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,6 +57,67 @@ pub struct PrimeConfig {
     pub rpc_addr: SocketAddr,
     /// Interval in seconds between upload attempts
     pub upload_interval_secs: u64,
+    /// Maximum number of batches in flight at once, pipelined over one connection
+    #[serde(default = "default_upload_concurrency")]
+    pub upload_concurrency: usize,
+    /// Maximum number of readings and statuses in a single batch
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    /// Connect over TLS; plain TCP when unset
+    #[serde(default)]
+    pub tls: Option<PrimeTlsConfig>,
+    /// Seconds between keepalive pings to ersha-prime; 0 turns them off
+    #[serde(default = "default_keepalive_interval_secs")]
+    pub keepalive_interval_secs: u64,
+    /// Seconds ersha-prime has to answer a keepalive before the connection
+    /// is dropped and reopened
+    #[serde(default = "default_keepalive_timeout_secs")]
+    pub keepalive_timeout_secs: u64,
+    /// Tunnel RPC over a WebSocket at this `ws://` or `wss://` URL instead of
+    /// connecting to `rpc_addr`, for networks that only let HTTP(S) out.
+    /// `wss://` is verified against `tls`.
+    #[serde(default)]
+    pub tunnel_url: Option<String>,
+}
+
+impl PrimeConfig {
+    /// Keepalive for the connection to ersha-prime, if enabled.
+    pub fn keepalive(&self) -> Option<Keepalive> {
+        (self.keepalive_interval_secs > 0).then(|| Keepalive {
+            interval: Duration::from_secs(self.keepalive_interval_secs),
+            timeout: Duration::from_secs(self.keepalive_timeout_secs),
+        })
+    }
+}
+
+/// TLS to ersha-prime, optionally authenticating with a client certificate.
+#[derive(Debug, Deserialize)]
+pub struct PrimeTlsConfig {
+    /// PEM CA that issued ersha-prime's certificate
+    pub ca: PathBuf,
+    /// PEM client certificate issued for this dispatcher's id
+    pub cert: Option<PathBuf>,
+    /// PEM private key for `cert`
+    pub key: Option<PathBuf>,
+    /// Name to verify ersha-prime's certificate against; the IP of
+    /// `rpc_addr` when unset
+    pub server_name: Option<String>,
+}
+
+fn default_upload_concurrency() -> usize {
+    4
+}
+
+fn default_max_batch_size() -> usize {
+    500
+}
+
+fn default_keepalive_interval_secs() -> u64 {
+    30
+}
+
+fn default_keepalive_timeout_secs() -> u64 {
+    10
 }
 
 #[derive(Debug, Deserialize)]
@@ -54,6 +133,84 @@ pub enum EdgeConfig {
     },
 }
 
+/// Selection of payload decoder profiles for device uplinks.
+#[derive(Debug, Default, Deserialize)]
+pub struct DecoderConfig {
+    /// Profile used when neither the device nor the fport has one.
+    pub default_profile: Option<String>,
+    /// Profile per device ID (ULID format)
+    #[serde(default)]
+    pub devices: HashMap<String, String>,
+    /// Profile per LoRaWAN fport
+    #[serde(default)]
+    pub fports: HashMap<String, String>,
+    /// Sensor ID (ULID format) registered with prime for each channel, per
+    /// device ID
+    #[serde(default)]
+    pub sensors: HashMap<String, HashMap<String, String>>,
+}
+
+/// Limits for metered (e.g. LTE) uplinks.
+#[derive(Debug, Default, Deserialize)]
+pub struct UplinkConfig {
+    /// Maximum bytes to upload per local calendar day. Unlimited when unset.
+    pub daily_budget_bytes: Option<u64>,
+    /// Window in which the whole backlog of readings is uploaded; outside
+    /// it, a batch of the backlog goes per upload. Always off-peak when unset.
+    pub off_peak: Option<OffPeakWindow>,
+}
+
+/// When devices count as disconnected.
+#[derive(Debug, Deserialize)]
+pub struct DeviceConfig {
+    /// How often devices are expected to send a reading or status
+    #[serde(default = "default_report_interval_secs")]
+    pub report_interval_secs: u64,
+    /// Reports a device may miss before it's reported disconnected
+    #[serde(default = "default_missed_reports")]
+    pub missed_reports: u32,
+}
+
+impl DeviceConfig {
+    pub fn disconnect_after(&self) -> Duration {
+        Duration::from_secs(self.report_interval_secs) * self.missed_reports.max(1)
+    }
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            report_interval_secs: default_report_interval_secs(),
+            missed_reports: default_missed_reports(),
+        }
+    }
+}
+
+fn default_report_interval_secs() -> u64 {
+    60
+}
+
+fn default_missed_reports() -> u32 {
+    3
+}
+
+/// Off-peak window in local hours, `[start_hour, end_hour)`. May wrap midnight.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct OffPeakWindow {
+    pub start_hour: i8,
+    pub end_hour: i8,
+}
+
+impl OffPeakWindow {
+    pub fn contains(&self, hour: i8) -> bool {
+        if self.start_hour <= self.end_hour {
+            hour >= self.start_hour && hour < self.end_hour
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+}
+
 impl Config {
     pub fn load(path: &Path) -> color_eyre::Result<Self> {
         let content = std::fs::read_to_string(path)?;
@@ -66,8 +223,10 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             dispatcher: DispatcherConfig {
-                id: "01JJNQ1KQCNZ8X9PQRV5ABCD12".to_string(),
+                id: None,
+                identity_path: default_identity_path(),
                 location: 0x8a2a1072b59ffff,
+                secret: None,
             },
             server: ServerConfig {
                 http_addr: "0.0.0.0:8081".parse().unwrap(),
@@ -76,12 +235,21 @@ impl Default for Config {
             prime: PrimeConfig {
                 rpc_addr: "127.0.0.1:9000".parse().unwrap(),
                 upload_interval_secs: 60,
+                upload_concurrency: default_upload_concurrency(),
+                max_batch_size: default_max_batch_size(),
+                tls: None,
+                keepalive_interval_secs: default_keepalive_interval_secs(),
+                keepalive_timeout_secs: default_keepalive_timeout_secs(),
+                tunnel_url: None,
             },
             edge: EdgeConfig::Mock {
                 reading_interval_secs: 5,
                 status_interval_secs: 30,
                 device_count: 3,
             },
+            uplink: UplinkConfig::default(),
+            decoders: DecoderConfig::default(),
+            devices: DeviceConfig::default(),
         }
     }
 }
@@ -0,0 +1,214 @@
+use ersha_core::{Percentage, SensorMetric};
+use ordered_float::NotNan;
+
+use super::{DecodedMetric, DecoderError, PayloadDecoder};
+
+const DIGITAL_INPUT: u8 = 0x00;
+const DIGITAL_OUTPUT: u8 = 0x01;
+const ANALOG_INPUT: u8 = 0x02;
+const ANALOG_OUTPUT: u8 = 0x03;
+const ILLUMINANCE: u8 = 0x65;
+const PRESENCE: u8 = 0x66;
+const TEMPERATURE: u8 = 0x67;
+const HUMIDITY: u8 = 0x68;
+const ACCELEROMETER: u8 = 0x71;
+const BAROMETER: u8 = 0x73;
+const GYROMETER: u8 = 0x86;
+const GPS: u8 = 0x88;
+
+/// Cayenne Low Power Payload decoder.
+///
+/// Temperature maps to air temperature, relative humidity to humidity and
+/// analog input (hundredths, rounded to whole percent) to soil moisture.
+/// Other standard LPP types are skipped since ersha has no matching metric.
+pub struct CayenneLppDecoder;
+
+impl PayloadDecoder for CayenneLppDecoder {
+    fn decode(&self, payload: &[u8]) -> Result<Vec<DecodedMetric>, DecoderError> {
+        let mut metrics = Vec::new();
+        let mut rest = payload;
+
+        while !rest.is_empty() {
+            if rest.len() < 2 {
+                return Err(DecoderError::Truncated {
+                    expected: 2,
+                    actual: rest.len(),
+                });
+            }
+
+            let channel = rest[0];
+            let data_type = rest[1];
+            let size = data_size(data_type)?;
+            let data = rest.get(2..2 + size).ok_or(DecoderError::Truncated {
+                expected: 2 + size,
+                actual: rest.len(),
+            })?;
+            rest = &rest[2 + size..];
+
+            let metric = match data_type {
+                TEMPERATURE => {
+                    let value = f64::from(i16::from_be_bytes([data[0], data[1]])) / 10.0;
+                    SensorMetric::AirTemp {
+                        value: NotNan::new(value)
+                            .map_err(|_| DecoderError::InvalidValue("temperature"))?,
+                    }
+                }
+                // Half percent steps, so at most 200.
+                HUMIDITY if data[0] > 200 => {
+                    return Err(DecoderError::InvalidValue("humidity"));
+                }
+                HUMIDITY => SensorMetric::Humidity {
+                    value: Percentage(data[0] / 2),
+                },
+                ANALOG_INPUT => {
+                    let hundredths = i16::from_be_bytes([data[0], data[1]]);
+                    if !(0..=10_000).contains(&hundredths) {
+                        return Err(DecoderError::InvalidValue("soil moisture"));
+                    }
+                    SensorMetric::SoilMoisture {
+                        value: Percentage(((hundredths + 50) / 100) as u8),
+                    }
+                }
+                _ => continue,
+            };
+
+            metrics.push(DecodedMetric {
+                channel,
+                metric,
+                confidence: None,
+            });
+        }
+
+        Ok(metrics)
+    }
+}
+
+fn data_size(data_type: u8) -> Result<usize, DecoderError> {
+    let size = match data_type {
+        DIGITAL_INPUT | DIGITAL_OUTPUT | PRESENCE | HUMIDITY => 1,
+        ANALOG_INPUT | ANALOG_OUTPUT | ILLUMINANCE | TEMPERATURE | BAROMETER => 2,
+        ACCELEROMETER | GYROMETER => 6,
+        GPS => 9,
+        other => return Err(DecoderError::UnsupportedType(other)),
+    };
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CayenneLppDecoder;
+    use crate::edge::decoder::{DecoderError, PayloadDecoder};
+    use ersha_core::{Percentage, SensorMetric};
+    use ordered_float::NotNan;
+
+    #[test]
+    fn decodes_mixed_payload() -> Result<(), DecoderError> {
+        let payload = [
+            0x03, 0x67, 0x01, 0x10, // channel 3, 27.2 °C
+            0x05, 0x68, 0x64, // channel 5, 50 %RH
+            0x06, 0x65, 0x00, 0x10, // channel 6, illuminance (skipped)
+            0x07, 0x02, 0x0f, 0xa0, // channel 7, analog 40.00
+        ];
+
+        let metrics = CayenneLppDecoder.decode(&payload)?;
+
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics[0].channel, 3);
+        assert_eq!(
+            metrics[0].metric,
+            SensorMetric::AirTemp {
+                value: NotNan::new(27.2).unwrap()
+            }
+        );
+        assert_eq!(
+            metrics[1].metric,
+            SensorMetric::Humidity {
+                value: Percentage(50)
+            }
+        );
+        assert_eq!(
+            metrics[2].metric,
+            SensorMetric::SoilMoisture {
+                value: Percentage(40)
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn negative_temperature() -> Result<(), DecoderError> {
+        let metrics = CayenneLppDecoder.decode(&[0x01, 0x67, 0xff, 0xd7])?;
+
+        assert_eq!(
+            metrics[0].metric,
+            SensorMetric::AirTemp {
+                value: NotNan::new(-4.1).unwrap()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_truncated_and_unknown() {
+        assert!(matches!(
+            CayenneLppDecoder.decode(&[0x01, 0x67, 0x01]),
+            Err(DecoderError::Truncated { .. })
+        ));
+        assert!(matches!(
+            CayenneLppDecoder.decode(&[0x01, 0xee, 0x01]),
+            Err(DecoderError::UnsupportedType(0xee))
+        ));
+    }
+
+    #[test]
+    fn analog_input_is_rounded_and_range_checked() -> Result<(), DecoderError> {
+        // 42.50 rounds up, 42.49 down.
+        let metrics =
+            CayenneLppDecoder.decode(&[0x01, 0x02, 0x10, 0x9a, 0x02, 0x02, 0x10, 0x99])?;
+        assert_eq!(
+            metrics[0].metric,
+            SensorMetric::SoilMoisture {
+                value: Percentage(43)
+            }
+        );
+        assert_eq!(
+            metrics[1].metric,
+            SensorMetric::SoilMoisture {
+                value: Percentage(42)
+            }
+        );
+
+        // -0.40 and 100.01 are out of range rather than clamped.
+        assert!(matches!(
+            CayenneLppDecoder.decode(&[0x01, 0x02, 0xff, 0xd8]),
+            Err(DecoderError::InvalidValue("soil moisture"))
+        ));
+        assert!(matches!(
+            CayenneLppDecoder.decode(&[0x01, 0x02, 0x27, 0x11]),
+            Err(DecoderError::InvalidValue("soil moisture"))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_humidity_over_100_percent() -> Result<(), DecoderError> {
+        let metrics = CayenneLppDecoder.decode(&[0x01, 0x68, 200])?;
+        assert_eq!(
+            metrics[0].metric,
+            SensorMetric::Humidity {
+                value: Percentage(100)
+            }
+        );
+
+        assert!(matches!(
+            CayenneLppDecoder.decode(&[0x01, 0x68, 201]),
+            Err(DecoderError::InvalidValue("humidity"))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use super::{DecodedMetric, DecoderError, PayloadDecoder};
+
+/// Decoder for devices that send a JSON array of [`DecodedMetric`]s.
+///
+/// ```json
+/// [{ "channel": 1, "metric": { "SoilMoisture": { "value": 42 } }, "confidence": 90 }]
+/// ```
+pub struct JsonDecoder;
+
+impl PayloadDecoder for JsonDecoder {
+    fn decode(&self, payload: &[u8]) -> Result<Vec<DecodedMetric>, DecoderError> {
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonDecoder;
+    use crate::edge::decoder::{DecoderError, PayloadDecoder};
+    use ersha_core::{Percentage, SensorMetric};
+
+    #[test]
+    fn decodes_metric_array() -> Result<(), DecoderError> {
+        let payload =
+            br#"[{ "channel": 1, "metric": { "Humidity": { "value": 61 } }, "confidence": null }]"#;
+
+        let metrics = JsonDecoder.decode(payload)?;
+
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(
+            metrics[0].metric,
+            SensorMetric::Humidity {
+                value: Percentage(61)
+            }
+        );
+        assert_eq!(metrics[0].confidence, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,397 @@
+pub mod cayenne;
+pub mod json;
+pub mod raw;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ersha_core::{
+    DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId, SensorMetric, SensorReading,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::Uplink;
+use crate::config::DecoderConfig;
+
+pub use cayenne::CayenneLppDecoder;
+pub use json::JsonDecoder;
+pub use raw::RawReadingDecoder;
+
+/// A single metric decoded from a device uplink.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedMetric {
+    /// Channel (sensor slot) on the device that produced the value.
+    pub channel: u8,
+    pub metric: SensorMetric,
+    /// Confidence reported by the device, if the encoding carries one.
+    pub confidence: Option<Percentage>,
+}
+
+#[derive(Debug, Error)]
+pub enum DecoderError {
+    #[error("payload truncated: expected {expected} bytes, got {actual}")]
+    Truncated { expected: usize, actual: usize },
+    #[error("unsupported data type 0x{0:02x}")]
+    UnsupportedType(u8),
+    #[error("invalid value for {0}")]
+    InvalidValue(&'static str),
+    #[error("serde json error: {0}")]
+    SerdeJson(#[from] serde_json::Error),
+    #[error("unknown decoder profile: {0}")]
+    UnknownProfile(String),
+    #[error("invalid device id '{0}' in decoder config")]
+    InvalidDeviceId(String),
+    #[error("invalid fport '{0}' in decoder config")]
+    InvalidFport(String),
+    #[error("invalid channel '{0}' in decoder config")]
+    InvalidChannel(String),
+    #[error("invalid sensor id '{0}' in decoder config")]
+    InvalidSensorId(String),
+    #[error("no decoder configured for device {device_id:?} on fport {fport:?}")]
+    NoDecoder {
+        device_id: DeviceId,
+        fport: Option<u8>,
+    },
+    #[error("no sensor configured for channel {channel} of device {device_id:?}")]
+    UnknownChannel { device_id: DeviceId, channel: u8 },
+}
+
+/// Confidence of decoded readings whose encoding carries none.
+const DEFAULT_CONFIDENCE: Percentage = Percentage(100);
+
+/// Decodes the raw uplink payload of a device into metrics.
+pub trait PayloadDecoder: Send + Sync + 'static {
+    fn decode(&self, payload: &[u8]) -> Result<Vec<DecodedMetric>, DecoderError>;
+}
+
+impl DecodedMetric {
+    /// Check the values against what their units allow, whichever decoder
+    /// produced them.
+    fn validate(&self) -> Result<(), DecoderError> {
+        let in_range = match &self.metric {
+            SensorMetric::SoilMoisture { value } | SensorMetric::Humidity { value } => {
+                value.0 <= 100
+            }
+            SensorMetric::SoilTemp { value } | SensorMetric::AirTemp { value } => value.is_finite(),
+            SensorMetric::Rainfall { value } => value.is_finite() && **value >= 0.0,
+        };
+        if !in_range {
+            return Err(DecoderError::InvalidValue(metric_name(&self.metric)));
+        }
+        if self.confidence.is_some_and(|confidence| confidence.0 > 100) {
+            return Err(DecoderError::InvalidValue("confidence"));
+        }
+
+        Ok(())
+    }
+}
+
+fn metric_name(metric: &SensorMetric) -> &'static str {
+    match metric {
+        SensorMetric::SoilMoisture { .. } => "soil moisture",
+        SensorMetric::SoilTemp { .. } => "soil temperature",
+        SensorMetric::AirTemp { .. } => "air temperature",
+        SensorMetric::Humidity { .. } => "humidity",
+        SensorMetric::Rainfall { .. } => "rainfall",
+    }
+}
+
+/// Decoders keyed by device profile, with per-device and per-fport selection.
+///
+/// A device-specific profile wins over an fport mapping, which wins over
+/// the default profile. Decoded channels are read as the sensors configured
+/// for them.
+#[derive(Clone, Default)]
+pub struct DecoderRegistry {
+    profiles: HashMap<String, Arc<dyn PayloadDecoder>>,
+    devices: HashMap<DeviceId, String>,
+    fports: HashMap<u8, String>,
+    default_profile: Option<String>,
+    sensors: HashMap<(DeviceId, u8), SensorId>,
+}
+
+impl DecoderRegistry {
+    /// Registry with the built-in `cayenne-lpp`, `raw` and `json` profiles.
+    pub fn builtin() -> Self {
+        Self::default()
+            .with_profile("cayenne-lpp", CayenneLppDecoder)
+            .with_profile("raw", RawReadingDecoder)
+            .with_profile("json", JsonDecoder)
+    }
+
+    /// Built-in profiles with device and fport selection taken from `config`.
+    pub fn from_config(config: &DecoderConfig) -> Result<Self, DecoderError> {
+        let mut registry = Self::builtin();
+
+        if let Some(profile) = &config.default_profile {
+            registry.ensure_profile(profile)?;
+            registry.default_profile = Some(profile.clone());
+        }
+
+        for (device_id, profile) in &config.devices {
+            let id = device_id
+                .parse()
+                .map(DeviceId)
+                .map_err(|_| DecoderError::InvalidDeviceId(device_id.clone()))?;
+            registry.ensure_profile(profile)?;
+            registry.devices.insert(id, profile.clone());
+        }
+
+        for (fport, profile) in &config.fports {
+            let port = fport
+                .parse()
+                .map_err(|_| DecoderError::InvalidFport(fport.clone()))?;
+            registry.ensure_profile(profile)?;
+            registry.fports.insert(port, profile.clone());
+        }
+
+        for (device_id, channels) in &config.sensors {
+            let id = device_id
+                .parse()
+                .map(DeviceId)
+                .map_err(|_| DecoderError::InvalidDeviceId(device_id.clone()))?;
+            for (channel, sensor_id) in channels {
+                let channel = channel
+                    .parse()
+                    .map_err(|_| DecoderError::InvalidChannel(channel.clone()))?;
+                let sensor = sensor_id
+                    .parse()
+                    .map(SensorId)
+                    .map_err(|_| DecoderError::InvalidSensorId(sensor_id.clone()))?;
+                registry.sensors.insert((id, channel), sensor);
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub fn with_profile(mut self, name: impl Into<String>, decoder: impl PayloadDecoder) -> Self {
+        self.profiles.insert(name.into(), Arc::new(decoder));
+        self
+    }
+
+    /// Names of all registered profiles, sorted.
+    pub fn profiles(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.profiles.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Find the decoder for an uplink from `device_id` on `fport`.
+    pub fn resolve(&self, device_id: DeviceId, fport: Option<u8>) -> Option<&dyn PayloadDecoder> {
+        let profile = self
+            .devices
+            .get(&device_id)
+            .or_else(|| fport.and_then(|port| self.fports.get(&port)))
+            .or(self.default_profile.as_ref())?;
+
+        self.profiles.get(profile).map(|decoder| decoder.as_ref())
+    }
+
+    pub fn decode(
+        &self,
+        device_id: DeviceId,
+        fport: Option<u8>,
+        payload: &[u8],
+    ) -> Result<Vec<DecodedMetric>, DecoderError> {
+        let metrics = self
+            .resolve(device_id, fport)
+            .ok_or(DecoderError::NoDecoder { device_id, fport })?
+            .decode(payload)?;
+        for metric in &metrics {
+            metric.validate()?;
+        }
+
+        Ok(metrics)
+    }
+
+    /// Sensor registered with prime for `channel` on `device_id`.
+    pub fn sensor(&self, device_id: DeviceId, channel: u8) -> Option<SensorId> {
+        self.sensors.get(&(device_id, channel)).copied()
+    }
+
+    /// Decode `uplink` into readings taken at `location` and relayed by
+    /// `dispatcher_id`, one per decoded metric. Fails if a channel has no
+    /// sensor configured, since prime would quarantine its readings.
+    pub fn readings(
+        &self,
+        uplink: &Uplink,
+        dispatcher_id: DispatcherId,
+        location: H3Cell,
+    ) -> Result<Vec<SensorReading>, DecoderError> {
+        let device_id = uplink.device_id;
+        let metrics = self.decode(device_id, uplink.fport, &uplink.payload)?;
+
+        metrics
+            .into_iter()
+            .map(|decoded| {
+                let sensor_id = self.sensor(device_id, decoded.channel).ok_or(
+                    DecoderError::UnknownChannel {
+                        device_id,
+                        channel: decoded.channel,
+                    },
+                )?;
+
+                Ok(SensorReading {
+                    id: ReadingId(ulid::Ulid::new()),
+                    device_id,
+                    dispatcher_id,
+                    metric: decoded.metric,
+                    location,
+                    confidence: decoded.confidence.unwrap_or(DEFAULT_CONFIDENCE),
+                    timestamp: uplink.received_at,
+                    sensor_id,
+                })
+            })
+            .collect()
+    }
+
+    fn ensure_profile(&self, profile: &str) -> Result<(), DecoderError> {
+        if self.profiles.contains_key(profile) {
+            Ok(())
+        } else {
+            Err(DecoderError::UnknownProfile(profile.to_owned()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecoderError, DecoderRegistry};
+    use crate::config::DecoderConfig;
+    use crate::edge::Uplink;
+    use ersha_core::{DeviceId, DispatcherId, H3Cell, Percentage, SensorId, SensorMetric};
+    use ulid::Ulid;
+
+    #[test]
+    fn device_profile_takes_precedence_over_fport() -> Result<(), DecoderError> {
+        let device = DeviceId(Ulid::new());
+        let config = DecoderConfig {
+            default_profile: None,
+            devices: [(device.0.to_string(), "json".to_owned())].into(),
+            fports: [("2".to_owned(), "cayenne-lpp".to_owned())].into(),
+            ..Default::default()
+        };
+        let registry = DecoderRegistry::from_config(&config)?;
+
+        // Cayenne LPP humidity on channel 1: 84 * 0.5% = 42%.
+        let lpp = [0x01, 0x68, 84];
+        let other = DeviceId(Ulid::new());
+        let decoded = registry.decode(other, Some(2), &lpp)?;
+        assert_eq!(
+            decoded[0].metric,
+            SensorMetric::Humidity {
+                value: Percentage(42)
+            }
+        );
+
+        assert!(matches!(
+            registry.decode(device, Some(2), &lpp),
+            Err(DecoderError::SerdeJson(_))
+        ));
+        assert!(matches!(
+            registry.decode(other, Some(3), &lpp),
+            Err(DecoderError::NoDecoder { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn uplinks_decode_into_readings_of_configured_sensors() -> Result<(), DecoderError> {
+        let device = DeviceId(Ulid::new());
+        let humidity = SensorId(Ulid::new());
+        let temperature = SensorId(Ulid::new());
+        let config = DecoderConfig {
+            default_profile: Some("cayenne-lpp".to_owned()),
+            sensors: [(
+                device.0.to_string(),
+                [
+                    ("1".to_owned(), humidity.0.to_string()),
+                    ("2".to_owned(), temperature.0.to_string()),
+                ]
+                .into(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let registry = DecoderRegistry::from_config(&config)?;
+        let uplink = Uplink {
+            device_id: device,
+            fport: Some(1),
+            // Humidity 42% on channel 1, 21.5 degrees on channel 2.
+            payload: vec![0x01, 0x68, 84, 0x02, 0x67, 0x00, 0xd7],
+            received_at: jiff::Timestamp::now(),
+        };
+
+        let readings = registry.readings(
+            &uplink,
+            DispatcherId(Ulid::new()),
+            H3Cell(0x8a2a1072b59ffff),
+        )?;
+        assert_eq!(readings.len(), 2);
+        assert_eq!(readings[0].sensor_id, humidity);
+        assert_eq!(readings[1].sensor_id, temperature);
+        assert!(readings.iter().all(|r| r.timestamp == uplink.received_at));
+
+        // Channel 3 has no sensor, so the whole uplink is refused.
+        let unknown = Uplink {
+            payload: vec![0x01, 0x68, 84, 0x03, 0x67, 0x00, 0xd7],
+            ..uplink
+        };
+        assert!(matches!(
+            registry.readings(
+                &unknown,
+                DispatcherId(Ulid::new()),
+                H3Cell(0x8a2a1072b59ffff),
+            ),
+            Err(DecoderError::UnknownChannel { channel: 3, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn decoded_values_are_checked_against_their_units() -> Result<(), DecoderError> {
+        let device = DeviceId(Ulid::new());
+        let config = DecoderConfig {
+            default_profile: Some("json".to_owned()),
+            ..Default::default()
+        };
+        let registry = DecoderRegistry::from_config(&config)?;
+
+        let over = br#"[{ "channel": 1, "metric": { "Humidity": { "value": 150 } }, "confidence": null }]"#;
+        assert!(matches!(
+            registry.decode(device, None, over),
+            Err(DecoderError::InvalidValue("humidity"))
+        ));
+
+        let unsure = br#"[{ "channel": 1, "metric": { "SoilMoisture": { "value": 40 } }, "confidence": 101 }]"#;
+        assert!(matches!(
+            registry.decode(device, None, unsure),
+            Err(DecoderError::InvalidValue("confidence"))
+        ));
+
+        let negative = br#"[{ "channel": 1, "metric": { "Rainfall": { "value": -2.0 } }, "confidence": null }]"#;
+        assert!(matches!(
+            registry.decode(device, None, negative),
+            Err(DecoderError::InvalidValue("rainfall"))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_profile_is_rejected() {
+        let config = DecoderConfig {
+            default_profile: Some("vendor-x".to_owned()),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            DecoderRegistry::from_config(&config),
+            Err(DecoderError::UnknownProfile(_))
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+use ersha_core::{Percentage, SensorMetric};
+use ordered_float::NotNan;
+
+use super::{DecodedMetric, DecoderError, PayloadDecoder};
+
+/// Size of one encoded reading.
+pub const RAW_READING_LEN: usize = 11;
+
+/// Decoder for the compact 11-byte reading used by ersha firmware.
+///
+/// Layout: `channel: u8`, `metric: u8`, `value: f64` (little endian),
+/// `confidence: u8`. A payload may carry several readings back to back.
+///
+/// Metric codes: `0` soil moisture, `1` soil temperature, `2` air
+/// temperature, `3` humidity, `4` rainfall.
+pub struct RawReadingDecoder;
+
+impl PayloadDecoder for RawReadingDecoder {
+    fn decode(&self, payload: &[u8]) -> Result<Vec<DecodedMetric>, DecoderError> {
+        if payload.is_empty() || !payload.len().is_multiple_of(RAW_READING_LEN) {
+            return Err(DecoderError::Truncated {
+                expected: payload.len().div_ceil(RAW_READING_LEN).max(1) * RAW_READING_LEN,
+                actual: payload.len(),
+            });
+        }
+
+        payload
+            .chunks_exact(RAW_READING_LEN)
+            .map(decode_reading)
+            .collect()
+    }
+}
+
+fn decode_reading(chunk: &[u8]) -> Result<DecodedMetric, DecoderError> {
+    let channel = chunk[0];
+    let value = f64::from_le_bytes(chunk[2..10].try_into().expect("chunk is 11 bytes"));
+    let confidence = percentage(f64::from(chunk[10]), "confidence")?;
+
+    let metric = match chunk[1] {
+        0 => SensorMetric::SoilMoisture {
+            value: percentage(value, "soil moisture")?,
+        },
+        1 => SensorMetric::SoilTemp {
+            value: not_nan(value, "soil temperature")?,
+        },
+        2 => SensorMetric::AirTemp {
+            value: not_nan(value, "air temperature")?,
+        },
+        3 => SensorMetric::Humidity {
+            value: percentage(value, "humidity")?,
+        },
+        4 => SensorMetric::Rainfall {
+            value: not_nan(value, "rainfall")?,
+        },
+        other => return Err(DecoderError::UnsupportedType(other)),
+    };
+
+    Ok(DecodedMetric {
+        channel,
+        metric,
+        confidence: Some(confidence),
+    })
+}
+
+fn not_nan(value: f64, field: &'static str) -> Result<NotNan<f64>, DecoderError> {
+    NotNan::new(value).map_err(|_| DecoderError::InvalidValue(field))
+}
+
+fn percentage(value: f64, field: &'static str) -> Result<Percentage, DecoderError> {
+    if (0.0..=100.0).contains(&value) {
+        Ok(Percentage(value.round() as u8))
+    } else {
+        Err(DecoderError::InvalidValue(field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RawReadingDecoder;
+    use crate::edge::decoder::{DecoderError, PayloadDecoder};
+    use ersha_core::{Percentage, SensorMetric};
+    use ordered_float::NotNan;
+
+    fn encode(channel: u8, metric: u8, value: f64, confidence: u8) -> Vec<u8> {
+        let mut bytes = vec![channel, metric];
+        bytes.extend_from_slice(&value.to_le_bytes());
+        bytes.push(confidence);
+        bytes
+    }
+
+    #[test]
+    fn decodes_consecutive_readings() -> Result<(), DecoderError> {
+        let mut payload = encode(1, 0, 42.0, 95);
+        payload.extend(encode(2, 4, 3.5, 80));
+
+        let metrics = RawReadingDecoder.decode(&payload)?;
+
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(
+            metrics[0].metric,
+            SensorMetric::SoilMoisture {
+                value: Percentage(42)
+            }
+        );
+        assert_eq!(metrics[0].confidence, Some(Percentage(95)));
+        assert_eq!(metrics[1].channel, 2);
+        assert_eq!(
+            metrics[1].metric,
+            SensorMetric::Rainfall {
+                value: NotNan::new(3.5).unwrap()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bad_length_and_values() {
+        assert!(matches!(
+            RawReadingDecoder.decode(&[0; 10]),
+            Err(DecoderError::Truncated { .. })
+        ));
+        assert!(matches!(
+            RawReadingDecoder.decode(&encode(1, 3, 140.0, 90)),
+            Err(DecoderError::InvalidValue("humidity"))
+        ));
+        assert!(matches!(
+            RawReadingDecoder.decode(&encode(1, 9, 1.0, 90)),
+            Err(DecoderError::UnsupportedType(9))
+        ));
+    }
+}
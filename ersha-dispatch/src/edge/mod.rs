@@ -1,7 +1,8 @@
+pub mod decoder;
 pub mod mock;
 
 use async_trait::async_trait;
-use ersha_core::{DeviceStatus, SensorReading};
+use ersha_core::{DeviceId, DeviceStatus, SensorReading};
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
@@ -12,6 +13,19 @@ pub enum EdgeData {
     Reading(SensorReading),
     /// A device status report.
     Status(DeviceStatus),
+    /// An encoded uplink, to be decoded into readings by the profile
+    /// configured for the device or fport.
+    Uplink(Uplink),
+}
+
+/// An uplink payload as received from a device.
+#[derive(Debug, Clone)]
+pub struct Uplink {
+    pub device_id: DeviceId,
+    /// LoRaWAN fport, if the radio has them
+    pub fport: Option<u8>,
+    pub payload: Vec<u8>,
+    pub received_at: jiff::Timestamp,
 }
 
 /// Trait for receiving data from edge devices.
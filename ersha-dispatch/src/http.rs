@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    response::Html,
+    routing::get,
+};
+use ersha_core::{DeviceId, DeviceStatus, SensorReading};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use ulid::Ulid;
+
+use crate::budget::{BudgetStats, UploadScheduler};
+use crate::identity::{DispatcherIdentity, IdentityStore};
+use crate::rpc_stats::{CallStats, RpcStats};
+use crate::storage::{
+    DeviceStatusStorage, SensorReadingsStorage, StorageMaintenance, StorageStats,
+};
+
+/// Page served at `/local` for technicians on the farm network.
+const LOCAL_DASHBOARD_HTML: &str = include_str!("../assets/local.html");
+
+/// State shared by the dispatcher's local HTTP API.
+#[derive(Clone)]
+pub struct HttpState<S> {
+    pub storage: S,
+    pub scheduler: UploadScheduler,
+    pub identity: IdentityStore,
+    pub rpc_stats: Arc<RpcStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+    pub identity: DispatcherIdentity,
+    pub storage: StorageStats,
+    pub budget: BudgetStats,
+    /// Calls made to ersha-prime, by message type.
+    pub rpc: BTreeMap<&'static str, CallStats>,
+}
+
+/// A device as seen locally by this dispatcher.
+#[derive(Debug, Serialize)]
+pub struct LocalDevice {
+    pub device_id: DeviceId,
+    /// Timestamp of the newest reading from any of the device's sensors.
+    pub last_reading_at: Option<jiff::Timestamp>,
+    /// Number of sensors that have reported readings.
+    pub sensor_count: usize,
+    /// Newest status report from the device.
+    pub last_status: Option<DeviceStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LatestReadingsQuery {
+    pub device_id: Option<Ulid>,
+}
+
+pub fn router<S>(state: HttpState<S>) -> Router
+where
+    S: SensorReadingsStorage + DeviceStatusStorage + StorageMaintenance,
+{
+    Router::new()
+        .route("/health", get(health_handler))
+        .route("/stats", get(stats_handler::<S>))
+        .route("/local", get(local_dashboard_handler))
+        .route("/local/devices", get(local_devices_handler::<S>))
+        .route(
+            "/local/latest-readings",
+            get(local_latest_readings_handler::<S>),
+        )
+        .with_state(state)
+}
+
+async fn health_handler() -> &'static str {
+    "OK"
+}
+
+async fn stats_handler<S>(
+    State(state): State<HttpState<S>>,
+) -> Result<Json<StatsResponse>, StatusCode>
+where
+    S: StorageMaintenance,
+{
+    let storage = state.storage.get_stats().await.map_err(|e| {
+        error!(error = ?e, "Failed to read storage stats");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(StatsResponse {
+        identity: state.identity.identity().await,
+        storage,
+        budget: state.scheduler.stats(&jiff::Zoned::now()).await,
+        rpc: state.rpc_stats.snapshot(),
+    }))
+}
+
+async fn local_dashboard_handler() -> Html<&'static str> {
+    Html(LOCAL_DASHBOARD_HTML)
+}
+
+async fn local_devices_handler<S>(
+    State(state): State<HttpState<S>>,
+) -> Result<Json<Vec<LocalDevice>>, StatusCode>
+where
+    S: SensorReadingsStorage + DeviceStatusStorage,
+{
+    let readings = SensorReadingsStorage::fetch_latest(&state.storage)
+        .await
+        .map_err(|e| {
+            error!(error = ?e, "Failed to fetch latest readings");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let statuses = DeviceStatusStorage::fetch_latest(&state.storage)
+        .await
+        .map_err(|e| {
+            error!(error = ?e, "Failed to fetch latest statuses");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut devices: HashMap<DeviceId, LocalDevice> = HashMap::new();
+
+    for reading in readings {
+        let device = devices
+            .entry(reading.device_id)
+            .or_insert_with(|| LocalDevice {
+                device_id: reading.device_id,
+                last_reading_at: None,
+                sensor_count: 0,
+                last_status: None,
+            });
+        device.sensor_count += 1;
+        device.last_reading_at = device.last_reading_at.max(Some(reading.timestamp));
+    }
+
+    for status in statuses {
+        let device = devices
+            .entry(status.device_id)
+            .or_insert_with(|| LocalDevice {
+                device_id: status.device_id,
+                last_reading_at: None,
+                sensor_count: 0,
+                last_status: None,
+            });
+        device.last_status = Some(status);
+    }
+
+    let mut devices: Vec<_> = devices.into_values().collect();
+    devices.sort_by_key(|d| d.device_id.0);
+
+    Ok(Json(devices))
+}
+
+async fn local_latest_readings_handler<S>(
+    State(state): State<HttpState<S>>,
+    Query(query): Query<LatestReadingsQuery>,
+) -> Result<Json<Vec<SensorReading>>, StatusCode>
+where
+    S: SensorReadingsStorage,
+{
+    let mut readings = SensorReadingsStorage::fetch_latest(&state.storage)
+        .await
+        .map_err(|e| {
+            error!(error = ?e, "Failed to fetch latest readings");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if let Some(device_id) = query.device_id {
+        readings.retain(|r| r.device_id == DeviceId(device_id));
+    }
+    readings.sort_by_key(|r| (r.device_id.0, r.sensor_id.0));
+
+    Ok(Json(readings))
+}
@@ -0,0 +1,219 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ersha_core::{DispatcherId, HelloRejectionReason};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tracing::warn;
+use ulid::Ulid;
+
+/// Provisioning state of this dispatcher as last reported by ersha-prime.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum ProvisioningState {
+    /// The dispatcher has not completed a hello with ersha-prime yet.
+    Unprovisioned,
+    /// ersha-prime accepted the dispatcher; uploads are allowed.
+    Accepted { at: jiff::Timestamp },
+    /// ersha-prime rejected the dispatcher; data is buffered locally only.
+    Rejected {
+        reason: HelloRejectionReason,
+        at: jiff::Timestamp,
+    },
+}
+
+impl ProvisioningState {
+    pub fn is_rejected(&self) -> bool {
+        matches!(self, ProvisioningState::Rejected { .. })
+    }
+}
+
+/// Identity of this dispatcher, persisted across restarts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DispatcherIdentity {
+    pub id: DispatcherId,
+    pub provisioning: ProvisioningState,
+}
+
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serde json error: {0}")]
+    SerdeJson(#[from] serde_json::Error),
+}
+
+/// File-backed store for the dispatcher identity.
+///
+/// The identity is written to a temporary file and renamed into place so a
+/// crash mid-write never leaves a truncated identity behind.
+#[derive(Clone)]
+pub struct IdentityStore {
+    path: PathBuf,
+    identity: Arc<RwLock<DispatcherIdentity>>,
+}
+
+impl IdentityStore {
+    /// Load the identity at `path`, creating it on first start.
+    ///
+    /// When `configured_id` is set it takes precedence over the persisted id.
+    /// Changing the id resets the provisioning state.
+    pub async fn open(
+        path: impl AsRef<Path>,
+        configured_id: Option<DispatcherId>,
+    ) -> Result<Self, IdentityError> {
+        let path = path.as_ref().to_path_buf();
+
+        let persisted = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => Some(serde_json::from_str::<DispatcherIdentity>(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let identity = match (persisted, configured_id) {
+            (Some(persisted), Some(id)) if persisted.id != id => {
+                warn!(
+                    persisted_id = ?persisted.id,
+                    configured_id = ?id,
+                    "Configured dispatcher ID differs from persisted identity, using configured ID"
+                );
+                DispatcherIdentity {
+                    id,
+                    provisioning: ProvisioningState::Unprovisioned,
+                }
+            }
+            (Some(persisted), _) => persisted,
+            (None, id) => DispatcherIdentity {
+                id: id.unwrap_or_else(|| DispatcherId(Ulid::new())),
+                provisioning: ProvisioningState::Unprovisioned,
+            },
+        };
+
+        let store = Self {
+            path,
+            identity: Arc::new(RwLock::new(identity.clone())),
+        };
+        store.persist(&identity).await?;
+
+        Ok(store)
+    }
+
+    pub async fn identity(&self) -> DispatcherIdentity {
+        self.identity.read().await.clone()
+    }
+
+    pub async fn id(&self) -> DispatcherId {
+        self.identity.read().await.id
+    }
+
+    /// Record a new provisioning state, persisting it if it changed.
+    pub async fn set_provisioning(&self, state: ProvisioningState) -> Result<(), IdentityError> {
+        let mut identity = self.identity.write().await;
+
+        if same_state(&identity.provisioning, &state) {
+            return Ok(());
+        }
+
+        let updated = DispatcherIdentity {
+            id: identity.id,
+            provisioning: state,
+        };
+        self.persist(&updated).await?;
+        *identity = updated;
+
+        Ok(())
+    }
+
+    async fn persist(&self, identity: &DispatcherIdentity) -> Result<(), IdentityError> {
+        let json = serde_json::to_string_pretty(identity)?;
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+
+        Ok(())
+    }
+}
+
+/// Compare provisioning states ignoring when they were recorded.
+fn same_state(a: &ProvisioningState, b: &ProvisioningState) -> bool {
+    match (a, b) {
+        (ProvisioningState::Unprovisioned, ProvisioningState::Unprovisioned) => true,
+        (ProvisioningState::Accepted { .. }, ProvisioningState::Accepted { .. }) => true,
+        (
+            ProvisioningState::Rejected { reason: a, .. },
+            ProvisioningState::Rejected { reason: b, .. },
+        ) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdentityError, IdentityStore, ProvisioningState};
+    use ersha_core::{DispatcherId, HelloRejectionReason};
+    use ulid::Ulid;
+
+    fn temp_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ersha-identity-{}.json", Ulid::new()))
+    }
+
+    #[tokio::test]
+    async fn identity_is_reused_across_opens() -> Result<(), IdentityError> {
+        let path = temp_path();
+
+        let first = IdentityStore::open(&path, None).await?.id().await;
+        let second = IdentityStore::open(&path, None).await?.id().await;
+
+        assert_eq!(first, second);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn configured_id_overrides_persisted() -> Result<(), IdentityError> {
+        let path = temp_path();
+
+        let store = IdentityStore::open(&path, None).await?;
+        store
+            .set_provisioning(ProvisioningState::Accepted {
+                at: jiff::Timestamp::now(),
+            })
+            .await?;
+
+        let configured = DispatcherId(Ulid::new());
+        let identity = IdentityStore::open(&path, Some(configured))
+            .await?
+            .identity()
+            .await;
+
+        assert_eq!(identity.id, configured);
+        assert_eq!(identity.provisioning, ProvisioningState::Unprovisioned);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejection_is_persisted() -> Result<(), IdentityError> {
+        let path = temp_path();
+
+        let store = IdentityStore::open(&path, None).await?;
+        store
+            .set_provisioning(ProvisioningState::Rejected {
+                reason: HelloRejectionReason::Suspended,
+                at: jiff::Timestamp::now(),
+            })
+            .await?;
+
+        let identity = IdentityStore::open(&path, None).await?.identity().await;
+        assert!(identity.provisioning.is_rejected());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,10 +1,23 @@
+pub mod budget;
 pub mod config;
 pub mod edge;
+pub mod http;
+pub mod identity;
+pub mod liveness;
+pub mod rpc_stats;
 pub mod storage;
 
-pub use config::{Config, DispatcherConfig, EdgeConfig, PrimeConfig, ServerConfig, StorageConfig};
+pub use budget::{BudgetStats, UploadPlan, UploadScheduler};
+pub use config::{
+    Config, DecoderConfig, DeviceConfig, DispatcherConfig, EdgeConfig, OffPeakWindow, PrimeConfig,
+    PrimeTlsConfig, ServerConfig, StorageConfig, UplinkConfig,
+};
+pub use edge::decoder::{DecodedMetric, DecoderError, DecoderRegistry, PayloadDecoder};
 pub use edge::mock::MockEdgeReceiver;
-pub use edge::{EdgeData, EdgeReceiver};
+pub use edge::{EdgeData, EdgeReceiver, Uplink};
+pub use identity::{DispatcherIdentity, IdentityStore, ProvisioningState};
+pub use liveness::DeviceWatch;
+pub use rpc_stats::{CallStats, RpcStats};
 pub use storage::memory::MemoryStorage;
 pub use storage::sqlite::SqliteStorage;
 pub use storage::{DeviceStatusStorage, SensorReadingsStorage, StorageMaintenance};
@@ -0,0 +1,176 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ersha_core::{DeviceDisconnection, DeviceDisconnectionRequest, DeviceId, DispatcherId};
+use jiff::{SignedDuration, Timestamp};
+
+/// Notices devices that stopped sending readings and statuses, so
+/// ersha-prime can list them offline until they report again.
+#[derive(Debug)]
+pub struct DeviceWatch {
+    disconnect_after: SignedDuration,
+    devices: Mutex<Watched>,
+}
+
+#[derive(Debug, Default)]
+struct Watched {
+    /// When each connected device was last heard from.
+    last_seen: HashMap<DeviceId, Timestamp>,
+    /// Devices gone quiet, with whether ersha-prime was told.
+    disconnected: HashMap<DeviceId, (Timestamp, bool)>,
+    /// Devices ersha-prime was told about that reported again.
+    reconnected: HashSet<DeviceId>,
+}
+
+impl DeviceWatch {
+    /// Devices not heard from for `disconnect_after` are disconnected.
+    pub fn new(disconnect_after: Duration) -> Self {
+        Self {
+            disconnect_after: SignedDuration::try_from(disconnect_after)
+                .unwrap_or(SignedDuration::MAX),
+            devices: Mutex::default(),
+        }
+    }
+
+    /// Record a reading or status from a device.
+    pub fn seen(&self, device_id: DeviceId, at: Timestamp) {
+        let mut devices = self.devices.lock().expect("device watch lock poisoned");
+        if let Some((_, true)) = devices.disconnected.remove(&device_id) {
+            devices.reconnected.insert(device_id);
+        }
+        let last_seen = devices.last_seen.entry(device_id).or_insert(at);
+        *last_seen = (*last_seen).max(at);
+    }
+
+    /// Disconnect the devices not heard from for too long by `now`.
+    /// Returns how many were newly disconnected.
+    pub fn check(&self, now: Timestamp) -> usize {
+        let mut devices = self.devices.lock().expect("device watch lock poisoned");
+        let quiet: Vec<_> = devices
+            .last_seen
+            .iter()
+            .filter(|(_, last_seen)| now.duration_since(**last_seen) >= self.disconnect_after)
+            .map(|(&device_id, &last_seen)| (device_id, last_seen))
+            .collect();
+        for &(device_id, last_seen) in &quiet {
+            devices.last_seen.remove(&device_id);
+            devices.disconnected.insert(device_id, (last_seen, false));
+        }
+
+        quiet.len()
+    }
+
+    /// Devices ersha-prime hasn't been told about yet, if any.
+    pub fn pending(&self, dispatcher_id: DispatcherId) -> Option<DeviceDisconnectionRequest> {
+        let devices = self.devices.lock().expect("device watch lock poisoned");
+        let disconnected: Box<[_]> = devices
+            .disconnected
+            .iter()
+            .filter(|(_, (_, reported))| !reported)
+            .map(|(&device_id, &(last_seen, _))| DeviceDisconnection {
+                device_id,
+                last_seen,
+            })
+            .collect();
+        if disconnected.is_empty() && devices.reconnected.is_empty() {
+            return None;
+        }
+
+        Some(DeviceDisconnectionRequest {
+            dispatcher_id,
+            disconnected,
+            reconnected: devices.reconnected.iter().copied().collect(),
+        })
+    }
+
+    /// Mark what `request` told ersha-prime as reported.
+    pub fn reported(&self, request: &DeviceDisconnectionRequest) {
+        let mut devices = self.devices.lock().expect("device watch lock poisoned");
+        for disconnection in request.disconnected.iter() {
+            // Unless it reported again since, and maybe went quiet again.
+            match devices.disconnected.get_mut(&disconnection.device_id) {
+                Some((last_seen, reported)) if *last_seen == disconnection.last_seen => {
+                    *reported = true;
+                }
+                _ => {}
+            }
+        }
+        for device_id in request.reconnected.iter() {
+            devices.reconnected.remove(device_id);
+        }
+    }
+
+    /// Number of devices currently disconnected.
+    pub fn disconnected(&self) -> usize {
+        let devices = self.devices.lock().expect("device watch lock poisoned");
+        devices.disconnected.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ulid::Ulid;
+
+    use super::*;
+
+    #[test]
+    fn quiet_devices_are_reported_once() {
+        let watch = DeviceWatch::new(Duration::from_secs(180));
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let (chatty, quiet) = (DeviceId(Ulid::new()), DeviceId(Ulid::new()));
+        let start = Timestamp::now();
+        let mins = |n| start + SignedDuration::from_mins(n);
+
+        watch.seen(chatty, start);
+        watch.seen(quiet, start);
+        assert_eq!(watch.check(mins(2)), 0);
+        assert_eq!(watch.pending(dispatcher_id), None);
+
+        watch.seen(chatty, mins(2));
+        assert_eq!(watch.check(mins(3)), 1);
+        let request = watch.pending(dispatcher_id).unwrap();
+        assert_eq!(
+            *request.disconnected,
+            [DeviceDisconnection {
+                device_id: quiet,
+                last_seen: start,
+            }]
+        );
+        assert!(request.reconnected.is_empty());
+
+        // Not reported yet, so asked again.
+        assert_eq!(watch.pending(dispatcher_id), Some(request.clone()));
+        watch.reported(&request);
+        assert_eq!(watch.pending(dispatcher_id), None);
+        assert_eq!(watch.disconnected(), 1);
+
+        watch.seen(quiet, mins(10));
+        let request = watch.pending(dispatcher_id).unwrap();
+        assert!(request.disconnected.is_empty());
+        assert_eq!(*request.reconnected, [quiet]);
+        watch.reported(&request);
+        assert_eq!(watch.pending(dispatcher_id), None);
+        assert_eq!(watch.disconnected(), 0);
+    }
+
+    #[test]
+    fn devices_back_before_being_reported_are_forgotten() {
+        let watch = DeviceWatch::new(Duration::from_secs(60));
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let device_id = DeviceId(Ulid::new());
+        let start = Timestamp::now();
+
+        watch.seen(device_id, start);
+        assert_eq!(watch.check(start + SignedDuration::from_mins(5)), 1);
+        let stale = watch.pending(dispatcher_id).unwrap();
+
+        watch.seen(device_id, start + SignedDuration::from_mins(6));
+        assert_eq!(watch.pending(dispatcher_id), None);
+
+        // A late answer to the report doesn't mark the device disconnected.
+        assert_eq!(watch.check(start + SignedDuration::from_mins(8)), 1);
+        watch.reported(&stale);
+        assert!(watch.pending(dispatcher_id).is_some());
+    }
+}
@@ -1,18 +1,30 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use axum::{Router, routing::get};
 use clap::Parser;
-use ersha_core::{BatchId, BatchUploadRequest, DispatcherId, H3Cell, HelloRequest};
+use ersha_core::{
+    BatchId, BatchUploadRequest, BatchUploadResponse, ChunkLimits, Compression,
+    DeviceDisconnectionResponse, DispatcherId, DispatcherStatus, DispatcherStatusResponse, H3Cell,
+    HelloRejectionReason, HelloRequest, HelloResponse, ItemOutcome, LinkQuality, ReadingId,
+    StatusId,
+};
 use ersha_dispatch::{
-    Config, DeviceStatusStorage, EdgeConfig, EdgeData, EdgeReceiver, MemoryStorage,
-    MockEdgeReceiver, SensorReadingsStorage, SqliteStorage, StorageConfig,
+    Config, DecoderRegistry, DeviceStatusStorage, DeviceWatch, EdgeConfig, EdgeData, EdgeReceiver,
+    IdentityStore, MemoryStorage, MockEdgeReceiver, PrimeTlsConfig, ProvisioningState, RpcStats,
+    SensorReadingsStorage, SqliteStorage, StorageConfig, StorageMaintenance, UploadPlan,
+    UploadScheduler,
+    http::{self, HttpState},
 };
-use ersha_rpc::Client;
+use ersha_rpc::tls::{self, TlsConnector, rustls::pki_types::ServerName};
+use ersha_rpc::{Client, CorrelationId, Keepalive, MAX_CHUNK_BYTES, auth, correlated, ws};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
+use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info, warn};
+use tracing::{Instrument, debug, error, info, warn};
 use ulid::Ulid;
 
 #[derive(Parser)]
@@ -45,11 +57,32 @@ async fn main() -> color_eyre::Result<()> {
         Config::default()
     };
 
-    let dispatcher_id: DispatcherId = DispatcherId(config.dispatcher.id.parse().map_err(|e| {
-        color_eyre::eyre::eyre!("invalid dispatcher ID '{}': {}", config.dispatcher.id, e)
-    })?);
+    let configured_id = config
+        .dispatcher
+        .id
+        .as_deref()
+        .map(|id| {
+            id.parse()
+                .map(DispatcherId)
+                .map_err(|e| color_eyre::eyre::eyre!("invalid dispatcher ID '{}': {}", id, e))
+        })
+        .transpose()?;
+
+    let decoders = DecoderRegistry::from_config(&config.decoders)?;
+    info!(profiles = ?decoders.profiles(), "Payload decoders loaded");
+
+    let identity = IdentityStore::open(&config.dispatcher.identity_path, configured_id).await?;
+    let dispatcher_id = identity.id().await;
     let location = H3Cell(config.dispatcher.location);
 
+    if let ProvisioningState::Rejected { reason, at } = identity.identity().await.provisioning {
+        warn!(
+            ?reason,
+            %at,
+            "Dispatcher was rejected by ersha-prime, uploads stay paused until it is accepted"
+        );
+    }
+
     info!(
         dispatcher_id = ?dispatcher_id,
         location = ?location,
@@ -62,12 +95,12 @@ async fn main() -> color_eyre::Result<()> {
         StorageConfig::Memory => {
             info!("Using in-memory storage");
             let storage = MemoryStorage::default();
-            run_dispatcher(config, storage, dispatcher_id, location).await?;
+            run_dispatcher(config, storage, identity, location, decoders).await?;
         }
         StorageConfig::Sqlite { ref path } => {
             info!(path = ?path, "Using SQLite storage");
             let storage = SqliteStorage::new(path).await?;
-            run_dispatcher(config, storage, dispatcher_id, location).await?;
+            run_dispatcher(config, storage, identity, location, decoders).await?;
         }
     }
 
@@ -77,15 +110,25 @@ async fn main() -> color_eyre::Result<()> {
 async fn run_dispatcher<S>(
     config: Config,
     storage: S,
-    dispatcher_id: DispatcherId,
+    identity: IdentityStore,
     location: H3Cell,
+    decoders: DecoderRegistry,
 ) -> color_eyre::Result<()>
 where
-    S: SensorReadingsStorage + DeviceStatusStorage + Clone + Send + Sync + 'static,
+    S: SensorReadingsStorage
+        + DeviceStatusStorage
+        + StorageMaintenance
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     <S as SensorReadingsStorage>::Error: std::error::Error + Send + Sync + 'static,
     <S as DeviceStatusStorage>::Error: std::error::Error + Send + Sync + 'static,
 {
     let cancel = CancellationToken::new();
+    let scheduler = UploadScheduler::new(&config.uplink);
+    let dispatcher_id = identity.id().await;
+    let watch = Arc::new(DeviceWatch::new(config.devices.disconnect_after()));
 
     // Create edge receiver based on config
     let edge_receiver = match &config.edge {
@@ -113,23 +156,75 @@ where
 
     // Spawn data collector task
     let storage_for_collector = storage.clone();
+    let watch_for_collector = watch.clone();
     let cancel_for_collector = cancel.clone();
     let collector_handle = tokio::spawn(async move {
-        run_data_collector(edge_rx, storage_for_collector, cancel_for_collector).await;
+        run_data_collector(
+            edge_rx,
+            storage_for_collector,
+            &watch_for_collector,
+            Uplinks {
+                decoders,
+                dispatcher_id,
+                location,
+            },
+            cancel_for_collector,
+        )
+        .await;
     });
 
     // Spawn uploader task
     let storage_for_uploader = storage.clone();
+    let scheduler_for_uploader = scheduler.clone();
+    let identity_for_uploader = identity.clone();
+    let rpc_stats = Arc::new(RpcStats::default());
     let cancel_for_uploader = cancel.clone();
-    let prime_addr = config.prime.rpc_addr;
-    let upload_interval = Duration::from_secs(config.prime.upload_interval_secs);
+    let tunnel = config
+        .prime
+        .tunnel_url
+        .as_deref()
+        .map(UplinkTunnel::new)
+        .transpose()?;
+    let uplink = UplinkSettings {
+        prime_addr: config.prime.rpc_addr,
+        location,
+        secret: config.dispatcher.secret.clone(),
+        upload_interval: Duration::from_secs(config.prime.upload_interval_secs),
+        upload_concurrency: config.prime.upload_concurrency.max(1),
+        max_batch_size: config.prime.max_batch_size,
+        tls: match &tunnel {
+            // Plain ws:// runs over TCP.
+            Some(tunnel) if !tunnel.endpoint.secure => None,
+            Some(tunnel) => {
+                let Some(tls) = &config.prime.tls else {
+                    color_eyre::eyre::bail!("a wss:// prime.tunnel_url needs prime.tls");
+                };
+                let server_name = ServerName::try_from(tunnel.endpoint.host.clone())?;
+                Some(UplinkTls::new(tls, server_name)?)
+            }
+            None => config
+                .prime
+                .tls
+                .as_ref()
+                .map(|tls| {
+                    UplinkTls::new(
+                        tls,
+                        ServerName::IpAddress(config.prime.rpc_addr.ip().into()),
+                    )
+                })
+                .transpose()?,
+        },
+        tunnel,
+        keepalive: config.prime.keepalive(),
+        rpc_stats: rpc_stats.clone(),
+        watch,
+    };
     let uploader_handle = tokio::spawn(async move {
         run_uploader(
             storage_for_uploader,
-            prime_addr,
-            dispatcher_id,
-            location,
-            upload_interval,
+            scheduler_for_uploader,
+            identity_for_uploader,
+            uplink,
             cancel_for_uploader,
         )
         .await;
@@ -137,7 +232,12 @@ where
 
     // HTTP server
     let http_addr = config.server.http_addr;
-    let axum_app = Router::new().route("/health", get(health_handler));
+    let axum_app = http::router(HttpState {
+        storage,
+        scheduler,
+        identity,
+        rpc_stats,
+    });
     let axum_listener = TcpListener::bind(http_addr).await?;
     info!(%http_addr, "HTTP server listening");
 
@@ -166,9 +266,19 @@ where
     Ok(())
 }
 
+/// How encoded uplinks are turned into readings.
+struct Uplinks {
+    decoders: DecoderRegistry,
+    dispatcher_id: DispatcherId,
+    /// Where decoded readings are located
+    location: H3Cell,
+}
+
 async fn run_data_collector<S>(
     mut edge_rx: mpsc::Receiver<EdgeData>,
     storage: S,
+    watch: &DeviceWatch,
+    uplinks: Uplinks,
     cancel: CancellationToken,
 ) where
     S: SensorReadingsStorage + DeviceStatusStorage,
@@ -184,6 +294,12 @@ async fn run_data_collector<S>(
                 break;
             }
             Some(data) = edge_rx.recv() => {
+                let device_id = match &data {
+                    EdgeData::Reading(reading) => reading.device_id,
+                    EdgeData::Status(status) => status.device_id,
+                    EdgeData::Uplink(uplink) => uplink.device_id,
+                };
+                watch.seen(device_id, jiff::Timestamp::now());
                 match data {
                     EdgeData::Reading(reading) => {
                         let reading_id = reading.id;
@@ -201,34 +317,133 @@ async fn run_data_collector<S>(
                             info!(status_id = ?status_id, "Stored device status");
                         }
                     }
+                    EdgeData::Uplink(uplink) => {
+                        let readings = match uplinks.decoders.readings(
+                            &uplink,
+                            uplinks.dispatcher_id,
+                            uplinks.location,
+                        ) {
+                            Ok(readings) => readings,
+                            Err(e) => {
+                                warn!(
+                                    error = %e,
+                                    device_id = ?uplink.device_id,
+                                    fport = ?uplink.fport,
+                                    "Dropping undecodable uplink"
+                                );
+                                continue;
+                            }
+                        };
+                        let count = readings.len();
+                        if let Err(e) = SensorReadingsStorage::store_batch(&storage, readings).await {
+                            error!(error = ?e, device_id = ?uplink.device_id, "Failed to store decoded readings");
+                        } else {
+                            info!(device_id = ?uplink.device_id, count, "Stored decoded readings");
+                        }
+                    }
                 }
             }
         }
     }
 }
 
-async fn run_uploader<S>(
-    storage: S,
-    prime_addr: std::net::SocketAddr,
-    dispatcher_id: DispatcherId,
+/// How the uploader reaches ersha-prime.
+struct UplinkSettings {
+    prime_addr: SocketAddr,
     location: H3Cell,
+    /// Shared secret used to sign the hello, if provisioned
+    secret: Option<String>,
     upload_interval: Duration,
+    upload_concurrency: usize,
+    max_batch_size: usize,
+    tls: Option<UplinkTls>,
+    /// WebSocket to tunnel through instead of connecting to `prime_addr`
+    tunnel: Option<UplinkTunnel>,
+    keepalive: Option<Keepalive>,
+    /// Where calls to ersha-prime are counted
+    rpc_stats: Arc<RpcStats>,
+    /// Devices to report disconnected
+    watch: Arc<DeviceWatch>,
+}
+
+/// A WebSocket URL to tunnel RPC through, and where it says to connect.
+struct UplinkTunnel {
+    url: String,
+    endpoint: ws::Endpoint,
+}
+
+impl UplinkTunnel {
+    fn new(url: &str) -> color_eyre::Result<Self> {
+        Ok(Self {
+            url: url.to_owned(),
+            endpoint: ws::Endpoint::parse(url)?,
+        })
+    }
+}
+
+/// TLS to ersha-prime, verified against the configured server name.
+struct UplinkTls {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl UplinkTls {
+    /// TLS as configured, verifying `default_server_name` unless the config
+    /// names another.
+    fn new(
+        config: &PrimeTlsConfig,
+        default_server_name: ServerName<'static>,
+    ) -> color_eyre::Result<Self> {
+        let identity = match (&config.cert, &config.key) {
+            (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+            (None, None) => None,
+            _ => color_eyre::eyre::bail!("prime.tls needs both cert and key, or neither"),
+        };
+        let server_name = match &config.server_name {
+            Some(name) => ServerName::try_from(name.clone())?,
+            None => default_server_name,
+        };
+
+        Ok(Self {
+            connector: TlsConnector::from(tls::client_config(&config.ca, identity)?),
+            server_name,
+        })
+    }
+}
+
+async fn run_uploader<S>(
+    storage: S,
+    scheduler: UploadScheduler,
+    identity: IdentityStore,
+    uplink: UplinkSettings,
     cancel: CancellationToken,
 ) where
     S: SensorReadingsStorage + DeviceStatusStorage,
     <S as SensorReadingsStorage>::Error: std::error::Error,
     <S as DeviceStatusStorage>::Error: std::error::Error,
 {
+    let upload_concurrency = uplink.upload_concurrency;
+
     info!(
-        prime_addr = %prime_addr,
-        upload_interval_secs = upload_interval.as_secs(),
+        prime_addr = %uplink.prime_addr,
+        upload_interval_secs = uplink.upload_interval.as_secs(),
+        upload_concurrency,
+        max_batch_size = uplink.max_batch_size,
+        authenticated = uplink.secret.is_some(),
         "Uploader started"
     );
 
-    let mut interval = tokio::time::interval(upload_interval);
-    let mut client: Option<Client> = None;
+    let dispatcher_id = identity.id().await;
+    let mut interval = tokio::time::interval(uplink.upload_interval);
+    // Connection to ersha-prime, shared by the uploads pipelined over it.
+    let mut connection: Option<Arc<PrimeConnection>> = None;
     let mut backoff = Duration::from_secs(1);
     const MAX_BACKOFF: Duration = Duration::from_secs(60);
+    // Link quality carried in the next status report.
+    let started = Instant::now();
+    let mut last_rtt_ms = None;
+    let mut failed_uploads = 0u32;
+    let mut batch_ids = BatchIds::default();
 
     loop {
         tokio::select! {
@@ -237,12 +452,27 @@ async fn run_uploader<S>(
                 break;
             }
             _ = interval.tick() => {
-                // Ensure we have a connected and registered client
-                if client.is_none() {
-                    match connect_and_register(prime_addr, dispatcher_id, location).await {
-                        Ok(c) => {
-                            client = Some(c);
+                // A connection that stopped answering keepalives is dead
+                if connection.as_ref().is_some_and(|c| c.client.is_closed()) {
+                    warn!("Connection to ersha-prime stopped responding, reconnecting");
+                    connection = None;
+                }
+                // Prime is shutting down and has answered what was sent
+                if connection.as_ref().is_some_and(|c| c.client.is_going_away()) {
+                    info!("ersha-prime is going away, reconnecting");
+                    connection = None;
+                }
+                // Connect and register unless still connected
+                if connection.is_none() {
+                    match connect_and_register(&uplink, &identity).await {
+                        Ok(Some(c)) => {
+                            connection = Some(Arc::new(c));
+                            backoff = Duration::from_secs(1);
+                        }
+                        Ok(None) => {
+                            // Rejected by prime: keep buffering and ask again next tick.
                             backoff = Duration::from_secs(1);
+                            continue;
                         }
                         Err(e) => {
                             warn!(error = %e, backoff_secs = backoff.as_secs(), "Failed to connect to ersha-prime, will retry");
@@ -252,8 +482,9 @@ async fn run_uploader<S>(
                         }
                     }
                 }
-
-                let c = client.as_ref().unwrap();
+                let Some(prime) = connection.clone() else {
+                    continue;
+                };
 
                 // Fetch pending data
                 let readings = match SensorReadingsStorage::fetch_pending(&storage).await {
@@ -272,70 +503,400 @@ async fn run_uploader<S>(
                     }
                 };
 
+                // Report every tick, even when idle, so prime knows we're alive.
+                let status = DispatcherStatus {
+                    dispatcher_id,
+                    pending_readings: readings.len() as u64,
+                    pending_statuses: statuses.len() as u64,
+                    link: LinkQuality {
+                        rtt_ms: last_rtt_ms,
+                        failed_uploads,
+                    },
+                    uptime_seconds: started.elapsed().as_secs(),
+                    timestamp: jiff::Timestamp::now(),
+                };
+                let sent = Instant::now();
+                match prime.client.dispatcher_status(status).await {
+                    Ok(DispatcherStatusResponse::Accepted) => {
+                        last_rtt_ms = Some(u32::try_from(sent.elapsed().as_millis()).unwrap_or(u32::MAX));
+                        failed_uploads = 0;
+                    }
+                    Ok(DispatcherStatusResponse::Rejected { reason }) => {
+                        warn!(?reason, "Status report rejected by ersha-prime");
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "Failed to send status report, dropping connection");
+                        connection = None;
+                        continue;
+                    }
+                }
+
+                let quiet = uplink.watch.check(jiff::Timestamp::now());
+                if quiet > 0 {
+                    warn!(devices = quiet, "Devices stopped reporting");
+                }
+                if let Some(request) = uplink.watch.pending(dispatcher_id) {
+                    match prime.client.device_disconnection(request.clone()).await {
+                        Ok(DeviceDisconnectionResponse::Accepted) => {
+                            info!(
+                                disconnected = request.disconnected.len(),
+                                reconnected = request.reconnected.len(),
+                                "Reported device disconnections"
+                            );
+                            uplink.watch.reported(&request);
+                        }
+                        Ok(DeviceDisconnectionResponse::Rejected { reason }) => {
+                            warn!(?reason, "Device disconnections rejected by ersha-prime, will retry");
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "Failed to report device disconnections, dropping connection");
+                            connection = None;
+                            continue;
+                        }
+                    }
+                }
+
                 if readings.is_empty() && statuses.is_empty() {
                     tracing::debug!("No pending data to upload");
                     continue;
                 }
 
-                info!(
-                    readings_count = readings.len(),
-                    statuses_count = statuses.len(),
-                    "Uploading batch to ersha-prime"
-                );
+                let now = jiff::Zoned::now();
+                let pending_count = readings.len() + statuses.len();
+                let plan = scheduler
+                    .plan(readings, statuses, uplink.max_batch_size, &now)
+                    .await;
 
-                // Collect IDs for marking as uploaded
-                let reading_ids: Vec<_> = readings.iter().map(|r| r.id).collect();
-                let status_ids: Vec<_> = statuses.iter().map(|s| s.id).collect();
+                if plan.is_empty() {
+                    info!(pending_count, "Uplink budget exhausted, deferring upload");
+                    continue;
+                }
 
-                let batch = BatchUploadRequest {
-                    id: BatchId(Ulid::new()),
-                    dispatcher_id,
-                    readings: readings.into_boxed_slice(),
-                    statuses: statuses.into_boxed_slice(),
-                    timestamp: jiff::Timestamp::now(),
-                };
+                let deferred = pending_count - plan.readings.len() - plan.statuses.len();
+                if deferred > 0 {
+                    tracing::debug!(deferred, "Deferring items to stay within uplink budget");
+                }
+
+                let mut batches = plan.into_batches(uplink.max_batch_size).into_iter();
+                let mut in_flight = JoinSet::new();
+                batch_ids.next_round();
+
+                loop {
+                    // Pipeline batches over the connection while it is up,
+                    // letting those in flight finish if prime is going away
+                    while connection.is_some()
+                        && !prime.client.is_going_away()
+                        && in_flight.len() < upload_concurrency
+                    {
+                        let Some(batch) = batches.next() else {
+                            break;
+                        };
+                        let batch_id = batch_ids.for_batch(&batch);
+                        // Prime logs its handling of the upload under the
+                        // same correlation ID.
+                        let correlation_id = CorrelationId::new();
+                        let span = tracing::info_span!("upload_batch", ?batch_id, %correlation_id);
+                        let upload =
+                            upload_batch(prime.clone(), dispatcher_id, batch_id, correlation_id, batch);
+                        in_flight.spawn(upload.instrument(span));
+                    }
 
-                match c.batch_upload(batch).await {
-                    Ok(resp) => {
-                        info!(batch_id = ?resp.id, "Batch uploaded successfully");
+                    let Some(joined) = in_flight.join_next().await else {
+                        break;
+                    };
 
-                        // Mark data as uploaded
-                        if let Err(e) = SensorReadingsStorage::mark_uploaded(&storage, &reading_ids).await {
-                            error!(error = ?e, "Failed to mark readings as uploaded");
+                    let outcome = match joined {
+                        Ok(joined) => joined,
+                        Err(e) => {
+                            error!(error = ?e, "Upload task failed");
+                            continue;
                         }
-                        if let Err(e) = DeviceStatusStorage::mark_uploaded(&storage, &status_ids).await {
-                            error!(error = ?e, "Failed to mark statuses as uploaded");
+                    };
+
+                    match outcome {
+                        Ok(uploaded) => {
+                            info!(
+                                batch_id = ?uploaded.batch_id,
+                                correlation_id = %uploaded.correlation_id,
+                                readings_count = uploaded.reading_ids.len(),
+                                statuses_count = uploaded.status_ids.len(),
+                                "Batch uploaded successfully"
+                            );
+                            scheduler.record(uploaded.estimated_bytes, &now).await;
+                            batch_ids.answered(uploaded.batch_id);
+
+                            // Mark only this batch's data as uploaded
+                            if let Err(e) = SensorReadingsStorage::mark_uploaded(&storage, &uploaded.reading_ids).await {
+                                error!(error = ?e, "Failed to mark readings as uploaded");
+                            }
+                            if let Err(e) = DeviceStatusStorage::mark_uploaded(&storage, &uploaded.status_ids).await {
+                                error!(error = ?e, "Failed to mark statuses as uploaded");
+                            }
+                        }
+                        Err(e) => {
+                            // The batch stays pending and is retried on a later tick.
+                            error!(error = ?e, "Failed to upload batch, dropping connection");
+                            failed_uploads = failed_uploads.saturating_add(1);
+                            connection = None;
                         }
-                    }
-                    Err(e) => {
-                        error!(error = ?e, "Failed to upload batch, will reconnect");
-                        client = None;
                     }
                 }
+
+                let remaining = batches.len();
+                if remaining > 0 {
+                    warn!(remaining, "Connection lost, remaining batches stay pending");
+                }
             }
         }
     }
 }
 
-async fn connect_and_register(
-    prime_addr: std::net::SocketAddr,
+/// A registered connection to ersha-prime.
+struct PrimeConnection {
+    client: Client,
+    /// Terms for streaming large batches, if prime supports it
+    chunking: Option<ChunkLimits>,
+}
+
+/// A batch acknowledged by ersha-prime.
+struct UploadedBatch {
+    batch_id: BatchId,
+    /// What prime logged its handling of the upload under
+    correlation_id: CorrelationId,
+    reading_ids: Vec<ReadingId>,
+    status_ids: Vec<StatusId>,
+    estimated_bytes: u64,
+}
+
+/// Ids to upload batches under.
+///
+/// A batch sent again with the same items, after its upload failed without
+/// an answer, keeps its id, so ersha-prime can answer the retry from its
+/// record of the first attempt. Ids unused for a whole round are forgotten.
+#[derive(Default)]
+struct BatchIds {
+    /// Batches sent without an answer this round, by their items
+    current: HashMap<(Vec<ReadingId>, Vec<StatusId>), BatchId>,
+    previous: HashMap<(Vec<ReadingId>, Vec<StatusId>), BatchId>,
+}
+
+impl BatchIds {
+    /// Start a round of uploads.
+    fn next_round(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    fn for_batch(&mut self, batch: &UploadPlan) -> BatchId {
+        let items = (
+            batch.readings.iter().map(|r| r.id).collect(),
+            batch.statuses.iter().map(|s| s.id).collect(),
+        );
+        let id = self
+            .previous
+            .remove(&items)
+            .unwrap_or_else(|| BatchId(Ulid::new()));
+        self.current.insert(items, id);
+        id
+    }
+
+    fn answered(&mut self, batch_id: BatchId) {
+        self.current.retain(|_, id| *id != batch_id);
+    }
+}
+
+/// Upload one batch over `connection`, alongside any others in flight on it.
+/// Batches too large for one frame are streamed in chunks when prime agreed
+/// to it.
+///
+/// The upload carries `correlation_id`, which prime logs its handling under.
+/// A batch rejected by prime is reported as an error so it stays pending.
+async fn upload_batch(
+    connection: Arc<PrimeConnection>,
     dispatcher_id: DispatcherId,
-    location: H3Cell,
-) -> color_eyre::Result<Client> {
-    let stream = TcpStream::connect(prime_addr).await?;
-    let client = Client::new(stream);
+    batch_id: BatchId,
+    correlation_id: CorrelationId,
+    batch: UploadPlan,
+) -> color_eyre::Result<UploadedBatch> {
+    // Collect IDs for marking as uploaded
+    let reading_ids: Vec<_> = batch.readings.iter().map(|r| r.id).collect();
+    let status_ids: Vec<_> = batch.statuses.iter().map(|s| s.id).collect();
+    debug!(readings = ?reading_ids, statuses = ?status_ids, "Uploading batch");
+
+    let request = BatchUploadRequest {
+        id: batch_id,
+        dispatcher_id,
+        readings: batch.readings.into_boxed_slice(),
+        statuses: batch.statuses.into_boxed_slice(),
+        timestamp: jiff::Timestamp::now(),
+    };
+
+    let upload = async {
+        match connection.chunking {
+            Some(limits) => {
+                connection
+                    .client
+                    .batch_upload_chunked(request, limits)
+                    .await
+            }
+            None => connection.client.batch_upload(request).await,
+        }
+    };
+    let response = correlated(correlation_id, upload).await;
+
+    match response {
+        Ok(BatchUploadResponse::Accepted {
+            id,
+            readings,
+            statuses,
+        }) => {
+            // Invalid items can never be accepted, so they are marked
+            // uploaded along with the rest instead of being retried forever.
+            let invalid = readings
+                .iter()
+                .map(|r| r.outcome)
+                .chain(statuses.iter().map(|s| s.outcome))
+                .filter(|outcome| matches!(outcome, ItemOutcome::Invalid(_)))
+                .count();
+            if invalid > 0 {
+                warn!(batch_id = ?id, invalid, "ersha-prime rejected invalid items in batch");
+            }
+
+            Ok(UploadedBatch {
+                batch_id: id,
+                correlation_id,
+                reading_ids,
+                status_ids,
+                estimated_bytes: batch.estimated_bytes,
+            })
+        }
+        Ok(BatchUploadResponse::Rejected { id, reason }) => Err(color_eyre::eyre::eyre!(
+            "batch {:?} rejected by ersha-prime: {reason:?}",
+            id
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Connect to ersha-prime through a WebSocket tunnel, over `tls` for `wss://`.
+async fn open_tunnel(tunnel: &UplinkTunnel, tls: Option<&UplinkTls>) -> color_eyre::Result<Client> {
+    let endpoint = &tunnel.endpoint;
+    let stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port)).await?;
+    Ok(match tls {
+        Some(tls) => {
+            let stream = tls
+                .connector
+                .connect(tls.server_name.clone(), stream)
+                .await?;
+            Client::new(ws::connect(&tunnel.url, stream).await?)
+        }
+        None => Client::new(ws::connect(&tunnel.url, stream).await?),
+    })
+}
+
+/// Connect to ersha-prime and perform the hello handshake.
+///
+/// Returns `None` when prime rejects the dispatcher. The outcome is recorded
+/// in the identity store either way.
+async fn connect_and_register(
+    uplink: &UplinkSettings,
+    identity: &IdentityStore,
+) -> color_eyre::Result<Option<PrimeConnection>> {
+    let client = match &uplink.tunnel {
+        Some(tunnel) => open_tunnel(tunnel, uplink.tls.as_ref()).await?,
+        None => {
+            let stream = TcpStream::connect(uplink.prime_addr).await?;
+            match &uplink.tls {
+                Some(tls) => Client::new(
+                    tls.connector
+                        .connect(tls.server_name.clone(), stream)
+                        .await?,
+                ),
+                None => Client::new(stream),
+            }
+        }
+    };
+    let client = client.with_metrics(uplink.rpc_stats.clone());
+    let client = match uplink.keepalive {
+        Some(keepalive) => client.with_keepalive(keepalive),
+        None => client,
+    };
+
+    let dispatcher_id = identity.id().await;
+    let credentials = uplink.secret.as_deref().map(|secret| {
+        auth::sign_hello(
+            secret.as_bytes(),
+            dispatcher_id,
+            uplink.location,
+            jiff::Timestamp::now(),
+            rand::random(),
+        )
+    });
 
     let hello = HelloRequest {
         dispatcher_id,
-        location,
+        location: uplink.location,
+        credentials,
+        max_chunk_bytes: Some(MAX_CHUNK_BYTES),
+        compression: Box::new([Compression::Zstd, Compression::Lz4]),
+        // Commands aren't relayed to devices yet, so nothing to push.
+        accepts_push: false,
     };
 
-    let resp = client.hello(hello).await?;
-    info!(dispatcher_id = ?resp.dispatcher_id, "Registered with ersha-prime");
+    match client.hello(hello).await? {
+        HelloResponse::Accepted {
+            dispatcher_id,
+            proof,
+            chunking,
+            ..
+        } => {
+            if let (Some(secret), Some(credentials)) = (&uplink.secret, &credentials) {
+                match proof {
+                    Some(proof)
+                        if auth::verify_server_proof(secret.as_bytes(), credentials, &proof) => {}
+                    Some(_) => {
+                        return Err(color_eyre::eyre::eyre!(
+                            "ersha-prime returned an invalid hello proof"
+                        ));
+                    }
+                    None => warn!(
+                        "ersha-prime has no secret provisioned for this dispatcher, connection is unauthenticated"
+                    ),
+                }
+            }
 
-    Ok(client)
-}
+            info!(dispatcher_id = ?dispatcher_id, "Registered with ersha-prime");
+            identity
+                .set_provisioning(ProvisioningState::Accepted {
+                    at: jiff::Timestamp::now(),
+                })
+                .await?;
 
-async fn health_handler() -> &'static str {
-    "OK"
+            Ok(Some(PrimeConnection { client, chunking }))
+        }
+        HelloResponse::Rejected {
+            dispatcher_id,
+            reason: HelloRejectionReason::Unavailable,
+        } => {
+            warn!(dispatcher_id = ?dispatcher_id, "ersha-prime could not verify dispatcher, will retry");
+            Ok(None)
+        }
+        HelloResponse::Rejected {
+            dispatcher_id,
+            reason,
+        } => {
+            warn!(
+                dispatcher_id = ?dispatcher_id,
+                ?reason,
+                "Rejected by ersha-prime, buffering data locally"
+            );
+            identity
+                .set_provisioning(ProvisioningState::Rejected {
+                    reason,
+                    at: jiff::Timestamp::now(),
+                })
+                .await?;
+
+            Ok(None)
+        }
+    }
 }
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ersha_rpc::{Outcome, RpcMetrics};
+use serde::Serialize;
+
+/// Calls made to ersha-prime, counted by message type for `/stats`.
+#[derive(Debug, Default)]
+pub struct RpcStats {
+    calls: Mutex<BTreeMap<&'static str, CallStats>>,
+}
+
+/// How the calls of one message type went.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct CallStats {
+    pub calls: u64,
+    /// Calls answered with an error.
+    pub errors: u64,
+    /// Calls not answered in time.
+    pub timeouts: u64,
+    /// Calls lost with their connection.
+    pub failures: u64,
+    /// Mean time to an answer, over calls answered with anything.
+    pub mean_latency_ms: Option<f64>,
+    pub max_latency_ms: u64,
+    #[serde(skip)]
+    answered: u64,
+    #[serde(skip)]
+    total_latency_ms: u64,
+}
+
+impl RpcStats {
+    /// Counts so far, by message type such as `BatchUploadRequest`.
+    pub fn snapshot(&self) -> BTreeMap<&'static str, CallStats> {
+        self.calls.lock().expect("rpc stats lock poisoned").clone()
+    }
+}
+
+impl RpcMetrics for RpcStats {
+    fn call_finished(&self, message: &'static str, outcome: &Outcome, elapsed: Duration) {
+        let mut calls = self.calls.lock().expect("rpc stats lock poisoned");
+        let stats = calls.entry(message).or_default();
+        stats.calls += 1;
+        match outcome {
+            Outcome::Ok => {}
+            Outcome::Error(_) => stats.errors += 1,
+            Outcome::Timeout => stats.timeouts += 1,
+            Outcome::Failed => stats.failures += 1,
+            // Given up on by the dispatcher itself, so no answer to time.
+            Outcome::Cancelled => return,
+        }
+        if matches!(outcome, Outcome::Ok | Outcome::Error(_)) {
+            let elapsed_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+            stats.answered += 1;
+            stats.total_latency_ms = stats.total_latency_ms.saturating_add(elapsed_ms);
+            stats.max_latency_ms = stats.max_latency_ms.max(elapsed_ms);
+            stats.mean_latency_ms = Some(stats.total_latency_ms as f64 / stats.answered as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_rpc::WireErrorCode;
+
+    use super::*;
+
+    #[test]
+    fn calls_are_counted_by_message_and_outcome() {
+        let stats = RpcStats::default();
+        let ms = Duration::from_millis;
+        stats.call_finished("BatchUploadRequest", &Outcome::Ok, ms(100));
+        let limited = Outcome::Error(WireErrorCode::RateLimited);
+        stats.call_finished("BatchUploadRequest", &limited, ms(300));
+        stats.call_finished("BatchUploadRequest", &Outcome::Timeout, ms(5000));
+        stats.call_finished("Ping", &Outcome::Failed, ms(1));
+
+        let snapshot = stats.snapshot();
+        let batches = snapshot["BatchUploadRequest"];
+        assert_eq!(batches.calls, 3);
+        assert_eq!(batches.errors, 1);
+        assert_eq!(batches.timeouts, 1);
+        assert_eq!(batches.mean_latency_ms, Some(200.0));
+        assert_eq!(batches.max_latency_ms, 300);
+        assert_eq!(snapshot["Ping"].failures, 1);
+        assert_eq!(snapshot["Ping"].mean_latency_ms, None);
+    }
+}
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use ersha_core::{DeviceStatus, ReadingId, SensorReading, StatusId};
+use ersha_core::{DeviceId, DeviceStatus, ReadingId, SensorId, SensorReading, StatusId};
 use thiserror::Error;
 use tokio::sync::RwLock;
 
@@ -99,6 +99,25 @@ impl SensorReadingsStorage for MemoryStorage {
 
         Ok(())
     }
+
+    async fn fetch_latest(&self) -> Result<Vec<SensorReading>, Self::Error> {
+        let map = self.sensor_readings.read().await;
+
+        let mut latest: HashMap<(DeviceId, SensorId), &SensorReading> = HashMap::new();
+        for stored in map.values() {
+            let reading = &stored.reading;
+            latest
+                .entry((reading.device_id, reading.sensor_id))
+                .and_modify(|current| {
+                    if reading.timestamp > current.timestamp {
+                        *current = reading;
+                    }
+                })
+                .or_insert(reading);
+        }
+
+        Ok(latest.into_values().cloned().collect())
+    }
 }
 
 #[async_trait]
@@ -160,6 +179,25 @@ impl DeviceStatusStorage for MemoryStorage {
 
         Ok(())
     }
+
+    async fn fetch_latest(&self) -> Result<Vec<DeviceStatus>, Self::Error> {
+        let map = self.device_statuses.read().await;
+
+        let mut latest: HashMap<DeviceId, &DeviceStatus> = HashMap::new();
+        for stored in map.values() {
+            let status = &stored.status;
+            latest
+                .entry(status.device_id)
+                .and_modify(|current| {
+                    if status.timestamp > current.timestamp {
+                        *current = status;
+                    }
+                })
+                .or_insert(status);
+        }
+
+        Ok(latest.into_values().cloned().collect())
+    }
 }
 
 #[async_trait]
@@ -425,6 +463,40 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn memory_fetch_latest() -> Result<(), MemoryStorageError> {
+        let storage: MemoryStorage = MemoryStorage::default();
+
+        let older = dummy_reading();
+        let mut newer = older.clone();
+        newer.id = ReadingId(Ulid::new());
+        newer.timestamp = older.timestamp + jiff::SignedDuration::from_secs(60);
+        let newer_id = newer.id;
+
+        SensorReadingsStorage::store(&storage, older).await?;
+        SensorReadingsStorage::store(&storage, newer).await?;
+        SensorReadingsStorage::store(&storage, dummy_reading()).await?;
+
+        let latest = SensorReadingsStorage::fetch_latest(&storage).await?;
+        assert_eq!(latest.len(), 2);
+        assert!(latest.iter().any(|r| r.id == newer_id));
+
+        let status = dummy_status();
+        let mut newer_status = status.clone();
+        newer_status.id = StatusId(Ulid::new());
+        newer_status.timestamp = status.timestamp + jiff::SignedDuration::from_secs(60);
+        let newer_status_id = newer_status.id;
+
+        DeviceStatusStorage::store(&storage, status).await?;
+        DeviceStatusStorage::store(&storage, newer_status).await?;
+
+        let latest = DeviceStatusStorage::fetch_latest(&storage).await?;
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].id, newer_status_id);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn memory_zero_duration_cleanup() -> Result<(), MemoryStorageError> {
         let storage: MemoryStorage = MemoryStorage::default();
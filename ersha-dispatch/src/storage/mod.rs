@@ -4,6 +4,7 @@ pub mod sqlite;
 
 use async_trait::async_trait;
 use ersha_core::{DeviceStatus, ReadingId, SensorReading, StatusId};
+use serde::Serialize;
 use std::time::Duration;
 
 /// Storage abstraction for sensor readings.
@@ -23,6 +24,9 @@ pub trait SensorReadingsStorage: Clone + Send + Sync + 'static {
 
     /// Mark sensor readings as successfully uploaded.
     async fn mark_uploaded(&self, ids: &[ReadingId]) -> Result<(), Self::Error>;
+
+    /// Fetch the newest stored reading of every sensor, uploaded or not.
+    async fn fetch_latest(&self) -> Result<Vec<SensorReading>, Self::Error>;
 }
 
 /// Storage abstraction for device status events.
@@ -42,6 +46,9 @@ pub trait DeviceStatusStorage: Clone + Send + Sync + 'static {
 
     /// Mark device status events as successfully uploaded.
     async fn mark_uploaded(&self, ids: &[StatusId]) -> Result<(), Self::Error>;
+
+    /// Fetch the newest stored status of every device, uploaded or not.
+    async fn fetch_latest(&self) -> Result<Vec<DeviceStatus>, Self::Error>;
 }
 
 /// Storage abstraction for maintenance operations.
@@ -58,7 +65,7 @@ pub trait StorageMaintenance: Clone + Send + Sync + 'static {
 }
 
 /// Statistics about stored data.
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, Serialize)]
 pub struct StorageStats {
     /// Number of pending sensor readings.
     pub sensor_readings_pending: usize,
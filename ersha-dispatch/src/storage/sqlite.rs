@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use sqlx::{Error as SqlxError, Row, SqlitePool};
+use sqlx::{Error as SqlxError, Row, SqliteConnection, SqlitePool};
 use std::path::Path;
 use std::time::Duration;
 
@@ -68,14 +68,18 @@ impl SqliteStorage {
     fn deserialize_status(json: &str) -> Result<DeviceStatus, SqliteStorageError> {
         Ok(serde_json::from_str(json)?)
     }
-}
 
-#[async_trait]
-impl SensorReadingsStorage for SqliteStorage {
-    type Error = SqliteStorageError;
+    fn nanos(timestamp: jiff::Timestamp) -> i64 {
+        timestamp
+            .as_nanosecond()
+            .clamp(i64::MIN.into(), i64::MAX.into()) as i64
+    }
 
-    async fn store(&self, reading: SensorReading) -> Result<(), Self::Error> {
-        let json = Self::serialize_reading(&reading)?;
+    async fn insert_reading(
+        conn: &mut SqliteConnection,
+        reading: &SensorReading,
+    ) -> Result<(), SqliteStorageError> {
+        let json = Self::serialize_reading(reading)?;
         let id_str = reading.id.0.to_string();
 
         sqlx::query(
@@ -83,9 +87,143 @@ impl SensorReadingsStorage for SqliteStorage {
         )
         .bind(&id_str)
         .bind(&json)
-        .execute(&self.pool)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO latest_readings (device_id, sensor_id, id, timestamp, reading_json)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (device_id, sensor_id) DO UPDATE SET
+                id = excluded.id,
+                timestamp = excluded.timestamp,
+                reading_json = excluded.reading_json
+            WHERE excluded.timestamp > latest_readings.timestamp
+            "#,
+        )
+        .bind(reading.device_id.0.to_string())
+        .bind(reading.sensor_id.0.to_string())
+        .bind(&id_str)
+        .bind(Self::nanos(reading.timestamp))
+        .bind(&json)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn insert_status(
+        conn: &mut SqliteConnection,
+        status: &DeviceStatus,
+    ) -> Result<(), SqliteStorageError> {
+        let json = Self::serialize_status(status)?;
+        let id_str = status.id.0.to_string();
+
+        sqlx::query(
+            "INSERT INTO device_statuses (id, status_json, state) VALUES (?, ?, 'pending')",
+        )
+        .bind(&id_str)
+        .bind(&json)
+        .execute(&mut *conn)
         .await?;
 
+        sqlx::query(
+            r#"
+            INSERT INTO latest_statuses (device_id, id, timestamp, status_json)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (device_id) DO UPDATE SET
+                id = excluded.id,
+                timestamp = excluded.timestamp,
+                status_json = excluded.status_json
+            WHERE excluded.timestamp > latest_statuses.timestamp
+            "#,
+        )
+        .bind(status.device_id.0.to_string())
+        .bind(&id_str)
+        .bind(Self::nanos(status.timestamp))
+        .bind(&json)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Drops latest entries whose row was cleaned up and, only if any were,
+    /// falls back to the newest remaining row for those keys.
+    async fn refresh_latest(conn: &mut SqliteConnection) -> Result<(), SqliteStorageError> {
+        let readings_dropped = sqlx::query(
+            "DELETE FROM latest_readings WHERE id NOT IN (SELECT id FROM sensor_readings)",
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+
+        if readings_dropped > 0 {
+            sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO latest_readings (device_id, sensor_id, id, timestamp, reading_json)
+                SELECT device_id, sensor_id, id, timestamp, reading_json FROM (
+                    SELECT
+                        id,
+                        json_extract(reading_json, '$.device_id') AS device_id,
+                        json_extract(reading_json, '$.sensor_id') AS sensor_id,
+                        CAST((julianday(json_extract(reading_json, '$.timestamp')) - 2440587.5) * 86400000000000 AS INTEGER) AS timestamp,
+                        reading_json,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY json_extract(reading_json, '$.device_id'),
+                                         json_extract(reading_json, '$.sensor_id')
+                            ORDER BY julianday(json_extract(reading_json, '$.timestamp')) DESC
+                        ) AS rn
+                    FROM sensor_readings
+                ) WHERE rn = 1
+                "#,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        let statuses_dropped = sqlx::query(
+            "DELETE FROM latest_statuses WHERE id NOT IN (SELECT id FROM device_statuses)",
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+
+        if statuses_dropped > 0 {
+            sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO latest_statuses (device_id, id, timestamp, status_json)
+                SELECT device_id, id, timestamp, status_json FROM (
+                    SELECT
+                        id,
+                        json_extract(status_json, '$.device_id') AS device_id,
+                        CAST((julianday(json_extract(status_json, '$.timestamp')) - 2440587.5) * 86400000000000 AS INTEGER) AS timestamp,
+                        status_json,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY json_extract(status_json, '$.device_id')
+                            ORDER BY julianday(json_extract(status_json, '$.timestamp')) DESC
+                        ) AS rn
+                    FROM device_statuses
+                ) WHERE rn = 1
+                "#,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SensorReadingsStorage for SqliteStorage {
+    type Error = SqliteStorageError;
+
+    async fn store(&self, reading: SensorReading) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_reading(&mut tx, &reading).await?;
+        tx.commit().await?;
+
         Ok(())
     }
 
@@ -96,17 +234,8 @@ impl SensorReadingsStorage for SqliteStorage {
 
         let mut tx = self.pool.begin().await?;
 
-        for reading in readings {
-            let json = Self::serialize_reading(&reading)?;
-            let id_str = reading.id.0.to_string();
-
-            sqlx::query(
-                "INSERT INTO sensor_readings (id, reading_json, state) VALUES (?, ?, 'pending')",
-            )
-            .bind(&id_str)
-            .bind(&json)
-            .execute(&mut *tx)
-            .await?;
+        for reading in &readings {
+            Self::insert_reading(&mut tx, reading).await?;
         }
 
         tx.commit().await?;
@@ -151,6 +280,20 @@ impl SensorReadingsStorage for SqliteStorage {
 
         Ok(())
     }
+
+    async fn fetch_latest(&self) -> Result<Vec<SensorReading>, Self::Error> {
+        let rows = sqlx::query("SELECT reading_json FROM latest_readings")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut readings = Vec::with_capacity(rows.len());
+        for row in rows {
+            let json: String = row.try_get("reading_json")?;
+            readings.push(Self::deserialize_reading(&json)?);
+        }
+
+        Ok(readings)
+    }
 }
 
 #[async_trait]
@@ -158,16 +301,9 @@ impl DeviceStatusStorage for SqliteStorage {
     type Error = SqliteStorageError;
 
     async fn store(&self, status: DeviceStatus) -> Result<(), Self::Error> {
-        let json = Self::serialize_status(&status)?;
-        let id_str = status.id.0.to_string();
-
-        sqlx::query(
-            "INSERT INTO device_statuses (id, status_json, state) VALUES (?, ?, 'pending')",
-        )
-        .bind(&id_str)
-        .bind(&json)
-        .execute(&self.pool)
-        .await?;
+        let mut tx = self.pool.begin().await?;
+        Self::insert_status(&mut tx, &status).await?;
+        tx.commit().await?;
 
         Ok(())
     }
@@ -179,17 +315,8 @@ impl DeviceStatusStorage for SqliteStorage {
 
         let mut tx = self.pool.begin().await?;
 
-        for status in statuses {
-            let json = Self::serialize_status(&status)?;
-            let id_str = status.id.0.to_string();
-
-            sqlx::query(
-                "INSERT INTO device_statuses (id, status_json, state) VALUES (?, ?, 'pending')",
-            )
-            .bind(&id_str)
-            .bind(&json)
-            .execute(&mut *tx)
-            .await?;
+        for status in &statuses {
+            Self::insert_status(&mut tx, status).await?;
         }
 
         tx.commit().await?;
@@ -234,6 +361,20 @@ impl DeviceStatusStorage for SqliteStorage {
 
         Ok(())
     }
+
+    async fn fetch_latest(&self) -> Result<Vec<DeviceStatus>, Self::Error> {
+        let rows = sqlx::query("SELECT status_json FROM latest_statuses")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut statuses = Vec::with_capacity(rows.len());
+        for row in rows {
+            let json: String = row.try_get("status_json")?;
+            statuses.push(Self::deserialize_status(&json)?);
+        }
+
+        Ok(statuses)
+    }
 }
 
 #[async_trait]
@@ -291,6 +432,7 @@ impl StorageMaintenance for SqliteStorage {
                     .await?
                     .rows_affected();
 
+            Self::refresh_latest(&mut tx).await?;
             tx.commit().await?;
 
             return Ok(CleanupStats {
@@ -319,6 +461,7 @@ impl StorageMaintenance for SqliteStorage {
             .await?
             .rows_affected();
 
+        Self::refresh_latest(&mut tx).await?;
         tx.commit().await?;
 
         Ok(CleanupStats {
@@ -645,6 +788,69 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn sqlite_fetch_latest() -> Result<(), SqliteStorageError> {
+        let storage = SqliteStorage::new_in_memory().await?;
+
+        let older = dummy_reading();
+        let mut newer = older.clone();
+        newer.id = ReadingId(Ulid::new());
+        newer.timestamp = older.timestamp + jiff::SignedDuration::from_secs(60);
+        let newer_id = newer.id;
+
+        SensorReadingsStorage::store(&storage, newer).await?;
+        SensorReadingsStorage::store(&storage, older).await?;
+        SensorReadingsStorage::store(&storage, dummy_reading()).await?;
+
+        let latest = SensorReadingsStorage::fetch_latest(&storage).await?;
+        assert_eq!(latest.len(), 2);
+        assert!(latest.iter().any(|r| r.id == newer_id));
+
+        let status = dummy_status();
+        let mut newer_status = status.clone();
+        newer_status.id = StatusId(Ulid::new());
+        newer_status.timestamp = status.timestamp + jiff::SignedDuration::from_secs(60);
+        let newer_status_id = newer_status.id;
+
+        DeviceStatusStorage::store(&storage, status).await?;
+        DeviceStatusStorage::store(&storage, newer_status).await?;
+
+        let latest = DeviceStatusStorage::fetch_latest(&storage).await?;
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].id, newer_status_id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_latest_falls_back_once_cleaned_up() -> Result<(), SqliteStorageError> {
+        let storage = SqliteStorage::new_in_memory().await?;
+
+        let older = dummy_reading();
+        let mut newer = older.clone();
+        newer.id = ReadingId(Ulid::new());
+        newer.timestamp = older.timestamp + jiff::SignedDuration::from_secs(60);
+        let (older_id, newer_id) = (older.id, newer.id);
+
+        SensorReadingsStorage::store_batch(&storage, vec![newer, older]).await?;
+        SensorReadingsStorage::mark_uploaded(&storage, &[newer_id]).await?;
+        storage.cleanup_uploaded(Duration::ZERO).await?;
+
+        let latest = SensorReadingsStorage::fetch_latest(&storage).await?;
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].id, older_id);
+
+        SensorReadingsStorage::mark_uploaded(&storage, &[older_id]).await?;
+        storage.cleanup_uploaded(Duration::ZERO).await?;
+        assert!(
+            SensorReadingsStorage::fetch_latest(&storage)
+                .await?
+                .is_empty()
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn sqlite_cleanup_only_affects_uploaded() -> Result<(), SqliteStorageError> {
         let storage = SqliteStorage::new_in_memory().await?;
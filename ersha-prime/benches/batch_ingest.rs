@@ -0,0 +1,168 @@
+//! Storing 10k-status batches in the in-memory and SQLite registries, as a
+//! bulk upload does, and registering 10k-item batches of devices and
+//! dispatchers, as a fleet import does.
+//!
+//! Run with `cargo bench -p ersha-prime --bench batch_ingest`.
+
+use std::path::Path;
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use ersha_core::{
+    Device, DeviceId, DeviceKind, DeviceState, DeviceStatus, Dispatcher, DispatcherId,
+    DispatcherState, H3Cell, Percentage, Sensor, SensorId, SensorKind, SensorMetric, StatusId,
+};
+use ersha_prime::config::SqlitePoolConfig;
+use ersha_prime::registry::{
+    DeviceRegistry, DeviceStatusRegistry, DispatcherRegistry,
+    memory::{InMemoryDeviceRegistry, InMemoryDeviceStatusRegistry, InMemoryDispatcherRegistry},
+    sqlite::{SqliteDeviceRegistry, SqliteDeviceStatusRegistry, SqliteDispatcherRegistry, connect},
+};
+use ordered_float::NotNan;
+use sqlx::SqlitePool;
+use tokio::runtime::Runtime;
+use ulid::Ulid;
+
+/// Items per batch.
+const BATCH_SIZE: usize = 10_000;
+
+fn statuses() -> Vec<DeviceStatus> {
+    let dispatcher_id = DispatcherId(Ulid::new());
+
+    (0..BATCH_SIZE)
+        .map(|i| DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id,
+            battery_percent: Percentage((i % 100) as u8),
+            uptime_seconds: 3600,
+            signal_rssi: -70,
+            errors: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+            sensor_statuses: Box::new([]),
+        })
+        .collect()
+}
+
+fn devices() -> Vec<Device> {
+    (0..BATCH_SIZE)
+        .map(|i| Device {
+            id: DeviceId(Ulid::new()),
+            kind: DeviceKind::Sensor,
+            state: DeviceState::Active,
+            location: H3Cell(0x8a2a1072b59ffff),
+            manufacturer: Some("Ersha".into()),
+            provisioned_at: jiff::Timestamp::now(),
+            sensors: Box::new([Sensor {
+                id: SensorId(Ulid::new()),
+                kind: SensorKind::SoilTemp,
+                metric: SensorMetric::SoilTemp {
+                    value: NotNan::new(15.0 + (i % 20) as f64).unwrap(),
+                },
+            }]),
+        })
+        .collect()
+}
+
+fn dispatchers() -> Vec<Dispatcher> {
+    (0..BATCH_SIZE)
+        .map(|_| Dispatcher {
+            id: DispatcherId(Ulid::new()),
+            state: DispatcherState::Active,
+            location: H3Cell(0x8a2a1072b59ffff),
+            provisioned_at: jiff::Timestamp::now(),
+        })
+        .collect()
+}
+
+async fn pool(dir: &Path) -> SqlitePool {
+    let path = dir.join(format!("{}.db", Ulid::new()));
+    connect(
+        &format!("{}?mode=rwc", path.display()),
+        &SqlitePoolConfig::default(),
+    )
+    .await
+    .unwrap()
+}
+
+fn batch_ingest(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let dir = std::env::temp_dir().join(format!("ersha-bench-{}", Ulid::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut group = c.benchmark_group("batch_ingest");
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+    group.sample_size(10);
+
+    let registry = InMemoryDeviceStatusRegistry::new();
+    group.bench_function("memory_statuses", |b| {
+        b.to_async(&runtime).iter_batched(
+            statuses,
+            |batch| async { registry.batch_store(batch).await.unwrap() },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let registry = InMemoryDeviceRegistry::new();
+    group.bench_function("memory_devices", |b| {
+        b.to_async(&runtime).iter_batched(
+            devices,
+            |batch| async { registry.batch_register(batch).await.unwrap() },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let registry = InMemoryDispatcherRegistry::new();
+    group.bench_function("memory_dispatchers", |b| {
+        b.to_async(&runtime).iter_batched(
+            dispatchers,
+            |batch| async { registry.batch_register(batch).await.unwrap() },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let registry = runtime.block_on(async {
+        SqliteDeviceStatusRegistry::with_pool(pool(&dir).await)
+            .await
+            .unwrap()
+    });
+    group.bench_function("sqlite_statuses", |b| {
+        b.to_async(&runtime).iter_batched(
+            statuses,
+            |batch| async { registry.batch_store(batch).await.unwrap() },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let registry = runtime.block_on(async {
+        SqliteDeviceRegistry::with_pool(pool(&dir).await)
+            .await
+            .unwrap()
+    });
+    group.bench_function("sqlite_devices", |b| {
+        b.to_async(&runtime).iter_batched(
+            devices,
+            |batch| async { registry.batch_register(batch).await.unwrap() },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let registry = runtime.block_on(async {
+        SqliteDispatcherRegistry::with_pool(pool(&dir).await)
+            .await
+            .unwrap()
+    });
+    group.bench_function("sqlite_dispatchers", |b| {
+        b.to_async(&runtime).iter_batched(
+            dispatchers,
+            |batch| async { registry.batch_register(batch).await.unwrap() },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+criterion_group!(benches, batch_ingest);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+//! Sustained ingest into the SQLite reading registry while the API reads,
+//! with writes going straight to the pool as they used to and through the
+//! single writer task.
+//!
+//! Run with `cargo bench -p ersha-prime --bench sqlite_ingest`.
+
+use std::path::Path;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use ersha_core::{
+    DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId, SensorMetric, SensorReading,
+};
+use ersha_prime::config::SqlitePoolConfig;
+use ersha_prime::registry::{
+    ReadingRegistry,
+    sqlite::{SqliteReadingRegistry, connect},
+};
+use ordered_float::NotNan;
+use tokio::runtime::Runtime;
+use ulid::Ulid;
+
+/// Dispatchers uploading at once.
+const UPLOADERS: usize = 8;
+
+/// Readings per uploaded batch.
+const BATCH_SIZE: usize = 500;
+
+fn batch() -> Vec<SensorReading> {
+    let dispatcher_id = DispatcherId(Ulid::new());
+    let device_id = DeviceId(Ulid::new());
+    let sensor_id = SensorId(Ulid::new());
+    let timestamp = jiff::Timestamp::now();
+
+    (0..BATCH_SIZE)
+        .map(|i| SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id,
+            dispatcher_id,
+            metric: SensorMetric::SoilTemp {
+                value: NotNan::new(15.0 + (i * 37 % 200) as f64 / 10.0).unwrap(),
+            },
+            location: H3Cell(0x8a2a1072b59ffff),
+            confidence: Percentage(90),
+            timestamp,
+            sensor_id,
+        })
+        .collect()
+}
+
+async fn registry(dir: &Path, config: &SqlitePoolConfig) -> SqliteReadingRegistry {
+    let path = dir.join(format!("{}.db", Ulid::new()));
+    let pool = connect(&format!("{}?mode=rwc", path.display()), config)
+        .await
+        .unwrap();
+
+    SqliteReadingRegistry::with_pool(pool)
+        .await
+        .unwrap()
+        .with_writer(config.write_queue)
+}
+
+/// Every uploader stores a batch while a reader counts readings.
+async fn round(registry: &SqliteReadingRegistry) {
+    let uploads: Vec<_> = (0..UPLOADERS)
+        .map(|_| {
+            let registry = registry.clone();
+            let batch = batch();
+            tokio::spawn(async move { registry.batch_store(batch).await })
+        })
+        .collect();
+    let reads = {
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            for _ in 0..UPLOADERS {
+                registry.count(None).await.unwrap();
+            }
+        })
+    };
+
+    for upload in uploads {
+        upload.await.unwrap().unwrap();
+    }
+    reads.await.unwrap();
+}
+
+fn ingest(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let dir = std::env::temp_dir().join(format!("ersha-bench-{}", Ulid::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut group = c.benchmark_group("sqlite_ingest");
+    group.throughput(Throughput::Elements((UPLOADERS * BATCH_SIZE) as u64));
+    for (name, config) in [
+        (
+            "pooled",
+            SqlitePoolConfig {
+                wal: false,
+                write_queue: 0,
+                ..SqlitePoolConfig::default()
+            },
+        ),
+        ("single_writer", SqlitePoolConfig::default()),
+    ] {
+        let registry = runtime.block_on(registry(&dir, &config));
+        group.bench_with_input(
+            BenchmarkId::new(name, UPLOADERS),
+            &registry,
+            |b, registry| b.to_async(&runtime).iter(|| round(registry)),
+        );
+    }
+    group.finish();
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+criterion_group!(benches, ingest);
+criterion_main!(benches);
@@ -0,0 +1 @@
+ALTER TABLE dispatchers ADD COLUMN secret TEXT;
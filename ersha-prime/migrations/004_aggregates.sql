@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS aggregates (
+    device_id TEXT NOT NULL,
+    sensor_id TEXT NOT NULL,
+    metric INTEGER NOT NULL,
+    granularity INTEGER NOT NULL,
+    bucket_start INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    sum REAL NOT NULL,
+    min REAL NOT NULL,
+    max REAL NOT NULL,
+    PRIMARY KEY (device_id, sensor_id, metric, granularity, bucket_start)
+);
+
+CREATE INDEX IF NOT EXISTS idx_aggregates_bucket ON aggregates (granularity, bucket_start);
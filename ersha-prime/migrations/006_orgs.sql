@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS orgs (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+ALTER TABLE api_keys ADD COLUMN org_id TEXT;
+ALTER TABLE dispatchers ADD COLUMN org_id TEXT;
+ALTER TABLE devices ADD COLUMN org_id TEXT;
+ALTER TABLE webhooks ADD COLUMN org_id TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_dispatchers_org ON dispatchers (org_id);
+CREATE INDEX IF NOT EXISTS idx_devices_org ON devices (org_id);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS indicators (
+    field INTEGER NOT NULL,
+    kind INTEGER NOT NULL,
+    day INTEGER NOT NULL,
+    value REAL NOT NULL,
+    computed_at INTEGER NOT NULL,
+    PRIMARY KEY (field, kind, day)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS commands (
+    id TEXT PRIMARY KEY NOT NULL,
+    device_id TEXT NOT NULL,
+    dispatcher_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    state INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    delivered_at INTEGER,
+    acked_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_commands_device ON commands (device_id);
+CREATE INDEX IF NOT EXISTS idx_commands_pending ON commands (dispatcher_id, state);
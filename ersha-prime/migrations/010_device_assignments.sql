@@ -0,0 +1,5 @@
+-- The dispatcher a device is assigned to. Only that dispatcher may upload
+-- the device's readings and statuses.
+ALTER TABLE devices ADD COLUMN dispatcher_id TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_devices_dispatcher ON devices (dispatcher_id);
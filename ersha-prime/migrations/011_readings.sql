@@ -0,0 +1,18 @@
+-- Sensor readings. Timestamps are nanoseconds since the Unix epoch so
+-- readings round-trip without losing precision.
+CREATE TABLE IF NOT EXISTS readings (
+    id TEXT PRIMARY KEY NOT NULL,
+    device_id TEXT NOT NULL,
+    dispatcher_id TEXT NOT NULL,
+    sensor_id TEXT NOT NULL,
+    metric INTEGER NOT NULL,
+    value REAL NOT NULL,
+    location INTEGER NOT NULL,
+    confidence INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_readings_device_timestamp ON readings (device_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_readings_device_sensor ON readings (device_id, sensor_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_readings_metric_timestamp ON readings (metric, timestamp);
+CREATE INDEX IF NOT EXISTS idx_readings_timestamp ON readings (timestamp);
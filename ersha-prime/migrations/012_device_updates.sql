@@ -0,0 +1,8 @@
+-- When a device was last changed, in nanoseconds since the epoch, and how
+-- it is installed beyond its H3 cell.
+ALTER TABLE devices ADD COLUMN updated_at INTEGER;
+ALTER TABLE devices ADD COLUMN placement_site TEXT;
+ALTER TABLE devices ADD COLUMN placement_depth_cm INTEGER;
+ALTER TABLE devices ADD COLUMN placement_notes TEXT;
+
+UPDATE devices SET updated_at = provisioned_at * 1000000000;
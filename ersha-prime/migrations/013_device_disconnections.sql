@@ -0,0 +1,3 @@
+-- When a device's dispatcher last heard from it, in nanoseconds since the
+-- epoch, while the dispatcher reports it disconnected. NULL otherwise.
+ALTER TABLE devices ADD COLUMN disconnected_since INTEGER;
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS irrigation_plans (
+    id TEXT PRIMARY KEY NOT NULL,
+    field INTEGER NOT NULL,
+    crop_coefficient REAL NOT NULL,
+    valve_device_id TEXT,
+    valve_channel INTEGER,
+    automatic INTEGER NOT NULL,
+    org_id TEXT,
+    -- The recommended window as JSON, NULL when the field needs no water
+    window TEXT,
+    planned_at INTEGER,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_irrigation_plans_field ON irrigation_plans (field);
@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS contacts (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    channels TEXT NOT NULL,
+    events TEXT NOT NULL,
+    thresholds TEXT NOT NULL,
+    quiet_hours TEXT,
+    org_id TEXT,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS notifications (
+    id TEXT PRIMARY KEY NOT NULL,
+    contact_id TEXT NOT NULL REFERENCES contacts(id) ON DELETE CASCADE,
+    channel TEXT NOT NULL,
+    address TEXT NOT NULL,
+    event TEXT NOT NULL,
+    state INTEGER NOT NULL,
+    attempts INTEGER NOT NULL,
+    next_attempt_at INTEGER NOT NULL,
+    last_attempt_at INTEGER,
+    last_error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_notifications_due ON notifications (state, next_attempt_at);
+CREATE INDEX IF NOT EXISTS idx_notifications_contact ON notifications (contact_id);
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    role TEXT NOT NULL,
+    org_id TEXT,
+    fields TEXT NOT NULL,
+    password_hash TEXT,
+    oidc_issuer TEXT,
+    oidc_subject TEXT,
+    created_at INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_oidc ON users (oidc_issuer, oidc_subject);
+
+ALTER TABLE api_keys ADD COLUMN user_id TEXT;
+ALTER TABLE api_keys ADD COLUMN expires_at INTEGER;
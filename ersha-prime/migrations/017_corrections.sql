@@ -0,0 +1,32 @@
+-- Calibration corrections. The range bounds are nanoseconds since the Unix
+-- epoch, like reading timestamps.
+CREATE TABLE IF NOT EXISTS corrections (
+    id TEXT PRIMARY KEY NOT NULL,
+    device_id TEXT NOT NULL,
+    sensor_id TEXT NOT NULL,
+    range_from INTEGER NOT NULL,
+    range_until INTEGER NOT NULL,
+    -- The transform as JSON
+    transform TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    state TEXT NOT NULL,
+    readings INTEGER NOT NULL,
+    error TEXT,
+    created_at INTEGER NOT NULL,
+    applied_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_corrections_device ON corrections (device_id);
+CREATE INDEX IF NOT EXISTS idx_corrections_state ON corrections (state);
+
+-- Values readings had before each correction changed them.
+CREATE TABLE IF NOT EXISTS reading_revisions (
+    reading_id TEXT NOT NULL,
+    correction_id TEXT NOT NULL,
+    previous REAL NOT NULL,
+    corrected REAL NOT NULL,
+    revised_at INTEGER NOT NULL,
+    PRIMARY KEY (reading_id, correction_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_reading_revisions_correction ON reading_revisions (correction_id);
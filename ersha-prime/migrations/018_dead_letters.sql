@@ -0,0 +1,20 @@
+-- Batch items refused on upload, kept for re-driving. The item is stored as
+-- the dispatcher sent it, as JSON.
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id TEXT PRIMARY KEY NOT NULL,
+    dispatcher_id TEXT NOT NULL,
+    batch_id TEXT NOT NULL,
+    -- "reading" or "status"
+    kind TEXT NOT NULL,
+    item_id TEXT NOT NULL,
+    item TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    state TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    received_at INTEGER NOT NULL,
+    redriven_at INTEGER,
+    UNIQUE (kind, item_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_dead_letters_dispatcher ON dead_letters (dispatcher_id);
+CREATE INDEX IF NOT EXISTS idx_dead_letters_state ON dead_letters (state);
@@ -0,0 +1,28 @@
+-- Free-form tags on devices and dispatchers. Kept apart from the devices
+-- and dispatchers themselves so re-registering one keeps its tags.
+CREATE TABLE IF NOT EXISTS device_tags (
+    device_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (device_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_tags_tag ON device_tags (tag);
+
+CREATE TABLE IF NOT EXISTS dispatcher_tags (
+    dispatcher_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (dispatcher_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_dispatcher_tags_tag ON dispatcher_tags (tag);
+
+-- Named selections of the fleet. `tags` is a JSON array; members carry
+-- every tag in it.
+CREATE TABLE IF NOT EXISTS groups (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    tags TEXT NOT NULL,
+    org_id TEXT,
+    created_at INTEGER NOT NULL
+);
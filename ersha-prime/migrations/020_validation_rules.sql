@@ -0,0 +1,17 @@
+-- Plausible values per metric kind, optionally scoped to an H3 region.
+CREATE TABLE IF NOT EXISTS validation_rules (
+    id TEXT PRIMARY KEY NOT NULL,
+    metric INTEGER NOT NULL,
+    within INTEGER,
+    min REAL,
+    max REAL,
+    max_change_per_hour REAL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_validation_rules_metric ON validation_rules (metric);
+
+-- How each reading fared against the rules covering it when ingested.
+ALTER TABLE readings ADD COLUMN quality TEXT NOT NULL DEFAULT 'good';
+
+CREATE INDEX IF NOT EXISTS idx_readings_quality ON readings (quality, timestamp);
@@ -0,0 +1,6 @@
+-- How many seconds apart each device is expected to report. Kept apart from
+-- the devices themselves so re-registering one keeps its interval.
+CREATE TABLE IF NOT EXISTS device_reporting_intervals (
+    device_id TEXT PRIMARY KEY NOT NULL,
+    interval_secs INTEGER NOT NULL
+);
@@ -0,0 +1,33 @@
+CREATE TABLE IF NOT EXISTS device_hardware_revs (
+    device_id TEXT PRIMARY KEY NOT NULL,
+    hardware_rev TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS firmware_images (
+    id TEXT PRIMARY KEY NOT NULL,
+    version TEXT NOT NULL,
+    hardware_rev TEXT,
+    size INTEGER NOT NULL,
+    sha256 TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS firmware_rollouts (
+    id TEXT PRIMARY KEY NOT NULL,
+    firmware_id TEXT NOT NULL,
+    hardware_rev TEXT,
+    tags TEXT NOT NULL,
+    org_id TEXT,
+    batch_size INTEGER NOT NULL,
+    state INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS firmware_rollout_devices (
+    rollout_id TEXT NOT NULL,
+    device_id TEXT NOT NULL,
+    state INTEGER NOT NULL,
+    command_id TEXT,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (rollout_id, device_id)
+);
@@ -0,0 +1,9 @@
+-- Free-form JSON metadata on devices, kept apart from the devices
+-- themselves so re-registering one keeps its metadata.
+CREATE TABLE IF NOT EXISTS device_metadata (
+    device_id TEXT PRIMARY KEY NOT NULL,
+    metadata TEXT NOT NULL
+);
+
+-- JSON Schema an organization's device metadata must match, if any.
+ALTER TABLE orgs ADD COLUMN metadata_schema TEXT;
@@ -0,0 +1,12 @@
+-- Events staged in the same transaction as the change that raised them,
+-- until relayed to webhooks and contacts.
+CREATE TABLE IF NOT EXISTS outbox (
+    id TEXT PRIMARY KEY NOT NULL,
+    event TEXT NOT NULL,
+    state INTEGER NOT NULL,
+    attempts INTEGER NOT NULL,
+    next_attempt_at INTEGER NOT NULL,
+    last_error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_due ON outbox (state, next_attempt_at);
@@ -0,0 +1,4 @@
+-- When a dispatcher was last changed, in nanoseconds since the epoch.
+ALTER TABLE dispatchers ADD COLUMN updated_at INTEGER;
+
+UPDATE dispatchers SET updated_at = provisioned_at * 1000000000;
@@ -0,0 +1,21 @@
+-- Device status reports. Error codes are kept as a bitmask alongside the
+-- errors themselves so they can be filtered on without parsing JSON.
+CREATE TABLE IF NOT EXISTS device_statuses (
+    id TEXT PRIMARY KEY NOT NULL,
+    device_id TEXT NOT NULL,
+    dispatcher_id TEXT NOT NULL,
+    battery_percent INTEGER NOT NULL,
+    uptime_seconds INTEGER NOT NULL,
+    signal_rssi INTEGER NOT NULL,
+    error_codes INTEGER NOT NULL,
+    errors TEXT NOT NULL,
+    sensor_statuses TEXT NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+
+-- Covers finding a device's latest status without touching the table.
+CREATE INDEX IF NOT EXISTS idx_device_statuses_device_timestamp
+    ON device_statuses (device_id, timestamp, id);
+CREATE INDEX IF NOT EXISTS idx_device_statuses_timestamp ON device_statuses (timestamp, id);
+CREATE INDEX IF NOT EXISTS idx_device_statuses_dispatcher_timestamp
+    ON device_statuses (dispatcher_id, timestamp);
@@ -0,0 +1,3 @@
+-- The one webhook or contact an entry is for, as JSON. Entries without one
+-- go to every subscriber of their event's kind.
+ALTER TABLE outbox ADD COLUMN recipient TEXT;
@@ -0,0 +1,26 @@
+-- Deleted users are kept, marked with when they were deleted, so the audit
+-- log can still name who made a change. Usernames and OIDC subjects are
+-- only unique among users who aren't deleted, which needs the table rebuilt
+-- without the column constraint.
+CREATE TABLE users_new (
+    id TEXT PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL,
+    name TEXT NOT NULL,
+    role TEXT NOT NULL,
+    org_id TEXT,
+    fields TEXT NOT NULL,
+    password_hash TEXT,
+    oidc_issuer TEXT,
+    oidc_subject TEXT,
+    created_at INTEGER NOT NULL,
+    deleted_at INTEGER
+);
+
+INSERT INTO users_new (id, username, name, role, org_id, fields, password_hash, oidc_issuer, oidc_subject, created_at)
+SELECT id, username, name, role, org_id, fields, password_hash, oidc_issuer, oidc_subject, created_at FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
+
+CREATE UNIQUE INDEX idx_users_username ON users (username) WHERE deleted_at IS NULL;
+CREATE UNIQUE INDEX idx_users_oidc ON users (oidc_issuer, oidc_subject) WHERE deleted_at IS NULL;
@@ -0,0 +1,179 @@
+use axum::{Extension, Json};
+
+use super::{ApiError, ErrorBody};
+use crate::auth::{Principal, Scope};
+use crate::backup::{Backup, BackupError, Backups};
+use crate::tuning::{Tunables, Tuning, TuningError};
+
+/// `GET /admin/config`
+///
+/// The settings in effect that can be changed by a reload.
+#[utoipa::path(
+    get,
+    path = "/admin/config",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Settings in effect", body = Tunables),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+    )
+)]
+pub async fn config(
+    Extension(principal): Extension<Principal>,
+    Extension(tuning): Extension<Tuning>,
+) -> Result<Json<Tunables>, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+
+    Ok(Json(tuning.current()))
+}
+
+/// `POST /admin/reload`
+///
+/// Re-read the configuration file and apply the log filter, rate limits,
+/// retention policies and page sizes. Other settings need a restart. An
+/// invalid file leaves the current settings in effect. Sending prime a
+/// `SIGHUP` does the same.
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Settings now in effect", body = Tunables),
+        (status = 400, description = "Invalid configuration file", body = ErrorBody),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+        (status = 409, description = "Started without a configuration file", body = ErrorBody),
+    )
+)]
+pub async fn reload(
+    Extension(principal): Extension<Principal>,
+    Extension(tuning): Extension<Tuning>,
+) -> Result<Json<Tunables>, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+
+    let tunables = tuning.reload().map_err(|e| match e {
+        TuningError::NoConfigFile => ApiError::Conflict(e.to_string()),
+        TuningError::Parse(_) | TuningError::LogFilter(_) | TuningError::Pagination => {
+            ApiError::BadRequest(e.to_string())
+        }
+        TuningError::Read(_) | TuningError::LogReload(_) => ApiError::internal(e),
+    })?;
+
+    tracing::info!(
+        ?tunables,
+        triggered_by = ?principal.key_id,
+        "configuration reloaded"
+    );
+
+    Ok(Json(tunables))
+}
+
+/// `POST /admin/backup`
+///
+/// Back the SQLite database up into the configured backup directory while
+/// prime keeps serving, compressed with zstd. Backups beyond the configured
+/// number are deleted.
+#[utoipa::path(
+    post,
+    path = "/admin/backup",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Backup taken", body = Backup),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+        (status = 409, description = "Registries are kept in memory", body = ErrorBody),
+    )
+)]
+pub async fn backup(
+    Extension(principal): Extension<Principal>,
+    Extension(backups): Extension<Backups>,
+) -> Result<Json<Backup>, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+
+    let backup = backups.take().await.map_err(|e| match e {
+        BackupError::Unsupported => ApiError::Conflict(e.to_string()),
+        _ => ApiError::internal(e),
+    })?;
+
+    tracing::info!(
+        file = %backup.file,
+        size_bytes = backup.size_bytes,
+        triggered_by = ?principal.key_id,
+        "database backed up"
+    );
+
+    Ok(Json(backup))
+}
+
+/// `GET /admin/backups`
+///
+/// Backups in the backup directory, newest first.
+#[utoipa::path(
+    get,
+    path = "/admin/backups",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Backups kept", body = Vec<Backup>),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+    )
+)]
+pub async fn backups(
+    Extension(principal): Extension<Principal>,
+    Extension(backups): Extension<Backups>,
+) -> Result<Json<Vec<Backup>>, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+
+    Ok(Json(backups.list().await.map_err(ApiError::internal)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Extension;
+    use ulid::Ulid;
+
+    use super::{backup, config, reload};
+    use crate::api::ApiError;
+    use crate::auth::Scope;
+    use crate::backup::Backups;
+    use crate::config::BackupConfig;
+    use crate::fixtures::{admin, principal};
+    use crate::org::OrgId;
+    use crate::tuning::{Tunables, Tuning};
+
+    #[tokio::test]
+    async fn only_platform_admins_see_and_reload_the_config() {
+        let tuning = Extension(Tuning::new(Tunables::default()));
+
+        let org_admin = principal(Scope::Admin, Some(OrgId(Ulid::new())));
+        assert!(matches!(
+            config(org_admin.clone(), tuning.clone()).await,
+            Err(ApiError::Forbidden)
+        ));
+        assert!(matches!(
+            reload(org_admin, tuning.clone()).await,
+            Err(ApiError::Forbidden)
+        ));
+
+        let current = config(admin(), tuning.clone()).await.unwrap();
+        assert_eq!(current.0, Tunables::default());
+        assert!(matches!(
+            reload(admin(), tuning).await,
+            Err(ApiError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn backups_need_sqlite_registries() {
+        let backups = Extension(Backups::new(BackupConfig::default(), None));
+
+        assert!(matches!(
+            backup(
+                principal(Scope::Admin, Some(OrgId(Ulid::new()))),
+                backups.clone()
+            )
+            .await,
+            Err(ApiError::Forbidden)
+        ));
+        assert!(matches!(
+            backup(admin(), backups).await,
+            Err(ApiError::Conflict(_))
+        ));
+    }
+}
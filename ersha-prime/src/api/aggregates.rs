@@ -0,0 +1,82 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+};
+use ersha_core::{DeviceId, SensorId};
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::IntoParams;
+
+use super::{ApiError, ErrorBody, parse_list, readings::parse_metric_kind, visible_device};
+use crate::auth::{Principal, Scope};
+use crate::registry::{AggregateRegistry, Registries, filter::AggregateFilter};
+use crate::rollup::{Aggregate, Granularity};
+
+/// Query parameters for `GET /api/devices/{id}/aggregates`.
+///
+/// List parameters are comma separated.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AggregatesQuery {
+    /// `hour` or `day`
+    #[serde(default)]
+    pub granularity: Granularity,
+    pub sensor_id: Option<String>,
+    /// Metric kinds, e.g. `soil_moisture,air_temp`
+    pub metric: Option<String>,
+    /// Only buckets starting at or after this time
+    pub from: Option<jiff::Timestamp>,
+    /// Only buckets starting at or before this time
+    pub to: Option<jiff::Timestamp>,
+}
+
+impl AggregatesQuery {
+    fn into_filter(self, device_id: DeviceId) -> Result<AggregateFilter, ApiError> {
+        Ok(AggregateFilter {
+            granularity: self.granularity,
+            device_ids: Some(vec![device_id]),
+            sensor_ids: parse_list("sensor_id", self.sensor_id.as_deref(), |s| {
+                s.parse().ok().map(SensorId)
+            })?,
+            metric_kinds: parse_list("metric", self.metric.as_deref(), parse_metric_kind)?,
+            after: self.from,
+            before: self.to,
+        })
+    }
+}
+
+/// `GET /api/devices/{id}/aggregates`
+///
+/// Hourly or daily rollups of the device's readings, maintained as batches
+/// are ingested.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/aggregates",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), AggregatesQuery),
+    responses(
+        (status = 200, description = "Aggregates ordered by bucket start, then sensor", body = Vec<Aggregate>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Query(query): Query<AggregatesQuery>,
+) -> Result<Json<Vec<Aggregate>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let device_id = DeviceId(id);
+    visible_device(&registries, &principal, device_id).await?;
+
+    let filter = query.into_filter(device_id)?;
+    let aggregates = registries
+        .aggregates()
+        .list(filter)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(aggregates))
+}
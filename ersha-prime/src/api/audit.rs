@@ -0,0 +1,194 @@
+use axum::{
+    Extension,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::IntoParams;
+
+use super::{ApiError, ErrorBody, Order, Page, page_limit, parse_cursor, parse_list};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{ApiKeyId, Principal, Scope};
+use crate::registry::{
+    AuditRegistry, Registries,
+    filter::{AuditFilter, AuditSortBy, Pagination, QueryOptions},
+};
+
+/// Query parameters for `GET /api/audit`.
+///
+/// List parameters are comma separated.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    /// Kind of record changed, e.g. `device` or `api_key`
+    pub entity: Option<String>,
+    /// Ids of the changed records
+    pub entity_id: Option<String>,
+    /// Actions such as `register`, `suspend` or `decommission`
+    pub action: Option<String>,
+    /// Only changes made by this API key
+    pub actor: Option<Ulid>,
+    /// Only changes made at or after this time
+    pub from: Option<jiff::Timestamp>,
+    /// Only changes made at or before this time
+    pub to: Option<jiff::Timestamp>,
+    /// Order by time of change
+    #[serde(default)]
+    pub order: Order,
+    /// Cursor returned as `next_cursor` by the previous page
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn into_options(self) -> Result<QueryOptions<AuditFilter, AuditSortBy>, ApiError> {
+        let entity = self
+            .entity
+            .as_deref()
+            .map(|s| {
+                EntityKind::parse(s)
+                    .ok_or_else(|| ApiError::BadRequest(format!("invalid entity: {s}")))
+            })
+            .transpose()?;
+
+        let filter = AuditFilter {
+            entity,
+            entity_ids: parse_list("entity_id", self.entity_id.as_deref(), |s| s.parse().ok())?,
+            actions: parse_list("action", self.action.as_deref(), AuditAction::parse)?,
+            actor: self.actor.map(ApiKeyId),
+            org_id: None,
+            after: self.from,
+            before: self.to,
+        };
+
+        Ok(QueryOptions {
+            filter,
+            sort_by: AuditSortBy::At,
+            sort_order: self.order.into(),
+            pagination: Pagination::Cursor {
+                after: parse_cursor(self.after.as_deref())?,
+                limit: page_limit(self.limit)?,
+            },
+        })
+    }
+}
+
+/// `GET /api/audit`
+///
+/// Changes made to devices, dispatchers, organizations, keys, webhooks and
+/// commands. Organization keys only see changes made by keys of their own
+/// organization.
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    tag = "audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "A page of audit entries", body = Page<AuditEntry>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Page<AuditEntry>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let mut options = query.into_options()?;
+    options.filter.org_id = principal.org_id;
+    let limit = options.pagination.limit();
+    let sort_by = options.sort_by;
+    let audit = registries.audit();
+
+    let total = audit
+        .count(Some(options.filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let items = audit.list(options).await.map_err(ApiError::registry)?;
+
+    Ok(Page::new(items, limit, total, |e| sort_by.cursor(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Extension, Json,
+        extract::{Path, Query, State},
+        http::HeaderMap,
+    };
+    use ersha_core::DeviceKind;
+    use ulid::Ulid;
+
+    use super::{AuditQuery, list};
+    use crate::api::RegisterQuery;
+    use crate::api::devices::{self, RegisterDevice};
+    use crate::audit::EntityKind;
+    use crate::auth::Scope;
+    use crate::events::{EventBus, LocalEventBus};
+    use crate::fixtures::{admin, principal};
+    use crate::org::OrgId;
+    use crate::registry::memory::InMemoryRegistries;
+
+    #[tokio::test]
+    async fn lifecycle_changes_are_recorded_per_org() {
+        let registries = InMemoryRegistries::default();
+        let operator = admin();
+
+        let (_, Json(device)) = devices::register(
+            State(registries.clone()),
+            operator.clone(),
+            Extension(Arc::new(LocalEventBus::new()) as Arc<dyn EventBus>),
+            Query(RegisterQuery::default()),
+            Json(RegisterDevice {
+                id: None,
+                kind: DeviceKind::Sensor,
+                location: 0x8a2a1072b59ffff,
+                manufacturer: None,
+                sensors: Vec::new(),
+                reporting_interval_secs: None,
+                hardware_rev: None,
+                metadata: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let (_, Json(device)) = devices::suspend(
+            State(registries.clone()),
+            operator.clone(),
+            Path(device.id.0),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+
+        let query = AuditQuery {
+            entity: Some("device".to_owned()),
+            entity_id: Some(device.id.0.to_string()),
+            ..Default::default()
+        };
+        let page = list(State(registries.clone()), operator.clone(), Query(query))
+            .await
+            .unwrap();
+        let mut actions: Vec<_> = page.items.iter().map(|e| e.action.as_str()).collect();
+        actions.sort();
+        assert_eq!(actions, ["register", "suspend"]);
+        assert!(
+            page.items
+                .iter()
+                .all(|e| e.entity == EntityKind::Device && e.actor == Some(operator.key_id))
+        );
+
+        let page = list(
+            State(registries),
+            principal(Scope::Admin, Some(OrgId(Ulid::new()))),
+            Query(AuditQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert!(page.items.is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use ersha_core::BatchUploadRequest;
+use ulid::Ulid;
+
+use super::{ApiError, ErrorBody, record_audit, visible_dispatcher};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::backfill::{BackfillId, BackfillJob, Backfills};
+use crate::registry::Registries;
+
+/// `POST /api/backfill`
+///
+/// Upload historical readings and statuses, as recovered from a gateway
+/// after an outage, for processing in the background. Items may be
+/// timestamped further ahead than in live batches; those already stored are
+/// skipped. Poll `GET /api/backfill/{id}` for progress.
+#[utoipa::path(
+    post,
+    path = "/api/backfill",
+    tag = "backfill",
+    request_body = BatchUploadRequest,
+    responses(
+        (status = 202, description = "Upload queued", body = BackfillJob),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown dispatcher", body = ErrorBody),
+        (status = 413, description = "Upload larger than allowed"),
+    )
+)]
+pub async fn submit<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(backfills): Extension<Backfills>,
+    Json(batch): Json<BatchUploadRequest>,
+) -> Result<(StatusCode, Json<BackfillJob>), ApiError> {
+    principal.require(Scope::Admin)?;
+    visible_dispatcher(&registries, &principal, batch.dispatcher_id).await?;
+
+    let job = backfills.submit(batch, jiff::Timestamp::now());
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::Backfill,
+            job.id.0,
+        )
+        .with_details(serde_json::json!({
+            "dispatcher_id": job.dispatcher_id,
+            "batch_id": job.batch_id,
+            "readings": job.readings.total,
+            "statuses": job.statuses.total,
+        })),
+    )
+    .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// `GET /api/backfill`
+///
+/// Uploads queued or running, and those finished recently, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/backfill",
+    tag = "backfill",
+    responses(
+        (status = 200, description = "Uploads", body = Vec<BackfillJob>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(backfills): Extension<Backfills>,
+) -> Result<Json<Vec<BackfillJob>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let mut jobs = Vec::new();
+    for job in backfills.list() {
+        match visible_dispatcher(&registries, &principal, job.dispatcher_id).await {
+            Ok(_) => jobs.push(job),
+            Err(ApiError::NotFound | ApiError::Forbidden) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Json(jobs))
+}
+
+/// `GET /api/backfill/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/backfill/{id}",
+    tag = "backfill",
+    params(("id" = String, Path, description = "Backfill id")),
+    responses(
+        (status = 200, description = "The upload and its progress", body = BackfillJob),
+        (status = 404, description = "Unknown or long finished upload", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(backfills): Extension<Backfills>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<BackfillJob>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let job = backfills.get(BackfillId(id)).ok_or(ApiError::NotFound)?;
+    visible_dispatcher(&registries, &principal, job.dispatcher_id).await?;
+
+    Ok(Json(job))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Extension, Json,
+        extract::{Path, State},
+        http::StatusCode,
+    };
+    use ersha_core::{
+        BatchId, BatchUploadRequest, Dispatcher, DispatcherId, DispatcherState, H3Cell,
+    };
+    use ulid::Ulid;
+
+    use super::{get, submit};
+    use crate::api::ApiError;
+    use crate::backfill::{BackfillState, Backfills};
+    use crate::config::BackfillConfig;
+    use crate::fixtures::admin;
+    use crate::registry::{DispatcherRegistry, memory::InMemoryRegistries};
+
+    fn upload(dispatcher_id: DispatcherId) -> Json<BatchUploadRequest> {
+        Json(BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            dispatcher_id,
+            readings: Box::new([]),
+            statuses: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn uploads_are_queued_for_known_dispatchers() {
+        let registries = InMemoryRegistries::default();
+        let backfills = Backfills::new(BackfillConfig::default());
+        let dispatcher_id = DispatcherId(Ulid::new());
+
+        let unknown = submit(
+            State(registries.clone()),
+            admin(),
+            Extension(backfills.clone()),
+            upload(dispatcher_id),
+        )
+        .await;
+        assert!(matches!(unknown, Err(ApiError::NotFound)));
+
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: dispatcher_id,
+                location: H3Cell(0x8a2a1072b59ffff),
+                state: DispatcherState::Active,
+                provisioned_at: jiff::Timestamp::now(),
+            })
+            .await
+            .unwrap();
+        let (status, Json(job)) = submit(
+            State(registries.clone()),
+            admin(),
+            Extension(backfills.clone()),
+            upload(dispatcher_id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(job.state, BackfillState::Queued);
+
+        let Json(fetched) = get(
+            State(registries),
+            admin(),
+            Extension(backfills),
+            Path(job.id.0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fetched, job);
+    }
+}
@@ -0,0 +1,266 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use ersha_core::{CommandKind, DeviceId, DeviceState, DispatcherId};
+use jiff::SignedDuration;
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, ErrorBody, page_limit, record_audit, visible_device, visible_dispatcher};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::command::{self, Command};
+use crate::registry::{CommandRegistry, Registries};
+
+/// How long a command stays deliverable when the request doesn't say.
+const DEFAULT_TTL_SECS: u64 = 60 * 60;
+/// Upper bound on `ttl_secs`.
+const MAX_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Body of `POST /api/devices/{id}/commands`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EnqueueCommand {
+    pub kind: CommandKind,
+    /// Seconds until the command expires, one hour by default
+    pub ttl_secs: Option<u64>,
+    /// Dispatcher to relay the command. Defaults to the one that most
+    /// recently forwarded data from the device.
+    pub dispatcher_id: Option<DispatcherId>,
+}
+
+/// `POST /api/devices/{id}/commands`
+///
+/// Queue a command for the device. Its dispatcher picks it up on its next
+/// command poll.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/commands",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = EnqueueCommand,
+    responses(
+        (status = 201, description = "Command queued", body = Command),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device or dispatcher", body = ErrorBody),
+        (status = 409, description = "Device is decommissioned or has no dispatcher", body = ErrorBody),
+    )
+)]
+pub async fn enqueue<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<EnqueueCommand>,
+) -> Result<(StatusCode, Json<Command>), ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let device_id = DeviceId(id);
+    let device = visible_device(&registries, &principal, device_id).await?;
+    if device.state == DeviceState::Decommissioned {
+        return Err(ApiError::Conflict("device is decommissioned".to_owned()));
+    }
+
+    let ttl_secs = request.ttl_secs.unwrap_or(DEFAULT_TTL_SECS);
+    if !(1..=MAX_TTL_SECS).contains(&ttl_secs) {
+        return Err(ApiError::BadRequest(format!(
+            "ttl_secs must be between 1 and {MAX_TTL_SECS}"
+        )));
+    }
+
+    let dispatcher_id = match request.dispatcher_id {
+        Some(dispatcher_id) => {
+            visible_dispatcher(&registries, &principal, dispatcher_id).await?;
+            dispatcher_id
+        }
+        None => command::route(&registries, device_id)
+            .await
+            .map_err(|e| ApiError::internal(&*e))?
+            .ok_or_else(|| {
+                ApiError::Conflict("device has not reported through any dispatcher".to_owned())
+            })?,
+    };
+
+    let command = Command::new(
+        device_id,
+        dispatcher_id,
+        request.kind,
+        jiff::Timestamp::now(),
+        SignedDuration::from_secs(ttl_secs as i64),
+    );
+    registries
+        .commands()
+        .enqueue(command.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Enqueue,
+            EntityKind::Command,
+            command.id.0,
+        )
+        .with_details(serde_json::json!({ "device_id": device_id, "kind": command.kind })),
+    )
+    .await?;
+
+    tracing::info!(
+        command_id = ?command.id,
+        ?device_id,
+        ?dispatcher_id,
+        queued_by = ?principal.key_id,
+        "device command queued"
+    );
+
+    Ok((StatusCode::CREATED, Json(command)))
+}
+
+/// Query parameters for `GET /api/devices/{id}/commands`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CommandsQuery {
+    pub limit: Option<usize>,
+}
+
+/// `GET /api/devices/{id}/commands`
+///
+/// The device's most recent commands and their delivery state, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/commands",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), CommandsQuery),
+    responses(
+        (status = 200, description = "Recent commands", body = Vec<Command>),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Query(query): Query<CommandsQuery>,
+) -> Result<Json<Vec<Command>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let device_id = DeviceId(id);
+    visible_device(&registries, &principal, device_id).await?;
+
+    let commands = registries.commands();
+    commands
+        .expire(jiff::Timestamp::now())
+        .await
+        .map_err(ApiError::registry)?;
+    let commands = commands
+        .list(device_id, page_limit(query.limit)?)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(commands))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json,
+        extract::{Path, Query, State},
+    };
+    use ersha_core::{
+        CommandKind, Device, DeviceId, DeviceKind, DeviceState, DispatcherId, H3Cell, Percentage,
+        ReadingId, SensorId, SensorMetric, SensorReading,
+    };
+    use ulid::Ulid;
+
+    use super::{CommandsQuery, EnqueueCommand, enqueue, list};
+    use crate::api::ApiError;
+    use crate::command::CommandState;
+    use crate::fixtures::admin;
+    use crate::registry::{DeviceRegistry, ReadingRegistry, memory::InMemoryRegistries};
+
+    fn request() -> Json<EnqueueCommand> {
+        Json(EnqueueCommand {
+            kind: CommandKind::Actuate {
+                channel: 0,
+                on: true,
+            },
+            ttl_secs: None,
+            dispatcher_id: None,
+        })
+    }
+
+    async fn device(registries: &InMemoryRegistries) -> DeviceId {
+        let id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn commands_go_to_the_dispatcher_that_last_heard_the_device() {
+        let registries = InMemoryRegistries::default();
+        let device_id = device(&registries).await;
+
+        let result = enqueue(
+            State(registries.clone()),
+            admin(),
+            Path(device_id.0),
+            request(),
+        )
+        .await;
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+
+        let dispatcher_id = DispatcherId(Ulid::new());
+        registries
+            .readings
+            .store(SensorReading {
+                id: ReadingId(Ulid::new()),
+                device_id,
+                dispatcher_id,
+                metric: SensorMetric::SoilMoisture {
+                    value: Percentage(30),
+                },
+                location: H3Cell(0x8a2a1072b59ffff),
+                confidence: Percentage(90),
+                timestamp: jiff::Timestamp::now(),
+                sensor_id: SensorId(Ulid::new()),
+            })
+            .await
+            .unwrap();
+
+        let (_, Json(command)) = enqueue(
+            State(registries.clone()),
+            admin(),
+            Path(device_id.0),
+            request(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(command.dispatcher_id, dispatcher_id);
+        assert_eq!(command.state, CommandState::Queued);
+
+        let Json(listed) = list(
+            State(registries),
+            admin(),
+            Path(device_id.0),
+            Query(CommandsQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listed, [command]);
+    }
+}
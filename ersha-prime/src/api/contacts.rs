@@ -0,0 +1,466 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, ErrorBody, nullable, page_limit, record_audit};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::notify::{Contact, ContactChannel, ContactId, Notification, QuietHours};
+use crate::registry::{ContactRegistry, Registries};
+use crate::webhook::{EventKind, Threshold};
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateContact {
+    pub name: String,
+    /// Each event is sent to every channel listed
+    pub channels: Vec<ContactChannel>,
+    pub events: Vec<EventKind>,
+    /// Required when subscribing to `threshold_crossed`
+    #[serde(default)]
+    pub thresholds: Vec<Threshold>,
+    /// Hours nothing is sent; notifications wait until they end
+    pub quiet_hours: Option<QuietHours>,
+}
+
+/// Body of `PATCH /api/contacts/{id}`. Fields left out are kept.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdateContact {
+    pub name: Option<String>,
+    pub channels: Option<Vec<ContactChannel>>,
+    pub events: Option<Vec<EventKind>>,
+    pub thresholds: Option<Vec<Threshold>>,
+    /// `null` removes the quiet hours
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<QuietHours>)]
+    pub quiet_hours: Option<Option<QuietHours>>,
+}
+
+/// Reject contacts that can't be notified of anything.
+fn validate(contact: &Contact) -> Result<(), ApiError> {
+    let bad_request = |message: &str| Err(ApiError::BadRequest(message.to_owned()));
+
+    if contact.name.trim().is_empty() {
+        return bad_request("name must not be empty");
+    }
+    if contact.channels.is_empty() {
+        return bad_request("add at least one channel");
+    }
+    if let Some(reason) = contact.channels.iter().find_map(ContactChannel::invalid) {
+        return bad_request(reason);
+    }
+    if contact.events.is_empty() {
+        return bad_request("subscribe to at least one event");
+    }
+
+    if contact.subscribes_to(EventKind::ThresholdCrossed) && contact.thresholds.is_empty() {
+        return bad_request("threshold_crossed needs at least one threshold");
+    }
+    if contact.thresholds.iter().any(|t| !t.value.is_finite()) {
+        return bad_request("threshold values must be finite");
+    }
+    if let Some(reason) = contact.quiet_hours.as_ref().and_then(QuietHours::invalid) {
+        return bad_request(reason);
+    }
+
+    Ok(())
+}
+
+/// The contact, if it exists and the caller may see it.
+async fn visible_contact<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: ContactId,
+) -> Result<Contact, ApiError> {
+    let contact = registries
+        .contacts()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    principal.check_access(contact.org_id)?;
+
+    Ok(contact)
+}
+
+/// `POST /api/contacts`
+///
+/// The contact belongs to the caller's organization and is only notified of
+/// its events.
+#[utoipa::path(
+    post,
+    path = "/api/contacts",
+    tag = "contacts",
+    request_body = CreateContact,
+    responses(
+        (status = 201, description = "Contact created", body = Contact),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<CreateContact>,
+) -> Result<(StatusCode, Json<Contact>), ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let mut contact = Contact::new(request.name, request.channels, request.events);
+    contact.thresholds = request.thresholds;
+    contact.quiet_hours = request.quiet_hours;
+    contact.org_id = principal.org_id;
+    validate(&contact)?;
+
+    registries
+        .contacts()
+        .create(contact.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::Contact,
+            contact.id.0,
+        )
+        .with_details(serde_json::json!({ "name": contact.name })),
+    )
+    .await?;
+
+    tracing::info!(contact_id = ?contact.id, created_by = ?principal.key_id, "contact created");
+
+    Ok((StatusCode::CREATED, Json(contact)))
+}
+
+/// `GET /api/contacts`
+#[utoipa::path(
+    get,
+    path = "/api/contacts",
+    tag = "contacts",
+    responses(
+        (status = 200, description = "Contacts visible to the caller", body = Vec<Contact>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<Contact>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let contacts = registries
+        .contacts()
+        .list()
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(
+        contacts
+            .into_iter()
+            .filter(|contact| principal.can_access(contact.org_id))
+            .collect(),
+    ))
+}
+
+/// `GET /api/contacts/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/contacts/{id}",
+    tag = "contacts",
+    params(("id" = String, Path, description = "Contact id")),
+    responses(
+        (status = 200, description = "The contact", body = Contact),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown contact", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<Contact>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    Ok(Json(
+        visible_contact(&registries, &principal, ContactId(id)).await?,
+    ))
+}
+
+/// `PATCH /api/contacts/{id}`
+///
+/// Notifications already queued keep the address they were queued for.
+#[utoipa::path(
+    patch,
+    path = "/api/contacts/{id}",
+    tag = "contacts",
+    params(("id" = String, Path, description = "Contact id")),
+    request_body = UpdateContact,
+    responses(
+        (status = 200, description = "Contact updated", body = Contact),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown contact", body = ErrorBody),
+    )
+)]
+pub async fn update<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<UpdateContact>,
+) -> Result<Json<Contact>, ApiError> {
+    principal.require(Scope::Admin)?;
+    let mut contact = visible_contact(&registries, &principal, ContactId(id)).await?;
+
+    let mut changed = Vec::new();
+    if let Some(name) = request.name {
+        contact.name = name;
+        changed.push("name");
+    }
+    if let Some(channels) = request.channels {
+        contact.channels = channels;
+        changed.push("channels");
+    }
+    if let Some(events) = request.events {
+        contact.events = events;
+        changed.push("events");
+    }
+    if let Some(thresholds) = request.thresholds {
+        contact.thresholds = thresholds;
+        changed.push("thresholds");
+    }
+    if let Some(quiet_hours) = request.quiet_hours {
+        contact.quiet_hours = quiet_hours;
+        changed.push("quiet_hours");
+    }
+    validate(&contact)?;
+
+    registries
+        .contacts()
+        .update(contact.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Update,
+            EntityKind::Contact,
+            contact.id.0,
+        )
+        .with_details(serde_json::json!({ "fields": changed })),
+    )
+    .await?;
+
+    Ok(Json(contact))
+}
+
+/// `DELETE /api/contacts/{id}`
+///
+/// Pending notifications are dropped along with the contact.
+#[utoipa::path(
+    delete,
+    path = "/api/contacts/{id}",
+    tag = "contacts",
+    params(("id" = String, Path, description = "Contact id")),
+    responses(
+        (status = 204, description = "Contact deleted"),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown contact", body = ErrorBody),
+    )
+)]
+pub async fn delete<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+    let contact = visible_contact(&registries, &principal, ContactId(id)).await?;
+
+    registries
+        .contacts()
+        .delete(contact.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Delete,
+            EntityKind::Contact,
+            contact.id.0,
+        ),
+    )
+    .await?;
+
+    tracing::info!(contact_id = ?contact.id, deleted_by = ?principal.key_id, "contact deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Query parameters for `GET /api/contacts/{id}/notifications`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationsQuery {
+    pub limit: Option<usize>,
+}
+
+/// `GET /api/contacts/{id}/notifications`
+///
+/// The contact's most recent notifications and their status, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/contacts/{id}/notifications",
+    tag = "contacts",
+    params(("id" = String, Path, description = "Contact id"), NotificationsQuery),
+    responses(
+        (status = 200, description = "Recent notifications", body = Vec<Notification>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown contact", body = ErrorBody),
+    )
+)]
+pub async fn notifications<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Query(query): Query<NotificationsQuery>,
+) -> Result<Json<Vec<Notification>>, ApiError> {
+    principal.require(Scope::Admin)?;
+    let contact = visible_contact(&registries, &principal, ContactId(id)).await?;
+
+    let notifications = registries
+        .contacts()
+        .notifications(contact.id, page_limit(query.limit)?)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(notifications))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json,
+        extract::{Path, State},
+        http::StatusCode,
+    };
+    use ulid::Ulid;
+
+    use super::{CreateContact, UpdateContact, create, list, update};
+    use crate::api::ApiError;
+    use crate::auth::Scope;
+    use crate::fixtures::{admin, principal};
+    use crate::notify::{Channel, ContactChannel, QuietHours};
+    use crate::org::OrgId;
+    use crate::registry::memory::InMemoryRegistries;
+    use crate::webhook::EventKind;
+
+    fn request(address: &str) -> CreateContact {
+        CreateContact {
+            name: "Tigist".to_owned(),
+            channels: vec![ContactChannel {
+                channel: Channel::Sms,
+                address: address.to_owned(),
+            }],
+            events: vec![EventKind::DeviceOffline],
+            thresholds: Vec::new(),
+            quiet_hours: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn contacts_are_scoped_to_their_organization() {
+        let registries = InMemoryRegistries::default();
+        let org_id = OrgId(Ulid::new());
+
+        let (status, Json(contact)) = create(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(org_id)),
+            Json(request("+251911234567")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(contact.org_id, Some(org_id));
+
+        let Json(listed) = list(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(org_id)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listed, std::slice::from_ref(&contact));
+        let Json(others) = list(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(OrgId(Ulid::new()))),
+        )
+        .await
+        .unwrap();
+        assert!(others.is_empty());
+
+        let Json(quiet) = update(
+            State(registries),
+            principal(Scope::Admin, Some(org_id)),
+            Path(contact.id.0),
+            Json(UpdateContact {
+                quiet_hours: Some(Some(QuietHours {
+                    from_hour: 20,
+                    until_hour: 6,
+                    utc_offset_minutes: 180,
+                })),
+                ..UpdateContact::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(quiet.quiet_hours.map(|q| q.until_hour), Some(6));
+    }
+
+    #[tokio::test]
+    async fn invalid_contacts_are_rejected() {
+        let registries = InMemoryRegistries::default();
+        let rejected = |request: CreateContact| {
+            let registries = registries.clone();
+            async move {
+                matches!(
+                    create(State(registries), admin(), Json(request)).await,
+                    Err(ApiError::BadRequest(_))
+                )
+            }
+        };
+
+        assert!(rejected(request("0911234567")).await);
+        assert!(
+            rejected(CreateContact {
+                channels: Vec::new(),
+                ..request("+251911234567")
+            })
+            .await
+        );
+        assert!(
+            rejected(CreateContact {
+                events: vec![EventKind::ThresholdCrossed],
+                ..request("+251911234567")
+            })
+            .await
+        );
+        assert!(
+            rejected(CreateContact {
+                quiet_hours: Some(QuietHours {
+                    from_hour: 24,
+                    until_hour: 6,
+                    utc_offset_minutes: 0,
+                }),
+                ..request("+251911234567")
+            })
+            .await
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use ersha_core::{DeviceId, ReadingId, SensorId};
+use jiff::Timestamp;
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use super::{ApiError, ErrorBody, record_audit, visible_device};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::correction::{Correction, Revision, Transform};
+use crate::registry::{CorrectionRegistry, ReadingRegistry, Registries};
+use crate::retention;
+use crate::rollup::Granularity;
+use crate::tuning::Tuning;
+
+/// Body of `POST /api/devices/{id}/corrections`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateCorrection {
+    /// The mis-calibrated sensor
+    pub sensor_id: SensorId,
+    /// Start of the range corrected, inclusive
+    pub from: Timestamp,
+    /// End of the range corrected, inclusive; not in the future
+    pub until: Timestamp,
+    pub transform: Transform,
+    pub reason: String,
+}
+
+/// `POST /api/devices/{id}/corrections`
+///
+/// Record a calibration correction of a sensor's readings. It is applied in
+/// the background; readings keep their original values as revisions.
+///
+/// The range can't start on a day retention has begun purging, since that
+/// day's rollup would be rebuilt from the readings left of it.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/corrections",
+    tag = "corrections",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = CreateCorrection,
+    responses(
+        (status = 202, description = "Correction recorded, to be applied", body = Correction),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(tuning): Extension<Tuning>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<CreateCorrection>,
+) -> Result<(StatusCode, Json<Correction>), ApiError> {
+    principal.require(Scope::Admin)?;
+    let device = visible_device(&registries, &principal, DeviceId(id)).await?;
+
+    let bad_request = |message: &str| Err(ApiError::BadRequest(message.to_owned()));
+    let now = Timestamp::now();
+    if !device
+        .sensors
+        .iter()
+        .any(|sensor| sensor.id == request.sensor_id)
+    {
+        return bad_request("the device has no such sensor");
+    }
+    if request.from >= request.until {
+        return bad_request("from must be before until");
+    }
+    if request.until > now {
+        return bad_request("until must not be in the future");
+    }
+    let retained = tuning.current().retention;
+    let purged_until = (retained.enabled && !retained.dry_run)
+        .then(|| retention::cutoff(now, retained.readings))
+        .flatten();
+    if purged_until.is_some_and(|cutoff| Granularity::Day.bucket_start(request.from) <= cutoff) {
+        return bad_request("from must be on a day whose readings are all still retained");
+    }
+    if let Some(reason) = request.transform.invalid() {
+        return bad_request(reason);
+    }
+    let reason = request.reason.trim();
+    if reason.is_empty() {
+        return bad_request("reason must not be empty");
+    }
+
+    let correction = Correction::new(
+        device.id,
+        request.sensor_id,
+        request.from,
+        request.until,
+        request.transform,
+        reason.to_owned(),
+        now,
+    );
+    registries
+        .corrections()
+        .create(correction.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::Correction,
+            correction.id.0,
+        )
+        .with_details(serde_json::json!({
+            "device_id": correction.device_id,
+            "sensor_id": correction.sensor_id,
+            "transform": correction.transform,
+        })),
+    )
+    .await?;
+
+    tracing::info!(correction_id = ?correction.id, device_id = ?device.id, created_by = ?principal.key_id, "reading correction recorded");
+
+    Ok((StatusCode::ACCEPTED, Json(correction)))
+}
+
+/// `GET /api/devices/{id}/corrections`
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/corrections",
+    tag = "corrections",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 200, description = "The device's corrections, oldest first", body = Vec<Correction>),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<Vec<Correction>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+    let device = visible_device(&registries, &principal, DeviceId(id)).await?;
+
+    let corrections = registries
+        .corrections()
+        .list(device.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(corrections))
+}
+
+/// `GET /api/readings/{id}/revisions`
+///
+/// How corrections changed a reading, oldest first. The first revision holds
+/// the value as ingested; none means the reading was never corrected.
+#[utoipa::path(
+    get,
+    path = "/api/readings/{id}/revisions",
+    tag = "corrections",
+    params(("id" = String, Path, description = "Reading id")),
+    responses(
+        (status = 200, description = "The reading's revisions", body = Vec<Revision>),
+        (status = 404, description = "Unknown reading", body = ErrorBody),
+    )
+)]
+pub async fn revisions<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<Vec<Revision>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let reading = registries
+        .readings()
+        .get(ReadingId(id))
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    visible_device(&registries, &principal, reading.device_id).await?;
+
+    let revisions = registries
+        .corrections()
+        .revisions(reading.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(revisions))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Extension, Json,
+        extract::{Path, State},
+        http::StatusCode,
+    };
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, H3Cell, Sensor, SensorId, SensorKind,
+        SensorMetric,
+    };
+    use jiff::{SignedDuration, Timestamp};
+    use ordered_float::NotNan;
+    use ulid::Ulid;
+
+    use super::{CreateCorrection, create, list};
+    use crate::api::ApiError;
+    use crate::correction::{CorrectionState, Transform};
+    use crate::fixtures::{admin, reader};
+    use crate::registry::{DeviceRegistry, memory::InMemoryRegistries};
+    use crate::tuning::{Tunables, Tuning};
+
+    #[tokio::test]
+    async fn corrections_are_validated_and_queued() {
+        let registries = InMemoryRegistries::default();
+        let state = || State(registries.clone());
+        let sensor_id = SensorId(Ulid::new());
+        let device_id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id: device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: Timestamp::now(),
+                sensors: Box::new([Sensor {
+                    id: sensor_id,
+                    kind: SensorKind::AirTemp,
+                    metric: SensorMetric::AirTemp {
+                        value: NotNan::default(),
+                    },
+                }]),
+            })
+            .await
+            .unwrap();
+
+        let now = Timestamp::now();
+        // Readings are kept for 90 days by default.
+        let tuning = || Extension(Tuning::new(Tunables::default()));
+        let request = |sensor_id, until: Timestamp, scale| {
+            Json(CreateCorrection {
+                sensor_id,
+                from: now - SignedDuration::from_hours(24),
+                until,
+                transform: Transform::Linear { scale, offset: 0.5 },
+                reason: "field calibration".to_owned(),
+            })
+        };
+        let path = || Path(device_id.0);
+
+        let bad = [
+            request(SensorId(Ulid::new()), now, 1.0),
+            request(sensor_id, now + SignedDuration::from_hours(1), 1.0),
+            request(sensor_id, now, 0.0),
+            Json(CreateCorrection {
+                from: now - SignedDuration::from_hours(90 * 24),
+                ..request(sensor_id, now, 1.0).0
+            }),
+        ];
+        for body in bad {
+            let result = create(state(), admin(), tuning(), path(), body).await;
+            assert!(matches!(result, Err(ApiError::BadRequest(_))));
+        }
+        let result = create(
+            state(),
+            reader(),
+            tuning(),
+            path(),
+            request(sensor_id, now, 1.0),
+        )
+        .await;
+        assert!(matches!(result, Err(ApiError::Forbidden)));
+
+        let (status, Json(correction)) = create(
+            state(),
+            admin(),
+            tuning(),
+            path(),
+            request(sensor_id, now, 1.0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(correction.state, CorrectionState::Pending);
+
+        let Json(listed) = list(state(), reader(), path()).await.unwrap();
+        assert_eq!(listed, vec![correction]);
+    }
+}
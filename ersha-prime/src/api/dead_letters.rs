@@ -0,0 +1,287 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use ersha_core::DispatcherId;
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, ErrorBody, page_limit, record_audit, scope_dispatchers, visible_dispatcher};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::config::AuthConfig;
+use crate::dead_letter::{self, DeadLetter, DeadLetterId, DeadLetterState};
+use crate::events::EventBus;
+use crate::registry::{DeadLetterRegistry, Registries, filter::DeadLetterFilter};
+
+/// Query parameters for `GET /api/dead-letters`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeadLettersQuery {
+    /// Only items uploaded by this dispatcher
+    #[param(value_type = Option<String>)]
+    pub dispatcher_id: Option<Ulid>,
+    /// `pending` or `redriven`
+    #[param(value_type = Option<String>)]
+    pub state: Option<DeadLetterState>,
+    pub limit: Option<usize>,
+}
+
+/// Body of `POST /api/dead-letters/redrive`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct RedriveRequest {
+    /// Only items uploaded by this dispatcher
+    #[schema(value_type = Option<String>)]
+    pub dispatcher_id: Option<Ulid>,
+    /// Most items to re-drive, oldest first
+    pub limit: Option<usize>,
+}
+
+/// A dead letter the caller may see. Those uploaded by other
+/// organizations' dispatchers are reported as not found.
+pub(super) async fn visible_letter<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: DeadLetterId,
+) -> Result<DeadLetter, ApiError> {
+    let letter = registries
+        .dead_letters()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    visible_dispatcher(registries, principal, letter.dispatcher_id).await?;
+
+    Ok(letter)
+}
+
+/// Send dead letters through validation again and audit the attempt.
+pub(super) async fn redrive_letters<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    auth: AuthConfig,
+    events: &dyn EventBus,
+    letters: Vec<DeadLetter>,
+) -> Result<Vec<DeadLetter>, ApiError> {
+    let letters = dead_letter::redrive(registries, auth, events, letters, jiff::Timestamp::now())
+        .await
+        .map_err(ApiError::internal)?;
+
+    for letter in &letters {
+        record_audit(
+            registries,
+            AuditEntry::by(
+                principal,
+                AuditAction::Redrive,
+                EntityKind::DeadLetter,
+                letter.id.0,
+            )
+            .with_details(serde_json::json!({
+                "dispatcher_id": letter.dispatcher_id,
+                "state": letter.state,
+                "reason": letter.reason,
+            })),
+        )
+        .await?;
+    }
+
+    Ok(letters)
+}
+
+/// `GET /api/dead-letters`
+///
+/// Items refused from uploaded batches, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/dead-letters",
+    tag = "dead-letters",
+    params(DeadLettersQuery),
+    responses(
+        (status = 200, description = "Dead letters", body = Vec<DeadLetter>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<DeadLettersQuery>,
+) -> Result<Json<Vec<DeadLetter>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let mut filter = DeadLetterFilter {
+        dispatcher_ids: query.dispatcher_id.map(|id| vec![DispatcherId(id)]),
+        state: query.state,
+        reasons: None,
+    };
+    if !scope_dispatchers(&registries, &principal, &mut filter.dispatcher_ids).await? {
+        return Ok(Json(Vec::new()));
+    }
+
+    let letters = registries
+        .dead_letters()
+        .list(filter, page_limit(query.limit)?)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(letters))
+}
+
+/// `GET /api/dead-letters/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/dead-letters/{id}",
+    tag = "dead-letters",
+    params(("id" = String, Path, description = "Dead letter id")),
+    responses(
+        (status = 200, description = "The dead letter", body = DeadLetter),
+        (status = 404, description = "Unknown dead letter", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<DeadLetter>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let letter = visible_letter(&registries, &principal, DeadLetterId(id)).await?;
+
+    Ok(Json(letter))
+}
+
+/// `POST /api/dead-letters/{id}/redrive`
+///
+/// Check the item again and store it if it now passes. One still refused
+/// stays pending with the latest reason.
+#[utoipa::path(
+    post,
+    path = "/api/dead-letters/{id}/redrive",
+    tag = "dead-letters",
+    params(("id" = String, Path, description = "Dead letter id")),
+    responses(
+        (status = 200, description = "The dead letter after the attempt", body = DeadLetter),
+        (status = 404, description = "Unknown dead letter", body = ErrorBody),
+        (status = 409, description = "Already re-driven", body = ErrorBody),
+    )
+)]
+pub async fn redrive<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(auth): Extension<AuthConfig>,
+    Extension(events): Extension<Arc<dyn EventBus>>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<DeadLetter>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let letter = visible_letter(&registries, &principal, DeadLetterId(id)).await?;
+    if letter.state != DeadLetterState::Pending {
+        return Err(ApiError::Conflict("already re-driven".to_owned()));
+    }
+
+    let mut letters =
+        redrive_letters(&registries, &principal, auth, &*events, vec![letter]).await?;
+    let letter = letters.pop().ok_or(ApiError::Internal)?;
+
+    Ok(Json(letter))
+}
+
+/// `POST /api/dead-letters/redrive`
+///
+/// Re-drive pending dead letters in bulk, oldest first, after fixing what
+/// got them refused.
+#[utoipa::path(
+    post,
+    path = "/api/dead-letters/redrive",
+    tag = "dead-letters",
+    request_body = RedriveRequest,
+    responses(
+        (status = 200, description = "The dead letters after the attempt", body = Vec<DeadLetter>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn redrive_all<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(auth): Extension<AuthConfig>,
+    Extension(events): Extension<Arc<dyn EventBus>>,
+    Json(request): Json<RedriveRequest>,
+) -> Result<Json<Vec<DeadLetter>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let mut filter = DeadLetterFilter {
+        dispatcher_ids: request.dispatcher_id.map(|id| vec![DispatcherId(id)]),
+        state: Some(DeadLetterState::Pending),
+        reasons: None,
+    };
+    if !scope_dispatchers(&registries, &principal, &mut filter.dispatcher_ids).await? {
+        return Ok(Json(Vec::new()));
+    }
+
+    let letters = registries
+        .dead_letters()
+        .list(filter, page_limit(request.limit)?)
+        .await
+        .map_err(ApiError::registry)?;
+    let letters = redrive_letters(&registries, &principal, auth, &*events, letters).await?;
+
+    Ok(Json(letters))
+}
+
+/// `DELETE /api/dead-letters/{id}`
+///
+/// Discard an item that will never be accepted.
+#[utoipa::path(
+    delete,
+    path = "/api/dead-letters/{id}",
+    tag = "dead-letters",
+    params(("id" = String, Path, description = "Dead letter id")),
+    responses(
+        (status = 204, description = "Dead letter discarded"),
+        (status = 404, description = "Unknown dead letter", body = ErrorBody),
+    )
+)]
+pub async fn delete<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let letter = visible_letter(&registries, &principal, DeadLetterId(id)).await?;
+    discard(&registries, &principal, letter).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a dead letter and audit it.
+pub(super) async fn discard<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    letter: DeadLetter,
+) -> Result<(), ApiError> {
+    registries
+        .dead_letters()
+        .delete(letter.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        registries,
+        AuditEntry::by(
+            principal,
+            AuditAction::Delete,
+            EntityKind::DeadLetter,
+            letter.id.0,
+        )
+        .with_details(serde_json::json!({
+            "dispatcher_id": letter.dispatcher_id,
+            "kind": letter.item.kind(),
+            "item_id": letter.item.item_id(),
+        })),
+    )
+    .await
+}
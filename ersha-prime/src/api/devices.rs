@@ -0,0 +1,1763 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+};
+use ersha_core::{
+    Device, DeviceId, DeviceKind, DeviceState, DeviceStatus, DispatcherId, H3Cell, Sensor,
+    SensorId, SensorReading,
+};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{
+    ApiError, BatchGet, BatchGetRequest, ErrorBody, Order, Page, RegisterQuery, Tagged,
+    already_registered, groups::with_group, if_match, nullable, page_limit, parse_cursor,
+    parse_list, record_audit, scope_fields, tagged, visible_device, visible_dispatcher,
+};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::events::{BusEvent, EventBus};
+use crate::group::normalize_tag;
+use crate::metadata;
+use crate::org::OrgId;
+use crate::placement::Placement;
+use crate::region;
+use crate::registry::{
+    DeviceRegistry, DeviceStatusRegistry, OrgRegistry, ReadingRegistry, Registries,
+    filter::{DeviceFilter, DeviceSortBy, Pagination, QueryOptions},
+};
+
+/// Query parameters for `GET /api/devices`.
+///
+/// List parameters are comma separated. Locations are H3 indexes in hex.
+/// `GET /api/devices` also takes `metadata.<key>=<value>` parameters,
+/// matching devices whose metadata sets `key` to `value`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DevicesQuery {
+    /// Device states, e.g. `active,suspended`. `offline` matches devices
+    /// marked disconnected.
+    pub state: Option<String>,
+    pub location: Option<String>,
+    /// H3 cells in hex; devices inside any of them match
+    pub within: Option<String>,
+    /// Part of the manufacturer name
+    pub manufacturer: Option<String>,
+    /// Only devices assigned to this dispatcher
+    pub dispatcher_id: Option<Ulid>,
+    /// Tags, e.g. `pilot-a,north-ridge`; devices carrying all of them match
+    pub tag: Option<String>,
+    /// Only members of this group
+    pub group: Option<Ulid>,
+    #[serde(default)]
+    pub sort_by: DeviceSortField,
+    #[serde(default)]
+    pub order: Order,
+    /// Cursor returned as `next_cursor` by the previous page
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceSortField {
+    #[default]
+    ProvisionedAt,
+    State,
+    Manufacturer,
+    SensorCount,
+}
+
+impl DevicesQuery {
+    pub(super) fn into_options(self) -> Result<QueryOptions<DeviceFilter, DeviceSortBy>, ApiError> {
+        let (states, disconnected) =
+            match parse_list("state", self.state.as_deref(), parse_state_filter)? {
+                None => (None, None),
+                Some(wanted) => {
+                    let offline = wanted.contains(&StateFilter::Offline);
+                    let states: Vec<DeviceState> = wanted
+                        .into_iter()
+                        .filter_map(|state| match state {
+                            StateFilter::Lifecycle(state) => Some(state),
+                            StateFilter::Offline => None,
+                        })
+                        .collect();
+                    let states = (!(offline && states.is_empty())).then_some(states);
+                    (states, offline.then_some(true))
+                }
+            };
+        let filter = DeviceFilter {
+            states,
+            disconnected,
+            locations: parse_list("location", self.location.as_deref(), |s| {
+                u64::from_str_radix(s, 16).ok().map(H3Cell)
+            })?,
+            within: parse_list("within", self.within.as_deref(), region::parse_cell)?,
+            manufacturer_pattern: self.manufacturer,
+            dispatcher_id: self.dispatcher_id.map(DispatcherId),
+            tags: parse_list("tag", self.tag.as_deref(), |s| normalize_tag(s).ok())?,
+            ..Default::default()
+        };
+
+        Ok(QueryOptions {
+            filter,
+            sort_by: match self.sort_by {
+                DeviceSortField::ProvisionedAt => DeviceSortBy::ProvisionAt,
+                DeviceSortField::State => DeviceSortBy::State,
+                DeviceSortField::Manufacturer => DeviceSortBy::Manufacturer,
+                DeviceSortField::SensorCount => DeviceSortBy::SensorCount,
+            },
+            sort_order: self.order.into(),
+            pagination: Pagination::Cursor {
+                after: parse_cursor(self.after.as_deref())?,
+                limit: page_limit(self.limit)?,
+            },
+        })
+    }
+}
+
+/// A value of the `state` filter of `GET /api/devices`.
+#[derive(Debug, Clone, PartialEq)]
+enum StateFilter {
+    Lifecycle(DeviceState),
+    /// Marked disconnected by its dispatcher or the watchdog
+    Offline,
+}
+
+fn parse_state_filter(s: &str) -> Option<StateFilter> {
+    match s {
+        "offline" => Some(StateFilter::Offline),
+        _ => parse_device_state(s).map(StateFilter::Lifecycle),
+    }
+}
+
+pub(super) fn parse_device_state(s: &str) -> Option<DeviceState> {
+    let state = match s {
+        "active" => DeviceState::Active,
+        "suspended" => DeviceState::Suspended,
+        "decommissioned" => DeviceState::Decommissioned,
+        _ => return None,
+    };
+
+    Some(state)
+}
+
+/// `GET /api/devices`
+#[utoipa::path(
+    get,
+    path = "/api/devices",
+    tag = "devices",
+    params(DevicesQuery),
+    responses(
+        (status = 200, description = "A page of devices", body = Page<Device>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<DevicesQuery>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Page<Device>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let group = query.group;
+    let mut options = query.into_options()?;
+    options.filter.metadata = metadata_filter(&params)?;
+    with_group(&registries, &principal, group, &mut options.filter.tags).await?;
+    list_devices(&registries, &principal, options).await
+}
+
+/// The `metadata.<key>=<value>` filters among a query's parameters.
+fn metadata_filter(
+    params: &[(String, String)],
+) -> Result<Option<Vec<(String, serde_json::Value)>>, ApiError> {
+    let filters = params
+        .iter()
+        .filter_map(|(name, value)| Some((name.strip_prefix("metadata.")?, value)))
+        .map(|(key, value)| metadata::parse_filter(key, value))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| ApiError::BadRequest(error.to_string()))?;
+
+    Ok((!filters.is_empty()).then_some(filters))
+}
+
+/// A page of the devices the caller may see.
+pub(super) async fn list_devices<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    mut options: QueryOptions<DeviceFilter, DeviceSortBy>,
+) -> Result<Page<Device>, ApiError> {
+    options.filter.org_id = principal.org_id;
+
+    let limit = options.pagination.limit();
+    let sort_by = options.sort_by;
+    if !scope_fields(principal, &mut options.filter.within) {
+        return Ok(Page::new(Vec::new(), limit, 0, |d| sort_by.cursor(d)));
+    }
+    let devices = registries.devices();
+
+    let total = devices
+        .count(Some(options.filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let items = devices.list(options).await.map_err(ApiError::registry)?;
+
+    Ok(Page::new(items, limit, total, |d| sort_by.cursor(d)))
+}
+
+/// Current conditions at a device.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceSnapshot {
+    pub device_id: DeviceId,
+    /// Newest reading of each sensor, ordered by sensor id
+    pub readings: Vec<SensorReading>,
+    /// Most recent status report, if any
+    pub status: Option<DeviceStatus>,
+}
+
+/// A device its dispatcher stopped hearing from.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OfflineDevice {
+    pub device_id: DeviceId,
+    /// Last time the dispatcher heard from the device
+    pub since: jiff::Timestamp,
+    /// The dispatcher that reported it, if the device is assigned to one
+    pub dispatcher_id: Option<DispatcherId>,
+}
+
+/// Body of `POST /api/devices`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RegisterDevice {
+    /// Generated when not given
+    pub id: Option<DeviceId>,
+    pub kind: DeviceKind,
+    /// H3 cell the device is installed at
+    pub location: u64,
+    pub manufacturer: Option<String>,
+    #[serde(default)]
+    pub sensors: Vec<Sensor>,
+    /// Seconds apart the device is expected to report; it is marked offline
+    /// when silent for longer
+    pub reporting_interval_secs: Option<u64>,
+    /// Hardware revision, targeted by firmware rollouts
+    pub hardware_rev: Option<String>,
+    /// Free-form JSON object, checked against the organization's metadata
+    /// schema if it has one
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// `POST /api/devices`
+///
+/// Devices registered by an organization key belong to its organization.
+/// Registering a taken id answers 409 with the device registered under it,
+/// unless `upsert` asks to re-provision that device, keeping its state,
+/// organization, dispatcher and placement.
+#[utoipa::path(
+    post,
+    path = "/api/devices",
+    tag = "devices",
+    params(RegisterQuery),
+    request_body = RegisterDevice,
+    responses(
+        (status = 201, description = "Device registered", body = Device),
+        (status = 200, description = "Device re-provisioned", body = Device),
+        (status = 400, description = "Invalid request or metadata", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 409, description = "A device with this id exists", body = ErrorBody),
+    )
+)]
+pub async fn register<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(events): Extension<Arc<dyn EventBus>>,
+    Query(query): Query<RegisterQuery>,
+    Json(request): Json<RegisterDevice>,
+) -> Result<(StatusCode, Json<Device>), ApiError> {
+    principal.require(Scope::Admin)?;
+    check_reporting_interval(request.reporting_interval_secs)?;
+    if let Some(metadata) = &request.metadata {
+        check_metadata(&registries, principal.org_id, metadata).await?;
+    }
+
+    let device_id = request.id.unwrap_or_else(|| DeviceId(Ulid::new()));
+    let devices = registries.devices();
+
+    let mut device = Device {
+        id: device_id,
+        kind: request.kind,
+        state: DeviceState::Active,
+        location: H3Cell(request.location),
+        manufacturer: request.manufacturer.map(Into::into),
+        provisioned_at: jiff::Timestamp::now(),
+        sensors: request.sensors.into_boxed_slice(),
+    };
+    let status = if query.upsert {
+        let existing = devices.get(device_id).await.map_err(ApiError::registry)?;
+        if let Some(existing) = &existing {
+            // Another organization's device is not the caller's to take over.
+            visible_device(&registries, &principal, device_id).await?;
+            // Re-provisioning is no way around the state machine.
+            device.state = existing.state.clone();
+            device.provisioned_at = existing.provisioned_at;
+        }
+        devices
+            .register(device.clone())
+            .await
+            .map_err(ApiError::registry)?;
+        if existing.is_some() {
+            StatusCode::OK
+        } else {
+            StatusCode::CREATED
+        }
+    } else {
+        let existing = devices
+            .register_new(device.clone())
+            .await
+            .map_err(ApiError::registry)?;
+        if existing.is_some() {
+            let visible = visible_device(&registries, &principal, device_id).await;
+            return Err(already_registered(visible, "device"));
+        }
+        StatusCode::CREATED
+    };
+    if request.reporting_interval_secs.is_some() {
+        devices
+            .set_reporting_interval(device_id, request.reporting_interval_secs)
+            .await
+            .map_err(ApiError::registry)?;
+    }
+    if request.hardware_rev.is_some() {
+        devices
+            .set_hardware_rev(device_id, request.hardware_rev.clone())
+            .await
+            .map_err(ApiError::registry)?;
+    }
+    if request.metadata.is_some() {
+        devices
+            .set_metadata(device_id, request.metadata)
+            .await
+            .map_err(ApiError::registry)?;
+    }
+
+    if principal.org_id.is_some() {
+        devices
+            .set_org(device_id, principal.org_id)
+            .await
+            .map_err(ApiError::registry)?;
+    }
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Register,
+            EntityKind::Device,
+            device_id.0,
+        ),
+    )
+    .await?;
+
+    tracing::info!(?device_id, registered_by = ?principal.key_id, "device registered");
+    events
+        .publish(BusEvent::DeviceRegistered {
+            device: device.clone(),
+            org_id: principal.org_id,
+        })
+        .await;
+
+    Ok((status, Json(device)))
+}
+
+/// Reject a reporting interval no device could keep to.
+fn check_reporting_interval(interval_secs: Option<u64>) -> Result<(), ApiError> {
+    if interval_secs == Some(0) {
+        return Err(ApiError::BadRequest(
+            "reporting_interval_secs must be positive".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reject metadata that isn't an object or doesn't match the schema of the
+/// organization the device belongs to.
+async fn check_metadata<R: Registries>(
+    registries: &R,
+    org_id: Option<OrgId>,
+    metadata: &serde_json::Value,
+) -> Result<(), ApiError> {
+    let schema = match org_id {
+        Some(org_id) => registries
+            .orgs()
+            .metadata_schema(org_id)
+            .await
+            .map_err(ApiError::registry)?,
+        None => None,
+    };
+
+    metadata::validate(metadata, schema.as_ref())
+        .map_err(|error| ApiError::BadRequest(error.to_string()))
+}
+
+/// A device with what prime keeps about it besides.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceView {
+    #[serde(flatten)]
+    pub device: Device,
+    pub placement: Placement,
+    pub tags: Vec<String>,
+    /// Seconds apart the device is expected to report, if it is watched
+    pub reporting_interval_secs: Option<u64>,
+    pub hardware_rev: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<serde_json::Value>,
+    /// Last change to the device, also sent as its `ETag`
+    pub updated_at: jiff::Timestamp,
+}
+
+impl DeviceView {
+    fn tagged(self) -> Tagged<DeviceView> {
+        tagged(self.updated_at, self)
+    }
+}
+
+/// Body of `PATCH /api/devices/{id}`. Fields left out are kept.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdateDevice {
+    /// H3 cell the device is installed at
+    pub location: Option<u64>,
+    /// `null` clears the manufacturer
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub manufacturer: Option<Option<String>>,
+    /// Sensors to attach. A removed sensor may be attached again to change it.
+    #[serde(default)]
+    pub add_sensors: Vec<Sensor>,
+    /// Ids of sensors to detach
+    #[serde(default)]
+    pub remove_sensors: Vec<SensorId>,
+    /// Replaces the device's placement
+    pub placement: Option<Placement>,
+    /// `null` stops watching whether the device reports
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<u64>)]
+    pub reporting_interval_secs: Option<Option<u64>>,
+    /// `null` forgets the device's hardware revision
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub hardware_rev: Option<Option<String>>,
+    /// Replaces the device's metadata; `null` clears it
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<Option<serde_json::Value>>,
+}
+
+impl UpdateDevice {
+    /// Names of the fields the update changes, for the audit log.
+    fn fields(&self) -> Vec<&'static str> {
+        [
+            ("location", self.location.is_some()),
+            ("manufacturer", self.manufacturer.is_some()),
+            ("add_sensors", !self.add_sensors.is_empty()),
+            ("remove_sensors", !self.remove_sensors.is_empty()),
+            ("placement", self.placement.is_some()),
+            (
+                "reporting_interval_secs",
+                self.reporting_interval_secs.is_some(),
+            ),
+            ("hardware_rev", self.hardware_rev.is_some()),
+            ("metadata", self.metadata.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect()
+    }
+
+    fn apply(self, device: Device) -> Result<(Device, Option<Placement>), ApiError> {
+        let mut sensors = device.sensors.into_vec();
+        for id in &self.remove_sensors {
+            let position = sensors
+                .iter()
+                .position(|sensor| sensor.id == *id)
+                .ok_or_else(|| ApiError::BadRequest(format!("unknown sensor {}", id.0)))?;
+            sensors.remove(position);
+        }
+        for sensor in self.add_sensors {
+            if sensors.iter().any(|attached| attached.id == sensor.id) {
+                return Err(ApiError::Conflict(format!(
+                    "sensor {} is already attached",
+                    sensor.id.0
+                )));
+            }
+            sensors.push(sensor);
+        }
+
+        let device = Device {
+            location: self.location.map(H3Cell).unwrap_or(device.location),
+            manufacturer: match self.manufacturer {
+                Some(manufacturer) => manufacturer.map(Into::into),
+                None => device.manufacturer,
+            },
+            sensors: sensors.into_boxed_slice(),
+            ..device
+        };
+
+        Ok((device, self.placement))
+    }
+}
+
+/// `GET /api/devices/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 200, description = "The device", body = DeviceView,
+            headers(("ETag" = String, description = "Revision to send as `If-Match` when updating"))),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Tagged<DeviceView>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let device_id = DeviceId(id);
+    let device = visible_device(&registries, &principal, device_id).await?;
+    let details = registries
+        .devices()
+        .details(device_id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    let devices = registries.devices();
+    let tags = devices.tags(device_id).await.map_err(ApiError::registry)?;
+    let reporting_interval_secs = devices
+        .reporting_interval(device_id)
+        .await
+        .map_err(ApiError::registry)?;
+    let hardware_rev = devices
+        .hardware_rev(device_id)
+        .await
+        .map_err(ApiError::registry)?;
+    let metadata = devices
+        .metadata(device_id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(DeviceView {
+        device,
+        placement: details.placement,
+        tags,
+        reporting_interval_secs,
+        hardware_rev,
+        metadata,
+        updated_at: details.updated_at,
+    }
+    .tagged())
+}
+
+/// `POST /api/devices/batch-get`
+///
+/// Look up many devices in one round trip. Devices the caller may not see
+/// are reported missing, as `GET /api/devices/{id}` reports them not found.
+#[utoipa::path(
+    post,
+    path = "/api/devices/batch-get",
+    tag = "devices",
+    request_body = BatchGetRequest,
+    responses(
+        (status = 200, description = "The devices found and the ids that weren't", body = BatchGet<Device>),
+        (status = 400, description = "Too many ids", body = ErrorBody),
+    )
+)]
+pub async fn batch_get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<BatchGetRequest>,
+) -> Result<Json<BatchGet<Device>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let ids: Vec<DeviceId> = request.into_ids()?.into_iter().map(DeviceId).collect();
+    let devices = registries.devices();
+    let mut registered: HashMap<DeviceId, Device> = devices
+        .get_many(&ids)
+        .await
+        .map_err(ApiError::registry)?
+        .into_iter()
+        .map(|device| (device.id, device))
+        .collect();
+
+    let mut found = Vec::with_capacity(registered.len());
+    let mut missing = Vec::new();
+    for id in ids {
+        let Some(device) = registered.remove(&id) else {
+            missing.push(id.0);
+            continue;
+        };
+
+        let owned = match principal.org_id {
+            Some(_) => principal.can_access(devices.org(id).await.map_err(ApiError::registry)?),
+            None => true,
+        };
+        if owned && principal.can_see(device.location) {
+            found.push(device);
+        } else {
+            missing.push(id.0);
+        }
+    }
+
+    Ok(Json(BatchGet { found, missing }))
+}
+
+/// `PATCH /api/devices/{id}`
+///
+/// Change a device's location, manufacturer, sensors, placement or metadata.
+/// Send the `ETag` of the device as read in `If-Match` to refuse the update
+/// if the device has changed since.
+#[utoipa::path(
+    patch,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(
+        ("id" = String, Path, description = "Device id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the device must still have"),
+    ),
+    request_body = UpdateDevice,
+    responses(
+        (status = 200, description = "Device updated", body = DeviceView,
+            headers(("ETag" = String, description = "The device's new revision"))),
+        (status = 400, description = "Unknown sensor removed", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+        (status = 409, description = "Device is decommissioned or sensor already attached", body = ErrorBody),
+        (status = 412, description = "Device changed since it was read", body = ErrorBody),
+    )
+)]
+pub async fn update<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateDevice>,
+) -> Result<Tagged<DeviceView>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let device_id = DeviceId(id);
+    let devices = registries.devices();
+    let device = visible_device(&registries, &principal, device_id).await?;
+    if device.state == DeviceState::Decommissioned {
+        return Err(ApiError::Conflict("device is decommissioned".to_owned()));
+    }
+
+    let details = devices
+        .details(device_id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    if let Some(expected) = if_match(&headers)?
+        && expected != details.updated_at
+    {
+        return Err(ApiError::PreconditionFailed);
+    }
+
+    let fields = request.fields();
+    let reporting_interval = request.reporting_interval_secs;
+    let hardware_rev = request.hardware_rev.clone();
+    let metadata = request.metadata.clone();
+    if let Some(interval_secs) = reporting_interval {
+        check_reporting_interval(interval_secs)?;
+    }
+    if let Some(Some(metadata)) = &metadata {
+        let org_id = devices.org(device_id).await.map_err(ApiError::registry)?;
+        check_metadata(&registries, org_id, metadata).await?;
+    }
+    let (device, placement) = request.apply(device)?;
+    let placement = placement.unwrap_or(details.placement);
+
+    // Updating only if nothing changed since `details` were read keeps
+    // concurrent updates from overwriting each other.
+    let updated_at = devices
+        .update(
+            device_id,
+            device.clone(),
+            placement.clone(),
+            Some(details.updated_at),
+        )
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::PreconditionFailed)?;
+    if let Some(interval_secs) = reporting_interval {
+        devices
+            .set_reporting_interval(device_id, interval_secs)
+            .await
+            .map_err(ApiError::registry)?;
+    }
+    if let Some(hardware_rev) = hardware_rev {
+        devices
+            .set_hardware_rev(device_id, hardware_rev)
+            .await
+            .map_err(ApiError::registry)?;
+    }
+    if let Some(metadata) = metadata {
+        devices
+            .set_metadata(device_id, metadata)
+            .await
+            .map_err(ApiError::registry)?;
+    }
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Update,
+            EntityKind::Device,
+            device_id.0,
+        )
+        .with_details(serde_json::json!({ "fields": fields })),
+    )
+    .await?;
+
+    tracing::info!(?device_id, ?fields, updated_by = ?principal.key_id, "device updated");
+
+    let tags = devices.tags(device_id).await.map_err(ApiError::registry)?;
+    let reporting_interval_secs = devices
+        .reporting_interval(device_id)
+        .await
+        .map_err(ApiError::registry)?;
+    let hardware_rev = devices
+        .hardware_rev(device_id)
+        .await
+        .map_err(ApiError::registry)?;
+    let metadata = devices
+        .metadata(device_id)
+        .await
+        .map_err(ApiError::registry)?;
+    Ok(DeviceView {
+        device,
+        placement,
+        tags,
+        reporting_interval_secs,
+        hardware_rev,
+        metadata,
+        updated_at,
+    }
+    .tagged())
+}
+
+/// `GET /api/devices/{id}/latest`
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/latest",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 200, description = "Latest reading per sensor and latest status", body = DeviceSnapshot),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn latest<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<DeviceSnapshot>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let device_id = DeviceId(id);
+    visible_device(&registries, &principal, device_id).await?;
+
+    let readings = registries
+        .readings()
+        .latest_per_sensor(device_id)
+        .await
+        .map_err(ApiError::registry)?;
+    let status = registries
+        .statuses()
+        .latest(device_id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(DeviceSnapshot {
+        device_id,
+        readings,
+        status,
+    }))
+}
+
+/// `GET /api/devices/offline`
+///
+/// Devices whose dispatcher reported them disconnected and hasn't heard
+/// from since, longest offline first.
+#[utoipa::path(
+    get,
+    path = "/api/devices/offline",
+    tag = "devices",
+    responses(
+        (status = 200, description = "Disconnected devices", body = Vec<OfflineDevice>),
+    )
+)]
+pub async fn offline<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<OfflineDevice>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let devices = registries.devices();
+    let mut disconnected = devices.disconnected().await.map_err(ApiError::registry)?;
+    disconnected.sort_by_key(|&(id, since)| (since, id.0));
+
+    let mut offline = Vec::with_capacity(disconnected.len());
+    for (device_id, since) in disconnected {
+        if principal.org_id.is_some()
+            && devices.org(device_id).await.map_err(ApiError::registry)? != principal.org_id
+        {
+            continue;
+        }
+        if principal.fields.is_some() {
+            let device = devices.get(device_id).await.map_err(ApiError::registry)?;
+            if !device.is_some_and(|device| principal.can_see(device.location)) {
+                continue;
+            }
+        }
+        let dispatcher_id = devices
+            .dispatcher(device_id)
+            .await
+            .map_err(ApiError::registry)?;
+        offline.push(OfflineDevice {
+            device_id,
+            since,
+            dispatcher_id,
+        });
+    }
+
+    Ok(Json(offline))
+}
+
+/// `POST /api/devices/{id}/suspend`
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/suspend",
+    tag = "devices",
+    params(
+        ("id" = String, Path, description = "Device id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the device must still have"),
+    ),
+    responses(
+        (status = 200, description = "Device suspended", body = Device,
+            headers(("ETag" = String, description = "The device's new revision"))),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+        (status = 409, description = "Device is decommissioned", body = ErrorBody),
+        (status = 412, description = "Device changed since it was read", body = ErrorBody),
+    )
+)]
+pub async fn suspend<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<Tagged<Device>, ApiError> {
+    transition(
+        &registries,
+        principal,
+        DeviceId(id),
+        &headers,
+        DeviceState::Suspended,
+    )
+    .await
+}
+
+/// `POST /api/devices/{id}/reactivate`
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/reactivate",
+    tag = "devices",
+    params(
+        ("id" = String, Path, description = "Device id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the device must still have"),
+    ),
+    responses(
+        (status = 200, description = "Device reactivated", body = Device,
+            headers(("ETag" = String, description = "The device's new revision"))),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+        (status = 409, description = "Device is decommissioned", body = ErrorBody),
+        (status = 412, description = "Device changed since it was read", body = ErrorBody),
+    )
+)]
+pub async fn reactivate<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<Tagged<Device>, ApiError> {
+    transition(
+        &registries,
+        principal,
+        DeviceId(id),
+        &headers,
+        DeviceState::Active,
+    )
+    .await
+}
+
+/// `POST /api/devices/{id}/decommission`
+///
+/// Decommissioning is permanent; readings from the device are refused from
+/// then on.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/decommission",
+    tag = "devices",
+    params(
+        ("id" = String, Path, description = "Device id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the device must still have"),
+    ),
+    responses(
+        (status = 200, description = "Device decommissioned", body = Device,
+            headers(("ETag" = String, description = "The device's new revision"))),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+        (status = 409, description = "Device is decommissioned", body = ErrorBody),
+        (status = 412, description = "Device changed since it was read", body = ErrorBody),
+    )
+)]
+pub async fn decommission<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<Tagged<Device>, ApiError> {
+    transition(
+        &registries,
+        principal,
+        DeviceId(id),
+        &headers,
+        DeviceState::Decommissioned,
+    )
+    .await
+}
+
+async fn transition<R: Registries>(
+    registries: &R,
+    principal: Principal,
+    id: DeviceId,
+    headers: &HeaderMap,
+    state: DeviceState,
+) -> Result<Tagged<Device>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let devices = registries.devices();
+    let details = devices
+        .details(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    let device = visible_device(registries, &principal, id).await?;
+
+    if device.state == DeviceState::Decommissioned {
+        return Err(ApiError::Conflict("device is decommissioned".to_owned()));
+    }
+    if let Some(expected) = if_match(headers)?
+        && expected != details.updated_at
+    {
+        return Err(ApiError::PreconditionFailed);
+    }
+
+    let action = match state {
+        DeviceState::Active => AuditAction::Reactivate,
+        DeviceState::Suspended => AuditAction::Suspend,
+        DeviceState::Decommissioned => AuditAction::Decommission,
+    };
+
+    // `details` were read before `device`, so a change made in between
+    // fails the update rather than being overwritten.
+    let device = Device { state, ..device };
+    let updated_at = devices
+        .update(
+            id,
+            device.clone(),
+            details.placement,
+            Some(details.updated_at),
+        )
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::PreconditionFailed)?;
+
+    record_audit(
+        registries,
+        AuditEntry::by(&principal, action, EntityKind::Device, id.0),
+    )
+    .await?;
+
+    tracing::info!(device_id = ?id, state = ?device.state, changed_by = ?principal.key_id, "device state changed");
+
+    Ok(tagged(updated_at, device))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AssignDispatcher {
+    pub dispatcher_id: DispatcherId,
+}
+
+/// `PUT /api/devices/{id}/dispatcher`
+///
+/// Assign the device to a dispatcher. Readings and statuses for the device
+/// are then refused from any other dispatcher.
+#[utoipa::path(
+    put,
+    path = "/api/devices/{id}/dispatcher",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = AssignDispatcher,
+    responses(
+        (status = 204, description = "Device assigned"),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device or dispatcher", body = ErrorBody),
+    )
+)]
+pub async fn assign_dispatcher<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<AssignDispatcher>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+    visible_dispatcher(&registries, &principal, request.dispatcher_id).await?;
+
+    set_dispatcher(
+        &registries,
+        principal,
+        DeviceId(id),
+        Some(request.dispatcher_id),
+    )
+    .await
+}
+
+/// `DELETE /api/devices/{id}/dispatcher`
+///
+/// Release the device from its dispatcher.
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}/dispatcher",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 204, description = "Device unassigned"),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn unassign_dispatcher<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    set_dispatcher(&registries, principal, DeviceId(id), None).await
+}
+
+async fn set_dispatcher<R: Registries>(
+    registries: &R,
+    principal: Principal,
+    id: DeviceId,
+    dispatcher_id: Option<DispatcherId>,
+) -> Result<StatusCode, ApiError> {
+    visible_device(registries, &principal, id).await?;
+
+    registries
+        .devices()
+        .set_dispatcher(id, dispatcher_id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::AssignDispatcher,
+            EntityKind::Device,
+            id.0,
+        )
+        .with_details(serde_json::json!({ "dispatcher_id": dispatcher_id })),
+    )
+    .await?;
+
+    tracing::info!(device_id = ?id, ?dispatcher_id, assigned_by = ?principal.key_id, "device dispatcher assigned");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Extension, Json,
+        extract::{Path, Query, State},
+        http::{HeaderMap, StatusCode, header},
+    };
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, DeviceStatus, Dispatcher, DispatcherId,
+        DispatcherState, H3Cell, Percentage, Sensor, SensorId, SensorKind, SensorMetric, StatusId,
+    };
+    use serde_json::json;
+    use ulid::Ulid;
+
+    use super::{
+        AssignDispatcher, DevicesQuery, RegisterDevice, UpdateDevice, assign_dispatcher, batch_get,
+        decommission, get, latest, list, offline, reactivate, register, suspend,
+        unassign_dispatcher, update,
+    };
+    use crate::api::{ApiError, BatchGetRequest, MAX_LIMIT, RegisterQuery};
+    use crate::auth::Scope;
+    use crate::events::{BusEvent, EventBus, LocalEventBus, Received};
+    use crate::fixtures::{admin, principal, reader};
+    use crate::org::{Org, OrgId};
+    use crate::placement::Placement;
+    use crate::registry::{
+        DeviceRegistry, DeviceStatusRegistry, DispatcherRegistry, OrgRegistry,
+        memory::InMemoryRegistries,
+    };
+
+    fn events(bus: &LocalEventBus) -> Extension<Arc<dyn EventBus>> {
+        Extension(Arc::new(bus.clone()))
+    }
+
+    async fn registered(registries: &InMemoryRegistries) -> Ulid {
+        let id = Ulid::new();
+        registries
+            .devices
+            .register(Device {
+                id: DeviceId(id),
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn decommissioned_device_cannot_come_back() {
+        let registries = InMemoryRegistries::default();
+        let id = registered(&registries).await;
+        let state = || State(registries.clone());
+
+        let (_, Json(device)) = suspend(state(), admin(), Path(id), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(device.state, DeviceState::Suspended);
+
+        let (_, Json(device)) = reactivate(state(), admin(), Path(id), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(device.state, DeviceState::Active);
+
+        let (_, Json(device)) = decommission(state(), admin(), Path(id), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(device.state, DeviceState::Decommissioned);
+
+        assert!(matches!(
+            reactivate(state(), admin(), Path(id), HeaderMap::new()).await,
+            Err(ApiError::Conflict(_))
+        ));
+
+        let stored = registries.devices.get(DeviceId(id)).await.unwrap().unwrap();
+        assert_eq!(stored.state, DeviceState::Decommissioned);
+    }
+
+    #[tokio::test]
+    async fn stale_etag_refuses_state_change() {
+        let registries = InMemoryRegistries::default();
+        let id = registered(&registries).await;
+        let state = || State(registries.clone());
+
+        let ([(_, read_etag)], _) = get(state(), admin(), Path(id)).await.unwrap();
+        let mut if_match = HeaderMap::new();
+        if_match.insert(header::IF_MATCH, read_etag.clone());
+        let ([(_, etag)], Json(device)) = suspend(state(), admin(), Path(id), if_match.clone())
+            .await
+            .unwrap();
+        assert_eq!(device.state, DeviceState::Suspended);
+        assert_ne!(etag, read_etag);
+
+        // Another admin acting on what they read before the suspension.
+        assert!(matches!(
+            reactivate(state(), admin(), Path(id), if_match).await,
+            Err(ApiError::PreconditionFailed)
+        ));
+        let stored = registries.devices.get(DeviceId(id)).await.unwrap().unwrap();
+        assert_eq!(stored.state, DeviceState::Suspended);
+
+        let mut if_match = HeaderMap::new();
+        if_match.insert(header::IF_MATCH, etag);
+        let (_, Json(device)) = reactivate(state(), admin(), Path(id), if_match)
+            .await
+            .unwrap();
+        assert_eq!(device.state, DeviceState::Active);
+    }
+
+    #[tokio::test]
+    async fn lifecycle_requires_admin() {
+        let registries = InMemoryRegistries::default();
+        let id = registered(&registries).await;
+        let read_only = reader();
+
+        assert!(matches!(
+            suspend(
+                State(registries.clone()),
+                read_only,
+                Path(id),
+                HeaderMap::new()
+            )
+            .await,
+            Err(ApiError::Forbidden)
+        ));
+        assert!(matches!(
+            suspend(
+                State(registries),
+                admin(),
+                Path(Ulid::new()),
+                HeaderMap::new()
+            )
+            .await,
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn device_is_assigned_to_a_known_dispatcher() {
+        let registries = InMemoryRegistries::default();
+        let id = registered(&registries).await;
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let request = || Json(AssignDispatcher { dispatcher_id });
+
+        assert!(matches!(
+            assign_dispatcher(State(registries.clone()), admin(), Path(id), request()).await,
+            Err(ApiError::NotFound)
+        ));
+
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: dispatcher_id,
+                location: H3Cell(0x8a2a1072b59ffff),
+                state: DispatcherState::Active,
+                provisioned_at: jiff::Timestamp::now(),
+            })
+            .await
+            .unwrap();
+        assign_dispatcher(State(registries.clone()), admin(), Path(id), request())
+            .await
+            .unwrap();
+        let assigned = registries.devices.dispatcher(DeviceId(id)).await.unwrap();
+        assert_eq!(assigned, Some(dispatcher_id));
+
+        unassign_dispatcher(State(registries.clone()), admin(), Path(id))
+            .await
+            .unwrap();
+        let assigned = registries.devices.dispatcher(DeviceId(id)).await.unwrap();
+        assert_eq!(assigned, None);
+    }
+
+    #[tokio::test]
+    async fn snapshot_carries_latest_status() {
+        let registries = InMemoryRegistries::default();
+        let id = registered(&registries).await;
+        let status = DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(id),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            battery_percent: Percentage(64),
+            uptime_seconds: 3600,
+            signal_rssi: -72,
+            errors: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+            sensor_statuses: Box::new([]),
+        };
+        registries.statuses.store(status.clone()).await.unwrap();
+
+        let Json(snapshot) = latest(State(registries.clone()), admin(), Path(id))
+            .await
+            .unwrap();
+        assert_eq!(snapshot.status, Some(status));
+        assert!(snapshot.readings.is_empty());
+
+        assert!(matches!(
+            latest(State(registries), admin(), Path(Ulid::new())).await,
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn offline_lists_disconnected_devices() {
+        let registries = InMemoryRegistries::default();
+        registered(&registries).await;
+        let gone = registered(&registries).await;
+        let since = jiff::Timestamp::now();
+        registries
+            .devices
+            .set_disconnected(DeviceId(gone), Some(since))
+            .await
+            .unwrap();
+
+        let Json(listed) = offline(State(registries.clone()), admin()).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].device_id, DeviceId(gone));
+        assert_eq!(listed[0].since, since);
+
+        let member = principal(Scope::ReadOnly, Some(OrgId(Ulid::new())));
+        let Json(listed) = offline(State(registries), member).await.unwrap();
+        assert!(listed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn offline_state_filters_the_device_list() {
+        let registries = InMemoryRegistries::default();
+        let watched = registered(&registries).await;
+        let gone = registered(&registries).await;
+        registries
+            .devices
+            .set_disconnected(DeviceId(gone), Some(jiff::Timestamp::now()))
+            .await
+            .unwrap();
+
+        let interval = |value: u64| {
+            Json(
+                serde_json::from_value::<UpdateDevice>(
+                    serde_json::json!({ "reporting_interval_secs": value }),
+                )
+                .unwrap(),
+            )
+        };
+        let state = || State(registries.clone());
+        assert!(matches!(
+            update(
+                state(),
+                admin(),
+                Path(watched),
+                HeaderMap::new(),
+                interval(0)
+            )
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+        let (_, Json(record)) = update(
+            state(),
+            admin(),
+            Path(watched),
+            HeaderMap::new(),
+            interval(600),
+        )
+        .await
+        .unwrap();
+        assert_eq!(record.reporting_interval_secs, Some(600));
+
+        let query = |state: &str| {
+            Query(DevicesQuery {
+                state: Some(state.to_owned()),
+                ..Default::default()
+            })
+        };
+        let page = list(state(), admin(), query("offline"), Query(Vec::new()))
+            .await
+            .unwrap();
+        let ids: Vec<DeviceId> = page.items.iter().map(|device| device.id).collect();
+        assert_eq!(ids, [DeviceId(gone)]);
+        let page = list(
+            state(),
+            admin(),
+            query("offline,suspended"),
+            Query(Vec::new()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.items.len(), 0);
+        assert!(matches!(
+            list(state(), admin(), query("asleep"), Query(Vec::new())).await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn registering_an_existing_device_conflicts() {
+        let registries = InMemoryRegistries::default();
+        let request = |id| {
+            Json(RegisterDevice {
+                id,
+                kind: DeviceKind::Sensor,
+                location: 0x8a2a1072b59ffff,
+                manufacturer: Some("Acme".to_owned()),
+                sensors: vec![],
+                reporting_interval_secs: None,
+                hardware_rev: None,
+                metadata: None,
+            })
+        };
+
+        let bus = LocalEventBus::new();
+        let mut published = bus.subscribe();
+
+        let (status, Json(device)) = register(
+            State(registries.clone()),
+            admin(),
+            events(&bus),
+            Query(RegisterQuery::default()),
+            request(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(device.state, DeviceState::Active);
+        let stored = registries.devices.get(device.id).await.unwrap().unwrap();
+        assert_eq!(stored.manufacturer.as_deref(), Some("Acme"));
+
+        let conflict = register(
+            State(registries.clone()),
+            admin(),
+            events(&bus),
+            Query(RegisterQuery::default()),
+            request(Some(device.id)),
+        )
+        .await;
+        let Err(ApiError::AlreadyRegistered(existing)) = conflict else {
+            panic!("expected a conflict, got {conflict:?}");
+        };
+        assert_eq!(existing["id"], device.id.0.to_string());
+        // Only the device actually registered is announced.
+        match published.try_recv() {
+            Some(Received::Event(event)) => assert!(matches!(
+                &*event,
+                BusEvent::DeviceRegistered { device: registered, org_id: None }
+                    if registered.id == device.id
+            )),
+            other => panic!("expected device_registered, got {other:?}"),
+        }
+        assert!(published.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn metadata_follows_the_org_schema_and_filters_lists() {
+        let registries = InMemoryRegistries::default();
+        let bus = LocalEventBus::new();
+        let org = Org::new("Oromia Coop");
+        registries.orgs.create(org.clone()).await.unwrap();
+        registries
+            .orgs
+            .set_metadata_schema(
+                org.id,
+                Some(json!({
+                    "type": "object",
+                    "properties": { "crop": { "type": "string" } },
+                    "required": ["crop"],
+                })),
+            )
+            .await
+            .unwrap();
+        let org_admin = || principal(Scope::Admin, Some(org.id));
+        let register_with = |metadata| {
+            register(
+                State(registries.clone()),
+                org_admin(),
+                events(&bus),
+                Query(RegisterQuery::default()),
+                Json(RegisterDevice {
+                    id: None,
+                    kind: DeviceKind::Sensor,
+                    location: 0x8a2a1072b59ffff,
+                    manufacturer: None,
+                    sensors: vec![],
+                    reporting_interval_secs: None,
+                    hardware_rev: None,
+                    metadata: Some(metadata),
+                }),
+            )
+        };
+
+        assert!(matches!(
+            register_with(json!({ "crop": 7 })).await,
+            Err(ApiError::BadRequest(_))
+        ));
+        let (_, Json(maize)) = register_with(json!({ "crop": "maize" })).await.unwrap();
+        let (_, Json(teff)) = register_with(json!({ "crop": "teff" })).await.unwrap();
+
+        let update = |metadata| {
+            update(
+                State(registries.clone()),
+                org_admin(),
+                Path(maize.id.0),
+                HeaderMap::new(),
+                Json(UpdateDevice {
+                    metadata: Some(metadata),
+                    ..Default::default()
+                }),
+            )
+        };
+        assert!(matches!(
+            update(Some(json!({ "rows": 12 }))).await,
+            Err(ApiError::BadRequest(_))
+        ));
+        let (_, Json(view)) = update(Some(json!({ "crop": "maize", "rows": 12 })))
+            .await
+            .unwrap();
+        assert_eq!(view.metadata, Some(json!({ "crop": "maize", "rows": 12 })));
+
+        let filter = |params: &[(&str, &str)]| {
+            Query(
+                params
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+            )
+        };
+        let page = list(
+            State(registries.clone()),
+            org_admin(),
+            Query(DevicesQuery::default()),
+            filter(&[("metadata.crop", "maize"), ("metadata.rows", "12")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            page.items.iter().map(|d| d.id).collect::<Vec<_>>(),
+            [maize.id]
+        );
+        let page = list(
+            State(registries.clone()),
+            org_admin(),
+            Query(DevicesQuery::default()),
+            filter(&[("metadata.crop", "teff")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            page.items.iter().map(|d| d.id).collect::<Vec<_>>(),
+            [teff.id]
+        );
+        assert!(matches!(
+            list(
+                State(registries),
+                org_admin(),
+                Query(DevicesQuery::default()),
+                filter(&[("metadata.crop.name", "maize")]),
+            )
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn upserting_reprovisions_only_the_callers_devices() {
+        let registries = InMemoryRegistries::default();
+        let bus = LocalEventBus::new();
+        let org_admin = |org_id| principal(Scope::Admin, Some(org_id));
+        let (ours, theirs) = (OrgId(Ulid::new()), OrgId(Ulid::new()));
+        let register_as = |principal, id, manufacturer: &str| {
+            register(
+                State(registries.clone()),
+                principal,
+                events(&bus),
+                Query(RegisterQuery { upsert: true }),
+                Json(RegisterDevice {
+                    id,
+                    kind: DeviceKind::Sensor,
+                    location: 0x8a2a1072b59ffff,
+                    manufacturer: Some(manufacturer.to_owned()),
+                    sensors: vec![],
+                    reporting_interval_secs: None,
+                    hardware_rev: None,
+                    metadata: None,
+                }),
+            )
+        };
+
+        let (status, Json(device)) = register_as(org_admin(ours), None, "Acme").await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, Json(reprovisioned)) = register_as(org_admin(ours), Some(device.id), "Globex")
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reprovisioned.manufacturer.as_deref(), Some("Globex"));
+        assert_eq!(registries.devices.org(device.id).await.unwrap(), Some(ours));
+
+        assert!(matches!(
+            register_as(org_admin(theirs), Some(device.id), "Initech").await,
+            Err(ApiError::NotFound)
+        ));
+        let stored = registries.devices.get(device.id).await.unwrap().unwrap();
+        assert_eq!(stored.manufacturer.as_deref(), Some("Globex"));
+    }
+
+    #[tokio::test]
+    async fn upserting_keeps_a_decommissioned_device_decommissioned() {
+        let registries = InMemoryRegistries::default();
+        let bus = LocalEventBus::new();
+        let id = DeviceId(registered(&registries).await);
+        let mut device = registries.devices.get(id).await.unwrap().unwrap();
+        device.state = DeviceState::Decommissioned;
+        registries.devices.register(device.clone()).await.unwrap();
+
+        let (status, Json(reprovisioned)) = register(
+            State(registries.clone()),
+            admin(),
+            events(&bus),
+            Query(RegisterQuery { upsert: true }),
+            Json(RegisterDevice {
+                id: Some(id),
+                kind: DeviceKind::Sensor,
+                location: 0x8a2a1072b59ffff,
+                manufacturer: Some("Globex".to_owned()),
+                sensors: vec![],
+                reporting_interval_secs: None,
+                hardware_rev: None,
+                metadata: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reprovisioned.state, DeviceState::Decommissioned);
+        assert_eq!(reprovisioned.provisioned_at, device.provisioned_at);
+        let stored = registries.devices.get(id).await.unwrap().unwrap();
+        assert_eq!(stored.state, DeviceState::Decommissioned);
+        assert_eq!(stored.manufacturer.as_deref(), Some("Globex"));
+    }
+
+    #[tokio::test]
+    async fn batch_get_reports_other_orgs_devices_missing() {
+        let registries = InMemoryRegistries::default();
+        let (ours, theirs) = (OrgId(Ulid::new()), OrgId(Ulid::new()));
+        let our_device = registered(&registries).await;
+        let their_device = registered(&registries).await;
+        registries
+            .devices
+            .set_org(DeviceId(our_device), Some(ours))
+            .await
+            .unwrap();
+        registries
+            .devices
+            .set_org(DeviceId(their_device), Some(theirs))
+            .await
+            .unwrap();
+        let unknown = Ulid::new();
+        let request = || {
+            Json(BatchGetRequest {
+                ids: vec![their_device, our_device, unknown, our_device],
+            })
+        };
+
+        let member = principal(Scope::Admin, Some(ours));
+        let Json(batch) = batch_get(State(registries.clone()), member, request())
+            .await
+            .unwrap();
+        assert_eq!(
+            batch.found.iter().map(|d| d.id.0).collect::<Vec<_>>(),
+            [our_device]
+        );
+        assert_eq!(batch.missing, [their_device, unknown]);
+
+        let Json(batch) = batch_get(State(registries.clone()), admin(), request())
+            .await
+            .unwrap();
+        assert_eq!(
+            batch.found.iter().map(|d| d.id.0).collect::<Vec<_>>(),
+            [their_device, our_device]
+        );
+        assert_eq!(batch.missing, [unknown]);
+
+        let too_many = Json(BatchGetRequest {
+            ids: (0..=MAX_LIMIT).map(|_| Ulid::new()).collect(),
+        });
+        assert!(matches!(
+            batch_get(State(registries), admin(), too_many).await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn updates_are_refused_once_the_device_changed() {
+        let registries = InMemoryRegistries::default();
+        let id = registered(&registries).await;
+        let state = || State(registries.clone());
+        let sensor = Sensor {
+            id: SensorId(Ulid::new()),
+            kind: SensorKind::Humidity,
+            metric: SensorMetric::Humidity {
+                value: Percentage(45),
+            },
+        };
+
+        let ([(_, read_etag)], _) = get(state(), admin(), Path(id)).await.unwrap();
+        let mut if_match = HeaderMap::new();
+        if_match.insert(header::IF_MATCH, read_etag.clone());
+
+        let request: UpdateDevice = serde_json::from_value(serde_json::json!({
+            "location": 0x8a2a1072b4a7fff_u64,
+            "manufacturer": "Acme",
+            "add_sensors": [sensor],
+            "placement": { "site": "North plot", "depth_cm": 30, "notes": null },
+        }))
+        .unwrap();
+        let ([(_, etag)], Json(record)) =
+            update(state(), admin(), Path(id), if_match.clone(), Json(request))
+                .await
+                .unwrap();
+        assert_ne!(etag, read_etag);
+        assert_eq!(record.device.location, H3Cell(0x8a2a1072b4a7fff));
+        assert_eq!(record.device.manufacturer.as_deref(), Some("Acme"));
+        assert_eq!(record.device.sensors.len(), 1);
+        assert_eq!(record.placement.depth_cm, Some(30));
+
+        let clear = || {
+            Json(
+                serde_json::from_value::<UpdateDevice>(serde_json::json!({
+                    "manufacturer": null,
+                    "remove_sensors": [sensor.id],
+                }))
+                .unwrap(),
+            )
+        };
+        assert!(matches!(
+            update(state(), admin(), Path(id), if_match, clear()).await,
+            Err(ApiError::PreconditionFailed)
+        ));
+
+        let ([(_, _)], Json(record)) =
+            update(state(), admin(), Path(id), HeaderMap::new(), clear())
+                .await
+                .unwrap();
+        assert_eq!(record.device.manufacturer, None);
+        assert!(record.device.sensors.is_empty());
+        assert_eq!(
+            record.placement,
+            Placement {
+                site: Some("North plot".to_owned()),
+                depth_cm: Some(30),
+                notes: None,
+            }
+        );
+
+        assert!(matches!(
+            update(state(), admin(), Path(id), HeaderMap::new(), clear()).await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
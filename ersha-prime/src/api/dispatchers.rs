@@ -0,0 +1,940 @@
+use std::collections::HashMap;
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+};
+use ersha_core::{Dispatcher, DispatcherId, DispatcherState, H3Cell};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{
+    ApiError, BatchGet, BatchGetRequest, ErrorBody, Order, Page, RegisterQuery, Tagged,
+    already_registered, groups::with_group, if_match, page_limit, parse_cursor, parse_list,
+    record_audit, tagged, visible_dispatcher,
+};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope, generate_secret};
+use crate::config::HealthConfig;
+use crate::group::normalize_tag;
+use crate::health::{Connectivity, DispatcherHealth};
+use crate::quota::{IngestQuotas, QuotaUsage};
+use crate::registry::{
+    DispatcherRegistry, DispatcherStatusRegistry, Registries,
+    filter::{DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortOrder},
+};
+
+/// Query parameters for `GET /api/dispatchers`.
+///
+/// List parameters are comma separated. Locations are H3 indexes in hex.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DispatchersQuery {
+    /// Dispatcher states, e.g. `active,suspended`
+    pub state: Option<String>,
+    pub location: Option<String>,
+    /// Tags, e.g. `pilot-a,north-ridge`; dispatchers carrying all of them
+    /// match
+    pub tag: Option<String>,
+    /// Only members of this group
+    pub group: Option<Ulid>,
+    /// Order by provisioning time
+    #[serde(default)]
+    pub order: Order,
+    /// Cursor returned as `next_cursor` by the previous page
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl DispatchersQuery {
+    fn into_options(self) -> Result<QueryOptions<DispatcherFilter, DispatcherSortBy>, ApiError> {
+        let filter = DispatcherFilter {
+            states: parse_list("state", self.state.as_deref(), |s| match s {
+                "active" => Some(DispatcherState::Active),
+                "suspended" => Some(DispatcherState::Suspended),
+                "pending" => Some(DispatcherState::Pending),
+                _ => None,
+            })?,
+            locations: parse_list("location", self.location.as_deref(), |s| {
+                u64::from_str_radix(s, 16).ok().map(H3Cell)
+            })?,
+            org_id: None,
+            tags: parse_list("tag", self.tag.as_deref(), |s| normalize_tag(s).ok())?,
+        };
+
+        Ok(QueryOptions {
+            filter,
+            sort_by: DispatcherSortBy::ProvisionAt,
+            sort_order: self.order.into(),
+            pagination: Pagination::Cursor {
+                after: parse_cursor(self.after.as_deref())?,
+                limit: page_limit(self.limit)?,
+            },
+        })
+    }
+}
+
+/// `GET /api/dispatchers`
+#[utoipa::path(
+    get,
+    path = "/api/dispatchers",
+    tag = "dispatchers",
+    params(DispatchersQuery),
+    responses(
+        (status = 200, description = "A page of dispatchers", body = Page<Dispatcher>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<DispatchersQuery>,
+) -> Result<Page<Dispatcher>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let group = query.group;
+    let mut options = query.into_options()?;
+    options.filter.org_id = principal.org_id;
+    with_group(&registries, &principal, group, &mut options.filter.tags).await?;
+    let limit = options.pagination.limit();
+    let sort_by = options.sort_by;
+    let dispatchers = registries.dispatchers();
+
+    let total = dispatchers
+        .count(Some(options.filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let items = dispatchers
+        .list(options)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Page::new(items, limit, total, |d| sort_by.cursor(d)))
+}
+
+/// Connectivity of every registered dispatcher.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthSummary {
+    pub online: usize,
+    pub offline: usize,
+    pub dispatchers: Vec<DispatcherHealth>,
+}
+
+pub(super) fn offline_window(config: HealthConfig) -> jiff::SignedDuration {
+    jiff::SignedDuration::from_secs(config.offline_after_secs as i64)
+}
+
+/// `POST /api/dispatchers/batch-get`
+///
+/// Look up many dispatchers in one round trip. Other organizations'
+/// dispatchers are reported missing.
+#[utoipa::path(
+    post,
+    path = "/api/dispatchers/batch-get",
+    tag = "dispatchers",
+    request_body = BatchGetRequest,
+    responses(
+        (status = 200, description = "The dispatchers found and the ids that weren't", body = BatchGet<Dispatcher>),
+        (status = 400, description = "Too many ids", body = ErrorBody),
+    )
+)]
+pub async fn batch_get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<BatchGetRequest>,
+) -> Result<Json<BatchGet<Dispatcher>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let ids: Vec<DispatcherId> = request.into_ids()?.into_iter().map(DispatcherId).collect();
+    let dispatchers = registries.dispatchers();
+    let mut registered: HashMap<DispatcherId, Dispatcher> = dispatchers
+        .get_many(&ids)
+        .await
+        .map_err(ApiError::registry)?
+        .into_iter()
+        .map(|dispatcher| (dispatcher.id, dispatcher))
+        .collect();
+
+    let mut found = Vec::with_capacity(registered.len());
+    let mut missing = Vec::new();
+    for id in ids {
+        let Some(dispatcher) = registered.remove(&id) else {
+            missing.push(id.0);
+            continue;
+        };
+
+        let owned = match principal.org_id {
+            Some(_) => principal.can_access(dispatchers.org(id).await.map_err(ApiError::registry)?),
+            None => true,
+        };
+        if owned {
+            found.push(dispatcher);
+        } else {
+            missing.push(id.0);
+        }
+    }
+
+    Ok(Json(BatchGet { found, missing }))
+}
+
+/// `GET /api/dispatchers/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/dispatchers/{id}",
+    tag = "dispatchers",
+    params(("id" = String, Path, description = "Dispatcher id")),
+    responses(
+        (status = 200, description = "The dispatcher", body = Dispatcher,
+            headers(("ETag" = String, description = "Revision to send as `If-Match` when changing its state"))),
+        (status = 404, description = "Unknown dispatcher", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Tagged<Dispatcher>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let dispatcher_id = DispatcherId(id);
+    let updated_at = revision(&registries, dispatcher_id).await?;
+    let dispatcher = visible_dispatcher(&registries, &principal, dispatcher_id).await?;
+
+    Ok(tagged(updated_at, dispatcher))
+}
+
+/// When the dispatcher was last changed. Read before the dispatcher itself,
+/// so a change made in between fails a later `If-Match` rather than going
+/// unnoticed.
+async fn revision<R: Registries>(
+    registries: &R,
+    id: DispatcherId,
+) -> Result<jiff::Timestamp, ApiError> {
+    registries
+        .dispatchers()
+        .updated_at(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)
+}
+
+/// `GET /api/dispatchers/{id}/status`
+///
+/// The dispatcher's latest status report and whether it is online.
+#[utoipa::path(
+    get,
+    path = "/api/dispatchers/{id}/status",
+    tag = "dispatchers",
+    params(("id" = String, Path, description = "Dispatcher id")),
+    responses(
+        (status = 200, description = "Dispatcher health", body = DispatcherHealth),
+        (status = 404, description = "Unknown dispatcher", body = ErrorBody),
+    )
+)]
+pub async fn status<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(config): Extension<HealthConfig>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<DispatcherHealth>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let dispatcher_id = DispatcherId(id);
+    let dispatcher = visible_dispatcher(&registries, &principal, dispatcher_id).await?;
+    let report = registries
+        .dispatcher_statuses()
+        .latest(dispatcher_id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(DispatcherHealth::assess(
+        dispatcher,
+        report,
+        jiff::Timestamp::now(),
+        offline_window(config),
+    )))
+}
+
+/// `GET /api/dispatchers/health`
+///
+/// Dispatchers not heard from within the configured window are offline.
+/// Organization keys only see their organization's dispatchers.
+#[utoipa::path(
+    get,
+    path = "/api/dispatchers/health",
+    tag = "dispatchers",
+    responses(
+        (status = 200, description = "Health of every registered dispatcher", body = HealthSummary),
+    )
+)]
+pub async fn health<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(config): Extension<HealthConfig>,
+) -> Result<Json<HealthSummary>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let dispatchers = registries.dispatchers();
+    let filter = DispatcherFilter {
+        org_id: principal.org_id,
+        ..Default::default()
+    };
+    let count = dispatchers
+        .count(Some(filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let dispatchers = dispatchers
+        .list(QueryOptions {
+            filter,
+            sort_by: DispatcherSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Offset {
+                offset: 0,
+                limit: count,
+            },
+        })
+        .await
+        .map_err(ApiError::registry)?;
+
+    let mut reports: HashMap<_, _> = registries
+        .dispatcher_statuses()
+        .list()
+        .await
+        .map_err(ApiError::registry)?
+        .into_iter()
+        .map(|report| (report.status.dispatcher_id, report))
+        .collect();
+
+    let now = jiff::Timestamp::now();
+    let window = offline_window(config);
+    let dispatchers: Vec<_> = dispatchers
+        .into_iter()
+        .map(|dispatcher| {
+            let report = reports.remove(&dispatcher.id);
+            DispatcherHealth::assess(dispatcher, report, now, window)
+        })
+        .collect();
+
+    let online = dispatchers
+        .iter()
+        .filter(|health| health.connectivity == Connectivity::Online)
+        .count();
+
+    Ok(Json(HealthSummary {
+        online,
+        offline: dispatchers.len() - online,
+        dispatchers,
+    }))
+}
+
+/// `GET /api/dispatchers/over-quota`
+///
+/// Dispatchers that uploaded more readings this hour than their quota
+/// allows, most excess first. Organization keys only see their
+/// organization's dispatchers.
+#[utoipa::path(
+    get,
+    path = "/api/dispatchers/over-quota",
+    tag = "dispatchers",
+    responses(
+        (status = 200, description = "Dispatchers over their hourly ingest quota", body = [QuotaUsage]),
+    )
+)]
+pub async fn over_quota<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(quotas): Extension<IngestQuotas>,
+) -> Result<Json<Vec<QuotaUsage>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let mut visible = Vec::new();
+    for usage in quotas.over_quota(jiff::Timestamp::now()) {
+        if principal.org_id.is_some() {
+            let owner = registries
+                .dispatchers()
+                .org(usage.dispatcher_id)
+                .await
+                .map_err(ApiError::registry)?;
+            if !principal.can_access(owner) {
+                continue;
+            }
+        }
+        visible.push(usage);
+    }
+
+    Ok(Json(visible))
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ProvisionSecret {
+    /// H3 cell to register the dispatcher at if it is not known yet
+    pub location: Option<u64>,
+}
+
+/// Body of `POST /api/dispatchers`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RegisterDispatcher {
+    /// Generated when not given
+    pub id: Option<DispatcherId>,
+    /// H3 cell the dispatcher is installed at
+    pub location: u64,
+}
+
+/// `POST /api/dispatchers`
+///
+/// Dispatchers registered by an organization key belong to its
+/// organization. Registering a taken id answers 409 with the dispatcher
+/// registered under it, unless `upsert` asks to re-provision that
+/// dispatcher, which keeps its state.
+#[utoipa::path(
+    post,
+    path = "/api/dispatchers",
+    tag = "dispatchers",
+    params(RegisterQuery),
+    request_body = RegisterDispatcher,
+    responses(
+        (status = 201, description = "Dispatcher registered", body = Dispatcher),
+        (status = 200, description = "Dispatcher re-provisioned", body = Dispatcher),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 409, description = "A dispatcher with this id exists", body = ErrorBody),
+    )
+)]
+pub async fn register<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<RegisterQuery>,
+    Json(request): Json<RegisterDispatcher>,
+) -> Result<(StatusCode, Json<Dispatcher>), ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let dispatcher_id = request.id.unwrap_or_else(|| DispatcherId(Ulid::new()));
+    let dispatchers = registries.dispatchers();
+    let mut dispatcher = Dispatcher {
+        id: dispatcher_id,
+        location: H3Cell(request.location),
+        state: DispatcherState::Active,
+        provisioned_at: jiff::Timestamp::now(),
+    };
+
+    let status = if query.upsert {
+        let existing = dispatchers
+            .get(dispatcher_id)
+            .await
+            .map_err(ApiError::registry)?;
+        if let Some(existing) = &existing {
+            // Another organization's dispatcher is not the caller's to take over.
+            visible_dispatcher(&registries, &principal, dispatcher_id).await?;
+            // A suspended or pending dispatcher stays so until reactivated
+            // or approved.
+            dispatcher.state = existing.state.clone();
+            dispatcher.provisioned_at = existing.provisioned_at;
+        }
+        dispatchers
+            .register(dispatcher.clone())
+            .await
+            .map_err(ApiError::registry)?;
+        if existing.is_some() {
+            StatusCode::OK
+        } else {
+            StatusCode::CREATED
+        }
+    } else {
+        let existing = dispatchers
+            .register_new(dispatcher.clone())
+            .await
+            .map_err(ApiError::registry)?;
+        if existing.is_some() {
+            let visible = visible_dispatcher(&registries, &principal, dispatcher_id).await;
+            return Err(already_registered(visible, "dispatcher"));
+        }
+        StatusCode::CREATED
+    };
+
+    claim_and_audit(&registries, &principal, dispatcher_id).await?;
+    tracing::info!(?dispatcher_id, registered_by = ?principal.key_id, "dispatcher registered");
+
+    Ok((status, Json(dispatcher)))
+}
+
+/// Give a registered dispatcher to the caller's organization and record who
+/// registered it.
+async fn claim_and_audit<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    dispatcher_id: DispatcherId,
+) -> Result<(), ApiError> {
+    if principal.org_id.is_some() {
+        registries
+            .dispatchers()
+            .set_org(dispatcher_id, principal.org_id)
+            .await
+            .map_err(ApiError::registry)?;
+    }
+
+    record_audit(
+        registries,
+        AuditEntry::by(
+            principal,
+            AuditAction::Register,
+            EntityKind::Dispatcher,
+            dispatcher_id.0,
+        ),
+    )
+    .await
+}
+
+/// A freshly provisioned dispatcher secret. It is shown only once.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DispatcherSecret {
+    pub dispatcher_id: DispatcherId,
+    pub secret: String,
+}
+
+/// `POST /api/dispatchers/{id}/secret`
+///
+/// Issue a new hello secret for the dispatcher, replacing any previous one.
+/// Unknown dispatchers are registered when a location is given, in the
+/// caller's organization.
+#[utoipa::path(
+    post,
+    path = "/api/dispatchers/{id}/secret",
+    tag = "dispatchers",
+    params(("id" = String, Path, description = "Dispatcher id")),
+    request_body = ProvisionSecret,
+    responses(
+        (status = 201, description = "Secret issued; it is shown only once", body = DispatcherSecret),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown dispatcher and no location given", body = ErrorBody),
+    )
+)]
+pub async fn provision_secret<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<ProvisionSecret>,
+) -> Result<(StatusCode, Json<DispatcherSecret>), ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let dispatcher_id = DispatcherId(id);
+    let dispatchers = registries.dispatchers();
+
+    let existing = dispatchers
+        .get(dispatcher_id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    // A dispatcher registered concurrently counts as existing.
+    let registered = match existing {
+        Some(_) => false,
+        None => {
+            let location = request.location.ok_or(ApiError::NotFound)?;
+            dispatchers
+                .register_new(Dispatcher {
+                    id: dispatcher_id,
+                    location: H3Cell(location),
+                    state: DispatcherState::Active,
+                    provisioned_at: jiff::Timestamp::now(),
+                })
+                .await
+                .map_err(ApiError::registry)?
+                .is_none()
+        }
+    };
+
+    if registered {
+        claim_and_audit(&registries, &principal, dispatcher_id).await?;
+    } else {
+        let owner = dispatchers
+            .org(dispatcher_id)
+            .await
+            .map_err(ApiError::registry)?;
+        principal.check_access(owner)?;
+    }
+
+    let secret = generate_secret();
+    dispatchers
+        .set_secret(dispatcher_id, Some(secret.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::ProvisionSecret,
+            EntityKind::Dispatcher,
+            dispatcher_id.0,
+        ),
+    )
+    .await?;
+
+    tracing::info!(?dispatcher_id, provisioned_by = ?principal.key_id, "dispatcher secret provisioned");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(DispatcherSecret {
+            dispatcher_id,
+            secret,
+        }),
+    ))
+}
+
+/// `POST /api/dispatchers/{id}/suspend`
+///
+/// A suspended dispatcher's hellos and batches are rejected.
+#[utoipa::path(
+    post,
+    path = "/api/dispatchers/{id}/suspend",
+    tag = "dispatchers",
+    params(
+        ("id" = String, Path, description = "Dispatcher id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the dispatcher must still have"),
+    ),
+    responses(
+        (status = 200, description = "Dispatcher suspended", body = Dispatcher,
+            headers(("ETag" = String, description = "The dispatcher's new revision"))),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown dispatcher", body = ErrorBody),
+        (status = 412, description = "Dispatcher changed since it was read", body = ErrorBody),
+    )
+)]
+pub async fn suspend<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<Tagged<Dispatcher>, ApiError> {
+    transition(
+        &registries,
+        principal,
+        DispatcherId(id),
+        &headers,
+        AuditAction::Suspend,
+    )
+    .await
+}
+
+/// `POST /api/dispatchers/{id}/reactivate`
+///
+/// Dispatchers awaiting approval are approved instead.
+#[utoipa::path(
+    post,
+    path = "/api/dispatchers/{id}/reactivate",
+    tag = "dispatchers",
+    params(
+        ("id" = String, Path, description = "Dispatcher id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the dispatcher must still have"),
+    ),
+    responses(
+        (status = 200, description = "Dispatcher reactivated", body = Dispatcher,
+            headers(("ETag" = String, description = "The dispatcher's new revision"))),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown dispatcher", body = ErrorBody),
+        (status = 409, description = "Dispatcher awaiting approval", body = ErrorBody),
+        (status = 412, description = "Dispatcher changed since it was read", body = ErrorBody),
+    )
+)]
+pub async fn reactivate<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<Tagged<Dispatcher>, ApiError> {
+    transition(
+        &registries,
+        principal,
+        DispatcherId(id),
+        &headers,
+        AuditAction::Reactivate,
+    )
+    .await
+}
+
+/// `POST /api/dispatchers/{id}/approve`
+///
+/// Let in a dispatcher that said hello unannounced. Its next hello is
+/// accepted and it may upload from then on.
+#[utoipa::path(
+    post,
+    path = "/api/dispatchers/{id}/approve",
+    tag = "dispatchers",
+    params(
+        ("id" = String, Path, description = "Dispatcher id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the dispatcher must still have"),
+    ),
+    responses(
+        (status = 200, description = "Dispatcher approved", body = Dispatcher,
+            headers(("ETag" = String, description = "The dispatcher's new revision"))),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown dispatcher", body = ErrorBody),
+        (status = 409, description = "Dispatcher not awaiting approval", body = ErrorBody),
+        (status = 412, description = "Dispatcher changed since it was read", body = ErrorBody),
+    )
+)]
+pub async fn approve<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<Tagged<Dispatcher>, ApiError> {
+    transition(
+        &registries,
+        principal,
+        DispatcherId(id),
+        &headers,
+        AuditAction::Approve,
+    )
+    .await
+}
+
+async fn transition<R: Registries>(
+    registries: &R,
+    principal: Principal,
+    id: DispatcherId,
+    headers: &HeaderMap,
+    action: AuditAction,
+) -> Result<Tagged<Dispatcher>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let updated_at = revision(registries, id).await?;
+    let dispatcher = visible_dispatcher(registries, &principal, id).await?;
+    let pending = dispatcher.state == DispatcherState::Pending;
+    if let Some(expected) = if_match(headers)?
+        && expected != updated_at
+    {
+        return Err(ApiError::PreconditionFailed);
+    }
+
+    let state = match action {
+        AuditAction::Suspend => DispatcherState::Suspended,
+        AuditAction::Reactivate if pending => {
+            return Err(ApiError::Conflict(
+                "dispatcher is awaiting approval, approve it instead".to_owned(),
+            ));
+        }
+        AuditAction::Approve if !pending => {
+            return Err(ApiError::Conflict(
+                "dispatcher isn't awaiting approval".to_owned(),
+            ));
+        }
+        _ => DispatcherState::Active,
+    };
+    let dispatcher = Dispatcher {
+        state,
+        ..dispatcher
+    };
+    let updated_at = registries
+        .dispatchers()
+        .update(id, dispatcher.clone(), Some(updated_at))
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::PreconditionFailed)?;
+
+    record_audit(
+        registries,
+        AuditEntry::by(&principal, action, EntityKind::Dispatcher, id.0),
+    )
+    .await?;
+
+    tracing::info!(dispatcher_id = ?id, state = ?dispatcher.state, changed_by = ?principal.key_id, "dispatcher state changed");
+
+    Ok(tagged(updated_at, dispatcher))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json,
+        extract::{Path, Query, State},
+        http::{HeaderMap, StatusCode, header},
+    };
+    use ersha_core::{Dispatcher, DispatcherId, DispatcherState, H3Cell};
+    use ulid::Ulid;
+
+    use super::{RegisterDispatcher, approve, get, reactivate, register, suspend};
+    use crate::api::{ApiError, RegisterQuery};
+    use crate::fixtures::admin;
+    use crate::registry::{DispatcherRegistry, memory::InMemoryRegistries};
+
+    #[tokio::test]
+    async fn only_pending_dispatchers_are_approved() {
+        let registries = InMemoryRegistries::default();
+        let id = Ulid::new();
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: DispatcherId(id),
+                location: H3Cell(0x8a2a1072b59ffff),
+                state: DispatcherState::Pending,
+                provisioned_at: jiff::Timestamp::now(),
+            })
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            reactivate(
+                State(registries.clone()),
+                admin(),
+                Path(id),
+                HeaderMap::new()
+            )
+            .await,
+            Err(ApiError::Conflict(_))
+        ));
+
+        let (_, Json(approved)) = approve(
+            State(registries.clone()),
+            admin(),
+            Path(id),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(approved.state, DispatcherState::Active);
+        let stored = registries
+            .dispatchers
+            .get(DispatcherId(id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.state, DispatcherState::Active);
+
+        assert!(matches!(
+            approve(
+                State(registries.clone()),
+                admin(),
+                Path(id),
+                HeaderMap::new()
+            )
+            .await,
+            Err(ApiError::Conflict(_))
+        ));
+        let (_, Json(suspended)) = suspend(
+            State(registries.clone()),
+            admin(),
+            Path(id),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(suspended.state, DispatcherState::Suspended);
+        assert!(matches!(
+            approve(State(registries), admin(), Path(id), HeaderMap::new()).await,
+            Err(ApiError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn stale_etag_refuses_state_change() {
+        let registries = InMemoryRegistries::default();
+        let id = Ulid::new();
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: DispatcherId(id),
+                location: H3Cell(0x8a2a1072b59ffff),
+                state: DispatcherState::Active,
+                provisioned_at: jiff::Timestamp::now(),
+            })
+            .await
+            .unwrap();
+        let state = || State(registries.clone());
+
+        let ([(_, read_etag)], _) = get(state(), admin(), Path(id)).await.unwrap();
+        let mut if_match = HeaderMap::new();
+        if_match.insert(header::IF_MATCH, read_etag.clone());
+        let ([(_, etag)], _) = suspend(state(), admin(), Path(id), if_match.clone())
+            .await
+            .unwrap();
+        assert_ne!(etag, read_etag);
+
+        // A second admin still holding the first read.
+        assert!(matches!(
+            reactivate(state(), admin(), Path(id), if_match).await,
+            Err(ApiError::PreconditionFailed)
+        ));
+        let stored = registries
+            .dispatchers
+            .get(DispatcherId(id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.state, DispatcherState::Suspended);
+
+        let ([(_, current)], _) = get(state(), admin(), Path(id)).await.unwrap();
+        assert_eq!(current, etag);
+    }
+
+    async fn upserted(registries: &InMemoryRegistries, id: Ulid) -> Dispatcher {
+        let (status, Json(dispatcher)) = register(
+            State(registries.clone()),
+            admin(),
+            Query(RegisterQuery { upsert: true }),
+            Json(RegisterDispatcher {
+                id: Some(DispatcherId(id)),
+                location: 0x8a2a1072b4a7fff,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+
+        dispatcher
+    }
+
+    #[tokio::test]
+    async fn upserting_keeps_a_suspended_dispatcher_suspended() {
+        let registries = InMemoryRegistries::default();
+        let id = Ulid::new();
+        let provisioned_at = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: DispatcherId(id),
+                location: H3Cell(0x8a2a1072b59ffff),
+                state: DispatcherState::Suspended,
+                provisioned_at,
+            })
+            .await
+            .unwrap();
+
+        let dispatcher = upserted(&registries, id).await;
+        assert_eq!(dispatcher.state, DispatcherState::Suspended);
+        assert_eq!(dispatcher.provisioned_at, provisioned_at);
+        let stored = registries
+            .dispatchers
+            .get(DispatcherId(id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.state, DispatcherState::Suspended);
+        assert_eq!(stored.location, H3Cell(0x8a2a1072b4a7fff));
+    }
+
+    #[tokio::test]
+    async fn upserting_leaves_a_pending_dispatcher_awaiting_approval() {
+        let registries = InMemoryRegistries::default();
+        let id = Ulid::new();
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: DispatcherId(id),
+                location: H3Cell(0x8a2a1072b59ffff),
+                state: DispatcherState::Pending,
+                provisioned_at: jiff::Timestamp::now(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            upserted(&registries, id).await.state,
+            DispatcherState::Pending
+        );
+        let (_, Json(approved)) = approve(State(registries), admin(), Path(id), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(approved.state, DispatcherState::Active);
+    }
+}
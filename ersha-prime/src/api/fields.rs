@@ -0,0 +1,487 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+};
+use ersha_core::{H3Cell, SensorKind};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::readings::parse_metric_kind;
+use super::{ApiError, ErrorBody, parse_list, regions::parse_region};
+use crate::auth::{Principal, Scope};
+use crate::config::IndicatorConfig;
+use crate::derived::{Indicator, IndicatorKind};
+use crate::forecast::{
+    self, ForecastError, ForecastPoint, ForecastRequest, Forecaster, Observation,
+};
+use crate::region;
+use crate::registry::{
+    DerivedMetricRegistry, DeviceRegistry, Registries,
+    filter::{DeviceFilter, IndicatorFilter},
+};
+
+/// Query parameters for `GET /api/fields/{id}/indicators`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IndicatorsQuery {
+    /// Indicator kinds, comma separated, e.g. `rainfall,frost_hours`
+    pub kind: Option<String>,
+    /// Only days starting at or after this time
+    pub from: Option<jiff::Timestamp>,
+    /// Only days starting at or before this time
+    pub to: Option<jiff::Timestamp>,
+}
+
+/// Hours forecast unless a request says otherwise.
+const DEFAULT_FORECAST_HOURS: u32 = 48;
+/// Longest forecast served, in hours.
+const MAX_FORECAST_HOURS: u32 = 168;
+
+/// Query parameters for `GET /api/fields/{id}/forecast`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ForecastQuery {
+    /// Metric to forecast, e.g. `soil_moisture`, the default
+    pub metric: Option<String>,
+    /// Hours ahead to forecast, 48 unless set, at most 168
+    pub hours: Option<u32>,
+}
+
+/// A field's predicted readings, one per hour.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldForecast {
+    pub field: H3Cell,
+    pub metric: SensorKind,
+    /// Model that made the forecast
+    pub model: String,
+    pub generated_at: jiff::Timestamp,
+    /// Hourly means the forecast was made from, oldest first
+    pub history: Vec<Observation>,
+    /// Empty when the field has no recent readings of the metric
+    pub points: Vec<ForecastPoint>,
+}
+
+/// A field's daily indicators and their totals over the requested days.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldIndicators {
+    pub field: H3Cell,
+    /// Indicators ordered by day, then kind
+    pub days: Vec<Indicator>,
+    pub summary: IndicatorSummary,
+}
+
+/// Totals over the returned days. Kinds without data are `null`.
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct IndicatorSummary {
+    pub growing_degree_days: Option<f64>,
+    pub rainfall: Option<f64>,
+    pub frost_hours: Option<f64>,
+    /// The most recent day's deficit
+    pub soil_moisture_deficit: Option<f64>,
+}
+
+impl IndicatorSummary {
+    fn new(days: &[Indicator]) -> Self {
+        let total = |kind: IndicatorKind| {
+            days.iter()
+                .filter(|i| i.kind == kind)
+                .map(|i| i.value)
+                .reduce(|sum, value| sum + value)
+        };
+
+        Self {
+            growing_degree_days: total(IndicatorKind::GrowingDegreeDays),
+            rainfall: total(IndicatorKind::Rainfall),
+            frost_hours: total(IndicatorKind::FrostHours),
+            soil_moisture_deficit: days
+                .iter()
+                .rfind(|i| i.kind == IndicatorKind::SoilMoistureDeficit)
+                .map(|i| i.value),
+        }
+    }
+}
+
+fn parse_indicator_kind(s: &str) -> Option<IndicatorKind> {
+    let kind = match s {
+        "growing_degree_days" => IndicatorKind::GrowingDegreeDays,
+        "rainfall" => IndicatorKind::Rainfall,
+        "frost_hours" => IndicatorKind::FrostHours,
+        "soil_moisture_deficit" => IndicatorKind::SoilMoistureDeficit,
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+/// `GET /api/fields/{id}/indicators`
+///
+/// Growing degree days, rainfall, frost hours and soil moisture deficit per
+/// day for a field, the H3 cell at the configured field resolution.
+/// Organization keys only see fields containing one of their devices.
+#[utoipa::path(
+    get,
+    path = "/api/fields/{id}/indicators",
+    tag = "fields",
+    params(("id" = String, Path, description = "Field H3 cell in hex"), IndicatorsQuery),
+    responses(
+        (status = 200, description = "The field's indicators", body = FieldIndicators),
+        (status = 400, description = "Invalid field or query", body = ErrorBody),
+        (status = 404, description = "Field not visible to the caller", body = ErrorBody),
+    )
+)]
+pub async fn indicators<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(config): Extension<IndicatorConfig>,
+    Path(id): Path<String>,
+    Query(query): Query<IndicatorsQuery>,
+) -> Result<Json<FieldIndicators>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+    let field = visible_field(&registries, &principal, &config, &id).await?;
+
+    let filter = IndicatorFilter {
+        field: Some(field),
+        kinds: parse_list("kind", query.kind.as_deref(), parse_indicator_kind)?,
+        after: query.from,
+        before: query.to,
+    };
+    let days = registries
+        .derived_metrics()
+        .list(filter)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(FieldIndicators {
+        field,
+        summary: IndicatorSummary::new(&days),
+        days,
+    }))
+}
+
+/// `GET /api/fields/{id}/forecast`
+///
+/// Predicted readings for a field, hourly from now, made by the configured
+/// forecaster from the field's recent hourly readings.
+#[utoipa::path(
+    get,
+    path = "/api/fields/{id}/forecast",
+    tag = "fields",
+    params(("id" = String, Path, description = "Field H3 cell in hex"), ForecastQuery),
+    responses(
+        (status = 200, description = "The field's forecast", body = FieldForecast),
+        (status = 400, description = "Invalid field or query", body = ErrorBody),
+        (status = 404, description = "Field not visible to the caller", body = ErrorBody),
+    )
+)]
+pub async fn forecast<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(config): Extension<IndicatorConfig>,
+    Extension(forecaster): Extension<Arc<dyn Forecaster>>,
+    Path(id): Path<String>,
+    Query(query): Query<ForecastQuery>,
+) -> Result<Json<FieldForecast>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+    let field = visible_field(&registries, &principal, &config, &id).await?;
+
+    let metric = match query.metric.as_deref() {
+        None => SensorKind::SoilMoisture,
+        Some(s) => parse_metric_kind(s)
+            .ok_or_else(|| ApiError::BadRequest(format!("unknown metric: {s}")))?,
+    };
+    let hours = query.hours.unwrap_or(DEFAULT_FORECAST_HOURS);
+    if !(1..=MAX_FORECAST_HOURS).contains(&hours) {
+        return Err(ApiError::BadRequest(format!(
+            "hours must be between 1 and {MAX_FORECAST_HOURS}"
+        )));
+    }
+
+    let now = jiff::Timestamp::now();
+    let since = now.checked_sub(forecaster.lookback()).unwrap_or(now);
+    let history = forecast::field_history(&registries, field, metric, since)
+        .await
+        .map_err(ApiError::internal)?;
+
+    let request = ForecastRequest {
+        field,
+        metric,
+        history,
+        from: now,
+        hours,
+    };
+    let points = match forecaster.forecast(&request).await {
+        Ok(points) => points,
+        Err(ForecastError::NoHistory) => Vec::new(),
+        Err(e @ ForecastError::UnsupportedMetric(_)) => {
+            return Err(ApiError::BadRequest(e.to_string()));
+        }
+        Err(e) => return Err(ApiError::internal(e)),
+    };
+
+    Ok(Json(FieldForecast {
+        field,
+        metric,
+        model: forecaster.name().to_owned(),
+        generated_at: now,
+        history: request.history,
+        points,
+    }))
+}
+
+/// The field `id` names, if the caller may see it.
+///
+/// Fields are H3 cells at the configured field resolution. Organization keys
+/// only see fields containing one of their devices, and users limited to
+/// fields only those.
+pub(super) async fn visible_field<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    config: &IndicatorConfig,
+    id: &str,
+) -> Result<H3Cell, ApiError> {
+    let field = parse_region(id)?;
+    if region::parent(field, config.field_resolution) != Some(field) {
+        return Err(ApiError::BadRequest(format!(
+            "fields are H3 cells at resolution {}",
+            config.field_resolution
+        )));
+    }
+    if !principal.can_see(field) {
+        return Err(ApiError::NotFound);
+    }
+
+    if let Some(org_id) = principal.org_id {
+        let owned = DeviceFilter::builder().within([field]).org(org_id).build();
+        let count = registries
+            .devices()
+            .count(Some(owned))
+            .await
+            .map_err(ApiError::registry)?;
+        if count == 0 {
+            return Err(ApiError::NotFound);
+        }
+    }
+
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Extension,
+        extract::{Path, Query, State},
+    };
+    use ersha_core::{Device, DeviceId, DeviceKind, DeviceState, H3Cell, SensorId, SensorKind};
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use std::sync::Arc;
+
+    use super::{ForecastQuery, IndicatorSummary, IndicatorsQuery, forecast, indicators};
+    use crate::api::ApiError;
+    use crate::auth::Scope;
+    use crate::config::{ForecastConfig, IndicatorConfig};
+    use crate::derived;
+    use crate::fixtures::{principal, reader};
+    use crate::forecast::{Forecaster, TrendForecaster};
+    use crate::org::OrgId;
+    use crate::registry::{AggregateRegistry, DeviceRegistry, memory::InMemoryRegistries};
+    use crate::rollup::{Aggregate, Granularity};
+
+    const FIELD: &str = "892a1072b5bffff";
+    const DAY: i64 = 86_400 * 20_000;
+
+    fn rain(device_id: DeviceId, day: i64, mm: f64) -> Aggregate {
+        Aggregate {
+            device_id,
+            sensor_id: SensorId(Ulid::from_parts(0, 1)),
+            metric: SensorKind::Rainfall,
+            granularity: Granularity::Day,
+            bucket_start: Timestamp::from_second(day).unwrap(),
+            count: 1,
+            sum: mm,
+            min: mm,
+            max: mm,
+        }
+    }
+
+    async fn field_with_rain() -> (InMemoryRegistries, OrgId, DeviceId) {
+        let registries = InMemoryRegistries::default();
+        let org_id = OrgId(Ulid::new());
+        let device_id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id: device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        registries
+            .devices
+            .set_org(device_id, Some(org_id))
+            .await
+            .unwrap();
+        registries
+            .aggregates
+            .merge(vec![
+                rain(device_id, DAY, 4.0),
+                rain(device_id, DAY + 86_400, 1.5),
+            ])
+            .await
+            .unwrap();
+
+        let now = Timestamp::from_second(DAY + 86_400 + 60).unwrap();
+        derived::refresh(&registries, &IndicatorConfig::default(), now)
+            .await
+            .unwrap();
+
+        (registries, org_id, device_id)
+    }
+
+    #[tokio::test]
+    async fn refreshed_indicators_are_summarised() {
+        let (registries, org_id, _) = field_with_rain().await;
+
+        let response = indicators(
+            State(registries),
+            principal(Scope::ReadOnly, Some(org_id)),
+            Extension(IndicatorConfig::default()),
+            Path(FIELD.to_owned()),
+            Query(IndicatorsQuery::default()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.days.len(), 2);
+        assert_eq!(
+            response.summary,
+            IndicatorSummary {
+                rainfall: Some(5.5),
+                ..IndicatorSummary::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn other_orgs_and_other_resolutions_are_rejected() {
+        let (registries, _, _) = field_with_rain().await;
+
+        let result = indicators(
+            State(registries.clone()),
+            principal(Scope::ReadOnly, Some(OrgId(Ulid::new()))),
+            Extension(IndicatorConfig::default()),
+            Path(FIELD.to_owned()),
+            Query(IndicatorsQuery::default()),
+        )
+        .await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+
+        let result = indicators(
+            State(registries),
+            reader(),
+            Extension(IndicatorConfig::default()),
+            Path("8a2a1072b59ffff".to_owned()),
+            Query(IndicatorsQuery::default()),
+        )
+        .await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+
+    fn trend() -> Extension<Arc<dyn Forecaster>> {
+        Extension(Arc::new(TrendForecaster::new(&ForecastConfig::default())))
+    }
+
+    #[tokio::test]
+    async fn forecasts_extend_the_fields_hourly_readings() {
+        let (registries, org_id, device_id) = field_with_rain().await;
+        let this_hour = Timestamp::now().as_second() / 3_600 * 3_600;
+        // Drying by half a point an hour over the last twelve hours.
+        let hourly = (1..=12)
+            .map(|n| {
+                let moisture = 24.0 + 0.5 * n as f64;
+                Aggregate {
+                    metric: SensorKind::SoilMoisture,
+                    granularity: Granularity::Hour,
+                    bucket_start: Timestamp::from_second(this_hour - n * 3_600).unwrap(),
+                    ..rain(device_id, 0, moisture)
+                }
+            })
+            .collect();
+        registries.aggregates.merge(hourly).await.unwrap();
+
+        let response = forecast(
+            State(registries),
+            principal(Scope::ReadOnly, Some(org_id)),
+            Extension(IndicatorConfig::default()),
+            trend(),
+            Path(FIELD.to_owned()),
+            Query(ForecastQuery {
+                hours: Some(24),
+                ..ForecastQuery::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.metric, SensorKind::SoilMoisture);
+        assert_eq!(response.model, "trend");
+        assert_eq!(response.history.len(), 12);
+        assert_eq!(response.points.len(), 24);
+        assert!(response.points[0].value < 24.5);
+        assert!(
+            response
+                .points
+                .windows(2)
+                .all(|pair| pair[1].value < pair[0].value)
+        );
+    }
+
+    #[tokio::test]
+    async fn forecasts_reject_unknown_metrics_and_horizons() {
+        let (registries, _, _) = field_with_rain().await;
+
+        for query in [
+            ForecastQuery {
+                metric: Some("wind".to_owned()),
+                hours: None,
+            },
+            ForecastQuery {
+                metric: None,
+                hours: Some(24 * 30),
+            },
+        ] {
+            let result = forecast(
+                State(registries.clone()),
+                reader(),
+                Extension(IndicatorConfig::default()),
+                trend(),
+                Path(FIELD.to_owned()),
+                Query(query),
+            )
+            .await;
+            assert!(matches!(result, Err(ApiError::BadRequest(_))));
+        }
+
+        // Readings of other metrics don't make a forecast.
+        let response = forecast(
+            State(registries),
+            reader(),
+            Extension(IndicatorConfig::default()),
+            trend(),
+            Path(FIELD.to_owned()),
+            Query(ForecastQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert!(response.history.is_empty());
+        assert!(response.points.is_empty());
+    }
+}
@@ -0,0 +1,641 @@
+use axum::{
+    Extension, Json,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, ErrorBody, record_audit};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope, hex};
+use crate::firmware::{
+    Firmware, FirmwareId, FirmwareImage, Rollout, RolloutDevice, RolloutId, RolloutProgress,
+    RolloutState, RolloutTarget,
+};
+use crate::registry::{FirmwareRegistry, Registries};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadQuery {
+    pub version: String,
+    /// Hardware revision the image is built for, if only one
+    pub hardware_rev: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateRollout {
+    pub firmware_id: FirmwareId,
+    /// Devices to update; the image's hardware revision if left out
+    #[serde(default)]
+    pub target: RolloutTarget,
+    /// Most devices updating at a time
+    pub batch_size: usize,
+}
+
+/// A rollout and how far its devices got.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RolloutView {
+    #[serde(flatten)]
+    pub rollout: Rollout,
+    pub progress: RolloutProgress,
+}
+
+async fn find_image<R: Registries>(
+    registries: &R,
+    id: FirmwareId,
+) -> Result<FirmwareImage, ApiError> {
+    registries
+        .firmware()
+        .image(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)
+}
+
+async fn find_rollout<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: RolloutId,
+) -> Result<Rollout, ApiError> {
+    let rollout = registries
+        .firmware()
+        .rollout(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    principal.check_access(rollout.org_id)?;
+
+    Ok(rollout)
+}
+
+/// `POST /api/firmware`
+///
+/// Upload a firmware image as the raw request body. Images are shared by
+/// every organization.
+#[utoipa::path(
+    post,
+    path = "/api/firmware",
+    tag = "firmware",
+    params(UploadQuery),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 201, description = "Image stored", body = FirmwareImage),
+        (status = 400, description = "Empty image or version", body = ErrorBody),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+        (status = 413, description = "Image larger than allowed"),
+    )
+)]
+pub async fn upload<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(firmware): Extension<Firmware>,
+    Query(query): Query<UploadQuery>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<FirmwareImage>), ApiError> {
+    principal.require_platform(Scope::Admin)?;
+    if body.is_empty() {
+        return Err(ApiError::BadRequest("image is empty".to_owned()));
+    }
+    if query.version.trim().is_empty() {
+        return Err(ApiError::BadRequest("version must not be empty".to_owned()));
+    }
+
+    let image = FirmwareImage {
+        id: FirmwareId(Ulid::new()),
+        version: query.version.trim().to_owned(),
+        hardware_rev: query.hardware_rev,
+        size: body.len() as u64,
+        sha256: hex(&Sha256::digest(&body)),
+        created_at: jiff::Timestamp::now(),
+    };
+    firmware
+        .store()
+        .put(image.id, &body)
+        .await
+        .map_err(ApiError::internal)?;
+    registries
+        .firmware()
+        .add_image(image.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::Firmware,
+            image.id.0,
+        )
+        .with_details(serde_json::json!({
+            "version": image.version,
+            "sha256": image.sha256,
+        })),
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(image)))
+}
+
+/// `GET /api/firmware`
+///
+/// Uploaded images, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/firmware",
+    tag = "firmware",
+    responses(
+        (status = 200, description = "Every image", body = Vec<FirmwareImage>),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<FirmwareImage>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let images = registries
+        .firmware()
+        .images()
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(images))
+}
+
+/// `GET /api/firmware/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/firmware/{id}",
+    tag = "firmware",
+    params(("id" = String, Path, description = "Firmware image id")),
+    responses(
+        (status = 200, description = "The image's metadata", body = FirmwareImage),
+        (status = 404, description = "Unknown image", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<FirmwareImage>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    Ok(Json(find_image(&registries, FirmwareId(id)).await?))
+}
+
+/// `GET /api/firmware/{id}/image`
+///
+/// The image itself, as downloaded by dispatchers told to update a device.
+#[utoipa::path(
+    get,
+    path = "/api/firmware/{id}/image",
+    tag = "firmware",
+    params(("id" = String, Path, description = "Firmware image id")),
+    responses(
+        (status = 200, description = "The image", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 404, description = "Unknown image", body = ErrorBody),
+    )
+)]
+pub async fn download<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(firmware): Extension<Firmware>,
+    Path(id): Path<Ulid>,
+) -> Result<impl IntoResponse, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+    let image = find_image(&registries, FirmwareId(id)).await?;
+
+    let contents = firmware
+        .store()
+        .get(image.id)
+        .await
+        .map_err(ApiError::internal)?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/octet-stream")],
+        contents,
+    ))
+}
+
+/// `POST /api/rollouts`
+///
+/// Start installing an image on the active devices of the caller's
+/// organization matching the target, `batch_size` of them at a time.
+#[utoipa::path(
+    post,
+    path = "/api/rollouts",
+    tag = "firmware",
+    request_body = CreateRollout,
+    responses(
+        (status = 201, description = "Rollout started", body = Rollout),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown image", body = ErrorBody),
+    )
+)]
+pub async fn create_rollout<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<CreateRollout>,
+) -> Result<(StatusCode, Json<Rollout>), ApiError> {
+    principal.require(Scope::Admin)?;
+    if request.batch_size == 0 {
+        return Err(ApiError::BadRequest(
+            "batch_size must be positive".to_owned(),
+        ));
+    }
+    let image = find_image(&registries, request.firmware_id).await?;
+
+    let mut target = request.target;
+    match (&image.hardware_rev, &target.hardware_rev) {
+        (Some(built_for), Some(wanted)) if built_for != wanted => {
+            return Err(ApiError::BadRequest(format!(
+                "image is built for hardware revision {built_for}"
+            )));
+        }
+        (Some(built_for), None) => target.hardware_rev = Some(built_for.clone()),
+        _ => {}
+    }
+
+    let rollout = Rollout {
+        id: RolloutId(Ulid::new()),
+        firmware_id: image.id,
+        target,
+        org_id: principal.org_id,
+        batch_size: request.batch_size,
+        state: RolloutState::Active,
+        created_at: jiff::Timestamp::now(),
+    };
+    registries
+        .firmware()
+        .create_rollout(rollout.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::Rollout,
+            rollout.id.0,
+        )
+        .with_details(serde_json::json!({
+            "firmware_id": rollout.firmware_id,
+            "target": rollout.target,
+        })),
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(rollout)))
+}
+
+/// `GET /api/rollouts`
+///
+/// Rollouts the caller may see, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/rollouts",
+    tag = "firmware",
+    responses(
+        (status = 200, description = "Rollouts", body = Vec<Rollout>),
+    )
+)]
+pub async fn list_rollouts<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<Rollout>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let rollouts = registries
+        .firmware()
+        .rollouts()
+        .await
+        .map_err(ApiError::registry)?
+        .into_iter()
+        .filter(|rollout| principal.can_access(rollout.org_id))
+        .collect();
+
+    Ok(Json(rollouts))
+}
+
+/// `GET /api/rollouts/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/rollouts/{id}",
+    tag = "firmware",
+    params(("id" = String, Path, description = "Rollout id")),
+    responses(
+        (status = 200, description = "The rollout and its progress", body = RolloutView),
+        (status = 404, description = "Unknown rollout", body = ErrorBody),
+    )
+)]
+pub async fn get_rollout<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<RolloutView>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+    let rollout = find_rollout(&registries, &principal, RolloutId(id)).await?;
+
+    let devices = registries
+        .firmware()
+        .rollout_devices(rollout.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(RolloutView {
+        rollout,
+        progress: RolloutProgress::of(&devices),
+    }))
+}
+
+/// `GET /api/rollouts/{id}/devices`
+///
+/// Devices enrolled in the rollout and how far each got.
+#[utoipa::path(
+    get,
+    path = "/api/rollouts/{id}/devices",
+    tag = "firmware",
+    params(("id" = String, Path, description = "Rollout id")),
+    responses(
+        (status = 200, description = "Enrolled devices", body = Vec<RolloutDevice>),
+        (status = 404, description = "Unknown rollout", body = ErrorBody),
+    )
+)]
+pub async fn rollout_devices<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<Vec<RolloutDevice>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+    let rollout = find_rollout(&registries, &principal, RolloutId(id)).await?;
+
+    let devices = registries
+        .firmware()
+        .rollout_devices(rollout.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(devices))
+}
+
+/// `POST /api/rollouts/{id}/pause`
+///
+/// Stop queuing updates; those already queued go on.
+#[utoipa::path(
+    post,
+    path = "/api/rollouts/{id}/pause",
+    tag = "firmware",
+    params(("id" = String, Path, description = "Rollout id")),
+    responses(
+        (status = 200, description = "Rollout paused", body = Rollout),
+        (status = 404, description = "Unknown rollout", body = ErrorBody),
+        (status = 409, description = "Rollout not active", body = ErrorBody),
+    )
+)]
+pub async fn pause<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<Rollout>, ApiError> {
+    transition(
+        &registries,
+        &principal,
+        RolloutId(id),
+        &[RolloutState::Active],
+        RolloutState::Paused,
+    )
+    .await
+}
+
+/// `POST /api/rollouts/{id}/resume`
+#[utoipa::path(
+    post,
+    path = "/api/rollouts/{id}/resume",
+    tag = "firmware",
+    params(("id" = String, Path, description = "Rollout id")),
+    responses(
+        (status = 200, description = "Rollout resumed", body = Rollout),
+        (status = 404, description = "Unknown rollout", body = ErrorBody),
+        (status = 409, description = "Rollout not paused", body = ErrorBody),
+    )
+)]
+pub async fn resume<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<Rollout>, ApiError> {
+    transition(
+        &registries,
+        &principal,
+        RolloutId(id),
+        &[RolloutState::Paused],
+        RolloutState::Active,
+    )
+    .await
+}
+
+/// `POST /api/rollouts/{id}/cancel`
+///
+/// Stop the rollout for good. Updates already queued aren't withdrawn.
+#[utoipa::path(
+    post,
+    path = "/api/rollouts/{id}/cancel",
+    tag = "firmware",
+    params(("id" = String, Path, description = "Rollout id")),
+    responses(
+        (status = 200, description = "Rollout cancelled", body = Rollout),
+        (status = 404, description = "Unknown rollout", body = ErrorBody),
+        (status = 409, description = "Rollout already over", body = ErrorBody),
+    )
+)]
+pub async fn cancel<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<Rollout>, ApiError> {
+    transition(
+        &registries,
+        &principal,
+        RolloutId(id),
+        &[RolloutState::Active, RolloutState::Paused],
+        RolloutState::Cancelled,
+    )
+    .await
+}
+
+async fn transition<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: RolloutId,
+    from: &[RolloutState],
+    to: RolloutState,
+) -> Result<Json<Rollout>, ApiError> {
+    principal.require(Scope::Admin)?;
+    let mut rollout = find_rollout(registries, principal, id).await?;
+
+    if !from.contains(&rollout.state) {
+        return Err(ApiError::Conflict(format!(
+            "rollout is {}",
+            rollout.state.as_str()
+        )));
+    }
+    registries
+        .firmware()
+        .set_rollout_state(id, to)
+        .await
+        .map_err(ApiError::registry)?;
+    rollout.state = to;
+
+    record_audit(
+        registries,
+        AuditEntry::by(principal, AuditAction::Update, EntityKind::Rollout, id.0)
+            .with_details(serde_json::json!({ "state": to })),
+    )
+    .await?;
+
+    Ok(Json(rollout))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Extension, Json,
+        body::Bytes,
+        extract::{Path, Query, State},
+        response::IntoResponse,
+    };
+    use ulid::Ulid;
+
+    use super::{
+        CreateRollout, UploadQuery, cancel, create_rollout, download, get_rollout, pause, upload,
+    };
+    use crate::api::ApiError;
+    use crate::auth::Scope;
+    use crate::config::FirmwareConfig;
+    use crate::firmware::{Firmware, RolloutState, RolloutTarget, disk::DiskStore};
+    use crate::fixtures::{admin, principal};
+    use crate::org::OrgId;
+    use crate::registry::memory::InMemoryRegistries;
+
+    #[tokio::test]
+    async fn uploaded_images_are_rolled_out_by_revision() {
+        let registries = InMemoryRegistries::default();
+        let dir = std::env::temp_dir().join(format!("ersha-firmware-{}", Ulid::new()));
+        let firmware = Extension(Firmware::new(
+            FirmwareConfig::default(),
+            Arc::new(DiskStore::new(&dir)),
+        ));
+        let query = || {
+            Query(UploadQuery {
+                version: "3.0.1".to_owned(),
+                hardware_rev: Some("rev-b".to_owned()),
+            })
+        };
+        let image = Bytes::from_static(b"\x7fELF firmware");
+
+        let org = Some(OrgId(Ulid::new()));
+        let by_org = upload(
+            State(registries.clone()),
+            principal(Scope::Admin, org),
+            firmware.clone(),
+            query(),
+            image.clone(),
+        )
+        .await;
+        assert!(matches!(by_org, Err(ApiError::Forbidden)));
+        let (_, Json(uploaded)) = upload(
+            State(registries.clone()),
+            admin(),
+            firmware.clone(),
+            query(),
+            image.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(uploaded.size, image.len() as u64);
+        let downloaded = download(
+            State(registries.clone()),
+            admin(),
+            firmware,
+            Path(uploaded.id.0),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        let body = axum::body::to_bytes(downloaded.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, image);
+
+        let request = |hardware_rev: Option<&str>| {
+            Json(CreateRollout {
+                firmware_id: uploaded.id,
+                target: RolloutTarget {
+                    hardware_rev: hardware_rev.map(str::to_owned),
+                    tags: vec![],
+                },
+                batch_size: 5,
+            })
+        };
+        let mismatched = create_rollout(
+            State(registries.clone()),
+            principal(Scope::Admin, org),
+            request(Some("rev-a")),
+        )
+        .await;
+        assert!(matches!(mismatched, Err(ApiError::BadRequest(_))));
+        let (_, Json(rollout)) = create_rollout(
+            State(registries.clone()),
+            principal(Scope::Admin, org),
+            request(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(rollout.target.hardware_rev.as_deref(), Some("rev-b"));
+        assert_eq!(rollout.org_id, org);
+
+        let other_org = principal(Scope::Admin, Some(OrgId(Ulid::new())));
+        assert!(matches!(
+            get_rollout(State(registries.clone()), other_org, Path(rollout.id.0)).await,
+            Err(ApiError::NotFound)
+        ));
+        let Json(paused) = pause(
+            State(registries.clone()),
+            principal(Scope::Admin, org),
+            Path(rollout.id.0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(paused.state, RolloutState::Paused);
+        let Json(cancelled) = cancel(
+            State(registries.clone()),
+            principal(Scope::Admin, org),
+            Path(rollout.id.0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(cancelled.state, RolloutState::Cancelled);
+        assert!(matches!(
+            pause(
+                State(registries),
+                principal(Scope::Admin, org),
+                Path(rollout.id.0)
+            )
+            .await,
+            Err(ApiError::Conflict(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,556 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+};
+use ersha_core::{
+    Device, DeviceId, DeviceKind, DeviceState, DispatcherId, Percentage, Sensor, SensorId,
+    SensorKind, SensorMetric,
+};
+use ordered_float::NotNan;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::readings::parse_metric_kind;
+use super::{ApiError, ErrorBody, MAX_LIMIT, record_audit, scope_fields, visible_dispatcher};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::events::{BusEvent, EventBus};
+use crate::region;
+use crate::registry::{
+    DeviceRegistry, Registries,
+    filter::{DeviceFilter, DeviceSortBy, Pagination, QueryOptions, SortOrder},
+};
+use crate::rollup::metric_kind_name;
+
+/// Upper bound on rows in one import.
+const MAX_IMPORT_ROWS: usize = 5000;
+
+const CSV_CONTENT_TYPE: &str = "text/csv";
+
+/// One device in an import or export.
+///
+/// Flat so that it maps onto a CSV row; JSON uses the same fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DeviceRecord {
+    /// Generated on import when empty
+    pub id: Option<DeviceId>,
+    pub kind: DeviceKind,
+    /// H3 cell the device is installed at, in hex
+    pub location: String,
+    pub manufacturer: Option<String>,
+    /// Dispatcher the device is assigned to
+    pub dispatcher_id: Option<DispatcherId>,
+    /// Sensor kinds separated by `;`, e.g. `soil_moisture;air_temp`
+    #[serde(default)]
+    pub sensors: String,
+}
+
+/// Outcome of one row of an import.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportRow {
+    /// 1-based position among the data rows
+    pub row: usize,
+    pub id: Option<DeviceId>,
+    /// Why the row was not imported
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportReport {
+    /// Devices registered; zero on a dry run
+    pub registered: usize,
+    pub rows: Vec<ImportRow>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FleetFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// Query parameters for `POST /api/devices/import`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    /// Validate every row without registering anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Query parameters for `GET /api/devices/export`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: FleetFormat,
+}
+
+/// `POST /api/devices/import`
+///
+/// Register many devices at once from a JSON array or, with a `text/csv`
+/// content type, a CSV file with a header row. Rows are validated one by
+/// one; valid rows are registered even when others fail.
+#[utoipa::path(
+    post,
+    path = "/api/devices/import",
+    tag = "devices",
+    params(ImportQuery),
+    request_body(content = Vec<DeviceRecord>, description = "Devices as a JSON array or CSV"),
+    responses(
+        (status = 200, description = "Per-row results", body = ImportReport),
+        (status = 400, description = "Unreadable body or too many rows", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn import<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(events): Extension<Arc<dyn EventBus>>,
+    Query(query): Query<ImportQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<ImportReport>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let is_csv = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(CSV_CONTENT_TYPE));
+    let records = if is_csv {
+        parse_csv(&body)
+    } else {
+        parse_json(&body)?
+    };
+    if records.len() > MAX_IMPORT_ROWS {
+        return Err(ApiError::BadRequest(format!(
+            "at most {MAX_IMPORT_ROWS} devices per import"
+        )));
+    }
+
+    let mut rows = Vec::with_capacity(records.len());
+    let mut devices = Vec::new();
+    let mut seen = HashSet::new();
+    let mut dispatchers = HashMap::new();
+    let provisioned_at = jiff::Timestamp::now();
+
+    for (index, record) in records.into_iter().enumerate() {
+        let checked = match record {
+            Ok(record) => {
+                check(
+                    &registries,
+                    &principal,
+                    record,
+                    provisioned_at,
+                    &mut seen,
+                    &mut dispatchers,
+                )
+                .await?
+            }
+            Err(e) => Err(e),
+        };
+        let (id, error) = match checked {
+            Ok((device, dispatcher_id)) => {
+                let id = device.id;
+                devices.push((device, dispatcher_id));
+                (Some(id), None)
+            }
+            Err(e) => (None, Some(e)),
+        };
+        rows.push(ImportRow {
+            row: index + 1,
+            id,
+            error,
+        });
+    }
+
+    if query.dry_run || devices.is_empty() {
+        return Ok(Json(ImportReport {
+            registered: 0,
+            rows,
+        }));
+    }
+
+    let registered = devices.len();
+    let registry = registries.devices();
+    registry
+        .batch_register(devices.iter().map(|(device, _)| device.clone()).collect())
+        .await
+        .map_err(ApiError::registry)?;
+
+    for (device, dispatcher_id) in devices {
+        if principal.org_id.is_some() {
+            registry
+                .set_org(device.id, principal.org_id)
+                .await
+                .map_err(ApiError::registry)?;
+        }
+        if dispatcher_id.is_some() {
+            registry
+                .set_dispatcher(device.id, dispatcher_id)
+                .await
+                .map_err(ApiError::registry)?;
+        }
+
+        record_audit(
+            &registries,
+            AuditEntry::by(
+                &principal,
+                AuditAction::Register,
+                EntityKind::Device,
+                device.id.0,
+            )
+            .with_details(serde_json::json!({ "import": true, "dispatcher_id": dispatcher_id })),
+        )
+        .await?;
+        events
+            .publish(BusEvent::DeviceRegistered {
+                device,
+                org_id: principal.org_id,
+            })
+            .await;
+    }
+
+    tracing::info!(
+        registered,
+        rejected = rows.len() - registered,
+        imported_by = ?principal.key_id,
+        "devices imported"
+    );
+
+    Ok(Json(ImportReport { registered, rows }))
+}
+
+/// `GET /api/devices/export`
+///
+/// Every device the caller may see, in the format accepted by
+/// `POST /api/devices/import`.
+#[utoipa::path(
+    get,
+    path = "/api/devices/export",
+    tag = "devices",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "All visible devices as JSON or CSV", body = Vec<DeviceRecord>),
+    )
+)]
+pub async fn export<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let mut filter = DeviceFilter {
+        org_id: principal.org_id,
+        ..Default::default()
+    };
+    let visible = scope_fields(&principal, &mut filter.within);
+
+    let mut records = Vec::new();
+    let devices = if visible {
+        all_devices(&registries, filter).await?
+    } else {
+        Vec::new()
+    };
+    for device in devices {
+        let dispatcher_id = registries
+            .devices()
+            .dispatcher(device.id)
+            .await
+            .map_err(ApiError::registry)?;
+        records.push(DeviceRecord::new(&device, dispatcher_id));
+    }
+
+    match query.format {
+        FleetFormat::Json => Ok(Json(records).into_response()),
+        FleetFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for record in &records {
+                writer.serialize(record).map_err(ApiError::internal)?;
+            }
+            let body = writer.into_inner().map_err(ApiError::internal)?;
+
+            Ok(([(header::CONTENT_TYPE, CSV_CONTENT_TYPE)], body).into_response())
+        }
+    }
+}
+
+/// Every device matching `filter`, oldest first, fetched a page at a time.
+pub(super) async fn all_devices<R: Registries>(
+    registries: &R,
+    filter: DeviceFilter,
+) -> Result<Vec<Device>, ApiError> {
+    let mut devices = Vec::new();
+    let mut after = None;
+    loop {
+        let page = registries
+            .devices()
+            .list(QueryOptions {
+                filter: filter.clone(),
+                sort_by: DeviceSortBy::ProvisionAt,
+                sort_order: SortOrder::Asc,
+                pagination: Pagination::Cursor {
+                    after,
+                    limit: MAX_LIMIT,
+                },
+            })
+            .await
+            .map_err(ApiError::registry)?;
+
+        let full = page.len() == MAX_LIMIT;
+        after = page
+            .last()
+            .map(|last| DeviceSortBy::ProvisionAt.cursor(last));
+        devices.extend(page);
+
+        if !full {
+            return Ok(devices);
+        }
+    }
+}
+
+impl DeviceRecord {
+    fn new(device: &Device, dispatcher_id: Option<DispatcherId>) -> Self {
+        let sensors: Vec<&str> = device
+            .sensors
+            .iter()
+            .map(|sensor| metric_kind_name(sensor.kind))
+            .collect();
+
+        Self {
+            id: Some(device.id),
+            kind: device.kind.clone(),
+            location: format!("{:x}", device.location.0),
+            manufacturer: device.manufacturer.as_deref().map(Into::into),
+            dispatcher_id,
+            sensors: sensors.join(";"),
+        }
+    }
+}
+
+type Parsed = Result<DeviceRecord, String>;
+
+fn parse_csv(body: &[u8]) -> Vec<Parsed> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body)
+        .deserialize()
+        .map(|record| record.map_err(|e| e.to_string()))
+        .collect()
+}
+
+fn parse_json(body: &[u8]) -> Result<Vec<Parsed>, ApiError> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(body)
+        .map_err(|e| ApiError::BadRequest(format!("expected a JSON array: {e}")))?;
+
+    Ok(values
+        .into_iter()
+        .map(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+        .collect())
+}
+
+/// Turn one record into a device ready to register, or say why it can't be.
+async fn check<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    record: DeviceRecord,
+    provisioned_at: jiff::Timestamp,
+    seen: &mut HashSet<DeviceId>,
+    dispatchers: &mut HashMap<DispatcherId, bool>,
+) -> Result<Result<(Device, Option<DispatcherId>), String>, ApiError> {
+    let Some(location) = region::parse_cell(record.location.trim_start_matches("0x")) else {
+        return Ok(Err(format!("invalid location: '{}'", record.location)));
+    };
+
+    let mut sensors = Vec::new();
+    for kind in record.sensors.split(';').map(str::trim) {
+        if kind.is_empty() {
+            continue;
+        }
+        let Some(kind) = parse_metric_kind(kind) else {
+            return Ok(Err(format!("invalid sensor kind: '{kind}'")));
+        };
+        sensors.push(sensor(kind));
+    }
+
+    let id = record.id.unwrap_or_else(|| DeviceId(Ulid::new()));
+    if !seen.insert(id) {
+        return Ok(Err("device appears more than once".to_owned()));
+    }
+    if registries
+        .devices()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .is_some()
+    {
+        return Ok(Err("device already registered".to_owned()));
+    }
+
+    if let Some(dispatcher_id) = record.dispatcher_id {
+        let known = match dispatchers.get(&dispatcher_id) {
+            Some(known) => *known,
+            None => {
+                let known = match visible_dispatcher(registries, principal, dispatcher_id).await {
+                    Ok(_) => true,
+                    Err(ApiError::NotFound) => false,
+                    Err(e) => return Err(e),
+                };
+                dispatchers.insert(dispatcher_id, known);
+                known
+            }
+        };
+        if !known {
+            return Ok(Err("unknown dispatcher".to_owned()));
+        }
+    }
+
+    let device = Device {
+        id,
+        kind: record.kind,
+        state: DeviceState::Active,
+        location,
+        manufacturer: record
+            .manufacturer
+            .filter(|m| !m.trim().is_empty())
+            .map(Into::into),
+        provisioned_at,
+        sensors: sensors.into_boxed_slice(),
+    };
+
+    Ok(Ok((device, record.dispatcher_id)))
+}
+
+/// A new sensor of the given kind. Its metric holds a zero value until the
+/// first reading arrives.
+fn sensor(kind: SensorKind) -> Sensor {
+    let zero = NotNan::default();
+    let metric = match kind {
+        SensorKind::SoilMoisture => SensorMetric::SoilMoisture {
+            value: Percentage(0),
+        },
+        SensorKind::SoilTemp => SensorMetric::SoilTemp { value: zero },
+        SensorKind::AirTemp => SensorMetric::AirTemp { value: zero },
+        SensorKind::Humidity => SensorMetric::Humidity {
+            value: Percentage(0),
+        },
+        SensorKind::Rainfall => SensorMetric::Rainfall { value: zero },
+    };
+
+    Sensor {
+        id: SensorId(Ulid::new()),
+        metric,
+        kind,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Extension,
+        body::Bytes,
+        extract::{Query, State},
+        http::{HeaderMap, HeaderValue, header},
+    };
+    use ulid::Ulid;
+
+    use super::{ExportQuery, FleetFormat, ImportQuery, export, import};
+    use crate::events::{EventBus, LocalEventBus};
+    use crate::fixtures::admin;
+    use crate::registry::{DeviceRegistry, memory::InMemoryRegistries};
+
+    fn events() -> Extension<Arc<dyn EventBus>> {
+        Extension(Arc::new(LocalEventBus::new()))
+    }
+
+    fn csv_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/csv"));
+        headers
+    }
+
+    #[tokio::test]
+    async fn csv_import_reports_each_row_and_round_trips() {
+        let registries = InMemoryRegistries::default();
+        let id = Ulid::new();
+        let csv = format!(
+            "id,kind,location,manufacturer,dispatcher_id,sensors\n\
+             {id},Sensor,8a2a1072b59ffff,Acme,,soil_moisture;air_temp\n\
+             ,Sensor,not-a-cell,,,\n\
+             {id},Sensor,8a2a1072b59ffff,,,\n\
+             ,Sensor,8a2a1072b59ffff,,{},\n",
+            Ulid::new()
+        );
+
+        let axum::Json(report) = import(
+            State(registries.clone()),
+            admin(),
+            events(),
+            Query(ImportQuery::default()),
+            csv_headers(),
+            Bytes::from(csv),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.registered, 1);
+        let errors: Vec<_> = report.rows.iter().map(|r| r.error.as_deref()).collect();
+        assert_eq!(errors[0], None);
+        assert!(errors[1].unwrap().starts_with("invalid location"));
+        assert_eq!(errors[2], Some("device appears more than once"));
+        assert_eq!(errors[3], Some("unknown dispatcher"));
+        assert_eq!(registries.devices.count(None).await.unwrap(), 1);
+
+        let response = export(
+            State(registries),
+            admin(),
+            Query(ExportQuery {
+                format: FleetFormat::Csv,
+            }),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            format!(
+                "id,kind,location,manufacturer,dispatcher_id,sensors\n\
+                 {id},Sensor,8a2a1072b59ffff,Acme,,soil_moisture;air_temp\n"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_registers_nothing() {
+        let registries = InMemoryRegistries::default();
+        let body = r#"[{"kind": "Sensor", "location": "8a2a1072b59ffff"}]"#;
+
+        let axum::Json(report) = import(
+            State(registries.clone()),
+            admin(),
+            events(),
+            Query(ImportQuery { dry_run: true }),
+            HeaderMap::new(),
+            Bytes::from_static(body.as_bytes()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.registered, 0);
+        assert!(report.rows[0].error.is_none());
+        assert_eq!(registries.devices.count(None).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+
+use axum::{
+    Extension, Json,
+    extract::{Query, State},
+    http::{HeaderName, header},
+};
+use ersha_core::{
+    DeviceId, DeviceKind, DeviceState, DispatcherId, DispatcherState, H3Cell, Percentage,
+};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::devices::parse_device_state;
+use super::dispatchers::offline_window;
+use super::fleet::all_devices;
+use super::{ApiError, ErrorBody, parse_list, scope_fields};
+use crate::auth::{Principal, Scope};
+use crate::config::HealthConfig;
+use crate::health::{Connectivity, DispatcherHealth};
+use crate::region;
+use crate::registry::{
+    DeviceStatusRegistry, DispatcherRegistry, DispatcherStatusRegistry, Registries,
+    filter::{
+        DeviceFilter, DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortOrder,
+    },
+};
+
+const GEOJSON_CONTENT_TYPE: &str = "application/geo+json";
+
+/// A feature collection served as `application/geo+json`.
+type GeoJson<P> = ([(HeaderName, &'static str); 1], Json<FeatureCollection<P>>);
+
+/// How each H3 cell is drawn.
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GeometryKind {
+    /// The cell's hexagon
+    #[default]
+    Polygon,
+    /// The cell's centre
+    Centroid,
+}
+
+/// Query parameters for `GET /api/devices.geojson` and
+/// `GET /api/dispatchers.geojson`.
+///
+/// List parameters are comma separated.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GeoJsonQuery {
+    #[serde(default)]
+    pub geometry: GeometryKind,
+    /// States, e.g. `active,suspended`
+    pub state: Option<String>,
+    /// H3 cells in hex; features inside any of them match
+    pub within: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    Point {
+        /// `[longitude, latitude]`
+        coordinates: [f64; 2],
+    },
+    Polygon {
+        /// A single closed ring of `[longitude, latitude]` points
+        coordinates: Vec<Vec<[f64; 2]>>,
+    },
+}
+
+impl Geometry {
+    /// `None` for cells that are not valid H3 indexes.
+    fn of(cell: H3Cell, kind: GeometryKind) -> Option<Self> {
+        let geometry = match kind {
+            GeometryKind::Polygon => Geometry::Polygon {
+                coordinates: vec![region::boundary(cell)?],
+            },
+            GeometryKind::Centroid => Geometry::Point {
+                coordinates: region::centroid(cell)?,
+            },
+        };
+
+        Some(geometry)
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename = "Feature")]
+pub struct Feature<P> {
+    pub id: Ulid,
+    pub geometry: Geometry,
+    pub properties: P,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename = "FeatureCollection")]
+pub struct FeatureCollection<P> {
+    pub features: Vec<Feature<P>>,
+}
+
+impl<P> FeatureCollection<P> {
+    fn served(self) -> GeoJson<P> {
+        ([(header::CONTENT_TYPE, GEOJSON_CONTENT_TYPE)], Json(self))
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceProperties {
+    pub id: DeviceId,
+    pub kind: DeviceKind,
+    pub state: DeviceState,
+    pub manufacturer: Option<String>,
+    /// H3 cell in hex
+    pub cell: String,
+    pub sensors: usize,
+    /// From the latest status report, if any
+    pub battery_percent: Option<Percentage>,
+    /// When the latest status report was taken
+    pub reported_at: Option<jiff::Timestamp>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DispatcherProperties {
+    pub id: DispatcherId,
+    pub state: DispatcherState,
+    /// H3 cell in hex
+    pub cell: String,
+    pub connectivity: Connectivity,
+    /// When central last received a status report
+    pub last_seen: Option<jiff::Timestamp>,
+}
+
+fn parse_within(query: &GeoJsonQuery) -> Result<Option<Vec<H3Cell>>, ApiError> {
+    parse_list("within", query.within.as_deref(), region::parse_cell)
+}
+
+/// `GET /api/devices.geojson`
+///
+/// Every device the caller may see as a GeoJSON feature, drawn from its H3
+/// cell, with its state and latest battery level as properties. Devices at
+/// invalid cells are left out.
+#[utoipa::path(
+    get,
+    path = "/api/devices.geojson",
+    tag = "devices",
+    params(GeoJsonQuery),
+    responses(
+        (status = 200, description = "Devices as a GeoJSON FeatureCollection",
+            body = FeatureCollection<DeviceProperties>, content_type = "application/geo+json"),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+pub async fn devices<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<GeoJsonQuery>,
+) -> Result<GeoJson<DeviceProperties>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let mut filter = DeviceFilter {
+        states: parse_list("state", query.state.as_deref(), parse_device_state)?,
+        within: parse_within(&query)?,
+        org_id: principal.org_id,
+        ..Default::default()
+    };
+    let visible = scope_fields(&principal, &mut filter.within);
+
+    let mut features = Vec::new();
+    let devices = if visible {
+        all_devices(&registries, filter).await?
+    } else {
+        Vec::new()
+    };
+    for device in devices {
+        let Some(geometry) = Geometry::of(device.location, query.geometry) else {
+            continue;
+        };
+        let status = registries
+            .statuses()
+            .latest(device.id)
+            .await
+            .map_err(ApiError::registry)?;
+
+        features.push(Feature {
+            id: device.id.0,
+            geometry,
+            properties: DeviceProperties {
+                id: device.id,
+                kind: device.kind,
+                state: device.state,
+                manufacturer: device.manufacturer.map(Into::into),
+                cell: format!("{:x}", device.location.0),
+                sensors: device.sensors.len(),
+                battery_percent: status.as_ref().map(|status| status.battery_percent),
+                reported_at: status.map(|status| status.timestamp),
+            },
+        });
+    }
+
+    Ok(FeatureCollection { features }.served())
+}
+
+/// `GET /api/dispatchers.geojson`
+///
+/// Every dispatcher the caller may see as a GeoJSON feature, drawn from its
+/// H3 cell, with its state and connectivity as properties.
+#[utoipa::path(
+    get,
+    path = "/api/dispatchers.geojson",
+    tag = "dispatchers",
+    params(GeoJsonQuery),
+    responses(
+        (status = 200, description = "Dispatchers as a GeoJSON FeatureCollection",
+            body = FeatureCollection<DispatcherProperties>, content_type = "application/geo+json"),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+pub async fn dispatchers<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(config): Extension<HealthConfig>,
+    Query(query): Query<GeoJsonQuery>,
+) -> Result<GeoJson<DispatcherProperties>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let filter = DispatcherFilter {
+        states: parse_list("state", query.state.as_deref(), |s| match s {
+            "active" => Some(DispatcherState::Active),
+            "suspended" => Some(DispatcherState::Suspended),
+            "pending" => Some(DispatcherState::Pending),
+            _ => None,
+        })?,
+        locations: None,
+        org_id: principal.org_id,
+        tags: None,
+    };
+    let within = parse_within(&query)?;
+
+    let dispatchers = registries.dispatchers();
+    let count = dispatchers
+        .count(Some(filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let dispatchers = dispatchers
+        .list(QueryOptions {
+            filter,
+            sort_by: DispatcherSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Offset {
+                offset: 0,
+                limit: count,
+            },
+        })
+        .await
+        .map_err(ApiError::registry)?;
+
+    let mut reports: HashMap<_, _> = registries
+        .dispatcher_statuses()
+        .list()
+        .await
+        .map_err(ApiError::registry)?
+        .into_iter()
+        .map(|report| (report.status.dispatcher_id, report))
+        .collect();
+
+    let now = jiff::Timestamp::now();
+    let window = offline_window(config);
+    let features = dispatchers
+        .into_iter()
+        .filter(|dispatcher| match &within {
+            Some(cells) => cells
+                .iter()
+                .any(|cell| dispatcher.location.is_within(*cell)),
+            None => true,
+        })
+        .filter_map(|dispatcher| {
+            let geometry = Geometry::of(dispatcher.location, query.geometry)?;
+            let cell = format!("{:x}", dispatcher.location.0);
+            let report = reports.remove(&dispatcher.id);
+            let health = DispatcherHealth::assess(dispatcher, report, now, window);
+
+            Some(Feature {
+                id: health.dispatcher_id.0,
+                geometry,
+                properties: DispatcherProperties {
+                    id: health.dispatcher_id,
+                    state: health.state,
+                    cell,
+                    connectivity: health.connectivity,
+                    last_seen: health.last_seen,
+                },
+            })
+        })
+        .collect();
+
+    Ok(FeatureCollection { features }.served())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json,
+        extract::{Query, State},
+    };
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, DeviceStatus, DispatcherId, H3Cell, Percentage,
+        StatusId,
+    };
+    use ulid::Ulid;
+
+    use super::{GeoJsonQuery, GeometryKind, devices};
+    use crate::fixtures::reader;
+    use crate::registry::{DeviceRegistry, DeviceStatusRegistry, memory::InMemoryRegistries};
+
+    #[tokio::test]
+    async fn devices_carry_state_and_latest_battery() {
+        let registries = InMemoryRegistries::default();
+        let principal = reader();
+
+        for (location, state) in [
+            (H3Cell(0x8a2a1072b59ffff), DeviceState::Active),
+            (H3Cell(0x8a2a1072b4a7fff), DeviceState::Suspended),
+            (H3Cell(0x1337deadbeef), DeviceState::Active),
+        ] {
+            let id = DeviceId(Ulid::new());
+            registries
+                .devices
+                .register(Device {
+                    id,
+                    kind: DeviceKind::Sensor,
+                    state,
+                    location,
+                    manufacturer: None,
+                    provisioned_at: jiff::Timestamp::now(),
+                    sensors: Box::new([]),
+                })
+                .await
+                .unwrap();
+            registries
+                .statuses
+                .store(DeviceStatus {
+                    id: StatusId(Ulid::new()),
+                    device_id: id,
+                    dispatcher_id: DispatcherId(Ulid::new()),
+                    battery_percent: Percentage(64),
+                    uptime_seconds: 3600,
+                    signal_rssi: -72,
+                    errors: Box::new([]),
+                    timestamp: jiff::Timestamp::now(),
+                    sensor_statuses: Box::new([]),
+                })
+                .await
+                .unwrap();
+        }
+
+        let query = GeoJsonQuery {
+            geometry: GeometryKind::Centroid,
+            state: Some("active".to_owned()),
+            within: None,
+        };
+        let (_, Json(collection)) =
+            devices(State(registries.clone()), principal.clone(), Query(query))
+                .await
+                .unwrap();
+        assert_eq!(collection.features.len(), 1);
+        assert_eq!(
+            collection.features[0].properties.battery_percent,
+            Some(Percentage(64))
+        );
+
+        let (headers, Json(collection)) =
+            devices(State(registries), principal, Query(GeoJsonQuery::default()))
+                .await
+                .unwrap();
+        assert_eq!(headers[0].1, "application/geo+json");
+        assert_eq!(collection.features.len(), 2);
+
+        let json = serde_json::to_value(&collection).unwrap();
+        assert_eq!(json["type"], "FeatureCollection");
+        assert_eq!(json["features"][0]["type"], "Feature");
+        assert_eq!(json["features"][0]["geometry"]["type"], "Polygon");
+        assert_eq!(
+            json["features"][0]["geometry"]["coordinates"][0]
+                .as_array()
+                .unwrap()
+                .len(),
+            7
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use super::{ApiError, ErrorBody, nullable, record_audit};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::group::{Group, GroupId, normalize_tags};
+use crate::registry::{GroupRegistry, Registries};
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateGroup {
+    pub name: String,
+    pub description: Option<String>,
+    /// Devices and dispatchers carrying every one of these are members
+    pub tags: Vec<String>,
+}
+
+/// Body of `PATCH /api/groups/{id}`. Fields left out are kept.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdateGroup {
+    pub name: Option<String>,
+    /// `null` removes the description
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub description: Option<Option<String>>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Reject groups without a name or that would take in the whole fleet.
+fn validate(group: &mut Group) -> Result<(), ApiError> {
+    if group.name.trim().is_empty() {
+        return Err(ApiError::BadRequest("name must not be empty".to_owned()));
+    }
+    group.tags =
+        normalize_tags(&group.tags).map_err(|reason| ApiError::BadRequest(reason.to_owned()))?;
+    if group.tags.is_empty() {
+        return Err(ApiError::BadRequest("add at least one tag".to_owned()));
+    }
+
+    Ok(())
+}
+
+/// The group, if it exists and the caller may see it.
+pub(super) async fn visible_group<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: GroupId,
+) -> Result<Group, ApiError> {
+    let group = registries
+        .groups()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    principal.check_access(group.org_id)?;
+
+    Ok(group)
+}
+
+/// Narrow a tag filter to the members of `group`, if one is given.
+pub(super) async fn with_group<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    group: Option<Ulid>,
+    tags: &mut Option<Vec<String>>,
+) -> Result<(), ApiError> {
+    let Some(id) = group else {
+        return Ok(());
+    };
+    let group = visible_group(registries, principal, GroupId(id)).await?;
+    tags.get_or_insert_default().extend(group.tags);
+
+    Ok(())
+}
+
+/// `POST /api/groups`
+///
+/// The group belongs to the caller's organization.
+#[utoipa::path(
+    post,
+    path = "/api/groups",
+    tag = "groups",
+    request_body = CreateGroup,
+    responses(
+        (status = 201, description = "Group created", body = Group),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<CreateGroup>,
+) -> Result<(StatusCode, Json<Group>), ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let mut group = Group::new(request.name, request.tags);
+    group.description = request.description;
+    group.org_id = principal.org_id;
+    validate(&mut group)?;
+
+    registries
+        .groups()
+        .create(group.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::Group,
+            group.id.0,
+        )
+        .with_details(serde_json::json!({ "name": group.name, "tags": group.tags })),
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(group)))
+}
+
+/// `GET /api/groups`
+#[utoipa::path(
+    get,
+    path = "/api/groups",
+    tag = "groups",
+    responses(
+        (status = 200, description = "Groups visible to the caller, by name", body = Vec<Group>),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<Group>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let groups = registries
+        .groups()
+        .list()
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(
+        groups
+            .into_iter()
+            .filter(|group| principal.can_access(group.org_id))
+            .collect(),
+    ))
+}
+
+/// `GET /api/groups/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/groups/{id}",
+    tag = "groups",
+    params(("id" = String, Path, description = "Group id")),
+    responses(
+        (status = 200, description = "The group", body = Group),
+        (status = 404, description = "Unknown group", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<Group>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    Ok(Json(
+        visible_group(&registries, &principal, GroupId(id)).await?,
+    ))
+}
+
+/// `PATCH /api/groups/{id}`
+#[utoipa::path(
+    patch,
+    path = "/api/groups/{id}",
+    tag = "groups",
+    params(("id" = String, Path, description = "Group id")),
+    request_body = UpdateGroup,
+    responses(
+        (status = 200, description = "Group updated", body = Group),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown group", body = ErrorBody),
+    )
+)]
+pub async fn update<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<UpdateGroup>,
+) -> Result<Json<Group>, ApiError> {
+    principal.require(Scope::Admin)?;
+    let mut group = visible_group(&registries, &principal, GroupId(id)).await?;
+
+    let mut changed = Vec::new();
+    if let Some(name) = request.name {
+        group.name = name;
+        changed.push("name");
+    }
+    if let Some(description) = request.description {
+        group.description = description;
+        changed.push("description");
+    }
+    if let Some(tags) = request.tags {
+        group.tags = tags;
+        changed.push("tags");
+    }
+    validate(&mut group)?;
+
+    registries
+        .groups()
+        .update(group.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Update,
+            EntityKind::Group,
+            group.id.0,
+        )
+        .with_details(serde_json::json!({ "fields": changed })),
+    )
+    .await?;
+
+    Ok(Json(group))
+}
+
+/// `DELETE /api/groups/{id}`
+///
+/// The tags on its members are kept.
+#[utoipa::path(
+    delete,
+    path = "/api/groups/{id}",
+    tag = "groups",
+    params(("id" = String, Path, description = "Group id")),
+    responses(
+        (status = 204, description = "Group deleted"),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown group", body = ErrorBody),
+    )
+)]
+pub async fn delete<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+    let group = visible_group(&registries, &principal, GroupId(id)).await?;
+
+    registries
+        .groups()
+        .delete(group.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Delete,
+            EntityKind::Group,
+            group.id.0,
+        ),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json,
+        extract::{Path, State},
+        http::StatusCode,
+    };
+    use ulid::Ulid;
+
+    use super::{CreateGroup, UpdateGroup, create, get, list, update};
+    use crate::api::ApiError;
+    use crate::auth::Scope;
+    use crate::fixtures::principal;
+    use crate::org::OrgId;
+    use crate::registry::memory::InMemoryRegistries;
+
+    #[tokio::test]
+    async fn groups_normalize_their_tags_and_stay_in_their_organization() {
+        let registries = InMemoryRegistries::default();
+        let org_id = OrgId(Ulid::new());
+
+        let (status, Json(group)) = create(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(org_id)),
+            Json(CreateGroup {
+                name: "North pilot".to_owned(),
+                description: None,
+                tags: vec!["Pilot-A".to_owned(), " north-ridge".to_owned()],
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(group.tags, ["north-ridge", "pilot-a"]);
+
+        let others = get(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(OrgId(Ulid::new()))),
+            Path(group.id.0),
+        )
+        .await;
+        assert!(matches!(others, Err(ApiError::NotFound)));
+        let Json(listed) = list(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(org_id)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listed, std::slice::from_ref(&group));
+
+        let emptied = update(
+            State(registries),
+            principal(Scope::Admin, Some(org_id)),
+            Path(group.id.0),
+            Json(UpdateGroup {
+                tags: Some(Vec::new()),
+                ..Default::default()
+            }),
+        )
+        .await;
+        assert!(matches!(emptied, Err(ApiError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    Extension, Json,
+    extract::{Query, State},
+};
+use ersha_core::{SensorKind, SensorReading};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, ErrorBody, readings::parse_metric_kind, scope_dispatchers, scope_fields};
+use crate::auth::{Principal, Scope};
+use crate::region;
+use crate::registry::{ReadingRegistry, Registries, filter::ReadingFilter};
+use crate::rollup::metric_value;
+
+const DEFAULT_RESOLUTION: u8 = 7;
+/// Finest H3 resolution.
+const MAX_RESOLUTION: u8 = 15;
+/// Window looked back over when none is given.
+const DEFAULT_WINDOW: jiff::SignedDuration = jiff::SignedDuration::from_hours(24);
+
+/// Query parameters for `GET /api/readings/heatmap`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HeatmapQuery {
+    /// Metric kind, e.g. `soil_moisture`
+    pub metric: String,
+    /// H3 resolution of the cells, 0 to 15; 7 by default
+    pub resolution: Option<u8>,
+    /// How far back readings count, e.g. `24h` or `PT6H`; 24 hours by
+    /// default
+    pub window: Option<String>,
+}
+
+/// Latest readings of one metric averaged by H3 cell.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Heatmap {
+    pub metric: SensorKind,
+    pub resolution: u8,
+    pub from: jiff::Timestamp,
+    pub to: jiff::Timestamp,
+    /// Cells with at least one sensor reporting, ordered by index
+    pub cells: Vec<HeatmapCell>,
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct HeatmapCell {
+    /// H3 cell in hex
+    pub cell: String,
+    /// Mean of the latest reading of each sensor in the cell
+    pub average: f64,
+    /// Sensors averaged
+    pub sensors: usize,
+}
+
+/// `GET /api/readings/heatmap`
+///
+/// The latest reading of each sensor within the window, bucketed by the
+/// cell containing it at `resolution` and averaged per cell, for drawing a
+/// regional heatmap. Readings located at a coarser resolution than asked
+/// for are left out.
+#[utoipa::path(
+    get,
+    path = "/api/readings/heatmap",
+    tag = "readings",
+    params(HeatmapQuery),
+    responses(
+        (status = 200, description = "Per-cell averages", body = Heatmap),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+pub async fn heatmap<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<HeatmapQuery>,
+) -> Result<Json<Heatmap>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let metric = parse_metric_kind(&query.metric)
+        .ok_or_else(|| ApiError::BadRequest(format!("invalid metric: '{}'", query.metric)))?;
+    let resolution = query.resolution.unwrap_or(DEFAULT_RESOLUTION);
+    if resolution > MAX_RESOLUTION {
+        return Err(ApiError::BadRequest(format!(
+            "resolution must be between 0 and {MAX_RESOLUTION}"
+        )));
+    }
+    let window = match query.window.as_deref() {
+        None => DEFAULT_WINDOW,
+        Some(s) => s
+            .parse::<jiff::SignedDuration>()
+            .ok()
+            .filter(|window| window.is_positive())
+            .ok_or_else(|| ApiError::BadRequest(format!("invalid window: '{s}'")))?,
+    };
+    let to = jiff::Timestamp::now();
+    let from = to
+        .checked_sub(window)
+        .map_err(|_| ApiError::BadRequest(format!("window too long: '{window}'")))?;
+
+    let mut filter = ReadingFilter {
+        metric_kinds: Some(vec![metric]),
+        after: Some(from),
+        before: Some(to),
+        ..Default::default()
+    };
+    let cells = if scope_dispatchers(&registries, &principal, &mut filter.dispatcher_ids).await?
+        && scope_fields(&principal, &mut filter.within)
+    {
+        let latest = registries
+            .readings()
+            .latest_matching(filter)
+            .await
+            .map_err(ApiError::registry)?;
+        cell_averages(&latest, resolution)
+    } else {
+        Vec::new()
+    };
+
+    Ok(Json(Heatmap {
+        metric,
+        resolution,
+        from,
+        to,
+        cells,
+    }))
+}
+
+/// Average `readings` by their ancestor cell at `resolution`.
+fn cell_averages<'a>(
+    readings: impl IntoIterator<Item = &'a SensorReading>,
+    resolution: u8,
+) -> Vec<HeatmapCell> {
+    let mut sums: BTreeMap<u64, (f64, usize)> = BTreeMap::new();
+    for reading in readings {
+        if let Some(cell) = region::parent(reading.location, resolution) {
+            let (sum, count) = sums.entry(cell.0).or_default();
+            *sum += metric_value(&reading.metric);
+            *count += 1;
+        }
+    }
+
+    sums.into_iter()
+        .map(|(cell, (sum, count))| HeatmapCell {
+            cell: format!("{cell:x}"),
+            average: sum / count as f64,
+            sensors: count,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::{Query, State};
+    use ersha_core::{
+        DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId, SensorMetric,
+        SensorReading,
+    };
+    use ulid::Ulid;
+
+    use super::{HeatmapCell, HeatmapQuery, heatmap};
+    use crate::api::ApiError;
+    use crate::fixtures::reader;
+    use crate::registry::{ReadingRegistry, memory::InMemoryRegistries};
+
+    /// Resolution 10 cells with different resolution 9 parents.
+    const CELL: H3Cell = H3Cell(0x8a2a1072b59ffff);
+    const ELSEWHERE: H3Cell = H3Cell(0x8a2a1072b4a7fff);
+
+    fn moisture(sensor_id: SensorId, location: H3Cell, value: u8, hours_ago: i64) -> SensorReading {
+        SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id: DeviceId(Ulid::nil()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(value),
+            },
+            location,
+            confidence: Percentage(90),
+            timestamp: jiff::Timestamp::now() - jiff::SignedDuration::from_hours(hours_ago),
+            sensor_id,
+        }
+    }
+
+    fn query(resolution: u8, window: &str) -> Query<HeatmapQuery> {
+        Query(HeatmapQuery {
+            metric: "soil_moisture".to_owned(),
+            resolution: Some(resolution),
+            window: Some(window.to_owned()),
+        })
+    }
+
+    #[tokio::test]
+    async fn latest_readings_are_averaged_by_parent_cell() {
+        let registries = InMemoryRegistries::default();
+        let (first, second, third) = (
+            SensorId(Ulid::new()),
+            SensorId(Ulid::new()),
+            SensorId(Ulid::new()),
+        );
+        registries
+            .readings
+            .batch_store(vec![
+                // Superseded by the sensor's newer reading.
+                moisture(first, CELL, 90, 3),
+                moisture(first, CELL, 20, 1),
+                moisture(second, CELL, 40, 2),
+                moisture(third, ELSEWHERE, 70, 1),
+                // Outside the window.
+                moisture(SensorId(Ulid::new()), ELSEWHERE, 10, 30),
+            ])
+            .await
+            .unwrap();
+
+        let map = heatmap(State(registries.clone()), reader(), query(9, "24h"))
+            .await
+            .unwrap();
+        let parent = |cell| format!("{:x}", crate::region::parent(cell, 9).unwrap().0);
+        let mut expected = vec![
+            HeatmapCell {
+                cell: parent(CELL),
+                average: 30.0,
+                sensors: 2,
+            },
+            HeatmapCell {
+                cell: parent(ELSEWHERE),
+                average: 70.0,
+                sensors: 1,
+            },
+        ];
+        expected.sort_by(|a, b| a.cell.cmp(&b.cell));
+        assert_eq!(map.cells, expected);
+
+        // Finer than the readings' cells, nothing can be placed.
+        let map = heatmap(State(registries.clone()), reader(), query(11, "24h"))
+            .await
+            .unwrap();
+        assert!(map.cells.is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_queries_are_rejected() {
+        let registries = InMemoryRegistries::default();
+        for query in [query(16, "24h"), query(7, "soon"), query(7, "-2h")] {
+            assert!(matches!(
+                heatmap(State(registries.clone()), reader(), query).await,
+                Err(ApiError::BadRequest(_))
+            ));
+        }
+    }
+}
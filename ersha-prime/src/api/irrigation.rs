@@ -0,0 +1,629 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use ersha_core::DeviceState;
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::fields::visible_field;
+use super::regions::parse_region;
+use super::{ApiError, ErrorBody, nullable, record_audit, visible_device};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::config::{IndicatorConfig, IrrigationConfig};
+use crate::irrigation::{self, IrrigationPlan, PlanId, Valve};
+use crate::region;
+use crate::registry::{IrrigationRegistry, Registries};
+
+/// Largest crop coefficient accepted; crops rarely exceed 1.3.
+const MAX_CROP_COEFFICIENT: f64 = 2.0;
+
+/// Body of `POST /api/irrigation/plans`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreatePlan {
+    /// Field H3 cell in hex
+    pub field: String,
+    pub crop_coefficient: f64,
+    /// Actuator output watering the field, on a device located in it
+    pub valve: Option<Valve>,
+    /// Open and close the valve for recommended windows; needs a valve
+    #[serde(default)]
+    pub automatic: bool,
+}
+
+/// Body of `PATCH /api/irrigation/plans/{id}`. Fields left out are kept.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdatePlan {
+    pub crop_coefficient: Option<f64>,
+    /// `null` removes the valve
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Valve>)]
+    pub valve: Option<Option<Valve>>,
+    pub automatic: Option<bool>,
+}
+
+/// Query parameters for `GET /api/irrigation/plans`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlansQuery {
+    /// Only the plan for this field, an H3 cell in hex
+    pub field: Option<String>,
+}
+
+/// Reject settings the planner can't work with.
+async fn validate<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    config: &IndicatorConfig,
+    plan: &IrrigationPlan,
+) -> Result<(), ApiError> {
+    let coefficient = plan.crop_coefficient;
+    if !(coefficient > 0.0 && coefficient <= MAX_CROP_COEFFICIENT) {
+        return Err(ApiError::BadRequest(format!(
+            "crop_coefficient must be above 0 and at most {MAX_CROP_COEFFICIENT}"
+        )));
+    }
+
+    let Some(valve) = plan.valve else {
+        if plan.automatic {
+            return Err(ApiError::BadRequest(
+                "automatic plans need a valve".to_owned(),
+            ));
+        }
+        return Ok(());
+    };
+    let device = visible_device(registries, principal, valve.device_id).await?;
+    if device.state == DeviceState::Decommissioned {
+        return Err(ApiError::Conflict(
+            "valve device is decommissioned".to_owned(),
+        ));
+    }
+    if region::parent(device.location, config.field_resolution) != Some(plan.field) {
+        return Err(ApiError::BadRequest(
+            "valve device is not located in the field".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fetch a plan the caller may see.
+async fn visible_plan<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: PlanId,
+) -> Result<IrrigationPlan, ApiError> {
+    let plan = registries
+        .irrigation()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    principal.check_access(plan.org_id)?;
+    if !principal.can_see(plan.field) {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(plan)
+}
+
+/// `POST /api/irrigation/plans`
+///
+/// Set up irrigation planning for a field. The response carries the first
+/// recommendation, if the field needs water.
+#[utoipa::path(
+    post,
+    path = "/api/irrigation/plans",
+    tag = "irrigation",
+    request_body = CreatePlan,
+    responses(
+        (status = 201, description = "Plan created", body = IrrigationPlan),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an agronomist or admin key", body = ErrorBody),
+        (status = 404, description = "Field or valve device not visible to the caller", body = ErrorBody),
+        (status = 409, description = "The field already has a plan", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(indicators): Extension<IndicatorConfig>,
+    Extension(config): Extension<IrrigationConfig>,
+    Json(request): Json<CreatePlan>,
+) -> Result<(StatusCode, Json<IrrigationPlan>), ApiError> {
+    principal.require(Scope::Agronomist)?;
+    let field = visible_field(&registries, &principal, &indicators, &request.field).await?;
+
+    let now = jiff::Timestamp::now();
+    let mut plan = IrrigationPlan {
+        valve: request.valve,
+        automatic: request.automatic,
+        org_id: principal.org_id,
+        ..IrrigationPlan::new(field, request.crop_coefficient, now)
+    };
+    validate(&registries, &principal, &indicators, &plan).await?;
+
+    let plans = registries.irrigation();
+    let existing = plans.list().await.map_err(ApiError::registry)?;
+    if existing
+        .iter()
+        .any(|other| other.field == field && other.org_id == plan.org_id)
+    {
+        return Err(ApiError::Conflict(
+            "the field already has a plan".to_owned(),
+        ));
+    }
+
+    irrigation::advance(&registries, &config, &mut plan, now)
+        .await
+        .map_err(ApiError::internal)?;
+    plans
+        .create(plan.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::IrrigationPlan,
+            plan.id.0,
+        )
+        .with_details(serde_json::json!({ "field": plan.field, "valve": plan.valve })),
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(plan)))
+}
+
+/// `GET /api/irrigation/plans`
+#[utoipa::path(
+    get,
+    path = "/api/irrigation/plans",
+    tag = "irrigation",
+    params(PlansQuery),
+    responses(
+        (status = 200, description = "Plans visible to the caller", body = Vec<IrrigationPlan>),
+        (status = 400, description = "Invalid field", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<PlansQuery>,
+) -> Result<Json<Vec<IrrigationPlan>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+    let field = query.field.as_deref().map(parse_region).transpose()?;
+
+    let plans = registries
+        .irrigation()
+        .list()
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(
+        plans
+            .into_iter()
+            .filter(|plan| principal.can_access(plan.org_id) && principal.can_see(plan.field))
+            .filter(|plan| field.is_none_or(|field| plan.field == field))
+            .collect(),
+    ))
+}
+
+/// `GET /api/irrigation/plans/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/irrigation/plans/{id}",
+    tag = "irrigation",
+    params(("id" = String, Path, description = "Plan id")),
+    responses(
+        (status = 200, description = "The plan", body = IrrigationPlan),
+        (status = 404, description = "Unknown plan", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<IrrigationPlan>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    Ok(Json(
+        visible_plan(&registries, &principal, PlanId(id)).await?,
+    ))
+}
+
+/// `PATCH /api/irrigation/plans/{id}`
+///
+/// Change a plan and replan it. Changing or removing the valve while a
+/// window has it open closes it first.
+#[utoipa::path(
+    patch,
+    path = "/api/irrigation/plans/{id}",
+    tag = "irrigation",
+    params(("id" = String, Path, description = "Plan id")),
+    request_body = UpdatePlan,
+    responses(
+        (status = 200, description = "The updated plan", body = IrrigationPlan),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an agronomist or admin key", body = ErrorBody),
+        (status = 404, description = "Unknown plan or valve device", body = ErrorBody),
+    )
+)]
+pub async fn update<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(indicators): Extension<IndicatorConfig>,
+    Extension(config): Extension<IrrigationConfig>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<UpdatePlan>,
+) -> Result<Json<IrrigationPlan>, ApiError> {
+    principal.require(Scope::Agronomist)?;
+    let mut plan = visible_plan(&registries, &principal, PlanId(id)).await?;
+    let now = jiff::Timestamp::now();
+
+    let mut changed = Vec::new();
+    if let Some(coefficient) = request.crop_coefficient {
+        plan.crop_coefficient = coefficient;
+        changed.push("crop_coefficient");
+    }
+    if let Some(automatic) = request.automatic {
+        plan.automatic = automatic;
+        changed.push("automatic");
+    }
+    let new_valve = request.valve.filter(|valve| *valve != plan.valve);
+    if let Some(valve) = new_valve {
+        let mut updated = plan.clone();
+        updated.valve = valve;
+        validate(&registries, &principal, &indicators, &updated).await?;
+
+        irrigation::stop(&registries, &mut plan, now)
+            .await
+            .map_err(ApiError::internal)?;
+        plan.valve = valve;
+        changed.push("valve");
+    } else {
+        validate(&registries, &principal, &indicators, &plan).await?;
+    }
+
+    // Replanned from scratch, unless a window is under way.
+    let under_way = plan
+        .window
+        .as_ref()
+        .is_some_and(|window| window.start <= now && now < window.end);
+    if !under_way {
+        plan.window = None;
+    }
+    irrigation::advance(&registries, &config, &mut plan, now)
+        .await
+        .map_err(ApiError::internal)?;
+    registries
+        .irrigation()
+        .update(plan.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Update,
+            EntityKind::IrrigationPlan,
+            plan.id.0,
+        )
+        .with_details(serde_json::json!({ "fields": changed })),
+    )
+    .await?;
+
+    Ok(Json(plan))
+}
+
+/// `DELETE /api/irrigation/plans/{id}`
+///
+/// A valve the plan has open is closed.
+#[utoipa::path(
+    delete,
+    path = "/api/irrigation/plans/{id}",
+    tag = "irrigation",
+    params(("id" = String, Path, description = "Plan id")),
+    responses(
+        (status = 204, description = "Plan deleted"),
+        (status = 403, description = "Not an agronomist or admin key", body = ErrorBody),
+        (status = 404, description = "Unknown plan", body = ErrorBody),
+    )
+)]
+pub async fn delete<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Agronomist)?;
+    let mut plan = visible_plan(&registries, &principal, PlanId(id)).await?;
+
+    irrigation::stop(&registries, &mut plan, jiff::Timestamp::now())
+        .await
+        .map_err(ApiError::internal)?;
+    registries
+        .irrigation()
+        .delete(plan.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Delete,
+            EntityKind::IrrigationPlan,
+            plan.id.0,
+        ),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Extension, Json,
+        extract::{Path, Query, State},
+        http::StatusCode,
+    };
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, DispatcherId, H3Cell, Percentage, ReadingId,
+        SensorId, SensorMetric, SensorReading,
+    };
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::{CreatePlan, PlansQuery, UpdatePlan, create, delete, list, update};
+    use crate::api::ApiError;
+    use crate::auth::Scope;
+    use crate::config::{IndicatorConfig, IrrigationConfig};
+    use crate::derived::{Indicator, IndicatorKind};
+    use crate::fixtures::principal;
+    use crate::irrigation::{IrrigationPlan, Valve};
+    use crate::org::OrgId;
+    use crate::registry::{
+        CommandRegistry, DerivedMetricRegistry, DeviceRegistry, IrrigationRegistry,
+        ReadingRegistry, memory::InMemoryRegistries,
+    };
+
+    const FIELD: &str = "892a1072b5bffff";
+
+    async fn valve_in_field(registries: &InMemoryRegistries, org_id: OrgId) -> Valve {
+        let device_id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id: device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        registries
+            .devices
+            .set_org(device_id, Some(org_id))
+            .await
+            .unwrap();
+        registries
+            .readings
+            .store(SensorReading {
+                id: ReadingId(Ulid::new()),
+                device_id,
+                dispatcher_id: DispatcherId(Ulid::new()),
+                metric: SensorMetric::SoilMoisture {
+                    value: Percentage(20),
+                },
+                location: H3Cell(0x8a2a1072b59ffff),
+                confidence: Percentage(90),
+                timestamp: Timestamp::now(),
+                sensor_id: SensorId(Ulid::new()),
+            })
+            .await
+            .unwrap();
+
+        Valve {
+            device_id,
+            channel: 0,
+        }
+    }
+
+    async fn create_plan(
+        registries: &InMemoryRegistries,
+        org_id: OrgId,
+        request: CreatePlan,
+    ) -> Result<IrrigationPlan, ApiError> {
+        let (status, Json(plan)) = create(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(org_id)),
+            Extension(IndicatorConfig::default()),
+            Extension(IrrigationConfig::default()),
+            Json(request),
+        )
+        .await?;
+        assert_eq!(status, StatusCode::CREATED);
+        Ok(plan)
+    }
+
+    #[tokio::test]
+    async fn dry_fields_get_a_window_on_creation() {
+        let registries = InMemoryRegistries::default();
+        let org_id = OrgId(Ulid::new());
+        let valve = valve_in_field(&registries, org_id).await;
+        registries
+            .derived_metrics
+            .upsert(vec![Indicator {
+                field: H3Cell(0x892a1072b5bffff),
+                kind: IndicatorKind::SoilMoistureDeficit,
+                day: crate::rollup::Granularity::Day.bucket_start(Timestamp::now()),
+                value: 15.0,
+                computed_at: Timestamp::now(),
+            }])
+            .await
+            .unwrap();
+
+        let plan = create_plan(
+            &registries,
+            org_id,
+            CreatePlan {
+                field: FIELD.to_owned(),
+                crop_coefficient: 0.9,
+                valve: Some(valve),
+                automatic: true,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(plan.org_id, Some(org_id));
+        assert!(plan.window.is_some());
+        assert!(plan.planned_at.is_some());
+
+        // One plan per field.
+        let again = create_plan(
+            &registries,
+            org_id,
+            CreatePlan {
+                field: FIELD.to_owned(),
+                crop_coefficient: 0.5,
+                valve: None,
+                automatic: false,
+            },
+        )
+        .await;
+        assert!(matches!(again, Err(ApiError::Conflict(_))));
+
+        let Json(listed) = list(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(org_id)),
+            Query(PlansQuery {
+                field: Some(FIELD.to_owned()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listed, std::slice::from_ref(&plan));
+        let Json(others) = list(
+            State(registries),
+            principal(Scope::Admin, Some(OrgId(Ulid::new()))),
+            Query(PlansQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert!(others.is_empty());
+    }
+
+    #[tokio::test]
+    async fn plans_are_validated() {
+        let registries = InMemoryRegistries::default();
+        let org_id = OrgId(Ulid::new());
+        let valve = valve_in_field(&registries, org_id).await;
+
+        for (crop_coefficient, valve, automatic) in [(0.0, Some(valve), false), (0.8, None, true)] {
+            let result = create_plan(
+                &registries,
+                org_id,
+                CreatePlan {
+                    field: FIELD.to_owned(),
+                    crop_coefficient,
+                    valve,
+                    automatic,
+                },
+            )
+            .await;
+            assert!(matches!(result, Err(ApiError::BadRequest(_))));
+        }
+
+        // Another organization's device can't be its valve.
+        let theirs = valve_in_field(&registries, OrgId(Ulid::new())).await;
+        let result = create_plan(
+            &registries,
+            org_id,
+            CreatePlan {
+                field: FIELD.to_owned(),
+                crop_coefficient: 0.8,
+                valve: Some(theirs),
+                automatic: false,
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn open_valves_are_closed_when_their_plan_goes() {
+        let registries = InMemoryRegistries::default();
+        let org_id = OrgId(Ulid::new());
+        let valve = valve_in_field(&registries, org_id).await;
+        let plan = create_plan(
+            &registries,
+            org_id,
+            CreatePlan {
+                field: FIELD.to_owned(),
+                crop_coefficient: 1.0,
+                valve: Some(valve),
+                automatic: false,
+            },
+        )
+        .await
+        .unwrap();
+
+        let Json(updated) = update(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(org_id)),
+            Extension(IndicatorConfig::default()),
+            Extension(IrrigationConfig::default()),
+            Path(plan.id.0),
+            Json(UpdatePlan {
+                crop_coefficient: Some(1.1),
+                ..UpdatePlan::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.crop_coefficient, 1.1);
+        assert_eq!(updated.valve, Some(valve));
+
+        // As if a window had opened the valve.
+        let now = Timestamp::now();
+        let mut open = updated.clone();
+        open.window = Some(crate::irrigation::IrrigationWindow {
+            start: now,
+            end: now + jiff::SignedDuration::from_hours(1),
+            water_mm: 5.0,
+            opened_by: Some(ersha_core::CommandId(Ulid::new())),
+            closed_by: None,
+        });
+        registries.irrigation.update(open).await.unwrap();
+
+        let status = delete(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(org_id)),
+            Path(plan.id.0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(registries.irrigation.get(plan.id).await.unwrap(), None);
+
+        let commands = registries.commands.list(valve.device_id, 10).await.unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            commands[0].kind,
+            ersha_core::CommandKind::Actuate {
+                channel: 0,
+                on: false
+            }
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use super::{ApiError, ErrorBody, record_audit};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{ApiKey, ApiKeyId, Principal, Scope};
+use crate::org::OrgId;
+use crate::registry::{ApiKeyRegistry, OrgRegistry, Registries};
+use crate::user::UserId;
+
+/// An API key as returned by the API. The secret hash is never exposed.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiKeyInfo {
+    pub id: ApiKeyId,
+    pub name: String,
+    pub scope: Scope,
+    /// Absent for platform-wide keys
+    pub org_id: Option<OrgId>,
+    /// The user a session key was issued to
+    pub user_id: Option<UserId>,
+    pub created_at: jiff::Timestamp,
+    pub expires_at: Option<jiff::Timestamp>,
+    pub revoked_at: Option<jiff::Timestamp>,
+}
+
+impl From<ApiKey> for ApiKeyInfo {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            scope: key.scope,
+            org_id: key.org_id,
+            user_id: key.user_id,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            revoked_at: key.revoked_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateApiKey {
+    pub name: String,
+    pub scope: Scope,
+    /// Organization the key acts for. Defaults to the caller's; only
+    /// platform-wide keys may pick another one or leave it empty.
+    #[serde(default)]
+    pub org_id: Option<OrgId>,
+}
+
+/// A newly created key. `token` is shown only once.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKeyInfo,
+    pub token: String,
+}
+
+/// `GET /api/keys`
+#[utoipa::path(
+    get,
+    path = "/api/keys",
+    tag = "keys",
+    responses(
+        (status = 200, description = "API keys of the caller's organization, or all of them for platform-wide keys", body = Vec<ApiKeyInfo>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<ApiKeyInfo>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let keys = registries
+        .api_keys()
+        .list()
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(
+        keys.into_iter()
+            .filter(|key| principal.can_access(key.org_id))
+            .map(ApiKeyInfo::from)
+            .collect(),
+    ))
+}
+
+/// `POST /api/keys`
+#[utoipa::path(
+    post,
+    path = "/api/keys",
+    tag = "keys",
+    request_body = CreateApiKey,
+    responses(
+        (status = 201, description = "Key created; the token is shown only once", body = CreatedApiKey),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key, a caller limited to fields, or a key for another organization", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<CreateApiKey>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), ApiError> {
+    principal.require(Scope::Admin)?;
+    // Keys aren't limited to fields, so a caller that is can't mint one.
+    if principal.fields.is_some() {
+        return Err(ApiError::Forbidden);
+    }
+
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::BadRequest("name must not be empty".to_owned()));
+    }
+
+    let org_id = match principal.org_id {
+        Some(own) if request.org_id.is_some_and(|org_id| org_id != own) => {
+            return Err(ApiError::Forbidden);
+        }
+        Some(own) => Some(own),
+        None => request.org_id,
+    };
+    if let Some(org_id) = org_id {
+        registries
+            .orgs()
+            .get(org_id)
+            .await
+            .map_err(ApiError::registry)?
+            .ok_or_else(|| ApiError::BadRequest("unknown organization".to_owned()))?;
+    }
+
+    let (mut key, token) = ApiKey::generate(name, request.scope);
+    key.org_id = org_id;
+    registries
+        .api_keys()
+        .create(key.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::ApiKey,
+            key.id.0,
+        )
+        .with_details(serde_json::json!({ "scope": key.scope, "org_id": key.org_id })),
+    )
+    .await?;
+
+    tracing::info!(key_id = ?key.id, scope = ?key.scope, org_id = ?key.org_id, created_by = ?principal.key_id, "API key created");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKey {
+            key: key.into(),
+            token,
+        }),
+    ))
+}
+
+/// `DELETE /api/keys/{id}`
+#[utoipa::path(
+    delete,
+    path = "/api/keys/{id}",
+    tag = "keys",
+    params(("id" = String, Path, description = "API key id")),
+    responses(
+        (status = 204, description = "Key revoked"),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown key", body = ErrorBody),
+    )
+)]
+pub async fn revoke<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let id = ApiKeyId(id);
+    let key = registries
+        .api_keys()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    principal.check_access(key.org_id)?;
+
+    registries
+        .api_keys()
+        .revoke(id, jiff::Timestamp::now())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(&principal, AuditAction::Revoke, EntityKind::ApiKey, id.0),
+    )
+    .await?;
+
+    tracing::info!(key_id = ?id, revoked_by = ?principal.key_id, "API key revoked");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{Extension, Json, extract::State, http::StatusCode};
+    use ersha_core::H3Cell;
+
+    use super::{CreateApiKey, create};
+    use crate::api::ApiError;
+    use crate::auth::{Principal, Scope};
+    use crate::fixtures::admin;
+    use crate::registry::memory::InMemoryRegistries;
+
+    fn request() -> Json<CreateApiKey> {
+        Json(CreateApiKey {
+            name: "ci".to_owned(),
+            scope: Scope::Admin,
+            org_id: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn admins_limited_to_fields_cannot_create_keys() {
+        let registries = InMemoryRegistries::default();
+
+        let limited = Extension(Principal {
+            fields: Some(Arc::from([H3Cell(0x892a1072b5bffff)])),
+            ..admin().0
+        });
+        assert!(matches!(
+            create(State(registries.clone()), limited, request()).await,
+            Err(ApiError::Forbidden)
+        ));
+
+        let (status, _) = create(State(registries), admin(), request()).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+    }
+}
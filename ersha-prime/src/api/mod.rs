@@ -0,0 +1,834 @@
+mod admin;
+mod aggregates;
+mod audit;
+mod backfill;
+mod commands;
+mod contacts;
+mod corrections;
+mod dead_letters;
+mod devices;
+mod dispatchers;
+mod fields;
+mod firmware;
+mod fleet;
+mod geojson;
+mod groups;
+mod heatmap;
+mod irrigation;
+mod keys;
+mod openapi;
+mod orgs;
+mod quality;
+mod quarantine;
+mod readings;
+mod regions;
+mod retention;
+mod series;
+mod statuses;
+mod stream;
+mod summary;
+mod tags;
+mod users;
+mod validation_rules;
+mod webhooks;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use axum::{
+    Extension, Json, Router,
+    extract::{DefaultBodyLimit, FromRequestParts, Query},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header, request::Parts},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+};
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
+use tracing::error;
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use ersha_core::query::PageToken;
+use ersha_core::{Device, DeviceId, Dispatcher, DispatcherId, H3Cell};
+
+use crate::audit::AuditEntry;
+use crate::auth::{self, Principal};
+use crate::backfill::Backfills;
+use crate::backup::Backups;
+use crate::config::{
+    AuthConfig, HealthConfig, IndicatorConfig, IrrigationConfig, PaginationConfig, QualityConfig,
+    UserConfig,
+};
+use crate::events::EventBus;
+use crate::firmware::Firmware;
+use crate::forecast::Forecaster;
+use crate::quota::IngestQuotas;
+use crate::ratelimit::{self, KeyRateLimiter};
+use crate::registry::{
+    AuditRegistry, DeviceRegistry, DispatcherRegistry, Registries, RegistryError,
+    filter::{Cursor, DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortOrder},
+};
+use crate::tuning::Tuning;
+
+pub use ersha_core::query::Order;
+pub use openapi::ApiDoc;
+
+/// Default page size when a request doesn't set `limit`.
+pub const DEFAULT_LIMIT: usize = 100;
+/// Upper bound on `limit`.
+pub const MAX_LIMIT: usize = 1000;
+
+/// Page sizes in effect, see [`set_page_limits`].
+static DEFAULT_PAGE_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_LIMIT);
+static MAX_PAGE_LIMIT: AtomicUsize = AtomicUsize::new(MAX_LIMIT);
+
+/// Change the page sizes of every list endpoint.
+pub fn set_page_limits(pagination: PaginationConfig) {
+    DEFAULT_PAGE_LIMIT.store(pagination.default_limit, Ordering::Relaxed);
+    MAX_PAGE_LIMIT.store(pagination.max_limit, Ordering::Relaxed);
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("missing or invalid API key")]
+    Unauthorized,
+    #[error("API key lacks the required scope")]
+    Forbidden,
+    #[error("not found")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    /// Registering an id that is taken; carries what is registered under it
+    #[error("already registered")]
+    AlreadyRegistered(serde_json::Value),
+    #[error("changed since it was read")]
+    PreconditionFailed,
+    #[error("rate limit exceeded")]
+    RateLimited { retry_after: Duration },
+    #[error("request timed out")]
+    Timeout,
+    #[error("internal error")]
+    Internal,
+}
+
+impl ApiError {
+    /// Log a backend error and hide its details from the client.
+    pub fn internal(error: impl std::error::Error) -> Self {
+        error!(error = %error, "API request failed");
+        ApiError::Internal
+    }
+
+    /// Translate a registry error: missing items are 404s, clashes 409s,
+    /// rejected input 400s, and backend failures are logged as internal.
+    pub fn registry(error: impl Into<RegistryError>) -> Self {
+        match error.into() {
+            RegistryError::NotFound => ApiError::NotFound,
+            RegistryError::Conflict(message) => ApiError::Conflict(message),
+            RegistryError::InvalidInput(message) => ApiError::BadRequest(message),
+            backend @ RegistryError::Backend(_) => ApiError::internal(backend),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) | ApiError::AlreadyRegistered(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Timeout => StatusCode::REQUEST_TIMEOUT,
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable identifier clients can match on instead of the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden => "forbidden",
+            ApiError::NotFound => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::AlreadyRegistered(_) => "already_registered",
+            ApiError::PreconditionFailed => "precondition_failed",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::Timeout => "timeout",
+            ApiError::Internal => "internal",
+        }
+    }
+}
+
+/// Content type of error responses.
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// An RFC 7807 problem details object, the body of every error response.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    /// URI naming the kind of problem, `urn:ersha:problem:<code>`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Short summary of the kind of problem, the status's reason phrase
+    pub title: String,
+    pub status: u16,
+    /// What went wrong with this request
+    pub detail: String,
+    /// Machine-readable error code, e.g. `not_found`
+    pub code: String,
+    /// The entity already registered under the requested id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub existing: Option<serde_json::Value>,
+}
+
+impl From<&ApiError> for ErrorBody {
+    fn from(error: &ApiError) -> Self {
+        let status = error.status();
+        Self {
+            kind: format!("urn:ersha:problem:{}", error.code()),
+            title: status.canonical_reason().unwrap_or_default().to_owned(),
+            status: status.as_u16(),
+            detail: error.to_string(),
+            code: error.code().to_owned(),
+            existing: match error {
+                ApiError::AlreadyRegistered(existing) => Some(existing.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Answer a registration whose id is taken. The caller only learns what is
+/// registered under it if they may see it.
+fn already_registered<T: Serialize>(visible: Result<T, ApiError>, what: &str) -> ApiError {
+    match visible.map(|existing| serde_json::to_value(existing)) {
+        Ok(Ok(existing)) => ApiError::AlreadyRegistered(existing),
+        Ok(Err(e)) => ApiError::internal(e),
+        Err(_) => ApiError::Conflict(format!("{what} already registered")),
+    }
+}
+
+/// Query parameters of registration endpoints.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RegisterQuery {
+    /// Re-provision an entity already registered under the id instead of
+    /// answering 409
+    #[serde(default)]
+    pub upsert: bool,
+}
+
+/// Body of the batch lookup endpoints.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchGetRequest {
+    /// Ids to look up, at most 1000
+    #[schema(value_type = Vec<String>)]
+    pub ids: Vec<Ulid>,
+}
+
+/// The result of a batch lookup, in the order the ids were asked for.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchGet<T> {
+    pub found: Vec<T>,
+    /// Ids with nothing registered under them that the caller may see
+    #[schema(value_type = Vec<String>)]
+    pub missing: Vec<Ulid>,
+}
+
+impl BatchGetRequest {
+    /// The requested ids without repeats, refusing more than a page's worth.
+    fn into_ids(self) -> Result<Vec<Ulid>, ApiError> {
+        if self.ids.len() > MAX_LIMIT {
+            return Err(ApiError::BadRequest(format!(
+                "at most {MAX_LIMIT} ids can be looked up at once"
+            )));
+        }
+
+        let mut seen = HashSet::with_capacity(self.ids.len());
+        Ok(self.ids.into_iter().filter(|id| seen.insert(*id)).collect())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody::from(&self);
+        let mut response = (
+            self.status(),
+            [(header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE)],
+            Json(body),
+        )
+            .into_response();
+
+        if let ApiError::RateLimited { retry_after } = self {
+            // Retry-After takes whole seconds; round up so clients don't retry early.
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+
+        response
+    }
+}
+
+/// Response header carrying the number of items matching a list query.
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+/// A page of results. Pass `next_cursor` as `after` to fetch the next page.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Items matching the query across all pages
+    pub total: usize,
+    /// Opaque token marking where this page ended
+    #[schema(value_type = Option<String>)]
+    pub next_cursor: Option<PageToken>,
+}
+
+impl<T> Page<T> {
+    /// Build a page, emitting a cursor only when the page is full.
+    pub fn new(items: Vec<T>, limit: usize, total: usize, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let next_cursor = if items.len() == limit {
+            items.last().map(|last| PageToken(cursor(last).encode()))
+        } else {
+            None
+        };
+
+        Self {
+            items,
+            total,
+            next_cursor,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Page<T> {
+    fn into_response(self) -> Response {
+        ([(TOTAL_COUNT_HEADER, self.total.to_string())], Json(self)).into_response()
+    }
+}
+
+impl From<Order> for SortOrder {
+    fn from(order: Order) -> Self {
+        match order {
+            Order::Asc => SortOrder::Asc,
+            Order::Desc => SortOrder::Desc,
+        }
+    }
+}
+
+fn page_limit(limit: Option<usize>) -> Result<usize, ApiError> {
+    match limit {
+        None => Ok(DEFAULT_PAGE_LIMIT.load(Ordering::Relaxed)),
+        Some(0) => Err(ApiError::BadRequest("limit must be positive".to_owned())),
+        Some(limit) => Ok(limit.min(MAX_PAGE_LIMIT.load(Ordering::Relaxed))),
+    }
+}
+
+/// Query string extractor answering a malformed query with a problem
+/// response, like every other error, rather than axum's plain text.
+pub struct ApiQuery<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequestParts<S> for ApiQuery<T> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+        Ok(Self(query))
+    }
+}
+
+/// The cursor behind an `after` token handed out as `next_cursor`.
+fn parse_cursor(after: Option<&str>) -> Result<Option<Cursor>, ApiError> {
+    after
+        .map(|token| {
+            Cursor::decode(token)
+                .ok_or_else(|| ApiError::BadRequest(format!("invalid cursor: '{token}'")))
+        })
+        .transpose()
+}
+
+/// Tell a field set to `null` apart from one left out.
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Parse a comma separated query parameter.
+fn parse_list<T>(
+    name: &str,
+    value: Option<&str>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Option<Vec<T>>, ApiError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            parse(item).ok_or_else(|| ApiError::BadRequest(format!("invalid {name}: '{item}'")))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+/// A response with the `ETag` of the revision it shows.
+type Tagged<T> = ([(HeaderName, HeaderValue); 1], Json<T>);
+
+/// Send `body` tagged with the revision it was last changed at.
+fn tagged<T>(updated_at: jiff::Timestamp, body: T) -> Tagged<T> {
+    let etag = format!("\"{}\"", updated_at.as_nanosecond());
+    let etag = HeaderValue::from_str(&etag).expect("digits and quotes are a valid header");
+
+    ([(header::ETAG, etag)], Json(body))
+}
+
+/// The revision named by an `If-Match` header, if one other than `*` is
+/// given.
+fn if_match(headers: &HeaderMap) -> Result<Option<jiff::Timestamp>, ApiError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| ApiError::PreconditionFailed)?
+        .trim();
+    if value == "*" {
+        return Ok(None);
+    }
+
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .and_then(|nanos| nanos.parse::<i128>().ok())
+        .and_then(|nanos| jiff::Timestamp::from_nanosecond(nanos).ok())
+        .map(Some)
+        .ok_or(ApiError::PreconditionFailed)
+}
+
+/// Fetch a device the caller may see. Other organizations' devices, and
+/// devices outside the caller's fields, are reported as not found.
+async fn visible_device<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: DeviceId,
+) -> Result<Device, ApiError> {
+    let devices = registries.devices();
+    let device = devices
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+
+    if principal.org_id.is_some() {
+        let owner = devices.org(id).await.map_err(ApiError::registry)?;
+        principal.check_access(owner)?;
+    }
+    if !principal.can_see(device.location) {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(device)
+}
+
+/// Fetch a dispatcher the caller may see. Other organizations' dispatchers
+/// are reported as not found.
+async fn visible_dispatcher<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: DispatcherId,
+) -> Result<Dispatcher, ApiError> {
+    let dispatchers = registries.dispatchers();
+    let dispatcher = dispatchers
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+
+    if principal.org_id.is_some() {
+        let owner = dispatchers.org(id).await.map_err(ApiError::registry)?;
+        principal.check_access(owner)?;
+    }
+
+    Ok(dispatcher)
+}
+
+/// Narrow a dispatcher id constraint to the caller's organization,
+/// returning `false` when none of the dispatchers are visible.
+///
+/// Readings and statuses belong to the organization of the dispatcher that
+/// uploaded them, so this is what scopes them. Registries treat an empty id
+/// list as unconstrained, so callers must return nothing on `false`.
+async fn scope_dispatchers<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    dispatcher_ids: &mut Option<Vec<DispatcherId>>,
+) -> Result<bool, ApiError> {
+    let Some(org_id) = principal.org_id else {
+        return Ok(true);
+    };
+
+    let dispatchers = registries.dispatchers();
+    let filter = DispatcherFilter::builder().org(org_id).build();
+    let count = dispatchers
+        .count(Some(filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let owned = dispatchers
+        .list(QueryOptions {
+            filter,
+            sort_by: DispatcherSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Offset {
+                offset: 0,
+                limit: count,
+            },
+        })
+        .await
+        .map_err(ApiError::registry)?
+        .into_iter()
+        .map(|dispatcher| dispatcher.id);
+
+    let scoped: Vec<DispatcherId> = match dispatcher_ids.take() {
+        Some(requested) if !requested.is_empty() => {
+            owned.filter(|id| requested.contains(id)).collect()
+        }
+        _ => owned.collect(),
+    };
+
+    let visible = !scoped.is_empty();
+    *dispatcher_ids = Some(scoped);
+
+    Ok(visible)
+}
+
+/// Narrow a `within` constraint to the caller's fields, returning `false`
+/// when none of the requested cells overlap them.
+///
+/// Like [`scope_dispatchers`], an empty list would be unconstrained, so
+/// callers must return nothing on `false`.
+fn scope_fields(principal: &Principal, within: &mut Option<Vec<H3Cell>>) -> bool {
+    let Some(fields) = &principal.fields else {
+        return true;
+    };
+
+    let mut scoped: Vec<H3Cell> = match within.take() {
+        Some(requested) if !requested.is_empty() => {
+            let inside_fields = requested
+                .iter()
+                .filter(|cell| fields.iter().any(|field| cell.is_within(*field)));
+            let inside_requested = fields
+                .iter()
+                .filter(|field| requested.iter().any(|cell| field.is_within(*cell)));
+            inside_fields.chain(inside_requested).copied().collect()
+        }
+        _ => fields.to_vec(),
+    };
+    scoped.sort_by_key(|cell| cell.0);
+    scoped.dedup();
+
+    let visible = !scoped.is_empty();
+    *within = Some(scoped);
+
+    visible
+}
+
+/// Append a change to the audit log.
+async fn record_audit<R: Registries>(registries: &R, entry: AuditEntry) -> Result<(), ApiError> {
+    registries
+        .audit()
+        .record(entry)
+        .await
+        .map_err(ApiError::registry)
+}
+
+/// Routes served under `/api` and `/admin`. Every route except the API docs
+/// and login requires an API key.
+///
+/// While rate limiting is tuned on, each API key may only make so many requests.
+#[allow(clippy::too_many_arguments)]
+pub fn router<R: Registries>(
+    registries: R,
+    events: Arc<dyn EventBus>,
+    health: HealthConfig,
+    indicators: IndicatorConfig,
+    forecaster: Arc<dyn Forecaster>,
+    irrigation: IrrigationConfig,
+    quality: QualityConfig,
+    quotas: IngestQuotas,
+    backfills: Backfills,
+    firmware: Firmware,
+    auth: AuthConfig,
+    users: UserConfig,
+    tuning: Tuning,
+    backups: Backups,
+) -> Router {
+    let max_backfill_bytes = backfills.config().max_body_mb.saturating_mul(1024 * 1024);
+    let max_image_bytes = firmware.config().max_image_mb.saturating_mul(1024 * 1024);
+
+    Router::new()
+        .route("/api/summary", get(summary::summary::<R>))
+        .route("/api/readings", get(readings::list::<R>))
+        .route("/api/readings/heatmap", get(heatmap::heatmap::<R>))
+        .route(
+            "/api/devices/{id}/readings",
+            get(readings::list_for_device::<R>),
+        )
+        .route("/api/stream/readings", get(stream::readings::<R>))
+        .route("/api/statuses", get(statuses::list::<R>))
+        .route(
+            "/api/devices/{id}/status-history",
+            get(statuses::history::<R>),
+        )
+        .route("/api/regions/{h3}/devices", get(regions::devices::<R>))
+        .route("/api/regions/{h3}/readings", get(regions::readings::<R>))
+        .route("/api/fields/{id}/indicators", get(fields::indicators::<R>))
+        .route("/api/fields/{id}/forecast", get(fields::forecast::<R>))
+        .route(
+            "/api/devices",
+            get(devices::list::<R>).post(devices::register::<R>),
+        )
+        .route(
+            "/api/devices/{id}",
+            get(devices::get::<R>).patch(devices::update::<R>),
+        )
+        .route("/api/devices/batch-get", post(devices::batch_get::<R>))
+        .route("/api/devices/{id}/latest", get(devices::latest::<R>))
+        .route("/api/devices/offline", get(devices::offline::<R>))
+        .route("/api/devices/{id}/aggregates", get(aggregates::list::<R>))
+        .route("/api/devices/{id}/series", get(series::series::<R>))
+        .route(
+            "/api/devices/{id}/data-quality",
+            get(quality::data_quality::<R>),
+        )
+        .route(
+            "/api/devices/{id}/commands",
+            get(commands::list::<R>).post(commands::enqueue::<R>),
+        )
+        .route(
+            "/api/devices/{id}/corrections",
+            get(corrections::list::<R>).post(corrections::create::<R>),
+        )
+        .route(
+            "/api/readings/{id}/revisions",
+            get(corrections::revisions::<R>),
+        )
+        .route("/api/devices.geojson", get(geojson::devices::<R>))
+        .route("/api/devices/import", post(fleet::import::<R>))
+        .route("/api/devices/export", get(fleet::export::<R>))
+        .route("/api/devices/{id}/org", put(orgs::assign_device::<R>))
+        .route(
+            "/api/devices/{id}/dispatcher",
+            put(devices::assign_dispatcher::<R>).delete(devices::unassign_dispatcher::<R>),
+        )
+        .route("/api/devices/{id}/suspend", post(devices::suspend::<R>))
+        .route(
+            "/api/devices/{id}/reactivate",
+            post(devices::reactivate::<R>),
+        )
+        .route(
+            "/api/devices/{id}/decommission",
+            post(devices::decommission::<R>),
+        )
+        .route(
+            "/api/groups",
+            get(groups::list::<R>).post(groups::create::<R>),
+        )
+        .route(
+            "/api/groups/{id}",
+            get(groups::get::<R>)
+                .patch(groups::update::<R>)
+                .delete(groups::delete::<R>),
+        )
+        .route("/api/tags", post(tags::assign::<R>))
+        .route(
+            "/api/validation-rules",
+            get(validation_rules::list::<R>).post(validation_rules::create::<R>),
+        )
+        .route(
+            "/api/validation-rules/{id}",
+            get(validation_rules::get::<R>)
+                .patch(validation_rules::update::<R>)
+                .delete(validation_rules::delete::<R>),
+        )
+        .route(
+            "/api/dispatchers",
+            get(dispatchers::list::<R>).post(dispatchers::register::<R>),
+        )
+        .route("/api/dispatchers.geojson", get(geojson::dispatchers::<R>))
+        .route(
+            "/api/dispatchers/batch-get",
+            post(dispatchers::batch_get::<R>),
+        )
+        .route("/api/dispatchers/health", get(dispatchers::health::<R>))
+        .route("/api/dispatchers/{id}", get(dispatchers::get::<R>))
+        .route(
+            "/api/dispatchers/over-quota",
+            get(dispatchers::over_quota::<R>),
+        )
+        .route(
+            "/api/dispatchers/{id}/status",
+            get(dispatchers::status::<R>),
+        )
+        .route(
+            "/api/dispatchers/{id}/secret",
+            post(dispatchers::provision_secret::<R>),
+        )
+        .route(
+            "/api/dispatchers/{id}/org",
+            put(orgs::assign_dispatcher::<R>),
+        )
+        .route(
+            "/api/dispatchers/{id}/suspend",
+            post(dispatchers::suspend::<R>),
+        )
+        .route(
+            "/api/dispatchers/{id}/reactivate",
+            post(dispatchers::reactivate::<R>),
+        )
+        .route(
+            "/api/dispatchers/{id}/approve",
+            post(dispatchers::approve::<R>),
+        )
+        .route("/api/dead-letters", get(dead_letters::list::<R>))
+        .route(
+            "/api/dead-letters/redrive",
+            post(dead_letters::redrive_all::<R>),
+        )
+        .route(
+            "/api/dead-letters/{id}",
+            get(dead_letters::get::<R>).delete(dead_letters::delete::<R>),
+        )
+        .route(
+            "/api/dead-letters/{id}/redrive",
+            post(dead_letters::redrive::<R>),
+        )
+        .route("/api/quarantine", get(quarantine::list::<R>))
+        .route("/api/quarantine/{id}", delete(quarantine::delete::<R>))
+        .route(
+            "/api/quarantine/{id}/release",
+            post(quarantine::release::<R>),
+        )
+        .route(
+            "/api/backfill",
+            get(backfill::list::<R>)
+                .post(backfill::submit::<R>)
+                .layer(DefaultBodyLimit::max(max_backfill_bytes)),
+        )
+        .route("/api/backfill/{id}", get(backfill::get::<R>))
+        .route(
+            "/api/firmware",
+            get(firmware::list::<R>)
+                .post(firmware::upload::<R>)
+                .layer(DefaultBodyLimit::max(max_image_bytes)),
+        )
+        .route("/api/firmware/{id}", get(firmware::get::<R>))
+        .route("/api/firmware/{id}/image", get(firmware::download::<R>))
+        .route(
+            "/api/rollouts",
+            get(firmware::list_rollouts::<R>).post(firmware::create_rollout::<R>),
+        )
+        .route("/api/rollouts/{id}", get(firmware::get_rollout::<R>))
+        .route(
+            "/api/rollouts/{id}/devices",
+            get(firmware::rollout_devices::<R>),
+        )
+        .route("/api/rollouts/{id}/pause", post(firmware::pause::<R>))
+        .route("/api/rollouts/{id}/resume", post(firmware::resume::<R>))
+        .route("/api/rollouts/{id}/cancel", post(firmware::cancel::<R>))
+        .route("/api/auth/me", get(users::me::<R>))
+        .route("/api/auth/logout", post(users::logout::<R>))
+        .route("/api/users", get(users::list::<R>).post(users::create::<R>))
+        .route(
+            "/api/users/{id}",
+            get(users::get::<R>)
+                .patch(users::update::<R>)
+                .delete(users::delete::<R>),
+        )
+        .route("/api/retention/run", post(retention::run::<R>))
+        .route("/api/audit", get(audit::list::<R>))
+        .route("/api/orgs", get(orgs::list::<R>).post(orgs::create::<R>))
+        .route(
+            "/api/orgs/{id}/metadata-schema",
+            get(orgs::metadata_schema::<R>).put(orgs::set_metadata_schema::<R>),
+        )
+        .route("/api/keys", get(keys::list::<R>).post(keys::create::<R>))
+        .route("/api/keys/{id}", delete(keys::revoke::<R>))
+        .route(
+            "/api/webhooks",
+            get(webhooks::list::<R>).post(webhooks::create::<R>),
+        )
+        .route("/api/webhooks/{id}", delete(webhooks::delete::<R>))
+        .route(
+            "/api/irrigation/plans",
+            get(irrigation::list::<R>).post(irrigation::create::<R>),
+        )
+        .route(
+            "/api/irrigation/plans/{id}",
+            get(irrigation::get::<R>)
+                .patch(irrigation::update::<R>)
+                .delete(irrigation::delete::<R>),
+        )
+        .route(
+            "/api/webhooks/{id}/deliveries",
+            get(webhooks::deliveries::<R>),
+        )
+        .route(
+            "/api/contacts",
+            get(contacts::list::<R>).post(contacts::create::<R>),
+        )
+        .route(
+            "/api/contacts/{id}",
+            get(contacts::get::<R>)
+                .patch(contacts::update::<R>)
+                .delete(contacts::delete::<R>),
+        )
+        .route(
+            "/api/contacts/{id}/notifications",
+            get(contacts::notifications::<R>),
+        )
+        .route("/admin/config", get(admin::config))
+        .route("/admin/reload", post(admin::reload))
+        .route("/admin/backup", post(admin::backup))
+        .route("/admin/backups", get(admin::backups))
+        .route_layer(middleware::from_fn_with_state(
+            KeyRateLimiter::new(&tuning),
+            ratelimit::limit,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            registries.clone(),
+            auth::authenticate::<R>,
+        ))
+        .route("/api/auth/login", post(users::login::<R>))
+        .route("/api/auth/oidc", post(users::oidc::<R>))
+        .route("/api/openapi.json", get(openapi::spec))
+        .route("/api/docs", get(openapi::docs))
+        .layer(Extension(events))
+        .layer(Extension(health))
+        .layer(Extension(indicators))
+        .layer(Extension(forecaster))
+        .layer(Extension(irrigation))
+        .layer(Extension(quality))
+        .layer(Extension(quotas))
+        .layer(Extension(backfills))
+        .layer(Extension(firmware))
+        .layer(Extension(auth))
+        .layer(Extension(users))
+        .layer(Extension(reqwest::Client::new()))
+        .layer(Extension(tuning))
+        .layer(Extension(backups))
+        .with_state(registries)
+}
@@ -0,0 +1,284 @@
+use axum::{Json, response::Html};
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+use super::{
+    admin, aggregates, audit, backfill, commands, contacts, corrections, dead_letters, devices,
+    dispatchers, fields, firmware, fleet, geojson, groups, heatmap, irrigation, keys, orgs,
+    quality, quarantine, readings, regions, retention, series, statuses, stream, summary, tags,
+    users, validation_rules, webhooks,
+};
+use crate::auth::API_KEY_HEADER;
+
+/// Swagger UI page served at `/api/docs`.
+const SWAGGER_UI_HTML: &str = include_str!("../../assets/swagger.html");
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "ersha-prime API"),
+    paths(
+        summary::summary,
+        readings::list,
+        heatmap::heatmap,
+        readings::list_for_device,
+        stream::readings,
+        statuses::list,
+        statuses::history,
+        regions::devices,
+        regions::readings,
+        fields::indicators,
+        fields::forecast,
+        devices::list,
+        devices::register,
+        devices::get,
+        devices::batch_get,
+        devices::update,
+        fleet::import,
+        fleet::export,
+        geojson::devices,
+        devices::latest,
+        devices::offline,
+        aggregates::list,
+        series::series,
+        quality::data_quality,
+        commands::enqueue,
+        commands::list,
+        corrections::create,
+        corrections::list,
+        corrections::revisions,
+        dead_letters::list,
+        dead_letters::get,
+        dead_letters::redrive,
+        dead_letters::redrive_all,
+        dead_letters::delete,
+        quarantine::list,
+        quarantine::release,
+        quarantine::delete,
+        backfill::submit,
+        backfill::list,
+        backfill::get,
+        firmware::upload,
+        firmware::list,
+        firmware::get,
+        firmware::download,
+        firmware::create_rollout,
+        firmware::list_rollouts,
+        firmware::get_rollout,
+        firmware::rollout_devices,
+        firmware::pause,
+        firmware::resume,
+        firmware::cancel,
+        devices::suspend,
+        devices::reactivate,
+        devices::decommission,
+        devices::assign_dispatcher,
+        devices::unassign_dispatcher,
+        dispatchers::list,
+        dispatchers::register,
+        dispatchers::batch_get,
+        dispatchers::health,
+        dispatchers::over_quota,
+        geojson::dispatchers,
+        dispatchers::get,
+        dispatchers::status,
+        dispatchers::provision_secret,
+        dispatchers::suspend,
+        dispatchers::reactivate,
+        dispatchers::approve,
+        groups::create,
+        groups::list,
+        groups::get,
+        groups::update,
+        groups::delete,
+        tags::assign,
+        validation_rules::create,
+        validation_rules::list,
+        validation_rules::get,
+        validation_rules::update,
+        validation_rules::delete,
+        orgs::create,
+        orgs::list,
+        orgs::assign_dispatcher,
+        orgs::assign_device,
+        orgs::metadata_schema,
+        orgs::set_metadata_schema,
+        keys::list,
+        keys::create,
+        keys::revoke,
+        users::create,
+        users::list,
+        users::get,
+        users::update,
+        users::delete,
+        users::login,
+        users::oidc,
+        users::me,
+        users::logout,
+        webhooks::create,
+        webhooks::list,
+        webhooks::delete,
+        webhooks::deliveries,
+        contacts::create,
+        contacts::list,
+        contacts::get,
+        contacts::update,
+        contacts::delete,
+        contacts::notifications,
+        irrigation::create,
+        irrigation::list,
+        irrigation::get,
+        irrigation::update,
+        irrigation::delete,
+        retention::run,
+        audit::list,
+        admin::config,
+        admin::reload,
+        admin::backup,
+        admin::backups,
+    ),
+    modifiers(&ApiKeyAuth),
+    security(("bearer" = []), ("api_key" = [])),
+    tags(
+        (name = "summary", description = "Counts across the fleet for the ops dashboard"),
+        (name = "readings", description = "Sensor readings ingested from dispatchers"),
+        (name = "statuses", description = "Device status reports"),
+        (name = "regions", description = "Devices and readings within an H3 cell"),
+        (name = "fields", description = "Agronomic indicators derived per field"),
+        (name = "devices", description = "Device state and lifecycle"),
+        (name = "corrections", description = "Retroactive calibration corrections of readings"),
+        (name = "dead-letters", description = "Batch items refused on upload, kept for re-driving"),
+        (name = "quarantine", description = "Uploaded items naming unknown devices or sensors, held for review"),
+        (name = "backfill", description = "Uploads of historical data recovered after outages"),
+        (name = "firmware", description = "Firmware images and their rollout to devices"),
+        (name = "dispatchers", description = "Dispatcher provisioning, lifecycle and health"),
+        (name = "groups", description = "Tags on devices and dispatchers, and named groups of them"),
+        (name = "validation", description = "Plausible ranges of readings, by metric kind and region"),
+        (name = "orgs", description = "Organizations and what they own"),
+        (name = "keys", description = "API key management"),
+        (name = "users", description = "People who log in, their roles and fields"),
+        (name = "auth", description = "Logging users in and out"),
+        (name = "webhooks", description = "Event subscriptions and their deliveries"),
+        (name = "contacts", description = "People notified of events by SMS or Telegram"),
+        (name = "irrigation", description = "Irrigation plans and their recommended windows"),
+        (name = "retention", description = "Purging of expired data"),
+        (name = "audit", description = "Who changed what in the registries"),
+        (name = "admin", description = "Runtime configuration and backups of prime"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the two ways of presenting an API key.
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+    }
+}
+
+/// `GET /api/openapi.json`
+pub async fn spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// `GET /api/docs`
+pub async fn docs() -> Html<&'static str> {
+    Html(SWAGGER_UI_HTML)
+}
+
+#[cfg(test)]
+mod tests {
+    use utoipa::OpenApi;
+
+    use super::ApiDoc;
+
+    #[test]
+    fn spec_covers_routes_and_schemas() {
+        let spec = ApiDoc::openapi();
+
+        for path in [
+            "/api/readings",
+            "/api/readings/heatmap",
+            "/api/devices",
+            "/api/dispatchers",
+            "/api/statuses",
+            "/api/devices/{id}",
+            "/api/devices/{id}/readings",
+            "/api/devices/{id}/decommission",
+            "/api/devices/{id}/aggregates",
+            "/api/devices/{id}/series",
+            "/api/devices/{id}/data-quality",
+            "/api/devices/{id}/commands",
+            "/api/devices/{id}/corrections",
+            "/api/readings/{id}/revisions",
+            "/api/dead-letters",
+            "/api/dead-letters/{id}",
+            "/api/dead-letters/{id}/redrive",
+            "/api/dead-letters/redrive",
+            "/api/quarantine",
+            "/api/quarantine/{id}/release",
+            "/api/backfill",
+            "/api/backfill/{id}",
+            "/api/firmware",
+            "/api/firmware/{id}/image",
+            "/api/rollouts",
+            "/api/rollouts/{id}/devices",
+            "/api/devices/{id}/dispatcher",
+            "/api/devices/offline",
+            "/api/devices/import",
+            "/api/devices/export",
+            "/api/devices.geojson",
+            "/api/dispatchers.geojson",
+            "/api/dispatchers/over-quota",
+            "/api/regions/{h3}/readings",
+            "/api/fields/{id}/indicators",
+            "/api/fields/{id}/forecast",
+            "/api/dispatchers/{id}",
+            "/api/dispatchers/{id}/secret",
+            "/api/groups",
+            "/api/groups/{id}",
+            "/api/tags",
+            "/api/validation-rules",
+            "/api/validation-rules/{id}",
+            "/api/keys/{id}",
+            "/api/users",
+            "/api/users/{id}",
+            "/api/auth/login",
+            "/api/auth/oidc",
+            "/api/auth/me",
+            "/api/auth/logout",
+            "/api/orgs",
+            "/api/dispatchers/{id}/org",
+            "/api/dispatchers/{id}/approve",
+            "/api/webhooks/{id}/deliveries",
+            "/api/contacts",
+            "/api/contacts/{id}",
+            "/api/contacts/{id}/notifications",
+            "/api/irrigation/plans",
+            "/api/irrigation/plans/{id}",
+            "/api/retention/run",
+            "/api/audit",
+            "/admin/config",
+            "/admin/reload",
+            "/admin/backup",
+            "/admin/backups",
+        ] {
+            assert!(spec.paths.paths.contains_key(path), "missing {path}");
+        }
+
+        let schemas = spec.components.unwrap().schemas;
+        for schema in ["SensorReading", "SensorMetric", "Device", "ApiKeyInfo"] {
+            assert!(schemas.contains_key(schema), "missing {schema}");
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use ersha_core::{DeviceId, DispatcherId};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use super::{ApiError, ErrorBody, record_audit};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::metadata;
+use crate::org::{Org, OrgId};
+use crate::registry::{DeviceRegistry, DispatcherRegistry, OrgRegistry, Registries};
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateOrg {
+    pub name: String,
+}
+
+/// `POST /api/orgs`
+#[utoipa::path(
+    post,
+    path = "/api/orgs",
+    tag = "orgs",
+    request_body = CreateOrg,
+    responses(
+        (status = 201, description = "Organization created", body = Org),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<CreateOrg>,
+) -> Result<(StatusCode, Json<Org>), ApiError> {
+    principal.require_platform(Scope::Admin)?;
+
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::BadRequest("name must not be empty".to_owned()));
+    }
+
+    let org = Org::new(name);
+    registries
+        .orgs()
+        .create(org.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(&principal, AuditAction::Create, EntityKind::Org, org.id.0),
+    )
+    .await?;
+
+    tracing::info!(org_id = ?org.id, name = %org.name, created_by = ?principal.key_id, "organization created");
+
+    Ok((StatusCode::CREATED, Json(org)))
+}
+
+/// `GET /api/orgs`
+#[utoipa::path(
+    get,
+    path = "/api/orgs",
+    tag = "orgs",
+    responses(
+        (status = 200, description = "The caller's organization, or all of them for platform-wide keys", body = Vec<Org>),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<Org>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let orgs = registries.orgs().list().await.map_err(ApiError::registry)?;
+
+    Ok(Json(
+        orgs.into_iter()
+            .filter(|org| principal.can_access(Some(org.id)))
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AssignOrg {
+    /// The new owner, or `null` to release it from any organization
+    pub org_id: Option<OrgId>,
+}
+
+async fn check_org<R: Registries>(registries: &R, org_id: Option<OrgId>) -> Result<(), ApiError> {
+    if let Some(org_id) = org_id {
+        registries
+            .orgs()
+            .get(org_id)
+            .await
+            .map_err(ApiError::registry)?
+            .ok_or_else(|| ApiError::BadRequest("unknown organization".to_owned()))?;
+    }
+
+    Ok(())
+}
+
+/// `PUT /api/dispatchers/{id}/org`
+///
+/// Move a dispatcher to another organization. Readings and statuses it
+/// uploaded before are visible to the new owner.
+#[utoipa::path(
+    put,
+    path = "/api/dispatchers/{id}/org",
+    tag = "orgs",
+    params(("id" = String, Path, description = "Dispatcher id")),
+    request_body = AssignOrg,
+    responses(
+        (status = 204, description = "Dispatcher assigned"),
+        (status = 400, description = "Unknown organization", body = ErrorBody),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+        (status = 404, description = "Unknown dispatcher", body = ErrorBody),
+    )
+)]
+pub async fn assign_dispatcher<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<AssignOrg>,
+) -> Result<StatusCode, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+    check_org(&registries, request.org_id).await?;
+
+    let dispatcher_id = DispatcherId(id);
+    let dispatchers = registries.dispatchers();
+    dispatchers
+        .get(dispatcher_id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    dispatchers
+        .set_org(dispatcher_id, request.org_id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::AssignOrg,
+            EntityKind::Dispatcher,
+            dispatcher_id.0,
+        )
+        .with_details(serde_json::json!({ "org_id": request.org_id })),
+    )
+    .await?;
+
+    tracing::info!(?dispatcher_id, org_id = ?request.org_id, assigned_by = ?principal.key_id, "dispatcher assigned");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `PUT /api/devices/{id}/org`
+#[utoipa::path(
+    put,
+    path = "/api/devices/{id}/org",
+    tag = "orgs",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = AssignOrg,
+    responses(
+        (status = 204, description = "Device assigned"),
+        (status = 400, description = "Unknown organization", body = ErrorBody),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn assign_device<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<AssignOrg>,
+) -> Result<StatusCode, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+    check_org(&registries, request.org_id).await?;
+
+    let device_id = DeviceId(id);
+    let devices = registries.devices();
+    devices
+        .get(device_id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    devices
+        .set_org(device_id, request.org_id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::AssignOrg,
+            EntityKind::Device,
+            device_id.0,
+        )
+        .with_details(serde_json::json!({ "org_id": request.org_id })),
+    )
+    .await?;
+
+    tracing::info!(?device_id, org_id = ?request.org_id, assigned_by = ?principal.key_id, "device assigned");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// An organization's device metadata schema.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MetadataSchema {
+    /// JSON Schema device metadata must match, or `null` for none
+    #[schema(value_type = Option<Object>)]
+    pub schema: Option<serde_json::Value>,
+}
+
+/// `GET /api/orgs/{id}/metadata-schema`
+#[utoipa::path(
+    get,
+    path = "/api/orgs/{id}/metadata-schema",
+    tag = "orgs",
+    params(("id" = String, Path, description = "Organization id")),
+    responses(
+        (status = 200, description = "The organization's metadata schema", body = MetadataSchema),
+        (status = 404, description = "Unknown organization", body = ErrorBody),
+    )
+)]
+pub async fn metadata_schema<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<MetadataSchema>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let org_id = OrgId(id);
+    principal.check_access(Some(org_id))?;
+    let orgs = registries.orgs();
+    orgs.get(org_id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    let schema = orgs
+        .metadata_schema(org_id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(MetadataSchema { schema }))
+}
+
+/// `PUT /api/orgs/{id}/metadata-schema`
+///
+/// Set the JSON Schema the organization's device metadata must match from
+/// now on. Metadata already stored isn't checked again.
+#[utoipa::path(
+    put,
+    path = "/api/orgs/{id}/metadata-schema",
+    tag = "orgs",
+    params(("id" = String, Path, description = "Organization id")),
+    request_body = MetadataSchema,
+    responses(
+        (status = 200, description = "Schema set", body = MetadataSchema),
+        (status = 400, description = "Not a valid JSON Schema", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown organization", body = ErrorBody),
+    )
+)]
+pub async fn set_metadata_schema<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<MetadataSchema>,
+) -> Result<Json<MetadataSchema>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let org_id = OrgId(id);
+    principal.check_access(Some(org_id))?;
+    if let Some(schema) = &request.schema {
+        metadata::check_schema(schema).map_err(|error| ApiError::BadRequest(error.to_string()))?;
+    }
+    registries
+        .orgs()
+        .set_metadata_schema(org_id, request.schema.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(&principal, AuditAction::Update, EntityKind::Org, org_id.0)
+            .with_details(serde_json::json!({ "fields": ["metadata_schema"] })),
+    )
+    .await?;
+
+    tracing::info!(?org_id, updated_by = ?principal.key_id, "metadata schema set");
+
+    Ok(Json(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json,
+        extract::{Path, Query, State},
+    };
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, Dispatcher, DispatcherId, DispatcherState,
+        H3Cell, Percentage, ReadingId, SensorId, SensorMetric, SensorReading,
+    };
+    use ulid::Ulid;
+
+    use super::{AssignOrg, CreateOrg, assign_device, assign_dispatcher, create};
+    use crate::api::{ApiError, ApiQuery, devices, readings};
+    use crate::auth::Scope;
+    use crate::fixtures::{admin, principal, reader};
+    use crate::org::OrgId;
+    use crate::registry::{
+        DeviceRegistry, DispatcherRegistry, ReadingRegistry, memory::InMemoryRegistries,
+    };
+
+    async fn org(registries: &InMemoryRegistries, name: &str) -> OrgId {
+        let (_, Json(org)) = create(
+            State(registries.clone()),
+            admin(),
+            Json(CreateOrg {
+                name: name.to_owned(),
+            }),
+        )
+        .await
+        .unwrap();
+        org.id
+    }
+
+    /// A device reporting through its own dispatcher, both owned by `org_id`.
+    async fn site(registries: &InMemoryRegistries, org_id: OrgId) -> DeviceId {
+        let device_id = DeviceId(Ulid::new());
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let location = H3Cell(0x8a2a1072b59ffff);
+        let now = jiff::Timestamp::now();
+
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: dispatcher_id,
+                location,
+                state: DispatcherState::Active,
+                provisioned_at: now,
+            })
+            .await
+            .unwrap();
+        registries
+            .devices
+            .register(Device {
+                id: device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location,
+                manufacturer: None,
+                provisioned_at: now,
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        registries
+            .readings
+            .store(SensorReading {
+                id: ReadingId(Ulid::new()),
+                device_id,
+                dispatcher_id,
+                metric: SensorMetric::SoilMoisture {
+                    value: Percentage(40),
+                },
+                location,
+                confidence: Percentage(95),
+                timestamp: now,
+                sensor_id: SensorId(Ulid::new()),
+            })
+            .await
+            .unwrap();
+
+        let admin = || admin();
+        let assign = || {
+            Json(AssignOrg {
+                org_id: Some(org_id),
+            })
+        };
+        assign_dispatcher(
+            State(registries.clone()),
+            admin(),
+            Path(dispatcher_id.0),
+            assign(),
+        )
+        .await
+        .unwrap();
+        assign_device(
+            State(registries.clone()),
+            admin(),
+            Path(device_id.0),
+            assign(),
+        )
+        .await
+        .unwrap();
+
+        device_id
+    }
+
+    #[tokio::test]
+    async fn organizations_only_see_their_own_data() {
+        let registries = InMemoryRegistries::default();
+        let ours = org(&registries, "Adama cooperative").await;
+        let theirs = org(&registries, "Bahir Dar cooperative").await;
+        let our_device = site(&registries, ours).await;
+        let their_device = site(&registries, theirs).await;
+        let member = || principal(Scope::ReadOnly, Some(ours));
+
+        let page = devices::list(
+            State(registries.clone()),
+            member(),
+            Query(Default::default()),
+            Query(Vec::new()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            page.items.iter().map(|d| d.id).collect::<Vec<_>>(),
+            [our_device]
+        );
+
+        let page = readings::list(
+            State(registries.clone()),
+            member(),
+            ApiQuery(Default::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items[0].device_id, our_device);
+
+        assert!(matches!(
+            devices::latest(State(registries.clone()), member(), Path(their_device.0)).await,
+            Err(ApiError::NotFound)
+        ));
+
+        let platform = readings::list(
+            State(registries.clone()),
+            reader(),
+            ApiQuery(Default::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(platform.total, 2);
+    }
+
+    #[tokio::test]
+    async fn only_platform_admins_manage_organizations() {
+        let registries = InMemoryRegistries::default();
+        let ours = org(&registries, "Adama cooperative").await;
+        let device_id = site(&registries, ours).await;
+
+        let result = create(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(ours)),
+            Json(CreateOrg {
+                name: "Rogue".to_owned(),
+            }),
+        )
+        .await;
+        assert!(matches!(result, Err(ApiError::Forbidden)));
+
+        let result = assign_device(
+            State(registries.clone()),
+            admin(),
+            Path(device_id.0),
+            Json(AssignOrg {
+                org_id: Some(OrgId(Ulid::new())),
+            }),
+        )
+        .await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+}
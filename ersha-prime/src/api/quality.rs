@@ -0,0 +1,220 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+};
+use ersha_core::DeviceId;
+use jiff::{SignedDuration, Timestamp};
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::IntoParams;
+
+use super::{ApiError, ErrorBody, visible_device};
+use crate::auth::{Principal, Scope};
+use crate::config::QualityConfig;
+use crate::quality::{DataQuality, QualityWindow, SensorQuality};
+use crate::registry::{ReadingRegistry, Registries};
+
+/// Range examined when a request doesn't set `from`.
+const DEFAULT_RANGE: SignedDuration = SignedDuration::from_hours(24);
+
+/// Query parameters for `GET /api/devices/{id}/data-quality`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DataQualityQuery {
+    /// Start of the range. Defaults to a day before `to`.
+    pub from: Option<Timestamp>,
+    /// End of the range. Defaults to now.
+    pub to: Option<Timestamp>,
+    /// Seconds between readings of a sensor reporting on schedule. Defaults
+    /// to the configured `expected_interval_secs`.
+    pub interval_secs: Option<u64>,
+}
+
+impl DataQualityQuery {
+    fn into_window(self, config: QualityConfig) -> Result<QualityWindow, ApiError> {
+        let to = self.to.unwrap_or_else(Timestamp::now);
+        let from = match self.from {
+            Some(from) => from,
+            None => to
+                .checked_sub(DEFAULT_RANGE)
+                .map_err(|_| ApiError::BadRequest("to is out of range".to_owned()))?,
+        };
+        if from >= to {
+            return Err(ApiError::BadRequest("from must be before to".to_owned()));
+        }
+
+        let interval_secs = self.interval_secs.unwrap_or(config.expected_interval_secs);
+        let interval = i64::try_from(interval_secs)
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(SignedDuration::from_secs)
+            .ok_or_else(|| ApiError::BadRequest("interval_secs must be positive".to_owned()))?;
+
+        Ok(QualityWindow::new(from, to, interval)
+            .with_thresholds(config.suspect_below_percent, config.bad_below_percent))
+    }
+}
+
+/// `GET /api/devices/{id}/data-quality`
+///
+/// How closely each of the device's sensors kept to its expected cadence,
+/// its longest silences, and the confidence of its readings. Sensors that
+/// sent nothing in the range are included with no readings received.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/data-quality",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), DataQualityQuery),
+    responses(
+        (status = 200, description = "Quality per sensor, ordered by sensor id", body = DataQuality),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn data_quality<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(config): Extension<QualityConfig>,
+    Path(id): Path<Ulid>,
+    Query(query): Query<DataQualityQuery>,
+) -> Result<Json<DataQuality>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let device_id = DeviceId(id);
+    let device = visible_device(&registries, &principal, device_id).await?;
+    let window = query.into_window(config)?;
+
+    let mut sensors = registries
+        .readings()
+        .data_quality(device_id, window)
+        .await
+        .map_err(ApiError::registry)?;
+    for sensor in device.sensors.iter() {
+        if !sensors.iter().any(|quality| quality.sensor_id == sensor.id) {
+            sensors.push(SensorQuality::compute(sensor.id, &window, Vec::new()));
+        }
+    }
+    sensors.sort_by_key(|quality| quality.sensor_id.0);
+
+    Ok(Json(DataQuality {
+        device_id,
+        from: window.from,
+        to: window.to,
+        expected_interval_secs: window.interval.as_secs(),
+        sensors,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Extension, Json,
+        extract::{Path, Query, State},
+    };
+    use ersha_core::{
+        DeviceKind, DispatcherId, H3Cell, Percentage, ReadingId, Sensor, SensorId, SensorKind,
+        SensorMetric, SensorReading,
+    };
+    use jiff::Timestamp;
+    use ordered_float::NotNan;
+    use ulid::Ulid;
+
+    use super::{DataQualityQuery, data_quality};
+    use crate::api::RegisterQuery;
+    use crate::api::devices::{self, RegisterDevice};
+    use crate::config::QualityConfig;
+    use crate::events::{EventBus, LocalEventBus};
+    use crate::fixtures::admin;
+    use crate::registry::{ReadingRegistry, Registries, memory::InMemoryRegistries};
+
+    #[tokio::test]
+    async fn silent_sensors_are_reported() {
+        let registries = InMemoryRegistries::default();
+        let reporting = SensorId(Ulid::new());
+        let moisture = SensorMetric::SoilMoisture {
+            value: Percentage(30),
+        };
+        let principal = admin();
+        let (_, Json(device)) = devices::register(
+            State(registries.clone()),
+            principal.clone(),
+            Extension(Arc::new(LocalEventBus::new()) as Arc<dyn EventBus>),
+            Query(RegisterQuery::default()),
+            Json(RegisterDevice {
+                id: None,
+                kind: DeviceKind::Sensor,
+                location: 0x8a2a1072b59ffff,
+                manufacturer: None,
+                sensors: vec![
+                    Sensor {
+                        id: reporting,
+                        metric: moisture.clone(),
+                        kind: SensorKind::SoilMoisture,
+                    },
+                    Sensor {
+                        id: SensorId(Ulid::new()),
+                        metric: SensorMetric::AirTemp {
+                            value: NotNan::new(20.0).unwrap(),
+                        },
+                        kind: SensorKind::AirTemp,
+                    },
+                ],
+                reporting_interval_secs: None,
+                hardware_rev: None,
+                metadata: None,
+            }),
+        )
+        .await
+        .unwrap();
+
+        for second in [0, 60, 120] {
+            registries
+                .readings()
+                .store(SensorReading {
+                    id: ReadingId(Ulid::new()),
+                    device_id: device.id,
+                    dispatcher_id: DispatcherId(Ulid::new()),
+                    metric: moisture.clone(),
+                    location: H3Cell(0x8a2a1072b59ffff),
+                    confidence: Percentage(90),
+                    timestamp: Timestamp::from_second(second).unwrap(),
+                    sensor_id: reporting,
+                })
+                .await
+                .unwrap();
+        }
+
+        let query = DataQualityQuery {
+            from: Some(Timestamp::from_second(0).unwrap()),
+            to: Some(Timestamp::from_second(180).unwrap()),
+            interval_secs: Some(60),
+        };
+        let Json(report) = data_quality(
+            State(registries.clone()),
+            principal.clone(),
+            Extension(QualityConfig::default()),
+            Path(device.id.0),
+            Query(query),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.sensors.len(), 2);
+        for sensor in &report.sensors {
+            let received = if sensor.sensor_id == reporting { 3 } else { 0 };
+            assert_eq!((sensor.expected, sensor.received), (3, received));
+        }
+
+        let missing = data_quality(
+            State(registries),
+            principal,
+            Extension(QualityConfig::default()),
+            Path(Ulid::new()),
+            Query(DataQualityQuery::default()),
+        )
+        .await;
+        assert!(missing.is_err());
+    }
+}
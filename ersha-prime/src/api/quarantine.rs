@@ -0,0 +1,271 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use ersha_core::DispatcherId;
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::IntoParams;
+
+use super::dead_letters::{discard, redrive_letters, visible_letter};
+use super::{ApiError, ErrorBody, page_limit, scope_dispatchers};
+use crate::auth::{Principal, Scope};
+use crate::config::AuthConfig;
+use crate::dead_letter::{DeadLetter, DeadLetterId, DeadLetterState, QUARANTINE_REASONS};
+use crate::events::EventBus;
+use crate::registry::{DeadLetterRegistry, Registries, filter::DeadLetterFilter};
+
+/// Query parameters for `GET /api/quarantine`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuarantineQuery {
+    /// Only items uploaded by this dispatcher
+    #[param(value_type = Option<String>)]
+    pub dispatcher_id: Option<Ulid>,
+    pub limit: Option<usize>,
+}
+
+/// A quarantined item the caller may see. Other dead letters are reported
+/// as not found.
+async fn quarantined<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: Ulid,
+) -> Result<DeadLetter, ApiError> {
+    let letter = visible_letter(registries, principal, DeadLetterId(id)).await?;
+    if !letter.is_quarantined() {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(letter)
+}
+
+/// `GET /api/quarantine`
+///
+/// Items held for naming devices or sensors prime doesn't know, oldest
+/// first.
+#[utoipa::path(
+    get,
+    path = "/api/quarantine",
+    tag = "quarantine",
+    params(QuarantineQuery),
+    responses(
+        (status = 200, description = "Quarantined items", body = Vec<DeadLetter>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<QuarantineQuery>,
+) -> Result<Json<Vec<DeadLetter>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let mut filter = DeadLetterFilter {
+        dispatcher_ids: query.dispatcher_id.map(|id| vec![DispatcherId(id)]),
+        state: Some(DeadLetterState::Pending),
+        reasons: Some(QUARANTINE_REASONS.to_vec()),
+    };
+    if !scope_dispatchers(&registries, &principal, &mut filter.dispatcher_ids).await? {
+        return Ok(Json(Vec::new()));
+    }
+
+    let letters = registries
+        .dead_letters()
+        .list(filter, page_limit(query.limit)?)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(letters))
+}
+
+/// `POST /api/quarantine/{id}/release`
+///
+/// Check the item again, once its device or sensor is registered, and
+/// store it if it now passes. An item refused for another reason leaves
+/// quarantine as an ordinary dead letter.
+#[utoipa::path(
+    post,
+    path = "/api/quarantine/{id}/release",
+    tag = "quarantine",
+    params(("id" = String, Path, description = "Dead letter id")),
+    responses(
+        (status = 200, description = "The item after the attempt", body = DeadLetter),
+        (status = 404, description = "Not a quarantined item", body = ErrorBody),
+    )
+)]
+pub async fn release<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(auth): Extension<AuthConfig>,
+    Extension(events): Extension<Arc<dyn EventBus>>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<DeadLetter>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let letter = quarantined(&registries, &principal, id).await?;
+    let mut letters =
+        redrive_letters(&registries, &principal, auth, &*events, vec![letter]).await?;
+    let letter = letters.pop().ok_or(ApiError::Internal)?;
+
+    Ok(Json(letter))
+}
+
+/// `DELETE /api/quarantine/{id}`
+///
+/// Discard an item from a device that doesn't belong to the fleet.
+#[utoipa::path(
+    delete,
+    path = "/api/quarantine/{id}",
+    tag = "quarantine",
+    params(("id" = String, Path, description = "Dead letter id")),
+    responses(
+        (status = 204, description = "Item discarded"),
+        (status = 404, description = "Not a quarantined item", body = ErrorBody),
+    )
+)]
+pub async fn delete<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let letter = quarantined(&registries, &principal, id).await?;
+    discard(&registries, &principal, letter).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Extension,
+        extract::{Path, Query, State},
+    };
+    use ersha_core::{
+        BatchId, Device, DeviceId, DeviceKind, DeviceState, Dispatcher, DispatcherId,
+        DispatcherState, H3Cell, InvalidItemReason, Percentage, ReadingId, Sensor, SensorId,
+        SensorKind, SensorMetric, SensorReading,
+    };
+    use ulid::Ulid;
+
+    use super::{QuarantineQuery, delete, list, release};
+    use crate::api::ApiError;
+    use crate::config::AuthConfig;
+    use crate::dead_letter::{DeadItem, DeadLetter, DeadLetterState};
+    use crate::events::{EventBus, LocalEventBus};
+    use crate::fixtures::admin;
+    use crate::registry::{
+        DeadLetterRegistry, DeviceRegistry, DispatcherRegistry, ReadingRegistry,
+        memory::InMemoryRegistries,
+    };
+
+    fn held(reading: &SensorReading, reason: InvalidItemReason) -> DeadLetter {
+        DeadLetter::new(
+            reading.dispatcher_id,
+            BatchId(Ulid::new()),
+            DeadItem::Reading(reading.clone()),
+            reason,
+            jiff::Timestamp::now(),
+        )
+    }
+
+    #[tokio::test]
+    async fn quarantined_readings_are_released_once_their_device_is_registered() {
+        let registries = InMemoryRegistries::default();
+        let events: Arc<dyn EventBus> = Arc::new(LocalEventBus::new());
+        let reading = SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(40),
+            },
+            location: H3Cell(0x8a2a1072b59ffff),
+            confidence: Percentage(90),
+            timestamp: jiff::Timestamp::now(),
+            sensor_id: SensorId(Ulid::new()),
+        };
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: reading.dispatcher_id,
+                location: reading.location,
+                state: DispatcherState::Active,
+                provisioned_at: jiff::Timestamp::now(),
+            })
+            .await
+            .unwrap();
+        let unknown = held(&reading, InvalidItemReason::UnknownDevice);
+        let refused = held(
+            &SensorReading {
+                id: ReadingId(Ulid::new()),
+                ..reading.clone()
+            },
+            InvalidItemReason::DeviceNotAssigned,
+        );
+        registries
+            .dead_letters
+            .record(vec![unknown.clone(), refused.clone()])
+            .await
+            .unwrap();
+
+        // Only the item from the unknown device is in quarantine.
+        let listed = list(
+            State(registries.clone()),
+            admin(),
+            Query(QuarantineQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, unknown.id);
+        assert!(matches!(
+            delete(State(registries.clone()), admin(), Path(refused.id.0)).await,
+            Err(ApiError::NotFound)
+        ));
+
+        let release_it = || {
+            release(
+                State(registries.clone()),
+                admin(),
+                Extension(AuthConfig::default()),
+                Extension(events.clone()),
+                Path(unknown.id.0),
+            )
+        };
+        let still_held = release_it().await.unwrap();
+        assert_eq!(still_held.state, DeadLetterState::Pending);
+        assert_eq!(still_held.reason, InvalidItemReason::UnknownDevice);
+
+        registries
+            .devices
+            .register(Device {
+                id: reading.device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([Sensor {
+                    id: reading.sensor_id,
+                    metric: reading.metric.clone(),
+                    kind: SensorKind::SoilMoisture,
+                }]),
+            })
+            .await
+            .unwrap();
+        let released = release_it().await.unwrap();
+        assert_eq!(released.state, DeadLetterState::Redriven);
+        assert!(registries.readings.get(reading.id).await.unwrap().is_some());
+
+        // Released items have left quarantine.
+        assert!(matches!(release_it().await, Err(ApiError::NotFound)));
+    }
+}
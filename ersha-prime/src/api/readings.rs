@@ -0,0 +1,274 @@
+use axum::{
+    Extension,
+    extract::{Path, State},
+};
+use ersha_core::query::{QueryValue, ReadingQuery, ReadingSort};
+use ersha_core::{DeviceId, SensorKind, SensorReading};
+use ulid::Ulid;
+
+use super::{
+    ApiError, ApiQuery, ErrorBody, Page, page_limit, parse_cursor, scope_dispatchers, scope_fields,
+    visible_device,
+};
+use crate::auth::{Principal, Scope};
+use crate::region;
+use crate::registry::{
+    ReadingRegistry, Registries,
+    filter::{Pagination, QueryOptions, ReadingFilter, ReadingSortBy},
+};
+
+pub(super) fn reading_options(
+    query: ReadingQuery,
+) -> Result<QueryOptions<ReadingFilter, ReadingSortBy>, ApiError> {
+    let within = query.within.into_filter();
+    if let Some(cell) = within
+        .iter()
+        .flatten()
+        .find(|cell| !region::is_cell(**cell))
+    {
+        return Err(ApiError::BadRequest(format!(
+            "invalid within: '{:x}'",
+            cell.0
+        )));
+    }
+
+    let filter = ReadingFilter {
+        device_ids: query.device_id.into_filter(),
+        sensor_ids: query.sensor_id.into_filter(),
+        dispatcher_ids: query.dispatcher_id.into_filter(),
+        metric_kinds: query.metric.into_filter(),
+        locations: query.location.into_filter(),
+        within,
+        after: query.from,
+        before: query.to,
+        confidence: match (query.min_confidence, query.max_confidence) {
+            (None, None) => None,
+            (min, max) => Some(min.unwrap_or(0)..=max.unwrap_or(100)),
+        },
+        value_range: match (query.min_value, query.max_value) {
+            (None, None) => None,
+            (min, max) => Some(min.unwrap_or(f64::NEG_INFINITY)..=max.unwrap_or(f64::INFINITY)),
+        },
+        quality: query.quality.into_filter(),
+    };
+
+    Ok(QueryOptions {
+        filter,
+        sort_by: match query.sort_by {
+            ReadingSort::Timestamp => ReadingSortBy::Timestamp,
+            ReadingSort::Confidence => ReadingSortBy::Confidence,
+        },
+        sort_order: query.order.into(),
+        pagination: Pagination::Cursor {
+            after: parse_cursor(query.after.as_ref().map(|token| token.as_str()))?,
+            limit: page_limit(query.limit)?,
+        },
+    })
+}
+
+pub(super) fn parse_metric_kind(s: &str) -> Option<SensorKind> {
+    SensorKind::parse(s)
+}
+
+/// `GET /api/readings`
+#[utoipa::path(
+    get,
+    path = "/api/readings",
+    tag = "readings",
+    params(ReadingQuery),
+    responses(
+        (status = 200, description = "A page of readings", body = Page<SensorReading>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ReadingQuery>,
+) -> Result<Page<SensorReading>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let options = reading_options(query)?;
+    list_readings(&registries, &principal, options).await
+}
+
+/// `GET /api/devices/{id}/readings`
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/readings",
+    tag = "readings",
+    params(("id" = String, Path, description = "Device id"), ReadingQuery),
+    responses(
+        (status = 200, description = "A page of the device's readings", body = Page<SensorReading>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn list_for_device<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    ApiQuery(query): ApiQuery<ReadingQuery>,
+) -> Result<Page<SensorReading>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let device_id = DeviceId(id);
+    visible_device(&registries, &principal, device_id).await?;
+
+    let mut options = reading_options(query)?;
+    options.filter.device_ids = Some(vec![device_id]);
+
+    list_readings(&registries, &principal, options).await
+}
+
+/// A page of readings uploaded through dispatchers the caller may see.
+pub(super) async fn list_readings<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    mut options: QueryOptions<ReadingFilter, ReadingSortBy>,
+) -> Result<Page<SensorReading>, ApiError> {
+    let limit = options.pagination.limit();
+    let sort_by = options.sort_by;
+    let readings = registries.readings();
+
+    if !scope_dispatchers(registries, principal, &mut options.filter.dispatcher_ids).await?
+        || !scope_fields(principal, &mut options.filter.within)
+    {
+        return Ok(Page::new(Vec::new(), limit, 0, |r| sort_by.cursor(r)));
+    }
+
+    let total = readings
+        .count(Some(options.filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let items = readings.list(options).await.map_err(ApiError::registry)?;
+
+    Ok(Page::new(items, limit, total, |r| sort_by.cursor(r)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Uri;
+    use ersha_core::query::{List, PageToken, ReadingQuery};
+    use ersha_core::{H3Cell, SensorKind};
+
+    use super::reading_options;
+    use axum::extract::FromRequestParts;
+
+    use crate::api::{ApiError, ApiQuery};
+    use crate::registry::filter::{Cursor, Pagination, SortKey};
+
+    /// Parse `uri`'s query string the way the readings handlers do.
+    async fn extract(uri: &str) -> Result<ReadingQuery, ApiError> {
+        let (mut parts, ()) = axum::http::Request::get(uri.parse::<Uri>().unwrap())
+            .body(())
+            .unwrap()
+            .into_parts();
+        let ApiQuery(query) = ApiQuery::from_request_parts(&mut parts, &()).await?;
+        Ok(query)
+    }
+
+    #[tokio::test]
+    async fn query_maps_to_filter() {
+        let query = extract(
+            "/api/readings?metric=soil_moisture,%20air_temp&location=8a2a1072b59ffff\
+             &min_confidence=80&limit=5000",
+        )
+        .await
+        .unwrap();
+
+        let options = reading_options(query).unwrap();
+
+        assert_eq!(
+            options.filter.metric_kinds,
+            Some(vec![SensorKind::SoilMoisture, SensorKind::AirTemp])
+        );
+        assert_eq!(
+            options.filter.locations,
+            Some(vec![H3Cell(0x8a2a1072b59ffff)])
+        );
+        assert_eq!(options.filter.confidence, Some(80..=100));
+        assert_eq!(options.filter.value_range, None);
+        assert!(matches!(
+            options.pagination,
+            Pagination::Cursor {
+                after: None,
+                limit: 1000
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn typed_query_round_trips_through_the_query_string() {
+        let query = ReadingQuery {
+            metric: List(vec![SensorKind::Rainfall, SensorKind::Humidity]),
+            within: List(vec![H3Cell(0x852a1073fffffff)]),
+            from: Some(jiff::Timestamp::from_second(1_700_000_000).unwrap()),
+            max_value: Some(20.5),
+            ..Default::default()
+        }
+        .limit(10);
+
+        let uri = format!(
+            "/api/readings?{}",
+            serde_urlencoded::to_string(&query).unwrap()
+        );
+
+        assert_eq!(extract(&uri).await.unwrap(), query);
+    }
+
+    #[test]
+    fn value_bounds_map_to_an_open_ended_range() {
+        let query = ReadingQuery {
+            metric: List(vec![SensorKind::SoilMoisture]),
+            max_value: Some(20.0),
+            ..Default::default()
+        };
+
+        let options = reading_options(query).unwrap();
+
+        assert_eq!(options.filter.value_range, Some(f64::NEG_INFINITY..=20.0));
+    }
+
+    #[tokio::test]
+    async fn invalid_list_item_is_rejected() {
+        for uri in [
+            "/api/readings?device_id=not-a-ulid",
+            "/api/readings?quality=dubious",
+        ] {
+            assert!(
+                matches!(extract(uri).await, Err(ApiError::BadRequest(_))),
+                "{uri}"
+            );
+        }
+
+        let query = ReadingQuery {
+            within: List(vec![H3Cell(0x1234)]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            reading_options(query),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn cursor_round_trips_through_after() {
+        let cursor = Cursor::new(
+            SortKey::Time(jiff::Timestamp::from_second(1_700_000_000).unwrap()),
+            ulid::Ulid::new(),
+        );
+        let query = ReadingQuery::default().after(PageToken(cursor.encode()));
+
+        let Pagination::Cursor { after, .. } = reading_options(query).unwrap().pagination else {
+            panic!("readings page by cursor");
+        };
+        assert_eq!(after, Some(cursor));
+
+        let query = ReadingQuery::default().after(PageToken("not-a-cursor".to_owned()));
+        assert!(matches!(
+            reading_options(query),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+use axum::{
+    Extension,
+    extract::{Path, Query, State},
+};
+use ersha_core::query::ReadingQuery;
+use ersha_core::{Device, H3Cell, SensorReading};
+
+use super::{
+    ApiError, ApiQuery, ErrorBody, Page,
+    devices::{DevicesQuery, list_devices},
+    groups::with_group,
+    readings::{list_readings, reading_options},
+};
+use crate::auth::{Principal, Scope};
+use crate::region;
+use crate::registry::Registries;
+
+pub(super) fn parse_region(h3: &str) -> Result<H3Cell, ApiError> {
+    region::parse_cell(h3).ok_or_else(|| ApiError::BadRequest(format!("invalid H3 cell: '{h3}'")))
+}
+
+/// `GET /api/regions/{h3}/devices`
+///
+/// Devices located in the cell or any of its descendants. Accepts the same
+/// filters as `GET /api/devices`.
+#[utoipa::path(
+    get,
+    path = "/api/regions/{h3}/devices",
+    tag = "regions",
+    params(("h3" = String, Path, description = "H3 cell in hex"), DevicesQuery),
+    responses(
+        (status = 200, description = "A page of devices inside the region", body = Page<Device>),
+        (status = 400, description = "Invalid cell or query", body = ErrorBody),
+    )
+)]
+pub async fn devices<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(h3): Path<String>,
+    Query(query): Query<DevicesQuery>,
+) -> Result<Page<Device>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let cell = parse_region(&h3)?;
+    let group = query.group;
+    let mut options = query.into_options()?;
+    options.filter.within = Some(vec![cell]);
+    with_group(&registries, &principal, group, &mut options.filter.tags).await?;
+
+    list_devices(&registries, &principal, options).await
+}
+
+/// `GET /api/regions/{h3}/readings`
+///
+/// Readings taken in the cell or any of its descendants. Accepts the same
+/// filters as `GET /api/readings`.
+#[utoipa::path(
+    get,
+    path = "/api/regions/{h3}/readings",
+    tag = "regions",
+    params(("h3" = String, Path, description = "H3 cell in hex"), ReadingQuery),
+    responses(
+        (status = 200, description = "A page of readings inside the region", body = Page<SensorReading>),
+        (status = 400, description = "Invalid cell or query", body = ErrorBody),
+    )
+)]
+pub async fn readings<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(h3): Path<String>,
+    ApiQuery(query): ApiQuery<ReadingQuery>,
+) -> Result<Page<SensorReading>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let cell = parse_region(&h3)?;
+    let mut options = reading_options(query)?;
+    options.filter.within = Some(vec![cell]);
+
+    list_readings(&registries, &principal, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::{Path, Query, State};
+    use ersha_core::{Device, DeviceId, DeviceKind, DeviceState, H3Cell};
+    use ulid::Ulid;
+
+    use super::devices;
+    use crate::api::ApiError;
+    use crate::api::devices::DevicesQuery;
+    use crate::fixtures::reader;
+    use crate::registry::{DeviceRegistry, memory::InMemoryRegistries};
+
+    const PARENT: &str = "892a1072b5bffff";
+
+    async fn register(registries: &InMemoryRegistries, location: u64) -> DeviceId {
+        let id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(location),
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn lists_devices_inside_region() {
+        let registries = InMemoryRegistries::default();
+        let inside = register(&registries, 0x8a2a1072b59ffff).await;
+        register(&registries, 0x8a2a1072b4a7fff).await;
+
+        let page = devices(
+            State(registries.clone()),
+            reader(),
+            Path(PARENT.to_owned()),
+            Query(DevicesQuery::default()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(page.total, 1);
+        assert_eq!(
+            page.items.iter().map(|d| d.id).collect::<Vec<_>>(),
+            [inside]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_cell_is_rejected() {
+        let result = devices(
+            State(InMemoryRegistries::default()),
+            reader(),
+            Path("1337deadbeef".to_owned()),
+            Query(DevicesQuery::default()),
+        )
+        .await;
+
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+}
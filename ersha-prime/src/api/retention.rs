@@ -0,0 +1,65 @@
+use axum::{
+    Extension, Json,
+    extract::{Query, State},
+};
+use jiff::Timestamp;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use super::{ApiError, ErrorBody};
+use crate::auth::{Principal, Scope};
+use crate::config::RetentionConfig;
+use crate::registry::Registries;
+use crate::retention::{self, SweepReport};
+use crate::tuning::Tuning;
+
+/// Query parameters for `POST /api/retention/run`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RunQuery {
+    /// Count what would be purged without deleting it. Defaults to the
+    /// configured `dry_run`.
+    pub dry_run: Option<bool>,
+}
+
+/// `POST /api/retention/run`
+///
+/// Run a retention sweep now with the configured policies, instead of
+/// waiting for the next scheduled one.
+#[utoipa::path(
+    post,
+    path = "/api/retention/run",
+    tag = "retention",
+    params(RunQuery),
+    responses(
+        (status = 200, description = "What the sweep purged, or would have purged", body = SweepReport),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+    )
+)]
+pub async fn run<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(tuning): Extension<Tuning>,
+    Query(query): Query<RunQuery>,
+) -> Result<Json<SweepReport>, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+
+    let config = tuning.current().retention;
+    let config = RetentionConfig {
+        dry_run: query.dry_run.unwrap_or(config.dry_run),
+        ..config
+    };
+    let report = retention::sweep(&registries, &config, Timestamp::now())
+        .await
+        .map_err(ApiError::internal)?;
+
+    tracing::info!(
+        readings = report.readings,
+        statuses = report.statuses,
+        dry_run = config.dry_run,
+        triggered_by = ?principal.key_id,
+        "retention sweep triggered"
+    );
+
+    Ok(Json(report))
+}
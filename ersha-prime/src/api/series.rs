@@ -0,0 +1,277 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+};
+use ersha_core::{DeviceId, SensorId, SensorKind};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, ErrorBody, readings::parse_metric_kind, visible_device};
+use crate::auth::{Principal, Scope};
+use crate::downsample::{Point, lttb};
+use crate::registry::{
+    ReadingRegistry, Registries,
+    filter::{Pagination, QueryOptions, ReadingFilter, ReadingSortBy, SortOrder},
+};
+use crate::rollup::metric_value;
+
+const DEFAULT_POINTS: usize = 500;
+const MAX_POINTS: usize = 5000;
+/// Readings fetched from the registry at a time.
+const FETCH_PAGE: usize = 10_000;
+/// Readings downsampled in one request, beyond which the range must be
+/// narrowed or the aggregates used instead.
+const MAX_READINGS: usize = 2_000_000;
+/// Range covered when no start is given.
+const DEFAULT_RANGE: jiff::SignedDuration = jiff::SignedDuration::from_hours(24);
+
+/// Query parameters for `GET /api/devices/{id}/series`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SeriesQuery {
+    /// Metric kind, e.g. `soil_moisture`
+    pub metric: String,
+    /// Points wanted per sensor, 3 to 5000; 500 by default
+    pub points: Option<usize>,
+    /// Only this sensor's readings
+    pub sensor_id: Option<Ulid>,
+    /// Start of the range; 24 hours before its end by default
+    pub from: Option<jiff::Timestamp>,
+    /// End of the range; now by default
+    pub to: Option<jiff::Timestamp>,
+}
+
+/// A device's readings of one metric, downsampled for plotting.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceSeries {
+    pub device_id: DeviceId,
+    pub metric: SensorKind,
+    pub from: jiff::Timestamp,
+    pub to: jiff::Timestamp,
+    /// One series per sensor, ordered by sensor id
+    pub sensors: Vec<SensorSeries>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SensorSeries {
+    pub sensor_id: SensorId,
+    /// Readings in the range before downsampling
+    pub readings: usize,
+    /// Ordered by time
+    pub points: Vec<Point>,
+}
+
+/// `GET /api/devices/{id}/series`
+///
+/// The device's readings of one metric over a time range, downsampled on
+/// the server with Largest-Triangle-Three-Buckets to about `points` per
+/// sensor, so a chart gets the same number of points however densely the
+/// sensor reports. Peaks and troughs are kept.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/series",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), SeriesQuery),
+    responses(
+        (status = 200, description = "Downsampled series", body = DeviceSeries),
+        (status = 400, description = "Invalid query, or too many readings in the range", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn series<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Query(query): Query<SeriesQuery>,
+) -> Result<Json<DeviceSeries>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let metric = parse_metric_kind(&query.metric)
+        .ok_or_else(|| ApiError::BadRequest(format!("invalid metric: '{}'", query.metric)))?;
+    let points = query.points.unwrap_or(DEFAULT_POINTS);
+    if !(3..=MAX_POINTS).contains(&points) {
+        return Err(ApiError::BadRequest(format!(
+            "points must be between 3 and {MAX_POINTS}"
+        )));
+    }
+    let to = query.to.unwrap_or_else(jiff::Timestamp::now);
+    let from = query.from.unwrap_or(to - DEFAULT_RANGE);
+    if from > to {
+        return Err(ApiError::BadRequest("from is after to".to_owned()));
+    }
+
+    let device_id = DeviceId(id);
+    visible_device(&registries, &principal, device_id).await?;
+
+    let filter = ReadingFilter {
+        device_ids: Some(vec![device_id]),
+        sensor_ids: query.sensor_id.map(|id| vec![SensorId(id)]),
+        metric_kinds: Some(vec![metric]),
+        after: Some(from),
+        before: Some(to),
+        ..Default::default()
+    };
+    let mut sensors = raw_series(&registries, filter).await?;
+    sensors.sort_by_key(|(sensor_id, _)| sensor_id.0);
+
+    Ok(Json(DeviceSeries {
+        device_id,
+        metric,
+        from,
+        to,
+        sensors: sensors
+            .into_iter()
+            .map(|(sensor_id, raw)| SensorSeries {
+                sensor_id,
+                readings: raw.len(),
+                points: lttb(&raw, points),
+            })
+            .collect(),
+    }))
+}
+
+/// Every reading matching `filter` as points, by sensor, oldest first.
+async fn raw_series<R: Registries>(
+    registries: &R,
+    filter: ReadingFilter,
+) -> Result<Vec<(SensorId, Vec<Point>)>, ApiError> {
+    let mut sensors: Vec<(SensorId, Vec<Point>)> = Vec::new();
+    let mut fetched = 0;
+    let mut after = None;
+    loop {
+        let page = registries
+            .readings()
+            .list(QueryOptions {
+                filter: filter.clone(),
+                sort_by: ReadingSortBy::Timestamp,
+                sort_order: SortOrder::Asc,
+                pagination: Pagination::Cursor {
+                    after,
+                    limit: FETCH_PAGE,
+                },
+            })
+            .await
+            .map_err(ApiError::registry)?;
+
+        fetched += page.len();
+        if fetched > MAX_READINGS {
+            return Err(ApiError::BadRequest(format!(
+                "more than {MAX_READINGS} readings in the range, narrow it or use the aggregates"
+            )));
+        }
+
+        let full = page.len() == FETCH_PAGE;
+        after = page
+            .last()
+            .map(|last| ReadingSortBy::Timestamp.cursor(last));
+        for reading in page {
+            let point = Point {
+                t: reading.timestamp,
+                value: metric_value(&reading.metric),
+            };
+            match sensors.iter_mut().find(|(id, _)| *id == reading.sensor_id) {
+                Some((_, points)) => points.push(point),
+                None => sensors.push((reading.sensor_id, vec![point])),
+            }
+        }
+
+        if !full {
+            return Ok(sensors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::{Path, Query, State};
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, DispatcherId, H3Cell, Percentage, ReadingId,
+        SensorId, SensorMetric, SensorReading,
+    };
+    use ulid::Ulid;
+
+    use super::{MAX_POINTS, SeriesQuery, series};
+    use crate::api::ApiError;
+    use crate::fixtures::reader;
+    use crate::registry::{DeviceRegistry, ReadingRegistry, memory::InMemoryRegistries};
+
+    fn query(points: usize) -> Query<SeriesQuery> {
+        Query(SeriesQuery {
+            metric: "soil_moisture".to_owned(),
+            points: Some(points),
+            sensor_id: None,
+            from: Some(jiff::Timestamp::UNIX_EPOCH),
+            to: Some(jiff::Timestamp::from_second(86_399).unwrap()),
+        })
+    }
+
+    #[tokio::test]
+    async fn each_sensor_is_downsampled_to_the_points_asked_for() {
+        let registries = InMemoryRegistries::default();
+        let device_id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id: device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+
+        let (dense, sparse) = (SensorId(Ulid::new()), SensorId(Ulid::new()));
+        let readings = (0..2_000)
+            .map(|minute| (dense, minute))
+            .chain((0..10).map(|hour| (sparse, hour * 60)))
+            .map(|(sensor_id, minute)| SensorReading {
+                id: ReadingId(Ulid::new()),
+                device_id,
+                dispatcher_id: DispatcherId(Ulid::new()),
+                metric: SensorMetric::SoilMoisture {
+                    value: Percentage((minute % 100) as u8),
+                },
+                location: H3Cell(0x8a2a1072b59ffff),
+                confidence: Percentage(95),
+                timestamp: jiff::Timestamp::from_second(minute * 60).unwrap(),
+                sensor_id,
+            })
+            .collect();
+        registries.readings.batch_store(readings).await.unwrap();
+
+        let downsampled = series(
+            State(registries.clone()),
+            reader(),
+            Path(device_id.0),
+            query(100),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(downsampled.sensors.len(), 2);
+        for sensor in &downsampled.sensors {
+            let (readings, points) = if sensor.sensor_id == dense {
+                (1_440, 100)
+            } else {
+                (10, 10)
+            };
+            assert_eq!(sensor.readings, readings);
+            assert_eq!(sensor.points.len(), points);
+        }
+
+        assert!(matches!(
+            series(
+                State(registries),
+                reader(),
+                Path(device_id.0),
+                query(MAX_POINTS + 1)
+            )
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
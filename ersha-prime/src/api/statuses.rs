@@ -0,0 +1,332 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+};
+use ersha_core::query::{PageToken, StatusQuery};
+use ersha_core::{DeviceId, DeviceStatus};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{
+    ApiError, ApiQuery, ErrorBody, Order, Page, page_limit, parse_cursor, scope_dispatchers,
+    visible_device,
+};
+use crate::auth::{Principal, Scope};
+use crate::battery::BatteryTrend;
+use crate::registry::{
+    DeviceStatusRegistry, Registries,
+    filter::{Pagination, QueryOptions, SortOrder, StatusFilter, StatusSortBy},
+};
+
+/// Most recent statuses in the range a battery trend is fitted to.
+const TREND_SAMPLES: usize = 10_000;
+
+fn status_options(
+    query: StatusQuery,
+) -> Result<QueryOptions<StatusFilter, StatusSortBy>, ApiError> {
+    let filter = StatusFilter {
+        device_ids: query.device_id.into_filter(),
+        dispatcher_ids: query.dispatcher_id.into_filter(),
+        after: query.from,
+        before: query.to,
+        ..Default::default()
+    };
+
+    Ok(QueryOptions {
+        filter,
+        sort_by: StatusSortBy::Timestamp,
+        sort_order: query.order.into(),
+        pagination: Pagination::Cursor {
+            after: parse_cursor(query.after.as_ref().map(|token| token.as_str()))?,
+            limit: page_limit(query.limit)?,
+        },
+    })
+}
+
+/// `GET /api/statuses`
+#[utoipa::path(
+    get,
+    path = "/api/statuses",
+    tag = "statuses",
+    params(StatusQuery),
+    responses(
+        (status = 200, description = "A page of device status reports", body = Page<DeviceStatus>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<StatusQuery>,
+) -> Result<Page<DeviceStatus>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let mut options = status_options(query)?;
+    let limit = options.pagination.limit();
+    let sort_by = options.sort_by;
+    let statuses = registries.statuses();
+
+    if !scope_dispatchers(&registries, &principal, &mut options.filter.dispatcher_ids).await? {
+        return Ok(Page::new(Vec::new(), limit, 0, |s| sort_by.cursor(s)));
+    }
+
+    let total = statuses
+        .count(Some(options.filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let items = statuses.list(options).await.map_err(ApiError::registry)?;
+
+    Ok(Page::new(items, limit, total, |s| sort_by.cursor(s)))
+}
+
+/// Query parameters for `GET /api/devices/{id}/status-history`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatusHistoryQuery {
+    /// Only statuses captured at or after this time
+    pub from: Option<jiff::Timestamp>,
+    /// Only statuses captured at or before this time
+    pub to: Option<jiff::Timestamp>,
+    /// Also compute this from the statuses in the range
+    pub derive: Option<Derive>,
+    /// Order by capture time
+    #[serde(default)]
+    pub order: Order,
+    /// Cursor returned as `next_cursor` by the previous page
+    #[param(value_type = Option<String>)]
+    pub after: Option<PageToken>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Derive {
+    BatteryTrend,
+}
+
+/// A page of a device's statuses, with anything derived from the whole range.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatusHistory {
+    pub items: Vec<DeviceStatus>,
+    /// Statuses in the range across all pages
+    pub total: usize,
+    /// Opaque token marking where this page ended
+    #[schema(value_type = Option<String>)]
+    pub next_cursor: Option<PageToken>,
+    /// Fitted to the most recent statuses in the range, when requested and
+    /// they span some time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_trend: Option<BatteryTrend>,
+}
+
+/// `GET /api/devices/{id}/status-history`
+///
+/// The device's status reports over a time range. With
+/// `derive=battery_trend` the response also estimates how fast the battery
+/// is draining and how many days it has left.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/status-history",
+    tag = "statuses",
+    params(("id" = String, Path, description = "Device id"), StatusHistoryQuery),
+    responses(
+        (status = 200, description = "A page of the device's status reports", body = StatusHistory),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+    )
+)]
+pub async fn history<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    ApiQuery(query): ApiQuery<StatusHistoryQuery>,
+) -> Result<Json<StatusHistory>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let device_id = DeviceId(id);
+    visible_device(&registries, &principal, device_id).await?;
+
+    let derive = query.derive;
+    let mut options = status_options(StatusQuery {
+        from: query.from,
+        to: query.to,
+        order: query.order,
+        after: query.after,
+        limit: query.limit,
+        ..Default::default()
+    })?;
+    options.filter.device_ids = Some(vec![device_id]);
+
+    let limit = options.pagination.limit();
+    let sort_by = options.sort_by;
+    let statuses = registries.statuses();
+
+    if !scope_dispatchers(&registries, &principal, &mut options.filter.dispatcher_ids).await? {
+        return Ok(Json(StatusHistory {
+            items: Vec::new(),
+            total: 0,
+            next_cursor: None,
+            battery_trend: None,
+        }));
+    }
+
+    let filter = options.filter.clone();
+    let total = statuses
+        .count(Some(filter.clone()))
+        .await
+        .map_err(ApiError::registry)?;
+    let page = Page::new(
+        statuses.list(options).await.map_err(ApiError::registry)?,
+        limit,
+        total,
+        |s| sort_by.cursor(s),
+    );
+
+    let battery_trend = match derive {
+        Some(Derive::BatteryTrend) => {
+            let mut recent = statuses
+                .list(QueryOptions {
+                    filter,
+                    sort_by: StatusSortBy::Timestamp,
+                    sort_order: SortOrder::Desc,
+                    pagination: Pagination::Cursor {
+                        after: None,
+                        limit: TREND_SAMPLES,
+                    },
+                })
+                .await
+                .map_err(ApiError::registry)?;
+            recent.reverse();
+
+            let samples: Vec<_> = recent
+                .iter()
+                .map(|s| (s.timestamp, s.battery_percent))
+                .collect();
+            BatteryTrend::compute(&samples)
+        }
+        None => None,
+    };
+
+    Ok(Json(StatusHistory {
+        items: page.items,
+        total: page.total,
+        next_cursor: page.next_cursor,
+        battery_trend,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::{Path, State},
+        response::IntoResponse,
+    };
+    use ersha_core::query::StatusQuery;
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, DeviceStatus, DispatcherId, H3Cell, Percentage,
+        StatusId,
+    };
+    use ulid::Ulid;
+
+    use super::{Derive, StatusHistoryQuery, history, list};
+    use crate::api::{ApiError, ApiQuery, TOTAL_COUNT_HEADER};
+    use crate::fixtures::reader;
+    use crate::registry::{DeviceRegistry, DeviceStatusRegistry, memory::InMemoryRegistries};
+
+    #[tokio::test]
+    async fn page_reports_total_beyond_limit() {
+        let registries = InMemoryRegistries::default();
+        let device_id = DeviceId(Ulid::new());
+        let statuses = (0..3)
+            .map(|_| DeviceStatus {
+                id: StatusId(Ulid::new()),
+                device_id,
+                dispatcher_id: DispatcherId(Ulid::new()),
+                battery_percent: Percentage(90),
+                uptime_seconds: 60,
+                signal_rssi: -70,
+                errors: Box::new([]),
+                timestamp: jiff::Timestamp::now(),
+                sensor_statuses: Box::new([]),
+            })
+            .collect();
+        registries.statuses.batch_store(statuses).await.unwrap();
+
+        let query = StatusQuery::default().devices([device_id]).limit(2);
+        let page = list(State(registries), reader(), ApiQuery(query))
+            .await
+            .unwrap();
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.total, 3);
+        assert!(page.next_cursor.is_some());
+
+        let response = page.into_response();
+        assert_eq!(response.headers()[TOTAL_COUNT_HEADER], "3");
+    }
+
+    #[tokio::test]
+    async fn history_derives_battery_trend() {
+        let registries = InMemoryRegistries::default();
+        let device_id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id: device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        // Three percentage points a day, down to 60 after ten days.
+        let statuses = (0..=10)
+            .map(|day| DeviceStatus {
+                id: StatusId(Ulid::new()),
+                device_id,
+                dispatcher_id: DispatcherId(Ulid::new()),
+                battery_percent: Percentage(90 - 3 * day as u8),
+                uptime_seconds: 60,
+                signal_rssi: -70,
+                errors: Box::new([]),
+                timestamp: jiff::Timestamp::from_second(day * 86_400).unwrap(),
+                sensor_statuses: Box::new([]),
+            })
+            .collect();
+        registries.statuses.batch_store(statuses).await.unwrap();
+
+        let query = StatusHistoryQuery {
+            derive: Some(Derive::BatteryTrend),
+            limit: Some(4),
+            ..Default::default()
+        };
+        let response = history(
+            State(registries.clone()),
+            reader(),
+            Path(device_id.0),
+            ApiQuery(query),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.items.len(), 4);
+        assert_eq!(response.total, 11);
+        let trend = response.battery_trend.as_ref().unwrap();
+        assert_eq!(trend.samples, 11);
+        assert_eq!(trend.latest_percent, 60);
+        assert!((trend.drain_percent_per_day - 3.0).abs() < 1e-9);
+        assert!((trend.days_to_empty.unwrap() - 20.0).abs() < 1e-9);
+
+        let unknown = history(
+            State(registries),
+            reader(),
+            Path(Ulid::new()),
+            ApiQuery(StatusHistoryQuery::default()),
+        )
+        .await;
+        assert!(matches!(unknown, Err(ApiError::NotFound)));
+    }
+}
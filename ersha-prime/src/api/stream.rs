@@ -0,0 +1,147 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    Extension,
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use ersha_core::{DeviceId, H3Cell};
+use futures_util::{Stream, StreamExt, stream};
+use serde::Deserialize;
+use tracing::error;
+use utoipa::IntoParams;
+
+use super::{
+    ApiError, ErrorBody, parse_list, readings::parse_metric_kind, scope_dispatchers, scope_fields,
+};
+use crate::auth::{Principal, Scope};
+use crate::events::{BusEvent, EventBus, Received};
+use crate::live::FeedFilter;
+use crate::registry::Registries;
+
+/// Query parameters for `GET /api/stream/readings`.
+///
+/// List parameters are comma separated.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    pub device_id: Option<String>,
+    /// Metric kinds, e.g. `soil_moisture,air_temp`
+    pub metric: Option<String>,
+    /// H3 cells in hex; readings inside any of them match
+    pub within: Option<String>,
+}
+
+impl StreamQuery {
+    fn into_filter(self) -> Result<FeedFilter, ApiError> {
+        Ok(FeedFilter {
+            device_ids: parse_list("device_id", self.device_id.as_deref(), |s| {
+                s.parse().ok().map(DeviceId)
+            })?,
+            metric_kinds: parse_list("metric", self.metric.as_deref(), parse_metric_kind)?,
+            within: parse_list("within", self.within.as_deref(), |s| {
+                u64::from_str_radix(s, 16).ok().map(H3Cell)
+            })?,
+            ..Default::default()
+        })
+    }
+}
+
+/// `GET /api/stream/readings`
+///
+/// Server-sent events carrying each matching reading as it is ingested. A
+/// `lagged` event reports how many readings a slow client missed.
+///
+/// Organization keys only receive readings from dispatchers their
+/// organization owned when the stream was opened, and users limited to
+/// fields only readings taken in them.
+#[utoipa::path(
+    get,
+    path = "/api/stream/readings",
+    tag = "readings",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "`reading` events carrying a SensorReading as JSON", content_type = "text/event-stream", body = String),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+pub async fn readings<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(events): Extension<Arc<dyn EventBus>>,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let mut filter = query.into_filter()?;
+    let visible = scope_dispatchers(&registries, &principal, &mut filter.dispatcher_ids).await?
+        && scope_fields(&principal, &mut filter.within);
+
+    let readings = events
+        .subscribe_lossy()
+        .into_stream()
+        .flat_map(move |received| {
+            let events: Vec<Event> = match received {
+                Received::Event(event) => match &*event {
+                    BusEvent::ReadingsIngested {
+                        readings,
+                        late: false,
+                    } if visible => readings
+                        .iter()
+                        .filter(|reading| filter.matches(reading))
+                        .filter_map(|reading| {
+                            Event::default()
+                                .event("reading")
+                                .json_data(reading)
+                                .inspect_err(
+                                    |e| error!(error = %e, "failed to encode live reading"),
+                                )
+                                .ok()
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                },
+                Received::Lagged(missed) => {
+                    vec![Event::default().event("lagged").data(missed.to_string())]
+                }
+            };
+
+            stream::iter(events.into_iter().map(Ok))
+        });
+
+    Ok(Sse::new(readings).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{H3Cell, SensorKind};
+
+    use super::StreamQuery;
+    use crate::api::ApiError;
+
+    #[test]
+    fn query_maps_to_filter() {
+        let query = StreamQuery {
+            metric: Some("rainfall".to_owned()),
+            within: Some("892a1072b5bffff".to_owned()),
+            ..Default::default()
+        };
+
+        let filter = query.into_filter().unwrap();
+
+        assert_eq!(filter.metric_kinds, Some(vec![SensorKind::Rainfall]));
+        assert_eq!(filter.within, Some(vec![H3Cell(0x892a1072b5bffff)]));
+        assert_eq!(filter.device_ids, None);
+    }
+
+    #[test]
+    fn invalid_cell_is_rejected() {
+        let query = StreamQuery {
+            within: Some("not-hex".to_owned()),
+            ..Default::default()
+        };
+
+        assert!(matches!(query.into_filter(), Err(ApiError::BadRequest(_))));
+    }
+}
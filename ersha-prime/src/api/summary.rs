@@ -0,0 +1,313 @@
+use axum::{Extension, Json, extract::State};
+use ersha_core::{DeviceState, DispatcherState};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{ApiError, scope_dispatchers, scope_fields};
+use crate::auth::{Principal, Scope};
+use crate::registry::{
+    DeviceRegistry, DeviceStatusRegistry, DispatcherRegistry, ReadingRegistry, Registries,
+    filter::{DeviceFilter, DispatcherFilter, ReadingFilter, StatusFilter},
+};
+
+/// How far back "recent" ingestion is counted.
+const RECENT: jiff::SignedDuration = jiff::SignedDuration::from_hours(24);
+
+/// Counts across the fleet the caller may see, for the ops dashboard.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FleetSummary {
+    pub devices: DeviceCounts,
+    pub dispatchers: DispatcherCounts,
+    pub readings: StoredCounts,
+    pub statuses: StoredCounts,
+    pub generated_at: jiff::Timestamp,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceCounts {
+    pub total: usize,
+    pub active: usize,
+    pub suspended: usize,
+    pub decommissioned: usize,
+    /// Marked disconnected by their dispatcher or the watchdog
+    pub offline: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DispatcherCounts {
+    pub total: usize,
+    pub active: usize,
+    pub suspended: usize,
+    /// Awaiting an operator's approval
+    pub pending: usize,
+}
+
+/// How much of something is stored and how fast it is growing.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct StoredCounts {
+    pub total: usize,
+    /// Taken in the last 24 hours
+    pub last_24h: usize,
+}
+
+/// `GET /api/summary`
+///
+/// Devices and dispatchers by state, and readings and statuses stored in
+/// all and over the last day. Organization keys only count their own.
+#[utoipa::path(
+    get,
+    path = "/api/summary",
+    tag = "summary",
+    responses(
+        (status = 200, description = "Fleet summary", body = FleetSummary),
+    )
+)]
+pub async fn summary<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<FleetSummary>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let now = jiff::Timestamp::now();
+    let since = now - RECENT;
+
+    let (devices, dispatchers, readings, statuses) = tokio::try_join!(
+        device_counts(&registries, &principal),
+        dispatcher_counts(&registries, &principal),
+        reading_counts(&registries, &principal, since),
+        status_counts(&registries, &principal, since),
+    )?;
+
+    Ok(Json(FleetSummary {
+        devices,
+        dispatchers,
+        readings,
+        statuses,
+        generated_at: now,
+    }))
+}
+
+async fn device_counts<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+) -> Result<DeviceCounts, ApiError> {
+    let mut scope = DeviceFilter {
+        org_id: principal.org_id,
+        ..Default::default()
+    };
+    if !scope_fields(principal, &mut scope.within) {
+        return Ok(DeviceCounts {
+            total: 0,
+            active: 0,
+            suspended: 0,
+            decommissioned: 0,
+            offline: 0,
+        });
+    }
+
+    let devices = registries.devices();
+    let count = |filter: DeviceFilter| async {
+        devices
+            .count(Some(filter))
+            .await
+            .map_err(ApiError::registry)
+    };
+    let in_state = |state: DeviceState| DeviceFilter {
+        states: Some(vec![state]),
+        ..scope.clone()
+    };
+
+    let (total, active, suspended, decommissioned, offline) = tokio::try_join!(
+        count(scope.clone()),
+        count(in_state(DeviceState::Active)),
+        count(in_state(DeviceState::Suspended)),
+        count(in_state(DeviceState::Decommissioned)),
+        count(DeviceFilter {
+            disconnected: Some(true),
+            ..scope.clone()
+        }),
+    )?;
+
+    Ok(DeviceCounts {
+        total,
+        active,
+        suspended,
+        decommissioned,
+        offline,
+    })
+}
+
+async fn dispatcher_counts<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+) -> Result<DispatcherCounts, ApiError> {
+    let scope = DispatcherFilter {
+        org_id: principal.org_id,
+        ..Default::default()
+    };
+
+    let dispatchers = registries.dispatchers();
+    let count = |filter: DispatcherFilter| async {
+        dispatchers
+            .count(Some(filter))
+            .await
+            .map_err(ApiError::registry)
+    };
+    let in_state = |state: DispatcherState| DispatcherFilter {
+        states: Some(vec![state]),
+        ..scope.clone()
+    };
+
+    let (total, active, suspended, pending) = tokio::try_join!(
+        count(scope.clone()),
+        count(in_state(DispatcherState::Active)),
+        count(in_state(DispatcherState::Suspended)),
+        count(in_state(DispatcherState::Pending)),
+    )?;
+
+    Ok(DispatcherCounts {
+        total,
+        active,
+        suspended,
+        pending,
+    })
+}
+
+async fn reading_counts<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    since: jiff::Timestamp,
+) -> Result<StoredCounts, ApiError> {
+    let mut scope = ReadingFilter::default();
+    if !scope_dispatchers(registries, principal, &mut scope.dispatcher_ids).await?
+        || !scope_fields(principal, &mut scope.within)
+    {
+        return Ok(StoredCounts::default());
+    }
+
+    let readings = registries.readings();
+    let recent = ReadingFilter {
+        after: Some(since),
+        ..scope.clone()
+    };
+    let (total, last_24h) =
+        tokio::try_join!(readings.count(Some(scope)), readings.count(Some(recent)),)
+            .map_err(ApiError::registry)?;
+
+    Ok(StoredCounts { total, last_24h })
+}
+
+async fn status_counts<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    since: jiff::Timestamp,
+) -> Result<StoredCounts, ApiError> {
+    let mut scope = StatusFilter::default();
+    if !scope_dispatchers(registries, principal, &mut scope.dispatcher_ids).await? {
+        return Ok(StoredCounts::default());
+    }
+
+    let statuses = registries.statuses();
+    let recent = StatusFilter {
+        after: Some(since),
+        ..scope.clone()
+    };
+    let (total, last_24h) =
+        tokio::try_join!(statuses.count(Some(scope)), statuses.count(Some(recent)),)
+            .map_err(ApiError::registry)?;
+
+    Ok(StoredCounts { total, last_24h })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::State;
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, DispatcherId, H3Cell, Percentage, ReadingId,
+        SensorId, SensorMetric, SensorReading,
+    };
+    use ulid::Ulid;
+
+    use super::summary;
+    use crate::auth::Scope;
+    use crate::fixtures::{principal, reader};
+    use crate::org::OrgId;
+    use crate::registry::{DeviceRegistry, ReadingRegistry, memory::InMemoryRegistries};
+
+    async fn register(registries: &InMemoryRegistries, state: DeviceState) -> DeviceId {
+        let id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id,
+                kind: DeviceKind::Sensor,
+                state,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn store(registries: &InMemoryRegistries, device_id: DeviceId, age_hours: i64) {
+        registries
+            .readings
+            .store(SensorReading {
+                id: ReadingId(Ulid::new()),
+                device_id,
+                dispatcher_id: DispatcherId(Ulid::new()),
+                metric: SensorMetric::SoilMoisture {
+                    value: Percentage(40),
+                },
+                location: H3Cell(0x8a2a1072b59ffff),
+                confidence: Percentage(95),
+                timestamp: jiff::Timestamp::now() - jiff::SignedDuration::from_hours(age_hours),
+                sensor_id: SensorId(Ulid::new()),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn counts_what_the_caller_can_see() {
+        let registries = InMemoryRegistries::default();
+        let org_id = OrgId(Ulid::new());
+        let active = register(&registries, DeviceState::Active).await;
+        let suspended = register(&registries, DeviceState::Suspended).await;
+        registries
+            .devices
+            .set_disconnected(suspended, Some(jiff::Timestamp::now()))
+            .await
+            .unwrap();
+        registries
+            .devices
+            .set_org(suspended, Some(org_id))
+            .await
+            .unwrap();
+        store(&registries, active, 1).await;
+        store(&registries, active, 48).await;
+
+        let fleet = summary(State(registries.clone()), reader())
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(fleet.devices.total, 2);
+        assert_eq!(fleet.devices.active, 1);
+        assert_eq!(fleet.devices.suspended, 1);
+        assert_eq!(fleet.devices.decommissioned, 0);
+        assert_eq!(fleet.devices.offline, 1);
+        assert_eq!(fleet.readings.total, 2);
+        assert_eq!(fleet.readings.last_24h, 1);
+
+        let org = summary(State(registries), principal(Scope::ReadOnly, Some(org_id)))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(org.devices.total, 1);
+        assert_eq!(org.devices.offline, 1);
+        assert_eq!(org.readings.total, 0);
+    }
+}
@@ -0,0 +1,234 @@
+use axum::{Extension, Json, extract::State};
+use ersha_core::{DeviceId, DispatcherId};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use super::{ApiError, ErrorBody, MAX_LIMIT, record_audit, visible_device, visible_dispatcher};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::group::normalize_tags;
+use crate::registry::{DeviceRegistry, DispatcherRegistry, Registries};
+
+/// Body of `POST /api/tags`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct AssignTags {
+    #[serde(default)]
+    pub devices: Vec<Ulid>,
+    #[serde(default)]
+    pub dispatchers: Vec<Ulid>,
+    /// Tags put on each device and dispatcher
+    #[serde(default)]
+    pub add: Vec<String>,
+    /// Tags taken off each device and dispatcher
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+/// Tags on one device or dispatcher.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct EntityTags {
+    pub id: Ulid,
+    pub tags: Vec<String>,
+}
+
+/// The tags on what was changed, after the change.
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct AssignedTags {
+    pub devices: Vec<EntityTags>,
+    pub dispatchers: Vec<EntityTags>,
+}
+
+/// `current` with `add` put on and `remove` taken off, sorted.
+fn apply(current: Vec<String>, add: &[String], remove: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = current
+        .into_iter()
+        .chain(add.iter().cloned())
+        .filter(|tag| !remove.contains(tag))
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// `POST /api/tags`
+///
+/// Add and remove tags on many devices and dispatchers at once. Nothing is
+/// changed if any of them is unknown.
+#[utoipa::path(
+    post,
+    path = "/api/tags",
+    tag = "groups",
+    request_body = AssignTags,
+    responses(
+        (status = 200, description = "Tags after the change", body = AssignedTags),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown device or dispatcher", body = ErrorBody),
+    )
+)]
+pub async fn assign<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<AssignTags>,
+) -> Result<Json<AssignedTags>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let bad_request = |reason: &str| ApiError::BadRequest(reason.to_owned());
+    if request.devices.len() + request.dispatchers.len() > MAX_LIMIT {
+        return Err(ApiError::BadRequest(format!(
+            "at most {MAX_LIMIT} devices and dispatchers per request"
+        )));
+    }
+    let add = normalize_tags(&request.add).map_err(bad_request)?;
+    let remove = normalize_tags(&request.remove).map_err(bad_request)?;
+    if add.is_empty() && remove.is_empty() {
+        return Err(bad_request("add or remove at least one tag"));
+    }
+
+    // Check every id first, so an unknown one changes nothing.
+    for &id in &request.devices {
+        visible_device(&registries, &principal, DeviceId(id)).await?;
+    }
+    for &id in &request.dispatchers {
+        visible_dispatcher(&registries, &principal, DispatcherId(id)).await?;
+    }
+
+    let details = serde_json::json!({ "add": add, "remove": remove });
+    let mut assigned = AssignedTags::default();
+
+    let devices = registries.devices();
+    for id in request.devices {
+        let current = devices
+            .tags(DeviceId(id))
+            .await
+            .map_err(ApiError::registry)?;
+        let tags = apply(current, &add, &remove);
+        devices
+            .set_tags(DeviceId(id), tags.clone())
+            .await
+            .map_err(ApiError::registry)?;
+
+        record_audit(
+            &registries,
+            AuditEntry::by(&principal, AuditAction::Update, EntityKind::Device, id)
+                .with_details(details.clone()),
+        )
+        .await?;
+        assigned.devices.push(EntityTags { id, tags });
+    }
+
+    let dispatchers = registries.dispatchers();
+    for id in request.dispatchers {
+        let current = dispatchers
+            .tags(DispatcherId(id))
+            .await
+            .map_err(ApiError::registry)?;
+        let tags = apply(current, &add, &remove);
+        dispatchers
+            .set_tags(DispatcherId(id), tags.clone())
+            .await
+            .map_err(ApiError::registry)?;
+
+        record_audit(
+            &registries,
+            AuditEntry::by(&principal, AuditAction::Update, EntityKind::Dispatcher, id)
+                .with_details(details.clone()),
+        )
+        .await?;
+        assigned.dispatchers.push(EntityTags { id, tags });
+    }
+
+    Ok(Json(assigned))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json,
+        extract::{Query, State},
+    };
+    use ersha_core::{Device, DeviceId, DeviceKind, DeviceState, H3Cell};
+    use ulid::Ulid;
+
+    use super::{AssignTags, assign};
+    use crate::api::ApiError;
+    use crate::api::devices::{DevicesQuery, list};
+    use crate::fixtures::admin;
+    use crate::registry::{DeviceRegistry, memory::InMemoryRegistries};
+
+    fn device() -> Device {
+        Device {
+            id: DeviceId(Ulid::new()),
+            kind: DeviceKind::Sensor,
+            state: DeviceState::Active,
+            location: H3Cell(0x8a2a1072b59ffff),
+            manufacturer: None,
+            provisioned_at: jiff::Timestamp::now(),
+            sensors: Box::new([]),
+        }
+    }
+
+    #[tokio::test]
+    async fn tagged_devices_can_be_listed_by_tag() {
+        let registries = InMemoryRegistries::default();
+        let (pilot, other) = (device(), device());
+        registries
+            .devices
+            .batch_register(vec![pilot.clone(), other.clone()])
+            .await
+            .unwrap();
+
+        let unknown = assign(
+            State(registries.clone()),
+            admin(),
+            Json(AssignTags {
+                devices: vec![pilot.id.0, Ulid::new()],
+                add: vec!["pilot-a".to_owned()],
+                ..Default::default()
+            }),
+        )
+        .await;
+        assert!(matches!(unknown, Err(ApiError::NotFound)));
+        assert!(registries.devices.tags(pilot.id).await.unwrap().is_empty());
+
+        let Json(assigned) = assign(
+            State(registries.clone()),
+            admin(),
+            Json(AssignTags {
+                devices: vec![pilot.id.0, other.id.0],
+                add: vec!["Pilot-A".to_owned(), "v2-hardware".to_owned()],
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(assigned.devices[0].tags, ["pilot-a", "v2-hardware"]);
+        let Json(removed) = assign(
+            State(registries.clone()),
+            admin(),
+            Json(AssignTags {
+                devices: vec![other.id.0],
+                remove: vec!["pilot-a".to_owned()],
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(removed.devices[0].tags, ["v2-hardware"]);
+
+        let page = list(
+            State(registries),
+            admin(),
+            Query(DevicesQuery {
+                tag: Some("pilot-a,v2-hardware".to_owned()),
+                ..Default::default()
+            }),
+            Query(Vec::new()),
+        )
+        .await
+        .unwrap();
+        let ids: Vec<DeviceId> = page.items.iter().map(|device| device.id).collect();
+        assert_eq!(ids, [pilot.id]);
+    }
+}
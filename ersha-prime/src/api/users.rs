@@ -0,0 +1,833 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use ersha_core::H3Cell;
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use super::fields::visible_field;
+use super::{ApiError, ErrorBody, record_audit};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{ApiKeyId, Principal, Scope};
+use crate::config::{IndicatorConfig, UserConfig};
+use crate::org::OrgId;
+use crate::registry::{ApiKeyRegistry, OrgRegistry, Registries, UserRegistry};
+use crate::user::{self, Credential, Role, User, UserId};
+
+/// Shortest password accepted.
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// How a user logs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginKind {
+    Password,
+    Oidc,
+}
+
+/// A user as returned by the API. Password hashes are never exposed.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserInfo {
+    pub id: UserId,
+    pub username: String,
+    pub name: String,
+    pub role: Role,
+    /// Absent for platform-wide users
+    pub org_id: Option<OrgId>,
+    /// Fields the user is limited to; empty for every field
+    pub fields: Vec<H3Cell>,
+    pub login: LoginKind,
+    pub created_at: Timestamp,
+}
+
+impl From<User> for UserInfo {
+    fn from(user: User) -> Self {
+        let login = match user.credential {
+            Credential::Password { .. } => LoginKind::Password,
+            Credential::Oidc { .. } => LoginKind::Oidc,
+        };
+
+        Self {
+            id: user.id,
+            username: user.username,
+            name: user.name,
+            role: user.role,
+            org_id: user.org_id,
+            fields: user.fields,
+            login,
+            created_at: user.created_at,
+        }
+    }
+}
+
+/// Body of `POST /api/users`. Set exactly one of `password` and
+/// `oidc_subject`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateUser {
+    pub username: String,
+    pub name: String,
+    pub role: Role,
+    pub password: Option<String>,
+    /// The user's subject at the configured OIDC provider
+    pub oidc_subject: Option<String>,
+    /// Field H3 cells in hex the user is limited to; every field when empty
+    #[serde(default)]
+    pub fields: Vec<String>,
+    /// Organization the user belongs to. Defaults to the caller's; only
+    /// platform-wide keys may pick another one or leave it empty.
+    #[serde(default)]
+    pub org_id: Option<OrgId>,
+}
+
+/// Body of `PATCH /api/users/{id}`. Fields left out are kept.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdateUser {
+    pub name: Option<String>,
+    pub role: Option<Role>,
+    /// Replaces the password and ends the user's sessions
+    pub password: Option<String>,
+    pub fields: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct OidcLogin {
+    /// Access token issued by the configured OIDC provider
+    pub access_token: String,
+}
+
+/// A new session. Send `token` as the API key until `expires_at`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: Timestamp,
+    pub user: UserInfo,
+}
+
+fn check_password(password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiError::BadRequest(format!(
+            "password must be at least {MIN_PASSWORD_LEN} characters"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Run Argon2 `work` on the blocking pool, as it takes long enough to hold
+/// up every other task on a runtime worker.
+async fn argon2<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, ApiError> {
+    tokio::task::spawn_blocking(work).await.map_err(|e| {
+        tracing::error!(error = %e, "password hashing failed");
+        ApiError::Internal
+    })
+}
+
+async fn hash_password(password: String) -> Result<String, ApiError> {
+    argon2(move || user::hash_password(&password)).await
+}
+
+/// Parse the fields a user is limited to. Callers limited to fields may only
+/// hand out fields of their own.
+async fn parse_fields<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    config: &IndicatorConfig,
+    fields: &[String],
+) -> Result<Vec<H3Cell>, ApiError> {
+    if fields.is_empty() && principal.fields.is_some() {
+        return Err(ApiError::Forbidden);
+    }
+
+    let mut cells = Vec::with_capacity(fields.len());
+    for field in fields {
+        cells.push(visible_field(registries, principal, config, field).await?);
+    }
+    cells.sort_by_key(|cell| cell.0);
+    cells.dedup();
+
+    Ok(cells)
+}
+
+/// The user, if it exists and the caller may see it.
+async fn visible_user<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: UserId,
+) -> Result<User, ApiError> {
+    let user = registries
+        .users()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    principal.check_access(user.org_id)?;
+
+    Ok(user)
+}
+
+/// The user, if the caller may see it and change it. Callers limited to
+/// fields may only change users limited to fields of their own.
+async fn managed_user<R: Registries>(
+    registries: &R,
+    principal: &Principal,
+    id: UserId,
+) -> Result<User, ApiError> {
+    let user = visible_user(registries, principal, id).await?;
+    if principal.fields.is_some()
+        && (user.fields.is_empty() || !user.fields.iter().all(|f| principal.can_see(*f)))
+    {
+        return Err(ApiError::Forbidden);
+    }
+
+    Ok(user)
+}
+
+/// Revoke every live session key of `user`.
+async fn end_sessions<R: Registries>(registries: &R, user: UserId) -> Result<(), ApiError> {
+    let keys = registries.api_keys();
+    let now = Timestamp::now();
+    for key in keys.list().await.map_err(ApiError::registry)? {
+        if key.user_id == Some(user) && key.revoked_at.is_none() {
+            keys.revoke(key.id, now).await.map_err(ApiError::registry)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Issue a session key for `user`.
+async fn start_session<R: Registries>(
+    registries: &R,
+    config: &UserConfig,
+    user: User,
+) -> Result<Json<Session>, ApiError> {
+    let lifetime = SignedDuration::from_hours(i64::from(config.session_hours));
+    let (key, token) = user.session(lifetime, Timestamp::now());
+    registries
+        .api_keys()
+        .create(key.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    tracing::info!(user_id = ?user.id, key_id = ?key.id, "user logged in");
+
+    Ok(Json(Session {
+        token,
+        expires_at: key.expires_at.unwrap_or(key.created_at),
+        user: user.into(),
+    }))
+}
+
+/// `POST /api/users`
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "users",
+    request_body = CreateUser,
+    responses(
+        (status = 201, description = "User created", body = UserInfo),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key, or a user for another organization", body = ErrorBody),
+        (status = 409, description = "The username is taken", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(config): Extension<UserConfig>,
+    Extension(indicators): Extension<IndicatorConfig>,
+    Json(request): Json<CreateUser>,
+) -> Result<(StatusCode, Json<UserInfo>), ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let username = request.username.trim();
+    if username.is_empty() {
+        return Err(ApiError::BadRequest(
+            "username must not be empty".to_owned(),
+        ));
+    }
+    let credential = match (request.password, request.oidc_subject) {
+        (Some(password), None) => {
+            check_password(&password)?;
+            Credential::Password {
+                hash: hash_password(password).await?,
+            }
+        }
+        (None, Some(subject)) => {
+            let Some(oidc) = &config.oidc else {
+                return Err(ApiError::BadRequest(
+                    "no OIDC provider is configured".to_owned(),
+                ));
+            };
+            Credential::Oidc {
+                issuer: oidc.issuer.clone(),
+                subject,
+            }
+        }
+        _ => {
+            return Err(ApiError::BadRequest(
+                "set exactly one of password and oidc_subject".to_owned(),
+            ));
+        }
+    };
+
+    let org_id = match principal.org_id {
+        Some(own) if request.org_id.is_some_and(|org_id| org_id != own) => {
+            return Err(ApiError::Forbidden);
+        }
+        Some(own) => Some(own),
+        None => request.org_id,
+    };
+    if let Some(org_id) = org_id {
+        registries
+            .orgs()
+            .get(org_id)
+            .await
+            .map_err(ApiError::registry)?
+            .ok_or_else(|| ApiError::BadRequest("unknown organization".to_owned()))?;
+    }
+
+    let users = registries.users();
+    if users
+        .get_by_username(username)
+        .await
+        .map_err(ApiError::registry)?
+        .is_some()
+    {
+        return Err(ApiError::Conflict("the username is taken".to_owned()));
+    }
+
+    let mut user = User::new(username.to_owned(), request.name, request.role, credential);
+    user.org_id = org_id;
+    user.fields = parse_fields(&registries, &principal, &indicators, &request.fields).await?;
+    users
+        .create(user.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(&principal, AuditAction::Create, EntityKind::User, user.id.0)
+            .with_details(serde_json::json!({ "username": user.username, "role": user.role })),
+    )
+    .await?;
+
+    tracing::info!(user_id = ?user.id, role = ?user.role, created_by = ?principal.key_id, "user created");
+
+    Ok((StatusCode::CREATED, Json(user.into())))
+}
+
+/// `GET /api/users`
+#[utoipa::path(
+    get,
+    path = "/api/users",
+    tag = "users",
+    responses(
+        (status = 200, description = "Users visible to the caller", body = Vec<UserInfo>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<UserInfo>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let users = registries
+        .users()
+        .list()
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(
+        users
+            .into_iter()
+            .filter(|user| principal.can_access(user.org_id))
+            .map(UserInfo::from)
+            .collect(),
+    ))
+}
+
+/// `GET /api/users/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}",
+    tag = "users",
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "The user", body = UserInfo),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<UserInfo>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let user = visible_user(&registries, &principal, UserId(id)).await?;
+
+    Ok(Json(user.into()))
+}
+
+/// `PATCH /api/users/{id}`
+///
+/// Role and field changes apply to the user's open sessions right away.
+#[utoipa::path(
+    patch,
+    path = "/api/users/{id}",
+    tag = "users",
+    params(("id" = String, Path, description = "User id")),
+    request_body = UpdateUser,
+    responses(
+        (status = 200, description = "User updated", body = UserInfo),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key, or a user outside the caller's fields", body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+pub async fn update<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Extension(indicators): Extension<IndicatorConfig>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<UpdateUser>,
+) -> Result<Json<UserInfo>, ApiError> {
+    principal.require(Scope::Admin)?;
+    let mut user = managed_user(&registries, &principal, UserId(id)).await?;
+
+    let mut changed = Vec::new();
+    if let Some(name) = request.name {
+        user.name = name;
+        changed.push("name");
+    }
+    if let Some(role) = request.role {
+        user.role = role;
+        changed.push("role");
+    }
+    if let Some(fields) = request.fields {
+        user.fields = parse_fields(&registries, &principal, &indicators, &fields).await?;
+        changed.push("fields");
+    }
+    let password_changed = request.password.is_some();
+    if let Some(password) = request.password {
+        if !matches!(user.credential, Credential::Password { .. }) {
+            return Err(ApiError::BadRequest(
+                "the user logs in through OIDC".to_owned(),
+            ));
+        }
+        check_password(&password)?;
+        user.credential = Credential::Password {
+            hash: hash_password(password).await?,
+        };
+        changed.push("password");
+    }
+
+    registries
+        .users()
+        .update(user.clone())
+        .await
+        .map_err(ApiError::registry)?;
+    if password_changed {
+        end_sessions(&registries, user.id).await?;
+    }
+
+    record_audit(
+        &registries,
+        AuditEntry::by(&principal, AuditAction::Update, EntityKind::User, user.id.0)
+            .with_details(serde_json::json!({ "fields": changed })),
+    )
+    .await?;
+
+    Ok(Json(user.into()))
+}
+
+/// `DELETE /api/users/{id}`
+///
+/// The user's sessions end along with it.
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}",
+    tag = "users",
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 403, description = "Not an admin key, or a user outside the caller's fields", body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+pub async fn delete<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+    let user = managed_user(&registries, &principal, UserId(id)).await?;
+
+    registries
+        .users()
+        .delete(user.id)
+        .await
+        .map_err(ApiError::registry)?;
+    end_sessions(&registries, user.id).await?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(&principal, AuditAction::Delete, EntityKind::User, user.id.0),
+    )
+    .await?;
+
+    tracing::info!(user_id = ?user.id, deleted_by = ?principal.key_id, "user deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `POST /api/auth/login`
+///
+/// Log in with a username and password. Needs no API key.
+#[utoipa::path(
+    post,
+    path = "/api/auth/login",
+    tag = "auth",
+    request_body = Login,
+    security(()),
+    responses(
+        (status = 200, description = "Logged in", body = Session),
+        (status = 401, description = "Unknown user or wrong password", body = ErrorBody),
+    )
+)]
+pub async fn login<R: Registries>(
+    State(registries): State<R>,
+    Extension(config): Extension<UserConfig>,
+    Json(request): Json<Login>,
+) -> Result<Json<Session>, ApiError> {
+    let user = registries
+        .users()
+        .get_by_username(request.username.trim())
+        .await
+        .map_err(ApiError::registry)?;
+
+    let password = request.password;
+    let Some(user) = user else {
+        argon2(move || user::reject_unknown(&password)).await?;
+        return Err(ApiError::Unauthorized);
+    };
+    let (user, verified) = argon2(move || {
+        let verified = user.verify_password(&password);
+        (user, verified)
+    })
+    .await?;
+    if !verified {
+        tracing::warn!(user_id = ?user.id, "rejected login");
+        return Err(ApiError::Unauthorized);
+    }
+
+    start_session(&registries, &config, user).await
+}
+
+/// `POST /api/auth/oidc`
+///
+/// Log in with an access token from the configured OIDC provider, for users
+/// created with its subject. Needs no API key.
+#[utoipa::path(
+    post,
+    path = "/api/auth/oidc",
+    tag = "auth",
+    request_body = OidcLogin,
+    security(()),
+    responses(
+        (status = 200, description = "Logged in", body = Session),
+        (status = 400, description = "No OIDC provider is configured", body = ErrorBody),
+        (status = 401, description = "The provider rejected the token, or no user has its subject", body = ErrorBody),
+    )
+)]
+pub async fn oidc<R: Registries>(
+    State(registries): State<R>,
+    Extension(config): Extension<UserConfig>,
+    Extension(client): Extension<reqwest::Client>,
+    Json(request): Json<OidcLogin>,
+) -> Result<Json<Session>, ApiError> {
+    let Some(oidc) = &config.oidc else {
+        return Err(ApiError::BadRequest(
+            "no OIDC provider is configured".to_owned(),
+        ));
+    };
+
+    let subject = user::oidc_subject(&client, oidc, &request.access_token)
+        .await
+        .map_err(|e| {
+            tracing::warn!(error = %e, "rejected OIDC login");
+            ApiError::Unauthorized
+        })?;
+    let user = registries
+        .users()
+        .get_by_subject(&oidc.issuer, &subject)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::Unauthorized)?;
+
+    start_session(&registries, &config, user).await
+}
+
+/// `GET /api/auth/me`
+#[utoipa::path(
+    get,
+    path = "/api/auth/me",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The logged in user", body = UserInfo),
+        (status = 404, description = "The key is not a session", body = ErrorBody),
+    )
+)]
+pub async fn me<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<UserInfo>, ApiError> {
+    let id = principal.user_id.ok_or(ApiError::NotFound)?;
+    let user = registries
+        .users()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(Json(user.into()))
+}
+
+/// `POST /api/auth/logout`
+///
+/// End the session the request was made with.
+#[utoipa::path(
+    post,
+    path = "/api/auth/logout",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Session ended"),
+        (status = 400, description = "The key is not a session", body = ErrorBody),
+    )
+)]
+pub async fn logout<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<StatusCode, ApiError> {
+    if principal.user_id.is_none() {
+        return Err(ApiError::BadRequest(
+            "only sessions can be logged out".to_owned(),
+        ));
+    }
+
+    revoke_session(&registries, principal.key_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn revoke_session<R: Registries>(registries: &R, id: ApiKeyId) -> Result<(), ApiError> {
+    registries
+        .api_keys()
+        .revoke(id, Timestamp::now())
+        .await
+        .map_err(ApiError::registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Extension, Json,
+        extract::{Path, Query, State},
+        http::StatusCode,
+    };
+    use ersha_core::{Device, DeviceId, DeviceKind, DeviceState, H3Cell};
+    use ulid::Ulid;
+
+    use super::{CreateUser, Login, UpdateUser, create, delete, login, me, update};
+    use crate::api::{ApiError, devices};
+    use crate::auth::{Principal, Scope, parse_token};
+    use crate::config::{IndicatorConfig, UserConfig};
+    use crate::fixtures::admin;
+    use crate::region;
+    use crate::registry::memory::InMemoryRegistries;
+    use crate::registry::{ApiKeyRegistry, DeviceRegistry, Registries, UserRegistry};
+    use crate::user::{Role, UserId};
+
+    const FIELD: H3Cell = H3Cell(0x892a1072b5bffff);
+
+    async fn device_in(registries: &InMemoryRegistries, location: H3Cell) -> DeviceId {
+        let id = DeviceId(Ulid::new());
+        registries
+            .devices()
+            .register(Device {
+                id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location,
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn officers_log_in_and_see_only_their_fields() {
+        let registries = InMemoryRegistries::default();
+        let indicators = IndicatorConfig::default();
+        let ours = device_in(&registries, H3Cell(0x8a2a1072b59ffff)).await;
+        let theirs = device_in(&registries, H3Cell(0x8a2a1072b4a7fff)).await;
+
+        let (status, Json(officer)) = create(
+            State(registries.clone()),
+            admin(),
+            Extension(UserConfig::default()),
+            Extension(indicators),
+            Json(CreateUser {
+                username: "officer".to_owned(),
+                name: "Extension Officer".to_owned(),
+                role: Role::Viewer,
+                password: Some("correct horse".to_owned()),
+                oidc_subject: None,
+                fields: vec![format!("{:x}", FIELD.0)],
+                org_id: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(officer.fields, vec![FIELD]);
+
+        let attempt = |password: &str| {
+            login(
+                State(registries.clone()),
+                Extension(UserConfig::default()),
+                Json(Login {
+                    username: "officer".to_owned(),
+                    password: password.to_owned(),
+                }),
+            )
+        };
+        assert!(matches!(
+            attempt("wrong horse").await,
+            Err(ApiError::Unauthorized)
+        ));
+        let Json(session) = attempt("correct horse").await.unwrap();
+
+        let (key_id, _) = parse_token(&session.token).unwrap();
+        let key = registries.api_keys().get(key_id).await.unwrap().unwrap();
+        let user = registries.users().get(officer.id).await.unwrap().unwrap();
+        let principal = Extension(user.principal(key.id));
+        assert_eq!(principal.scope, Scope::ReadOnly);
+
+        let Json(me) = me(State(registries.clone()), principal.clone())
+            .await
+            .unwrap();
+        assert_eq!(me.username, "officer");
+
+        let page = devices::list(
+            State(registries.clone()),
+            principal.clone(),
+            Query(devices::DevicesQuery::default()),
+            Query(Vec::new()),
+        )
+        .await
+        .unwrap();
+        let visible: Vec<DeviceId> = page.items.iter().map(|d| d.id).collect();
+        assert_eq!(visible, vec![ours]);
+        assert!(matches!(
+            devices::get(State(registries), principal, Path(theirs.0)).await,
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    async fn user_in(registries: &InMemoryRegistries, username: &str, fields: &[H3Cell]) -> UserId {
+        let (_, Json(user)) = create(
+            State(registries.clone()),
+            admin(),
+            Extension(UserConfig::default()),
+            Extension(IndicatorConfig::default()),
+            Json(CreateUser {
+                username: username.to_owned(),
+                name: username.to_owned(),
+                role: Role::Viewer,
+                password: Some("correct horse".to_owned()),
+                oidc_subject: None,
+                fields: fields
+                    .iter()
+                    .map(|field| format!("{:x}", field.0))
+                    .collect(),
+                org_id: None,
+            }),
+        )
+        .await
+        .unwrap();
+        user.id
+    }
+
+    #[tokio::test]
+    async fn admins_limited_to_fields_only_manage_users_within_them() {
+        let registries = InMemoryRegistries::default();
+        let elsewhere = region::parent(H3Cell(0x8a2a1072b4a7fff), 9).unwrap();
+        let ours = user_in(&registries, "ours", &[FIELD]).await;
+        let everywhere = user_in(&registries, "everywhere", &[]).await;
+        let theirs = user_in(&registries, "theirs", &[elsewhere]).await;
+        let limited = Extension(Principal {
+            fields: Some(Arc::from([FIELD])),
+            ..admin().0
+        });
+        let promote = || {
+            Json(UpdateUser {
+                role: Some(Role::Admin),
+                ..UpdateUser::default()
+            })
+        };
+
+        // Neither users unlimited to fields nor those in other fields can be
+        // changed or deleted.
+        for id in [everywhere, theirs] {
+            assert!(matches!(
+                update(
+                    State(registries.clone()),
+                    limited.clone(),
+                    Extension(IndicatorConfig::default()),
+                    Path(id.0),
+                    promote(),
+                )
+                .await,
+                Err(ApiError::Forbidden)
+            ));
+            assert!(matches!(
+                delete(State(registries.clone()), limited.clone(), Path(id.0)).await,
+                Err(ApiError::Forbidden)
+            ));
+            let user = registries.users().get(id).await.unwrap().unwrap();
+            assert_eq!(user.role, Role::Viewer);
+        }
+
+        let Json(promoted) = update(
+            State(registries.clone()),
+            limited,
+            Extension(IndicatorConfig::default()),
+            Path(ours.0),
+            promote(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(promoted.role, Role::Admin);
+    }
+}
@@ -0,0 +1,394 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use ersha_core::{H3Cell, SensorKind};
+use serde::Deserialize;
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use super::{ApiError, ErrorBody, nullable, record_audit};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::registry::{Registries, ValidationRuleRegistry};
+use crate::validation::{ValidationRule, ValidationRuleId};
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateValidationRule {
+    pub metric: SensorKind,
+    /// Only readings taken inside this cell; everywhere if left out
+    pub within: Option<H3Cell>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Largest change from the sensor's previous reading, per hour
+    pub max_change_per_hour: Option<f64>,
+}
+
+/// Body of `PATCH /api/validation-rules/{id}`. Fields left out are kept;
+/// `null` removes a limit.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdateValidationRule {
+    pub metric: Option<SensorKind>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<H3Cell>)]
+    pub within: Option<Option<H3Cell>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<f64>)]
+    pub min: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<f64>)]
+    pub max: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<f64>)]
+    pub max_change_per_hour: Option<Option<f64>>,
+}
+
+/// Reject rules that limit nothing or can never be met.
+fn validate(rule: &ValidationRule) -> Result<(), ApiError> {
+    let bad_request = |reason: &str| Err(ApiError::BadRequest(reason.to_owned()));
+    let limits = [rule.min, rule.max, rule.max_change_per_hour];
+
+    if limits.iter().all(Option::is_none) {
+        return bad_request("set at least one of min, max and max_change_per_hour");
+    }
+    if limits.iter().flatten().any(|limit| !limit.is_finite()) {
+        return bad_request("limits must be finite numbers");
+    }
+    if let (Some(min), Some(max)) = (rule.min, rule.max)
+        && min > max
+    {
+        return bad_request("min must not be above max");
+    }
+    if rule.max_change_per_hour.is_some_and(|limit| limit <= 0.0) {
+        return bad_request("max_change_per_hour must be positive");
+    }
+
+    Ok(())
+}
+
+async fn find_rule<R: Registries>(
+    registries: &R,
+    id: ValidationRuleId,
+) -> Result<ValidationRule, ApiError> {
+    registries
+        .validation_rules()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)
+}
+
+/// `POST /api/validation-rules`
+///
+/// Rules apply to readings ingested from then on, across organizations.
+#[utoipa::path(
+    post,
+    path = "/api/validation-rules",
+    tag = "validation",
+    request_body = CreateValidationRule,
+    responses(
+        (status = 201, description = "Rule created", body = ValidationRule),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<CreateValidationRule>,
+) -> Result<(StatusCode, Json<ValidationRule>), ApiError> {
+    principal.require_platform(Scope::Admin)?;
+
+    let mut rule = ValidationRule::new(request.metric);
+    rule.within = request.within;
+    rule.min = request.min;
+    rule.max = request.max;
+    rule.max_change_per_hour = request.max_change_per_hour;
+    validate(&rule)?;
+
+    registries
+        .validation_rules()
+        .create(rule.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::ValidationRule,
+            rule.id.0,
+        )
+        .with_details(serde_json::json!({ "metric": rule.metric })),
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+/// `GET /api/validation-rules`
+#[utoipa::path(
+    get,
+    path = "/api/validation-rules",
+    tag = "validation",
+    responses(
+        (status = 200, description = "Every validation rule", body = Vec<ValidationRule>),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<ValidationRule>>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    let rules = registries
+        .validation_rules()
+        .list()
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(rules))
+}
+
+/// `GET /api/validation-rules/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/validation-rules/{id}",
+    tag = "validation",
+    params(("id" = String, Path, description = "Validation rule id")),
+    responses(
+        (status = 200, description = "The rule", body = ValidationRule),
+        (status = 404, description = "Unknown rule", body = ErrorBody),
+    )
+)]
+pub async fn get<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<Json<ValidationRule>, ApiError> {
+    principal.require(Scope::ReadOnly)?;
+
+    Ok(Json(find_rule(&registries, ValidationRuleId(id)).await?))
+}
+
+/// `PATCH /api/validation-rules/{id}`
+///
+/// Readings already flagged keep their status.
+#[utoipa::path(
+    patch,
+    path = "/api/validation-rules/{id}",
+    tag = "validation",
+    params(("id" = String, Path, description = "Validation rule id")),
+    request_body = UpdateValidationRule,
+    responses(
+        (status = 200, description = "Rule updated", body = ValidationRule),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+        (status = 404, description = "Unknown rule", body = ErrorBody),
+    )
+)]
+pub async fn update<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Json(request): Json<UpdateValidationRule>,
+) -> Result<Json<ValidationRule>, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+    let mut rule = find_rule(&registries, ValidationRuleId(id)).await?;
+
+    let mut changed = Vec::new();
+    if let Some(metric) = request.metric {
+        rule.metric = metric;
+        changed.push("metric");
+    }
+    if let Some(within) = request.within {
+        rule.within = within;
+        changed.push("within");
+    }
+    if let Some(min) = request.min {
+        rule.min = min;
+        changed.push("min");
+    }
+    if let Some(max) = request.max {
+        rule.max = max;
+        changed.push("max");
+    }
+    if let Some(limit) = request.max_change_per_hour {
+        rule.max_change_per_hour = limit;
+        changed.push("max_change_per_hour");
+    }
+    validate(&rule)?;
+
+    registries
+        .validation_rules()
+        .update(rule.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Update,
+            EntityKind::ValidationRule,
+            rule.id.0,
+        )
+        .with_details(serde_json::json!({ "fields": changed })),
+    )
+    .await?;
+
+    Ok(Json(rule))
+}
+
+/// `DELETE /api/validation-rules/{id}`
+#[utoipa::path(
+    delete,
+    path = "/api/validation-rules/{id}",
+    tag = "validation",
+    params(("id" = String, Path, description = "Validation rule id")),
+    responses(
+        (status = 204, description = "Rule deleted"),
+        (status = 403, description = "Not a platform-wide admin key", body = ErrorBody),
+        (status = 404, description = "Unknown rule", body = ErrorBody),
+    )
+)]
+pub async fn delete<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require_platform(Scope::Admin)?;
+    let rule = find_rule(&registries, ValidationRuleId(id)).await?;
+
+    registries
+        .validation_rules()
+        .delete(rule.id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Delete,
+            EntityKind::ValidationRule,
+            rule.id.0,
+        ),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Json,
+        extract::{Path, State},
+        http::StatusCode,
+    };
+    use ersha_core::query::{List, ReadingQuery};
+    use ersha_core::{
+        DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId, SensorKind, SensorMetric,
+        SensorReading,
+    };
+    use tokio_util::sync::CancellationToken;
+    use ulid::Ulid;
+
+    use super::{CreateValidationRule, UpdateValidationRule, create, update};
+    use crate::api::readings::list;
+    use crate::api::{ApiError, ApiQuery};
+    use crate::auth::Scope;
+    use crate::events::{BusEvent, EventBus, LocalEventBus};
+    use crate::fixtures::{admin, principal};
+    use crate::org::OrgId;
+    use crate::registry::{ReadingRegistry, memory::InMemoryRegistries};
+    use crate::validation::{self, QualityStatus};
+
+    fn moisture(value: u8) -> SensorReading {
+        SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(value),
+            },
+            location: H3Cell(0x8a2a1072b59ffff),
+            confidence: Percentage(90),
+            timestamp: jiff::Timestamp::now(),
+            sensor_id: SensorId(Ulid::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn ingested_readings_are_flagged_by_the_rules() {
+        let registries = InMemoryRegistries::default();
+        let request = || CreateValidationRule {
+            metric: SensorKind::SoilMoisture,
+            within: None,
+            min: Some(5.0),
+            max: Some(70.0),
+            max_change_per_hour: None,
+        };
+
+        let org_admin = create(
+            State(registries.clone()),
+            principal(Scope::Admin, Some(OrgId(Ulid::new()))),
+            Json(request()),
+        )
+        .await;
+        assert!(matches!(org_admin, Err(ApiError::Forbidden)));
+        let (status, Json(rule)) = create(State(registries.clone()), admin(), Json(request()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let inverted = update(
+            State(registries.clone()),
+            admin(),
+            Path(rule.id.0),
+            Json(UpdateValidationRule {
+                min: Some(Some(90.0)),
+                ..Default::default()
+            }),
+        )
+        .await;
+        assert!(matches!(inverted, Err(ApiError::BadRequest(_))));
+
+        let (normal, spike) = (moisture(40), moisture(95));
+        registries
+            .readings
+            .batch_store(vec![normal.clone(), spike.clone()])
+            .await
+            .unwrap();
+        let bus = LocalEventBus::new();
+        let task = tokio::spawn(validation::run(
+            registries.clone(),
+            bus.subscribe(),
+            CancellationToken::new(),
+        ));
+        bus.publish(BusEvent::ReadingsIngested {
+            readings: Arc::new([normal, spike.clone()]),
+            late: false,
+        })
+        .await;
+        drop(bus);
+        task.await.unwrap();
+
+        let page = list(
+            State(registries),
+            admin(),
+            ApiQuery(ReadingQuery {
+                quality: List(vec![QualityStatus::OutOfRange]),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        let ids: Vec<ReadingId> = page.items.iter().map(|reading| reading.id).collect();
+        assert_eq!(ids, [spike.id]);
+    }
+}
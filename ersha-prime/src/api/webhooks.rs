@@ -0,0 +1,421 @@
+use std::net::IpAddr;
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::{IntoParams, ToSchema};
+
+use super::{ApiError, ErrorBody, page_limit, record_audit};
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::{Principal, Scope};
+use crate::org::OrgId;
+use crate::registry::{Registries, WebhookRegistry};
+use crate::webhook::{Delivery, EventKind, Threshold, Webhook, WebhookId};
+
+/// A webhook as returned by the API. The signing secret is never exposed.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookInfo {
+    pub id: WebhookId,
+    pub url: String,
+    pub events: Vec<EventKind>,
+    pub thresholds: Vec<Threshold>,
+    /// Absent for platform-wide webhooks, which receive every organization's events
+    pub org_id: Option<OrgId>,
+    pub created_at: jiff::Timestamp,
+}
+
+impl From<Webhook> for WebhookInfo {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            thresholds: webhook.thresholds,
+            org_id: webhook.org_id,
+            created_at: webhook.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateWebhook {
+    /// `http` or `https` URL that events are POSTed to
+    pub url: String,
+    pub events: Vec<EventKind>,
+    /// Required when subscribing to `threshold_crossed`
+    #[serde(default)]
+    pub thresholds: Vec<Threshold>,
+}
+
+impl CreateWebhook {
+    fn validate(&self) -> Result<Url, ApiError> {
+        let url =
+            Url::parse(&self.url).map_err(|e| ApiError::BadRequest(format!("invalid url: {e}")))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(ApiError::BadRequest(
+                "url must use http or https".to_owned(),
+            ));
+        }
+
+        if self.events.is_empty() {
+            return Err(ApiError::BadRequest(
+                "subscribe to at least one event".to_owned(),
+            ));
+        }
+
+        let wants_thresholds = self.events.contains(&EventKind::ThresholdCrossed);
+        if wants_thresholds && self.thresholds.is_empty() {
+            return Err(ApiError::BadRequest(
+                "threshold_crossed needs at least one threshold".to_owned(),
+            ));
+        }
+        if self.thresholds.iter().any(|t| !t.value.is_finite()) {
+            return Err(ApiError::BadRequest(
+                "threshold values must be finite".to_owned(),
+            ));
+        }
+
+        Ok(url)
+    }
+}
+
+/// Refuse URLs whose host resolves to a loopback, private, link-local or
+/// otherwise non-public address, so webhooks can't reach into prime's own
+/// network.
+async fn check_target(url: &Url) -> Result<(), ApiError> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| ApiError::BadRequest("url must have a host".to_owned()))?;
+    // IPv6 hosts keep their brackets.
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<IpAddr> = match literal.parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            let port = url.port_or_known_default().unwrap_or(443);
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| ApiError::BadRequest(format!("cannot resolve {host}: {e}")))?
+                .map(|addr| addr.ip())
+                .collect()
+        }
+    };
+
+    if addrs.is_empty() || !addrs.into_iter().all(is_global) {
+        return Err(ApiError::BadRequest(
+            "url must point at a public address".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether `ip` is publicly routable.
+fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Shared address space (carrier-grade NAT)
+                || (a == 100 && (64..128).contains(&b))
+                // Benchmarking
+                || (a == 198 && (18..20).contains(&b))
+                // Reserved
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_global(ip.into());
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local
+                || (first & 0xfe00) == 0xfc00
+                // Link-local
+                || (first & 0xffc0) == 0xfe80
+                // Documentation
+                || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+        }
+    }
+}
+
+/// A newly created webhook. `secret` is shown only once.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: WebhookInfo,
+    /// Key for verifying the payload signature
+    pub secret: String,
+}
+
+/// `POST /api/webhooks`
+///
+/// The webhook belongs to the caller's organization and only receives its
+/// events.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 201, description = "Webhook created; the secret is shown only once", body = CreatedWebhook),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn create<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<CreateWebhook>,
+) -> Result<(StatusCode, Json<CreatedWebhook>), ApiError> {
+    principal.require(Scope::Admin)?;
+    let url = request.validate()?;
+    check_target(&url).await?;
+
+    let mut webhook = Webhook::generate(request.url, request.events, request.thresholds);
+    webhook.org_id = principal.org_id;
+    registries
+        .webhooks()
+        .create(webhook.clone())
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(
+            &principal,
+            AuditAction::Create,
+            EntityKind::Webhook,
+            webhook.id.0,
+        )
+        .with_details(serde_json::json!({ "url": webhook.url })),
+    )
+    .await?;
+
+    tracing::info!(webhook_id = ?webhook.id, url = %webhook.url, created_by = ?principal.key_id, "webhook created");
+
+    let secret = webhook.secret.clone();
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedWebhook {
+            webhook: webhook.into(),
+            secret,
+        }),
+    ))
+}
+
+/// `GET /api/webhooks`
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Webhooks visible to the caller", body = Vec<WebhookInfo>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    )
+)]
+pub async fn list<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<WebhookInfo>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let webhooks = registries
+        .webhooks()
+        .list()
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(
+        webhooks
+            .into_iter()
+            .filter(|webhook| principal.can_access(webhook.org_id))
+            .map(WebhookInfo::from)
+            .collect(),
+    ))
+}
+
+/// `DELETE /api/webhooks/{id}`
+///
+/// Pending deliveries are dropped along with the webhook.
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "Webhook deleted"),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown webhook", body = ErrorBody),
+    )
+)]
+pub async fn delete<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+) -> Result<StatusCode, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let id = WebhookId(id);
+    let webhook = registries
+        .webhooks()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    principal.check_access(webhook.org_id)?;
+
+    registries
+        .webhooks()
+        .delete(id)
+        .await
+        .map_err(ApiError::registry)?;
+
+    record_audit(
+        &registries,
+        AuditEntry::by(&principal, AuditAction::Delete, EntityKind::Webhook, id.0),
+    )
+    .await?;
+
+    tracing::info!(webhook_id = ?id, deleted_by = ?principal.key_id, "webhook deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Query parameters for `GET /api/webhooks/{id}/deliveries`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveriesQuery {
+    pub limit: Option<usize>,
+}
+
+/// `GET /api/webhooks/{id}/deliveries`
+///
+/// The webhook's most recent deliveries and their status, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id"), DeliveriesQuery),
+    responses(
+        (status = 200, description = "Recent deliveries", body = Vec<Delivery>),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+        (status = 404, description = "Unknown webhook", body = ErrorBody),
+    )
+)]
+pub async fn deliveries<R: Registries>(
+    State(registries): State<R>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Ulid>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Result<Json<Vec<Delivery>>, ApiError> {
+    principal.require(Scope::Admin)?;
+
+    let id = WebhookId(id);
+    let webhook = registries
+        .webhooks()
+        .get(id)
+        .await
+        .map_err(ApiError::registry)?
+        .ok_or(ApiError::NotFound)?;
+    principal.check_access(webhook.org_id)?;
+
+    let deliveries = registries
+        .webhooks()
+        .deliveries(id, page_limit(query.limit)?)
+        .await
+        .map_err(ApiError::registry)?;
+
+    Ok(Json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::SensorKind;
+    use reqwest::Url;
+
+    use super::{CreateWebhook, check_target};
+    use crate::api::ApiError;
+    use crate::webhook::{Direction, EventKind, Threshold};
+
+    fn request(url: &str, events: Vec<EventKind>, thresholds: Vec<Threshold>) -> CreateWebhook {
+        CreateWebhook {
+            url: url.to_owned(),
+            events,
+            thresholds,
+        }
+    }
+
+    #[test]
+    fn invalid_subscriptions_are_rejected() {
+        let threshold = Threshold {
+            metric: SensorKind::Rainfall,
+            direction: Direction::Above,
+            value: 50.0,
+        };
+        let rejected =
+            |request: CreateWebhook| matches!(request.validate(), Err(ApiError::BadRequest(_)));
+
+        assert!(rejected(request(
+            "ftp://example.com",
+            vec![EventKind::AlertRaised],
+            vec![]
+        )));
+        assert!(rejected(request("https://example.com", vec![], vec![])));
+        assert!(rejected(request(
+            "https://example.com",
+            vec![EventKind::ThresholdCrossed],
+            vec![]
+        )));
+        assert!(
+            request(
+                "https://example.com/hook",
+                vec![EventKind::ThresholdCrossed],
+                vec![threshold]
+            )
+            .validate()
+            .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn webhooks_must_target_public_addresses() {
+        let target = |url: &str| {
+            let url = Url::parse(url).unwrap();
+            async move { check_target(&url).await }
+        };
+
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://10.0.0.5/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(
+                matches!(target(url).await, Err(ApiError::BadRequest(_))),
+                "{url} was accepted"
+            );
+        }
+
+        assert!(target("https://93.184.215.14/hook").await.is_ok());
+        assert!(target("https://[2606:4700::1111]/hook").await.is_ok());
+    }
+}
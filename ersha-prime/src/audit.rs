@@ -0,0 +1,198 @@
+//! Record of who changed what in the registries.
+//!
+//! Entries are append-only. Nothing in prime hard-deletes devices or
+//! dispatchers; decommissioning and suspension are state changes, so the
+//! audited entity stays queryable after the change.
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::auth::{ApiKeyId, Principal};
+use crate::org::OrgId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct AuditId(pub Ulid);
+
+/// The kind of record a change was made to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityKind {
+    Device,
+    Dispatcher,
+    Org,
+    ApiKey,
+    Webhook,
+    Command,
+    IrrigationPlan,
+    Contact,
+    User,
+    Correction,
+    DeadLetter,
+    Backfill,
+    Group,
+    ValidationRule,
+    Firmware,
+    Rollout,
+}
+
+impl EntityKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EntityKind::Device => "device",
+            EntityKind::Dispatcher => "dispatcher",
+            EntityKind::Org => "org",
+            EntityKind::ApiKey => "api_key",
+            EntityKind::Webhook => "webhook",
+            EntityKind::Command => "command",
+            EntityKind::IrrigationPlan => "irrigation_plan",
+            EntityKind::Contact => "contact",
+            EntityKind::User => "user",
+            EntityKind::Correction => "correction",
+            EntityKind::DeadLetter => "dead_letter",
+            EntityKind::Backfill => "backfill",
+            EntityKind::Group => "group",
+            EntityKind::ValidationRule => "validation_rule",
+            EntityKind::Firmware => "firmware",
+            EntityKind::Rollout => "rollout",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let kind = match s {
+            "device" => EntityKind::Device,
+            "dispatcher" => EntityKind::Dispatcher,
+            "org" => EntityKind::Org,
+            "api_key" => EntityKind::ApiKey,
+            "webhook" => EntityKind::Webhook,
+            "command" => EntityKind::Command,
+            "irrigation_plan" => EntityKind::IrrigationPlan,
+            "contact" => EntityKind::Contact,
+            "user" => EntityKind::User,
+            "correction" => EntityKind::Correction,
+            "dead_letter" => EntityKind::DeadLetter,
+            "backfill" => EntityKind::Backfill,
+            "group" => EntityKind::Group,
+            "validation_rule" => EntityKind::ValidationRule,
+            "firmware" => EntityKind::Firmware,
+            "rollout" => EntityKind::Rollout,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Register,
+    Update,
+    Suspend,
+    Reactivate,
+    /// Pending dispatcher let in by an operator
+    Approve,
+    Decommission,
+    /// Moved into or out of an organization
+    AssignOrg,
+    /// Assigned to or released from a dispatcher
+    AssignDispatcher,
+    ProvisionSecret,
+    Create,
+    Revoke,
+    Delete,
+    Enqueue,
+    /// Dead-lettered item sent through validation again
+    Redrive,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Register => "register",
+            AuditAction::Update => "update",
+            AuditAction::Suspend => "suspend",
+            AuditAction::Reactivate => "reactivate",
+            AuditAction::Approve => "approve",
+            AuditAction::Decommission => "decommission",
+            AuditAction::AssignOrg => "assign_org",
+            AuditAction::AssignDispatcher => "assign_dispatcher",
+            AuditAction::ProvisionSecret => "provision_secret",
+            AuditAction::Create => "create",
+            AuditAction::Revoke => "revoke",
+            AuditAction::Delete => "delete",
+            AuditAction::Enqueue => "enqueue",
+            AuditAction::Redrive => "redrive",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let action = match s {
+            "register" => AuditAction::Register,
+            "update" => AuditAction::Update,
+            "suspend" => AuditAction::Suspend,
+            "reactivate" => AuditAction::Reactivate,
+            "approve" => AuditAction::Approve,
+            "decommission" => AuditAction::Decommission,
+            "assign_org" => AuditAction::AssignOrg,
+            "assign_dispatcher" => AuditAction::AssignDispatcher,
+            "provision_secret" => AuditAction::ProvisionSecret,
+            "create" => AuditAction::Create,
+            "revoke" => AuditAction::Revoke,
+            "delete" => AuditAction::Delete,
+            "enqueue" => AuditAction::Enqueue,
+            "redrive" => AuditAction::Redrive,
+            _ => return None,
+        };
+
+        Some(action)
+    }
+}
+
+/// One change to one record.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct AuditEntry {
+    pub id: AuditId,
+    pub at: Timestamp,
+    /// Key that made the change; `None` when prime made it itself, such as
+    /// registering a dispatcher on its first hello
+    pub actor: Option<ApiKeyId>,
+    /// Organization of the acting key
+    pub org_id: Option<OrgId>,
+    pub action: AuditAction,
+    pub entity: EntityKind,
+    pub entity_id: Ulid,
+    /// Action specific context, e.g. the organization something was assigned to
+    pub details: Option<serde_json::Value>,
+}
+
+impl AuditEntry {
+    /// A change made through the API by `principal`.
+    pub fn by(principal: &Principal, action: AuditAction, entity: EntityKind, id: Ulid) -> Self {
+        Self {
+            actor: Some(principal.key_id),
+            org_id: principal.org_id,
+            ..Self::system(action, entity, id)
+        }
+    }
+
+    /// A change prime made on its own.
+    pub fn system(action: AuditAction, entity: EntityKind, id: Ulid) -> Self {
+        Self {
+            id: AuditId(Ulid::new()),
+            at: Timestamp::now(),
+            actor: None,
+            org_id: None,
+            action,
+            entity,
+            entity_id: id,
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use ersha_core::H3Cell;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::api::ApiError;
+use crate::org::OrgId;
+use crate::registry::{ApiKeyRegistry, Registries, UserRegistry};
+use crate::user::UserId;
+
+/// Prefix of every API key token.
+const TOKEN_PREFIX: &str = "ek_";
+/// Header accepted as an alternative to `Authorization: Bearer`.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Unique identifier for an API key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyId(pub Ulid);
+
+/// What an API key is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Query data only.
+    ReadOnly,
+    /// Everything, including key management.
+    Admin,
+    /// Machine access for dispatchers.
+    Dispatcher,
+    /// Query data and manage irrigation.
+    Agronomist,
+}
+
+impl Scope {
+    /// Whether a key with this scope may perform an action requiring `required`.
+    pub fn allows(self, required: Scope) -> bool {
+        self == Scope::Admin
+            || self == required
+            || (self == Scope::Agronomist && required == Scope::ReadOnly)
+    }
+}
+
+/// A stored API key. Only the hash of the secret is kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: ApiKeyId,
+    pub name: String,
+    pub scope: Scope,
+    /// The organization the key acts for. Keys without one are
+    /// platform-wide and see every organization's data.
+    pub org_id: Option<OrgId>,
+    pub secret_hash: String,
+    pub created_at: jiff::Timestamp,
+    pub revoked_at: Option<jiff::Timestamp>,
+    /// The user a session key was issued to at login
+    pub user_id: Option<UserId>,
+    /// When a session key stops working
+    pub expires_at: Option<jiff::Timestamp>,
+}
+
+impl ApiKey {
+    /// Create a platform-wide key with a fresh secret, returning it with its token.
+    ///
+    /// The token is only available here; it cannot be recovered later.
+    pub fn generate(name: impl Into<String>, scope: Scope) -> (Self, String) {
+        let id = ApiKeyId(Ulid::new());
+        let secret = generate_secret();
+
+        let key = Self {
+            id,
+            name: name.into(),
+            scope,
+            org_id: None,
+            secret_hash: hash_secret(&secret),
+            created_at: jiff::Timestamp::now(),
+            revoked_at: None,
+            user_id: None,
+            expires_at: None,
+        };
+
+        (key, format!("{TOKEN_PREFIX}{}.{secret}", id.0))
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    pub fn is_expired(&self, now: jiff::Timestamp) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Check `secret` against the stored hash.
+    pub fn verify(&self, secret: &str) -> bool {
+        constant_time_eq(hash_secret(secret).as_bytes(), self.secret_hash.as_bytes())
+    }
+}
+
+/// The authenticated caller of an API request.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub key_id: ApiKeyId,
+    pub scope: Scope,
+    pub org_id: Option<OrgId>,
+    /// The user logged in with the key, for session keys
+    pub user_id: Option<UserId>,
+    /// Fields the caller is limited to within its organization; every field
+    /// when `None`
+    pub fields: Option<Arc<[H3Cell]>>,
+}
+
+impl Principal {
+    pub fn require(&self, scope: Scope) -> Result<(), ApiError> {
+        if self.scope.allows(scope) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden)
+        }
+    }
+
+    /// Require `scope` on a platform-wide key, for actions that span
+    /// organizations.
+    pub fn require_platform(&self, scope: Scope) -> Result<(), ApiError> {
+        self.require(scope)?;
+
+        if self.org_id.is_none() {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden)
+        }
+    }
+
+    /// Whether the caller may see a resource owned by `owner`.
+    ///
+    /// Platform-wide keys see everything; organization keys only see their
+    /// own organization's resources.
+    pub fn can_access(&self, owner: Option<OrgId>) -> bool {
+        self.org_id.is_none() || self.org_id == owner
+    }
+
+    /// Reject access to another organization's resource as if it didn't
+    /// exist, so ids can't be probed across organizations.
+    pub fn check_access(&self, owner: Option<OrgId>) -> Result<(), ApiError> {
+        if self.can_access(owner) {
+            Ok(())
+        } else {
+            Err(ApiError::NotFound)
+        }
+    }
+
+    /// Whether `cell` lies in one of the caller's fields.
+    pub fn can_see(&self, cell: H3Cell) -> bool {
+        self.fields
+            .as_ref()
+            .is_none_or(|fields| fields.iter().any(|field| cell.is_within(*field)))
+    }
+}
+
+/// Split a token into the key id and its secret.
+pub fn parse_token(token: &str) -> Option<(ApiKeyId, &str)> {
+    let (id, secret) = token.strip_prefix(TOKEN_PREFIX)?.split_once('.')?;
+    let id = id.parse().ok()?;
+
+    Some((ApiKeyId(id), secret))
+}
+
+/// A random 256-bit secret, hex encoded.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::rng().fill(&mut secret);
+    hex(&secret)
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex(&Sha256::digest(secret.as_bytes()))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut out, b| {
+            let _ = write!(out, "{b:02x}");
+            out
+        })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Middleware that resolves the request's API key into a [`Principal`].
+///
+/// Accepts `Authorization: Bearer <token>` or `X-Api-Key: <token>`.
+/// Requests without a valid, unrevoked key are rejected with 401, as are
+/// expired session keys and those of deleted users. Session keys act with
+/// their user's current role, organization and fields.
+pub async fn authenticate<R: Registries>(
+    State(registries): State<R>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let headers = request.headers();
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+        })
+        .ok_or(ApiError::Unauthorized)?;
+
+    let (id, secret) = parse_token(token.trim()).ok_or(ApiError::Unauthorized)?;
+
+    let now = jiff::Timestamp::now();
+    let key = registries
+        .api_keys()
+        .get(id)
+        .await
+        .map_err(ApiError::internal)?
+        .filter(|key| !key.is_revoked() && !key.is_expired(now) && key.verify(secret))
+        .ok_or(ApiError::Unauthorized)?;
+
+    let principal = match key.user_id {
+        Some(user_id) => registries
+            .users()
+            .get(user_id)
+            .await
+            .map_err(ApiError::internal)?
+            .ok_or(ApiError::Unauthorized)?
+            .principal(key.id),
+        None => Principal {
+            key_id: key.id,
+            scope: key.scope,
+            org_id: key.org_id,
+            user_id: None,
+            fields: None,
+        },
+    };
+    request.extensions_mut().insert(principal);
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use ulid::Ulid;
+
+    use super::{ApiKey, ApiKeyId, Principal, Scope, parse_token};
+    use crate::org::OrgId;
+
+    #[test]
+    fn generated_token_verifies() {
+        let (key, token) = ApiKey::generate("ops", Scope::Admin);
+
+        let (id, secret) = parse_token(&token).expect("token should parse");
+        assert_eq!(id, key.id);
+        assert!(key.verify(secret));
+        assert!(!key.verify("wrong"));
+        assert!(!token.contains(&key.secret_hash));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        assert!(parse_token("").is_none());
+        assert!(parse_token("ek_notaulid.secret").is_none());
+        assert!(parse_token("01JJNQ1KQCNZ8X9PQRV5ABCD12.secret").is_none());
+    }
+
+    #[test]
+    fn scopes() {
+        assert!(Scope::Admin.allows(Scope::ReadOnly));
+        assert!(Scope::ReadOnly.allows(Scope::ReadOnly));
+        assert!(!Scope::ReadOnly.allows(Scope::Admin));
+        assert!(!Scope::Dispatcher.allows(Scope::ReadOnly));
+        assert!(Scope::Agronomist.allows(Scope::ReadOnly));
+        assert!(!Scope::Agronomist.allows(Scope::Admin));
+    }
+
+    #[test]
+    fn organization_keys_only_see_their_organization() {
+        let ours = OrgId(Ulid::new());
+        let theirs = OrgId(Ulid::new());
+        let principal = |org_id| Principal {
+            key_id: ApiKeyId(Ulid::new()),
+            scope: Scope::Admin,
+            org_id,
+            user_id: None,
+            fields: None,
+        };
+
+        let platform = principal(None);
+        assert!(platform.can_access(Some(theirs)));
+        assert!(platform.can_access(None));
+        assert!(platform.require_platform(Scope::Admin).is_ok());
+
+        let member = principal(Some(ours));
+        assert!(member.can_access(Some(ours)));
+        assert!(!member.can_access(Some(theirs)));
+        assert!(!member.can_access(None));
+        assert!(member.check_access(Some(theirs)).is_err());
+        assert!(member.require_platform(Scope::Admin).is_err());
+    }
+}
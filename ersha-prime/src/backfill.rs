@@ -0,0 +1,708 @@
+//! Historical data uploaded over HTTP.
+//!
+//! After an outage, data recovered from a gateway's SD card is uploaded
+//! through `POST /api/backfill` as one large batch. Each upload becomes a
+//! [`BackfillJob`], processed in the background a chunk at a time so live
+//! ingestion isn't held up, with its progress kept for the status endpoint.
+//!
+//! Items go through the same checks as batches from dispatchers, except that
+//! they may be further ahead of prime's clock. Readings already stored, under
+//! the same id or for the same sensor and time, are skipped as duplicates, so
+//! an upload overlapping what the dispatcher sent live is safe. Refused items
+//! are dead-lettered, and stored ones published as late.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ersha_core::{
+    BatchId, BatchUploadRequest, DeviceId, DeviceStatus, DispatcherId, ReadingId, SensorId,
+    SensorReading, StatusId,
+};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::config::{AuthConfig, BackfillConfig};
+use crate::dead_letter::{DeadItem, DeadLetter};
+use crate::events::{BusEvent, EventBus};
+use crate::metrics;
+use crate::registry::{
+    DeadLetterRegistry, DeviceStatusRegistry, ReadingRegistry, Registries,
+    filter::{Pagination, QueryOptions, ReadingFilter, ReadingSortBy, SortOrder},
+};
+use crate::rpc::{self, ItemChecks};
+
+/// Existing readings looked up per page when checking for duplicates.
+const LOOKUP_PAGE: usize = 1000;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum BackfillError {
+    #[error("failed to look up devices: {0}")]
+    Devices(#[source] BoxError),
+    #[error("failed to look up existing readings: {0}")]
+    Readings(#[source] BoxError),
+    #[error("failed to store items: {0}")]
+    Store(#[source] BoxError),
+    #[error("failed to record dead letters: {0}")]
+    DeadLetters(#[source] BoxError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct BackfillId(pub Ulid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BackfillState {
+    /// Waiting for earlier uploads to finish
+    Queued,
+    Running,
+    Completed,
+    /// Stopped part way; items processed so far are kept
+    Failed,
+}
+
+/// Progress through one kind of item in an upload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ItemCounts {
+    /// Items in the upload
+    pub total: usize,
+    /// Items dealt with so far
+    pub processed: usize,
+    pub stored: usize,
+    /// Already stored, or repeated within the upload
+    pub duplicate: usize,
+    /// Refused by validation and dead-lettered
+    pub refused: usize,
+}
+
+/// An upload of historical data and how far along it is.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct BackfillJob {
+    pub id: BackfillId,
+    /// Id of the uploaded batch, recorded on its dead letters
+    pub batch_id: BatchId,
+    pub dispatcher_id: DispatcherId,
+    pub state: BackfillState,
+    pub readings: ItemCounts,
+    pub statuses: ItemCounts,
+    pub submitted_at: Timestamp,
+    pub started_at: Option<Timestamp>,
+    pub finished_at: Option<Timestamp>,
+    /// Why the job failed
+    pub error: Option<String>,
+}
+
+struct Jobs {
+    /// Every kept job, oldest first
+    jobs: VecDeque<BackfillJob>,
+    /// Uploads waiting to be processed, oldest first
+    pending: VecDeque<(BackfillId, BatchUploadRequest)>,
+}
+
+/// Uploads of historical data, processed one at a time by [`run`].
+///
+/// Jobs live in memory: one cut short by a restart is uploaded again, its
+/// items stored before the restart being skipped as duplicates.
+#[derive(Clone)]
+pub struct Backfills {
+    config: BackfillConfig,
+    jobs: Arc<Mutex<Jobs>>,
+    submitted: Arc<Notify>,
+}
+
+impl Backfills {
+    pub fn new(config: BackfillConfig) -> Self {
+        Self {
+            config,
+            jobs: Arc::new(Mutex::new(Jobs {
+                jobs: VecDeque::new(),
+                pending: VecDeque::new(),
+            })),
+            submitted: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> BackfillConfig {
+        self.config
+    }
+
+    /// Queue `batch` for processing.
+    pub fn submit(&self, batch: BatchUploadRequest, now: Timestamp) -> BackfillJob {
+        let job = BackfillJob {
+            id: BackfillId(Ulid::new()),
+            batch_id: batch.id,
+            dispatcher_id: batch.dispatcher_id,
+            state: BackfillState::Queued,
+            readings: ItemCounts {
+                total: batch.readings.len(),
+                ..ItemCounts::default()
+            },
+            statuses: ItemCounts {
+                total: batch.statuses.len(),
+                ..ItemCounts::default()
+            },
+            submitted_at: now,
+            started_at: None,
+            finished_at: None,
+            error: None,
+        };
+
+        {
+            let mut jobs = self.lock();
+            jobs.jobs.push_back(job.clone());
+            jobs.pending.push_back((job.id, batch));
+        }
+        self.submitted.notify_one();
+
+        job
+    }
+
+    pub fn get(&self, id: BackfillId) -> Option<BackfillJob> {
+        self.lock().jobs.iter().find(|job| job.id == id).cloned()
+    }
+
+    /// Kept jobs, newest first.
+    pub fn list(&self) -> Vec<BackfillJob> {
+        self.lock().jobs.iter().rev().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Jobs> {
+        self.jobs.lock().expect("backfill jobs lock poisoned")
+    }
+
+    fn next(&self) -> Option<(BackfillId, BatchUploadRequest)> {
+        self.lock().pending.pop_front()
+    }
+
+    fn update(&self, id: BackfillId, change: impl FnOnce(&mut BackfillJob)) {
+        let mut jobs = self.lock();
+        if let Some(job) = jobs.jobs.iter_mut().find(|job| job.id == id) {
+            change(job);
+        }
+    }
+
+    /// Forget the oldest finished jobs beyond the configured number.
+    fn prune(&self) {
+        let mut jobs = self.lock();
+        let finished = |job: &BackfillJob| {
+            matches!(job.state, BackfillState::Completed | BackfillState::Failed)
+        };
+        let mut excess = jobs
+            .jobs
+            .iter()
+            .filter(|job| finished(job))
+            .count()
+            .saturating_sub(self.config.keep_jobs);
+        jobs.jobs.retain(|job| {
+            let forget = excess > 0 && finished(job);
+            if forget {
+                excess -= 1;
+            }
+            !forget
+        });
+    }
+}
+
+/// Process uploads as they are submitted until cancelled.
+pub async fn run<R: Registries>(
+    registries: R,
+    backfills: Backfills,
+    auth: AuthConfig,
+    events: Arc<dyn EventBus>,
+    cancel: CancellationToken,
+) {
+    loop {
+        while let Some((id, batch)) = backfills.next() {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = process(&registries, &backfills, auth, &*events, id, batch) => {}
+            }
+        }
+
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = backfills.submitted.notified() => {}
+        }
+    }
+}
+
+/// Process one upload, recording how it went on its job.
+pub async fn process<R: Registries>(
+    registries: &R,
+    backfills: &Backfills,
+    auth: AuthConfig,
+    events: &dyn EventBus,
+    id: BackfillId,
+    batch: BatchUploadRequest,
+) {
+    backfills.update(id, |job| {
+        job.state = BackfillState::Running;
+        job.started_at = Some(Timestamp::now());
+    });
+
+    let result = backfill(registries, backfills, auth, events, id, batch).await;
+
+    backfills.update(id, |job| {
+        job.finished_at = Some(Timestamp::now());
+        match &result {
+            Ok(()) => {
+                info!(
+                    backfill_id = ?id,
+                    readings_stored = job.readings.stored,
+                    statuses_stored = job.statuses.stored,
+                    "backfill completed"
+                );
+                job.state = BackfillState::Completed;
+            }
+            Err(e) => {
+                error!(error = ?e, backfill_id = ?id, "backfill failed");
+                job.state = BackfillState::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+    });
+    backfills.prune();
+}
+
+async fn backfill<R: Registries>(
+    registries: &R,
+    backfills: &Backfills,
+    auth: AuthConfig,
+    events: &dyn EventBus,
+    id: BackfillId,
+    batch: BatchUploadRequest,
+) -> Result<(), BackfillError> {
+    let config = backfills.config();
+    let chunk_size = config.chunk_size.max(1);
+    let skew = Duration::from_secs(config.max_future_skew_secs);
+    let BatchUploadRequest {
+        id: batch_id,
+        dispatcher_id,
+        readings,
+        statuses,
+        ..
+    } = batch;
+
+    let mut seen_readings = HashSet::new();
+    let mut seen_samples = HashSet::new();
+    for chunk in readings.chunks(chunk_size) {
+        let now = Timestamp::now();
+        let checks = checks(registries, auth, dispatcher_id, chunk, now, skew).await?;
+
+        let mut counts = ItemCounts::default();
+        let mut valid = Vec::new();
+        let mut refused = Vec::new();
+        for reading in chunk {
+            counts.processed += 1;
+            if let Some(reason) = checks.reading(reading) {
+                refused.push((DeadItem::Reading(reading.clone()), reason));
+            } else if !seen_readings.insert(reading.id)
+                || !seen_samples.insert((reading.sensor_id, reading.timestamp))
+            {
+                counts.duplicate += 1;
+            } else {
+                valid.push(reading.clone());
+            }
+        }
+
+        let fresh = without_existing(registries, valid.clone()).await?;
+        let stored: HashSet<ReadingId> = registries
+            .readings()
+            .batch_store(fresh)
+            .await
+            .map_err(|e| BackfillError::Store(Box::new(e)))?
+            .into_iter()
+            .collect();
+        counts.stored = stored.len();
+        counts.duplicate += valid.len() - stored.len();
+        counts.refused = refused.len();
+        dead_letter(registries, dispatcher_id, batch_id, refused, now).await?;
+
+        valid.retain(|reading| stored.contains(&reading.id));
+        if !valid.is_empty() {
+            events
+                .publish(BusEvent::ReadingsIngested {
+                    readings: valid.into(),
+                    late: true,
+                })
+                .await;
+        }
+        metrics::record_backfilled("readings", &counts);
+        backfills.update(id, |job| add(&mut job.readings, counts));
+        tokio::task::yield_now().await;
+    }
+
+    let mut seen_statuses = HashSet::new();
+    for chunk in statuses.chunks(chunk_size) {
+        let now = Timestamp::now();
+        let checks = checks(registries, auth, dispatcher_id, chunk, now, skew).await?;
+
+        let mut counts = ItemCounts::default();
+        let mut valid = Vec::new();
+        let mut refused = Vec::new();
+        for status in chunk {
+            counts.processed += 1;
+            if let Some(reason) = checks.status(status) {
+                refused.push((DeadItem::Status(status.clone()), reason));
+            } else if !seen_statuses.insert(status.id) {
+                counts.duplicate += 1;
+            } else {
+                valid.push(status.clone());
+            }
+        }
+
+        let stored: HashSet<StatusId> = registries
+            .statuses()
+            .batch_store(valid.clone())
+            .await
+            .map_err(|e| BackfillError::Store(Box::new(e)))?
+            .into_iter()
+            .collect();
+        counts.stored = stored.len();
+        counts.duplicate += valid.len() - stored.len();
+        counts.refused = refused.len();
+        dead_letter(registries, dispatcher_id, batch_id, refused, now).await?;
+
+        valid.retain(|status| stored.contains(&status.id));
+        if !valid.is_empty() {
+            events
+                .publish(BusEvent::StatusesIngested {
+                    statuses: valid.into(),
+                })
+                .await;
+        }
+        metrics::record_backfilled("statuses", &counts);
+        backfills.update(id, |job| add(&mut job.statuses, counts));
+        tokio::task::yield_now().await;
+    }
+
+    Ok(())
+}
+
+fn add(total: &mut ItemCounts, counts: ItemCounts) {
+    total.processed += counts.processed;
+    total.stored += counts.stored;
+    total.duplicate += counts.duplicate;
+    total.refused += counts.refused;
+}
+
+/// An item from a device.
+trait Item {
+    fn device_id(&self) -> DeviceId;
+}
+
+impl Item for SensorReading {
+    fn device_id(&self) -> DeviceId {
+        self.device_id
+    }
+}
+
+impl Item for DeviceStatus {
+    fn device_id(&self) -> DeviceId {
+        self.device_id
+    }
+}
+
+/// The checks for `items`, accepting timestamps up to `skew` ahead of `now`.
+async fn checks<R: Registries>(
+    registries: &R,
+    auth: AuthConfig,
+    dispatcher_id: DispatcherId,
+    items: &[impl Item],
+    now: Timestamp,
+    skew: Duration,
+) -> Result<ItemChecks, BackfillError> {
+    let device_ids = items.iter().map(Item::device_id).collect();
+    let checks = rpc::item_checks(registries, auth, dispatcher_id, device_ids, now)
+        .await
+        .map_err(|e| BackfillError::Devices(Box::new(e)))?;
+
+    Ok(checks.accept_until(now + skew))
+}
+
+/// `readings` less those whose sensor already has a reading stored at the
+/// same time, as when the upload overlaps what was sent live under other ids.
+async fn without_existing<R: Registries>(
+    registries: &R,
+    readings: Vec<SensorReading>,
+) -> Result<Vec<SensorReading>, BackfillError> {
+    let mut spans: HashMap<SensorId, (Timestamp, Timestamp)> = HashMap::new();
+    for reading in &readings {
+        spans
+            .entry(reading.sensor_id)
+            .and_modify(|(first, last)| {
+                *first = (*first).min(reading.timestamp);
+                *last = (*last).max(reading.timestamp);
+            })
+            .or_insert((reading.timestamp, reading.timestamp));
+    }
+
+    let mut existing = HashSet::new();
+    for (sensor_id, (first, last)) in spans {
+        let mut after = None;
+        loop {
+            let page = registries
+                .readings()
+                .list(QueryOptions {
+                    filter: ReadingFilter::builder()
+                        .sensor_ids([sensor_id])
+                        .after(first)
+                        .before(last)
+                        .build(),
+                    sort_by: ReadingSortBy::Timestamp,
+                    sort_order: SortOrder::Asc,
+                    pagination: Pagination::Cursor {
+                        after,
+                        limit: LOOKUP_PAGE,
+                    },
+                })
+                .await
+                .map_err(|e| BackfillError::Readings(Box::new(e)))?;
+
+            let full = page.len() == LOOKUP_PAGE;
+            after = page
+                .last()
+                .map(|reading| ReadingSortBy::Timestamp.cursor(reading));
+            existing.extend(
+                page.into_iter()
+                    .map(|reading| (reading.sensor_id, reading.timestamp)),
+            );
+            if !full {
+                break;
+            }
+        }
+    }
+
+    Ok(readings
+        .into_iter()
+        .filter(|reading| !existing.contains(&(reading.sensor_id, reading.timestamp)))
+        .collect())
+}
+
+async fn dead_letter<R: Registries>(
+    registries: &R,
+    dispatcher_id: DispatcherId,
+    batch_id: BatchId,
+    refused: Vec<(DeadItem, ersha_core::InvalidItemReason)>,
+    now: Timestamp,
+) -> Result<(), BackfillError> {
+    if refused.is_empty() {
+        return Ok(());
+    }
+
+    let count = refused.len();
+    let letters = refused
+        .into_iter()
+        .map(|(item, reason)| DeadLetter::new(dispatcher_id, batch_id, item, reason, now))
+        .collect();
+    registries
+        .dead_letters()
+        .record(letters)
+        .await
+        .map_err(|e| BackfillError::DeadLetters(Box::new(e)))?;
+    metrics::record_dead_letters(count);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ersha_core::{
+        BatchId, BatchUploadRequest, DeviceId, DeviceStatus, DispatcherId, Percentage, ReadingId,
+        SensorId, SensorReading, StatusId,
+    };
+    use jiff::{SignedDuration, Timestamp};
+    use ulid::Ulid;
+
+    use super::{BackfillState, Backfills, ItemCounts, process};
+    use crate::config::{AuthConfig, BackfillConfig};
+    use crate::events::{EventBus, LocalEventBus};
+    use crate::fixtures;
+    use crate::registry::{
+        DeadLetterRegistry, ReadingRegistry, Registries, memory::InMemoryRegistries,
+    };
+
+    fn auth() -> AuthConfig {
+        AuthConfig {
+            require_dispatcher_auth: false,
+            approve_new_dispatchers: true,
+            hello_max_skew_secs: 300,
+            require_device_assignment: false,
+            quarantine_unknown_devices: false,
+        }
+    }
+
+    fn reading(
+        dispatcher_id: DispatcherId,
+        sensor_id: SensorId,
+        timestamp: Timestamp,
+    ) -> SensorReading {
+        SensorReading {
+            dispatcher_id,
+            timestamp,
+            sensor_id,
+            ..fixtures::reading()
+        }
+    }
+
+    fn status(dispatcher_id: DispatcherId, timestamp: Timestamp) -> DeviceStatus {
+        DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id,
+            battery_percent: Percentage(80),
+            uptime_seconds: 60,
+            signal_rssi: -70,
+            errors: Box::new([]),
+            timestamp,
+            sensor_statuses: Box::new([]),
+        }
+    }
+
+    #[tokio::test]
+    async fn uploads_skip_duplicates_and_accept_drifted_clocks() {
+        let registries = InMemoryRegistries::default();
+        let events = LocalEventBus::new();
+        let backfills = Backfills::new(BackfillConfig {
+            chunk_size: 2,
+            ..BackfillConfig::default()
+        });
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let sensor = SensorId(Ulid::new());
+        let now = Timestamp::now();
+        let month_ago = now - SignedDuration::from_hours(24 * 30);
+
+        // Sent live before the outage, and recovered again under a new id.
+        let live = reading(dispatcher_id, sensor, month_ago);
+        registries
+            .readings()
+            .batch_store(vec![live.clone()])
+            .await
+            .unwrap();
+        let recovered = SensorReading {
+            id: ReadingId(Ulid::new()),
+            ..live.clone()
+        };
+        let old = reading(
+            dispatcher_id,
+            sensor,
+            month_ago + SignedDuration::from_mins(15),
+        );
+        let drifted = reading(dispatcher_id, sensor, now + SignedDuration::from_hours(2));
+        let too_far = reading(dispatcher_id, sensor, now + SignedDuration::from_hours(48));
+        let batch = BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            dispatcher_id,
+            readings: vec![
+                live.clone(),
+                recovered,
+                old.clone(),
+                old.clone(),
+                drifted.clone(),
+                too_far,
+            ]
+            .into_boxed_slice(),
+            statuses: vec![status(dispatcher_id, month_ago)].into_boxed_slice(),
+            timestamp: now,
+        };
+
+        let job = backfills.submit(batch.clone(), now);
+        assert_eq!(job.state, BackfillState::Queued);
+        let (id, queued) = backfills.next().unwrap();
+        process(&registries, &backfills, auth(), &events, id, queued).await;
+
+        let job = backfills.get(id).unwrap();
+        assert_eq!(job.state, BackfillState::Completed);
+        assert_eq!(
+            job.readings,
+            ItemCounts {
+                total: 6,
+                processed: 6,
+                stored: 2,
+                duplicate: 3,
+                refused: 1,
+            }
+        );
+        assert_eq!(job.statuses.stored, 1);
+        for reading in [&old, &drifted] {
+            assert!(
+                registries
+                    .readings()
+                    .get(reading.id)
+                    .await
+                    .unwrap()
+                    .is_some()
+            );
+        }
+        assert_eq!(
+            registries
+                .dead_letters()
+                .list(Default::default(), 10)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Uploading it all again stores nothing new.
+        let again = backfills.submit(batch, now);
+        let (id, queued) = backfills.next().unwrap();
+        assert_eq!(id, again.id);
+        process(&registries, &backfills, auth(), &events, id, queued).await;
+        let job = backfills.get(id).unwrap();
+        assert_eq!(job.readings.stored, 0);
+        assert_eq!(job.statuses.duplicate, 1);
+    }
+
+    #[tokio::test]
+    async fn only_recent_finished_jobs_are_kept() {
+        let registries = InMemoryRegistries::default();
+        let events: Arc<dyn EventBus> = Arc::new(LocalEventBus::new());
+        let backfills = Backfills::new(BackfillConfig {
+            keep_jobs: 1,
+            ..BackfillConfig::default()
+        });
+        let dispatcher_id = DispatcherId(Ulid::new());
+
+        for _ in 0..3 {
+            let batch = BatchUploadRequest {
+                id: BatchId(Ulid::new()),
+                dispatcher_id,
+                readings: Box::new([]),
+                statuses: Box::new([]),
+                timestamp: Timestamp::now(),
+            };
+            backfills.submit(batch, Timestamp::now());
+        }
+        let queued = backfills.submit(
+            BatchUploadRequest {
+                id: BatchId(Ulid::new()),
+                dispatcher_id,
+                readings: Box::new([]),
+                statuses: Box::new([]),
+                timestamp: Timestamp::now(),
+            },
+            Timestamp::now(),
+        );
+        for _ in 0..3 {
+            let (id, batch) = backfills.next().unwrap();
+            process(&registries, &backfills, auth(), &*events, id, batch).await;
+        }
+
+        let jobs = backfills.list();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].id, queued.id);
+        assert_eq!(jobs[0].state, BackfillState::Queued);
+        assert_eq!(jobs[1].state, BackfillState::Completed);
+    }
+}
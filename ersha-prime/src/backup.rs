@@ -0,0 +1,321 @@
+//! Online backups of the SQLite database, compressed with zstd.
+//!
+//! A backup is a `VACUUM INTO` copy of the live database, taken while prime
+//! keeps serving, compressed into `<dir>/ersha-prime-<time>.sqlite.zst`.
+//! Backups are taken through `POST /admin/backup` and, with an interval
+//! configured, on a schedule. Only the newest `keep` are kept.
+//! `ersha-prime restore <backup>` puts one back in place of the database
+//! while prime is stopped.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use jiff::Timestamp;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tokio::time::{Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::config::BackupConfig;
+
+const PREFIX: &str = "ersha-prime-";
+const SUFFIX: &str = ".sqlite.zst";
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error("registries aren't kept in SQLite")]
+    Unsupported,
+    #[error("{} already exists", .0.display())]
+    Exists(PathBuf),
+    #[error("database error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// One compressed backup.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Backup {
+    /// File name within the backup directory
+    pub file: String,
+    pub size_bytes: u64,
+    pub created_at: Timestamp,
+}
+
+/// Takes and rotates backups of the database behind `pool`, one at a time.
+#[derive(Clone)]
+pub struct Backups {
+    config: BackupConfig,
+    pool: Option<SqlitePool>,
+    running: Arc<Mutex<()>>,
+}
+
+impl Backups {
+    /// Backups of the database behind `pool`, or none when registries are
+    /// kept in memory.
+    pub fn new(config: BackupConfig, pool: Option<SqlitePool>) -> Self {
+        Self {
+            config,
+            pool,
+            running: Arc::default(),
+        }
+    }
+
+    /// Back the database up now, then delete backups beyond the newest
+    /// `keep`. Waits for a backup already under way to finish first.
+    pub async fn take(&self) -> Result<Backup, BackupError> {
+        let pool = self.pool.as_ref().ok_or(BackupError::Unsupported)?;
+        let _running = self.running.lock().await;
+
+        let dir = &self.config.dir;
+        tokio::fs::create_dir_all(dir).await?;
+        let created_at = Timestamp::now();
+        let file = format!(
+            "{PREFIX}{}{SUFFIX}",
+            created_at.strftime("%Y%m%dT%H%M%S%.3fZ")
+        );
+
+        // VACUUM INTO refuses to overwrite, so clear what a failed backup
+        // may have left.
+        let copy = dir.join(format!(".{file}.sqlite"));
+        remove_if_exists(&copy).await?;
+        sqlx::query("VACUUM INTO ?")
+            .bind(copy.to_string_lossy().into_owned())
+            .execute(pool)
+            .await?;
+
+        let target = dir.join(&file);
+        let level = self.config.level;
+        let compressed = {
+            let (copy, target) = (copy.clone(), target.clone());
+            tokio::task::spawn_blocking(move || compress(&copy, &target, level))
+                .await
+                .map_err(std::io::Error::other)
+        };
+        let removed = remove_if_exists(&copy).await;
+        let size_bytes = compressed??;
+        removed?;
+
+        self.rotate().await?;
+
+        Ok(Backup {
+            file,
+            size_bytes,
+            created_at,
+        })
+    }
+
+    /// Backups in the directory, newest first.
+    pub async fn list(&self) -> Result<Vec<Backup>, BackupError> {
+        let mut entries = match tokio::fs::read_dir(&self.config.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut backups = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(file) = entry.file_name().into_string() else {
+                continue;
+            };
+            if !(file.starts_with(PREFIX) && file.ends_with(SUFFIX)) {
+                continue;
+            }
+
+            let metadata = entry.metadata().await?;
+            let created_at = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| Timestamp::try_from(modified).ok())
+                .unwrap_or_default();
+            backups.push(Backup {
+                file,
+                size_bytes: metadata.len(),
+                created_at,
+            });
+        }
+        // Names sort by the time they were taken at.
+        backups.sort_by(|a, b| b.file.cmp(&a.file));
+
+        Ok(backups)
+    }
+
+    async fn rotate(&self) -> Result<(), BackupError> {
+        if self.config.keep == 0 {
+            return Ok(());
+        }
+
+        for stale in self.list().await?.iter().skip(self.config.keep) {
+            info!(file = %stale.file, "deleting old backup");
+            tokio::fs::remove_file(self.config.dir.join(&stale.file)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Take a backup every `interval_secs` until cancelled, if configured to.
+pub async fn run(backups: Backups, cancel: CancellationToken) {
+    let Some(interval_secs) = backups.config.interval_secs else {
+        return;
+    };
+
+    let period = Duration::from_secs(interval_secs.max(1));
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        match backups.take().await {
+            Ok(backup) => info!(
+                file = %backup.file,
+                size_bytes = backup.size_bytes,
+                "database backed up"
+            ),
+            Err(e) => error!(error = %e, "failed to back the database up"),
+        }
+    }
+}
+
+/// Compress `source` into `target`, returning the compressed size. The
+/// target only appears once complete.
+fn compress(source: &Path, target: &Path, level: i32) -> Result<u64, std::io::Error> {
+    let partial = target.with_extension("zst.part");
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    zstd::stream::copy_encode(BufReader::new(File::open(source)?), &mut writer, level)?;
+    writer.flush()?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    std::fs::rename(&partial, target)?;
+
+    Ok(std::fs::metadata(target)?.len())
+}
+
+/// Replace the database at `database` with the backup at `backup`. Refuses
+/// to replace an existing database unless `force`. Prime must not be
+/// running on the database meanwhile.
+pub fn restore(backup: &Path, database: &Path, force: bool) -> Result<(), BackupError> {
+    if database.exists() && !force {
+        return Err(BackupError::Exists(database.to_owned()));
+    }
+
+    let mut partial = database.as_os_str().to_owned();
+    partial.push(".restoring");
+    let partial = PathBuf::from(partial);
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    zstd::stream::copy_decode(BufReader::new(File::open(backup)?), &mut writer)?;
+    writer.flush()?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+
+    // A write-ahead log left by the replaced database would be replayed
+    // onto the restored one.
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = database.as_os_str().to_owned();
+        sidecar.push(suffix);
+        match std::fs::remove_file(PathBuf::from(sidecar)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    std::fs::rename(&partial, database)?;
+
+    Ok(())
+}
+
+async fn remove_if_exists(path: &Path) -> Result<(), std::io::Error> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+    use ulid::Ulid;
+
+    use super::{BackupError, Backups, restore};
+    use crate::config::BackupConfig;
+
+    #[tokio::test]
+    async fn backups_rotate_and_restore() {
+        let dir = std::env::temp_dir().join(format!("ersha-backups-{}", Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = SqlitePoolOptions::new()
+            .connect(&format!(
+                "sqlite:{}?mode=rwc",
+                dir.join("live.db").display()
+            ))
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE crops (name TEXT)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO crops VALUES ('teff')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let backups = Backups::new(
+            BackupConfig {
+                dir: dir.clone(),
+                keep: 2,
+                ..BackupConfig::default()
+            },
+            Some(pool),
+        );
+
+        let first = backups.take().await.unwrap();
+        backups.take().await.unwrap();
+        let last = backups.take().await.unwrap();
+        let kept = backups.list().await.unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].file, last.file);
+        assert!(kept.iter().all(|backup| backup.file != first.file));
+
+        let database = dir.join("restored.db");
+        restore(&dir.join(&last.file), &database, false).unwrap();
+        assert!(matches!(
+            restore(&dir.join(&last.file), &database, false),
+            Err(BackupError::Exists(_))
+        ));
+        let restored = SqlitePoolOptions::new()
+            .connect(&format!("sqlite:{}", database.display()))
+            .await
+            .unwrap();
+        let name: String = sqlx::query_scalar("SELECT name FROM crops")
+            .fetch_one(&restored)
+            .await
+            .unwrap();
+        assert_eq!(name, "teff");
+
+        restored.close().await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn memory_registries_cant_be_backed_up() {
+        let backups = Backups::new(BackupConfig::default(), None);
+
+        assert!(matches!(
+            backups.take().await,
+            Err(BackupError::Unsupported)
+        ));
+    }
+}
@@ -0,0 +1,108 @@
+//! Battery drain of a device, estimated from its status reports.
+//!
+//! The drain rate is the least-squares slope of battery level over time, so
+//! a single noisy report doesn't swing the estimate. Projecting that rate
+//! forward from the latest level gives the days left before the battery is
+//! empty, which is what maintenance rounds are planned around.
+
+use ersha_core::Percentage;
+use jiff::Timestamp;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// How fast a device's battery is draining and when it will run out.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct BatteryTrend {
+    /// Status reports the trend was fitted to
+    pub samples: usize,
+    pub from: Timestamp,
+    pub to: Timestamp,
+    /// Battery level at `to`
+    pub latest_percent: u8,
+    /// Percentage points lost per day; negative while charging
+    pub drain_percent_per_day: f64,
+    /// Days from `to` until the battery is empty, absent when it isn't draining
+    pub days_to_empty: Option<f64>,
+}
+
+impl BatteryTrend {
+    /// Fit a trend to battery levels ordered by time.
+    ///
+    /// Returns `None` unless the samples span some time, since a rate can't
+    /// be drawn from one instant.
+    pub fn compute(samples: &[(Timestamp, Percentage)]) -> Option<Self> {
+        let (&(from, _), &(to, latest)) = (samples.first()?, samples.last()?);
+        if from >= to {
+            return None;
+        }
+
+        let days = |at: Timestamp| at.duration_since(from).as_secs_f64() / SECONDS_PER_DAY;
+        let n = samples.len() as f64;
+        let mean_t = samples.iter().map(|(at, _)| days(*at)).sum::<f64>() / n;
+        let mean_p = samples.iter().map(|(_, p)| f64::from(p.0)).sum::<f64>() / n;
+
+        let (covariance, variance) =
+            samples
+                .iter()
+                .fold((0.0, 0.0), |(covariance, variance), (at, percent)| {
+                    let dt = days(*at) - mean_t;
+                    let dp = f64::from(percent.0) - mean_p;
+                    (covariance + dt * dp, variance + dt * dt)
+                });
+        let drain_percent_per_day = -covariance / variance;
+
+        let days_to_empty =
+            (drain_percent_per_day > 0.0).then(|| f64::from(latest.0) / drain_percent_per_day);
+
+        Some(Self {
+            samples: samples.len(),
+            from,
+            to,
+            latest_percent: latest.0,
+            drain_percent_per_day,
+            days_to_empty,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::Percentage;
+    use jiff::Timestamp;
+
+    use super::BatteryTrend;
+
+    fn day(n: i64, percent: u8) -> (Timestamp, Percentage) {
+        (
+            Timestamp::from_second(n * 86_400).unwrap(),
+            Percentage(percent),
+        )
+    }
+
+    #[test]
+    fn steady_drain_projects_days_to_empty() {
+        let trend =
+            BatteryTrend::compute(&[day(0, 90), day(1, 88), day(2, 86), day(3, 84)]).unwrap();
+
+        assert_eq!(trend.samples, 4);
+        assert_eq!(trend.latest_percent, 84);
+        assert!((trend.drain_percent_per_day - 2.0).abs() < 1e-9);
+        assert!((trend.days_to_empty.unwrap() - 42.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn charging_has_no_days_to_empty() {
+        let trend = BatteryTrend::compute(&[day(0, 50), day(1, 60)]).unwrap();
+
+        assert!(trend.drain_percent_per_day < 0.0);
+        assert_eq!(trend.days_to_empty, None);
+    }
+
+    #[test]
+    fn a_single_instant_has_no_trend() {
+        assert_eq!(BatteryTrend::compute(&[]), None);
+        assert_eq!(BatteryTrend::compute(&[day(1, 80), day(1, 79)]), None);
+    }
+}
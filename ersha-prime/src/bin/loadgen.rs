@@ -0,0 +1,288 @@
+//! Load generator for ersha-prime.
+//!
+//! Simulates dispatchers over real RPC connections: each says hello, then
+//! uploads batches of readings at a fixed rate until the run ends. Reports
+//! achieved throughput and upload latency percentiles.
+//!
+//! The dispatchers and their devices are new to prime, which must run with
+//! `approve_new_dispatchers`, `require_dispatcher_auth` and
+//! `quarantine_unknown_devices` off.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use color_eyre::eyre::{bail, eyre};
+use ersha_core::{
+    BatchId, BatchUploadRequest, BatchUploadResponse, DeviceId, DispatcherId, H3Cell, HelloRequest,
+    HelloResponse, ItemOutcome, Percentage, ReadingId, SensorId, SensorMetric, SensorReading,
+};
+use ersha_rpc::{Client, ClientError};
+use ordered_float::NotNan;
+use rand::Rng;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+use ulid::Ulid;
+
+#[derive(Parser)]
+#[command(name = "ersha-prime-loadgen")]
+#[command(about = "Load test ersha-prime with simulated dispatchers")]
+struct Cli {
+    /// RPC address of the prime under test
+    #[arg(short, long, default_value = "127.0.0.1:9000")]
+    addr: SocketAddr,
+    /// Number of simulated dispatchers, each on its own connection
+    #[arg(short, long, default_value_t = 10)]
+    dispatchers: usize,
+    /// Devices behind each dispatcher
+    #[arg(long, default_value_t = 20)]
+    devices: usize,
+    /// Readings per batch
+    #[arg(short, long, default_value_t = 100)]
+    batch_size: usize,
+    /// Batches per second uploaded by each dispatcher
+    #[arg(short, long, default_value_t = 1.0)]
+    rate: f64,
+    /// Length of the run in seconds
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+    /// Seconds to wait for each upload before counting it as failed
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+}
+
+/// H3 cell the simulated dispatchers and devices sit in.
+const LOCATION: H3Cell = H3Cell(0x8a2a1072b59ffff);
+
+/// Outcome of a single batch upload.
+enum Upload {
+    Accepted { stored: usize },
+    Rejected,
+    Failed(String),
+}
+
+/// What one simulated dispatcher observed.
+#[derive(Default)]
+struct Tally {
+    latencies: Vec<Duration>,
+    readings_stored: usize,
+    batches_accepted: usize,
+    batches_rejected: usize,
+    failures: BTreeMap<String, usize>,
+}
+
+impl Tally {
+    fn record(&mut self, upload: Upload, latency: Duration) {
+        match upload {
+            Upload::Accepted { stored } => {
+                self.latencies.push(latency);
+                self.batches_accepted += 1;
+                self.readings_stored += stored;
+            }
+            Upload::Rejected => {
+                self.latencies.push(latency);
+                self.batches_rejected += 1;
+            }
+            Upload::Failed(reason) => *self.failures.entry(reason).or_default() += 1,
+        }
+    }
+
+    fn merge(&mut self, other: Tally) {
+        self.latencies.extend(other.latencies);
+        self.readings_stored += other.readings_stored;
+        self.batches_accepted += other.batches_accepted;
+        self.batches_rejected += other.batches_rejected;
+        for (reason, count) in other.failures {
+            *self.failures.entry(reason).or_default() += count;
+        }
+    }
+}
+
+struct Dispatcher {
+    id: DispatcherId,
+    devices: Vec<(DeviceId, SensorId)>,
+}
+
+impl Dispatcher {
+    fn new(devices: usize) -> Self {
+        Self {
+            id: DispatcherId(Ulid::new()),
+            devices: (0..devices.max(1))
+                .map(|_| (DeviceId(Ulid::new()), SensorId(Ulid::new())))
+                .collect(),
+        }
+    }
+
+    fn batch(&self, size: usize) -> BatchUploadRequest {
+        let mut rng = rand::rng();
+        let timestamp = jiff::Timestamp::now();
+        let readings = (0..size)
+            .map(|i| {
+                let (device_id, sensor_id) = self.devices[i % self.devices.len()];
+                SensorReading {
+                    id: ReadingId(Ulid::new()),
+                    device_id,
+                    dispatcher_id: self.id,
+                    metric: SensorMetric::SoilTemp {
+                        value: NotNan::new(rng.random_range(15.0..35.0)).unwrap(),
+                    },
+                    location: LOCATION,
+                    confidence: Percentage(rng.random_range(85..100)),
+                    timestamp,
+                    sensor_id,
+                }
+            })
+            .collect();
+
+        BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            dispatcher_id: self.id,
+            readings,
+            statuses: Box::new([]),
+            timestamp,
+        }
+    }
+}
+
+async fn upload(client: &Client, batch: BatchUploadRequest) -> Upload {
+    match client.batch_upload(batch).await {
+        Ok(BatchUploadResponse::Accepted { readings, .. }) => Upload::Accepted {
+            stored: readings
+                .iter()
+                .filter(|r| r.outcome == ItemOutcome::Stored)
+                .count(),
+        },
+        Ok(BatchUploadResponse::Rejected { .. }) => Upload::Rejected,
+        Err(ClientError::ErrorResponse(error)) => Upload::Failed(format!("{:?}", error.code)),
+        Err(e) => Upload::Failed(e.to_string()),
+    }
+}
+
+/// Say hello as a new dispatcher, then upload at `cli.rate` until `until`.
+async fn simulate(cli: &Cli, until: Instant) -> color_eyre::Result<Tally> {
+    let dispatcher = Dispatcher::new(cli.devices);
+    let stream = TcpStream::connect(cli.addr).await?;
+    let client = Client::new(stream).with_timeout(Duration::from_secs(cli.timeout));
+
+    let hello = HelloRequest {
+        dispatcher_id: dispatcher.id,
+        location: LOCATION,
+        credentials: None,
+        max_chunk_bytes: None,
+        compression: Box::new([]),
+        accepts_push: false,
+    };
+    if let HelloResponse::Rejected { reason, .. } = client.hello(hello).await? {
+        bail!("hello rejected: {reason:?}");
+    }
+
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / cli.rate));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let mut tally = Tally::default();
+    loop {
+        ticks.tick().await;
+        if Instant::now() >= until {
+            break;
+        }
+
+        let batch = dispatcher.batch(cli.batch_size);
+        let started = Instant::now();
+        let upload = upload(&client, batch).await;
+        tally.record(upload, started.elapsed());
+    }
+
+    Ok(tally)
+}
+
+/// The latency below which `percentile` of `sorted` fall.
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn report(cli: &Cli, mut tally: Tally, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    let batches = tally.batches_accepted + tally.batches_rejected;
+    let target = cli.dispatchers as f64 * cli.rate;
+    tally.latencies.sort_unstable();
+
+    println!("duration:          {seconds:.1}s");
+    println!("dispatchers:       {}", cli.dispatchers);
+    println!(
+        "batches:           {batches} ({} accepted, {} rejected)",
+        tally.batches_accepted, tally.batches_rejected
+    );
+    println!(
+        "batch rate:        {:.1}/s (target {target:.1}/s)",
+        batches as f64 / seconds
+    );
+    println!(
+        "readings stored:   {} ({:.1}/s)",
+        tally.readings_stored,
+        tally.readings_stored as f64 / seconds
+    );
+    for (label, p) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("max", 100.0)] {
+        println!(
+            "latency {label}:       {:.2}ms",
+            percentile(&tally.latencies, p).as_secs_f64() * 1000.0
+        );
+    }
+    for (reason, count) in &tally.failures {
+        println!("failed ({reason}): {count}");
+    }
+}
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            std::env::var("RUST_LOG").unwrap_or_else(|_| "ersha_prime_loadgen=info".to_owned()),
+        )
+        .init();
+
+    let cli = Cli::parse();
+    if !(cli.rate.is_finite() && cli.rate > 0.0) {
+        return Err(eyre!(
+            "rate must be a positive number of batches per second"
+        ));
+    }
+    let cli = Arc::new(cli);
+
+    info!(
+        addr = %cli.addr,
+        dispatchers = cli.dispatchers,
+        batch_size = cli.batch_size,
+        rate = cli.rate,
+        "Starting load"
+    );
+
+    let started = Instant::now();
+    let until = started + Duration::from_secs(cli.duration);
+    let mut dispatchers = JoinSet::new();
+    for _ in 0..cli.dispatchers {
+        let cli = cli.clone();
+        dispatchers.spawn(async move { simulate(&cli, until).await });
+    }
+
+    let mut tally = Tally::default();
+    while let Some(result) = dispatchers.join_next().await {
+        match result? {
+            Ok(dispatcher) => tally.merge(dispatcher),
+            Err(e) => warn!(error = %e, "simulated dispatcher failed"),
+        }
+    }
+
+    report(&cli, tally, started.elapsed());
+
+    Ok(())
+}
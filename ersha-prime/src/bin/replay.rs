@@ -0,0 +1,216 @@
+//! Replays RPC captures against ersha-prime.
+//!
+//! Captures are recorded by running ersha-prime with `--record`. Each
+//! captured connection is replayed over a connection of its own, sending the
+//! requests it received in order and printing what the server answers now.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use ersha_rpc::capture::{CaptureReader, CapturedEnvelope};
+use ersha_rpc::{RpcTcp, WireMessage, auth};
+use rand::Rng;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+#[derive(Parser)]
+#[command(name = "ersha-prime-replay")]
+#[command(about = "Replay RPC envelopes recorded by ersha-prime --record")]
+struct Cli {
+    /// Capture file to replay
+    capture: PathBuf,
+    /// RPC address of the prime to replay against
+    #[arg(short, long, default_value = "127.0.0.1:9000")]
+    addr: SocketAddr,
+    /// Only replay this captured connection
+    #[arg(long)]
+    connection: Option<u64>,
+    /// Sign hellos afresh with this dispatcher secret, as recorded
+    /// signatures are stale or redacted
+    #[arg(long)]
+    secret: Option<String>,
+    /// Keep the gaps between envelopes as recorded, rather than sending
+    /// each as soon as the previous one is answered
+    #[arg(long)]
+    pace: bool,
+    /// Print the capture instead of replaying it
+    #[arg(long)]
+    list: bool,
+    /// Seconds to wait for each answer
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+}
+
+/// Whether the server answered `payload` when it was recorded, so it is
+/// worth sending again. Replies, cancellations and keepalive answers belong
+/// to the original connection.
+fn replayed(captured: &CapturedEnvelope) -> bool {
+    captured.envelope.reply_to.is_none()
+        && !matches!(
+            captured.envelope.payload,
+            WireMessage::Pong | WireMessage::Cancel(_) | WireMessage::GoingAway
+        )
+}
+
+/// The variant name of `payload`, e.g. `BatchUploadRequest`.
+fn kind(payload: &WireMessage) -> String {
+    let debug = format!("{payload:?}");
+    let end = debug.find(['(', ' ', '{']).unwrap_or(debug.len());
+    debug[..end].to_owned()
+}
+
+/// `payload` shortened to fit a line.
+fn summary(payload: &WireMessage) -> String {
+    const MAX_CHARS: usize = 160;
+    let debug = format!("{payload:?}");
+    match debug.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &debug[..end]),
+        None => debug,
+    }
+}
+
+fn sign(payload: &mut WireMessage, secret: Option<&str>) {
+    let (WireMessage::HelloRequest(hello), Some(secret)) = (payload, secret) else {
+        return;
+    };
+    hello.credentials = Some(auth::sign_hello(
+        secret.as_bytes(),
+        hello.dispatcher_id,
+        hello.location,
+        jiff::Timestamp::now(),
+        rand::rng().random(),
+    ));
+}
+
+/// Send what `connection` received, in order, over a new connection.
+async fn replay(
+    cli: &Cli,
+    connection: u64,
+    envelopes: Vec<CapturedEnvelope>,
+    start: jiff::Timestamp,
+    started: Instant,
+) -> color_eyre::Result<()> {
+    let stream = TcpStream::connect(cli.addr).await?;
+    let mut rpc = RpcTcp::new(stream, 1024).answering_pings();
+    // Pushed requests are left unanswered, as they were sent to the
+    // original dispatcher.
+    let mut incoming = rpc.incoming();
+    tokio::spawn(async move { while incoming.recv().await.is_some() {} });
+    let caller = rpc.caller();
+    let timeout = Duration::from_secs(cli.timeout);
+
+    for captured in envelopes {
+        if cli.pace {
+            let offset = captured.received_at.duration_since(start);
+            let offset = Duration::try_from(offset).unwrap_or_default();
+            tokio::time::sleep_until(started + offset).await;
+        }
+
+        let mut payload = captured.envelope.payload;
+        sign(&mut payload, cli.secret.as_deref());
+        let request = kind(&payload);
+        // Only the last chunk of a streamed batch is answered.
+        if matches!(&payload, WireMessage::BatchUploadChunk(chunk) if !chunk.last) {
+            caller.send(payload).await?;
+            continue;
+        }
+
+        let sent = Instant::now();
+        match caller.call(payload, timeout).await {
+            Ok(response) => println!(
+                "[{connection}] {request} -> {} ({:.1}ms)",
+                summary(&response.payload),
+                sent.elapsed().as_secs_f64() * 1000.0
+            ),
+            Err(e) => println!("[{connection}] {request} -> failed: {e}"),
+        }
+    }
+
+    rpc.close();
+    Ok(())
+}
+
+fn list(envelopes: &BTreeMap<u64, Vec<CapturedEnvelope>>) {
+    for (connection, envelopes) in envelopes {
+        for captured in envelopes {
+            println!(
+                "[{connection}] {} {:?} {}",
+                captured.received_at,
+                captured.envelope.msg_id,
+                summary(&captured.envelope.payload)
+            );
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            std::env::var("RUST_LOG").unwrap_or_else(|_| "ersha_prime_replay=info".to_owned()),
+        )
+        .init();
+
+    let cli = Cli::parse();
+
+    let mut connections: BTreeMap<u64, Vec<CapturedEnvelope>> = BTreeMap::new();
+    let mut start = None;
+    for captured in CaptureReader::open(&cli.capture)? {
+        let captured = captured?;
+        if cli
+            .connection
+            .is_some_and(|only| only != captured.connection)
+        {
+            continue;
+        }
+        start.get_or_insert(captured.received_at);
+        if cli.list || replayed(&captured) {
+            connections
+                .entry(captured.connection)
+                .or_default()
+                .push(captured);
+        }
+    }
+    let Some(start) = start else {
+        info!("Nothing to replay");
+        return Ok(());
+    };
+
+    if cli.list {
+        list(&connections);
+        return Ok(());
+    }
+
+    info!(
+        addr = %cli.addr,
+        connections = connections.len(),
+        paced = cli.pace,
+        "Replaying capture"
+    );
+
+    let cli = Arc::new(cli);
+    let started = Instant::now();
+    let mut replays = JoinSet::new();
+    for (connection, envelopes) in connections {
+        let cli = cli.clone();
+        replays.spawn(async move {
+            let result = replay(&cli, connection, envelopes, start, started).await;
+            (connection, result)
+        });
+    }
+
+    while let Some(result) = replays.join_next().await {
+        if let (connection, Err(e)) = result? {
+            warn!(connection, error = %e, "replay failed");
+        }
+    }
+
+    Ok(())
+}
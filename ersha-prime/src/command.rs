@@ -0,0 +1,92 @@
+//! Commands queued for devices and relayed by their dispatchers.
+//!
+//! A command starts out `queued`, becomes `delivered` once its dispatcher
+//! polls for it or prime pushes it to the connected dispatcher, and `acked`
+//! when the dispatcher confirms it was passed on.
+//! Commands still queued or delivered at `expires_at` become `expired`.
+
+use ersha_core::{CommandId, CommandKind, DeviceCommand, DeviceId, DispatcherId};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::registry::{DeviceStatusRegistry, ReadingRegistry, Registries};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandState {
+    /// Waiting for the dispatcher to poll, or to be pushed to it
+    Queued,
+    /// Handed to the dispatcher, waiting for its acknowledgement
+    Delivered,
+    /// The dispatcher passed the command on to the device
+    Acked,
+    /// Not acknowledged before it expired
+    Expired,
+}
+
+/// A command for one device and its progress towards it.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Command {
+    pub id: CommandId,
+    pub device_id: DeviceId,
+    /// Dispatcher relaying the command to the device
+    pub dispatcher_id: DispatcherId,
+    pub kind: CommandKind,
+    pub state: CommandState,
+    pub created_at: Timestamp,
+    pub expires_at: Timestamp,
+    pub delivered_at: Option<Timestamp>,
+    pub acked_at: Option<Timestamp>,
+}
+
+impl Command {
+    pub fn new(
+        device_id: DeviceId,
+        dispatcher_id: DispatcherId,
+        kind: CommandKind,
+        created_at: Timestamp,
+        ttl: SignedDuration,
+    ) -> Self {
+        Self {
+            id: CommandId(Ulid::new()),
+            device_id,
+            dispatcher_id,
+            kind,
+            state: CommandState::Queued,
+            created_at,
+            expires_at: created_at.checked_add(ttl).unwrap_or(Timestamp::MAX),
+            delivered_at: None,
+            acked_at: None,
+        }
+    }
+
+    /// The command as sent to the dispatcher.
+    pub fn to_wire(&self) -> DeviceCommand {
+        DeviceCommand {
+            id: self.id,
+            device_id: self.device_id,
+            kind: self.kind.clone(),
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+/// The dispatcher that most recently forwarded a reading or status from the
+/// device, which is the one expected to reach it.
+pub async fn route<R: Registries>(
+    registries: &R,
+    device_id: DeviceId,
+) -> Result<Option<DispatcherId>, Box<dyn std::error::Error + Send + Sync>> {
+    let readings = registries.readings().latest_per_sensor(device_id).await?;
+    let status = registries.statuses().latest(device_id).await?;
+
+    let latest = readings
+        .into_iter()
+        .map(|reading| (reading.timestamp, reading.dispatcher_id))
+        .chain(status.map(|status| (status.timestamp, status.dispatcher_id)))
+        .max_by_key(|(timestamp, _)| *timestamp);
+
+    Ok(latest.map(|(_, dispatcher_id)| dispatcher_id))
+}
@@ -1,12 +1,1102 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use serde::Deserialize;
+use ersha_core::{DispatcherId, Percentage};
+use ersha_rpc::{
+    ConnectionLimits, Keepalive, MAX_FRAME_BYTES, Overflow, Quota, RateLimits, ReadLimits,
+    WriteQueue,
+};
+
+use crate::registry::memory::MemoryLimits;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub registry: RegistryConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub users: UserConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub indicators: IndicatorConfig,
+    #[serde(default)]
+    pub forecast: ForecastConfig,
+    #[serde(default)]
+    pub irrigation: IrrigationConfig,
+    #[serde(default)]
+    pub corrections: CorrectionConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub firmware: FirmwareConfig,
+    #[serde(default)]
+    pub data_quality: QualityConfig,
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub pagination: PaginationConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    #[serde(default)]
+    pub backfill: BackfillConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    /// Publish ingested readings and statuses to a message broker
+    #[serde(default)]
+    pub egress: Option<EgressConfig>,
+    /// Mirror ingested readings into a time-series database
+    #[serde(default)]
+    pub timeseries: Option<TimeseriesConfig>,
+    /// Serve RPC over TLS; plain TCP when unset
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+/// Dispatcher authentication on the RPC hello.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct AuthConfig {
+    /// Reject dispatchers without a provisioned secret, including unknown ones
+    #[serde(default)]
+    pub require_dispatcher_auth: bool,
+    /// Record unknown dispatchers as pending, refusing their uploads until an
+    /// operator approves them. When off, they may upload straight away.
+    #[serde(default = "default_approve_new_dispatchers")]
+    pub approve_new_dispatchers: bool,
+    /// Maximum clock difference in seconds accepted on a signed hello
+    #[serde(default = "default_hello_max_skew_secs")]
+    pub hello_max_skew_secs: u64,
+    /// Refuse items from devices not assigned to the uploading dispatcher.
+    /// When off, only devices assigned to another dispatcher are refused.
+    #[serde(default)]
+    pub require_device_assignment: bool,
+    /// Quarantine readings from devices prime doesn't know, or naming
+    /// sensors their device doesn't have, for review. When off, they are
+    /// stored like any other.
+    #[serde(default = "default_quarantine_unknown_devices")]
+    pub quarantine_unknown_devices: bool,
+}
+
+fn default_approve_new_dispatchers() -> bool {
+    true
+}
+
+fn default_quarantine_unknown_devices() -> bool {
+    true
+}
+
+fn default_hello_max_skew_secs() -> u64 {
+    300
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            require_dispatcher_auth: false,
+            approve_new_dispatchers: default_approve_new_dispatchers(),
+            hello_max_skew_secs: default_hello_max_skew_secs(),
+            require_device_assignment: false,
+            quarantine_unknown_devices: default_quarantine_unknown_devices(),
+        }
+    }
+}
+
+/// Dispatcher liveness reporting.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct HealthConfig {
+    /// Seconds without a status report after which a dispatcher is offline
+    #[serde(default = "default_offline_after_secs")]
+    pub offline_after_secs: u64,
+}
+
+fn default_offline_after_secs() -> u64 {
+    300
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            offline_after_secs: default_offline_after_secs(),
+        }
+    }
+}
+
+/// Background purging of old data.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    #[serde(default = "default_retention_enabled")]
+    pub enabled: bool,
+    /// Log what would be purged without deleting anything
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Seconds between retention sweeps
+    #[serde(default = "default_retention_interval_secs")]
+    pub interval_secs: u64,
+    /// Rows deleted per registry call
+    #[serde(default = "default_retention_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_readings_policy")]
+    pub readings: RetentionPolicy,
+    #[serde(default = "default_statuses_policy")]
+    pub statuses: RetentionPolicy,
+}
+
+/// How long one kind of data is kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Age in days after which data is purged. Kept forever when unset.
+    pub max_age_days: Option<u64>,
+}
+
+fn default_retention_enabled() -> bool {
+    true
+}
+
+fn default_retention_interval_secs() -> u64 {
+    3600
+}
+
+fn default_retention_batch_size() -> usize {
+    1000
+}
+
+fn default_readings_policy() -> RetentionPolicy {
+    RetentionPolicy {
+        max_age_days: Some(90),
+    }
+}
+
+fn default_statuses_policy() -> RetentionPolicy {
+    RetentionPolicy {
+        max_age_days: Some(30),
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_retention_enabled(),
+            dry_run: false,
+            interval_secs: default_retention_interval_secs(),
+            batch_size: default_retention_batch_size(),
+            readings: default_readings_policy(),
+            statuses: default_statuses_policy(),
+        }
+    }
+}
+
+/// Delivery of webhook events.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct WebhookConfig {
+    /// Attempts before a delivery is marked failed
+    #[serde(default = "default_webhook_max_attempts")]
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled on every further failure
+    #[serde(default = "default_webhook_initial_backoff_secs")]
+    pub initial_backoff_secs: u64,
+    #[serde(default = "default_webhook_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    /// Per-request timeout for subscriber endpoints
+    #[serde(default = "default_webhook_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Seconds between checks for due deliveries
+    #[serde(default = "default_webhook_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    8
+}
+
+fn default_webhook_initial_backoff_secs() -> u64 {
+    10
+}
+
+fn default_webhook_max_backoff_secs() -> u64 {
+    3600
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+fn default_webhook_poll_interval_secs() -> u64 {
+    5
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_webhook_max_attempts(),
+            initial_backoff_secs: default_webhook_initial_backoff_secs(),
+            max_backoff_secs: default_webhook_max_backoff_secs(),
+            timeout_secs: default_webhook_timeout_secs(),
+            poll_interval_secs: default_webhook_poll_interval_secs(),
+        }
+    }
+}
+
+/// Field indicators derived from the rollups.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct IndicatorConfig {
+    #[serde(default = "default_indicators_enabled")]
+    pub enabled: bool,
+    /// Seconds between recomputations
+    #[serde(default = "default_indicators_interval_secs")]
+    pub interval_secs: u64,
+    /// Days before today recomputed each time, to pick up late uploads
+    #[serde(default = "default_indicators_lookback_days")]
+    pub lookback_days: u32,
+    /// H3 resolution of a field; devices are grouped by their cell's
+    /// ancestor at this resolution
+    #[serde(default = "default_field_resolution")]
+    pub field_resolution: u8,
+    /// Base temperature for growing degree days, in °C
+    #[serde(default = "default_gdd_base_celsius")]
+    pub gdd_base_celsius: f64,
+    /// Air temperature at or below which an hour counts as a frost hour, in °C
+    #[serde(default)]
+    pub frost_threshold_celsius: f64,
+    /// Soil moisture at field capacity, in percent; the deficit is measured
+    /// against it
+    #[serde(default = "default_field_capacity_percent")]
+    pub field_capacity_percent: f64,
+}
+
+fn default_indicators_enabled() -> bool {
+    true
+}
+
+fn default_indicators_interval_secs() -> u64 {
+    3600
+}
+
+fn default_indicators_lookback_days() -> u32 {
+    2
+}
+
+fn default_field_resolution() -> u8 {
+    9
+}
+
+fn default_gdd_base_celsius() -> f64 {
+    10.0
+}
+
+fn default_field_capacity_percent() -> f64 {
+    35.0
+}
+
+impl Default for IndicatorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_indicators_enabled(),
+            interval_secs: default_indicators_interval_secs(),
+            lookback_days: default_indicators_lookback_days(),
+            field_resolution: default_field_resolution(),
+            gdd_base_celsius: default_gdd_base_celsius(),
+            frost_threshold_celsius: 0.0,
+            field_capacity_percent: default_field_capacity_percent(),
+        }
+    }
+}
+
+/// Forecasts of field readings by the built-in trend model.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ForecastConfig {
+    /// Hours of readings the trend is fitted to
+    #[serde(default = "default_forecast_lookback_hours")]
+    pub lookback_hours: u32,
+}
+
+fn default_forecast_lookback_hours() -> u32 {
+    72
+}
+
+impl Default for ForecastConfig {
+    fn default() -> Self {
+        Self {
+            lookback_hours: default_forecast_lookback_hours(),
+        }
+    }
+}
+
+/// Irrigation planning from the field indicators.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct IrrigationConfig {
+    #[serde(default = "default_irrigation_enabled")]
+    pub enabled: bool,
+    /// Seconds between replanning, and so how late valves may switch
+    #[serde(default = "default_irrigation_interval_secs")]
+    pub interval_secs: u64,
+    /// Soil moisture deficit the crop needs refilled before irrigating, in
+    /// percentage points
+    #[serde(default = "default_trigger_deficit_percent")]
+    pub trigger_deficit_percent: f64,
+    /// Water refilling one percentage point of soil moisture over the root
+    /// zone, in mm
+    #[serde(default = "default_mm_per_percent")]
+    pub mm_per_percent: f64,
+    /// Water the irrigation system applies per hour, in mm
+    #[serde(default = "default_application_mm_per_hour")]
+    pub application_mm_per_hour: f64,
+    /// UTC hour windows start at
+    #[serde(default = "default_irrigation_start_hour")]
+    pub start_hour: u8,
+    /// Longest window, in hours
+    #[serde(default = "default_irrigation_max_hours")]
+    pub max_hours: u32,
+}
+
+fn default_irrigation_enabled() -> bool {
+    true
+}
+
+fn default_irrigation_interval_secs() -> u64 {
+    300
+}
+
+fn default_trigger_deficit_percent() -> f64 {
+    5.0
+}
+
+fn default_mm_per_percent() -> f64 {
+    3.0
+}
+
+fn default_application_mm_per_hour() -> f64 {
+    5.0
+}
+
+fn default_irrigation_start_hour() -> u8 {
+    4
+}
+
+fn default_irrigation_max_hours() -> u32 {
+    8
+}
+
+impl Default for IrrigationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_irrigation_enabled(),
+            interval_secs: default_irrigation_interval_secs(),
+            trigger_deficit_percent: default_trigger_deficit_percent(),
+            mm_per_percent: default_mm_per_percent(),
+            application_mm_per_hour: default_application_mm_per_hour(),
+            start_hour: default_irrigation_start_hour(),
+            max_hours: default_irrigation_max_hours(),
+        }
+    }
+}
+
+/// Reprocessing of readings by calibration corrections.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct CorrectionConfig {
+    /// Seconds between checks for corrections to apply
+    #[serde(default = "default_correction_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_correction_interval_secs() -> u64 {
+    30
+}
+
+impl Default for CorrectionConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_correction_interval_secs(),
+        }
+    }
+}
+
+/// Detection of devices that stopped reporting at their expected interval.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct WatchdogConfig {
+    #[serde(default = "default_watchdog_enabled")]
+    pub enabled: bool,
+    /// Seconds between scans of the devices expected to report
+    #[serde(default = "default_watchdog_interval_secs")]
+    pub interval_secs: u64,
+    /// Seconds past its expected interval before a device is offline
+    #[serde(default = "default_grace_secs")]
+    pub grace_secs: u64,
+}
+
+fn default_watchdog_enabled() -> bool {
+    true
+}
+
+fn default_watchdog_interval_secs() -> u64 {
+    60
+}
+
+fn default_grace_secs() -> u64 {
+    300
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_watchdog_enabled(),
+            interval_secs: default_watchdog_interval_secs(),
+            grace_secs: default_grace_secs(),
+        }
+    }
+}
+
+/// Firmware images and their rollout to devices.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FirmwareConfig {
+    #[serde(default)]
+    pub store: ImageStoreConfig,
+    /// Base URL dispatchers download images from, under
+    /// `/api/firmware/{id}/image`
+    #[serde(default = "default_firmware_download_url")]
+    pub download_url: String,
+    /// Largest image accepted, in megabytes
+    #[serde(default = "default_firmware_max_image_mb")]
+    pub max_image_mb: usize,
+    /// Seconds between passes over the active rollouts
+    #[serde(default = "default_firmware_interval_secs")]
+    pub interval_secs: u64,
+    /// Seconds an update command stays deliverable before the device's
+    /// update counts as failed
+    #[serde(default = "default_firmware_command_ttl_secs")]
+    pub command_ttl_secs: u64,
+}
+
+fn default_firmware_download_url() -> String {
+    "http://localhost:8080".to_owned()
+}
+
+fn default_firmware_max_image_mb() -> usize {
+    16
+}
+
+fn default_firmware_interval_secs() -> u64 {
+    30
+}
+
+fn default_firmware_command_ttl_secs() -> u64 {
+    86_400
+}
+
+impl Default for FirmwareConfig {
+    fn default() -> Self {
+        Self {
+            store: ImageStoreConfig::default(),
+            download_url: default_firmware_download_url(),
+            max_image_mb: default_firmware_max_image_mb(),
+            interval_secs: default_firmware_interval_secs(),
+            command_ttl_secs: default_firmware_command_ttl_secs(),
+        }
+    }
+}
+
+/// Where firmware images are kept.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ImageStoreConfig {
+    /// Files in a local directory
+    Disk { path: String },
+    /// An S3-compatible bucket, addressed path-style
+    S3 {
+        /// Base URL of the service, e.g. `http://localhost:9000`
+        endpoint: String,
+        bucket: String,
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+    },
+}
+
+impl Default for ImageStoreConfig {
+    fn default() -> Self {
+        ImageStoreConfig::Disk {
+            path: "firmware".to_owned(),
+        }
+    }
+}
+
+/// Assessment of how reliably devices report.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct QualityConfig {
+    /// Seconds between readings of a sensor reporting on schedule, unless a
+    /// request sets its own
+    #[serde(default = "default_expected_interval_secs")]
+    pub expected_interval_secs: u64,
+    /// Readings with a lower confidence are suspect
+    #[serde(default = "default_suspect_below_percent")]
+    pub suspect_below_percent: Percentage,
+    /// Readings with a lower confidence are bad
+    #[serde(default = "default_bad_below_percent")]
+    pub bad_below_percent: Percentage,
+}
+
+fn default_expected_interval_secs() -> u64 {
+    60
+}
+
+fn default_suspect_below_percent() -> Percentage {
+    Percentage(80)
+}
+
+fn default_bad_below_percent() -> Percentage {
+    Percentage(50)
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            expected_interval_secs: default_expected_interval_secs(),
+            suspect_below_percent: default_suspect_below_percent(),
+            bad_below_percent: default_bad_below_percent(),
+        }
+    }
+}
+
+/// Throttling of HTTP clients and dispatcher connections.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_rate_limit_enabled")]
+    pub enabled: bool,
+    /// HTTP requests per API key
+    #[serde(default = "default_http_quota")]
+    pub http: Quota,
+    /// RPC requests per dispatcher connection
+    #[serde(default = "default_rpc_requests_quota")]
+    pub rpc_requests: Quota,
+    /// Readings uploaded per dispatcher connection
+    #[serde(default = "default_rpc_readings_quota")]
+    pub rpc_readings: Quota,
+    /// Bytes of batches streamed in chunks per dispatcher connection
+    #[serde(default = "default_rpc_chunk_bytes_quota")]
+    pub rpc_chunk_bytes: Quota,
+}
+
+fn default_rate_limit_enabled() -> bool {
+    true
+}
+
+fn default_http_quota() -> Quota {
+    Quota::new(20, 100)
+}
+
+fn default_rpc_requests_quota() -> Quota {
+    Quota::new(10, 50)
+}
+
+fn default_rpc_readings_quota() -> Quota {
+    Quota::new(1000, 5000)
+}
+
+fn default_rpc_chunk_bytes_quota() -> Quota {
+    Quota::new(1_000_000, 16_000_000)
+}
+
+impl RateLimitConfig {
+    /// Per-API-key HTTP quota, if limiting is enabled.
+    pub fn http(&self) -> Option<Quota> {
+        self.enabled.then_some(self.http)
+    }
+
+    /// Limits for each dispatcher connection.
+    pub fn rpc(&self) -> RateLimits {
+        if !self.enabled {
+            return RateLimits::default();
+        }
+
+        RateLimits {
+            requests: Some(self.rpc_requests),
+            readings: Some(self.rpc_readings),
+            chunk_bytes: Some(self.rpc_chunk_bytes),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_rate_limit_enabled(),
+            http: default_http_quota(),
+            rpc_requests: default_rpc_requests_quota(),
+            rpc_readings: default_rpc_readings_quota(),
+            rpc_chunk_bytes: default_rpc_chunk_bytes_quota(),
+        }
+    }
+}
+
+/// Bounds on readings and statuses held in memory, which they only are with
+/// the in-memory registry.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct MemoryConfig {
+    #[serde(default = "default_readings_limits")]
+    pub readings: MemoryLimits,
+    #[serde(default = "default_statuses_limits")]
+    pub statuses: MemoryLimits,
+}
+
+fn default_readings_limits() -> MemoryLimits {
+    MemoryLimits {
+        max_entries: Some(1_000_000),
+        max_age_secs: None,
+    }
+}
+
+fn default_statuses_limits() -> MemoryLimits {
+    MemoryLimits {
+        max_entries: Some(100_000),
+        max_age_secs: None,
+    }
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            readings: default_readings_limits(),
+            statuses: default_statuses_limits(),
+        }
+    }
+}
+
+/// Diagnostic output.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogConfig {
+    /// Tracing filter directives, e.g. `ersha_prime=debug,sqlx=warn`.
+    /// Falls back to `RUST_LOG`, then to info for prime itself.
+    pub filter: Option<String>,
+}
+
+/// Page sizes of list endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PaginationConfig {
+    /// Page size when a request doesn't set `limit`
+    #[serde(default = "default_page_limit")]
+    pub default_limit: usize,
+    /// Upper bound on `limit`
+    #[serde(default = "default_max_page_limit")]
+    pub max_limit: usize,
+}
+
+fn default_page_limit() -> usize {
+    crate::api::DEFAULT_LIMIT
+}
+
+fn default_max_page_limit() -> usize {
+    crate::api::MAX_LIMIT
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            default_limit: default_page_limit(),
+            max_limit: default_max_page_limit(),
+        }
+    }
+}
+
+/// In-process cache of devices, which ingested items are checked against,
+/// and of each device's latest reading and status, dropped for a device
+/// whenever data is ingested for it.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_enabled")]
+    pub enabled: bool,
+}
+
+fn default_cache_enabled() -> bool {
+    true
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_cache_enabled(),
+        }
+    }
+}
+
+/// Hourly limits on the readings each dispatcher uploads, against gateways
+/// replaying old data.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QuotaConfig {
+    /// Readings per dispatcher per hour; unlimited when unset
+    pub readings_per_hour: Option<u64>,
+    /// Per dispatcher limits, by id, overriding `readings_per_hour`
+    #[serde(default)]
+    pub dispatchers: HashMap<DispatcherId, u64>,
+    #[serde(default)]
+    pub action: QuotaAction,
+}
+
+impl QuotaConfig {
+    /// Readings per hour allowed for `dispatcher_id`, if limited.
+    pub fn limit(&self, dispatcher_id: DispatcherId) -> Option<u64> {
+        self.dispatchers
+            .get(&dispatcher_id)
+            .copied()
+            .or(self.readings_per_hour)
+    }
+}
+
+/// What happens to batches over a dispatcher's quota.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Refuse the batch; the dispatcher keeps it and retries later
+    #[default]
+    Reject,
+    /// Store the batch and flag the dispatcher as over quota
+    Flag,
+}
+
+/// Uploads of historical data through `POST /api/backfill`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BackfillConfig {
+    /// How far ahead of prime's clock items may be timestamped, for
+    /// gateways whose clock drifted while offline
+    #[serde(default = "default_backfill_max_future_skew_secs")]
+    pub max_future_skew_secs: u64,
+    /// Largest upload accepted, in megabytes
+    #[serde(default = "default_backfill_max_body_mb")]
+    pub max_body_mb: usize,
+    /// Items validated and stored at a time, between which live ingestion
+    /// gets its turn
+    #[serde(default = "default_backfill_chunk_size")]
+    pub chunk_size: usize,
+    /// Finished jobs whose progress is kept for the status endpoint
+    #[serde(default = "default_backfill_keep_jobs")]
+    pub keep_jobs: usize,
+}
+
+fn default_backfill_max_future_skew_secs() -> u64 {
+    86_400
+}
+
+fn default_backfill_max_body_mb() -> usize {
+    256
+}
+
+fn default_backfill_chunk_size() -> usize {
+    1000
+}
+
+fn default_backfill_keep_jobs() -> usize {
+    100
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            max_future_skew_secs: default_backfill_max_future_skew_secs(),
+            max_body_mb: default_backfill_max_body_mb(),
+            chunk_size: default_backfill_chunk_size(),
+            keep_jobs: default_backfill_keep_jobs(),
+        }
+    }
+}
+
+/// Compressed backups of the SQLite database, see [`crate::backup`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackupConfig {
+    /// Directory backups are written to
+    #[serde(default = "default_backup_dir")]
+    pub dir: PathBuf,
+    /// Seconds between scheduled backups, or none to only back up through
+    /// `POST /admin/backup`
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// Newest backups kept, older ones being deleted; 0 keeps them all
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+    /// zstd compression level, 1 to 22
+    #[serde(default = "default_backup_level")]
+    pub level: i32,
+}
+
+fn default_backup_dir() -> PathBuf {
+    PathBuf::from("backups")
+}
+
+fn default_backup_keep() -> usize {
+    7
+}
+
+fn default_backup_level() -> i32 {
+    3
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_backup_dir(),
+            interval_secs: None,
+            keep: default_backup_keep(),
+            level: default_backup_level(),
+        }
+    }
+}
+
+/// A message broker ingested readings and statuses are published to, one
+/// record per reading or status, keyed by device.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EgressConfig {
+    pub broker: BrokerConfig,
+    /// Kafka topic or NATS subject readings are published to
+    #[serde(default = "default_egress_readings_topic")]
+    pub readings_topic: String,
+    /// Kafka topic or NATS subject statuses are published to
+    #[serde(default = "default_egress_statuses_topic")]
+    pub statuses_topic: String,
+    #[serde(default)]
+    pub format: EgressFormat,
+    /// Attempts before a record is given up on
+    #[serde(default = "default_egress_max_attempts")]
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled on every further failure
+    #[serde(default = "default_egress_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_egress_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Records held while the broker is slow or down. Newer records are
+    /// dropped once it fills, so ingestion never waits on the broker.
+    #[serde(default = "default_egress_buffer")]
+    pub buffer: usize,
+}
+
+fn default_egress_readings_topic() -> String {
+    "ersha.readings".to_owned()
+}
+
+fn default_egress_statuses_topic() -> String {
+    "ersha.statuses".to_owned()
+}
+
+fn default_egress_max_attempts() -> u32 {
+    5
+}
+
+fn default_egress_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_egress_max_backoff_ms() -> u64 {
+    30_000
+}
+
+fn default_egress_buffer() -> usize {
+    10_000
+}
+
+/// Broker for egress. Each needs prime built with its feature.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BrokerConfig {
+    /// Kafka, through the `kafka` feature
+    Kafka {
+        /// Comma separated `host:port` bootstrap servers
+        brokers: String,
+        /// Further librdkafka producer properties
+        #[serde(default)]
+        properties: HashMap<String, String>,
+    },
+    /// NATS core publishing, through the `nats` feature
+    Nats { url: String },
+}
+
+/// How egress records are serialized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EgressFormat {
+    /// A flat JSON object per record
+    #[default]
+    Json,
+    /// The JSON object wrapped with its schema, as Kafka Connect's
+    /// `JsonConverter` expects with `schemas.enable`
+    SchemaJson,
+}
+
+/// A time-series database ingested readings are mirrored into, in batches,
+/// for graphing with its own Grafana datasource.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeseriesConfig {
+    pub sink: SinkConfig,
+    /// Most readings written in one request
+    #[serde(default = "default_timeseries_batch_size")]
+    pub batch_size: usize,
+    /// Attempts before a batch is given up on
+    #[serde(default = "default_timeseries_max_attempts")]
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled on every further failure
+    #[serde(default = "default_timeseries_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_timeseries_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Readings held while the database is slow or down. Newer readings are
+    /// dropped once it fills, so ingestion never waits on the database.
+    #[serde(default = "default_timeseries_buffer")]
+    pub buffer: usize,
+}
+
+fn default_timeseries_batch_size() -> usize {
+    1000
+}
+
+fn default_timeseries_max_attempts() -> u32 {
+    5
+}
+
+fn default_timeseries_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_timeseries_max_backoff_ms() -> u64 {
+    30_000
+}
+
+fn default_timeseries_buffer() -> usize {
+    50_000
+}
+
+/// Database readings are mirrored into.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    /// InfluxDB 2 or later, written line protocol over HTTP
+    Influx {
+        /// Base URL of the server, e.g. `http://localhost:8086`
+        url: String,
+        org: String,
+        bucket: String,
+        /// API token with write access to `bucket`
+        token: String,
+        #[serde(default = "default_influx_measurement")]
+        measurement: String,
+    },
+    /// A TimescaleDB hypertable, created if missing. Needs prime built with
+    /// the `timescale` feature.
+    Timescale {
+        /// Postgres connection URL
+        url: String,
+        #[serde(default = "default_timescale_table")]
+        table: String,
+    },
+}
+
+fn default_influx_measurement() -> String {
+    "reading".to_owned()
+}
+
+fn default_timescale_table() -> String {
+    "readings".to_owned()
+}
+
+/// Logins of users to the API and dashboard.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserConfig {
+    /// Hours a session key issued at login lasts
+    #[serde(default = "default_session_hours")]
+    pub session_hours: u32,
+    /// Log users in through an OIDC provider too
+    pub oidc: Option<OidcConfig>,
+}
+
+impl Default for UserConfig {
+    fn default() -> Self {
+        Self {
+            session_hours: default_session_hours(),
+            oidc: None,
+        }
+    }
+}
+
+fn default_session_hours() -> u32 {
+    12
+}
+
+/// An OIDC provider whose users are mapped to prime users by subject.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+    /// Issuer URL, recorded with each user's subject
+    pub issuer: String,
+    /// Userinfo endpoint access tokens are checked against
+    pub userinfo_url: String,
+}
+
+/// Senders for notifying contacts of alerts. A channel without a sender
+/// isn't delivered to. Retries and timeouts follow `[webhooks]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NotificationConfig {
+    pub sms: Option<SmsConfig>,
+    pub telegram: Option<TelegramConfig>,
+}
+
+/// An HTTP SMS gateway, sent a JSON object with the recipient and text.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmsConfig {
+    /// Endpoint messages are POSTed to
+    pub url: String,
+    /// Sent as a bearer token when set
+    pub token: Option<String>,
+    /// Sender id or number, sent as `from` when set
+    pub sender: Option<String>,
+    /// Name of the field holding the recipient's number
+    #[serde(default = "default_sms_to_field")]
+    pub to_field: String,
+    /// Name of the field holding the message text
+    #[serde(default = "default_sms_text_field")]
+    pub text_field: String,
+}
+
+fn default_sms_to_field() -> String {
+    "to".to_owned()
+}
+
+fn default_sms_text_field() -> String {
+    "message".to_owned()
+}
+
+/// A Telegram bot that messages contacts' chats.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    #[serde(default = "default_telegram_api_url")]
+    pub api_url: String,
+}
+
+fn default_telegram_api_url() -> String {
+    "https://api.telegram.org".to_owned()
+}
+
+/// TLS for the RPC server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain presented to dispatchers
+    pub cert: PathBuf,
+    /// PEM private key for `cert`
+    pub key: PathBuf,
+    /// PEM CA issuing dispatcher client certificates. When set, dispatchers
+    /// must present a certificate naming their id to connect.
+    pub client_ca: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -15,13 +1105,240 @@ pub struct ServerConfig {
     pub rpc_addr: SocketAddr,
     /// Address for the HTTP server to listen on
     pub http_addr: SocketAddr,
+    /// Seconds between keepalive pings to each dispatcher; 0 turns them off
+    #[serde(default = "default_keepalive_interval_secs")]
+    pub keepalive_interval_secs: u64,
+    /// Seconds a dispatcher has to answer a keepalive before it is dropped
+    #[serde(default = "default_keepalive_timeout_secs")]
+    pub keepalive_timeout_secs: u64,
+    /// Messages queued at most for each dispatcher that reads slowly
+    #[serde(default = "default_write_queue_capacity")]
+    pub write_queue_capacity: usize,
+    /// What happens to messages sent while a dispatcher's queue is full
+    #[serde(default)]
+    pub write_queue_overflow: Overflow,
+    /// Seconds a dispatcher has to finish sending a frame it started; 0
+    /// waits forever
+    #[serde(default = "default_frame_timeout_secs")]
+    pub frame_timeout_secs: u64,
+    /// Largest frame accepted from a dispatcher, compressed or not. Batches
+    /// are streamed in chunks that fit.
+    #[serde(default = "default_max_frame_bytes")]
+    pub max_frame_bytes: u32,
+    /// Seconds to wait on shutdown for dispatchers to finish the uploads
+    /// they are in the middle of
+    #[serde(default = "default_drain_timeout_secs")]
+    pub drain_timeout_secs: u64,
+    /// Also accept RPC tunneled over WebSockets on the HTTP server, for
+    /// dispatchers whose network only lets HTTP(S) out
+    #[serde(default)]
+    pub rpc_tunnel: bool,
+    /// Dispatcher connections open at once, tunneled ones included; 0 leaves
+    /// them unbounded. Keep it under the open file limit: past it, new
+    /// connections wait to be accepted.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// Connections open at once from one IP address; 0 leaves them
+    /// unbounded. Dispatchers behind a shared NAT count together, and so do
+    /// tunneled connections whose address isn't known.
+    #[serde(default)]
+    pub max_connections_per_ip: usize,
+    /// Connections accepted per second, with `accept_burst` on top; 0
+    /// accepts them as fast as they come
+    #[serde(default = "default_accept_per_second")]
+    pub accept_per_second: u32,
+    #[serde(default = "default_accept_burst")]
+    pub accept_burst: u32,
+    /// Seconds a new connection has to finish its TLS handshake and have a
+    /// hello accepted before it is closed; 0 waits forever
+    #[serde(default = "default_hello_timeout_secs")]
+    pub hello_timeout_secs: u64,
+}
+
+fn default_keepalive_interval_secs() -> u64 {
+    30
+}
+
+fn default_keepalive_timeout_secs() -> u64 {
+    10
+}
+
+fn default_write_queue_capacity() -> usize {
+    1024
+}
+
+fn default_frame_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_frame_bytes() -> u32 {
+    MAX_FRAME_BYTES
+}
+
+fn default_drain_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_connections() -> usize {
+    10_000
+}
+
+fn default_accept_per_second() -> u32 {
+    200
+}
+
+fn default_accept_burst() -> u32 {
+    500
+}
+
+fn default_hello_timeout_secs() -> u64 {
+    10
+}
+
+impl ServerConfig {
+    /// Keepalive for dispatcher connections, if enabled.
+    pub fn keepalive(&self) -> Option<Keepalive> {
+        (self.keepalive_interval_secs > 0).then(|| Keepalive {
+            interval: Duration::from_secs(self.keepalive_interval_secs),
+            timeout: Duration::from_secs(self.keepalive_timeout_secs),
+        })
+    }
+
+    /// Bound on the messages waiting to be written to each dispatcher.
+    pub fn write_queue(&self) -> WriteQueue {
+        WriteQueue {
+            capacity: self.write_queue_capacity,
+            overflow: self.write_queue_overflow,
+        }
+    }
+
+    /// Bounds on the dispatcher connections held open.
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections: (self.max_connections > 0).then_some(self.max_connections),
+            max_per_ip: (self.max_connections_per_ip > 0).then_some(self.max_connections_per_ip),
+            accept_rate: (self.accept_per_second > 0)
+                .then(|| Quota::new(self.accept_per_second, self.accept_burst)),
+            hello_timeout: (self.hello_timeout_secs > 0)
+                .then(|| Duration::from_secs(self.hello_timeout_secs)),
+        }
+    }
+
+    /// Bounds on the frames read from each dispatcher.
+    pub fn read_limits(&self) -> ReadLimits {
+        ReadLimits {
+            max_frame_bytes: self.max_frame_bytes,
+            timeout: (self.frame_timeout_secs > 0)
+                .then(|| Duration::from_secs(self.frame_timeout_secs)),
+        }
+    }
+}
+
+/// How the HTTP server treats every request, whichever route it is for.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct HttpConfig {
+    /// Origins, such as `https://dashboard.example.com`, whose browser pages
+    /// may call the API. None may when empty.
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+    /// Largest request body accepted, in kilobytes. Bulk uploads and
+    /// firmware images have their own limits.
+    #[serde(default = "default_max_body_kb")]
+    pub max_body_kb: usize,
+    /// Seconds a request has to be answered before it fails with a 408; 0
+    /// waits forever. Streams keep going once their response has started.
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// Gzip responses for clients that accept it
+    #[serde(default = "default_compression")]
+    pub compression: bool,
+}
+
+fn default_max_body_kb() -> usize {
+    2048
+}
+
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_compression() -> bool {
+    true
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            cors_origins: Vec::new(),
+            max_body_kb: default_max_body_kb(),
+            request_timeout_secs: default_request_timeout_secs(),
+            compression: default_compression(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum RegistryConfig {
     Memory,
-    Sqlite { path: PathBuf },
+    Sqlite {
+        path: PathBuf,
+        #[serde(default)]
+        pool: SqlitePoolConfig,
+    },
+}
+
+/// Connections to the SQLite database, shared by every registry.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SqlitePoolConfig {
+    #[serde(default = "default_sqlite_max_connections")]
+    pub max_connections: u32,
+    /// Milliseconds a statement waits on a locked database before failing
+    /// with `database is locked`
+    #[serde(default = "default_sqlite_busy_timeout_ms")]
+    pub busy_timeout_ms: u64,
+    /// Seconds a query waits for a free connection
+    #[serde(default = "default_sqlite_acquire_timeout_secs")]
+    pub acquire_timeout_secs: u64,
+    /// Write-ahead logging, letting reads go on while a write is in progress
+    #[serde(default = "default_sqlite_wal")]
+    pub wal: bool,
+    /// Writes of readings and statuses waiting for the single writer task;
+    /// 0 lets them go straight to the pool instead
+    #[serde(default = "default_sqlite_write_queue")]
+    pub write_queue: usize,
+}
+
+fn default_sqlite_max_connections() -> u32 {
+    8
+}
+
+fn default_sqlite_busy_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_sqlite_acquire_timeout_secs() -> u64 {
+    30
+}
+
+fn default_sqlite_wal() -> bool {
+    true
+}
+
+fn default_sqlite_write_queue() -> usize {
+    1024
+}
+
+impl Default for SqlitePoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: default_sqlite_max_connections(),
+            busy_timeout_ms: default_sqlite_busy_timeout_ms(),
+            acquire_timeout_secs: default_sqlite_acquire_timeout_secs(),
+            wal: default_sqlite_wal(),
+            write_queue: default_sqlite_write_queue(),
+        }
+    }
 }
 
 impl Config {
@@ -38,8 +1355,46 @@ impl Default for Config {
             server: ServerConfig {
                 rpc_addr: "0.0.0.0:9000".parse().unwrap(),
                 http_addr: "0.0.0.0:8080".parse().unwrap(),
+                keepalive_interval_secs: default_keepalive_interval_secs(),
+                keepalive_timeout_secs: default_keepalive_timeout_secs(),
+                write_queue_capacity: default_write_queue_capacity(),
+                write_queue_overflow: Overflow::default(),
+                frame_timeout_secs: default_frame_timeout_secs(),
+                max_frame_bytes: default_max_frame_bytes(),
+                drain_timeout_secs: default_drain_timeout_secs(),
+                rpc_tunnel: false,
+                max_connections: default_max_connections(),
+                max_connections_per_ip: 0,
+                accept_per_second: default_accept_per_second(),
+                accept_burst: default_accept_burst(),
+                hello_timeout_secs: default_hello_timeout_secs(),
             },
             registry: RegistryConfig::Memory,
+            http: HttpConfig::default(),
+            auth: AuthConfig::default(),
+            users: UserConfig::default(),
+            health: HealthConfig::default(),
+            retention: RetentionConfig::default(),
+            webhooks: WebhookConfig::default(),
+            notifications: NotificationConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            indicators: IndicatorConfig::default(),
+            forecast: ForecastConfig::default(),
+            irrigation: IrrigationConfig::default(),
+            corrections: CorrectionConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            firmware: FirmwareConfig::default(),
+            data_quality: QualityConfig::default(),
+            memory: MemoryConfig::default(),
+            log: LogConfig::default(),
+            pagination: PaginationConfig::default(),
+            cache: CacheConfig::default(),
+            quota: QuotaConfig::default(),
+            backfill: BackfillConfig::default(),
+            backup: BackupConfig::default(),
+            egress: None,
+            timeseries: None,
+            tls: None,
         }
     }
 }
@@ -0,0 +1,510 @@
+//! Retroactive corrections of mis-calibrated sensors.
+//!
+//! A [`Correction`] records a transform to apply to one sensor's readings
+//! over a time range. The reprocessing task rewrites those readings with
+//! corrected values and rebuilds the rollups covering them. Every change is
+//! kept as a [`Revision`] linking the reading to the correction and the
+//! value it replaced, so the value as ingested is never lost.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use ersha_core::{DeviceId, Percentage, ReadingId, SensorId, SensorMetric, SensorReading};
+use jiff::{SignedDuration, Timestamp};
+use ordered_float::NotNan;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::config::CorrectionConfig;
+use crate::registry::{
+    AggregateRegistry, CorrectionRegistry, ReadingRegistry, Registries,
+    filter::{Cursor, Pagination, QueryOptions, ReadingFilter, ReadingSortBy, SortOrder},
+};
+use crate::rollup::{self, Aggregate, AggregateKey, Granularity};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Readings rewritten at a time.
+const BATCH: usize = 500;
+
+#[derive(Debug, Error)]
+pub enum CorrectionError {
+    #[error("failed to read or rewrite readings: {0}")]
+    Readings(#[source] BoxError),
+    #[error("failed to record revisions: {0}")]
+    Revisions(#[source] BoxError),
+    #[error("failed to rebuild rollups: {0}")]
+    Aggregates(#[source] BoxError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct CorrectionId(pub Ulid);
+
+/// How a corrected value is computed from the stored one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Transform {
+    /// `value * scale + offset`
+    Linear { scale: f64, offset: f64 },
+}
+
+impl Transform {
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Transform::Linear { scale, offset } => value * scale + offset,
+        }
+    }
+
+    /// Why the transform can't be applied, if it can't.
+    pub fn invalid(self) -> Option<&'static str> {
+        match self {
+            Transform::Linear { scale, offset } if !scale.is_finite() || !offset.is_finite() => {
+                Some("scale and offset must be finite")
+            }
+            Transform::Linear { scale: 0.0, .. } => Some("scale must not be zero"),
+            Transform::Linear { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CorrectionState {
+    /// Waiting for the reprocessing task
+    Pending,
+    /// Every reading in range has been corrected
+    Applied,
+}
+
+impl CorrectionState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CorrectionState::Pending => "pending",
+            CorrectionState::Applied => "applied",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let state = match s {
+            "pending" => CorrectionState::Pending,
+            "applied" => CorrectionState::Applied,
+            _ => return None,
+        };
+
+        Some(state)
+    }
+}
+
+/// A calibration correction of one sensor's readings taken from `from`
+/// through `until`.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Correction {
+    pub id: CorrectionId,
+    pub device_id: DeviceId,
+    pub sensor_id: SensorId,
+    pub from: Timestamp,
+    pub until: Timestamp,
+    pub transform: Transform,
+    /// Why the readings are corrected, e.g. the calibration check that found
+    /// the error
+    pub reason: String,
+    pub state: CorrectionState,
+    /// Readings corrected so far
+    pub readings: usize,
+    /// Why the last attempt to apply the correction failed; it is retried
+    pub error: Option<String>,
+    pub created_at: Timestamp,
+    pub applied_at: Option<Timestamp>,
+}
+
+impl Correction {
+    pub fn new(
+        device_id: DeviceId,
+        sensor_id: SensorId,
+        from: Timestamp,
+        until: Timestamp,
+        transform: Transform,
+        reason: String,
+        now: Timestamp,
+    ) -> Self {
+        Self {
+            id: CorrectionId(Ulid::new()),
+            device_id,
+            sensor_id,
+            from,
+            until,
+            transform,
+            reason,
+            state: CorrectionState::Pending,
+            readings: 0,
+            error: None,
+            created_at: now,
+            applied_at: None,
+        }
+    }
+}
+
+/// A change a correction made to a reading. A reading's first revision
+/// holds its value as ingested.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Revision {
+    pub reading_id: ReadingId,
+    pub correction_id: CorrectionId,
+    pub previous: f64,
+    pub corrected: f64,
+    pub revised_at: Timestamp,
+}
+
+/// `metric` holding `value` instead, rounded and clamped for percentages.
+fn with_value(metric: &SensorMetric, value: f64) -> SensorMetric {
+    let percentage = || Percentage(value.round().clamp(0.0, 100.0) as u8);
+    let not_nan = |old: NotNan<f64>| NotNan::new(value).unwrap_or(old);
+
+    match *metric {
+        SensorMetric::SoilMoisture { .. } => SensorMetric::SoilMoisture {
+            value: percentage(),
+        },
+        SensorMetric::Humidity { .. } => SensorMetric::Humidity {
+            value: percentage(),
+        },
+        SensorMetric::SoilTemp { value: old } => SensorMetric::SoilTemp {
+            value: not_nan(old),
+        },
+        SensorMetric::AirTemp { value: old } => SensorMetric::AirTemp {
+            value: not_nan(old),
+        },
+        SensorMetric::Rainfall { value: old } => SensorMetric::Rainfall {
+            value: not_nan(old),
+        },
+    }
+}
+
+/// Apply pending corrections every `interval_secs` until cancelled.
+pub async fn run<R: Registries>(
+    registries: R,
+    config: CorrectionConfig,
+    cancel: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        match apply_pending(&registries, Timestamp::now()).await {
+            Ok(0) => {}
+            Ok(applied) => info!(applied, "reading corrections applied"),
+            Err(e) => error!(error = %e, "reading corrections failed"),
+        }
+    }
+}
+
+/// Apply every pending correction, oldest first, returning how many were
+/// applied. A correction that fails stays pending with its error recorded.
+pub async fn apply_pending<R: Registries>(
+    registries: &R,
+    now: Timestamp,
+) -> Result<usize, CorrectionError> {
+    let corrections = registries.corrections();
+    let pending = corrections
+        .pending()
+        .await
+        .map_err(|e| CorrectionError::Revisions(Box::new(e)))?;
+
+    let mut applied = 0;
+    for mut correction in pending {
+        match apply(registries, &correction, now).await {
+            Ok(readings) => {
+                correction.state = CorrectionState::Applied;
+                correction.readings = readings;
+                correction.error = None;
+                correction.applied_at = Some(now);
+                applied += 1;
+            }
+            Err(e) => {
+                error!(correction_id = ?correction.id, error = %e, "failed to apply correction");
+                correction.error = Some(e.to_string());
+            }
+        }
+
+        corrections
+            .update(correction)
+            .await
+            .map_err(|e| CorrectionError::Revisions(Box::new(e)))?;
+    }
+
+    Ok(applied)
+}
+
+/// Correct the readings in range of `correction` and rebuild their rollups,
+/// returning how many readings it has corrected.
+///
+/// Safe to retry: readings the correction already revised are set to their
+/// recorded corrected value rather than transformed again.
+pub async fn apply<R: Registries>(
+    registries: &R,
+    correction: &Correction,
+    now: Timestamp,
+) -> Result<usize, CorrectionError> {
+    let readings = registries.readings();
+    let corrections = registries.corrections();
+
+    let revised: HashMap<ReadingId, f64> = corrections
+        .revisions_by(correction.id)
+        .await
+        .map_err(|e| CorrectionError::Revisions(Box::new(e)))?
+        .into_iter()
+        .map(|revision| (revision.reading_id, revision.corrected))
+        .collect();
+    let mut corrected = revised.len();
+
+    let filter = ReadingFilter::builder()
+        .device_ids([correction.device_id])
+        .sensor_ids([correction.sensor_id])
+        .after(correction.from)
+        .before(correction.until)
+        .build();
+    let mut after = None;
+    loop {
+        let page = sensor_readings(registries, filter.clone(), after).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = Some(ReadingSortBy::Timestamp.cursor(last));
+        let full = page.len() == BATCH;
+
+        let mut revisions = Vec::new();
+        let mut rewritten = Vec::new();
+        for mut reading in page {
+            let previous = rollup::metric_value(&reading.metric);
+            let value = match revised.get(&reading.id) {
+                Some(&value) => value,
+                None => {
+                    let metric = with_value(&reading.metric, correction.transform.apply(previous));
+                    let value = rollup::metric_value(&metric);
+                    revisions.push(Revision {
+                        reading_id: reading.id,
+                        correction_id: correction.id,
+                        previous,
+                        corrected: value,
+                        revised_at: now,
+                    });
+                    value
+                }
+            };
+
+            if value != previous {
+                reading.metric = with_value(&reading.metric, value);
+                rewritten.push(reading);
+            }
+        }
+
+        // Revisions go first, so a retry after a failed rewrite knows the
+        // value each reading should end up with.
+        corrected += revisions.len();
+        corrections
+            .record_revisions(revisions)
+            .await
+            .map_err(|e| CorrectionError::Revisions(Box::new(e)))?;
+        for reading in rewritten {
+            readings
+                .store(reading)
+                .await
+                .map_err(|e| CorrectionError::Readings(Box::new(e)))?;
+        }
+
+        if !full {
+            break;
+        }
+    }
+
+    rebuild_rollups(registries, correction).await?;
+
+    Ok(corrected)
+}
+
+/// Recompute the rollups of the days `correction` covers from the sensor's
+/// stored readings.
+async fn rebuild_rollups<R: Registries>(
+    registries: &R,
+    correction: &Correction,
+) -> Result<(), CorrectionError> {
+    let start = Granularity::Day.bucket_start(correction.from);
+    let end = Granularity::Day.bucket_start(correction.until) + SignedDuration::from_hours(24);
+    let filter = ReadingFilter::builder()
+        .device_ids([correction.device_id])
+        .sensor_ids([correction.sensor_id])
+        .after(start)
+        .before(end - SignedDuration::from_nanos(1))
+        .build();
+
+    let mut rebuilt: HashMap<AggregateKey, Aggregate> = HashMap::new();
+    let mut after = None;
+    loop {
+        let page = sensor_readings(registries, filter.clone(), after).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = Some(ReadingSortBy::Timestamp.cursor(last));
+        let full = page.len() == BATCH;
+
+        for partial in rollup::rollup(&page) {
+            rebuilt
+                .entry(partial.key())
+                .and_modify(|aggregate| aggregate.merge(&partial))
+                .or_insert(partial);
+        }
+
+        if !full {
+            break;
+        }
+    }
+
+    registries
+        .aggregates()
+        .replace(rebuilt.into_values().collect())
+        .await
+        .map_err(|e| CorrectionError::Aggregates(Box::new(e)))
+}
+
+/// The page of matching readings following `after`, oldest first.
+async fn sensor_readings<R: Registries>(
+    registries: &R,
+    filter: ReadingFilter,
+    after: Option<Cursor>,
+) -> Result<Vec<SensorReading>, CorrectionError> {
+    registries
+        .readings()
+        .list(QueryOptions {
+            filter,
+            sort_by: ReadingSortBy::Timestamp,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Cursor {
+                after,
+                limit: BATCH,
+            },
+        })
+        .await
+        .map_err(|e| CorrectionError::Readings(Box::new(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, SensorId, SensorMetric, SensorReading};
+    use jiff::Timestamp;
+    use ordered_float::NotNan;
+    use ulid::Ulid;
+
+    use super::{Correction, CorrectionState, Transform, apply_pending};
+    use crate::fixtures;
+    use crate::registry::filter::AggregateFilter;
+    use crate::registry::memory::InMemoryRegistries;
+    use crate::registry::{AggregateRegistry, CorrectionRegistry, ReadingRegistry, Registries};
+    use crate::rollup::{self, Granularity};
+
+    fn reading(device_id: DeviceId, sensor_id: SensorId, second: i64, temp: f64) -> SensorReading {
+        SensorReading {
+            device_id,
+            metric: SensorMetric::SoilTemp {
+                value: NotNan::new(temp).unwrap(),
+            },
+            timestamp: Timestamp::from_second(second).unwrap(),
+            sensor_id,
+            ..fixtures::reading()
+        }
+    }
+
+    #[tokio::test]
+    async fn corrections_rewrite_readings_in_range_and_keep_originals() {
+        let registries = InMemoryRegistries::default();
+        let device = DeviceId(Ulid::new());
+        let sensor = SensorId(Ulid::new());
+        let before = reading(device, sensor, 3_600, 20.0);
+        let inside = reading(device, sensor, 7_200, 21.0);
+        let other_sensor = reading(device, SensorId(Ulid::new()), 7_200, 21.0);
+        let stored = vec![before.clone(), inside.clone(), other_sensor.clone()];
+        registries
+            .readings()
+            .batch_store(stored.clone())
+            .await
+            .unwrap();
+        registries
+            .aggregates()
+            .merge(rollup::rollup(&stored))
+            .await
+            .unwrap();
+
+        let correction = Correction::new(
+            device,
+            sensor,
+            Timestamp::from_second(7_000).unwrap(),
+            Timestamp::from_second(8_000).unwrap(),
+            Transform::Linear {
+                scale: 1.0,
+                offset: -1.5,
+            },
+            "probe read 1.5 °C high".to_owned(),
+            Timestamp::now(),
+        );
+        registries
+            .corrections()
+            .create(correction.clone())
+            .await
+            .unwrap();
+
+        let now = Timestamp::now();
+        assert_eq!(apply_pending(&registries, now).await.unwrap(), 1);
+        // Applied corrections aren't applied again.
+        assert_eq!(apply_pending(&registries, now).await.unwrap(), 0);
+
+        let value = |id| {
+            let registries = registries.clone();
+            async move {
+                let reading = registries.readings().get(id).await.unwrap().unwrap();
+                rollup::metric_value(&reading.metric)
+            }
+        };
+        assert_eq!(value(inside.id).await, 19.5);
+        assert_eq!(value(before.id).await, 20.0);
+        assert_eq!(value(other_sensor.id).await, 21.0);
+
+        let revisions = registries.corrections().revisions(inside.id).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].correction_id, correction.id);
+        assert_eq!(
+            (revisions[0].previous, revisions[0].corrected),
+            (21.0, 19.5)
+        );
+
+        let applied = registries
+            .corrections()
+            .get(correction.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(applied.state, CorrectionState::Applied);
+        assert_eq!(applied.readings, 1);
+
+        let daily = registries
+            .aggregates()
+            .list(
+                AggregateFilter::builder(Granularity::Day)
+                    .device_ids([device])
+                    .build(),
+            )
+            .await
+            .unwrap();
+        let corrected = daily.iter().find(|a| a.sensor_id == sensor).unwrap();
+        assert_eq!(corrected.count, 2);
+        assert_eq!(corrected.sum, 39.5);
+        assert_eq!(corrected.min, 19.5);
+    }
+}
@@ -0,0 +1,390 @@
+//! Batch items prime refused.
+//!
+//! Items failing validation are kept as [`DeadLetter`]s, with the reason and
+//! the dispatcher that sent them, rather than dropped. Once the cause is
+//! fixed — a device assigned to the right dispatcher, say, or a clock
+//! corrected — they can be re-driven through the same checks as a batch.
+//!
+//! Items naming devices or sensors prime doesn't know are quarantined: they
+//! are dead letters like any other, reviewed on their own until the device
+//! is registered and they are released, or they are discarded.
+
+use std::collections::{HashMap, HashSet};
+
+use ersha_core::{BatchId, DeviceId, DeviceStatus, DispatcherId, InvalidItemReason, SensorReading};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::config::AuthConfig;
+use crate::events::{BusEvent, EventBus};
+use crate::registry::{DeadLetterRegistry, DeviceStatusRegistry, ReadingRegistry, Registries};
+use crate::rpc;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum DeadLetterError {
+    #[error("failed to look up devices: {0}")]
+    Devices(#[source] BoxError),
+    #[error("failed to store re-driven items: {0}")]
+    Store(#[source] BoxError),
+    #[error("failed to update dead letters: {0}")]
+    DeadLetters(#[source] BoxError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct DeadLetterId(pub Ulid);
+
+/// Reasons an item is quarantined for rather than merely refused.
+pub const QUARANTINE_REASONS: [InvalidItemReason; 2] = [
+    InvalidItemReason::UnknownDevice,
+    InvalidItemReason::UnknownSensor,
+];
+
+/// A refused item, as the dispatcher sent it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", content = "item", rename_all = "snake_case")]
+pub enum DeadItem {
+    Reading(SensorReading),
+    Status(DeviceStatus),
+}
+
+impl DeadItem {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeadItem::Reading(_) => "reading",
+            DeadItem::Status(_) => "status",
+        }
+    }
+
+    /// The id of the reading or status.
+    pub fn item_id(&self) -> Ulid {
+        match self {
+            DeadItem::Reading(reading) => reading.id.0,
+            DeadItem::Status(status) => status.id.0,
+        }
+    }
+
+    pub fn device_id(&self) -> DeviceId {
+        match self {
+            DeadItem::Reading(reading) => reading.device_id,
+            DeadItem::Status(status) => status.device_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterState {
+    /// Refused, and again on every re-drive so far
+    Pending,
+    /// Accepted on a re-drive
+    Redriven,
+}
+
+impl DeadLetterState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeadLetterState::Pending => "pending",
+            DeadLetterState::Redriven => "redriven",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let state = match s {
+            "pending" => DeadLetterState::Pending,
+            "redriven" => DeadLetterState::Redriven,
+            _ => return None,
+        };
+
+        Some(state)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DeadLetter {
+    pub id: DeadLetterId,
+    /// The dispatcher that uploaded the item
+    pub dispatcher_id: DispatcherId,
+    pub batch_id: BatchId,
+    pub item: DeadItem,
+    /// Why the item was refused, on its latest attempt
+    pub reason: InvalidItemReason,
+    pub state: DeadLetterState,
+    /// Times the item was re-driven
+    pub attempts: u32,
+    pub received_at: Timestamp,
+    pub redriven_at: Option<Timestamp>,
+}
+
+impl DeadLetter {
+    pub fn new(
+        dispatcher_id: DispatcherId,
+        batch_id: BatchId,
+        item: DeadItem,
+        reason: InvalidItemReason,
+        now: Timestamp,
+    ) -> Self {
+        Self {
+            id: DeadLetterId(Ulid::new()),
+            dispatcher_id,
+            batch_id,
+            item,
+            reason,
+            state: DeadLetterState::Pending,
+            attempts: 0,
+            received_at: now,
+            redriven_at: None,
+        }
+    }
+
+    /// Whether the item is held for naming a device or sensor prime doesn't
+    /// know.
+    pub fn is_quarantined(&self) -> bool {
+        self.state == DeadLetterState::Pending && QUARANTINE_REASONS.contains(&self.reason)
+    }
+}
+
+/// Check the pending `letters` again as of `now`, storing the items that
+/// pass as if their dispatcher had just uploaded them, and return the
+/// letters as updated. Items still refused stay pending with the latest
+/// reason.
+///
+/// Re-driven items are published to `events` like ingested ones, readings
+/// marked late: they are by the time they're accepted.
+pub async fn redrive<R: Registries>(
+    registries: &R,
+    auth: AuthConfig,
+    events: &dyn EventBus,
+    letters: Vec<DeadLetter>,
+    now: Timestamp,
+) -> Result<Vec<DeadLetter>, DeadLetterError> {
+    let mut by_dispatcher: HashMap<DispatcherId, Vec<DeadLetter>> = HashMap::new();
+    for letter in letters {
+        if letter.state == DeadLetterState::Pending {
+            by_dispatcher
+                .entry(letter.dispatcher_id)
+                .or_default()
+                .push(letter);
+        }
+    }
+
+    let mut updated = Vec::new();
+    for (dispatcher_id, letters) in by_dispatcher {
+        let device_ids: HashSet<DeviceId> = letters
+            .iter()
+            .map(|letter| letter.item.device_id())
+            .collect();
+        let checks = rpc::item_checks(registries, auth, dispatcher_id, device_ids, now)
+            .await
+            .map_err(|e| DeadLetterError::Devices(Box::new(e)))?;
+
+        let mut readings = Vec::new();
+        let mut statuses = Vec::new();
+        for mut letter in letters {
+            letter.attempts += 1;
+            let refused = match &letter.item {
+                DeadItem::Reading(reading) => checks.reading(reading),
+                DeadItem::Status(status) => checks.status(status),
+            };
+            match refused {
+                Some(reason) => letter.reason = reason,
+                None => {
+                    match &letter.item {
+                        DeadItem::Reading(reading) => readings.push(reading.clone()),
+                        DeadItem::Status(status) => statuses.push(status.clone()),
+                    }
+                    letter.state = DeadLetterState::Redriven;
+                    letter.redriven_at = Some(now);
+                }
+            }
+            updated.push(letter);
+        }
+
+        // Items stored before are skipped, so a re-drive that fails part way
+        // can be repeated.
+        let stored: HashSet<_> = registries
+            .readings()
+            .batch_store(readings.clone())
+            .await
+            .map_err(|e| DeadLetterError::Store(Box::new(e)))?
+            .into_iter()
+            .collect();
+        let stored_statuses: HashSet<_> = registries
+            .statuses()
+            .batch_store(statuses.clone())
+            .await
+            .map_err(|e| DeadLetterError::Store(Box::new(e)))?
+            .into_iter()
+            .collect();
+
+        readings.retain(|reading| stored.contains(&reading.id));
+        statuses.retain(|status| stored_statuses.contains(&status.id));
+        if !readings.is_empty() {
+            events
+                .publish(BusEvent::ReadingsIngested {
+                    readings: readings.into(),
+                    late: true,
+                })
+                .await;
+        }
+        if !statuses.is_empty() {
+            events
+                .publish(BusEvent::StatusesIngested {
+                    statuses: statuses.into(),
+                })
+                .await;
+        }
+    }
+
+    for letter in &updated {
+        registries
+            .dead_letters()
+            .update(letter.clone())
+            .await
+            .map_err(|e| DeadLetterError::DeadLetters(Box::new(e)))?;
+    }
+    updated.sort_by_key(|letter| letter.id.0);
+
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        BatchId, Device, DeviceId, DeviceKind, DeviceState, DispatcherId, H3Cell,
+        InvalidItemReason, Percentage, ReadingId, SensorId, SensorMetric, SensorReading,
+    };
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::{DeadItem, DeadLetter, DeadLetterState, redrive};
+    use crate::config::AuthConfig;
+    use crate::events::{BusEvent, EventBus, LocalEventBus, Received};
+    use crate::registry::{
+        DeadLetterRegistry, DeviceRegistry, ReadingRegistry, Registries, memory::InMemoryRegistries,
+    };
+
+    #[tokio::test]
+    async fn redriven_items_are_stored_once_the_cause_is_fixed() {
+        let registries = InMemoryRegistries::default();
+        let events = LocalEventBus::new();
+        let mut published = events.subscribe();
+        let auth = AuthConfig {
+            require_dispatcher_auth: false,
+            approve_new_dispatchers: true,
+            hello_max_skew_secs: 300,
+            require_device_assignment: false,
+            quarantine_unknown_devices: false,
+        };
+        let dispatcher = DispatcherId(Ulid::new());
+        let other = DispatcherId(Ulid::new());
+        let device = DeviceId(Ulid::new());
+        registries
+            .devices()
+            .register(Device {
+                id: device,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        registries
+            .devices()
+            .set_dispatcher(device, Some(other))
+            .await
+            .unwrap();
+
+        let reading = SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id: device,
+            dispatcher_id: dispatcher,
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(40),
+            },
+            location: H3Cell(0x8a2a1072b59ffff),
+            confidence: Percentage(90),
+            timestamp: Timestamp::now(),
+            sensor_id: SensorId(Ulid::new()),
+        };
+        let letter = DeadLetter::new(
+            dispatcher,
+            BatchId(Ulid::new()),
+            DeadItem::Reading(reading.clone()),
+            InvalidItemReason::DeviceNotAssigned,
+            Timestamp::now(),
+        );
+        // Recording the same item again keeps the first letter.
+        registries
+            .dead_letters()
+            .record(vec![letter.clone(), letter.clone()])
+            .await
+            .unwrap();
+
+        let still_refused = redrive(
+            &registries,
+            auth,
+            &events,
+            vec![letter.clone()],
+            Timestamp::now(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(still_refused[0].state, DeadLetterState::Pending);
+        assert_eq!(still_refused[0].attempts, 1);
+        assert!(
+            registries
+                .readings()
+                .get(reading.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        registries
+            .devices()
+            .set_dispatcher(device, Some(dispatcher))
+            .await
+            .unwrap();
+        let redriven = redrive(
+            &registries,
+            auth,
+            &events,
+            still_refused.clone(),
+            Timestamp::now(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(redriven[0].state, DeadLetterState::Redriven);
+        assert_eq!(redriven[0].attempts, 2);
+        assert_eq!(
+            registries.readings().get(reading.id).await.unwrap(),
+            Some(reading.clone())
+        );
+        match published.try_recv() {
+            Some(Received::Event(event)) => assert!(matches!(
+                &*event,
+                BusEvent::ReadingsIngested { readings, late: true } if readings[..] == [reading]
+            )),
+            other => panic!("expected the re-driven reading, got {other:?}"),
+        }
+        assert_eq!(
+            registries.dead_letters().get(letter.id).await.unwrap(),
+            Some(redriven[0].clone())
+        );
+        // Letters already re-driven are left alone.
+        assert!(
+            redrive(&registries, auth, &events, redriven, Timestamp::now())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Per-field agronomic indicators derived from the stored rollups.
+//!
+//! A field is an H3 cell at the configured resolution. Every device located
+//! inside it contributes, and each indicator is computed per UTC day.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+use ersha_core::{DeviceId, H3Cell, SensorKind};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::config::IndicatorConfig;
+use crate::region;
+use crate::registry::{
+    AggregateRegistry, DerivedMetricRegistry, DeviceRegistry, Registries,
+    filter::{AggregateFilter, DeviceSortBy, Pagination, QueryOptions, SortOrder},
+};
+use crate::rollup::{Aggregate, Granularity};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum DerivedError {
+    #[error("failed to list devices: {0}")]
+    Devices(#[source] BoxError),
+    #[error("failed to read aggregates: {0}")]
+    Aggregates(#[source] BoxError),
+    #[error("failed to store indicators: {0}")]
+    Store(#[source] BoxError),
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum IndicatorKind {
+    /// Degrees above the base temperature, from the day's mean air temperature
+    GrowingDegreeDays,
+    /// Rain gauge total in mm, averaged over the field's gauges
+    Rainfall,
+    /// Hours in which any air temperature sensor reached the frost threshold
+    FrostHours,
+    /// Percentage points of soil moisture below field capacity
+    SoilMoistureDeficit,
+}
+
+impl IndicatorKind {
+    pub const ALL: [IndicatorKind; 4] = [
+        IndicatorKind::GrowingDegreeDays,
+        IndicatorKind::Rainfall,
+        IndicatorKind::FrostHours,
+        IndicatorKind::SoilMoistureDeficit,
+    ];
+}
+
+/// One indicator for one field and day.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Indicator {
+    pub field: H3Cell,
+    pub kind: IndicatorKind,
+    /// Start of the UTC day
+    pub day: Timestamp,
+    pub value: f64,
+    pub computed_at: Timestamp,
+}
+
+/// Recompute every `interval_secs` until cancelled.
+pub async fn run<R: Registries>(registries: R, config: IndicatorConfig, cancel: CancellationToken) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        match refresh(&registries, &config, Timestamp::now()).await {
+            Ok(stored) => info!(stored, "field indicators refreshed"),
+            Err(e) => error!(error = %e, "field indicator refresh failed"),
+        }
+    }
+}
+
+/// Recompute indicators for every field over the lookback window ending at
+/// `now`, returning how many were stored.
+pub async fn refresh<R: Registries>(
+    registries: &R,
+    config: &IndicatorConfig,
+    now: Timestamp,
+) -> Result<usize, DerivedError> {
+    let today = Granularity::Day.bucket_start(now);
+    let lookback = SignedDuration::from_hours(i64::from(config.lookback_days) * 24);
+    let since = today.checked_sub(lookback).unwrap_or(today);
+
+    let mut stored = 0;
+    for (field, device_ids) in fields(registries, config.field_resolution).await? {
+        let indicators =
+            field_indicators(registries, config, field, device_ids, since, now).await?;
+        stored += indicators.len();
+
+        registries
+            .derived_metrics()
+            .upsert(indicators)
+            .await
+            .map_err(|e| DerivedError::Store(Box::new(e)))?;
+    }
+
+    Ok(stored)
+}
+
+/// Registered devices grouped by the field they are located in.
+async fn fields<R: Registries>(
+    registries: &R,
+    resolution: u8,
+) -> Result<BTreeMap<u64, Vec<DeviceId>>, DerivedError> {
+    let devices = registries.devices();
+    let count = devices
+        .count(None)
+        .await
+        .map_err(|e| DerivedError::Devices(Box::new(e)))?;
+    let devices = devices
+        .list(QueryOptions {
+            filter: Default::default(),
+            sort_by: DeviceSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Offset {
+                offset: 0,
+                limit: count,
+            },
+        })
+        .await
+        .map_err(|e| DerivedError::Devices(Box::new(e)))?;
+
+    let mut fields: BTreeMap<u64, Vec<DeviceId>> = BTreeMap::new();
+    for device in devices {
+        // Devices placed coarser than a field can't be attributed to one.
+        if let Some(field) = region::parent(device.location, resolution) {
+            fields.entry(field.0).or_default().push(device.id);
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Compute a field's indicators from its devices' rollups since `since`.
+pub async fn field_indicators<R: Registries>(
+    registries: &R,
+    config: &IndicatorConfig,
+    field: u64,
+    device_ids: Vec<DeviceId>,
+    since: Timestamp,
+    now: Timestamp,
+) -> Result<Vec<Indicator>, DerivedError> {
+    let aggregates = registries.aggregates();
+    let daily = aggregates
+        .list(
+            AggregateFilter::builder(Granularity::Day)
+                .device_ids(device_ids.iter().copied())
+                .metric_kinds([
+                    SensorKind::AirTemp,
+                    SensorKind::Rainfall,
+                    SensorKind::SoilMoisture,
+                ])
+                .after(since)
+                .build(),
+        )
+        .await
+        .map_err(|e| DerivedError::Aggregates(Box::new(e)))?;
+    let hourly = aggregates
+        .list(
+            AggregateFilter::builder(Granularity::Hour)
+                .device_ids(device_ids)
+                .metric_kinds([SensorKind::AirTemp])
+                .after(since)
+                .build(),
+        )
+        .await
+        .map_err(|e| DerivedError::Aggregates(Box::new(e)))?;
+
+    Ok(compute(config, H3Cell(field), &daily, &hourly, now))
+}
+
+/// Derive indicators per day from daily and hourly aggregates of one field.
+pub fn compute(
+    config: &IndicatorConfig,
+    field: H3Cell,
+    daily: &[Aggregate],
+    hourly: &[Aggregate],
+    computed_at: Timestamp,
+) -> Vec<Indicator> {
+    let mut days: BTreeMap<Timestamp, HashMap<SensorKind, Vec<&Aggregate>>> = BTreeMap::new();
+    for aggregate in daily {
+        days.entry(aggregate.bucket_start)
+            .or_default()
+            .entry(aggregate.metric)
+            .or_default()
+            .push(aggregate);
+    }
+
+    let mut frost: BTreeMap<Timestamp, BTreeSet<Timestamp>> = BTreeMap::new();
+    for aggregate in hourly.iter().filter(|a| a.metric == SensorKind::AirTemp) {
+        let hours = frost
+            .entry(Granularity::Day.bucket_start(aggregate.bucket_start))
+            .or_default();
+        if aggregate.min <= config.frost_threshold_celsius {
+            hours.insert(aggregate.bucket_start);
+        }
+    }
+
+    let indicator = |kind, day, value| Indicator {
+        field,
+        kind,
+        day,
+        value,
+        computed_at,
+    };
+    let mean = |values: &mut dyn Iterator<Item = f64>| {
+        let (sum, count) = values.fold((0.0, 0usize), |(sum, count), v| (sum + v, count + 1));
+        sum / count as f64
+    };
+
+    let mut indicators = Vec::new();
+    for (day, metrics) in &days {
+        if let Some(temps) = metrics.get(&SensorKind::AirTemp) {
+            let low = mean(&mut temps.iter().map(|a| a.min));
+            let high = mean(&mut temps.iter().map(|a| a.max));
+            let gdd = ((low + high) / 2.0 - config.gdd_base_celsius).max(0.0);
+            indicators.push(indicator(IndicatorKind::GrowingDegreeDays, *day, gdd));
+        }
+
+        if let Some(gauges) = metrics.get(&SensorKind::Rainfall) {
+            let rainfall = mean(&mut gauges.iter().map(|a| a.sum));
+            indicators.push(indicator(IndicatorKind::Rainfall, *day, rainfall));
+        }
+
+        if let Some(probes) = metrics.get(&SensorKind::SoilMoisture) {
+            let moisture = mean(&mut probes.iter().map(|a| a.mean()));
+            let deficit = (config.field_capacity_percent - moisture).max(0.0);
+            indicators.push(indicator(IndicatorKind::SoilMoistureDeficit, *day, deficit));
+        }
+    }
+
+    for (day, hours) in frost {
+        indicators.push(indicator(
+            IndicatorKind::FrostHours,
+            day,
+            hours.len() as f64,
+        ));
+    }
+
+    indicators.sort_by_key(|i| (i.day, i.kind));
+    indicators
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, H3Cell, SensorId, SensorKind};
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::{IndicatorKind, compute};
+    use crate::config::IndicatorConfig;
+    use crate::rollup::{Aggregate, Granularity};
+
+    const FIELD: H3Cell = H3Cell(0x892a1072b5bffff);
+    const DAY: i64 = 86_400 * 20_000;
+
+    fn aggregate(
+        metric: SensorKind,
+        granularity: Granularity,
+        bucket: i64,
+        values: &[f64],
+    ) -> Aggregate {
+        Aggregate {
+            device_id: DeviceId(Ulid::new()),
+            sensor_id: SensorId(Ulid::new()),
+            metric,
+            granularity,
+            bucket_start: Timestamp::from_second(bucket).unwrap(),
+            count: values.len() as u64,
+            sum: values.iter().sum(),
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+
+    fn value(indicators: &[super::Indicator], kind: IndicatorKind) -> Option<f64> {
+        indicators.iter().find(|i| i.kind == kind).map(|i| i.value)
+    }
+
+    #[test]
+    fn indicators_from_one_day_of_rollups() {
+        let config = IndicatorConfig::default();
+        let daily = [
+            aggregate(SensorKind::AirTemp, Granularity::Day, DAY, &[-2.0, 26.0]),
+            aggregate(SensorKind::AirTemp, Granularity::Day, DAY, &[2.0, 30.0]),
+            aggregate(SensorKind::Rainfall, Granularity::Day, DAY, &[1.5, 2.5]),
+            aggregate(SensorKind::Rainfall, Granularity::Day, DAY, &[6.0]),
+            aggregate(
+                SensorKind::SoilMoisture,
+                Granularity::Day,
+                DAY,
+                &[20.0, 30.0],
+            ),
+        ];
+        let hourly = [
+            aggregate(SensorKind::AirTemp, Granularity::Hour, DAY, &[-2.0]),
+            aggregate(SensorKind::AirTemp, Granularity::Hour, DAY, &[1.0]),
+            aggregate(SensorKind::AirTemp, Granularity::Hour, DAY + 3_600, &[-0.5]),
+            aggregate(SensorKind::AirTemp, Granularity::Hour, DAY + 7_200, &[4.0]),
+        ];
+
+        let indicators = compute(&config, FIELD, &daily, &hourly, Timestamp::now());
+
+        // Mean low 0 °C, mean high 28 °C, base 10 °C.
+        assert_eq!(
+            value(&indicators, IndicatorKind::GrowingDegreeDays),
+            Some(4.0)
+        );
+        assert_eq!(value(&indicators, IndicatorKind::Rainfall), Some(5.0));
+        assert_eq!(value(&indicators, IndicatorKind::FrostHours), Some(2.0));
+        assert_eq!(
+            value(&indicators, IndicatorKind::SoilMoistureDeficit),
+            Some(10.0)
+        );
+        assert!(
+            indicators
+                .iter()
+                .all(|i| i.field == FIELD && i.day.as_second() == DAY)
+        );
+    }
+
+    #[test]
+    fn missing_sensors_produce_no_indicator() {
+        let config = IndicatorConfig::default();
+        let daily = [aggregate(
+            SensorKind::SoilMoisture,
+            Granularity::Day,
+            DAY,
+            &[50.0],
+        )];
+
+        let indicators = compute(&config, FIELD, &daily, &[], Timestamp::now());
+
+        assert_eq!(indicators.len(), 1);
+        // Wetter than field capacity is no deficit.
+        assert_eq!(
+            value(&indicators, IndicatorKind::SoilMoistureDeficit),
+            Some(0.0)
+        );
+    }
+}
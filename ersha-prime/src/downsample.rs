@@ -0,0 +1,110 @@
+//! Downsampling of time series for plotting.
+//!
+//! Largest-Triangle-Three-Buckets (Steinarsson, 2013) keeps the points that
+//! shape a line chart: the series is split into as many buckets as points
+//! are wanted, and from each the point forming the largest triangle with
+//! the point kept before it and the average of the next bucket is kept.
+//! Peaks and troughs survive, which plain averaging would flatten.
+
+use jiff::Timestamp;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A value at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct Point {
+    pub t: Timestamp,
+    pub value: f64,
+}
+
+/// Reduce `points`, ordered by time, to `threshold` of them, always keeping
+/// the first and last. Series already no longer than `threshold`, and
+/// thresholds below 3, leave the points as they are.
+pub fn lttb(points: &[Point], threshold: usize) -> Vec<Point> {
+    let n = points.len();
+    if threshold >= n || threshold < 3 {
+        return points.to_vec();
+    }
+
+    let x = |point: &Point| point.t.as_millisecond() as f64;
+    // Buckets between the first and last points, which are always kept.
+    let every = (n - 2) as f64 / (threshold - 2) as f64;
+    let bucket_start = |i: usize| ((i as f64 * every) as usize + 1).min(n - 1);
+
+    let mut sampled = Vec::with_capacity(threshold);
+    sampled.push(points[0]);
+    let mut kept = 0;
+    for i in 0..threshold - 2 {
+        let next = &points[bucket_start(i + 1)..bucket_start(i + 2).max(bucket_start(i + 1) + 1)];
+        let (avg_x, avg_y) = next
+            .iter()
+            .fold((0.0, 0.0), |(sx, sy), p| (sx + x(p), sy + p.value));
+        let (avg_x, avg_y) = (avg_x / next.len() as f64, avg_y / next.len() as f64);
+
+        let a = points[kept];
+        let (ax, ay) = (x(&a), a.value);
+        let mut max_area = -1.0;
+        let mut chosen = bucket_start(i);
+        for (j, p) in points
+            .iter()
+            .enumerate()
+            .take(bucket_start(i + 1))
+            .skip(bucket_start(i))
+        {
+            let area = ((ax - avg_x) * (p.value - ay) - (ax - x(p)) * (avg_y - ay)).abs();
+            if area > max_area {
+                max_area = area;
+                chosen = j;
+            }
+        }
+
+        sampled.push(points[chosen]);
+        kept = chosen;
+    }
+    sampled.push(points[n - 1]);
+
+    sampled
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::Timestamp;
+
+    use super::{Point, lttb};
+
+    fn series(values: &[f64]) -> Vec<Point> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Point {
+                t: Timestamp::from_second(i as i64 * 60).unwrap(),
+                value: *value,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn short_series_are_kept_whole() {
+        let points = series(&[1.0, 2.0, 3.0]);
+
+        assert_eq!(lttb(&points, 500), points);
+        assert_eq!(lttb(&points, 2), points);
+    }
+
+    #[test]
+    fn spikes_survive_downsampling() {
+        let mut values = vec![10.0; 10_000];
+        values[4_321] = 90.0;
+        values[7_777] = -40.0;
+        let points = series(&values);
+
+        let sampled = lttb(&points, 100);
+
+        assert_eq!(sampled.len(), 100);
+        assert_eq!(sampled[0], points[0]);
+        assert_eq!(sampled[99], points[9_999]);
+        assert!(sampled.windows(2).all(|w| w[0].t < w[1].t));
+        assert!(sampled.contains(&points[4_321]));
+        assert!(sampled.contains(&points[7_777]));
+    }
+}
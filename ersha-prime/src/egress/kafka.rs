@@ -0,0 +1,45 @@
+//! Kafka publishing through librdkafka.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rdkafka::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+
+use super::{EgressError, Publisher};
+
+/// How long a record may wait in librdkafka's own queue for room.
+const ENQUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct KafkaPublisher {
+    producer: FutureProducer,
+}
+
+impl KafkaPublisher {
+    /// A producer for `brokers`, with `properties` overriding its defaults.
+    pub fn new(brokers: &str, properties: &HashMap<String, String>) -> Result<Self, EgressError> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers);
+        for (key, value) in properties {
+            config.set(key, value);
+        }
+        let producer = config
+            .create()
+            .map_err(|err| EgressError::Broker(err.to_string()))?;
+
+        Ok(Self { producer })
+    }
+}
+
+#[async_trait]
+impl Publisher for KafkaPublisher {
+    async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> Result<(), EgressError> {
+        let record = FutureRecord::to(topic).key(key).payload(payload);
+        self.producer
+            .send(record, ENQUEUE_TIMEOUT)
+            .await
+            .map(|_| ())
+            .map_err(|(err, _)| EgressError::Broker(err.to_string()))
+    }
+}
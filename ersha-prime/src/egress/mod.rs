@@ -0,0 +1,602 @@
+//! Ingested readings and statuses published to a message broker.
+//!
+//! Each stored reading and status becomes one record on its stream's Kafka
+//! topic or NATS subject, so a data lake can consume the raw stream without
+//! polling the API. Records wait in a bounded buffer while the broker is slow
+//! or down, are retried with backoff, and are dropped rather than holding up
+//! ingestion once the buffer is full.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use ersha_core::{DeviceStatus, SensorReading};
+use jiff::Timestamp;
+use serde::Serialize;
+use serde_json::{Value, json};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::config::{BrokerConfig, EgressConfig, EgressFormat};
+use crate::events::{BusEvent, Received, Subscription};
+use crate::metrics;
+use crate::rollup::{metric_kind_name, metric_value};
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "nats")]
+pub mod nats;
+
+#[derive(Debug, Error)]
+pub enum EgressError {
+    #[error("prime was built without the `{0}` feature")]
+    Unsupported(&'static str),
+    #[error("broker error: {0}")]
+    Broker(String),
+}
+
+/// Publishes serialized records to a broker.
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Publish `payload` to `topic`, returning once the broker has it.
+    async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> Result<(), EgressError>;
+}
+
+/// A publisher for the configured broker.
+pub async fn connect(broker: &BrokerConfig) -> Result<Arc<dyn Publisher>, EgressError> {
+    match broker {
+        #[cfg(feature = "kafka")]
+        BrokerConfig::Kafka {
+            brokers,
+            properties,
+        } => Ok(Arc::new(kafka::KafkaPublisher::new(brokers, properties)?)),
+        #[cfg(not(feature = "kafka"))]
+        BrokerConfig::Kafka { .. } => Err(EgressError::Unsupported("kafka")),
+        #[cfg(feature = "nats")]
+        BrokerConfig::Nats { url } => Ok(Arc::new(nats::NatsPublisher::connect(url).await?)),
+        #[cfg(not(feature = "nats"))]
+        BrokerConfig::Nats { .. } => Err(EgressError::Unsupported("nats")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Readings,
+    Statuses,
+}
+
+impl Stream {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stream::Readings => "readings",
+            Stream::Statuses => "statuses",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EgressOutcome {
+    Published,
+    /// Failed, to be tried again
+    Retried,
+    /// Failed on the last attempt
+    Failed,
+    /// Not queued, the buffer being full
+    Dropped,
+}
+
+/// A serialized reading or status waiting to be published.
+#[derive(Debug, Clone)]
+struct Record {
+    stream: Stream,
+    /// Device the record is from, so a device's records share a partition
+    key: String,
+    payload: Vec<u8>,
+    queued_at: Instant,
+}
+
+/// A reading as published, flattened for tables in a data lake.
+#[derive(Debug, Serialize)]
+struct ReadingRecord {
+    id: String,
+    device_id: String,
+    dispatcher_id: String,
+    sensor_id: String,
+    metric: &'static str,
+    value: f64,
+    /// H3 cell, in hex
+    location: String,
+    confidence: u8,
+    timestamp: Timestamp,
+    /// Stored well after it was taken, as when re-driven from dead letters
+    late: bool,
+}
+
+impl ReadingRecord {
+    fn new(reading: &SensorReading, late: bool) -> Self {
+        Self {
+            id: reading.id.0.to_string(),
+            device_id: reading.device_id.0.to_string(),
+            dispatcher_id: reading.dispatcher_id.0.to_string(),
+            sensor_id: reading.sensor_id.0.to_string(),
+            metric: metric_kind_name(reading.metric.kind()),
+            value: metric_value(&reading.metric),
+            location: format!("{:x}", reading.location.0),
+            confidence: reading.confidence.0,
+            timestamp: reading.timestamp,
+            late,
+        }
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "struct",
+            "name": "ersha.Reading",
+            "optional": false,
+            "fields": [
+                field("id", "string", false),
+                field("device_id", "string", false),
+                field("dispatcher_id", "string", false),
+                field("sensor_id", "string", false),
+                field("metric", "string", false),
+                field("value", "double", false),
+                field("location", "string", false),
+                field("confidence", "int16", false),
+                field("timestamp", "string", false),
+                field("late", "boolean", false),
+            ],
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatusRecord {
+    id: String,
+    device_id: String,
+    dispatcher_id: String,
+    battery_percent: u8,
+    uptime_seconds: u64,
+    signal_rssi: i16,
+    errors: Vec<ErrorRecord>,
+    sensors: Vec<SensorStatusRecord>,
+    timestamp: Timestamp,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorRecord {
+    code: String,
+    message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SensorStatusRecord {
+    sensor_id: String,
+    state: String,
+    last_reading: Option<Timestamp>,
+}
+
+impl StatusRecord {
+    fn new(status: &DeviceStatus) -> Self {
+        Self {
+            id: status.id.0.to_string(),
+            device_id: status.device_id.0.to_string(),
+            dispatcher_id: status.dispatcher_id.0.to_string(),
+            battery_percent: status.battery_percent.0,
+            uptime_seconds: status.uptime_seconds,
+            signal_rssi: status.signal_rssi,
+            errors: status
+                .errors
+                .iter()
+                .map(|error| ErrorRecord {
+                    code: format!("{:?}", error.code),
+                    message: error.message.as_deref().map(str::to_owned),
+                })
+                .collect(),
+            sensors: status
+                .sensor_statuses
+                .iter()
+                .map(|sensor| SensorStatusRecord {
+                    sensor_id: sensor.sensor_id.0.to_string(),
+                    state: format!("{:?}", sensor.state),
+                    last_reading: sensor.last_reading,
+                })
+                .collect(),
+            timestamp: status.timestamp,
+        }
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "struct",
+            "name": "ersha.Status",
+            "optional": false,
+            "fields": [
+                field("id", "string", false),
+                field("device_id", "string", false),
+                field("dispatcher_id", "string", false),
+                field("battery_percent", "int16", false),
+                field("uptime_seconds", "int64", false),
+                field("signal_rssi", "int16", false),
+                {
+                    "field": "errors",
+                    "type": "array",
+                    "optional": false,
+                    "items": {
+                        "type": "struct",
+                        "optional": false,
+                        "fields": [
+                            field("code", "string", false),
+                            field("message", "string", true),
+                        ],
+                    },
+                },
+                {
+                    "field": "sensors",
+                    "type": "array",
+                    "optional": false,
+                    "items": {
+                        "type": "struct",
+                        "optional": false,
+                        "fields": [
+                            field("sensor_id", "string", false),
+                            field("state", "string", false),
+                            field("last_reading", "string", true),
+                        ],
+                    },
+                },
+                field("timestamp", "string", false),
+            ],
+        })
+    }
+}
+
+/// A field of a Kafka Connect struct schema.
+fn field(name: &str, kind: &str, optional: bool) -> Value {
+    json!({ "field": name, "type": kind, "optional": optional })
+}
+
+fn serialize(format: EgressFormat, record: &impl Serialize, schema: fn() -> Value) -> Vec<u8> {
+    let result = match format {
+        EgressFormat::Json => serde_json::to_vec(record),
+        EgressFormat::SchemaJson => serde_json::to_vec(&json!({
+            "schema": schema(),
+            "payload": record,
+        })),
+    };
+    result.expect("egress records serialize to JSON")
+}
+
+/// The records to publish for `event`, if any.
+fn records(format: EgressFormat, event: &BusEvent) -> Vec<Record> {
+    let queued_at = Instant::now();
+    match event {
+        BusEvent::ReadingsIngested { readings, late } => readings
+            .iter()
+            .map(|reading| Record {
+                stream: Stream::Readings,
+                key: reading.device_id.0.to_string(),
+                payload: serialize(
+                    format,
+                    &ReadingRecord::new(reading, *late),
+                    ReadingRecord::schema,
+                ),
+                queued_at,
+            })
+            .collect(),
+        BusEvent::StatusesIngested { statuses } => statuses
+            .iter()
+            .map(|status| Record {
+                stream: Stream::Statuses,
+                key: status.device_id.0.to_string(),
+                payload: serialize(format, &StatusRecord::new(status), StatusRecord::schema),
+                queued_at,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Wait before retrying after the `attempts`th failure.
+fn backoff(config: &EgressConfig, attempts: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    let millis = config
+        .initial_backoff_ms
+        .saturating_mul(factor)
+        .min(config.max_backoff_ms);
+    Duration::from_millis(millis)
+}
+
+/// Publish ingested readings and statuses from `events` until cancelled or
+/// the bus is gone, once everything queued has been published.
+pub async fn run(
+    publisher: Arc<dyn Publisher>,
+    config: EgressConfig,
+    events: Subscription,
+    cancel: CancellationToken,
+) {
+    let (queue, mut queued) = mpsc::channel(config.buffer.max(1));
+
+    tokio::select! {
+        _ = cancel.cancelled() => {}
+        _ = async {
+            tokio::join!(
+                enqueue(config.format, events, queue),
+                publish_queued(&*publisher, &config, &mut queued),
+            )
+        } => {}
+    }
+}
+
+/// Queue records for every event. Never waits on the broker, so the
+/// subscription keeps up with ingestion whatever the broker does.
+async fn enqueue(format: EgressFormat, mut events: Subscription, queue: mpsc::Sender<Record>) {
+    while let Some(received) = events.recv().await {
+        let Received::Event(event) = received else {
+            continue;
+        };
+
+        for record in records(format, &event) {
+            let stream = record.stream;
+            if queue.try_send(record).is_err() {
+                metrics::record_egress(stream, EgressOutcome::Dropped);
+            }
+        }
+        metrics::set_egress_queued(queue.max_capacity() - queue.capacity());
+    }
+}
+
+async fn publish_queued(
+    publisher: &dyn Publisher,
+    config: &EgressConfig,
+    queued: &mut mpsc::Receiver<Record>,
+) {
+    while let Some(record) = queued.recv().await {
+        metrics::set_egress_queued(queued.len());
+        publish(publisher, config, &record).await;
+    }
+}
+
+/// Publish `record`, retrying failures up to the configured attempts.
+async fn publish(publisher: &dyn Publisher, config: &EgressConfig, record: &Record) {
+    let topic = match record.stream {
+        Stream::Readings => &config.readings_topic,
+        Stream::Statuses => &config.statuses_topic,
+    };
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match publisher.publish(topic, &record.key, &record.payload).await {
+            Ok(()) => {
+                metrics::record_egress(record.stream, EgressOutcome::Published);
+                metrics::set_egress_lag(record.queued_at.elapsed());
+                return;
+            }
+            Err(err) if attempts >= config.max_attempts => {
+                warn!(%topic, attempts, "Giving up on egress record: {err}");
+                metrics::record_egress(record.stream, EgressOutcome::Failed);
+                return;
+            }
+            Err(err) => {
+                warn!(%topic, attempts, "Failed to publish egress record: {err}");
+                metrics::record_egress(record.stream, EgressOutcome::Retried);
+                tokio::time::sleep(backoff(config, attempts)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use ersha_core::{
+        DeviceError, DeviceErrorCode, DeviceId, DeviceStatus, DispatcherId, Percentage, SensorId,
+        SensorState, SensorStatus, StatusId,
+    };
+    use jiff::Timestamp;
+    use serde_json::Value;
+    use tokio_util::sync::CancellationToken;
+    use ulid::Ulid;
+
+    use super::{EgressError, Publisher, Stream, records, run};
+    use crate::config::{BrokerConfig, EgressConfig, EgressFormat};
+    use crate::events::{BusEvent, EventBus, LocalEventBus};
+    use crate::fixtures::reading;
+
+    /// Records every publish, failing the first `failures` of them.
+    #[derive(Default)]
+    struct RecordingPublisher {
+        failures: Mutex<usize>,
+        published: Mutex<Vec<(String, String, Value)>>,
+    }
+
+    #[async_trait]
+    impl Publisher for RecordingPublisher {
+        async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> Result<(), EgressError> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(EgressError::Broker("unavailable".to_owned()));
+            }
+            self.published.lock().unwrap().push((
+                topic.to_owned(),
+                key.to_owned(),
+                serde_json::from_slice(payload).unwrap(),
+            ));
+            Ok(())
+        }
+    }
+
+    fn config(format: EgressFormat) -> EgressConfig {
+        EgressConfig {
+            broker: BrokerConfig::Nats {
+                url: "nats://localhost:4222".to_owned(),
+            },
+            readings_topic: "readings".to_owned(),
+            statuses_topic: "statuses".to_owned(),
+            format,
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+            buffer: 16,
+        }
+    }
+
+    fn status() -> DeviceStatus {
+        DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            battery_percent: Percentage(80),
+            uptime_seconds: 3600,
+            signal_rssi: -70,
+            errors: vec![DeviceError {
+                code: DeviceErrorCode::LowBattery,
+                message: None,
+            }]
+            .into_boxed_slice(),
+            timestamp: Timestamp::now(),
+            sensor_statuses: vec![SensorStatus {
+                sensor_id: SensorId(Ulid::new()),
+                state: SensorState::Active,
+                last_reading: None,
+            }]
+            .into_boxed_slice(),
+        }
+    }
+
+    fn payload(record: &super::Record) -> Value {
+        serde_json::from_slice(&record.payload).unwrap()
+    }
+
+    #[test]
+    fn readings_are_flattened() {
+        let reading = reading();
+        let event = BusEvent::ReadingsIngested {
+            readings: Arc::new([reading.clone()]),
+            late: true,
+        };
+
+        let records = records(EgressFormat::Json, &event);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].stream, Stream::Readings);
+        assert_eq!(records[0].key, reading.device_id.0.to_string());
+        let payload = payload(&records[0]);
+        assert_eq!(payload["metric"], "soil_moisture");
+        assert_eq!(payload["value"], 40.0);
+        assert_eq!(payload["location"], "8a2a1072b59ffff");
+        assert_eq!(payload["late"], true);
+    }
+
+    #[test]
+    fn schema_json_describes_every_field() {
+        let events = [
+            BusEvent::ReadingsIngested {
+                readings: Arc::new([reading()]),
+                late: false,
+            },
+            BusEvent::StatusesIngested {
+                statuses: Arc::new([status()]),
+            },
+        ];
+
+        for event in events {
+            let record = &records(EgressFormat::SchemaJson, &event)[0];
+            let envelope = payload(record);
+            let flat = payload(&records(EgressFormat::Json, &event)[0]);
+
+            let fields: Vec<&str> = envelope["schema"]["fields"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|field| field["field"].as_str().unwrap())
+                .collect();
+            let keys: Vec<&str> = flat
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect();
+            assert_eq!(fields.len(), keys.len());
+            assert!(keys.iter().all(|key| fields.contains(key)));
+            assert_eq!(envelope["payload"]["id"], flat["id"]);
+        }
+    }
+
+    #[test]
+    fn other_events_are_not_published() {
+        let event = BusEvent::AlertRaised {
+            message: "frost".to_owned(),
+            org_id: None,
+        };
+
+        assert!(records(EgressFormat::Json, &event).is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_publishes_are_retried() {
+        let publisher = Arc::new(RecordingPublisher {
+            failures: Mutex::new(2),
+            ..Default::default()
+        });
+        let bus = LocalEventBus::new();
+        let task = tokio::spawn(run(
+            publisher.clone(),
+            config(EgressFormat::Json),
+            bus.subscribe(),
+            CancellationToken::new(),
+        ));
+
+        let reading = reading();
+        bus.publish(BusEvent::ReadingsIngested {
+            readings: Arc::new([reading.clone()]),
+            late: false,
+        })
+        .await;
+        bus.publish(BusEvent::StatusesIngested {
+            statuses: Arc::new([status()]),
+        })
+        .await;
+        drop(bus);
+        task.await.unwrap();
+
+        let published = publisher.published.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0].0, "readings");
+        assert_eq!(published[0].1, reading.device_id.0.to_string());
+        assert_eq!(published[1].0, "statuses");
+    }
+
+    #[tokio::test]
+    async fn records_are_given_up_after_max_attempts() {
+        let publisher = Arc::new(RecordingPublisher {
+            failures: Mutex::new(3),
+            ..Default::default()
+        });
+        let bus = LocalEventBus::new();
+        let task = tokio::spawn(run(
+            publisher.clone(),
+            config(EgressFormat::Json),
+            bus.subscribe(),
+            CancellationToken::new(),
+        ));
+
+        for _ in 0..2 {
+            bus.publish(BusEvent::ReadingsIngested {
+                readings: Arc::new([reading()]),
+                late: false,
+            })
+            .await;
+        }
+        drop(bus);
+        task.await.unwrap();
+
+        // The first record used up every failure; the second went through.
+        assert_eq!(publisher.published.lock().unwrap().len(), 1);
+    }
+}
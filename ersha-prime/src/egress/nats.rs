@@ -0,0 +1,40 @@
+//! NATS core publishing. NATS has no message keys; each record carries its
+//! device id.
+
+use async_nats::{Client, ConnectOptions};
+use async_trait::async_trait;
+
+use super::{EgressError, Publisher};
+
+pub struct NatsPublisher {
+    client: Client,
+}
+
+impl NatsPublisher {
+    /// Connect to `url`, retrying in the background if the server isn't up
+    /// yet rather than failing startup.
+    pub async fn connect(url: &str) -> Result<Self, EgressError> {
+        let client = ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect(url)
+            .await
+            .map_err(|err| EgressError::Broker(err.to_string()))?;
+
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl Publisher for NatsPublisher {
+    async fn publish(&self, topic: &str, _key: &str, payload: &[u8]) -> Result<(), EgressError> {
+        self.client
+            .publish(topic.to_owned(), payload.to_vec().into())
+            .await
+            .map_err(|err| EgressError::Broker(err.to_string()))?;
+        // Publishing only buffers; flush so failures reach the retries.
+        self.client
+            .flush()
+            .await
+            .map_err(|err| EgressError::Broker(err.to_string()))
+    }
+}
@@ -0,0 +1,286 @@
+//! Things that happen inside prime, for whatever wants to react to them.
+//!
+//! Ingestion and the API publish [`BusEvent`]s to an [`EventBus`] instead of
+//! calling each consumer. Rollups, the live stream, threshold watching and
+//! webhook and contact delivery each subscribe, so a new consumer is a new
+//! subscriber rather than another step in the batch handler.
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use ersha_core::{Device, DeviceId, DeviceStatus, DispatcherId, SensorReading};
+use futures_util::{Stream, stream};
+use jiff::Timestamp;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::metrics;
+use crate::org::OrgId;
+
+/// Events queued per subscriber that must see every event.
+const QUEUE_CAPACITY: usize = 256;
+/// Events buffered per subscriber that may miss some before it misses the oldest.
+const BROADCAST_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum BusEvent {
+    /// Readings newly stored, in the order they were uploaded
+    ReadingsIngested {
+        readings: Arc<[SensorReading]>,
+        /// Stored well after they were taken, as when re-driven from dead
+        /// letters. Consumers reacting live skip them.
+        late: bool,
+    },
+    /// Device statuses newly stored
+    StatusesIngested { statuses: Arc<[DeviceStatus]> },
+    DeviceRegistered {
+        device: Device,
+        org_id: Option<OrgId>,
+    },
+    /// A dispatcher stopped hearing from a device
+    DeviceOffline {
+        device_id: DeviceId,
+        dispatcher_id: DispatcherId,
+        last_seen: Timestamp,
+        org_id: Option<OrgId>,
+    },
+    AlertRaised {
+        message: String,
+        org_id: Option<OrgId>,
+    },
+}
+
+impl BusEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BusEvent::ReadingsIngested { .. } => "readings_ingested",
+            BusEvent::StatusesIngested { .. } => "statuses_ingested",
+            BusEvent::DeviceRegistered { .. } => "device_registered",
+            BusEvent::DeviceOffline { .. } => "device_offline",
+            BusEvent::AlertRaised { .. } => "alert_raised",
+        }
+    }
+}
+
+/// Carries events from publishers to subscribers.
+///
+/// Subscribers only receive events published after they subscribed, so
+/// consumers subscribe before prime starts serving.
+#[async_trait]
+pub trait EventBus: Send + Sync {
+    /// Hand `event` to every subscriber, waiting while a subscriber that must
+    /// see every event has a full queue.
+    async fn publish(&self, event: BusEvent);
+
+    /// Every event published from now on. Publishers wait for a subscriber
+    /// that falls behind, so it must keep up with ingestion.
+    fn subscribe(&self) -> Subscription;
+
+    /// Events published from now on. A subscriber that falls behind misses
+    /// the oldest events rather than slowing down publishers.
+    fn subscribe_lossy(&self) -> Subscription;
+}
+
+/// What a subscriber receives.
+#[derive(Debug, Clone)]
+pub enum Received {
+    Event(Arc<BusEvent>),
+    /// A lossy subscriber fell behind and missed this many events
+    Lagged(u64),
+}
+
+enum Source {
+    Queue(mpsc::Receiver<Arc<BusEvent>>),
+    Broadcast(broadcast::Receiver<Arc<BusEvent>>),
+}
+
+/// Events for one subscriber.
+pub struct Subscription {
+    source: Source,
+}
+
+impl Subscription {
+    /// The next event, or `None` once the bus is gone.
+    pub async fn recv(&mut self) -> Option<Received> {
+        match &mut self.source {
+            Source::Queue(receiver) => receiver.recv().await.map(Received::Event),
+            Source::Broadcast(receiver) => match receiver.recv().await {
+                Ok(event) => Some(Received::Event(event)),
+                Err(broadcast::error::RecvError::Lagged(missed)) => Some(Received::Lagged(missed)),
+                Err(broadcast::error::RecvError::Closed) => None,
+            },
+        }
+    }
+
+    /// An event already waiting, if any.
+    pub fn try_recv(&mut self) -> Option<Received> {
+        match &mut self.source {
+            Source::Queue(receiver) => receiver.try_recv().ok().map(Received::Event),
+            Source::Broadcast(receiver) => match receiver.try_recv() {
+                Ok(event) => Some(Received::Event(event)),
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                    Some(Received::Lagged(missed))
+                }
+                Err(_) => None,
+            },
+        }
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Received> {
+        stream::unfold(self, |mut subscription| async move {
+            let received = subscription.recv().await?;
+            Some((received, subscription))
+        })
+    }
+}
+
+/// An [`EventBus`] within the process, over tokio channels.
+#[derive(Clone)]
+pub struct LocalEventBus {
+    queues: Arc<Mutex<Vec<mpsc::Sender<Arc<BusEvent>>>>>,
+    broadcast: broadcast::Sender<Arc<BusEvent>>,
+}
+
+impl LocalEventBus {
+    pub fn new() -> Self {
+        let (broadcast, _) = broadcast::channel(BROADCAST_CAPACITY);
+
+        Self {
+            queues: Arc::default(),
+            broadcast,
+        }
+    }
+}
+
+impl Default for LocalEventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl EventBus for LocalEventBus {
+    async fn publish(&self, event: BusEvent) {
+        metrics::record_bus_event(event.kind());
+        let event = Arc::new(event);
+
+        let queues = {
+            let mut queues = self.queues.lock().expect("event queues lock poisoned");
+            queues.retain(|queue| !queue.is_closed());
+            queues.clone()
+        };
+        for queue in queues {
+            // Sending only fails when the subscriber has gone since.
+            let _ = queue.send(event.clone()).await;
+        }
+
+        // Sending only fails when nobody is subscribed.
+        let _ = self.broadcast.send(event);
+    }
+
+    fn subscribe(&self) -> Subscription {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        self.queues
+            .lock()
+            .expect("event queues lock poisoned")
+            .push(sender);
+
+        Subscription {
+            source: Source::Queue(receiver),
+        }
+    }
+
+    fn subscribe_lossy(&self) -> Subscription {
+        Subscription {
+            source: Source::Broadcast(self.broadcast.subscribe()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ersha_core::{
+        DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId, SensorMetric,
+        SensorReading,
+    };
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::{BusEvent, EventBus, LocalEventBus, Received};
+
+    fn alert(message: &str) -> BusEvent {
+        BusEvent::AlertRaised {
+            message: message.to_owned(),
+            org_id: None,
+        }
+    }
+
+    fn event(received: Option<Received>) -> Arc<BusEvent> {
+        match received {
+            Some(Received::Event(event)) => event,
+            other => panic!("expected an event, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_events_published_after_subscribing() {
+        let bus = LocalEventBus::new();
+        bus.publish(alert("before")).await;
+
+        let mut all = bus.subscribe();
+        let mut lossy = bus.subscribe_lossy();
+        let reading = SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(40),
+            },
+            location: H3Cell(0x8a2a1072b59ffff),
+            confidence: Percentage(90),
+            timestamp: Timestamp::now(),
+            sensor_id: SensorId(Ulid::new()),
+        };
+        bus.publish(BusEvent::ReadingsIngested {
+            readings: Arc::new([reading.clone()]),
+            late: false,
+        })
+        .await;
+
+        for subscription in [&mut all, &mut lossy] {
+            assert!(matches!(
+                &*event(subscription.recv().await),
+                BusEvent::ReadingsIngested { readings, .. } if readings[..] == [reading.clone()]
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn lossy_subscribers_report_missed_events() {
+        let bus = LocalEventBus::new();
+        let mut lossy = bus.subscribe_lossy();
+
+        for n in 0..super::BROADCAST_CAPACITY + 3 {
+            bus.publish(alert(&n.to_string())).await;
+        }
+
+        assert!(matches!(lossy.recv().await, Some(Received::Lagged(3))));
+        assert!(matches!(
+            &*event(lossy.recv().await),
+            BusEvent::AlertRaised { message, .. } if message == "3"
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_subscribers_are_forgotten() {
+        let bus = LocalEventBus::new();
+        let kept = bus.subscribe();
+        drop(bus.subscribe());
+
+        bus.publish(alert("after")).await;
+
+        assert_eq!(bus.queues.lock().unwrap().len(), 1);
+        drop(kept);
+    }
+}
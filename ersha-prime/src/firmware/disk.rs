@@ -0,0 +1,45 @@
+//! Images kept as files in a directory, created on first upload.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+
+use super::{FirmwareId, ImageStore, StoreError};
+
+pub struct DiskStore {
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+        }
+    }
+
+    fn path(&self, id: FirmwareId) -> PathBuf {
+        self.dir.join(format!("{}.bin", id.0))
+    }
+}
+
+#[async_trait]
+impl ImageStore for DiskStore {
+    async fn put(&self, id: FirmwareId, image: &[u8]) -> Result<(), StoreError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // Written aside and renamed so a half-written image is never served.
+        let partial = self.dir.join(format!("{}.partial", id.0));
+        tokio::fs::write(&partial, image).await?;
+        tokio::fs::rename(&partial, self.path(id)).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: FirmwareId) -> Result<Option<Vec<u8>>, StoreError> {
+        match tokio::fs::read(self.path(id)).await {
+            Ok(image) => Ok(Some(image)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
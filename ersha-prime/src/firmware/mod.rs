@@ -0,0 +1,642 @@
+//! Firmware images and their rollout to devices.
+//!
+//! Images are uploaded once and kept in an [`ImageStore`], on disk or in an
+//! S3-compatible bucket, with their metadata in the registry. A [`Rollout`]
+//! installs an image on the active devices matching its target, a batch at
+//! a time: [`run`] enrolls matching devices, queues an `update_firmware`
+//! command for up to `batch_size` of them through the command channel, and
+//! queues more as dispatchers acknowledge the earlier ones. Each device's
+//! progress follows its command.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ersha_core::{CommandId, CommandKind, DeviceId, DeviceState};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::command::{self, Command, CommandState};
+use crate::config::{FirmwareConfig, ImageStoreConfig};
+use crate::org::OrgId;
+use crate::registry::{
+    CommandRegistry, DeviceRegistry, FirmwareRegistry, Registries,
+    filter::{DeviceFilter, DeviceSortBy, Pagination, QueryOptions, SortOrder},
+};
+
+pub mod disk;
+pub mod s3;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct FirmwareId(pub Ulid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct RolloutId(pub Ulid);
+
+/// An uploaded firmware image.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FirmwareImage {
+    pub id: FirmwareId,
+    pub version: String,
+    /// Hardware revision the image is built for, if only one
+    pub hardware_rev: Option<String>,
+    /// Size of the image in bytes
+    pub size: u64,
+    /// Hex encoded SHA-256 of the image
+    pub sha256: String,
+    pub created_at: Timestamp,
+}
+
+/// Devices a rollout installs its image on, besides being active and
+/// belonging to the rollout's organization.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RolloutTarget {
+    /// Only devices of this hardware revision
+    pub hardware_rev: Option<String>,
+    /// Only devices carrying all of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutState {
+    /// Enrolling devices and queuing commands
+    Active,
+    /// Commands already queued go on; no more are queued
+    Paused,
+    /// Stopped for good
+    Cancelled,
+    /// Every enrolled device acknowledged the update or failed to
+    Completed,
+}
+
+impl RolloutState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RolloutState::Active => "active",
+            RolloutState::Paused => "paused",
+            RolloutState::Cancelled => "cancelled",
+            RolloutState::Completed => "completed",
+        }
+    }
+}
+
+/// A campaign installing one image on the devices matching its target.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Rollout {
+    pub id: RolloutId,
+    pub firmware_id: FirmwareId,
+    pub target: RolloutTarget,
+    pub org_id: Option<OrgId>,
+    /// Most devices updating at a time
+    pub batch_size: usize,
+    pub state: RolloutState,
+    pub created_at: Timestamp,
+}
+
+/// How far a device got with a rollout's update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceRolloutState {
+    /// Waiting for a free slot in the batch, or for a dispatcher to reach it
+    Pending,
+    /// Command queued for the device's dispatcher
+    Queued,
+    /// Command handed to the dispatcher
+    Delivered,
+    /// The dispatcher passed the command on to the device
+    Acked,
+    /// The command expired before it was acknowledged
+    Failed,
+}
+
+impl DeviceRolloutState {
+    /// Whether the device takes up a slot in the batch.
+    pub fn in_flight(&self) -> bool {
+        matches!(
+            self,
+            DeviceRolloutState::Queued | DeviceRolloutState::Delivered
+        )
+    }
+}
+
+/// A device enrolled in a rollout and its progress.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct RolloutDevice {
+    pub rollout_id: RolloutId,
+    pub device_id: DeviceId,
+    pub state: DeviceRolloutState,
+    /// Command carrying the update, once queued
+    pub command_id: Option<CommandId>,
+    pub updated_at: Timestamp,
+}
+
+/// Devices of a rollout in each state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RolloutProgress {
+    pub pending: usize,
+    pub queued: usize,
+    pub delivered: usize,
+    pub acked: usize,
+    pub failed: usize,
+}
+
+impl RolloutProgress {
+    pub fn of(devices: &[RolloutDevice]) -> Self {
+        let mut progress = Self::default();
+        for device in devices {
+            let count = match device.state {
+                DeviceRolloutState::Pending => &mut progress.pending,
+                DeviceRolloutState::Queued => &mut progress.queued,
+                DeviceRolloutState::Delivered => &mut progress.delivered,
+                DeviceRolloutState::Acked => &mut progress.acked,
+                DeviceRolloutState::Failed => &mut progress.failed,
+            };
+            *count += 1;
+        }
+
+        progress
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server answered {0}")]
+    Rejected(u16),
+    #[error("invalid endpoint {0:?}")]
+    InvalidEndpoint(String),
+}
+
+/// Where image contents are kept.
+#[async_trait]
+pub trait ImageStore: Send + Sync {
+    async fn put(&self, id: FirmwareId, image: &[u8]) -> Result<(), StoreError>;
+    /// The image, or `None` if none was stored under `id`.
+    async fn get(&self, id: FirmwareId) -> Result<Option<Vec<u8>>, StoreError>;
+}
+
+/// The configured image store.
+pub fn open(config: &ImageStoreConfig) -> Arc<dyn ImageStore> {
+    match config {
+        ImageStoreConfig::Disk { path } => Arc::new(disk::DiskStore::new(path)),
+        ImageStoreConfig::S3 {
+            endpoint,
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key,
+        } => Arc::new(s3::S3Store::new(
+            reqwest::Client::new(),
+            endpoint,
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key,
+        )),
+    }
+}
+
+/// Image contents and the store they are kept in, shared by the API.
+#[derive(Clone)]
+pub struct Firmware {
+    config: FirmwareConfig,
+    store: Arc<dyn ImageStore>,
+}
+
+impl Firmware {
+    pub fn new(config: FirmwareConfig, store: Arc<dyn ImageStore>) -> Self {
+        Self { config, store }
+    }
+
+    pub fn config(&self) -> &FirmwareConfig {
+        &self.config
+    }
+
+    pub fn store(&self) -> &dyn ImageStore {
+        &*self.store
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RolloutError {
+    #[error("failed to read or update rollouts: {0}")]
+    Rollouts(#[source] BoxError),
+    #[error("failed to select devices: {0}")]
+    Devices(#[source] BoxError),
+    #[error("failed to queue or follow commands: {0}")]
+    Commands(#[source] BoxError),
+}
+
+/// Changes made by one pass over the active rollouts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Advance {
+    pub enrolled: usize,
+    pub queued: usize,
+    pub completed: usize,
+}
+
+/// Advance the active rollouts every `interval_secs` until cancelled.
+pub async fn run<R: Registries>(registries: R, config: FirmwareConfig, cancel: CancellationToken) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        match advance(&registries, &config, Timestamp::now()).await {
+            Ok(Advance {
+                enrolled: 0,
+                queued: 0,
+                completed: 0,
+            }) => {}
+            Ok(advance) => info!(
+                enrolled = advance.enrolled,
+                queued = advance.queued,
+                completed = advance.completed,
+                "advanced firmware rollouts"
+            ),
+            Err(e) => error!(error = %e, "failed to advance firmware rollouts"),
+        }
+    }
+}
+
+/// Enroll the devices matching each active rollout, follow the commands
+/// already queued, and queue commands for pending devices while the batch
+/// has room.
+pub async fn advance<R: Registries>(
+    registries: &R,
+    config: &FirmwareConfig,
+    now: Timestamp,
+) -> Result<Advance, RolloutError> {
+    let firmware = registries.firmware();
+    let rollout_error = |e| RolloutError::Rollouts(Box::new(e));
+
+    let mut advance = Advance::default();
+    for rollout in firmware.rollouts().await.map_err(rollout_error)? {
+        if rollout.state != RolloutState::Active {
+            continue;
+        }
+        let Some(image) = firmware
+            .image(rollout.firmware_id)
+            .await
+            .map_err(rollout_error)?
+        else {
+            continue;
+        };
+
+        let targets = targets(registries, &rollout).await?;
+        advance.enrolled += firmware
+            .enroll(rollout.id, targets, now)
+            .await
+            .map_err(rollout_error)?;
+
+        let mut devices = firmware
+            .rollout_devices(rollout.id)
+            .await
+            .map_err(rollout_error)?;
+        let mut changed = Vec::new();
+        for device in devices.iter_mut().filter(|device| device.state.in_flight()) {
+            let Some(state) = followed(registries, device).await? else {
+                continue;
+            };
+            device.state = state;
+            device.updated_at = now;
+            changed.push(device.clone());
+        }
+
+        let mut free = rollout
+            .batch_size
+            .saturating_sub(devices.iter().filter(|d| d.state.in_flight()).count());
+        for device in devices
+            .iter_mut()
+            .filter(|device| device.state == DeviceRolloutState::Pending)
+        {
+            if free == 0 {
+                break;
+            }
+            let dispatcher = command::route(registries, device.device_id)
+                .await
+                .map_err(RolloutError::Commands)?;
+            // Not heard from yet; tried again on the next pass.
+            let Some(dispatcher) = dispatcher else {
+                continue;
+            };
+
+            let command = Command::new(
+                device.device_id,
+                dispatcher,
+                update_command(config, &image),
+                now,
+                SignedDuration::from_secs(config.command_ttl_secs as i64),
+            );
+            device.command_id = Some(command.id);
+            registries
+                .commands()
+                .enqueue(command)
+                .await
+                .map_err(|e| RolloutError::Commands(Box::new(e)))?;
+            device.state = DeviceRolloutState::Queued;
+            device.updated_at = now;
+            changed.push(device.clone());
+            advance.queued += 1;
+            free -= 1;
+        }
+
+        if !changed.is_empty() {
+            firmware
+                .update_rollout_devices(changed)
+                .await
+                .map_err(rollout_error)?;
+        }
+        let finished = devices.iter().all(|device| {
+            matches!(
+                device.state,
+                DeviceRolloutState::Acked | DeviceRolloutState::Failed
+            )
+        });
+        if !devices.is_empty() && finished {
+            firmware
+                .set_rollout_state(rollout.id, RolloutState::Completed)
+                .await
+                .map_err(rollout_error)?;
+            advance.completed += 1;
+        }
+    }
+
+    Ok(advance)
+}
+
+/// Active devices of the rollout's organization matching its target.
+async fn targets<R: Registries>(
+    registries: &R,
+    rollout: &Rollout,
+) -> Result<Vec<DeviceId>, RolloutError> {
+    let devices = registries.devices();
+    let device_error = |e| RolloutError::Devices(Box::new(e));
+
+    let filter = DeviceFilter {
+        states: Some(vec![DeviceState::Active]),
+        tags: (!rollout.target.tags.is_empty()).then(|| rollout.target.tags.clone()),
+        org_id: rollout.org_id,
+        ..Default::default()
+    };
+    let count = devices
+        .count(Some(filter.clone()))
+        .await
+        .map_err(device_error)?;
+    let matching = devices
+        .list(QueryOptions {
+            filter,
+            sort_by: DeviceSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Offset {
+                offset: 0,
+                limit: count,
+            },
+        })
+        .await
+        .map_err(device_error)?;
+
+    let mut targets = Vec::with_capacity(matching.len());
+    for device in matching {
+        if let Some(wanted) = &rollout.target.hardware_rev {
+            let rev = devices
+                .hardware_rev(device.id)
+                .await
+                .map_err(device_error)?;
+            if rev.as_ref() != Some(wanted) {
+                continue;
+            }
+        }
+        targets.push(device.id);
+    }
+
+    Ok(targets)
+}
+
+/// The device's new state if its command moved on.
+async fn followed<R: Registries>(
+    registries: &R,
+    device: &RolloutDevice,
+) -> Result<Option<DeviceRolloutState>, RolloutError> {
+    let Some(command_id) = device.command_id else {
+        return Ok(None);
+    };
+    let command = registries
+        .commands()
+        .get(command_id)
+        .await
+        .map_err(|e| RolloutError::Commands(Box::new(e)))?;
+
+    let state = match command.map(|command| command.state) {
+        Some(CommandState::Queued) => DeviceRolloutState::Queued,
+        Some(CommandState::Delivered) => DeviceRolloutState::Delivered,
+        Some(CommandState::Acked) => DeviceRolloutState::Acked,
+        Some(CommandState::Expired) | None => DeviceRolloutState::Failed,
+    };
+
+    Ok((state != device.state).then_some(state))
+}
+
+/// The command telling a device to install `image`.
+fn update_command(config: &FirmwareConfig, image: &FirmwareImage) -> CommandKind {
+    CommandKind::UpdateFirmware {
+        version: image.version.as_str().into(),
+        url: image_url(config, image.id).into(),
+        sha256: image.sha256.as_str().into(),
+    }
+}
+
+/// Where dispatchers download an image from.
+pub fn image_url(config: &FirmwareConfig, id: FirmwareId) -> String {
+    format!(
+        "{}/api/firmware/{}/image",
+        config.download_url.trim_end_matches('/'),
+        id.0
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        CommandKind, Device, DeviceId, DeviceKind, DeviceState, DeviceStatus, DispatcherId, H3Cell,
+        Percentage, StatusId,
+    };
+    use jiff::{SignedDuration, Timestamp};
+    use ulid::Ulid;
+
+    use super::{
+        Advance, DeviceRolloutState, FirmwareId, FirmwareImage, Rollout, RolloutId,
+        RolloutProgress, RolloutState, RolloutTarget, advance,
+    };
+    use crate::config::FirmwareConfig;
+    use crate::registry::{
+        CommandRegistry, DeviceRegistry, DeviceStatusRegistry, FirmwareRegistry,
+        memory::InMemoryRegistries,
+    };
+
+    async fn device(
+        registries: &InMemoryRegistries,
+        dispatcher_id: DispatcherId,
+        rev: &str,
+    ) -> DeviceId {
+        let id = DeviceId(Ulid::new());
+        registries
+            .devices
+            .register(Device {
+                id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: H3Cell(0x8a2a1072b59ffff),
+                manufacturer: None,
+                provisioned_at: Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+        registries
+            .devices
+            .set_hardware_rev(id, Some(rev.to_owned()))
+            .await
+            .unwrap();
+        registries
+            .statuses
+            .store(DeviceStatus {
+                id: StatusId(Ulid::new()),
+                device_id: id,
+                dispatcher_id,
+                battery_percent: Percentage(80),
+                uptime_seconds: 60,
+                signal_rssi: -70,
+                errors: Box::new([]),
+                timestamp: Timestamp::now(),
+                sensor_statuses: Box::new([]),
+            })
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn rollouts_update_matching_devices_a_batch_at_a_time() {
+        let registries = InMemoryRegistries::default();
+        let dispatcher = DispatcherId(Ulid::new());
+        for _ in 0..3 {
+            device(&registries, dispatcher, "rev-b").await;
+        }
+        let other = device(&registries, dispatcher, "rev-a").await;
+
+        let image = FirmwareImage {
+            id: FirmwareId(Ulid::new()),
+            version: "2.1.0".to_owned(),
+            hardware_rev: Some("rev-b".to_owned()),
+            size: 4,
+            sha256: "ab".repeat(32),
+            created_at: Timestamp::now(),
+        };
+        registries.firmware.add_image(image.clone()).await.unwrap();
+        let rollout = Rollout {
+            id: RolloutId(Ulid::new()),
+            firmware_id: image.id,
+            target: RolloutTarget {
+                hardware_rev: Some("rev-b".to_owned()),
+                tags: Vec::new(),
+            },
+            org_id: None,
+            batch_size: 2,
+            state: RolloutState::Active,
+            created_at: Timestamp::now(),
+        };
+        registries
+            .firmware
+            .create_rollout(rollout.clone())
+            .await
+            .unwrap();
+        let config = FirmwareConfig::default();
+        let now = Timestamp::now();
+
+        let first = advance(&registries, &config, now).await.unwrap();
+        assert_eq!(
+            first,
+            Advance {
+                enrolled: 3,
+                queued: 2,
+                completed: 0,
+            }
+        );
+        let devices = registries
+            .firmware
+            .rollout_devices(rollout.id)
+            .await
+            .unwrap();
+        assert!(devices.iter().all(|device| device.device_id != other));
+        let queued = devices
+            .iter()
+            .find(|device| device.state == DeviceRolloutState::Queued)
+            .unwrap();
+        let command = registries
+            .commands
+            .get(queued.command_id.unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            &command.kind,
+            CommandKind::UpdateFirmware { version, sha256, .. }
+                if &**version == "2.1.0" && **sha256 == *image.sha256
+        ));
+
+        // The batch is full until the dispatcher acknowledges.
+        let full = advance(&registries, &config, now).await.unwrap();
+        assert_eq!(full, Advance::default());
+
+        let delivered = registries
+            .commands
+            .deliver(dispatcher, now, 10)
+            .await
+            .unwrap();
+        registries
+            .commands
+            .ack(dispatcher, delivered.iter().map(|c| c.id).collect(), now)
+            .await
+            .unwrap();
+        let next = advance(&registries, &config, now).await.unwrap();
+        assert_eq!(next.queued, 1);
+
+        let later = now + SignedDuration::from_secs(config.command_ttl_secs as i64 + 1);
+        registries.commands.expire(later).await.unwrap();
+        let done = advance(&registries, &config, later).await.unwrap();
+        assert_eq!(done.completed, 1);
+        let devices = registries
+            .firmware
+            .rollout_devices(rollout.id)
+            .await
+            .unwrap();
+        assert_eq!(
+            RolloutProgress::of(&devices),
+            RolloutProgress {
+                acked: 2,
+                failed: 1,
+                ..Default::default()
+            }
+        );
+        let rollout = registries.firmware.rollout(rollout.id).await.unwrap();
+        assert_eq!(rollout.unwrap().state, RolloutState::Completed);
+    }
+}
@@ -0,0 +1,212 @@
+//! Images kept as objects in an S3-compatible bucket.
+//!
+//! Objects are addressed path-style, `{endpoint}/{bucket}/firmware/{id}`,
+//! which MinIO and other self-hosted stores expect, and requests are signed
+//! with AWS Signature Version 4.
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use jiff::Timestamp;
+use reqwest::{Method, StatusCode};
+use sha2::{Digest, Sha256};
+
+use super::{FirmwareId, ImageStore, StoreError};
+use crate::auth::hex;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub struct S3Store {
+    client: reqwest::Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl S3Store {
+    pub fn new(
+        client: reqwest::Client,
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key_id: &str,
+        secret_access_key: &str,
+    ) -> Self {
+        Self {
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            bucket: bucket.to_owned(),
+            region: region.to_owned(),
+            access_key_id: access_key_id.to_owned(),
+            secret_access_key: secret_access_key.to_owned(),
+        }
+    }
+
+    fn key(&self, id: FirmwareId) -> String {
+        format!("/{}/firmware/{}", self.bucket, id.0)
+    }
+
+    /// A request for the object at `key`, signed as of `now`.
+    fn request(
+        &self,
+        method: Method,
+        key: &str,
+        body: &[u8],
+        now: Timestamp,
+    ) -> Result<reqwest::RequestBuilder, StoreError> {
+        let invalid = || StoreError::InvalidEndpoint(self.endpoint.clone());
+        let url = reqwest::Url::parse(&format!("{}{key}", self.endpoint)).map_err(|_| invalid())?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => return Err(invalid()),
+        };
+        let payload_hash = hex(&Sha256::digest(body));
+        let authorization = self.authorization(method.as_str(), key, &host, &payload_hash, now);
+
+        Ok(self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", now.strftime("%Y%m%dT%H%M%SZ").to_string())
+            .header(reqwest::header::AUTHORIZATION, authorization))
+    }
+
+    /// The `Authorization` header signing a request for `key`.
+    fn authorization(
+        &self,
+        method: &str,
+        key: &str,
+        host: &str,
+        payload_hash: &str,
+        now: Timestamp,
+    ) -> String {
+        let date = now.strftime("%Y%m%d").to_string();
+        let amz_date = now.strftime("%Y%m%dT%H%M%SZ").to_string();
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical_request = format!(
+            "{method}\n{key}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\n\
+             x-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}"
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let secret = format!("AWS4{}", self.secret_access_key);
+        let signing_key = [date.as_str(), &self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(secret.into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+        let signature = hex(&hmac(&signing_key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, \
+             Signature={signature}",
+            self.access_key_id
+        )
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[async_trait]
+impl ImageStore for S3Store {
+    async fn put(&self, id: FirmwareId, image: &[u8]) -> Result<(), StoreError> {
+        let response = self
+            .request(Method::PUT, &self.key(id), image, Timestamp::now())?
+            .body(image.to_vec())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(StoreError::Rejected(response.status().as_u16()));
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, id: FirmwareId) -> Result<Option<Vec<u8>>, StoreError> {
+        let response = self
+            .request(Method::GET, &self.key(id), &[], Timestamp::now())?
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            status => Err(StoreError::Rejected(status.as_u16())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use axum::Router;
+    use axum::body::Bytes;
+    use axum::extract::Path;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::put;
+    use ulid::Ulid;
+
+    use super::S3Store;
+    use crate::firmware::{FirmwareId, ImageStore};
+
+    type Objects = Arc<Mutex<HashMap<String, (HeaderMap, Bytes)>>>;
+
+    #[tokio::test]
+    async fn images_round_trip_through_signed_requests() {
+        let objects: Objects = Arc::default();
+        let app = Router::new().route(
+            "/firmware-bucket/firmware/{id}",
+            put({
+                let objects = objects.clone();
+                move |Path(id): Path<String>, headers: HeaderMap, body: Bytes| async move {
+                    objects.lock().unwrap().insert(id, (headers, body));
+                    StatusCode::OK
+                }
+            })
+            .get({
+                let objects = objects.clone();
+                move |Path(id): Path<String>| async move {
+                    match objects.lock().unwrap().get(&id) {
+                        Some((_, body)) => Ok(body.clone()),
+                        None => Err(StatusCode::NOT_FOUND),
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let store = S3Store::new(
+            reqwest::Client::new(),
+            &format!("http://{addr}/"),
+            "firmware-bucket",
+            "eu-central-1",
+            "AKIDEXAMPLE",
+            "secret",
+        );
+        let id = FirmwareId(Ulid::new());
+        store.put(id, b"\x7fELF").await.unwrap();
+
+        assert_eq!(
+            store.get(id).await.unwrap().as_deref(),
+            Some(&b"\x7fELF"[..])
+        );
+        assert_eq!(store.get(FirmwareId(Ulid::new())).await.unwrap(), None);
+        let objects = objects.lock().unwrap();
+        let (headers, _) = &objects[&id.0.to_string()];
+        let authorization = headers["authorization"].to_str().unwrap();
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(authorization.contains("/eu-central-1/s3/aws4_request"));
+    }
+}
@@ -0,0 +1,54 @@
+//! Values shared by the crate's unit tests.
+
+use axum::Extension;
+use ersha_core::{
+    DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId, SensorMetric, SensorReading,
+};
+use jiff::Timestamp;
+use ulid::Ulid;
+
+use crate::auth::{ApiKeyId, Principal, Scope};
+use crate::org::OrgId;
+
+/// Where fixture readings are taken.
+pub(crate) const LOCATION: H3Cell = H3Cell(0x8a2a1072b59ffff);
+
+/// A soil moisture reading taken now by a device, dispatcher and sensor of
+/// its own. Tests override the fields they care about with struct update
+/// syntax.
+pub(crate) fn reading() -> SensorReading {
+    SensorReading {
+        id: ReadingId(Ulid::new()),
+        device_id: DeviceId(Ulid::new()),
+        dispatcher_id: DispatcherId(Ulid::new()),
+        metric: SensorMetric::SoilMoisture {
+            value: Percentage(40),
+        },
+        location: LOCATION,
+        confidence: Percentage(90),
+        timestamp: Timestamp::now(),
+        sensor_id: SensorId(Ulid::new()),
+    }
+}
+
+/// A fresh key with `scope` within `org_id`, or every organization when
+/// `None`, over all of its fields.
+pub(crate) fn principal(scope: Scope, org_id: Option<OrgId>) -> Extension<Principal> {
+    Extension(Principal {
+        key_id: ApiKeyId(Ulid::new()),
+        scope,
+        org_id,
+        user_id: None,
+        fields: None,
+    })
+}
+
+/// A fresh admin key over every organization.
+pub(crate) fn admin() -> Extension<Principal> {
+    principal(Scope::Admin, None)
+}
+
+/// A fresh read-only key over every organization.
+pub(crate) fn reader() -> Extension<Principal> {
+    principal(Scope::ReadOnly, None)
+}
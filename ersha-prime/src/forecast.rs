@@ -0,0 +1,295 @@
+//! Forecasts of a field's readings, to plan irrigation by.
+//!
+//! [`Forecaster`] is where models plug in. Prime ships [`TrendForecaster`],
+//! which extrapolates the recent trend; a client of an external model
+//! implements the trait in its place.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use ersha_core::{H3Cell, SensorKind};
+use jiff::{SignedDuration, Timestamp};
+use serde::Serialize;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::config::ForecastConfig;
+use crate::registry::{
+    AggregateRegistry, DeviceRegistry, Registries,
+    filter::{AggregateFilter, DeviceFilter, DeviceSortBy, Pagination, QueryOptions, SortOrder},
+};
+use crate::rollup::Granularity;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Fewest hourly observations a trend is fitted to; with fewer, the latest
+/// value is carried forward instead.
+const MIN_TREND_HOURS: usize = 6;
+
+#[derive(Debug, Error)]
+pub enum ForecastError {
+    #[error("{0:?} can't be forecast")]
+    UnsupportedMetric(SensorKind),
+    #[error("no readings to forecast from")]
+    NoHistory,
+    #[error("failed to read history: {0}")]
+    History(#[source] BoxError),
+    #[error("forecast model failed: {0}")]
+    Model(#[source] BoxError),
+}
+
+/// A field's mean reading over one hour.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct Observation {
+    /// Start of the hour
+    pub hour: Timestamp,
+    pub value: f64,
+}
+
+/// A predicted reading.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct ForecastPoint {
+    pub at: Timestamp,
+    pub value: f64,
+}
+
+/// What a forecast is asked for.
+#[derive(Debug, Clone)]
+pub struct ForecastRequest {
+    pub field: H3Cell,
+    pub metric: SensorKind,
+    /// Hourly observations over the forecaster's lookback, oldest first
+    pub history: Vec<Observation>,
+    /// When the forecast starts
+    pub from: Timestamp,
+    /// Hours ahead of `from` to predict, one point per hour
+    pub hours: u32,
+}
+
+/// Predicts a field's readings from its recent history.
+#[async_trait]
+pub trait Forecaster: Send + Sync {
+    /// Name of the model, reported with its forecasts.
+    fn name(&self) -> &str;
+
+    /// How far back the history passed to [`forecast`] reaches.
+    ///
+    /// [`forecast`]: Forecaster::forecast
+    fn lookback(&self) -> SignedDuration;
+
+    async fn forecast(
+        &self,
+        request: &ForecastRequest,
+    ) -> Result<Vec<ForecastPoint>, ForecastError>;
+}
+
+/// Extrapolates the least squares trend of the history, or carries the
+/// latest value forward when there is too little history for a trend.
+#[derive(Debug, Clone)]
+pub struct TrendForecaster {
+    lookback: SignedDuration,
+}
+
+impl TrendForecaster {
+    pub fn new(config: &ForecastConfig) -> Self {
+        Self {
+            lookback: SignedDuration::from_hours(i64::from(config.lookback_hours.max(1))),
+        }
+    }
+}
+
+#[async_trait]
+impl Forecaster for TrendForecaster {
+    fn name(&self) -> &str {
+        "trend"
+    }
+
+    fn lookback(&self) -> SignedDuration {
+        self.lookback
+    }
+
+    async fn forecast(
+        &self,
+        request: &ForecastRequest,
+    ) -> Result<Vec<ForecastPoint>, ForecastError> {
+        let latest = request.history.last().ok_or(ForecastError::NoHistory)?;
+        let slope = match request.history.len() {
+            n if n < MIN_TREND_HOURS => 0.0,
+            _ => slope_per_hour(&request.history),
+        };
+        let (low, high) = bounds(request.metric);
+
+        Ok((1..=i64::from(request.hours))
+            .map(|hour| {
+                let at = request.from + SignedDuration::from_hours(hour);
+                let hours_ahead = at.duration_since(latest.hour).as_secs_f64() / 3600.0;
+                let value = (latest.value + slope * hours_ahead).clamp(low, high);
+                ForecastPoint { at, value }
+            })
+            .collect())
+    }
+}
+
+/// Least squares slope of `history`, in units per hour.
+fn slope_per_hour(history: &[Observation]) -> f64 {
+    let start = history[0].hour;
+    let points: Vec<(f64, f64)> = history
+        .iter()
+        .map(|o| (o.hour.duration_since(start).as_secs_f64() / 3600.0, o.value))
+        .collect();
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |(c, v), (x, y)| {
+        (c + (x - mean_x) * (y - mean_y), v + (x - mean_x).powi(2))
+    });
+
+    if variance == 0.0 {
+        0.0
+    } else {
+        covariance / variance
+    }
+}
+
+/// Range a metric's readings can take.
+fn bounds(metric: SensorKind) -> (f64, f64) {
+    match metric {
+        SensorKind::SoilMoisture | SensorKind::Humidity => (0.0, 100.0),
+        SensorKind::Rainfall => (0.0, f64::INFINITY),
+        SensorKind::SoilTemp | SensorKind::AirTemp => (f64::NEG_INFINITY, f64::INFINITY),
+    }
+}
+
+/// Hourly means of `metric` over the devices located in `field` since
+/// `since`, oldest first.
+pub async fn field_history<R: Registries>(
+    registries: &R,
+    field: H3Cell,
+    metric: SensorKind,
+    since: Timestamp,
+) -> Result<Vec<Observation>, ForecastError> {
+    let devices = registries.devices();
+    let in_field = DeviceFilter::builder().within([field]).build();
+    let count = devices
+        .count(Some(in_field.clone()))
+        .await
+        .map_err(|e| ForecastError::History(Box::new(e)))?;
+    let devices = devices
+        .list(QueryOptions {
+            filter: in_field,
+            sort_by: DeviceSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Offset {
+                offset: 0,
+                limit: count,
+            },
+        })
+        .await
+        .map_err(|e| ForecastError::History(Box::new(e)))?;
+    if devices.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let hourly = registries
+        .aggregates()
+        .list(
+            AggregateFilter::builder(Granularity::Hour)
+                .device_ids(devices.iter().map(|device| device.id))
+                .metric_kinds([metric])
+                .after(since)
+                .build(),
+        )
+        .await
+        .map_err(|e| ForecastError::History(Box::new(e)))?;
+
+    // Weighted by readings, so chattier probes count for more.
+    let mut hours: BTreeMap<Timestamp, (f64, u64)> = BTreeMap::new();
+    for aggregate in &hourly {
+        let (sum, count) = hours.entry(aggregate.bucket_start).or_default();
+        *sum += aggregate.sum;
+        *count += aggregate.count;
+    }
+
+    Ok(hours
+        .into_iter()
+        .filter(|(_, (_, count))| *count > 0)
+        .map(|(hour, (sum, count))| Observation {
+            hour,
+            value: sum / count as f64,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{H3Cell, SensorKind};
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::{ForecastError, ForecastRequest, Forecaster, Observation, TrendForecaster};
+    use crate::config::ForecastConfig;
+
+    const HOUR: i64 = 3_600 * 500_000;
+
+    fn hour(n: i64) -> Timestamp {
+        Timestamp::from_second(HOUR + n * 3_600).unwrap()
+    }
+
+    fn request(history: Vec<Observation>, hours: u32) -> ForecastRequest {
+        ForecastRequest {
+            field: H3Cell(0x892a1072b5bffff),
+            metric: SensorKind::SoilMoisture,
+            from: history.last().map_or(hour(0), |o| o.hour),
+            history,
+            hours,
+        }
+    }
+
+    #[tokio::test]
+    async fn drying_soil_keeps_drying() {
+        let forecaster = TrendForecaster::new(&ForecastConfig::default());
+        // Half a point drier each hour.
+        let history = (0..12)
+            .map(|n| Observation {
+                hour: hour(n),
+                value: 30.0 - 0.5 * n as f64,
+            })
+            .collect();
+
+        let points = forecaster.forecast(&request(history, 60)).await.unwrap();
+
+        assert_eq!(points.len(), 60);
+        assert_eq!(points[0].at, hour(12));
+        assert!((points[0].value - 24.0).abs() < 1e-9);
+        assert!((points[9].value - 19.5).abs() < 1e-9);
+        // Soil can't get drier than dry.
+        assert_eq!(points[59].value, 0.0);
+    }
+
+    #[tokio::test]
+    async fn short_histories_carry_the_latest_value_forward() {
+        let forecaster = TrendForecaster::new(&ForecastConfig::default());
+        let history = vec![
+            Observation {
+                hour: hour(0),
+                value: 31.0,
+            },
+            Observation {
+                hour: hour(1),
+                value: 28.0,
+            },
+        ];
+
+        let points = forecaster.forecast(&request(history, 3)).await.unwrap();
+        assert_eq!(
+            points.iter().map(|p| p.value).collect::<Vec<_>>(),
+            [28.0, 28.0, 28.0]
+        );
+        assert_eq!(
+            points[2].at.duration_since(points[0].at),
+            SignedDuration::from_hours(2)
+        );
+
+        let empty = forecaster.forecast(&request(Vec::new(), 3)).await;
+        assert!(matches!(empty, Err(ForecastError::NoHistory)));
+    }
+}
@@ -0,0 +1,95 @@
+//! Tags on devices and dispatchers, and named groups of them.
+//!
+//! Tags are free-form labels such as `pilot-a` or `v2-hardware`, kept beside
+//! the devices and dispatchers they're put on. A [`Group`] names a set of
+//! tags; its members are the devices and dispatchers carrying all of them.
+
+use std::collections::BTreeSet;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::org::OrgId;
+
+/// Longest tag accepted, in characters.
+pub const MAX_TAG_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct GroupId(pub Ulid);
+
+/// A named selection of the fleet by tag.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Group {
+    pub id: GroupId,
+    pub name: String,
+    pub description: Option<String>,
+    /// Devices and dispatchers carrying every one of these are members
+    pub tags: Vec<String>,
+    /// Only visible to this organization, or to everyone if `None`
+    pub org_id: Option<OrgId>,
+    pub created_at: Timestamp,
+}
+
+impl Group {
+    pub fn new(name: String, tags: Vec<String>) -> Self {
+        Self {
+            id: GroupId(Ulid::new()),
+            name,
+            description: None,
+            tags,
+            org_id: None,
+            created_at: Timestamp::now(),
+        }
+    }
+}
+
+/// `tag` trimmed and lowercased, so `Pilot-A` and `pilot-a` are one tag, or
+/// why it can't be used.
+///
+/// Commas are refused since list filters are comma separated.
+pub fn normalize_tag(tag: &str) -> Result<String, &'static str> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        return Err("tags must not be empty");
+    }
+    if tag.chars().count() > MAX_TAG_LEN {
+        return Err("tags must be at most 64 characters");
+    }
+    if tag.chars().any(|c| c == ',' || c.is_control()) {
+        return Err("tags must not contain commas or control characters");
+    }
+
+    Ok(tag)
+}
+
+/// Each of `tags` normalized, sorted and without repeats.
+pub fn normalize_tags<'a>(
+    tags: impl IntoIterator<Item = &'a String>,
+) -> Result<Vec<String>, &'static str> {
+    let tags = tags
+        .into_iter()
+        .map(|tag| normalize_tag(tag))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+
+    Ok(tags.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_tag, normalize_tags};
+
+    #[test]
+    fn tags_are_trimmed_lowercased_and_deduplicated() {
+        let tags = ["North-Ridge", " pilot-a ", "pilot-A"].map(String::from);
+
+        assert_eq!(
+            normalize_tags(&tags).unwrap(),
+            ["north-ridge", "pilot-a"].map(String::from)
+        );
+        assert!(normalize_tag("  ").is_err());
+        assert!(normalize_tag("a,b").is_err());
+        assert!(normalize_tag(&"x".repeat(65)).is_err());
+    }
+}
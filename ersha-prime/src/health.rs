@@ -0,0 +1,124 @@
+//! Dispatcher liveness derived from their status reports.
+
+use ersha_core::{Dispatcher, DispatcherId, DispatcherState, DispatcherStatus};
+use jiff::{SignedDuration, Timestamp};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A dispatcher's latest status report and when central received it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DispatcherReport {
+    pub status: DispatcherStatus,
+    pub received_at: Timestamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Connectivity {
+    Online,
+    /// Not heard from within the configured window, or never
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DispatcherHealth {
+    pub dispatcher_id: DispatcherId,
+    pub state: DispatcherState,
+    pub connectivity: Connectivity,
+    /// When central last received a status report
+    pub last_seen: Option<Timestamp>,
+    /// Latest report, with the pending backlog and link quality
+    pub status: Option<DispatcherStatus>,
+}
+
+impl DispatcherHealth {
+    /// Assess a dispatcher as of `now`, treating it as offline when its last
+    /// report is older than `window`.
+    pub fn assess(
+        dispatcher: Dispatcher,
+        report: Option<DispatcherReport>,
+        now: Timestamp,
+        window: SignedDuration,
+    ) -> Self {
+        let connectivity = match &report {
+            Some(report) if now.duration_since(report.received_at) <= window => {
+                Connectivity::Online
+            }
+            _ => Connectivity::Offline,
+        };
+
+        Self {
+            dispatcher_id: dispatcher.id,
+            state: dispatcher.state,
+            connectivity,
+            last_seen: report.as_ref().map(|report| report.received_at),
+            status: report.map(|report| report.status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        Dispatcher, DispatcherId, DispatcherState, DispatcherStatus, H3Cell, LinkQuality,
+    };
+    use jiff::{SignedDuration, Timestamp};
+    use ulid::Ulid;
+
+    use super::{Connectivity, DispatcherHealth, DispatcherReport};
+
+    fn dispatcher() -> Dispatcher {
+        Dispatcher {
+            id: DispatcherId(Ulid::new()),
+            location: H3Cell(0x8a2a1072b59ffff),
+            state: DispatcherState::Active,
+            provisioned_at: Timestamp::UNIX_EPOCH,
+        }
+    }
+
+    fn report(dispatcher_id: DispatcherId, received_at: Timestamp) -> DispatcherReport {
+        DispatcherReport {
+            status: DispatcherStatus {
+                dispatcher_id,
+                pending_readings: 10,
+                pending_statuses: 0,
+                link: LinkQuality {
+                    rtt_ms: Some(40),
+                    failed_uploads: 0,
+                },
+                uptime_seconds: 600,
+                timestamp: received_at,
+            },
+            received_at,
+        }
+    }
+
+    #[test]
+    fn offline_after_window() {
+        let dispatcher = dispatcher();
+        let seen = Timestamp::from_second(1_000).unwrap();
+        let window = SignedDuration::from_secs(300);
+        let at = |second| Timestamp::from_second(second).unwrap();
+
+        let fresh = DispatcherHealth::assess(
+            dispatcher.clone(),
+            Some(report(dispatcher.id, seen)),
+            at(1_300),
+            window,
+        );
+        assert_eq!(fresh.connectivity, Connectivity::Online);
+        assert_eq!(fresh.last_seen, Some(seen));
+
+        let stale = DispatcherHealth::assess(
+            dispatcher.clone(),
+            Some(report(dispatcher.id, seen)),
+            at(1_301),
+            window,
+        );
+        assert_eq!(stale.connectivity, Connectivity::Offline);
+
+        let silent = DispatcherHealth::assess(dispatcher, None, at(1_000), window);
+        assert_eq!(silent.connectivity, Connectivity::Offline);
+        assert_eq!(silent.last_seen, None);
+    }
+}
@@ -0,0 +1,231 @@
+//! Deployment-specific processing of ingested items.
+//!
+//! A deployment embedding prime as a library implements [`IngestionHook`]
+//! and registers it in the [`IngestionHooks`] handed to
+//! [`handle_batch_upload`](crate::rpc::handle_batch_upload). Hooks run inline
+//! on the items of each batch that passed validation, before they are
+//! stored, so they can enrich or correct readings, say with data from a
+//! weather service, without changes to the batch handler.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ersha_core::{BatchId, DeviceStatus, DispatcherId, SensorReading};
+use jiff::Timestamp;
+use thiserror::Error;
+
+/// A hook failing, which fails the batch.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct HookError(Box<dyn std::error::Error + Send + Sync>);
+
+impl HookError {
+    pub fn new(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Self(error.into())
+    }
+}
+
+/// The batch a hook's items arrived in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchContext {
+    pub batch_id: BatchId,
+    pub dispatcher_id: DispatcherId,
+    pub received_at: Timestamp,
+}
+
+/// Processing run on ingested items before they are stored.
+///
+/// Items may be changed in place, but must keep their ids and devices, which
+/// were checked before hooks run. Every method does nothing by default.
+///
+/// An error fails the whole batch as unavailable, and the dispatcher sends it
+/// again later. A hook that would rather store items unprocessed than hold
+/// ingestion up, say while the service it calls is down, handles its errors
+/// itself.
+#[async_trait]
+pub trait IngestionHook: Send + Sync {
+    /// Named in logs when the hook fails.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Process all the batch's items at once, before each is passed to
+    /// [`on_reading`](Self::on_reading) and [`on_status`](Self::on_status).
+    async fn on_batch(
+        &self,
+        _batch: &BatchContext,
+        _readings: &mut [SensorReading],
+        _statuses: &mut [DeviceStatus],
+    ) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    async fn on_reading(
+        &self,
+        _batch: &BatchContext,
+        _reading: &mut SensorReading,
+    ) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    async fn on_status(
+        &self,
+        _batch: &BatchContext,
+        _status: &mut DeviceStatus,
+    ) -> Result<(), HookError> {
+        Ok(())
+    }
+}
+
+/// Hooks run on every batch, in the order they were registered.
+#[derive(Clone, Default)]
+pub struct IngestionHooks {
+    hooks: Vec<Arc<dyn IngestionHook>>,
+}
+
+impl IngestionHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `hook` after those registered so far.
+    pub fn with(mut self, hook: impl IngestionHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Run each hook over the batch's items in turn, stopping at the first to
+    /// fail. Returns its name along with the error.
+    pub async fn run(
+        &self,
+        batch: &BatchContext,
+        readings: &mut [SensorReading],
+        statuses: &mut [DeviceStatus],
+    ) -> Result<(), (String, HookError)> {
+        for hook in &self.hooks {
+            let failed = |e| (hook.name().to_owned(), e);
+
+            hook.on_batch(batch, readings, statuses)
+                .await
+                .map_err(failed)?;
+            for reading in readings.iter_mut() {
+                hook.on_reading(batch, reading).await.map_err(failed)?;
+            }
+            for status in statuses.iter_mut() {
+                hook.on_status(batch, status).await.map_err(failed)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use ersha_core::{BatchId, DeviceId, DeviceStatus, DispatcherId, Percentage, SensorReading};
+    use ulid::Ulid;
+
+    use super::{BatchContext, HookError, IngestionHook, IngestionHooks};
+    use crate::fixtures::reading;
+
+    /// Records the calls it gets, failing readings from a device.
+    struct Recorder {
+        name: &'static str,
+        calls: &'static Mutex<Vec<String>>,
+        fail_for: Option<DeviceId>,
+    }
+
+    #[async_trait]
+    impl IngestionHook for Recorder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn on_batch(
+            &self,
+            _batch: &BatchContext,
+            readings: &mut [SensorReading],
+            _statuses: &mut [DeviceStatus],
+        ) -> Result<(), HookError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} batch of {}", self.name, readings.len()));
+            Ok(())
+        }
+
+        async fn on_reading(
+            &self,
+            _batch: &BatchContext,
+            reading: &mut SensorReading,
+        ) -> Result<(), HookError> {
+            if Some(reading.device_id) == self.fail_for {
+                return Err(HookError::new("weather service unreachable"));
+            }
+            reading.confidence = Percentage(reading.confidence.0 / 2);
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} reading", self.name));
+            Ok(())
+        }
+    }
+
+    fn context() -> BatchContext {
+        BatchContext {
+            batch_id: BatchId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            received_at: jiff::Timestamp::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks_run_in_order_until_one_fails() {
+        static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        let mut readings = vec![reading(), reading()];
+        let hooks = IngestionHooks::new()
+            .with(Recorder {
+                name: "first",
+                calls: &CALLS,
+                fail_for: None,
+            })
+            .with(Recorder {
+                name: "second",
+                calls: &CALLS,
+                fail_for: None,
+            });
+
+        hooks.run(&context(), &mut readings, &mut []).await.unwrap();
+
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            [
+                "first batch of 2",
+                "first reading",
+                "first reading",
+                "second batch of 2",
+                "second reading",
+                "second reading",
+            ]
+        );
+        assert!(readings.iter().all(|r| r.confidence == Percentage(22)));
+
+        let failing = IngestionHooks::new().with(Recorder {
+            name: "weather",
+            calls: &CALLS,
+            fail_for: Some(readings[1].device_id),
+        });
+        let (name, _) = failing
+            .run(&context(), &mut readings, &mut [])
+            .await
+            .unwrap_err();
+        assert_eq!(name, "weather");
+    }
+}
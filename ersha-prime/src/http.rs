@@ -0,0 +1,246 @@
+//! Limits and headers applied to every HTTP request, configured by
+//! [`HttpConfig`]: CORS for the web dashboard, a default body size limit,
+//! a request timeout and gzip compression. Every request is also given an
+//! ID, logged with everything done handling it and returned in the
+//! `x-request-id` header.
+
+use std::time::Duration;
+
+use axum::{
+    Router,
+    extract::{DefaultBodyLimit, Request, State},
+    http::{HeaderName, HeaderValue, Method, header, header::InvalidHeaderValue},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+    request_id::{MakeRequestId, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+};
+use tracing::Instrument;
+use ulid::Ulid;
+
+use crate::api::{ApiError, TOTAL_COUNT_HEADER};
+use crate::config::HttpConfig;
+
+/// Uploads that may take longer than the request timeout to send. They are
+/// bounded by their own body limits instead.
+const UPLOADS: [&str; 2] = ["/api/backfill", "/api/firmware"];
+
+/// Header carrying the ID a request is logged under. One sent by the client,
+/// such as a proxy in front of prime, is kept.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Wrap `router` in the layers `config` asks for.
+///
+/// Fails if a CORS origin isn't a valid header value.
+pub fn layer(router: Router, config: &HttpConfig) -> Result<Router, InvalidHeaderValue> {
+    let mut router = router.layer(DefaultBodyLimit::max(
+        config.max_body_kb.saturating_mul(1024),
+    ));
+
+    if config.request_timeout_secs > 0 {
+        router = router.layer(middleware::from_fn_with_state(
+            Duration::from_secs(config.request_timeout_secs),
+            time_out,
+        ));
+    }
+
+    if config.compression {
+        router = router.layer(CompressionLayer::new().gzip(true));
+    }
+
+    if !config.cors_origins.is_empty() {
+        let origins = config
+            .cors_origins
+            .iter()
+            .map(|origin| HeaderValue::from_str(origin))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        router = router.layer(
+            CorsLayer::new()
+                .allow_origin(AllowOrigin::list(origins))
+                .allow_methods([
+                    Method::GET,
+                    Method::POST,
+                    Method::PUT,
+                    Method::PATCH,
+                    Method::DELETE,
+                ])
+                .allow_headers([
+                    header::AUTHORIZATION,
+                    header::CONTENT_TYPE,
+                    header::IF_MATCH,
+                ])
+                .expose_headers([
+                    HeaderName::from_static(TOTAL_COUNT_HEADER),
+                    header::ETAG,
+                    header::RETRY_AFTER,
+                    HeaderName::from_static(REQUEST_ID_HEADER),
+                ])
+                .max_age(Duration::from_secs(3600)),
+        );
+    }
+
+    // Outermost, so that requests answered by the layers above are logged
+    // under their ID too.
+    let request_id = HeaderName::from_static(REQUEST_ID_HEADER);
+    router = router
+        .layer(middleware::from_fn(trace_request))
+        .layer(PropagateRequestIdLayer::new(request_id.clone()))
+        .layer(SetRequestIdLayer::new(request_id, UlidRequestId));
+
+    Ok(router)
+}
+
+/// Gives requests without an ID a new ULID.
+#[derive(Clone, Copy)]
+struct UlidRequestId;
+
+impl MakeRequestId for UlidRequestId {
+    fn make_request_id<B>(&mut self, _request: &axum::http::Request<B>) -> Option<RequestId> {
+        HeaderValue::from_str(&Ulid::new().to_string())
+            .ok()
+            .map(RequestId::new)
+    }
+}
+
+/// Middleware handling each request in a span carrying its ID.
+async fn trace_request(request: Request, next: Next) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "http_request",
+        request_id,
+        method = %request.method(),
+        path = request.uri().path(),
+    );
+
+    next.run(request).instrument(span).await
+}
+
+/// Middleware answering requests that take longer than `timeout` with 408.
+async fn time_out(State(timeout): State<Duration>, request: Request, next: Next) -> Response {
+    if request.method() == Method::POST && UPLOADS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => ApiError::Timeout.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::{get, post},
+    };
+    use tower::ServiceExt;
+
+    use super::{REQUEST_ID_HEADER, layer};
+    use crate::config::HttpConfig;
+
+    fn app(config: &HttpConfig) -> Router {
+        let router = Router::new()
+            .route("/fast", get(|| async { "x".repeat(4096) }))
+            .route(
+                "/slow",
+                get(|| async { tokio::time::sleep(Duration::from_secs(5)).await }),
+            )
+            .route("/echo", post(|body: String| async move { body }));
+
+        layer(router, config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cors_allows_only_listed_origins() {
+        let config = HttpConfig {
+            cors_origins: vec!["https://dashboard.example.com".to_owned()],
+            ..Default::default()
+        };
+
+        for (origin, allowed) in [
+            ("https://dashboard.example.com", true),
+            ("https://elsewhere.example.com", false),
+        ] {
+            let request = Request::options("/fast")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .body(Body::empty())
+                .unwrap();
+            let response = app(&config).oneshot(request).await.unwrap();
+
+            assert_eq!(
+                response
+                    .headers()
+                    .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                allowed,
+                "{origin}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected() {
+        let config = HttpConfig {
+            max_body_kb: 1,
+            ..Default::default()
+        };
+
+        let request = Request::post("/echo")
+            .body(Body::from("x".repeat(2048)))
+            .unwrap();
+        let response = app(&config).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_time_out() {
+        let config = HttpConfig {
+            request_timeout_secs: 1,
+            ..Default::default()
+        };
+
+        let request = Request::get("/slow").body(Body::empty()).unwrap();
+        let response = app(&config).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn responses_are_gzipped_when_accepted() {
+        let request = Request::get("/fast")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(&HttpConfig::default()).oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    }
+
+    #[tokio::test]
+    async fn request_ids_are_assigned_or_kept() {
+        let request = Request::get("/fast").body(Body::empty()).unwrap();
+        let response = app(&HttpConfig::default()).oneshot(request).await.unwrap();
+        let assigned = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(ulid::Ulid::from_string(assigned).is_ok());
+
+        let request = Request::get("/fast")
+            .header(REQUEST_ID_HEADER, "from-the-proxy")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(&HttpConfig::default()).oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "from-the-proxy");
+    }
+}
@@ -0,0 +1,159 @@
+//! Batch ids as idempotency keys.
+//!
+//! A dispatcher that loses the response to a batch, say to a timeout, sends
+//! it again under the same id. Prime answers the retry with the response it
+//! gave the first time instead of processing the batch again.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use ersha_core::{BatchId, BatchUploadResponse, DispatcherId};
+
+/// Batches remembered per dispatcher by default.
+const DEFAULT_PER_DISPATCHER: usize = 16;
+
+/// How long a batch is remembered by default. Dispatchers retry within
+/// seconds to minutes.
+const DEFAULT_TTL: jiff::SignedDuration = jiff::SignedDuration::from_mins(10);
+
+/// Responses to the batches each dispatcher sent recently.
+///
+/// Only accepted batches are remembered: a rejected batch may well be
+/// accepted when retried. A retry arriving while the first attempt is still
+/// being processed is processed too, with storage discarding duplicates.
+#[derive(Clone)]
+pub struct RecentBatches {
+    per_dispatcher: usize,
+    ttl: jiff::SignedDuration,
+    batches: Arc<Mutex<HashMap<DispatcherId, VecDeque<Answered>>>>,
+}
+
+struct Answered {
+    id: BatchId,
+    at: jiff::Timestamp,
+    response: BatchUploadResponse,
+}
+
+impl Default for RecentBatches {
+    fn default() -> Self {
+        Self::new(DEFAULT_PER_DISPATCHER, DEFAULT_TTL)
+    }
+}
+
+impl RecentBatches {
+    /// Remember the last `per_dispatcher` batches of each dispatcher, for
+    /// `ttl` each.
+    pub fn new(per_dispatcher: usize, ttl: jiff::SignedDuration) -> Self {
+        Self {
+            per_dispatcher,
+            ttl,
+            batches: Arc::default(),
+        }
+    }
+
+    /// The response given to batch `id` of `dispatcher_id`, if it was
+    /// accepted recently.
+    pub fn get(
+        &self,
+        dispatcher_id: DispatcherId,
+        id: BatchId,
+        now: jiff::Timestamp,
+    ) -> Option<BatchUploadResponse> {
+        let batches = self.batches.lock().expect("recent batches lock poisoned");
+        batches
+            .get(&dispatcher_id)?
+            .iter()
+            .find(|answered| answered.id == id && now.duration_since(answered.at) < self.ttl)
+            .map(|answered| answered.response.clone())
+    }
+
+    /// Remember `response` to a batch of `dispatcher_id`.
+    pub fn record(
+        &self,
+        dispatcher_id: DispatcherId,
+        response: &BatchUploadResponse,
+        now: jiff::Timestamp,
+    ) {
+        if !matches!(response, BatchUploadResponse::Accepted { .. }) || self.per_dispatcher == 0 {
+            return;
+        }
+
+        let mut batches = self.batches.lock().expect("recent batches lock poisoned");
+        let answered = batches.entry(dispatcher_id).or_default();
+        answered.retain(|answered| now.duration_since(answered.at) < self.ttl);
+        if answered.len() == self.per_dispatcher {
+            answered.pop_front();
+        }
+        answered.push_back(Answered {
+            id: response.id(),
+            at: now,
+            response: response.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{BatchId, BatchRejectionReason, BatchUploadResponse, DispatcherId};
+    use ulid::Ulid;
+
+    use super::RecentBatches;
+
+    fn at(second: i64) -> jiff::Timestamp {
+        jiff::Timestamp::from_second(second).unwrap()
+    }
+
+    fn accepted() -> BatchUploadResponse {
+        BatchUploadResponse::Accepted {
+            id: BatchId(Ulid::new()),
+            readings: Box::new([]),
+            statuses: Box::new([]),
+        }
+    }
+
+    #[test]
+    fn accepted_batches_are_remembered_for_a_while() {
+        let recent = RecentBatches::new(2, jiff::SignedDuration::from_secs(60));
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let response = accepted();
+        recent.record(dispatcher_id, &response, at(0));
+
+        assert_eq!(
+            recent.get(dispatcher_id, response.id(), at(30)),
+            Some(response.clone())
+        );
+        // Batch ids are only keys within their dispatcher.
+        let other = DispatcherId(Ulid::new());
+        assert_eq!(recent.get(other, response.id(), at(30)), None);
+        assert_eq!(recent.get(dispatcher_id, response.id(), at(60)), None);
+
+        let rejected = BatchUploadResponse::Rejected {
+            id: BatchId(Ulid::new()),
+            reason: BatchRejectionReason::Unavailable,
+        };
+        recent.record(dispatcher_id, &rejected, at(0));
+        assert_eq!(recent.get(dispatcher_id, rejected.id(), at(0)), None);
+    }
+
+    #[test]
+    fn oldest_batches_are_forgotten_first() {
+        let recent = RecentBatches::new(2, jiff::SignedDuration::from_secs(60));
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let responses = [accepted(), accepted(), accepted()];
+        for response in &responses {
+            recent.record(dispatcher_id, response, at(0));
+        }
+
+        assert_eq!(recent.get(dispatcher_id, responses[0].id(), at(0)), None);
+        assert!(
+            recent
+                .get(dispatcher_id, responses[1].id(), at(0))
+                .is_some()
+        );
+        assert!(
+            recent
+                .get(dispatcher_id, responses[2].id(), at(0))
+                .is_some()
+        );
+    }
+}
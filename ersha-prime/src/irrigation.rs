@@ -0,0 +1,475 @@
+//! Irrigation planning from the field indicators.
+//!
+//! An [`IrrigationPlan`] sets up a field for irrigation: its crop
+//! coefficient and, optionally, the valve watering it. Plans are replanned
+//! periodically, recommending the next window to irrigate in when the soil
+//! has dried enough. Plans marked automatic open the valve at the start of
+//! the window and close it at the end, by queuing commands for the device.
+
+use std::time::Duration;
+
+use ersha_core::{CommandId, CommandKind, DeviceId, H3Cell};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::command::{self, Command};
+use crate::config::IrrigationConfig;
+use crate::derived::IndicatorKind;
+use crate::org::OrgId;
+use crate::registry::{
+    CommandRegistry, DerivedMetricRegistry, IrrigationRegistry, Registries, filter::IndicatorFilter,
+};
+use crate::rollup::Granularity;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// How long a command closing a valve stays deliverable.
+const CLOSE_TTL: SignedDuration = SignedDuration::from_hours(1);
+
+#[derive(Debug, Error)]
+pub enum IrrigationError {
+    #[error("failed to read indicators: {0}")]
+    Indicators(#[source] BoxError),
+    #[error("failed to queue a valve command: {0}")]
+    Commands(#[source] BoxError),
+    #[error("failed to store plans: {0}")]
+    Store(#[source] BoxError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct PlanId(pub Ulid);
+
+/// An actuator output of a device that waters a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Valve {
+    pub device_id: DeviceId,
+    /// Output switched by `actuate` commands
+    pub channel: u8,
+}
+
+/// When to irrigate and how much.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct IrrigationWindow {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// Water to apply, in mm
+    pub water_mm: f64,
+    /// Command queued to open the valve, once it is
+    pub opened_by: Option<CommandId>,
+    /// Command queued to close the valve, once it is
+    pub closed_by: Option<CommandId>,
+}
+
+/// A field's irrigation setup and its recommended window.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct IrrigationPlan {
+    pub id: PlanId,
+    pub field: H3Cell,
+    /// Share of the soil moisture deficit the crop needs refilled, usually
+    /// between 0.3 and 1.2 over a season
+    pub crop_coefficient: f64,
+    pub valve: Option<Valve>,
+    /// Open and close the valve for recommended windows
+    pub automatic: bool,
+    pub org_id: Option<OrgId>,
+    /// Next window to irrigate in, if the field needs water
+    pub window: Option<IrrigationWindow>,
+    /// When the window was last recommended
+    pub planned_at: Option<Timestamp>,
+    pub created_at: Timestamp,
+}
+
+impl IrrigationPlan {
+    pub fn new(field: H3Cell, crop_coefficient: f64, created_at: Timestamp) -> Self {
+        Self {
+            id: PlanId(Ulid::new()),
+            field,
+            crop_coefficient,
+            valve: None,
+            automatic: false,
+            org_id: None,
+            window: None,
+            planned_at: None,
+            created_at,
+        }
+    }
+}
+
+/// Replan every `interval_secs` until cancelled.
+pub async fn run<R: Registries>(
+    registries: R,
+    config: IrrigationConfig,
+    cancel: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        match refresh(&registries, &config, Timestamp::now()).await {
+            Ok(windows) => info!(windows, "irrigation plans refreshed"),
+            Err(e) => error!(error = %e, "irrigation planning failed"),
+        }
+    }
+}
+
+/// Bring every plan up to date at `now`, returning how many recommend a
+/// window.
+pub async fn refresh<R: Registries>(
+    registries: &R,
+    config: &IrrigationConfig,
+    now: Timestamp,
+) -> Result<usize, IrrigationError> {
+    let plans = registries
+        .irrigation()
+        .list()
+        .await
+        .map_err(|e| IrrigationError::Store(Box::new(e)))?;
+
+    let mut windows = 0;
+    for mut plan in plans {
+        advance(registries, config, &mut plan, now).await?;
+        windows += usize::from(plan.window.is_some());
+
+        registries
+            .irrigation()
+            .update(plan)
+            .await
+            .map_err(|e| IrrigationError::Store(Box::new(e)))?;
+    }
+
+    Ok(windows)
+}
+
+/// Move `plan` along at `now`: close the valve after a window, open it at
+/// the start of one, and recommend the next window otherwise.
+///
+/// A window isn't replanned while it is under way, so its valve commands
+/// stay paired.
+pub async fn advance<R: Registries>(
+    registries: &R,
+    config: &IrrigationConfig,
+    plan: &mut IrrigationPlan,
+    now: Timestamp,
+) -> Result<(), IrrigationError> {
+    let valve = plan.valve;
+    let Some(window) = plan.window.as_mut() else {
+        return replan(registries, config, plan, now).await;
+    };
+
+    if now >= window.end {
+        // The valve is closed even if the plan was since made manual.
+        close(registries, valve, window, now).await?;
+    }
+
+    if window.start <= now && now < window.end {
+        if let (Some(valve), true, None) = (valve, plan.automatic, window.opened_by) {
+            let ttl = window.end.duration_since(now);
+            window.opened_by = actuate(registries, valve, true, now, ttl).await?;
+        }
+        return Ok(());
+    }
+    if window.opened_by.is_some() && window.closed_by.is_none() {
+        // Retried next time.
+        return Ok(());
+    }
+
+    replan(registries, config, plan, now).await
+}
+
+/// Close `plan`'s valve if its window opened it, ahead of the window's end.
+/// The window isn't replanned until it ends.
+pub async fn stop<R: Registries>(
+    registries: &R,
+    plan: &mut IrrigationPlan,
+    now: Timestamp,
+) -> Result<(), IrrigationError> {
+    match plan.window.as_mut() {
+        Some(window) => close(registries, plan.valve, window, now).await,
+        None => Ok(()),
+    }
+}
+
+/// Close the valve `window` opened, unless it was closed already.
+async fn close<R: Registries>(
+    registries: &R,
+    valve: Option<Valve>,
+    window: &mut IrrigationWindow,
+    now: Timestamp,
+) -> Result<(), IrrigationError> {
+    if let (Some(valve), Some(_), None) = (valve, window.opened_by, window.closed_by) {
+        window.closed_by = actuate(registries, valve, false, now, CLOSE_TTL).await?;
+    }
+    Ok(())
+}
+
+/// Recommend `plan`'s next window from its field's latest indicators.
+async fn replan<R: Registries>(
+    registries: &R,
+    config: &IrrigationConfig,
+    plan: &mut IrrigationPlan,
+    now: Timestamp,
+) -> Result<(), IrrigationError> {
+    let since = Granularity::Day
+        .bucket_start(now)
+        .checked_sub(SignedDuration::from_hours(24))
+        .unwrap_or(now);
+    let indicators = registries
+        .derived_metrics()
+        .list(
+            IndicatorFilter::builder()
+                .field(plan.field)
+                .kinds([IndicatorKind::SoilMoistureDeficit, IndicatorKind::Rainfall])
+                .after(since)
+                .build(),
+        )
+        .await
+        .map_err(|e| IrrigationError::Indicators(Box::new(e)))?;
+
+    let deficit = indicators
+        .iter()
+        .rfind(|i| i.kind == IndicatorKind::SoilMoistureDeficit);
+    // Rain on the day of the deficit counts too, as the day's mean moisture
+    // only partly reflects rain that fell late in it.
+    let rainfall = deficit.map_or(0.0, |deficit| {
+        indicators
+            .iter()
+            .filter(|i| i.kind == IndicatorKind::Rainfall && i.day >= deficit.day)
+            .map(|i| i.value)
+            .sum()
+    });
+
+    plan.window = deficit
+        .and_then(|deficit| recommend(config, plan.crop_coefficient, deficit.value, rainfall, now));
+    plan.planned_at = Some(now);
+    Ok(())
+}
+
+/// The window to irrigate in for a soil moisture `deficit` in percentage
+/// points and `rainfall` in mm since, or `None` if the crop doesn't need
+/// water yet.
+pub fn recommend(
+    config: &IrrigationConfig,
+    crop_coefficient: f64,
+    deficit: f64,
+    rainfall: f64,
+    now: Timestamp,
+) -> Option<IrrigationWindow> {
+    let deficit = deficit * crop_coefficient;
+    if deficit < config.trigger_deficit_percent {
+        return None;
+    }
+    let water_mm = deficit * config.mm_per_percent - rainfall.max(0.0);
+    if water_mm <= 0.0 {
+        return None;
+    }
+
+    let hours = (water_mm / config.application_mm_per_hour).min(f64::from(config.max_hours));
+    let start = next_start(now, config.start_hour);
+    // Rounded up to the minute.
+    let minutes = (hours * 60.0).ceil() as i64;
+
+    Some(IrrigationWindow {
+        start,
+        end: start + SignedDuration::from_mins(minutes),
+        water_mm: hours * config.application_mm_per_hour,
+        opened_by: None,
+        closed_by: None,
+    })
+}
+
+/// The first `hour` UTC at or after `now`.
+fn next_start(now: Timestamp, hour: u8) -> Timestamp {
+    let hour = SignedDuration::from_hours(hour.min(23).into());
+    let today = Granularity::Day.bucket_start(now) + hour;
+    if today >= now {
+        today
+    } else {
+        today + SignedDuration::from_hours(24)
+    }
+}
+
+/// Queue a command switching `valve`, through the dispatcher last heard
+/// from the device. Returns `None` if the device can't be reached yet.
+async fn actuate<R: Registries>(
+    registries: &R,
+    valve: Valve,
+    on: bool,
+    now: Timestamp,
+    ttl: SignedDuration,
+) -> Result<Option<CommandId>, IrrigationError> {
+    let Some(dispatcher_id) = command::route(registries, valve.device_id)
+        .await
+        .map_err(IrrigationError::Commands)?
+    else {
+        warn!(device_id = ?valve.device_id, "no dispatcher reaches the valve");
+        return Ok(None);
+    };
+
+    let command = Command::new(
+        valve.device_id,
+        dispatcher_id,
+        CommandKind::Actuate {
+            channel: valve.channel,
+            on,
+        },
+        now,
+        ttl,
+    );
+    let id = command.id;
+    registries
+        .commands()
+        .enqueue(command)
+        .await
+        .map_err(|e| IrrigationError::Commands(Box::new(e)))?;
+
+    info!(command_id = ?id, device_id = ?valve.device_id, on, "irrigation valve command queued");
+    Ok(Some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId, SensorMetric,
+        SensorReading,
+    };
+    use jiff::{SignedDuration, Timestamp};
+    use ulid::Ulid;
+
+    use super::{IrrigationPlan, Valve, advance, recommend};
+    use crate::command::CommandState;
+    use crate::config::IrrigationConfig;
+    use crate::derived::{Indicator, IndicatorKind};
+    use crate::registry::{
+        CommandRegistry, DerivedMetricRegistry, ReadingRegistry, memory::InMemoryRegistries,
+    };
+
+    const FIELD: H3Cell = H3Cell(0x892a1072b5bffff);
+    const DAY: i64 = 86_400 * 20_000;
+
+    fn at(second: i64) -> Timestamp {
+        Timestamp::from_second(second).unwrap()
+    }
+
+    #[test]
+    fn dry_fields_are_watered_at_the_start_hour() {
+        let config = IrrigationConfig::default();
+
+        // 12 points below field capacity, of which the crop needs 80%.
+        let window = recommend(&config, 0.8, 12.0, 2.0, at(DAY + 3_600)).unwrap();
+        let water = 12.0 * 0.8 * config.mm_per_percent - 2.0;
+        assert_eq!(
+            window.start,
+            at(DAY) + SignedDuration::from_hours(config.start_hour.into())
+        );
+        assert!((window.water_mm - water).abs() < 0.1);
+        let minutes = window.end.duration_since(window.start).as_mins();
+        assert_eq!(
+            minutes,
+            (water / config.application_mm_per_hour * 60.0).ceil() as i64
+        );
+
+        // Past the start hour, the window moves to the next day.
+        let later = recommend(&config, 0.8, 12.0, 2.0, at(DAY + 12 * 3_600)).unwrap();
+        assert_eq!(later.start, window.start + SignedDuration::from_hours(24));
+
+        // Moist enough, or rained enough.
+        assert_eq!(recommend(&config, 0.8, 4.0, 0.0, at(DAY)), None);
+        assert_eq!(recommend(&config, 0.8, 12.0, 100.0, at(DAY)), None);
+    }
+
+    #[tokio::test]
+    async fn automatic_plans_open_and_close_the_valve() {
+        let registries = InMemoryRegistries::default();
+        let config = IrrigationConfig::default();
+        let device_id = DeviceId(Ulid::new());
+        let dispatcher_id = DispatcherId(Ulid::new());
+        registries
+            .readings
+            .store(SensorReading {
+                id: ReadingId(Ulid::new()),
+                device_id,
+                dispatcher_id,
+                metric: SensorMetric::SoilMoisture {
+                    value: Percentage(20),
+                },
+                location: H3Cell(0x8a2a1072b59ffff),
+                confidence: Percentage(90),
+                timestamp: at(DAY),
+                sensor_id: SensorId(Ulid::new()),
+            })
+            .await
+            .unwrap();
+        registries
+            .derived_metrics
+            .upsert(vec![Indicator {
+                field: FIELD,
+                kind: IndicatorKind::SoilMoistureDeficit,
+                day: at(DAY),
+                value: 15.0,
+                computed_at: at(DAY + 60),
+            }])
+            .await
+            .unwrap();
+
+        let mut plan = IrrigationPlan {
+            valve: Some(Valve {
+                device_id,
+                channel: 2,
+            }),
+            automatic: true,
+            ..IrrigationPlan::new(FIELD, 1.0, at(DAY))
+        };
+        advance(&registries, &config, &mut plan, at(DAY + 60))
+            .await
+            .unwrap();
+        let window = plan.window.clone().unwrap();
+        assert_eq!(window.opened_by, None);
+
+        let during = window.start + SignedDuration::from_mins(1);
+        advance(&registries, &config, &mut plan, during)
+            .await
+            .unwrap();
+        let opened_by = plan.window.as_ref().unwrap().opened_by.unwrap();
+        // Opening is only queued once.
+        advance(&registries, &config, &mut plan, during)
+            .await
+            .unwrap();
+        assert_eq!(plan.window.as_ref().unwrap().opened_by, Some(opened_by));
+
+        advance(&registries, &config, &mut plan, window.end)
+            .await
+            .unwrap();
+        let commands = registries.commands.list(device_id, 10).await.unwrap();
+        assert_eq!(commands.len(), 2);
+        assert!(
+            commands
+                .iter()
+                .all(|c| c.state == CommandState::Queued && c.dispatcher_id == dispatcher_id)
+        );
+        let open = commands.iter().find(|c| c.id == opened_by).unwrap();
+        assert_eq!(open.expires_at, window.end);
+        assert_eq!(
+            open.kind,
+            ersha_core::CommandKind::Actuate {
+                channel: 2,
+                on: true
+            }
+        );
+        // Replanned once closed; still dry, so watered again tomorrow.
+        assert_eq!(
+            plan.window.as_ref().unwrap().start,
+            window.start + SignedDuration::from_hours(24)
+        );
+    }
+}
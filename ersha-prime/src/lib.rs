@@ -1,2 +1,47 @@
+pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod backfill;
+pub mod backup;
+pub mod battery;
+pub mod command;
 pub mod config;
+pub mod correction;
+pub mod dead_letter;
+pub mod derived;
+pub mod downsample;
+pub mod egress;
+pub mod events;
+pub mod firmware;
+#[cfg(test)]
+mod fixtures;
+pub mod forecast;
+pub mod group;
+pub mod health;
+pub mod hooks;
+pub mod http;
+pub mod idempotency;
+pub mod irrigation;
+pub mod live;
+pub mod metadata;
+pub mod metrics;
+pub mod notify;
+pub mod org;
+pub mod outbox;
+pub mod placement;
+pub mod quality;
+pub mod quota;
+pub mod ratelimit;
+pub mod region;
 pub mod registry;
+pub mod retention;
+pub mod rollup;
+pub mod rpc;
+pub mod server;
+pub mod timeseries;
+pub mod tuning;
+pub mod tunnel;
+pub mod user;
+pub mod validation;
+pub mod watchdog;
+pub mod webhook;
@@ -0,0 +1,103 @@
+use ersha_core::{DeviceId, DispatcherId, H3Cell, SensorKind, SensorReading};
+
+/// Which live readings a subscriber wants. Empty criteria match everything.
+#[derive(Debug, Default, Clone)]
+pub struct FeedFilter {
+    pub device_ids: Option<Vec<DeviceId>>,
+    pub metric_kinds: Option<Vec<SensorKind>>,
+    /// Cells that readings must lie within, at any resolution.
+    pub within: Option<Vec<H3Cell>>,
+    pub dispatcher_ids: Option<Vec<DispatcherId>>,
+}
+
+impl FeedFilter {
+    pub fn matches(&self, reading: &SensorReading) -> bool {
+        if let Some(device_ids) = &self.device_ids
+            && !device_ids.is_empty()
+            && !device_ids.contains(&reading.device_id)
+        {
+            return false;
+        }
+
+        if let Some(dispatcher_ids) = &self.dispatcher_ids
+            && !dispatcher_ids.is_empty()
+            && !dispatcher_ids.contains(&reading.dispatcher_id)
+        {
+            return false;
+        }
+
+        if let Some(kinds) = &self.metric_kinds
+            && !kinds.is_empty()
+            && !kinds.contains(&reading.metric.kind())
+        {
+            return false;
+        }
+
+        if let Some(cells) = &self.within
+            && !cells.is_empty()
+            && !cells.iter().any(|cell| reading.location.is_within(*cell))
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, H3Cell, SensorKind, SensorReading};
+    use ulid::Ulid;
+
+    use super::FeedFilter;
+    use crate::fixtures;
+
+    /// A resolution 10 cell and its resolution 9 parent.
+    const CELL: H3Cell = H3Cell(0x8a2a1072b59ffff);
+    const PARENT: H3Cell = H3Cell(0x892a1072b5bffff);
+
+    fn reading(device_id: DeviceId, location: H3Cell) -> SensorReading {
+        SensorReading {
+            device_id,
+            location,
+            ..fixtures::reading()
+        }
+    }
+
+    #[test]
+    fn cell_containment() {
+        assert_eq!(CELL.resolution(), 10);
+        assert_eq!(PARENT.resolution(), 9);
+        assert!(CELL.is_within(PARENT));
+        assert!(CELL.is_within(CELL));
+        assert!(!PARENT.is_within(CELL));
+        assert!(!CELL.is_within(H3Cell(0x892a1072b4bffff)));
+    }
+
+    #[test]
+    fn filter_matches_devices_metrics_and_area() {
+        let device = DeviceId(Ulid::new());
+        let r = reading(device, CELL);
+
+        assert!(FeedFilter::default().matches(&r));
+
+        let by_device = FeedFilter {
+            device_ids: Some(vec![DeviceId(Ulid::new())]),
+            ..Default::default()
+        };
+        assert!(!by_device.matches(&r));
+
+        let by_metric = FeedFilter {
+            metric_kinds: Some(vec![SensorKind::AirTemp]),
+            ..Default::default()
+        };
+        assert!(!by_metric.matches(&r));
+
+        let by_area = FeedFilter {
+            device_ids: Some(vec![device]),
+            within: Some(vec![PARENT]),
+            ..Default::default()
+        };
+        assert!(by_area.matches(&r));
+    }
+}
@@ -1,19 +1,34 @@
-use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use axum::{Router, routing::get};
-use clap::Parser;
-use ersha_core::{Dispatcher, DispatcherState, HelloRequest, HelloResponse};
+use clap::{Parser, Subcommand};
 use ersha_prime::{
+    backup::{self, Backups},
     config::{Config, RegistryConfig},
+    metrics,
     registry::{
-        DispatcherRegistry, memory::InMemoryDispatcherRegistry, sqlite::SqliteDispatcherRegistry,
+        Registries,
+        cached::CachedRegistries,
+        memory::{
+            InMemoryDeviceStatusRegistry, InMemoryDispatcherStatusRegistry, InMemoryIngestRegistry,
+            InMemoryReadingRegistry, InMemoryRegistries,
+        },
+        sqlite::{
+            self, SchemaStatus, SqliteAggregateRegistry, SqliteApiKeyRegistry, SqliteAuditRegistry,
+            SqliteCommandRegistry, SqliteContactRegistry, SqliteCorrectionRegistry,
+            SqliteDeadLetterRegistry, SqliteDerivedMetricRegistry, SqliteDeviceRegistry,
+            SqliteDeviceStatusRegistry, SqliteDispatcherRegistry, SqliteFirmwareRegistry,
+            SqliteGroupRegistry, SqliteIngestRegistry, SqliteIrrigationRegistry, SqliteOrgRegistry,
+            SqliteOutboxRegistry, SqliteReadingRegistry, SqliteRegistries, SqliteUserRegistry,
+            SqliteValidationRuleRegistry, SqliteWebhookRegistry,
+        },
     },
+    server::PrimeServer,
+    tuning::{DEFAULT_LOG_FILTER, Tunables, Tuning},
 };
-use ersha_rpc::Server;
-use tokio::net::TcpListener;
-use tokio_util::sync::CancellationToken;
-use tracing::info;
+use ersha_rpc::capture::CaptureWriter;
+use sqlx::SqlitePool;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(name = "ersha-prime")]
@@ -22,125 +37,290 @@ struct Cli {
     /// Path to the configuration file
     #[arg(short, long, default_value = "ersha-prime.toml")]
     config: PathBuf,
+    /// Append every RPC envelope received to this capture file, for
+    /// replaying with ersha-prime-replay
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Leave dispatcher credentials out of the capture
+    #[arg(long, requires = "record")]
+    redact: bool,
+    /// Apply pending database migrations and exit, without serving
+    #[arg(long)]
+    migrate_only: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Replace the SQLite database with a backup taken by prime, while
+    /// prime is stopped
+    Restore {
+        /// Compressed backup to restore
+        backup: PathBuf,
+        /// Replace the database even if one exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Inspect the database schema
+    Migrations {
+        #[command(subcommand)]
+        command: MigrationsCommand,
+    },
 }
 
-struct AppState<R: DispatcherRegistry> {
-    dispatcher_registry: R,
+#[derive(Subcommand)]
+enum MigrationsCommand {
+    /// List the migrations applied to the database and those pending,
+    /// without changing it
+    Status,
 }
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
 
-    let filter =
-        std::env::var("RUST_LOG").unwrap_or_else(|_| "tracing=info,ersha_prime=info".to_owned());
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
+    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_FILTER.to_owned());
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter.clone())
         .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
-        .init();
+        .with_filter_reloading();
+    let log_reload = subscriber.reload_handle();
+    subscriber.init();
 
     let cli = Cli::parse();
 
-    let config = if cli.config.exists() {
-        info!(path = ?cli.config, "Loading configuration");
-        Config::load(&cli.config)?
+    let config_file = cli.config.exists().then(|| cli.config.clone());
+    let config = if let Some(path) = &config_file {
+        info!(path = ?path, "Loading configuration");
+        Config::load(path)?
     } else {
         info!("No configuration file found, using defaults");
         Config::default()
     };
 
+    match &cli.command {
+        Some(Command::Restore { backup, force }) => {
+            let RegistryConfig::Sqlite { path, .. } = &config.registry else {
+                color_eyre::eyre::bail!("registries aren't kept in SQLite, nothing to restore");
+            };
+            backup::restore(backup, path, *force)?;
+            info!(backup = ?backup, path = ?path, "Database restored");
+            return Ok(());
+        }
+        Some(Command::Migrations {
+            command: MigrationsCommand::Status,
+        }) => {
+            let status = sqlite::schema_status(&open_database(&config).await?).await?;
+            print_schema_status(&status);
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if cli.migrate_only {
+        migrate_database(&open_database(&config).await?).await?;
+        return Ok(());
+    }
+
+    let tunables = Tunables::from_config(&config)?;
+    if tunables.log_filter != filter {
+        log_reload.reload(EnvFilter::try_new(&tunables.log_filter)?)?;
+    }
+    let mut tuning = Tuning::new(tunables)
+        .with_log_reload(move |filter| log_reload.reload(filter).map_err(|e| e.to_string()));
+    if let Some(path) = config_file {
+        tuning = tuning.with_config_file(path);
+    }
+
+    let capture = match &cli.record {
+        Some(path) => {
+            let capture = CaptureWriter::open(path)?;
+            warn!(path = ?path, redacted = cli.redact, "Recording RPC envelopes");
+            Some(if cli.redact {
+                capture.with_redaction()
+            } else {
+                capture
+            })
+        }
+        None => None,
+    };
+
     info!(rpc_addr = %config.server.rpc_addr, http_addr = %config.server.http_addr, "Starting servers");
 
-    match config.registry {
+    match &config.registry {
         RegistryConfig::Memory => {
-            info!("Using in-memory dispatcher registry");
-            let registry = InMemoryDispatcherRegistry::new();
-            run_server(registry, config.server.rpc_addr, config.server.http_addr).await?;
+            info!("Using in-memory registries");
+            let defaults = InMemoryRegistries::default();
+            let readings = InMemoryReadingRegistry::with_limits(config.memory.readings);
+            let statuses = InMemoryDeviceStatusRegistry::with_limits(config.memory.statuses);
+            let registries = InMemoryRegistries {
+                ingest: InMemoryIngestRegistry::new(
+                    readings.clone(),
+                    statuses.clone(),
+                    defaults.dead_letters.clone(),
+                    defaults.outbox.clone(),
+                ),
+                readings,
+                statuses,
+                ..defaults
+            };
+            let backups = Backups::new(config.backup.clone(), None);
+            run(registries, config, tuning, backups, capture).await?;
         }
-        RegistryConfig::Sqlite { path } => {
-            info!(path = ?path, "Using SQLite dispatcher registry");
-            let registry = SqliteDispatcherRegistry::new(path.to_string_lossy()).await?;
-            run_server(registry, config.server.rpc_addr, config.server.http_addr).await?;
+        RegistryConfig::Sqlite {
+            path,
+            pool: pool_config,
+        } => {
+            info!(
+                path = ?path,
+                max_connections = pool_config.max_connections,
+                write_queue = pool_config.write_queue,
+                "Using SQLite registries"
+            );
+            let pool = sqlite::connect(&path.to_string_lossy(), pool_config).await?;
+            migrate_database(&pool).await?;
+            let readings = SqliteReadingRegistry::with_pool(pool.clone())
+                .await?
+                .with_writer(pool_config.write_queue);
+            let registries = SqliteRegistries {
+                devices: SqliteDeviceRegistry::with_pool(pool.clone()).await?,
+                dispatchers: SqliteDispatcherRegistry::with_pool(pool.clone()).await?,
+                statuses: SqliteDeviceStatusRegistry::with_pool(pool.clone())
+                    .await?
+                    .sharing_writer(&readings),
+                ingest: SqliteIngestRegistry::with_pool(pool.clone())
+                    .await?
+                    .sharing_writer(&readings),
+                readings,
+                dispatcher_statuses: InMemoryDispatcherStatusRegistry::new(),
+                aggregates: SqliteAggregateRegistry::with_pool(pool.clone()).await?,
+                derived_metrics: SqliteDerivedMetricRegistry::with_pool(pool.clone()).await?,
+                commands: SqliteCommandRegistry::with_pool(pool.clone()).await?,
+                irrigation: SqliteIrrigationRegistry::with_pool(pool.clone()).await?,
+                corrections: SqliteCorrectionRegistry::with_pool(pool.clone()).await?,
+                dead_letters: SqliteDeadLetterRegistry::with_pool(pool.clone()).await?,
+                audit: SqliteAuditRegistry::with_pool(pool.clone()).await?,
+                webhooks: SqliteWebhookRegistry::with_pool(pool.clone()).await?,
+                contacts: SqliteContactRegistry::with_pool(pool.clone()).await?,
+                outbox: SqliteOutboxRegistry::with_pool(pool.clone()).await?,
+                groups: SqliteGroupRegistry::with_pool(pool.clone()).await?,
+                validation_rules: SqliteValidationRuleRegistry::with_pool(pool.clone()).await?,
+                firmware: SqliteFirmwareRegistry::with_pool(pool.clone()).await?,
+                orgs: SqliteOrgRegistry::with_pool(pool.clone()).await?,
+                api_keys: SqliteApiKeyRegistry::with_pool(pool.clone()).await?,
+                users: SqliteUserRegistry::with_pool(pool.clone()).await?,
+            };
+            let backups = Backups::new(config.backup.clone(), Some(pool));
+            run(registries, config, tuning, backups, capture).await?;
         }
     }
 
     Ok(())
 }
 
-async fn run_server<R>(
-    registry: R,
-    rpc_addr: SocketAddr,
-    http_addr: SocketAddr,
-) -> color_eyre::Result<()>
-where
-    R: DispatcherRegistry,
-{
-    let state = AppState {
-        dispatcher_registry: registry,
+/// Connect to the SQLite database the configuration names.
+async fn open_database(config: &Config) -> color_eyre::Result<SqlitePool> {
+    let RegistryConfig::Sqlite { path, pool } = &config.registry else {
+        color_eyre::eyre::bail!("registries aren't kept in SQLite, there is no database");
     };
 
-    let cancel = CancellationToken::new();
-
-    let rpc_listener = TcpListener::bind(rpc_addr).await?;
-    info!(%rpc_addr, "RPC server listening");
-
-    let rpc_server = Server::new(rpc_listener, state).on_hello(
-        |hello: HelloRequest, _msg_id, _rpc, state: &AppState<R>| {
-            let dispatcher_registry = state.dispatcher_registry.clone();
-            async move {
-                info!(
-                    dispatcher_id = ?hello.dispatcher_id,
-                    location = ?hello.location,
-                    "received hello request"
-                );
-
-                let dispatcher = Dispatcher {
-                    id: hello.dispatcher_id,
-                    location: hello.location,
-                    state: DispatcherState::Active,
-                    provisioned_at: jiff::Timestamp::now(),
-                };
-
-                if let Err(e) = dispatcher_registry.register(dispatcher).await {
-                    tracing::error!(error = ?e, "failed to register dispatcher");
-                } else {
-                    info!(dispatcher_id = ?hello.dispatcher_id, "dispatcher registered");
-                }
-
-                HelloResponse {
-                    dispatcher_id: hello.dispatcher_id,
-                }
-            }
-        },
-    );
+    Ok(sqlite::connect(&path.to_string_lossy(), pool).await?)
+}
 
-    let axum_app = Router::new().route("/health", get(health_handler));
+/// Apply pending migrations, refusing a database migrated by a newer prime.
+async fn migrate_database(pool: &SqlitePool) -> color_eyre::Result<()> {
+    let applied = sqlite::migrate(pool).await?;
+    for migration in &applied {
+        info!(
+            version = migration.version,
+            description = %migration.description,
+            "Applied database migration"
+        );
+    }
+    info!(
+        version = SchemaStatus::binary_version(),
+        applied = applied.len(),
+        "Database schema is current"
+    );
 
-    let axum_listener = TcpListener::bind(http_addr).await?;
-    info!(%http_addr, "HTTP server listening");
+    Ok(())
+}
 
-    let cancel_clone = cancel.clone();
-    tokio::select! {
-        _ = rpc_server.serve(cancel.clone()) => {
-            info!("RPC server shut down");
-        }
-        result = axum::serve(axum_listener, axum_app).with_graceful_shutdown(async move {
-            cancel_clone.cancelled().await;
-        }) => {
-            if let Err(e) = result {
-                tracing::error!(error = ?e, "HTTP server error");
-            }
-            info!("HTTP server shut down");
-        }
-        _ = tokio::signal::ctrl_c() => {
-            info!("Received Ctrl+C, shutting down...");
-            cancel.cancel();
+fn print_schema_status(status: &SchemaStatus) {
+    let binary = SchemaStatus::binary_version();
+    match status.database_version() {
+        Some(database) => {
+            println!("database at version {database}, ersha-prime knows up to {binary}")
         }
+        None => println!("database not migrated yet, ersha-prime knows up to {binary}"),
     }
 
-    Ok(())
+    for migration in &status.applied {
+        let state = match (migration.success, migration.known) {
+            (false, _) => "failed",
+            (true, false) if migration.version > binary => "newer",
+            (true, false) => "modified",
+            (true, true) => "applied",
+        };
+        println!(
+            "{state:<8} {:03} {} ({})",
+            migration.version, migration.description, migration.installed_on
+        );
+    }
+    for migration in &status.pending {
+        println!(
+            "{:<8} {:03} {}",
+            "pending", migration.version, migration.description
+        );
+    }
+
+    if let Err(e) = status.check() {
+        println!("\n{e}");
+    }
+}
+
+/// Serve `registries`, behind the device and latest value caches when they
+/// are enabled.
+async fn run<R: Registries>(
+    registries: R,
+    config: Config,
+    tuning: Tuning,
+    backups: Backups,
+    capture: Option<CaptureWriter>,
+) -> color_eyre::Result<()> {
+    if config.cache.enabled {
+        info!("Caching devices and latest readings and statuses");
+        run_server(
+            CachedRegistries::new(registries),
+            config,
+            tuning,
+            backups,
+            capture,
+        )
+        .await
+    } else {
+        run_server(registries, config, tuning, backups, capture).await
+    }
 }
 
-async fn health_handler() -> &'static str {
-    "OK"
+async fn run_server<R: Registries>(
+    registries: R,
+    config: Config,
+    tuning: Tuning,
+    backups: Backups,
+    capture: Option<CaptureWriter>,
+) -> color_eyre::Result<()> {
+    let mut server = PrimeServer::new(registries, config)
+        .with_tuning(tuning)
+        .with_backups(backups)
+        .with_metrics(metrics::install()?)
+        .with_signal_handling();
+    if let Some(capture) = capture {
+        server = server.with_capture(capture);
+    }
+
+    server.start().await?.serve().await;
+
+    Ok(())
 }
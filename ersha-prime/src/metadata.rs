@@ -0,0 +1,130 @@
+//! Free-form metadata on devices.
+//!
+//! Metadata is a JSON object of whatever an organization wants to keep about
+//! its devices, such as the crop a field grows or who installed a probe.
+//! An organization may pin down its shape with a JSON Schema, which every
+//! write of a device's metadata is then checked against. Devices are found
+//! by metadata with `metadata.<key>=<value>` filters on its top-level keys.
+
+use serde_json::Value;
+
+/// Longest metadata accepted, serialized, in bytes.
+pub const MAX_METADATA_BYTES: usize = 16 * 1024;
+
+/// Why metadata, or a schema for it, was refused.
+#[derive(Debug, thiserror::Error)]
+pub enum MetadataError {
+    #[error("metadata must be a JSON object")]
+    NotAnObject,
+    #[error("metadata is larger than {MAX_METADATA_BYTES} bytes")]
+    TooLarge,
+    #[error("invalid metadata schema: {0}")]
+    InvalidSchema(String),
+    #[error("metadata doesn't match the organization's schema: {0}")]
+    Rejected(String),
+    #[error("invalid metadata key '{0}'")]
+    InvalidKey(String),
+}
+
+/// Check that `metadata` is an object of acceptable size and, when the
+/// organization has a schema, that it matches it.
+pub fn validate(metadata: &Value, schema: Option<&Value>) -> Result<(), MetadataError> {
+    if !metadata.is_object() {
+        return Err(MetadataError::NotAnObject);
+    }
+    if metadata.to_string().len() > MAX_METADATA_BYTES {
+        return Err(MetadataError::TooLarge);
+    }
+
+    if let Some(schema) = schema {
+        compile(schema)?
+            .validate(metadata)
+            .map_err(|error| MetadataError::Rejected(error.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Check that `schema` is a JSON Schema metadata can be validated against.
+pub fn check_schema(schema: &Value) -> Result<(), MetadataError> {
+    compile(schema).map(drop)
+}
+
+fn compile(schema: &Value) -> Result<jsonschema::Validator, MetadataError> {
+    jsonschema::validator_for(schema)
+        .map_err(|error| MetadataError::InvalidSchema(error.to_string()))
+}
+
+/// Parse one `metadata.<key>=<value>` filter.
+///
+/// Keys are limited to letters, digits, `_` and `-`. Values that parse as
+/// JSON, such as numbers and booleans, match that JSON value; anything else
+/// matches a string.
+pub fn parse_filter(key: &str, value: &str) -> Result<(String, Value), MetadataError> {
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(MetadataError::InvalidKey(key.to_owned()));
+    }
+
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()));
+
+    Ok((key.to_owned(), value))
+}
+
+/// Whether `metadata` has each of `filters`' keys set to its value.
+pub fn matches(metadata: Option<&Value>, filters: &[(String, Value)]) -> bool {
+    filters
+        .iter()
+        .all(|(key, value)| metadata.and_then(|metadata| metadata.get(key)) == Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{MetadataError, matches, parse_filter, validate};
+
+    #[test]
+    fn metadata_is_checked_against_the_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "crop": { "type": "string" } },
+            "required": ["crop"],
+        });
+
+        assert!(validate(&json!({ "crop": "maize" }), Some(&schema)).is_ok());
+        assert!(matches!(
+            validate(&json!({ "crop": 4 }), Some(&schema)),
+            Err(MetadataError::Rejected(_))
+        ));
+        assert!(matches!(
+            validate(&json!(["maize"]), None),
+            Err(MetadataError::NotAnObject)
+        ));
+        assert!(matches!(
+            validate(&json!({}), Some(&json!({ "type": 7 }))),
+            Err(MetadataError::InvalidSchema(_))
+        ));
+    }
+
+    #[test]
+    fn filters_match_json_values_or_strings() {
+        let metadata = json!({ "crop": "maize", "rows": 12, "irrigated": true });
+
+        let filters = [
+            parse_filter("crop", "maize").unwrap(),
+            parse_filter("rows", "12").unwrap(),
+            parse_filter("irrigated", "true").unwrap(),
+        ];
+        assert!(matches(Some(&metadata), &filters));
+        assert!(!matches(
+            Some(&metadata),
+            &[parse_filter("rows", "\"12\"").unwrap()]
+        ));
+        assert!(!matches(None, &filters));
+        assert!(parse_filter("crop.name", "maize").is_err());
+    }
+}
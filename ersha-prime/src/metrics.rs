@@ -0,0 +1,483 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use ersha_core::{
+    BatchUploadResponse, CommandPollResponse, DeviceDisconnectionResponse, DispatcherId,
+    DispatcherStatusResponse, HelloResponse, ItemOutcome, ReadingId,
+};
+use ersha_rpc::{Outcome, RpcMetrics};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder, PrometheusHandle};
+
+use crate::backfill::ItemCounts;
+use crate::config::QuotaAction;
+use crate::egress::{EgressOutcome, Stream};
+use crate::notify::Channel;
+use crate::outbox::OutboxState;
+use crate::timeseries::SinkOutcome;
+use crate::validation::QualityStatus;
+use crate::webhook::DeliveryState;
+
+pub const RPC_REQUESTS: &str = "ersha_prime_rpc_requests_total";
+pub const RPC_DURATION: &str = "ersha_prime_rpc_request_duration_seconds";
+pub const RPC_HANDLED: &str = "ersha_prime_rpc_handled_total";
+pub const RPC_CALLS: &str = "ersha_prime_rpc_calls_total";
+pub const RPC_CALL_DURATION: &str = "ersha_prime_rpc_call_duration_seconds";
+pub const BATCH_ITEMS: &str = "ersha_prime_batch_items";
+pub const READINGS_INGESTED: &str = "ersha_prime_readings_ingested_total";
+pub const REGISTRY_DURATION: &str = "ersha_prime_registry_duration_seconds";
+pub const RPC_CONNECTIONS: &str = "ersha_prime_rpc_connections";
+pub const RPC_QUEUED: &str = "ersha_prime_rpc_queued_messages";
+pub const RPC_REFUSED: &str = "ersha_prime_rpc_connections_refused_total";
+pub const HTTP_REQUESTS: &str = "ersha_prime_http_requests_total";
+pub const HTTP_DURATION: &str = "ersha_prime_http_request_duration_seconds";
+pub const RETENTION_PURGED: &str = "ersha_prime_retention_purged_total";
+pub const WEBHOOK_DELIVERIES: &str = "ersha_prime_webhook_delivery_attempts_total";
+pub const NOTIFICATIONS: &str = "ersha_prime_notification_attempts_total";
+pub const OUTBOX_RELAYS: &str = "ersha_prime_outbox_relay_attempts_total";
+pub const COMMANDS_DELIVERED: &str = "ersha_prime_commands_delivered_total";
+pub const MEMORY_EVICTIONS: &str = "ersha_prime_memory_evictions_total";
+pub const MEMORY_ENTRIES: &str = "ersha_prime_memory_entries";
+pub const CACHE_LOOKUPS: &str = "ersha_prime_cache_lookups_total";
+pub const QUOTA_EXCESS: &str = "ersha_prime_quota_excess_readings_total";
+pub const DEAD_LETTERS: &str = "ersha_prime_dead_letters_total";
+pub const BACKFILL_ITEMS: &str = "ersha_prime_backfill_items_total";
+pub const BUS_EVENTS: &str = "ersha_prime_bus_events_total";
+pub const BUS_EVENTS_MISSED: &str = "ersha_prime_bus_events_missed_total";
+pub const EGRESS_RECORDS: &str = "ersha_prime_egress_records_total";
+pub const EGRESS_QUEUED: &str = "ersha_prime_egress_queued_records";
+pub const EGRESS_LAG: &str = "ersha_prime_egress_lag_seconds";
+pub const TIMESERIES_READINGS: &str = "ersha_prime_timeseries_readings_total";
+pub const TIMESERIES_QUEUED: &str = "ersha_prime_timeseries_queued_readings";
+pub const READINGS_FLAGGED: &str = "ersha_prime_readings_flagged_total";
+
+/// Install the global Prometheus recorder. Render the returned handle on `/metrics`.
+pub fn install() -> Result<PrometheusHandle, BuildError> {
+    let handle = PrometheusBuilder::new().install_recorder()?;
+    describe();
+
+    Ok(handle)
+}
+
+fn describe() {
+    describe_counter!(
+        RPC_REQUESTS,
+        "RPC requests handled, by message type and outcome"
+    );
+    describe_histogram!(RPC_DURATION, "RPC request latency, by message type");
+    describe_counter!(
+        RPC_HANDLED,
+        "RPC requests answered or given up on, by message type and how they ended"
+    );
+    describe_counter!(
+        RPC_CALLS,
+        "RPC calls made to dispatchers, by message type and how they ended"
+    );
+    describe_histogram!(
+        RPC_CALL_DURATION,
+        "Latency of RPC calls made to dispatchers, by message type"
+    );
+    describe_histogram!(BATCH_ITEMS, "Items per uploaded batch, by item kind");
+    describe_counter!(READINGS_INGESTED, "Readings newly stored, by dispatcher");
+    describe_histogram!(REGISTRY_DURATION, "Latency of registry operations");
+    describe_gauge!(RPC_CONNECTIONS, "Open dispatcher RPC connections");
+    describe_gauge!(
+        RPC_QUEUED,
+        "Messages waiting to be written to dispatchers, across connections"
+    );
+    describe_counter!(
+        RPC_REFUSED,
+        "RPC connections closed on accept, their address having too many open"
+    );
+    describe_counter!(HTTP_REQUESTS, "HTTP requests, by route and status code");
+    describe_histogram!(HTTP_DURATION, "HTTP request latency, by route");
+    describe_counter!(RETENTION_PURGED, "Expired records purged, by kind");
+    describe_counter!(
+        WEBHOOK_DELIVERIES,
+        "Webhook delivery attempts, by resulting delivery state"
+    );
+    describe_counter!(
+        NOTIFICATIONS,
+        "SMS and Telegram notification attempts, by channel and resulting state"
+    );
+    describe_counter!(
+        OUTBOX_RELAYS,
+        "Attempts to relay outbox events to subscribers, by resulting state"
+    );
+    describe_counter!(COMMANDS_DELIVERED, "Device commands handed to dispatchers");
+    describe_counter!(
+        MEMORY_EVICTIONS,
+        "Entries dropped by in-memory registries to stay within their limits, by kind"
+    );
+    describe_gauge!(
+        MEMORY_ENTRIES,
+        "Entries held by in-memory registries, by kind"
+    );
+    describe_counter!(
+        CACHE_LOOKUPS,
+        "Lookups of cached devices and latest values, by cache and hit or miss"
+    );
+    describe_counter!(
+        QUOTA_EXCESS,
+        "Readings in batches over a dispatcher's hourly quota, by dispatcher and whether refused or flagged"
+    );
+    describe_counter!(DEAD_LETTERS, "Batch items refused and kept for re-driving");
+    describe_counter!(
+        BACKFILL_ITEMS,
+        "Items of historical uploads processed, by kind and whether stored, duplicate or refused"
+    );
+    describe_counter!(
+        BUS_EVENTS,
+        "Events published on the internal event bus, by kind"
+    );
+    describe_counter!(
+        BUS_EVENTS_MISSED,
+        "Events lossy subscribers fell too far behind to receive, by subscriber"
+    );
+    describe_counter!(
+        EGRESS_RECORDS,
+        "Records for the egress broker, by stream and whether published, retried, failed or dropped"
+    );
+    describe_gauge!(
+        EGRESS_QUEUED,
+        "Records waiting to be published to the egress broker"
+    );
+    describe_gauge!(
+        EGRESS_LAG,
+        "Seconds between ingesting the last record published to the egress broker and publishing it"
+    );
+    describe_counter!(
+        TIMESERIES_READINGS,
+        "Readings for the time-series sink, by whether written, retried, failed or dropped"
+    );
+    describe_gauge!(
+        TIMESERIES_QUEUED,
+        "Readings waiting to be written to the time-series sink"
+    );
+    describe_counter!(
+        READINGS_FLAGGED,
+        "Readings breaking a validation rule, by quality status"
+    );
+}
+
+pub fn record_hello(response: &HelloResponse) {
+    let outcome = match response {
+        HelloResponse::Accepted { .. } => "accepted",
+        HelloResponse::Rejected { .. } => "rejected",
+    };
+    counter!(RPC_REQUESTS, "message" => "hello", "outcome" => outcome).increment(1);
+}
+
+pub fn record_dispatcher_status(response: &DispatcherStatusResponse) {
+    let outcome = match response {
+        DispatcherStatusResponse::Accepted => "accepted",
+        DispatcherStatusResponse::Rejected { .. } => "rejected",
+    };
+    counter!(RPC_REQUESTS, "message" => "dispatcher_status", "outcome" => outcome).increment(1);
+}
+
+pub fn record_command_poll(response: &CommandPollResponse) {
+    let outcome = match response {
+        CommandPollResponse::Accepted { commands } => {
+            counter!(COMMANDS_DELIVERED).increment(commands.len() as u64);
+            "accepted"
+        }
+        CommandPollResponse::Rejected { .. } => "rejected",
+    };
+    counter!(RPC_REQUESTS, "message" => "command_poll", "outcome" => outcome).increment(1);
+}
+
+pub fn record_device_disconnection(response: &DeviceDisconnectionResponse) {
+    let outcome = match response {
+        DeviceDisconnectionResponse::Accepted => "accepted",
+        DeviceDisconnectionResponse::Rejected { .. } => "rejected",
+    };
+    counter!(RPC_REQUESTS, "message" => "device_disconnection", "outcome" => outcome).increment(1);
+}
+
+/// Record a batch answered from the response to its first attempt.
+pub fn record_retried_batch() {
+    counter!(RPC_REQUESTS, "message" => "batch_upload", "outcome" => "retried").increment(1);
+}
+
+/// Record a processed batch of `readings` and `statuses` items.
+pub fn record_batch(
+    dispatcher_id: DispatcherId,
+    readings: usize,
+    statuses: usize,
+    response: &BatchUploadResponse,
+) {
+    let (outcome, readings_stored) = match response {
+        BatchUploadResponse::Accepted { readings, .. } => (
+            "accepted",
+            readings
+                .iter()
+                .filter(|r| r.outcome == ItemOutcome::Stored)
+                .count(),
+        ),
+        BatchUploadResponse::Rejected { .. } => ("rejected", 0),
+    };
+    counter!(RPC_REQUESTS, "message" => "batch_upload", "outcome" => outcome).increment(1);
+
+    histogram!(BATCH_ITEMS, "kind" => "readings").record(readings as f64);
+    histogram!(BATCH_ITEMS, "kind" => "statuses").record(statuses as f64);
+
+    counter!(READINGS_INGESTED, "dispatcher_id" => dispatcher_id.0.to_string())
+        .increment(readings_stored as u64);
+}
+
+pub fn record_purged(kind: &'static str, purged: usize) {
+    counter!(RETENTION_PURGED, "kind" => kind).increment(purged as u64);
+}
+
+/// Record the size of a bounded in-memory registry after a write.
+pub fn record_memory_stats(kind: &'static str, entries: usize, evicted: usize) {
+    gauge!(MEMORY_ENTRIES, "kind" => kind).set(entries as f64);
+    if evicted > 0 {
+        counter!(MEMORY_EVICTIONS, "kind" => kind).increment(evicted as u64);
+    }
+}
+
+pub fn record_cache_lookup(cache: &'static str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    counter!(CACHE_LOOKUPS, "cache" => cache, "result" => result).increment(1);
+}
+
+/// Record a batch over its dispatcher's quota. Refused batches never get a
+/// response, so they are also counted as requests here.
+pub fn record_quota_excess(dispatcher_id: DispatcherId, readings: usize, action: QuotaAction) {
+    let action = match action {
+        QuotaAction::Reject => {
+            counter!(RPC_REQUESTS, "message" => "batch_upload", "outcome" => "over_quota")
+                .increment(1);
+            "rejected"
+        }
+        QuotaAction::Flag => "flagged",
+    };
+    counter!(QUOTA_EXCESS, "dispatcher_id" => dispatcher_id.0.to_string(), "action" => action)
+        .increment(readings as u64);
+}
+
+/// Count items refused from a batch and kept as dead letters.
+pub fn record_dead_letters(count: usize) {
+    counter!(DEAD_LETTERS).increment(count as u64);
+}
+
+pub fn record_backfilled(kind: &'static str, counts: &ItemCounts) {
+    for (outcome, count) in [
+        ("stored", counts.stored),
+        ("duplicate", counts.duplicate),
+        ("refused", counts.refused),
+    ] {
+        counter!(BACKFILL_ITEMS, "kind" => kind, "outcome" => outcome).increment(count as u64);
+    }
+}
+
+pub fn record_bus_event(kind: &'static str) {
+    counter!(BUS_EVENTS, "kind" => kind).increment(1);
+}
+
+pub fn record_events_missed(subscriber: &'static str, missed: u64) {
+    counter!(BUS_EVENTS_MISSED, "subscriber" => subscriber).increment(missed);
+}
+
+pub fn record_egress(stream: Stream, outcome: EgressOutcome) {
+    let outcome = match outcome {
+        EgressOutcome::Published => "published",
+        EgressOutcome::Retried => "retried",
+        EgressOutcome::Failed => "failed",
+        EgressOutcome::Dropped => "dropped",
+    };
+    counter!(EGRESS_RECORDS, "stream" => stream.name(), "outcome" => outcome).increment(1);
+}
+
+pub fn set_egress_queued(queued: usize) {
+    gauge!(EGRESS_QUEUED).set(queued as f64);
+}
+
+pub fn set_egress_lag(lag: Duration) {
+    gauge!(EGRESS_LAG).set(lag.as_secs_f64());
+}
+
+pub fn record_timeseries(outcome: SinkOutcome, readings: usize) {
+    let outcome = match outcome {
+        SinkOutcome::Written => "written",
+        SinkOutcome::Retried => "retried",
+        SinkOutcome::Failed => "failed",
+        SinkOutcome::Dropped => "dropped",
+    };
+    counter!(TIMESERIES_READINGS, "outcome" => outcome).increment(readings as u64);
+}
+
+/// Count readings flagged by the validation rules.
+pub fn record_flagged(flagged: &[(ReadingId, QualityStatus)]) {
+    for (_, status) in flagged {
+        counter!(READINGS_FLAGGED, "status" => status.as_str()).increment(1);
+    }
+}
+
+pub fn set_timeseries_queued(queued: usize) {
+    gauge!(TIMESERIES_QUEUED).set(queued as f64);
+}
+
+pub fn record_webhook_delivery(state: DeliveryState) {
+    let state = match state {
+        DeliveryState::Pending => "retrying",
+        DeliveryState::Delivered => "delivered",
+        DeliveryState::Failed => "failed",
+    };
+    counter!(WEBHOOK_DELIVERIES, "state" => state).increment(1);
+}
+
+pub fn record_outbox(state: OutboxState) {
+    let state = match state {
+        OutboxState::Pending => "retrying",
+        OutboxState::Relayed => "relayed",
+        OutboxState::Poisoned => "poisoned",
+    };
+    counter!(OUTBOX_RELAYS, "state" => state).increment(1);
+}
+
+pub fn record_notification(channel: Channel, state: DeliveryState) {
+    let state = match state {
+        DeliveryState::Pending => "retrying",
+        DeliveryState::Delivered => "delivered",
+        DeliveryState::Failed => "failed",
+    };
+    counter!(NOTIFICATIONS, "channel" => channel.as_str(), "state" => state).increment(1);
+}
+
+pub fn set_rpc_connections(open: usize) {
+    gauge!(RPC_CONNECTIONS).set(open as f64);
+}
+
+pub fn set_rpc_queued(queued: usize) {
+    gauge!(RPC_QUEUED).set(queued as f64);
+}
+
+/// Time a registry call, labelled with `op` such as `readings.batch_store`.
+pub async fn timed<F: Future>(op: &'static str, future: F) -> F::Output {
+    let start = Instant::now();
+    let output = future.await;
+    histogram!(REGISTRY_DURATION, "op" => op).record(start.elapsed().as_secs_f64());
+
+    output
+}
+
+/// Records the RPC server's connections, requests and pushed calls.
+pub struct RpcRecorder;
+
+impl RpcMetrics for RpcRecorder {
+    fn connection_refused(&self) {
+        counter!(RPC_REFUSED).increment(1);
+    }
+
+    fn call_finished(&self, message: &'static str, outcome: &Outcome, elapsed: Duration) {
+        counter!(RPC_CALLS, "message" => message, "outcome" => outcome.label()).increment(1);
+        histogram!(RPC_CALL_DURATION, "message" => message).record(elapsed.as_secs_f64());
+    }
+
+    fn request_handled(&self, message: &'static str, outcome: &Outcome, elapsed: Duration) {
+        counter!(RPC_HANDLED, "message" => message, "outcome" => outcome.label()).increment(1);
+        histogram!(RPC_DURATION, "message" => message).record(elapsed.as_secs_f64());
+    }
+}
+
+/// Middleware counting HTTP requests by matched route and status code.
+pub async fn track_http(request: Request, next: Next) -> Response {
+    // Label by route template so ids in paths don't explode cardinality.
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_owned());
+    let method = request.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+
+    histogram!(HTTP_DURATION, "method" => method.clone(), "route" => route.clone())
+        .record(start.elapsed().as_secs_f64());
+    counter!(HTTP_REQUESTS, "method" => method, "route" => route, "status" => status).increment(1);
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ersha_core::{
+        BatchId, BatchUploadResponse, DispatcherId, ItemOutcome, ItemResult, ReadingId,
+    };
+    use ersha_rpc::{Outcome, RpcMetrics, WireErrorCode};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use ulid::Ulid;
+
+    use super::{RpcRecorder, record_batch, set_rpc_connections};
+
+    #[test]
+    fn batch_metrics_are_tagged_with_dispatcher() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let reading_id = ReadingId(Ulid::new());
+
+        metrics::with_local_recorder(&recorder, || {
+            let response = BatchUploadResponse::Accepted {
+                id: BatchId(Ulid::new()),
+                readings: Box::new([ItemResult {
+                    id: reading_id,
+                    outcome: ItemOutcome::Stored,
+                }]),
+                statuses: Box::new([]),
+            };
+            record_batch(dispatcher_id, 1, 0, &response);
+            set_rpc_connections(3);
+        });
+
+        let rendered = handle.render();
+        assert!(rendered.contains(&format!(
+            "ersha_prime_readings_ingested_total{{dispatcher_id=\"{}\"}} 1",
+            dispatcher_id.0
+        )));
+        assert!(rendered.contains(
+            "ersha_prime_rpc_requests_total{message=\"batch_upload\",outcome=\"accepted\"} 1"
+        ));
+        assert!(rendered.contains("ersha_prime_rpc_connections 3"));
+    }
+
+    #[test]
+    fn rpc_requests_are_counted_by_outcome() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            let elapsed = Duration::from_millis(5);
+            RpcRecorder.request_handled("HelloRequest", &Outcome::Ok, elapsed);
+            let limited = Outcome::Error(WireErrorCode::RateLimited);
+            RpcRecorder.request_handled("BatchUploadRequest", &limited, elapsed);
+            RpcRecorder.call_finished("CommandDispatchRequest", &Outcome::Timeout, elapsed);
+        });
+
+        let rendered = handle.render();
+        assert!(
+            rendered.contains(
+                "ersha_prime_rpc_handled_total{message=\"HelloRequest\",outcome=\"ok\"} 1"
+            )
+        );
+        assert!(rendered.contains(
+            "ersha_prime_rpc_handled_total{message=\"BatchUploadRequest\",outcome=\"error\"} 1"
+        ));
+        assert!(rendered.contains(
+            "ersha_prime_rpc_calls_total{message=\"CommandDispatchRequest\",outcome=\"timeout\"} 1"
+        ));
+        assert!(rendered.contains("ersha_prime_rpc_request_duration_seconds"));
+    }
+}
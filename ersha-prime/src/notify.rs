@@ -0,0 +1,670 @@
+//! Alerts sent to people by SMS and Telegram.
+//!
+//! A [`Contact`] is someone to notify, the channels to reach them on and the
+//! events they want. Each event becomes one [`Notification`] per channel,
+//! sent with retries like webhook deliveries. Notifications falling in a
+//! contact's quiet hours wait until the quiet hours end.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::config::{NotificationConfig, SmsConfig, TelegramConfig, WebhookConfig};
+use crate::metrics;
+use crate::org::OrgId;
+use crate::registry::{ContactRegistry, Registries};
+use crate::webhook::{self, DeliveryState, Direction, Event, EventKind, Threshold};
+
+/// Notifications attempted per poll of the worker.
+const NOTIFICATION_BATCH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct ContactId(pub Ulid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct NotificationId(pub Ulid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Sms,
+    Telegram,
+}
+
+impl Channel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channel::Sms => "sms",
+            Channel::Telegram => "telegram",
+        }
+    }
+}
+
+/// Where to reach a contact on a channel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ContactChannel {
+    pub channel: Channel,
+    /// Phone number in international format for `sms`, chat id for `telegram`
+    pub address: String,
+}
+
+impl ContactChannel {
+    /// Why the address can't be sent to on its channel, if it can't.
+    pub fn invalid(&self) -> Option<&'static str> {
+        match self.channel {
+            Channel::Sms => {
+                let digits = self.address.strip_prefix('+').unwrap_or_default();
+                let valid =
+                    (7..=15).contains(&digits.len()) && digits.bytes().all(|b| b.is_ascii_digit());
+                (!valid).then_some("sms numbers must look like +251911234567")
+            }
+            Channel::Telegram => {
+                let id = self.address.strip_prefix('-').unwrap_or(&self.address);
+                let valid = !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit());
+                (!valid).then_some("telegram addresses must be numeric chat ids")
+            }
+        }
+    }
+}
+
+/// Hours of the day a contact isn't notified, in the contact's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct QuietHours {
+    /// Hour quiet hours start, 0-23
+    pub from_hour: u8,
+    /// Hour quiet hours end, 0-23. Earlier than `from_hour` for quiet
+    /// hours spanning midnight.
+    pub until_hour: u8,
+    /// The contact's offset from UTC, e.g. 180 for East Africa Time
+    #[serde(default)]
+    pub utc_offset_minutes: i16,
+}
+
+impl QuietHours {
+    /// Why these quiet hours are unusable, if they are.
+    pub fn invalid(&self) -> Option<&'static str> {
+        if self.from_hour > 23 || self.until_hour > 23 {
+            Some("quiet hours must be between 0 and 23")
+        } else if self.utc_offset_minutes.abs() > 14 * 60 {
+            Some("utc_offset_minutes must be within 14 hours of UTC")
+        } else {
+            None
+        }
+    }
+
+    /// When quiet hours end, if `now` falls in them.
+    pub fn resumes_at(&self, now: Timestamp) -> Option<Timestamp> {
+        let offset = i64::from(self.utc_offset_minutes) * 60;
+        let local = now.as_second() + offset;
+        let hour = local.rem_euclid(86_400) / 3_600;
+        let (from, until) = (i64::from(self.from_hour), i64::from(self.until_hour));
+
+        let quiet = if from <= until {
+            from <= hour && hour < until
+        } else {
+            hour >= from || hour < until
+        };
+        if !quiet {
+            return None;
+        }
+
+        let mut resume = local - local.rem_euclid(86_400) + until * 3_600;
+        if resume <= local {
+            resume += 86_400;
+        }
+        Some(now + SignedDuration::from_secs(resume - local))
+    }
+}
+
+/// Someone notified of events by SMS or Telegram.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Contact {
+    pub id: ContactId,
+    pub name: String,
+    /// Every channel is notified of each event
+    pub channels: Vec<ContactChannel>,
+    pub events: Vec<EventKind>,
+    /// Checked against ingested readings when subscribed to `threshold_crossed`
+    pub thresholds: Vec<Threshold>,
+    pub quiet_hours: Option<QuietHours>,
+    /// Only this organization's events are sent. Platform-wide contacts
+    /// receive every event.
+    pub org_id: Option<OrgId>,
+    pub created_at: Timestamp,
+}
+
+impl Contact {
+    pub fn new(name: String, channels: Vec<ContactChannel>, events: Vec<EventKind>) -> Self {
+        Self {
+            id: ContactId(Ulid::new()),
+            name,
+            channels,
+            events,
+            thresholds: Vec::new(),
+            quiet_hours: None,
+            org_id: None,
+            created_at: Timestamp::now(),
+        }
+    }
+
+    pub fn subscribes_to(&self, kind: EventKind) -> bool {
+        self.events.contains(&kind)
+    }
+
+    /// Whether events concerning `org_id` may be sent to this contact.
+    pub fn receives(&self, org_id: Option<OrgId>) -> bool {
+        self.org_id.is_none() || self.org_id == org_id
+    }
+
+    /// `event` on its way to each of the contact's channels.
+    pub fn notify(&self, event: &Event) -> Vec<Notification> {
+        self.channels
+            .iter()
+            .map(|channel| Notification::new(self.id, channel.clone(), event.clone()))
+            .collect()
+    }
+}
+
+/// One event on its way to one of a contact's channels.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Notification {
+    pub id: NotificationId,
+    pub contact_id: ContactId,
+    pub channel: Channel,
+    pub address: String,
+    pub event: Event,
+    pub state: DeliveryState,
+    pub attempts: u32,
+    pub next_attempt_at: Timestamp,
+    pub last_attempt_at: Option<Timestamp>,
+    pub last_error: Option<String>,
+}
+
+impl Notification {
+    pub fn new(contact_id: ContactId, to: ContactChannel, event: Event) -> Self {
+        Self {
+            id: NotificationId(Ulid::new()),
+            contact_id,
+            channel: to.channel,
+            address: to.address,
+            next_attempt_at: event.occurred_at,
+            event,
+            state: DeliveryState::Pending,
+            attempts: 0,
+            last_attempt_at: None,
+            last_error: None,
+        }
+    }
+}
+
+/// Queue `event` for every contact subscribed to its kind that may receive
+/// its organization's events.
+///
+/// Returns how many notifications were queued.
+pub async fn emit<C: ContactRegistry>(contacts: &C, event: Event) -> Result<usize, C::Error> {
+    let notifications: Vec<Notification> = contacts
+        .list()
+        .await?
+        .iter()
+        .filter(|contact| contact.subscribes_to(event.kind) && contact.receives(event.org_id))
+        .flat_map(|contact| contact.notify(&event))
+        .collect();
+
+    let queued = notifications.len();
+    if queued > 0 {
+        contacts.enqueue(notifications).await?;
+    }
+
+    Ok(queued)
+}
+
+/// `event` as a line of text short enough for an SMS.
+pub fn message(event: &Event) -> String {
+    let data = &event.data;
+    let device = data["device_id"].as_str().unwrap_or("unknown");
+
+    match event.kind {
+        EventKind::DeviceRegistered => format!("Ersha: device {device} registered"),
+        EventKind::DeviceOffline => match data["last_seen"].as_str() {
+            Some(last_seen) => {
+                format!("Ersha: device {device} went offline, last seen {last_seen}")
+            }
+            None => format!("Ersha: device {device} went offline"),
+        },
+        EventKind::AlertRaised => match data["message"].as_str() {
+            Some(text) => format!("Ersha alert: {text}"),
+            None => "Ersha alert raised".to_owned(),
+        },
+        EventKind::ThresholdCrossed => {
+            let threshold: Option<Threshold> =
+                serde_json::from_value(data["threshold"].clone()).ok();
+            let value = data["value"].as_f64();
+            match (threshold, value) {
+                (Some(threshold), Some(value)) => {
+                    let direction = match threshold.direction {
+                        Direction::Above => "rose above",
+                        Direction::Below => "fell below",
+                    };
+                    format!(
+                        "Ersha: {:?} {direction} {} (now {value})",
+                        threshold.metric, threshold.value
+                    )
+                }
+                _ => "Ersha: a threshold was crossed".to_owned(),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("gateway answered {0}")]
+    Rejected(u16),
+}
+
+/// Sends text to an address on one channel.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, address: &str, text: &str) -> Result<(), NotifyError>;
+}
+
+/// Sends SMS through an HTTP gateway.
+pub struct SmsGateway {
+    client: reqwest::Client,
+    config: SmsConfig,
+}
+
+impl SmsGateway {
+    pub fn new(client: reqwest::Client, config: SmsConfig) -> Self {
+        Self { client, config }
+    }
+}
+
+#[async_trait]
+impl Notifier for SmsGateway {
+    async fn send(&self, address: &str, text: &str) -> Result<(), NotifyError> {
+        let mut body = serde_json::Map::new();
+        body.insert(self.config.to_field.clone(), address.into());
+        body.insert(self.config.text_field.clone(), text.into());
+        if let Some(sender) = &self.config.sender {
+            body.insert("from".to_owned(), sender.as_str().into());
+        }
+
+        let mut request = self.client.post(&self.config.url).json(&body);
+        if let Some(token) = &self.config.token {
+            request = request.bearer_auth(token);
+        }
+
+        check(request.send().await?.status())
+    }
+}
+
+/// Messages Telegram chats as a bot.
+pub struct TelegramBot {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl TelegramBot {
+    pub fn new(client: reqwest::Client, config: &TelegramConfig) -> Self {
+        Self {
+            client,
+            url: format!(
+                "{}/bot{}/sendMessage",
+                config.api_url.trim_end_matches('/'),
+                config.bot_token
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramBot {
+    async fn send(&self, address: &str, text: &str) -> Result<(), NotifyError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({ "chat_id": address, "text": text }))
+            .send()
+            .await?;
+
+        check(response.status())
+    }
+}
+
+fn check(status: reqwest::StatusCode) -> Result<(), NotifyError> {
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(NotifyError::Rejected(status.as_u16()))
+    }
+}
+
+/// The sender for each configured channel.
+#[derive(Clone, Default)]
+pub struct Notifiers {
+    senders: HashMap<Channel, Arc<dyn Notifier>>,
+}
+
+impl Notifiers {
+    pub fn from_config(config: &NotificationConfig, client: reqwest::Client) -> Self {
+        let mut notifiers = Self::default();
+        if let Some(sms) = &config.sms {
+            notifiers = notifiers.with(
+                Channel::Sms,
+                Arc::new(SmsGateway::new(client.clone(), sms.clone())),
+            );
+        }
+        if let Some(telegram) = &config.telegram {
+            notifiers = notifiers.with(
+                Channel::Telegram,
+                Arc::new(TelegramBot::new(client, telegram)),
+            );
+        }
+        notifiers
+    }
+
+    pub fn with(mut self, channel: Channel, notifier: Arc<dyn Notifier>) -> Self {
+        self.senders.insert(channel, notifier);
+        self
+    }
+
+    pub fn get(&self, channel: Channel) -> Option<&Arc<dyn Notifier>> {
+        self.senders.get(&channel)
+    }
+}
+
+/// Send due notifications every `poll_interval_secs` until cancelled.
+pub async fn run_deliveries<R: Registries>(
+    registries: R,
+    notifications: NotificationConfig,
+    config: WebhookConfig,
+    cancel: CancellationToken,
+) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            error!(error = %e, "failed to build notification HTTP client, notifications are disabled");
+            return;
+        }
+    };
+    let notifiers = Notifiers::from_config(&notifications, client);
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) =
+            deliver_due(registries.contacts(), &notifiers, &config, Timestamp::now()).await
+        {
+            error!(error = %e, "failed to process notifications");
+        }
+    }
+}
+
+/// Send every notification due at `now`, recording the outcome of each.
+/// Those falling in their contact's quiet hours are put off until the quiet
+/// hours end, without counting as an attempt.
+pub async fn deliver_due<C: ContactRegistry>(
+    contacts: &C,
+    notifiers: &Notifiers,
+    config: &WebhookConfig,
+    now: Timestamp,
+) -> Result<usize, C::Error> {
+    let due = contacts.due(now, NOTIFICATION_BATCH).await?;
+    let attempted = due.len();
+
+    for mut notification in due {
+        let Some(contact) = contacts.get(notification.contact_id).await? else {
+            continue;
+        };
+
+        if let Some(resumes_at) = contact.quiet_hours.and_then(|quiet| quiet.resumes_at(now)) {
+            notification.next_attempt_at = resumes_at;
+            contacts.update_notification(notification).await?;
+            continue;
+        }
+
+        let Some(notifier) = notifiers.get(notification.channel) else {
+            notification.state = DeliveryState::Failed;
+            notification.last_error = Some(format!(
+                "no {} sender is configured",
+                notification.channel.as_str()
+            ));
+            metrics::record_notification(notification.channel, notification.state);
+            contacts.update_notification(notification).await?;
+            continue;
+        };
+
+        let outcome = notifier
+            .send(&notification.address, &message(&notification.event))
+            .await;
+        notification.attempts += 1;
+        notification.last_attempt_at = Some(now);
+
+        match outcome {
+            Ok(()) => {
+                notification.state = DeliveryState::Delivered;
+                notification.last_error = None;
+            }
+            Err(e) => notification.last_error = Some(e.to_string()),
+        }
+
+        if notification.state == DeliveryState::Pending {
+            if notification.attempts >= config.max_attempts {
+                warn!(
+                    notification_id = ?notification.id,
+                    channel = notification.channel.as_str(),
+                    "giving up on notification"
+                );
+                notification.state = DeliveryState::Failed;
+            } else {
+                notification.next_attempt_at =
+                    now + webhook::backoff(config, notification.attempts);
+            }
+        }
+
+        metrics::record_notification(notification.channel, notification.state);
+        contacts.update_notification(notification).await?;
+    }
+
+    Ok(attempted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::{
+        Channel, Contact, ContactChannel, Notifier, Notifiers, NotifyError, QuietHours,
+        deliver_due, emit,
+    };
+    use crate::config::WebhookConfig;
+    use crate::registry::{ContactRegistry, memory::InMemoryContactRegistry};
+    use crate::webhook::{DeliveryState, Event, EventKind};
+
+    /// 2026-03-02T00:00:00Z, a Monday.
+    fn midnight() -> Timestamp {
+        "2026-03-02T00:00:00Z".parse().unwrap()
+    }
+
+    fn at_hour(hour: i64) -> Timestamp {
+        midnight() + SignedDuration::from_hours(hour)
+    }
+
+    #[test]
+    fn quiet_hours_span_midnight_in_local_time() {
+        // 21:00 to 06:00 East Africa Time, UTC+3.
+        let quiet = QuietHours {
+            from_hour: 21,
+            until_hour: 6,
+            utc_offset_minutes: 180,
+        };
+
+        // 17:00 UTC is 20:00 local.
+        assert_eq!(quiet.resumes_at(at_hour(17)), None);
+        // 18:00 UTC is 21:00 local; quiet until 06:00 local, 03:00 UTC.
+        assert_eq!(quiet.resumes_at(at_hour(18)), Some(at_hour(27)));
+        // 01:30 UTC is 04:30 local, still quiet.
+        let early = at_hour(1) + SignedDuration::from_mins(30);
+        assert_eq!(quiet.resumes_at(early), Some(at_hour(3)));
+        assert_eq!(quiet.resumes_at(at_hour(3)), None);
+
+        let never = QuietHours {
+            from_hour: 8,
+            until_hour: 8,
+            utc_offset_minutes: 0,
+        };
+        assert_eq!(never.resumes_at(at_hour(8)), None);
+    }
+
+    /// Fails the first `failures` sends, then records the rest.
+    #[derive(Default)]
+    struct Recorder {
+        failures: Mutex<usize>,
+        sent: Mutex<Vec<(String, String)>>,
+    }
+
+    #[async_trait]
+    impl Notifier for Recorder {
+        async fn send(&self, address: &str, text: &str) -> Result<(), NotifyError> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(NotifyError::Rejected(502));
+            }
+            self.sent
+                .lock()
+                .unwrap()
+                .push((address.to_owned(), text.to_owned()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn notifications_wait_out_quiet_hours_and_are_retried() {
+        let contacts = InMemoryContactRegistry::new();
+        let mut contact = Contact::new(
+            "Abebe".to_owned(),
+            vec![
+                ContactChannel {
+                    channel: Channel::Sms,
+                    address: "+251911234567".to_owned(),
+                },
+                ContactChannel {
+                    channel: Channel::Telegram,
+                    address: "123456789".to_owned(),
+                },
+            ],
+            vec![EventKind::DeviceOffline],
+        );
+        contact.quiet_hours = Some(QuietHours {
+            from_hour: 22,
+            until_hour: 6,
+            utc_offset_minutes: 0,
+        });
+        contacts.create(contact.clone()).await.unwrap();
+
+        let mut event = Event::new(
+            EventKind::DeviceOffline,
+            serde_json::json!({ "device_id": "01ARZ3NDEKTSV4RRFFQ69G5FAV" }),
+        );
+        event.occurred_at = at_hour(-1);
+        assert_eq!(emit(&contacts, event).await.unwrap(), 2);
+        let ignored = Event::new(EventKind::AlertRaised, serde_json::json!({}));
+        assert_eq!(emit(&contacts, ignored).await.unwrap(), 0);
+
+        let sms = Arc::new(Recorder {
+            failures: Mutex::new(1),
+            ..Recorder::default()
+        });
+        // Telegram has no sender configured.
+        let notifiers = Notifiers::default().with(Channel::Sms, sms.clone());
+        let config = WebhookConfig {
+            initial_backoff_secs: 10,
+            ..WebhookConfig::default()
+        };
+
+        // Midnight is quiet: both wait until 06:00 without an attempt.
+        deliver_due(&contacts, &notifiers, &config, midnight())
+            .await
+            .unwrap();
+        let waiting = contacts.notifications(contact.id, 10).await.unwrap();
+        assert!(waiting.iter().all(|n| n.next_attempt_at == at_hour(6)));
+        assert!(waiting.iter().all(|n| n.attempts == 0));
+        assert_eq!(
+            deliver_due(&contacts, &notifiers, &config, at_hour(5))
+                .await
+                .unwrap(),
+            0
+        );
+
+        deliver_due(&contacts, &notifiers, &config, at_hour(6))
+            .await
+            .unwrap();
+        let by_channel = |channel| {
+            let contacts = contacts.clone();
+            async move {
+                contacts
+                    .notifications(contact.id, 10)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .find(|n| n.channel == channel)
+                    .unwrap()
+            }
+        };
+        let telegram = by_channel(Channel::Telegram).await;
+        assert_eq!(telegram.state, DeliveryState::Failed);
+        let retried = by_channel(Channel::Sms).await;
+        assert_eq!(retried.state, DeliveryState::Pending);
+        assert_eq!(
+            retried.next_attempt_at,
+            at_hour(6) + SignedDuration::from_secs(10)
+        );
+
+        deliver_due(
+            &contacts,
+            &notifiers,
+            &config,
+            at_hour(6) + SignedDuration::from_secs(10),
+        )
+        .await
+        .unwrap();
+        let delivered = by_channel(Channel::Sms).await;
+        assert_eq!(delivered.state, DeliveryState::Delivered);
+        assert_eq!(delivered.attempts, 2);
+
+        let sent = sms.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "+251911234567");
+        assert!(
+            sent[0]
+                .1
+                .contains("01ARZ3NDEKTSV4RRFFQ69G5FAV went offline")
+        );
+    }
+}
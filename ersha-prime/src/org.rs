@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+/// Unique identifier for an organization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct OrgId(pub Ulid);
+
+/// A tenant of the prime instance, typically a cooperative.
+///
+/// Dispatchers, devices, API keys and webhooks belong to at most one
+/// organization. Readings and statuses belong to the organization of the
+/// dispatcher that uploaded them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Org {
+    pub id: OrgId,
+    pub name: String,
+    pub created_at: jiff::Timestamp,
+}
+
+impl Org {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            id: OrgId(Ulid::new()),
+            name: name.into(),
+            created_at: jiff::Timestamp::now(),
+        }
+    }
+}
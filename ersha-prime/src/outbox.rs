@@ -0,0 +1,261 @@
+//! Events staged for subscribers alongside the change that raised them.
+//!
+//! An event handed to webhooks and contacts only after its change is
+//! committed is lost if prime stops in between. Instead, registries stage
+//! the event in the outbox in the same transaction as the change, and
+//! [`run_relay`] later fans it out into webhook deliveries and contact
+//! notifications, which are retried on their own. Relaying is at least
+//! once: an entry that fails part way is relayed again in full, and
+//! subscribers can tell repeats apart by the event id.
+//!
+//! An entry that keeps failing, or whose event can't be read back, is
+//! poisoned: set aside with its last error instead of holding up the rest.
+
+use std::time::Duration;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+use crate::config::WebhookConfig;
+use crate::metrics;
+use crate::notify::{self, ContactId};
+use crate::registry::{
+    ContactRegistry, OutboxRegistry, Registries, RegistryError, WebhookRegistry,
+};
+use crate::webhook::{self, Delivery, Event, WebhookId, backoff};
+
+/// Entries relayed per poll of the worker.
+const RELAY_BATCH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxState {
+    /// Waiting to be relayed, or for a retry
+    Pending,
+    /// Queued for every subscriber
+    Relayed,
+    /// Gave up on, after the configured number of attempts or because the
+    /// stored event is unreadable
+    Poisoned,
+}
+
+/// The one subscriber an entry is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recipient {
+    Webhook(WebhookId),
+    Contact(ContactId),
+}
+
+/// One event in the outbox.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutboxEntry {
+    pub event: Event,
+    /// Relayed to this subscriber alone rather than to every one subscribed
+    /// to the event's kind, as for threshold crossings
+    pub recipient: Option<Recipient>,
+    pub state: OutboxState,
+    pub attempts: u32,
+    pub next_attempt_at: Timestamp,
+    pub last_error: Option<String>,
+}
+
+impl OutboxEntry {
+    pub fn new(event: Event) -> Self {
+        Self {
+            next_attempt_at: event.occurred_at,
+            event,
+            recipient: None,
+            state: OutboxState::Pending,
+            attempts: 0,
+            last_error: None,
+        }
+    }
+
+    /// An entry relayed to `recipient` only.
+    pub fn to(recipient: Recipient, event: Event) -> Self {
+        Self {
+            recipient: Some(recipient),
+            ..Self::new(event)
+        }
+    }
+
+    /// Record an attempt to relay the entry at `now`, scheduling a retry or
+    /// poisoning it once out of attempts if it failed.
+    fn record_attempt(
+        &mut self,
+        result: Result<(), RegistryError>,
+        config: &WebhookConfig,
+        now: Timestamp,
+    ) {
+        self.attempts += 1;
+        match result {
+            Ok(()) => {
+                self.state = OutboxState::Relayed;
+                self.last_error = None;
+            }
+            Err(e) => {
+                self.last_error = Some(e.to_string());
+                if self.attempts >= config.max_attempts {
+                    self.state = OutboxState::Poisoned;
+                } else {
+                    self.next_attempt_at = now + backoff(config, self.attempts);
+                }
+            }
+        }
+    }
+}
+
+/// Relay due entries every `poll_interval_secs` until cancelled. Retries
+/// follow the webhook backoff.
+pub async fn run_relay<R: Registries>(
+    registries: R,
+    config: WebhookConfig,
+    cancel: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) = relay_due(&registries, &config, Timestamp::now()).await {
+            error!(error = %e, "failed to relay outbox events");
+        }
+    }
+}
+
+/// Queue deliveries and notifications for every entry due at `now`,
+/// recording the outcome of each.
+pub async fn relay_due<R: Registries>(
+    registries: &R,
+    config: &WebhookConfig,
+    now: Timestamp,
+) -> Result<usize, RegistryError> {
+    let outbox = registries.outbox();
+    let due = outbox.due(now, RELAY_BATCH).await.map_err(Into::into)?;
+    let attempted = due.len();
+
+    for mut entry in due {
+        let relayed = relay(registries, &entry).await;
+        entry.record_attempt(relayed, config, now);
+        if entry.state == OutboxState::Poisoned {
+            warn!(
+                event_id = ?entry.event.id,
+                kind = entry.event.kind.as_str(),
+                error = entry.last_error.as_deref().unwrap_or_default(),
+                "poisoning outbox event"
+            );
+        }
+
+        metrics::record_outbox(entry.state);
+        outbox.update(entry).await.map_err(Into::into)?;
+    }
+
+    Ok(attempted)
+}
+
+async fn relay<R: Registries>(registries: &R, entry: &OutboxEntry) -> Result<(), RegistryError> {
+    let event = &entry.event;
+    match entry.recipient {
+        None => {
+            notify::emit(registries.contacts(), event.clone())
+                .await
+                .map_err(Into::into)?;
+            webhook::emit(registries.webhooks(), event.clone())
+                .await
+                .map_err(Into::into)?;
+        }
+        // A subscriber removed since is skipped.
+        Some(Recipient::Webhook(id)) => {
+            let webhooks = registries.webhooks();
+            if webhooks.get(id).await.map_err(Into::into)?.is_some() {
+                webhooks
+                    .enqueue(vec![Delivery::new(id, event.clone())])
+                    .await
+                    .map_err(Into::into)?;
+            }
+        }
+        Some(Recipient::Contact(id)) => {
+            let contacts = registries.contacts();
+            if let Some(contact) = contacts.get(id).await.map_err(Into::into)? {
+                contacts
+                    .enqueue(contact.notify(event))
+                    .await
+                    .map_err(Into::into)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::{OutboxEntry, OutboxState, relay_due};
+    use crate::config::WebhookConfig;
+    use crate::registry::{
+        OutboxRegistry, Registries, RegistryError, WebhookRegistry, memory::InMemoryRegistries,
+    };
+    use crate::webhook::{Event, EventKind, Webhook};
+
+    #[tokio::test]
+    async fn staged_events_are_relayed_once() {
+        let registries = InMemoryRegistries::default();
+        let webhook = Webhook::generate(
+            "http://localhost/hook".to_owned(),
+            vec![EventKind::DeviceOffline],
+            vec![],
+        );
+        registries.webhooks().create(webhook.clone()).await.unwrap();
+
+        let event = Event::new(EventKind::DeviceOffline, serde_json::json!({}));
+        registries
+            .outbox()
+            .stage(vec![OutboxEntry::new(event.clone())])
+            .await
+            .unwrap();
+
+        let config = WebhookConfig::default();
+        let now = Timestamp::now() + SignedDuration::from_secs(1);
+        assert_eq!(relay_due(&registries, &config, now).await.unwrap(), 1);
+        assert_eq!(relay_due(&registries, &config, now).await.unwrap(), 0);
+
+        let deliveries = registries
+            .webhooks()
+            .deliveries(webhook.id, 10)
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event, event);
+        assert!(registries.outbox().poisoned(10).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn entries_out_of_attempts_are_poisoned() {
+        let config = WebhookConfig {
+            max_attempts: 2,
+            initial_backoff_secs: 10,
+            ..WebhookConfig::default()
+        };
+        let now = Timestamp::now();
+        let mut entry = OutboxEntry::new(Event::new(EventKind::AlertRaised, serde_json::json!({})));
+
+        entry.record_attempt(Err(RegistryError::NotFound), &config, now);
+        assert_eq!(entry.state, OutboxState::Pending);
+        assert_eq!(entry.next_attempt_at, now + SignedDuration::from_secs(10));
+
+        entry.record_attempt(Err(RegistryError::NotFound), &config, now);
+        assert_eq!(entry.state, OutboxState::Poisoned);
+        assert_eq!(entry.attempts, 2);
+        assert!(entry.last_error.is_some());
+    }
+}
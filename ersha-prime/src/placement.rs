@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// How a device is installed, beyond the H3 cell it sits in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Placement {
+    /// Name of the farm, plot or field the device is installed at
+    pub site: Option<String>,
+    /// Depth of soil probes below the surface, in centimetres
+    pub depth_cm: Option<u32>,
+    pub notes: Option<String>,
+}
+
+/// When a device or dispatcher was last changed. Strictly increases with
+/// every change, so it doubles as the revision for optimistic concurrency.
+pub fn next_update(previous: Option<jiff::Timestamp>) -> jiff::Timestamp {
+    let now = jiff::Timestamp::now();
+    let after = previous.and_then(|previous| {
+        previous
+            .checked_add(jiff::SignedDuration::from_nanos(1))
+            .ok()
+    });
+
+    after.map_or(now, |after| after.max(now))
+}
@@ -0,0 +1,259 @@
+//! Data quality of a device's readings over a time range.
+//!
+//! Each sensor is measured against the cadence it is expected to report at:
+//! how many readings arrived compared to how many should have, where the
+//! longest silences were, and how confident the readings were. Readings are
+//! classed as suspect or bad by their confidence.
+
+use ersha_core::{DeviceId, Percentage, SensorId};
+use jiff::{SignedDuration, Timestamp};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::config::QualityConfig;
+
+/// Longest gaps reported per sensor.
+pub const MAX_GAPS: usize = 3;
+
+/// Time range examined and the cadence readings are expected at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityWindow {
+    pub from: Timestamp,
+    pub to: Timestamp,
+    pub interval: SignedDuration,
+    /// Confidence below which a reading is suspect
+    pub suspect_below: Percentage,
+    /// Confidence below which a reading is bad
+    pub bad_below: Percentage,
+}
+
+impl QualityWindow {
+    pub fn new(from: Timestamp, to: Timestamp, interval: SignedDuration) -> Self {
+        let defaults = QualityConfig::default();
+
+        Self {
+            from,
+            to,
+            interval,
+            suspect_below: defaults.suspect_below_percent,
+            bad_below: defaults.bad_below_percent,
+        }
+    }
+
+    pub fn with_thresholds(mut self, suspect_below: Percentage, bad_below: Percentage) -> Self {
+        self.suspect_below = suspect_below;
+        self.bad_below = bad_below;
+        self
+    }
+
+    /// Readings a sensor reporting at the expected cadence sends in the window.
+    pub fn expected(&self) -> u64 {
+        let span = self.to.duration_since(self.from).as_secs().max(0);
+        let interval = self.interval.as_secs().max(1);
+
+        (span / interval) as u64
+    }
+
+    pub fn contains(&self, timestamp: Timestamp) -> bool {
+        self.from <= timestamp && timestamp <= self.to
+    }
+}
+
+/// A span without readings, longer than the expected interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Gap {
+    pub from: Timestamp,
+    pub to: Timestamp,
+    pub duration_secs: i64,
+}
+
+/// Nearest-rank percentiles of reading confidence, in percent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ConfidencePercentiles {
+    pub p5: u8,
+    pub p25: u8,
+    pub p50: u8,
+    pub p75: u8,
+    pub p95: u8,
+}
+
+impl ConfidencePercentiles {
+    /// Percentiles of `sorted`, which must be in ascending order.
+    fn of_sorted(sorted: &[u8]) -> Option<Self> {
+        if sorted.is_empty() {
+            return None;
+        }
+
+        let rank = |p: usize| {
+            let index = (p * sorted.len()).div_ceil(100).max(1) - 1;
+            sorted[index.min(sorted.len() - 1)]
+        };
+
+        Some(Self {
+            p5: rank(5),
+            p25: rank(25),
+            p50: rank(50),
+            p75: rank(75),
+            p95: rank(95),
+        })
+    }
+}
+
+/// Quality of one sensor's readings within a [`QualityWindow`].
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct SensorQuality {
+    pub sensor_id: SensorId,
+    pub expected: u64,
+    pub received: u64,
+    /// Received readings as a percentage of expected ones. Above 100 when
+    /// the sensor reports faster than expected.
+    pub adherence_percent: f64,
+    /// Longest gaps first, at most [`MAX_GAPS`]
+    pub longest_gaps: Vec<Gap>,
+    /// Readings with a confidence below the suspect threshold but not bad
+    pub suspect_percent: f64,
+    /// Readings with a confidence below the bad threshold
+    pub bad_percent: f64,
+    /// Absent when no readings were received
+    pub confidence: Option<ConfidencePercentiles>,
+}
+
+impl SensorQuality {
+    /// Assess a sensor from the timestamp and confidence of each of its
+    /// readings in the window, in any order.
+    pub fn compute(
+        sensor_id: SensorId,
+        window: &QualityWindow,
+        mut samples: Vec<(Timestamp, Percentage)>,
+    ) -> Self {
+        samples.sort_by_key(|(timestamp, _)| *timestamp);
+
+        let expected = window.expected();
+        let received = samples.len() as u64;
+        let percent_of = |count: usize, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                count as f64 * 100.0 / total as f64
+            }
+        };
+
+        let mut confidences: Vec<u8> = samples.iter().map(|(_, c)| c.0).collect();
+        confidences.sort_unstable();
+        let bad = confidences
+            .iter()
+            .filter(|c| **c < window.bad_below.0)
+            .count();
+        let suspect = confidences
+            .iter()
+            .filter(|c| **c < window.suspect_below.0)
+            .count()
+            - bad;
+
+        Self {
+            sensor_id,
+            expected,
+            received,
+            adherence_percent: percent_of(samples.len(), expected),
+            longest_gaps: longest_gaps(window, samples.iter().map(|(t, _)| *t)),
+            suspect_percent: percent_of(suspect, received),
+            bad_percent: percent_of(bad, received),
+            confidence: ConfidencePercentiles::of_sorted(&confidences),
+        }
+    }
+}
+
+/// The [`MAX_GAPS`] longest spans between the window edges and readings, at
+/// ascending `timestamps`, that exceed the expected interval.
+fn longest_gaps(window: &QualityWindow, timestamps: impl Iterator<Item = Timestamp>) -> Vec<Gap> {
+    let mut gaps = Vec::new();
+    let mut previous = window.from;
+    for timestamp in timestamps.chain([window.to]) {
+        let duration = timestamp.duration_since(previous);
+        if duration > window.interval {
+            gaps.push(Gap {
+                from: previous,
+                to: timestamp,
+                duration_secs: duration.as_secs(),
+            });
+        }
+        previous = timestamp;
+    }
+
+    gaps.sort_by(|a, b| {
+        b.duration_secs
+            .cmp(&a.duration_secs)
+            .then(a.from.cmp(&b.from))
+    });
+    gaps.truncate(MAX_GAPS);
+
+    gaps
+}
+
+/// Data quality report of a device, one entry per sensor.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DataQuality {
+    pub device_id: DeviceId,
+    pub from: Timestamp,
+    pub to: Timestamp,
+    pub expected_interval_secs: i64,
+    pub sensors: Vec<SensorQuality>,
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{Percentage, SensorId};
+    use jiff::{SignedDuration, Timestamp};
+    use ulid::Ulid;
+
+    use super::{ConfidencePercentiles, QualityWindow, SensorQuality};
+
+    fn at(second: i64) -> Timestamp {
+        Timestamp::from_second(second).unwrap()
+    }
+
+    #[test]
+    fn cadence_gaps_and_confidence() {
+        let window = QualityWindow::new(at(0), at(600), SignedDuration::from_secs(60));
+        // Every minute, except for a silence from 240 to 480.
+        let samples = [0, 60, 120, 180, 240, 480, 540, 600]
+            .into_iter()
+            .zip([95, 90, 85, 70, 60, 40, 99, 100])
+            .map(|(second, confidence)| (at(second), Percentage(confidence)))
+            .collect();
+
+        let quality = SensorQuality::compute(SensorId(Ulid::new()), &window, samples);
+
+        assert_eq!(quality.expected, 10);
+        assert_eq!(quality.received, 8);
+        assert_eq!(quality.adherence_percent, 80.0);
+        assert_eq!(quality.longest_gaps.len(), 1);
+        assert_eq!(quality.longest_gaps[0].from, at(240));
+        assert_eq!(quality.longest_gaps[0].duration_secs, 240);
+        assert_eq!(quality.suspect_percent, 25.0);
+        assert_eq!(quality.bad_percent, 12.5);
+        assert_eq!(
+            quality.confidence,
+            Some(ConfidencePercentiles {
+                p5: 40,
+                p25: 60,
+                p50: 85,
+                p75: 95,
+                p95: 100,
+            })
+        );
+    }
+
+    #[test]
+    fn silent_sensor_is_one_gap() {
+        let window = QualityWindow::new(at(0), at(3600), SignedDuration::from_secs(300));
+
+        let quality = SensorQuality::compute(SensorId(Ulid::new()), &window, Vec::new());
+
+        assert_eq!(quality.expected, 12);
+        assert_eq!(quality.received, 0);
+        assert_eq!(quality.adherence_percent, 0.0);
+        assert_eq!(quality.longest_gaps[0].duration_secs, 3600);
+        assert_eq!(quality.confidence, None);
+    }
+}
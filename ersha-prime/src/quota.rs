@@ -0,0 +1,215 @@
+//! Hourly ingest quotas for dispatchers.
+//!
+//! Readings are counted per dispatcher per hour of prime's clock, as batches
+//! arrive. A gateway replaying months of stored data shows up as a
+//! dispatcher far over its quota rather than as a flood of readings.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use ersha_core::DispatcherId;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::config::{QuotaAction, QuotaConfig};
+
+const HOUR_SECONDS: i64 = 60 * 60;
+
+/// Whether a batch fits its dispatcher's quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Within the quota, or the dispatcher has none
+    Within,
+    /// Over the quota of `limit` readings per hour, and stored regardless
+    Flagged { limit: u64 },
+    /// Over the quota of `limit` readings per hour, and refused
+    Rejected { limit: u64 },
+}
+
+/// Ingest of a dispatcher that went over its quota this hour.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct QuotaUsage {
+    pub dispatcher_id: DispatcherId,
+    /// Start of the hour being counted
+    pub hour: jiff::Timestamp,
+    /// Readings allowed per hour
+    pub limit: u64,
+    /// Readings accepted this hour
+    pub accepted: u64,
+    /// Readings over the quota this hour, refused or flagged
+    pub excess: u64,
+    #[schema(value_type = String)]
+    pub action: QuotaAction,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    accepted: u64,
+    excess: u64,
+}
+
+#[derive(Debug, Default)]
+struct HourlyUsage {
+    /// Hours since the epoch
+    hour: i64,
+    dispatchers: HashMap<DispatcherId, Usage>,
+}
+
+/// Readings uploaded by each dispatcher in the current hour, checked
+/// against the configured quotas. The default limits nothing.
+#[derive(Clone, Default)]
+pub struct IngestQuotas {
+    config: Arc<QuotaConfig>,
+    usage: Arc<Mutex<HourlyUsage>>,
+}
+
+impl IngestQuotas {
+    pub fn new(config: QuotaConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            usage: Arc::new(Mutex::new(HourlyUsage::default())),
+        }
+    }
+
+    /// Charge a batch of `readings` to `dispatcher_id` at `now`.
+    ///
+    /// Refused batches are not counted as accepted, so a dispatcher retrying
+    /// them gets through once the hour turns.
+    pub fn charge(
+        &self,
+        dispatcher_id: DispatcherId,
+        readings: usize,
+        now: jiff::Timestamp,
+    ) -> Admission {
+        let Some(limit) = self.config.limit(dispatcher_id) else {
+            return Admission::Within;
+        };
+
+        let mut usage = self.current(now);
+        let usage = usage.dispatchers.entry(dispatcher_id).or_default();
+        let readings = readings as u64;
+        let total = usage.accepted + readings;
+        if total <= limit {
+            usage.accepted = total;
+            return Admission::Within;
+        }
+
+        match self.config.action {
+            QuotaAction::Reject => {
+                usage.excess += readings;
+                Admission::Rejected { limit }
+            }
+            QuotaAction::Flag => {
+                usage.excess += total - usage.accepted.max(limit);
+                usage.accepted = total;
+                Admission::Flagged { limit }
+            }
+        }
+    }
+
+    /// Dispatchers over their quota in the hour of `now`, most excess first.
+    pub fn over_quota(&self, now: jiff::Timestamp) -> Vec<QuotaUsage> {
+        let usage = self.current(now);
+        let hour = jiff::Timestamp::from_second(usage.hour * HOUR_SECONDS)
+            .expect("hour derived from a valid timestamp");
+
+        let mut over: Vec<_> = usage
+            .dispatchers
+            .iter()
+            .filter(|(_, usage)| usage.excess > 0)
+            .filter_map(|(&dispatcher_id, usage)| {
+                Some(QuotaUsage {
+                    dispatcher_id,
+                    hour,
+                    limit: self.config.limit(dispatcher_id)?,
+                    accepted: usage.accepted,
+                    excess: usage.excess,
+                    action: self.config.action,
+                })
+            })
+            .collect();
+        over.sort_by_key(|usage| Reverse(usage.excess));
+
+        over
+    }
+
+    /// Usage for the hour of `now`, starting over when the hour has turned.
+    fn current(&self, now: jiff::Timestamp) -> std::sync::MutexGuard<'_, HourlyUsage> {
+        let hour = now.as_second().div_euclid(HOUR_SECONDS);
+        let mut usage = self.usage.lock().expect("quota lock poisoned");
+        if hour > usage.hour {
+            *usage = HourlyUsage {
+                hour,
+                dispatchers: HashMap::new(),
+            };
+        }
+
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ersha_core::DispatcherId;
+    use ulid::Ulid;
+
+    use super::{Admission, IngestQuotas};
+    use crate::config::{QuotaAction, QuotaConfig};
+
+    fn at(second: i64) -> jiff::Timestamp {
+        jiff::Timestamp::from_second(second).unwrap()
+    }
+
+    #[test]
+    fn excess_is_refused_until_the_hour_turns() {
+        let busy = DispatcherId(Ulid::new());
+        let trusted = DispatcherId(Ulid::new());
+        let quotas = IngestQuotas::new(QuotaConfig {
+            readings_per_hour: Some(100),
+            dispatchers: HashMap::from([(trusted, 1000)]),
+            action: QuotaAction::Reject,
+        });
+
+        assert_eq!(quotas.charge(busy, 60, at(0)), Admission::Within);
+        assert_eq!(
+            quotas.charge(busy, 60, at(10)),
+            Admission::Rejected { limit: 100 }
+        );
+        assert_eq!(quotas.charge(busy, 40, at(20)), Admission::Within);
+        assert_eq!(quotas.charge(trusted, 500, at(30)), Admission::Within);
+
+        let over = quotas.over_quota(at(40));
+        assert_eq!(over.len(), 1);
+        assert_eq!(over[0].dispatcher_id, busy);
+        assert_eq!((over[0].accepted, over[0].excess), (100, 60));
+
+        assert_eq!(quotas.charge(busy, 60, at(3600)), Admission::Within);
+        assert!(quotas.over_quota(at(3600)).is_empty());
+    }
+
+    #[test]
+    fn flagged_batches_are_accepted() {
+        let dispatcher = DispatcherId(Ulid::new());
+        let quotas = IngestQuotas::new(QuotaConfig {
+            readings_per_hour: Some(100),
+            dispatchers: HashMap::new(),
+            action: QuotaAction::Flag,
+        });
+
+        assert_eq!(quotas.charge(dispatcher, 80, at(0)), Admission::Within);
+        assert_eq!(
+            quotas.charge(dispatcher, 50, at(1)),
+            Admission::Flagged { limit: 100 }
+        );
+        assert_eq!(
+            quotas.charge(dispatcher, 50, at(2)),
+            Admission::Flagged { limit: 100 }
+        );
+
+        let over = quotas.over_quota(at(3));
+        assert_eq!((over[0].accepted, over[0].excess), (180, 80));
+    }
+}
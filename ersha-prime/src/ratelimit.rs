@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use ersha_rpc::TokenBucket;
+use tokio::sync::watch;
+
+use crate::api::ApiError;
+use crate::auth::{ApiKeyId, Principal};
+use crate::tuning::{Tunables, Tuning};
+
+/// HTTP rate limiting with a separate token bucket per API key.
+///
+/// The quota follows the [`Tuning`]; when it changes, every key starts over
+/// with a full bucket.
+#[derive(Clone)]
+pub struct KeyRateLimiter {
+    tunables: watch::Receiver<Tunables>,
+    buckets: Arc<Mutex<HashMap<ApiKeyId, TokenBucket>>>,
+}
+
+impl KeyRateLimiter {
+    pub fn new(tuning: &Tuning) -> Self {
+        Self {
+            tunables: tuning.subscribe(),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Charge one request to `key`, or return how long it must wait.
+    pub fn check(&self, key: ApiKeyId) -> Result<(), Duration> {
+        let Some(quota) = self.tunables.borrow().rate_limit.http() else {
+            return Ok(());
+        };
+
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        let bucket = buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(quota));
+        if bucket.quota() != quota {
+            *bucket = TokenBucket::new(quota);
+        }
+
+        bucket.try_take(1)
+    }
+}
+
+/// Middleware rejecting requests over their key's quota with 429.
+///
+/// Runs after [`authenticate`](crate::auth::authenticate), so every request
+/// reaching it carries a [`Principal`].
+pub async fn limit(
+    State(limiter): State<KeyRateLimiter>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(key_id) = request
+        .extensions()
+        .get::<Principal>()
+        .map(|principal| principal.key_id)
+    else {
+        return Ok(next.run(request).await);
+    };
+
+    if let Err(retry_after) = limiter.check(key_id) {
+        tracing::warn!(?key_id, ?retry_after, "API key rate limited");
+        return Err(ApiError::RateLimited { retry_after });
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{http::StatusCode, response::IntoResponse};
+    use ersha_rpc::Quota;
+    use ulid::Ulid;
+
+    use super::KeyRateLimiter;
+    use crate::api::{ApiError, ErrorBody, PROBLEM_CONTENT_TYPE};
+    use crate::auth::ApiKeyId;
+    use crate::config::RateLimitConfig;
+    use crate::tuning::{Tunables, Tuning};
+
+    fn tuning(http: Quota) -> Tuning {
+        Tuning::new(Tunables {
+            rate_limit: RateLimitConfig {
+                http,
+                ..RateLimitConfig::default()
+            },
+            ..Tunables::default()
+        })
+    }
+
+    #[test]
+    fn keys_have_separate_buckets() {
+        let limiter = KeyRateLimiter::new(&tuning(Quota::new(0, 2)));
+        let busy = ApiKeyId(Ulid::new());
+        let quiet = ApiKeyId(Ulid::new());
+
+        assert!(limiter.check(busy).is_ok());
+        assert!(limiter.check(busy).is_ok());
+        assert!(limiter.check(busy).is_err());
+        assert!(limiter.check(quiet).is_ok());
+    }
+
+    #[test]
+    fn retuned_quotas_apply_to_existing_keys() {
+        let tuning = tuning(Quota::new(0, 1));
+        let limiter = KeyRateLimiter::new(&tuning);
+        let key = ApiKeyId(Ulid::new());
+
+        assert!(limiter.check(key).is_ok());
+        assert!(limiter.check(key).is_err());
+
+        let mut tunables = tuning.current();
+        tunables.rate_limit.http = Quota::new(0, 3);
+        tuning.apply(tunables.clone()).unwrap();
+        for _ in 0..3 {
+            assert!(limiter.check(key).is_ok());
+        }
+        assert!(limiter.check(key).is_err());
+
+        tunables.rate_limit.enabled = false;
+        tuning.apply(tunables).unwrap();
+        assert!(limiter.check(key).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_responses_say_when_to_retry() {
+        let response = ApiError::RateLimited {
+            retry_after: Duration::from_millis(1500),
+        }
+        .into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "2");
+        assert_eq!(response.headers()["content-type"], PROBLEM_CONTENT_TYPE);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let problem: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.status, 429);
+        assert_eq!(problem.code, "rate_limited");
+        assert_eq!(problem.kind, "urn:ersha:problem:rate_limited");
+    }
+}
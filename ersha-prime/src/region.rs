@@ -0,0 +1,125 @@
+//! H3 region lookups backed by h3o.
+
+use std::ops::RangeInclusive;
+
+use ersha_core::H3Cell;
+use h3o::{CellIndex, LatLng, Resolution};
+
+/// Parse a hex H3 index, rejecting anything that is not a valid cell.
+pub fn parse_cell(s: &str) -> Option<H3Cell> {
+    let index = u64::from_str_radix(s, 16).ok()?;
+    let cell = CellIndex::try_from(index).ok()?;
+
+    Some(H3Cell(cell.into()))
+}
+
+/// Whether `cell` is a valid H3 cell index.
+pub fn is_cell(cell: H3Cell) -> bool {
+    CellIndex::try_from(cell.0).is_ok()
+}
+
+/// The ancestor of `cell` at `resolution`, or the cell itself at its own
+/// resolution. `None` for invalid cells or resolutions finer than the cell.
+pub fn parent(cell: H3Cell, resolution: u8) -> Option<H3Cell> {
+    let cell = CellIndex::try_from(cell.0).ok()?;
+    let resolution = Resolution::try_from(resolution).ok()?;
+
+    cell.parent(resolution).map(|parent| H3Cell(parent.into()))
+}
+
+/// Centre of `cell` as `[longitude, latitude]` in degrees, the order GeoJSON
+/// uses. `None` for invalid cells.
+pub fn centroid(cell: H3Cell) -> Option<[f64; 2]> {
+    let cell = CellIndex::try_from(cell.0).ok()?;
+    let center = LatLng::from(cell);
+
+    Some([center.lng(), center.lat()])
+}
+
+/// Outline of `cell` as a closed ring of `[longitude, latitude]` points,
+/// counter-clockwise as GeoJSON expects. `None` for invalid cells.
+pub fn boundary(cell: H3Cell) -> Option<Vec<[f64; 2]>> {
+    let cell = CellIndex::try_from(cell.0).ok()?;
+    let mut ring: Vec<[f64; 2]> = cell
+        .boundary()
+        .iter()
+        .map(|vertex| [vertex.lng(), vertex.lat()])
+        .collect();
+    ring.push(*ring.first()?);
+
+    Some(ring)
+}
+
+/// Index ranges covering `cell` and every descendant, one range per resolution.
+///
+/// Children at a given resolution are contiguous in index order, so a region
+/// lookup becomes a few range scans instead of enumerating every child.
+pub fn descendant_ranges(cell: H3Cell) -> Vec<RangeInclusive<u64>> {
+    let Ok(parent) = CellIndex::try_from(cell.0) else {
+        return Vec::new();
+    };
+
+    Resolution::range(parent.resolution(), Resolution::Fifteen)
+        .filter_map(|resolution| {
+            let last = parent.children_count(resolution) - 1;
+            let first = parent.child_at(0, resolution)?;
+            let last = parent.child_at(last, resolution)?;
+
+            Some(u64::from(first)..=u64::from(last))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::H3Cell;
+
+    use super::{boundary, centroid, descendant_ranges, parent, parse_cell};
+
+    const CELL: H3Cell = H3Cell(0x8a2a1072b59ffff);
+    const PARENT: H3Cell = H3Cell(0x892a1072b5bffff);
+    const ELSEWHERE: H3Cell = H3Cell(0x8a2a1072b4a7fff);
+
+    #[test]
+    fn parses_valid_cells_only() {
+        assert_eq!(parse_cell("892a1072b5bffff"), Some(PARENT));
+        assert_eq!(parse_cell("1337deadbeef"), None);
+        assert_eq!(parse_cell("not-hex"), None);
+    }
+
+    #[test]
+    fn parent_is_coarser_or_same() {
+        assert_eq!(parent(CELL, 9), Some(PARENT));
+        assert_eq!(parent(CELL, 10), Some(CELL));
+        assert_eq!(parent(PARENT, 10), None);
+    }
+
+    #[test]
+    fn ranges_cover_descendants() {
+        let ranges = descendant_ranges(PARENT);
+        let covered = |cell: H3Cell| ranges.iter().any(|range| range.contains(&cell.0));
+
+        assert_eq!(ranges.len(), 7);
+        assert!(covered(PARENT));
+        assert!(covered(CELL));
+        assert!(!covered(ELSEWHERE));
+        assert!(ranges.iter().all(|range| {
+            let (first, last) = (H3Cell(*range.start()), H3Cell(*range.end()));
+            first.is_within(PARENT) && last.is_within(PARENT)
+        }));
+    }
+
+    #[test]
+    fn outline_is_a_closed_ring_around_the_centre() {
+        let [lng, lat] = centroid(CELL).unwrap();
+        let ring = boundary(CELL).unwrap();
+
+        assert_eq!(ring.len(), 7);
+        assert_eq!(ring.first(), ring.last());
+        for [vertex_lng, vertex_lat] in &ring {
+            assert!((vertex_lng - lng).abs() < 0.01);
+            assert!((vertex_lat - lat).abs() < 0.01);
+        }
+        assert_eq!(centroid(H3Cell(0x1337deadbeef)), None);
+    }
+}
@@ -0,0 +1,815 @@
+//! Caching of devices and of the latest reading and status of each.
+//!
+//! [`CachedRegistries`] wraps another set of registries and answers device
+//! and "latest" lookups from memory. Storing readings or statuses for a
+//! device drops what is cached for it, and purges drop everything. Devices
+//! are dropped whenever they are registered or changed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ersha_core::{
+    Device, DeviceId, DeviceStatus, DispatcherId, ReadingId, Sensor, SensorReading, StatusId,
+};
+use tokio::sync::RwLock;
+
+use crate::metrics;
+use crate::org::OrgId;
+use crate::placement::Placement;
+use crate::quality::{QualityWindow, SensorQuality};
+use crate::registry::{
+    DeviceDetails, DeviceRegistry, DeviceStatusRegistry, IngestBatch, IngestRegistry, Ingested,
+    ReadingRegistry, Registries,
+    filter::{
+        DeviceFilter, DeviceSortBy, QueryOptions, ReadingFilter, ReadingSortBy, StatusFilter,
+        StatusSortBy,
+    },
+};
+use crate::validation::QualityStatus;
+use crate::webhook::Event;
+
+/// Devices whose generations are kept before they are all dropped at once.
+/// Generations only matter to lookups still in flight, which starting a new
+/// epoch turns away just the same.
+const MAX_GENERATIONS: usize = 10_000;
+
+/// Values by device.
+///
+/// Every invalidation bumps a generation, and a value looked up after a miss
+/// is only cached if no invalidation happened in between. A slow lookup
+/// racing an ingest therefore never caches what the ingest superseded.
+struct DeviceCache<V> {
+    name: &'static str,
+    state: Arc<RwLock<CacheState<V>>>,
+}
+
+struct CacheState<V> {
+    entries: HashMap<DeviceId, V>,
+    generations: HashMap<DeviceId, u64>,
+    /// Bumped when the whole cache or every generation is dropped
+    epoch: u64,
+}
+
+/// Generation of a device's entry when a lookup missed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Generation {
+    epoch: u64,
+    device: u64,
+}
+
+impl<V> CacheState<V> {
+    fn generation(&self, device: DeviceId) -> Generation {
+        Generation {
+            epoch: self.epoch,
+            device: self.generations.get(&device).copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl<V: Clone> DeviceCache<V> {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            state: Arc::new(RwLock::new(CacheState {
+                entries: HashMap::new(),
+                generations: HashMap::new(),
+                epoch: 0,
+            })),
+        }
+    }
+
+    /// The cached value, or the generation to fill it at on a miss.
+    async fn lookup(&self, device: DeviceId) -> Result<V, Generation> {
+        let state = self.state.read().await;
+        let found = state.entries.get(&device).cloned();
+        metrics::record_cache_lookup(self.name, found.is_some());
+
+        found.ok_or_else(|| state.generation(device))
+    }
+
+    async fn fill(&self, device: DeviceId, generation: Generation, value: V) {
+        let mut state = self.state.write().await;
+        if state.generation(device) == generation {
+            state.entries.insert(device, value);
+        }
+    }
+
+    async fn invalidate(&self, devices: impl IntoIterator<Item = DeviceId>) {
+        let mut state = self.state.write().await;
+        for device in devices {
+            state.entries.remove(&device);
+            *state.generations.entry(device).or_default() += 1;
+        }
+        if state.generations.len() > MAX_GENERATIONS {
+            state.generations.clear();
+            state.epoch += 1;
+        }
+    }
+
+    async fn clear(&self) {
+        let mut state = self.state.write().await;
+        state.entries.clear();
+        state.generations.clear();
+        state.epoch += 1;
+    }
+}
+
+impl<V> Clone for DeviceCache<V> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name,
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// A [`ReadingRegistry`] caching the latest reading per sensor of each device.
+#[derive(Clone)]
+pub struct CachedReadingRegistry<T> {
+    inner: T,
+    latest: DeviceCache<Vec<SensorReading>>,
+}
+
+impl<T: ReadingRegistry> CachedReadingRegistry<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            latest: DeviceCache::new("latest_readings"),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: ReadingRegistry> ReadingRegistry for CachedReadingRegistry<T> {
+    type Error = T::Error;
+
+    async fn store(&self, reading: SensorReading) -> Result<(), Self::Error> {
+        let device = reading.device_id;
+        let result = self.inner.store(reading).await;
+        self.latest.invalidate([device]).await;
+
+        result
+    }
+
+    async fn get(&self, id: ReadingId) -> Result<Option<SensorReading>, Self::Error> {
+        self.inner.get(id).await
+    }
+
+    async fn batch_store(
+        &self,
+        readings: Vec<SensorReading>,
+    ) -> Result<Vec<ReadingId>, Self::Error> {
+        let devices: Vec<_> = readings.iter().map(|reading| reading.device_id).collect();
+        let result = self.inner.batch_store(readings).await;
+        self.latest.invalidate(devices).await;
+
+        result
+    }
+
+    async fn latest_per_sensor(&self, device: DeviceId) -> Result<Vec<SensorReading>, Self::Error> {
+        let generation = match self.latest.lookup(device).await {
+            Ok(readings) => return Ok(readings),
+            Err(generation) => generation,
+        };
+
+        let readings = self.inner.latest_per_sensor(device).await?;
+        self.latest.fill(device, generation, readings.clone()).await;
+
+        Ok(readings)
+    }
+
+    async fn latest_matching(
+        &self,
+        filter: ReadingFilter,
+    ) -> Result<Vec<SensorReading>, Self::Error> {
+        self.inner.latest_matching(filter).await
+    }
+
+    async fn data_quality(
+        &self,
+        device: DeviceId,
+        window: QualityWindow,
+    ) -> Result<Vec<SensorQuality>, Self::Error> {
+        self.inner.data_quality(device, window).await
+    }
+
+    async fn purge(&self, before: jiff::Timestamp, limit: usize) -> Result<usize, Self::Error> {
+        let result = self.inner.purge(before, limit).await;
+        self.latest.clear().await;
+
+        result
+    }
+
+    async fn set_quality(
+        &self,
+        statuses: Vec<(ReadingId, QualityStatus)>,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_quality(statuses).await
+    }
+
+    async fn count(&self, filter: Option<ReadingFilter>) -> Result<usize, Self::Error> {
+        self.inner.count(filter).await
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<ReadingFilter, ReadingSortBy>,
+    ) -> Result<Vec<SensorReading>, Self::Error> {
+        self.inner.list(options).await
+    }
+}
+
+/// A [`DeviceStatusRegistry`] caching the latest status of each device.
+#[derive(Clone)]
+pub struct CachedStatusRegistry<T> {
+    inner: T,
+    latest: DeviceCache<Option<DeviceStatus>>,
+}
+
+impl<T: DeviceStatusRegistry> CachedStatusRegistry<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            latest: DeviceCache::new("latest_statuses"),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: DeviceStatusRegistry> DeviceStatusRegistry for CachedStatusRegistry<T> {
+    type Error = T::Error;
+
+    async fn store(&self, status: DeviceStatus) -> Result<(), Self::Error> {
+        let device = status.device_id;
+        let result = self.inner.store(status).await;
+        self.latest.invalidate([device]).await;
+
+        result
+    }
+
+    async fn get(&self, id: StatusId) -> Result<Option<DeviceStatus>, Self::Error> {
+        self.inner.get(id).await
+    }
+
+    async fn batch_store(&self, statuses: Vec<DeviceStatus>) -> Result<Vec<StatusId>, Self::Error> {
+        let devices: Vec<_> = statuses.iter().map(|status| status.device_id).collect();
+        let result = self.inner.batch_store(statuses).await;
+        self.latest.invalidate(devices).await;
+
+        result
+    }
+
+    async fn latest(&self, device: DeviceId) -> Result<Option<DeviceStatus>, Self::Error> {
+        let generation = match self.latest.lookup(device).await {
+            Ok(status) => return Ok(status),
+            Err(generation) => generation,
+        };
+
+        let status = self.inner.latest(device).await?;
+        self.latest.fill(device, generation, status.clone()).await;
+
+        Ok(status)
+    }
+
+    async fn purge(&self, before: jiff::Timestamp, limit: usize) -> Result<usize, Self::Error> {
+        let result = self.inner.purge(before, limit).await;
+        self.latest.clear().await;
+
+        result
+    }
+
+    async fn count(&self, filter: Option<StatusFilter>) -> Result<usize, Self::Error> {
+        self.inner.count(filter).await
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<StatusFilter, StatusSortBy>,
+    ) -> Result<Vec<DeviceStatus>, Self::Error> {
+        self.inner.list(options).await
+    }
+}
+
+/// An [`IngestRegistry`] dropping the latest readings and statuses cached
+/// for the devices of each upload.
+#[derive(Clone)]
+pub struct CachedIngestRegistry<T> {
+    inner: T,
+    readings: DeviceCache<Vec<SensorReading>>,
+    statuses: DeviceCache<Option<DeviceStatus>>,
+}
+
+#[async_trait]
+impl<T: IngestRegistry> IngestRegistry for CachedIngestRegistry<T> {
+    type Error = T::Error;
+
+    async fn ingest(&self, batch: IngestBatch) -> Result<Ingested, Self::Error> {
+        let readings: Vec<_> = batch.readings.iter().map(|r| r.device_id).collect();
+        let statuses: Vec<_> = batch.statuses.iter().map(|s| s.device_id).collect();
+        let result = self.inner.ingest(batch).await;
+        self.readings.invalidate(readings).await;
+        self.statuses.invalidate(statuses).await;
+
+        result
+    }
+}
+
+/// A [`DeviceRegistry`] caching devices and the dispatcher each is
+/// assigned to, which every ingested batch is checked against. Only what
+/// exists is cached, so uploads naming made-up devices can't fill the cache.
+#[derive(Clone)]
+pub struct CachedDeviceRegistry<T> {
+    inner: T,
+    devices: DeviceCache<Option<Device>>,
+    dispatchers: DeviceCache<Option<DispatcherId>>,
+}
+
+impl<T: DeviceRegistry> CachedDeviceRegistry<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            devices: DeviceCache::new("devices"),
+            dispatchers: DeviceCache::new("device_dispatchers"),
+        }
+    }
+
+    async fn invalidate(&self, devices: impl IntoIterator<Item = DeviceId> + Clone) {
+        self.devices.invalidate(devices.clone()).await;
+        self.dispatchers.invalidate(devices).await;
+    }
+}
+
+#[async_trait]
+impl<T: DeviceRegistry> DeviceRegistry for CachedDeviceRegistry<T> {
+    type Error = T::Error;
+
+    async fn register(&self, device: Device) -> Result<(), Self::Error> {
+        let id = device.id;
+        let result = self.inner.register(device).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn register_new(&self, device: Device) -> Result<Option<Device>, Self::Error> {
+        let id = device.id;
+        let result = self.inner.register_new(device).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn get(&self, id: DeviceId) -> Result<Option<Device>, Self::Error> {
+        let generation = match self.devices.lookup(id).await {
+            Ok(device) => return Ok(device),
+            Err(generation) => generation,
+        };
+
+        let device = self.inner.get(id).await?;
+        if device.is_some() {
+            self.devices.fill(id, generation, device.clone()).await;
+        }
+
+        Ok(device)
+    }
+
+    async fn get_many(&self, ids: &[DeviceId]) -> Result<Vec<Device>, Self::Error> {
+        self.inner.get_many(ids).await
+    }
+
+    async fn update(
+        &self,
+        id: DeviceId,
+        new: Device,
+        placement: Placement,
+        expected: Option<jiff::Timestamp>,
+    ) -> Result<Option<jiff::Timestamp>, Self::Error> {
+        let result = self.inner.update(id, new, placement, expected).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn details(&self, id: DeviceId) -> Result<Option<DeviceDetails>, Self::Error> {
+        self.inner.details(id).await
+    }
+
+    async fn suspend(&self, id: DeviceId) -> Result<(), Self::Error> {
+        let result = self.inner.suspend(id).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn reactivate(&self, id: DeviceId) -> Result<(), Self::Error> {
+        let result = self.inner.reactivate(id).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn decommission(&self, id: DeviceId) -> Result<(), Self::Error> {
+        let result = self.inner.decommission(id).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn set_org(&self, id: DeviceId, org: Option<OrgId>) -> Result<(), Self::Error> {
+        self.inner.set_org(id, org).await
+    }
+
+    async fn org(&self, id: DeviceId) -> Result<Option<OrgId>, Self::Error> {
+        self.inner.org(id).await
+    }
+
+    async fn set_dispatcher(
+        &self,
+        id: DeviceId,
+        dispatcher: Option<DispatcherId>,
+    ) -> Result<(), Self::Error> {
+        let result = self.inner.set_dispatcher(id, dispatcher).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn dispatcher(&self, id: DeviceId) -> Result<Option<DispatcherId>, Self::Error> {
+        let generation = match self.dispatchers.lookup(id).await {
+            Ok(dispatcher) => return Ok(dispatcher),
+            Err(generation) => generation,
+        };
+
+        let dispatcher = self.inner.dispatcher(id).await?;
+        if dispatcher.is_some() {
+            self.dispatchers.fill(id, generation, dispatcher).await;
+        }
+
+        Ok(dispatcher)
+    }
+
+    async fn set_tags(&self, id: DeviceId, tags: Vec<String>) -> Result<(), Self::Error> {
+        self.inner.set_tags(id, tags).await
+    }
+
+    async fn tags(&self, id: DeviceId) -> Result<Vec<String>, Self::Error> {
+        self.inner.tags(id).await
+    }
+
+    async fn set_disconnected(
+        &self,
+        id: DeviceId,
+        since: Option<jiff::Timestamp>,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_disconnected(id, since).await
+    }
+
+    async fn mark_offline(
+        &self,
+        id: DeviceId,
+        since: jiff::Timestamp,
+        event: Event,
+    ) -> Result<(), Self::Error> {
+        self.inner.mark_offline(id, since, event).await
+    }
+
+    async fn disconnected(&self) -> Result<Vec<(DeviceId, jiff::Timestamp)>, Self::Error> {
+        self.inner.disconnected().await
+    }
+
+    async fn set_reporting_interval(
+        &self,
+        id: DeviceId,
+        interval_secs: Option<u64>,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_reporting_interval(id, interval_secs).await
+    }
+
+    async fn reporting_interval(&self, id: DeviceId) -> Result<Option<u64>, Self::Error> {
+        self.inner.reporting_interval(id).await
+    }
+
+    async fn reporting_intervals(&self) -> Result<Vec<(DeviceId, u64)>, Self::Error> {
+        self.inner.reporting_intervals().await
+    }
+
+    async fn set_hardware_rev(
+        &self,
+        id: DeviceId,
+        hardware_rev: Option<String>,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_hardware_rev(id, hardware_rev).await
+    }
+
+    async fn hardware_rev(&self, id: DeviceId) -> Result<Option<String>, Self::Error> {
+        self.inner.hardware_rev(id).await
+    }
+
+    async fn set_metadata(
+        &self,
+        id: DeviceId,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_metadata(id, metadata).await
+    }
+
+    async fn metadata(&self, id: DeviceId) -> Result<Option<serde_json::Value>, Self::Error> {
+        self.inner.metadata(id).await
+    }
+
+    async fn add_sensor(&self, id: DeviceId, sensor: Sensor) -> Result<(), Self::Error> {
+        let result = self.inner.add_sensor(id, sensor).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn add_sensors(
+        &self,
+        id: DeviceId,
+        sensors: impl Iterator<Item = Sensor> + Send,
+    ) -> Result<(), Self::Error> {
+        let result = self.inner.add_sensors(id, sensors).await;
+        self.invalidate([id]).await;
+
+        result
+    }
+
+    async fn batch_register(&self, devices: Vec<Device>) -> Result<(), Self::Error> {
+        let ids: Vec<_> = devices.iter().map(|device| device.id).collect();
+        let result = self.inner.batch_register(devices).await;
+        self.invalidate(ids).await;
+
+        result
+    }
+
+    async fn count(&self, filter: Option<DeviceFilter>) -> Result<usize, Self::Error> {
+        self.inner.count(filter).await
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<DeviceFilter, DeviceSortBy>,
+    ) -> Result<Vec<Device>, Self::Error> {
+        self.inner.list(options).await
+    }
+}
+
+/// Registries answering device, latest reading and status lookups from a
+/// cache.
+#[derive(Clone)]
+pub struct CachedRegistries<R: Registries> {
+    inner: R,
+    devices: CachedDeviceRegistry<R::Devices>,
+    readings: CachedReadingRegistry<R::Readings>,
+    statuses: CachedStatusRegistry<R::Statuses>,
+    ingest: CachedIngestRegistry<R::Ingest>,
+}
+
+impl<R: Registries> CachedRegistries<R> {
+    pub fn new(inner: R) -> Self {
+        let readings = CachedReadingRegistry::new(inner.readings().clone());
+        let statuses = CachedStatusRegistry::new(inner.statuses().clone());
+        let ingest = CachedIngestRegistry {
+            inner: inner.ingest().clone(),
+            readings: readings.latest.clone(),
+            statuses: statuses.latest.clone(),
+        };
+
+        Self {
+            devices: CachedDeviceRegistry::new(inner.devices().clone()),
+            readings,
+            statuses,
+            ingest,
+            inner,
+        }
+    }
+}
+
+impl<R: Registries> Registries for CachedRegistries<R> {
+    type Devices = CachedDeviceRegistry<R::Devices>;
+    type Dispatchers = R::Dispatchers;
+    type Readings = CachedReadingRegistry<R::Readings>;
+    type Statuses = CachedStatusRegistry<R::Statuses>;
+    type Ingest = CachedIngestRegistry<R::Ingest>;
+    type DispatcherStatuses = R::DispatcherStatuses;
+    type Aggregates = R::Aggregates;
+    type DerivedMetrics = R::DerivedMetrics;
+    type Commands = R::Commands;
+    type Irrigation = R::Irrigation;
+    type Corrections = R::Corrections;
+    type DeadLetters = R::DeadLetters;
+    type Audit = R::Audit;
+    type Webhooks = R::Webhooks;
+    type Contacts = R::Contacts;
+    type Outbox = R::Outbox;
+    type Groups = R::Groups;
+    type ValidationRules = R::ValidationRules;
+    type Firmware = R::Firmware;
+    type Orgs = R::Orgs;
+    type ApiKeys = R::ApiKeys;
+    type Users = R::Users;
+
+    fn devices(&self) -> &Self::Devices {
+        &self.devices
+    }
+
+    fn dispatchers(&self) -> &Self::Dispatchers {
+        self.inner.dispatchers()
+    }
+
+    fn readings(&self) -> &Self::Readings {
+        &self.readings
+    }
+
+    fn statuses(&self) -> &Self::Statuses {
+        &self.statuses
+    }
+
+    fn ingest(&self) -> &Self::Ingest {
+        &self.ingest
+    }
+
+    fn dispatcher_statuses(&self) -> &Self::DispatcherStatuses {
+        self.inner.dispatcher_statuses()
+    }
+
+    fn aggregates(&self) -> &Self::Aggregates {
+        self.inner.aggregates()
+    }
+
+    fn derived_metrics(&self) -> &Self::DerivedMetrics {
+        self.inner.derived_metrics()
+    }
+
+    fn commands(&self) -> &Self::Commands {
+        self.inner.commands()
+    }
+
+    fn irrigation(&self) -> &Self::Irrigation {
+        self.inner.irrigation()
+    }
+
+    fn corrections(&self) -> &Self::Corrections {
+        self.inner.corrections()
+    }
+
+    fn dead_letters(&self) -> &Self::DeadLetters {
+        self.inner.dead_letters()
+    }
+
+    fn audit(&self) -> &Self::Audit {
+        self.inner.audit()
+    }
+
+    fn webhooks(&self) -> &Self::Webhooks {
+        self.inner.webhooks()
+    }
+
+    fn contacts(&self) -> &Self::Contacts {
+        self.inner.contacts()
+    }
+
+    fn outbox(&self) -> &Self::Outbox {
+        self.inner.outbox()
+    }
+
+    fn groups(&self) -> &Self::Groups {
+        self.inner.groups()
+    }
+
+    fn validation_rules(&self) -> &Self::ValidationRules {
+        self.inner.validation_rules()
+    }
+
+    fn firmware(&self) -> &Self::Firmware {
+        self.inner.firmware()
+    }
+
+    fn orgs(&self) -> &Self::Orgs {
+        self.inner.orgs()
+    }
+
+    fn api_keys(&self) -> &Self::ApiKeys {
+        self.inner.api_keys()
+    }
+
+    fn users(&self) -> &Self::Users {
+        self.inner.users()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{Device, DeviceId, DeviceKind, DeviceState, H3Cell, SensorId, SensorReading};
+    use ulid::Ulid;
+
+    use super::{CachedDeviceRegistry, CachedReadingRegistry, DeviceCache, MAX_GENERATIONS};
+    use crate::fixtures;
+    use crate::registry::{
+        DeviceRegistry, ReadingRegistry,
+        memory::{InMemoryDeviceRegistry, InMemoryReadingRegistry},
+    };
+
+    fn reading(device_id: DeviceId, second: i64) -> SensorReading {
+        SensorReading {
+            device_id,
+            timestamp: jiff::Timestamp::from_second(second).unwrap(),
+            sensor_id: SensorId(Ulid::nil()),
+            ..fixtures::reading()
+        }
+    }
+
+    #[tokio::test]
+    async fn ingest_replaces_cached_latest_readings() {
+        let inner = InMemoryReadingRegistry::new();
+        let cached = CachedReadingRegistry::new(inner.clone());
+        let device = DeviceId(Ulid::new());
+
+        let first = reading(device, 0);
+        cached.batch_store(vec![first.clone()]).await.unwrap();
+        assert_eq!(
+            cached.latest_per_sensor(device).await.unwrap(),
+            vec![first.clone()]
+        );
+
+        // Served from the cache: bypassing it leaves the answer unchanged.
+        inner.store(reading(device, 60)).await.unwrap();
+        assert_eq!(cached.latest_per_sensor(device).await.unwrap(), [first]);
+
+        let newer = reading(device, 120);
+        cached.batch_store(vec![newer.clone()]).await.unwrap();
+        assert_eq!(cached.latest_per_sensor(device).await.unwrap(), [newer]);
+    }
+
+    #[tokio::test]
+    async fn only_known_devices_are_cached() {
+        let inner = InMemoryDeviceRegistry::new();
+        let cached = CachedDeviceRegistry::new(inner.clone());
+        let device = Device {
+            id: DeviceId(Ulid::new()),
+            kind: DeviceKind::Sensor,
+            state: DeviceState::Active,
+            location: H3Cell(0x8a2a1072b59ffff),
+            manufacturer: None,
+            provisioned_at: jiff::Timestamp::now(),
+            sensors: Box::new([]),
+        };
+        assert!(cached.get(device.id).await.unwrap().is_none());
+
+        // The absence wasn't cached, so registering behind the cache's back
+        // shows up right away.
+        inner.register(device.clone()).await.unwrap();
+        assert!(cached.get(device.id).await.unwrap().is_some());
+
+        // Served from the cache: bypassing it leaves the answer unchanged.
+        inner.decommission(device.id).await.unwrap();
+        let cached_state = cached.get(device.id).await.unwrap().unwrap().state;
+        assert_eq!(cached_state, DeviceState::Active);
+
+        cached.decommission(device.id).await.unwrap();
+        let retired = cached.get(device.id).await.unwrap().unwrap();
+        assert_eq!(retired.state, DeviceState::Decommissioned);
+    }
+
+    #[tokio::test]
+    async fn lookups_racing_an_invalidation_are_not_cached() {
+        let cache = DeviceCache::new("test");
+        let device = DeviceId(Ulid::new());
+
+        let generation = cache.lookup(device).await.unwrap_err();
+        cache.invalidate([device]).await;
+        cache.fill(device, generation, "stale").await;
+        assert!(cache.lookup(device).await.is_err());
+
+        let generation = cache.lookup(device).await.unwrap_err();
+        cache.clear().await;
+        cache.fill(device, generation, "stale").await;
+        let generation = cache.lookup(device).await.unwrap_err();
+
+        cache.fill(device, generation, "fresh").await;
+        assert_eq!(cache.lookup(device).await, Ok("fresh"));
+    }
+
+    #[tokio::test]
+    async fn generations_are_dropped_once_too_many() {
+        let cache = DeviceCache::new("test");
+        let device = DeviceId(Ulid::new());
+        let generation = cache.lookup(device).await.unwrap_err();
+
+        let others: Vec<_> = (0..=MAX_GENERATIONS)
+            .map(|_| DeviceId(Ulid::new()))
+            .collect();
+        cache.invalidate(others).await;
+        assert!(cache.state.read().await.generations.is_empty());
+
+        // Lookups from before still can't be cached.
+        cache.fill(device, generation, "stale").await;
+        assert!(cache.lookup(device).await.is_err());
+
+        cache.invalidate([device]).await;
+        cache.clear().await;
+        assert!(cache.state.read().await.generations.is_empty());
+    }
+}
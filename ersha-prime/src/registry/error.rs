@@ -0,0 +1,101 @@
+//! What went wrong in a registry, whatever its backend.
+//!
+//! Every registry error converts into [`RegistryError`], so callers decide
+//! what to do by matching a variant: the API answers `NotFound` with a 404
+//! and `Conflict` with a 409, and only `Backend` errors are logged as
+//! failures of prime itself.
+
+/// The kind of failure behind a registry error.
+#[derive(Debug, thiserror::Error)]
+pub enum RegistryError {
+    #[error("not found")]
+    NotFound,
+    /// The write clashes with what is already stored, such as a duplicate key
+    #[error("{0}")]
+    Conflict(String),
+    /// The request can't be stored as given
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error(transparent)]
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl RegistryError {
+    pub fn backend(error: impl std::error::Error + Send + Sync + 'static) -> Self {
+        RegistryError::Backend(Box::new(error))
+    }
+}
+
+impl From<sqlx::Error> for RegistryError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => RegistryError::NotFound,
+            // The driver message names tables and columns, so it stays out
+            // of what clients see
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                tracing::debug!(error = e.message(), "Unique constraint violated");
+                RegistryError::Conflict("already exists".to_owned())
+            }
+            sqlx::Error::Database(e) if e.is_check_violation() => {
+                tracing::debug!(error = e.message(), "Check constraint violated");
+                RegistryError::InvalidInput("invalid value".to_owned())
+            }
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::RegistryError;
+    use crate::registry::memory::InMemoryError;
+
+    #[tokio::test]
+    async fn duplicate_keys_are_conflicts() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE t (id TEXT PRIMARY KEY)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO t VALUES ('a')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let duplicate = sqlx::query("INSERT INTO t VALUES ('a')")
+            .execute(&pool)
+            .await
+            .unwrap_err();
+        let RegistryError::Conflict(message) = RegistryError::from(duplicate) else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(message, "already exists");
+
+        let missing = sqlx::query_scalar::<_, String>("SELECT id FROM t WHERE id = 'b'")
+            .fetch_one(&pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            RegistryError::from(missing),
+            RegistryError::NotFound
+        ));
+    }
+
+    #[test]
+    fn misses_and_backend_failures_keep_their_kind() {
+        assert!(matches!(
+            RegistryError::from(InMemoryError::NotFound),
+            RegistryError::NotFound
+        ));
+        assert!(matches!(
+            RegistryError::from(sqlx::Error::PoolClosed),
+            RegistryError::Backend(_)
+        ));
+    }
+}
@@ -1,9 +1,21 @@
-use ersha_core::{DeviceId, DeviceKind, DeviceState, DispatcherState, H3Cell};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ersha_core::{
+    Device, DeviceErrorCode, DeviceId, DeviceKind, DeviceState, DeviceStatus, Dispatcher,
+    DispatcherId, DispatcherState, H3Cell, InvalidItemReason, SensorId, SensorKind, SensorReading,
+};
 
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::auth::ApiKeyId;
+use crate::dead_letter::DeadLetterState;
+use crate::derived::IndicatorKind;
+use crate::org::OrgId;
+use crate::rollup::Granularity;
+use crate::validation::QualityStatus;
 use jiff;
 use std::ops::RangeInclusive;
 use ulid::Ulid;
 
+#[derive(Debug, Clone, Copy)]
 pub enum DeviceSortBy {
     State,
     Manufacturer,
@@ -11,10 +23,75 @@ pub enum DeviceSortBy {
     SensorCount,
 }
 
+impl DeviceSortBy {
+    /// Where `device` falls in the order, as carried by cursors.
+    pub fn cursor(&self, device: &Device) -> Cursor {
+        let key = match self {
+            DeviceSortBy::State => SortKey::Int(device.state.clone() as i64),
+            // Devices without a manufacturer sort as an empty one.
+            DeviceSortBy::Manufacturer => SortKey::Text(
+                device
+                    .manufacturer
+                    .as_deref()
+                    .unwrap_or_default()
+                    .to_owned(),
+            ),
+            DeviceSortBy::ProvisionAt => SortKey::Time(device.provisioned_at),
+            DeviceSortBy::SensorCount => SortKey::Int(device.sensors.len() as i64),
+        };
+
+        Cursor::new(key, device.id.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum DispatcherSortBy {
     ProvisionAt,
 }
 
+impl DispatcherSortBy {
+    pub fn cursor(&self, dispatcher: &Dispatcher) -> Cursor {
+        let key = match self {
+            DispatcherSortBy::ProvisionAt => SortKey::Time(dispatcher.provisioned_at),
+        };
+
+        Cursor::new(key, dispatcher.id.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReadingSortBy {
+    Timestamp,
+    Confidence,
+}
+
+impl ReadingSortBy {
+    pub fn cursor(&self, reading: &SensorReading) -> Cursor {
+        let key = match self {
+            ReadingSortBy::Timestamp => SortKey::Time(reading.timestamp),
+            ReadingSortBy::Confidence => SortKey::Int(reading.confidence.0.into()),
+        };
+
+        Cursor::new(key, reading.id.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum StatusSortBy {
+    Timestamp,
+}
+
+impl StatusSortBy {
+    pub fn cursor(&self, status: &DeviceStatus) -> Cursor {
+        let key = match self {
+            StatusSortBy::Timestamp => SortKey::Time(status.timestamp),
+        };
+
+        Cursor::new(key, status.id.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum SortOrder {
     Asc,
     Desc,
@@ -22,7 +99,78 @@ pub enum SortOrder {
 
 pub enum Pagination {
     Offset { offset: usize, limit: usize },
-    Cursor { after: Option<Ulid>, limit: usize },
+    Cursor { after: Option<Cursor>, limit: usize },
+}
+
+impl Pagination {
+    pub fn limit(&self) -> usize {
+        match self {
+            Pagination::Offset { limit, .. } | Pagination::Cursor { limit, .. } => *limit,
+        }
+    }
+}
+
+/// The value an item is sorted by.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortKey {
+    Time(jiff::Timestamp),
+    Int(i64),
+    Text(String),
+}
+
+/// Where a page ended: the sort key and id of its last item. The next page
+/// starts strictly after it, found by comparison rather than by looking the
+/// item up, so it holds even once that item is gone.
+///
+/// Ordering by key, then id, is the order lists are sorted in.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub key: SortKey,
+    pub id: Ulid,
+}
+
+impl Cursor {
+    pub fn new(key: SortKey, id: Ulid) -> Self {
+        Self { key, id }
+    }
+
+    /// An opaque, URL-safe token handed out as `next_cursor`.
+    pub fn encode(&self) -> String {
+        let key = match &self.key {
+            SortKey::Time(timestamp) => format!("t{}", timestamp.as_nanosecond()),
+            SortKey::Int(n) => format!("i{n}"),
+            SortKey::Text(text) => format!("s{text}"),
+        };
+
+        URL_SAFE_NO_PAD.encode(format!("{}.{key}", self.id))
+    }
+
+    /// The cursor behind a token from [`Cursor::encode`], or `None` if it
+    /// isn't one.
+    pub fn decode(token: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(token).ok()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        let (id, key) = decoded.split_once('.')?;
+
+        let key = match key.split_at_checked(1)? {
+            ("t", nanos) => {
+                SortKey::Time(jiff::Timestamp::from_nanosecond(nanos.parse().ok()?).ok()?)
+            }
+            ("i", n) => SortKey::Int(n.parse().ok()?),
+            ("s", text) => SortKey::Text(text.to_owned()),
+            _ => return None,
+        };
+
+        Some(Self::new(key, id.parse().ok()?))
+    }
+
+    /// Whether an item at `position` comes after the cursor in `order`.
+    pub fn precedes(&self, position: &Cursor, order: &SortOrder) -> bool {
+        match order {
+            SortOrder::Asc => position > self,
+            SortOrder::Desc => position < self,
+        }
+    }
 }
 
 pub struct QueryOptions<F, S> {
@@ -32,16 +180,29 @@ pub struct QueryOptions<F, S> {
     pub pagination: Pagination,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct DeviceFilter {
     pub ids: Option<Vec<DeviceId>>,
     pub states: Option<Vec<DeviceState>>,
     pub kinds: Option<Vec<DeviceKind>>,
     pub locations: Option<Vec<H3Cell>>,
+    /// Locations inside any of these cells, at any finer resolution
+    pub within: Option<Vec<H3Cell>>,
     pub provisioned_after: Option<jiff::Timestamp>,
     pub provisioned_before: Option<jiff::Timestamp>,
     pub sensor_count: Option<RangeInclusive<usize>>,
     pub manufacturer_pattern: Option<String>,
+    /// Only devices assigned to this organization
+    pub org_id: Option<OrgId>,
+    /// Only devices assigned to this dispatcher
+    pub dispatcher_id: Option<DispatcherId>,
+    /// Only devices carrying every one of these tags
+    pub tags: Option<Vec<String>>,
+    /// Only devices marked disconnected, or only those that aren't
+    pub disconnected: Option<bool>,
+    /// Only devices whose metadata sets each of these top-level keys to
+    /// the value given
+    pub metadata: Option<Vec<(String, serde_json::Value)>>,
 }
 
 impl DeviceFilter {
@@ -92,6 +253,14 @@ impl DeviceFilterBuilder {
         self
     }
 
+    pub fn within<I>(mut self, cells: I) -> Self
+    where
+        I: IntoIterator<Item = H3Cell>,
+    {
+        self.filter.within = Some(cells.into_iter().collect());
+        self
+    }
+
     pub fn provisioned_after(mut self, ts: jiff::Timestamp) -> Self {
         self.filter.provisioned_after = Some(ts);
         self
@@ -115,6 +284,37 @@ impl DeviceFilterBuilder {
         self
     }
 
+    pub fn org(mut self, org_id: OrgId) -> Self {
+        self.filter.org_id = Some(org_id);
+        self
+    }
+
+    pub fn dispatcher(mut self, dispatcher_id: DispatcherId) -> Self {
+        self.filter.dispatcher_id = Some(dispatcher_id);
+        self
+    }
+
+    pub fn tags<I>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.filter.tags = Some(tags.into_iter().collect());
+        self
+    }
+
+    pub fn disconnected(mut self, disconnected: bool) -> Self {
+        self.filter.disconnected = Some(disconnected);
+        self
+    }
+
+    pub fn metadata(mut self, key: impl Into<String>, value: serde_json::Value) -> Self {
+        self.filter
+            .metadata
+            .get_or_insert_with(Vec::new)
+            .push((key.into(), value));
+        self
+    }
+
     pub fn build(self) -> DeviceFilter {
         self.filter
     }
@@ -124,6 +324,10 @@ impl DeviceFilterBuilder {
 pub struct DispatcherFilter {
     pub states: Option<Vec<DispatcherState>>,
     pub locations: Option<Vec<H3Cell>>,
+    /// Only dispatchers assigned to this organization
+    pub org_id: Option<OrgId>,
+    /// Only dispatchers carrying every one of these tags
+    pub tags: Option<Vec<String>>,
 }
 
 impl DispatcherFilter {
@@ -158,7 +362,482 @@ impl DispatcherFilterBuilder {
         self
     }
 
+    pub fn org(mut self, org_id: OrgId) -> Self {
+        self.filter.org_id = Some(org_id);
+        self
+    }
+
+    pub fn tags<I>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.filter.tags = Some(tags.into_iter().collect());
+        self
+    }
+
     pub fn build(self) -> DispatcherFilter {
         self.filter
     }
 }
+
+#[derive(Default, Clone)]
+pub struct ReadingFilter {
+    pub device_ids: Option<Vec<DeviceId>>,
+    pub sensor_ids: Option<Vec<SensorId>>,
+    pub dispatcher_ids: Option<Vec<DispatcherId>>,
+    pub metric_kinds: Option<Vec<SensorKind>>,
+    pub locations: Option<Vec<H3Cell>>,
+    /// Locations inside any of these cells, at any finer resolution
+    pub within: Option<Vec<H3Cell>>,
+    pub after: Option<jiff::Timestamp>,
+    pub before: Option<jiff::Timestamp>,
+    pub confidence: Option<RangeInclusive<u8>>,
+    /// Values in their metric's unit; an infinite end leaves that side open
+    pub value_range: Option<RangeInclusive<f64>>,
+    pub quality: Option<Vec<QualityStatus>>,
+}
+
+impl ReadingFilter {
+    pub fn builder() -> ReadingFilterBuilder {
+        ReadingFilterBuilder::new()
+    }
+}
+
+#[derive(Default)]
+pub struct ReadingFilterBuilder {
+    filter: ReadingFilter,
+}
+
+impl ReadingFilterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn device_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = DeviceId>,
+    {
+        self.filter.device_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn sensor_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = SensorId>,
+    {
+        self.filter.sensor_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn dispatcher_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = DispatcherId>,
+    {
+        self.filter.dispatcher_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn metric_kinds<I>(mut self, kinds: I) -> Self
+    where
+        I: IntoIterator<Item = SensorKind>,
+    {
+        self.filter.metric_kinds = Some(kinds.into_iter().collect());
+        self
+    }
+
+    pub fn locations<I>(mut self, locations: I) -> Self
+    where
+        I: IntoIterator<Item = H3Cell>,
+    {
+        self.filter.locations = Some(locations.into_iter().collect());
+        self
+    }
+
+    pub fn within<I>(mut self, cells: I) -> Self
+    where
+        I: IntoIterator<Item = H3Cell>,
+    {
+        self.filter.within = Some(cells.into_iter().collect());
+        self
+    }
+
+    pub fn after(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.after = Some(ts);
+        self
+    }
+
+    pub fn before(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.before = Some(ts);
+        self
+    }
+
+    pub fn confidence(mut self, range: RangeInclusive<u8>) -> Self {
+        self.filter.confidence = Some(range);
+        self
+    }
+
+    pub fn value_range(mut self, range: RangeInclusive<f64>) -> Self {
+        self.filter.value_range = Some(range);
+        self
+    }
+
+    pub fn quality<I>(mut self, statuses: I) -> Self
+    where
+        I: IntoIterator<Item = QualityStatus>,
+    {
+        self.filter.quality = Some(statuses.into_iter().collect());
+        self
+    }
+
+    pub fn build(self) -> ReadingFilter {
+        self.filter
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct StatusFilter {
+    pub device_ids: Option<Vec<DeviceId>>,
+    pub dispatcher_ids: Option<Vec<DispatcherId>>,
+    pub after: Option<jiff::Timestamp>,
+    pub before: Option<jiff::Timestamp>,
+    pub battery: Option<RangeInclusive<u8>>,
+    /// Statuses reporting at least one error, or none at all
+    pub has_errors: Option<bool>,
+    /// Statuses reporting any of these error codes
+    pub error_codes: Option<Vec<DeviceErrorCode>>,
+}
+
+impl StatusFilter {
+    pub fn builder() -> StatusFilterBuilder {
+        StatusFilterBuilder::new()
+    }
+}
+
+#[derive(Default)]
+pub struct StatusFilterBuilder {
+    filter: StatusFilter,
+}
+
+impl StatusFilterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn device_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = DeviceId>,
+    {
+        self.filter.device_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn dispatcher_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = DispatcherId>,
+    {
+        self.filter.dispatcher_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn after(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.after = Some(ts);
+        self
+    }
+
+    pub fn before(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.before = Some(ts);
+        self
+    }
+
+    pub fn battery(mut self, range: RangeInclusive<u8>) -> Self {
+        self.filter.battery = Some(range);
+        self
+    }
+
+    pub fn has_errors(mut self, has_errors: bool) -> Self {
+        self.filter.has_errors = Some(has_errors);
+        self
+    }
+
+    pub fn error_codes<I>(mut self, codes: I) -> Self
+    where
+        I: IntoIterator<Item = DeviceErrorCode>,
+    {
+        self.filter.error_codes = Some(codes.into_iter().collect());
+        self
+    }
+
+    pub fn build(self) -> StatusFilter {
+        self.filter
+    }
+}
+
+/// Aggregates of one granularity, optionally narrowed down further.
+#[derive(Default, Clone)]
+pub struct AggregateFilter {
+    pub granularity: Granularity,
+    pub device_ids: Option<Vec<DeviceId>>,
+    pub sensor_ids: Option<Vec<SensorId>>,
+    pub metric_kinds: Option<Vec<SensorKind>>,
+    /// Buckets starting at or after this time
+    pub after: Option<jiff::Timestamp>,
+    /// Buckets starting at or before this time
+    pub before: Option<jiff::Timestamp>,
+}
+
+impl AggregateFilter {
+    pub fn builder(granularity: Granularity) -> AggregateFilterBuilder {
+        AggregateFilterBuilder::new(granularity)
+    }
+}
+
+pub struct AggregateFilterBuilder {
+    filter: AggregateFilter,
+}
+
+impl AggregateFilterBuilder {
+    pub fn new(granularity: Granularity) -> Self {
+        Self {
+            filter: AggregateFilter {
+                granularity,
+                ..AggregateFilter::default()
+            },
+        }
+    }
+
+    pub fn device_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = DeviceId>,
+    {
+        self.filter.device_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn sensor_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = SensorId>,
+    {
+        self.filter.sensor_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn metric_kinds<I>(mut self, kinds: I) -> Self
+    where
+        I: IntoIterator<Item = SensorKind>,
+    {
+        self.filter.metric_kinds = Some(kinds.into_iter().collect());
+        self
+    }
+
+    pub fn after(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.after = Some(ts);
+        self
+    }
+
+    pub fn before(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.before = Some(ts);
+        self
+    }
+
+    pub fn build(self) -> AggregateFilter {
+        self.filter
+    }
+}
+
+/// Indicators of one field, optionally narrowed down by kind and day.
+#[derive(Default, Clone)]
+pub struct IndicatorFilter {
+    pub field: Option<H3Cell>,
+    pub kinds: Option<Vec<IndicatorKind>>,
+    /// Days starting at or after this time
+    pub after: Option<jiff::Timestamp>,
+    /// Days starting at or before this time
+    pub before: Option<jiff::Timestamp>,
+}
+
+impl IndicatorFilter {
+    pub fn builder() -> IndicatorFilterBuilder {
+        IndicatorFilterBuilder::new()
+    }
+}
+
+#[derive(Default)]
+pub struct IndicatorFilterBuilder {
+    filter: IndicatorFilter,
+}
+
+impl IndicatorFilterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field(mut self, field: H3Cell) -> Self {
+        self.filter.field = Some(field);
+        self
+    }
+
+    pub fn kinds<I>(mut self, kinds: I) -> Self
+    where
+        I: IntoIterator<Item = IndicatorKind>,
+    {
+        self.filter.kinds = Some(kinds.into_iter().collect());
+        self
+    }
+
+    pub fn after(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.after = Some(ts);
+        self
+    }
+
+    pub fn before(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.before = Some(ts);
+        self
+    }
+
+    pub fn build(self) -> IndicatorFilter {
+        self.filter
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AuditSortBy {
+    At,
+}
+
+impl AuditSortBy {
+    pub fn cursor(&self, entry: &AuditEntry) -> Cursor {
+        let key = match self {
+            AuditSortBy::At => SortKey::Time(entry.at),
+        };
+
+        Cursor::new(key, entry.id.0)
+    }
+}
+
+/// Audit entries, narrowed down by what changed, who changed it and when.
+#[derive(Default, Clone)]
+pub struct AuditFilter {
+    pub entity: Option<EntityKind>,
+    pub entity_ids: Option<Vec<Ulid>>,
+    pub actions: Option<Vec<AuditAction>>,
+    pub actor: Option<ApiKeyId>,
+    /// Only changes made by keys of this organization
+    pub org_id: Option<OrgId>,
+    /// Changes made at or after this time
+    pub after: Option<jiff::Timestamp>,
+    /// Changes made at or before this time
+    pub before: Option<jiff::Timestamp>,
+}
+
+impl AuditFilter {
+    pub fn builder() -> AuditFilterBuilder {
+        AuditFilterBuilder::new()
+    }
+}
+
+#[derive(Default)]
+pub struct AuditFilterBuilder {
+    filter: AuditFilter,
+}
+
+impl AuditFilterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entity(mut self, entity: EntityKind) -> Self {
+        self.filter.entity = Some(entity);
+        self
+    }
+
+    pub fn entity_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = Ulid>,
+    {
+        self.filter.entity_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn actions<I>(mut self, actions: I) -> Self
+    where
+        I: IntoIterator<Item = AuditAction>,
+    {
+        self.filter.actions = Some(actions.into_iter().collect());
+        self
+    }
+
+    pub fn actor(mut self, actor: ApiKeyId) -> Self {
+        self.filter.actor = Some(actor);
+        self
+    }
+
+    pub fn org(mut self, org_id: OrgId) -> Self {
+        self.filter.org_id = Some(org_id);
+        self
+    }
+
+    pub fn after(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.after = Some(ts);
+        self
+    }
+
+    pub fn before(mut self, ts: jiff::Timestamp) -> Self {
+        self.filter.before = Some(ts);
+        self
+    }
+
+    pub fn build(self) -> AuditFilter {
+        self.filter
+    }
+}
+
+/// Dead letters, optionally narrowed down by dispatcher, state and the
+/// reason they were refused.
+#[derive(Default, Clone)]
+pub struct DeadLetterFilter {
+    pub dispatcher_ids: Option<Vec<DispatcherId>>,
+    pub state: Option<DeadLetterState>,
+    pub reasons: Option<Vec<InvalidItemReason>>,
+}
+
+impl DeadLetterFilter {
+    pub fn builder() -> DeadLetterFilterBuilder {
+        DeadLetterFilterBuilder::new()
+    }
+}
+
+#[derive(Default)]
+pub struct DeadLetterFilterBuilder {
+    filter: DeadLetterFilter,
+}
+
+impl DeadLetterFilterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dispatcher_ids<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = DispatcherId>,
+    {
+        self.filter.dispatcher_ids = Some(ids.into_iter().collect());
+        self
+    }
+
+    pub fn state(mut self, state: DeadLetterState) -> Self {
+        self.filter.state = Some(state);
+        self
+    }
+
+    pub fn reasons<I>(mut self, reasons: I) -> Self
+    where
+        I: IntoIterator<Item = InvalidItemReason>,
+    {
+        self.filter.reasons = Some(reasons.into_iter().collect());
+        self
+    }
+
+    pub fn build(self) -> DeadLetterFilter {
+        self.filter
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::registry::{AggregateRegistry, filter::AggregateFilter};
+use crate::rollup::{Aggregate, AggregateKey};
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryAggregateRegistry {
+    aggregates: Arc<RwLock<HashMap<AggregateKey, Aggregate>>>,
+}
+
+impl InMemoryAggregateRegistry {
+    pub fn new() -> Self {
+        Self {
+            aggregates: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryAggregateRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl AggregateRegistry for InMemoryAggregateRegistry {
+    type Error = InMemoryError;
+
+    async fn merge(&self, partials: Vec<Aggregate>) -> Result<(), Self::Error> {
+        let mut aggregates = self.aggregates.write().await;
+        for partial in partials {
+            match aggregates.entry(partial.key()) {
+                Entry::Occupied(mut entry) => entry.get_mut().merge(&partial),
+                Entry::Vacant(entry) => {
+                    entry.insert(partial);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn replace(&self, replacements: Vec<Aggregate>) -> Result<(), Self::Error> {
+        let mut aggregates = self.aggregates.write().await;
+        for aggregate in replacements {
+            aggregates.insert(aggregate.key(), aggregate);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, filter: AggregateFilter) -> Result<Vec<Aggregate>, Self::Error> {
+        let aggregates = self.aggregates.read().await;
+        let mut matching: Vec<Aggregate> = aggregates
+            .values()
+            .filter(|aggregate| matches(aggregate, &filter))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|aggregate| (aggregate.bucket_start, aggregate.sensor_id.0));
+
+        Ok(matching)
+    }
+}
+
+fn matches(aggregate: &Aggregate, filter: &AggregateFilter) -> bool {
+    if aggregate.granularity != filter.granularity {
+        return false;
+    }
+
+    if let Some(device_ids) = &filter.device_ids
+        && !device_ids.is_empty()
+        && !device_ids.contains(&aggregate.device_id)
+    {
+        return false;
+    }
+
+    if let Some(sensor_ids) = &filter.sensor_ids
+        && !sensor_ids.is_empty()
+        && !sensor_ids.contains(&aggregate.sensor_id)
+    {
+        return false;
+    }
+
+    if let Some(kinds) = &filter.metric_kinds
+        && !kinds.is_empty()
+        && !kinds.contains(&aggregate.metric)
+    {
+        return false;
+    }
+
+    if let Some(after) = filter.after
+        && aggregate.bucket_start < after
+    {
+        return false;
+    }
+
+    if let Some(before) = filter.before
+        && aggregate.bucket_start > before
+    {
+        return false;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, SensorId, SensorKind};
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::InMemoryAggregateRegistry;
+    use crate::registry::{AggregateRegistry, filter::AggregateFilter};
+    use crate::rollup::{Aggregate, Granularity};
+
+    fn aggregate(device_id: DeviceId, granularity: Granularity, value: f64) -> Aggregate {
+        Aggregate {
+            device_id,
+            sensor_id: SensorId(Ulid::from_parts(0, 1)),
+            metric: SensorKind::AirTemp,
+            granularity,
+            bucket_start: Timestamp::UNIX_EPOCH,
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    #[tokio::test]
+    async fn merge_accumulates_per_bucket() {
+        let reg = InMemoryAggregateRegistry::new();
+        let device = DeviceId(Ulid::new());
+
+        reg.merge(vec![
+            aggregate(device, Granularity::Hour, 20.0),
+            aggregate(device, Granularity::Day, 20.0),
+        ])
+        .await
+        .unwrap();
+        reg.merge(vec![aggregate(device, Granularity::Hour, 10.0)])
+            .await
+            .unwrap();
+
+        let hourly = reg
+            .list(
+                AggregateFilter::builder(Granularity::Hour)
+                    .device_ids([device])
+                    .build(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(hourly.len(), 1);
+        assert_eq!(hourly[0].count, 2);
+        assert_eq!(hourly[0].min, 10.0);
+        assert_eq!(hourly[0].mean(), 15.0);
+
+        let daily = reg
+            .list(AggregateFilter::builder(Granularity::Day).build())
+            .await
+            .unwrap();
+        assert_eq!(daily[0].count, 1);
+    }
+}
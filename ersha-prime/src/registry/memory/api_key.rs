@@ -0,0 +1,87 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::auth::{ApiKey, ApiKeyId};
+use crate::registry::ApiKeyRegistry;
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryApiKeyRegistry {
+    keys: Arc<RwLock<HashMap<ApiKeyId, ApiKey>>>,
+}
+
+impl InMemoryApiKeyRegistry {
+    pub fn new() -> Self {
+        Self {
+            keys: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryApiKeyRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ApiKeyRegistry for InMemoryApiKeyRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, key: ApiKey) -> Result<(), Self::Error> {
+        let mut keys = self.keys.write().await;
+        let _ = keys.insert(key.id, key);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: ApiKeyId) -> Result<Option<ApiKey>, Self::Error> {
+        let keys = self.keys.read().await;
+        Ok(keys.get(&id).cloned())
+    }
+
+    async fn revoke(&self, id: ApiKeyId, at: jiff::Timestamp) -> Result<(), Self::Error> {
+        let mut keys = self.keys.write().await;
+        let key = keys.get_mut(&id).ok_or(InMemoryError::NotFound)?;
+        key.revoked_at.get_or_insert(at);
+
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize, Self::Error> {
+        let keys = self.keys.read().await;
+        Ok(keys.len())
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, Self::Error> {
+        let keys = self.keys.read().await;
+        let mut keys: Vec<_> = keys.values().cloned().collect();
+        keys.sort_by_key(|key| key.id.0);
+
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryApiKeyRegistry;
+    use crate::auth::{ApiKey, Scope};
+    use crate::registry::ApiKeyRegistry;
+
+    #[tokio::test]
+    async fn test_create_and_revoke() {
+        let reg = InMemoryApiKeyRegistry::new();
+        let (key, _) = ApiKey::generate("dashboard", Scope::ReadOnly);
+        let id = key.id;
+
+        reg.create(key).await.unwrap();
+        assert_eq!(reg.count().await.unwrap(), 1);
+        assert!(!reg.get(id).await.unwrap().unwrap().is_revoked());
+
+        reg.revoke(id, jiff::Timestamp::now()).await.unwrap();
+        assert!(reg.get(id).await.unwrap().unwrap().is_revoked());
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::audit::AuditEntry;
+use crate::registry::{
+    AuditRegistry,
+    filter::{AuditFilter, AuditSortBy, QueryOptions},
+};
+
+use super::{InMemoryError, paginate};
+
+#[derive(Clone)]
+pub struct InMemoryAuditRegistry {
+    entries: Arc<RwLock<Vec<AuditEntry>>>,
+}
+
+impl InMemoryAuditRegistry {
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+}
+
+impl Default for InMemoryAuditRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl AuditRegistry for InMemoryAuditRegistry {
+    type Error = InMemoryError;
+
+    async fn record(&self, entry: AuditEntry) -> Result<(), Self::Error> {
+        let mut entries = self.entries.write().await;
+        entries.push(entry);
+
+        Ok(())
+    }
+
+    async fn count(&self, filter: Option<AuditFilter>) -> Result<usize, Self::Error> {
+        let entries = self.entries.read().await;
+        let filter = filter.unwrap_or_default();
+
+        Ok(entries.iter().filter(|e| matches(e, &filter)).count())
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<AuditFilter, AuditSortBy>,
+    ) -> Result<Vec<AuditEntry>, Self::Error> {
+        let entries = self.entries.read().await;
+        let filtered: Vec<&AuditEntry> = entries
+            .iter()
+            .filter(|e| matches(e, &options.filter))
+            .collect();
+
+        let sort_by = options.sort_by;
+
+        Ok(paginate(
+            filtered,
+            &options.sort_order,
+            &options.pagination,
+            |entry| sort_by.cursor(entry),
+        ))
+    }
+}
+
+fn matches(entry: &AuditEntry, filter: &AuditFilter) -> bool {
+    if filter.entity.is_some_and(|entity| entity != entry.entity) {
+        return false;
+    }
+
+    if let Some(ids) = &filter.entity_ids
+        && !ids.is_empty()
+        && !ids.contains(&entry.entity_id)
+    {
+        return false;
+    }
+
+    if let Some(actions) = &filter.actions
+        && !actions.is_empty()
+        && !actions.contains(&entry.action)
+    {
+        return false;
+    }
+
+    if filter.actor.is_some_and(|actor| Some(actor) != entry.actor) {
+        return false;
+    }
+
+    if filter.org_id.is_some_and(|org| Some(org) != entry.org_id) {
+        return false;
+    }
+
+    if filter.after.is_some_and(|after| entry.at < after) {
+        return false;
+    }
+
+    if filter.before.is_some_and(|before| entry.at > before) {
+        return false;
+    }
+
+    true
+}
@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ops::Deref;
+
+use ersha_core::{DeviceStatus, ReadingId, SensorReading, StatusId};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+/// Caps on what an in-memory registry keeps. Unbounded by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryLimits {
+    /// Entries kept before the oldest are evicted
+    pub max_entries: Option<usize>,
+    /// Age in seconds after which entries are evicted
+    pub max_age_secs: Option<u64>,
+}
+
+/// Size of a bounded in-memory registry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryStats {
+    pub entries: usize,
+    /// Entries dropped to stay within the limits since start
+    pub evicted: u64,
+}
+
+/// An entry evicted oldest first.
+pub(super) trait Aged {
+    type Id: Copy + Eq + Hash;
+
+    fn id(&self) -> Self::Id;
+    fn timestamp(&self) -> Timestamp;
+    /// Breaks ties between entries with the same timestamp.
+    fn tiebreak(&self) -> Ulid;
+}
+
+impl Aged for SensorReading {
+    type Id = ReadingId;
+
+    fn id(&self) -> ReadingId {
+        self.id
+    }
+
+    fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    fn tiebreak(&self) -> Ulid {
+        self.id.0
+    }
+}
+
+impl Aged for DeviceStatus {
+    type Id = StatusId;
+
+    fn id(&self) -> StatusId {
+        self.id
+    }
+
+    fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    fn tiebreak(&self) -> Ulid {
+        self.id.0
+    }
+}
+
+/// Entries by id, with an index by age for evicting the oldest.
+///
+/// Reads go through the map it derefs to; writes must go through its own
+/// methods to keep the index in step.
+pub(super) struct BoundedStore<T: Aged> {
+    entries: HashMap<T::Id, T>,
+    by_age: BTreeMap<(Timestamp, Ulid), T::Id>,
+    limits: MemoryLimits,
+    evicted: u64,
+}
+
+impl<T: Aged> BoundedStore<T> {
+    pub(super) fn new(limits: MemoryLimits) -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_age: BTreeMap::new(),
+            limits,
+            evicted: 0,
+        }
+    }
+
+    /// Insert or replace an entry.
+    pub(super) fn insert(&mut self, entry: T) {
+        let id = entry.id();
+        self.remove(id);
+        self.by_age
+            .insert((entry.timestamp(), entry.tiebreak()), id);
+        self.entries.insert(id, entry);
+    }
+
+    /// Insert an entry unless its id is already known. Returns whether it
+    /// was inserted.
+    pub(super) fn insert_new(&mut self, entry: T) -> bool {
+        if self.entries.contains_key(&entry.id()) {
+            return false;
+        }
+
+        self.insert(entry);
+        true
+    }
+
+    pub(super) fn remove(&mut self, id: T::Id) -> Option<T> {
+        let entry = self.entries.remove(&id)?;
+        self.by_age.remove(&(entry.timestamp(), entry.tiebreak()));
+
+        Some(entry)
+    }
+
+    /// Ids of up to `limit` of the oldest entries taken at or before `before`.
+    pub(super) fn oldest(&self, before: Timestamp, limit: usize) -> Vec<T::Id> {
+        self.by_age
+            .iter()
+            .take_while(|((timestamp, _), _)| *timestamp <= before)
+            .take(limit)
+            .map(|(_, id)| *id)
+            .collect()
+    }
+
+    /// Drop entries older than the maximum age as of `now`, then the oldest
+    /// ones above the maximum count. Returns how many were dropped.
+    pub(super) fn evict(&mut self, now: Timestamp) -> usize {
+        let mut evicted = 0;
+
+        let max_age = self
+            .limits
+            .max_age_secs
+            .and_then(|secs| i64::try_from(secs).ok())
+            .map(SignedDuration::from_secs);
+        if let Some(cutoff) = max_age.and_then(|age| now.checked_sub(age).ok()) {
+            while let Some(entry) = self.by_age.first_entry() {
+                if entry.key().0 >= cutoff {
+                    break;
+                }
+                let id = entry.remove();
+                self.entries.remove(&id);
+                evicted += 1;
+            }
+        }
+
+        if let Some(max_entries) = self.limits.max_entries {
+            while self.entries.len() > max_entries {
+                let Some((_, id)) = self.by_age.pop_first() else {
+                    break;
+                };
+                self.entries.remove(&id);
+                evicted += 1;
+            }
+        }
+
+        self.evicted += evicted as u64;
+        evicted
+    }
+
+    pub(super) fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            entries: self.entries.len(),
+            evicted: self.evicted,
+        }
+    }
+}
+
+impl<T: Aged> Deref for BoundedStore<T> {
+    type Target = HashMap<T::Id, T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use ersha_core::{CommandId, DeviceId, DispatcherId};
+use jiff::Timestamp;
+use tokio::sync::RwLock;
+
+use crate::command::{Command, CommandState};
+use crate::registry::CommandRegistry;
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryCommandRegistry {
+    commands: Arc<RwLock<HashMap<CommandId, Command>>>,
+}
+
+impl InMemoryCommandRegistry {
+    pub fn new() -> Self {
+        Self {
+            commands: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryCommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl CommandRegistry for InMemoryCommandRegistry {
+    type Error = InMemoryError;
+
+    async fn enqueue(&self, command: Command) -> Result<(), Self::Error> {
+        let mut commands = self.commands.write().await;
+        commands.insert(command.id, command);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: CommandId) -> Result<Option<Command>, Self::Error> {
+        let commands = self.commands.read().await;
+        Ok(commands.get(&id).cloned())
+    }
+
+    async fn list(&self, device: DeviceId, limit: usize) -> Result<Vec<Command>, Self::Error> {
+        let commands = self.commands.read().await;
+        let mut matching: Vec<&Command> = commands
+            .values()
+            .filter(|c| c.device_id == device)
+            .collect();
+        matching.sort_by_key(|c| std::cmp::Reverse(c.id.0));
+
+        Ok(matching.into_iter().take(limit).cloned().collect())
+    }
+
+    async fn deliver(
+        &self,
+        dispatcher: DispatcherId,
+        now: Timestamp,
+        limit: usize,
+    ) -> Result<Vec<Command>, Self::Error> {
+        let mut commands = self.commands.write().await;
+        let mut pending: Vec<&mut Command> = commands
+            .values_mut()
+            .filter(|c| {
+                c.dispatcher_id == dispatcher
+                    && c.state == CommandState::Queued
+                    && c.expires_at > now
+            })
+            .collect();
+        pending.sort_by_key(|c| c.id.0);
+
+        Ok(pending
+            .into_iter()
+            .take(limit)
+            .map(|command| {
+                command.state = CommandState::Delivered;
+                command.delivered_at = Some(now);
+                command.clone()
+            })
+            .collect())
+    }
+
+    async fn ack(
+        &self,
+        dispatcher: DispatcherId,
+        ids: Vec<CommandId>,
+        now: Timestamp,
+    ) -> Result<usize, Self::Error> {
+        let mut commands = self.commands.write().await;
+        let mut acked = 0;
+        for id in ids {
+            let Some(command) = commands
+                .get_mut(&id)
+                .filter(|c| c.dispatcher_id == dispatcher && c.state == CommandState::Delivered)
+            else {
+                continue;
+            };
+            command.state = CommandState::Acked;
+            command.acked_at = Some(now);
+            acked += 1;
+        }
+
+        Ok(acked)
+    }
+
+    async fn expire(&self, now: Timestamp) -> Result<usize, Self::Error> {
+        let mut commands = self.commands.write().await;
+        let mut expired = 0;
+        for command in commands.values_mut().filter(|c| {
+            matches!(c.state, CommandState::Queued | CommandState::Delivered) && c.expires_at <= now
+        }) {
+            command.state = CommandState::Expired;
+            expired += 1;
+        }
+
+        Ok(expired)
+    }
+}
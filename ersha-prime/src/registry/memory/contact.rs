@@ -0,0 +1,125 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::notify::{Contact, ContactId, Notification, NotificationId};
+use crate::registry::ContactRegistry;
+use crate::webhook::DeliveryState;
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryContactRegistry {
+    contacts: Arc<RwLock<HashMap<ContactId, Contact>>>,
+    notifications: Arc<RwLock<HashMap<NotificationId, Notification>>>,
+}
+
+impl InMemoryContactRegistry {
+    pub fn new() -> Self {
+        Self {
+            contacts: Arc::new(RwLock::new(HashMap::new())),
+            notifications: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryContactRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ContactRegistry for InMemoryContactRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, contact: Contact) -> Result<(), Self::Error> {
+        let mut contacts = self.contacts.write().await;
+        let _ = contacts.insert(contact.id, contact);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: ContactId) -> Result<Option<Contact>, Self::Error> {
+        let contacts = self.contacts.read().await;
+        Ok(contacts.get(&id).cloned())
+    }
+
+    async fn update(&self, contact: Contact) -> Result<(), Self::Error> {
+        let mut contacts = self.contacts.write().await;
+        let existing = contacts
+            .get_mut(&contact.id)
+            .ok_or(InMemoryError::NotFound)?;
+        *existing = contact;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: ContactId) -> Result<(), Self::Error> {
+        let mut contacts = self.contacts.write().await;
+        contacts.remove(&id).ok_or(InMemoryError::NotFound)?;
+
+        let mut notifications = self.notifications.write().await;
+        notifications.retain(|_, notification| notification.contact_id != id);
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Contact>, Self::Error> {
+        let contacts = self.contacts.read().await;
+        let mut all: Vec<Contact> = contacts.values().cloned().collect();
+        all.sort_by_key(|contact| contact.id.0);
+
+        Ok(all)
+    }
+
+    async fn enqueue(&self, new: Vec<Notification>) -> Result<(), Self::Error> {
+        let mut notifications = self.notifications.write().await;
+        for notification in new {
+            notifications.insert(notification.id, notification);
+        }
+
+        Ok(())
+    }
+
+    async fn due(
+        &self,
+        now: jiff::Timestamp,
+        limit: usize,
+    ) -> Result<Vec<Notification>, Self::Error> {
+        let notifications = self.notifications.read().await;
+        let mut due: Vec<&Notification> = notifications
+            .values()
+            .filter(|n| n.state == DeliveryState::Pending && n.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|n| (n.next_attempt_at, n.id.0));
+
+        Ok(due.into_iter().take(limit).cloned().collect())
+    }
+
+    async fn update_notification(&self, notification: Notification) -> Result<(), Self::Error> {
+        let mut notifications = self.notifications.write().await;
+        let existing = notifications
+            .get_mut(&notification.id)
+            .ok_or(InMemoryError::NotFound)?;
+        *existing = notification;
+
+        Ok(())
+    }
+
+    async fn notifications(
+        &self,
+        id: ContactId,
+        limit: usize,
+    ) -> Result<Vec<Notification>, Self::Error> {
+        let notifications = self.notifications.read().await;
+        let mut matching: Vec<&Notification> = notifications
+            .values()
+            .filter(|n| n.contact_id == id)
+            .collect();
+        matching.sort_by_key(|n| std::cmp::Reverse(n.id.0));
+
+        Ok(matching.into_iter().take(limit).cloned().collect())
+    }
+}
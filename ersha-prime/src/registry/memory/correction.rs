@@ -0,0 +1,106 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use ersha_core::{DeviceId, ReadingId};
+use tokio::sync::RwLock;
+
+use crate::correction::{Correction, CorrectionId, CorrectionState, Revision};
+use crate::registry::CorrectionRegistry;
+
+use super::InMemoryError;
+
+#[derive(Clone, Default)]
+pub struct InMemoryCorrectionRegistry {
+    corrections: Arc<RwLock<HashMap<CorrectionId, Correction>>>,
+    revisions: Arc<RwLock<Vec<Revision>>>,
+}
+
+impl InMemoryCorrectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn matching(&self, keep: impl Fn(&Correction) -> bool) -> Vec<Correction> {
+        let corrections = self.corrections.read().await;
+        let mut matching: Vec<Correction> = corrections
+            .values()
+            .filter(|correction| keep(correction))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|correction| correction.id.0);
+
+        matching
+    }
+}
+
+#[async_trait]
+impl CorrectionRegistry for InMemoryCorrectionRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, correction: Correction) -> Result<(), Self::Error> {
+        let mut corrections = self.corrections.write().await;
+        corrections.insert(correction.id, correction);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: CorrectionId) -> Result<Option<Correction>, Self::Error> {
+        let corrections = self.corrections.read().await;
+        Ok(corrections.get(&id).cloned())
+    }
+
+    async fn update(&self, correction: Correction) -> Result<(), Self::Error> {
+        let mut corrections = self.corrections.write().await;
+        let existing = corrections
+            .get_mut(&correction.id)
+            .ok_or(InMemoryError::NotFound)?;
+        *existing = correction;
+
+        Ok(())
+    }
+
+    async fn list(&self, device: DeviceId) -> Result<Vec<Correction>, Self::Error> {
+        Ok(self
+            .matching(|correction| correction.device_id == device)
+            .await)
+    }
+
+    async fn pending(&self) -> Result<Vec<Correction>, Self::Error> {
+        Ok(self
+            .matching(|correction| correction.state == CorrectionState::Pending)
+            .await)
+    }
+
+    async fn record_revisions(&self, new: Vec<Revision>) -> Result<(), Self::Error> {
+        let mut revisions = self.revisions.write().await;
+        for revision in new {
+            let recorded = revisions.iter().any(|existing| {
+                existing.reading_id == revision.reading_id
+                    && existing.correction_id == revision.correction_id
+            });
+            if !recorded {
+                revisions.push(revision);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn revisions(&self, reading: ReadingId) -> Result<Vec<Revision>, Self::Error> {
+        let revisions = self.revisions.read().await;
+        Ok(revisions
+            .iter()
+            .filter(|revision| revision.reading_id == reading)
+            .cloned()
+            .collect())
+    }
+
+    async fn revisions_by(&self, correction: CorrectionId) -> Result<Vec<Revision>, Self::Error> {
+        let revisions = self.revisions.read().await;
+        Ok(revisions
+            .iter()
+            .filter(|revision| revision.correction_id == correction)
+            .cloned()
+            .collect())
+    }
+}
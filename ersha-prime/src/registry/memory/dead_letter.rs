@@ -0,0 +1,87 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::dead_letter::{DeadLetter, DeadLetterId};
+use crate::registry::{DeadLetterRegistry, filter::DeadLetterFilter};
+
+use super::InMemoryError;
+
+#[derive(Clone, Default)]
+pub struct InMemoryDeadLetterRegistry {
+    letters: Arc<RwLock<HashMap<DeadLetterId, DeadLetter>>>,
+}
+
+impl InMemoryDeadLetterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl DeadLetterRegistry for InMemoryDeadLetterRegistry {
+    type Error = InMemoryError;
+
+    async fn record(&self, new: Vec<DeadLetter>) -> Result<(), Self::Error> {
+        let mut letters = self.letters.write().await;
+        for letter in new {
+            let held = letters.values().any(|existing| {
+                existing.item.kind() == letter.item.kind()
+                    && existing.item.item_id() == letter.item.item_id()
+            });
+            if !held {
+                letters.insert(letter.id, letter);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, id: DeadLetterId) -> Result<Option<DeadLetter>, Self::Error> {
+        let letters = self.letters.read().await;
+        Ok(letters.get(&id).cloned())
+    }
+
+    async fn update(&self, letter: DeadLetter) -> Result<(), Self::Error> {
+        let mut letters = self.letters.write().await;
+        let existing = letters.get_mut(&letter.id).ok_or(InMemoryError::NotFound)?;
+        *existing = letter;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: DeadLetterId) -> Result<(), Self::Error> {
+        let mut letters = self.letters.write().await;
+        letters.remove(&id).ok_or(InMemoryError::NotFound)?;
+
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        filter: DeadLetterFilter,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, Self::Error> {
+        let letters = self.letters.read().await;
+        let mut matching: Vec<DeadLetter> = letters
+            .values()
+            .filter(|letter| {
+                let dispatcher = match &filter.dispatcher_ids {
+                    Some(ids) if !ids.is_empty() => ids.contains(&letter.dispatcher_id),
+                    _ => true,
+                };
+                let reason = match &filter.reasons {
+                    Some(reasons) if !reasons.is_empty() => reasons.contains(&letter.reason),
+                    _ => true,
+                };
+                dispatcher && reason && filter.state.is_none_or(|state| letter.state == state)
+            })
+            .cloned()
+            .collect();
+        matching.sort_by_key(|letter| letter.id.0);
+        matching.truncate(limit);
+
+        Ok(matching)
+    }
+}
@@ -0,0 +1,84 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use jiff::Timestamp;
+use tokio::sync::RwLock;
+
+use crate::derived::{Indicator, IndicatorKind};
+use crate::registry::{DerivedMetricRegistry, filter::IndicatorFilter};
+
+use super::InMemoryError;
+
+/// Field, day and kind: the identity an upsert replaces on.
+type IndicatorKey = (u64, Timestamp, IndicatorKind);
+
+#[derive(Clone)]
+pub struct InMemoryDerivedMetricRegistry {
+    indicators: Arc<RwLock<BTreeMap<IndicatorKey, Indicator>>>,
+}
+
+impl InMemoryDerivedMetricRegistry {
+    pub fn new() -> Self {
+        Self {
+            indicators: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryDerivedMetricRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl DerivedMetricRegistry for InMemoryDerivedMetricRegistry {
+    type Error = InMemoryError;
+
+    async fn upsert(&self, indicators: Vec<Indicator>) -> Result<(), Self::Error> {
+        let mut stored = self.indicators.write().await;
+        for indicator in indicators {
+            stored.insert(
+                (indicator.field.0, indicator.day, indicator.kind),
+                indicator,
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, filter: IndicatorFilter) -> Result<Vec<Indicator>, Self::Error> {
+        let indicators = self.indicators.read().await;
+        let mut matching: Vec<Indicator> = indicators
+            .values()
+            .filter(|indicator| matches(indicator, &filter))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|indicator| (indicator.day, indicator.kind));
+
+        Ok(matching)
+    }
+}
+
+fn matches(indicator: &Indicator, filter: &IndicatorFilter) -> bool {
+    if filter.field.is_some_and(|field| field != indicator.field) {
+        return false;
+    }
+
+    if let Some(kinds) = &filter.kinds
+        && !kinds.is_empty()
+        && !kinds.contains(&indicator.kind)
+    {
+        return false;
+    }
+
+    if filter.after.is_some_and(|after| indicator.day < after) {
+        return false;
+    }
+
+    if filter.before.is_some_and(|before| indicator.day > before) {
+        return false;
+    }
+
+    true
+}
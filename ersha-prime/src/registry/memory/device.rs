@@ -1,27 +1,84 @@
 use std::{collections::HashMap, sync::Arc};
 
 use async_trait::async_trait;
-use ersha_core::{Device, DeviceId, DeviceState, Sensor};
+use ersha_core::{Device, DeviceId, DeviceState, DispatcherId, Sensor};
 use tokio::sync::RwLock;
 
+use crate::metadata;
+use crate::org::OrgId;
+use crate::outbox::OutboxEntry;
+use crate::placement::{Placement, next_update};
 use crate::registry::{
-    DeviceRegistry,
-    filter::{DeviceFilter, DeviceSortBy, Pagination, QueryOptions, SortOrder},
+    DeviceDetails, DeviceRegistry, OutboxRegistry,
+    filter::{DeviceFilter, DeviceSortBy, QueryOptions},
 };
+use crate::webhook::Event;
 
-use super::InMemoryError;
+use super::{InMemoryError, InMemoryOutboxRegistry, paginate};
 
 #[derive(Clone)]
 pub struct InMemoryDeviceRegistry {
     devices: Arc<RwLock<HashMap<DeviceId, Device>>>,
+    orgs: Arc<RwLock<HashMap<DeviceId, OrgId>>>,
+    dispatchers: Arc<RwLock<HashMap<DeviceId, DispatcherId>>>,
+    tags: Arc<RwLock<HashMap<DeviceId, Vec<String>>>>,
+    details: Arc<RwLock<HashMap<DeviceId, DeviceDetails>>>,
+    disconnected: Arc<RwLock<HashMap<DeviceId, jiff::Timestamp>>>,
+    reporting_intervals: Arc<RwLock<HashMap<DeviceId, u64>>>,
+    hardware_revs: Arc<RwLock<HashMap<DeviceId, String>>>,
+    metadata: Arc<RwLock<HashMap<DeviceId, serde_json::Value>>>,
+    outbox: InMemoryOutboxRegistry,
 }
 
 impl InMemoryDeviceRegistry {
     pub fn new() -> Self {
         Self {
             devices: Arc::new(RwLock::new(HashMap::new())),
+            orgs: Arc::new(RwLock::new(HashMap::new())),
+            dispatchers: Arc::new(RwLock::new(HashMap::new())),
+            tags: Arc::new(RwLock::new(HashMap::new())),
+            details: Arc::new(RwLock::new(HashMap::new())),
+            disconnected: Arc::new(RwLock::new(HashMap::new())),
+            reporting_intervals: Arc::new(RwLock::new(HashMap::new())),
+            hardware_revs: Arc::new(RwLock::new(HashMap::new())),
+            metadata: Arc::new(RwLock::new(HashMap::new())),
+            outbox: InMemoryOutboxRegistry::new(),
         }
     }
+
+    /// Stage events in `outbox` rather than one of the registry's own.
+    pub fn with_outbox(mut self, outbox: InMemoryOutboxRegistry) -> Self {
+        self.outbox = outbox;
+        self
+    }
+}
+
+impl InMemoryDeviceRegistry {
+    async fn set_state(&self, id: DeviceId, state: DeviceState) -> Result<(), InMemoryError> {
+        let mut devices = self.devices.write().await;
+        let device = devices.get_mut(&id).ok_or(InMemoryError::NotFound)?;
+        device.state = state;
+        self.touch(id).await;
+
+        Ok(())
+    }
+
+    /// Record that the device changed, keeping its placement.
+    async fn touch(&self, id: DeviceId) -> jiff::Timestamp {
+        touch(&mut *self.details.write().await, id)
+    }
+}
+
+fn touch(details: &mut HashMap<DeviceId, DeviceDetails>, id: DeviceId) -> jiff::Timestamp {
+    let previous = details.get(&id);
+    let updated = DeviceDetails {
+        placement: previous.map(|d| d.placement.clone()).unwrap_or_default(),
+        updated_at: next_update(previous.map(|d| d.updated_at)),
+    };
+    let updated_at = updated.updated_at;
+    details.insert(id, updated);
+
+    updated_at
 }
 
 impl Default for InMemoryDeviceRegistry {
@@ -36,11 +93,25 @@ impl DeviceRegistry for InMemoryDeviceRegistry {
 
     async fn register(&self, device: Device) -> Result<(), Self::Error> {
         let mut devices = self.devices.write().await;
-        let _ = devices.insert(device.id, device);
+        let id = device.id;
+        let _ = devices.insert(id, device);
+        self.touch(id).await;
 
         Ok(())
     }
 
+    async fn register_new(&self, device: Device) -> Result<Option<Device>, Self::Error> {
+        let mut devices = self.devices.write().await;
+        if let Some(existing) = devices.get(&device.id) {
+            return Ok(Some(existing.clone()));
+        }
+        let id = device.id;
+        devices.insert(id, device);
+        self.touch(id).await;
+
+        Ok(None)
+    }
+
     async fn add_sensor(&self, id: DeviceId, sensor: Sensor) -> Result<(), Self::Error> {
         let mut devices = self.devices.write().await;
         let mut device = devices.get(&id).cloned().ok_or(InMemoryError::NotFound)?;
@@ -53,6 +124,7 @@ impl DeviceRegistry for InMemoryDeviceRegistry {
 
         let new = Device { ..device };
         devices.insert(id, new);
+        self.touch(id).await;
         Ok(())
     }
 
@@ -67,6 +139,7 @@ impl DeviceRegistry for InMemoryDeviceRegistry {
         device.sensors = device.sensors.into_iter().chain(sensors).collect();
 
         devices.insert(id, device);
+        self.touch(id).await;
         Ok(())
     }
 
@@ -75,30 +148,245 @@ impl DeviceRegistry for InMemoryDeviceRegistry {
         Ok(devices.get(&id).cloned())
     }
 
-    async fn update(&self, id: DeviceId, new: Device) -> Result<(), Self::Error> {
-        let mut devices = self.devices.write().await;
-        let _old = devices.insert(id, new);
-        Ok(())
+    async fn get_many(&self, ids: &[DeviceId]) -> Result<Vec<Device>, Self::Error> {
+        let devices = self.devices.read().await;
+        Ok(ids
+            .iter()
+            .filter_map(|id| devices.get(id).cloned())
+            .collect())
     }
 
-    async fn suspend(&self, id: DeviceId) -> Result<(), Self::Error> {
-        let device = self.get(id).await?.ok_or(InMemoryError::NotFound)?;
+    async fn update(
+        &self,
+        id: DeviceId,
+        new: Device,
+        placement: Placement,
+        expected: Option<jiff::Timestamp>,
+    ) -> Result<Option<jiff::Timestamp>, Self::Error> {
+        let mut devices = self.devices.write().await;
+        if !devices.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
 
-        self.update(
+        let mut details = self.details.write().await;
+        let previous = details.get(&id).map(|d| d.updated_at);
+        if expected.is_some() && expected != previous {
+            return Ok(None);
+        }
+
+        let updated_at = next_update(previous);
+        devices.insert(id, Device { id, ..new });
+        details.insert(
             id,
-            Device {
-                state: DeviceState::Suspended,
-                ..device
+            DeviceDetails {
+                placement,
+                updated_at,
             },
-        )
-        .await?;
+        );
+
+        Ok(Some(updated_at))
+    }
+
+    async fn details(&self, id: DeviceId) -> Result<Option<DeviceDetails>, Self::Error> {
+        let details = self.details.read().await;
+        Ok(details.get(&id).cloned())
+    }
+
+    async fn suspend(&self, id: DeviceId) -> Result<(), Self::Error> {
+        self.set_state(id, DeviceState::Suspended).await
+    }
+
+    async fn reactivate(&self, id: DeviceId) -> Result<(), Self::Error> {
+        self.set_state(id, DeviceState::Active).await
+    }
+
+    async fn decommission(&self, id: DeviceId) -> Result<(), Self::Error> {
+        self.set_state(id, DeviceState::Decommissioned).await
+    }
+
+    async fn set_org(&self, id: DeviceId, org: Option<OrgId>) -> Result<(), Self::Error> {
+        if !self.devices.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut orgs = self.orgs.write().await;
+        match org {
+            Some(org) => orgs.insert(id, org),
+            None => orgs.remove(&id),
+        };
 
         Ok(())
     }
 
-    async fn batch_register(&self, devices: Vec<Device>) -> Result<(), Self::Error> {
-        for device in devices {
-            self.register(device).await?;
+    async fn org(&self, id: DeviceId) -> Result<Option<OrgId>, Self::Error> {
+        let orgs = self.orgs.read().await;
+        Ok(orgs.get(&id).copied())
+    }
+
+    async fn set_dispatcher(
+        &self,
+        id: DeviceId,
+        dispatcher: Option<DispatcherId>,
+    ) -> Result<(), Self::Error> {
+        if !self.devices.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut dispatchers = self.dispatchers.write().await;
+        match dispatcher {
+            Some(dispatcher) => dispatchers.insert(id, dispatcher),
+            None => dispatchers.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    async fn dispatcher(&self, id: DeviceId) -> Result<Option<DispatcherId>, Self::Error> {
+        let dispatchers = self.dispatchers.read().await;
+        Ok(dispatchers.get(&id).copied())
+    }
+
+    async fn set_tags(&self, id: DeviceId, tags: Vec<String>) -> Result<(), Self::Error> {
+        if !self.devices.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut all = self.tags.write().await;
+        if tags.is_empty() {
+            all.remove(&id);
+        } else {
+            all.insert(id, tags);
+        }
+
+        Ok(())
+    }
+
+    async fn tags(&self, id: DeviceId) -> Result<Vec<String>, Self::Error> {
+        let tags = self.tags.read().await;
+        Ok(tags.get(&id).cloned().unwrap_or_default())
+    }
+
+    async fn set_disconnected(
+        &self,
+        id: DeviceId,
+        since: Option<jiff::Timestamp>,
+    ) -> Result<(), Self::Error> {
+        if !self.devices.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut disconnected = self.disconnected.write().await;
+        match since {
+            Some(since) => disconnected.insert(id, since),
+            None => disconnected.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    async fn mark_offline(
+        &self,
+        id: DeviceId,
+        since: jiff::Timestamp,
+        event: Event,
+    ) -> Result<(), Self::Error> {
+        if !self.devices.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut disconnected = self.disconnected.write().await;
+        disconnected.insert(id, since);
+        self.outbox.stage(vec![OutboxEntry::new(event)]).await
+    }
+
+    async fn disconnected(&self) -> Result<Vec<(DeviceId, jiff::Timestamp)>, Self::Error> {
+        let disconnected = self.disconnected.read().await;
+        Ok(disconnected
+            .iter()
+            .map(|(id, since)| (*id, *since))
+            .collect())
+    }
+
+    async fn set_reporting_interval(
+        &self,
+        id: DeviceId,
+        interval_secs: Option<u64>,
+    ) -> Result<(), Self::Error> {
+        if !self.devices.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut intervals = self.reporting_intervals.write().await;
+        match interval_secs {
+            Some(secs) => intervals.insert(id, secs),
+            None => intervals.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    async fn reporting_interval(&self, id: DeviceId) -> Result<Option<u64>, Self::Error> {
+        let intervals = self.reporting_intervals.read().await;
+        Ok(intervals.get(&id).copied())
+    }
+
+    async fn reporting_intervals(&self) -> Result<Vec<(DeviceId, u64)>, Self::Error> {
+        let intervals = self.reporting_intervals.read().await;
+        Ok(intervals.iter().map(|(id, secs)| (*id, *secs)).collect())
+    }
+
+    async fn set_hardware_rev(
+        &self,
+        id: DeviceId,
+        hardware_rev: Option<String>,
+    ) -> Result<(), Self::Error> {
+        if !self.devices.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut revs = self.hardware_revs.write().await;
+        match hardware_rev {
+            Some(rev) => revs.insert(id, rev),
+            None => revs.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    async fn hardware_rev(&self, id: DeviceId) -> Result<Option<String>, Self::Error> {
+        let revs = self.hardware_revs.read().await;
+        Ok(revs.get(&id).cloned())
+    }
+
+    async fn set_metadata(
+        &self,
+        id: DeviceId,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Self::Error> {
+        if !self.devices.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut all = self.metadata.write().await;
+        match metadata {
+            Some(metadata) => all.insert(id, metadata),
+            None => all.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    async fn metadata(&self, id: DeviceId) -> Result<Option<serde_json::Value>, Self::Error> {
+        let metadata = self.metadata.read().await;
+        Ok(metadata.get(&id).cloned())
+    }
+
+    async fn batch_register(&self, new: Vec<Device>) -> Result<(), Self::Error> {
+        let mut devices = self.devices.write().await;
+        let mut details = self.details.write().await;
+        for device in new {
+            touch(&mut details, device.id);
+            devices.insert(device.id, device);
         }
 
         Ok(())
@@ -107,7 +395,18 @@ impl DeviceRegistry for InMemoryDeviceRegistry {
     async fn count(&self, filter: Option<DeviceFilter>) -> Result<usize, Self::Error> {
         let devices = self.devices.read().await;
         if let Some(filter) = filter {
-            let filtered = filter_devices(&devices, &filter);
+            let (orgs, dispatchers) = (self.orgs.read().await, self.dispatchers.read().await);
+            let tags = self.tags.read().await;
+            let disconnected = self.disconnected.read().await;
+            let metadata = self.metadata.read().await;
+            let assignments = Assignments {
+                orgs: &orgs,
+                dispatchers: &dispatchers,
+                tags: &tags,
+                disconnected: &disconnected,
+                metadata: &metadata,
+            };
+            let filtered = filter_devices(&devices, &assignments, &filter);
 
             return Ok(filtered.count());
         }
@@ -120,72 +419,92 @@ impl DeviceRegistry for InMemoryDeviceRegistry {
         options: QueryOptions<DeviceFilter, DeviceSortBy>,
     ) -> Result<Vec<Device>, Self::Error> {
         let devices = self.devices.read().await;
-        let filtered: Vec<&Device> = filter_devices(&devices, &options.filter).collect();
-        let sorted = sort_devices(filtered, &options.sort_by, &options.sort_order);
-        let paginated = paginate_devices(sorted, &options.pagination);
-
-        Ok(paginated)
+        let (orgs, dispatchers) = (self.orgs.read().await, self.dispatchers.read().await);
+        let tags = self.tags.read().await;
+        let disconnected = self.disconnected.read().await;
+        let metadata = self.metadata.read().await;
+        let assignments = Assignments {
+            orgs: &orgs,
+            dispatchers: &dispatchers,
+            tags: &tags,
+            disconnected: &disconnected,
+            metadata: &metadata,
+        };
+        let filtered: Vec<&Device> =
+            filter_devices(&devices, &assignments, &options.filter).collect();
+        let sort_by = options.sort_by;
+
+        Ok(paginate(
+            filtered,
+            &options.sort_order,
+            &options.pagination,
+            |device| sort_by.cursor(device),
+        ))
     }
 }
 
-fn sort_devices<'a>(
-    mut devices: Vec<&'a Device>,
-    sort_by: &DeviceSortBy,
-    sort_order: &SortOrder,
-) -> Vec<&'a Device> {
-    devices.sort_by(|a, b| {
-        let ord = match sort_by {
-            DeviceSortBy::State => (a.state.clone() as i32).cmp(&(b.state.clone() as i32)),
-            DeviceSortBy::Manufacturer => a.manufacturer.cmp(&b.manufacturer),
-            DeviceSortBy::ProvisionAt => a.provisioned_at.cmp(&b.provisioned_at),
-            DeviceSortBy::SensorCount => a.sensors.len().cmp(&b.sensors.len()),
-        };
+/// What each device is assigned to, whether it is connected and its
+/// metadata, kept beside the devices themselves.
+struct Assignments<'a> {
+    orgs: &'a HashMap<DeviceId, OrgId>,
+    dispatchers: &'a HashMap<DeviceId, DispatcherId>,
+    tags: &'a HashMap<DeviceId, Vec<String>>,
+    disconnected: &'a HashMap<DeviceId, jiff::Timestamp>,
+    metadata: &'a HashMap<DeviceId, serde_json::Value>,
+}
 
-        match sort_order {
-            SortOrder::Asc => ord,
-            SortOrder::Desc => ord.reverse(),
+fn filter_devices<'a>(
+    devices: &'a HashMap<DeviceId, Device>,
+    assignments: &'a Assignments<'a>,
+    filter: &DeviceFilter,
+) -> impl Iterator<Item = &'a Device> {
+    devices.values().filter(|device| {
+        if let Some(org_id) = filter.org_id
+            && assignments.orgs.get(&device.id) != Some(&org_id)
+        {
+            return false;
         }
-    });
 
-    devices
-}
+        if let Some(dispatcher_id) = filter.dispatcher_id
+            && assignments.dispatchers.get(&device.id) != Some(&dispatcher_id)
+        {
+            return false;
+        }
 
-fn paginate_devices(devices: Vec<&Device>, pagination: &Pagination) -> Vec<Device> {
-    match pagination {
-        Pagination::Offset { offset, limit } => devices
-            .into_iter()
-            .skip(*offset)
-            .take(*limit)
-            .cloned()
-            .collect(),
-        Pagination::Cursor { after, limit } => {
-            if let Some(inner_ulid) = after {
-                let id = DeviceId(*inner_ulid);
-                return devices
-                    .into_iter()
-                    .skip_while(|device| device.id != id)
-                    .skip(1)
-                    .take(*limit)
-                    .cloned()
-                    .collect();
+        if let Some(wanted) = &filter.tags {
+            let tags = assignments.tags.get(&device.id);
+            if !wanted
+                .iter()
+                .all(|tag| tags.is_some_and(|tags| tags.contains(tag)))
+            {
+                return false;
             }
+        }
 
-            vec![]
+        if let Some(disconnected) = filter.disconnected
+            && assignments.disconnected.contains_key(&device.id) != disconnected
+        {
+            return false;
+        }
+
+        if let Some(wanted) = &filter.metadata
+            && !metadata::matches(assignments.metadata.get(&device.id), wanted)
+        {
+            return false;
         }
-    }
-}
 
-fn filter_devices<'a>(
-    devices: &'a HashMap<DeviceId, Device>,
-    filter: &DeviceFilter,
-) -> impl Iterator<Item = &'a Device> {
-    devices.values().filter(|device| {
         if let Some(locations) = &filter.locations
             && !locations.contains(&device.location)
         {
             return false;
         }
 
+        if let Some(cells) = &filter.within
+            && !cells.iter().any(|cell| device.location.is_within(*cell))
+        {
+            return false;
+        }
+
         if let Some(states) = &filter.states
             && !states.contains(&device.state)
         {
@@ -285,6 +604,50 @@ mod tests {
         assert_eq!(fetched.manufacturer.as_deref(), Some("Apple"));
     }
 
+    #[tokio::test]
+    async fn test_device_lifecycle() {
+        let registry = device_registry();
+        let id = DeviceId(Ulid::new());
+        registry.register(mock_device(id.0, "Apple")).await.unwrap();
+
+        registry.suspend(id).await.unwrap();
+        let fetched = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(fetched.state, DeviceState::Suspended);
+
+        registry.reactivate(id).await.unwrap();
+        let fetched = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(fetched.state, DeviceState::Active);
+
+        registry.decommission(id).await.unwrap();
+        let fetched = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(fetched.state, DeviceState::Decommissioned);
+
+        assert!(registry.reactivate(DeviceId(Ulid::new())).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_devices() {
+        let registry = device_registry();
+        let id = DeviceId(Ulid::new());
+        registry.register(mock_device(id.0, "Apple")).await.unwrap();
+        let updated_at = registry.details(id).await.unwrap().unwrap().updated_at;
+
+        let since = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+        registry.set_disconnected(id, Some(since)).await.unwrap();
+        assert_eq!(registry.disconnected().await.unwrap(), [(id, since)]);
+        let details = registry.details(id).await.unwrap().unwrap();
+        assert_eq!(details.updated_at, updated_at);
+
+        registry.set_disconnected(id, None).await.unwrap();
+        assert!(registry.disconnected().await.unwrap().is_empty());
+        assert!(
+            registry
+                .set_disconnected(DeviceId(Ulid::new()), Some(since))
+                .await
+                .is_err()
+        );
+    }
+
     #[tokio::test]
     async fn test_add_sensor() {
         let registry = device_registry();
@@ -387,7 +750,8 @@ mod tests {
         let id2 = Ulid::new();
         let id3 = Ulid::new();
 
-        registry.register(mock_device(id1, "A")).await.unwrap();
+        let first = mock_device(id1, "A");
+        registry.register(first.clone()).await.unwrap();
         registry.register(mock_device(id2, "B")).await.unwrap();
         registry.register(mock_device(id3, "C")).await.unwrap();
 
@@ -396,7 +760,7 @@ mod tests {
             sort_by: DeviceSortBy::Manufacturer,
             sort_order: SortOrder::Asc,
             pagination: Pagination::Cursor {
-                after: Some(id1),
+                after: Some(DeviceSortBy::Manufacturer.cursor(&first)),
                 limit: 1,
             },
         };
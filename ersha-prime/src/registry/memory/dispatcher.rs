@@ -4,22 +4,42 @@ use async_trait::async_trait;
 use ersha_core::{Dispatcher, DispatcherId, DispatcherState};
 use tokio::sync::RwLock;
 
+use crate::org::OrgId;
+use crate::placement::next_update;
 use crate::registry::{
     DispatcherRegistry,
-    filter::{DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortOrder},
+    filter::{DispatcherFilter, DispatcherSortBy, QueryOptions},
 };
 
-use super::InMemoryError;
+use super::{InMemoryError, paginate};
 
 #[derive(Clone)]
 pub struct InMemoryDispatcherRegistry {
     dispatchers: Arc<RwLock<HashMap<DispatcherId, Dispatcher>>>,
+    secrets: Arc<RwLock<HashMap<DispatcherId, String>>>,
+    orgs: Arc<RwLock<HashMap<DispatcherId, OrgId>>>,
+    tags: Arc<RwLock<HashMap<DispatcherId, Vec<String>>>>,
+    /// When each dispatcher was last changed
+    updated: Arc<RwLock<HashMap<DispatcherId, jiff::Timestamp>>>,
 }
 
 impl InMemoryDispatcherRegistry {
     pub fn new() -> Self {
         Self {
             dispatchers: Arc::new(RwLock::new(HashMap::new())),
+            updated: Arc::new(RwLock::new(HashMap::new())),
+            secrets: Arc::new(RwLock::new(HashMap::new())),
+            orgs: Arc::new(RwLock::new(HashMap::new())),
+            tags: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Record that the dispatchers changed.
+    async fn touch(&self, ids: impl IntoIterator<Item = DispatcherId>) {
+        let mut updated = self.updated.write().await;
+        for id in ids {
+            let previous = updated.get(&id).copied();
+            updated.insert(id, next_update(previous));
         }
     }
 }
@@ -36,19 +56,68 @@ impl DispatcherRegistry for InMemoryDispatcherRegistry {
 
     async fn register(&self, dispatcher: Dispatcher) -> Result<(), Self::Error> {
         let mut dispatchers = self.dispatchers.write().await;
-        let _ = dispatchers.insert(dispatcher.id, dispatcher);
+        let id = dispatcher.id;
+        let _ = dispatchers.insert(id, dispatcher);
+        self.touch([id]).await;
+
         Ok(())
     }
 
+    async fn register_new(
+        &self,
+        dispatcher: Dispatcher,
+    ) -> Result<Option<Dispatcher>, Self::Error> {
+        let mut dispatchers = self.dispatchers.write().await;
+        if let Some(existing) = dispatchers.get(&dispatcher.id) {
+            return Ok(Some(existing.clone()));
+        }
+        let id = dispatcher.id;
+        dispatchers.insert(id, dispatcher);
+        self.touch([id]).await;
+
+        Ok(None)
+    }
+
     async fn get(&self, id: DispatcherId) -> Result<Option<Dispatcher>, Self::Error> {
         let dispatchers = self.dispatchers.read().await;
         Ok(dispatchers.get(&id).cloned())
     }
 
-    async fn update(&self, id: DispatcherId, new: Dispatcher) -> Result<(), Self::Error> {
+    async fn get_many(&self, ids: &[DispatcherId]) -> Result<Vec<Dispatcher>, Self::Error> {
+        let dispatchers = self.dispatchers.read().await;
+        Ok(ids
+            .iter()
+            .filter_map(|id| dispatchers.get(id).cloned())
+            .collect())
+    }
+
+    async fn update(
+        &self,
+        id: DispatcherId,
+        new: Dispatcher,
+        expected: Option<jiff::Timestamp>,
+    ) -> Result<Option<jiff::Timestamp>, Self::Error> {
         let mut dispatchers = self.dispatchers.write().await;
-        let _old = dispatchers.insert(id, new);
-        Ok(())
+        if !dispatchers.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut updated = self.updated.write().await;
+        let previous = updated.get(&id).copied();
+        if expected.is_some() && expected != previous {
+            return Ok(None);
+        }
+
+        let updated_at = next_update(previous);
+        dispatchers.insert(id, Dispatcher { id, ..new });
+        updated.insert(id, updated_at);
+
+        Ok(Some(updated_at))
+    }
+
+    async fn updated_at(&self, id: DispatcherId) -> Result<Option<jiff::Timestamp>, Self::Error> {
+        let updated = self.updated.read().await;
+        Ok(updated.get(&id).copied())
     }
 
     async fn suspend(&self, id: DispatcherId) -> Result<(), Self::Error> {
@@ -60,24 +129,108 @@ impl DispatcherRegistry for InMemoryDispatcherRegistry {
                 state: DispatcherState::Suspended,
                 ..dispatcher
             },
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn reactivate(&self, id: DispatcherId) -> Result<(), Self::Error> {
+        let dispatcher = self.get(id).await?.ok_or(InMemoryError::NotFound)?;
+
+        self.update(
+            id,
+            Dispatcher {
+                state: DispatcherState::Active,
+                ..dispatcher
+            },
+            None,
         )
         .await?;
 
         Ok(())
     }
 
-    async fn batch_register(&self, dispatchers: Vec<Dispatcher>) -> Result<(), Self::Error> {
-        for dispatcher in dispatchers {
-            self.register(dispatcher).await?;
+    async fn set_secret(
+        &self,
+        id: DispatcherId,
+        secret: Option<String>,
+    ) -> Result<(), Self::Error> {
+        if !self.dispatchers.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
         }
 
+        let mut secrets = self.secrets.write().await;
+        match secret {
+            Some(secret) => secrets.insert(id, secret),
+            None => secrets.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    async fn get_secret(&self, id: DispatcherId) -> Result<Option<String>, Self::Error> {
+        let secrets = self.secrets.read().await;
+        Ok(secrets.get(&id).cloned())
+    }
+
+    async fn set_tags(&self, id: DispatcherId, tags: Vec<String>) -> Result<(), Self::Error> {
+        if !self.dispatchers.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut all = self.tags.write().await;
+        if tags.is_empty() {
+            all.remove(&id);
+        } else {
+            all.insert(id, tags);
+        }
+
+        Ok(())
+    }
+
+    async fn tags(&self, id: DispatcherId) -> Result<Vec<String>, Self::Error> {
+        let tags = self.tags.read().await;
+        Ok(tags.get(&id).cloned().unwrap_or_default())
+    }
+
+    async fn set_org(&self, id: DispatcherId, org: Option<OrgId>) -> Result<(), Self::Error> {
+        if !self.dispatchers.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut orgs = self.orgs.write().await;
+        match org {
+            Some(org) => orgs.insert(id, org),
+            None => orgs.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    async fn org(&self, id: DispatcherId) -> Result<Option<OrgId>, Self::Error> {
+        let orgs = self.orgs.read().await;
+        Ok(orgs.get(&id).copied())
+    }
+
+    async fn batch_register(&self, new: Vec<Dispatcher>) -> Result<(), Self::Error> {
+        let mut dispatchers = self.dispatchers.write().await;
+        let ids: Vec<_> = new.iter().map(|dispatcher| dispatcher.id).collect();
+        dispatchers.extend(
+            new.into_iter()
+                .map(|dispatcher| (dispatcher.id, dispatcher)),
+        );
+        self.touch(ids).await;
+
         Ok(())
     }
 
     async fn count(&self, filter: Option<DispatcherFilter>) -> Result<usize, Self::Error> {
         let dispatchers = self.dispatchers.read().await;
         if let Some(filter) = filter {
-            let filtered = filter_dispatchers(&dispatchers, &filter);
+            let (orgs, tags) = (self.orgs.read().await, self.tags.read().await);
+            let filtered = filter_dispatchers(&dispatchers, &orgs, &tags, &filter);
 
             return Ok(filtered.count());
         }
@@ -90,64 +243,43 @@ impl DispatcherRegistry for InMemoryDispatcherRegistry {
         options: QueryOptions<DispatcherFilter, DispatcherSortBy>,
     ) -> Result<Vec<Dispatcher>, Self::Error> {
         let dispatchers = self.dispatchers.read().await;
+        let (orgs, tags) = (self.orgs.read().await, self.tags.read().await);
         let filtered: Vec<&Dispatcher> =
-            filter_dispatchers(&dispatchers, &options.filter).collect();
-        let sorted = sort_dispatchers(filtered, &options.sort_by, &options.sort_order);
-        let paginated = paginate_dispatchers(sorted, &options.pagination);
-
-        Ok(paginated)
+            filter_dispatchers(&dispatchers, &orgs, &tags, &options.filter).collect();
+        let sort_by = options.sort_by;
+
+        Ok(paginate(
+            filtered,
+            &options.sort_order,
+            &options.pagination,
+            |dispatcher| sort_by.cursor(dispatcher),
+        ))
     }
 }
 
-fn paginate_dispatchers(dispatchers: Vec<&Dispatcher>, pagination: &Pagination) -> Vec<Dispatcher> {
-    match pagination {
-        Pagination::Offset { offset, limit } => dispatchers
-            .into_iter()
-            .skip(*offset)
-            .take(*limit)
-            .cloned()
-            .collect(),
-        Pagination::Cursor { after, limit } => {
-            if let Some(inner_ulid) = after {
-                let id = DispatcherId(*inner_ulid);
-                return dispatchers
-                    .into_iter()
-                    .skip_while(|dispatcher| dispatcher.id != id)
-                    .skip(1)
-                    .take(*limit)
-                    .cloned()
-                    .collect();
-            }
-
-            vec![]
-        }
-    }
-}
-
-fn sort_dispatchers<'a>(
-    mut dispatchers: Vec<&'a Dispatcher>,
-    sort_by: &DispatcherSortBy,
-    sort_order: &SortOrder,
-) -> Vec<&'a Dispatcher> {
-    dispatchers.sort_by(|a, b| {
-        let ord = match sort_by {
-            DispatcherSortBy::ProvisionAt => a.provisioned_at.cmp(&b.provisioned_at),
-        };
-
-        match sort_order {
-            SortOrder::Asc => ord,
-            SortOrder::Desc => ord.reverse(),
-        }
-    });
-
-    dispatchers
-}
-
 fn filter_dispatchers<'a>(
     dispatchers: &'a HashMap<DispatcherId, Dispatcher>,
+    orgs: &'a HashMap<DispatcherId, OrgId>,
+    tags: &'a HashMap<DispatcherId, Vec<String>>,
     filter: &DispatcherFilter,
 ) -> impl Iterator<Item = &'a Dispatcher> {
     dispatchers.values().filter(|dispatcher| {
+        if let Some(org_id) = filter.org_id
+            && orgs.get(&dispatcher.id) != Some(&org_id)
+        {
+            return false;
+        }
+
+        if let Some(wanted) = &filter.tags {
+            let tags = tags.get(&dispatcher.id);
+            if !wanted
+                .iter()
+                .all(|tag| tags.is_some_and(|tags| tags.contains(tag)))
+            {
+                return false;
+            }
+        }
+
         if let Some(locations) = &filter.locations
             && !locations.contains(&dispatcher.location)
         {
@@ -171,7 +303,7 @@ mod tests {
 
     use crate::registry::DispatcherRegistry;
     use crate::registry::filter::{
-        DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortOrder,
+        Cursor, DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortKey, SortOrder,
     };
     use ersha_core::{Dispatcher, DispatcherId, DispatcherState, H3Cell};
 
@@ -218,6 +350,11 @@ mod tests {
 
         let updated = reg.get(id).await.unwrap().unwrap();
         assert_eq!(updated.state, DispatcherState::Suspended);
+
+        reg.reactivate(id).await.unwrap();
+
+        let updated = reg.get(id).await.unwrap().unwrap();
+        assert_eq!(updated.state, DispatcherState::Active);
     }
 
     #[tokio::test]
@@ -316,7 +453,10 @@ mod tests {
             sort_by: DispatcherSortBy::ProvisionAt,
             sort_order: SortOrder::Asc,
             pagination: Pagination::Cursor {
-                after: Some(id1.0),
+                after: Some(Cursor::new(
+                    SortKey::Time(Timestamp::from_second(10).unwrap()),
+                    id1.0,
+                )),
                 limit: 1,
             },
         };
@@ -325,4 +465,59 @@ mod tests {
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].id, id2);
     }
+
+    #[tokio::test]
+    async fn test_cursor_pagination_first_page() {
+        let reg = dispatcher_registry();
+        let id1 = DispatcherId(Ulid::new());
+        let id2 = DispatcherId(Ulid::new());
+
+        reg.batch_register(vec![
+            dispatcher(
+                id1,
+                DispatcherState::Active,
+                Timestamp::from_second(10).unwrap(),
+            ),
+            dispatcher(
+                id2,
+                DispatcherState::Active,
+                Timestamp::from_second(20).unwrap(),
+            ),
+        ])
+        .await
+        .unwrap();
+
+        let options = QueryOptions {
+            filter: DispatcherFilter::default(),
+            sort_by: DispatcherSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Cursor {
+                after: None,
+                limit: 1,
+            },
+        };
+
+        let results = reg.list(options).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, id1);
+    }
+
+    #[tokio::test]
+    async fn test_secret_survives_update() {
+        let reg = dispatcher_registry();
+        let id = DispatcherId(Ulid::new());
+
+        assert!(reg.set_secret(id, Some("s3cret".into())).await.is_err());
+
+        reg.register(dispatcher(id, DispatcherState::Active, Timestamp::now()))
+            .await
+            .unwrap();
+        reg.set_secret(id, Some("s3cret".into())).await.unwrap();
+        reg.suspend(id).await.unwrap();
+
+        assert_eq!(reg.get_secret(id).await.unwrap().as_deref(), Some("s3cret"));
+
+        reg.set_secret(id, None).await.unwrap();
+        assert_eq!(reg.get_secret(id).await.unwrap(), None);
+    }
 }
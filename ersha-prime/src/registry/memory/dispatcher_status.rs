@@ -0,0 +1,96 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use ersha_core::DispatcherId;
+use tokio::sync::RwLock;
+
+use crate::health::DispatcherReport;
+use crate::registry::DispatcherStatusRegistry;
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryDispatcherStatusRegistry {
+    reports: Arc<RwLock<HashMap<DispatcherId, DispatcherReport>>>,
+}
+
+impl InMemoryDispatcherStatusRegistry {
+    pub fn new() -> Self {
+        Self {
+            reports: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryDispatcherStatusRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl DispatcherStatusRegistry for InMemoryDispatcherStatusRegistry {
+    type Error = InMemoryError;
+
+    async fn record(&self, report: DispatcherReport) -> Result<(), Self::Error> {
+        let mut reports = self.reports.write().await;
+        let _ = reports.insert(report.status.dispatcher_id, report);
+
+        Ok(())
+    }
+
+    async fn latest(&self, id: DispatcherId) -> Result<Option<DispatcherReport>, Self::Error> {
+        let reports = self.reports.read().await;
+        Ok(reports.get(&id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<DispatcherReport>, Self::Error> {
+        let reports = self.reports.read().await;
+        Ok(reports.values().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DispatcherId, DispatcherStatus, LinkQuality};
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::InMemoryDispatcherStatusRegistry;
+    use crate::health::DispatcherReport;
+    use crate::registry::DispatcherStatusRegistry;
+
+    fn report(dispatcher_id: DispatcherId, pending_readings: u64) -> DispatcherReport {
+        DispatcherReport {
+            status: DispatcherStatus {
+                dispatcher_id,
+                pending_readings,
+                pending_statuses: 0,
+                link: LinkQuality {
+                    rtt_ms: None,
+                    failed_uploads: 0,
+                },
+                uptime_seconds: 60,
+                timestamp: Timestamp::now(),
+            },
+            received_at: Timestamp::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_keeps_latest() {
+        let reg = InMemoryDispatcherStatusRegistry::new();
+        let id = DispatcherId(Ulid::new());
+
+        reg.record(report(id, 10)).await.unwrap();
+        reg.record(report(id, 3)).await.unwrap();
+        reg.record(report(DispatcherId(Ulid::new()), 0))
+            .await
+            .unwrap();
+
+        let latest = reg.latest(id).await.unwrap().unwrap();
+        assert_eq!(latest.status.pending_readings, 3);
+        assert_eq!(reg.list().await.unwrap().len(), 2);
+        assert_eq!(reg.latest(DispatcherId(Ulid::new())).await.unwrap(), None);
+    }
+}
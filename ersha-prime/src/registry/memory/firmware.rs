@@ -0,0 +1,132 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use ersha_core::DeviceId;
+use jiff::Timestamp;
+use tokio::sync::RwLock;
+
+use crate::firmware::{
+    DeviceRolloutState, FirmwareId, FirmwareImage, Rollout, RolloutDevice, RolloutId, RolloutState,
+};
+use crate::registry::FirmwareRegistry;
+
+use super::InMemoryError;
+
+#[derive(Clone, Default)]
+pub struct InMemoryFirmwareRegistry {
+    images: Arc<RwLock<HashMap<FirmwareId, FirmwareImage>>>,
+    rollouts: Arc<RwLock<HashMap<RolloutId, Rollout>>>,
+    /// Devices of each rollout, in the order they were enrolled
+    devices: Arc<RwLock<HashMap<RolloutId, Vec<RolloutDevice>>>>,
+}
+
+impl InMemoryFirmwareRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl FirmwareRegistry for InMemoryFirmwareRegistry {
+    type Error = InMemoryError;
+
+    async fn add_image(&self, image: FirmwareImage) -> Result<(), Self::Error> {
+        let mut images = self.images.write().await;
+        images.insert(image.id, image);
+
+        Ok(())
+    }
+
+    async fn image(&self, id: FirmwareId) -> Result<Option<FirmwareImage>, Self::Error> {
+        let images = self.images.read().await;
+        Ok(images.get(&id).cloned())
+    }
+
+    async fn images(&self) -> Result<Vec<FirmwareImage>, Self::Error> {
+        let images = self.images.read().await;
+        let mut images: Vec<FirmwareImage> = images.values().cloned().collect();
+        images.sort_by_key(|image| std::cmp::Reverse(image.id.0));
+
+        Ok(images)
+    }
+
+    async fn create_rollout(&self, rollout: Rollout) -> Result<(), Self::Error> {
+        let mut rollouts = self.rollouts.write().await;
+        rollouts.insert(rollout.id, rollout);
+
+        Ok(())
+    }
+
+    async fn rollout(&self, id: RolloutId) -> Result<Option<Rollout>, Self::Error> {
+        let rollouts = self.rollouts.read().await;
+        Ok(rollouts.get(&id).cloned())
+    }
+
+    async fn rollouts(&self) -> Result<Vec<Rollout>, Self::Error> {
+        let rollouts = self.rollouts.read().await;
+        let mut rollouts: Vec<Rollout> = rollouts.values().cloned().collect();
+        rollouts.sort_by_key(|rollout| std::cmp::Reverse(rollout.id.0));
+
+        Ok(rollouts)
+    }
+
+    async fn set_rollout_state(
+        &self,
+        id: RolloutId,
+        state: RolloutState,
+    ) -> Result<(), Self::Error> {
+        let mut rollouts = self.rollouts.write().await;
+        let rollout = rollouts.get_mut(&id).ok_or(InMemoryError::NotFound)?;
+        rollout.state = state;
+
+        Ok(())
+    }
+
+    async fn enroll(
+        &self,
+        id: RolloutId,
+        devices: Vec<DeviceId>,
+        now: Timestamp,
+    ) -> Result<usize, Self::Error> {
+        let mut all = self.devices.write().await;
+        let enrolled = all.entry(id).or_default();
+
+        let mut added = 0;
+        for device_id in devices {
+            if enrolled.iter().any(|device| device.device_id == device_id) {
+                continue;
+            }
+            enrolled.push(RolloutDevice {
+                rollout_id: id,
+                device_id,
+                state: DeviceRolloutState::Pending,
+                command_id: None,
+                updated_at: now,
+            });
+            added += 1;
+        }
+
+        Ok(added)
+    }
+
+    async fn rollout_devices(&self, id: RolloutId) -> Result<Vec<RolloutDevice>, Self::Error> {
+        let all = self.devices.read().await;
+        Ok(all.get(&id).cloned().unwrap_or_default())
+    }
+
+    async fn update_rollout_devices(&self, devices: Vec<RolloutDevice>) -> Result<(), Self::Error> {
+        let mut all = self.devices.write().await;
+        for device in devices {
+            let Some(enrolled) = all.get_mut(&device.rollout_id).and_then(|enrolled| {
+                enrolled
+                    .iter_mut()
+                    .find(|enrolled| enrolled.device_id == device.device_id)
+            }) else {
+                continue;
+            };
+            *enrolled = device;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::group::{Group, GroupId};
+use crate::registry::GroupRegistry;
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryGroupRegistry {
+    groups: Arc<RwLock<HashMap<GroupId, Group>>>,
+}
+
+impl InMemoryGroupRegistry {
+    pub fn new() -> Self {
+        Self {
+            groups: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryGroupRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl GroupRegistry for InMemoryGroupRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, group: Group) -> Result<(), Self::Error> {
+        let mut groups = self.groups.write().await;
+        let _ = groups.insert(group.id, group);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: GroupId) -> Result<Option<Group>, Self::Error> {
+        let groups = self.groups.read().await;
+        Ok(groups.get(&id).cloned())
+    }
+
+    async fn update(&self, group: Group) -> Result<(), Self::Error> {
+        let mut groups = self.groups.write().await;
+        let existing = groups.get_mut(&group.id).ok_or(InMemoryError::NotFound)?;
+        *existing = group;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: GroupId) -> Result<(), Self::Error> {
+        let mut groups = self.groups.write().await;
+        groups.remove(&id).ok_or(InMemoryError::NotFound)?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Group>, Self::Error> {
+        let groups = self.groups.read().await;
+        let mut all: Vec<Group> = groups.values().cloned().collect();
+        all.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.0.cmp(&b.id.0)));
+
+        Ok(all)
+    }
+}
@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+
+use crate::registry::{
+    DeadLetterRegistry, DeviceStatusRegistry, IngestBatch, IngestRegistry, Ingested,
+    OutboxRegistry, ReadingRegistry,
+};
+
+use super::{
+    InMemoryDeadLetterRegistry, InMemoryDeviceStatusRegistry, InMemoryError,
+    InMemoryOutboxRegistry, InMemoryReadingRegistry,
+};
+
+/// Stores uploads in the readings, statuses, dead letters and outbox it was
+/// built with. None of those writes can fail, so a batch is never left half
+/// stored.
+#[derive(Clone)]
+pub struct InMemoryIngestRegistry {
+    readings: InMemoryReadingRegistry,
+    statuses: InMemoryDeviceStatusRegistry,
+    dead_letters: InMemoryDeadLetterRegistry,
+    outbox: InMemoryOutboxRegistry,
+}
+
+impl InMemoryIngestRegistry {
+    pub fn new(
+        readings: InMemoryReadingRegistry,
+        statuses: InMemoryDeviceStatusRegistry,
+        dead_letters: InMemoryDeadLetterRegistry,
+        outbox: InMemoryOutboxRegistry,
+    ) -> Self {
+        Self {
+            readings,
+            statuses,
+            dead_letters,
+            outbox,
+        }
+    }
+}
+
+#[async_trait]
+impl IngestRegistry for InMemoryIngestRegistry {
+    type Error = InMemoryError;
+
+    async fn ingest(&self, batch: IngestBatch) -> Result<Ingested, Self::Error> {
+        let readings = self.readings.batch_store(batch.readings).await?;
+        let statuses = self.statuses.batch_store(batch.statuses).await?;
+        if !batch.dead_letters.is_empty() {
+            self.dead_letters.record(batch.dead_letters).await?;
+        }
+        if !batch.events.is_empty() {
+            self.outbox.stage(batch.events).await?;
+        }
+
+        Ok(Ingested { readings, statuses })
+    }
+}
@@ -0,0 +1,68 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::irrigation::{IrrigationPlan, PlanId};
+use crate::registry::IrrigationRegistry;
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryIrrigationRegistry {
+    plans: Arc<RwLock<HashMap<PlanId, IrrigationPlan>>>,
+}
+
+impl InMemoryIrrigationRegistry {
+    pub fn new() -> Self {
+        Self {
+            plans: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryIrrigationRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl IrrigationRegistry for InMemoryIrrigationRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, plan: IrrigationPlan) -> Result<(), Self::Error> {
+        let mut plans = self.plans.write().await;
+        plans.insert(plan.id, plan);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: PlanId) -> Result<Option<IrrigationPlan>, Self::Error> {
+        let plans = self.plans.read().await;
+        Ok(plans.get(&id).cloned())
+    }
+
+    async fn update(&self, plan: IrrigationPlan) -> Result<(), Self::Error> {
+        let mut plans = self.plans.write().await;
+        let existing = plans.get_mut(&plan.id).ok_or(InMemoryError::NotFound)?;
+        *existing = plan;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: PlanId) -> Result<(), Self::Error> {
+        let mut plans = self.plans.write().await;
+        plans.remove(&id).ok_or(InMemoryError::NotFound)?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<IrrigationPlan>, Self::Error> {
+        let plans = self.plans.read().await;
+        let mut all: Vec<IrrigationPlan> = plans.values().cloned().collect();
+        all.sort_by_key(|plan| plan.id.0);
+
+        Ok(all)
+    }
+}
@@ -1,11 +1,283 @@
+mod aggregate;
+mod api_key;
+mod audit;
+mod bounded;
+mod command;
+mod contact;
+mod correction;
+mod dead_letter;
+mod derived;
 mod device;
 mod dispatcher;
+mod dispatcher_status;
+mod firmware;
+mod group;
+mod ingest;
+mod irrigation;
+mod org;
+mod outbox;
+mod reading;
+mod status;
+mod user;
+mod validation_rule;
+mod webhook;
 
+pub use aggregate::InMemoryAggregateRegistry;
+pub use api_key::InMemoryApiKeyRegistry;
+pub use audit::InMemoryAuditRegistry;
+pub use bounded::{MemoryLimits, MemoryStats};
+pub use command::InMemoryCommandRegistry;
+pub use contact::InMemoryContactRegistry;
+pub use correction::InMemoryCorrectionRegistry;
+pub use dead_letter::InMemoryDeadLetterRegistry;
+pub use derived::InMemoryDerivedMetricRegistry;
 pub use device::InMemoryDeviceRegistry;
 pub use dispatcher::InMemoryDispatcherRegistry;
+pub use dispatcher_status::InMemoryDispatcherStatusRegistry;
+pub use firmware::InMemoryFirmwareRegistry;
+pub use group::InMemoryGroupRegistry;
+pub use ingest::InMemoryIngestRegistry;
+pub use irrigation::InMemoryIrrigationRegistry;
+pub use org::InMemoryOrgRegistry;
+pub use outbox::InMemoryOutboxRegistry;
+pub use reading::InMemoryReadingRegistry;
+pub use status::InMemoryDeviceStatusRegistry;
+pub use user::InMemoryUserRegistry;
+pub use validation_rule::InMemoryValidationRuleRegistry;
+pub use webhook::InMemoryWebhookRegistry;
+
+use super::{
+    Registries, RegistryError,
+    filter::{Cursor, Pagination, SortOrder},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum InMemoryError {
     #[error("not found")]
     NotFound,
 }
+
+impl From<InMemoryError> for RegistryError {
+    fn from(error: InMemoryError) -> Self {
+        match error {
+            InMemoryError::NotFound => RegistryError::NotFound,
+        }
+    }
+}
+
+/// Sort `items` by their position in `order` and take the requested page.
+/// A cursor page starts at the first item past the cursor, found by binary
+/// search.
+fn paginate<T: Clone>(
+    items: Vec<&T>,
+    order: &SortOrder,
+    pagination: &Pagination,
+    cursor: impl Fn(&T) -> Cursor,
+) -> Vec<T> {
+    let mut sorted: Vec<(Cursor, &T)> =
+        items.into_iter().map(|item| (cursor(item), item)).collect();
+    sorted.sort_by(|(a, _), (b, _)| match order {
+        SortOrder::Asc => a.cmp(b),
+        SortOrder::Desc => b.cmp(a),
+    });
+
+    let (skip, limit) = match pagination {
+        Pagination::Offset { offset, limit } => (*offset, *limit),
+        Pagination::Cursor { after, limit } => {
+            let start = after.as_ref().map_or(0, |after| {
+                sorted.partition_point(|(position, _)| !after.precedes(position, order))
+            });
+            (start, *limit)
+        }
+    };
+
+    sorted
+        .into_iter()
+        .skip(skip)
+        .take(limit)
+        .map(|(_, item)| item.clone())
+        .collect()
+}
+
+/// Registries that keep everything in memory.
+///
+/// The device registry stages events in `outbox`, and `ingest` writes to
+/// `readings`, `statuses`, `dead_letters` and `outbox`, so each is built
+/// together with the registries it writes to; replace none of them alone.
+#[derive(Clone)]
+pub struct InMemoryRegistries {
+    pub devices: InMemoryDeviceRegistry,
+    pub dispatchers: InMemoryDispatcherRegistry,
+    pub readings: InMemoryReadingRegistry,
+    pub statuses: InMemoryDeviceStatusRegistry,
+    pub ingest: InMemoryIngestRegistry,
+    pub dispatcher_statuses: InMemoryDispatcherStatusRegistry,
+    pub aggregates: InMemoryAggregateRegistry,
+    pub derived_metrics: InMemoryDerivedMetricRegistry,
+    pub commands: InMemoryCommandRegistry,
+    pub irrigation: InMemoryIrrigationRegistry,
+    pub corrections: InMemoryCorrectionRegistry,
+    pub dead_letters: InMemoryDeadLetterRegistry,
+    pub audit: InMemoryAuditRegistry,
+    pub webhooks: InMemoryWebhookRegistry,
+    pub contacts: InMemoryContactRegistry,
+    pub outbox: InMemoryOutboxRegistry,
+    pub groups: InMemoryGroupRegistry,
+    pub validation_rules: InMemoryValidationRuleRegistry,
+    pub firmware: InMemoryFirmwareRegistry,
+    pub orgs: InMemoryOrgRegistry,
+    pub api_keys: InMemoryApiKeyRegistry,
+    pub users: InMemoryUserRegistry,
+}
+
+impl Default for InMemoryRegistries {
+    fn default() -> Self {
+        let outbox = InMemoryOutboxRegistry::new();
+        let readings = InMemoryReadingRegistry::default();
+        let statuses = InMemoryDeviceStatusRegistry::default();
+        let dead_letters = InMemoryDeadLetterRegistry::default();
+
+        Self {
+            devices: InMemoryDeviceRegistry::new().with_outbox(outbox.clone()),
+            dispatchers: InMemoryDispatcherRegistry::default(),
+            ingest: InMemoryIngestRegistry::new(
+                readings.clone(),
+                statuses.clone(),
+                dead_letters.clone(),
+                outbox.clone(),
+            ),
+            readings,
+            statuses,
+            dispatcher_statuses: InMemoryDispatcherStatusRegistry::default(),
+            aggregates: InMemoryAggregateRegistry::default(),
+            derived_metrics: InMemoryDerivedMetricRegistry::default(),
+            commands: InMemoryCommandRegistry::default(),
+            irrigation: InMemoryIrrigationRegistry::default(),
+            corrections: InMemoryCorrectionRegistry::default(),
+            dead_letters,
+            audit: InMemoryAuditRegistry::default(),
+            webhooks: InMemoryWebhookRegistry::default(),
+            contacts: InMemoryContactRegistry::default(),
+            outbox,
+            groups: InMemoryGroupRegistry::default(),
+            validation_rules: InMemoryValidationRuleRegistry::default(),
+            firmware: InMemoryFirmwareRegistry::default(),
+            orgs: InMemoryOrgRegistry::default(),
+            api_keys: InMemoryApiKeyRegistry::default(),
+            users: InMemoryUserRegistry::default(),
+        }
+    }
+}
+
+impl Registries for InMemoryRegistries {
+    type Devices = InMemoryDeviceRegistry;
+    type Dispatchers = InMemoryDispatcherRegistry;
+    type Readings = InMemoryReadingRegistry;
+    type Statuses = InMemoryDeviceStatusRegistry;
+    type Ingest = InMemoryIngestRegistry;
+    type DispatcherStatuses = InMemoryDispatcherStatusRegistry;
+    type Aggregates = InMemoryAggregateRegistry;
+    type DerivedMetrics = InMemoryDerivedMetricRegistry;
+    type Commands = InMemoryCommandRegistry;
+    type Irrigation = InMemoryIrrigationRegistry;
+    type Corrections = InMemoryCorrectionRegistry;
+    type DeadLetters = InMemoryDeadLetterRegistry;
+    type Audit = InMemoryAuditRegistry;
+    type Webhooks = InMemoryWebhookRegistry;
+    type Contacts = InMemoryContactRegistry;
+    type Outbox = InMemoryOutboxRegistry;
+    type Groups = InMemoryGroupRegistry;
+    type ValidationRules = InMemoryValidationRuleRegistry;
+    type Firmware = InMemoryFirmwareRegistry;
+    type Orgs = InMemoryOrgRegistry;
+    type ApiKeys = InMemoryApiKeyRegistry;
+    type Users = InMemoryUserRegistry;
+
+    fn devices(&self) -> &Self::Devices {
+        &self.devices
+    }
+
+    fn dispatchers(&self) -> &Self::Dispatchers {
+        &self.dispatchers
+    }
+
+    fn readings(&self) -> &Self::Readings {
+        &self.readings
+    }
+
+    fn statuses(&self) -> &Self::Statuses {
+        &self.statuses
+    }
+
+    fn ingest(&self) -> &Self::Ingest {
+        &self.ingest
+    }
+
+    fn dispatcher_statuses(&self) -> &Self::DispatcherStatuses {
+        &self.dispatcher_statuses
+    }
+
+    fn aggregates(&self) -> &Self::Aggregates {
+        &self.aggregates
+    }
+
+    fn derived_metrics(&self) -> &Self::DerivedMetrics {
+        &self.derived_metrics
+    }
+
+    fn commands(&self) -> &Self::Commands {
+        &self.commands
+    }
+
+    fn irrigation(&self) -> &Self::Irrigation {
+        &self.irrigation
+    }
+
+    fn corrections(&self) -> &Self::Corrections {
+        &self.corrections
+    }
+
+    fn dead_letters(&self) -> &Self::DeadLetters {
+        &self.dead_letters
+    }
+
+    fn audit(&self) -> &Self::Audit {
+        &self.audit
+    }
+
+    fn webhooks(&self) -> &Self::Webhooks {
+        &self.webhooks
+    }
+
+    fn contacts(&self) -> &Self::Contacts {
+        &self.contacts
+    }
+
+    fn outbox(&self) -> &Self::Outbox {
+        &self.outbox
+    }
+
+    fn groups(&self) -> &Self::Groups {
+        &self.groups
+    }
+
+    fn validation_rules(&self) -> &Self::ValidationRules {
+        &self.validation_rules
+    }
+
+    fn firmware(&self) -> &Self::Firmware {
+        &self.firmware
+    }
+
+    fn orgs(&self) -> &Self::Orgs {
+        &self.orgs
+    }
+
+    fn api_keys(&self) -> &Self::ApiKeys {
+        &self.api_keys
+    }
+
+    fn users(&self) -> &Self::Users {
+        &self.users
+    }
+}
@@ -0,0 +1,78 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::org::{Org, OrgId};
+use crate::registry::OrgRegistry;
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryOrgRegistry {
+    orgs: Arc<RwLock<HashMap<OrgId, Org>>>,
+    metadata_schemas: Arc<RwLock<HashMap<OrgId, serde_json::Value>>>,
+}
+
+impl InMemoryOrgRegistry {
+    pub fn new() -> Self {
+        Self {
+            orgs: Arc::new(RwLock::new(HashMap::new())),
+            metadata_schemas: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryOrgRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl OrgRegistry for InMemoryOrgRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, org: Org) -> Result<(), Self::Error> {
+        let mut orgs = self.orgs.write().await;
+        let _ = orgs.insert(org.id, org);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: OrgId) -> Result<Option<Org>, Self::Error> {
+        let orgs = self.orgs.read().await;
+        Ok(orgs.get(&id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<Org>, Self::Error> {
+        let orgs = self.orgs.read().await;
+        let mut all: Vec<Org> = orgs.values().cloned().collect();
+        all.sort_by_key(|org| org.id.0);
+
+        Ok(all)
+    }
+
+    async fn set_metadata_schema(
+        &self,
+        id: OrgId,
+        schema: Option<serde_json::Value>,
+    ) -> Result<(), Self::Error> {
+        if !self.orgs.read().await.contains_key(&id) {
+            return Err(InMemoryError::NotFound);
+        }
+
+        let mut schemas = self.metadata_schemas.write().await;
+        match schema {
+            Some(schema) => schemas.insert(id, schema),
+            None => schemas.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    async fn metadata_schema(&self, id: OrgId) -> Result<Option<serde_json::Value>, Self::Error> {
+        let schemas = self.metadata_schemas.read().await;
+        Ok(schemas.get(&id).cloned())
+    }
+}
@@ -0,0 +1,79 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::outbox::{OutboxEntry, OutboxState};
+use crate::registry::OutboxRegistry;
+use crate::webhook::EventId;
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryOutboxRegistry {
+    entries: Arc<RwLock<HashMap<EventId, OutboxEntry>>>,
+}
+
+impl InMemoryOutboxRegistry {
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryOutboxRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl OutboxRegistry for InMemoryOutboxRegistry {
+    type Error = InMemoryError;
+
+    async fn stage(&self, new: Vec<OutboxEntry>) -> Result<(), Self::Error> {
+        let mut entries = self.entries.write().await;
+        for entry in new {
+            entries.insert(entry.event.id, entry);
+        }
+
+        Ok(())
+    }
+
+    async fn due(
+        &self,
+        now: jiff::Timestamp,
+        limit: usize,
+    ) -> Result<Vec<OutboxEntry>, Self::Error> {
+        let entries = self.entries.read().await;
+        let mut due: Vec<&OutboxEntry> = entries
+            .values()
+            .filter(|e| e.state == OutboxState::Pending && e.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|e| (e.next_attempt_at, e.event.id.0));
+
+        Ok(due.into_iter().take(limit).cloned().collect())
+    }
+
+    async fn update(&self, entry: OutboxEntry) -> Result<(), Self::Error> {
+        let mut entries = self.entries.write().await;
+        let existing = entries
+            .get_mut(&entry.event.id)
+            .ok_or(InMemoryError::NotFound)?;
+        *existing = entry;
+
+        Ok(())
+    }
+
+    async fn poisoned(&self, limit: usize) -> Result<Vec<OutboxEntry>, Self::Error> {
+        let entries = self.entries.read().await;
+        let mut poisoned: Vec<&OutboxEntry> = entries
+            .values()
+            .filter(|e| e.state == OutboxState::Poisoned)
+            .collect();
+        poisoned.sort_by_key(|e| std::cmp::Reverse(e.event.id.0));
+
+        Ok(poisoned.into_iter().take(limit).cloned().collect())
+    }
+}
@@ -0,0 +1,624 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use ersha_core::{DeviceId, Percentage, ReadingId, SensorId, SensorReading};
+use tokio::sync::RwLock;
+use ulid::Ulid;
+
+use crate::metrics;
+use crate::quality::{QualityWindow, SensorQuality};
+use crate::registry::{
+    ReadingRegistry,
+    filter::{QueryOptions, ReadingFilter, ReadingSortBy},
+};
+use crate::rollup::metric_value;
+use crate::validation::QualityStatus;
+
+use super::bounded::{BoundedStore, MemoryLimits, MemoryStats};
+use super::{InMemoryError, paginate};
+
+#[derive(Clone)]
+pub struct InMemoryReadingRegistry {
+    readings: Arc<RwLock<BoundedStore<SensorReading>>>,
+    /// Statuses of readings that aren't good
+    quality: Arc<RwLock<HashMap<ReadingId, QualityStatus>>>,
+}
+
+impl InMemoryReadingRegistry {
+    pub fn new() -> Self {
+        Self::with_limits(MemoryLimits::default())
+    }
+
+    /// Keep at most as many readings as `limits` allow, evicting the oldest
+    /// as new ones are stored.
+    pub fn with_limits(limits: MemoryLimits) -> Self {
+        Self {
+            readings: Arc::new(RwLock::new(BoundedStore::new(limits))),
+            quality: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn stats(&self) -> MemoryStats {
+        self.readings.read().await.stats()
+    }
+}
+
+impl Default for InMemoryReadingRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ReadingRegistry for InMemoryReadingRegistry {
+    type Error = InMemoryError;
+
+    async fn store(&self, reading: SensorReading) -> Result<(), Self::Error> {
+        let mut readings = self.readings.write().await;
+        readings.insert(reading);
+        evict(&mut readings);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: ReadingId) -> Result<Option<SensorReading>, Self::Error> {
+        let readings = self.readings.read().await;
+        Ok(readings.get(&id).cloned())
+    }
+
+    async fn batch_store(&self, new: Vec<SensorReading>) -> Result<Vec<ReadingId>, Self::Error> {
+        let mut readings = self.readings.write().await;
+        let mut stored = Vec::with_capacity(new.len());
+        for reading in new {
+            let id = reading.id;
+            if readings.insert_new(reading) {
+                stored.push(id);
+            }
+        }
+        evict(&mut readings);
+
+        Ok(stored)
+    }
+
+    async fn latest_per_sensor(&self, device: DeviceId) -> Result<Vec<SensorReading>, Self::Error> {
+        let readings = self.readings.read().await;
+        let mut latest: HashMap<SensorId, &SensorReading> = HashMap::new();
+        for reading in readings.values().filter(|r| r.device_id == device) {
+            latest
+                .entry(reading.sensor_id)
+                .and_modify(|current| {
+                    if (reading.timestamp, reading.id.0) > (current.timestamp, current.id.0) {
+                        *current = reading;
+                    }
+                })
+                .or_insert(reading);
+        }
+
+        let mut latest: Vec<SensorReading> = latest.into_values().cloned().collect();
+        latest.sort_by_key(|reading| reading.sensor_id.0);
+
+        Ok(latest)
+    }
+
+    async fn latest_matching(
+        &self,
+        filter: ReadingFilter,
+    ) -> Result<Vec<SensorReading>, Self::Error> {
+        let readings = self.readings.read().await;
+        let quality = self.quality.read().await;
+        let mut latest: HashMap<(DeviceId, SensorId), &SensorReading> = HashMap::new();
+        for reading in filter_readings(&readings, &quality, &filter) {
+            latest
+                .entry((reading.device_id, reading.sensor_id))
+                .and_modify(|current| {
+                    if (reading.timestamp, reading.id.0) > (current.timestamp, current.id.0) {
+                        *current = reading;
+                    }
+                })
+                .or_insert(reading);
+        }
+
+        let mut latest: Vec<SensorReading> = latest.into_values().cloned().collect();
+        latest.sort_by_key(|reading| (reading.device_id.0, reading.sensor_id.0));
+
+        Ok(latest)
+    }
+
+    async fn data_quality(
+        &self,
+        device: DeviceId,
+        window: QualityWindow,
+    ) -> Result<Vec<SensorQuality>, Self::Error> {
+        let readings = self.readings.read().await;
+        let mut samples: BTreeMap<Ulid, Vec<(jiff::Timestamp, Percentage)>> = BTreeMap::new();
+        for reading in readings
+            .values()
+            .filter(|r| r.device_id == device && window.contains(r.timestamp))
+        {
+            samples
+                .entry(reading.sensor_id.0)
+                .or_default()
+                .push((reading.timestamp, reading.confidence));
+        }
+
+        Ok(samples
+            .into_iter()
+            .map(|(id, samples)| SensorQuality::compute(SensorId(id), &window, samples))
+            .collect())
+    }
+
+    async fn purge(&self, before: jiff::Timestamp, limit: usize) -> Result<usize, Self::Error> {
+        let mut readings = self.readings.write().await;
+        let expired = readings.oldest(before, limit);
+        for id in &expired {
+            readings.remove(*id);
+        }
+
+        Ok(expired.len())
+    }
+
+    async fn set_quality(
+        &self,
+        statuses: Vec<(ReadingId, QualityStatus)>,
+    ) -> Result<(), Self::Error> {
+        let readings = self.readings.read().await;
+        let mut quality = self.quality.write().await;
+        // Forget readings evicted or purged since.
+        quality.retain(|id, _| readings.contains_key(id));
+        for (id, status) in statuses {
+            if !readings.contains_key(&id) {
+                continue;
+            }
+            if status == QualityStatus::Good {
+                quality.remove(&id);
+            } else {
+                quality.insert(id, status);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn count(&self, filter: Option<ReadingFilter>) -> Result<usize, Self::Error> {
+        let readings = self.readings.read().await;
+        if let Some(filter) = filter {
+            let quality = self.quality.read().await;
+            return Ok(filter_readings(&readings, &quality, &filter).count());
+        }
+
+        Ok(readings.len())
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<ReadingFilter, ReadingSortBy>,
+    ) -> Result<Vec<SensorReading>, Self::Error> {
+        let readings = self.readings.read().await;
+        let quality = self.quality.read().await;
+        let filtered: Vec<&SensorReading> =
+            filter_readings(&readings, &quality, &options.filter).collect();
+        let sort_by = options.sort_by;
+
+        Ok(paginate(
+            filtered,
+            &options.sort_order,
+            &options.pagination,
+            |reading| sort_by.cursor(reading),
+        ))
+    }
+}
+
+fn filter_readings<'a>(
+    readings: &'a HashMap<ReadingId, SensorReading>,
+    quality: &'a HashMap<ReadingId, QualityStatus>,
+    filter: &'a ReadingFilter,
+) -> impl Iterator<Item = &'a SensorReading> {
+    readings.values().filter(move |reading| {
+        if let Some(device_ids) = &filter.device_ids
+            && !device_ids.is_empty()
+            && !device_ids.contains(&reading.device_id)
+        {
+            return false;
+        }
+
+        if let Some(sensor_ids) = &filter.sensor_ids
+            && !sensor_ids.is_empty()
+            && !sensor_ids.contains(&reading.sensor_id)
+        {
+            return false;
+        }
+
+        if let Some(dispatcher_ids) = &filter.dispatcher_ids
+            && !dispatcher_ids.is_empty()
+            && !dispatcher_ids.contains(&reading.dispatcher_id)
+        {
+            return false;
+        }
+
+        if let Some(kinds) = &filter.metric_kinds
+            && !kinds.is_empty()
+            && !kinds.contains(&reading.metric.kind())
+        {
+            return false;
+        }
+
+        if let Some(locations) = &filter.locations
+            && !locations.is_empty()
+            && !locations.contains(&reading.location)
+        {
+            return false;
+        }
+
+        if let Some(cells) = &filter.within
+            && !cells.is_empty()
+            && !cells.iter().any(|cell| reading.location.is_within(*cell))
+        {
+            return false;
+        }
+
+        if let Some(after) = filter.after
+            && reading.timestamp < after
+        {
+            return false;
+        }
+
+        if let Some(before) = filter.before
+            && reading.timestamp > before
+        {
+            return false;
+        }
+
+        if let Some(confidence) = &filter.confidence
+            && !confidence.contains(&reading.confidence.0)
+        {
+            return false;
+        }
+
+        if let Some(range) = &filter.value_range
+            && !range.contains(&metric_value(&reading.metric))
+        {
+            return false;
+        }
+
+        if let Some(statuses) = &filter.quality
+            && !statuses.is_empty()
+            && !statuses.contains(&quality.get(&reading.id).copied().unwrap_or_default())
+        {
+            return false;
+        }
+
+        true
+    })
+}
+
+fn evict(readings: &mut BoundedStore<SensorReading>) {
+    let evicted = readings.evict(jiff::Timestamp::now());
+    metrics::record_memory_stats("readings", readings.len(), evicted);
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, Percentage, ReadingId, SensorKind, SensorMetric, SensorReading};
+    use jiff::Timestamp;
+    use ordered_float::NotNan;
+    use ulid::Ulid;
+
+    use super::InMemoryReadingRegistry;
+    use crate::fixtures;
+    use crate::quality::QualityWindow;
+    use crate::registry::ReadingRegistry;
+    use crate::registry::filter::{
+        Pagination, QueryOptions, ReadingFilter, ReadingSortBy, SortOrder,
+    };
+    use crate::registry::memory::{MemoryLimits, MemoryStats};
+
+    fn reading(
+        device_id: DeviceId,
+        metric: SensorMetric,
+        second: i64,
+        confidence: u8,
+    ) -> SensorReading {
+        SensorReading {
+            device_id,
+            metric,
+            confidence: Percentage(confidence),
+            timestamp: Timestamp::from_second(second).unwrap(),
+            ..fixtures::reading()
+        }
+    }
+
+    fn moisture(value: u8) -> SensorMetric {
+        SensorMetric::SoilMoisture {
+            value: Percentage(value),
+        }
+    }
+
+    fn options(
+        filter: ReadingFilter,
+        pagination: Pagination,
+    ) -> QueryOptions<ReadingFilter, ReadingSortBy> {
+        QueryOptions {
+            filter,
+            sort_by: ReadingSortBy::Timestamp,
+            sort_order: SortOrder::Asc,
+            pagination,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_and_get() {
+        let reg = InMemoryReadingRegistry::new();
+        let r = reading(DeviceId(Ulid::new()), moisture(40), 10, 90);
+        let id = r.id;
+
+        reg.store(r.clone()).await.unwrap();
+
+        assert_eq!(reg.get(id).await.unwrap(), Some(r));
+        assert_eq!(reg.count(None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_store_skips_existing() {
+        let reg = InMemoryReadingRegistry::new();
+        let device = DeviceId(Ulid::new());
+        let first = reading(device, moisture(40), 10, 90);
+        let second = reading(device, moisture(41), 20, 90);
+
+        let stored = reg.batch_store(vec![first.clone()]).await.unwrap();
+        assert_eq!(stored, vec![first.id]);
+
+        let replay = SensorReading {
+            confidence: Percentage(10),
+            ..first.clone()
+        };
+        let stored = reg.batch_store(vec![replay, second.clone()]).await.unwrap();
+        assert_eq!(stored, vec![second.id]);
+        assert_eq!(reg.get(first.id).await.unwrap(), Some(first));
+        assert_eq!(reg.count(None).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_filters() {
+        let reg = InMemoryReadingRegistry::new();
+        let device = DeviceId(Ulid::new());
+        let other = DeviceId(Ulid::new());
+
+        reg.batch_store(vec![
+            reading(device, moisture(40), 10, 90),
+            reading(device, moisture(41), 20, 50),
+            reading(
+                device,
+                SensorMetric::AirTemp {
+                    value: NotNan::new(21.5).unwrap(),
+                },
+                30,
+                95,
+            ),
+            reading(other, moisture(42), 40, 99),
+        ])
+        .await
+        .unwrap();
+
+        let by_device = ReadingFilter::builder().device_ids([device]).build();
+        assert_eq!(reg.count(Some(by_device)).await.unwrap(), 3);
+
+        let by_kind = ReadingFilter::builder()
+            .device_ids([device])
+            .metric_kinds([SensorKind::SoilMoisture])
+            .build();
+        assert_eq!(reg.count(Some(by_kind)).await.unwrap(), 2);
+
+        let by_time = ReadingFilter::builder()
+            .after(Timestamp::from_second(15).unwrap())
+            .before(Timestamp::from_second(35).unwrap())
+            .build();
+        assert_eq!(reg.count(Some(by_time)).await.unwrap(), 2);
+
+        let by_confidence = ReadingFilter::builder().confidence(80..=95).build();
+        assert_eq!(reg.count(Some(by_confidence)).await.unwrap(), 2);
+
+        let dry = ReadingFilter::builder()
+            .metric_kinds([SensorKind::SoilMoisture])
+            .value_range(f64::NEG_INFINITY..=41.0)
+            .build();
+        assert_eq!(reg.count(Some(dry)).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_latest_per_sensor() {
+        let reg = InMemoryReadingRegistry::new();
+        let device = DeviceId(Ulid::new());
+        let old = reading(device, moisture(40), 10, 90);
+        let new = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(20).unwrap(),
+            ..old.clone()
+        };
+        let other_sensor = reading(device, moisture(41), 5, 90);
+
+        reg.batch_store(vec![
+            old,
+            new.clone(),
+            other_sensor.clone(),
+            reading(DeviceId(Ulid::new()), moisture(42), 30, 90),
+        ])
+        .await
+        .unwrap();
+
+        let mut expected = vec![new, other_sensor];
+        expected.sort_by_key(|r| r.sensor_id.0);
+        assert_eq!(reg.latest_per_sensor(device).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_latest_matching() {
+        let reg = InMemoryReadingRegistry::new();
+        let device = DeviceId(Ulid::new());
+        let old = reading(device, moisture(40), 10, 90);
+        let new = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(20).unwrap(),
+            ..old.clone()
+        };
+        // Past the window, so the sensor's latest within it is `new`.
+        let later = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(90).unwrap(),
+            ..old.clone()
+        };
+        // The same sensor id on another device is another sensor.
+        let elsewhere = SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            ..old.clone()
+        };
+        let temperature = reading(
+            device,
+            SensorMetric::AirTemp {
+                value: NotNan::new(21.5).unwrap(),
+            },
+            15,
+            90,
+        );
+        reg.batch_store(vec![
+            old,
+            new.clone(),
+            later,
+            elsewhere.clone(),
+            temperature,
+        ])
+        .await
+        .unwrap();
+
+        let filter = ReadingFilter::builder()
+            .metric_kinds([SensorKind::SoilMoisture])
+            .before(Timestamp::from_second(60).unwrap())
+            .build();
+        let mut expected = vec![new, elsewhere];
+        expected.sort_by_key(|r| (r.device_id.0, r.sensor_id.0));
+        assert_eq!(reg.latest_matching(filter).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_pagination() {
+        let reg = InMemoryReadingRegistry::new();
+        let device = DeviceId(Ulid::new());
+        let readings: Vec<_> = (0..5)
+            .map(|i| reading(device, moisture(40), i, 90))
+            .collect();
+        let ids: Vec<_> = readings.iter().map(|r| r.id).collect();
+        reg.batch_store(readings).await.unwrap();
+
+        let first = reg
+            .list(options(
+                ReadingFilter::default(),
+                Pagination::Cursor {
+                    after: None,
+                    limit: 2,
+                },
+            ))
+            .await
+            .unwrap();
+        assert_eq!(first.iter().map(|r| r.id).collect::<Vec<_>>(), ids[..2]);
+
+        let second = reg
+            .list(options(
+                ReadingFilter::default(),
+                Pagination::Cursor {
+                    after: Some(ReadingSortBy::Timestamp.cursor(&first[1])),
+                    limit: 2,
+                },
+            ))
+            .await
+            .unwrap();
+        assert_eq!(second.iter().map(|r| r.id).collect::<Vec<_>>(), ids[2..4]);
+    }
+
+    #[tokio::test]
+    async fn test_data_quality_per_sensor() {
+        let reg = InMemoryReadingRegistry::new();
+        let device = DeviceId(Ulid::new());
+        let first = reading(device, moisture(40), 0, 90);
+        let second = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(60).unwrap(),
+            confidence: Percentage(30),
+            ..first.clone()
+        };
+        let outside = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(500).unwrap(),
+            ..first.clone()
+        };
+        let other_sensor = reading(device, moisture(41), 30, 90);
+
+        reg.batch_store(vec![
+            first.clone(),
+            second,
+            outside,
+            other_sensor.clone(),
+            reading(DeviceId(Ulid::new()), moisture(42), 30, 90),
+        ])
+        .await
+        .unwrap();
+
+        let window = QualityWindow::new(
+            Timestamp::from_second(0).unwrap(),
+            Timestamp::from_second(120).unwrap(),
+            jiff::SignedDuration::from_secs(60),
+        );
+        let quality = reg.data_quality(device, window).await.unwrap();
+
+        let mut sensors = vec![first.sensor_id, other_sensor.sensor_id];
+        sensors.sort_by_key(|id| id.0);
+        assert_eq!(
+            quality.iter().map(|q| q.sensor_id).collect::<Vec<_>>(),
+            sensors
+        );
+        let first = quality
+            .iter()
+            .find(|q| q.sensor_id == first.sensor_id)
+            .unwrap();
+        assert_eq!((first.expected, first.received), (2, 2));
+        assert_eq!(first.bad_percent, 50.0);
+    }
+
+    #[tokio::test]
+    async fn test_oldest_readings_are_evicted_beyond_max_entries() {
+        let reg = InMemoryReadingRegistry::with_limits(MemoryLimits {
+            max_entries: Some(2),
+            max_age_secs: None,
+        });
+        let device = DeviceId(Ulid::new());
+        let oldest = reading(device, moisture(40), 10, 90);
+        let middle = reading(device, moisture(41), 20, 90);
+        let newest = reading(device, moisture(42), 30, 90);
+
+        reg.batch_store(vec![middle.clone(), oldest.clone()])
+            .await
+            .unwrap();
+        reg.store(newest.clone()).await.unwrap();
+
+        assert_eq!(reg.get(oldest.id).await.unwrap(), None);
+        assert_eq!(reg.count(None).await.unwrap(), 2);
+        assert_eq!(
+            reg.stats().await,
+            MemoryStats {
+                entries: 2,
+                evicted: 1,
+            }
+        );
+
+        // Purging keeps the age index in step with the entries.
+        assert_eq!(
+            reg.purge(Timestamp::from_second(20).unwrap(), 10)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(reg.get(newest.id).await.unwrap(), Some(newest));
+    }
+}
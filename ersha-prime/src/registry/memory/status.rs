@@ -0,0 +1,325 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use ersha_core::{DeviceId, DeviceStatus, StatusId};
+use tokio::sync::RwLock;
+
+use crate::metrics;
+use crate::registry::{
+    DeviceStatusRegistry,
+    filter::{QueryOptions, StatusFilter, StatusSortBy},
+};
+
+use super::bounded::{BoundedStore, MemoryLimits, MemoryStats};
+use super::{InMemoryError, paginate};
+
+#[derive(Clone)]
+pub struct InMemoryDeviceStatusRegistry {
+    statuses: Arc<RwLock<BoundedStore<DeviceStatus>>>,
+}
+
+impl InMemoryDeviceStatusRegistry {
+    pub fn new() -> Self {
+        Self::with_limits(MemoryLimits::default())
+    }
+
+    /// Keep at most as many statuses as `limits` allow, evicting the oldest
+    /// as new ones are stored.
+    pub fn with_limits(limits: MemoryLimits) -> Self {
+        Self {
+            statuses: Arc::new(RwLock::new(BoundedStore::new(limits))),
+        }
+    }
+
+    pub async fn stats(&self) -> MemoryStats {
+        self.statuses.read().await.stats()
+    }
+}
+
+impl Default for InMemoryDeviceStatusRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl DeviceStatusRegistry for InMemoryDeviceStatusRegistry {
+    type Error = InMemoryError;
+
+    async fn store(&self, status: DeviceStatus) -> Result<(), Self::Error> {
+        let mut statuses = self.statuses.write().await;
+        statuses.insert(status);
+        evict(&mut statuses);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: StatusId) -> Result<Option<DeviceStatus>, Self::Error> {
+        let statuses = self.statuses.read().await;
+        Ok(statuses.get(&id).cloned())
+    }
+
+    async fn batch_store(&self, new: Vec<DeviceStatus>) -> Result<Vec<StatusId>, Self::Error> {
+        let mut statuses = self.statuses.write().await;
+        let mut stored = Vec::with_capacity(new.len());
+        for status in new {
+            let id = status.id;
+            if statuses.insert_new(status) {
+                stored.push(id);
+            }
+        }
+        evict(&mut statuses);
+
+        Ok(stored)
+    }
+
+    async fn latest(&self, device: DeviceId) -> Result<Option<DeviceStatus>, Self::Error> {
+        let statuses = self.statuses.read().await;
+        let latest = statuses
+            .values()
+            .filter(|status| status.device_id == device)
+            .max_by_key(|status| (status.timestamp, status.id.0));
+
+        Ok(latest.cloned())
+    }
+
+    async fn purge(&self, before: jiff::Timestamp, limit: usize) -> Result<usize, Self::Error> {
+        let mut statuses = self.statuses.write().await;
+        let expired = statuses.oldest(before, limit);
+        for id in &expired {
+            statuses.remove(*id);
+        }
+
+        Ok(expired.len())
+    }
+
+    async fn count(&self, filter: Option<StatusFilter>) -> Result<usize, Self::Error> {
+        let statuses = self.statuses.read().await;
+        if let Some(filter) = filter {
+            return Ok(filter_statuses(&statuses, &filter).count());
+        }
+
+        Ok(statuses.len())
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<StatusFilter, StatusSortBy>,
+    ) -> Result<Vec<DeviceStatus>, Self::Error> {
+        let statuses = self.statuses.read().await;
+        let filtered: Vec<&DeviceStatus> = filter_statuses(&statuses, &options.filter).collect();
+
+        let sort_by = options.sort_by;
+
+        Ok(paginate(
+            filtered,
+            &options.sort_order,
+            &options.pagination,
+            |status| sort_by.cursor(status),
+        ))
+    }
+}
+
+fn filter_statuses<'a>(
+    statuses: &'a HashMap<StatusId, DeviceStatus>,
+    filter: &StatusFilter,
+) -> impl Iterator<Item = &'a DeviceStatus> {
+    statuses.values().filter(|status| {
+        if let Some(device_ids) = &filter.device_ids
+            && !device_ids.is_empty()
+            && !device_ids.contains(&status.device_id)
+        {
+            return false;
+        }
+
+        if let Some(dispatcher_ids) = &filter.dispatcher_ids
+            && !dispatcher_ids.is_empty()
+            && !dispatcher_ids.contains(&status.dispatcher_id)
+        {
+            return false;
+        }
+
+        if let Some(after) = filter.after
+            && status.timestamp < after
+        {
+            return false;
+        }
+
+        if let Some(before) = filter.before
+            && status.timestamp > before
+        {
+            return false;
+        }
+
+        if let Some(battery) = &filter.battery
+            && !battery.contains(&status.battery_percent.0)
+        {
+            return false;
+        }
+
+        if let Some(has_errors) = filter.has_errors
+            && status.errors.is_empty() == has_errors
+        {
+            return false;
+        }
+
+        if let Some(codes) = &filter.error_codes
+            && !codes.is_empty()
+            && !status
+                .errors
+                .iter()
+                .any(|error| codes.contains(&error.code))
+        {
+            return false;
+        }
+
+        true
+    })
+}
+
+fn evict(statuses: &mut BoundedStore<DeviceStatus>) {
+    let evicted = statuses.evict(jiff::Timestamp::now());
+    metrics::record_memory_stats("statuses", statuses.len(), evicted);
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, DeviceStatus, DispatcherId, Percentage, StatusId};
+    use ulid::Ulid;
+
+    use super::InMemoryDeviceStatusRegistry;
+    use crate::registry::memory::{MemoryLimits, MemoryStats};
+    use crate::registry::{
+        DeviceStatusRegistry,
+        filter::{Pagination, QueryOptions, SortOrder, StatusFilter, StatusSortBy},
+    };
+
+    fn status(battery: u8) -> DeviceStatus {
+        DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            battery_percent: Percentage(battery),
+            uptime_seconds: 60,
+            signal_rssi: -70,
+            errors: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+            sensor_statuses: Box::new([]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_and_get() {
+        let reg = InMemoryDeviceStatusRegistry::new();
+        let s = status(80);
+
+        reg.store(s.clone()).await.unwrap();
+
+        assert_eq!(reg.get(s.id).await.unwrap(), Some(s));
+        assert_eq!(reg.count(None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_store_skips_existing() {
+        let reg = InMemoryDeviceStatusRegistry::new();
+        let first = status(80);
+        let second = status(70);
+
+        reg.batch_store(vec![first.clone()]).await.unwrap();
+
+        let replay = DeviceStatus {
+            battery_percent: Percentage(5),
+            ..first.clone()
+        };
+        let stored = reg.batch_store(vec![replay, second.clone()]).await.unwrap();
+
+        assert_eq!(stored, vec![second.id]);
+        assert_eq!(reg.get(first.id).await.unwrap(), Some(first));
+    }
+
+    #[tokio::test]
+    async fn test_latest_for_device() {
+        let reg = InMemoryDeviceStatusRegistry::new();
+        let older = status(80);
+        let newer = DeviceStatus {
+            id: StatusId(Ulid::new()),
+            battery_percent: Percentage(75),
+            timestamp: older.timestamp + jiff::SignedDuration::from_secs(60),
+            ..older.clone()
+        };
+
+        reg.batch_store(vec![newer.clone(), older.clone(), status(50)])
+            .await
+            .unwrap();
+
+        assert_eq!(reg.latest(older.device_id).await.unwrap(), Some(newer));
+        assert_eq!(reg.latest(DeviceId(Ulid::new())).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_through_device() {
+        let reg = InMemoryDeviceStatusRegistry::new();
+        let first = status(80);
+        let statuses: Vec<_> = (1..4)
+            .map(|i| DeviceStatus {
+                id: StatusId(Ulid::new()),
+                timestamp: first.timestamp + jiff::SignedDuration::from_secs(i),
+                ..first.clone()
+            })
+            .collect();
+
+        reg.batch_store([vec![first.clone(), status(50)], statuses.clone()].concat())
+            .await
+            .unwrap();
+
+        let filter = StatusFilter::builder()
+            .device_ids([first.device_id])
+            .build();
+        assert_eq!(reg.count(Some(filter.clone())).await.unwrap(), 4);
+
+        let page = |after| {
+            reg.list(QueryOptions {
+                filter: filter.clone(),
+                sort_by: StatusSortBy::Timestamp,
+                sort_order: SortOrder::Desc,
+                pagination: Pagination::Cursor { after, limit: 3 },
+            })
+        };
+
+        let newest = page(None).await.unwrap();
+        assert_eq!(newest[0], statuses[2]);
+        assert_eq!(newest.len(), 3);
+
+        let rest = page(Some(StatusSortBy::Timestamp.cursor(&newest[2])))
+            .await
+            .unwrap();
+        assert_eq!(rest, vec![first]);
+    }
+
+    #[tokio::test]
+    async fn test_statuses_older_than_max_age_are_evicted() {
+        let reg = InMemoryDeviceStatusRegistry::with_limits(MemoryLimits {
+            max_entries: None,
+            max_age_secs: Some(3600),
+        });
+        let stale = DeviceStatus {
+            timestamp: jiff::Timestamp::now() - jiff::SignedDuration::from_hours(2),
+            ..status(80)
+        };
+        let fresh = status(70);
+
+        reg.batch_store(vec![stale.clone(), fresh.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(reg.get(stale.id).await.unwrap(), None);
+        assert_eq!(reg.get(fresh.id).await.unwrap(), Some(fresh));
+        assert_eq!(
+            reg.stats().await,
+            MemoryStats {
+                entries: 1,
+                evicted: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::registry::UserRegistry;
+use crate::user::{Credential, User, UserId};
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryUserRegistry {
+    users: Arc<RwLock<HashMap<UserId, User>>>,
+    /// Deleted users, with when they were deleted
+    deleted: Arc<RwLock<HashMap<UserId, (User, jiff::Timestamp)>>>,
+}
+
+impl InMemoryUserRegistry {
+    pub fn new() -> Self {
+        Self {
+            users: Arc::new(RwLock::new(HashMap::new())),
+            deleted: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryUserRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl UserRegistry for InMemoryUserRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, user: User) -> Result<(), Self::Error> {
+        let mut users = self.users.write().await;
+        let _ = users.insert(user.id, user);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: UserId) -> Result<Option<User>, Self::Error> {
+        let users = self.users.read().await;
+        Ok(users.get(&id).cloned())
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Self::Error> {
+        let users = self.users.read().await;
+        Ok(users
+            .values()
+            .find(|user| user.username == username)
+            .cloned())
+    }
+
+    async fn get_by_subject(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<User>, Self::Error> {
+        let users = self.users.read().await;
+        Ok(users
+            .values()
+            .find(|user| {
+                matches!(
+                    &user.credential,
+                    Credential::Oidc { issuer: i, subject: s } if i == issuer && s == subject
+                )
+            })
+            .cloned())
+    }
+
+    async fn update(&self, user: User) -> Result<(), Self::Error> {
+        let mut users = self.users.write().await;
+        let existing = users.get_mut(&user.id).ok_or(InMemoryError::NotFound)?;
+        *existing = user;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: UserId) -> Result<(), Self::Error> {
+        let mut users = self.users.write().await;
+        let user = users.remove(&id).ok_or(InMemoryError::NotFound)?;
+        self.deleted
+            .write()
+            .await
+            .insert(id, (user, jiff::Timestamp::now()));
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<User>, Self::Error> {
+        let users = self.users.read().await;
+        let mut all: Vec<User> = users.values().cloned().collect();
+        all.sort_by_key(|user| user.id.0);
+
+        Ok(all)
+    }
+}
@@ -0,0 +1,68 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::registry::ValidationRuleRegistry;
+use crate::validation::{ValidationRule, ValidationRuleId};
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryValidationRuleRegistry {
+    rules: Arc<RwLock<HashMap<ValidationRuleId, ValidationRule>>>,
+}
+
+impl InMemoryValidationRuleRegistry {
+    pub fn new() -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryValidationRuleRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ValidationRuleRegistry for InMemoryValidationRuleRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, rule: ValidationRule) -> Result<(), Self::Error> {
+        let mut rules = self.rules.write().await;
+        let _ = rules.insert(rule.id, rule);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: ValidationRuleId) -> Result<Option<ValidationRule>, Self::Error> {
+        let rules = self.rules.read().await;
+        Ok(rules.get(&id).cloned())
+    }
+
+    async fn update(&self, rule: ValidationRule) -> Result<(), Self::Error> {
+        let mut rules = self.rules.write().await;
+        let existing = rules.get_mut(&rule.id).ok_or(InMemoryError::NotFound)?;
+        *existing = rule;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: ValidationRuleId) -> Result<(), Self::Error> {
+        let mut rules = self.rules.write().await;
+        rules.remove(&id).ok_or(InMemoryError::NotFound)?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<ValidationRule>, Self::Error> {
+        let rules = self.rules.read().await;
+        let mut all: Vec<ValidationRule> = rules.values().cloned().collect();
+        all.sort_by_key(|rule| rule.id.0);
+
+        Ok(all)
+    }
+}
@@ -0,0 +1,104 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::registry::WebhookRegistry;
+use crate::webhook::{Delivery, DeliveryId, DeliveryState, Webhook, WebhookId};
+
+use super::InMemoryError;
+
+#[derive(Clone)]
+pub struct InMemoryWebhookRegistry {
+    webhooks: Arc<RwLock<HashMap<WebhookId, Webhook>>>,
+    deliveries: Arc<RwLock<HashMap<DeliveryId, Delivery>>>,
+}
+
+impl InMemoryWebhookRegistry {
+    pub fn new() -> Self {
+        Self {
+            webhooks: Arc::new(RwLock::new(HashMap::new())),
+            deliveries: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for InMemoryWebhookRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl WebhookRegistry for InMemoryWebhookRegistry {
+    type Error = InMemoryError;
+
+    async fn create(&self, webhook: Webhook) -> Result<(), Self::Error> {
+        let mut webhooks = self.webhooks.write().await;
+        let _ = webhooks.insert(webhook.id, webhook);
+
+        Ok(())
+    }
+
+    async fn get(&self, id: WebhookId) -> Result<Option<Webhook>, Self::Error> {
+        let webhooks = self.webhooks.read().await;
+        Ok(webhooks.get(&id).cloned())
+    }
+
+    async fn delete(&self, id: WebhookId) -> Result<(), Self::Error> {
+        let mut webhooks = self.webhooks.write().await;
+        webhooks.remove(&id).ok_or(InMemoryError::NotFound)?;
+
+        let mut deliveries = self.deliveries.write().await;
+        deliveries.retain(|_, delivery| delivery.webhook_id != id);
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, Self::Error> {
+        let webhooks = self.webhooks.read().await;
+        let mut all: Vec<Webhook> = webhooks.values().cloned().collect();
+        all.sort_by_key(|webhook| webhook.id.0);
+
+        Ok(all)
+    }
+
+    async fn enqueue(&self, new: Vec<Delivery>) -> Result<(), Self::Error> {
+        let mut deliveries = self.deliveries.write().await;
+        for delivery in new {
+            deliveries.insert(delivery.id, delivery);
+        }
+
+        Ok(())
+    }
+
+    async fn due(&self, now: jiff::Timestamp, limit: usize) -> Result<Vec<Delivery>, Self::Error> {
+        let deliveries = self.deliveries.read().await;
+        let mut due: Vec<&Delivery> = deliveries
+            .values()
+            .filter(|d| d.state == DeliveryState::Pending && d.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|d| (d.next_attempt_at, d.id.0));
+
+        Ok(due.into_iter().take(limit).cloned().collect())
+    }
+
+    async fn update_delivery(&self, delivery: Delivery) -> Result<(), Self::Error> {
+        let mut deliveries = self.deliveries.write().await;
+        let existing = deliveries
+            .get_mut(&delivery.id)
+            .ok_or(InMemoryError::NotFound)?;
+        *existing = delivery;
+
+        Ok(())
+    }
+
+    async fn deliveries(&self, id: WebhookId, limit: usize) -> Result<Vec<Delivery>, Self::Error> {
+        let deliveries = self.deliveries.read().await;
+        let mut matching: Vec<&Delivery> =
+            deliveries.values().filter(|d| d.webhook_id == id).collect();
+        matching.sort_by_key(|d| std::cmp::Reverse(d.id.0));
+
+        Ok(matching.into_iter().take(limit).cloned().collect())
+    }
+}
@@ -1,19 +1,165 @@
+pub mod cached;
+pub mod error;
 pub mod filter;
 pub mod memory;
 pub mod sqlite;
 
+use crate::audit::AuditEntry;
+use crate::auth::{ApiKey, ApiKeyId};
+use crate::command::Command;
+use crate::correction::{Correction, CorrectionId, Revision};
+use crate::dead_letter::{DeadLetter, DeadLetterId};
+use crate::derived::Indicator;
+use crate::firmware::{FirmwareId, FirmwareImage, Rollout, RolloutDevice, RolloutId, RolloutState};
+use crate::group::{Group, GroupId};
+use crate::health::DispatcherReport;
+use crate::irrigation::{IrrigationPlan, PlanId};
+use crate::notify::{Contact, ContactId, Notification};
+use crate::org::{Org, OrgId};
+use crate::outbox::OutboxEntry;
+use crate::placement::Placement;
+use crate::quality::{QualityWindow, SensorQuality};
+use crate::rollup::Aggregate;
+use crate::user::{User, UserId};
+use crate::validation::{QualityStatus, ValidationRule, ValidationRuleId};
+use crate::webhook::{Delivery, Event, Webhook, WebhookId};
 use async_trait::async_trait;
-use ersha_core::{Device, DeviceId, Dispatcher, DispatcherId, Sensor};
-use filter::{DeviceFilter, DeviceSortBy, DispatcherFilter, DispatcherSortBy, QueryOptions};
+pub use error::RegistryError;
+use ersha_core::{
+    CommandId, Device, DeviceId, DeviceStatus, Dispatcher, DispatcherId, ReadingId, Sensor,
+    SensorReading, StatusId,
+};
+use filter::{
+    AggregateFilter, AuditFilter, AuditSortBy, DeadLetterFilter, DeviceFilter, DeviceSortBy,
+    DispatcherFilter, DispatcherSortBy, IndicatorFilter, QueryOptions, ReadingFilter,
+    ReadingSortBy, StatusFilter, StatusSortBy,
+};
+
+/// What prime keeps about a device besides the device itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceDetails {
+    pub placement: Placement,
+    /// Changes with every registration, update, state change or added
+    /// sensor; see [`crate::placement::next_update`].
+    pub updated_at: jiff::Timestamp,
+}
+
+/// What an upload leaves to be stored.
+#[derive(Debug, Clone, Default)]
+pub struct IngestBatch {
+    pub readings: Vec<SensorReading>,
+    pub statuses: Vec<DeviceStatus>,
+    /// Items refused by validation, kept for re-driving
+    pub dead_letters: Vec<DeadLetter>,
+    /// Events raised by the upload, staged in the outbox
+    pub events: Vec<OutboxEntry>,
+}
+
+/// Ids of what an [`IngestBatch`] stored, leaving out items already held.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ingested {
+    pub readings: Vec<ReadingId>,
+    pub statuses: Vec<StatusId>,
+}
 
 #[async_trait]
 pub trait DeviceRegistry: Clone + Send + Sync + 'static {
-    type Error: std::error::Error + Send + Sync + 'static;
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
 
     async fn register(&self, device: Device) -> Result<(), Self::Error>;
+    /// Register the device unless its id is taken, in which case the device
+    /// already registered under it is returned and nothing changes.
+    async fn register_new(&self, device: Device) -> Result<Option<Device>, Self::Error>;
     async fn get(&self, id: DeviceId) -> Result<Option<Device>, Self::Error>;
-    async fn update(&self, id: DeviceId, new: Device) -> Result<(), Self::Error>;
+    /// The devices registered under any of `ids`, in no particular order.
+    /// Ids with no device are skipped.
+    async fn get_many(&self, ids: &[DeviceId]) -> Result<Vec<Device>, Self::Error>;
+    /// Replace the device's fields, sensors and placement. With `expected`,
+    /// the device is only changed if it was last updated at that time.
+    /// Returns when it was updated, or `None` if `expected` is stale.
+    async fn update(
+        &self,
+        id: DeviceId,
+        new: Device,
+        placement: Placement,
+        expected: Option<jiff::Timestamp>,
+    ) -> Result<Option<jiff::Timestamp>, Self::Error>;
+    /// Placement and last update of the device, if it is registered.
+    async fn details(&self, id: DeviceId) -> Result<Option<DeviceDetails>, Self::Error>;
     async fn suspend(&self, id: DeviceId) -> Result<(), Self::Error>;
+    async fn reactivate(&self, id: DeviceId) -> Result<(), Self::Error>;
+    async fn decommission(&self, id: DeviceId) -> Result<(), Self::Error>;
+
+    /// Assign the device to an organization, or release it with `None`.
+    async fn set_org(&self, id: DeviceId, org: Option<OrgId>) -> Result<(), Self::Error>;
+    /// The organization the device belongs to, if any.
+    async fn org(&self, id: DeviceId) -> Result<Option<OrgId>, Self::Error>;
+
+    /// Assign the device to the dispatcher allowed to upload its data, or
+    /// release it with `None`.
+    async fn set_dispatcher(
+        &self,
+        id: DeviceId,
+        dispatcher: Option<DispatcherId>,
+    ) -> Result<(), Self::Error>;
+    /// The dispatcher the device is assigned to, if any.
+    async fn dispatcher(&self, id: DeviceId) -> Result<Option<DispatcherId>, Self::Error>;
+
+    /// Replace the device's tags. Tags aren't a change to the device, so
+    /// its `updated_at` is kept.
+    async fn set_tags(&self, id: DeviceId, tags: Vec<String>) -> Result<(), Self::Error>;
+    /// The device's tags, sorted.
+    async fn tags(&self, id: DeviceId) -> Result<Vec<String>, Self::Error>;
+
+    /// Mark the device disconnected, not heard from by its dispatcher
+    /// `since` then, or reporting again with `None`. Connectivity isn't a
+    /// change to the device, so its `updated_at` is kept.
+    async fn set_disconnected(
+        &self,
+        id: DeviceId,
+        since: Option<jiff::Timestamp>,
+    ) -> Result<(), Self::Error>;
+    /// Mark the device disconnected `since` then, staging `event` in the
+    /// outbox in the same transaction so that subscribers hear of it even if
+    /// prime stops right after.
+    async fn mark_offline(
+        &self,
+        id: DeviceId,
+        since: jiff::Timestamp,
+        event: Event,
+    ) -> Result<(), Self::Error>;
+    /// Devices marked disconnected, and since when.
+    async fn disconnected(&self) -> Result<Vec<(DeviceId, jiff::Timestamp)>, Self::Error>;
+
+    /// Set how many seconds apart the device is expected to report, or stop
+    /// expecting it to with `None`. Kept across re-registration.
+    async fn set_reporting_interval(
+        &self,
+        id: DeviceId,
+        interval_secs: Option<u64>,
+    ) -> Result<(), Self::Error>;
+    /// Seconds apart the device is expected to report, if set.
+    async fn reporting_interval(&self, id: DeviceId) -> Result<Option<u64>, Self::Error>;
+    /// Every device expected to report, and how many seconds apart.
+    async fn reporting_intervals(&self) -> Result<Vec<(DeviceId, u64)>, Self::Error>;
+
+    /// Record the device's hardware revision, which firmware rollouts
+    /// target, or forget it with `None`. Kept across re-registration.
+    async fn set_hardware_rev(
+        &self,
+        id: DeviceId,
+        hardware_rev: Option<String>,
+    ) -> Result<(), Self::Error>;
+    /// The device's hardware revision, if recorded.
+    async fn hardware_rev(&self, id: DeviceId) -> Result<Option<String>, Self::Error>;
+    /// Replace the device's metadata, or clear it with `None`. Kept when the
+    /// device is registered again.
+    async fn set_metadata(
+        &self,
+        id: DeviceId,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Self::Error>;
+    async fn metadata(&self, id: DeviceId) -> Result<Option<serde_json::Value>, Self::Error>;
 
     async fn add_sensor(&self, id: DeviceId, sensor: Sensor) -> Result<(), Self::Error>;
     async fn add_sensors(
@@ -31,12 +177,48 @@ pub trait DeviceRegistry: Clone + Send + Sync + 'static {
 
 #[async_trait]
 pub trait DispatcherRegistry: Clone + Send + Sync + 'static {
-    type Error: std::error::Error + Send + Sync + 'static;
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
 
     async fn register(&self, dispatcher: Dispatcher) -> Result<(), Self::Error>;
+    /// Register the dispatcher unless its id is taken, in which case the
+    /// dispatcher already registered under it is returned.
+    async fn register_new(&self, dispatcher: Dispatcher)
+    -> Result<Option<Dispatcher>, Self::Error>;
     async fn get(&self, id: DispatcherId) -> Result<Option<Dispatcher>, Self::Error>;
-    async fn update(&self, id: DispatcherId, new: Dispatcher) -> Result<(), Self::Error>;
+    /// The dispatchers registered under any of `ids`, in no particular
+    /// order. Ids with no dispatcher are skipped.
+    async fn get_many(&self, ids: &[DispatcherId]) -> Result<Vec<Dispatcher>, Self::Error>;
+    /// Replace the dispatcher's fields. With `expected`, the dispatcher is
+    /// only changed if it was last updated at that time. Returns when it was
+    /// updated, or `None` if `expected` is stale.
+    async fn update(
+        &self,
+        id: DispatcherId,
+        new: Dispatcher,
+        expected: Option<jiff::Timestamp>,
+    ) -> Result<Option<jiff::Timestamp>, Self::Error>;
+    /// When the dispatcher was last registered or changed, if it is; see
+    /// [`crate::placement::next_update`].
+    async fn updated_at(&self, id: DispatcherId) -> Result<Option<jiff::Timestamp>, Self::Error>;
     async fn suspend(&self, id: DispatcherId) -> Result<(), Self::Error>;
+    async fn reactivate(&self, id: DispatcherId) -> Result<(), Self::Error>;
+
+    /// Assign the dispatcher to an organization, or release it with `None`.
+    ///
+    /// Readings and statuses it uploads belong to that organization.
+    async fn set_org(&self, id: DispatcherId, org: Option<OrgId>) -> Result<(), Self::Error>;
+    /// The organization the dispatcher belongs to, if any.
+    async fn org(&self, id: DispatcherId) -> Result<Option<OrgId>, Self::Error>;
+
+    /// Set or clear the shared secret used to authenticate the dispatcher's hello.
+    async fn set_secret(&self, id: DispatcherId, secret: Option<String>)
+    -> Result<(), Self::Error>;
+    async fn get_secret(&self, id: DispatcherId) -> Result<Option<String>, Self::Error>;
+
+    /// Replace the dispatcher's tags.
+    async fn set_tags(&self, id: DispatcherId, tags: Vec<String>) -> Result<(), Self::Error>;
+    /// The dispatcher's tags, sorted.
+    async fn tags(&self, id: DispatcherId) -> Result<Vec<String>, Self::Error>;
 
     async fn batch_register(&self, dispatchers: Vec<Dispatcher>) -> Result<(), Self::Error>;
     async fn count(&self, filter: Option<DispatcherFilter>) -> Result<usize, Self::Error>;
@@ -45,3 +227,437 @@ pub trait DispatcherRegistry: Clone + Send + Sync + 'static {
         options: QueryOptions<DispatcherFilter, DispatcherSortBy>,
     ) -> Result<Vec<Dispatcher>, Self::Error>;
 }
+
+#[async_trait]
+pub trait ReadingRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn store(&self, reading: SensorReading) -> Result<(), Self::Error>;
+    async fn get(&self, id: ReadingId) -> Result<Option<SensorReading>, Self::Error>;
+
+    /// Store readings whose ids are not known yet, leaving existing ones untouched.
+    ///
+    /// Returns the ids that were stored. The batch is applied atomically.
+    async fn batch_store(
+        &self,
+        readings: Vec<SensorReading>,
+    ) -> Result<Vec<ReadingId>, Self::Error>;
+    /// The newest reading of each of the device's sensors, ordered by sensor id.
+    async fn latest_per_sensor(&self, device: DeviceId) -> Result<Vec<SensorReading>, Self::Error>;
+    /// The newest reading of each sensor among those matching `filter`,
+    /// ordered by device and sensor id.
+    async fn latest_matching(
+        &self,
+        filter: ReadingFilter,
+    ) -> Result<Vec<SensorReading>, Self::Error>;
+    /// Cadence and confidence of each of the device's sensors with readings
+    /// taken within `window`, ordered by sensor id.
+    async fn data_quality(
+        &self,
+        device: DeviceId,
+        window: QualityWindow,
+    ) -> Result<Vec<SensorQuality>, Self::Error>;
+    /// Delete up to `limit` of the oldest readings taken at or before `before`.
+    ///
+    /// Returns how many were deleted.
+    async fn purge(&self, before: jiff::Timestamp, limit: usize) -> Result<usize, Self::Error>;
+    /// Set the quality status of stored readings. Unknown ids are skipped.
+    async fn set_quality(
+        &self,
+        statuses: Vec<(ReadingId, QualityStatus)>,
+    ) -> Result<(), Self::Error>;
+    async fn count(&self, filter: Option<ReadingFilter>) -> Result<usize, Self::Error>;
+    async fn list(
+        &self,
+        options: QueryOptions<ReadingFilter, ReadingSortBy>,
+    ) -> Result<Vec<SensorReading>, Self::Error>;
+}
+
+#[async_trait]
+pub trait DeviceStatusRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn store(&self, status: DeviceStatus) -> Result<(), Self::Error>;
+    async fn get(&self, id: StatusId) -> Result<Option<DeviceStatus>, Self::Error>;
+
+    /// Store statuses whose ids are not known yet, leaving existing ones untouched.
+    ///
+    /// Returns the ids that were stored. The batch is applied atomically.
+    async fn batch_store(&self, statuses: Vec<DeviceStatus>) -> Result<Vec<StatusId>, Self::Error>;
+    /// The most recent status reported for the device.
+    async fn latest(&self, device: DeviceId) -> Result<Option<DeviceStatus>, Self::Error>;
+    /// Delete up to `limit` of the oldest statuses taken at or before `before`.
+    ///
+    /// Returns how many were deleted.
+    async fn purge(&self, before: jiff::Timestamp, limit: usize) -> Result<usize, Self::Error>;
+    async fn count(&self, filter: Option<StatusFilter>) -> Result<usize, Self::Error>;
+    async fn list(
+        &self,
+        options: QueryOptions<StatusFilter, StatusSortBy>,
+    ) -> Result<Vec<DeviceStatus>, Self::Error>;
+}
+
+#[async_trait]
+pub trait IngestRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    /// Store an upload's readings, statuses and dead letters and stage its
+    /// events, either all of them or none. Readings and statuses whose ids are known already are
+    /// left untouched, as by their registries' `batch_store`.
+    async fn ingest(&self, batch: IngestBatch) -> Result<Ingested, Self::Error>;
+}
+
+#[async_trait]
+pub trait DispatcherStatusRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    /// Record a report, replacing the previous one from the same dispatcher.
+    async fn record(&self, report: DispatcherReport) -> Result<(), Self::Error>;
+    async fn latest(&self, id: DispatcherId) -> Result<Option<DispatcherReport>, Self::Error>;
+    /// The latest report of every dispatcher that has sent one.
+    async fn list(&self) -> Result<Vec<DispatcherReport>, Self::Error>;
+}
+
+#[async_trait]
+pub trait AggregateRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    /// Fold partial aggregates into the stored ones for the same buckets,
+    /// creating buckets that don't exist yet. The merge is applied atomically.
+    async fn merge(&self, partials: Vec<Aggregate>) -> Result<(), Self::Error>;
+    /// Store aggregates, overwriting the buckets with the same key.
+    async fn replace(&self, aggregates: Vec<Aggregate>) -> Result<(), Self::Error>;
+    /// Matching aggregates ordered by bucket start, then sensor id.
+    async fn list(&self, filter: AggregateFilter) -> Result<Vec<Aggregate>, Self::Error>;
+}
+
+#[async_trait]
+pub trait DerivedMetricRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    /// Store indicators, replacing any with the same field, kind and day.
+    async fn upsert(&self, indicators: Vec<Indicator>) -> Result<(), Self::Error>;
+    /// Matching indicators ordered by day, then kind.
+    async fn list(&self, filter: IndicatorFilter) -> Result<Vec<Indicator>, Self::Error>;
+}
+
+#[async_trait]
+pub trait AuditRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn record(&self, entry: AuditEntry) -> Result<(), Self::Error>;
+    async fn count(&self, filter: Option<AuditFilter>) -> Result<usize, Self::Error>;
+    async fn list(
+        &self,
+        options: QueryOptions<AuditFilter, AuditSortBy>,
+    ) -> Result<Vec<AuditEntry>, Self::Error>;
+}
+
+#[async_trait]
+pub trait CommandRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn enqueue(&self, command: Command) -> Result<(), Self::Error>;
+    async fn get(&self, id: CommandId) -> Result<Option<Command>, Self::Error>;
+    /// The device's `limit` most recent commands, newest first.
+    async fn list(&self, device: DeviceId, limit: usize) -> Result<Vec<Command>, Self::Error>;
+    /// Mark up to `limit` of the dispatcher's queued commands that expire
+    /// after `now` as delivered, returning them oldest first.
+    async fn deliver(
+        &self,
+        dispatcher: DispatcherId,
+        now: jiff::Timestamp,
+        limit: usize,
+    ) -> Result<Vec<Command>, Self::Error>;
+    /// Mark the dispatcher's delivered commands among `ids` as acknowledged.
+    ///
+    /// Returns how many were acknowledged; other ids are ignored.
+    async fn ack(
+        &self,
+        dispatcher: DispatcherId,
+        ids: Vec<CommandId>,
+        now: jiff::Timestamp,
+    ) -> Result<usize, Self::Error>;
+    /// Expire queued and delivered commands whose expiry is at or before `now`.
+    ///
+    /// Returns how many expired.
+    async fn expire(&self, now: jiff::Timestamp) -> Result<usize, Self::Error>;
+}
+
+#[async_trait]
+pub trait IrrigationRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, plan: IrrigationPlan) -> Result<(), Self::Error>;
+    async fn get(&self, id: PlanId) -> Result<Option<IrrigationPlan>, Self::Error>;
+    /// Replace the plan with the same id.
+    async fn update(&self, plan: IrrigationPlan) -> Result<(), Self::Error>;
+    async fn delete(&self, id: PlanId) -> Result<(), Self::Error>;
+    /// Every plan, ordered by id.
+    async fn list(&self) -> Result<Vec<IrrigationPlan>, Self::Error>;
+}
+
+#[async_trait]
+pub trait CorrectionRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, correction: Correction) -> Result<(), Self::Error>;
+    async fn get(&self, id: CorrectionId) -> Result<Option<Correction>, Self::Error>;
+    /// Replace the correction with the same id.
+    async fn update(&self, correction: Correction) -> Result<(), Self::Error>;
+    /// Corrections of `device`, oldest first.
+    async fn list(&self, device: DeviceId) -> Result<Vec<Correction>, Self::Error>;
+    /// Corrections not applied yet, oldest first.
+    async fn pending(&self) -> Result<Vec<Correction>, Self::Error>;
+
+    /// Store revisions, ignoring ones already recorded for the same reading
+    /// and correction.
+    async fn record_revisions(&self, revisions: Vec<Revision>) -> Result<(), Self::Error>;
+    /// Revisions of `reading`, oldest first.
+    async fn revisions(&self, reading: ReadingId) -> Result<Vec<Revision>, Self::Error>;
+    /// Revisions made by `correction`.
+    async fn revisions_by(&self, correction: CorrectionId) -> Result<Vec<Revision>, Self::Error>;
+}
+
+#[async_trait]
+pub trait DeadLetterRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    /// Store refused items, ignoring items already held as a dead letter.
+    async fn record(&self, letters: Vec<DeadLetter>) -> Result<(), Self::Error>;
+    async fn get(&self, id: DeadLetterId) -> Result<Option<DeadLetter>, Self::Error>;
+    /// Replace the dead letter with the same id.
+    async fn update(&self, letter: DeadLetter) -> Result<(), Self::Error>;
+    async fn delete(&self, id: DeadLetterId) -> Result<(), Self::Error>;
+    /// Up to `limit` dead letters matching `filter`, oldest first.
+    async fn list(
+        &self,
+        filter: DeadLetterFilter,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, Self::Error>;
+}
+
+#[async_trait]
+pub trait WebhookRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, webhook: Webhook) -> Result<(), Self::Error>;
+    async fn get(&self, id: WebhookId) -> Result<Option<Webhook>, Self::Error>;
+    /// Delete the webhook along with its deliveries.
+    async fn delete(&self, id: WebhookId) -> Result<(), Self::Error>;
+    async fn list(&self) -> Result<Vec<Webhook>, Self::Error>;
+
+    async fn enqueue(&self, deliveries: Vec<Delivery>) -> Result<(), Self::Error>;
+    /// Up to `limit` pending deliveries whose next attempt is at or before
+    /// `now`, oldest first.
+    async fn due(&self, now: jiff::Timestamp, limit: usize) -> Result<Vec<Delivery>, Self::Error>;
+    /// Replace a delivery with its updated state.
+    async fn update_delivery(&self, delivery: Delivery) -> Result<(), Self::Error>;
+    /// The webhook's `limit` most recent deliveries, newest first.
+    async fn deliveries(&self, id: WebhookId, limit: usize) -> Result<Vec<Delivery>, Self::Error>;
+}
+
+#[async_trait]
+pub trait ContactRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, contact: Contact) -> Result<(), Self::Error>;
+    async fn get(&self, id: ContactId) -> Result<Option<Contact>, Self::Error>;
+    async fn update(&self, contact: Contact) -> Result<(), Self::Error>;
+    /// Delete the contact along with its notifications.
+    async fn delete(&self, id: ContactId) -> Result<(), Self::Error>;
+    async fn list(&self) -> Result<Vec<Contact>, Self::Error>;
+
+    async fn enqueue(&self, notifications: Vec<Notification>) -> Result<(), Self::Error>;
+    /// Up to `limit` pending notifications whose next attempt is at or
+    /// before `now`, oldest first.
+    async fn due(
+        &self,
+        now: jiff::Timestamp,
+        limit: usize,
+    ) -> Result<Vec<Notification>, Self::Error>;
+    /// Replace a notification with its updated state.
+    async fn update_notification(&self, notification: Notification) -> Result<(), Self::Error>;
+    /// The contact's `limit` most recent notifications, newest first.
+    async fn notifications(
+        &self,
+        id: ContactId,
+        limit: usize,
+    ) -> Result<Vec<Notification>, Self::Error>;
+}
+
+/// Events waiting to be relayed to webhooks and contacts; see
+/// [`crate::outbox`].
+#[async_trait]
+pub trait OutboxRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn stage(&self, entries: Vec<OutboxEntry>) -> Result<(), Self::Error>;
+    /// Up to `limit` pending entries whose next attempt is at or before
+    /// `now`, oldest first. Entries whose event can't be read back are
+    /// poisoned rather than returned.
+    async fn due(
+        &self,
+        now: jiff::Timestamp,
+        limit: usize,
+    ) -> Result<Vec<OutboxEntry>, Self::Error>;
+    /// Replace an entry with its updated state.
+    async fn update(&self, entry: OutboxEntry) -> Result<(), Self::Error>;
+    /// The `limit` most recently staged poisoned entries, newest first.
+    async fn poisoned(&self, limit: usize) -> Result<Vec<OutboxEntry>, Self::Error>;
+}
+
+#[async_trait]
+pub trait GroupRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, group: Group) -> Result<(), Self::Error>;
+    async fn get(&self, id: GroupId) -> Result<Option<Group>, Self::Error>;
+    async fn update(&self, group: Group) -> Result<(), Self::Error>;
+    async fn delete(&self, id: GroupId) -> Result<(), Self::Error>;
+    async fn list(&self) -> Result<Vec<Group>, Self::Error>;
+}
+
+#[async_trait]
+pub trait ValidationRuleRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, rule: ValidationRule) -> Result<(), Self::Error>;
+    async fn get(&self, id: ValidationRuleId) -> Result<Option<ValidationRule>, Self::Error>;
+    async fn update(&self, rule: ValidationRule) -> Result<(), Self::Error>;
+    async fn delete(&self, id: ValidationRuleId) -> Result<(), Self::Error>;
+    async fn list(&self) -> Result<Vec<ValidationRule>, Self::Error>;
+}
+
+#[async_trait]
+pub trait FirmwareRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn add_image(&self, image: FirmwareImage) -> Result<(), Self::Error>;
+    async fn image(&self, id: FirmwareId) -> Result<Option<FirmwareImage>, Self::Error>;
+    /// Every image, newest first.
+    async fn images(&self) -> Result<Vec<FirmwareImage>, Self::Error>;
+
+    async fn create_rollout(&self, rollout: Rollout) -> Result<(), Self::Error>;
+    async fn rollout(&self, id: RolloutId) -> Result<Option<Rollout>, Self::Error>;
+    /// Every rollout, newest first.
+    async fn rollouts(&self) -> Result<Vec<Rollout>, Self::Error>;
+    async fn set_rollout_state(
+        &self,
+        id: RolloutId,
+        state: RolloutState,
+    ) -> Result<(), Self::Error>;
+    /// Add `devices` to the rollout as pending, leaving those already in it
+    /// as they are. Returns how many were added.
+    async fn enroll(
+        &self,
+        id: RolloutId,
+        devices: Vec<DeviceId>,
+        now: jiff::Timestamp,
+    ) -> Result<usize, Self::Error>;
+    /// The devices enrolled in the rollout, in the order they were.
+    async fn rollout_devices(&self, id: RolloutId) -> Result<Vec<RolloutDevice>, Self::Error>;
+    /// Record the progress of devices already enrolled.
+    async fn update_rollout_devices(&self, devices: Vec<RolloutDevice>) -> Result<(), Self::Error>;
+}
+
+#[async_trait]
+pub trait OrgRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, org: Org) -> Result<(), Self::Error>;
+    async fn get(&self, id: OrgId) -> Result<Option<Org>, Self::Error>;
+    async fn list(&self) -> Result<Vec<Org>, Self::Error>;
+    /// Set the JSON Schema the organization's device metadata must match,
+    /// or drop it with `None`.
+    async fn set_metadata_schema(
+        &self,
+        id: OrgId,
+        schema: Option<serde_json::Value>,
+    ) -> Result<(), Self::Error>;
+    async fn metadata_schema(&self, id: OrgId) -> Result<Option<serde_json::Value>, Self::Error>;
+}
+
+#[async_trait]
+pub trait ApiKeyRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, key: ApiKey) -> Result<(), Self::Error>;
+    async fn get(&self, id: ApiKeyId) -> Result<Option<ApiKey>, Self::Error>;
+    async fn revoke(&self, id: ApiKeyId, at: jiff::Timestamp) -> Result<(), Self::Error>;
+
+    async fn count(&self) -> Result<usize, Self::Error>;
+    async fn list(&self) -> Result<Vec<ApiKey>, Self::Error>;
+}
+
+#[async_trait]
+pub trait UserRegistry: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static + Into<RegistryError>;
+
+    async fn create(&self, user: User) -> Result<(), Self::Error>;
+    async fn get(&self, id: UserId) -> Result<Option<User>, Self::Error>;
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Self::Error>;
+    /// The user mapped to `subject` at the OIDC provider `issuer`.
+    async fn get_by_subject(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<User>, Self::Error>;
+    async fn update(&self, user: User) -> Result<(), Self::Error>;
+    /// Soft-delete a user: it is no longer found and its username is free
+    /// again, but its record is kept with when it was deleted.
+    async fn delete(&self, id: UserId) -> Result<(), Self::Error>;
+    async fn list(&self) -> Result<Vec<User>, Self::Error>;
+}
+
+/// The set of registries backing a prime instance.
+///
+/// Lets servers and handlers stay generic over the storage backend without
+/// carrying one type parameter per registry.
+pub trait Registries: Clone + Send + Sync + 'static {
+    type Devices: DeviceRegistry;
+    type Dispatchers: DispatcherRegistry;
+    type Readings: ReadingRegistry;
+    type Statuses: DeviceStatusRegistry;
+    type Ingest: IngestRegistry;
+    type DispatcherStatuses: DispatcherStatusRegistry;
+    type Aggregates: AggregateRegistry;
+    type DerivedMetrics: DerivedMetricRegistry;
+    type Commands: CommandRegistry;
+    type Irrigation: IrrigationRegistry;
+    type Corrections: CorrectionRegistry;
+    type DeadLetters: DeadLetterRegistry;
+    type Audit: AuditRegistry;
+    type Webhooks: WebhookRegistry;
+    type Contacts: ContactRegistry;
+    type Outbox: OutboxRegistry;
+    type Groups: GroupRegistry;
+    type ValidationRules: ValidationRuleRegistry;
+    type Firmware: FirmwareRegistry;
+    type Orgs: OrgRegistry;
+    type ApiKeys: ApiKeyRegistry;
+    type Users: UserRegistry;
+
+    fn devices(&self) -> &Self::Devices;
+    fn dispatchers(&self) -> &Self::Dispatchers;
+    fn readings(&self) -> &Self::Readings;
+    fn statuses(&self) -> &Self::Statuses;
+    fn ingest(&self) -> &Self::Ingest;
+    fn dispatcher_statuses(&self) -> &Self::DispatcherStatuses;
+    fn aggregates(&self) -> &Self::Aggregates;
+    fn derived_metrics(&self) -> &Self::DerivedMetrics;
+    fn commands(&self) -> &Self::Commands;
+    fn irrigation(&self) -> &Self::Irrigation;
+    fn corrections(&self) -> &Self::Corrections;
+    fn dead_letters(&self) -> &Self::DeadLetters;
+    fn audit(&self) -> &Self::Audit;
+    fn webhooks(&self) -> &Self::Webhooks;
+    fn contacts(&self) -> &Self::Contacts;
+    fn outbox(&self) -> &Self::Outbox;
+    fn groups(&self) -> &Self::Groups;
+    fn validation_rules(&self) -> &Self::ValidationRules;
+    fn firmware(&self) -> &Self::Firmware;
+    fn orgs(&self) -> &Self::Orgs;
+    fn api_keys(&self) -> &Self::ApiKeys;
+    fn users(&self) -> &Self::Users;
+}
@@ -0,0 +1,312 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{DeviceId, SensorId, SensorKind};
+use sqlx::{
+    QueryBuilder, Row, Sqlite, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions,
+    sqlite::SqliteRow,
+};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::registry::{AggregateRegistry, RegistryError, filter::AggregateFilter};
+use crate::rollup::{Aggregate, Granularity};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteAggregateError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid sensor kind: {0}")]
+    InvalidSensorKind(i32),
+    #[error("invalid granularity: {0}")]
+    InvalidGranularity(i32),
+}
+
+impl From<SqliteAggregateError> for RegistryError {
+    fn from(error: SqliteAggregateError) -> Self {
+        match error {
+            SqliteAggregateError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteAggregateRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteAggregateRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteAggregateError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteAggregateError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteAggregateError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl AggregateRegistry for SqliteAggregateRegistry {
+    type Error = SqliteAggregateError;
+
+    async fn merge(&self, partials: Vec<Aggregate>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for partial in partials {
+            sqlx::query(
+                r#"
+                INSERT INTO aggregates
+                    (device_id, sensor_id, metric, granularity, bucket_start, count, sum, min, max)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(device_id, sensor_id, metric, granularity, bucket_start) DO UPDATE SET
+                    count = count + excluded.count,
+                    sum = sum + excluded.sum,
+                    min = MIN(min, excluded.min),
+                    max = MAX(max, excluded.max)
+                "#,
+            )
+            .bind(partial.device_id.0.to_string())
+            .bind(partial.sensor_id.0.to_string())
+            .bind(partial.metric as i32)
+            .bind(partial.granularity as i32)
+            .bind(partial.bucket_start.as_second())
+            .bind(partial.count as i64)
+            .bind(partial.sum)
+            .bind(partial.min)
+            .bind(partial.max)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn replace(&self, aggregates: Vec<Aggregate>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for aggregate in aggregates {
+            sqlx::query(
+                r#"
+                INSERT OR REPLACE INTO aggregates
+                    (device_id, sensor_id, metric, granularity, bucket_start, count, sum, min, max)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(aggregate.device_id.0.to_string())
+            .bind(aggregate.sensor_id.0.to_string())
+            .bind(aggregate.metric as i32)
+            .bind(aggregate.granularity as i32)
+            .bind(aggregate.bucket_start.as_second())
+            .bind(aggregate.count as i64)
+            .bind(aggregate.sum)
+            .bind(aggregate.min)
+            .bind(aggregate.max)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn list(&self, filter: AggregateFilter) -> Result<Vec<Aggregate>, Self::Error> {
+        let mut query_builder = QueryBuilder::new(
+            "SELECT device_id, sensor_id, metric, granularity, bucket_start, count, sum, min, max \
+             FROM aggregates WHERE granularity = ",
+        );
+        query_builder.push_bind(filter.granularity as i32);
+
+        let mut query_builder = filter_aggregates(query_builder, filter);
+        query_builder.push(" ORDER BY bucket_start ASC, sensor_id ASC");
+
+        let rows = query_builder.build().fetch_all(&self.pool).await?;
+
+        rows.into_iter().map(map_row_to_aggregate).collect()
+    }
+}
+
+fn filter_aggregates(
+    mut query_builder: QueryBuilder<Sqlite>,
+    filter: AggregateFilter,
+) -> QueryBuilder<Sqlite> {
+    if let Some(device_ids) = filter.device_ids
+        && !device_ids.is_empty()
+    {
+        query_builder.push(" AND device_id IN (");
+        let mut separated = query_builder.separated(", ");
+        for id in device_ids {
+            separated.push_bind(id.0.to_string());
+        }
+        separated.push_unseparated(")");
+    }
+
+    if let Some(sensor_ids) = filter.sensor_ids
+        && !sensor_ids.is_empty()
+    {
+        query_builder.push(" AND sensor_id IN (");
+        let mut separated = query_builder.separated(", ");
+        for id in sensor_ids {
+            separated.push_bind(id.0.to_string());
+        }
+        separated.push_unseparated(")");
+    }
+
+    if let Some(kinds) = filter.metric_kinds
+        && !kinds.is_empty()
+    {
+        query_builder.push(" AND metric IN (");
+        let mut separated = query_builder.separated(", ");
+        for kind in kinds {
+            separated.push_bind(kind as i32);
+        }
+        separated.push_unseparated(")");
+    }
+
+    if let Some(after) = filter.after {
+        query_builder.push(" AND bucket_start >= ");
+        query_builder.push_bind(after.as_second());
+    }
+
+    if let Some(before) = filter.before {
+        query_builder.push(" AND bucket_start <= ");
+        query_builder.push_bind(before.as_second());
+    }
+
+    query_builder
+}
+
+fn map_row_to_aggregate(row: SqliteRow) -> Result<Aggregate, SqliteAggregateError> {
+    let parse_ulid = |column: &str| -> Result<Ulid, SqliteAggregateError> {
+        let s: String = row.try_get(column)?;
+        Ulid::from_str(&s).map_err(|_| SqliteAggregateError::InvalidUlid(s))
+    };
+    let device_id = DeviceId(parse_ulid("device_id")?);
+    let sensor_id = SensorId(parse_ulid("sensor_id")?);
+
+    let metric = match row.try_get::<i32, _>("metric")? {
+        0 => SensorKind::SoilMoisture,
+        1 => SensorKind::SoilTemp,
+        2 => SensorKind::AirTemp,
+        3 => SensorKind::Humidity,
+        4 => SensorKind::Rainfall,
+        other => return Err(SqliteAggregateError::InvalidSensorKind(other)),
+    };
+
+    let granularity = match row.try_get::<i32, _>("granularity")? {
+        0 => Granularity::Hour,
+        1 => Granularity::Day,
+        other => return Err(SqliteAggregateError::InvalidGranularity(other)),
+    };
+
+    let bucket_start: i64 = row.try_get("bucket_start")?;
+    let bucket_start = jiff::Timestamp::from_second(bucket_start)
+        .map_err(|_| SqliteAggregateError::InvalidTimestamp(bucket_start))?;
+
+    let count: i64 = row.try_get("count")?;
+
+    Ok(Aggregate {
+        device_id,
+        sensor_id,
+        metric,
+        granularity,
+        bucket_start,
+        count: count as u64,
+        sum: row.try_get("sum")?,
+        min: row.try_get("min")?,
+        max: row.try_get("max")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, SensorId, SensorKind};
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::SqliteAggregateRegistry;
+    use crate::registry::{AggregateRegistry, filter::AggregateFilter};
+    use crate::rollup::{Aggregate, Granularity};
+
+    fn aggregate(sensor_id: SensorId, bucket: i64, value: f64) -> Aggregate {
+        Aggregate {
+            device_id: DeviceId(Ulid::from_parts(0, 1)),
+            sensor_id,
+            metric: SensorKind::Rainfall,
+            granularity: Granularity::Hour,
+            bucket_start: Timestamp::from_second(bucket).unwrap(),
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_upserts_buckets() {
+        let registry = SqliteAggregateRegistry::new_in_memory().await.unwrap();
+        let sensor = SensorId(Ulid::new());
+
+        registry
+            .merge(vec![
+                aggregate(sensor, 0, 2.0),
+                aggregate(sensor, 3_600, 1.0),
+            ])
+            .await
+            .unwrap();
+        registry
+            .merge(vec![aggregate(sensor, 0, 6.0)])
+            .await
+            .unwrap();
+
+        let all = registry
+            .list(AggregateFilter::builder(Granularity::Hour).build())
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].count, 2);
+        assert_eq!(all[0].sum, 8.0);
+        assert_eq!(all[0].max, 6.0);
+        assert_eq!(all[1].bucket_start.as_second(), 3_600);
+
+        let later = registry
+            .list(
+                AggregateFilter::builder(Granularity::Hour)
+                    .sensor_ids([sensor])
+                    .after(Timestamp::from_second(1).unwrap())
+                    .build(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(later.len(), 1);
+
+        let daily = registry
+            .list(AggregateFilter::builder(Granularity::Day).build())
+            .await
+            .unwrap();
+        assert!(daily.is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::auth::{ApiKey, ApiKeyId, Scope};
+use crate::config::SqlitePoolConfig;
+use crate::org::OrgId;
+use crate::registry::{ApiKeyRegistry, RegistryError};
+use crate::user::UserId;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteApiKeyError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid scope: {0}")]
+    InvalidScope(i32),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteApiKeyError> for RegistryError {
+    fn from(error: SqliteApiKeyError) -> Self {
+        match error {
+            SqliteApiKeyError::NotFound => RegistryError::NotFound,
+            SqliteApiKeyError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteApiKeyRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteApiKeyRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteApiKeyError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteApiKeyError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteApiKeyError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl ApiKeyRegistry for SqliteApiKeyRegistry {
+    type Error = SqliteApiKeyError;
+
+    async fn create(&self, key: ApiKey) -> Result<(), Self::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO api_keys
+                (id, name, scope, org_id, secret_hash, created_at, revoked_at, user_id, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(key.id.0.to_string())
+        .bind(key.name)
+        .bind(key.scope as i32)
+        .bind(key.org_id.map(|org| org.0.to_string()))
+        .bind(key.secret_hash)
+        .bind(key.created_at.as_second())
+        .bind(key.revoked_at.map(|at| at.as_second()))
+        .bind(key.user_id.map(|user| user.0.to_string()))
+        .bind(key.expires_at.map(|at| at.as_second()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: ApiKeyId) -> Result<Option<ApiKey>, Self::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT id, name, scope, org_id, secret_hash, created_at, revoked_at, user_id, expires_at
+            FROM api_keys WHERE id = ?
+            "#,
+        )
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|r| row_to_key(&r)).transpose()
+    }
+
+    async fn revoke(&self, id: ApiKeyId, at: jiff::Timestamp) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE api_keys SET revoked_at = COALESCE(revoked_at, ?) WHERE id = ?
+            "#,
+        )
+        .bind(at.as_second())
+        .bind(id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteApiKeyError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize, Self::Error> {
+        let count: i64 = sqlx::query("SELECT COUNT(*) FROM api_keys")
+            .fetch_one(&self.pool)
+            .await?
+            .try_get(0)?;
+
+        Ok(count as usize)
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, Self::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, scope, org_id, secret_hash, created_at, revoked_at, user_id, expires_at
+            FROM api_keys ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(row_to_key).collect()
+    }
+}
+
+fn row_to_key(r: &SqliteRow) -> Result<ApiKey, SqliteApiKeyError> {
+    let id = r.try_get::<String, _>("id")?;
+    let ulid = Ulid::from_str(&id).map_err(|_| SqliteApiKeyError::InvalidUlid(id))?;
+
+    let scope = match r.try_get::<i32, _>("scope")? {
+        0 => Scope::ReadOnly,
+        1 => Scope::Admin,
+        2 => Scope::Dispatcher,
+        3 => Scope::Agronomist,
+        other => return Err(SqliteApiKeyError::InvalidScope(other)),
+    };
+
+    let org_id = r
+        .try_get::<Option<String>, _>("org_id")?
+        .map(|id| {
+            Ulid::from_str(&id)
+                .map(OrgId)
+                .map_err(|_| SqliteApiKeyError::InvalidUlid(id))
+        })
+        .transpose()?;
+    let user_id = r
+        .try_get::<Option<String>, _>("user_id")?
+        .map(|id| {
+            Ulid::from_str(&id)
+                .map(UserId)
+                .map_err(|_| SqliteApiKeyError::InvalidUlid(id))
+        })
+        .transpose()?;
+
+    let timestamp = |secs: i64| {
+        jiff::Timestamp::from_second(secs).map_err(|_| SqliteApiKeyError::InvalidTimestamp(secs))
+    };
+
+    Ok(ApiKey {
+        id: ApiKeyId(ulid),
+        name: r.try_get("name")?,
+        scope,
+        org_id,
+        secret_hash: r.try_get("secret_hash")?,
+        created_at: timestamp(r.try_get("created_at")?)?,
+        revoked_at: r
+            .try_get::<Option<i64>, _>("revoked_at")?
+            .map(timestamp)
+            .transpose()?,
+        user_id,
+        expires_at: r
+            .try_get::<Option<i64>, _>("expires_at")?
+            .map(timestamp)
+            .transpose()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SqliteApiKeyError, SqliteApiKeyRegistry};
+    use crate::auth::{ApiKey, ApiKeyId, Scope, parse_token};
+    use crate::registry::ApiKeyRegistry;
+    use ulid::Ulid;
+
+    #[tokio::test]
+    async fn test_sqlite_create_get_and_revoke() {
+        let registry = SqliteApiKeyRegistry::new_in_memory().await.unwrap();
+        let (key, token) = ApiKey::generate("ops", Scope::Admin);
+        let id = key.id;
+
+        registry.create(key).await.unwrap();
+
+        let stored = registry.get(id).await.unwrap().expect("key should exist");
+        let (_, secret) = parse_token(&token).unwrap();
+        assert!(stored.verify(secret));
+        assert_eq!(stored.scope, Scope::Admin);
+        assert_eq!(registry.list().await.unwrap().len(), 1);
+
+        registry.revoke(id, jiff::Timestamp::now()).await.unwrap();
+        assert!(registry.get(id).await.unwrap().unwrap().is_revoked());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_revoke_unknown_key() {
+        let registry = SqliteApiKeyRegistry::new_in_memory().await.unwrap();
+
+        let result = registry
+            .revoke(ApiKeyId(Ulid::new()), jiff::Timestamp::now())
+            .await;
+        assert!(matches!(result, Err(SqliteApiKeyError::NotFound)));
+    }
+}
@@ -0,0 +1,310 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{
+    QueryBuilder, Row, Sqlite, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions,
+    sqlite::SqliteRow,
+};
+use ulid::Ulid;
+
+use crate::audit::{AuditAction, AuditEntry, AuditId, EntityKind};
+use crate::auth::ApiKeyId;
+use crate::config::SqlitePoolConfig;
+use crate::org::OrgId;
+use crate::registry::{
+    AuditRegistry, RegistryError,
+    filter::{AuditFilter, AuditSortBy, Pagination, QueryOptions, SortOrder},
+};
+
+use super::push_after;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteAuditError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid audit action: {0}")]
+    InvalidAction(String),
+    #[error("invalid entity kind: {0}")]
+    InvalidEntity(String),
+}
+
+impl From<SqliteAuditError> for RegistryError {
+    fn from(error: SqliteAuditError) -> Self {
+        match error {
+            SqliteAuditError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteAuditRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteAuditRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteAuditError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteAuditError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteAuditError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl AuditRegistry for SqliteAuditRegistry {
+    type Error = SqliteAuditError;
+
+    async fn record(&self, entry: AuditEntry) -> Result<(), Self::Error> {
+        let details = entry
+            .details
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log (id, at, actor, org_id, action, entity, entity_id, details)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(entry.id.0.to_string())
+        .bind(entry.at.as_second())
+        .bind(entry.actor.map(|actor| actor.0.to_string()))
+        .bind(entry.org_id.map(|org| org.0.to_string()))
+        .bind(entry.action.as_str())
+        .bind(entry.entity.as_str())
+        .bind(entry.entity_id.to_string())
+        .bind(details)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn count(&self, filter: Option<AuditFilter>) -> Result<usize, Self::Error> {
+        let query_builder = QueryBuilder::new("SELECT COUNT(*) FROM audit_log WHERE 1=1");
+        let mut query_builder = filter_entries(query_builder, filter.unwrap_or_default());
+
+        let count: i64 = query_builder
+            .build()
+            .fetch_one(&self.pool)
+            .await?
+            .try_get(0)?;
+
+        Ok(count as usize)
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<AuditFilter, AuditSortBy>,
+    ) -> Result<Vec<AuditEntry>, Self::Error> {
+        let query_builder = QueryBuilder::new(
+            "SELECT id, at, actor, org_id, action, entity, entity_id, details \
+             FROM audit_log WHERE 1=1",
+        );
+        let mut query_builder = filter_entries(query_builder, options.filter);
+
+        let order = match options.sort_order {
+            SortOrder::Asc => " ASC",
+            SortOrder::Desc => " DESC",
+        };
+        let column = match options.sort_by {
+            AuditSortBy::At => "at",
+        };
+
+        if let Pagination::Cursor {
+            after: Some(after), ..
+        } = &options.pagination
+        {
+            query_builder.push(" AND ");
+            push_after(
+                &mut query_builder,
+                column,
+                &options.sort_order,
+                after,
+                jiff::Timestamp::as_second,
+            );
+        }
+
+        query_builder.push(format!(" ORDER BY {column}{order}, id{order}"));
+
+        match options.pagination {
+            Pagination::Offset { offset, limit } => {
+                query_builder.push(" LIMIT ");
+                query_builder.push_bind(limit as i64);
+                query_builder.push(" OFFSET ");
+                query_builder.push_bind(offset as i64);
+            }
+            Pagination::Cursor { limit, .. } => {
+                query_builder.push(" LIMIT ");
+                query_builder.push_bind(limit as i64);
+            }
+        }
+
+        let rows = query_builder.build().fetch_all(&self.pool).await?;
+
+        rows.into_iter().map(map_row_to_entry).collect()
+    }
+}
+
+fn filter_entries(
+    mut query_builder: QueryBuilder<Sqlite>,
+    filter: AuditFilter,
+) -> QueryBuilder<Sqlite> {
+    if let Some(entity) = filter.entity {
+        query_builder.push(" AND entity = ");
+        query_builder.push_bind(entity.as_str());
+    }
+
+    if let Some(ids) = filter.entity_ids
+        && !ids.is_empty()
+    {
+        query_builder.push(" AND entity_id IN (");
+        let mut separated = query_builder.separated(", ");
+        for id in ids {
+            separated.push_bind(id.to_string());
+        }
+        separated.push_unseparated(")");
+    }
+
+    if let Some(actions) = filter.actions
+        && !actions.is_empty()
+    {
+        query_builder.push(" AND action IN (");
+        let mut separated = query_builder.separated(", ");
+        for action in actions {
+            separated.push_bind(action.as_str());
+        }
+        separated.push_unseparated(")");
+    }
+
+    if let Some(actor) = filter.actor {
+        query_builder.push(" AND actor = ");
+        query_builder.push_bind(actor.0.to_string());
+    }
+
+    if let Some(org_id) = filter.org_id {
+        query_builder.push(" AND org_id = ");
+        query_builder.push_bind(org_id.0.to_string());
+    }
+
+    if let Some(after) = filter.after {
+        query_builder.push(" AND at >= ");
+        query_builder.push_bind(after.as_second());
+    }
+
+    if let Some(before) = filter.before {
+        query_builder.push(" AND at <= ");
+        query_builder.push_bind(before.as_second());
+    }
+
+    query_builder
+}
+
+fn map_row_to_entry(row: SqliteRow) -> Result<AuditEntry, SqliteAuditError> {
+    let parse_ulid = |s: String| Ulid::from_str(&s).map_err(|_| SqliteAuditError::InvalidUlid(s));
+
+    let at: i64 = row.try_get("at")?;
+    let action: String = row.try_get("action")?;
+    let entity: String = row.try_get("entity")?;
+    let details: Option<String> = row.try_get("details")?;
+
+    Ok(AuditEntry {
+        id: AuditId(parse_ulid(row.try_get("id")?)?),
+        at: jiff::Timestamp::from_second(at).map_err(|_| SqliteAuditError::InvalidTimestamp(at))?,
+        actor: row
+            .try_get::<Option<String>, _>("actor")?
+            .map(|s| parse_ulid(s).map(ApiKeyId))
+            .transpose()?,
+        org_id: row
+            .try_get::<Option<String>, _>("org_id")?
+            .map(|s| parse_ulid(s).map(OrgId))
+            .transpose()?,
+        action: AuditAction::parse(&action).ok_or(SqliteAuditError::InvalidAction(action))?,
+        entity: EntityKind::parse(&entity).ok_or(SqliteAuditError::InvalidEntity(entity))?,
+        entity_id: parse_ulid(row.try_get("entity_id")?)?,
+        details: details.as_deref().map(serde_json::from_str).transpose()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::SqliteAuditRegistry;
+    use crate::audit::{AuditAction, AuditEntry, EntityKind};
+    use crate::registry::{
+        AuditRegistry,
+        filter::{AuditFilter, AuditSortBy, Pagination, QueryOptions, SortOrder},
+    };
+
+    fn entry(action: AuditAction, entity_id: Ulid, at: i64) -> AuditEntry {
+        AuditEntry {
+            at: Timestamp::from_second(at).unwrap(),
+            ..AuditEntry::system(action, EntityKind::Device, entity_id)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_and_page_newest_first() {
+        let registry = SqliteAuditRegistry::new_in_memory().await.unwrap();
+        let device = Ulid::new();
+        let registered = entry(AuditAction::Register, device, 10)
+            .with_details(serde_json::json!({ "location": "8a2a1072b59ffff" }));
+        let suspended = entry(AuditAction::Suspend, device, 20);
+        let other = entry(AuditAction::Register, Ulid::new(), 30);
+        for e in [&registered, &suspended, &other] {
+            registry.record(e.clone()).await.unwrap();
+        }
+
+        let filter = AuditFilter::builder()
+            .entity(EntityKind::Device)
+            .entity_ids([device])
+            .build();
+        assert_eq!(registry.count(Some(filter.clone())).await.unwrap(), 2);
+
+        let page = |after| {
+            registry.list(QueryOptions {
+                filter: filter.clone(),
+                sort_by: AuditSortBy::At,
+                sort_order: SortOrder::Desc,
+                pagination: Pagination::Cursor { after, limit: 1 },
+            })
+        };
+        let first = page(None).await.unwrap();
+        assert_eq!(first, vec![suspended]);
+        let second = page(Some(AuditSortBy::At.cursor(&first[0]))).await.unwrap();
+        assert_eq!(second, vec![registered]);
+
+        let register_after = AuditFilter::builder()
+            .actions([AuditAction::Register])
+            .after(Timestamp::from_second(15).unwrap())
+            .build();
+        assert_eq!(registry.count(Some(register_after)).await.unwrap(), 1);
+    }
+}
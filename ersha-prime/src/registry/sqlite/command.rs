@@ -0,0 +1,322 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{CommandId, DeviceId, DispatcherId};
+use jiff::Timestamp;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::command::{Command, CommandState};
+use crate::config::SqlitePoolConfig;
+use crate::registry::{CommandRegistry, RegistryError};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteCommandError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid command state: {0}")]
+    InvalidState(i32),
+}
+
+impl From<SqliteCommandError> for RegistryError {
+    fn from(error: SqliteCommandError) -> Self {
+        match error {
+            SqliteCommandError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteCommandRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteCommandRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteCommandError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteCommandError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteCommandError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const COLUMNS: &str = "id, device_id, dispatcher_id, kind, state, created_at, expires_at, \
+                       delivered_at, acked_at";
+
+#[async_trait]
+impl CommandRegistry for SqliteCommandRegistry {
+    type Error = SqliteCommandError;
+
+    async fn enqueue(&self, command: Command) -> Result<(), Self::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO commands
+                (id, device_id, dispatcher_id, kind, state, created_at, expires_at, delivered_at, acked_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(command.id.0.to_string())
+        .bind(command.device_id.0.to_string())
+        .bind(command.dispatcher_id.0.to_string())
+        .bind(serde_json::to_string(&command.kind)?)
+        .bind(command.state as i32)
+        .bind(command.created_at.as_second())
+        .bind(command.expires_at.as_second())
+        .bind(command.delivered_at.map(|t| t.as_second()))
+        .bind(command.acked_at.map(|t| t.as_second()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: CommandId) -> Result<Option<Command>, Self::Error> {
+        let row = sqlx::query(&format!("SELECT {COLUMNS} FROM commands WHERE id = ?"))
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(map_row_to_command).transpose()
+    }
+
+    async fn list(&self, device: DeviceId, limit: usize) -> Result<Vec<Command>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {COLUMNS} FROM commands WHERE device_id = ? ORDER BY id DESC LIMIT ?"
+        ))
+        .bind(device.0.to_string())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_command).collect()
+    }
+
+    async fn deliver(
+        &self,
+        dispatcher: DispatcherId,
+        now: Timestamp,
+        limit: usize,
+    ) -> Result<Vec<Command>, Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query(&format!(
+            "SELECT {COLUMNS} FROM commands \
+             WHERE dispatcher_id = ? AND state = ? AND expires_at > ? \
+             ORDER BY id ASC LIMIT ?"
+        ))
+        .bind(dispatcher.0.to_string())
+        .bind(CommandState::Queued as i32)
+        .bind(now.as_second())
+        .bind(limit as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut delivered = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut command = map_row_to_command(row)?;
+            command.state = CommandState::Delivered;
+            command.delivered_at = Some(now);
+
+            sqlx::query("UPDATE commands SET state = ?, delivered_at = ? WHERE id = ?")
+                .bind(command.state as i32)
+                .bind(now.as_second())
+                .bind(command.id.0.to_string())
+                .execute(&mut *tx)
+                .await?;
+
+            delivered.push(command);
+        }
+
+        tx.commit().await?;
+
+        Ok(delivered)
+    }
+
+    async fn ack(
+        &self,
+        dispatcher: DispatcherId,
+        ids: Vec<CommandId>,
+        now: Timestamp,
+    ) -> Result<usize, Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut acked = 0;
+        for id in ids {
+            let result = sqlx::query(
+                "UPDATE commands SET state = ?, acked_at = ? \
+                 WHERE id = ? AND dispatcher_id = ? AND state = ?",
+            )
+            .bind(CommandState::Acked as i32)
+            .bind(now.as_second())
+            .bind(id.0.to_string())
+            .bind(dispatcher.0.to_string())
+            .bind(CommandState::Delivered as i32)
+            .execute(&mut *tx)
+            .await?;
+            acked += result.rows_affected() as usize;
+        }
+
+        tx.commit().await?;
+
+        Ok(acked)
+    }
+
+    async fn expire(&self, now: Timestamp) -> Result<usize, Self::Error> {
+        let result =
+            sqlx::query("UPDATE commands SET state = ? WHERE state IN (?, ?) AND expires_at <= ?")
+                .bind(CommandState::Expired as i32)
+                .bind(CommandState::Queued as i32)
+                .bind(CommandState::Delivered as i32)
+                .bind(now.as_second())
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected() as usize)
+    }
+}
+
+fn map_row_to_command(row: SqliteRow) -> Result<Command, SqliteCommandError> {
+    let parse_ulid = |column: &str| -> Result<Ulid, SqliteCommandError> {
+        let s: String = row.try_get(column)?;
+        Ulid::from_str(&s).map_err(|_| SqliteCommandError::InvalidUlid(s))
+    };
+    let parse_timestamp = |second: i64| {
+        Timestamp::from_second(second).map_err(|_| SqliteCommandError::InvalidTimestamp(second))
+    };
+
+    let state = match row.try_get::<i32, _>("state")? {
+        0 => CommandState::Queued,
+        1 => CommandState::Delivered,
+        2 => CommandState::Acked,
+        3 => CommandState::Expired,
+        other => return Err(SqliteCommandError::InvalidState(other)),
+    };
+    let kind: String = row.try_get("kind")?;
+
+    Ok(Command {
+        id: CommandId(parse_ulid("id")?),
+        device_id: DeviceId(parse_ulid("device_id")?),
+        dispatcher_id: DispatcherId(parse_ulid("dispatcher_id")?),
+        kind: serde_json::from_str(&kind)?,
+        state,
+        created_at: parse_timestamp(row.try_get("created_at")?)?,
+        expires_at: parse_timestamp(row.try_get("expires_at")?)?,
+        delivered_at: row
+            .try_get::<Option<i64>, _>("delivered_at")?
+            .map(parse_timestamp)
+            .transpose()?,
+        acked_at: row
+            .try_get::<Option<i64>, _>("acked_at")?
+            .map(parse_timestamp)
+            .transpose()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{CommandId, CommandKind, DeviceId, DispatcherId};
+    use jiff::{SignedDuration, Timestamp};
+    use ulid::Ulid;
+
+    use super::SqliteCommandRegistry;
+    use crate::command::{Command, CommandState};
+    use crate::registry::CommandRegistry;
+
+    fn at(second: i64) -> Timestamp {
+        Timestamp::from_second(second).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_command_lifecycle() {
+        let registry = SqliteCommandRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let dispatcher = DispatcherId(Ulid::new());
+        let ttl = SignedDuration::from_secs(60);
+
+        let valve = Command {
+            id: CommandId(Ulid::from_parts(0, 1)),
+            ..Command::new(
+                device,
+                dispatcher,
+                CommandKind::Actuate {
+                    channel: 1,
+                    on: true,
+                },
+                at(0),
+                ttl,
+            )
+        };
+        let stale = Command {
+            id: CommandId(Ulid::from_parts(0, 2)),
+            ..Command::new(device, dispatcher, CommandKind::Reboot, at(0), ttl)
+        };
+        registry.enqueue(valve.clone()).await.unwrap();
+        registry.enqueue(stale.clone()).await.unwrap();
+
+        let delivered = registry.deliver(dispatcher, at(10), 1).await.unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].id, valve.id);
+        assert_eq!(delivered[0].state, CommandState::Delivered);
+
+        let other = DispatcherId(Ulid::new());
+        assert_eq!(
+            registry.ack(other, vec![valve.id], at(20)).await.unwrap(),
+            0
+        );
+        assert_eq!(
+            registry
+                .ack(dispatcher, vec![valve.id, stale.id], at(20))
+                .await
+                .unwrap(),
+            1
+        );
+
+        assert_eq!(registry.expire(at(60)).await.unwrap(), 1);
+        assert!(
+            registry
+                .deliver(dispatcher, at(61), 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let listed = registry.list(device, 10).await.unwrap();
+        assert_eq!(listed.len(), 2);
+        let valve = registry.get(valve.id).await.unwrap().unwrap();
+        assert_eq!(valve.state, CommandState::Acked);
+        assert_eq!(valve.acked_at, Some(at(20)));
+        assert_eq!(
+            valve.kind,
+            CommandKind::Actuate {
+                channel: 1,
+                on: true
+            }
+        );
+        let stale = registry.get(stale.id).await.unwrap().unwrap();
+        assert_eq!(stale.state, CommandState::Expired);
+    }
+}
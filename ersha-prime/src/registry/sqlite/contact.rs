@@ -0,0 +1,391 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::notify::{Channel, Contact, ContactId, Notification, NotificationId};
+use crate::org::OrgId;
+use crate::registry::{ContactRegistry, RegistryError};
+use crate::webhook::DeliveryState;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteContactError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid channel: {0}")]
+    InvalidChannel(String),
+    #[error("invalid delivery state: {0}")]
+    InvalidState(i32),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteContactError> for RegistryError {
+    fn from(error: SqliteContactError) -> Self {
+        match error {
+            SqliteContactError::NotFound => RegistryError::NotFound,
+            SqliteContactError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteContactRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteContactRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteContactError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteContactError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteContactError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const CONTACT_COLUMNS: &str =
+    "id, name, channels, events, thresholds, quiet_hours, org_id, created_at";
+
+const NOTIFICATION_COLUMNS: &str = "id, contact_id, channel, address, event, state, attempts, \
+                                    next_attempt_at, last_attempt_at, last_error";
+
+#[async_trait]
+impl ContactRegistry for SqliteContactRegistry {
+    type Error = SqliteContactError;
+
+    async fn create(&self, contact: Contact) -> Result<(), Self::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO contacts ({CONTACT_COLUMNS}) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(contact.id.0.to_string())
+        .bind(contact.name)
+        .bind(serde_json::to_string(&contact.channels)?)
+        .bind(serde_json::to_string(&contact.events)?)
+        .bind(serde_json::to_string(&contact.thresholds)?)
+        .bind(
+            contact
+                .quiet_hours
+                .map(|q| serde_json::to_string(&q))
+                .transpose()?,
+        )
+        .bind(contact.org_id.map(|org| org.0.to_string()))
+        .bind(contact.created_at.as_second())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: ContactId) -> Result<Option<Contact>, Self::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {CONTACT_COLUMNS} FROM contacts WHERE id = ?"
+        ))
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(map_row_to_contact).transpose()
+    }
+
+    async fn update(&self, contact: Contact) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE contacts
+            SET name = ?, channels = ?, events = ?, thresholds = ?, quiet_hours = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(contact.name)
+        .bind(serde_json::to_string(&contact.channels)?)
+        .bind(serde_json::to_string(&contact.events)?)
+        .bind(serde_json::to_string(&contact.thresholds)?)
+        .bind(
+            contact
+                .quiet_hours
+                .map(|q| serde_json::to_string(&q))
+                .transpose()?,
+        )
+        .bind(contact.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteContactError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: ContactId) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM notifications WHERE contact_id = ?")
+            .bind(id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query("DELETE FROM contacts WHERE id = ?")
+            .bind(id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteContactError::NotFound);
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Contact>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {CONTACT_COLUMNS} FROM contacts ORDER BY id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_contact).collect()
+    }
+
+    async fn enqueue(&self, notifications: Vec<Notification>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for notification in notifications {
+            sqlx::query(&format!(
+                "INSERT INTO notifications ({NOTIFICATION_COLUMNS}) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            ))
+            .bind(notification.id.0.to_string())
+            .bind(notification.contact_id.0.to_string())
+            .bind(notification.channel.as_str())
+            .bind(notification.address)
+            .bind(serde_json::to_string(&notification.event)?)
+            .bind(notification.state as i32)
+            .bind(notification.attempts as i64)
+            .bind(notification.next_attempt_at.as_second())
+            .bind(notification.last_attempt_at.map(|at| at.as_second()))
+            .bind(notification.last_error)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn due(
+        &self,
+        now: jiff::Timestamp,
+        limit: usize,
+    ) -> Result<Vec<Notification>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {NOTIFICATION_COLUMNS} FROM notifications \
+             WHERE state = ? AND next_attempt_at <= ? \
+             ORDER BY next_attempt_at, id LIMIT ?"
+        ))
+        .bind(DeliveryState::Pending as i32)
+        .bind(now.as_second())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_notification).collect()
+    }
+
+    async fn update_notification(&self, notification: Notification) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE notifications
+            SET state = ?, attempts = ?, next_attempt_at = ?, last_attempt_at = ?, last_error = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(notification.state as i32)
+        .bind(notification.attempts as i64)
+        .bind(notification.next_attempt_at.as_second())
+        .bind(notification.last_attempt_at.map(|at| at.as_second()))
+        .bind(notification.last_error)
+        .bind(notification.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteContactError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn notifications(
+        &self,
+        id: ContactId,
+        limit: usize,
+    ) -> Result<Vec<Notification>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {NOTIFICATION_COLUMNS} FROM notifications \
+             WHERE contact_id = ? ORDER BY id DESC LIMIT ?"
+        ))
+        .bind(id.0.to_string())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_notification).collect()
+    }
+}
+
+fn parse_ulid(row: &SqliteRow, column: &str) -> Result<Ulid, SqliteContactError> {
+    let s: String = row.try_get(column)?;
+    Ulid::from_str(&s).map_err(|_| SqliteContactError::InvalidUlid(s))
+}
+
+fn parse_timestamp(second: i64) -> Result<jiff::Timestamp, SqliteContactError> {
+    jiff::Timestamp::from_second(second).map_err(|_| SqliteContactError::InvalidTimestamp(second))
+}
+
+fn map_row_to_contact(row: SqliteRow) -> Result<Contact, SqliteContactError> {
+    let channels: String = row.try_get("channels")?;
+    let events: String = row.try_get("events")?;
+    let thresholds: String = row.try_get("thresholds")?;
+    let quiet_hours: Option<String> = row.try_get("quiet_hours")?;
+
+    Ok(Contact {
+        id: ContactId(parse_ulid(&row, "id")?),
+        name: row.try_get("name")?,
+        channels: serde_json::from_str(&channels)?,
+        events: serde_json::from_str(&events)?,
+        thresholds: serde_json::from_str(&thresholds)?,
+        quiet_hours: quiet_hours.map(|q| serde_json::from_str(&q)).transpose()?,
+        org_id: row
+            .try_get::<Option<String>, _>("org_id")?
+            .map(|id| {
+                Ulid::from_str(&id)
+                    .map(OrgId)
+                    .map_err(|_| SqliteContactError::InvalidUlid(id))
+            })
+            .transpose()?,
+        created_at: parse_timestamp(row.try_get("created_at")?)?,
+    })
+}
+
+fn map_row_to_notification(row: SqliteRow) -> Result<Notification, SqliteContactError> {
+    let channel = match row.try_get::<String, _>("channel")?.as_str() {
+        "sms" => Channel::Sms,
+        "telegram" => Channel::Telegram,
+        other => return Err(SqliteContactError::InvalidChannel(other.to_owned())),
+    };
+    let event: String = row.try_get("event")?;
+    let state = match row.try_get::<i32, _>("state")? {
+        0 => DeliveryState::Pending,
+        1 => DeliveryState::Delivered,
+        2 => DeliveryState::Failed,
+        other => return Err(SqliteContactError::InvalidState(other)),
+    };
+    let attempts: i64 = row.try_get("attempts")?;
+
+    Ok(Notification {
+        id: NotificationId(parse_ulid(&row, "id")?),
+        contact_id: ContactId(parse_ulid(&row, "contact_id")?),
+        channel,
+        address: row.try_get("address")?,
+        event: serde_json::from_str(&event)?,
+        state,
+        attempts: attempts as u32,
+        next_attempt_at: parse_timestamp(row.try_get("next_attempt_at")?)?,
+        last_attempt_at: row
+            .try_get::<Option<i64>, _>("last_attempt_at")?
+            .map(parse_timestamp)
+            .transpose()?,
+        last_error: row.try_get("last_error")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::SqliteContactRegistry;
+    use crate::notify::{Channel, Contact, ContactChannel, QuietHours};
+    use crate::registry::ContactRegistry;
+    use crate::webhook::{DeliveryState, Event, EventKind};
+
+    #[tokio::test]
+    async fn test_contacts_and_notifications_round_trip() {
+        let registry = SqliteContactRegistry::new_in_memory().await.unwrap();
+        let mut contact = Contact::new(
+            "Almaz".to_owned(),
+            vec![ContactChannel {
+                channel: Channel::Telegram,
+                address: "123456789".to_owned(),
+            }],
+            vec![EventKind::DeviceOffline],
+        );
+        registry.create(contact.clone()).await.unwrap();
+
+        contact.quiet_hours = Some(QuietHours {
+            from_hour: 21,
+            until_hour: 6,
+            utc_offset_minutes: 180,
+        });
+        registry.update(contact.clone()).await.unwrap();
+        let stored = registry.get(contact.id).await.unwrap().unwrap();
+        assert_eq!(stored.quiet_hours, contact.quiet_hours);
+        assert_eq!(stored.channels, contact.channels);
+
+        let event = Event::new(EventKind::DeviceOffline, serde_json::json!({}));
+        let notification = contact.notify(&event).remove(0);
+        registry.enqueue(vec![notification.clone()]).await.unwrap();
+
+        let now = Timestamp::now() + SignedDuration::from_secs(1);
+        let mut due = registry.due(now, 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].channel, Channel::Telegram);
+        assert_eq!(due[0].event, notification.event);
+
+        let mut sent = due.remove(0);
+        sent.state = DeliveryState::Delivered;
+        sent.attempts = 1;
+        registry.update_notification(sent).await.unwrap();
+
+        assert!(registry.due(now, 10).await.unwrap().is_empty());
+        let listed = registry.notifications(contact.id, 10).await.unwrap();
+        assert_eq!(listed[0].state, DeliveryState::Delivered);
+
+        registry.delete(contact.id).await.unwrap();
+        assert_eq!(registry.get(contact.id).await.unwrap(), None);
+        assert!(
+            registry
+                .notifications(contact.id, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{DeviceId, ReadingId, SensorId};
+use jiff::Timestamp;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::correction::{Correction, CorrectionId, CorrectionState, Revision};
+use crate::registry::{CorrectionRegistry, RegistryError};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteCorrectionError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("timestamp out of range: {0}")]
+    TimestampOutOfRange(Timestamp),
+    #[error("invalid state: {0}")]
+    InvalidState(String),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteCorrectionError> for RegistryError {
+    fn from(error: SqliteCorrectionError) -> Self {
+        match error {
+            SqliteCorrectionError::NotFound => RegistryError::NotFound,
+            SqliteCorrectionError::Sqlx(e) => e.into(),
+            e @ SqliteCorrectionError::TimestampOutOfRange(..) => {
+                RegistryError::InvalidInput(e.to_string())
+            }
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteCorrectionRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteCorrectionRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteCorrectionError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteCorrectionError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteCorrectionError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    async fn list_where(
+        &self,
+        condition: &str,
+        bind: &str,
+    ) -> Result<Vec<Correction>, SqliteCorrectionError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {COLUMNS} FROM corrections WHERE {condition} ORDER BY id"
+        ))
+        .bind(bind)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_correction).collect()
+    }
+
+    async fn revisions_where(
+        &self,
+        column: &str,
+        id: Ulid,
+    ) -> Result<Vec<Revision>, SqliteCorrectionError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {REVISION_COLUMNS} FROM reading_revisions WHERE {column} = ? \
+             ORDER BY revised_at, rowid"
+        ))
+        .bind(id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_revision).collect()
+    }
+}
+
+const COLUMNS: &str = "id, device_id, sensor_id, range_from, range_until, transform, reason, \
+                       state, readings, error, created_at, applied_at";
+
+const REVISION_COLUMNS: &str = "reading_id, correction_id, previous, corrected, revised_at";
+
+#[async_trait]
+impl CorrectionRegistry for SqliteCorrectionRegistry {
+    type Error = SqliteCorrectionError;
+
+    async fn create(&self, correction: Correction) -> Result<(), Self::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO corrections ({COLUMNS}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(correction.id.0.to_string())
+        .bind(correction.device_id.0.to_string())
+        .bind(correction.sensor_id.0.to_string())
+        .bind(to_nanos(correction.from)?)
+        .bind(to_nanos(correction.until)?)
+        .bind(serde_json::to_string(&correction.transform)?)
+        .bind(&correction.reason)
+        .bind(correction.state.as_str())
+        .bind(correction.readings as i64)
+        .bind(&correction.error)
+        .bind(correction.created_at.as_second())
+        .bind(correction.applied_at.map(|t| t.as_second()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: CorrectionId) -> Result<Option<Correction>, Self::Error> {
+        let row = sqlx::query(&format!("SELECT {COLUMNS} FROM corrections WHERE id = ?"))
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(map_row_to_correction).transpose()
+    }
+
+    async fn update(&self, correction: Correction) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE corrections
+            SET state = ?, readings = ?, error = ?, applied_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(correction.state.as_str())
+        .bind(correction.readings as i64)
+        .bind(&correction.error)
+        .bind(correction.applied_at.map(|t| t.as_second()))
+        .bind(correction.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteCorrectionError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, device: DeviceId) -> Result<Vec<Correction>, Self::Error> {
+        self.list_where("device_id = ?", &device.0.to_string())
+            .await
+    }
+
+    async fn pending(&self) -> Result<Vec<Correction>, Self::Error> {
+        self.list_where("state = ?", CorrectionState::Pending.as_str())
+            .await
+    }
+
+    async fn record_revisions(&self, revisions: Vec<Revision>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for revision in revisions {
+            sqlx::query(&format!(
+                "INSERT OR IGNORE INTO reading_revisions ({REVISION_COLUMNS}) VALUES (?, ?, ?, ?, ?)"
+            ))
+            .bind(revision.reading_id.0.to_string())
+            .bind(revision.correction_id.0.to_string())
+            .bind(revision.previous)
+            .bind(revision.corrected)
+            .bind(revision.revised_at.as_second())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn revisions(&self, reading: ReadingId) -> Result<Vec<Revision>, Self::Error> {
+        self.revisions_where("reading_id", reading.0).await
+    }
+
+    async fn revisions_by(&self, correction: CorrectionId) -> Result<Vec<Revision>, Self::Error> {
+        self.revisions_where("correction_id", correction.0).await
+    }
+}
+
+fn to_nanos(timestamp: Timestamp) -> Result<i64, SqliteCorrectionError> {
+    i64::try_from(timestamp.as_nanosecond())
+        .map_err(|_| SqliteCorrectionError::TimestampOutOfRange(timestamp))
+}
+
+fn from_nanos(nanos: i64) -> Result<Timestamp, SqliteCorrectionError> {
+    Timestamp::from_nanosecond(i128::from(nanos))
+        .map_err(|_| SqliteCorrectionError::InvalidTimestamp(nanos))
+}
+
+fn from_second(second: i64) -> Result<Timestamp, SqliteCorrectionError> {
+    Timestamp::from_second(second).map_err(|_| SqliteCorrectionError::InvalidTimestamp(second))
+}
+
+fn parse_ulid(s: String) -> Result<Ulid, SqliteCorrectionError> {
+    Ulid::from_str(&s).map_err(|_| SqliteCorrectionError::InvalidUlid(s))
+}
+
+fn map_row_to_correction(row: SqliteRow) -> Result<Correction, SqliteCorrectionError> {
+    let transform: String = row.try_get("transform")?;
+    let state: String = row.try_get("state")?;
+
+    Ok(Correction {
+        id: CorrectionId(parse_ulid(row.try_get("id")?)?),
+        device_id: DeviceId(parse_ulid(row.try_get("device_id")?)?),
+        sensor_id: SensorId(parse_ulid(row.try_get("sensor_id")?)?),
+        from: from_nanos(row.try_get("range_from")?)?,
+        until: from_nanos(row.try_get("range_until")?)?,
+        transform: serde_json::from_str(&transform)?,
+        reason: row.try_get("reason")?,
+        state: CorrectionState::parse(&state).ok_or(SqliteCorrectionError::InvalidState(state))?,
+        readings: row.try_get::<i64, _>("readings")? as usize,
+        error: row.try_get("error")?,
+        created_at: from_second(row.try_get("created_at")?)?,
+        applied_at: row
+            .try_get::<Option<i64>, _>("applied_at")?
+            .map(from_second)
+            .transpose()?,
+    })
+}
+
+fn map_row_to_revision(row: SqliteRow) -> Result<Revision, SqliteCorrectionError> {
+    Ok(Revision {
+        reading_id: ReadingId(parse_ulid(row.try_get("reading_id")?)?),
+        correction_id: CorrectionId(parse_ulid(row.try_get("correction_id")?)?),
+        previous: row.try_get("previous")?,
+        corrected: row.try_get("corrected")?,
+        revised_at: from_second(row.try_get("revised_at")?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, ReadingId, SensorId};
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::SqliteCorrectionRegistry;
+    use crate::correction::{Correction, CorrectionState, Revision, Transform};
+    use crate::registry::CorrectionRegistry;
+
+    #[tokio::test]
+    async fn test_corrections_and_revisions_round_trip() {
+        let registry = SqliteCorrectionRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let mut correction = Correction::new(
+            device,
+            SensorId(Ulid::new()),
+            Timestamp::from_nanosecond(1_700_000_000_123_456_789).unwrap(),
+            Timestamp::from_second(1_702_592_000).unwrap(),
+            Transform::Linear {
+                scale: 0.95,
+                offset: 2.0,
+            },
+            "recalibrated".to_owned(),
+            Timestamp::now(),
+        );
+        registry.create(correction.clone()).await.unwrap();
+
+        let pending = registry.pending().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].from, correction.from);
+        assert_eq!(pending[0].transform, correction.transform);
+
+        let reading = ReadingId(Ulid::new());
+        let revision = Revision {
+            reading_id: reading,
+            correction_id: correction.id,
+            previous: 30.0,
+            corrected: 30.5,
+            revised_at: Timestamp::from_second(1_702_600_000).unwrap(),
+        };
+        registry
+            .record_revisions(vec![revision.clone(), revision.clone()])
+            .await
+            .unwrap();
+        assert_eq!(registry.revisions(reading).await.unwrap(), vec![revision]);
+
+        correction.state = CorrectionState::Applied;
+        correction.readings = 1;
+        registry.update(correction).await.unwrap();
+        assert!(registry.pending().await.unwrap().is_empty());
+        assert_eq!(registry.list(device).await.unwrap()[0].readings, 1);
+    }
+}
@@ -0,0 +1,297 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{BatchId, DispatcherId, InvalidItemReason};
+use jiff::Timestamp;
+use sqlx::{
+    QueryBuilder, Row, SqliteConnection, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions,
+    sqlite::SqliteRow,
+};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::dead_letter::{DeadLetter, DeadLetterId, DeadLetterState};
+use crate::registry::{DeadLetterRegistry, RegistryError, filter::DeadLetterFilter};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteDeadLetterError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid state: {0}")]
+    InvalidState(String),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteDeadLetterError> for RegistryError {
+    fn from(error: SqliteDeadLetterError) -> Self {
+        match error {
+            SqliteDeadLetterError::NotFound => RegistryError::NotFound,
+            SqliteDeadLetterError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteDeadLetterRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteDeadLetterRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteDeadLetterError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteDeadLetterError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteDeadLetterError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const COLUMNS: &str = "id, dispatcher_id, batch_id, kind, item_id, item, reason, state, attempts, \
+                       received_at, redriven_at";
+
+#[async_trait]
+impl DeadLetterRegistry for SqliteDeadLetterRegistry {
+    type Error = SqliteDeadLetterError;
+
+    async fn record(&self, letters: Vec<DeadLetter>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+        record(&mut tx, letters).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: DeadLetterId) -> Result<Option<DeadLetter>, Self::Error> {
+        let row = sqlx::query(&format!("SELECT {COLUMNS} FROM dead_letters WHERE id = ?"))
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(map_row_to_dead_letter).transpose()
+    }
+
+    async fn update(&self, letter: DeadLetter) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE dead_letters
+            SET reason = ?, state = ?, attempts = ?, redriven_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(serde_json::to_string(&letter.reason)?)
+        .bind(letter.state.as_str())
+        .bind(i64::from(letter.attempts))
+        .bind(letter.redriven_at.map(|t| t.as_second()))
+        .bind(letter.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteDeadLetterError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: DeadLetterId) -> Result<(), Self::Error> {
+        let result = sqlx::query("DELETE FROM dead_letters WHERE id = ?")
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteDeadLetterError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        filter: DeadLetterFilter,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, Self::Error> {
+        let mut query_builder =
+            QueryBuilder::new(format!("SELECT {COLUMNS} FROM dead_letters WHERE 1 = 1"));
+        if let Some(ids) = filter.dispatcher_ids.filter(|ids| !ids.is_empty()) {
+            query_builder.push(" AND dispatcher_id IN (");
+            let mut separated = query_builder.separated(", ");
+            for id in ids {
+                separated.push_bind(id.0.to_string());
+            }
+            separated.push_unseparated(")");
+        }
+        if let Some(state) = filter.state {
+            query_builder.push(" AND state = ");
+            query_builder.push_bind(state.as_str());
+        }
+        if let Some(reasons) = filter.reasons.filter(|reasons| !reasons.is_empty()) {
+            query_builder.push(" AND reason IN (");
+            let mut separated = query_builder.separated(", ");
+            for reason in reasons {
+                separated.push_bind(serde_json::to_string(&reason)?);
+            }
+            separated.push_unseparated(")");
+        }
+        query_builder.push(" ORDER BY id LIMIT ");
+        query_builder.push_bind(limit as i64);
+
+        let rows = query_builder.build().fetch_all(&self.pool).await?;
+
+        rows.into_iter().map(map_row_to_dead_letter).collect()
+    }
+}
+
+fn from_second(second: i64) -> Result<Timestamp, SqliteDeadLetterError> {
+    Timestamp::from_second(second).map_err(|_| SqliteDeadLetterError::InvalidTimestamp(second))
+}
+
+fn parse_ulid(s: String) -> Result<Ulid, SqliteDeadLetterError> {
+    Ulid::from_str(&s).map_err(|_| SqliteDeadLetterError::InvalidUlid(s))
+}
+
+/// Insert the dead letters not held yet on `conn`, as part of whatever
+/// transaction it is in.
+pub(super) async fn record(
+    conn: &mut SqliteConnection,
+    letters: Vec<DeadLetter>,
+) -> Result<(), SqliteDeadLetterError> {
+    for letter in letters {
+        sqlx::query(&format!(
+            "INSERT OR IGNORE INTO dead_letters ({COLUMNS}) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(letter.id.0.to_string())
+        .bind(letter.dispatcher_id.0.to_string())
+        .bind(letter.batch_id.0.to_string())
+        .bind(letter.item.kind())
+        .bind(letter.item.item_id().to_string())
+        .bind(serde_json::to_string(&letter.item)?)
+        .bind(serde_json::to_string(&letter.reason)?)
+        .bind(letter.state.as_str())
+        .bind(i64::from(letter.attempts))
+        .bind(letter.received_at.as_second())
+        .bind(letter.redriven_at.map(|t| t.as_second()))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+fn map_row_to_dead_letter(row: SqliteRow) -> Result<DeadLetter, SqliteDeadLetterError> {
+    let item: String = row.try_get("item")?;
+    let reason: String = row.try_get("reason")?;
+    let state: String = row.try_get("state")?;
+
+    Ok(DeadLetter {
+        id: DeadLetterId(parse_ulid(row.try_get("id")?)?),
+        dispatcher_id: DispatcherId(parse_ulid(row.try_get("dispatcher_id")?)?),
+        batch_id: BatchId(parse_ulid(row.try_get("batch_id")?)?),
+        item: serde_json::from_str(&item)?,
+        reason: serde_json::from_str::<InvalidItemReason>(&reason)?,
+        state: DeadLetterState::parse(&state).ok_or(SqliteDeadLetterError::InvalidState(state))?,
+        attempts: row.try_get::<i64, _>("attempts")? as u32,
+        received_at: from_second(row.try_get("received_at")?)?,
+        redriven_at: row
+            .try_get::<Option<i64>, _>("redriven_at")?
+            .map(from_second)
+            .transpose()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        BatchId, DeviceId, DeviceStatus, DispatcherId, InvalidItemReason, Percentage, StatusId,
+    };
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::SqliteDeadLetterRegistry;
+    use crate::dead_letter::{DeadItem, DeadLetter, DeadLetterState};
+    use crate::registry::{DeadLetterRegistry, filter::DeadLetterFilter};
+
+    #[tokio::test]
+    async fn test_dead_letters_round_trip() {
+        let registry = SqliteDeadLetterRegistry::new_in_memory().await.unwrap();
+        let dispatcher = DispatcherId(Ulid::new());
+        let status = DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: dispatcher,
+            battery_percent: Percentage(120),
+            uptime_seconds: 60,
+            signal_rssi: -70,
+            errors: Box::new([]),
+            timestamp: Timestamp::from_second(1_700_000_000).unwrap(),
+            sensor_statuses: Box::new([]),
+        };
+        let mut letter = DeadLetter::new(
+            dispatcher,
+            BatchId(Ulid::new()),
+            DeadItem::Status(status.clone()),
+            InvalidItemReason::PercentageOutOfRange,
+            Timestamp::from_second(1_700_000_100).unwrap(),
+        );
+        let copy = DeadLetter::new(
+            dispatcher,
+            BatchId(Ulid::new()),
+            DeadItem::Status(status),
+            InvalidItemReason::PercentageOutOfRange,
+            Timestamp::from_second(1_700_000_200).unwrap(),
+        );
+        registry
+            .record(vec![letter.clone(), copy.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(registry.get(letter.id).await.unwrap(), Some(letter.clone()));
+        // The same item sent again isn't held twice.
+        assert_eq!(registry.get(copy.id).await.unwrap(), None);
+
+        letter.state = DeadLetterState::Redriven;
+        letter.attempts = 1;
+        letter.redriven_at = Some(Timestamp::from_second(1_700_000_300).unwrap());
+        registry.update(letter.clone()).await.unwrap();
+
+        let pending = DeadLetterFilter::builder()
+            .state(DeadLetterState::Pending)
+            .build();
+        assert!(registry.list(pending, 10).await.unwrap().is_empty());
+        let by_dispatcher = DeadLetterFilter::builder()
+            .dispatcher_ids([dispatcher])
+            .build();
+        assert_eq!(
+            registry.list(by_dispatcher, 10).await.unwrap(),
+            vec![letter.clone()]
+        );
+
+        registry.delete(letter.id).await.unwrap();
+        assert_eq!(registry.get(letter.id).await.unwrap(), None);
+    }
+}
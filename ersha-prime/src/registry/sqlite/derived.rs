@@ -0,0 +1,215 @@
+use async_trait::async_trait;
+use ersha_core::H3Cell;
+use sqlx::{
+    QueryBuilder, Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow,
+};
+
+use crate::config::SqlitePoolConfig;
+use crate::derived::{Indicator, IndicatorKind};
+use crate::registry::{DerivedMetricRegistry, RegistryError, filter::IndicatorFilter};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteDerivedMetricError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid indicator kind: {0}")]
+    InvalidIndicatorKind(i32),
+}
+
+impl From<SqliteDerivedMetricError> for RegistryError {
+    fn from(error: SqliteDerivedMetricError) -> Self {
+        match error {
+            SqliteDerivedMetricError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteDerivedMetricRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteDerivedMetricRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteDerivedMetricError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteDerivedMetricError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteDerivedMetricError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl DerivedMetricRegistry for SqliteDerivedMetricRegistry {
+    type Error = SqliteDerivedMetricError;
+
+    async fn upsert(&self, indicators: Vec<Indicator>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for indicator in indicators {
+            sqlx::query(
+                r#"
+                INSERT INTO indicators (field, kind, day, value, computed_at)
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT(field, kind, day) DO UPDATE SET
+                    value = excluded.value,
+                    computed_at = excluded.computed_at
+                "#,
+            )
+            .bind(indicator.field.0 as i64)
+            .bind(indicator.kind as i32)
+            .bind(indicator.day.as_second())
+            .bind(indicator.value)
+            .bind(indicator.computed_at.as_second())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn list(&self, filter: IndicatorFilter) -> Result<Vec<Indicator>, Self::Error> {
+        let mut query_builder = QueryBuilder::new(
+            "SELECT field, kind, day, value, computed_at FROM indicators WHERE 1=1",
+        );
+
+        if let Some(field) = filter.field {
+            query_builder.push(" AND field = ");
+            query_builder.push_bind(field.0 as i64);
+        }
+
+        if let Some(kinds) = filter.kinds
+            && !kinds.is_empty()
+        {
+            query_builder.push(" AND kind IN (");
+            let mut separated = query_builder.separated(", ");
+            for kind in kinds {
+                separated.push_bind(kind as i32);
+            }
+            separated.push_unseparated(")");
+        }
+
+        if let Some(after) = filter.after {
+            query_builder.push(" AND day >= ");
+            query_builder.push_bind(after.as_second());
+        }
+
+        if let Some(before) = filter.before {
+            query_builder.push(" AND day <= ");
+            query_builder.push_bind(before.as_second());
+        }
+
+        query_builder.push(" ORDER BY day ASC, kind ASC");
+
+        let rows = query_builder.build().fetch_all(&self.pool).await?;
+
+        rows.into_iter().map(map_row_to_indicator).collect()
+    }
+}
+
+fn map_row_to_indicator(row: SqliteRow) -> Result<Indicator, SqliteDerivedMetricError> {
+    let kind = match row.try_get::<i32, _>("kind")? {
+        0 => IndicatorKind::GrowingDegreeDays,
+        1 => IndicatorKind::Rainfall,
+        2 => IndicatorKind::FrostHours,
+        3 => IndicatorKind::SoilMoistureDeficit,
+        other => return Err(SqliteDerivedMetricError::InvalidIndicatorKind(other)),
+    };
+
+    let parse_timestamp = |column: &str| -> Result<jiff::Timestamp, SqliteDerivedMetricError> {
+        let second: i64 = row.try_get(column)?;
+        jiff::Timestamp::from_second(second)
+            .map_err(|_| SqliteDerivedMetricError::InvalidTimestamp(second))
+    };
+
+    Ok(Indicator {
+        field: H3Cell(row.try_get::<i64, _>("field")? as u64),
+        kind,
+        day: parse_timestamp("day")?,
+        value: row.try_get("value")?,
+        computed_at: parse_timestamp("computed_at")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::H3Cell;
+    use jiff::Timestamp;
+
+    use super::SqliteDerivedMetricRegistry;
+    use crate::derived::{Indicator, IndicatorKind};
+    use crate::registry::{DerivedMetricRegistry, filter::IndicatorFilter};
+
+    const FIELD: H3Cell = H3Cell(0x892a1072b5bffff);
+
+    fn indicator(kind: IndicatorKind, day: i64, value: f64) -> Indicator {
+        Indicator {
+            field: FIELD,
+            kind,
+            day: Timestamp::from_second(day).unwrap(),
+            value,
+            computed_at: Timestamp::from_second(day + 3_600).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upsert_replaces_same_day() {
+        let registry = SqliteDerivedMetricRegistry::new_in_memory().await.unwrap();
+
+        registry
+            .upsert(vec![
+                indicator(IndicatorKind::Rainfall, 0, 2.0),
+                indicator(IndicatorKind::FrostHours, 0, 3.0),
+                indicator(IndicatorKind::Rainfall, 86_400, 1.0),
+            ])
+            .await
+            .unwrap();
+        registry
+            .upsert(vec![indicator(IndicatorKind::Rainfall, 0, 5.0)])
+            .await
+            .unwrap();
+
+        let all = registry
+            .list(IndicatorFilter::builder().field(FIELD).build())
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0], indicator(IndicatorKind::Rainfall, 0, 5.0));
+        assert_eq!(all[1].kind, IndicatorKind::FrostHours);
+
+        let rain_later = registry
+            .list(
+                IndicatorFilter::builder()
+                    .kinds([IndicatorKind::Rainfall])
+                    .after(Timestamp::from_second(1).unwrap())
+                    .build(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            rain_later,
+            vec![indicator(IndicatorKind::Rainfall, 86_400, 1.0)]
+        );
+    }
+}
@@ -1,8 +1,8 @@
 use std::str::FromStr;
 
 use ersha_core::{
-    Device, DeviceId, DeviceKind, DeviceState, H3Cell, Percentage, Sensor, SensorId, SensorKind,
-    SensorMetric,
+    Device, DeviceId, DeviceKind, DeviceState, DispatcherId, H3Cell, Percentage, Sensor, SensorId,
+    SensorKind, SensorMetric,
 };
 use ordered_float::NotNan;
 use sqlx::{
@@ -13,13 +13,32 @@ use ulid::Ulid;
 
 use async_trait::async_trait;
 
+use crate::config::SqlitePoolConfig;
+use crate::org::OrgId;
+use crate::outbox::OutboxEntry;
+use crate::placement::Placement;
+use crate::region;
 use crate::registry::{
-    DeviceRegistry,
+    DeviceDetails, DeviceRegistry, RegistryError,
     filter::{DeviceFilter, DeviceSortBy, Pagination, QueryOptions, SortOrder},
 };
+use crate::webhook::Event;
+
+use super::outbox::{self, SqliteOutboxError};
+use super::push_after;
 
 static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
+/// Devices, or sensors, per `INSERT` when registering in bulk.
+const INSERT_CHUNK: usize = 500;
+
+/// Ids bound per `IN (...)` when looking devices up in bulk.
+const LOOKUP_CHUNK: usize = 500;
+
+/// Assignment marking a device as changed now, keeping `updated_at`
+/// strictly increasing. Binds the current time in nanoseconds.
+const TOUCH: &str = "updated_at = MAX(?, COALESCE(updated_at + 1, 0))";
+
 #[derive(Debug, thiserror::Error)]
 pub enum SqliteDeviceError {
     #[error("sqlx error: {0}")]
@@ -38,10 +57,29 @@ pub enum SqliteDeviceError {
     InvalidMetricType(i32),
     #[error("invalid sensor kind: {0}")]
     InvalidSensorKind(i32),
+    #[error("timestamp out of range: {0}")]
+    TimestampOutOfRange(jiff::Timestamp),
+    #[error("invalid metadata: {0}")]
+    InvalidMetadata(serde_json::Error),
+    #[error("outbox error: {0}")]
+    Outbox(#[from] SqliteOutboxError),
     #[error("not found")]
     NotFound,
 }
 
+impl From<SqliteDeviceError> for RegistryError {
+    fn from(error: SqliteDeviceError) -> Self {
+        match error {
+            SqliteDeviceError::NotFound => RegistryError::NotFound,
+            SqliteDeviceError::Sqlx(e) => e.into(),
+            e @ SqliteDeviceError::TimestampOutOfRange(..) => {
+                RegistryError::InvalidInput(e.to_string())
+            }
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SqliteDeviceRegistry {
     pool: SqlitePool,
@@ -49,9 +87,12 @@ pub struct SqliteDeviceRegistry {
 
 impl SqliteDeviceRegistry {
     pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteDeviceError> {
-        let connection_string = format!("sqlite:{}", path.as_ref());
-        let pool = SqlitePoolOptions::new().connect(&connection_string).await?;
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
 
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteDeviceError> {
         MIGRATOR.run(&pool).await?;
 
         Ok(Self { pool })
@@ -64,6 +105,24 @@ impl SqliteDeviceRegistry {
 
         Ok(Self { pool })
     }
+
+    /// Change only the state column; re-registering would reset `sensor_count`.
+    async fn set_state(&self, id: DeviceId, state: DeviceState) -> Result<(), SqliteDeviceError> {
+        let result = sqlx::query(&format!(
+            "UPDATE devices SET state = ?, {TOUCH} WHERE id = ?"
+        ))
+        .bind(state as i32)
+        .bind(now_nanos()?)
+        .bind(id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteDeviceError::NotFound);
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -73,8 +132,14 @@ impl DeviceRegistry for SqliteDeviceRegistry {
     async fn register(&self, device: Device) -> Result<(), Self::Error> {
         sqlx::query(
             r#"
-            INSERT OR REPLACE INTO devices (id, kind, state, location, manufacturer, provisioned_at)
-            VALUES (?, ?, ?, ?, ?, ?)
+            INSERT OR REPLACE INTO devices
+                (id, kind, state, location, manufacturer, provisioned_at, org_id, dispatcher_id,
+                 placement_site, placement_depth_cm, placement_notes, updated_at,
+                 disconnected_since)
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6, old.org_id, old.dispatcher_id,
+                old.placement_site, old.placement_depth_cm, old.placement_notes,
+                MAX(?7, COALESCE(old.updated_at + 1, 0)), old.disconnected_since
+            FROM (SELECT 1) LEFT JOIN devices AS old ON old.id = ?1
             "#,
         )
         .bind(device.id.0.to_string())
@@ -83,6 +148,9 @@ impl DeviceRegistry for SqliteDeviceRegistry {
         .bind(device.location.0 as i64)
         .bind(device.manufacturer)
         .bind(device.provisioned_at.as_second())
+        // Re-registering keeps the device's organization, dispatcher,
+        // placement and connectivity.
+        .bind(now_nanos()?)
         .execute(&self.pool)
         .await?;
 
@@ -92,6 +160,36 @@ impl DeviceRegistry for SqliteDeviceRegistry {
         Ok(())
     }
 
+    async fn register_new(&self, device: Device) -> Result<Option<Device>, Self::Error> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO devices
+                (id, kind, state, location, manufacturer, provisioned_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO NOTHING
+            "#,
+        )
+        .bind(device.id.0.to_string())
+        .bind(device.kind as i32)
+        .bind(device.state as i32)
+        .bind(device.location.0 as i64)
+        .bind(device.manufacturer)
+        .bind(device.provisioned_at.as_second())
+        .bind(now_nanos()?)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        if inserted == 0 {
+            return self.get(device.id).await;
+        }
+
+        self.add_sensors(device.id, device.sensors.into_iter())
+            .await?;
+
+        Ok(None)
+    }
+
     async fn add_sensor(&self, id: DeviceId, sensor: Sensor) -> Result<(), Self::Error> {
         let (metric_type, metric_value) = disect_metric(sensor.metric);
 
@@ -109,6 +207,12 @@ impl DeviceRegistry for SqliteDeviceRegistry {
         .execute(&self.pool)
         .await?;
 
+        sqlx::query(&format!("UPDATE devices SET {TOUCH} WHERE id = ?"))
+            .bind(now_nanos()?)
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
         Ok(())
     }
 
@@ -137,6 +241,12 @@ impl DeviceRegistry for SqliteDeviceRegistry {
             .await?;
         }
 
+        sqlx::query(&format!("UPDATE devices SET {TOUCH} WHERE id = ?"))
+            .bind(now_nanos()?)
+            .bind(id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+
         tx.commit().await?;
         Ok(())
     }
@@ -208,6 +318,7 @@ impl DeviceRegistry for SqliteDeviceRegistry {
         let state = match r.try_get::<i32, _>("state")? {
             0 => DeviceState::Active,
             1 => DeviceState::Suspended,
+            2 => DeviceState::Decommissioned,
             other => return Err(Self::Error::InvalidState(other)),
         };
 
@@ -231,45 +342,530 @@ impl DeviceRegistry for SqliteDeviceRegistry {
         }))
     }
 
-    async fn update(&self, id: DeviceId, new: Device) -> Result<(), Self::Error> {
-        let old = self.get(id).await?.ok_or(Self::Error::NotFound)?;
-        let new = Device { id: old.id, ..new };
+    async fn get_many(&self, ids: &[DeviceId]) -> Result<Vec<Device>, Self::Error> {
+        let mut devices = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(LOOKUP_CHUNK) {
+            let options = QueryOptions {
+                filter: DeviceFilter::builder().ids(chunk.iter().copied()).build(),
+                sort_by: DeviceSortBy::ProvisionAt,
+                sort_order: SortOrder::Asc,
+                pagination: Pagination::Offset {
+                    offset: 0,
+                    limit: chunk.len(),
+                },
+            };
+            devices.extend(self.list(options).await?);
+        }
 
-        self.register(new).await
+        Ok(devices)
     }
 
-    async fn suspend(&self, id: DeviceId) -> Result<(), Self::Error> {
-        let device = self.get(id).await?.ok_or(Self::Error::NotFound)?;
+    async fn update(
+        &self,
+        id: DeviceId,
+        new: Device,
+        placement: Placement,
+        expected: Option<jiff::Timestamp>,
+    ) -> Result<Option<jiff::Timestamp>, Self::Error> {
+        let expected = expected.map(to_nanos).transpose()?;
+        let mut tx = self.pool.begin().await?;
 
-        let new = Device {
-            state: DeviceState::Suspended,
-            ..device
+        let updated_at = sqlx::query(&format!(
+            r#"
+            UPDATE devices SET
+                kind = ?, state = ?, location = ?, manufacturer = ?, provisioned_at = ?,
+                placement_site = ?, placement_depth_cm = ?, placement_notes = ?, {TOUCH}
+            WHERE id = ? AND (? IS NULL OR updated_at = ?)
+            RETURNING updated_at
+            "#
+        ))
+        .bind(new.kind as i32)
+        .bind(new.state as i32)
+        .bind(new.location.0 as i64)
+        .bind(new.manufacturer)
+        .bind(new.provisioned_at.as_second())
+        .bind(placement.site)
+        .bind(placement.depth_cm)
+        .bind(placement.notes)
+        .bind(now_nanos()?)
+        .bind(id.0.to_string())
+        .bind(expected)
+        .bind(expected)
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(|row| row.try_get::<i64, _>("updated_at"))
+        .transpose()?;
+
+        let Some(updated_at) = updated_at else {
+            let exists = sqlx::query("SELECT 1 FROM devices WHERE id = ?")
+                .bind(id.0.to_string())
+                .fetch_optional(&mut *tx)
+                .await?
+                .is_some();
+
+            return if exists {
+                Ok(None)
+            } else {
+                Err(SqliteDeviceError::NotFound)
+            };
         };
 
-        self.register(new).await
-    }
-
-    async fn batch_register(&self, devices: Vec<Device>) -> Result<(), Self::Error> {
-        let mut tx = self.pool.begin().await?;
+        // Replace rather than upsert so the sensor_count triggers stay exact.
+        sqlx::query("DELETE FROM sensors WHERE device_id = ?")
+            .bind(id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+        for sensor in new.sensors {
+            let (metric_type, metric_value) = disect_metric(sensor.metric);
 
-        for device in devices {
             sqlx::query(
                 r#"
-            INSERT OR REPLACE INTO devices (id, kind, state, location, manufacturer, provisioned_at)
-            VALUES (?, ?, ?, ?, ?, ?)
-            "#,
+                INSERT INTO sensors (id, kind, metric_type, metric_value, device_id)
+                VALUES (?, ?, ?, ?, ?)
+                "#,
             )
-            .bind(device.id.0.to_string())
-            .bind(device.kind as i32)
-            .bind(device.state as i32)
-            .bind(device.location.0 as i64)
-            .bind(device.manufacturer)
-            .bind(device.provisioned_at.as_second())
+            .bind(sensor.id.0.to_string())
+            .bind(sensor.kind as i32)
+            .bind(metric_type)
+            .bind(metric_value)
+            .bind(id.0.to_string())
             .execute(&mut *tx)
             .await?;
+        }
+
+        tx.commit().await?;
+
+        from_nanos(updated_at).map(Some)
+    }
+
+    async fn details(&self, id: DeviceId) -> Result<Option<DeviceDetails>, Self::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT placement_site, placement_depth_cm, placement_notes, updated_at
+            FROM devices WHERE id = ?
+            "#,
+        )
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(DeviceDetails {
+            placement: Placement {
+                site: row.try_get("placement_site")?,
+                depth_cm: row.try_get("placement_depth_cm")?,
+                notes: row.try_get("placement_notes")?,
+            },
+            updated_at: from_nanos(row.try_get("updated_at")?)?,
+        }))
+    }
+
+    async fn suspend(&self, id: DeviceId) -> Result<(), Self::Error> {
+        self.set_state(id, DeviceState::Suspended).await
+    }
+
+    async fn reactivate(&self, id: DeviceId) -> Result<(), Self::Error> {
+        self.set_state(id, DeviceState::Active).await
+    }
+
+    async fn decommission(&self, id: DeviceId) -> Result<(), Self::Error> {
+        self.set_state(id, DeviceState::Decommissioned).await
+    }
+
+    async fn set_org(&self, id: DeviceId, org: Option<OrgId>) -> Result<(), Self::Error> {
+        let result = sqlx::query("UPDATE devices SET org_id = ? WHERE id = ?")
+            .bind(org.map(|org| org.0.to_string()))
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteDeviceError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn org(&self, id: DeviceId) -> Result<Option<OrgId>, Self::Error> {
+        let org_id = sqlx::query("SELECT org_id FROM devices WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|r| r.try_get::<Option<String>, _>("org_id"))
+            .transpose()?
+            .flatten();
+
+        org_id
+            .map(|id| {
+                Ulid::from_str(&id)
+                    .map(OrgId)
+                    .map_err(|_| SqliteDeviceError::InvalidUlid(id))
+            })
+            .transpose()
+    }
+
+    async fn set_dispatcher(
+        &self,
+        id: DeviceId,
+        dispatcher: Option<DispatcherId>,
+    ) -> Result<(), Self::Error> {
+        let result = sqlx::query("UPDATE devices SET dispatcher_id = ? WHERE id = ?")
+            .bind(dispatcher.map(|dispatcher| dispatcher.0.to_string()))
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteDeviceError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn dispatcher(&self, id: DeviceId) -> Result<Option<DispatcherId>, Self::Error> {
+        let dispatcher_id = sqlx::query("SELECT dispatcher_id FROM devices WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|r| r.try_get::<Option<String>, _>("dispatcher_id"))
+            .transpose()?
+            .flatten();
+
+        dispatcher_id
+            .map(|id| {
+                Ulid::from_str(&id)
+                    .map(DispatcherId)
+                    .map_err(|_| SqliteDeviceError::InvalidUlid(id))
+            })
+            .transpose()
+    }
+
+    async fn set_tags(&self, id: DeviceId, tags: Vec<String>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let known = sqlx::query("SELECT 1 FROM devices WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&mut *tx)
+            .await?;
+        if known.is_none() {
+            return Err(SqliteDeviceError::NotFound);
+        }
+
+        sqlx::query("DELETE FROM device_tags WHERE device_id = ?")
+            .bind(id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+        for tag in tags {
+            sqlx::query("INSERT OR IGNORE INTO device_tags (device_id, tag) VALUES (?, ?)")
+                .bind(id.0.to_string())
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn tags(&self, id: DeviceId) -> Result<Vec<String>, Self::Error> {
+        let tags =
+            sqlx::query_scalar("SELECT tag FROM device_tags WHERE device_id = ? ORDER BY tag")
+                .bind(id.0.to_string())
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(tags)
+    }
+
+    async fn set_disconnected(
+        &self,
+        id: DeviceId,
+        since: Option<jiff::Timestamp>,
+    ) -> Result<(), Self::Error> {
+        let result = sqlx::query("UPDATE devices SET disconnected_since = ? WHERE id = ?")
+            .bind(since.map(to_nanos).transpose()?)
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteDeviceError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn mark_offline(
+        &self,
+        id: DeviceId,
+        since: jiff::Timestamp,
+        event: Event,
+    ) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query("UPDATE devices SET disconnected_since = ? WHERE id = ?")
+            .bind(to_nanos(since)?)
+            .bind(id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(SqliteDeviceError::NotFound);
+        }
+        outbox::stage(&mut tx, &[OutboxEntry::new(event)]).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn disconnected(&self) -> Result<Vec<(DeviceId, jiff::Timestamp)>, Self::Error> {
+        let rows = sqlx::query(
+            "SELECT id, disconnected_since FROM devices WHERE disconnected_since IS NOT NULL",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let id: String = row.try_get("id")?;
+                let id = Ulid::from_str(&id)
+                    .map(DeviceId)
+                    .map_err(|_| SqliteDeviceError::InvalidUlid(id))?;
+                Ok((id, from_nanos(row.try_get("disconnected_since")?)?))
+            })
+            .collect()
+    }
+
+    async fn set_reporting_interval(
+        &self,
+        id: DeviceId,
+        interval_secs: Option<u64>,
+    ) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let known = sqlx::query("SELECT 1 FROM devices WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&mut *tx)
+            .await?;
+        if known.is_none() {
+            return Err(SqliteDeviceError::NotFound);
+        }
+
+        match interval_secs {
+            Some(secs) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO device_reporting_intervals (device_id, interval_secs) \
+                     VALUES (?, ?)",
+                )
+                .bind(id.0.to_string())
+                .bind(secs as i64)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM device_reporting_intervals WHERE device_id = ?")
+                    .bind(id.0.to_string())
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn reporting_interval(&self, id: DeviceId) -> Result<Option<u64>, Self::Error> {
+        let secs: Option<i64> = sqlx::query_scalar(
+            "SELECT interval_secs FROM device_reporting_intervals WHERE device_id = ?",
+        )
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(secs.map(|secs| secs as u64))
+    }
+
+    async fn reporting_intervals(&self) -> Result<Vec<(DeviceId, u64)>, Self::Error> {
+        let rows = sqlx::query("SELECT device_id, interval_secs FROM device_reporting_intervals")
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let id: String = row.try_get("device_id")?;
+                let id = Ulid::from_str(&id)
+                    .map(DeviceId)
+                    .map_err(|_| SqliteDeviceError::InvalidUlid(id))?;
+                let secs: i64 = row.try_get("interval_secs")?;
+                Ok((id, secs as u64))
+            })
+            .collect()
+    }
+
+    async fn set_hardware_rev(
+        &self,
+        id: DeviceId,
+        hardware_rev: Option<String>,
+    ) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let known = sqlx::query("SELECT 1 FROM devices WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&mut *tx)
+            .await?;
+        if known.is_none() {
+            return Err(SqliteDeviceError::NotFound);
+        }
+
+        match hardware_rev {
+            Some(rev) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO device_hardware_revs (device_id, hardware_rev) \
+                     VALUES (?, ?)",
+                )
+                .bind(id.0.to_string())
+                .bind(rev)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM device_hardware_revs WHERE device_id = ?")
+                    .bind(id.0.to_string())
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn hardware_rev(&self, id: DeviceId) -> Result<Option<String>, Self::Error> {
+        let rev =
+            sqlx::query_scalar("SELECT hardware_rev FROM device_hardware_revs WHERE device_id = ?")
+                .bind(id.0.to_string())
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(rev)
+    }
+
+    async fn set_metadata(
+        &self,
+        id: DeviceId,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let known = sqlx::query("SELECT 1 FROM devices WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&mut *tx)
+            .await?;
+        if known.is_none() {
+            return Err(SqliteDeviceError::NotFound);
+        }
 
-            self.add_sensors(device.id, device.sensors.into_iter())
+        match metadata {
+            Some(metadata) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO device_metadata (device_id, metadata) VALUES (?, ?)",
+                )
+                .bind(id.0.to_string())
+                .bind(metadata.to_string())
+                .execute(&mut *tx)
                 .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM device_metadata WHERE device_id = ?")
+                    .bind(id.0.to_string())
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn metadata(&self, id: DeviceId) -> Result<Option<serde_json::Value>, Self::Error> {
+        let metadata: Option<String> =
+            sqlx::query_scalar("SELECT metadata FROM device_metadata WHERE device_id = ?")
+                .bind(id.0.to_string())
+                .fetch_optional(&self.pool)
+                .await?;
+
+        metadata
+            .map(|metadata| serde_json::from_str(&metadata))
+            .transpose()
+            .map_err(SqliteDeviceError::InvalidMetadata)
+    }
+
+    async fn batch_register(&self, devices: Vec<Device>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+        let now = now_nanos()?;
+
+        let mut sensors = Vec::new();
+        let mut devices = devices.into_iter().peekable();
+        while devices.peek().is_some() {
+            let mut chunk: Vec<Device> = devices.by_ref().take(INSERT_CHUNK).collect();
+            for device in &mut chunk {
+                let id = device.id;
+                sensors.extend(
+                    std::mem::take(&mut device.sensors)
+                        .into_iter()
+                        .map(|sensor| (id, sensor)),
+                );
+            }
+
+            // Re-registering keeps the device's organization, dispatcher,
+            // placement and connectivity, as `register` does.
+            let mut query_builder = QueryBuilder::new(
+                r#"
+                INSERT OR REPLACE INTO devices
+                    (id, kind, state, location, manufacturer, provisioned_at, org_id,
+                     dispatcher_id, placement_site, placement_depth_cm, placement_notes,
+                     updated_at, disconnected_since)
+                SELECT incoming.column1, incoming.column2, incoming.column3, incoming.column4,
+                    incoming.column5, incoming.column6, old.org_id, old.dispatcher_id,
+                    old.placement_site, old.placement_depth_cm, old.placement_notes, MAX("#,
+            );
+            query_builder.push_bind(now);
+            query_builder.push(", COALESCE(old.updated_at + 1, 0)), old.disconnected_since FROM (");
+            query_builder.push_values(chunk, |mut row, device| {
+                row.push_bind(device.id.0.to_string())
+                    .push_bind(device.kind as i32)
+                    .push_bind(device.state as i32)
+                    .push_bind(device.location.0 as i64)
+                    .push_bind(device.manufacturer)
+                    .push_bind(device.provisioned_at.as_second());
+            });
+            query_builder
+                .push(") AS incoming LEFT JOIN devices AS old ON old.id = incoming.column1");
+            query_builder.build().execute(&mut *tx).await?;
+        }
+
+        let mut sensors = sensors.into_iter().peekable();
+        while sensors.peek().is_some() {
+            let chunk: Vec<(DeviceId, Sensor)> = sensors.by_ref().take(INSERT_CHUNK).collect();
+            let mut query_builder = QueryBuilder::new(
+                "INSERT OR REPLACE INTO sensors (id, kind, metric_type, metric_value, device_id) ",
+            );
+            query_builder.push_values(chunk, |mut row, (device_id, sensor)| {
+                let (metric_type, metric_value) = disect_metric(sensor.metric);
+                row.push_bind(sensor.id.0.to_string())
+                    .push_bind(sensor.kind as i32)
+                    .push_bind(metric_type)
+                    .push_bind(metric_value)
+                    .push_bind(device_id.0.to_string());
+            });
+            query_builder.build().execute(&mut *tx).await?;
         }
 
         tx.commit().await?;
@@ -280,7 +876,7 @@ impl DeviceRegistry for SqliteDeviceRegistry {
         let mut query_builder = QueryBuilder::new("SELECT COUNT(*) FROM devices ");
 
         if let Some(filter) = filter {
-            query_builder = filter_devices(query_builder, filter);
+            (query_builder, _) = filter_devices(query_builder, filter);
         }
 
         let query = query_builder.build();
@@ -297,28 +893,47 @@ impl DeviceRegistry for SqliteDeviceRegistry {
             "SELECT id, kind, state, location, manufacturer, provisioned_at, sensor_count FROM devices ",
         );
 
-        query_builder = filter_devices(query_builder, options.filter);
+        let has_where;
+        (query_builder, has_where) = filter_devices(query_builder, options.filter);
 
-        query_builder.push(match options.sort_by {
-            DeviceSortBy::State => " ORDER BY state",
-            DeviceSortBy::Manufacturer => " ORDER BY manufacturer",
-            DeviceSortBy::ProvisionAt => " ORDER BY provisioned_at ",
-            DeviceSortBy::SensorCount => " ORDER BY sensor_count",
-        });
+        let column = match options.sort_by {
+            DeviceSortBy::State => "state",
+            // NULL never compares, so missing manufacturers sort as empty.
+            DeviceSortBy::Manufacturer => "COALESCE(manufacturer, '')",
+            DeviceSortBy::ProvisionAt => "provisioned_at",
+            DeviceSortBy::SensorCount => "sensor_count",
+        };
+        let order = match options.sort_order {
+            SortOrder::Asc => " ASC",
+            SortOrder::Desc => " DESC",
+        };
 
-        query_builder.push(match options.sort_order {
-            SortOrder::Asc => " ASC ",
-            SortOrder::Desc => " DESC ",
-        });
+        if let Pagination::Cursor {
+            after: Some(after), ..
+        } = &options.pagination
+        {
+            query_builder.push(if has_where { " AND " } else { " WHERE " });
+            push_after(
+                &mut query_builder,
+                column,
+                &options.sort_order,
+                after,
+                jiff::Timestamp::as_second,
+            );
+        }
+
+        query_builder.push(format!(" ORDER BY {column}{order}"));
+
+        // Tie-break on id so pages are stable.
+        query_builder.push(", id");
+        query_builder.push(order);
 
         match options.pagination {
             Pagination::Offset { offset, limit } => {
                 query_builder.push(" LIMIT ").push_bind(limit as i64);
                 query_builder.push(" OFFSET ").push_bind(offset as i64);
             }
-            Pagination::Cursor { limit, after: _ } => {
-                // Note: Efficient cursor pagination usually requires modifying
-                // the WHERE clause. For simple usage, we apply LIMIT here.
+            Pagination::Cursor { limit, .. } => {
                 query_builder.push(" LIMIT ").push_bind(limit as i64);
             }
         }
@@ -381,6 +996,7 @@ fn map_row_to_device(r: SqliteRow) -> Result<Device, SqliteDeviceError> {
         state: match r.try_get::<i32, _>("state")? {
             0 => DeviceState::Active,
             1 => DeviceState::Suspended,
+            2 => DeviceState::Decommissioned,
             other => return Err(SqliteDeviceError::InvalidState(other)),
         },
         location: H3Cell(r.try_get::<i64, _>("location")? as u64),
@@ -435,10 +1051,11 @@ fn map_row_to_sensor(row: SqliteRow) -> Result<Sensor, SqliteDeviceError> {
     })
 }
 
+/// Append `WHERE` clauses for `filter`, returning whether any were added.
 fn filter_devices<'a>(
     mut query_builder: QueryBuilder<'a, Sqlite>,
     filter: DeviceFilter,
-) -> QueryBuilder<'a, Sqlite> {
+) -> (QueryBuilder<'a, Sqlite>, bool) {
     let mut has_where = false;
 
     let mut prefix = |qb: &mut QueryBuilder<'a, Sqlite>| {
@@ -472,6 +1089,7 @@ fn filter_devices<'a>(
             let val = match state {
                 DeviceState::Active => 0,
                 DeviceState::Suspended => 1,
+                DeviceState::Decommissioned => 2,
             };
             separated.push_bind(val);
         }
@@ -505,6 +1123,22 @@ fn filter_devices<'a>(
         separated.push_unseparated(")");
     }
 
+    if let Some(cells) = filter.within
+        && !cells.is_empty()
+    {
+        prefix(&mut query_builder);
+        query_builder.push("(");
+        let mut separated = query_builder.separated(" OR ");
+        for range in cells.into_iter().flat_map(region::descendant_ranges) {
+            separated
+                .push("location BETWEEN ")
+                .push_bind_unseparated(*range.start() as i64)
+                .push_unseparated(" AND ")
+                .push_bind_unseparated(*range.end() as i64);
+        }
+        separated.push_unseparated(")");
+    }
+
     if let Some(after) = filter.provisioned_after {
         prefix(&mut query_builder);
         query_builder
@@ -536,7 +1170,67 @@ fn filter_devices<'a>(
             .push_bind(format!("%{}%", pattern));
     }
 
-    query_builder
+    if let Some(org_id) = filter.org_id {
+        prefix(&mut query_builder);
+        query_builder
+            .push("org_id = ")
+            .push_bind(org_id.0.to_string());
+    }
+
+    if let Some(dispatcher_id) = filter.dispatcher_id {
+        prefix(&mut query_builder);
+        query_builder
+            .push("dispatcher_id = ")
+            .push_bind(dispatcher_id.0.to_string());
+    }
+
+    if let Some(tags) = filter.tags {
+        for tag in tags {
+            prefix(&mut query_builder);
+            query_builder
+                .push("id IN (SELECT device_id FROM device_tags WHERE tag = ")
+                .push_bind(tag)
+                .push(")");
+        }
+    }
+
+    if let Some(metadata) = filter.metadata {
+        for (key, value) in metadata {
+            // Keys are plain identifiers, so quoting them makes a safe path.
+            prefix(&mut query_builder);
+            query_builder
+                .push("id IN (SELECT device_id FROM device_metadata WHERE json_extract(metadata, ")
+                .push_bind(format!("$.\"{key}\""))
+                .push(") = json_extract(")
+                .push_bind(value.to_string())
+                .push(", '$'))");
+        }
+    }
+
+    if let Some(disconnected) = filter.disconnected {
+        prefix(&mut query_builder);
+        query_builder.push(if disconnected {
+            "disconnected_since IS NOT NULL"
+        } else {
+            "disconnected_since IS NULL"
+        });
+    }
+
+    (query_builder, has_where)
+}
+
+fn now_nanos() -> Result<i64, SqliteDeviceError> {
+    to_nanos(jiff::Timestamp::now())
+}
+
+fn to_nanos(timestamp: jiff::Timestamp) -> Result<i64, SqliteDeviceError> {
+    i64::try_from(timestamp.as_nanosecond())
+        .map_err(|_| SqliteDeviceError::TimestampOutOfRange(timestamp))
+}
+
+fn from_nanos(nanos: i64) -> Result<jiff::Timestamp, SqliteDeviceError> {
+    jiff::Timestamp::from_nanosecond(i128::from(nanos))
+        .map_err(|_| SqliteDeviceError::InvalidTimestamp(nanos))
 }
 
 fn disect_metric(metric: SensorMetric) -> (i32, f64) {
@@ -553,18 +1247,23 @@ fn disect_metric(metric: SensorMetric) -> (i32, f64) {
 mod tests {
     use ersha_core::Percentage;
     use ordered_float::NotNan;
+    use sqlx::sqlite::SqlitePoolOptions;
     use ulid::Ulid;
 
-    use crate::registry::DeviceRegistry;
+    use crate::placement::Placement;
     use crate::registry::filter::{
         DeviceFilter, DeviceSortBy, Pagination, QueryOptions, SortOrder,
     };
+    use crate::registry::sqlite::SqliteOutboxRegistry;
+    use crate::registry::{DeviceRegistry, OutboxRegistry};
     use ersha_core::{
-        Device, DeviceId, DeviceKind, DeviceState, H3Cell, Sensor, SensorId, SensorKind,
-        SensorMetric,
+        Device, DeviceId, DeviceKind, DeviceState, DispatcherId, H3Cell, Sensor, SensorId,
+        SensorKind, SensorMetric,
     };
 
-    use super::SqliteDeviceRegistry;
+    use crate::webhook::offline_event;
+
+    use super::{SqliteDeviceError, SqliteDeviceRegistry};
 
     fn mock_device(id: Ulid) -> Device {
         Device {
@@ -610,6 +1309,29 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_get_many_skips_unknown_ids() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+        let devices: Vec<Device> = (0..3).map(|_| mock_device(Ulid::new())).collect();
+        registry.batch_register(devices.clone()).await.unwrap();
+
+        let unknown = DeviceId(Ulid::new());
+        let mut fetched = registry
+            .get_many(&[devices[2].id, unknown, devices[0].id])
+            .await
+            .unwrap();
+        fetched.sort_by_key(|device| device.id.0);
+
+        let mut expected = vec![devices[0].id, devices[2].id];
+        expected.sort_by_key(|id| id.0);
+        assert_eq!(
+            fetched.iter().map(|device| device.id).collect::<Vec<_>>(),
+            expected
+        );
+        assert!(fetched.iter().all(|device| device.sensors.len() == 1));
+        assert!(registry.get_many(&[]).await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_filter_by_manufacturer() {
         let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
@@ -643,6 +1365,77 @@ mod tests {
         assert_eq!(results[0].manufacturer.as_deref(), Some("Apple"));
     }
 
+    #[tokio::test]
+    async fn test_filter_within_region() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let inside = mock_device(Ulid::new());
+        let mut parent = mock_device(Ulid::new());
+        parent.location = H3Cell(0x892a1072b5bffff);
+        let mut outside = mock_device(Ulid::new());
+        outside.location = H3Cell(0x8a2a1072b4a7fff);
+
+        for device in [inside.clone(), parent.clone(), outside] {
+            registry.register(device).await.unwrap();
+        }
+
+        let options = QueryOptions {
+            filter: DeviceFilter::builder()
+                .within([H3Cell(0x892a1072b5bffff)])
+                .build(),
+            sort_by: DeviceSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Offset {
+                offset: 0,
+                limit: 10,
+            },
+        };
+
+        let mut found: Vec<_> = registry
+            .list(options)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        found.sort_by_key(|id| id.0);
+
+        let mut expected = vec![inside.id, parent.id];
+        expected.sort_by_key(|id| id.0);
+        assert_eq!(found, expected);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_pagination() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let mut ids = Vec::new();
+        for second in [30, 10, 20] {
+            let mut device = mock_device(Ulid::new());
+            device.provisioned_at = jiff::Timestamp::from_second(second).unwrap();
+            ids.push((second, device.id));
+            registry.register(device).await.unwrap();
+        }
+        ids.sort_by_key(|(second, _)| *second);
+        let ids: Vec<_> = ids.into_iter().map(|(_, id)| id).collect();
+
+        let page = |after| QueryOptions {
+            filter: DeviceFilter::default(),
+            sort_by: DeviceSortBy::ProvisionAt,
+            sort_order: SortOrder::Asc,
+            pagination: Pagination::Cursor { after, limit: 2 },
+        };
+
+        let first = registry.list(page(None)).await.unwrap();
+        assert_eq!(first.iter().map(|d| d.id).collect::<Vec<_>>(), ids[..2]);
+
+        let second = registry
+            .list(page(Some(DeviceSortBy::ProvisionAt.cursor(&first[1]))))
+            .await
+            .unwrap();
+        assert_eq!(second.iter().map(|d| d.id).collect::<Vec<_>>(), ids[2..]);
+    }
+
     #[tokio::test]
     async fn test_suspend_device() {
         let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
@@ -657,6 +1450,211 @@ mod tests {
         assert_eq!(fetched.state, DeviceState::Suspended);
     }
 
+    #[tokio::test]
+    async fn test_dispatcher_assignment_survives_reregistration() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let id = DeviceId(Ulid::new());
+        let dispatcher = DispatcherId(Ulid::new());
+        registry.register(mock_device(id.0)).await.unwrap();
+        registry.register(mock_device(Ulid::new())).await.unwrap();
+        registry.set_dispatcher(id, Some(dispatcher)).await.unwrap();
+
+        registry.register(mock_device(id.0)).await.unwrap();
+        assert_eq!(registry.dispatcher(id).await.unwrap(), Some(dispatcher));
+
+        let assigned = DeviceFilter::builder().dispatcher(dispatcher).build();
+        assert_eq!(registry.count(Some(assigned.clone())).await.unwrap(), 1);
+
+        registry.set_dispatcher(id, None).await.unwrap();
+        assert_eq!(registry.dispatcher(id).await.unwrap(), None);
+        assert_eq!(registry.count(Some(assigned)).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_batch_register_spans_chunks_and_keeps_assignments() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let id = DeviceId(Ulid::new());
+        let dispatcher = DispatcherId(Ulid::new());
+        registry.register(mock_device(id.0)).await.unwrap();
+        registry.set_dispatcher(id, Some(dispatcher)).await.unwrap();
+
+        let mut devices: Vec<_> = (0..1200).map(|_| mock_device(Ulid::new())).collect();
+        devices.push(Device {
+            state: DeviceState::Suspended,
+            ..mock_device(id.0)
+        });
+        registry.batch_register(devices).await.unwrap();
+
+        assert_eq!(registry.count(None).await.unwrap(), 1201);
+        assert_eq!(registry.dispatcher(id).await.unwrap(), Some(dispatcher));
+
+        let device = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(device.state, DeviceState::Suspended);
+        assert!(!device.sensors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tags_survive_reregistration_and_filter() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let id = DeviceId(Ulid::new());
+        let other = DeviceId(Ulid::new());
+        registry.register(mock_device(id.0)).await.unwrap();
+        registry.register(mock_device(other.0)).await.unwrap();
+        registry
+            .set_tags(id, vec!["north-ridge".to_owned(), "pilot-a".to_owned()])
+            .await
+            .unwrap();
+        registry
+            .set_tags(other, vec!["pilot-a".to_owned()])
+            .await
+            .unwrap();
+
+        registry.register(mock_device(id.0)).await.unwrap();
+        assert_eq!(registry.tags(id).await.unwrap(), ["north-ridge", "pilot-a"]);
+
+        let pilot = DeviceFilter::builder().tags(["pilot-a".to_owned()]).build();
+        assert_eq!(registry.count(Some(pilot)).await.unwrap(), 2);
+        let both = DeviceFilter::builder()
+            .tags(["pilot-a".to_owned(), "north-ridge".to_owned()])
+            .build();
+        assert_eq!(registry.count(Some(both.clone())).await.unwrap(), 1);
+
+        registry.set_tags(id, Vec::new()).await.unwrap();
+        assert_eq!(registry.count(Some(both)).await.unwrap(), 0);
+        assert!(matches!(
+            registry
+                .set_tags(DeviceId(Ulid::new()), vec!["pilot-a".to_owned()])
+                .await,
+            Err(SqliteDeviceError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_metadata_survives_reregistration_and_filters() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let id = DeviceId(Ulid::new());
+        let other = DeviceId(Ulid::new());
+        registry.register(mock_device(id.0)).await.unwrap();
+        registry.register(mock_device(other.0)).await.unwrap();
+        let metadata = serde_json::json!({ "crop": "maize", "rows": 12, "irrigated": true });
+        registry
+            .set_metadata(id, Some(metadata.clone()))
+            .await
+            .unwrap();
+        registry
+            .set_metadata(other, Some(serde_json::json!({ "crop": "teff" })))
+            .await
+            .unwrap();
+
+        registry.register(mock_device(id.0)).await.unwrap();
+        assert_eq!(registry.metadata(id).await.unwrap(), Some(metadata));
+
+        let count = |filter: DeviceFilter| registry.count(Some(filter));
+        let maize = DeviceFilter::builder()
+            .metadata("crop", serde_json::json!("maize"))
+            .build();
+        assert_eq!(count(maize.clone()).await.unwrap(), 1);
+        let typed = DeviceFilter::builder()
+            .metadata("rows", serde_json::json!(12))
+            .metadata("irrigated", serde_json::json!(true))
+            .build();
+        assert_eq!(count(typed).await.unwrap(), 1);
+        let quoted = DeviceFilter::builder()
+            .metadata("rows", serde_json::json!("12"))
+            .build();
+        assert_eq!(count(quoted).await.unwrap(), 0);
+
+        registry.set_metadata(id, None).await.unwrap();
+        assert_eq!(registry.metadata(id).await.unwrap(), None);
+        assert_eq!(count(maize).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_disconnection_survives_reregistration() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let id = DeviceId(Ulid::new());
+        registry.register(mock_device(id.0)).await.unwrap();
+        registry.register(mock_device(Ulid::new())).await.unwrap();
+        let since = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+        registry.set_disconnected(id, Some(since)).await.unwrap();
+
+        registry.register(mock_device(id.0)).await.unwrap();
+        assert_eq!(registry.disconnected().await.unwrap(), [(id, since)]);
+
+        registry.set_disconnected(id, None).await.unwrap();
+        assert!(registry.disconnected().await.unwrap().is_empty());
+        assert!(
+            registry
+                .set_disconnected(DeviceId(Ulid::new()), Some(since))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_going_offline_stages_its_event() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let registry = SqliteDeviceRegistry::with_pool(pool.clone()).await.unwrap();
+        let outbox = SqliteOutboxRegistry::with_pool(pool).await.unwrap();
+
+        let id = DeviceId(Ulid::new());
+        registry.register(mock_device(id.0)).await.unwrap();
+        let since = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+        let event = offline_event(id, DispatcherId(Ulid::new()), since, None);
+        registry
+            .mark_offline(id, since, event.clone())
+            .await
+            .unwrap();
+        assert_eq!(registry.disconnected().await.unwrap(), [(id, since)]);
+
+        // Nothing is staged for a device that isn't there.
+        let unknown = DeviceId(Ulid::new());
+        let stray = offline_event(unknown, DispatcherId(Ulid::new()), since, None);
+        assert!(registry.mark_offline(unknown, since, stray).await.is_err());
+
+        let due = outbox.due(jiff::Timestamp::now(), 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].event, event);
+    }
+
+    #[tokio::test]
+    async fn test_device_lifecycle() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let id = DeviceId(Ulid::new());
+        registry.register(mock_device(id.0)).await.unwrap();
+
+        registry.decommission(id).await.unwrap();
+        let fetched = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(fetched.state, DeviceState::Decommissioned);
+        assert_eq!(fetched.sensors.len(), 1);
+
+        let decommissioned = DeviceFilter {
+            states: Some(vec![DeviceState::Decommissioned]),
+            sensor_count: Some(1..=1),
+            ..Default::default()
+        };
+        assert_eq!(registry.count(Some(decommissioned)).await.unwrap(), 1);
+
+        registry.reactivate(id).await.unwrap();
+        let fetched = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(fetched.state, DeviceState::Active);
+
+        assert!(matches!(
+            registry.suspend(DeviceId(Ulid::new())).await,
+            Err(SqliteDeviceError::NotFound)
+        ));
+    }
+
     #[tokio::test]
     async fn test_add_sensor_individually() {
         let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
@@ -684,4 +1682,77 @@ mod tests {
         assert_eq!(fetched.sensors.len(), 1);
         assert!(matches!(fetched.sensors[0].kind, SensorKind::Humidity));
     }
+
+    #[tokio::test]
+    async fn test_update_replaces_sensors_unless_stale() {
+        let registry = SqliteDeviceRegistry::new_in_memory().await.unwrap();
+
+        let id = DeviceId(Ulid::new());
+        let device = mock_device(id.0);
+        registry.register(device.clone()).await.unwrap();
+        let dispatcher = DispatcherId(Ulid::new());
+        registry.set_dispatcher(id, Some(dispatcher)).await.unwrap();
+        let before = registry.details(id).await.unwrap().unwrap();
+        assert_eq!(before.placement, Placement::default());
+
+        let replacement = Sensor {
+            id: SensorId(Ulid::new()),
+            kind: SensorKind::Humidity,
+            metric: SensorMetric::Humidity {
+                value: Percentage(45),
+            },
+        };
+        let updated = Device {
+            location: H3Cell(0x8a2a1072b4a7fff),
+            manufacturer: None,
+            sensors: vec![replacement.clone()].into_boxed_slice(),
+            ..device.clone()
+        };
+        let placement = Placement {
+            site: Some("North plot".to_owned()),
+            depth_cm: Some(30),
+            notes: None,
+        };
+
+        let updated_at = registry
+            .update(id, updated, placement.clone(), Some(before.updated_at))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(updated_at > before.updated_at);
+
+        let fetched = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(fetched.location, H3Cell(0x8a2a1072b4a7fff));
+        assert_eq!(fetched.manufacturer, None);
+        assert_eq!(fetched.sensors.len(), 1);
+        assert_eq!(fetched.sensors[0].id, replacement.id);
+        let one_sensor = DeviceFilter {
+            sensor_count: Some(1..=1),
+            ..Default::default()
+        };
+        assert_eq!(registry.count(Some(one_sensor)).await.unwrap(), 1);
+        assert_eq!(registry.dispatcher(id).await.unwrap(), Some(dispatcher));
+
+        let after = registry.details(id).await.unwrap().unwrap();
+        assert_eq!(after.placement, placement);
+        assert_eq!(after.updated_at, updated_at);
+
+        let stale = registry
+            .update(id, device, Placement::default(), Some(before.updated_at))
+            .await
+            .unwrap();
+        assert_eq!(stale, None);
+
+        registry.register(mock_device(id.0)).await.unwrap();
+        let reregistered = registry.details(id).await.unwrap().unwrap();
+        assert_eq!(reregistered.placement, placement);
+        assert!(reregistered.updated_at > updated_at);
+
+        assert!(matches!(
+            registry
+                .update(DeviceId(Ulid::new()), mock_device(id.0), placement, None)
+                .await,
+            Err(SqliteDeviceError::NotFound)
+        ));
+    }
 }
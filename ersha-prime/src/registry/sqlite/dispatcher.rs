@@ -1,18 +1,44 @@
 use std::str::FromStr;
 
 use ersha_core::{Dispatcher, DispatcherId, DispatcherState, H3Cell};
-use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions};
+use sqlx::{
+    QueryBuilder, Row, Sqlite, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions,
+    sqlite::SqliteRow,
+};
 use ulid::Ulid;
 
 use async_trait::async_trait;
 
+use crate::config::SqlitePoolConfig;
+use crate::org::OrgId;
 use crate::registry::{
-    DispatcherRegistry,
+    DispatcherRegistry, RegistryError,
     filter::{DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortOrder},
 };
 
+use super::push_after;
+
 static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
+/// Dispatchers per `INSERT`, at five bound parameters each.
+const INSERT_CHUNK: usize = 500;
+
+/// Ids bound per `IN (...)` when looking dispatchers up in bulk.
+const LOOKUP_CHUNK: usize = 500;
+
+/// Assignment marking a dispatcher as changed now, keeping `updated_at`
+/// strictly increasing. Binds the current time in nanoseconds.
+const TOUCH: &str = "updated_at = MAX(?, COALESCE(updated_at + 1, 0))";
+
+/// Upsert clause replacing a dispatcher's fields and marking it changed.
+const UPSERT: &str = r#"
+    ON CONFLICT(id) DO UPDATE SET
+        state = excluded.state,
+        location = excluded.location,
+        provisioned_at = excluded.provisioned_at,
+        updated_at = MAX(excluded.updated_at, COALESCE(updated_at + 1, 0))
+"#;
+
 #[derive(Debug, thiserror::Error)]
 pub enum SqliteDispatcherError {
     #[error("sqlx error: {0}")]
@@ -29,6 +55,16 @@ pub enum SqliteDispatcherError {
     NotFound,
 }
 
+impl From<SqliteDispatcherError> for RegistryError {
+    fn from(error: SqliteDispatcherError) -> Self {
+        match error {
+            SqliteDispatcherError::NotFound => RegistryError::NotFound,
+            SqliteDispatcherError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SqliteDispatcherRegistry {
     pool: SqlitePool,
@@ -36,9 +72,12 @@ pub struct SqliteDispatcherRegistry {
 
 impl SqliteDispatcherRegistry {
     pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteDispatcherError> {
-        let connection_string = format!("sqlite:{}", path.as_ref());
-        let pool = SqlitePoolOptions::new().connect(&connection_string).await?;
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
 
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteDispatcherError> {
         MIGRATOR.run(&pool).await?;
 
         Ok(Self { pool })
@@ -51,6 +90,27 @@ impl SqliteDispatcherRegistry {
 
         Ok(Self { pool })
     }
+
+    async fn set_state(
+        &self,
+        id: DispatcherId,
+        state: DispatcherState,
+    ) -> Result<(), SqliteDispatcherError> {
+        let result = sqlx::query(&format!(
+            "UPDATE dispatchers SET state = ?, {TOUCH} WHERE id = ?"
+        ))
+        .bind(state as i32)
+        .bind(now_nanos())
+        .bind(id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteDispatcherError::NotFound);
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -58,22 +118,51 @@ impl DispatcherRegistry for SqliteDispatcherRegistry {
     type Error = SqliteDispatcherError;
 
     async fn register(&self, dispatcher: Dispatcher) -> Result<(), Self::Error> {
-        sqlx::query(
+        sqlx::query(&format!(
             r#"
-            INSERT OR REPLACE INTO dispatchers (id, state, location, provisioned_at)
-            VALUES (?, ?, ?, ?)
-            "#,
-        )
+            INSERT INTO dispatchers (id, state, location, provisioned_at, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            {UPSERT}
+            "#
+        ))
         .bind(dispatcher.id.0.to_string())
         .bind(dispatcher.state as i32)
         .bind(dispatcher.location.0 as i64)
         .bind(dispatcher.provisioned_at.as_second())
+        .bind(now_nanos())
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
+    async fn register_new(
+        &self,
+        dispatcher: Dispatcher,
+    ) -> Result<Option<Dispatcher>, Self::Error> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO dispatchers (id, state, location, provisioned_at, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO NOTHING
+            "#,
+        )
+        .bind(dispatcher.id.0.to_string())
+        .bind(dispatcher.state as i32)
+        .bind(dispatcher.location.0 as i64)
+        .bind(dispatcher.provisioned_at.as_second())
+        .bind(now_nanos())
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        if inserted == 0 {
+            return self.get(dispatcher.id).await;
+        }
+
+        Ok(None)
+    }
+
     async fn get(&self, id: DispatcherId) -> Result<Option<Dispatcher>, Self::Error> {
         let row = sqlx::query(
             r#"
@@ -84,65 +173,211 @@ impl DispatcherRegistry for SqliteDispatcherRegistry {
         .fetch_optional(&self.pool)
         .await?;
 
-        row.map(|r| -> Result<Dispatcher, SqliteDispatcherError> {
-            let id = r.try_get::<String, _>("id")?;
-            let ulid = Ulid::from_str(&id)
-                .map_err(|_| SqliteDispatcherError::InvalidUlid(id.to_string()))?;
+        row.map(map_row_to_dispatcher).transpose()
+    }
 
-            let provisioned_at = r.try_get::<i64, _>("provisioned_at")?;
-            let provisioned_at = jiff::Timestamp::from_second(provisioned_at)
-                .map_err(|_| SqliteDispatcherError::InvalidTimestamp(provisioned_at))?;
+    async fn get_many(&self, ids: &[DispatcherId]) -> Result<Vec<Dispatcher>, Self::Error> {
+        let mut dispatchers = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(LOOKUP_CHUNK) {
+            let mut query_builder = QueryBuilder::<Sqlite>::new(
+                "SELECT id, state, location, provisioned_at FROM dispatchers WHERE id IN (",
+            );
+            let mut separated = query_builder.separated(", ");
+            for id in chunk {
+                separated.push_bind(id.0.to_string());
+            }
+            separated.push_unseparated(")");
+
+            let rows = query_builder.build().fetch_all(&self.pool).await?;
+            for row in rows {
+                dispatchers.push(map_row_to_dispatcher(row)?);
+            }
+        }
 
-            let state = match r.try_get::<i32, _>("state")? {
-                0 => DispatcherState::Active,
-                1 => DispatcherState::Suspended,
-                other => return Err(SqliteDispatcherError::InvalidState(other)),
-            };
+        Ok(dispatchers)
+    }
 
-            Ok(Dispatcher {
-                id: DispatcherId(ulid),
-                location: H3Cell(r.try_get::<i64, _>("location")? as u64),
-                state,
-                provisioned_at,
-            })
-        })
-        .transpose()
+    async fn update(
+        &self,
+        id: DispatcherId,
+        new: Dispatcher,
+        expected: Option<jiff::Timestamp>,
+    ) -> Result<Option<jiff::Timestamp>, Self::Error> {
+        let expected = expected.map(|at| at.as_nanosecond() as i64);
+        let updated_at = sqlx::query(&format!(
+            r#"
+            UPDATE dispatchers SET state = ?, location = ?, provisioned_at = ?, {TOUCH}
+            WHERE id = ? AND (? IS NULL OR updated_at = ?)
+            RETURNING updated_at
+            "#
+        ))
+        .bind(new.state as i32)
+        .bind(new.location.0 as i64)
+        .bind(new.provisioned_at.as_second())
+        .bind(now_nanos())
+        .bind(id.0.to_string())
+        .bind(expected)
+        .bind(expected)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| row.try_get::<i64, _>("updated_at"))
+        .transpose()?;
+
+        match updated_at {
+            Some(updated_at) => from_nanos(updated_at).map(Some),
+            // Tell a stale revision apart from a dispatcher that isn't there.
+            None if self.updated_at(id).await?.is_some() => Ok(None),
+            None => Err(SqliteDispatcherError::NotFound),
+        }
     }
 
-    async fn update(&self, id: DispatcherId, new: Dispatcher) -> Result<(), Self::Error> {
-        let old = self.get(id).await?.ok_or(SqliteDispatcherError::NotFound)?;
-        let new = Dispatcher { id: old.id, ..new };
+    async fn updated_at(&self, id: DispatcherId) -> Result<Option<jiff::Timestamp>, Self::Error> {
+        let updated_at =
+            sqlx::query_scalar::<_, Option<i64>>("SELECT updated_at FROM dispatchers WHERE id = ?")
+                .bind(id.0.to_string())
+                .fetch_optional(&self.pool)
+                .await?
+                .flatten();
 
-        self.register(new).await
+        updated_at.map(from_nanos).transpose()
     }
 
     async fn suspend(&self, id: DispatcherId) -> Result<(), Self::Error> {
-        let dispatcher = self.get(id).await?.ok_or(SqliteDispatcherError::NotFound)?;
+        self.set_state(id, DispatcherState::Suspended).await
+    }
 
-        let new = Dispatcher {
-            state: DispatcherState::Suspended,
-            ..dispatcher
-        };
+    async fn reactivate(&self, id: DispatcherId) -> Result<(), Self::Error> {
+        self.set_state(id, DispatcherState::Active).await
+    }
+
+    async fn set_secret(
+        &self,
+        id: DispatcherId,
+        secret: Option<String>,
+    ) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE dispatchers SET secret = ? WHERE id = ?
+            "#,
+        )
+        .bind(secret)
+        .bind(id.0.to_string())
+        .execute(&self.pool)
+        .await?;
 
-        self.register(new).await
+        if result.rows_affected() == 0 {
+            return Err(SqliteDispatcherError::NotFound);
+        }
+
+        Ok(())
     }
 
-    async fn batch_register(&self, dispatchers: Vec<Dispatcher>) -> Result<(), Self::Error> {
+    async fn get_secret(&self, id: DispatcherId) -> Result<Option<String>, Self::Error> {
+        let secret = sqlx::query(
+            r#"
+            SELECT secret FROM dispatchers WHERE id = ?
+            "#,
+        )
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|r| r.try_get::<Option<String>, _>("secret"))
+        .transpose()?
+        .flatten();
+
+        Ok(secret)
+    }
+
+    async fn set_tags(&self, id: DispatcherId, tags: Vec<String>) -> Result<(), Self::Error> {
         let mut tx = self.pool.begin().await?;
 
-        for dispatcher in dispatchers {
-            sqlx::query(
-                r#"
-                INSERT OR REPLACE INTO dispatchers (id, state, location, provisioned_at)
-                VALUES (?, ?, ?, ?)
-                "#,
-            )
-            .bind(dispatcher.id.0.to_string())
-            .bind(dispatcher.state as i32)
-            .bind(dispatcher.location.0 as i64)
-            .bind(dispatcher.provisioned_at.as_second())
+        let known = sqlx::query("SELECT 1 FROM dispatchers WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&mut *tx)
+            .await?;
+        if known.is_none() {
+            return Err(SqliteDispatcherError::NotFound);
+        }
+
+        sqlx::query("DELETE FROM dispatcher_tags WHERE dispatcher_id = ?")
+            .bind(id.0.to_string())
             .execute(&mut *tx)
             .await?;
+        for tag in tags {
+            sqlx::query("INSERT OR IGNORE INTO dispatcher_tags (dispatcher_id, tag) VALUES (?, ?)")
+                .bind(id.0.to_string())
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn tags(&self, id: DispatcherId) -> Result<Vec<String>, Self::Error> {
+        let tags = sqlx::query_scalar(
+            "SELECT tag FROM dispatcher_tags WHERE dispatcher_id = ? ORDER BY tag",
+        )
+        .bind(id.0.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    async fn set_org(&self, id: DispatcherId, org: Option<OrgId>) -> Result<(), Self::Error> {
+        let result = sqlx::query("UPDATE dispatchers SET org_id = ? WHERE id = ?")
+            .bind(org.map(|org| org.0.to_string()))
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteDispatcherError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn org(&self, id: DispatcherId) -> Result<Option<OrgId>, Self::Error> {
+        let org_id = sqlx::query("SELECT org_id FROM dispatchers WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|r| r.try_get::<Option<String>, _>("org_id"))
+            .transpose()?
+            .flatten();
+
+        org_id
+            .map(|id| {
+                Ulid::from_str(&id)
+                    .map(OrgId)
+                    .map_err(|_| SqliteDispatcherError::InvalidUlid(id))
+            })
+            .transpose()
+    }
+
+    async fn batch_register(&self, dispatchers: Vec<Dispatcher>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut dispatchers = dispatchers.into_iter().peekable();
+        while dispatchers.peek().is_some() {
+            let chunk: Vec<Dispatcher> = dispatchers.by_ref().take(INSERT_CHUNK).collect();
+            let now = now_nanos();
+            let mut query_builder = QueryBuilder::new(
+                "INSERT INTO dispatchers (id, state, location, provisioned_at, updated_at) ",
+            );
+            query_builder.push_values(chunk, |mut row, dispatcher| {
+                row.push_bind(dispatcher.id.0.to_string())
+                    .push_bind(dispatcher.state as i32)
+                    .push_bind(dispatcher.location.0 as i64)
+                    .push_bind(dispatcher.provisioned_at.as_second())
+                    .push_bind(now);
+            });
+            query_builder.push(UPSERT);
+            query_builder.build().execute(&mut *tx).await?;
         }
 
         tx.commit().await?;
@@ -153,7 +388,7 @@ impl DispatcherRegistry for SqliteDispatcherRegistry {
         let mut query_builder = QueryBuilder::new("SELECT COUNT(*) FROM dispatchers ");
 
         if let Some(filter) = filter {
-            query_builder = filter_dispatchers(query_builder, filter);
+            (query_builder, _) = filter_dispatchers(query_builder, filter);
         }
 
         let query = query_builder.build();
@@ -169,93 +404,152 @@ impl DispatcherRegistry for SqliteDispatcherRegistry {
         let mut query_builder =
             QueryBuilder::new("SELECT id, state, location, provisioned_at FROM dispatchers");
 
-        query_builder = filter_dispatchers(query_builder, options.filter);
+        let has_where;
+        (query_builder, has_where) = filter_dispatchers(query_builder, options.filter);
 
-        match options.sort_by {
-            DispatcherSortBy::ProvisionAt => query_builder.push(" ORDER BY provisioned_at"),
+        let order = match options.sort_order {
+            SortOrder::Asc => " ASC",
+            SortOrder::Desc => " DESC",
         };
 
-        match options.sort_order {
-            SortOrder::Asc => query_builder.push(" ASC "),
-            SortOrder::Desc => query_builder.push(" DESC "),
-        };
+        if let Pagination::Cursor {
+            after: Some(after), ..
+        } = &options.pagination
+        {
+            query_builder.push(if has_where { " AND " } else { " WHERE " });
 
-        if let Pagination::Offset { offset, limit } = options.pagination {
-            query_builder.push(" LIMIT ");
-            query_builder.push_bind(limit as i64);
+            let column = match options.sort_by {
+                DispatcherSortBy::ProvisionAt => "provisioned_at",
+            };
+            push_after(
+                &mut query_builder,
+                column,
+                &options.sort_order,
+                after,
+                jiff::Timestamp::as_second,
+            );
+        }
 
-            query_builder.push(" OFFSET ");
-            query_builder.push_bind(offset as i64);
+        match options.sort_by {
+            DispatcherSortBy::ProvisionAt => query_builder.push(" ORDER BY provisioned_at"),
+        };
+        query_builder.push(order);
+
+        // Tie-break on id so pages are stable.
+        query_builder.push(", id");
+        query_builder.push(order);
+
+        match options.pagination {
+            Pagination::Offset { offset, limit } => {
+                query_builder.push(" LIMIT ");
+                query_builder.push_bind(limit as i64);
+
+                query_builder.push(" OFFSET ");
+                query_builder.push_bind(offset as i64);
+            }
+            Pagination::Cursor { limit, .. } => {
+                query_builder.push(" LIMIT ");
+                query_builder.push_bind(limit as i64);
+            }
         }
 
         let query = query_builder.build();
         let rows = query.fetch_all(&self.pool).await?;
 
-        rows.into_iter()
-            .map(|r| {
-                let id = r.try_get::<String, _>("id")?;
-                let ulid =
-                    Ulid::from_str(&id).map_err(|_| SqliteDispatcherError::InvalidUlid(id))?;
-
-                let provisioned_at = r.try_get::<i64, _>("provisioned_at")?;
-                let provisioned_at = jiff::Timestamp::from_second(provisioned_at)
-                    .map_err(|_| SqliteDispatcherError::InvalidTimestamp(provisioned_at))?;
-
-                let state = match r.try_get::<i32, _>("state")? {
-                    0 => DispatcherState::Active,
-                    1 => DispatcherState::Suspended,
-                    other => return Err(SqliteDispatcherError::InvalidState(other)),
-                };
-
-                Ok(Dispatcher {
-                    id: DispatcherId(ulid),
-                    provisioned_at,
-                    state,
-                    location: H3Cell(r.try_get::<i64, _>("location")? as u64),
-                })
-            })
-            .collect()
+        rows.into_iter().map(map_row_to_dispatcher).collect()
     }
 }
 
+fn map_row_to_dispatcher(r: SqliteRow) -> Result<Dispatcher, SqliteDispatcherError> {
+    let id = r.try_get::<String, _>("id")?;
+    let ulid = Ulid::from_str(&id).map_err(|_| SqliteDispatcherError::InvalidUlid(id))?;
+
+    let provisioned_at = r.try_get::<i64, _>("provisioned_at")?;
+    let provisioned_at = jiff::Timestamp::from_second(provisioned_at)
+        .map_err(|_| SqliteDispatcherError::InvalidTimestamp(provisioned_at))?;
+
+    let state = match r.try_get::<i32, _>("state")? {
+        0 => DispatcherState::Active,
+        1 => DispatcherState::Suspended,
+        2 => DispatcherState::Pending,
+        other => return Err(SqliteDispatcherError::InvalidState(other)),
+    };
+
+    Ok(Dispatcher {
+        id: DispatcherId(ulid),
+        provisioned_at,
+        state,
+        location: H3Cell(r.try_get::<i64, _>("location")? as u64),
+    })
+}
+
+fn now_nanos() -> i64 {
+    jiff::Timestamp::now().as_nanosecond() as i64
+}
+
+fn from_nanos(nanos: i64) -> Result<jiff::Timestamp, SqliteDispatcherError> {
+    jiff::Timestamp::from_nanosecond(i128::from(nanos))
+        .map_err(|_| SqliteDispatcherError::InvalidTimestamp(nanos))
+}
+
+/// Append `WHERE` clauses for `filter`, returning whether any were added.
 fn filter_dispatchers(
     mut query_builder: QueryBuilder<Sqlite>,
     filter: DispatcherFilter,
-) -> QueryBuilder<Sqlite> {
+) -> (QueryBuilder<Sqlite>, bool) {
     let mut has_where = false;
 
+    let mut prefix = |qb: &mut QueryBuilder<Sqlite>| {
+        if has_where {
+            qb.push(" AND ");
+        } else {
+            qb.push(" WHERE ");
+            has_where = true;
+        }
+    };
+
     if let Some(states) = filter.states
         && !states.is_empty()
     {
-        query_builder.push(" WHERE state IN (");
+        prefix(&mut query_builder);
+        query_builder.push("state IN (");
         let mut separated = query_builder.separated(", ");
         for state in states {
             separated.push_bind(state as i32);
         }
         separated.push_unseparated(")");
-        has_where = true;
     }
 
     if let Some(locations) = filter.locations
         && !locations.is_empty()
     {
-        if has_where {
-            query_builder.push(" AND ");
-        } else {
-            query_builder.push(" WHERE ");
-        }
-
+        prefix(&mut query_builder);
         query_builder.push("location IN (");
-
         let mut separated = query_builder.separated(", ");
         for location in locations {
             separated.push_bind(location.0 as i64);
         }
-
         separated.push_unseparated(")");
     }
 
-    query_builder
+    if let Some(org_id) = filter.org_id {
+        prefix(&mut query_builder);
+        query_builder
+            .push("org_id = ")
+            .push_bind(org_id.0.to_string());
+    }
+
+    if let Some(tags) = filter.tags {
+        for tag in tags {
+            prefix(&mut query_builder);
+            query_builder
+                .push("id IN (SELECT dispatcher_id FROM dispatcher_tags WHERE tag = ")
+                .push_bind(tag)
+                .push(")");
+        }
+    }
+
+    (query_builder, has_where)
 }
 
 #[cfg(test)]
@@ -263,13 +557,14 @@ mod tests {
     use jiff::Timestamp;
     use ulid::Ulid;
 
+    use crate::org::OrgId;
     use crate::registry::DispatcherRegistry;
     use crate::registry::filter::{
-        DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortOrder,
+        Cursor, DispatcherFilter, DispatcherSortBy, Pagination, QueryOptions, SortKey, SortOrder,
     };
     use ersha_core::{Dispatcher, DispatcherId, DispatcherState, H3Cell};
 
-    use super::SqliteDispatcherRegistry;
+    use super::{SqliteDispatcherError, SqliteDispatcherRegistry};
 
     fn dispatcher(
         id: DispatcherId,
@@ -355,6 +650,8 @@ mod tests {
             filter: DispatcherFilter {
                 states: None,
                 locations: None,
+                org_id: None,
+                tags: None,
             },
             sort_by: DispatcherSortBy::ProvisionAt,
             sort_order: SortOrder::Asc,
@@ -474,4 +771,386 @@ mod tests {
 
         assert_eq!(registry.count(Some(active_filter)).await.unwrap(), 0);
     }
+
+    #[tokio::test]
+    async fn test_sqlite_suspend_logic() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+        let id = DispatcherId(Ulid::new());
+
+        registry
+            .register(dispatcher(id, DispatcherState::Active, Timestamp::now()))
+            .await
+            .unwrap();
+        registry.suspend(id).await.unwrap();
+
+        let updated = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(updated.state, DispatcherState::Suspended);
+
+        registry.reactivate(id).await.unwrap();
+
+        let updated = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(updated.state, DispatcherState::Active);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_count_with_filter() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+        let id1 = DispatcherId(Ulid::new());
+        let id2 = DispatcherId(Ulid::new());
+
+        registry
+            .batch_register(vec![
+                dispatcher(id1, DispatcherState::Active, Timestamp::now()),
+                Dispatcher {
+                    location: H3Cell(7),
+                    ..dispatcher(id2, DispatcherState::Suspended, Timestamp::now())
+                },
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(registry.count(None).await.unwrap(), 2);
+
+        let filter = DispatcherFilter {
+            states: Some(vec![DispatcherState::Active]),
+            ..Default::default()
+        };
+        assert_eq!(registry.count(Some(filter)).await.unwrap(), 1);
+
+        let filter = DispatcherFilter {
+            states: Some(vec![DispatcherState::Active, DispatcherState::Suspended]),
+            locations: Some(vec![H3Cell(7)]),
+            org_id: None,
+            tags: None,
+        };
+        assert_eq!(registry.count(Some(filter)).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_list_sorting_and_pagination() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+
+        let id1 = DispatcherId(Ulid::new());
+        let id2 = DispatcherId(Ulid::new());
+        let id3 = DispatcherId(Ulid::new());
+
+        registry
+            .batch_register(vec![
+                dispatcher(
+                    id1,
+                    DispatcherState::Active,
+                    Timestamp::from_second(100).unwrap(),
+                ),
+                dispatcher(
+                    id2,
+                    DispatcherState::Active,
+                    Timestamp::from_second(300).unwrap(),
+                ),
+                dispatcher(
+                    id3,
+                    DispatcherState::Active,
+                    Timestamp::from_second(200).unwrap(),
+                ),
+            ])
+            .await
+            .unwrap();
+
+        let options = QueryOptions {
+            sort_order: SortOrder::Desc,
+            pagination: Pagination::Offset {
+                offset: 0,
+                limit: 2,
+            },
+            ..default_options()
+        };
+
+        let results = registry.list(options).await.unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].id, id2);
+        assert_eq!(results[1].id, id3);
+    }
+
+    #[tokio::test]
+    async fn test_register_new_keeps_the_existing_dispatcher() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+        let id = DispatcherId(Ulid::new());
+        let first = dispatcher(
+            id,
+            DispatcherState::Active,
+            Timestamp::from_second(1).unwrap(),
+        );
+
+        assert!(registry.register_new(first).await.unwrap().is_none());
+
+        let second = dispatcher(id, DispatcherState::Suspended, Timestamp::now());
+        let existing = registry.register_new(second).await.unwrap().unwrap();
+        assert_eq!(existing.state, DispatcherState::Active);
+
+        let stored = registry.get(id).await.unwrap().unwrap();
+        assert_eq!(stored.state, DispatcherState::Active);
+    }
+
+    #[tokio::test]
+    async fn test_get_many_skips_unknown_ids() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+        let known = DispatcherId(Ulid::new());
+        registry
+            .register(dispatcher(
+                known,
+                DispatcherState::Suspended,
+                Timestamp::now(),
+            ))
+            .await
+            .unwrap();
+
+        let fetched = registry
+            .get_many(&[DispatcherId(Ulid::new()), known])
+            .await
+            .unwrap();
+
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].id, known);
+        assert_eq!(fetched[0].state, DispatcherState::Suspended);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_cursor_pagination() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+
+        let ids: Vec<_> = (0..5).map(|_| DispatcherId(Ulid::new())).collect();
+        registry
+            .batch_register(
+                ids.iter()
+                    .enumerate()
+                    .map(|(i, id)| {
+                        dispatcher(
+                            *id,
+                            DispatcherState::Active,
+                            Timestamp::from_second(i as i64 * 10).unwrap(),
+                        )
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let at = |i: usize, id: DispatcherId| {
+            Cursor::new(
+                SortKey::Time(Timestamp::from_second(i as i64 * 10).unwrap()),
+                id.0,
+            )
+        };
+        let cursor = |after, sort_order| QueryOptions {
+            sort_order,
+            pagination: Pagination::Cursor { after, limit: 2 },
+            ..default_options()
+        };
+
+        let first = registry.list(cursor(None, SortOrder::Asc)).await.unwrap();
+        assert_eq!(first.iter().map(|d| d.id).collect::<Vec<_>>(), ids[..2]);
+
+        let second = registry
+            .list(cursor(Some(at(1, first[1].id)), SortOrder::Asc))
+            .await
+            .unwrap();
+        assert_eq!(second.iter().map(|d| d.id).collect::<Vec<_>>(), ids[2..4]);
+
+        let desc = registry
+            .list(cursor(Some(at(2, ids[2])), SortOrder::Desc))
+            .await
+            .unwrap();
+        assert_eq!(
+            desc.iter().map(|d| d.id).collect::<Vec<_>>(),
+            vec![ids[1], ids[0]]
+        );
+
+        let past_the_end = registry
+            .list(cursor(
+                Some(at(5, DispatcherId(Ulid::new()))),
+                SortOrder::Asc,
+            ))
+            .await
+            .unwrap();
+        assert!(past_the_end.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_cursor_pagination_with_filter() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+
+        let active1 = DispatcherId(Ulid::new());
+        let suspended = DispatcherId(Ulid::new());
+        let active2 = DispatcherId(Ulid::new());
+
+        registry
+            .batch_register(vec![
+                dispatcher(
+                    active1,
+                    DispatcherState::Active,
+                    Timestamp::from_second(1).unwrap(),
+                ),
+                dispatcher(
+                    suspended,
+                    DispatcherState::Suspended,
+                    Timestamp::from_second(2).unwrap(),
+                ),
+                dispatcher(
+                    active2,
+                    DispatcherState::Active,
+                    Timestamp::from_second(3).unwrap(),
+                ),
+            ])
+            .await
+            .unwrap();
+
+        let at =
+            |id: DispatcherId| Cursor::new(SortKey::Time(Timestamp::from_second(1).unwrap()), id.0);
+        let options = QueryOptions {
+            filter: DispatcherFilter::builder()
+                .states([DispatcherState::Active])
+                .build(),
+            pagination: Pagination::Cursor {
+                after: Some(at(active1)),
+                limit: 10,
+            },
+            ..default_options()
+        };
+
+        let results = registry.list(options).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, active2);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_secret_survives_update() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+        let id = DispatcherId(Ulid::new());
+
+        assert!(
+            registry
+                .set_secret(id, Some("s3cret".into()))
+                .await
+                .is_err()
+        );
+
+        registry
+            .register(dispatcher(id, DispatcherState::Active, Timestamp::now()))
+            .await
+            .unwrap();
+        registry
+            .set_secret(id, Some("s3cret".into()))
+            .await
+            .unwrap();
+        registry.suspend(id).await.unwrap();
+
+        assert_eq!(
+            registry.get_secret(id).await.unwrap().as_deref(),
+            Some("s3cret")
+        );
+
+        registry.set_secret(id, None).await.unwrap();
+        assert_eq!(registry.get_secret(id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_update_checks_the_revision() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+        let id = DispatcherId(Ulid::new());
+        let d = dispatcher(id, DispatcherState::Active, Timestamp::now());
+
+        assert!(matches!(
+            registry.update(id, d.clone(), None).await,
+            Err(SqliteDispatcherError::NotFound)
+        ));
+
+        registry.register(d.clone()).await.unwrap();
+        let registered = registry.updated_at(id).await.unwrap().unwrap();
+        let suspended = Dispatcher {
+            state: DispatcherState::Suspended,
+            ..d
+        };
+        let updated = registry
+            .update(id, suspended, Some(registered))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(updated > registered);
+        assert_eq!(registry.updated_at(id).await.unwrap(), Some(updated));
+
+        // The revision read before the update is stale now.
+        assert_eq!(
+            registry.update(id, d, Some(registered)).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            registry.get(id).await.unwrap().unwrap().state,
+            DispatcherState::Suspended
+        );
+
+        registry.reactivate(id).await.unwrap();
+        assert!(registry.updated_at(id).await.unwrap().unwrap() > updated);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_org_filter() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+        let ours = DispatcherId(Ulid::new());
+        let theirs = DispatcherId(Ulid::new());
+        let org_id = OrgId(Ulid::new());
+
+        for id in [ours, theirs] {
+            registry
+                .register(dispatcher(id, DispatcherState::Active, Timestamp::now()))
+                .await
+                .unwrap();
+        }
+        registry.set_org(ours, Some(org_id)).await.unwrap();
+        registry.suspend(ours).await.unwrap();
+        assert_eq!(registry.org(ours).await.unwrap(), Some(org_id));
+
+        let filter = DispatcherFilter::builder().org(org_id).build();
+        let results = registry
+            .list(QueryOptions {
+                filter,
+                ..default_options()
+            })
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, ours);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_tag_filter() {
+        let registry = SqliteDispatcherRegistry::new_in_memory().await.unwrap();
+        let tagged = DispatcherId(Ulid::new());
+        let untagged = DispatcherId(Ulid::new());
+
+        for id in [tagged, untagged] {
+            registry
+                .register(dispatcher(id, DispatcherState::Active, Timestamp::now()))
+                .await
+                .unwrap();
+        }
+        registry
+            .set_tags(tagged, vec!["v2-hardware".to_owned()])
+            .await
+            .unwrap();
+        registry.suspend(tagged).await.unwrap();
+        assert_eq!(registry.tags(tagged).await.unwrap(), ["v2-hardware"]);
+
+        let filter = DispatcherFilter::builder()
+            .tags(["v2-hardware".to_owned()])
+            .build();
+        let results = registry
+            .list(QueryOptions {
+                filter,
+                ..default_options()
+            })
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, tagged);
+    }
 }
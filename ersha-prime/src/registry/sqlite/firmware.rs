@@ -0,0 +1,409 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{CommandId, DeviceId};
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::firmware::{
+    DeviceRolloutState, FirmwareId, FirmwareImage, Rollout, RolloutDevice, RolloutId, RolloutState,
+    RolloutTarget,
+};
+use crate::org::OrgId;
+use crate::registry::{FirmwareRegistry, RegistryError};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteFirmwareError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid rollout state: {0}")]
+    InvalidRolloutState(i32),
+    #[error("invalid device rollout state: {0}")]
+    InvalidDeviceState(i32),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteFirmwareError> for RegistryError {
+    fn from(error: SqliteFirmwareError) -> Self {
+        match error {
+            SqliteFirmwareError::NotFound => RegistryError::NotFound,
+            SqliteFirmwareError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteFirmwareRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteFirmwareRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteFirmwareError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteFirmwareError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteFirmwareError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const IMAGE_COLUMNS: &str = "id, version, hardware_rev, size, sha256, created_at";
+const ROLLOUT_COLUMNS: &str =
+    "id, firmware_id, hardware_rev, tags, org_id, batch_size, state, created_at";
+const DEVICE_COLUMNS: &str = "rollout_id, device_id, state, command_id, updated_at";
+
+#[async_trait]
+impl FirmwareRegistry for SqliteFirmwareRegistry {
+    type Error = SqliteFirmwareError;
+
+    async fn add_image(&self, image: FirmwareImage) -> Result<(), Self::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO firmware_images ({IMAGE_COLUMNS}) VALUES (?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(image.id.0.to_string())
+        .bind(image.version)
+        .bind(image.hardware_rev)
+        .bind(image.size as i64)
+        .bind(image.sha256)
+        .bind(nanos(image.created_at))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn image(&self, id: FirmwareId) -> Result<Option<FirmwareImage>, Self::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {IMAGE_COLUMNS} FROM firmware_images WHERE id = ?"
+        ))
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(map_row_to_image).transpose()
+    }
+
+    async fn images(&self) -> Result<Vec<FirmwareImage>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {IMAGE_COLUMNS} FROM firmware_images ORDER BY id DESC"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_image).collect()
+    }
+
+    async fn create_rollout(&self, rollout: Rollout) -> Result<(), Self::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO firmware_rollouts ({ROLLOUT_COLUMNS}) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(rollout.id.0.to_string())
+        .bind(rollout.firmware_id.0.to_string())
+        .bind(rollout.target.hardware_rev)
+        .bind(serde_json::to_string(&rollout.target.tags)?)
+        .bind(rollout.org_id.map(|org| org.0.to_string()))
+        .bind(rollout.batch_size as i64)
+        .bind(rollout_state_code(rollout.state))
+        .bind(nanos(rollout.created_at))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn rollout(&self, id: RolloutId) -> Result<Option<Rollout>, Self::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {ROLLOUT_COLUMNS} FROM firmware_rollouts WHERE id = ?"
+        ))
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(map_row_to_rollout).transpose()
+    }
+
+    async fn rollouts(&self) -> Result<Vec<Rollout>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {ROLLOUT_COLUMNS} FROM firmware_rollouts ORDER BY id DESC"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_rollout).collect()
+    }
+
+    async fn set_rollout_state(
+        &self,
+        id: RolloutId,
+        state: RolloutState,
+    ) -> Result<(), Self::Error> {
+        let result = sqlx::query("UPDATE firmware_rollouts SET state = ? WHERE id = ?")
+            .bind(rollout_state_code(state))
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteFirmwareError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn enroll(
+        &self,
+        id: RolloutId,
+        devices: Vec<DeviceId>,
+        now: jiff::Timestamp,
+    ) -> Result<usize, Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut added = 0;
+        for device_id in devices {
+            let result = sqlx::query(&format!(
+                "INSERT OR IGNORE INTO firmware_rollout_devices ({DEVICE_COLUMNS}) \
+                 VALUES (?, ?, ?, NULL, ?)"
+            ))
+            .bind(id.0.to_string())
+            .bind(device_id.0.to_string())
+            .bind(device_state_code(DeviceRolloutState::Pending))
+            .bind(nanos(now))
+            .execute(&mut *tx)
+            .await?;
+            added += result.rows_affected() as usize;
+        }
+
+        tx.commit().await?;
+
+        Ok(added)
+    }
+
+    async fn rollout_devices(&self, id: RolloutId) -> Result<Vec<RolloutDevice>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {DEVICE_COLUMNS} FROM firmware_rollout_devices \
+             WHERE rollout_id = ? ORDER BY rowid"
+        ))
+        .bind(id.0.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_device).collect()
+    }
+
+    async fn update_rollout_devices(&self, devices: Vec<RolloutDevice>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for device in devices {
+            sqlx::query(
+                r#"
+                UPDATE firmware_rollout_devices
+                SET state = ?, command_id = ?, updated_at = ?
+                WHERE rollout_id = ? AND device_id = ?
+                "#,
+            )
+            .bind(device_state_code(device.state))
+            .bind(device.command_id.map(|id| id.0.to_string()))
+            .bind(nanos(device.updated_at))
+            .bind(device.rollout_id.0.to_string())
+            .bind(device.device_id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+fn nanos(timestamp: jiff::Timestamp) -> i64 {
+    timestamp.as_nanosecond() as i64
+}
+
+fn from_nanos(nanos: i64) -> Result<jiff::Timestamp, SqliteFirmwareError> {
+    jiff::Timestamp::from_nanosecond(nanos as i128)
+        .map_err(|_| SqliteFirmwareError::InvalidTimestamp(nanos))
+}
+
+fn ulid(s: String) -> Result<Ulid, SqliteFirmwareError> {
+    Ulid::from_str(&s).map_err(|_| SqliteFirmwareError::InvalidUlid(s))
+}
+
+fn rollout_state_code(state: RolloutState) -> i32 {
+    match state {
+        RolloutState::Active => 0,
+        RolloutState::Paused => 1,
+        RolloutState::Cancelled => 2,
+        RolloutState::Completed => 3,
+    }
+}
+
+fn device_state_code(state: DeviceRolloutState) -> i32 {
+    match state {
+        DeviceRolloutState::Pending => 0,
+        DeviceRolloutState::Queued => 1,
+        DeviceRolloutState::Delivered => 2,
+        DeviceRolloutState::Acked => 3,
+        DeviceRolloutState::Failed => 4,
+    }
+}
+
+fn map_row_to_image(row: SqliteRow) -> Result<FirmwareImage, SqliteFirmwareError> {
+    let size: i64 = row.try_get("size")?;
+
+    Ok(FirmwareImage {
+        id: FirmwareId(ulid(row.try_get("id")?)?),
+        version: row.try_get("version")?,
+        hardware_rev: row.try_get("hardware_rev")?,
+        size: size as u64,
+        sha256: row.try_get("sha256")?,
+        created_at: from_nanos(row.try_get("created_at")?)?,
+    })
+}
+
+fn map_row_to_rollout(row: SqliteRow) -> Result<Rollout, SqliteFirmwareError> {
+    let tags: String = row.try_get("tags")?;
+    let batch_size: i64 = row.try_get("batch_size")?;
+    let state = match row.try_get::<i32, _>("state")? {
+        0 => RolloutState::Active,
+        1 => RolloutState::Paused,
+        2 => RolloutState::Cancelled,
+        3 => RolloutState::Completed,
+        other => return Err(SqliteFirmwareError::InvalidRolloutState(other)),
+    };
+
+    Ok(Rollout {
+        id: RolloutId(ulid(row.try_get("id")?)?),
+        firmware_id: FirmwareId(ulid(row.try_get("firmware_id")?)?),
+        target: RolloutTarget {
+            hardware_rev: row.try_get("hardware_rev")?,
+            tags: serde_json::from_str(&tags)?,
+        },
+        org_id: row
+            .try_get::<Option<String>, _>("org_id")?
+            .map(ulid)
+            .transpose()?
+            .map(OrgId),
+        batch_size: batch_size as usize,
+        state,
+        created_at: from_nanos(row.try_get("created_at")?)?,
+    })
+}
+
+fn map_row_to_device(row: SqliteRow) -> Result<RolloutDevice, SqliteFirmwareError> {
+    let state = match row.try_get::<i32, _>("state")? {
+        0 => DeviceRolloutState::Pending,
+        1 => DeviceRolloutState::Queued,
+        2 => DeviceRolloutState::Delivered,
+        3 => DeviceRolloutState::Acked,
+        4 => DeviceRolloutState::Failed,
+        other => return Err(SqliteFirmwareError::InvalidDeviceState(other)),
+    };
+
+    Ok(RolloutDevice {
+        rollout_id: RolloutId(ulid(row.try_get("rollout_id")?)?),
+        device_id: DeviceId(ulid(row.try_get("device_id")?)?),
+        state,
+        command_id: row
+            .try_get::<Option<String>, _>("command_id")?
+            .map(ulid)
+            .transpose()?
+            .map(CommandId),
+        updated_at: from_nanos(row.try_get("updated_at")?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{CommandId, DeviceId};
+    use ulid::Ulid;
+
+    use super::SqliteFirmwareRegistry;
+    use crate::firmware::{
+        DeviceRolloutState, FirmwareId, FirmwareImage, Rollout, RolloutId, RolloutState,
+        RolloutTarget,
+    };
+    use crate::org::OrgId;
+    use crate::registry::FirmwareRegistry;
+
+    #[tokio::test]
+    async fn test_rollout_progress_round_trips() {
+        let registry = SqliteFirmwareRegistry::new_in_memory().await.unwrap();
+        let now = jiff::Timestamp::now();
+        let image = FirmwareImage {
+            id: FirmwareId(Ulid::new()),
+            version: "1.4.2".to_owned(),
+            hardware_rev: None,
+            size: 1024,
+            sha256: "0f".repeat(32),
+            created_at: now,
+        };
+        registry.add_image(image.clone()).await.unwrap();
+        assert_eq!(registry.images().await.unwrap(), vec![image.clone()]);
+
+        let rollout = Rollout {
+            id: RolloutId(Ulid::new()),
+            firmware_id: image.id,
+            target: RolloutTarget {
+                hardware_rev: Some("rev-c".to_owned()),
+                tags: vec!["pilot-a".to_owned()],
+            },
+            org_id: Some(OrgId(Ulid::new())),
+            batch_size: 10,
+            state: RolloutState::Active,
+            created_at: now,
+        };
+        registry.create_rollout(rollout.clone()).await.unwrap();
+        registry
+            .set_rollout_state(rollout.id, RolloutState::Paused)
+            .await
+            .unwrap();
+        let stored = registry.rollout(rollout.id).await.unwrap().unwrap();
+        assert_eq!(stored.state, RolloutState::Paused);
+        assert_eq!(stored.target, rollout.target);
+
+        let (first, second) = (DeviceId(Ulid::new()), DeviceId(Ulid::new()));
+        let added = registry
+            .enroll(rollout.id, vec![first, second], now)
+            .await
+            .unwrap();
+        assert_eq!(added, 2);
+        let again = registry.enroll(rollout.id, vec![first], now).await.unwrap();
+        assert_eq!(again, 0);
+
+        let mut devices = registry.rollout_devices(rollout.id).await.unwrap();
+        assert_eq!(devices[0].device_id, first);
+        devices[0].state = DeviceRolloutState::Queued;
+        devices[0].command_id = Some(CommandId(Ulid::new()));
+        registry
+            .update_rollout_devices(vec![devices[0].clone()])
+            .await
+            .unwrap();
+        assert_eq!(registry.rollout_devices(rollout.id).await.unwrap(), devices);
+    }
+}
@@ -0,0 +1,190 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::group::{Group, GroupId};
+use crate::org::OrgId;
+use crate::registry::{GroupRegistry, RegistryError};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteGroupError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteGroupError> for RegistryError {
+    fn from(error: SqliteGroupError) -> Self {
+        match error {
+            SqliteGroupError::NotFound => RegistryError::NotFound,
+            SqliteGroupError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteGroupRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteGroupRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteGroupError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteGroupError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteGroupError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const GROUP_COLUMNS: &str = "id, name, description, tags, org_id, created_at";
+
+#[async_trait]
+impl GroupRegistry for SqliteGroupRegistry {
+    type Error = SqliteGroupError;
+
+    async fn create(&self, group: Group) -> Result<(), Self::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO groups ({GROUP_COLUMNS}) VALUES (?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(group.id.0.to_string())
+        .bind(group.name)
+        .bind(group.description)
+        .bind(serde_json::to_string(&group.tags)?)
+        .bind(group.org_id.map(|org| org.0.to_string()))
+        .bind(group.created_at.as_second())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: GroupId) -> Result<Option<Group>, Self::Error> {
+        let row = sqlx::query(&format!("SELECT {GROUP_COLUMNS} FROM groups WHERE id = ?"))
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(map_row_to_group).transpose()
+    }
+
+    async fn update(&self, group: Group) -> Result<(), Self::Error> {
+        let result =
+            sqlx::query("UPDATE groups SET name = ?, description = ?, tags = ? WHERE id = ?")
+                .bind(group.name)
+                .bind(group.description)
+                .bind(serde_json::to_string(&group.tags)?)
+                .bind(group.id.0.to_string())
+                .execute(&self.pool)
+                .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteGroupError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: GroupId) -> Result<(), Self::Error> {
+        let result = sqlx::query("DELETE FROM groups WHERE id = ?")
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteGroupError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Group>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {GROUP_COLUMNS} FROM groups ORDER BY name, id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_group).collect()
+    }
+}
+
+fn map_row_to_group(row: SqliteRow) -> Result<Group, SqliteGroupError> {
+    let id: String = row.try_get("id")?;
+    let tags: String = row.try_get("tags")?;
+    let created_at: i64 = row.try_get("created_at")?;
+
+    Ok(Group {
+        id: Ulid::from_str(&id)
+            .map(GroupId)
+            .map_err(|_| SqliteGroupError::InvalidUlid(id))?,
+        name: row.try_get("name")?,
+        description: row.try_get("description")?,
+        tags: serde_json::from_str(&tags)?,
+        org_id: row
+            .try_get::<Option<String>, _>("org_id")?
+            .map(|id| {
+                Ulid::from_str(&id)
+                    .map(OrgId)
+                    .map_err(|_| SqliteGroupError::InvalidUlid(id))
+            })
+            .transpose()?,
+        created_at: jiff::Timestamp::from_second(created_at)
+            .map_err(|_| SqliteGroupError::InvalidTimestamp(created_at))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteGroupRegistry;
+    use crate::group::Group;
+    use crate::registry::GroupRegistry;
+
+    #[tokio::test]
+    async fn test_groups_round_trip() {
+        let registry = SqliteGroupRegistry::new_in_memory().await.unwrap();
+        let mut group = Group::new(
+            "North pilot".to_owned(),
+            vec!["north-ridge".to_owned(), "pilot-a".to_owned()],
+        );
+        group.created_at = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+        registry.create(group.clone()).await.unwrap();
+
+        group.description = Some("First season on the ridge".to_owned());
+        group.tags.pop();
+        registry.update(group.clone()).await.unwrap();
+        assert_eq!(registry.get(group.id).await.unwrap(), Some(group.clone()));
+        assert_eq!(registry.list().await.unwrap(), vec![group.clone()]);
+
+        registry.delete(group.id).await.unwrap();
+        assert_eq!(registry.get(group.id).await.unwrap(), None);
+        assert!(registry.delete(group.id).await.is_err());
+    }
+}
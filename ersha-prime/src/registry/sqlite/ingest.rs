@@ -0,0 +1,225 @@
+use async_trait::async_trait;
+use sqlx::{
+    Connection, Sqlite, SqlitePool, migrate::Migrator, pool::PoolConnection,
+    sqlite::SqlitePoolOptions,
+};
+
+use crate::config::SqlitePoolConfig;
+use crate::registry::{IngestBatch, IngestRegistry, Ingested, RegistryError};
+
+use super::dead_letter::{self, SqliteDeadLetterError};
+use super::outbox::{self, SqliteOutboxError};
+use super::reading::{self, SqliteReadingError, SqliteReadingRegistry};
+use super::status::{self, SqliteDeviceStatusError};
+use super::writer::Writer;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteIngestError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("reading error: {0}")]
+    Reading(#[from] SqliteReadingError),
+    #[error("status error: {0}")]
+    Status(#[from] SqliteDeviceStatusError),
+    #[error("dead letter error: {0}")]
+    DeadLetter(#[from] SqliteDeadLetterError),
+    #[error("outbox error: {0}")]
+    Outbox(#[from] SqliteOutboxError),
+}
+
+impl From<SqliteIngestError> for RegistryError {
+    fn from(error: SqliteIngestError) -> Self {
+        match error {
+            SqliteIngestError::Sqlx(e) => e.into(),
+            SqliteIngestError::Reading(e) => e.into(),
+            SqliteIngestError::Status(e) => e.into(),
+            SqliteIngestError::DeadLetter(e) => e.into(),
+            SqliteIngestError::Outbox(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+/// Stores an upload's readings, statuses and dead letters, and stages its
+/// events, in one transaction.
+#[derive(Clone)]
+pub struct SqliteIngestRegistry {
+    pool: SqlitePool,
+    /// Runs writes in turn when set, rather than on any pool connection
+    writer: Option<Writer>,
+}
+
+impl SqliteIngestRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteIngestError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteIngestError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool, writer: None })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteIngestError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool, writer: None })
+    }
+
+    /// Queue writes behind those of `readings`, which other writes to the
+    /// same tables go through too.
+    pub fn sharing_writer(self, readings: &SqliteReadingRegistry) -> Self {
+        Self {
+            writer: readings.writer(),
+            ..self
+        }
+    }
+
+    /// Run `write` through the writer task, if there is one.
+    async fn write<T, F, Fut>(&self, write: F) -> Result<T, SqliteIngestError>
+    where
+        T: Send + 'static,
+        F: FnOnce(PoolConnection<Sqlite>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, SqliteIngestError>> + Send + 'static,
+    {
+        match &self.writer {
+            Some(writer) => writer.run(write).await?,
+            None => write(self.pool.acquire().await?).await,
+        }
+    }
+}
+
+#[async_trait]
+impl IngestRegistry for SqliteIngestRegistry {
+    type Error = SqliteIngestError;
+
+    async fn ingest(&self, batch: IngestBatch) -> Result<Ingested, Self::Error> {
+        self.write(|mut conn| async move {
+            let mut tx = conn.begin().await?;
+            let readings = reading::insert_new(&mut tx, batch.readings).await?;
+            let statuses = status::insert_new(&mut tx, batch.statuses).await?;
+            dead_letter::record(&mut tx, batch.dead_letters).await?;
+            outbox::stage(&mut tx, &batch.events).await?;
+            tx.commit().await?;
+
+            Ok(Ingested { readings, statuses })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        BatchId, DeviceId, DeviceStatus, DispatcherId, H3Cell, InvalidItemReason, Percentage,
+        ReadingId, SensorId, SensorMetric, SensorReading, StatusId,
+    };
+    use ulid::Ulid;
+
+    use super::SqliteIngestRegistry;
+    use crate::dead_letter::{DeadItem, DeadLetter};
+    use crate::outbox::OutboxEntry;
+    use crate::registry::{IngestBatch, IngestRegistry, Ingested};
+    use crate::webhook::{Event, EventKind};
+
+    fn batch() -> IngestBatch {
+        let device_id = DeviceId(Ulid::new());
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let timestamp = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+        let reading = SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id,
+            dispatcher_id,
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(40),
+            },
+            location: H3Cell(0x8a2a1072b59ffff),
+            confidence: Percentage(90),
+            timestamp,
+            sensor_id: SensorId(Ulid::new()),
+        };
+        let status = DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id,
+            dispatcher_id,
+            battery_percent: Percentage(80),
+            uptime_seconds: 3600,
+            signal_rssi: -70,
+            errors: vec![].into_boxed_slice(),
+            timestamp,
+            sensor_statuses: vec![].into_boxed_slice(),
+        };
+        let mut refused = reading.clone();
+        refused.id = ReadingId(Ulid::new());
+        let letter = DeadLetter::new(
+            dispatcher_id,
+            BatchId(Ulid::new()),
+            DeadItem::Reading(refused),
+            InvalidItemReason::UnknownDevice,
+            timestamp,
+        );
+
+        IngestBatch {
+            readings: vec![reading],
+            statuses: vec![status],
+            dead_letters: vec![letter],
+            events: vec![OutboxEntry::new(Event::new(
+                EventKind::AlertRaised,
+                serde_json::json!({}),
+            ))],
+        }
+    }
+
+    async fn count(registry: &SqliteIngestRegistry, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+            .fetch_one(&registry.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ingest_stores_everything_once() {
+        let registry = SqliteIngestRegistry::new_in_memory().await.unwrap();
+        let batch = batch();
+        let expected = Ingested {
+            readings: vec![batch.readings[0].id],
+            statuses: vec![batch.statuses[0].id],
+        };
+
+        assert_eq!(registry.ingest(batch.clone()).await.unwrap(), expected);
+        assert_eq!(count(&registry, "dead_letters").await, 1);
+        assert_eq!(count(&registry, "outbox").await, 1);
+
+        // A retried upload stores nothing new. Its events are raised afresh,
+        // if at all, so it brings none of the first upload's.
+        let retried = IngestBatch {
+            events: vec![],
+            ..batch
+        };
+        assert_eq!(registry.ingest(retried).await.unwrap(), Ingested::default());
+        assert_eq!(count(&registry, "readings").await, 1);
+        assert_eq!(count(&registry, "dead_letters").await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_status_write_keeps_no_readings() {
+        let registry = SqliteIngestRegistry::new_in_memory().await.unwrap();
+        sqlx::query("DROP TABLE device_statuses")
+            .execute(&registry.pool)
+            .await
+            .unwrap();
+
+        assert!(registry.ingest(batch()).await.is_err());
+        assert_eq!(count(&registry, "readings").await, 0);
+        assert_eq!(count(&registry, "dead_letters").await, 0);
+        assert_eq!(count(&registry, "outbox").await, 0);
+    }
+}
@@ -0,0 +1,273 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{DeviceId, H3Cell};
+use jiff::Timestamp;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::irrigation::{IrrigationPlan, PlanId, Valve};
+use crate::org::OrgId;
+use crate::registry::{IrrigationRegistry, RegistryError};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteIrrigationError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid valve channel: {0}")]
+    InvalidChannel(i64),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteIrrigationError> for RegistryError {
+    fn from(error: SqliteIrrigationError) -> Self {
+        match error {
+            SqliteIrrigationError::NotFound => RegistryError::NotFound,
+            SqliteIrrigationError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteIrrigationRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteIrrigationRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteIrrigationError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteIrrigationError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteIrrigationError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const COLUMNS: &str = "id, field, crop_coefficient, valve_device_id, valve_channel, automatic, \
+                       org_id, window, planned_at, created_at";
+
+#[async_trait]
+impl IrrigationRegistry for SqliteIrrigationRegistry {
+    type Error = SqliteIrrigationError;
+
+    async fn create(&self, plan: IrrigationPlan) -> Result<(), Self::Error> {
+        let window = plan
+            .window
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        sqlx::query(&format!(
+            "INSERT INTO irrigation_plans ({COLUMNS}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(plan.id.0.to_string())
+        .bind(plan.field.0 as i64)
+        .bind(plan.crop_coefficient)
+        .bind(plan.valve.map(|valve| valve.device_id.0.to_string()))
+        .bind(plan.valve.map(|valve| i64::from(valve.channel)))
+        .bind(plan.automatic)
+        .bind(plan.org_id.map(|org| org.0.to_string()))
+        .bind(window)
+        .bind(plan.planned_at.map(|t| t.as_second()))
+        .bind(plan.created_at.as_second())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: PlanId) -> Result<Option<IrrigationPlan>, Self::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {COLUMNS} FROM irrigation_plans WHERE id = ?"
+        ))
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(map_row_to_plan).transpose()
+    }
+
+    async fn update(&self, plan: IrrigationPlan) -> Result<(), Self::Error> {
+        let window = plan
+            .window
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let result = sqlx::query(
+            r#"
+            UPDATE irrigation_plans
+            SET field = ?, crop_coefficient = ?, valve_device_id = ?, valve_channel = ?,
+                automatic = ?, org_id = ?, window = ?, planned_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(plan.field.0 as i64)
+        .bind(plan.crop_coefficient)
+        .bind(plan.valve.map(|valve| valve.device_id.0.to_string()))
+        .bind(plan.valve.map(|valve| i64::from(valve.channel)))
+        .bind(plan.automatic)
+        .bind(plan.org_id.map(|org| org.0.to_string()))
+        .bind(window)
+        .bind(plan.planned_at.map(|t| t.as_second()))
+        .bind(plan.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteIrrigationError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: PlanId) -> Result<(), Self::Error> {
+        let result = sqlx::query("DELETE FROM irrigation_plans WHERE id = ?")
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteIrrigationError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<IrrigationPlan>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {COLUMNS} FROM irrigation_plans ORDER BY id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_plan).collect()
+    }
+}
+
+fn map_row_to_plan(row: SqliteRow) -> Result<IrrigationPlan, SqliteIrrigationError> {
+    let parse_ulid = |s: String| -> Result<Ulid, SqliteIrrigationError> {
+        Ulid::from_str(&s).map_err(|_| SqliteIrrigationError::InvalidUlid(s))
+    };
+    let parse_timestamp = |second: i64| {
+        Timestamp::from_second(second).map_err(|_| SqliteIrrigationError::InvalidTimestamp(second))
+    };
+
+    let valve = match (
+        row.try_get::<Option<String>, _>("valve_device_id")?,
+        row.try_get::<Option<i64>, _>("valve_channel")?,
+    ) {
+        (Some(device_id), Some(channel)) => Some(Valve {
+            device_id: DeviceId(parse_ulid(device_id)?),
+            channel: u8::try_from(channel)
+                .map_err(|_| SqliteIrrigationError::InvalidChannel(channel))?,
+        }),
+        _ => None,
+    };
+    let window = row
+        .try_get::<Option<String>, _>("window")?
+        .map(|window| serde_json::from_str(&window))
+        .transpose()?;
+
+    Ok(IrrigationPlan {
+        id: PlanId(parse_ulid(row.try_get("id")?)?),
+        field: H3Cell(row.try_get::<i64, _>("field")? as u64),
+        crop_coefficient: row.try_get("crop_coefficient")?,
+        valve,
+        automatic: row.try_get("automatic")?,
+        org_id: row
+            .try_get::<Option<String>, _>("org_id")?
+            .map(|org| parse_ulid(org).map(OrgId))
+            .transpose()?,
+        window,
+        planned_at: row
+            .try_get::<Option<i64>, _>("planned_at")?
+            .map(parse_timestamp)
+            .transpose()?,
+        created_at: parse_timestamp(row.try_get("created_at")?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{CommandId, DeviceId, H3Cell};
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::{SqliteIrrigationError, SqliteIrrigationRegistry};
+    use crate::irrigation::{IrrigationPlan, IrrigationWindow, Valve};
+    use crate::org::OrgId;
+    use crate::registry::IrrigationRegistry;
+
+    fn at(second: i64) -> Timestamp {
+        Timestamp::from_second(second).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_plan_round_trip() {
+        let registry = SqliteIrrigationRegistry::new_in_memory().await.unwrap();
+        let mut plan = IrrigationPlan {
+            org_id: Some(OrgId(Ulid::new())),
+            ..IrrigationPlan::new(H3Cell(0x892a1072b5bffff), 0.85, at(1_000))
+        };
+        let other = IrrigationPlan::new(H3Cell(0x892a1072b5bffff), 1.1, at(1_000));
+        registry.create(plan.clone()).await.unwrap();
+        registry.create(other.clone()).await.unwrap();
+        assert_eq!(registry.get(plan.id).await.unwrap(), Some(plan.clone()));
+
+        plan.valve = Some(Valve {
+            device_id: DeviceId(Ulid::new()),
+            channel: 3,
+        });
+        plan.automatic = true;
+        plan.window = Some(IrrigationWindow {
+            start: at(4_000),
+            end: at(9_400),
+            water_mm: 22.5,
+            opened_by: Some(CommandId(Ulid::new())),
+            closed_by: None,
+        });
+        plan.planned_at = Some(at(2_000));
+        registry.update(plan.clone()).await.unwrap();
+        assert_eq!(registry.get(plan.id).await.unwrap(), Some(plan.clone()));
+
+        let listed = registry.list().await.unwrap();
+        let mut expected = vec![plan.clone(), other.clone()];
+        expected.sort_by_key(|p| p.id.0);
+        assert_eq!(listed, expected);
+
+        registry.delete(other.id).await.unwrap();
+        assert_eq!(registry.get(other.id).await.unwrap(), None);
+        assert!(matches!(
+            registry.delete(other.id).await,
+            Err(SqliteIrrigationError::NotFound)
+        ));
+        assert!(matches!(
+            registry.update(other).await,
+            Err(SqliteIrrigationError::NotFound)
+        ));
+    }
+}
@@ -1,5 +1,250 @@
+mod aggregate;
+mod api_key;
+mod audit;
+mod command;
+mod contact;
+mod correction;
+mod dead_letter;
+mod derived;
 mod device;
 mod dispatcher;
+mod firmware;
+mod group;
+mod ingest;
+mod irrigation;
+mod org;
+mod outbox;
+mod reading;
+mod schema;
+mod status;
+mod user;
+mod validation_rule;
+mod webhook;
+mod writer;
 
+pub use aggregate::SqliteAggregateRegistry;
+pub use api_key::SqliteApiKeyRegistry;
+pub use audit::SqliteAuditRegistry;
+pub use command::SqliteCommandRegistry;
+pub use contact::SqliteContactRegistry;
+pub use correction::SqliteCorrectionRegistry;
+pub use dead_letter::SqliteDeadLetterRegistry;
+pub use derived::SqliteDerivedMetricRegistry;
 pub use device::SqliteDeviceRegistry;
 pub use dispatcher::SqliteDispatcherRegistry;
+pub use firmware::SqliteFirmwareRegistry;
+pub use group::SqliteGroupRegistry;
+pub use ingest::SqliteIngestRegistry;
+pub use irrigation::SqliteIrrigationRegistry;
+pub use org::SqliteOrgRegistry;
+pub use outbox::SqliteOutboxRegistry;
+pub use reading::SqliteReadingRegistry;
+pub use schema::{
+    AppliedMigration, PendingMigration, SchemaError, SchemaStatus, migrate, schema_status,
+};
+pub use status::SqliteDeviceStatusRegistry;
+pub use user::SqliteUserRegistry;
+pub use validation_rule::SqliteValidationRuleRegistry;
+pub use webhook::SqliteWebhookRegistry;
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use sqlx::{
+    QueryBuilder, Sqlite, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+};
+
+use super::{
+    Registries,
+    filter::{Cursor, SortKey, SortOrder},
+    memory::InMemoryDispatcherStatusRegistry,
+};
+use crate::config::SqlitePoolConfig;
+
+/// A pool of connections to the database at `path`, to be shared by the
+/// registries.
+pub async fn connect(path: &str, config: &SqlitePoolConfig) -> Result<SqlitePool, sqlx::Error> {
+    let mut options = SqliteConnectOptions::from_str(&format!("sqlite:{path}"))?
+        .busy_timeout(Duration::from_millis(config.busy_timeout_ms));
+    if config.wal {
+        // Durable at checkpoints rather than every commit, which WAL keeps
+        // consistent across crashes.
+        options = options
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal);
+    }
+
+    SqlitePoolOptions::new()
+        .max_connections(config.max_connections.max(1))
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .connect_with(options)
+        .await
+}
+
+/// Keyset pagination: continue strictly after `cursor` in `order`, where
+/// `column` holds the sort key and `time` gives timestamps as stored.
+fn push_after(
+    query_builder: &mut QueryBuilder<'_, Sqlite>,
+    column: &str,
+    order: &SortOrder,
+    cursor: &Cursor,
+    time: fn(jiff::Timestamp) -> i64,
+) {
+    let cmp = match order {
+        SortOrder::Asc => ">",
+        SortOrder::Desc => "<",
+    };
+
+    query_builder.push(format!("({column}, id) {cmp} ("));
+    match &cursor.key {
+        SortKey::Time(timestamp) => query_builder.push_bind(time(*timestamp)),
+        SortKey::Int(n) => query_builder.push_bind(*n),
+        SortKey::Text(text) => query_builder.push_bind(text.clone()),
+    };
+    query_builder.push(", ");
+    query_builder.push_bind(cursor.id.to_string());
+    query_builder.push(")");
+}
+
+/// Registries persisted in SQLite.
+///
+/// Dispatcher status reports are not persisted yet and live in memory.
+#[derive(Clone)]
+pub struct SqliteRegistries {
+    pub devices: SqliteDeviceRegistry,
+    pub dispatchers: SqliteDispatcherRegistry,
+    pub readings: SqliteReadingRegistry,
+    pub statuses: SqliteDeviceStatusRegistry,
+    pub ingest: SqliteIngestRegistry,
+    pub dispatcher_statuses: InMemoryDispatcherStatusRegistry,
+    pub aggregates: SqliteAggregateRegistry,
+    pub derived_metrics: SqliteDerivedMetricRegistry,
+    pub commands: SqliteCommandRegistry,
+    pub irrigation: SqliteIrrigationRegistry,
+    pub corrections: SqliteCorrectionRegistry,
+    pub dead_letters: SqliteDeadLetterRegistry,
+    pub audit: SqliteAuditRegistry,
+    pub webhooks: SqliteWebhookRegistry,
+    pub contacts: SqliteContactRegistry,
+    pub outbox: SqliteOutboxRegistry,
+    pub groups: SqliteGroupRegistry,
+    pub validation_rules: SqliteValidationRuleRegistry,
+    pub firmware: SqliteFirmwareRegistry,
+    pub orgs: SqliteOrgRegistry,
+    pub api_keys: SqliteApiKeyRegistry,
+    pub users: SqliteUserRegistry,
+}
+
+impl Registries for SqliteRegistries {
+    type Devices = SqliteDeviceRegistry;
+    type Dispatchers = SqliteDispatcherRegistry;
+    type Readings = SqliteReadingRegistry;
+    type Statuses = SqliteDeviceStatusRegistry;
+    type Ingest = SqliteIngestRegistry;
+    type DispatcherStatuses = InMemoryDispatcherStatusRegistry;
+    type Aggregates = SqliteAggregateRegistry;
+    type DerivedMetrics = SqliteDerivedMetricRegistry;
+    type Commands = SqliteCommandRegistry;
+    type Irrigation = SqliteIrrigationRegistry;
+    type Corrections = SqliteCorrectionRegistry;
+    type DeadLetters = SqliteDeadLetterRegistry;
+    type Audit = SqliteAuditRegistry;
+    type Webhooks = SqliteWebhookRegistry;
+    type Contacts = SqliteContactRegistry;
+    type Outbox = SqliteOutboxRegistry;
+    type Groups = SqliteGroupRegistry;
+    type ValidationRules = SqliteValidationRuleRegistry;
+    type Firmware = SqliteFirmwareRegistry;
+    type Orgs = SqliteOrgRegistry;
+    type ApiKeys = SqliteApiKeyRegistry;
+    type Users = SqliteUserRegistry;
+
+    fn devices(&self) -> &Self::Devices {
+        &self.devices
+    }
+
+    fn dispatchers(&self) -> &Self::Dispatchers {
+        &self.dispatchers
+    }
+
+    fn readings(&self) -> &Self::Readings {
+        &self.readings
+    }
+
+    fn statuses(&self) -> &Self::Statuses {
+        &self.statuses
+    }
+
+    fn ingest(&self) -> &Self::Ingest {
+        &self.ingest
+    }
+
+    fn dispatcher_statuses(&self) -> &Self::DispatcherStatuses {
+        &self.dispatcher_statuses
+    }
+
+    fn aggregates(&self) -> &Self::Aggregates {
+        &self.aggregates
+    }
+
+    fn derived_metrics(&self) -> &Self::DerivedMetrics {
+        &self.derived_metrics
+    }
+
+    fn commands(&self) -> &Self::Commands {
+        &self.commands
+    }
+
+    fn irrigation(&self) -> &Self::Irrigation {
+        &self.irrigation
+    }
+
+    fn corrections(&self) -> &Self::Corrections {
+        &self.corrections
+    }
+
+    fn dead_letters(&self) -> &Self::DeadLetters {
+        &self.dead_letters
+    }
+
+    fn audit(&self) -> &Self::Audit {
+        &self.audit
+    }
+
+    fn webhooks(&self) -> &Self::Webhooks {
+        &self.webhooks
+    }
+
+    fn contacts(&self) -> &Self::Contacts {
+        &self.contacts
+    }
+
+    fn outbox(&self) -> &Self::Outbox {
+        &self.outbox
+    }
+
+    fn groups(&self) -> &Self::Groups {
+        &self.groups
+    }
+
+    fn validation_rules(&self) -> &Self::ValidationRules {
+        &self.validation_rules
+    }
+
+    fn firmware(&self) -> &Self::Firmware {
+        &self.firmware
+    }
+
+    fn orgs(&self) -> &Self::Orgs {
+        &self.orgs
+    }
+
+    fn api_keys(&self) -> &Self::ApiKeys {
+        &self.api_keys
+    }
+
+    fn users(&self) -> &Self::Users {
+        &self.users
+    }
+}
@@ -0,0 +1,143 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::org::{Org, OrgId};
+use crate::registry::{OrgRegistry, RegistryError};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteOrgError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid metadata schema: {0}")]
+    InvalidSchema(serde_json::Error),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteOrgError> for RegistryError {
+    fn from(error: SqliteOrgError) -> Self {
+        match error {
+            SqliteOrgError::Sqlx(e) => e.into(),
+            SqliteOrgError::NotFound => RegistryError::NotFound,
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteOrgRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteOrgRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteOrgError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteOrgError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteOrgError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl OrgRegistry for SqliteOrgRegistry {
+    type Error = SqliteOrgError;
+
+    async fn create(&self, org: Org) -> Result<(), Self::Error> {
+        sqlx::query("INSERT INTO orgs (id, name, created_at) VALUES (?, ?, ?)")
+            .bind(org.id.0.to_string())
+            .bind(org.name)
+            .bind(org.created_at.as_second())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: OrgId) -> Result<Option<Org>, Self::Error> {
+        let row = sqlx::query("SELECT id, name, created_at FROM orgs WHERE id = ?")
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|r| row_to_org(&r)).transpose()
+    }
+
+    async fn list(&self) -> Result<Vec<Org>, Self::Error> {
+        let rows = sqlx::query("SELECT id, name, created_at FROM orgs ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(row_to_org).collect()
+    }
+
+    async fn set_metadata_schema(
+        &self,
+        id: OrgId,
+        schema: Option<serde_json::Value>,
+    ) -> Result<(), Self::Error> {
+        let result = sqlx::query("UPDATE orgs SET metadata_schema = ? WHERE id = ?")
+            .bind(schema.map(|schema| schema.to_string()))
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(SqliteOrgError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn metadata_schema(&self, id: OrgId) -> Result<Option<serde_json::Value>, Self::Error> {
+        let schema: Option<Option<String>> =
+            sqlx::query_scalar("SELECT metadata_schema FROM orgs WHERE id = ?")
+                .bind(id.0.to_string())
+                .fetch_optional(&self.pool)
+                .await?;
+
+        schema
+            .flatten()
+            .map(|schema| serde_json::from_str(&schema))
+            .transpose()
+            .map_err(SqliteOrgError::InvalidSchema)
+    }
+}
+
+fn row_to_org(r: &SqliteRow) -> Result<Org, SqliteOrgError> {
+    let id = r.try_get::<String, _>("id")?;
+    let ulid = Ulid::from_str(&id).map_err(|_| SqliteOrgError::InvalidUlid(id))?;
+
+    let created_at = r.try_get::<i64, _>("created_at")?;
+    let created_at = jiff::Timestamp::from_second(created_at)
+        .map_err(|_| SqliteOrgError::InvalidTimestamp(created_at))?;
+
+    Ok(Org {
+        id: OrgId(ulid),
+        name: r.try_get("name")?,
+        created_at,
+    })
+}
@@ -0,0 +1,257 @@
+use async_trait::async_trait;
+use sqlx::{
+    Row, SqliteConnection, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions,
+    sqlite::SqliteRow,
+};
+use tracing::warn;
+
+use crate::config::SqlitePoolConfig;
+use crate::outbox::{OutboxEntry, OutboxState};
+use crate::registry::{OutboxRegistry, RegistryError};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteOutboxError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid outbox state: {0}")]
+    InvalidState(i32),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteOutboxError> for RegistryError {
+    fn from(error: SqliteOutboxError) -> Self {
+        match error {
+            SqliteOutboxError::NotFound => RegistryError::NotFound,
+            SqliteOutboxError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteOutboxRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteOutboxRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteOutboxError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteOutboxError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteOutboxError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const OUTBOX_COLUMNS: &str = "id, event, recipient, state, attempts, next_attempt_at, last_error";
+
+/// Stage `entries` on `conn`, so that other registries can do so inside
+/// their own transactions.
+pub(super) async fn stage(
+    conn: &mut SqliteConnection,
+    entries: &[OutboxEntry],
+) -> Result<(), SqliteOutboxError> {
+    for entry in entries {
+        sqlx::query(&format!(
+            "INSERT INTO outbox ({OUTBOX_COLUMNS}) VALUES (?, ?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(entry.event.id.0.to_string())
+        .bind(serde_json::to_string(&entry.event)?)
+        .bind(
+            entry
+                .recipient
+                .map(|r| serde_json::to_string(&r))
+                .transpose()?,
+        )
+        .bind(entry.state as i32)
+        .bind(entry.attempts as i64)
+        .bind(entry.next_attempt_at.as_second())
+        .bind(entry.last_error.as_deref())
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl OutboxRegistry for SqliteOutboxRegistry {
+    type Error = SqliteOutboxError;
+
+    async fn stage(&self, entries: Vec<OutboxEntry>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+        stage(&mut tx, &entries).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn due(
+        &self,
+        now: jiff::Timestamp,
+        limit: usize,
+    ) -> Result<Vec<OutboxEntry>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {OUTBOX_COLUMNS} FROM outbox \
+             WHERE state = ? AND next_attempt_at <= ? \
+             ORDER BY next_attempt_at, id LIMIT ?"
+        ))
+        .bind(OutboxState::Pending as i32)
+        .bind(now.as_second())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut due = Vec::with_capacity(rows.len());
+        for row in rows {
+            match map_row_to_entry(&row) {
+                Ok(entry) => due.push(entry),
+                Err(SqliteOutboxError::Json(e)) => {
+                    let id: String = row.try_get("id")?;
+                    warn!(event_id = %id, error = %e, "poisoning unreadable outbox event");
+                    sqlx::query("UPDATE outbox SET state = ?, last_error = ? WHERE id = ?")
+                        .bind(OutboxState::Poisoned as i32)
+                        .bind(format!("unreadable event: {e}"))
+                        .bind(id)
+                        .execute(&self.pool)
+                        .await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(due)
+    }
+
+    async fn update(&self, entry: OutboxEntry) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE outbox
+            SET state = ?, attempts = ?, next_attempt_at = ?, last_error = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(entry.state as i32)
+        .bind(entry.attempts as i64)
+        .bind(entry.next_attempt_at.as_second())
+        .bind(entry.last_error)
+        .bind(entry.event.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteOutboxError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn poisoned(&self, limit: usize) -> Result<Vec<OutboxEntry>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {OUTBOX_COLUMNS} FROM outbox WHERE state = ? ORDER BY id DESC LIMIT ?"
+        ))
+        .bind(OutboxState::Poisoned as i32)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Entries poisoned for being unreadable stay unreadable, and are
+        // left to be inspected in the database.
+        Ok(rows
+            .iter()
+            .filter_map(|row| map_row_to_entry(row).ok())
+            .collect())
+    }
+}
+
+fn parse_timestamp(second: i64) -> Result<jiff::Timestamp, SqliteOutboxError> {
+    jiff::Timestamp::from_second(second).map_err(|_| SqliteOutboxError::InvalidTimestamp(second))
+}
+
+fn map_row_to_entry(row: &SqliteRow) -> Result<OutboxEntry, SqliteOutboxError> {
+    let event: String = row.try_get("event")?;
+    let recipient: Option<String> = row.try_get("recipient")?;
+    let state = match row.try_get::<i32, _>("state")? {
+        0 => OutboxState::Pending,
+        1 => OutboxState::Relayed,
+        2 => OutboxState::Poisoned,
+        other => return Err(SqliteOutboxError::InvalidState(other)),
+    };
+    let attempts: i64 = row.try_get("attempts")?;
+
+    Ok(OutboxEntry {
+        event: serde_json::from_str(&event)?,
+        recipient: recipient.as_deref().map(serde_json::from_str).transpose()?,
+        state,
+        attempts: attempts as u32,
+        next_attempt_at: parse_timestamp(row.try_get("next_attempt_at")?)?,
+        last_error: row.try_get("last_error")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::SqliteOutboxRegistry;
+    use crate::outbox::{OutboxEntry, OutboxState};
+    use crate::registry::OutboxRegistry;
+    use crate::webhook::{Event, EventKind};
+
+    #[tokio::test]
+    async fn test_unreadable_events_are_poisoned() {
+        let registry = SqliteOutboxRegistry::new_in_memory().await.unwrap();
+        let readable =
+            OutboxEntry::new(Event::new(EventKind::DeviceOffline, serde_json::json!({})));
+        let unreadable =
+            OutboxEntry::new(Event::new(EventKind::AlertRaised, serde_json::json!({})));
+        registry
+            .stage(vec![readable.clone(), unreadable.clone()])
+            .await
+            .unwrap();
+        sqlx::query("UPDATE outbox SET event = '{\"kind\":' WHERE id = ?")
+            .bind(unreadable.event.id.0.to_string())
+            .execute(&registry.pool)
+            .await
+            .unwrap();
+
+        let now = Timestamp::now() + SignedDuration::from_secs(1);
+        let due = registry.due(now, 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].event, readable.event);
+
+        let mut relayed = readable;
+        relayed.state = OutboxState::Relayed;
+        relayed.attempts = 1;
+        registry.update(relayed).await.unwrap();
+        assert!(registry.due(now, 10).await.unwrap().is_empty());
+
+        let state: i32 = sqlx::query_scalar("SELECT state FROM outbox WHERE id = ?")
+            .bind(unreadable.event.id.0.to_string())
+            .fetch_one(&registry.pool)
+            .await
+            .unwrap();
+        assert_eq!(state, OutboxState::Poisoned as i32);
+    }
+}
@@ -0,0 +1,922 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{
+    DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId, SensorKind, SensorMetric,
+    SensorReading,
+};
+use ordered_float::NotNan;
+use sqlx::{
+    Connection, QueryBuilder, Row, Sqlite, SqliteConnection, SqlitePool, migrate::Migrator,
+    pool::PoolConnection, sqlite::SqlitePoolOptions, sqlite::SqliteRow,
+};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::quality::{QualityWindow, SensorQuality};
+use crate::region;
+use crate::registry::{
+    ReadingRegistry, RegistryError,
+    filter::{Pagination, QueryOptions, ReadingFilter, ReadingSortBy, SortOrder},
+};
+use crate::validation::QualityStatus;
+
+use super::push_after;
+use super::writer::Writer;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Readings per `INSERT`, well below SQLite's limit on bound parameters.
+const INSERT_CHUNK: usize = 500;
+
+const COLUMNS: &str =
+    "id, device_id, dispatcher_id, sensor_id, metric, value, location, confidence, timestamp";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteReadingError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("timestamp out of range: {0}")]
+    TimestampOutOfRange(jiff::Timestamp),
+    #[error("invalid sensor kind: {0}")]
+    InvalidSensorKind(i32),
+    #[error("invalid metric value: {0}")]
+    InvalidValue(f64),
+}
+
+impl From<SqliteReadingError> for RegistryError {
+    fn from(error: SqliteReadingError) -> Self {
+        match error {
+            SqliteReadingError::Sqlx(e) => e.into(),
+            e @ SqliteReadingError::TimestampOutOfRange(..) => {
+                RegistryError::InvalidInput(e.to_string())
+            }
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteReadingRegistry {
+    pool: SqlitePool,
+    /// Runs writes in turn when set, rather than on any pool connection
+    writer: Option<Writer>,
+}
+
+impl SqliteReadingRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteReadingError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteReadingError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool, writer: None })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteReadingError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool, writer: None })
+    }
+
+    /// Queue writes for a single writer task, up to `queue` of them, so
+    /// concurrent uploads take turns instead of contending for the database
+    /// lock. A `queue` of 0 leaves writes going straight to the pool.
+    pub fn with_writer(self, queue: usize) -> Self {
+        let writer = (queue > 0).then(|| Writer::spawn(self.pool.clone(), queue));
+
+        Self { writer, ..self }
+    }
+
+    /// The writer task writes are queued for, to be shared with registries
+    /// written in the same uploads.
+    pub(crate) fn writer(&self) -> Option<Writer> {
+        self.writer.clone()
+    }
+
+    /// Run `write` through the writer task, if there is one.
+    async fn write<T, F, Fut>(&self, write: F) -> Result<T, SqliteReadingError>
+    where
+        T: Send + 'static,
+        F: FnOnce(PoolConnection<Sqlite>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, SqliteReadingError>> + Send + 'static,
+    {
+        match &self.writer {
+            Some(writer) => writer.run(write).await?,
+            None => write(self.pool.acquire().await?).await,
+        }
+    }
+}
+
+#[async_trait]
+impl ReadingRegistry for SqliteReadingRegistry {
+    type Error = SqliteReadingError;
+
+    async fn store(&self, reading: SensorReading) -> Result<(), Self::Error> {
+        self.write(|mut conn| async move {
+            let mut query_builder =
+                QueryBuilder::new(format!("INSERT OR REPLACE INTO readings ({COLUMNS}) "));
+            push_values(&mut query_builder, vec![reading])?;
+            query_builder.build().execute(&mut *conn).await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get(&self, id: ReadingId) -> Result<Option<SensorReading>, Self::Error> {
+        let row = sqlx::query(&format!("SELECT {COLUMNS} FROM readings WHERE id = ?"))
+            .bind(id.0.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(map_row_to_reading).transpose()
+    }
+
+    async fn batch_store(
+        &self,
+        readings: Vec<SensorReading>,
+    ) -> Result<Vec<ReadingId>, Self::Error> {
+        self.write(|mut conn| async move {
+            let mut tx = conn.begin().await?;
+            let stored = insert_new(&mut tx, readings).await?;
+            tx.commit().await?;
+
+            Ok(stored)
+        })
+        .await
+    }
+
+    async fn latest_per_sensor(&self, device: DeviceId) -> Result<Vec<SensorReading>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT {COLUMNS} FROM (
+                SELECT *, ROW_NUMBER() OVER (
+                    PARTITION BY sensor_id ORDER BY timestamp DESC, id DESC
+                ) AS position
+                FROM readings WHERE device_id = ?
+            )
+            WHERE position = 1
+            ORDER BY sensor_id
+            "#
+        ))
+        .bind(device.0.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_reading).collect()
+    }
+
+    async fn latest_matching(
+        &self,
+        filter: ReadingFilter,
+    ) -> Result<Vec<SensorReading>, Self::Error> {
+        let query_builder = QueryBuilder::new(format!(
+            r#"
+            SELECT {COLUMNS} FROM (
+                SELECT *, ROW_NUMBER() OVER (
+                    PARTITION BY device_id, sensor_id ORDER BY timestamp DESC, id DESC
+                ) AS position
+                FROM readings WHERE 1=1"#
+        ));
+        let mut query_builder = filter_readings(query_builder, filter)?;
+        query_builder.push(
+            r#"
+            )
+            WHERE position = 1
+            ORDER BY device_id, sensor_id
+            "#,
+        );
+
+        let rows = query_builder.build().fetch_all(&self.pool).await?;
+
+        rows.into_iter().map(map_row_to_reading).collect()
+    }
+
+    async fn data_quality(
+        &self,
+        device: DeviceId,
+        window: QualityWindow,
+    ) -> Result<Vec<SensorQuality>, Self::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT sensor_id, timestamp, confidence FROM readings
+            WHERE device_id = ? AND timestamp BETWEEN ? AND ?
+            ORDER BY sensor_id, timestamp
+            "#,
+        )
+        .bind(device.0.to_string())
+        .bind(to_nanos(window.from)?)
+        .bind(to_nanos(window.to)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut sensors: Vec<(SensorId, Vec<(jiff::Timestamp, Percentage)>)> = Vec::new();
+        for row in rows {
+            let sensor_id = SensorId(parse_ulid(row.try_get("sensor_id")?)?);
+            let sample = (
+                from_nanos(row.try_get("timestamp")?)?,
+                Percentage(row.try_get("confidence")?),
+            );
+
+            match sensors.last_mut() {
+                Some((last, samples)) if *last == sensor_id => samples.push(sample),
+                _ => sensors.push((sensor_id, vec![sample])),
+            }
+        }
+
+        Ok(sensors
+            .into_iter()
+            .map(|(sensor_id, samples)| SensorQuality::compute(sensor_id, &window, samples))
+            .collect())
+    }
+
+    async fn purge(&self, before: jiff::Timestamp, limit: usize) -> Result<usize, Self::Error> {
+        let before = to_nanos(before)?;
+        self.write(move |mut conn| async move {
+            let result = sqlx::query(
+                r#"
+                DELETE FROM readings WHERE id IN (
+                    SELECT id FROM readings WHERE timestamp <= ?
+                    ORDER BY timestamp, id LIMIT ?
+                )
+                "#,
+            )
+            .bind(before)
+            .bind(limit as i64)
+            .execute(&mut *conn)
+            .await?;
+
+            Ok(result.rows_affected() as usize)
+        })
+        .await
+    }
+
+    async fn set_quality(
+        &self,
+        statuses: Vec<(ReadingId, QualityStatus)>,
+    ) -> Result<(), Self::Error> {
+        self.write(|mut conn| async move {
+            let mut tx = conn.begin().await?;
+            for (id, status) in statuses {
+                sqlx::query("UPDATE readings SET quality = ? WHERE id = ?")
+                    .bind(status.as_str())
+                    .bind(id.0.to_string())
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn count(&self, filter: Option<ReadingFilter>) -> Result<usize, Self::Error> {
+        let query_builder = QueryBuilder::new("SELECT COUNT(*) FROM readings WHERE 1=1");
+        let mut query_builder = filter_readings(query_builder, filter.unwrap_or_default())?;
+
+        let count: i64 = query_builder
+            .build()
+            .fetch_one(&self.pool)
+            .await?
+            .try_get(0)?;
+
+        Ok(count as usize)
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<ReadingFilter, ReadingSortBy>,
+    ) -> Result<Vec<SensorReading>, Self::Error> {
+        let query_builder = QueryBuilder::new(format!("SELECT {COLUMNS} FROM readings WHERE 1=1"));
+        let mut query_builder = filter_readings(query_builder, options.filter)?;
+
+        let order = match options.sort_order {
+            SortOrder::Asc => " ASC",
+            SortOrder::Desc => " DESC",
+        };
+        let column = match options.sort_by {
+            ReadingSortBy::Timestamp => "timestamp",
+            ReadingSortBy::Confidence => "confidence",
+        };
+
+        if let Pagination::Cursor {
+            after: Some(after), ..
+        } = &options.pagination
+        {
+            query_builder.push(" AND ");
+            push_after(
+                &mut query_builder,
+                column,
+                &options.sort_order,
+                after,
+                cursor_nanos,
+            );
+        }
+
+        query_builder.push(format!(" ORDER BY {column}{order}, id{order}"));
+
+        match options.pagination {
+            Pagination::Offset { offset, limit } => {
+                query_builder.push(" LIMIT ");
+                query_builder.push_bind(limit as i64);
+                query_builder.push(" OFFSET ");
+                query_builder.push_bind(offset as i64);
+            }
+            Pagination::Cursor { limit, .. } => {
+                query_builder.push(" LIMIT ");
+                query_builder.push_bind(limit as i64);
+            }
+        }
+
+        let rows = query_builder.build().fetch_all(&self.pool).await?;
+
+        rows.into_iter().map(map_row_to_reading).collect()
+    }
+}
+
+/// Append `VALUES` for `readings`.
+/// Insert the readings whose ids aren't held yet on `conn`, returning their
+/// ids. Part of whatever transaction `conn` is in.
+pub(super) async fn insert_new(
+    conn: &mut SqliteConnection,
+    readings: Vec<SensorReading>,
+) -> Result<Vec<ReadingId>, SqliteReadingError> {
+    let mut stored = Vec::with_capacity(readings.len());
+
+    let mut readings = readings.into_iter().peekable();
+    while readings.peek().is_some() {
+        let chunk: Vec<SensorReading> = readings.by_ref().take(INSERT_CHUNK).collect();
+
+        let mut query_builder = QueryBuilder::new(format!("INSERT INTO readings ({COLUMNS}) "));
+        push_values(&mut query_builder, chunk)?;
+        query_builder.push(" ON CONFLICT(id) DO NOTHING RETURNING id");
+
+        for row in query_builder.build().fetch_all(&mut *conn).await? {
+            stored.push(ReadingId(parse_ulid(row.try_get("id")?)?));
+        }
+    }
+
+    Ok(stored)
+}
+
+fn push_values(
+    query_builder: &mut QueryBuilder<Sqlite>,
+    readings: Vec<SensorReading>,
+) -> Result<(), SqliteReadingError> {
+    let readings = readings
+        .into_iter()
+        .map(|reading| Ok((to_nanos(reading.timestamp)?, reading)))
+        .collect::<Result<Vec<_>, SqliteReadingError>>()?;
+
+    query_builder.push_values(readings, |mut row, (timestamp, reading)| {
+        let (metric, value) = disect_metric(&reading.metric);
+        row.push_bind(reading.id.0.to_string())
+            .push_bind(reading.device_id.0.to_string())
+            .push_bind(reading.dispatcher_id.0.to_string())
+            .push_bind(reading.sensor_id.0.to_string())
+            .push_bind(metric)
+            .push_bind(value)
+            .push_bind(reading.location.0 as i64)
+            .push_bind(i64::from(reading.confidence.0))
+            .push_bind(timestamp);
+    });
+
+    Ok(())
+}
+
+fn filter_readings(
+    mut query_builder: QueryBuilder<Sqlite>,
+    filter: ReadingFilter,
+) -> Result<QueryBuilder<Sqlite>, SqliteReadingError> {
+    let ids = [
+        (
+            "device_id",
+            filter
+                .device_ids
+                .map(|ids| ids.into_iter().map(|id| id.0).collect()),
+        ),
+        (
+            "sensor_id",
+            filter
+                .sensor_ids
+                .map(|ids| ids.into_iter().map(|id| id.0).collect()),
+        ),
+        (
+            "dispatcher_id",
+            filter
+                .dispatcher_ids
+                .map(|ids| ids.into_iter().map(|id| id.0).collect()),
+        ),
+    ];
+    for (column, ids) in ids {
+        let ids: Option<Vec<Ulid>> = ids;
+        if let Some(ids) = ids
+            && !ids.is_empty()
+        {
+            query_builder.push(format!(" AND {column} IN ("));
+            let mut separated = query_builder.separated(", ");
+            for id in ids {
+                separated.push_bind(id.to_string());
+            }
+            separated.push_unseparated(")");
+        }
+    }
+
+    if let Some(kinds) = filter.metric_kinds
+        && !kinds.is_empty()
+    {
+        query_builder.push(" AND metric IN (");
+        let mut separated = query_builder.separated(", ");
+        for kind in kinds {
+            separated.push_bind(kind as i32);
+        }
+        separated.push_unseparated(")");
+    }
+
+    if let Some(locations) = filter.locations
+        && !locations.is_empty()
+    {
+        query_builder.push(" AND location IN (");
+        let mut separated = query_builder.separated(", ");
+        for location in locations {
+            separated.push_bind(location.0 as i64);
+        }
+        separated.push_unseparated(")");
+    }
+
+    if let Some(cells) = filter.within
+        && !cells.is_empty()
+    {
+        query_builder.push(" AND (");
+        let ranges: Vec<_> = cells
+            .into_iter()
+            .flat_map(region::descendant_ranges)
+            .collect();
+        if ranges.is_empty() {
+            query_builder.push("0");
+        }
+        let mut separated = query_builder.separated(" OR ");
+        for range in ranges {
+            separated
+                .push("location BETWEEN ")
+                .push_bind_unseparated(*range.start() as i64)
+                .push_unseparated(" AND ")
+                .push_bind_unseparated(*range.end() as i64);
+        }
+        separated.push_unseparated(")");
+    }
+
+    if let Some(after) = filter.after {
+        query_builder.push(" AND timestamp >= ");
+        query_builder.push_bind(to_nanos(after)?);
+    }
+
+    if let Some(before) = filter.before {
+        query_builder.push(" AND timestamp <= ");
+        query_builder.push_bind(to_nanos(before)?);
+    }
+
+    if let Some(confidence) = filter.confidence {
+        query_builder.push(" AND confidence BETWEEN ");
+        query_builder.push_bind(i64::from(*confidence.start()));
+        query_builder.push(" AND ");
+        query_builder.push_bind(i64::from(*confidence.end()));
+    }
+
+    if let Some(range) = filter.value_range {
+        if range.start().is_finite() {
+            query_builder.push(" AND value >= ");
+            query_builder.push_bind(*range.start());
+        }
+        if range.end().is_finite() {
+            query_builder.push(" AND value <= ");
+            query_builder.push_bind(*range.end());
+        }
+    }
+
+    if let Some(statuses) = filter.quality
+        && !statuses.is_empty()
+    {
+        query_builder.push(" AND quality IN (");
+        let mut separated = query_builder.separated(", ");
+        for status in statuses {
+            separated.push_bind(status.as_str());
+        }
+        separated.push_unseparated(")");
+    }
+
+    Ok(query_builder)
+}
+
+fn to_nanos(timestamp: jiff::Timestamp) -> Result<i64, SqliteReadingError> {
+    i64::try_from(timestamp.as_nanosecond())
+        .map_err(|_| SqliteReadingError::TimestampOutOfRange(timestamp))
+}
+
+/// Nanoseconds of a cursor's timestamp, saturating those beyond what is
+/// stored.
+fn cursor_nanos(timestamp: jiff::Timestamp) -> i64 {
+    i64::try_from(timestamp.as_nanosecond()).unwrap_or(i64::MAX)
+}
+
+fn from_nanos(nanos: i64) -> Result<jiff::Timestamp, SqliteReadingError> {
+    jiff::Timestamp::from_nanosecond(i128::from(nanos))
+        .map_err(|_| SqliteReadingError::InvalidTimestamp(nanos))
+}
+
+fn parse_ulid(s: String) -> Result<Ulid, SqliteReadingError> {
+    Ulid::from_str(&s).map_err(|_| SqliteReadingError::InvalidUlid(s))
+}
+
+fn disect_metric(metric: &SensorMetric) -> (i32, f64) {
+    match metric {
+        SensorMetric::SoilMoisture { value } => (SensorKind::SoilMoisture as i32, value.0 as f64),
+        SensorMetric::SoilTemp { value } => (SensorKind::SoilTemp as i32, value.into_inner()),
+        SensorMetric::AirTemp { value } => (SensorKind::AirTemp as i32, value.into_inner()),
+        SensorMetric::Humidity { value } => (SensorKind::Humidity as i32, value.0 as f64),
+        SensorMetric::Rainfall { value } => (SensorKind::Rainfall as i32, value.into_inner()),
+    }
+}
+
+fn map_row_to_reading(row: SqliteRow) -> Result<SensorReading, SqliteReadingError> {
+    let metric: i32 = row.try_get("metric")?;
+    let value: f64 = row.try_get("value")?;
+    let not_nan =
+        |value: f64| NotNan::new(value).map_err(|_| SqliteReadingError::InvalidValue(value));
+
+    let metric = match metric {
+        0 => SensorMetric::SoilMoisture {
+            value: Percentage(value as u8),
+        },
+        1 => SensorMetric::SoilTemp {
+            value: not_nan(value)?,
+        },
+        2 => SensorMetric::AirTemp {
+            value: not_nan(value)?,
+        },
+        3 => SensorMetric::Humidity {
+            value: Percentage(value as u8),
+        },
+        4 => SensorMetric::Rainfall {
+            value: not_nan(value)?,
+        },
+        other => return Err(SqliteReadingError::InvalidSensorKind(other)),
+    };
+
+    Ok(SensorReading {
+        id: ReadingId(parse_ulid(row.try_get("id")?)?),
+        device_id: DeviceId(parse_ulid(row.try_get("device_id")?)?),
+        dispatcher_id: DispatcherId(parse_ulid(row.try_get("dispatcher_id")?)?),
+        metric,
+        location: H3Cell(row.try_get::<i64, _>("location")? as u64),
+        confidence: Percentage(row.try_get("confidence")?),
+        timestamp: from_nanos(row.try_get("timestamp")?)?,
+        sensor_id: SensorId(parse_ulid(row.try_get("sensor_id")?)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        DeviceId, H3Cell, Percentage, ReadingId, SensorKind, SensorMetric, SensorReading,
+    };
+    use jiff::{SignedDuration, Timestamp};
+    use ordered_float::NotNan;
+    use ulid::Ulid;
+
+    use super::SqliteReadingRegistry;
+    use crate::config::SqlitePoolConfig;
+    use crate::fixtures;
+    use crate::quality::QualityWindow;
+    use crate::registry::{
+        ReadingRegistry,
+        filter::{Pagination, QueryOptions, ReadingFilter, ReadingSortBy, SortOrder},
+        sqlite::connect,
+    };
+    use crate::validation::QualityStatus;
+
+    fn reading(
+        device_id: DeviceId,
+        metric: SensorMetric,
+        second: i64,
+        confidence: u8,
+    ) -> SensorReading {
+        SensorReading {
+            device_id,
+            metric,
+            confidence: Percentage(confidence),
+            timestamp: Timestamp::from_second(second).unwrap(),
+            ..fixtures::reading()
+        }
+    }
+
+    fn moisture(value: u8) -> SensorMetric {
+        SensorMetric::SoilMoisture {
+            value: Percentage(value),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_store_round_trips_and_skips_existing() {
+        let reg = SqliteReadingRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let precise = SensorReading {
+            timestamp: Timestamp::from_nanosecond(1_700_000_000_123_456_789).unwrap(),
+            metric: SensorMetric::AirTemp {
+                value: NotNan::new(21.5).unwrap(),
+            },
+            ..reading(device, moisture(0), 0, 90)
+        };
+        let other = reading(device, moisture(41), 20, 90);
+
+        let stored = reg.batch_store(vec![precise.clone()]).await.unwrap();
+        assert_eq!(stored, vec![precise.id]);
+        assert_eq!(reg.get(precise.id).await.unwrap(), Some(precise.clone()));
+
+        let replay = SensorReading {
+            confidence: Percentage(10),
+            ..precise.clone()
+        };
+        let stored = reg.batch_store(vec![replay, other.clone()]).await.unwrap();
+        assert_eq!(stored, vec![other.id]);
+        assert_eq!(reg.get(precise.id).await.unwrap(), Some(precise));
+        assert_eq!(reg.count(None).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_batches_take_turns_through_the_writer() {
+        let dir = std::env::temp_dir().join(format!("ersha-readings-{}", Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = format!("{}?mode=rwc", dir.join("readings.db").display());
+        let config = SqlitePoolConfig {
+            busy_timeout_ms: 0,
+            ..SqlitePoolConfig::default()
+        };
+        let pool = connect(&path, &config).await.unwrap();
+        let reg = SqliteReadingRegistry::with_pool(pool)
+            .await
+            .unwrap()
+            .with_writer(4);
+
+        // Without waiting on locks, these only all succeed one at a time.
+        let uploads = (0..16).map(|_| {
+            let reg = reg.clone();
+            tokio::spawn(async move {
+                let device = DeviceId(Ulid::new());
+                let batch = (0..100)
+                    .map(|second| reading(device, moisture(40), second, 90))
+                    .collect();
+                reg.batch_store(batch).await
+            })
+        });
+        for upload in uploads.collect::<Vec<_>>() {
+            assert_eq!(upload.await.unwrap().unwrap().len(), 100);
+        }
+        assert_eq!(reg.count(None).await.unwrap(), 1600);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filters() {
+        let reg = SqliteReadingRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let other = DeviceId(Ulid::new());
+        let elsewhere = SensorReading {
+            location: H3Cell(0x8a2a1072b4a7fff),
+            ..reading(other, moisture(42), 40, 99)
+        };
+
+        reg.batch_store(vec![
+            reading(device, moisture(40), 10, 90),
+            reading(device, moisture(41), 20, 50),
+            reading(
+                device,
+                SensorMetric::AirTemp {
+                    value: NotNan::new(21.5).unwrap(),
+                },
+                30,
+                95,
+            ),
+            elsewhere,
+        ])
+        .await
+        .unwrap();
+
+        let by_device = ReadingFilter::builder().device_ids([device]).build();
+        assert_eq!(reg.count(Some(by_device)).await.unwrap(), 3);
+
+        let by_kind = ReadingFilter::builder()
+            .device_ids([device])
+            .metric_kinds([SensorKind::SoilMoisture])
+            .build();
+        assert_eq!(reg.count(Some(by_kind)).await.unwrap(), 2);
+
+        let by_time = ReadingFilter::builder()
+            .after(Timestamp::from_second(15).unwrap())
+            .before(Timestamp::from_second(35).unwrap())
+            .build();
+        assert_eq!(reg.count(Some(by_time)).await.unwrap(), 2);
+
+        let by_confidence = ReadingFilter::builder().confidence(80..=95).build();
+        assert_eq!(reg.count(Some(by_confidence)).await.unwrap(), 2);
+
+        let dry = ReadingFilter::builder()
+            .metric_kinds([SensorKind::SoilMoisture])
+            .value_range(f64::NEG_INFINITY..=41.0)
+            .build();
+        assert_eq!(reg.count(Some(dry)).await.unwrap(), 2);
+
+        let warm = ReadingFilter::builder().value_range(20.0..=25.0).build();
+        assert_eq!(reg.count(Some(warm)).await.unwrap(), 1);
+
+        let within = ReadingFilter::builder()
+            .within([H3Cell(0x892a1072b5bffff)])
+            .build();
+        assert_eq!(reg.count(Some(within)).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_pagination_and_purge() {
+        let reg = SqliteReadingRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let readings: Vec<_> = (0..5)
+            .map(|i| reading(device, moisture(40), i, 90))
+            .collect();
+        let ids: Vec<_> = readings.iter().map(|r| r.id).collect();
+        reg.batch_store(readings).await.unwrap();
+
+        let page = |after| {
+            reg.list(QueryOptions {
+                filter: ReadingFilter::default(),
+                sort_by: ReadingSortBy::Timestamp,
+                sort_order: SortOrder::Desc,
+                pagination: Pagination::Cursor { after, limit: 2 },
+            })
+        };
+        let first = page(None).await.unwrap();
+        assert_eq!(
+            first.iter().map(|r| r.id).collect::<Vec<_>>(),
+            [ids[4], ids[3]]
+        );
+        let second = page(Some(ReadingSortBy::Timestamp.cursor(&first[1])))
+            .await
+            .unwrap();
+        assert_eq!(
+            second.iter().map(|r| r.id).collect::<Vec<_>>(),
+            [ids[2], ids[1]]
+        );
+
+        let purged = reg
+            .purge(Timestamp::from_second(3).unwrap(), 3)
+            .await
+            .unwrap();
+        assert_eq!(purged, 3);
+        assert_eq!(reg.get(ids[2]).await.unwrap(), None);
+        assert!(reg.get(ids[3]).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_latest_matching() {
+        let reg = SqliteReadingRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let old = reading(device, moisture(40), 10, 90);
+        let new = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(20).unwrap(),
+            ..old.clone()
+        };
+        // Past the window, so the sensor's latest within it is `new`.
+        let later = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(90).unwrap(),
+            ..old.clone()
+        };
+        // The same sensor id on another device is another sensor.
+        let elsewhere = SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            ..old.clone()
+        };
+        let temperature = reading(
+            device,
+            SensorMetric::AirTemp {
+                value: NotNan::new(21.5).unwrap(),
+            },
+            15,
+            90,
+        );
+        reg.batch_store(vec![
+            old,
+            new.clone(),
+            later,
+            elsewhere.clone(),
+            temperature,
+        ])
+        .await
+        .unwrap();
+
+        let filter = ReadingFilter::builder()
+            .metric_kinds([SensorKind::SoilMoisture])
+            .before(Timestamp::from_second(60).unwrap())
+            .build();
+        let mut expected = vec![new, elsewhere];
+        expected.sort_by_key(|r| (r.device_id.0, r.sensor_id.0));
+        assert_eq!(reg.latest_matching(filter).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_latest_per_sensor_and_data_quality() {
+        let reg = SqliteReadingRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let old = reading(device, moisture(40), 0, 90);
+        let new = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(60).unwrap(),
+            confidence: Percentage(30),
+            ..old.clone()
+        };
+        let other_sensor = reading(device, moisture(41), 30, 90);
+        reg.batch_store(vec![
+            old.clone(),
+            new.clone(),
+            other_sensor.clone(),
+            reading(DeviceId(Ulid::new()), moisture(42), 30, 90),
+        ])
+        .await
+        .unwrap();
+
+        let mut expected = vec![new.clone(), other_sensor.clone()];
+        expected.sort_by_key(|r| r.sensor_id.0);
+        assert_eq!(reg.latest_per_sensor(device).await.unwrap(), expected);
+
+        let window = QualityWindow::new(
+            Timestamp::from_second(0).unwrap(),
+            Timestamp::from_second(120).unwrap(),
+            SignedDuration::from_secs(60),
+        );
+        let quality = reg.data_quality(device, window).await.unwrap();
+        assert_eq!(
+            quality.iter().map(|q| q.sensor_id).collect::<Vec<_>>(),
+            expected.iter().map(|r| r.sensor_id).collect::<Vec<_>>()
+        );
+        let old = quality
+            .iter()
+            .find(|q| q.sensor_id == old.sensor_id)
+            .unwrap();
+        assert_eq!((old.expected, old.received), (2, 2));
+        assert_eq!(old.bad_percent, 50.0);
+    }
+
+    #[tokio::test]
+    async fn test_quality_is_set_and_filtered() {
+        let reg = SqliteReadingRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let spike = reading(device, moisture(99), 10, 90);
+        let normal = reading(device, moisture(40), 20, 90);
+        reg.batch_store(vec![spike.clone(), normal.clone()])
+            .await
+            .unwrap();
+
+        reg.set_quality(vec![
+            (spike.id, QualityStatus::OutOfRange),
+            (ReadingId(Ulid::new()), QualityStatus::RateExceeded),
+        ])
+        .await
+        .unwrap();
+
+        let flagged = ReadingFilter::builder()
+            .quality([QualityStatus::OutOfRange, QualityStatus::RateExceeded])
+            .build();
+        let listed = reg
+            .list(QueryOptions {
+                filter: flagged,
+                sort_by: ReadingSortBy::Timestamp,
+                sort_order: SortOrder::Asc,
+                pagination: Pagination::Offset {
+                    offset: 0,
+                    limit: 10,
+                },
+            })
+            .await
+            .unwrap();
+        assert_eq!(listed, vec![spike]);
+        let good = ReadingFilter::builder()
+            .quality([QualityStatus::Good])
+            .build();
+        assert_eq!(reg.count(Some(good)).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,196 @@
+//! The version of a database's schema, against the migrations built into
+//! prime.
+//!
+//! Registries apply pending migrations as they open, but a database already
+//! migrated by a newer prime can't be opened safely by an older one. Startup
+//! checks the schema first, so that a rolled back prime stops with an error
+//! saying so rather than running against tables it doesn't know.
+
+use std::collections::HashMap;
+
+use sqlx::{Row, SqlitePool, migrate::Migrator};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error(
+        "database schema is at version {database}, but this ersha-prime only knows up to \
+         {binary}: upgrade ersha-prime, or restore a backup taken before the upgrade"
+    )]
+    TooNew { database: i64, binary: i64 },
+    #[error("migration {0} applied to the database differs from the one in this ersha-prime")]
+    Modified(i64),
+    #[error("migration {0} failed part way, and the database needs repairing by hand")]
+    Dirty(i64),
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+}
+
+/// A migration applied to the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub description: String,
+    /// As SQLite recorded it, in UTC
+    pub installed_on: String,
+    /// Whether it completed
+    pub success: bool,
+    /// Whether this prime has it, with the same contents
+    pub known: bool,
+}
+
+/// A migration this prime has that the database hasn't applied yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingMigration {
+    pub version: i64,
+    pub description: String,
+}
+
+/// Where a database's schema stands against this prime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaStatus {
+    pub applied: Vec<AppliedMigration>,
+    pub pending: Vec<PendingMigration>,
+}
+
+impl SchemaStatus {
+    /// The latest migration applied to the database, if any.
+    pub fn database_version(&self) -> Option<i64> {
+        self.applied.iter().map(|m| m.version).max()
+    }
+
+    /// The latest migration built into this prime.
+    pub fn binary_version() -> i64 {
+        MIGRATOR.iter().map(|m| m.version).max().unwrap_or_default()
+    }
+
+    /// Fails unless this prime can run on the database, applying the
+    /// pending migrations first.
+    pub fn check(&self) -> Result<(), SchemaError> {
+        if let Some(dirty) = self.applied.iter().find(|m| !m.success) {
+            return Err(SchemaError::Dirty(dirty.version));
+        }
+
+        let binary = Self::binary_version();
+        if let Some(database) = self.database_version().filter(|v| *v > binary) {
+            return Err(SchemaError::TooNew { database, binary });
+        }
+        if let Some(modified) = self.applied.iter().find(|m| !m.known) {
+            return Err(SchemaError::Modified(modified.version));
+        }
+
+        Ok(())
+    }
+}
+
+/// Compare the migrations applied to the database behind `pool` with those
+/// built into this prime.
+pub async fn schema_status(pool: &SqlitePool) -> Result<SchemaStatus, SchemaError> {
+    let tracked: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master \
+         WHERE type = 'table' AND name = '_sqlx_migrations')",
+    )
+    .fetch_one(pool)
+    .await?;
+    let rows = if tracked {
+        sqlx::query(
+            "SELECT version, description, installed_on, success, checksum \
+             FROM _sqlx_migrations ORDER BY version",
+        )
+        .fetch_all(pool)
+        .await?
+    } else {
+        Vec::new()
+    };
+
+    let known: HashMap<i64, &[u8]> = MIGRATOR
+        .iter()
+        .map(|m| (m.version, m.checksum.as_ref()))
+        .collect();
+    let mut applied = Vec::with_capacity(rows.len());
+    for row in rows {
+        let version: i64 = row.try_get("version")?;
+        let checksum: Vec<u8> = row.try_get("checksum")?;
+        applied.push(AppliedMigration {
+            version,
+            description: row.try_get("description")?,
+            installed_on: row.try_get("installed_on")?,
+            success: row.try_get("success")?,
+            known: known.get(&version) == Some(&checksum.as_slice()),
+        });
+    }
+
+    let pending = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .filter(|m| applied.iter().all(|a| a.version != m.version))
+        .map(|m| PendingMigration {
+            version: m.version,
+            description: m.description.to_string(),
+        })
+        .collect();
+
+    Ok(SchemaStatus { applied, pending })
+}
+
+/// Check the database behind `pool` can be run on by this prime, then apply
+/// the pending migrations. Returns those applied.
+pub async fn migrate(pool: &SqlitePool) -> Result<Vec<PendingMigration>, SchemaError> {
+    let status = schema_status(pool).await?;
+    status.check()?;
+    MIGRATOR.run(pool).await?;
+
+    Ok(status.pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::{SchemaError, SchemaStatus, migrate, schema_status};
+
+    #[tokio::test]
+    async fn test_databases_newer_than_prime_are_refused() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        let fresh = schema_status(&pool).await.unwrap();
+        assert!(fresh.applied.is_empty());
+        assert_eq!(
+            fresh.pending.last().unwrap().version,
+            SchemaStatus::binary_version()
+        );
+
+        let applied = migrate(&pool).await.unwrap();
+        assert_eq!(applied, fresh.pending);
+        let current = schema_status(&pool).await.unwrap();
+        assert!(current.pending.is_empty());
+        assert_eq!(
+            current.database_version(),
+            Some(SchemaStatus::binary_version())
+        );
+        assert!(migrate(&pool).await.unwrap().is_empty());
+
+        // As left by a later prime.
+        let later = SchemaStatus::binary_version() + 1;
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations \
+             (version, description, success, checksum, execution_time) \
+             VALUES (?, 'later', TRUE, x'00', 0)",
+        )
+        .bind(later)
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(matches!(
+            migrate(&pool).await,
+            Err(SchemaError::TooNew { database, .. }) if database == later
+        ));
+    }
+}
@@ -0,0 +1,604 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{DeviceErrorCode, DeviceId, DeviceStatus, DispatcherId, Percentage, StatusId};
+use sqlx::{
+    Connection, QueryBuilder, Row, Sqlite, SqliteConnection, SqlitePool, migrate::Migrator,
+    pool::PoolConnection, sqlite::SqlitePoolOptions, sqlite::SqliteRow,
+};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::registry::{
+    DeviceStatusRegistry, RegistryError,
+    filter::{Pagination, QueryOptions, SortOrder, StatusFilter, StatusSortBy},
+};
+
+use super::push_after;
+use super::reading::SqliteReadingRegistry;
+use super::writer::Writer;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Statuses per `INSERT`, well below SQLite's limit on bound parameters.
+const INSERT_CHUNK: usize = 500;
+
+const COLUMNS: &str = "id, device_id, dispatcher_id, battery_percent, uptime_seconds, \
+    signal_rssi, error_codes, errors, sensor_statuses, timestamp";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteDeviceStatusError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("timestamp out of range: {0}")]
+    TimestampOutOfRange(jiff::Timestamp),
+}
+
+impl From<SqliteDeviceStatusError> for RegistryError {
+    fn from(error: SqliteDeviceStatusError) -> Self {
+        match error {
+            SqliteDeviceStatusError::Sqlx(e) => e.into(),
+            e @ SqliteDeviceStatusError::TimestampOutOfRange(..) => {
+                RegistryError::InvalidInput(e.to_string())
+            }
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteDeviceStatusRegistry {
+    pool: SqlitePool,
+    /// Runs writes in turn when set, rather than on any pool connection
+    writer: Option<Writer>,
+}
+
+impl SqliteDeviceStatusRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteDeviceStatusError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteDeviceStatusError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool, writer: None })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteDeviceStatusError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool, writer: None })
+    }
+
+    /// Queue writes for a single writer task, up to `queue` of them. A
+    /// `queue` of 0 leaves writes going straight to the pool.
+    pub fn with_writer(self, queue: usize) -> Self {
+        let writer = (queue > 0).then(|| Writer::spawn(self.pool.clone(), queue));
+
+        Self { writer, ..self }
+    }
+
+    /// Queue writes behind those of `readings`, so the statuses and readings
+    /// of an upload take turns rather than contend for the database lock.
+    pub fn sharing_writer(self, readings: &SqliteReadingRegistry) -> Self {
+        Self {
+            writer: readings.writer(),
+            ..self
+        }
+    }
+
+    /// Run `write` through the writer task, if there is one.
+    async fn write<T, F, Fut>(&self, write: F) -> Result<T, SqliteDeviceStatusError>
+    where
+        T: Send + 'static,
+        F: FnOnce(PoolConnection<Sqlite>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, SqliteDeviceStatusError>> + Send + 'static,
+    {
+        match &self.writer {
+            Some(writer) => writer.run(write).await?,
+            None => write(self.pool.acquire().await?).await,
+        }
+    }
+}
+
+#[async_trait]
+impl DeviceStatusRegistry for SqliteDeviceStatusRegistry {
+    type Error = SqliteDeviceStatusError;
+
+    async fn store(&self, status: DeviceStatus) -> Result<(), Self::Error> {
+        self.write(|mut conn| async move {
+            let mut query_builder = QueryBuilder::new(format!(
+                "INSERT OR REPLACE INTO device_statuses ({COLUMNS}) "
+            ));
+            push_values(&mut query_builder, vec![status])?;
+            query_builder.build().execute(&mut *conn).await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get(&self, id: StatusId) -> Result<Option<DeviceStatus>, Self::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {COLUMNS} FROM device_statuses WHERE id = ?"
+        ))
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(map_row_to_status).transpose()
+    }
+
+    async fn batch_store(&self, statuses: Vec<DeviceStatus>) -> Result<Vec<StatusId>, Self::Error> {
+        self.write(|mut conn| async move {
+            let mut tx = conn.begin().await?;
+            let stored = insert_new(&mut tx, statuses).await?;
+            tx.commit().await?;
+
+            Ok(stored)
+        })
+        .await
+    }
+
+    async fn latest(&self, device: DeviceId) -> Result<Option<DeviceStatus>, Self::Error> {
+        // The inner lookup is answered from the device/timestamp index alone.
+        let row = sqlx::query(&format!(
+            r#"
+            SELECT {COLUMNS} FROM device_statuses WHERE id = (
+                SELECT id FROM device_statuses WHERE device_id = ?
+                ORDER BY timestamp DESC, id DESC LIMIT 1
+            )
+            "#
+        ))
+        .bind(device.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(map_row_to_status).transpose()
+    }
+
+    async fn purge(&self, before: jiff::Timestamp, limit: usize) -> Result<usize, Self::Error> {
+        let before = to_nanos(before)?;
+        self.write(move |mut conn| async move {
+            let result = sqlx::query(
+                r#"
+                DELETE FROM device_statuses WHERE id IN (
+                    SELECT id FROM device_statuses WHERE timestamp <= ?
+                    ORDER BY timestamp, id LIMIT ?
+                )
+                "#,
+            )
+            .bind(before)
+            .bind(limit as i64)
+            .execute(&mut *conn)
+            .await?;
+
+            Ok(result.rows_affected() as usize)
+        })
+        .await
+    }
+
+    async fn count(&self, filter: Option<StatusFilter>) -> Result<usize, Self::Error> {
+        let query_builder = QueryBuilder::new("SELECT COUNT(*) FROM device_statuses WHERE 1=1");
+        let mut query_builder = filter_statuses(query_builder, filter.unwrap_or_default())?;
+
+        let count: i64 = query_builder
+            .build()
+            .fetch_one(&self.pool)
+            .await?
+            .try_get(0)?;
+
+        Ok(count as usize)
+    }
+
+    async fn list(
+        &self,
+        options: QueryOptions<StatusFilter, StatusSortBy>,
+    ) -> Result<Vec<DeviceStatus>, Self::Error> {
+        let query_builder =
+            QueryBuilder::new(format!("SELECT {COLUMNS} FROM device_statuses WHERE 1=1"));
+        let mut query_builder = filter_statuses(query_builder, options.filter)?;
+
+        let order = match options.sort_order {
+            SortOrder::Asc => " ASC",
+            SortOrder::Desc => " DESC",
+        };
+        let column = match options.sort_by {
+            StatusSortBy::Timestamp => "timestamp",
+        };
+
+        if let Pagination::Cursor {
+            after: Some(after), ..
+        } = &options.pagination
+        {
+            query_builder.push(" AND ");
+            push_after(
+                &mut query_builder,
+                column,
+                &options.sort_order,
+                after,
+                cursor_nanos,
+            );
+        }
+
+        query_builder.push(format!(" ORDER BY {column}{order}, id{order}"));
+
+        match options.pagination {
+            Pagination::Offset { offset, limit } => {
+                query_builder.push(" LIMIT ");
+                query_builder.push_bind(limit as i64);
+                query_builder.push(" OFFSET ");
+                query_builder.push_bind(offset as i64);
+            }
+            Pagination::Cursor { limit, .. } => {
+                query_builder.push(" LIMIT ");
+                query_builder.push_bind(limit as i64);
+            }
+        }
+
+        let rows = query_builder.build().fetch_all(&self.pool).await?;
+
+        rows.into_iter().map(map_row_to_status).collect()
+    }
+}
+
+/// Append `VALUES` for `statuses`.
+/// Insert the statuses whose ids aren't held yet on `conn`, returning their
+/// ids. Part of whatever transaction `conn` is in.
+pub(super) async fn insert_new(
+    conn: &mut SqliteConnection,
+    statuses: Vec<DeviceStatus>,
+) -> Result<Vec<StatusId>, SqliteDeviceStatusError> {
+    let mut stored = Vec::with_capacity(statuses.len());
+
+    let mut statuses = statuses.into_iter().peekable();
+    while statuses.peek().is_some() {
+        let chunk: Vec<DeviceStatus> = statuses.by_ref().take(INSERT_CHUNK).collect();
+
+        let mut query_builder =
+            QueryBuilder::new(format!("INSERT INTO device_statuses ({COLUMNS}) "));
+        push_values(&mut query_builder, chunk)?;
+        query_builder.push(" ON CONFLICT(id) DO NOTHING RETURNING id");
+
+        for row in query_builder.build().fetch_all(&mut *conn).await? {
+            stored.push(StatusId(parse_ulid(row.try_get("id")?)?));
+        }
+    }
+
+    Ok(stored)
+}
+
+fn push_values(
+    query_builder: &mut QueryBuilder<Sqlite>,
+    statuses: Vec<DeviceStatus>,
+) -> Result<(), SqliteDeviceStatusError> {
+    let statuses = statuses
+        .into_iter()
+        .map(|status| {
+            let errors = serde_json::to_string(&status.errors)?;
+            let sensor_statuses = serde_json::to_string(&status.sensor_statuses)?;
+            Ok((to_nanos(status.timestamp)?, errors, sensor_statuses, status))
+        })
+        .collect::<Result<Vec<_>, SqliteDeviceStatusError>>()?;
+
+    query_builder.push_values(
+        statuses,
+        |mut row, (timestamp, errors, sensor_statuses, status)| {
+            let codes = status
+                .errors
+                .iter()
+                .fold(0, |codes, error| codes | code_bit(&error.code));
+            row.push_bind(status.id.0.to_string())
+                .push_bind(status.device_id.0.to_string())
+                .push_bind(status.dispatcher_id.0.to_string())
+                .push_bind(i64::from(status.battery_percent.0))
+                .push_bind(status.uptime_seconds as i64)
+                .push_bind(i64::from(status.signal_rssi))
+                .push_bind(codes)
+                .push_bind(errors)
+                .push_bind(sensor_statuses)
+                .push_bind(timestamp);
+        },
+    );
+
+    Ok(())
+}
+
+fn filter_statuses(
+    mut query_builder: QueryBuilder<Sqlite>,
+    filter: StatusFilter,
+) -> Result<QueryBuilder<Sqlite>, SqliteDeviceStatusError> {
+    let ids = [
+        (
+            "device_id",
+            filter
+                .device_ids
+                .map(|ids| ids.into_iter().map(|id| id.0).collect()),
+        ),
+        (
+            "dispatcher_id",
+            filter
+                .dispatcher_ids
+                .map(|ids| ids.into_iter().map(|id| id.0).collect()),
+        ),
+    ];
+    for (column, ids) in ids {
+        let ids: Option<Vec<Ulid>> = ids;
+        if let Some(ids) = ids
+            && !ids.is_empty()
+        {
+            query_builder.push(format!(" AND {column} IN ("));
+            let mut separated = query_builder.separated(", ");
+            for id in ids {
+                separated.push_bind(id.to_string());
+            }
+            separated.push_unseparated(")");
+        }
+    }
+
+    if let Some(after) = filter.after {
+        query_builder.push(" AND timestamp >= ");
+        query_builder.push_bind(to_nanos(after)?);
+    }
+
+    if let Some(before) = filter.before {
+        query_builder.push(" AND timestamp <= ");
+        query_builder.push_bind(to_nanos(before)?);
+    }
+
+    if let Some(battery) = filter.battery {
+        query_builder.push(" AND battery_percent BETWEEN ");
+        query_builder.push_bind(i64::from(*battery.start()));
+        query_builder.push(" AND ");
+        query_builder.push_bind(i64::from(*battery.end()));
+    }
+
+    // Every error carries a code, so only statuses without errors have none.
+    if let Some(has_errors) = filter.has_errors {
+        query_builder.push(if has_errors {
+            " AND error_codes != 0"
+        } else {
+            " AND error_codes = 0"
+        });
+    }
+
+    if let Some(codes) = filter.error_codes
+        && !codes.is_empty()
+    {
+        let mask = codes.iter().fold(0, |mask, code| mask | code_bit(code));
+        query_builder.push(" AND (error_codes & ");
+        query_builder.push_bind(mask);
+        query_builder.push(") != 0");
+    }
+
+    Ok(query_builder)
+}
+
+/// The bit standing for `code` in the `error_codes` column.
+fn code_bit(code: &DeviceErrorCode) -> i64 {
+    match code {
+        DeviceErrorCode::LowBattery => 1,
+        DeviceErrorCode::SensorFault => 1 << 1,
+        DeviceErrorCode::RadioFault => 1 << 2,
+        DeviceErrorCode::Unknown => 1 << 3,
+    }
+}
+
+fn to_nanos(timestamp: jiff::Timestamp) -> Result<i64, SqliteDeviceStatusError> {
+    i64::try_from(timestamp.as_nanosecond())
+        .map_err(|_| SqliteDeviceStatusError::TimestampOutOfRange(timestamp))
+}
+
+/// Nanoseconds of a cursor's timestamp, saturating those beyond what is
+/// stored.
+fn cursor_nanos(timestamp: jiff::Timestamp) -> i64 {
+    i64::try_from(timestamp.as_nanosecond()).unwrap_or(i64::MAX)
+}
+
+fn from_nanos(nanos: i64) -> Result<jiff::Timestamp, SqliteDeviceStatusError> {
+    jiff::Timestamp::from_nanosecond(i128::from(nanos))
+        .map_err(|_| SqliteDeviceStatusError::InvalidTimestamp(nanos))
+}
+
+fn parse_ulid(s: String) -> Result<Ulid, SqliteDeviceStatusError> {
+    Ulid::from_str(&s).map_err(|_| SqliteDeviceStatusError::InvalidUlid(s))
+}
+
+fn map_row_to_status(row: SqliteRow) -> Result<DeviceStatus, SqliteDeviceStatusError> {
+    let errors: String = row.try_get("errors")?;
+    let sensor_statuses: String = row.try_get("sensor_statuses")?;
+
+    Ok(DeviceStatus {
+        id: StatusId(parse_ulid(row.try_get("id")?)?),
+        device_id: DeviceId(parse_ulid(row.try_get("device_id")?)?),
+        dispatcher_id: DispatcherId(parse_ulid(row.try_get("dispatcher_id")?)?),
+        battery_percent: Percentage(row.try_get("battery_percent")?),
+        uptime_seconds: row.try_get::<i64, _>("uptime_seconds")? as u64,
+        signal_rssi: row.try_get("signal_rssi")?,
+        errors: serde_json::from_str(&errors)?,
+        timestamp: from_nanos(row.try_get("timestamp")?)?,
+        sensor_statuses: serde_json::from_str(&sensor_statuses)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        DeviceError, DeviceErrorCode, DeviceId, DeviceStatus, DispatcherId, Percentage, SensorId,
+        SensorState, SensorStatus, StatusId,
+    };
+    use ulid::Ulid;
+
+    use super::SqliteDeviceStatusRegistry;
+    use crate::registry::{
+        DeviceStatusRegistry,
+        filter::{Pagination, QueryOptions, SortOrder, StatusFilter, StatusSortBy},
+        memory::InMemoryDeviceStatusRegistry,
+    };
+
+    fn status(
+        device_id: DeviceId,
+        second: i64,
+        battery: u8,
+        errors: &[DeviceErrorCode],
+    ) -> DeviceStatus {
+        DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id,
+            dispatcher_id: DispatcherId(Ulid::new()),
+            battery_percent: Percentage(battery),
+            uptime_seconds: u64::from(u32::MAX) + 1,
+            signal_rssi: -70,
+            errors: errors
+                .iter()
+                .map(|code| DeviceError {
+                    code: code.clone(),
+                    message: Some("firmware says".into()),
+                })
+                .collect(),
+            timestamp: jiff::Timestamp::from_second(second).unwrap(),
+            sensor_statuses: Box::new([SensorStatus {
+                sensor_id: SensorId(Ulid::new()),
+                state: SensorState::Faulty,
+                last_reading: Some(jiff::Timestamp::from_second(second - 5).unwrap()),
+            }]),
+        }
+    }
+
+    /// Every page of `filter`, oldest first, `limit` at a time.
+    async fn pages<R: DeviceStatusRegistry>(
+        reg: &R,
+        filter: StatusFilter,
+        limit: usize,
+    ) -> Vec<Vec<DeviceStatus>> {
+        let mut pages = Vec::new();
+        let mut after = None;
+        loop {
+            let page = reg
+                .list(QueryOptions {
+                    filter: filter.clone(),
+                    sort_by: StatusSortBy::Timestamp,
+                    sort_order: SortOrder::Asc,
+                    pagination: Pagination::Cursor { after, limit },
+                })
+                .await
+                .map_err(|_| ())
+                .unwrap();
+            if page.is_empty() {
+                return pages;
+            }
+            after = page.last().map(|last| StatusSortBy::Timestamp.cursor(last));
+            pages.push(page);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_and_latest() {
+        let reg = SqliteDeviceStatusRegistry::new_in_memory().await.unwrap();
+        let device = DeviceId(Ulid::new());
+        let older = status(device, 10, 90, &[DeviceErrorCode::RadioFault]);
+        let newer = status(device, 20, 80, &[]);
+
+        reg.batch_store(vec![newer.clone(), older.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(reg.get(older.id).await.unwrap(), Some(older));
+        assert_eq!(reg.latest(device).await.unwrap(), Some(newer));
+        assert_eq!(reg.latest(DeviceId(Ulid::new())).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_matches_the_memory_registry() {
+        let memory = InMemoryDeviceStatusRegistry::new();
+        let sqlite = SqliteDeviceStatusRegistry::new_in_memory()
+            .await
+            .unwrap()
+            .with_writer(4);
+        let (device, other) = (DeviceId(Ulid::new()), DeviceId(Ulid::new()));
+
+        let mut statuses = vec![
+            status(device, 10, 95, &[]),
+            status(device, 20, 15, &[DeviceErrorCode::LowBattery]),
+            status(
+                device,
+                30,
+                40,
+                &[DeviceErrorCode::SensorFault, DeviceErrorCode::RadioFault],
+            ),
+            status(other, 20, 60, &[DeviceErrorCode::Unknown]),
+            status(other, 40, 55, &[]),
+        ];
+        // Ties on timestamp are broken by id.
+        statuses.extend((0..3).map(|_| status(other, 50, 50, &[])));
+
+        let stored = memory.batch_store(statuses.clone()).await.unwrap();
+        assert_eq!(sqlite.batch_store(statuses.clone()).await.unwrap(), stored);
+
+        // Replays are skipped by both.
+        let replay = vec![statuses[0].clone(), status(device, 60, 70, &[])];
+        let stored = memory.batch_store(replay.clone()).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(sqlite.batch_store(replay).await.unwrap(), stored);
+
+        for device in [device, other] {
+            assert_eq!(
+                memory.latest(device).await.unwrap(),
+                sqlite.latest(device).await.unwrap()
+            );
+        }
+
+        let filters = [
+            StatusFilter::default(),
+            StatusFilter::builder().device_ids([device]).build(),
+            StatusFilter::builder()
+                .dispatcher_ids([statuses[1].dispatcher_id, statuses[3].dispatcher_id])
+                .build(),
+            StatusFilter::builder()
+                .after(jiff::Timestamp::from_second(20).unwrap())
+                .before(jiff::Timestamp::from_second(40).unwrap())
+                .build(),
+            StatusFilter::builder().battery(10..=55).build(),
+            StatusFilter::builder().has_errors(true).build(),
+            StatusFilter::builder().has_errors(false).build(),
+            StatusFilter::builder()
+                .error_codes([DeviceErrorCode::RadioFault, DeviceErrorCode::Unknown])
+                .build(),
+            StatusFilter::builder()
+                .device_ids([device])
+                .has_errors(true)
+                .battery(0..=20)
+                .build(),
+        ];
+        for filter in filters {
+            let count = memory.count(Some(filter.clone())).await.unwrap();
+            assert_eq!(sqlite.count(Some(filter.clone())).await.unwrap(), count);
+
+            let expected = pages(&memory, filter.clone(), 2).await;
+            assert_eq!(pages(&sqlite, filter, 2).await, expected);
+            assert_eq!(expected.iter().map(Vec::len).sum::<usize>(), count);
+        }
+
+        let before = jiff::Timestamp::from_second(40).unwrap();
+        assert_eq!(
+            sqlite.purge(before, 3).await.unwrap(),
+            memory.purge(before, 3).await.unwrap()
+        );
+        assert_eq!(
+            pages(&sqlite, StatusFilter::default(), 10).await,
+            pages(&memory, StatusFilter::default(), 10).await
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::org::OrgId;
+use crate::registry::{RegistryError, UserRegistry};
+use crate::user::{Credential, Role, User, UserId};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteUserError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid role: {0}")]
+    InvalidRole(String),
+    #[error("user {0} has neither a password nor an OIDC subject")]
+    MissingCredential(String),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteUserError> for RegistryError {
+    fn from(error: SqliteUserError) -> Self {
+        match error {
+            SqliteUserError::NotFound => RegistryError::NotFound,
+            SqliteUserError::Sqlx(e) => e.into(),
+            e @ SqliteUserError::MissingCredential(..) => {
+                RegistryError::InvalidInput(e.to_string())
+            }
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteUserRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteUserRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteUserError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteUserError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteUserError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    async fn get_where(
+        &self,
+        condition: &str,
+        binds: &[&str],
+    ) -> Result<Option<User>, SqliteUserError> {
+        let sql = format!("SELECT {COLUMNS} FROM users WHERE ({condition}) AND deleted_at IS NULL");
+        let mut query = sqlx::query(&sql);
+        for bind in binds {
+            query = query.bind(*bind);
+        }
+
+        let row = query.fetch_optional(&self.pool).await?;
+        row.map(map_row_to_user).transpose()
+    }
+}
+
+const COLUMNS: &str = "id, username, name, role, org_id, fields, password_hash, oidc_issuer, \
+                       oidc_subject, created_at";
+
+/// The password hash, or the OIDC issuer and subject, of `credential`.
+fn credential_columns(credential: &Credential) -> (Option<&str>, Option<&str>, Option<&str>) {
+    match credential {
+        Credential::Password { hash } => (Some(hash), None, None),
+        Credential::Oidc { issuer, subject } => (None, Some(issuer), Some(subject)),
+    }
+}
+
+#[async_trait]
+impl UserRegistry for SqliteUserRegistry {
+    type Error = SqliteUserError;
+
+    async fn create(&self, user: User) -> Result<(), Self::Error> {
+        let (password_hash, oidc_issuer, oidc_subject) = credential_columns(&user.credential);
+        sqlx::query(&format!(
+            "INSERT INTO users ({COLUMNS}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(user.id.0.to_string())
+        .bind(&user.username)
+        .bind(&user.name)
+        .bind(user.role.as_str())
+        .bind(user.org_id.map(|org| org.0.to_string()))
+        .bind(serde_json::to_string(&user.fields)?)
+        .bind(password_hash)
+        .bind(oidc_issuer)
+        .bind(oidc_subject)
+        .bind(user.created_at.as_second())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: UserId) -> Result<Option<User>, Self::Error> {
+        self.get_where("id = ?", &[&id.0.to_string()]).await
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Self::Error> {
+        self.get_where("username = ?", &[username]).await
+    }
+
+    async fn get_by_subject(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<User>, Self::Error> {
+        self.get_where("oidc_issuer = ? AND oidc_subject = ?", &[issuer, subject])
+            .await
+    }
+
+    async fn update(&self, user: User) -> Result<(), Self::Error> {
+        let (password_hash, oidc_issuer, oidc_subject) = credential_columns(&user.credential);
+        let result = sqlx::query(
+            r#"
+            UPDATE users
+            SET name = ?, role = ?, fields = ?, password_hash = ?, oidc_issuer = ?, oidc_subject = ?
+            WHERE id = ? AND deleted_at IS NULL
+            "#,
+        )
+        .bind(&user.name)
+        .bind(user.role.as_str())
+        .bind(serde_json::to_string(&user.fields)?)
+        .bind(password_hash)
+        .bind(oidc_issuer)
+        .bind(oidc_subject)
+        .bind(user.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteUserError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: UserId) -> Result<(), Self::Error> {
+        let result =
+            sqlx::query("UPDATE users SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL")
+                .bind(jiff::Timestamp::now().as_second())
+                .bind(id.0.to_string())
+                .execute(&self.pool)
+                .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteUserError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<User>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {COLUMNS} FROM users WHERE deleted_at IS NULL ORDER BY id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_user).collect()
+    }
+}
+
+fn map_row_to_user(row: SqliteRow) -> Result<User, SqliteUserError> {
+    let id: String = row.try_get("id")?;
+    let ulid = Ulid::from_str(&id).map_err(|_| SqliteUserError::InvalidUlid(id.clone()))?;
+    let role: String = row.try_get("role")?;
+    let fields: String = row.try_get("fields")?;
+    let created_at: i64 = row.try_get("created_at")?;
+
+    let credential = match (
+        row.try_get::<Option<String>, _>("password_hash")?,
+        row.try_get::<Option<String>, _>("oidc_issuer")?,
+        row.try_get::<Option<String>, _>("oidc_subject")?,
+    ) {
+        (Some(hash), _, _) => Credential::Password { hash },
+        (None, Some(issuer), Some(subject)) => Credential::Oidc { issuer, subject },
+        _ => return Err(SqliteUserError::MissingCredential(id)),
+    };
+
+    Ok(User {
+        id: UserId(ulid),
+        username: row.try_get("username")?,
+        name: row.try_get("name")?,
+        role: Role::parse(&role).ok_or(SqliteUserError::InvalidRole(role))?,
+        org_id: row
+            .try_get::<Option<String>, _>("org_id")?
+            .map(|id| {
+                Ulid::from_str(&id)
+                    .map(OrgId)
+                    .map_err(|_| SqliteUserError::InvalidUlid(id))
+            })
+            .transpose()?,
+        fields: serde_json::from_str(&fields)?,
+        credential,
+        created_at: jiff::Timestamp::from_second(created_at)
+            .map_err(|_| SqliteUserError::InvalidTimestamp(created_at))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::H3Cell;
+
+    use super::{SqliteUserError, SqliteUserRegistry};
+    use crate::registry::UserRegistry;
+    use crate::user::{Credential, Role, User};
+
+    #[tokio::test]
+    async fn test_users_are_found_by_login() {
+        let registry = SqliteUserRegistry::new_in_memory().await.unwrap();
+        let mut officer = User::new(
+            "officer".to_owned(),
+            "Extension Officer".to_owned(),
+            Role::Viewer,
+            Credential::Oidc {
+                issuer: "https://accounts.example.com".to_owned(),
+                subject: "248289761001".to_owned(),
+            },
+        );
+        officer.fields = vec![H3Cell(0x892a1072b5bffff)];
+        registry.create(officer.clone()).await.unwrap();
+
+        let found = registry
+            .get_by_subject("https://accounts.example.com", "248289761001")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, officer.id);
+        assert_eq!(found.fields, officer.fields);
+        assert_eq!(found.credential, officer.credential);
+        assert!(
+            registry
+                .get_by_subject("https://other.example.com", "248289761001")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        officer.role = Role::Agronomist;
+        officer.credential = Credential::Password {
+            hash: "$argon2id$placeholder".to_owned(),
+        };
+        registry.update(officer.clone()).await.unwrap();
+        let found = registry.get_by_username("officer").await.unwrap().unwrap();
+        assert_eq!(found.role, Role::Agronomist);
+        assert_eq!(found.credential, officer.credential);
+
+        registry.delete(officer.id).await.unwrap();
+        assert_eq!(registry.list().await.unwrap(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn test_deleted_users_are_kept_but_not_found() {
+        let registry = SqliteUserRegistry::new_in_memory().await.unwrap();
+        let user = || {
+            User::new(
+                "officer".to_owned(),
+                "Extension Officer".to_owned(),
+                Role::Viewer,
+                Credential::Password {
+                    hash: "$argon2id$placeholder".to_owned(),
+                },
+            )
+        };
+        let deleted = user();
+        registry.create(deleted.clone()).await.unwrap();
+        registry.delete(deleted.id).await.unwrap();
+
+        assert!(registry.get(deleted.id).await.unwrap().is_none());
+        assert!(registry.get_by_username("officer").await.unwrap().is_none());
+        assert!(matches!(
+            registry.update(deleted.clone()).await,
+            Err(SqliteUserError::NotFound)
+        ));
+        assert!(matches!(
+            registry.delete(deleted.id).await,
+            Err(SqliteUserError::NotFound)
+        ));
+
+        // The username is free again, while the deleted record stays.
+        let successor = user();
+        registry.create(successor.clone()).await.unwrap();
+        let found = registry.get_by_username("officer").await.unwrap().unwrap();
+        assert_eq!(found.id, successor.id);
+
+        let deleted_at: Option<i64> =
+            sqlx::query_scalar("SELECT deleted_at FROM users WHERE id = ?")
+                .bind(deleted.id.0.to_string())
+                .fetch_one(&registry.pool)
+                .await
+                .unwrap();
+        assert!(deleted_at.is_some());
+    }
+}
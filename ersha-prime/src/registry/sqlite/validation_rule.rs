@@ -0,0 +1,205 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ersha_core::{H3Cell, SensorKind};
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::registry::{RegistryError, ValidationRuleRegistry};
+use crate::validation::{ValidationRule, ValidationRuleId};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteValidationRuleError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid sensor kind: {0}")]
+    InvalidSensorKind(i32),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteValidationRuleError> for RegistryError {
+    fn from(error: SqliteValidationRuleError) -> Self {
+        match error {
+            SqliteValidationRuleError::NotFound => RegistryError::NotFound,
+            SqliteValidationRuleError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteValidationRuleRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteValidationRuleRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteValidationRuleError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteValidationRuleError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteValidationRuleError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const RULE_COLUMNS: &str = "id, metric, within, min, max, max_change_per_hour, created_at";
+
+#[async_trait]
+impl ValidationRuleRegistry for SqliteValidationRuleRegistry {
+    type Error = SqliteValidationRuleError;
+
+    async fn create(&self, rule: ValidationRule) -> Result<(), Self::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO validation_rules ({RULE_COLUMNS}) VALUES (?, ?, ?, ?, ?, ?, ?)"
+        ))
+        .bind(rule.id.0.to_string())
+        .bind(rule.metric as i32)
+        .bind(rule.within.map(|cell| cell.0 as i64))
+        .bind(rule.min)
+        .bind(rule.max)
+        .bind(rule.max_change_per_hour)
+        .bind(rule.created_at.as_second())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: ValidationRuleId) -> Result<Option<ValidationRule>, Self::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {RULE_COLUMNS} FROM validation_rules WHERE id = ?"
+        ))
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(map_row_to_rule).transpose()
+    }
+
+    async fn update(&self, rule: ValidationRule) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE validation_rules
+            SET metric = ?, within = ?, min = ?, max = ?, max_change_per_hour = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(rule.metric as i32)
+        .bind(rule.within.map(|cell| cell.0 as i64))
+        .bind(rule.min)
+        .bind(rule.max)
+        .bind(rule.max_change_per_hour)
+        .bind(rule.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteValidationRuleError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: ValidationRuleId) -> Result<(), Self::Error> {
+        let result = sqlx::query("DELETE FROM validation_rules WHERE id = ?")
+            .bind(id.0.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteValidationRuleError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<ValidationRule>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {RULE_COLUMNS} FROM validation_rules ORDER BY id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_rule).collect()
+    }
+}
+
+fn map_row_to_rule(row: SqliteRow) -> Result<ValidationRule, SqliteValidationRuleError> {
+    let id: String = row.try_get("id")?;
+    let created_at: i64 = row.try_get("created_at")?;
+
+    let metric = match row.try_get::<i32, _>("metric")? {
+        0 => SensorKind::SoilMoisture,
+        1 => SensorKind::SoilTemp,
+        2 => SensorKind::AirTemp,
+        3 => SensorKind::Humidity,
+        4 => SensorKind::Rainfall,
+        other => return Err(SqliteValidationRuleError::InvalidSensorKind(other)),
+    };
+
+    Ok(ValidationRule {
+        id: Ulid::from_str(&id)
+            .map(ValidationRuleId)
+            .map_err(|_| SqliteValidationRuleError::InvalidUlid(id))?,
+        metric,
+        within: row
+            .try_get::<Option<i64>, _>("within")?
+            .map(|cell| H3Cell(cell as u64)),
+        min: row.try_get("min")?,
+        max: row.try_get("max")?,
+        max_change_per_hour: row.try_get("max_change_per_hour")?,
+        created_at: jiff::Timestamp::from_second(created_at)
+            .map_err(|_| SqliteValidationRuleError::InvalidTimestamp(created_at))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{H3Cell, SensorKind};
+
+    use super::SqliteValidationRuleRegistry;
+    use crate::registry::ValidationRuleRegistry;
+    use crate::validation::ValidationRule;
+
+    #[tokio::test]
+    async fn test_rules_round_trip() {
+        let registry = SqliteValidationRuleRegistry::new_in_memory().await.unwrap();
+        let mut rule = ValidationRule::new(SensorKind::AirTemp);
+        rule.within = Some(H3Cell(0x862a1072fffffff));
+        rule.min = Some(-15.0);
+        rule.created_at = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+        registry.create(rule.clone()).await.unwrap();
+
+        rule.max = Some(48.5);
+        rule.max_change_per_hour = Some(8.0);
+        registry.update(rule.clone()).await.unwrap();
+        assert_eq!(registry.get(rule.id).await.unwrap(), Some(rule.clone()));
+        assert_eq!(registry.list().await.unwrap(), vec![rule.clone()]);
+
+        registry.delete(rule.id).await.unwrap();
+        assert_eq!(registry.get(rule.id).await.unwrap(), None);
+        assert!(registry.delete(rule.id).await.is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions, sqlite::SqliteRow};
+use ulid::Ulid;
+
+use crate::config::SqlitePoolConfig;
+use crate::org::OrgId;
+use crate::registry::{RegistryError, WebhookRegistry};
+use crate::webhook::{Delivery, DeliveryId, DeliveryState, Webhook, WebhookId};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteWebhookError {
+    #[error("sqlx error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ULID: {0}")]
+    InvalidUlid(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("invalid delivery state: {0}")]
+    InvalidState(i32),
+    #[error("not found")]
+    NotFound,
+}
+
+impl From<SqliteWebhookError> for RegistryError {
+    fn from(error: SqliteWebhookError) -> Self {
+        match error {
+            SqliteWebhookError::NotFound => RegistryError::NotFound,
+            SqliteWebhookError::Sqlx(e) => e.into(),
+            other => RegistryError::backend(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteWebhookRegistry {
+    pool: SqlitePool,
+}
+
+impl SqliteWebhookRegistry {
+    pub async fn new(path: impl AsRef<str>) -> Result<Self, SqliteWebhookError> {
+        let pool = super::connect(path.as_ref(), &SqlitePoolConfig::default()).await?;
+
+        Self::with_pool(pool).await
+    }
+
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, SqliteWebhookError> {
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn new_in_memory() -> Result<Self, SqliteWebhookError> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const DELIVERY_COLUMNS: &str = "id, webhook_id, event, state, attempts, next_attempt_at, \
+                                last_attempt_at, last_status, last_error";
+
+#[async_trait]
+impl WebhookRegistry for SqliteWebhookRegistry {
+    type Error = SqliteWebhookError;
+
+    async fn create(&self, webhook: Webhook) -> Result<(), Self::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO webhooks (id, url, events, thresholds, secret, org_id, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(webhook.id.0.to_string())
+        .bind(webhook.url)
+        .bind(serde_json::to_string(&webhook.events)?)
+        .bind(serde_json::to_string(&webhook.thresholds)?)
+        .bind(webhook.secret)
+        .bind(webhook.org_id.map(|org| org.0.to_string()))
+        .bind(webhook.created_at.as_second())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: WebhookId) -> Result<Option<Webhook>, Self::Error> {
+        let row = sqlx::query(
+            "SELECT id, url, events, thresholds, secret, org_id, created_at FROM webhooks WHERE id = ?",
+        )
+        .bind(id.0.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(map_row_to_webhook).transpose()
+    }
+
+    async fn delete(&self, id: WebhookId) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM webhook_deliveries WHERE webhook_id = ?")
+            .bind(id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = ?")
+            .bind(id.0.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteWebhookError::NotFound);
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, Self::Error> {
+        let rows = sqlx::query(
+            "SELECT id, url, events, thresholds, secret, org_id, created_at FROM webhooks ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_webhook).collect()
+    }
+
+    async fn enqueue(&self, deliveries: Vec<Delivery>) -> Result<(), Self::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for delivery in deliveries {
+            sqlx::query(&format!(
+                "INSERT INTO webhook_deliveries ({DELIVERY_COLUMNS}) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            ))
+            .bind(delivery.id.0.to_string())
+            .bind(delivery.webhook_id.0.to_string())
+            .bind(serde_json::to_string(&delivery.event)?)
+            .bind(delivery.state as i32)
+            .bind(delivery.attempts as i64)
+            .bind(delivery.next_attempt_at.as_second())
+            .bind(delivery.last_attempt_at.map(|at| at.as_second()))
+            .bind(delivery.last_status.map(i64::from))
+            .bind(delivery.last_error)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn due(&self, now: jiff::Timestamp, limit: usize) -> Result<Vec<Delivery>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries \
+             WHERE state = ? AND next_attempt_at <= ? \
+             ORDER BY next_attempt_at, id LIMIT ?"
+        ))
+        .bind(DeliveryState::Pending as i32)
+        .bind(now.as_second())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_delivery).collect()
+    }
+
+    async fn update_delivery(&self, delivery: Delivery) -> Result<(), Self::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET state = ?, attempts = ?, next_attempt_at = ?, last_attempt_at = ?,
+                last_status = ?, last_error = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(delivery.state as i32)
+        .bind(delivery.attempts as i64)
+        .bind(delivery.next_attempt_at.as_second())
+        .bind(delivery.last_attempt_at.map(|at| at.as_second()))
+        .bind(delivery.last_status.map(i64::from))
+        .bind(delivery.last_error)
+        .bind(delivery.id.0.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SqliteWebhookError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn deliveries(&self, id: WebhookId, limit: usize) -> Result<Vec<Delivery>, Self::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries \
+             WHERE webhook_id = ? ORDER BY id DESC LIMIT ?"
+        ))
+        .bind(id.0.to_string())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(map_row_to_delivery).collect()
+    }
+}
+
+fn parse_ulid(row: &SqliteRow, column: &str) -> Result<Ulid, SqliteWebhookError> {
+    let s: String = row.try_get(column)?;
+    Ulid::from_str(&s).map_err(|_| SqliteWebhookError::InvalidUlid(s))
+}
+
+fn parse_timestamp(second: i64) -> Result<jiff::Timestamp, SqliteWebhookError> {
+    jiff::Timestamp::from_second(second).map_err(|_| SqliteWebhookError::InvalidTimestamp(second))
+}
+
+fn map_row_to_webhook(row: SqliteRow) -> Result<Webhook, SqliteWebhookError> {
+    let events: String = row.try_get("events")?;
+    let thresholds: String = row.try_get("thresholds")?;
+
+    Ok(Webhook {
+        id: WebhookId(parse_ulid(&row, "id")?),
+        url: row.try_get("url")?,
+        events: serde_json::from_str(&events)?,
+        thresholds: serde_json::from_str(&thresholds)?,
+        secret: row.try_get("secret")?,
+        org_id: row
+            .try_get::<Option<String>, _>("org_id")?
+            .map(|id| {
+                Ulid::from_str(&id)
+                    .map(OrgId)
+                    .map_err(|_| SqliteWebhookError::InvalidUlid(id))
+            })
+            .transpose()?,
+        created_at: parse_timestamp(row.try_get("created_at")?)?,
+    })
+}
+
+fn map_row_to_delivery(row: SqliteRow) -> Result<Delivery, SqliteWebhookError> {
+    let event: String = row.try_get("event")?;
+    let state = match row.try_get::<i32, _>("state")? {
+        0 => DeliveryState::Pending,
+        1 => DeliveryState::Delivered,
+        2 => DeliveryState::Failed,
+        other => return Err(SqliteWebhookError::InvalidState(other)),
+    };
+    let attempts: i64 = row.try_get("attempts")?;
+    let last_status: Option<i64> = row.try_get("last_status")?;
+
+    Ok(Delivery {
+        id: DeliveryId(parse_ulid(&row, "id")?),
+        webhook_id: WebhookId(parse_ulid(&row, "webhook_id")?),
+        event: serde_json::from_str(&event)?,
+        state,
+        attempts: attempts as u32,
+        next_attempt_at: parse_timestamp(row.try_get("next_attempt_at")?)?,
+        last_attempt_at: row
+            .try_get::<Option<i64>, _>("last_attempt_at")?
+            .map(parse_timestamp)
+            .transpose()?,
+        last_status: last_status.map(|status| status as u16),
+        last_error: row.try_get("last_error")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::SqliteWebhookRegistry;
+    use crate::registry::WebhookRegistry;
+    use crate::webhook::{Delivery, DeliveryState, Event, EventKind, Webhook};
+
+    #[tokio::test]
+    async fn test_deliveries_round_trip() {
+        let registry = SqliteWebhookRegistry::new_in_memory().await.unwrap();
+        let webhook = Webhook::generate(
+            "https://example.com/hook".to_owned(),
+            vec![EventKind::AlertRaised],
+            vec![],
+        );
+        registry.create(webhook.clone()).await.unwrap();
+
+        let event = Event::new(EventKind::AlertRaised, serde_json::json!({ "level": 2 }));
+        let delivery = Delivery::new(webhook.id, event);
+        registry.enqueue(vec![delivery.clone()]).await.unwrap();
+
+        let now = Timestamp::now() + SignedDuration::from_secs(1);
+        let mut due = registry.due(now, 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].event, delivery.event);
+
+        let mut delivered = due.remove(0);
+        delivered.state = DeliveryState::Delivered;
+        delivered.attempts = 1;
+        delivered.last_status = Some(200);
+        registry.update_delivery(delivered).await.unwrap();
+
+        assert!(registry.due(now, 10).await.unwrap().is_empty());
+        let listed = registry.deliveries(webhook.id, 10).await.unwrap();
+        assert_eq!(listed[0].last_status, Some(200));
+
+        registry.delete(webhook.id).await.unwrap();
+        assert_eq!(registry.get(webhook.id).await.unwrap(), None);
+        assert!(
+            registry
+                .deliveries(webhook.id, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! A task running writes one after another.
+//!
+//! SQLite allows a single writer at a time. Writes racing from many pool
+//! connections wait on each other's locks and, once `busy_timeout` runs out,
+//! fail with `database is locked`. Queued here, they take turns instead and
+//! reads carry on alongside them.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use sqlx::{Sqlite, SqlitePool, pool::PoolConnection};
+use tokio::sync::{mpsc, oneshot};
+
+type Pending = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+type Job = Box<dyn FnOnce(Result<PoolConnection<Sqlite>, sqlx::Error>) -> Pending + Send>;
+
+#[derive(Clone)]
+pub struct Writer {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Writer {
+    /// Start the task, with room for `queue` writes waiting their turn. It
+    /// stops once every clone of the writer is dropped.
+    pub fn spawn(pool: SqlitePool, queue: usize) -> Self {
+        let (jobs, mut rx) = mpsc::channel::<Job>(queue.max(1));
+        tokio::spawn(async move {
+            while let Some(job) = rx.recv().await {
+                job(pool.acquire().await).await;
+            }
+        });
+
+        Self { jobs }
+    }
+
+    /// Run `write` on a connection once the writes queued before it are done.
+    pub async fn run<T, F, Fut>(&self, write: F) -> Result<T, sqlx::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(PoolConnection<Sqlite>) -> Fut + Send + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move |conn| {
+            Box::pin(async move {
+                let result = match conn {
+                    Ok(conn) => Ok(write(conn).await),
+                    Err(e) => Err(e),
+                };
+                let _ = tx.send(result);
+            })
+        });
+
+        self.jobs
+            .send(job)
+            .await
+            .map_err(|_| sqlx::Error::PoolClosed)?;
+        rx.await.map_err(|_| sqlx::Error::PoolClosed)?
+    }
+}
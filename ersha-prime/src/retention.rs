@@ -0,0 +1,263 @@
+use std::future::Future;
+use std::time::Duration;
+
+use jiff::{SignedDuration, Timestamp};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::config::{RetentionConfig, RetentionPolicy};
+use crate::metrics;
+use crate::registry::{
+    DeviceStatusRegistry, ReadingRegistry, Registries,
+    filter::{ReadingFilter, StatusFilter},
+};
+use crate::tuning::Tunables;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum RetentionError {
+    #[error("failed to purge readings: {0}")]
+    Readings(#[source] BoxError),
+    #[error("failed to purge statuses: {0}")]
+    Statuses(#[source] BoxError),
+}
+
+/// What a sweep purged, or would have purged on a dry run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SweepReport {
+    pub readings: usize,
+    pub statuses: usize,
+}
+
+/// Sweep every `interval_secs` until cancelled.
+///
+/// Follows the retention settings of `tunables`: a changed interval counts
+/// from the previous sweep, and nothing is swept while retention is disabled.
+pub async fn run<R: Registries>(
+    registries: R,
+    mut tunables: watch::Receiver<Tunables>,
+    cancel: CancellationToken,
+) {
+    let mut last_sweep: Option<Instant> = None;
+
+    loop {
+        let config = tunables.borrow_and_update().retention;
+        let due = last_sweep.map_or_else(Instant::now, |last| {
+            last + Duration::from_secs(config.interval_secs.max(1))
+        });
+
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            changed = tunables.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                continue;
+            }
+            _ = tokio::time::sleep_until(due), if config.enabled => {}
+        }
+
+        last_sweep = Some(Instant::now());
+        match sweep(&registries, &config, Timestamp::now()).await {
+            Ok(report) => info!(
+                readings = report.readings,
+                statuses = report.statuses,
+                dry_run = config.dry_run,
+                "retention sweep finished"
+            ),
+            Err(e) => error!(error = %e, "retention sweep failed"),
+        }
+    }
+}
+
+/// Purge everything older than its policy allows as of `now`.
+pub async fn sweep<R: Registries>(
+    registries: &R,
+    config: &RetentionConfig,
+    now: Timestamp,
+) -> Result<SweepReport, RetentionError> {
+    let mut report = SweepReport::default();
+    let batch_size = config.batch_size.max(1);
+
+    if let Some(cutoff) = cutoff(now, config.readings) {
+        let readings = registries.readings();
+        report.readings = if config.dry_run {
+            let filter = ReadingFilter::builder().before(cutoff).build();
+            readings.count(Some(filter)).await
+        } else {
+            purge_in_batches("readings", batch_size, || {
+                readings.purge(cutoff, batch_size)
+            })
+            .await
+        }
+        .map_err(|e| RetentionError::Readings(Box::new(e)))?;
+    }
+
+    if let Some(cutoff) = cutoff(now, config.statuses) {
+        let statuses = registries.statuses();
+        report.statuses = if config.dry_run {
+            let filter = StatusFilter::builder().before(cutoff).build();
+            statuses.count(Some(filter)).await
+        } else {
+            purge_in_batches("statuses", batch_size, || {
+                statuses.purge(cutoff, batch_size)
+            })
+            .await
+        }
+        .map_err(|e| RetentionError::Statuses(Box::new(e)))?;
+    }
+
+    Ok(report)
+}
+
+/// The newest timestamp that is past the policy's age, if it has one.
+pub(crate) fn cutoff(now: Timestamp, policy: RetentionPolicy) -> Option<Timestamp> {
+    let days = i64::try_from(policy.max_age_days?).ok()?;
+    let age = SignedDuration::from_hours(days.checked_mul(24)?);
+
+    // Nothing can be older than the minimum timestamp.
+    now.checked_sub(age).ok()
+}
+
+/// Call `purge` until a batch comes back short, logging progress per batch.
+async fn purge_in_batches<F, Fut, E>(
+    kind: &'static str,
+    batch_size: usize,
+    mut purge: F,
+) -> Result<usize, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<usize, E>>,
+{
+    let mut total = 0;
+    loop {
+        let purged = purge().await?;
+        total += purged;
+
+        if purged > 0 {
+            metrics::record_purged(kind, purged);
+            info!(kind, purged, total, "purged expired batch");
+        }
+        if purged < batch_size {
+            return Ok(total);
+        }
+
+        // Let ingest take the registry lock between batches.
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DeviceId, DeviceStatus, DispatcherId, Percentage, SensorReading, StatusId};
+    use jiff::{SignedDuration, Timestamp};
+    use ulid::Ulid;
+
+    use super::{SweepReport, sweep};
+    use crate::config::{RetentionConfig, RetentionPolicy};
+    use crate::fixtures;
+    use crate::registry::{
+        DeviceStatusRegistry, ReadingRegistry, Registries, memory::InMemoryRegistries,
+    };
+
+    const DAY: SignedDuration = SignedDuration::from_hours(24);
+
+    fn reading(timestamp: Timestamp) -> SensorReading {
+        SensorReading {
+            timestamp,
+            ..fixtures::reading()
+        }
+    }
+
+    fn status(timestamp: Timestamp) -> DeviceStatus {
+        DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            battery_percent: Percentage(80),
+            uptime_seconds: 60,
+            signal_rssi: -70,
+            errors: Box::new([]),
+            timestamp,
+            sensor_statuses: Box::new([]),
+        }
+    }
+
+    #[tokio::test]
+    async fn sweep_purges_expired_data_in_batches() {
+        let registries = InMemoryRegistries::default();
+        let now = Timestamp::from_second(1_000 * 86_400).unwrap();
+
+        let old_readings: Vec<_> = (0..5).map(|_| reading(now - DAY * 91)).collect();
+        let fresh = reading(now - DAY * 89);
+        registries
+            .readings()
+            .batch_store([old_readings, vec![fresh.clone()]].concat())
+            .await
+            .unwrap();
+        registries
+            .statuses()
+            .batch_store(vec![status(now - DAY * 31), status(now - DAY * 29)])
+            .await
+            .unwrap();
+
+        let config = RetentionConfig {
+            dry_run: true,
+            batch_size: 2,
+            ..RetentionConfig::default()
+        };
+        let report = sweep(&registries, &config, now).await.unwrap();
+        assert_eq!(
+            report,
+            SweepReport {
+                readings: 5,
+                statuses: 1
+            }
+        );
+        assert_eq!(registries.readings().count(None).await.unwrap(), 6);
+
+        let config = RetentionConfig {
+            dry_run: false,
+            ..config
+        };
+        let report = sweep(&registries, &config, now).await.unwrap();
+        assert_eq!(
+            report,
+            SweepReport {
+                readings: 5,
+                statuses: 1
+            }
+        );
+        assert_eq!(registries.readings().count(None).await.unwrap(), 1);
+        assert_eq!(
+            registries.readings().get(fresh.id).await.unwrap(),
+            Some(fresh)
+        );
+        assert_eq!(registries.statuses().count(None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn unset_max_age_keeps_data_forever() {
+        let registries = InMemoryRegistries::default();
+        registries
+            .readings()
+            .store(reading(Timestamp::UNIX_EPOCH))
+            .await
+            .unwrap();
+
+        let config = RetentionConfig {
+            readings: RetentionPolicy { max_age_days: None },
+            ..RetentionConfig::default()
+        };
+        let report = sweep(&registries, &config, Timestamp::now()).await.unwrap();
+
+        assert_eq!(report.readings, 0);
+        assert_eq!(registries.readings().count(None).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,281 @@
+//! Hourly and daily rollups of sensor readings, maintained on ingest.
+
+use std::collections::HashMap;
+
+use ersha_core::{DeviceId, SensorId, SensorKind, SensorMetric, SensorReading};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::events::{BusEvent, Received, Subscription};
+use crate::metrics;
+use crate::registry::{AggregateRegistry, Registries};
+
+/// Width of a rollup bucket.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl Granularity {
+    pub const ALL: [Granularity; 2] = [Granularity::Hour, Granularity::Day];
+
+    fn seconds(self) -> i64 {
+        match self {
+            Granularity::Hour => 3_600,
+            Granularity::Day => 86_400,
+        }
+    }
+
+    /// Start of the UTC bucket containing `timestamp`.
+    pub fn bucket_start(self, timestamp: Timestamp) -> Timestamp {
+        let second = timestamp.as_second();
+        let start = second - second.rem_euclid(self.seconds());
+
+        Timestamp::from_second(start).expect("bucket start is within range")
+    }
+}
+
+/// Identifies the bucket an aggregate summarises.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AggregateKey {
+    pub device_id: DeviceId,
+    pub sensor_id: SensorId,
+    pub metric: SensorKind,
+    pub granularity: Granularity,
+    pub bucket_start: Timestamp,
+}
+
+/// Summary of one sensor's readings within one bucket.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Aggregate {
+    pub device_id: DeviceId,
+    pub sensor_id: SensorId,
+    pub metric: SensorKind,
+    pub granularity: Granularity,
+    pub bucket_start: Timestamp,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Aggregate {
+    fn new(key: AggregateKey, value: f64) -> Self {
+        Self {
+            device_id: key.device_id,
+            sensor_id: key.sensor_id,
+            metric: key.metric,
+            granularity: key.granularity,
+            bucket_start: key.bucket_start,
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    pub fn key(&self) -> AggregateKey {
+        AggregateKey {
+            device_id: self.device_id,
+            sensor_id: self.sensor_id,
+            metric: self.metric,
+            granularity: self.granularity,
+            bucket_start: self.bucket_start,
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    /// Fold another aggregate of the same bucket into this one.
+    pub fn merge(&mut self, other: &Aggregate) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// The reading's value in its metric's unit.
+pub fn metric_value(metric: &SensorMetric) -> f64 {
+    match metric {
+        SensorMetric::SoilMoisture { value } | SensorMetric::Humidity { value } => {
+            f64::from(value.0)
+        }
+        SensorMetric::SoilTemp { value }
+        | SensorMetric::AirTemp { value }
+        | SensorMetric::Rainfall { value } => value.into_inner(),
+    }
+}
+
+/// Name of `kind` in the API and exported data.
+pub fn metric_kind_name(kind: SensorKind) -> &'static str {
+    match kind {
+        SensorKind::SoilMoisture => "soil_moisture",
+        SensorKind::SoilTemp => "soil_temp",
+        SensorKind::AirTemp => "air_temp",
+        SensorKind::Humidity => "humidity",
+        SensorKind::Rainfall => "rainfall",
+    }
+}
+
+/// Fold readings into one partial aggregate per sensor and bucket, at every
+/// granularity, ready to merge into the stored rollups.
+pub fn rollup<'a>(readings: impl IntoIterator<Item = &'a SensorReading>) -> Vec<Aggregate> {
+    let mut partials: HashMap<AggregateKey, Aggregate> = HashMap::new();
+
+    for reading in readings {
+        let value = metric_value(&reading.metric);
+        for granularity in Granularity::ALL {
+            let key = AggregateKey {
+                device_id: reading.device_id,
+                sensor_id: reading.sensor_id,
+                metric: reading.metric.kind(),
+                granularity,
+                bucket_start: granularity.bucket_start(reading.timestamp),
+            };
+            partials
+                .entry(key)
+                .and_modify(|aggregate| aggregate.merge(&Aggregate::new(key, value)))
+                .or_insert_with(|| Aggregate::new(key, value));
+        }
+    }
+
+    partials.into_values().collect()
+}
+
+/// Fold ingested readings into the stored rollups until cancelled.
+///
+/// Rollups are derived from readings that are already stored, so a failure
+/// to merge is logged rather than retried.
+pub async fn run<R: Registries>(
+    registries: R,
+    mut events: Subscription,
+    cancel: CancellationToken,
+) {
+    loop {
+        let event = tokio::select! {
+            _ = cancel.cancelled() => break,
+            received = events.recv() => match received {
+                Some(Received::Event(event)) => event,
+                Some(Received::Lagged(_)) => continue,
+                None => break,
+            },
+        };
+        let BusEvent::ReadingsIngested { readings, .. } = &*event else {
+            continue;
+        };
+
+        if let Err(e) = metrics::timed(
+            "aggregates.merge",
+            registries.aggregates().merge(rollup(readings.iter())),
+        )
+        .await
+        {
+            error!(error = ?e, count = readings.len(), "failed to update rollups");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ersha_core::{Percentage, ReadingId, SensorId, SensorMetric, SensorReading};
+    use jiff::Timestamp;
+    use tokio_util::sync::CancellationToken;
+    use ulid::Ulid;
+
+    use super::{Granularity, rollup, run};
+    use crate::events::{BusEvent, EventBus, LocalEventBus};
+    use crate::fixtures;
+    use crate::registry::{
+        AggregateRegistry, Registries, filter::AggregateFilter, memory::InMemoryRegistries,
+    };
+
+    fn reading(sensor_id: SensorId, second: i64, value: u8) -> SensorReading {
+        SensorReading {
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(value),
+            },
+            timestamp: Timestamp::from_second(second).unwrap(),
+            sensor_id,
+            ..fixtures::reading()
+        }
+    }
+
+    #[test]
+    fn bucket_start_truncates_to_utc_boundary() {
+        let at = Timestamp::from_second(90_061).unwrap();
+
+        assert_eq!(Granularity::Hour.bucket_start(at).as_second(), 90_000);
+        assert_eq!(Granularity::Day.bucket_start(at).as_second(), 86_400);
+    }
+
+    #[test]
+    fn rollup_groups_by_bucket() {
+        let sensor = SensorId(Ulid::new());
+        let first = reading(sensor, 10, 40);
+        // Same sensor and device, next hour of the same day.
+        let second = SensorReading {
+            timestamp: Timestamp::from_second(3_700).unwrap(),
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(60),
+            },
+            ..first.clone()
+        };
+
+        let mut aggregates = rollup([&first, &second]);
+        aggregates.sort_by_key(|a| (a.granularity == Granularity::Day, a.bucket_start));
+
+        assert_eq!(aggregates.len(), 3);
+        let (hours, days) = aggregates.split_at(2);
+        assert_eq!(hours[0].count, 1);
+        assert_eq!(hours[1].bucket_start.as_second(), 3_600);
+        assert_eq!(days[0].count, 2);
+        assert_eq!(days[0].min, 40.0);
+        assert_eq!(days[0].max, 60.0);
+        assert_eq!(days[0].mean(), 50.0);
+    }
+
+    #[tokio::test]
+    async fn ingested_readings_are_rolled_up() {
+        let registries = InMemoryRegistries::default();
+        let bus = LocalEventBus::new();
+        let task = tokio::spawn(run(
+            registries.clone(),
+            bus.subscribe(),
+            CancellationToken::new(),
+        ));
+
+        let first = reading(SensorId(Ulid::new()), 10, 40);
+        let second = SensorReading {
+            id: ReadingId(Ulid::new()),
+            timestamp: Timestamp::from_second(20).unwrap(),
+            ..first.clone()
+        };
+        bus.publish(BusEvent::ReadingsIngested {
+            readings: Arc::new([first, second]),
+            late: true,
+        })
+        .await;
+        // The subscriber stops once it has seen every event and the bus is gone.
+        drop(bus);
+        task.await.unwrap();
+
+        let aggregates = registries
+            .aggregates()
+            .list(AggregateFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].count, 2);
+    }
+}
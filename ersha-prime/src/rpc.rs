@@ -0,0 +1,2367 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::time::Duration;
+
+use ersha_core::{
+    BatchRejectionReason, BatchUploadRequest, BatchUploadResponse, CommandDispatchRequest,
+    CommandPoll, CommandPollResponse, DeviceDisconnectionRequest, DeviceDisconnectionResponse,
+    DeviceId, DeviceState, DeviceStatus, Dispatcher, DispatcherId, DispatcherState,
+    DispatcherStatus, DispatcherStatusResponse, HelloRejectionReason, HelloRequest, HelloResponse,
+    InvalidItemReason, ItemOutcome, ItemResult, SensorId, SensorMetric, SensorReading,
+};
+use ersha_rpc::auth::{SeenNonces, server_proof, verify_hello};
+use ersha_rpc::tls::PeerCertificate;
+use ersha_rpc::{CancellationToken, Dispatchers, WireError, WireErrorCode};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, info, warn};
+
+use crate::audit::{AuditAction, AuditEntry, EntityKind};
+use crate::config::{AuthConfig, QuotaAction};
+use crate::dead_letter::{DeadItem, DeadLetter};
+use crate::events::{BusEvent, EventBus};
+use crate::health::DispatcherReport;
+use crate::hooks::{BatchContext, IngestionHooks};
+use crate::idempotency::RecentBatches;
+use crate::metrics;
+use crate::quota::{Admission, IngestQuotas};
+use crate::registry::{
+    AuditRegistry, CommandRegistry, DeviceRegistry, DispatcherRegistry, DispatcherStatusRegistry,
+    IngestBatch, IngestRegistry, Registries,
+};
+use crate::webhook::{ThresholdWatch, offline_event};
+
+/// How far ahead of prime's clock an item may be timestamped.
+const MAX_FUTURE_SKEW: Duration = Duration::from_secs(5 * 60);
+/// Oldest acceptable item timestamp, 2000-01-01. Devices whose clock was
+/// never set report the Unix epoch or whatever their RTC starts at.
+const EARLIEST_TIMESTAMP: jiff::Timestamp = jiff::Timestamp::constant(946_684_800, 0);
+/// Newest timestamp the registries can store, in nanoseconds as an `i64`.
+const LATEST_STORABLE: jiff::Timestamp =
+    jiff::Timestamp::constant(i64::MAX / 1_000_000_000, (i64::MAX % 1_000_000_000) as i32);
+/// Commands handed to a dispatcher per poll or push.
+const COMMANDS_PER_POLL: usize = 100;
+/// How often queued commands are pushed to connected dispatchers.
+const COMMAND_PUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a dispatcher has to answer a command push.
+const COMMAND_PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Authenticate and register a dispatcher saying hello.
+///
+/// Dispatchers with a provisioned secret must sign their hello. Without a
+/// secret they are accepted only when `require_dispatcher_auth` is off, in
+/// which case unknown dispatchers are registered on first contact: as
+/// pending, to be approved by an operator, when `approve_new_dispatchers` is
+/// on.
+///
+/// A signed hello is accepted once: its nonce is kept in `nonces` while the
+/// signature is fresh, and a hello repeating it is unauthorized.
+///
+/// A client certificate (`peer`) must be issued to the dispatcher saying
+/// hello, and that dispatcher must already be registered. It stands in for
+/// a secret when `require_dispatcher_auth` is on.
+pub async fn handle_hello<R: Registries>(
+    registries: &R,
+    auth: AuthConfig,
+    nonces: &SeenNonces,
+    hello: HelloRequest,
+    peer: Option<&PeerCertificate>,
+) -> HelloResponse {
+    let response = hello_response(registries, auth, nonces, hello, peer).await;
+    metrics::record_hello(&response);
+
+    response
+}
+
+async fn hello_response<R: Registries>(
+    registries: &R,
+    auth: AuthConfig,
+    nonces: &SeenNonces,
+    hello: HelloRequest,
+    peer: Option<&PeerCertificate>,
+) -> HelloResponse {
+    let dispatcher_id = hello.dispatcher_id;
+    let dispatcher_registry = registries.dispatchers();
+
+    info!(
+        ?dispatcher_id,
+        location = ?hello.location,
+        signed = hello.credentials.is_some(),
+        "received hello request"
+    );
+
+    let rejected = |reason| HelloResponse::Rejected {
+        dispatcher_id,
+        reason,
+    };
+
+    let lookup = async {
+        let existing =
+            metrics::timed("dispatchers.get", dispatcher_registry.get(dispatcher_id)).await?;
+        let secret = match existing {
+            Some(_) => {
+                metrics::timed(
+                    "dispatchers.get_secret",
+                    dispatcher_registry.get_secret(dispatcher_id),
+                )
+                .await?
+            }
+            None => None,
+        };
+        Ok::<_, <R::Dispatchers as DispatcherRegistry>::Error>((existing, secret))
+    };
+
+    let (existing, secret) = match lookup.await {
+        Ok(found) => found,
+        Err(e) => {
+            error!(error = ?e, "failed to look up dispatcher");
+            return rejected(HelloRejectionReason::Unavailable);
+        }
+    };
+
+    if let Some(peer) = peer {
+        if !peer.identifies(dispatcher_id) {
+            warn!(
+                ?dispatcher_id,
+                "rejecting hello with a certificate issued to another dispatcher"
+            );
+            return rejected(HelloRejectionReason::CertificateMismatch);
+        }
+        if existing.is_none() {
+            warn!(
+                ?dispatcher_id,
+                "rejecting hello with a certificate for an unregistered dispatcher"
+            );
+            return rejected(HelloRejectionReason::CertificateMismatch);
+        }
+    }
+
+    let proof = match (&secret, &hello.credentials) {
+        (Some(secret), _) => {
+            let max_skew = Duration::from_secs(auth.hello_max_skew_secs);
+            let now = jiff::Timestamp::now();
+            if let Err(e) = verify_hello(secret.as_bytes(), &hello, now, max_skew)
+                .and_then(|()| nonces.check(&hello, now, max_skew))
+            {
+                warn!(?dispatcher_id, error = %e, "rejecting unauthenticated hello");
+                return rejected(HelloRejectionReason::Unauthorized);
+            }
+
+            hello
+                .credentials
+                .as_ref()
+                .map(|credentials| server_proof(secret.as_bytes(), credentials))
+        }
+        (None, _) if auth.require_dispatcher_auth && peer.is_none() => {
+            warn!(
+                ?dispatcher_id,
+                "rejecting hello from dispatcher without a provisioned secret"
+            );
+            return rejected(HelloRejectionReason::Unauthorized);
+        }
+        (None, Some(_)) => {
+            warn!(
+                ?dispatcher_id,
+                "hello is signed but no secret is provisioned, accepting unauthenticated"
+            );
+            None
+        }
+        (None, None) => None,
+    };
+
+    match existing {
+        Some(dispatcher) if dispatcher.state == DispatcherState::Suspended => {
+            warn!(?dispatcher_id, "rejecting hello from suspended dispatcher");
+            return rejected(HelloRejectionReason::Suspended);
+        }
+        Some(dispatcher) if dispatcher.state == DispatcherState::Pending => {
+            info!(?dispatcher_id, "dispatcher still awaiting approval");
+            return rejected(HelloRejectionReason::PendingApproval);
+        }
+        Some(dispatcher) => {
+            // Known dispatchers keep their original provisioning record;
+            // only a changed location is written back.
+            if dispatcher.location != hello.location {
+                let updated = Dispatcher {
+                    location: hello.location,
+                    ..dispatcher
+                };
+                if let Err(e) = metrics::timed(
+                    "dispatchers.update",
+                    dispatcher_registry.update(dispatcher_id, updated, None),
+                )
+                .await
+                {
+                    error!(error = ?e, "failed to update dispatcher location");
+                } else {
+                    audit(
+                        registries,
+                        AuditEntry::system(
+                            AuditAction::Update,
+                            EntityKind::Dispatcher,
+                            dispatcher_id.0,
+                        )
+                        .with_details(serde_json::json!({ "location": hello.location })),
+                    )
+                    .await;
+                }
+            }
+            info!(?dispatcher_id, "dispatcher reconnected");
+        }
+        None => {
+            let state = if auth.approve_new_dispatchers {
+                DispatcherState::Pending
+            } else {
+                DispatcherState::Active
+            };
+            let dispatcher = Dispatcher {
+                id: dispatcher_id,
+                location: hello.location,
+                state: state.clone(),
+                provisioned_at: jiff::Timestamp::now(),
+            };
+
+            if let Err(e) = metrics::timed(
+                "dispatchers.register",
+                dispatcher_registry.register(dispatcher),
+            )
+            .await
+            {
+                error!(error = ?e, "failed to register dispatcher");
+                return rejected(HelloRejectionReason::Unavailable);
+            }
+            audit(
+                registries,
+                AuditEntry::system(
+                    AuditAction::Register,
+                    EntityKind::Dispatcher,
+                    dispatcher_id.0,
+                ),
+            )
+            .await;
+            if state == DispatcherState::Pending {
+                warn!(
+                    ?dispatcher_id,
+                    "unknown dispatcher recorded, its uploads are refused until approved"
+                );
+                return rejected(HelloRejectionReason::PendingApproval);
+            }
+            info!(?dispatcher_id, "dispatcher registered");
+        }
+    }
+
+    // The RPC server fills in the chunking and compression it supports.
+    HelloResponse::Accepted {
+        dispatcher_id,
+        proof,
+        chunking: None,
+        compression: None,
+    }
+}
+
+/// Record a change prime made on a dispatcher's behalf. Failing to audit
+/// doesn't fail the RPC.
+async fn audit<R: Registries>(registries: &R, entry: AuditEntry) {
+    if let Err(e) = registries.audit().record(entry).await {
+        error!(error = ?e, "failed to record audit entry");
+    }
+}
+
+/// Validate and persist a batch uploaded by a dispatcher.
+///
+/// Only registered, active dispatchers may upload, and only for devices not
+/// assigned to another dispatcher. Invalid items are reported and kept as
+/// dead letters for re-driving; items already stored are reported as
+/// duplicates, so a batch retried after a partial failure is applied exactly
+/// once. Newly stored readings and statuses are published to `events`.
+///
+/// Batches over the dispatcher's hourly quota are refused with
+/// [`WireErrorCode::QuotaExceeded`], or stored and flagged, as configured.
+///
+/// A batch sent again under the id of one accepted recently is answered with
+/// the response to the first, without being processed again.
+///
+/// Valid items go through `hooks` before they are stored. A hook failing
+/// rejects the batch as [`BatchRejectionReason::Unavailable`]. The
+/// thresholds the stored readings cross are staged in the outbox along with
+/// them, as found by `thresholds`.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_batch_upload<R: Registries>(
+    registries: &R,
+    auth: AuthConfig,
+    quotas: &IngestQuotas,
+    events: &dyn EventBus,
+    recent: &RecentBatches,
+    hooks: &IngestionHooks,
+    thresholds: &ThresholdWatch,
+    batch: BatchUploadRequest,
+) -> Result<BatchUploadResponse, WireError> {
+    let dispatcher_id = batch.dispatcher_id;
+    let (readings, statuses) = (batch.readings.len(), batch.statuses.len());
+
+    if let Some(response) = recent.get(dispatcher_id, batch.id, jiff::Timestamp::now()) {
+        info!(batch_id = ?batch.id, ?dispatcher_id, "answering retried batch with its first response");
+        metrics::record_retried_batch();
+        return Ok(response);
+    }
+
+    let response =
+        batch_response(registries, auth, quotas, events, hooks, thresholds, batch).await?;
+    metrics::record_batch(dispatcher_id, readings, statuses, &response);
+    recent.record(dispatcher_id, &response, jiff::Timestamp::now());
+
+    Ok(response)
+}
+
+async fn batch_response<R: Registries>(
+    registries: &R,
+    auth: AuthConfig,
+    quotas: &IngestQuotas,
+    events: &dyn EventBus,
+    hooks: &IngestionHooks,
+    thresholds: &ThresholdWatch,
+    batch: BatchUploadRequest,
+) -> Result<BatchUploadResponse, WireError> {
+    let batch_id = batch.id;
+    let dispatcher_id = batch.dispatcher_id;
+
+    info!(
+        ?batch_id,
+        ?dispatcher_id,
+        readings_count = batch.readings.len(),
+        statuses_count = batch.statuses.len(),
+        "received batch upload"
+    );
+
+    let rejected = |reason| {
+        Ok(BatchUploadResponse::Rejected {
+            id: batch_id,
+            reason,
+        })
+    };
+
+    match metrics::timed(
+        "dispatchers.get",
+        registries.dispatchers().get(dispatcher_id),
+    )
+    .await
+    {
+        Ok(Some(dispatcher)) if dispatcher.state == DispatcherState::Active => {}
+        Ok(Some(dispatcher)) if dispatcher.state == DispatcherState::Pending => {
+            warn!(
+                ?dispatcher_id,
+                "rejecting batch from dispatcher awaiting approval"
+            );
+            return rejected(BatchRejectionReason::PendingApproval);
+        }
+        Ok(Some(_)) => {
+            warn!(?dispatcher_id, "rejecting batch from suspended dispatcher");
+            return rejected(BatchRejectionReason::Suspended);
+        }
+        Ok(None) => {
+            warn!(?dispatcher_id, "rejecting batch from unknown dispatcher");
+            return rejected(BatchRejectionReason::UnknownDispatcher);
+        }
+        Err(e) => {
+            error!(error = ?e, "failed to look up dispatcher");
+            return rejected(BatchRejectionReason::Unavailable);
+        }
+    }
+
+    let now = jiff::Timestamp::now();
+    match quotas.charge(dispatcher_id, batch.readings.len(), now) {
+        Admission::Within => {}
+        Admission::Flagged { limit } => {
+            warn!(?dispatcher_id, limit, "dispatcher over its hourly quota");
+            metrics::record_quota_excess(dispatcher_id, batch.readings.len(), QuotaAction::Flag);
+        }
+        Admission::Rejected { limit } => {
+            warn!(?dispatcher_id, limit, "refusing batch over hourly quota");
+            metrics::record_quota_excess(dispatcher_id, batch.readings.len(), QuotaAction::Reject);
+            return Err(WireError {
+                code: WireErrorCode::QuotaExceeded,
+                message: format!("quota of {limit} readings per hour exceeded"),
+            });
+        }
+    }
+
+    let device_ids = batch
+        .readings
+        .iter()
+        .map(|r| r.device_id)
+        .chain(batch.statuses.iter().map(|s| s.device_id))
+        .collect();
+    let checks = match item_checks(registries, auth, dispatcher_id, device_ids, now).await {
+        Ok(checks) => checks,
+        Err(e) => {
+            error!(error = ?e, "failed to look up devices");
+            return rejected(BatchRejectionReason::Unavailable);
+        }
+    };
+
+    let readings = validate(batch.readings.into_vec(), |r: &SensorReading| {
+        (r.id, checks.reading(r))
+    });
+    let statuses = validate(batch.statuses.into_vec(), |s: &DeviceStatus| {
+        (s.id, checks.status(s))
+    });
+    let (reading_checks, status_checks) = (readings.checks, statuses.checks);
+    let dead_letters: Vec<DeadLetter> = readings
+        .invalid
+        .into_iter()
+        .map(|(reading, reason)| (DeadItem::Reading(reading), reason))
+        .chain(
+            statuses
+                .invalid
+                .into_iter()
+                .map(|(status, reason)| (DeadItem::Status(status), reason)),
+        )
+        .map(|(item, reason)| DeadLetter::new(dispatcher_id, batch_id, item, reason, now))
+        .collect();
+    let (mut readings, mut statuses) = (readings.valid, statuses.valid);
+
+    let context = BatchContext {
+        batch_id,
+        dispatcher_id,
+        received_at: now,
+    };
+    if let Err((hook, e)) = hooks.run(&context, &mut readings, &mut statuses).await {
+        error!(error = %e, hook, ?batch_id, "ingestion hook failed");
+        return rejected(BatchRejectionReason::Unavailable);
+    }
+
+    let (crossed, crossings) = match thresholds
+        .crossings(registries, dispatcher_id, &readings)
+        .await
+    {
+        Ok(found) => found,
+        Err(e) => {
+            error!(error = %e, ?batch_id, "failed to check thresholds");
+            return rejected(BatchRejectionReason::Unavailable);
+        }
+    };
+
+    // Readings, statuses, refused items and the events they raise are stored
+    // together or not at all, so a failed upload leaves nothing behind for
+    // its retry to trip on.
+    let dead_lettered = dead_letters.len();
+    let batch = IngestBatch {
+        readings: readings.clone(),
+        statuses: statuses.clone(),
+        dead_letters,
+        events: crossed,
+    };
+    let ingested = match metrics::timed("ingest", registries.ingest().ingest(batch)).await {
+        Ok(ingested) => ingested,
+        Err(e) => {
+            error!(error = ?e, ?batch_id, "failed to store batch");
+            return rejected(BatchRejectionReason::Unavailable);
+        }
+    };
+    crossings.commit();
+    let stored_readings: HashSet<_> = ingested.readings.into_iter().collect();
+    let stored_statuses: HashSet<_> = ingested.statuses.into_iter().collect();
+    if dead_lettered > 0 {
+        warn!(
+            ?batch_id,
+            ?dispatcher_id,
+            count = dead_lettered,
+            "refused items dead-lettered"
+        );
+        metrics::record_dead_letters(dead_lettered);
+    }
+
+    let new_readings: Vec<SensorReading> = readings
+        .into_iter()
+        .filter(|reading| stored_readings.contains(&reading.id))
+        .collect();
+    if !new_readings.is_empty() {
+        events
+            .publish(BusEvent::ReadingsIngested {
+                readings: new_readings.into(),
+                late: false,
+            })
+            .await;
+    }
+    let new_statuses: Vec<DeviceStatus> = statuses
+        .into_iter()
+        .filter(|status| stored_statuses.contains(&status.id))
+        .collect();
+    if !new_statuses.is_empty() {
+        events
+            .publish(BusEvent::StatusesIngested {
+                statuses: new_statuses.into(),
+            })
+            .await;
+    }
+
+    let readings = outcomes(reading_checks, &stored_readings);
+    let statuses = outcomes(status_checks, &stored_statuses);
+
+    info!(
+        ?batch_id,
+        readings_stored = stored_readings.len(),
+        statuses_stored = stored_statuses.len(),
+        "batch processed"
+    );
+    debug!(
+        ?batch_id,
+        readings = ?stored_readings,
+        statuses = ?stored_statuses,
+        "stored items"
+    );
+
+    Ok(BatchUploadResponse::Accepted {
+        id: batch_id,
+        readings,
+        statuses,
+    })
+}
+
+/// Record a dispatcher's status report as its latest.
+///
+/// Like batches, reports are only accepted from registered, active dispatchers.
+pub async fn handle_dispatcher_status<R: Registries>(
+    registries: &R,
+    status: DispatcherStatus,
+) -> DispatcherStatusResponse {
+    let response = dispatcher_status_response(registries, status).await;
+    metrics::record_dispatcher_status(&response);
+
+    response
+}
+
+async fn dispatcher_status_response<R: Registries>(
+    registries: &R,
+    status: DispatcherStatus,
+) -> DispatcherStatusResponse {
+    let dispatcher_id = status.dispatcher_id;
+    let rejected = |reason| DispatcherStatusResponse::Rejected { reason };
+
+    match metrics::timed(
+        "dispatchers.get",
+        registries.dispatchers().get(dispatcher_id),
+    )
+    .await
+    {
+        Ok(Some(dispatcher)) if dispatcher.state == DispatcherState::Active => {}
+        Ok(Some(dispatcher)) if dispatcher.state == DispatcherState::Pending => {
+            warn!(
+                ?dispatcher_id,
+                "rejecting status from dispatcher awaiting approval"
+            );
+            return rejected(BatchRejectionReason::PendingApproval);
+        }
+        Ok(Some(_)) => {
+            warn!(?dispatcher_id, "rejecting status from suspended dispatcher");
+            return rejected(BatchRejectionReason::Suspended);
+        }
+        Ok(None) => {
+            warn!(?dispatcher_id, "rejecting status from unknown dispatcher");
+            return rejected(BatchRejectionReason::UnknownDispatcher);
+        }
+        Err(e) => {
+            error!(error = ?e, "failed to look up dispatcher");
+            return rejected(BatchRejectionReason::Unavailable);
+        }
+    }
+
+    debug!(
+        ?dispatcher_id,
+        pending_readings = status.pending_readings,
+        pending_statuses = status.pending_statuses,
+        rtt_ms = ?status.link.rtt_ms,
+        "received dispatcher status"
+    );
+
+    let report = DispatcherReport {
+        status,
+        received_at: jiff::Timestamp::now(),
+    };
+
+    match metrics::timed(
+        "dispatcher_statuses.record",
+        registries.dispatcher_statuses().record(report),
+    )
+    .await
+    {
+        Ok(()) => DispatcherStatusResponse::Accepted,
+        Err(e) => {
+            error!(error = ?e, ?dispatcher_id, "failed to record dispatcher status");
+            rejected(BatchRejectionReason::Unavailable)
+        }
+    }
+}
+
+/// Acknowledge the commands a dispatcher passed on and hand it the next ones.
+pub async fn handle_command_poll<R: Registries>(
+    registries: &R,
+    poll: CommandPoll,
+) -> CommandPollResponse {
+    let response = command_poll_response(registries, poll).await;
+    metrics::record_command_poll(&response);
+
+    response
+}
+
+async fn command_poll_response<R: Registries>(
+    registries: &R,
+    poll: CommandPoll,
+) -> CommandPollResponse {
+    let dispatcher_id = poll.dispatcher_id;
+    let rejected = |reason| CommandPollResponse::Rejected { reason };
+
+    match metrics::timed(
+        "dispatchers.get",
+        registries.dispatchers().get(dispatcher_id),
+    )
+    .await
+    {
+        Ok(Some(dispatcher)) if dispatcher.state == DispatcherState::Active => {}
+        Ok(Some(_)) => {
+            warn!(
+                ?dispatcher_id,
+                "rejecting command poll from suspended dispatcher"
+            );
+            return rejected(BatchRejectionReason::Suspended);
+        }
+        Ok(None) => {
+            warn!(
+                ?dispatcher_id,
+                "rejecting command poll from unknown dispatcher"
+            );
+            return rejected(BatchRejectionReason::UnknownDispatcher);
+        }
+        Err(e) => {
+            error!(error = ?e, "failed to look up dispatcher");
+            return rejected(BatchRejectionReason::Unavailable);
+        }
+    }
+
+    let commands = registries.commands();
+    let now = jiff::Timestamp::now();
+
+    let delivered = async {
+        commands.expire(now).await?;
+        let acked = commands
+            .ack(dispatcher_id, poll.acks.into_vec(), now)
+            .await?;
+        let delivered = commands
+            .deliver(dispatcher_id, now, COMMANDS_PER_POLL)
+            .await?;
+        Ok::<_, <R::Commands as CommandRegistry>::Error>((acked, delivered))
+    };
+
+    match metrics::timed("commands.poll", delivered).await {
+        Ok((acked, delivered)) => {
+            debug!(
+                ?dispatcher_id,
+                acked,
+                delivered = delivered.len(),
+                "handled command poll"
+            );
+            CommandPollResponse::Accepted {
+                commands: delivered.iter().map(|command| command.to_wire()).collect(),
+            }
+        }
+        Err(e) => {
+            error!(error = ?e, ?dispatcher_id, "failed to poll commands");
+            rejected(BatchRejectionReason::Unavailable)
+        }
+    }
+}
+
+/// Mark the devices a dispatcher stopped hearing from as disconnected,
+/// raising a `device_offline` event for each, and those it hears from again
+/// as connected.
+///
+/// Devices prime doesn't know, has decommissioned or assigned to another
+/// dispatcher are left as they are.
+pub async fn handle_device_disconnection<R: Registries>(
+    registries: &R,
+    events: &dyn EventBus,
+    request: DeviceDisconnectionRequest,
+) -> DeviceDisconnectionResponse {
+    let response = device_disconnection_response(registries, events, request).await;
+    metrics::record_device_disconnection(&response);
+
+    response
+}
+
+async fn device_disconnection_response<R: Registries>(
+    registries: &R,
+    events: &dyn EventBus,
+    request: DeviceDisconnectionRequest,
+) -> DeviceDisconnectionResponse {
+    let dispatcher_id = request.dispatcher_id;
+    let rejected = |reason| DeviceDisconnectionResponse::Rejected { reason };
+
+    match metrics::timed(
+        "dispatchers.get",
+        registries.dispatchers().get(dispatcher_id),
+    )
+    .await
+    {
+        Ok(Some(dispatcher)) if dispatcher.state == DispatcherState::Active => {}
+        Ok(Some(_)) => {
+            warn!(
+                ?dispatcher_id,
+                "rejecting device disconnections from suspended dispatcher"
+            );
+            return rejected(BatchRejectionReason::Suspended);
+        }
+        Ok(None) => {
+            warn!(
+                ?dispatcher_id,
+                "rejecting device disconnections from unknown dispatcher"
+            );
+            return rejected(BatchRejectionReason::UnknownDispatcher);
+        }
+        Err(e) => {
+            error!(error = ?e, "failed to look up dispatcher");
+            return rejected(BatchRejectionReason::Unavailable);
+        }
+    }
+
+    let devices = registries.devices();
+    // Whether the dispatcher speaks for the device.
+    let reports_for = |id| async move {
+        let reporting = match devices.get(id).await? {
+            Some(device) => device.state != DeviceState::Decommissioned,
+            None => false,
+        };
+        let assigned = devices.dispatcher(id).await?;
+        Ok::<_, <R::Devices as DeviceRegistry>::Error>(
+            reporting && assigned.is_none_or(|assigned| assigned == dispatcher_id),
+        )
+    };
+    let marked = async {
+        // Retried requests don't raise events again.
+        let already: HashSet<DeviceId> = devices
+            .disconnected()
+            .await?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+
+        let mut offline = Vec::new();
+        for disconnection in request.disconnected.iter() {
+            let id = disconnection.device_id;
+            if already.contains(&id) || !reports_for(id).await? {
+                continue;
+            }
+            let org_id = devices.org(id).await?;
+            let event = offline_event(id, dispatcher_id, disconnection.last_seen, org_id);
+            devices
+                .mark_offline(id, disconnection.last_seen, event)
+                .await?;
+            offline.push((*disconnection, org_id));
+        }
+
+        let mut online = 0;
+        for &id in request.reconnected.iter() {
+            if already.contains(&id) && reports_for(id).await? {
+                devices.set_disconnected(id, None).await?;
+                online += 1;
+            }
+        }
+
+        Ok::<_, <R::Devices as DeviceRegistry>::Error>((offline, online))
+    };
+
+    let (offline, online) = match metrics::timed("devices.set_disconnected", marked).await {
+        Ok(marked) => marked,
+        Err(e) => {
+            error!(error = ?e, ?dispatcher_id, "failed to record device disconnections");
+            return rejected(BatchRejectionReason::Unavailable);
+        }
+    };
+
+    debug!(
+        ?dispatcher_id,
+        disconnected = offline.len(),
+        reconnected = online,
+        "handled device disconnections"
+    );
+    for (disconnection, org_id) in offline {
+        info!(
+            ?dispatcher_id,
+            device_id = ?disconnection.device_id,
+            last_seen = %disconnection.last_seen,
+            "device disconnected"
+        );
+        events
+            .publish(BusEvent::DeviceOffline {
+                device_id: disconnection.device_id,
+                dispatcher_id,
+                last_seen: disconnection.last_seen,
+                org_id,
+            })
+            .await;
+    }
+
+    DeviceDisconnectionResponse::Accepted
+}
+
+/// Push queued commands to connected dispatchers that accept pushes until
+/// cancelled, so they needn't wait for the next poll.
+pub async fn push_commands<R: Registries>(
+    registries: R,
+    dispatchers: Dispatchers,
+    cancel: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(COMMAND_PUSH_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        push_queued_commands(&registries, &dispatchers).await;
+    }
+}
+
+/// Hand each connected dispatcher its queued commands, acknowledging those
+/// it passed on. Returns how many were acknowledged.
+///
+/// Commands pushed but not acknowledged stay delivered until a poll
+/// acknowledges them or they expire, as with a poll whose response is lost.
+pub async fn push_queued_commands<R: Registries>(
+    registries: &R,
+    dispatchers: &Dispatchers,
+) -> usize {
+    let commands = registries.commands();
+    let mut acked = 0;
+
+    for dispatcher_id in dispatchers.connected() {
+        let now = jiff::Timestamp::now();
+        let delivered = match commands
+            .deliver(dispatcher_id, now, COMMANDS_PER_POLL)
+            .await
+        {
+            Ok(delivered) if delivered.is_empty() => continue,
+            Ok(delivered) => delivered,
+            Err(e) => {
+                error!(error = ?e, ?dispatcher_id, "failed to deliver commands");
+                continue;
+            }
+        };
+
+        let request = CommandDispatchRequest {
+            commands: delivered.iter().map(|command| command.to_wire()).collect(),
+        };
+        let response = match dispatchers
+            .dispatch_commands(dispatcher_id, request, COMMAND_PUSH_TIMEOUT)
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(error = %e, ?dispatcher_id, "failed to push commands");
+                continue;
+            }
+        };
+
+        match commands
+            .ack(
+                dispatcher_id,
+                response.acks.into_vec(),
+                jiff::Timestamp::now(),
+            )
+            .await
+        {
+            Ok(count) => {
+                debug!(
+                    ?dispatcher_id,
+                    pushed = delivered.len(),
+                    acked = count,
+                    "pushed commands"
+                );
+                acked += count;
+            }
+            Err(e) => error!(error = ?e, ?dispatcher_id, "failed to acknowledge pushed commands"),
+        }
+    }
+
+    acked
+}
+
+/// An item id with its outcome if it was settled before storing.
+type Checked<I> = (I, Result<(), ItemOutcome>);
+
+/// A batch's items split by the outcome of their checks.
+struct Validated<T, I> {
+    /// Items worth storing
+    valid: Vec<T>,
+    /// Items that failed validation, with the reason
+    invalid: Vec<(T, InvalidItemReason)>,
+    /// Per-item record of the checks, in batch order
+    checks: Vec<Checked<I>>,
+}
+
+/// Split items into those worth storing and those refused, recording the
+/// checks of each.
+///
+/// Items repeated within the batch are kept once; later copies are recorded
+/// as duplicates.
+fn validate<T, I>(
+    items: Vec<T>,
+    check: impl Fn(&T) -> (I, Option<InvalidItemReason>),
+) -> Validated<T, I>
+where
+    I: Copy + Eq + Hash,
+{
+    let mut seen = HashSet::with_capacity(items.len());
+    let mut valid = Vec::with_capacity(items.len());
+    let mut invalid = Vec::new();
+    let mut checks = Vec::with_capacity(items.len());
+
+    for item in items {
+        let (id, reason) = check(&item);
+        let checked = match reason {
+            Some(reason) => {
+                invalid.push((item, reason));
+                Err(ItemOutcome::Invalid(reason))
+            }
+            None if !seen.insert(id) => Err(ItemOutcome::Duplicate),
+            None => {
+                valid.push(item);
+                Ok(())
+            }
+        };
+        checks.push((id, checked));
+    }
+
+    Validated {
+        valid,
+        invalid,
+        checks,
+    }
+}
+
+fn outcomes<I>(checks: Vec<Checked<I>>, stored: &HashSet<I>) -> Box<[ItemResult<I>]>
+where
+    I: Copy + Eq + Hash,
+{
+    checks
+        .into_iter()
+        .map(|(id, checked)| {
+            let outcome = match checked {
+                Err(outcome) => outcome,
+                Ok(()) if stored.contains(&id) => ItemOutcome::Stored,
+                Ok(()) => ItemOutcome::Duplicate,
+            };
+            ItemResult { id, outcome }
+        })
+        .collect()
+}
+
+/// What prime knows about the devices in a batch.
+#[derive(Default)]
+struct DeviceStanding {
+    /// Sensors of each registered device
+    sensors: HashMap<DeviceId, HashSet<SensorId>>,
+    decommissioned: HashSet<DeviceId>,
+    /// Dispatcher each assigned device belongs to
+    assigned: HashMap<DeviceId, DispatcherId>,
+}
+
+/// Look up the devices items are reported for.
+async fn device_standing<R: Registries>(
+    registries: &R,
+    device_ids: HashSet<DeviceId>,
+) -> Result<DeviceStanding, <R::Devices as DeviceRegistry>::Error> {
+    let devices = registries.devices();
+    let mut standing = DeviceStanding::default();
+    for id in device_ids {
+        if let Some(device) = metrics::timed("devices.get", devices.get(id)).await? {
+            if device.state == DeviceState::Decommissioned {
+                standing.decommissioned.insert(id);
+            }
+            let sensors = device.sensors.iter().map(|sensor| sensor.id).collect();
+            standing.sensors.insert(id, sensors);
+        }
+        if let Some(dispatcher_id) =
+            metrics::timed("devices.dispatcher", devices.dispatcher(id)).await?
+        {
+            standing.assigned.insert(id, dispatcher_id);
+        }
+    }
+
+    Ok(standing)
+}
+
+/// The rules items `dispatcher_id` reports for `device_ids` are checked
+/// against as of `now`.
+pub(crate) async fn item_checks<R: Registries>(
+    registries: &R,
+    auth: AuthConfig,
+    dispatcher_id: DispatcherId,
+    device_ids: HashSet<DeviceId>,
+    now: jiff::Timestamp,
+) -> Result<ItemChecks, <R::Devices as DeviceRegistry>::Error> {
+    Ok(ItemChecks {
+        dispatcher_id,
+        devices: device_standing(registries, device_ids).await?,
+        require_assignment: auth.require_device_assignment,
+        quarantine_unknown: auth.quarantine_unknown_devices,
+        latest: now + MAX_FUTURE_SKEW,
+    })
+}
+
+/// Per-item validation rules for one batch.
+pub(crate) struct ItemChecks {
+    dispatcher_id: DispatcherId,
+    devices: DeviceStanding,
+    /// Refuse devices that aren't assigned to any dispatcher
+    require_assignment: bool,
+    /// Refuse devices and sensors that aren't registered
+    quarantine_unknown: bool,
+    /// Newest acceptable item timestamp.
+    latest: jiff::Timestamp,
+}
+
+impl ItemChecks {
+    /// Accept items timestamped up to `latest` instead, as far as they can
+    /// be stored.
+    pub(crate) fn accept_until(self, latest: jiff::Timestamp) -> Self {
+        Self {
+            latest: latest.min(LATEST_STORABLE),
+            ..self
+        }
+    }
+
+    /// Whether an item timestamped `at` is within the accepted range, so a
+    /// wild clock costs that item rather than failing the batch to store.
+    fn timestamp(&self, at: jiff::Timestamp) -> Option<InvalidItemReason> {
+        if at < EARLIEST_TIMESTAMP {
+            Some(InvalidItemReason::StaleTimestamp)
+        } else if at > self.latest {
+            Some(InvalidItemReason::FutureTimestamp)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the uploading dispatcher may report for `device_id` at all.
+    fn device(&self, device_id: DeviceId) -> Option<InvalidItemReason> {
+        let assigned = self.devices.assigned.get(&device_id);
+
+        if self.quarantine_unknown && !self.devices.sensors.contains_key(&device_id) {
+            Some(InvalidItemReason::UnknownDevice)
+        } else if self.devices.decommissioned.contains(&device_id) {
+            Some(InvalidItemReason::DeviceDecommissioned)
+        } else if assigned.map_or(self.require_assignment, |&id| id != self.dispatcher_id) {
+            Some(InvalidItemReason::DeviceNotAssigned)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the reading's device has the sensor it names.
+    fn has_sensor(&self, reading: &SensorReading) -> bool {
+        self.devices
+            .sensors
+            .get(&reading.device_id)
+            .is_some_and(|sensors| sensors.contains(&reading.sensor_id))
+    }
+
+    pub(crate) fn reading(&self, reading: &SensorReading) -> Option<InvalidItemReason> {
+        let percentage = match reading.metric {
+            SensorMetric::SoilMoisture { value } | SensorMetric::Humidity { value } => Some(value),
+            _ => None,
+        };
+
+        if reading.dispatcher_id != self.dispatcher_id {
+            Some(InvalidItemReason::DispatcherMismatch)
+        } else if let Some(reason) = self.device(reading.device_id) {
+            Some(reason)
+        } else if self.quarantine_unknown && !self.has_sensor(reading) {
+            Some(InvalidItemReason::UnknownSensor)
+        } else if reading.confidence.0 > 100 || percentage.is_some_and(|p| p.0 > 100) {
+            Some(InvalidItemReason::PercentageOutOfRange)
+        } else {
+            self.timestamp(reading.timestamp)
+        }
+    }
+
+    pub(crate) fn status(&self, status: &DeviceStatus) -> Option<InvalidItemReason> {
+        if status.dispatcher_id != self.dispatcher_id {
+            Some(InvalidItemReason::DispatcherMismatch)
+        } else if let Some(reason) = self.device(status.device_id) {
+            Some(reason)
+        } else if status.battery_percent.0 > 100 {
+            Some(InvalidItemReason::PercentageOutOfRange)
+        } else {
+            self.timestamp(status.timestamp)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{
+        BatchId, BatchRejectionReason, BatchUploadRequest, BatchUploadResponse,
+        CommandDispatchResponse, CommandId, CommandKind, CommandPoll, CommandPollResponse, Device,
+        DeviceDisconnection, DeviceDisconnectionRequest, DeviceDisconnectionResponse, DeviceId,
+        DeviceKind, DeviceState, DeviceStatus, Dispatcher, DispatcherId, DispatcherState,
+        DispatcherStatus, DispatcherStatusResponse, H3Cell, HelloRejectionReason, HelloRequest,
+        HelloResponse, InvalidItemReason, ItemOutcome, LinkQuality, Percentage, Sensor, SensorKind,
+        SensorReading, StatusId,
+    };
+    use ersha_rpc::auth::{SeenNonces, sign_hello, verify_server_proof};
+    use ersha_rpc::tls::{PeerCertificate, dispatcher_name};
+    use ersha_rpc::{
+        CancellationToken, Client, Router, RpcTcp, Server, WireErrorCode, WireMessage,
+    };
+    use jiff::SignedDuration;
+    use rcgen::{CertificateParams, KeyPair};
+    use tokio::net::{TcpListener, TcpStream};
+    use ulid::Ulid;
+
+    use super::{
+        handle_batch_upload, handle_command_poll, handle_device_disconnection,
+        handle_dispatcher_status, handle_hello, push_queued_commands,
+    };
+    use crate::command::{Command, CommandState};
+    use crate::config::{AuthConfig, QuotaAction, QuotaConfig, WebhookConfig};
+    use crate::dead_letter::{DeadLetter, QUARANTINE_REASONS};
+    use crate::events::{BusEvent, EventBus, LocalEventBus, Received};
+    use crate::fixtures;
+    use crate::hooks::{BatchContext, HookError, IngestionHook, IngestionHooks};
+    use crate::idempotency::RecentBatches;
+    use crate::outbox;
+    use crate::quota::IngestQuotas;
+    use crate::registry::{
+        AggregateRegistry, CommandRegistry, DeadLetterRegistry, DeviceRegistry,
+        DeviceStatusRegistry, DispatcherRegistry, DispatcherStatusRegistry, ReadingRegistry,
+        WebhookRegistry,
+        filter::{AggregateFilter, DeadLetterFilter},
+        memory::InMemoryRegistries,
+    };
+    use crate::rollup::{self, Granularity};
+    use crate::webhook::{Direction, EventKind, Threshold, ThresholdWatch, Webhook};
+
+    const LOCATION: H3Cell = H3Cell(0x8a2a1072b59ffff);
+
+    fn required() -> AuthConfig {
+        AuthConfig {
+            require_dispatcher_auth: true,
+            ..Default::default()
+        }
+    }
+
+    /// Ingestion accepting devices prime has never registered.
+    fn unchecked() -> AuthConfig {
+        AuthConfig {
+            quarantine_unknown_devices: false,
+            ..Default::default()
+        }
+    }
+
+    fn hello(dispatcher_id: DispatcherId, secret: Option<&str>) -> HelloRequest {
+        HelloRequest {
+            dispatcher_id,
+            location: LOCATION,
+            credentials: secret.map(|secret| {
+                sign_hello(
+                    secret.as_bytes(),
+                    dispatcher_id,
+                    LOCATION,
+                    jiff::Timestamp::now(),
+                    7,
+                )
+            }),
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
+        }
+    }
+
+    async fn provisioned(registries: &InMemoryRegistries, secret: &str) -> DispatcherId {
+        let id = DispatcherId(Ulid::new());
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id,
+                location: LOCATION,
+                state: DispatcherState::Active,
+                provisioned_at: jiff::Timestamp::now(),
+            })
+            .await
+            .unwrap();
+        registries
+            .dispatchers
+            .set_secret(id, Some(secret.to_owned()))
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn signed_hello_is_accepted_with_proof() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+
+        let request = hello(id, Some("s3cret"));
+        let credentials = request.credentials.unwrap();
+
+        match handle_hello(
+            &registries,
+            required(),
+            &SeenNonces::default(),
+            request,
+            None,
+        )
+        .await
+        {
+            HelloResponse::Accepted {
+                proof: Some(proof), ..
+            } => assert!(verify_server_proof(b"s3cret", &credentials, &proof)),
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn replayed_hello_is_unauthorized() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let nonces = SeenNonces::default();
+        let request = hello(id, Some("s3cret"));
+
+        assert!(matches!(
+            handle_hello(&registries, required(), &nonces, request.clone(), None).await,
+            HelloResponse::Accepted { .. }
+        ));
+        assert_eq!(
+            handle_hello(&registries, required(), &nonces, request, None).await,
+            HelloResponse::Rejected {
+                dispatcher_id: id,
+                reason: HelloRejectionReason::Unauthorized,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_or_missing_signature_is_unauthorized() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+
+        for request in [hello(id, Some("wrong")), hello(id, None)] {
+            assert_eq!(
+                handle_hello(
+                    &registries,
+                    AuthConfig::default(),
+                    &SeenNonces::default(),
+                    request,
+                    None
+                )
+                .await,
+                HelloResponse::Rejected {
+                    dispatcher_id: id,
+                    reason: HelloRejectionReason::Unauthorized,
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_dispatcher_depends_on_policy() {
+        let registries = InMemoryRegistries::default();
+        let id = DispatcherId(Ulid::new());
+
+        assert!(matches!(
+            handle_hello(
+                &registries,
+                required(),
+                &SeenNonces::default(),
+                hello(id, None),
+                None
+            )
+            .await,
+            HelloResponse::Rejected {
+                reason: HelloRejectionReason::Unauthorized,
+                ..
+            }
+        ));
+        assert!(registries.dispatchers.get(id).await.unwrap().is_none());
+
+        let open = AuthConfig {
+            approve_new_dispatchers: false,
+            ..AuthConfig::default()
+        };
+        assert!(matches!(
+            handle_hello(
+                &registries,
+                open,
+                &SeenNonces::default(),
+                hello(id, None),
+                None
+            )
+            .await,
+            HelloResponse::Accepted { proof: None, .. }
+        ));
+        assert!(registries.dispatchers.get(id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn unknown_dispatchers_wait_for_approval() {
+        let registries = InMemoryRegistries::default();
+        let id = DispatcherId(Ulid::new());
+        let pending = HelloResponse::Rejected {
+            dispatcher_id: id,
+            reason: HelloRejectionReason::PendingApproval,
+        };
+
+        for _ in 0..2 {
+            assert_eq!(
+                handle_hello(
+                    &registries,
+                    AuthConfig::default(),
+                    &SeenNonces::default(),
+                    hello(id, None),
+                    None
+                )
+                .await,
+                pending
+            );
+        }
+        let recorded = registries.dispatchers.get(id).await.unwrap().unwrap();
+        assert_eq!(recorded.state, DispatcherState::Pending);
+
+        let (quotas, events, recent, hooks, thresholds) = (
+            IngestQuotas::default(),
+            LocalEventBus::new(),
+            RecentBatches::default(),
+            IngestionHooks::default(),
+            ThresholdWatch::default(),
+        );
+        let upload = || {
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &quotas,
+                &events,
+                &recent,
+                &hooks,
+                &thresholds,
+                batch(id, vec![reading(id)], vec![]),
+            )
+        };
+        assert!(matches!(
+            upload().await.unwrap(),
+            BatchUploadResponse::Rejected {
+                reason: BatchRejectionReason::PendingApproval,
+                ..
+            }
+        ));
+
+        // Approved by an operator.
+        registries.dispatchers.reactivate(id).await.unwrap();
+        assert!(matches!(
+            handle_hello(
+                &registries,
+                AuthConfig::default(),
+                &SeenNonces::default(),
+                hello(id, None),
+                None
+            )
+            .await,
+            HelloResponse::Accepted { .. }
+        ));
+        assert_eq!(outcomes(upload().await.unwrap()).0, [ItemOutcome::Stored]);
+    }
+
+    fn certificate(dispatcher_id: DispatcherId) -> PeerCertificate {
+        let key = KeyPair::generate().unwrap();
+        let certificate = CertificateParams::new(vec![dispatcher_name(dispatcher_id)])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        PeerCertificate::new(certificate.der().clone())
+    }
+
+    #[tokio::test]
+    async fn client_certificate_must_name_a_registered_dispatcher() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let other = provisioned(&registries, "0th3r").await;
+        let unknown = DispatcherId(Ulid::new());
+
+        for (request, peer) in [
+            (hello(id, Some("s3cret")), certificate(other)),
+            (hello(unknown, None), certificate(unknown)),
+        ] {
+            assert!(matches!(
+                handle_hello(
+                    &registries,
+                    AuthConfig::default(),
+                    &SeenNonces::default(),
+                    request,
+                    Some(&peer)
+                )
+                .await,
+                HelloResponse::Rejected {
+                    reason: HelloRejectionReason::CertificateMismatch,
+                    ..
+                }
+            ));
+        }
+        assert!(registries.dispatchers.get(unknown).await.unwrap().is_none());
+
+        let unsecured = DispatcherId(Ulid::new());
+        registries
+            .dispatchers
+            .register(Dispatcher {
+                id: unsecured,
+                location: LOCATION,
+                state: DispatcherState::Active,
+                provisioned_at: jiff::Timestamp::now(),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            handle_hello(
+                &registries,
+                required(),
+                &SeenNonces::default(),
+                hello(unsecured, None),
+                Some(&certificate(unsecured))
+            )
+            .await,
+            HelloResponse::Accepted { proof: None, .. }
+        ));
+    }
+
+    fn reading(dispatcher_id: DispatcherId) -> SensorReading {
+        SensorReading {
+            dispatcher_id,
+            location: LOCATION,
+            ..fixtures::reading()
+        }
+    }
+
+    fn status(dispatcher_id: DispatcherId) -> DeviceStatus {
+        DeviceStatus {
+            id: StatusId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id,
+            battery_percent: Percentage(80),
+            uptime_seconds: 60,
+            signal_rssi: -70,
+            errors: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+            sensor_statuses: Box::new([]),
+        }
+    }
+
+    fn batch(
+        dispatcher_id: DispatcherId,
+        readings: Vec<SensorReading>,
+        statuses: Vec<DeviceStatus>,
+    ) -> BatchUploadRequest {
+        BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            dispatcher_id,
+            readings: readings.into_boxed_slice(),
+            statuses: statuses.into_boxed_slice(),
+            timestamp: jiff::Timestamp::now(),
+        }
+    }
+
+    fn outcomes(response: BatchUploadResponse) -> (Vec<ItemOutcome>, Vec<ItemOutcome>) {
+        match response {
+            BatchUploadResponse::Accepted {
+                readings, statuses, ..
+            } => (
+                readings.iter().map(|r| r.outcome).collect(),
+                statuses.iter().map(|s| s.outcome).collect(),
+            ),
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_is_stored_once() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let events = LocalEventBus::new();
+        let rollups = tokio::spawn(rollup::run(
+            registries.clone(),
+            events.subscribe(),
+            CancellationToken::new(),
+        ));
+
+        let first = reading(id);
+        let request = batch(id, vec![first.clone(), first.clone()], vec![status(id)]);
+
+        let (readings, statuses) = outcomes(
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &IngestQuotas::default(),
+                &events,
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &ThresholdWatch::default(),
+                request.clone(),
+            )
+            .await
+            .unwrap(),
+        );
+        assert_eq!(readings, [ItemOutcome::Stored, ItemOutcome::Duplicate]);
+        assert_eq!(statuses, [ItemOutcome::Stored]);
+
+        // A retried batch is acknowledged without storing anything twice.
+        let (readings, statuses) = outcomes(
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &IngestQuotas::default(),
+                &events,
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &ThresholdWatch::default(),
+                request,
+            )
+            .await
+            .unwrap(),
+        );
+        assert_eq!(readings, [ItemOutcome::Duplicate, ItemOutcome::Duplicate]);
+        assert_eq!(statuses, [ItemOutcome::Duplicate]);
+
+        assert_eq!(registries.readings.count(None).await.unwrap(), 1);
+        assert_eq!(registries.statuses.count(None).await.unwrap(), 1);
+
+        // Rollups only count the reading that was actually stored.
+        drop(events);
+        rollups.await.unwrap();
+        let hourly = registries
+            .aggregates
+            .list(AggregateFilter::builder(Granularity::Hour).build())
+            .await
+            .unwrap();
+        assert_eq!(hourly.len(), 1);
+        assert_eq!(hourly[0].count, 1);
+        assert_eq!(hourly[0].sum, 40.0);
+    }
+
+    #[tokio::test]
+    async fn retried_batch_gets_its_first_response() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let (quotas, events, recent, hooks, thresholds) = (
+            IngestQuotas::default(),
+            LocalEventBus::default(),
+            RecentBatches::default(),
+            IngestionHooks::default(),
+            ThresholdWatch::default(),
+        );
+        let request = batch(id, vec![reading(id)], vec![]);
+
+        let upload = |request| {
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &quotas,
+                &events,
+                &recent,
+                &hooks,
+                &thresholds,
+                request,
+            )
+        };
+        let first = upload(request.clone()).await.unwrap();
+        let retried = upload(request.clone()).await.unwrap();
+        assert_eq!(retried, first);
+        assert_eq!(outcomes(retried).0, [ItemOutcome::Stored]);
+
+        // The same items under a new id are processed again.
+        let resent = BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            ..request
+        };
+        assert_eq!(
+            outcomes(upload(resent).await.unwrap()).0,
+            [ItemOutcome::Duplicate]
+        );
+        assert_eq!(registries.readings.count(None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_items_are_skipped() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+
+        let foreign = reading(DispatcherId(Ulid::new()));
+        let overfull = SensorReading {
+            confidence: Percentage(101),
+            ..reading(id)
+        };
+        let future = DeviceStatus {
+            timestamp: jiff::Timestamp::now() + std::time::Duration::from_secs(3600),
+            ..status(id)
+        };
+
+        let request = batch(id, vec![foreign, overfull, reading(id)], vec![future]);
+        let (readings, statuses) = outcomes(
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &IngestQuotas::default(),
+                &LocalEventBus::default(),
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &ThresholdWatch::default(),
+                request,
+            )
+            .await
+            .unwrap(),
+        );
+
+        assert_eq!(
+            readings,
+            [
+                ItemOutcome::Invalid(InvalidItemReason::DispatcherMismatch),
+                ItemOutcome::Invalid(InvalidItemReason::PercentageOutOfRange),
+                ItemOutcome::Stored,
+            ]
+        );
+        assert_eq!(
+            statuses,
+            [ItemOutcome::Invalid(InvalidItemReason::FutureTimestamp)]
+        );
+        assert_eq!(registries.readings.count(None).await.unwrap(), 1);
+        assert_eq!(registries.statuses.count(None).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn items_from_unset_clocks_are_skipped() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        // Before what the registries can store as nanoseconds.
+        let ancient = SensorReading {
+            timestamp: "1600-01-01T00:00:00Z".parse().unwrap(),
+            ..reading(id)
+        };
+        let epoch = DeviceStatus {
+            timestamp: jiff::Timestamp::UNIX_EPOCH,
+            ..status(id)
+        };
+
+        let request = batch(id, vec![ancient, reading(id)], vec![epoch]);
+        let (readings, statuses) = outcomes(
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &IngestQuotas::default(),
+                &LocalEventBus::default(),
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &ThresholdWatch::default(),
+                request,
+            )
+            .await
+            .unwrap(),
+        );
+
+        assert_eq!(
+            readings,
+            [
+                ItemOutcome::Invalid(InvalidItemReason::StaleTimestamp),
+                ItemOutcome::Stored,
+            ]
+        );
+        assert_eq!(
+            statuses,
+            [ItemOutcome::Invalid(InvalidItemReason::StaleTimestamp)]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_requires_active_dispatcher() {
+        let registries = InMemoryRegistries::default();
+
+        let unknown = DispatcherId(Ulid::new());
+        let response = handle_batch_upload(
+            &registries,
+            unchecked(),
+            &IngestQuotas::default(),
+            &LocalEventBus::default(),
+            &RecentBatches::default(),
+            &IngestionHooks::default(),
+            &ThresholdWatch::default(),
+            batch(unknown, vec![reading(unknown)], vec![]),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            response,
+            BatchUploadResponse::Rejected {
+                reason: BatchRejectionReason::UnknownDispatcher,
+                ..
+            }
+        ));
+
+        let id = provisioned(&registries, "s3cret").await;
+        registries.dispatchers.suspend(id).await.unwrap();
+        let response = handle_batch_upload(
+            &registries,
+            unchecked(),
+            &IngestQuotas::default(),
+            &LocalEventBus::default(),
+            &RecentBatches::default(),
+            &IngestionHooks::default(),
+            &ThresholdWatch::default(),
+            batch(id, vec![reading(id)], vec![]),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            response,
+            BatchUploadResponse::Rejected {
+                reason: BatchRejectionReason::Suspended,
+                ..
+            }
+        ));
+
+        assert_eq!(registries.readings.count(None).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn batches_over_quota_are_refused() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let quotas = IngestQuotas::new(QuotaConfig {
+            readings_per_hour: Some(2),
+            action: QuotaAction::Reject,
+            ..QuotaConfig::default()
+        });
+        let events = LocalEventBus::default();
+        let recent = RecentBatches::default();
+        let hooks = IngestionHooks::default();
+        let thresholds = ThresholdWatch::default();
+        let upload = |readings| {
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &quotas,
+                &events,
+                &recent,
+                &hooks,
+                &thresholds,
+                batch(id, readings, vec![]),
+            )
+        };
+
+        assert!(upload(vec![reading(id), reading(id)]).await.is_ok());
+        let error = upload(vec![reading(id)]).await.unwrap_err();
+        assert_eq!(error.code, WireErrorCode::QuotaExceeded);
+
+        assert_eq!(registries.readings.count(None).await.unwrap(), 2);
+        assert_eq!(quotas.over_quota(jiff::Timestamp::now()).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stored_readings_are_published() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let events = LocalEventBus::new();
+        let mut live = events.subscribe();
+
+        let first = reading(id);
+        let request = batch(id, vec![first.clone()], vec![]);
+        handle_batch_upload(
+            &registries,
+            unchecked(),
+            &IngestQuotas::default(),
+            &events,
+            &RecentBatches::default(),
+            &IngestionHooks::default(),
+            &ThresholdWatch::default(),
+            request.clone(),
+        )
+        .await
+        .unwrap();
+        // A replayed batch stores nothing new and publishes nothing.
+        handle_batch_upload(
+            &registries,
+            unchecked(),
+            &IngestQuotas::default(),
+            &events,
+            &RecentBatches::default(),
+            &IngestionHooks::default(),
+            &ThresholdWatch::default(),
+            request,
+        )
+        .await
+        .unwrap();
+
+        drop(events);
+        match live.recv().await {
+            Some(Received::Event(event)) => assert!(matches!(
+                &*event,
+                BusEvent::ReadingsIngested { readings, late: false } if readings[..] == [first]
+            )),
+            other => panic!("expected the stored readings, got {other:?}"),
+        }
+        assert!(live.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn threshold_crossings_are_staged_with_their_readings() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let watcher = |value| {
+            Webhook::generate(
+                "http://localhost/hook".to_owned(),
+                vec![EventKind::ThresholdCrossed],
+                vec![Threshold {
+                    metric: SensorKind::SoilMoisture,
+                    direction: Direction::Below,
+                    value,
+                }],
+            )
+        };
+        let (dry, parched) = (watcher(50.0), watcher(10.0));
+        registries.webhooks.create(dry.clone()).await.unwrap();
+        registries.webhooks.create(parched.clone()).await.unwrap();
+
+        let thresholds = ThresholdWatch::default();
+        let first = reading(id);
+        let mut second = reading(id);
+        second.sensor_id = first.sensor_id;
+        for reading in [first, second] {
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &IngestQuotas::default(),
+                &LocalEventBus::new(),
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &thresholds,
+                batch(id, vec![reading], vec![]),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Staying past the threshold doesn't raise a second crossing, and
+        // only the webhook whose threshold was crossed hears of it.
+        let config = WebhookConfig::default();
+        let later = jiff::Timestamp::now() + SignedDuration::from_secs(1);
+        assert_eq!(
+            outbox::relay_due(&registries, &config, later)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            registries
+                .webhooks
+                .deliveries(dry.id, 10)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(
+            registries
+                .webhooks
+                .deliveries(parched.id, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    /// Marks readings as checked against the weather, or fails.
+    struct Weather {
+        reachable: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl IngestionHook for Weather {
+        async fn on_reading(
+            &self,
+            _batch: &BatchContext,
+            reading: &mut SensorReading,
+        ) -> Result<(), HookError> {
+            if !self.reachable {
+                return Err(HookError::new("weather service unreachable"));
+            }
+            reading.confidence = Percentage(50);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks_process_readings_before_they_are_stored() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let upload = |hooks: IngestionHooks| {
+            let registries = registries.clone();
+            async move {
+                let first = reading(id);
+                let response = handle_batch_upload(
+                    &registries,
+                    unchecked(),
+                    &IngestQuotas::default(),
+                    &LocalEventBus::new(),
+                    &RecentBatches::default(),
+                    &hooks,
+                    &ThresholdWatch::default(),
+                    batch(id, vec![first.clone()], vec![]),
+                )
+                .await
+                .unwrap();
+                (first.id, response)
+            }
+        };
+
+        let (stored, response) =
+            upload(IngestionHooks::new().with(Weather { reachable: true })).await;
+        assert_eq!(outcomes(response).0, [ItemOutcome::Stored]);
+        let reading = registries.readings.get(stored).await.unwrap().unwrap();
+        assert_eq!(reading.confidence, Percentage(50));
+
+        let (refused, response) =
+            upload(IngestionHooks::new().with(Weather { reachable: false })).await;
+        assert!(matches!(
+            response,
+            BatchUploadResponse::Rejected {
+                reason: BatchRejectionReason::Unavailable,
+                ..
+            }
+        ));
+        assert!(registries.readings.get(refused).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn decommissioned_device_data_is_refused() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+
+        let retired = reading(id);
+        registries
+            .devices
+            .register(Device {
+                id: retired.device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Decommissioned,
+                location: LOCATION,
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([]),
+            })
+            .await
+            .unwrap();
+
+        // Devices prime has never heard of are accepted unless quarantined.
+        let request = batch(id, vec![retired, reading(id)], vec![]);
+        let (readings, _) = outcomes(
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &IngestQuotas::default(),
+                &LocalEventBus::default(),
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &ThresholdWatch::default(),
+                request,
+            )
+            .await
+            .unwrap(),
+        );
+
+        assert_eq!(
+            readings,
+            [
+                ItemOutcome::Invalid(InvalidItemReason::DeviceDecommissioned),
+                ItemOutcome::Stored,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_devices_and_sensors_are_quarantined() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+
+        let (known, stray, unregistered) = (reading(id), reading(id), reading(id));
+        registries
+            .devices
+            .register(Device {
+                id: known.device_id,
+                kind: DeviceKind::Sensor,
+                state: DeviceState::Active,
+                location: LOCATION,
+                manufacturer: None,
+                provisioned_at: jiff::Timestamp::now(),
+                sensors: Box::new([Sensor {
+                    id: known.sensor_id,
+                    metric: known.metric.clone(),
+                    kind: SensorKind::SoilMoisture,
+                }]),
+            })
+            .await
+            .unwrap();
+        let stray = SensorReading {
+            device_id: known.device_id,
+            ..stray
+        };
+
+        let request = batch(id, vec![known, stray, unregistered], vec![status(id)]);
+        let (readings, statuses) = outcomes(
+            handle_batch_upload(
+                &registries,
+                AuthConfig::default(),
+                &IngestQuotas::default(),
+                &LocalEventBus::default(),
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &ThresholdWatch::default(),
+                request,
+            )
+            .await
+            .unwrap(),
+        );
+
+        assert_eq!(
+            readings,
+            [
+                ItemOutcome::Stored,
+                ItemOutcome::Invalid(InvalidItemReason::UnknownSensor),
+                ItemOutcome::Invalid(InvalidItemReason::UnknownDevice),
+            ]
+        );
+        assert_eq!(
+            statuses,
+            [ItemOutcome::Invalid(InvalidItemReason::UnknownDevice)]
+        );
+        let quarantined = registries
+            .dead_letters
+            .list(
+                DeadLetterFilter::builder()
+                    .reasons(QUARANTINE_REASONS)
+                    .build(),
+                10,
+            )
+            .await
+            .unwrap();
+        assert_eq!(quarantined.len(), 3);
+        assert!(quarantined.iter().all(DeadLetter::is_quarantined));
+    }
+
+    #[tokio::test]
+    async fn devices_assigned_elsewhere_are_refused() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+
+        let (own, foreign, unassigned) = (reading(id), reading(id), reading(id));
+        for (device_id, dispatcher_id) in [
+            (own.device_id, id),
+            (foreign.device_id, DispatcherId(Ulid::new())),
+        ] {
+            registries
+                .devices
+                .register(Device {
+                    id: device_id,
+                    kind: DeviceKind::Sensor,
+                    state: DeviceState::Active,
+                    location: LOCATION,
+                    manufacturer: None,
+                    provisioned_at: jiff::Timestamp::now(),
+                    sensors: Box::new([]),
+                })
+                .await
+                .unwrap();
+            registries
+                .devices
+                .set_dispatcher(device_id, Some(dispatcher_id))
+                .await
+                .unwrap();
+        }
+
+        let request = batch(id, vec![own, foreign, unassigned], vec![]);
+        let (lenient, _) = outcomes(
+            handle_batch_upload(
+                &registries,
+                unchecked(),
+                &IngestQuotas::default(),
+                &LocalEventBus::default(),
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &ThresholdWatch::default(),
+                request.clone(),
+            )
+            .await
+            .unwrap(),
+        );
+        assert_eq!(
+            lenient,
+            [
+                ItemOutcome::Stored,
+                ItemOutcome::Invalid(InvalidItemReason::DeviceNotAssigned),
+                ItemOutcome::Stored,
+            ]
+        );
+
+        let strict = AuthConfig {
+            require_device_assignment: true,
+            ..unchecked()
+        };
+        let request = BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            ..request
+        };
+        let (strict, _) = outcomes(
+            handle_batch_upload(
+                &registries,
+                strict,
+                &IngestQuotas::default(),
+                &LocalEventBus::default(),
+                &RecentBatches::default(),
+                &IngestionHooks::default(),
+                &ThresholdWatch::default(),
+                request,
+            )
+            .await
+            .unwrap(),
+        );
+        assert_eq!(
+            strict,
+            [
+                ItemOutcome::Duplicate,
+                ItemOutcome::Invalid(InvalidItemReason::DeviceNotAssigned),
+                ItemOutcome::Invalid(InvalidItemReason::DeviceNotAssigned),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dispatcher_status_is_recorded_for_known_dispatchers() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let status = |dispatcher_id| DispatcherStatus {
+            dispatcher_id,
+            pending_readings: 42,
+            pending_statuses: 1,
+            link: LinkQuality {
+                rtt_ms: Some(120),
+                failed_uploads: 2,
+            },
+            uptime_seconds: 900,
+            timestamp: jiff::Timestamp::now(),
+        };
+
+        let response = handle_dispatcher_status(&registries, status(id)).await;
+        assert_eq!(response, DispatcherStatusResponse::Accepted);
+
+        let report = registries.dispatcher_statuses.latest(id).await.unwrap();
+        assert_eq!(report.unwrap().status.pending_readings, 42);
+
+        let unknown = DispatcherId(Ulid::new());
+        let response = handle_dispatcher_status(&registries, status(unknown)).await;
+        assert_eq!(
+            response,
+            DispatcherStatusResponse::Rejected {
+                reason: BatchRejectionReason::UnknownDispatcher
+            }
+        );
+        assert_eq!(
+            registries
+                .dispatcher_statuses
+                .latest(unknown)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn device_disconnections_are_recorded_for_own_devices() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let events = LocalEventBus::new();
+        let mut offline = events.subscribe();
+
+        let (own, foreign, unknown) = (
+            DeviceId(Ulid::new()),
+            DeviceId(Ulid::new()),
+            DeviceId(Ulid::new()),
+        );
+        for (device_id, dispatcher_id) in [(own, id), (foreign, DispatcherId(Ulid::new()))] {
+            registries
+                .devices
+                .register(Device {
+                    id: device_id,
+                    kind: DeviceKind::Sensor,
+                    state: DeviceState::Active,
+                    location: LOCATION,
+                    manufacturer: None,
+                    provisioned_at: jiff::Timestamp::now(),
+                    sensors: Box::new([]),
+                })
+                .await
+                .unwrap();
+            registries
+                .devices
+                .set_dispatcher(device_id, Some(dispatcher_id))
+                .await
+                .unwrap();
+        }
+
+        let last_seen = jiff::Timestamp::now() - SignedDuration::from_mins(5);
+        let request = DeviceDisconnectionRequest {
+            dispatcher_id: id,
+            disconnected: [own, foreign, unknown]
+                .map(|device_id| DeviceDisconnection {
+                    device_id,
+                    last_seen,
+                })
+                .into(),
+            reconnected: Box::new([]),
+        };
+        for _ in 0..2 {
+            let response = handle_device_disconnection(&registries, &events, request.clone()).await;
+            assert_eq!(response, DeviceDisconnectionResponse::Accepted);
+        }
+        assert_eq!(
+            registries.devices.disconnected().await.unwrap(),
+            [(own, last_seen)]
+        );
+        // Only the first report of the own device raises an event.
+        match offline.try_recv() {
+            Some(Received::Event(event)) => assert!(matches!(
+                *event,
+                BusEvent::DeviceOffline { device_id, .. } if device_id == own
+            )),
+            other => panic!("expected device_offline, got {other:?}"),
+        }
+        assert!(offline.try_recv().is_none());
+
+        let request = DeviceDisconnectionRequest {
+            dispatcher_id: id,
+            disconnected: Box::new([]),
+            reconnected: Box::new([own]),
+        };
+        let response = handle_device_disconnection(&registries, &events, request.clone()).await;
+        assert_eq!(response, DeviceDisconnectionResponse::Accepted);
+        assert!(registries.devices.disconnected().await.unwrap().is_empty());
+
+        let request = DeviceDisconnectionRequest {
+            dispatcher_id: DispatcherId(Ulid::new()),
+            ..request
+        };
+        assert_eq!(
+            handle_device_disconnection(&registries, &events, request).await,
+            DeviceDisconnectionResponse::Rejected {
+                reason: BatchRejectionReason::UnknownDispatcher
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn command_polls_deliver_then_acknowledge() {
+        let registries = InMemoryRegistries::default();
+        let id = provisioned(&registries, "s3cret").await;
+        let command = Command::new(
+            DeviceId(Ulid::new()),
+            id,
+            CommandKind::SetReportInterval { seconds: 300 },
+            jiff::Timestamp::now(),
+            SignedDuration::from_mins(10),
+        );
+        registries.commands.enqueue(command.clone()).await.unwrap();
+        let poll = |acks: Vec<CommandId>| CommandPoll {
+            dispatcher_id: id,
+            acks: acks.into(),
+        };
+
+        assert_eq!(
+            handle_command_poll(&registries, poll(vec![])).await,
+            CommandPollResponse::Accepted {
+                commands: Box::new([command.to_wire()])
+            }
+        );
+        assert_eq!(
+            handle_command_poll(&registries, poll(vec![command.id])).await,
+            CommandPollResponse::Accepted {
+                commands: Box::new([])
+            }
+        );
+
+        let stored = registries.commands.get(command.id).await.unwrap().unwrap();
+        assert_eq!(stored.state, CommandState::Acked);
+
+        let unknown = CommandPoll {
+            dispatcher_id: DispatcherId(Ulid::new()),
+            acks: Box::new([]),
+        };
+        assert_eq!(
+            handle_command_poll(&registries, unknown).await,
+            CommandPollResponse::Rejected {
+                reason: BatchRejectionReason::UnknownDispatcher
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn queued_commands_are_pushed_to_connected_dispatchers() {
+        let registries = InMemoryRegistries::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ()).with_router(Router::new().route(
+            |hello: HelloRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                HelloResponse::Accepted {
+                    dispatcher_id: hello.dispatcher_id,
+                    proof: None,
+                    chunking: None,
+                    compression: None,
+                }
+            },
+        ));
+        let dispatchers = server.dispatchers();
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let id = DispatcherId(Ulid::new());
+        let command = Command::new(
+            DeviceId(Ulid::new()),
+            id,
+            CommandKind::Reboot,
+            jiff::Timestamp::now(),
+            SignedDuration::from_mins(10),
+        );
+        registries.commands.enqueue(command.clone()).await.unwrap();
+
+        // The command waits until its dispatcher connects.
+        assert_eq!(push_queued_commands(&registries, &dispatchers).await, 0);
+
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        let mut incoming = client.incoming().unwrap();
+        let pushed = tokio::spawn(async move {
+            let envelope = incoming.recv().await.unwrap();
+            let WireMessage::CommandDispatchRequest(request) = envelope.payload else {
+                panic!("expected commands, got {:?}", envelope.payload);
+            };
+            let acks = request.commands.iter().map(|command| command.id).collect();
+            let response = WireMessage::CommandDispatchResponse(CommandDispatchResponse { acks });
+            incoming.reply(envelope.msg_id, response).await.unwrap();
+            request.commands
+        });
+        let hello = HelloRequest {
+            accepts_push: true,
+            ..hello(id, None)
+        };
+        client.hello(hello).await.unwrap();
+
+        assert_eq!(push_queued_commands(&registries, &dispatchers).await, 1);
+        assert_eq!(*pushed.await.unwrap(), [command.to_wire()]);
+        let stored = registries.commands.get(command.id).await.unwrap().unwrap();
+        assert_eq!(stored.state, CommandState::Acked);
+
+        cancel.cancel();
+    }
+}
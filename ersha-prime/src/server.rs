@@ -0,0 +1,849 @@
+//! A whole prime server, built programmatically.
+//!
+//! [`PrimeServer`] wires registries into the RPC and HTTP servers and the
+//! background tasks behind them, as the `ersha-prime` binary does from its
+//! configuration file. Integration tests and deployments embedding prime
+//! build one, [`start`](PrimeServer::start) it, and stop it through the
+//! returned [`PrimeHandle`] or a cancellation token of their own.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use axum::http::header::InvalidHeaderValue;
+use axum::{Router, middleware, routing::get};
+use ersha_core::{
+    BatchUploadRequest, CommandPoll, DeviceDisconnectionRequest, DispatcherStatus, HelloRequest,
+};
+use ersha_rpc::auth::SeenNonces;
+use ersha_rpc::capture::CaptureWriter;
+use ersha_rpc::middleware::require_hello;
+use ersha_rpc::tls::{self, TlsError};
+use ersha_rpc::{RpcTcp, Server, SharedRateLimits};
+use metrics_exporter_prometheus::PrometheusHandle;
+use thiserror::Error;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::auth::{ApiKey, Scope};
+use crate::backfill::{self, Backfills};
+use crate::backup::{self, Backups};
+use crate::config::{Config, ServerConfig};
+use crate::egress::{self, EgressError};
+use crate::events::{EventBus, LocalEventBus};
+use crate::firmware::{self, Firmware};
+use crate::forecast::TrendForecaster;
+use crate::hooks::IngestionHooks;
+use crate::idempotency::RecentBatches;
+use crate::quota::IngestQuotas;
+use crate::registry::{ApiKeyRegistry, Registries, RegistryError};
+use crate::timeseries::{self, SinkError};
+use crate::tuning::{Tunables, Tuning, TuningError};
+use crate::webhook::ThresholdWatch;
+use crate::{
+    api, correction, derived, http, irrigation, metrics, notify, outbox, retention, rollup, rpc,
+    tunnel, validation, watchdog, webhook,
+};
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("registry error: {0}")]
+    Registry(#[from] RegistryError),
+    #[error(transparent)]
+    Tuning(#[from] TuningError),
+    #[error("TLS error: {0}")]
+    Tls(#[from] TlsError),
+    #[error("invalid HTTP header value: {0}")]
+    Header(#[from] InvalidHeaderValue),
+    #[error("egress error: {0}")]
+    Egress(#[from] EgressError),
+    #[error("time-series sink error: {0}")]
+    Timeseries(#[from] SinkError),
+}
+
+/// Background work a server may run besides serving. Each also needs
+/// enabling in the configuration where it has a switch there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subsystem {
+    /// Scheduled database backups
+    Backups,
+    /// Deleting data past its retention
+    Retention,
+    /// Field indicators derived from readings
+    Indicators,
+    /// Irrigation planning
+    Irrigation,
+    /// Applying scheduled calibration corrections
+    Corrections,
+    /// Marking devices that stopped reporting offline
+    Watchdog,
+    /// Firmware rollouts
+    Firmware,
+    /// Webhook and outbox deliveries, and the events and thresholds they
+    /// watch
+    Webhooks,
+    /// SMS and Telegram notifications
+    Notifications,
+    /// Publishing ingested data to a message broker
+    Egress,
+    /// Mirroring readings into a time-series database
+    Timeseries,
+    /// Pushing queued commands to connected dispatchers
+    CommandPush,
+}
+
+/// Builds a prime server over `R`.
+///
+/// Everything not set on the builder comes from the [`Config`].
+pub struct PrimeServer<R> {
+    registries: R,
+    config: Config,
+    tuning: Option<Tuning>,
+    backups: Option<Backups>,
+    hooks: IngestionHooks,
+    capture: Option<CaptureWriter>,
+    prometheus: Option<PrometheusHandle>,
+    disabled: HashSet<Subsystem>,
+    signals: bool,
+    cancel: CancellationToken,
+}
+
+impl<R: Registries> PrimeServer<R> {
+    pub fn new(registries: R, config: Config) -> Self {
+        Self {
+            registries,
+            config,
+            tuning: None,
+            backups: None,
+            hooks: IngestionHooks::default(),
+            capture: None,
+            prometheus: None,
+            disabled: HashSet::new(),
+            signals: false,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Listen for dispatchers on `addr`. Port 0 picks a free port, which
+    /// [`PrimeHandle::rpc_addr`] tells.
+    pub fn with_rpc_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.server.rpc_addr = addr;
+        self
+    }
+
+    /// Serve the API on `addr`. Port 0 picks a free port, which
+    /// [`PrimeHandle::http_addr`] tells.
+    pub fn with_http_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.server.http_addr = addr;
+        self
+    }
+
+    /// Stop when `cancel` is cancelled.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Settings changed without a restart, by default those of the
+    /// configuration.
+    pub fn with_tuning(mut self, tuning: Tuning) -> Self {
+        self.tuning = Some(tuning);
+        self
+    }
+
+    /// Backups taken through the API and, when an interval is configured,
+    /// on a schedule. Without them, backups are refused.
+    pub fn with_backups(mut self, backups: Backups) -> Self {
+        self.backups = Some(backups);
+        self
+    }
+
+    pub fn with_hooks(mut self, hooks: IngestionHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Record every RPC envelope received.
+    pub fn with_capture(mut self, capture: CaptureWriter) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Serve `/metrics` from `prometheus`, as installed by
+    /// [`metrics::install`]. The recorder is process-wide, so servers
+    /// without one still record metrics if it is installed.
+    pub fn with_metrics(mut self, prometheus: PrometheusHandle) -> Self {
+        self.prometheus = Some(prometheus);
+        self
+    }
+
+    /// Don't run `subsystem`, whatever the configuration says.
+    pub fn without(mut self, subsystem: Subsystem) -> Self {
+        self.disabled.insert(subsystem);
+        self
+    }
+
+    /// Shut down on Ctrl+C, and reload the configuration on `SIGHUP`.
+    pub fn with_signal_handling(mut self) -> Self {
+        self.signals = true;
+        self
+    }
+
+    fn runs(&self, subsystem: Subsystem) -> bool {
+        !self.disabled.contains(&subsystem)
+    }
+
+    /// Start the background tasks, then both servers. Fails without
+    /// starting anything if a listener can't be bound or a connection
+    /// configured can't be made.
+    pub async fn start(self) -> Result<PrimeHandle, ServerError> {
+        let Config {
+            auth,
+            health,
+            webhooks,
+            indicators,
+            forecast,
+            irrigation,
+            corrections,
+            watchdog,
+            data_quality,
+            ..
+        } = self.config;
+        let config = &self.config;
+        let registries = self.registries.clone();
+        let ServerConfig {
+            rpc_addr,
+            http_addr,
+            rpc_tunnel,
+            drain_timeout_secs,
+            ..
+        } = config.server;
+
+        // Whatever can fail comes first, before any task is spawned.
+        let rpc_listener = TcpListener::bind(rpc_addr).await?;
+        let http_listener = TcpListener::bind(http_addr).await?;
+        let tls = match &config.tls {
+            Some(tls) => Some(tls::server_config(
+                &tls.cert,
+                &tls.key,
+                tls.client_ca.as_deref(),
+            )?),
+            None => None,
+        };
+        let publisher = match &config.egress {
+            Some(egress) if self.runs(Subsystem::Egress) => {
+                Some(egress::connect(&egress.broker).await?)
+            }
+            _ => None,
+        };
+        let sink = match &config.timeseries {
+            Some(timeseries) if self.runs(Subsystem::Timeseries) => {
+                Some(timeseries::connect(&timeseries.sink).await?)
+            }
+            _ => None,
+        };
+        let tuning = match self.tuning.clone() {
+            Some(tuning) => tuning,
+            None => Tuning::new(Tunables::from_config(config)?),
+        };
+        bootstrap_admin_key(&registries).await?;
+
+        let cancel = self.cancel.clone();
+        let events: Arc<dyn EventBus> = Arc::new(LocalEventBus::new());
+        let quotas = IngestQuotas::new(config.quota.clone());
+        let backfills = Backfills::new(config.backfill);
+        let backups = self
+            .backups
+            .clone()
+            .unwrap_or_else(|| Backups::new(config.backup.clone(), None));
+        let firmware = Firmware::new(
+            config.firmware.clone(),
+            firmware::open(&config.firmware.store),
+        );
+
+        if let Some(interval_secs) = config.backup.interval_secs
+            && self.runs(Subsystem::Backups)
+        {
+            info!(
+                interval_secs,
+                dir = ?config.backup.dir,
+                keep = config.backup.keep,
+                "Starting backup task"
+            );
+            tokio::spawn(backup::run(backups.clone(), cancel.clone()));
+        }
+
+        if self.runs(Subsystem::Retention) {
+            let retention = tuning.current().retention;
+            info!(
+                enabled = retention.enabled,
+                dry_run = retention.dry_run,
+                "Starting retention task"
+            );
+            tokio::spawn(retention::run(
+                registries.clone(),
+                tuning.subscribe(),
+                cancel.clone(),
+            ));
+        }
+
+        if indicators.enabled && self.runs(Subsystem::Indicators) {
+            info!(
+                field_resolution = indicators.field_resolution,
+                "Starting field indicator task"
+            );
+            tokio::spawn(derived::run(registries.clone(), indicators, cancel.clone()));
+        }
+
+        if irrigation.enabled && self.runs(Subsystem::Irrigation) {
+            info!("Starting irrigation planning task");
+            tokio::spawn(irrigation::run(
+                registries.clone(),
+                irrigation,
+                cancel.clone(),
+            ));
+        }
+
+        if self.runs(Subsystem::Corrections) {
+            tokio::spawn(correction::run(
+                registries.clone(),
+                corrections,
+                cancel.clone(),
+            ));
+        }
+
+        if watchdog.enabled && self.runs(Subsystem::Watchdog) {
+            info!(
+                grace_secs = watchdog.grace_secs,
+                "Starting device watchdog task"
+            );
+            tokio::spawn(watchdog::run(
+                registries.clone(),
+                events.clone(),
+                watchdog,
+                cancel.clone(),
+            ));
+        }
+
+        if self.runs(Subsystem::Firmware) {
+            info!(
+                interval_secs = config.firmware.interval_secs,
+                "Starting firmware rollout task"
+            );
+            tokio::spawn(firmware::run(
+                registries.clone(),
+                config.firmware.clone(),
+                cancel.clone(),
+            ));
+        }
+
+        if self.runs(Subsystem::Webhooks) {
+            tokio::spawn(outbox::run_relay(
+                registries.clone(),
+                webhooks,
+                cancel.clone(),
+            ));
+            tokio::spawn(webhook::run_deliveries(
+                registries.clone(),
+                webhooks,
+                cancel.clone(),
+            ));
+        }
+        if self.runs(Subsystem::Notifications) {
+            info!(
+                sms = config.notifications.sms.is_some(),
+                telegram = config.notifications.telegram.is_some(),
+                "Starting notification task"
+            );
+            tokio::spawn(notify::run_deliveries(
+                registries.clone(),
+                config.notifications.clone(),
+                webhooks,
+                cancel.clone(),
+            ));
+        }
+
+        // Subscribers start before the servers so they see every event.
+        tokio::spawn(rollup::run(
+            registries.clone(),
+            events.subscribe(),
+            cancel.clone(),
+        ));
+        tokio::spawn(validation::run(
+            registries.clone(),
+            events.subscribe(),
+            cancel.clone(),
+        ));
+        if self.runs(Subsystem::Webhooks) {
+            tokio::spawn(webhook::relay_events(
+                registries.clone(),
+                events.subscribe(),
+                cancel.clone(),
+            ));
+        }
+        tokio::spawn(backfill::run(
+            registries.clone(),
+            backfills.clone(),
+            auth,
+            events.clone(),
+            cancel.clone(),
+        ));
+        if let (Some(publisher), Some(egress)) = (publisher, config.egress.clone()) {
+            info!(
+                readings_topic = egress.readings_topic,
+                statuses_topic = egress.statuses_topic,
+                "Starting egress task"
+            );
+            tokio::spawn(egress::run(
+                publisher,
+                egress,
+                events.subscribe(),
+                cancel.clone(),
+            ));
+        }
+        if let (Some(sink), Some(timeseries)) = (sink, config.timeseries.clone()) {
+            info!(
+                batch_size = timeseries.batch_size,
+                "Starting time-series sink task"
+            );
+            tokio::spawn(timeseries::run(
+                sink,
+                timeseries,
+                events.subscribe(),
+                cancel.clone(),
+            ));
+        }
+
+        let rpc_addr = rpc_listener.local_addr()?;
+        info!(%rpc_addr, "RPC server listening");
+
+        let nonces = SeenNonces::default();
+        let rpc_router =
+            ersha_rpc::Router::new()
+                .route(
+                    move |hello: HelloRequest, _msg_id, connection: &RpcTcp, registries: &R| {
+                        let registries = registries.clone();
+                        let nonces = nonces.clone();
+                        let peer = connection.peer_certificate().cloned();
+                        async move {
+                            rpc::handle_hello(&registries, auth, &nonces, hello, peer.as_ref())
+                                .await
+                        }
+                    },
+                )
+                .route({
+                    let events = events.clone();
+                    let quotas = quotas.clone();
+                    let recent = RecentBatches::default();
+                    let hooks = self.hooks.clone();
+                    let thresholds = ThresholdWatch::default();
+                    move |batch: BatchUploadRequest, _msg_id, _rpc, registries: &R| {
+                        let registries = registries.clone();
+                        let events = events.clone();
+                        let quotas = quotas.clone();
+                        let recent = recent.clone();
+                        let hooks = hooks.clone();
+                        let thresholds = thresholds.clone();
+                        async move {
+                            rpc::handle_batch_upload(
+                                &registries,
+                                auth,
+                                &quotas,
+                                &*events,
+                                &recent,
+                                &hooks,
+                                &thresholds,
+                                batch,
+                            )
+                            .await
+                        }
+                    }
+                })
+                .route(|status: DispatcherStatus, _msg_id, _rpc, registries: &R| {
+                    let registries = registries.clone();
+                    async move { rpc::handle_dispatcher_status(&registries, status).await }
+                })
+                .route(|poll: CommandPoll, _msg_id, _rpc, registries: &R| {
+                    let registries = registries.clone();
+                    async move { rpc::handle_command_poll(&registries, poll).await }
+                })
+                .route({
+                    let events = events.clone();
+                    move |request: DeviceDisconnectionRequest, _msg_id, _rpc, registries: &R| {
+                        let registries = registries.clone();
+                        let events = events.clone();
+                        async move {
+                            rpc::handle_device_disconnection(&registries, &*events, request).await
+                        }
+                    }
+                })
+                .layer(require_hello)
+                .on_disconnect(|dispatcher_id, _registries: &R| async move {
+                    if let Some(dispatcher_id) = dispatcher_id {
+                        info!(?dispatcher_id, "Dispatcher disconnected");
+                    }
+                });
+        let mut rpc_server = Server::new(rpc_listener, registries.clone())
+            .with_rate_limits(tuning.current().rate_limit.rpc())
+            .with_write_queue(config.server.write_queue())
+            .with_read_limits(config.server.read_limits())
+            .with_connection_limits(config.server.connection_limits())
+            .with_drain_timeout(Duration::from_secs(drain_timeout_secs))
+            .with_metrics(Arc::new(metrics::RpcRecorder))
+            .with_router(rpc_router);
+
+        if let Some(tls) = tls {
+            info!(
+                client_certificates = config.tls.as_ref().is_some_and(|t| t.client_ca.is_some()),
+                "Serving RPC over TLS"
+            );
+            rpc_server = rpc_server.with_tls(tls);
+        }
+
+        if let Some(keepalive) = config.server.keepalive() {
+            rpc_server = rpc_server.with_keepalive(keepalive);
+        }
+
+        if let Some(capture) = self.capture.clone() {
+            rpc_server = rpc_server.with_capture(capture);
+        }
+
+        if self.runs(Subsystem::CommandPush) {
+            tokio::spawn(rpc::push_commands(
+                registries.clone(),
+                rpc_server.dispatchers(),
+                cancel.clone(),
+            ));
+        }
+
+        tokio::spawn(follow_rpc_rate_limits(
+            tuning.subscribe(),
+            rpc_server.rate_limits(),
+        ));
+
+        let mut axum_app = Router::new().route("/health", get(health_handler));
+        if let Some(prometheus) = self.prometheus {
+            let connections = rpc_server.connections();
+            let queued = rpc_server.queued();
+            axum_app = axum_app.route(
+                "/metrics",
+                get(move || {
+                    metrics::set_rpc_connections(connections.load(Ordering::Relaxed));
+                    metrics::set_rpc_queued(queued.load(Ordering::Relaxed));
+                    std::future::ready(prometheus.render())
+                }),
+            );
+        }
+        let mut axum_app = axum_app
+            .merge(api::router(
+                registries,
+                events,
+                health,
+                indicators,
+                Arc::new(TrendForecaster::new(&forecast)),
+                irrigation,
+                data_quality,
+                quotas,
+                backfills,
+                firmware,
+                auth,
+                config.users.clone(),
+                tuning.clone(),
+                backups,
+            ))
+            .layer(middleware::from_fn(metrics::track_http));
+        if rpc_tunnel {
+            info!(%http_addr, path = tunnel::TUNNEL_PATH, "Accepting RPC tunneled over WebSockets");
+            axum_app = axum_app.merge(tunnel::router(rpc_server.tunnel()));
+        }
+        let axum_app = http::layer(axum_app, &config.http)?;
+
+        let http_addr = http_listener.local_addr()?;
+        info!(%http_addr, "HTTP server listening");
+
+        if self.signals {
+            let shutdown = cancel.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = shutdown.cancelled() => {}
+                    _ = tokio::signal::ctrl_c() => {
+                        info!("Received Ctrl+C, shutting down...");
+                        shutdown.cancel();
+                    }
+                }
+            });
+            #[cfg(unix)]
+            tokio::spawn(reload_on_hangup(tuning, cancel.clone()));
+        }
+
+        // Either server stopping stops the other, each finishing what it is
+        // in the middle of.
+        let rpc = {
+            let cancel = cancel.clone();
+            async move {
+                rpc_server.serve(cancel.clone()).await;
+                info!("RPC server shut down");
+                cancel.cancel();
+            }
+        };
+        let http = {
+            let cancel = cancel.clone();
+            async move {
+                let result = axum::serve(
+                    http_listener,
+                    axum_app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(cancel.clone().cancelled_owned())
+                .await;
+                if let Err(e) = result {
+                    error!(error = ?e, "HTTP server error");
+                }
+                info!("HTTP server shut down");
+                cancel.cancel();
+            }
+        };
+        let serving = tokio::spawn(async move {
+            tokio::join!(rpc, http);
+        });
+
+        Ok(PrimeHandle {
+            rpc_addr,
+            http_addr,
+            cancel,
+            serving,
+        })
+    }
+}
+
+/// A running prime server.
+pub struct PrimeHandle {
+    rpc_addr: SocketAddr,
+    http_addr: SocketAddr,
+    cancel: CancellationToken,
+    serving: JoinHandle<()>,
+}
+
+impl PrimeHandle {
+    /// Address dispatchers connect to.
+    pub fn rpc_addr(&self) -> SocketAddr {
+        self.rpc_addr
+    }
+
+    /// Address the API is served on.
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_addr
+    }
+
+    /// Token that stops the server when cancelled, and is cancelled once
+    /// either of its servers stops.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Serve until shut down, through the cancellation token or a signal,
+    /// or until either server fails.
+    pub async fn serve(self) {
+        if let Err(e) = self.serving.await {
+            error!(error = ?e, "Prime server task failed");
+        }
+    }
+
+    /// Stop serving and the background tasks, waiting for the servers to
+    /// finish what they are in the middle of.
+    pub async fn shutdown(self) {
+        self.cancel.cancel();
+        self.serve().await;
+    }
+}
+
+/// Create an admin API key when none exist so the API is reachable on first start.
+async fn bootstrap_admin_key<R: Registries>(registries: &R) -> Result<(), RegistryError> {
+    if registries.api_keys().count().await.map_err(Into::into)? > 0 {
+        return Ok(());
+    }
+
+    let (key, token) = ApiKey::generate("bootstrap", Scope::Admin);
+    registries
+        .api_keys()
+        .create(key)
+        .await
+        .map_err(Into::into)?;
+
+    warn!(
+        token = %token,
+        "No API keys found, created a bootstrap admin key. Store it now, it will not be shown again"
+    );
+
+    Ok(())
+}
+
+/// Keep the limits of dispatcher connections in line with the tuning.
+async fn follow_rpc_rate_limits(mut tunables: watch::Receiver<Tunables>, limits: SharedRateLimits) {
+    while tunables.changed().await.is_ok() {
+        limits.set(tunables.borrow_and_update().rate_limit.rpc());
+    }
+}
+
+/// Reload the configuration file on every `SIGHUP`.
+#[cfg(unix)]
+async fn reload_on_hangup(tuning: Tuning, cancel: CancellationToken) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!(error = %e, "Cannot listen for SIGHUP, reload through the API instead");
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            received = hangups.recv() => {
+                if received.is_none() {
+                    break;
+                }
+            }
+        }
+
+        match tuning.reload() {
+            Ok(tunables) => info!(?tunables, "Configuration reloaded on SIGHUP"),
+            Err(e) => error!(error = %e, "Configuration reload failed, keeping previous settings"),
+        }
+    }
+}
+
+async fn health_handler() -> &'static str {
+    "OK"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use ersha_core::{
+        BatchId, BatchUploadRequest, Dispatcher, DispatcherId, DispatcherState, H3Cell,
+        HelloRejectionReason, HelloRequest, HelloResponse,
+    };
+    use ersha_rpc::{Client, ClientError, WireErrorCode};
+    use tokio::net::TcpStream;
+    use ulid::Ulid;
+
+    use super::{PrimeServer, Subsystem};
+    use crate::config::Config;
+    use crate::registry::{ApiKeyRegistry, DispatcherRegistry, memory::InMemoryRegistries};
+
+    const LOCATION: H3Cell = H3Cell(0x8a2a1072b59ffff);
+
+    fn hello(dispatcher_id: DispatcherId) -> HelloRequest {
+        HelloRequest {
+            dispatcher_id,
+            location: LOCATION,
+            credentials: None,
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn servers_start_on_free_ports_and_shut_down() {
+        let registries = InMemoryRegistries::default();
+        let any_port: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let prime = PrimeServer::new(registries.clone(), Config::default())
+            .with_rpc_addr(any_port)
+            .with_http_addr(any_port)
+            .without(Subsystem::Firmware)
+            .start()
+            .await
+            .unwrap();
+
+        // The bootstrap admin key is created on start.
+        assert_eq!(registries.api_keys.count().await.unwrap(), 1);
+
+        let health = reqwest::get(format!("http://{}/health", prime.http_addr()))
+            .await
+            .unwrap();
+        assert_eq!(health.text().await.unwrap(), "OK");
+        let metrics = reqwest::get(format!("http://{}/metrics", prime.http_addr()))
+            .await
+            .unwrap();
+        assert_eq!(metrics.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let client = Client::new(TcpStream::connect(prime.rpc_addr()).await.unwrap());
+        let hello = client
+            .hello(hello(DispatcherId(Ulid::new())))
+            .await
+            .unwrap();
+        // Unknown dispatchers wait for an operator's approval.
+        assert!(matches!(
+            hello,
+            HelloResponse::Rejected {
+                reason: HelloRejectionReason::PendingApproval,
+                ..
+            }
+        ));
+        drop(client);
+
+        let stopped = prime.cancellation_token();
+        prime.shutdown().await;
+        assert!(stopped.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn uploads_for_another_dispatcher_are_forbidden() {
+        let registries = InMemoryRegistries::default();
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let id = DispatcherId(Ulid::new());
+            registries
+                .dispatchers
+                .register(Dispatcher {
+                    id,
+                    location: LOCATION,
+                    state: DispatcherState::Active,
+                    provisioned_at: jiff::Timestamp::now(),
+                })
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        let any_port: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let prime = PrimeServer::new(registries.clone(), Config::default())
+            .with_rpc_addr(any_port)
+            .with_http_addr(any_port)
+            .without(Subsystem::Firmware)
+            .start()
+            .await
+            .unwrap();
+
+        let client = Client::new(TcpStream::connect(prime.rpc_addr()).await.unwrap());
+        let accepted = client.hello(hello(ids[0])).await.unwrap();
+        assert!(matches!(accepted, HelloResponse::Accepted { .. }));
+
+        let batch = BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            dispatcher_id: ids[1],
+            readings: Box::new([]),
+            statuses: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+        };
+        match client.batch_upload(batch).await {
+            Err(ClientError::ErrorResponse(error)) => {
+                assert_eq!(error.code, WireErrorCode::Forbidden)
+            }
+            other => panic!("expected a forbidden error, got {other:?}"),
+        }
+        drop(client);
+
+        prime.shutdown().await;
+    }
+}
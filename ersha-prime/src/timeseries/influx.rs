@@ -0,0 +1,197 @@
+//! InfluxDB writes in line protocol.
+//!
+//! Each reading is a point in one measurement, tagged with its metric,
+//! device, dispatcher, sensor and H3 cell, with the value and confidence as
+//! fields. InfluxDB keeps one point per series and timestamp, so writing a
+//! reading again overwrites it.
+
+use std::fmt::Write as _;
+
+use async_trait::async_trait;
+use ersha_core::SensorReading;
+
+use super::{ReadingSink, SinkError};
+use crate::rollup::{metric_kind_name, metric_value};
+
+pub struct InfluxSink {
+    client: reqwest::Client,
+    write_url: String,
+    org: String,
+    bucket: String,
+    token: String,
+    measurement: String,
+}
+
+impl InfluxSink {
+    pub fn new(
+        client: reqwest::Client,
+        url: &str,
+        org: &str,
+        bucket: &str,
+        token: &str,
+        measurement: &str,
+    ) -> Self {
+        Self {
+            client,
+            write_url: format!("{}/api/v2/write", url.trim_end_matches('/')),
+            org: org.to_owned(),
+            bucket: bucket.to_owned(),
+            token: token.to_owned(),
+            measurement: measurement.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl ReadingSink for InfluxSink {
+    async fn write(&self, readings: &[SensorReading]) -> Result<(), SinkError> {
+        let response = self
+            .client
+            .post(&self.write_url)
+            .query(&[
+                ("org", self.org.as_str()),
+                ("bucket", self.bucket.as_str()),
+                ("precision", "ns"),
+            ])
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!("Token {}", self.token),
+            )
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(lines(&self.measurement, readings))
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(SinkError::Rejected(response.status().as_u16()))
+        }
+    }
+}
+
+/// `readings` in line protocol, one point per line.
+pub fn lines(measurement: &str, readings: &[SensorReading]) -> String {
+    let measurement = escape(measurement, &[',', ' ']);
+    let mut body = String::new();
+    for reading in readings {
+        // Writing to a String can't fail.
+        let _ = writeln!(
+            body,
+            "{measurement},metric={},device_id={},dispatcher_id={},sensor_id={},location={:x} value={},confidence={}i {}",
+            metric_kind_name(reading.metric.kind()),
+            reading.device_id.0,
+            reading.dispatcher_id.0,
+            reading.sensor_id.0,
+            reading.location.0,
+            metric_value(&reading.metric),
+            reading.confidence.0,
+            reading.timestamp.as_nanosecond(),
+        );
+    }
+    body
+}
+
+/// `value` with each of `special` backslash escaped.
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::Router;
+    use axum::extract::RawQuery;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use ersha_core::{Percentage, SensorMetric, SensorReading};
+    use jiff::Timestamp;
+    use ordered_float::NotNan;
+
+    use super::{InfluxSink, lines};
+    use crate::fixtures;
+    use crate::timeseries::{ReadingSink, SinkError};
+
+    fn reading(metric: SensorMetric) -> SensorReading {
+        SensorReading {
+            metric,
+            timestamp: Timestamp::from_second(1_700_000_000).unwrap(),
+            ..fixtures::reading()
+        }
+    }
+
+    #[test]
+    fn readings_are_points_tagged_by_source() {
+        let reading = reading(SensorMetric::AirTemp {
+            value: NotNan::new(21.5).unwrap(),
+        });
+
+        let body = lines("field readings", std::slice::from_ref(&reading));
+
+        assert_eq!(
+            body,
+            format!(
+                "field\\ readings,metric=air_temp,device_id={},dispatcher_id={},sensor_id={},location=8a2a1072b59ffff value=21.5,confidence=90i 1700000000000000000\n",
+                reading.device_id.0, reading.dispatcher_id.0, reading.sensor_id.0,
+            )
+        );
+    }
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Option<String>, String)>>>;
+
+    #[tokio::test]
+    async fn writes_are_authorized_to_the_bucket() {
+        let received: Received = Arc::default();
+        let app = Router::new().route(
+            "/api/v2/write",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap, RawQuery(query): RawQuery, body: String| async move {
+                    let mut received = received.lock().unwrap();
+                    received.push((headers, query, body));
+                    // Refuse the first write only.
+                    if received.len() == 1 {
+                        StatusCode::UNAUTHORIZED
+                    } else {
+                        StatusCode::NO_CONTENT
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let sink = InfluxSink::new(
+            reqwest::Client::new(),
+            &format!("http://{addr}/"),
+            "ersha",
+            "farm",
+            "secret",
+            "reading",
+        );
+        let readings = [reading(SensorMetric::SoilMoisture {
+            value: Percentage(40),
+        })];
+
+        assert!(matches!(
+            sink.write(&readings).await,
+            Err(SinkError::Rejected(401))
+        ));
+        sink.write(&readings).await.unwrap();
+
+        let received = received.lock().unwrap();
+        let (headers, query, body) = &received[1];
+        assert_eq!(headers["authorization"], "Token secret");
+        assert_eq!(query.as_deref(), Some("org=ersha&bucket=farm&precision=ns"));
+        assert_eq!(body, &lines("reading", &readings));
+    }
+}
@@ -0,0 +1,280 @@
+//! Ingested readings mirrored into a time-series database.
+//!
+//! Existing Grafana dashboards graph InfluxDB or TimescaleDB directly, so
+//! every stored reading is also written to one of them, in batches. Readings
+//! wait in a bounded buffer while the database is slow or down, batches are
+//! retried with backoff, and readings are dropped rather than holding up
+//! ingestion once the buffer is full.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ersha_core::SensorReading;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::config::{SinkConfig, TimeseriesConfig};
+use crate::events::{BusEvent, Received, Subscription};
+use crate::metrics;
+
+pub mod influx;
+#[cfg(feature = "timescale")]
+pub mod timescale;
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error("prime was built without the `{0}` feature")]
+    Unsupported(&'static str),
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server answered {0}")]
+    Rejected(u16),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("invalid table name {0:?}")]
+    InvalidTable(String),
+}
+
+/// Writes readings to a time-series database.
+#[async_trait]
+pub trait ReadingSink: Send + Sync {
+    /// Write `readings`, returning once the database has stored them.
+    /// Writing a reading again doesn't duplicate it.
+    async fn write(&self, readings: &[SensorReading]) -> Result<(), SinkError>;
+}
+
+/// A sink for the configured database.
+pub async fn connect(config: &SinkConfig) -> Result<Arc<dyn ReadingSink>, SinkError> {
+    match config {
+        SinkConfig::Influx {
+            url,
+            org,
+            bucket,
+            token,
+            measurement,
+        } => Ok(Arc::new(influx::InfluxSink::new(
+            reqwest::Client::new(),
+            url,
+            org,
+            bucket,
+            token,
+            measurement,
+        ))),
+        #[cfg(feature = "timescale")]
+        SinkConfig::Timescale { url, table } => Ok(Arc::new(
+            timescale::TimescaleSink::connect(url, table).await?,
+        )),
+        #[cfg(not(feature = "timescale"))]
+        SinkConfig::Timescale { .. } => Err(SinkError::Unsupported("timescale")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkOutcome {
+    Written,
+    /// Failed, to be tried again
+    Retried,
+    /// Failed on the last attempt
+    Failed,
+    /// Not queued, the buffer being full
+    Dropped,
+}
+
+/// Wait before retrying after the `attempts`th failure.
+fn backoff(config: &TimeseriesConfig, attempts: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    let millis = config
+        .initial_backoff_ms
+        .saturating_mul(factor)
+        .min(config.max_backoff_ms);
+    Duration::from_millis(millis)
+}
+
+/// Write ingested readings from `events` to `sink` until cancelled or the
+/// bus is gone, once everything queued has been written.
+pub async fn run(
+    sink: Arc<dyn ReadingSink>,
+    config: TimeseriesConfig,
+    events: Subscription,
+    cancel: CancellationToken,
+) {
+    let (queue, mut queued) = mpsc::channel(config.buffer.max(1));
+
+    tokio::select! {
+        _ = cancel.cancelled() => {}
+        _ = async {
+            tokio::join!(
+                enqueue(events, queue),
+                write_queued(&*sink, &config, &mut queued),
+            )
+        } => {}
+    }
+}
+
+/// Queue every ingested reading. Never waits on the database, so the
+/// subscription keeps up with ingestion whatever the database does.
+async fn enqueue(mut events: Subscription, queue: mpsc::Sender<SensorReading>) {
+    while let Some(received) = events.recv().await {
+        let Received::Event(event) = received else {
+            continue;
+        };
+        let BusEvent::ReadingsIngested { readings, .. } = &*event else {
+            continue;
+        };
+
+        let mut dropped = 0;
+        for reading in readings.iter() {
+            if queue.try_send(reading.clone()).is_err() {
+                dropped += 1;
+            }
+        }
+        if dropped > 0 {
+            metrics::record_timeseries(SinkOutcome::Dropped, dropped);
+        }
+        metrics::set_timeseries_queued(queue.max_capacity() - queue.capacity());
+    }
+}
+
+async fn write_queued(
+    sink: &dyn ReadingSink,
+    config: &TimeseriesConfig,
+    queued: &mut mpsc::Receiver<SensorReading>,
+) {
+    let mut batch = Vec::with_capacity(config.batch_size.max(1));
+    while queued.recv_many(&mut batch, config.batch_size.max(1)).await > 0 {
+        metrics::set_timeseries_queued(queued.len());
+        write(sink, config, &batch).await;
+        batch.clear();
+    }
+}
+
+/// Write `batch`, retrying failures up to the configured attempts.
+async fn write(sink: &dyn ReadingSink, config: &TimeseriesConfig, batch: &[SensorReading]) {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match sink.write(batch).await {
+            Ok(()) => {
+                metrics::record_timeseries(SinkOutcome::Written, batch.len());
+                return;
+            }
+            Err(err) if attempts >= config.max_attempts => {
+                warn!(
+                    readings = batch.len(),
+                    attempts, "Giving up on writing readings to the time-series sink: {err}"
+                );
+                metrics::record_timeseries(SinkOutcome::Failed, batch.len());
+                return;
+            }
+            Err(err) => {
+                warn!(
+                    readings = batch.len(),
+                    attempts, "Failed to write readings to the time-series sink: {err}"
+                );
+                metrics::record_timeseries(SinkOutcome::Retried, batch.len());
+                tokio::time::sleep(backoff(config, attempts)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use ersha_core::SensorReading;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{ReadingSink, SinkError, run};
+    use crate::config::{SinkConfig, TimeseriesConfig};
+    use crate::events::{BusEvent, EventBus, LocalEventBus};
+    use crate::fixtures::reading;
+
+    /// Records every batch written, failing the first `failures` writes.
+    #[derive(Default)]
+    struct RecordingSink {
+        failures: Mutex<usize>,
+        batches: Mutex<Vec<Vec<SensorReading>>>,
+    }
+
+    #[async_trait]
+    impl ReadingSink for RecordingSink {
+        async fn write(&self, readings: &[SensorReading]) -> Result<(), SinkError> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(SinkError::Rejected(503));
+            }
+            self.batches.lock().unwrap().push(readings.to_vec());
+            Ok(())
+        }
+    }
+
+    fn config(batch_size: usize) -> TimeseriesConfig {
+        TimeseriesConfig {
+            sink: SinkConfig::Timescale {
+                url: "postgres://localhost/ersha".to_owned(),
+                table: "readings".to_owned(),
+            },
+            batch_size,
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+            buffer: 16,
+        }
+    }
+
+    #[tokio::test]
+    async fn readings_are_written_in_batches_with_retries() {
+        let sink = Arc::new(RecordingSink {
+            failures: Mutex::new(1),
+            ..Default::default()
+        });
+        let bus = LocalEventBus::new();
+        let events = bus.subscribe();
+
+        let readings: Vec<SensorReading> = (0..5).map(|_| reading()).collect();
+        bus.publish(BusEvent::ReadingsIngested {
+            readings: readings.clone().into(),
+            late: false,
+        })
+        .await;
+        bus.publish(BusEvent::AlertRaised {
+            message: "frost".to_owned(),
+            org_id: None,
+        })
+        .await;
+        drop(bus);
+        run(sink.clone(), config(2), events, CancellationToken::new()).await;
+
+        let batches = sink.batches.lock().unwrap();
+        assert!(batches.iter().all(|batch| batch.len() <= 2));
+        let written: Vec<SensorReading> = batches.iter().flatten().cloned().collect();
+        assert_eq!(written, readings);
+    }
+
+    #[tokio::test]
+    async fn batches_are_given_up_after_max_attempts() {
+        let sink = Arc::new(RecordingSink {
+            failures: Mutex::new(3),
+            ..Default::default()
+        });
+        let bus = LocalEventBus::new();
+        let events = bus.subscribe();
+
+        bus.publish(BusEvent::ReadingsIngested {
+            readings: vec![reading(), reading()].into(),
+            late: false,
+        })
+        .await;
+        drop(bus);
+        run(sink.clone(), config(1), events, CancellationToken::new()).await;
+
+        // The first reading used up every failure; the second was written.
+        assert_eq!(sink.batches.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,96 @@
+//! TimescaleDB writes into a hypertable of readings.
+//!
+//! The table is created and made a hypertable on `time` when missing. Rows
+//! are unique by reading id and time, so writing a reading again is a no-op.
+
+use async_trait::async_trait;
+use ersha_core::SensorReading;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::{Postgres, QueryBuilder};
+
+use super::{ReadingSink, SinkError};
+use crate::rollup::{metric_kind_name, metric_value};
+
+/// Connections held to the database.
+const MAX_CONNECTIONS: u32 = 4;
+
+pub struct TimescaleSink {
+    pool: PgPool,
+    table: String,
+}
+
+impl TimescaleSink {
+    /// Connect to `url` and create `table` as a hypertable if missing.
+    pub async fn connect(url: &str, table: &str) -> Result<Self, SinkError> {
+        // The table name is spliced into statements, so only plain and
+        // schema-qualified identifiers are accepted.
+        let valid = !table.is_empty()
+            && table.split('.').all(|part| {
+                part.chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            });
+        if !valid {
+            return Err(SinkError::InvalidTable(table.to_owned()));
+        }
+
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(url)
+            .await?;
+
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                time TIMESTAMPTZ NOT NULL,
+                reading_id TEXT NOT NULL,
+                device_id TEXT NOT NULL,
+                dispatcher_id TEXT NOT NULL,
+                sensor_id TEXT NOT NULL,
+                metric TEXT NOT NULL,
+                value DOUBLE PRECISION NOT NULL,
+                confidence SMALLINT NOT NULL,
+                location TEXT NOT NULL,
+                UNIQUE (reading_id, time)
+            )"
+        ))
+        .execute(&pool)
+        .await?;
+        sqlx::query("SELECT create_hypertable($1, 'time', if_not_exists => TRUE)")
+            .bind(table)
+            .execute(&pool)
+            .await?;
+
+        Ok(Self {
+            pool,
+            table: table.to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl ReadingSink for TimescaleSink {
+    async fn write(&self, readings: &[SensorReading]) -> Result<(), SinkError> {
+        let mut query = QueryBuilder::<Postgres>::new(format!(
+            "INSERT INTO {} (time, reading_id, device_id, dispatcher_id, sensor_id, metric, value, confidence, location) ",
+            self.table
+        ));
+        query.push_values(readings, |mut row, reading| {
+            row.push("CAST(")
+                .push_bind_unseparated(reading.timestamp.to_string())
+                .push_unseparated(" AS TIMESTAMPTZ)")
+                .push_bind(reading.id.0.to_string())
+                .push_bind(reading.device_id.0.to_string())
+                .push_bind(reading.dispatcher_id.0.to_string())
+                .push_bind(reading.sensor_id.0.to_string())
+                .push_bind(metric_kind_name(reading.metric.kind()))
+                .push_bind(metric_value(&reading.metric))
+                .push_bind(i16::from(reading.confidence.0))
+                .push_bind(format!("{:x}", reading.location.0));
+        });
+        query.push(" ON CONFLICT DO NOTHING");
+
+        query.build().execute(&self.pool).await?;
+        Ok(())
+    }
+}
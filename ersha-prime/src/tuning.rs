@@ -0,0 +1,206 @@
+//! Settings that can change while prime is running.
+//!
+//! A reload re-reads the configuration file and applies the log filter, rate
+//! limits, retention policies and page sizes. Everything else in the file,
+//! such as listen addresses, the registry and authentication, only takes
+//! effect on restart.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::watch;
+use tracing_subscriber::EnvFilter;
+use utoipa::ToSchema;
+
+use crate::api;
+use crate::config::{Config, PaginationConfig, RateLimitConfig, RetentionConfig};
+
+/// Log filter used when neither the configuration nor `RUST_LOG` sets one.
+pub const DEFAULT_LOG_FILTER: &str = "tracing=info,ersha_prime=info";
+
+#[derive(Debug, Error)]
+pub enum TuningError {
+    #[error("prime was started without a configuration file")]
+    NoConfigFile,
+    #[error("failed to read configuration: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("invalid configuration: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("invalid log filter: {0}")]
+    LogFilter(#[from] tracing_subscriber::filter::ParseError),
+    #[error("pagination default_limit must be between 1 and max_limit")]
+    Pagination,
+    #[error("failed to apply log filter: {0}")]
+    LogReload(String),
+}
+
+/// The settings applied without a restart.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Tunables {
+    /// Tracing filter directives in effect
+    pub log_filter: String,
+    #[schema(value_type = Object)]
+    pub rate_limit: RateLimitConfig,
+    #[schema(value_type = Object)]
+    pub retention: RetentionConfig,
+    #[schema(value_type = Object)]
+    pub pagination: PaginationConfig,
+}
+
+impl Tunables {
+    /// Pick out and check the tunable settings of `config`.
+    pub fn from_config(config: &Config) -> Result<Self, TuningError> {
+        let log_filter = match &config.log.filter {
+            Some(filter) => filter.clone(),
+            None => std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_FILTER.to_owned()),
+        };
+        EnvFilter::try_new(&log_filter)?;
+
+        let pagination = config.pagination;
+        if pagination.default_limit == 0 || pagination.default_limit > pagination.max_limit {
+            return Err(TuningError::Pagination);
+        }
+
+        Ok(Self {
+            log_filter,
+            rate_limit: config.rate_limit,
+            retention: config.retention,
+            pagination,
+        })
+    }
+}
+
+impl Default for Tunables {
+    fn default() -> Self {
+        Self {
+            log_filter: DEFAULT_LOG_FILTER.to_owned(),
+            rate_limit: RateLimitConfig::default(),
+            retention: RetentionConfig::default(),
+            pagination: PaginationConfig::default(),
+        }
+    }
+}
+
+type ApplyLogFilter = dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync;
+
+/// The [`Tunables`] in effect, shared by everything that reads them.
+///
+/// Background tasks [`subscribe`](Self::subscribe) to be woken on changes.
+/// Page sizes are process-wide and set whenever tunables are applied.
+#[derive(Clone)]
+pub struct Tuning {
+    path: Option<PathBuf>,
+    tunables: Arc<watch::Sender<Tunables>>,
+    log: Option<Arc<ApplyLogFilter>>,
+}
+
+impl Tuning {
+    pub fn new(tunables: Tunables) -> Self {
+        api::set_page_limits(tunables.pagination);
+
+        Self {
+            path: None,
+            tunables: Arc::new(watch::Sender::new(tunables)),
+            log: None,
+        }
+    }
+
+    /// Configuration file re-read by [`reload`](Self::reload).
+    pub fn with_config_file(mut self, path: PathBuf) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    /// Install a changed log filter, typically through a
+    /// `tracing_subscriber` reload handle.
+    pub fn with_log_reload<F>(mut self, apply: F) -> Self
+    where
+        F: Fn(EnvFilter) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.log = Some(Arc::new(apply));
+        self
+    }
+
+    pub fn current(&self) -> Tunables {
+        self.tunables.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Tunables> {
+        self.tunables.subscribe()
+    }
+
+    /// Re-read the configuration file and apply its tunable settings.
+    ///
+    /// On error nothing is applied and the previous settings stay in effect.
+    pub fn reload(&self) -> Result<Tunables, TuningError> {
+        let path = self.path.as_ref().ok_or(TuningError::NoConfigFile)?;
+        let content = std::fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&content)?;
+        let tunables = Tunables::from_config(&config)?;
+
+        self.apply(tunables.clone())?;
+
+        Ok(tunables)
+    }
+
+    pub fn apply(&self, tunables: Tunables) -> Result<(), TuningError> {
+        let filter_changed = self.tunables.borrow().log_filter != tunables.log_filter;
+        if let Some(apply) = &self.log
+            && filter_changed
+        {
+            apply(EnvFilter::try_new(&tunables.log_filter)?).map_err(TuningError::LogReload)?;
+        }
+
+        api::set_page_limits(tunables.pagination);
+        self.tunables.send_replace(tunables);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ulid::Ulid;
+
+    use super::{Tunables, Tuning, TuningError};
+
+    #[test]
+    fn reload_applies_the_file_or_keeps_the_previous_settings() {
+        let path = std::env::temp_dir().join(format!("ersha-prime-{}.toml", Ulid::new()));
+        let tuning = Tuning::new(Tunables::default()).with_config_file(path.clone());
+        let mut tunables = tuning.subscribe();
+
+        std::fs::write(
+            &path,
+            r#"
+            [server]
+            rpc_addr = "0.0.0.0:9000"
+            http_addr = "0.0.0.0:8080"
+
+            [registry]
+            type = "memory"
+
+            [log]
+            filter = "ersha_prime=debug"
+
+            [retention.readings]
+            max_age_days = 7
+            "#,
+        )
+        .unwrap();
+        let applied = tuning.reload().unwrap();
+        assert_eq!(applied.log_filter, "ersha_prime=debug");
+        assert_eq!(applied.retention.readings.max_age_days, Some(7));
+        assert!(tunables.has_changed().unwrap());
+        assert_eq!(*tunables.borrow_and_update(), applied);
+
+        std::fs::write(&path, "[server]\nrpc_addr = 9000\n").unwrap();
+        let result = tuning.reload();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(TuningError::Parse(_))));
+        assert_eq!(tuning.current(), applied);
+        assert!(!tunables.has_changed().unwrap());
+    }
+}
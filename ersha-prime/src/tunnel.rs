@@ -0,0 +1,109 @@
+//! RPC tunneled over WebSockets on the HTTP server, for dispatchers on
+//! networks that only let HTTP(S) out.
+
+use std::future;
+use std::net::SocketAddr;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{
+        ConnectInfo, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::Extensions,
+    response::Response,
+    routing::get,
+};
+use ersha_rpc::Tunnel;
+use ersha_rpc::ws::{Tunneled, WsMessage, WsStream};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tracing::debug;
+
+/// Path dispatchers open their tunnel on.
+pub const TUNNEL_PATH: &str = "/rpc";
+
+/// Routes handing WebSockets opened on [`TUNNEL_PATH`] to the RPC server.
+pub fn router(tunnel: Tunnel) -> Router {
+    Router::new()
+        .route(TUNNEL_PATH, get(upgrade))
+        .with_state(tunnel)
+}
+
+/// Tunnels are bounded per address like direct connections, by the peer
+/// the HTTP server accepted them from when it records one.
+async fn upgrade(
+    ws: WebSocketUpgrade,
+    State(tunnel): State<Tunnel>,
+    extensions: Extensions,
+) -> Response {
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    ws.on_upgrade(move |socket| async move {
+        debug!(?peer, "RPC tunnel opened");
+        tunnel.connect(stream(socket), peer).await;
+    })
+}
+
+/// An axum WebSocket message, as a tunnel reads it.
+struct TunnelMessage(Message);
+
+impl WsMessage for TunnelMessage {
+    fn binary(data: Bytes) -> Self {
+        Self(Message::Binary(data))
+    }
+
+    fn into_tunneled(self) -> Tunneled {
+        match self.0 {
+            Message::Binary(data) => Tunneled::Data(data),
+            Message::Ping(_) | Message::Pong(_) => Tunneled::Control,
+            Message::Close(_) => Tunneled::Close,
+            Message::Text(_) => Tunneled::Text,
+        }
+    }
+}
+
+/// `socket` read and written as the RPC stream it tunnels.
+fn stream(
+    socket: WebSocket,
+) -> WsStream<
+    impl Stream<Item = Result<TunnelMessage, axum::Error>>
+    + Sink<TunnelMessage, Error = axum::Error>
+    + Send
+    + Unpin
+    + 'static,
+> {
+    let socket = socket
+        .map(|message| message.map(TunnelMessage))
+        .with(|TunnelMessage(message)| future::ready(Ok::<_, axum::Error>(message)));
+    WsStream::new(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_rpc::{CancellationToken, Client, Server, ws};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn rpc_is_served_over_the_http_server() {
+        let rpc_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut server = Server::new(rpc_listener, ());
+        let app = router(server.tunnel());
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let http_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_addr = http_listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(http_listener, app).await });
+
+        let stream = TcpStream::connect(http_addr).await.unwrap();
+        let url = format!("ws://{http_addr}{TUNNEL_PATH}");
+        let client = Client::new(ws::connect(&url, stream).await.unwrap());
+        client.ping().await.unwrap();
+
+        cancel.cancel();
+    }
+}
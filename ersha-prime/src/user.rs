@@ -0,0 +1,238 @@
+//! People who log in to prime, and what they may see.
+//!
+//! Users log in with a password or through the OIDC provider configured in
+//! `[users.oidc]`, and are issued a session key: an API key tied to the user
+//! that expires. Requests made with it act with the user's current role, in
+//! the user's organization, limited to the user's fields if any are set.
+
+use std::sync::LazyLock;
+
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use ersha_core::H3Cell;
+use jiff::{SignedDuration, Timestamp};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::auth::{ApiKey, ApiKeyId, Principal, Scope};
+use crate::config::OidcConfig;
+use crate::org::OrgId;
+
+/// Hash checked when the user logging in doesn't exist, so that takes as
+/// long as a wrong password.
+static UNKNOWN_USER_HASH: LazyLock<String> = LazyLock::new(|| hash_password("unknown user"));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct UserId(pub Ulid);
+
+/// What a user may do, within their organization and fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Query data only
+    Viewer,
+    /// Query data and manage irrigation
+    Agronomist,
+    /// Everything, including managing users
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Agronomist => "agronomist",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let role = match s {
+            "viewer" => Role::Viewer,
+            "agronomist" => Role::Agronomist,
+            "admin" => Role::Admin,
+            _ => return None,
+        };
+
+        Some(role)
+    }
+
+    /// The scope the user's session keys act with.
+    pub fn scope(self) -> Scope {
+        match self {
+            Role::Viewer => Scope::ReadOnly,
+            Role::Agronomist => Scope::Agronomist,
+            Role::Admin => Scope::Admin,
+        }
+    }
+}
+
+/// How a user proves who they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    /// Argon2 hash of the user's password, as a PHC string
+    Password { hash: String },
+    /// The user's identity at an OIDC provider
+    Oidc { issuer: String, subject: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub id: UserId,
+    /// Unique login name
+    pub username: String,
+    pub name: String,
+    pub role: Role,
+    /// Platform-wide users see every organization
+    pub org_id: Option<OrgId>,
+    /// Fields, H3 cells at the field resolution, the user is limited to.
+    /// Empty for every field of the organization.
+    pub fields: Vec<H3Cell>,
+    pub credential: Credential,
+    pub created_at: Timestamp,
+}
+
+impl User {
+    pub fn new(username: String, name: String, role: Role, credential: Credential) -> Self {
+        Self {
+            id: UserId(Ulid::new()),
+            username,
+            name,
+            role,
+            org_id: None,
+            fields: Vec::new(),
+            credential,
+            created_at: Timestamp::now(),
+        }
+    }
+
+    /// Check `password` against the user's, if they log in with one.
+    pub fn verify_password(&self, password: &str) -> bool {
+        match &self.credential {
+            Credential::Password { hash } => verify_password(hash, password),
+            Credential::Oidc { .. } => false,
+        }
+    }
+
+    /// What requests made with the user's session key `key_id` may do.
+    pub fn principal(&self, key_id: ApiKeyId) -> Principal {
+        Principal {
+            key_id,
+            scope: self.role.scope(),
+            org_id: self.org_id,
+            user_id: Some(self.id),
+            fields: (!self.fields.is_empty()).then(|| self.fields.as_slice().into()),
+        }
+    }
+
+    /// Issue a session key lasting `lifetime`, returning it with its token.
+    pub fn session(&self, lifetime: SignedDuration, now: Timestamp) -> (ApiKey, String) {
+        let (mut key, token) =
+            ApiKey::generate(format!("session: {}", self.username), self.role.scope());
+        key.org_id = self.org_id;
+        key.user_id = Some(self.id);
+        key.created_at = now;
+        key.expires_at = Some(now.checked_add(lifetime).unwrap_or(Timestamp::MAX));
+
+        (key, token)
+    }
+}
+
+/// Argon2 hash of `password` with a fresh salt.
+pub fn hash_password(password: &str) -> String {
+    let salt: [u8; 16] = rand::rng().random();
+    let salt = SaltString::encode_b64(&salt).expect("16 bytes is a valid salt");
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("default Argon2 parameters hash any password")
+        .to_string()
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Spend as long as checking a password, for logins to unknown users.
+pub fn reject_unknown(password: &str) {
+    let _ = verify_password(&UNKNOWN_USER_HASH, password);
+}
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("provider answered {0}")]
+    Rejected(u16),
+    #[error("userinfo has no subject")]
+    MissingSubject,
+}
+
+/// The subject `access_token` was issued for, as the provider's userinfo
+/// endpoint reports it. The endpoint rejects tokens it didn't issue.
+pub async fn oidc_subject(
+    client: &reqwest::Client,
+    config: &OidcConfig,
+    access_token: &str,
+) -> Result<String, OidcError> {
+    let response = client
+        .get(&config.userinfo_url)
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(OidcError::Rejected(response.status().as_u16()));
+    }
+
+    let userinfo: serde_json::Value = response.json().await?;
+    userinfo["sub"]
+        .as_str()
+        .map(str::to_owned)
+        .ok_or(OidcError::MissingSubject)
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::H3Cell;
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::{Credential, Role, User, hash_password};
+    use crate::auth::{Scope, parse_token};
+
+    #[test]
+    fn sessions_act_as_the_user() {
+        let mut user = User::new(
+            "hana".to_owned(),
+            "Hana Bekele".to_owned(),
+            Role::Agronomist,
+            Credential::Password {
+                hash: hash_password("correct horse"),
+            },
+        );
+        assert!(user.verify_password("correct horse"));
+        assert!(!user.verify_password("wrong horse"));
+
+        let now = Timestamp::now();
+        let (key, token) = user.session(SignedDuration::from_hours(12), now);
+        let (_, secret) = parse_token(&token).unwrap();
+        assert!(key.verify(secret));
+        assert_eq!(key.user_id, Some(user.id));
+        assert!(!key.is_expired(now));
+        assert!(key.is_expired(now + SignedDuration::from_hours(12)));
+
+        let field = H3Cell(0x892a1072b5bffff);
+        user.fields = vec![field];
+        let principal = user.principal(key.id);
+        assert_eq!(principal.scope, Scope::Agronomist);
+        assert!(principal.can_see(field));
+        assert!(principal.can_see(H3Cell(0x8a2a1072b59ffff)));
+        assert!(!principal.can_see(H3Cell(0x8a2a1072b4a7fff)));
+    }
+}
@@ -0,0 +1,255 @@
+//! Plausibility rules for readings.
+//!
+//! A [`ValidationRule`] bounds the values of one metric kind, everywhere or
+//! inside a region, and how fast they may change. Ingested readings are
+//! checked against the finest rule covering them and flagged with a
+//! [`QualityStatus`]; readings no rule covers are good.
+
+use std::collections::HashMap;
+
+use ersha_core::{H3Cell, ReadingId, SensorId, SensorKind, SensorReading};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::events::{BusEvent, Received, Subscription};
+use crate::metrics;
+use crate::registry::{ReadingRegistry, Registries, ValidationRuleRegistry};
+use crate::rollup::metric_value;
+
+pub use ersha_core::QualityStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct ValidationRuleId(pub Ulid);
+
+/// Plausible values of one metric kind.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ValidationRule {
+    pub id: ValidationRuleId,
+    pub metric: SensorKind,
+    /// Only readings taken inside this cell, or everywhere if `None`
+    pub within: Option<H3Cell>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Largest change from the sensor's previous reading, per hour
+    pub max_change_per_hour: Option<f64>,
+    pub created_at: Timestamp,
+}
+
+impl ValidationRule {
+    pub fn new(metric: SensorKind) -> Self {
+        Self {
+            id: ValidationRuleId(Ulid::new()),
+            metric,
+            within: None,
+            min: None,
+            max: None,
+            max_change_per_hour: None,
+            created_at: Timestamp::now(),
+        }
+    }
+
+    pub fn covers(&self, reading: &SensorReading) -> bool {
+        self.metric == reading.metric.kind()
+            && self
+                .within
+                .is_none_or(|cell| reading.location.is_within(cell))
+    }
+
+    /// Resolution of the region the rule is scoped to; rules for everywhere
+    /// come before any region.
+    fn specificity(&self) -> Option<u8> {
+        self.within.map(|cell| cell.resolution())
+    }
+}
+
+/// The finest of `rules` covering `reading`.
+pub fn rule_for<'a>(
+    rules: &'a [ValidationRule],
+    reading: &SensorReading,
+) -> Option<&'a ValidationRule> {
+    rules
+        .iter()
+        .filter(|rule| rule.covers(reading))
+        .max_by_key(|rule| rule.specificity())
+}
+
+/// Checks readings against rules, remembering each sensor's latest value to
+/// measure how fast it changes.
+#[derive(Debug, Default)]
+pub struct Validator {
+    previous: HashMap<SensorId, (Timestamp, f64)>,
+}
+
+impl Validator {
+    /// Status of `reading` under `rules`.
+    ///
+    /// Late readings are only checked against the range, since the sensor's
+    /// previous value is not the one before them.
+    pub fn check(
+        &mut self,
+        rules: &[ValidationRule],
+        reading: &SensorReading,
+        late: bool,
+    ) -> QualityStatus {
+        let value = metric_value(&reading.metric);
+        let previous = if late {
+            None
+        } else {
+            self.remember(reading.sensor_id, reading.timestamp, value)
+        };
+
+        let Some(rule) = rule_for(rules, reading) else {
+            return QualityStatus::Good;
+        };
+        if rule.min.is_some_and(|min| value < min) || rule.max.is_some_and(|max| value > max) {
+            return QualityStatus::OutOfRange;
+        }
+        if let (Some(limit), Some((at, before))) = (rule.max_change_per_hour, previous) {
+            let hours = reading.timestamp.duration_since(at).as_secs_f64() / 3_600.0;
+            if hours > 0.0 && (value - before).abs() / hours > limit {
+                return QualityStatus::RateExceeded;
+            }
+        }
+
+        QualityStatus::Good
+    }
+
+    /// Record `value` as the sensor's latest if it's newer, returning the one
+    /// it follows.
+    fn remember(
+        &mut self,
+        sensor_id: SensorId,
+        timestamp: Timestamp,
+        value: f64,
+    ) -> Option<(Timestamp, f64)> {
+        let previous = self.previous.get(&sensor_id).copied();
+        if previous.is_some_and(|(at, _)| timestamp <= at) {
+            return None;
+        }
+        self.previous.insert(sensor_id, (timestamp, value));
+
+        previous
+    }
+}
+
+/// Flag ingested readings that break the validation rules until cancelled.
+///
+/// Rules are loaded for each batch, so edits apply to the next upload.
+pub async fn run<R: Registries>(
+    registries: R,
+    mut events: Subscription,
+    cancel: CancellationToken,
+) {
+    let mut validator = Validator::default();
+
+    loop {
+        let event = tokio::select! {
+            _ = cancel.cancelled() => break,
+            received = events.recv() => match received {
+                Some(Received::Event(event)) => event,
+                Some(Received::Lagged(_)) => continue,
+                None => break,
+            },
+        };
+        let BusEvent::ReadingsIngested { readings, late } = &*event else {
+            continue;
+        };
+
+        let rules = match registries.validation_rules().list().await {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!(error = ?e, "failed to load validation rules");
+                continue;
+            }
+        };
+
+        let mut ordered: Vec<&SensorReading> = readings.iter().collect();
+        ordered.sort_by_key(|reading| (reading.timestamp, reading.id.0));
+        let flagged: Vec<(ReadingId, QualityStatus)> = ordered
+            .into_iter()
+            .map(|reading| (reading.id, validator.check(&rules, reading, *late)))
+            .filter(|(_, status)| *status != QualityStatus::Good)
+            .collect();
+        if flagged.is_empty() {
+            continue;
+        }
+
+        metrics::record_flagged(&flagged);
+        if let Err(e) = registries.readings().set_quality(flagged).await {
+            error!(error = ?e, "failed to flag readings");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{H3Cell, Percentage, SensorId, SensorKind, SensorMetric, SensorReading};
+    use jiff::Timestamp;
+    use ulid::Ulid;
+
+    use super::{QualityStatus, ValidationRule, Validator};
+    use crate::fixtures;
+    use crate::region;
+
+    fn reading(sensor_id: SensorId, location: u64, second: i64, value: u8) -> SensorReading {
+        SensorReading {
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(value),
+            },
+            location: H3Cell(location),
+            timestamp: Timestamp::from_second(second).unwrap(),
+            sensor_id,
+            ..fixtures::reading()
+        }
+    }
+
+    #[test]
+    fn finest_rule_applies_and_rate_is_per_sensor() {
+        let field = 0x8a2a1072b59ffff;
+        let elsewhere = 0x8a3a1072b59ffff;
+        let region = region::parent(H3Cell(field), 6).unwrap();
+
+        let mut everywhere = ValidationRule::new(SensorKind::SoilMoisture);
+        everywhere.max = Some(60.0);
+        everywhere.max_change_per_hour = Some(10.0);
+        let mut wetland = ValidationRule::new(SensorKind::SoilMoisture);
+        wetland.within = Some(region);
+        wetland.max = Some(95.0);
+        let rules = [everywhere, wetland];
+
+        let mut validator = Validator::default();
+        let sensor = SensorId(Ulid::new());
+        assert_eq!(
+            validator.check(&rules, &reading(sensor, field, 0, 90), false),
+            QualityStatus::Good
+        );
+        assert_eq!(
+            validator.check(
+                &rules,
+                &reading(SensorId(Ulid::new()), elsewhere, 0, 90),
+                false
+            ),
+            QualityStatus::OutOfRange
+        );
+
+        let probe = SensorId(Ulid::new());
+        validator.check(&rules, &reading(probe, elsewhere, 0, 20), false);
+        assert_eq!(
+            validator.check(&rules, &reading(probe, elsewhere, 1_800, 30), false),
+            QualityStatus::RateExceeded
+        );
+        assert_eq!(
+            validator.check(&rules, &reading(probe, elsewhere, 5_400, 40), false),
+            QualityStatus::Good
+        );
+        // Late readings don't count as the sensor's previous value.
+        assert_eq!(
+            validator.check(&rules, &reading(probe, elsewhere, 60, 55), true),
+            QualityStatus::Good
+        );
+    }
+}
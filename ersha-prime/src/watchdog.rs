@@ -0,0 +1,248 @@
+//! Detection of devices that stopped reporting.
+//!
+//! Devices given an expected reporting interval are scanned periodically.
+//! One whose latest reading or status is older than its interval plus the
+//! configured grace period is marked disconnected, the same as when its
+//! dispatcher reports it so, and a `device_offline` event is raised. It is
+//! marked connected again once it reports.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use ersha_core::{DeviceId, DeviceState, DispatcherId};
+use jiff::{SignedDuration, Timestamp};
+use thiserror::Error;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::config::WatchdogConfig;
+use crate::events::{BusEvent, EventBus};
+use crate::registry::{DeviceRegistry, DeviceStatusRegistry, ReadingRegistry, Registries};
+use crate::webhook::offline_event;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum WatchdogError {
+    #[error("failed to read or mark devices: {0}")]
+    Devices(#[source] BoxError),
+    #[error("failed to read latest readings or statuses: {0}")]
+    Reports(#[source] BoxError),
+}
+
+/// Devices a scan changed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Scan {
+    pub offline: usize,
+    pub reconnected: usize,
+}
+
+/// Scan every `interval_secs` until cancelled.
+pub async fn run<R: Registries>(
+    registries: R,
+    events: Arc<dyn EventBus>,
+    config: WatchdogConfig,
+    cancel: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let grace = SignedDuration::from_secs(config.grace_secs as i64);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        match scan(&registries, events.as_ref(), grace, Timestamp::now()).await {
+            Ok(Scan {
+                offline: 0,
+                reconnected: 0,
+            }) => {}
+            Ok(scan) => info!(
+                offline = scan.offline,
+                reconnected = scan.reconnected,
+                "device watchdog scan"
+            ),
+            Err(e) => error!(error = %e, "device watchdog scan failed"),
+        }
+    }
+}
+
+/// Mark active devices silent for longer than their interval plus `grace`
+/// as of `now` disconnected, and those marked but heard from since as
+/// connected.
+///
+/// Devices that never reported are left alone.
+pub async fn scan<R: Registries>(
+    registries: &R,
+    events: &dyn EventBus,
+    grace: SignedDuration,
+    now: Timestamp,
+) -> Result<Scan, WatchdogError> {
+    let devices = registries.devices();
+    let device_error = |e| WatchdogError::Devices(Box::new(e));
+
+    let watched = devices.reporting_intervals().await.map_err(device_error)?;
+    let disconnected = devices.disconnected().await.map_err(device_error)?;
+    let disconnected_since = |id| {
+        disconnected
+            .iter()
+            .find(|&&(device, _)| device == id)
+            .map(|&(_, since)| since)
+    };
+
+    let mut scan = Scan::default();
+    for (id, interval_secs) in watched {
+        let active = devices
+            .get(id)
+            .await
+            .map_err(device_error)?
+            .is_some_and(|device| device.state == DeviceState::Active);
+        if !active {
+            continue;
+        }
+        let Some((last_seen, dispatcher_id)) = last_report(registries, id).await? else {
+            continue;
+        };
+
+        let deadline = last_seen + SignedDuration::from_secs(interval_secs as i64) + grace;
+        match disconnected_since(id) {
+            None if now > deadline => {
+                let org_id = devices.org(id).await.map_err(device_error)?;
+                let event = offline_event(id, dispatcher_id, last_seen, org_id);
+                devices
+                    .mark_offline(id, last_seen, event)
+                    .await
+                    .map_err(device_error)?;
+                info!(device_id = ?id, %last_seen, "device missed its reporting interval");
+                events
+                    .publish(BusEvent::DeviceOffline {
+                        device_id: id,
+                        dispatcher_id,
+                        last_seen,
+                        org_id,
+                    })
+                    .await;
+                scan.offline += 1;
+            }
+            Some(since) if now <= deadline && last_seen > since => {
+                devices
+                    .set_disconnected(id, None)
+                    .await
+                    .map_err(device_error)?;
+                scan.reconnected += 1;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(scan)
+}
+
+/// When the device last reported, by reading or status, and through which
+/// dispatcher.
+async fn last_report<R: Registries>(
+    registries: &R,
+    id: DeviceId,
+) -> Result<Option<(Timestamp, DispatcherId)>, WatchdogError> {
+    let readings = registries
+        .readings()
+        .latest_per_sensor(id)
+        .await
+        .map_err(|e| WatchdogError::Reports(Box::new(e)))?;
+    let status = registries
+        .statuses()
+        .latest(id)
+        .await
+        .map_err(|e| WatchdogError::Reports(Box::new(e)))?;
+
+    Ok(readings
+        .iter()
+        .map(|reading| (reading.timestamp, reading.dispatcher_id))
+        .chain(status.map(|status| (status.timestamp, status.dispatcher_id)))
+        .max_by_key(|&(timestamp, _)| timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{Device, DeviceId, DeviceKind, DeviceState, H3Cell, SensorReading};
+    use jiff::{SignedDuration, Timestamp};
+    use ulid::Ulid;
+
+    use super::{Scan, scan};
+    use crate::events::{EventBus, LocalEventBus, Received};
+    use crate::fixtures;
+    use crate::registry::{
+        DeviceRegistry, OutboxRegistry, ReadingRegistry, memory::InMemoryRegistries,
+    };
+    use crate::webhook::EventKind;
+
+    fn reading(device_id: DeviceId, second: i64) -> SensorReading {
+        SensorReading {
+            device_id,
+            timestamp: Timestamp::from_second(second).unwrap(),
+            ..fixtures::reading()
+        }
+    }
+
+    #[tokio::test]
+    async fn silent_devices_go_offline_and_come_back() {
+        let registries = InMemoryRegistries::default();
+        let bus = LocalEventBus::new();
+        let mut offline_events = bus.subscribe();
+        let device = Device {
+            id: DeviceId(Ulid::new()),
+            kind: DeviceKind::Sensor,
+            state: DeviceState::Active,
+            location: H3Cell(0x8a2a1072b59ffff),
+            manufacturer: None,
+            provisioned_at: Timestamp::from_second(0).unwrap(),
+            sensors: Box::new([]),
+        };
+        let id = device.id;
+        registries.devices.register(device).await.unwrap();
+        registries
+            .devices
+            .set_reporting_interval(id, Some(600))
+            .await
+            .unwrap();
+        registries
+            .readings
+            .batch_store(vec![reading(id, 1_000)])
+            .await
+            .unwrap();
+        let grace = SignedDuration::from_secs(60);
+        let at = |second| Timestamp::from_second(second).unwrap();
+
+        let quiet = scan(&registries, &bus, grace, at(1_600)).await.unwrap();
+        assert_eq!(quiet, Scan::default());
+
+        let silent = scan(&registries, &bus, grace, at(1_700)).await.unwrap();
+        assert_eq!(silent.offline, 1);
+        assert_eq!(
+            registries.devices.disconnected().await.unwrap(),
+            [(id, at(1_000))]
+        );
+        assert!(matches!(
+            offline_events.recv().await,
+            Some(Received::Event(_))
+        ));
+        let staged = registries.outbox.due(Timestamp::now(), 10).await.unwrap();
+        assert_eq!(staged.len(), 1);
+        assert_eq!(staged[0].event.kind, EventKind::DeviceOffline);
+        // Already offline, so no second alert.
+        let again = scan(&registries, &bus, grace, at(1_800)).await.unwrap();
+        assert_eq!(again, Scan::default());
+
+        registries
+            .readings
+            .batch_store(vec![reading(id, 1_900)])
+            .await
+            .unwrap();
+        let back = scan(&registries, &bus, grace, at(1_950)).await.unwrap();
+        assert_eq!(back.reconnected, 1);
+        assert!(registries.devices.disconnected().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,854 @@
+//! Webhook subscriptions and the signed, retried delivery of their events.
+//!
+//! Every delivery is a JSON [`Event`] POSTed to the subscriber. The
+//! [`SIGNATURE_HEADER`] carries `sha256=<hex>`, an HMAC-SHA256 keyed with the
+//! webhook secret over `"{timestamp}.{body}"`, where `timestamp` is the
+//! [`TIMESTAMP_HEADER`] value in unix seconds.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ersha_core::{DeviceId, DispatcherId, SensorId, SensorKind, SensorReading};
+use futures_util::{StreamExt, stream};
+use hmac::{Hmac, Mac};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+use ulid::Ulid;
+use utoipa::ToSchema;
+
+use crate::auth::{generate_secret, hex};
+use crate::config::WebhookConfig;
+use crate::events::{BusEvent, Received, Subscription};
+use crate::metrics;
+use crate::notify::{self, Contact};
+use crate::org::OrgId;
+use crate::outbox::{OutboxEntry, Recipient};
+use crate::registry::{
+    ContactRegistry, DispatcherRegistry, Registries, RegistryError, WebhookRegistry,
+};
+use crate::rollup::metric_value;
+
+pub const SIGNATURE_HEADER: &str = "x-ersha-signature";
+pub const TIMESTAMP_HEADER: &str = "x-ersha-timestamp";
+pub const EVENT_HEADER: &str = "x-ersha-event";
+pub const DELIVERY_HEADER: &str = "x-ersha-delivery";
+
+/// Deliveries attempted per poll of the worker.
+const DELIVERY_BATCH: usize = 100;
+/// Deliveries attempted at once.
+const DELIVERY_CONCURRENCY: usize = 16;
+/// How often the threshold watch picks up new or changed subscriptions.
+const SUBSCRIPTION_REFRESH: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct WebhookId(pub Ulid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct EventId(pub Ulid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct DeliveryId(pub Ulid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    DeviceRegistered,
+    DeviceOffline,
+    AlertRaised,
+    /// A reading crossed one of the subscription's thresholds
+    ThresholdCrossed,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::DeviceRegistered => "device_registered",
+            EventKind::DeviceOffline => "device_offline",
+            EventKind::AlertRaised => "alert_raised",
+            EventKind::ThresholdCrossed => "threshold_crossed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Rising from at or below the value to above it
+    Above,
+    /// Falling from at or above the value to below it
+    Below,
+}
+
+/// A bound on a metric, e.g. soil moisture going below 20%.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Threshold {
+    pub metric: SensorKind,
+    pub direction: Direction,
+    /// In the metric's unit
+    pub value: f64,
+}
+
+impl Threshold {
+    /// Whether `value` is past the threshold.
+    pub fn is_beyond(&self, value: f64) -> bool {
+        match self.direction {
+            Direction::Above => value > self.value,
+            Direction::Below => value < self.value,
+        }
+    }
+}
+
+/// A subscriber URL and the events it wants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    pub id: WebhookId,
+    pub url: String,
+    pub events: Vec<EventKind>,
+    /// Checked against ingested readings when subscribed to `threshold_crossed`
+    pub thresholds: Vec<Threshold>,
+    /// Key for the payload signature
+    pub secret: String,
+    /// Only this organization's events are delivered. Platform-wide
+    /// webhooks receive every event.
+    pub org_id: Option<OrgId>,
+    pub created_at: Timestamp,
+}
+
+impl Webhook {
+    /// Create a subscription with a fresh signing secret.
+    pub fn generate(url: String, events: Vec<EventKind>, thresholds: Vec<Threshold>) -> Self {
+        Self {
+            id: WebhookId(Ulid::new()),
+            url,
+            events,
+            thresholds,
+            secret: generate_secret(),
+            org_id: None,
+            created_at: Timestamp::now(),
+        }
+    }
+
+    pub fn subscribes_to(&self, kind: EventKind) -> bool {
+        self.events.contains(&kind)
+    }
+
+    /// Whether events concerning `org_id` may be delivered here.
+    pub fn receives(&self, org_id: Option<OrgId>) -> bool {
+        self.org_id.is_none() || self.org_id == org_id
+    }
+}
+
+/// Something that happened, as delivered to subscribers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Event {
+    pub id: EventId,
+    pub kind: EventKind,
+    pub occurred_at: Timestamp,
+    /// Organization the event concerns, if any
+    #[serde(default)]
+    pub org_id: Option<OrgId>,
+    #[schema(value_type = Object)]
+    pub data: serde_json::Value,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, data: serde_json::Value) -> Self {
+        Self {
+            id: EventId(Ulid::new()),
+            kind,
+            occurred_at: Timestamp::now(),
+            org_id: None,
+            data,
+        }
+    }
+
+    pub fn with_org(mut self, org_id: Option<OrgId>) -> Self {
+        self.org_id = org_id;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    /// Waiting for its first attempt or a retry
+    Pending,
+    Delivered,
+    /// Gave up after the configured number of attempts
+    Failed,
+}
+
+/// One event on its way to one webhook.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Delivery {
+    pub id: DeliveryId,
+    pub webhook_id: WebhookId,
+    pub event: Event,
+    pub state: DeliveryState,
+    pub attempts: u32,
+    pub next_attempt_at: Timestamp,
+    pub last_attempt_at: Option<Timestamp>,
+    /// HTTP status of the last attempt, if the subscriber answered
+    pub last_status: Option<u16>,
+    pub last_error: Option<String>,
+}
+
+impl Delivery {
+    pub fn new(webhook_id: WebhookId, event: Event) -> Self {
+        Self {
+            id: DeliveryId(Ulid::new()),
+            webhook_id,
+            next_attempt_at: event.occurred_at,
+            event,
+            state: DeliveryState::Pending,
+            attempts: 0,
+            last_attempt_at: None,
+            last_status: None,
+            last_error: None,
+        }
+    }
+}
+
+/// Queue `event` for every webhook subscribed to its kind that may receive
+/// its organization's events.
+///
+/// Returns how many deliveries were queued.
+pub async fn emit<W: WebhookRegistry>(webhooks: &W, event: Event) -> Result<usize, W::Error> {
+    let deliveries: Vec<Delivery> = webhooks
+        .list()
+        .await?
+        .into_iter()
+        .filter(|webhook| webhook.subscribes_to(event.kind) && webhook.receives(event.org_id))
+        .map(|webhook| Delivery::new(webhook.id, event.clone()))
+        .collect();
+
+    let queued = deliveries.len();
+    if queued > 0 {
+        webhooks.enqueue(deliveries).await?;
+    }
+
+    Ok(queued)
+}
+
+/// `sha256=<hex>` signature of `body` sent at `timestamp`.
+pub fn sign(secret: &str, timestamp: Timestamp, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_second().to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!("sha256={}", hex(&mac.finalize().into_bytes()))
+}
+
+/// Wait before retrying after the `attempts`th failure.
+pub(crate) fn backoff(config: &WebhookConfig, attempts: u32) -> SignedDuration {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    let secs = config
+        .initial_backoff_secs
+        .saturating_mul(factor)
+        .min(config.max_backoff_secs);
+
+    SignedDuration::from_secs(secs as i64)
+}
+
+/// Deliver due events every `poll_interval_secs` until cancelled.
+pub async fn run_deliveries<R: Registries>(
+    registries: R,
+    config: WebhookConfig,
+    cancel: CancellationToken,
+) {
+    // Redirects could lead deliveries to addresses webhooks may not target.
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            error!(error = %e, "failed to build webhook HTTP client, webhooks are disabled");
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) = deliver_due(registries.webhooks(), &client, &config, Timestamp::now()).await
+        {
+            error!(error = %e, "failed to process webhook deliveries");
+        }
+    }
+}
+
+/// Attempt every delivery due at `now`, recording the outcome of each.
+/// Up to [`DELIVERY_CONCURRENCY`] are in flight at once, so one slow
+/// subscriber doesn't hold up the rest.
+pub async fn deliver_due<W: WebhookRegistry>(
+    webhooks: &W,
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    now: Timestamp,
+) -> Result<usize, W::Error> {
+    let due = webhooks.due(now, DELIVERY_BATCH).await?;
+    let attempted = due.len();
+
+    let mut deliveries = stream::iter(due)
+        .map(|delivery| deliver(webhooks, client, config, now, delivery))
+        .buffer_unordered(DELIVERY_CONCURRENCY);
+    while let Some(delivered) = deliveries.next().await {
+        delivered?;
+    }
+
+    Ok(attempted)
+}
+
+async fn deliver<W: WebhookRegistry>(
+    webhooks: &W,
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    now: Timestamp,
+    mut delivery: Delivery,
+) -> Result<(), W::Error> {
+    let Some(webhook) = webhooks.get(delivery.webhook_id).await? else {
+        return Ok(());
+    };
+
+    let outcome = attempt(client, &webhook, &delivery.event, now).await;
+    delivery.attempts += 1;
+    delivery.last_attempt_at = Some(now);
+
+    match outcome {
+        Ok(status) if (200..300).contains(&status) => {
+            delivery.state = DeliveryState::Delivered;
+            delivery.last_status = Some(status);
+            delivery.last_error = None;
+        }
+        Ok(status) => {
+            delivery.last_status = Some(status);
+            delivery.last_error = Some(format!("subscriber answered {status}"));
+        }
+        Err(e) => {
+            delivery.last_status = None;
+            delivery.last_error = Some(e.to_string());
+        }
+    }
+
+    if delivery.state == DeliveryState::Pending {
+        if delivery.attempts >= config.max_attempts {
+            warn!(delivery_id = ?delivery.id, url = %webhook.url, "giving up on webhook delivery");
+            delivery.state = DeliveryState::Failed;
+        } else {
+            delivery.next_attempt_at = now + backoff(config, delivery.attempts);
+        }
+    }
+
+    metrics::record_webhook_delivery(delivery.state);
+    webhooks.update_delivery(delivery).await
+}
+
+async fn attempt(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    event: &Event,
+    now: Timestamp,
+) -> Result<u16, reqwest::Error> {
+    let body = serde_json::to_vec(event).expect("events serialize to JSON");
+
+    let response = client
+        .post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, event.kind.as_str())
+        .header(DELIVERY_HEADER, event.id.0.to_string())
+        .header(TIMESTAMP_HEADER, now.as_second().to_string())
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, now, &body))
+        .body(body)
+        .send()
+        .await?;
+
+    Ok(response.status().as_u16())
+}
+
+/// A subscriber with thresholds to watch readings against.
+trait Watcher {
+    fn watcher_id(&self) -> Ulid;
+    fn thresholds(&self) -> &[Threshold];
+}
+
+impl Watcher for Webhook {
+    fn watcher_id(&self) -> Ulid {
+        self.id.0
+    }
+
+    fn thresholds(&self) -> &[Threshold] {
+        &self.thresholds
+    }
+}
+
+impl Watcher for Contact {
+    fn watcher_id(&self) -> Ulid {
+        self.id.0
+    }
+
+    fn thresholds(&self) -> &[Threshold] {
+        &self.thresholds
+    }
+}
+
+/// Which sensors are currently past which thresholds, so only crossings fire.
+#[derive(Clone, Default)]
+struct ThresholdState {
+    beyond: HashMap<(Ulid, usize, SensorId), bool>,
+}
+
+impl ThresholdState {
+    /// Thresholds of `watchers` that `reading` has just crossed.
+    fn crossed<'a, W: Watcher>(
+        &mut self,
+        watchers: &'a [W],
+        reading: &SensorReading,
+    ) -> Vec<(&'a W, &'a Threshold)> {
+        let kind = reading.metric.kind();
+        let value = metric_value(&reading.metric);
+        let mut crossed = Vec::new();
+
+        for watcher in watchers {
+            for (index, threshold) in watcher.thresholds().iter().enumerate() {
+                if threshold.metric != kind {
+                    continue;
+                }
+
+                let beyond = threshold.is_beyond(value);
+                let was_beyond = self
+                    .beyond
+                    .insert((watcher.watcher_id(), index, reading.sensor_id), beyond)
+                    .unwrap_or(false);
+                if beyond && !was_beyond {
+                    crossed.push((watcher, threshold));
+                }
+            }
+        }
+
+        crossed
+    }
+}
+
+fn threshold_event(threshold: &Threshold, reading: &SensorReading, org_id: Option<OrgId>) -> Event {
+    Event::new(
+        EventKind::ThresholdCrossed,
+        serde_json::json!({
+            "threshold": threshold,
+            "value": metric_value(&reading.metric),
+            "reading": reading,
+        }),
+    )
+    .with_org(org_id)
+}
+
+/// Raises `threshold_crossed` events, for webhooks and contacts alike, from
+/// readings as they are ingested.
+///
+/// Each crossing is staged in the outbox for its one subscriber, in the same
+/// transaction as the readings, so none is lost if prime stops before
+/// relaying it. Which sensors are past which thresholds is only updated once
+/// that transaction commits, and uploads are watched one at a time.
+#[derive(Clone, Default)]
+pub struct ThresholdWatch {
+    state: Arc<Mutex<WatchState>>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    webhooks: Vec<Webhook>,
+    contacts: Vec<Contact>,
+    refreshed_at: Option<Instant>,
+    webhooks_beyond: ThresholdState,
+    contacts_beyond: ThresholdState,
+}
+
+/// Crossings found in an upload, to be committed once it is stored.
+pub struct Crossings<'a> {
+    state: MutexGuard<'a, WatchState>,
+    webhooks_beyond: ThresholdState,
+    contacts_beyond: ThresholdState,
+}
+
+impl Crossings<'_> {
+    /// Record the upload as stored, so its crossings don't fire again.
+    pub fn commit(mut self) {
+        self.state.webhooks_beyond = self.webhooks_beyond;
+        self.state.contacts_beyond = self.contacts_beyond;
+    }
+}
+
+impl ThresholdWatch {
+    /// Outbox entries for the thresholds `readings`, uploaded by
+    /// `dispatcher_id`, cross. Dropping the returned [`Crossings`] instead of
+    /// committing them leaves the watch as it was.
+    pub async fn crossings<R: Registries>(
+        &self,
+        registries: &R,
+        dispatcher_id: DispatcherId,
+        readings: &[SensorReading],
+    ) -> Result<(Vec<OutboxEntry>, Crossings<'_>), RegistryError> {
+        let mut state = self.state.lock().await;
+        if state
+            .refreshed_at
+            .is_none_or(|at| at.elapsed() >= SUBSCRIPTION_REFRESH)
+        {
+            refresh(registries, &mut state).await;
+        }
+
+        let mut webhooks_beyond = state.webhooks_beyond.clone();
+        let mut contacts_beyond = state.contacts_beyond.clone();
+        let mut entries = Vec::new();
+        if !readings.is_empty() && (!state.webhooks.is_empty() || !state.contacts.is_empty()) {
+            // Readings belong to the organization of the dispatcher that
+            // uploaded them.
+            let org_id = registries
+                .dispatchers()
+                .org(dispatcher_id)
+                .await
+                .map_err(Into::into)?;
+
+            for reading in readings {
+                for (webhook, threshold) in webhooks_beyond.crossed(&state.webhooks, reading) {
+                    if webhook.receives(org_id) {
+                        entries.push(OutboxEntry::to(
+                            Recipient::Webhook(webhook.id),
+                            threshold_event(threshold, reading, org_id),
+                        ));
+                    }
+                }
+                for (contact, threshold) in contacts_beyond.crossed(&state.contacts, reading) {
+                    if contact.receives(org_id) {
+                        entries.push(OutboxEntry::to(
+                            Recipient::Contact(contact.id),
+                            threshold_event(threshold, reading, org_id),
+                        ));
+                    }
+                }
+            }
+        }
+        if !entries.is_empty() {
+            debug!(count = entries.len(), "thresholds crossed");
+        }
+
+        Ok((
+            entries,
+            Crossings {
+                state,
+                webhooks_beyond,
+                contacts_beyond,
+            },
+        ))
+    }
+}
+
+/// Pick up new or changed subscriptions, keeping the old ones on failure.
+async fn refresh<R: Registries>(registries: &R, state: &mut WatchState) {
+    match registries.webhooks().list().await {
+        Ok(all) => {
+            state.webhooks = all
+                .into_iter()
+                .filter(|w| w.subscribes_to(EventKind::ThresholdCrossed))
+                .collect();
+        }
+        Err(e) => error!(error = %e, "failed to refresh webhook subscriptions"),
+    }
+    match registries.contacts().list().await {
+        Ok(all) => {
+            state.contacts = all
+                .into_iter()
+                .filter(|c| c.subscribes_to(EventKind::ThresholdCrossed))
+                .collect();
+        }
+        Err(e) => error!(error = %e, "failed to refresh contact subscriptions"),
+    }
+    state.refreshed_at = Some(Instant::now());
+}
+
+/// The `device_offline` event for a device last seen by its dispatcher at
+/// `last_seen`.
+pub fn offline_event(
+    device_id: DeviceId,
+    dispatcher_id: DispatcherId,
+    last_seen: Timestamp,
+    org_id: Option<OrgId>,
+) -> Event {
+    Event::new(
+        EventKind::DeviceOffline,
+        serde_json::json!({
+            "device_id": device_id,
+            "dispatcher_id": dispatcher_id,
+            "last_seen": last_seen,
+        }),
+    )
+    .with_org(org_id)
+}
+
+/// The event delivered to subscribers for `event`, if it is one they can
+/// subscribe to and isn't relayed through the [`crate::outbox`].
+pub fn outbound(event: &BusEvent) -> Option<Event> {
+    let event = match event {
+        BusEvent::DeviceRegistered { device, org_id } => Event::new(
+            EventKind::DeviceRegistered,
+            serde_json::json!({
+                "device_id": device.id,
+                "kind": device.kind,
+                "location": device.location,
+            }),
+        )
+        .with_org(*org_id),
+        BusEvent::AlertRaised { message, org_id } => Event::new(
+            EventKind::AlertRaised,
+            serde_json::json!({ "message": message }),
+        )
+        .with_org(*org_id),
+        // Staged in the outbox along with the disconnection instead.
+        BusEvent::DeviceOffline { .. } => return None,
+        BusEvent::ReadingsIngested { .. } | BusEvent::StatusesIngested { .. } => return None,
+    };
+
+    Some(event)
+}
+
+/// Queue deliveries to webhooks and notifications to contacts for the events
+/// they subscribe to, until cancelled.
+pub async fn relay_events<R: Registries>(
+    registries: R,
+    mut events: Subscription,
+    cancel: CancellationToken,
+) {
+    loop {
+        let event = tokio::select! {
+            _ = cancel.cancelled() => break,
+            received = events.recv() => match received {
+                Some(Received::Event(event)) => event,
+                Some(Received::Lagged(_)) => continue,
+                None => break,
+            },
+        };
+        let Some(event) = outbound(&event) else {
+            continue;
+        };
+
+        if let Err(e) = notify::emit(registries.contacts(), event.clone()).await {
+            error!(error = ?e, kind = event.kind.as_str(), "failed to notify contacts of event");
+        }
+        if let Err(e) = emit(registries.webhooks(), event.clone()).await {
+            error!(error = ?e, kind = event.kind.as_str(), "failed to queue event");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{Router, http::HeaderMap, http::StatusCode, routing::post};
+    use ersha_core::{
+        Device, DeviceId, DeviceKind, DeviceState, DispatcherId, H3Cell, Percentage, ReadingId,
+        SensorId, SensorKind, SensorMetric, SensorReading,
+    };
+    use jiff::{SignedDuration, Timestamp};
+    use tokio_util::sync::CancellationToken;
+    use ulid::Ulid;
+
+    use super::{
+        DeliveryState, Direction, Event, EventKind, SIGNATURE_HEADER, TIMESTAMP_HEADER, Threshold,
+        ThresholdState, Webhook, deliver_due, emit, relay_events, sign,
+    };
+    use crate::config::WebhookConfig;
+    use crate::events::{BusEvent, EventBus, LocalEventBus};
+    use crate::registry::{
+        Registries, WebhookRegistry,
+        memory::{InMemoryRegistries, InMemoryWebhookRegistry},
+    };
+
+    fn moisture(sensor_id: SensorId, value: u8) -> SensorReading {
+        SensorReading {
+            id: ReadingId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            metric: SensorMetric::SoilMoisture {
+                value: Percentage(value),
+            },
+            location: H3Cell(0x8a2a1072b59ffff),
+            confidence: Percentage(90),
+            timestamp: Timestamp::now(),
+            sensor_id,
+        }
+    }
+
+    #[test]
+    fn thresholds_fire_only_when_crossed() {
+        let webhook = Webhook::generate(
+            "http://localhost/hook".to_owned(),
+            vec![EventKind::ThresholdCrossed],
+            vec![Threshold {
+                metric: SensorKind::SoilMoisture,
+                direction: Direction::Below,
+                value: 20.0,
+            }],
+        );
+        let webhooks = [webhook];
+        let sensor = SensorId(Ulid::new());
+        let mut state = ThresholdState::default();
+
+        let fired = |state: &mut ThresholdState, value| {
+            state.crossed(&webhooks, &moisture(sensor, value)).len()
+        };
+
+        assert_eq!(fired(&mut state, 30), 0);
+        assert_eq!(fired(&mut state, 15), 1);
+        // Staying dry doesn't fire again until it recovers first.
+        assert_eq!(fired(&mut state, 10), 0);
+        assert_eq!(fired(&mut state, 25), 0);
+        assert_eq!(fired(&mut state, 5), 1);
+    }
+
+    #[tokio::test]
+    async fn bus_events_are_queued_for_subscribed_webhooks() {
+        let registries = InMemoryRegistries::default();
+        let webhook = Webhook::generate(
+            "http://localhost/hook".to_owned(),
+            vec![EventKind::DeviceRegistered],
+            vec![],
+        );
+        registries.webhooks().create(webhook.clone()).await.unwrap();
+
+        let bus = LocalEventBus::new();
+        let relay = tokio::spawn(relay_events(
+            registries.clone(),
+            bus.subscribe(),
+            CancellationToken::new(),
+        ));
+        let device = Device {
+            id: DeviceId(Ulid::new()),
+            kind: DeviceKind::Sensor,
+            state: DeviceState::Active,
+            location: H3Cell(0x8a2a1072b59ffff),
+            manufacturer: None,
+            provisioned_at: Timestamp::now(),
+            sensors: Box::new([]),
+        };
+        bus.publish(BusEvent::DeviceRegistered {
+            device: device.clone(),
+            org_id: None,
+        })
+        .await;
+        bus.publish(BusEvent::ReadingsIngested {
+            readings: Arc::new([moisture(SensorId(Ulid::new()), 40)]),
+            late: false,
+        })
+        .await;
+        drop(bus);
+        relay.await.unwrap();
+
+        let deliveries = registries
+            .webhooks()
+            .deliveries(webhook.id, 10)
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event.kind, EventKind::DeviceRegistered);
+        assert_eq!(
+            deliveries[0].event.data["device_id"],
+            serde_json::json!(device.id)
+        );
+    }
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Vec<u8>)>>>;
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried_with_a_signature() {
+        let received: Received = Arc::default();
+        let app = Router::new().route(
+            "/hook",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                    let mut received = received.lock().unwrap();
+                    received.push((headers, body.to_vec()));
+                    // Fail the first attempt only.
+                    if received.len() == 1 {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::NO_CONTENT
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let webhooks = InMemoryWebhookRegistry::new();
+        let webhook = Webhook::generate(
+            format!("http://{addr}/hook"),
+            vec![EventKind::DeviceOffline],
+            vec![],
+        );
+        let secret = webhook.secret.clone();
+        webhooks.create(webhook.clone()).await.unwrap();
+
+        let queued = emit(
+            &webhooks,
+            Event::new(EventKind::DeviceOffline, serde_json::json!({})),
+        )
+        .await
+        .unwrap();
+        assert_eq!(queued, 1);
+        let ignored = Event::new(EventKind::AlertRaised, serde_json::json!({}));
+        assert_eq!(emit(&webhooks, ignored).await.unwrap(), 0);
+
+        let config = WebhookConfig {
+            initial_backoff_secs: 10,
+            ..WebhookConfig::default()
+        };
+        let client = reqwest::Client::new();
+        let now = Timestamp::now();
+
+        deliver_due(&webhooks, &client, &config, now).await.unwrap();
+        let delivery = webhooks.deliveries(webhook.id, 10).await.unwrap().remove(0);
+        assert_eq!(delivery.state, DeliveryState::Pending);
+        assert_eq!(delivery.last_status, Some(503));
+        assert_eq!(
+            delivery.next_attempt_at,
+            now + SignedDuration::from_secs(10)
+        );
+
+        // Not due yet.
+        assert_eq!(
+            deliver_due(&webhooks, &client, &config, now).await.unwrap(),
+            0
+        );
+
+        let later = now + SignedDuration::from_secs(10);
+        deliver_due(&webhooks, &client, &config, later)
+            .await
+            .unwrap();
+        let delivery = webhooks.deliveries(webhook.id, 10).await.unwrap().remove(0);
+        assert_eq!(delivery.state, DeliveryState::Delivered);
+        assert_eq!(delivery.attempts, 2);
+
+        let received = received.lock().unwrap();
+        let (headers, body) = &received[1];
+        let sent_at: i64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+        let expected = sign(&secret, Timestamp::from_second(sent_at).unwrap(), body);
+        assert_eq!(headers[SIGNATURE_HEADER].to_str().unwrap(), expected);
+    }
+}
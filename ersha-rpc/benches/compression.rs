@@ -0,0 +1,56 @@
+//! Bandwidth saved by frame compression on typical batch uploads.
+//!
+//! Run with `cargo bench -p ersha-rpc --bench compression`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ersha_core::Compression;
+use ersha_rpc::{WireMessage, compress};
+use support::{batch, envelope};
+
+mod support;
+
+const ITERATIONS: u32 = 50;
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    println!(
+        "{:>8}  {:<5}  {:>9}  {:>6}  {:>10}",
+        "readings", "codec", "bytes", "saved", "time"
+    );
+
+    for size in [50, 500, 5000] {
+        let envelope = envelope(WireMessage::BatchUploadRequest(batch(size)));
+        let raw = postcard::to_stdvec(&envelope).unwrap();
+        println!(
+            "{size:>8}  {:<5}  {:>9}  {:>6}  {:>10}",
+            "none",
+            raw.len(),
+            "-",
+            "-"
+        );
+
+        for codec in [Compression::Lz4, Compression::Zstd] {
+            let compressed = compress(codec, &raw).unwrap();
+            let elapsed = time(|| {
+                black_box(compress(codec, black_box(&raw)).unwrap());
+            });
+            let saved = 100.0 * (1.0 - compressed.len() as f64 / raw.len() as f64);
+            println!(
+                "{size:>8}  {:<5}  {:>9}  {:>5.1}%  {:>10?}",
+                format!("{codec:?}").to_lowercase(),
+                compressed.len(),
+                saved,
+                elapsed
+            );
+        }
+    }
+}
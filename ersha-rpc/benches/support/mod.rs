@@ -0,0 +1,56 @@
+//! Fixtures shared by the benchmarks.
+
+use ersha_core::{
+    BatchId, BatchUploadRequest, DeviceId, DispatcherId, H3Cell, Percentage, ReadingId, SensorId,
+    SensorMetric, SensorReading,
+};
+use ersha_rpc::{Envelope, MessageId, WireMessage};
+use ordered_float::NotNan;
+use ulid::Ulid;
+
+/// A batch of `size` soil temperature readings from 50 devices, as a
+/// dispatcher would upload after a few minutes of buffering.
+pub fn batch(size: usize) -> BatchUploadRequest {
+    let dispatcher_id = DispatcherId(Ulid::new());
+    let devices: Vec<_> = (0..50)
+        .map(|_| (DeviceId(Ulid::new()), SensorId(Ulid::new())))
+        .collect();
+    let timestamp = jiff::Timestamp::now();
+
+    let readings = (0..size)
+        .map(|i| {
+            let (device_id, sensor_id) = devices[i % devices.len()];
+            SensorReading {
+                id: ReadingId(Ulid::new()),
+                device_id,
+                dispatcher_id,
+                metric: SensorMetric::SoilTemp {
+                    value: NotNan::new(15.0 + (i * 37 % 200) as f64 / 10.0).unwrap(),
+                },
+                location: H3Cell(0x8a2a1072b59ffff),
+                confidence: Percentage(85 + (i % 15) as u8),
+                timestamp,
+                sensor_id,
+            }
+        })
+        .collect();
+
+    BatchUploadRequest {
+        id: BatchId(Ulid::new()),
+        dispatcher_id,
+        readings,
+        statuses: Box::new([]),
+        timestamp,
+    }
+}
+
+/// `payload` as sent on its own, unanswered and without a deadline.
+pub fn envelope(payload: WireMessage) -> Envelope {
+    Envelope {
+        msg_id: MessageId::new(),
+        reply_to: None,
+        deadline_ms: None,
+        correlation_id: None,
+        payload,
+    }
+}
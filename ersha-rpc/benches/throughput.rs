@@ -0,0 +1,108 @@
+//! Serialization and frame throughput, to weigh compression and schema
+//! changes against.
+//!
+//! Run with `cargo bench -p ersha-rpc --bench throughput`.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use ersha_core::{Compression, DispatcherId, DispatcherStatus, LinkQuality};
+use ersha_rpc::{Envelope, WireMessage, read_frame, write_compressed_frame};
+use support::{batch, envelope};
+use tokio::runtime::Runtime;
+use ulid::Ulid;
+
+mod support;
+
+/// Readings per batch in the frame benchmarks, up to about what fits a
+/// frame uncompressed.
+const FRAME_SIZES: [usize; 3] = [50, 500, 5000];
+
+/// Buffered between writer and reader, as a socket would.
+const DUPLEX_BYTES: usize = 64 * 1024;
+
+fn status() -> WireMessage {
+    WireMessage::DispatcherStatusRequest(DispatcherStatus {
+        dispatcher_id: DispatcherId(Ulid::new()),
+        pending_readings: 1200,
+        pending_statuses: 40,
+        link: LinkQuality {
+            rtt_ms: Some(180),
+            failed_uploads: 0,
+        },
+        uptime_seconds: 86_400,
+        timestamp: jiff::Timestamp::now(),
+    })
+}
+
+fn envelopes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("envelope");
+    for (name, envelope) in [
+        ("ping", envelope(WireMessage::Ping)),
+        ("status", envelope(status())),
+        (
+            "batch_500",
+            envelope(WireMessage::BatchUploadRequest(batch(500))),
+        ),
+    ] {
+        let bytes = postcard::to_stdvec(&envelope).unwrap();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function(BenchmarkId::new("encode", name), |b| {
+            b.iter(|| postcard::to_stdvec(black_box(&envelope)).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("decode", name), |b| {
+            b.iter(|| postcard::from_bytes::<Envelope>(black_box(&bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn frames(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("frame");
+    for size in FRAME_SIZES {
+        let envelope = envelope(WireMessage::BatchUploadRequest(batch(size)));
+        let bytes = postcard::to_stdvec(&envelope).unwrap().len();
+        // Throughput of the envelope carried, whatever goes over the wire.
+        group.throughput(Throughput::Bytes(bytes as u64));
+
+        for codec in [None, Some(Compression::Lz4), Some(Compression::Zstd)] {
+            let name = codec.map_or("none".to_owned(), |codec| {
+                format!("{codec:?}").to_lowercase()
+            });
+            group.bench_with_input(BenchmarkId::new(name, size), &envelope, |b, envelope| {
+                b.to_async(&runtime).iter(|| async {
+                    let (mut writer, mut reader) = tokio::io::duplex(DUPLEX_BYTES);
+                    let (written, read) = tokio::join!(
+                        write_compressed_frame(&mut writer, envelope, codec),
+                        read_frame(&mut reader),
+                    );
+                    written.unwrap();
+                    black_box(read.unwrap())
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn batches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_upload");
+    for size in [10, 100, 1000, 10_000] {
+        let batch = batch(size);
+        let bytes = postcard::to_stdvec(&batch).unwrap();
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("serialize", size), &batch, |b, batch| {
+            b.iter(|| postcard::to_stdvec(black_box(batch)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("deserialize", size), &bytes, |b, bytes| {
+            b.iter(|| {
+                postcard::from_bytes::<ersha_core::BatchUploadRequest>(black_box(bytes)).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, envelopes, frames, batches);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-use ersha_core::{DispatcherId, H3Cell, HelloRequest};
+use ersha_core::{DispatcherId, H3Cell, HelloRequest, HelloResponse};
 use ersha_rpc::Client;
 use tokio::net::TcpStream;
 use tracing::{error, info};
@@ -44,14 +44,25 @@ async fn main() {
     let hello_request = HelloRequest {
         dispatcher_id: DispatcherId(ulid::Ulid::new()),
         location: H3Cell(0x8a2a1072b59ffff), // Example H3 cell
+        credentials: None,
+        max_chunk_bytes: None,
+        compression: Box::new([]),
+        accepts_push: false,
     };
 
     match client.hello(hello_request).await {
-        Ok(response) => {
-            info!(
-                "hello response received: dispatcher_id = {:?}",
-                response.dispatcher_id
+        Ok(HelloResponse::Accepted { dispatcher_id, .. }) => {
+            info!("hello accepted: dispatcher_id = {:?}", dispatcher_id);
+        }
+        Ok(HelloResponse::Rejected {
+            dispatcher_id,
+            reason,
+        }) => {
+            error!(
+                "hello rejected: dispatcher_id = {:?}, reason = {:?}",
+                dispatcher_id, reason
             );
+            std::process::exit(1);
         }
         Err(e) => {
             error!("hello request failed: {}", e);
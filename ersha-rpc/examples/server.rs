@@ -1,8 +1,10 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use ersha_core::{BatchUploadRequest, BatchUploadResponse, HelloRequest, HelloResponse};
-use ersha_rpc::{CancellationToken, Server};
+use ersha_core::{
+    BatchUploadRequest, BatchUploadResponse, HelloRequest, HelloResponse, ItemOutcome, ItemResult,
+};
+use ersha_rpc::{CancellationToken, Ping, Router, Server};
 use tokio::net::TcpListener;
 use tracing::{error, info};
 
@@ -41,14 +43,14 @@ async fn main() {
     };
 
     let server = Server::new(listener, state)
-        .on_ping(|_msg_id, _rpc, state| {
+        .with_router(Router::new().route(|_ping: Ping, _msg_id, _rpc, state: &AppState| {
             let counter = state.request_count.clone();
             async move {
                 let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
                 info!("received ping #{}, responding with pong", count);
             }
         })
-        .on_hello(|hello: HelloRequest, _msg_id, _rpc, state| {
+        .route(|hello: HelloRequest, _msg_id, _rpc, state: &AppState| {
             let counter = state.request_count.clone();
             async move {
                 let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
@@ -57,12 +59,15 @@ async fn main() {
                     count, hello.dispatcher_id, hello.location
                 );
 
-                HelloResponse {
+                HelloResponse::Accepted {
                     dispatcher_id: hello.dispatcher_id,
+                    proof: None,
+                    chunking: None,
+                    compression: None,
                 }
             }
         })
-        .on_batch_upload(|request: BatchUploadRequest, _msg_id, _rpc, state| {
+        .route(|request: BatchUploadRequest, _msg_id, _rpc, state: &AppState| {
             let counter = state.request_count.clone();
             async move {
                 let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
@@ -74,9 +79,27 @@ async fn main() {
                     request.readings.len(),
                     request.statuses.len()
                 );
-                BatchUploadResponse { id: request.id }
+                BatchUploadResponse::Accepted {
+                    id: request.id,
+                    readings: request
+                        .readings
+                        .iter()
+                        .map(|r| ItemResult {
+                            id: r.id,
+                            outcome: ItemOutcome::Stored,
+                        })
+                        .collect(),
+                    statuses: request
+                        .statuses
+                        .iter()
+                        .map(|s| ItemResult {
+                            id: s.id,
+                            outcome: ItemOutcome::Stored,
+                        })
+                        .collect(),
+                }
             }
-        });
+        }));
 
     // Set up graceful shutdown on Ctrl+C
     let cancel = CancellationToken::new();
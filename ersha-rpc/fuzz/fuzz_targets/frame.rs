@@ -0,0 +1,18 @@
+//! Feeds arbitrary bytes to the frame reader and the envelope decoder, which
+//! must reject anything malformed without panicking or over-allocating.
+//!
+//! Run with `cargo fuzz run frame` from `ersha-rpc`.
+
+#![no_main]
+
+use ersha_rpc::{Envelope, ReadLimits, read_frame_with};
+use futures_util::FutureExt;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut frames = data;
+    // Read frames until the bytes run out or stop making sense.
+    while let Some(Ok(_)) = read_frame_with(&mut frames, &ReadLimits::default()).now_or_never() {}
+
+    let _ = postcard::from_bytes::<Envelope>(data);
+});
@@ -0,0 +1,293 @@
+//! Shared-secret authentication for the hello handshake.
+//!
+//! The dispatcher signs its hello with HMAC-SHA256 keyed by a secret it
+//! shares with central. Central checks the signature and answers with a
+//! proof derived from it, so each side knows the other holds the secret.
+//! Central also remembers the nonces it has accepted for as long as their
+//! signatures are fresh, so a captured hello can't be replayed.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ersha_core::{DispatcherId, H3Cell, HelloCredentials, HelloRequest};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const HELLO_CONTEXT: &[u8] = b"ersha-hello-v1";
+const PROOF_CONTEXT: &[u8] = b"ersha-hello-ack-v1";
+/// Hellos a dispatcher may say within the allowed skew.
+const DEFAULT_NONCES_PER_DISPATCHER: usize = 1024;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum HelloAuthError {
+    #[error("hello carries no credentials")]
+    MissingCredentials,
+    #[error("hello timestamp is outside the allowed clock skew")]
+    Stale,
+    #[error("hello signature does not match")]
+    BadSignature,
+    #[error("hello nonce was already used")]
+    Replayed,
+}
+
+/// Sign a hello for `dispatcher_id` at `location`.
+pub fn sign_hello(
+    secret: &[u8],
+    dispatcher_id: DispatcherId,
+    location: H3Cell,
+    timestamp: jiff::Timestamp,
+    nonce: u64,
+) -> HelloCredentials {
+    let mac = hello_mac(secret, dispatcher_id, location, timestamp, nonce)
+        .finalize()
+        .into_bytes()
+        .into();
+
+    HelloCredentials {
+        timestamp,
+        nonce,
+        mac,
+    }
+}
+
+/// Check the credentials on `hello` against `secret`.
+///
+/// Signatures older or newer than `max_skew` relative to `now` are rejected.
+pub fn verify_hello(
+    secret: &[u8],
+    hello: &HelloRequest,
+    now: jiff::Timestamp,
+    max_skew: Duration,
+) -> Result<(), HelloAuthError> {
+    let credentials = hello
+        .credentials
+        .as_ref()
+        .ok_or(HelloAuthError::MissingCredentials)?;
+
+    let skew = now.duration_since(credentials.timestamp).unsigned_abs();
+    if skew > max_skew {
+        return Err(HelloAuthError::Stale);
+    }
+
+    hello_mac(
+        secret,
+        hello.dispatcher_id,
+        hello.location,
+        credentials.timestamp,
+        credentials.nonce,
+    )
+    .verify_slice(&credentials.mac)
+    .map_err(|_| HelloAuthError::BadSignature)
+}
+
+/// Nonces of recently accepted hellos, per dispatcher.
+///
+/// A nonce is remembered until its hello's timestamp falls outside the
+/// allowed skew, after which [`verify_hello`] refuses it as stale anyway.
+/// At most `per_dispatcher` are remembered for each dispatcher; hellos past
+/// that are refused until older ones expire.
+#[derive(Clone)]
+pub struct SeenNonces {
+    per_dispatcher: usize,
+    seen: Arc<Mutex<HashMap<DispatcherId, VecDeque<Seen>>>>,
+}
+
+struct Seen {
+    at: jiff::Timestamp,
+    nonce: u64,
+}
+
+impl Default for SeenNonces {
+    fn default() -> Self {
+        Self::new(DEFAULT_NONCES_PER_DISPATCHER)
+    }
+}
+
+impl SeenNonces {
+    pub fn new(per_dispatcher: usize) -> Self {
+        Self {
+            per_dispatcher,
+            seen: Arc::default(),
+        }
+    }
+
+    /// Remember the nonce of a verified `hello`, refusing it if it was seen
+    /// before.
+    pub fn check(
+        &self,
+        hello: &HelloRequest,
+        now: jiff::Timestamp,
+        max_skew: Duration,
+    ) -> Result<(), HelloAuthError> {
+        let credentials = hello
+            .credentials
+            .as_ref()
+            .ok_or(HelloAuthError::MissingCredentials)?;
+        let fresh = |at: jiff::Timestamp| now.duration_since(at).unsigned_abs() <= max_skew;
+
+        let mut seen = self.seen.lock().expect("seen nonces lock poisoned");
+        seen.retain(|_, nonces| {
+            nonces.retain(|seen| fresh(seen.at));
+            !nonces.is_empty()
+        });
+        let nonces = seen.entry(hello.dispatcher_id).or_default();
+        if nonces.len() >= self.per_dispatcher
+            || nonces.iter().any(|seen| seen.nonce == credentials.nonce)
+        {
+            return Err(HelloAuthError::Replayed);
+        }
+        nonces.push_back(Seen {
+            at: credentials.timestamp,
+            nonce: credentials.nonce,
+        });
+
+        Ok(())
+    }
+}
+
+/// Proof returned by central that it accepted `credentials` with `secret`.
+pub fn server_proof(secret: &[u8], credentials: &HelloCredentials) -> [u8; 32] {
+    proof_mac(secret, credentials)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Check a proof returned by central for the credentials we sent.
+pub fn verify_server_proof(secret: &[u8], credentials: &HelloCredentials, proof: &[u8]) -> bool {
+    proof_mac(secret, credentials).verify_slice(proof).is_ok()
+}
+
+fn hello_mac(
+    secret: &[u8],
+    dispatcher_id: DispatcherId,
+    location: H3Cell,
+    timestamp: jiff::Timestamp,
+    nonce: u64,
+) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(HELLO_CONTEXT);
+    mac.update(&dispatcher_id.0.to_bytes());
+    mac.update(&location.0.to_be_bytes());
+    mac.update(&timestamp.as_nanosecond().to_be_bytes());
+    mac.update(&nonce.to_be_bytes());
+    mac
+}
+
+fn proof_mac(secret: &[u8], credentials: &HelloCredentials) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(PROOF_CONTEXT);
+    mac.update(&credentials.mac);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ersha_core::{DispatcherId, H3Cell, HelloRequest};
+
+    use super::{
+        HelloAuthError, SeenNonces, server_proof, sign_hello, verify_hello, verify_server_proof,
+    };
+
+    const SKEW: Duration = Duration::from_secs(300);
+
+    fn hello(secret: &[u8], at: jiff::Timestamp) -> HelloRequest {
+        let dispatcher_id = DispatcherId(ulid::Ulid::new());
+        let location = H3Cell(0x8a2a1072b59ffff);
+
+        HelloRequest {
+            dispatcher_id,
+            location,
+            credentials: Some(sign_hello(secret, dispatcher_id, location, at, 42)),
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
+        }
+    }
+
+    #[test]
+    fn signed_hello_verifies() {
+        let now = jiff::Timestamp::now();
+        let request = hello(b"s3cret", now);
+
+        assert_eq!(verify_hello(b"s3cret", &request, now, SKEW), Ok(()));
+
+        let credentials = request.credentials.unwrap();
+        let proof = server_proof(b"s3cret", &credentials);
+        assert!(verify_server_proof(b"s3cret", &credentials, &proof));
+        assert!(!verify_server_proof(b"other", &credentials, &proof));
+    }
+
+    #[test]
+    fn wrong_secret_or_tampering_is_rejected() {
+        let now = jiff::Timestamp::now();
+        let mut request = hello(b"s3cret", now);
+
+        assert_eq!(
+            verify_hello(b"other", &request, now, SKEW),
+            Err(HelloAuthError::BadSignature)
+        );
+
+        request.location = H3Cell(1);
+        assert_eq!(
+            verify_hello(b"s3cret", &request, now, SKEW),
+            Err(HelloAuthError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn stale_or_missing_credentials_are_rejected() {
+        let now = jiff::Timestamp::now();
+        let old = now - jiff::SignedDuration::from_secs(3600);
+
+        assert_eq!(
+            verify_hello(b"s3cret", &hello(b"s3cret", old), now, SKEW),
+            Err(HelloAuthError::Stale)
+        );
+
+        let mut request = hello(b"s3cret", now);
+        request.credentials = None;
+        assert_eq!(
+            verify_hello(b"s3cret", &request, now, SKEW),
+            Err(HelloAuthError::MissingCredentials)
+        );
+    }
+
+    #[test]
+    fn replayed_nonces_are_rejected_while_fresh() {
+        let now = jiff::Timestamp::now();
+        let seen = SeenNonces::new(2);
+        let request = hello(b"s3cret", now);
+
+        assert_eq!(seen.check(&request, now, SKEW), Ok(()));
+        assert_eq!(
+            seen.check(&request, now, SKEW),
+            Err(HelloAuthError::Replayed)
+        );
+
+        // Another dispatcher may happen to pick the same nonce.
+        assert_eq!(seen.check(&hello(b"s3cret", now), now, SKEW), Ok(()));
+
+        let mut other = request.clone();
+        other.credentials.as_mut().unwrap().nonce = 7;
+        assert_eq!(seen.check(&other, now, SKEW), Ok(()));
+        let mut third = request.clone();
+        third.credentials.as_mut().unwrap().nonce = 8;
+        assert_eq!(seen.check(&third, now, SKEW), Err(HelloAuthError::Replayed));
+
+        // Forgotten once stale, when the signature itself is refused.
+        let later = now + jiff::SignedDuration::from_secs(301);
+        assert_eq!(
+            verify_hello(b"s3cret", &request, later, SKEW),
+            Err(HelloAuthError::Stale)
+        );
+        third.credentials.as_mut().unwrap().timestamp = later;
+        assert_eq!(seen.check(&third, later, SKEW), Ok(()));
+    }
+}
@@ -0,0 +1,254 @@
+//! Recording of the envelopes a server receives, to replay field issues
+//! against another server.
+//!
+//! A capture file starts with [`CAPTURE_MAGIC`], followed by records each
+//! prefixed with their length as a big-endian `u32`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{Envelope, MAX_FRAME_BYTES, WireMessage};
+
+/// Marks a file as a capture, and its format version.
+pub const CAPTURE_MAGIC: &[u8; 8] = b"ERSHACP1";
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("postcard error: {0}")]
+    Postcard(#[from] postcard::Error),
+    #[error("not a capture file")]
+    NotACapture,
+    #[error("record too large")]
+    RecordTooLarge,
+}
+
+/// An envelope as a server received it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapturedEnvelope {
+    pub received_at: jiff::Timestamp,
+    /// Connection it came in on, numbered by the server in accept order.
+    pub connection: u64,
+    pub envelope: Envelope,
+}
+
+/// Appends received envelopes to a capture file. Cheap to clone; clones
+/// append to the same file.
+///
+/// Each record is flushed as it is written, so a capture survives the
+/// server crashing. Writes block, so capture is meant for debugging rather
+/// than for servers under load.
+#[derive(Clone)]
+pub struct CaptureWriter {
+    file: Arc<Mutex<BufWriter<File>>>,
+    redact: bool,
+}
+
+impl CaptureWriter {
+    /// Append to the capture at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, CaptureError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(CAPTURE_MAGIC)?;
+        } else {
+            let mut magic = [0; CAPTURE_MAGIC.len()];
+            file.read_exact(&mut magic)
+                .map_err(|_| CaptureError::NotACapture)?;
+            if &magic != CAPTURE_MAGIC {
+                return Err(CaptureError::NotACapture);
+            }
+        }
+
+        Ok(Self {
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+            redact: false,
+        })
+    }
+
+    /// Leave secrets out of what is recorded. See [`redact`].
+    pub fn with_redaction(mut self) -> Self {
+        self.redact = true;
+        self
+    }
+
+    pub fn record(&self, connection: u64, envelope: &Envelope) -> Result<(), CaptureError> {
+        let mut captured = CapturedEnvelope {
+            received_at: jiff::Timestamp::now(),
+            connection,
+            envelope: envelope.clone(),
+        };
+        if self.redact {
+            redact(&mut captured.envelope.payload);
+        }
+        let bytes = postcard::to_stdvec(&captured)?;
+        let len = u32::try_from(bytes.len()).map_err(|_| CaptureError::RecordTooLarge)?;
+
+        let mut file = self.file.lock().expect("capture lock poisoned");
+        file.write_all(&len.to_be_bytes())?;
+        file.write_all(&bytes)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Drop the secrets a message carries: a hello's credentials.
+pub fn redact(payload: &mut WireMessage) {
+    if let WireMessage::HelloRequest(hello) = payload {
+        hello.credentials = None;
+    }
+}
+
+/// Reads the envelopes recorded in a capture file, in the order received.
+pub struct CaptureReader {
+    file: BufReader<File>,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> Result<Self, CaptureError> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; CAPTURE_MAGIC.len()];
+        file.read_exact(&mut magic)
+            .map_err(|_| CaptureError::NotACapture)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(CaptureError::NotACapture);
+        }
+
+        Ok(Self { file })
+    }
+
+    fn read_record(&mut self) -> Result<Option<CapturedEnvelope>, CaptureError> {
+        let mut len = [0; 4];
+        match self.file.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_be_bytes(len);
+        if len > MAX_FRAME_BYTES {
+            return Err(CaptureError::RecordTooLarge);
+        }
+
+        let mut bytes = vec![0; len as usize];
+        self.file.read_exact(&mut bytes)?;
+        Ok(Some(postcard::from_bytes(&bytes)?))
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = Result<CapturedEnvelope, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ersha_core::{DispatcherId, H3Cell, HelloCredentials, HelloRequest};
+    use ulid::Ulid;
+
+    use super::*;
+    use crate::MessageId;
+
+    fn capture_path() -> PathBuf {
+        std::env::temp_dir().join(format!("ersha-rpc-capture-{}.bin", Ulid::new()))
+    }
+
+    fn envelope(payload: WireMessage) -> Envelope {
+        Envelope {
+            msg_id: MessageId::new(),
+            reply_to: None,
+            deadline_ms: Some(5000),
+            correlation_id: None,
+            payload,
+        }
+    }
+
+    fn hello() -> WireMessage {
+        WireMessage::HelloRequest(HelloRequest {
+            dispatcher_id: DispatcherId(Ulid::new()),
+            location: H3Cell(0x8a2a1072b59ffff),
+            credentials: Some(HelloCredentials {
+                timestamp: jiff::Timestamp::now(),
+                nonce: 7,
+                mac: [1; 32],
+            }),
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
+        })
+    }
+
+    #[test]
+    fn captures_are_appended_and_read_back_in_order() {
+        let path = capture_path();
+        let (hello, ping) = (envelope(hello()), envelope(WireMessage::Ping));
+
+        CaptureWriter::open(&path)
+            .unwrap()
+            .record(0, &hello)
+            .unwrap();
+        // Reopening appends rather than starting over.
+        CaptureWriter::open(&path)
+            .unwrap()
+            .record(1, &ping)
+            .unwrap();
+
+        let captured: Vec<_> = CaptureReader::open(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(captured.len(), 2);
+        assert_eq!((captured[0].connection, &captured[0].envelope), (0, &hello));
+        assert_eq!((captured[1].connection, &captured[1].envelope), (1, &ping));
+        assert!(captured[0].received_at <= captured[1].received_at);
+    }
+
+    #[test]
+    fn redacted_captures_leave_out_credentials() {
+        let path = capture_path();
+        let hello = envelope(hello());
+
+        let writer = CaptureWriter::open(&path).unwrap().with_redaction();
+        writer.record(0, &hello).unwrap();
+
+        let captured = CaptureReader::open(&path).unwrap().next().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let WireMessage::HelloRequest(recorded) = captured.envelope.payload else {
+            panic!("expected a hello, got {:?}", captured.envelope.payload);
+        };
+        assert_eq!(recorded.credentials, None);
+        assert_eq!(captured.envelope.msg_id, hello.msg_id);
+    }
+
+    #[test]
+    fn other_files_are_not_captures() {
+        let path = capture_path();
+        std::fs::write(&path, b"dispatcher_id = 1\n").unwrap();
+
+        assert!(matches!(
+            CaptureReader::open(&path),
+            Err(CaptureError::NotACapture)
+        ));
+        assert!(matches!(
+            CaptureWriter::open(&path),
+            Err(CaptureError::NotACapture)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+//! Streaming of batches too large for a single frame.
+//!
+//! A batch is postcard-encoded and cut into [`BatchUploadChunk`]s sent in
+//! order. Only the last chunk is answered, with the response to the whole
+//! batch once it has been reassembled.
+
+use std::collections::HashMap;
+
+use ersha_core::{BatchId, BatchUploadRequest, ChunkLimits};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::MAX_FRAME_BYTES;
+
+/// Largest chunk carried by one message, leaving room in the frame for the
+/// envelope around it.
+pub const MAX_CHUNK_BYTES: u32 = MAX_FRAME_BYTES - 1024;
+
+/// Largest reassembled batch a server accepts unless configured otherwise.
+pub const DEFAULT_MAX_BATCH_BYTES: u64 = 64_000_000; // 64 MB
+
+/// Batches being reassembled at once on one connection.
+const MAX_PARTIAL_BATCHES: usize = 4;
+
+/// A piece of an encoded [`BatchUploadRequest`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BatchUploadChunk {
+    pub batch_id: BatchId,
+    /// Position of this chunk in the batch, from 0.
+    pub sequence: u32,
+    /// Whether this is the final chunk of the batch.
+    pub last: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+pub enum ChunkError {
+    #[error("chunk {sequence} of batch {batch_id:?} arrived out of order")]
+    OutOfSequence { batch_id: BatchId, sequence: u32 },
+    #[error("batch {0:?} exceeds {1} bytes")]
+    TooLarge(BatchId, u64),
+    #[error("too many batches being streamed at once")]
+    TooManyBatches,
+    #[error("batch {0:?} does not match the chunks it was streamed in")]
+    Mismatched(BatchId),
+    #[error("failed to decode batch: {0}")]
+    Decode(#[from] postcard::Error),
+}
+
+/// Chunking terms for a hello that asked to stream in `requested` byte
+/// chunks, given the largest batch the server reassembles.
+pub fn negotiate(requested: Option<u32>, max_batch_bytes: u64) -> Option<ChunkLimits> {
+    let requested = requested?;
+    (requested > 0 && max_batch_bytes > 0).then(|| ChunkLimits {
+        chunk_bytes: requested.min(MAX_CHUNK_BYTES),
+        max_batch_bytes,
+    })
+}
+
+/// Encode `request` and cut it into chunks of at most `chunk_bytes`.
+pub fn split_batch(
+    request: &BatchUploadRequest,
+    chunk_bytes: u32,
+) -> Result<Vec<BatchUploadChunk>, postcard::Error> {
+    let bytes = postcard::to_stdvec(request)?;
+    let pieces: Vec<_> = bytes.chunks(chunk_bytes.max(1) as usize).collect();
+    let count = pieces.len();
+
+    Ok(pieces
+        .into_iter()
+        .enumerate()
+        .map(|(sequence, data)| BatchUploadChunk {
+            batch_id: request.id,
+            sequence: sequence as u32,
+            last: sequence + 1 == count,
+            data: data.to_vec(),
+        })
+        .collect())
+}
+
+struct Partial {
+    next: u32,
+    bytes: Vec<u8>,
+}
+
+/// Reassembles the batches streamed on one connection.
+///
+/// A batch whose chunks break the rules is dropped; its remaining chunks
+/// fail as out of order, so the error reaches the client on the last one.
+pub(crate) struct ChunkAssembler {
+    max_batch_bytes: u64,
+    partial: HashMap<BatchId, Partial>,
+}
+
+impl ChunkAssembler {
+    pub(crate) fn new(max_batch_bytes: u64) -> Self {
+        Self {
+            max_batch_bytes,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// Add `chunk`, returning the batch once its last chunk is in.
+    pub(crate) fn push(
+        &mut self,
+        chunk: BatchUploadChunk,
+    ) -> Result<Option<BatchUploadRequest>, ChunkError> {
+        let BatchUploadChunk {
+            batch_id,
+            sequence,
+            last,
+            data,
+        } = chunk;
+        let out_of_sequence = ChunkError::OutOfSequence { batch_id, sequence };
+
+        let mut partial = match self.partial.remove(&batch_id) {
+            Some(partial) if partial.next == sequence => partial,
+            Some(_) => return Err(out_of_sequence),
+            None if sequence != 0 => return Err(out_of_sequence),
+            None if self.partial.len() >= MAX_PARTIAL_BATCHES => {
+                return Err(ChunkError::TooManyBatches);
+            }
+            None => Partial {
+                next: 0,
+                bytes: Vec::new(),
+            },
+        };
+
+        if (partial.bytes.len() + data.len()) as u64 > self.max_batch_bytes {
+            return Err(ChunkError::TooLarge(batch_id, self.max_batch_bytes));
+        }
+        partial.bytes.extend_from_slice(&data);
+        partial.next += 1;
+
+        if !last {
+            self.partial.insert(batch_id, partial);
+            return Ok(None);
+        }
+
+        let request: BatchUploadRequest = postcard::from_bytes(&partial.bytes)?;
+        if request.id != batch_id {
+            return Err(ChunkError::Mismatched(batch_id));
+        }
+
+        Ok(Some(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{BatchId, BatchUploadRequest, DispatcherId};
+    use ulid::Ulid;
+
+    use super::{ChunkAssembler, ChunkError, split_batch};
+
+    fn batch() -> BatchUploadRequest {
+        BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            dispatcher_id: DispatcherId(Ulid::new()),
+            readings: Box::new([]),
+            statuses: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+        }
+    }
+
+    #[test]
+    fn chunks_reassemble_into_the_batch() {
+        let batch = batch();
+        let chunks = split_batch(&batch, 8).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.last().unwrap().last);
+
+        let mut assembler = ChunkAssembler::new(1024);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert_eq!(assembler.push(chunk.clone()).unwrap(), None);
+        }
+        assert_eq!(assembler.push(last.clone()).unwrap(), Some(batch));
+    }
+
+    #[test]
+    fn broken_streams_are_refused() {
+        let chunks = split_batch(&batch(), 8).unwrap();
+
+        let mut assembler = ChunkAssembler::new(1024);
+        assembler.push(chunks[0].clone()).unwrap();
+        assert!(matches!(
+            assembler.push(chunks[2].clone()),
+            Err(ChunkError::OutOfSequence { sequence: 2, .. })
+        ));
+        // The batch was dropped, so the rest of it is out of order too.
+        assert!(matches!(
+            assembler.push(chunks[1].clone()),
+            Err(ChunkError::OutOfSequence { sequence: 1, .. })
+        ));
+
+        let mut assembler = ChunkAssembler::new(10);
+        assembler.push(chunks[0].clone()).unwrap();
+        assert!(matches!(
+            assembler.push(chunks[1].clone()),
+            Err(ChunkError::TooLarge(..))
+        ));
+    }
+}
@@ -1,14 +1,22 @@
-use ersha_core::{BatchUploadRequest, BatchUploadResponse, HelloRequest, HelloResponse};
+use ersha_core::{
+    BatchUploadRequest, BatchUploadResponse, ChunkLimits, CommandPoll, CommandPollResponse,
+    DeviceDisconnectionRequest, DeviceDisconnectionResponse, DispatcherStatus,
+    DispatcherStatusResponse, HelloRejectionReason, HelloRequest, HelloResponse,
+};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::{RpcError, RpcTcp, WireError, WireMessage};
+use crate::{
+    Incoming, Keepalive, RpcError, RpcMetrics, RpcTcp, WireError, WireMessage, split_batch,
+};
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Client {
     rpc: RpcTcp,
+    incoming: Mutex<Option<Incoming>>,
     timeout: Duration,
 }
 
@@ -20,16 +28,43 @@ pub enum ClientError {
     UnexpectedResponse,
     #[error("error response: {0:?}")]
     ErrorResponse(WireError),
+    #[error("failed to connect: {0}")]
+    Connect(std::io::Error),
+    #[error("hello rejected: {0:?}")]
+    HelloRejected(HelloRejectionReason),
+    #[error("failed to encode batch: {0}")]
+    Encode(#[from] postcard::Error),
+    #[error("batch of {0} bytes exceeds the agreed maximum")]
+    BatchTooLarge(usize),
+}
+
+impl ClientError {
+    /// Whether the connection is unusable, so the call may succeed over a new one.
+    pub fn is_disconnect(&self) -> bool {
+        matches!(self, ClientError::Rpc(_) | ClientError::Connect(_))
+    }
 }
 
 impl Client {
-    pub fn new(stream: TcpStream) -> Self {
+    /// A client over `stream`, typically a TCP or TLS connection.
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
         Self::with_buffer(stream, 1024)
     }
 
-    pub fn with_buffer(stream: TcpStream, buffer: usize) -> Self {
+    pub fn with_buffer<S>(stream: S, buffer: usize) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let mut rpc = RpcTcp::new(stream, buffer)
+            .answering_pings()
+            .notifying_cancellations();
+        let incoming = rpc.incoming();
         Self {
-            rpc: RpcTcp::new(stream, buffer),
+            rpc,
+            incoming: Mutex::new(Some(incoming)),
             timeout: DEFAULT_TIMEOUT,
         }
     }
@@ -39,6 +74,46 @@ impl Client {
         self
     }
 
+    /// Report the calls made through this client to `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn RpcMetrics>) -> Self {
+        self.rpc = self.rpc.with_metrics(metrics);
+        self
+    }
+
+    /// Ping the server periodically, closing the connection once it stops
+    /// answering. See [`RpcTcp::keepalive`].
+    pub fn with_keepalive(self, keepalive: Keepalive) -> Self {
+        self.rpc.keepalive(keepalive);
+        self
+    }
+
+    /// Whether the connection was closed, e.g. because the server stopped
+    /// answering keepalives. Calls on a closed client fail.
+    pub fn is_closed(&self) -> bool {
+        self.rpc.is_closed()
+    }
+
+    /// Whether the server is shutting down. Calls already made are still
+    /// answered, but new ones should go over a new connection.
+    pub fn is_going_away(&self) -> bool {
+        self.rpc.is_going_away()
+    }
+
+    /// Wait until the server says it is shutting down.
+    pub async fn going_away(&self) {
+        self.rpc.going_away().await
+    }
+
+    /// Requests the server pushes, such as `CommandDispatchRequest`s, to be
+    /// answered through [`Incoming::reply`]. `None` once taken.
+    ///
+    /// Servers only push to clients whose hello set `accepts_push`, and
+    /// those must keep receiving: unread pushes eventually stall the
+    /// connection.
+    pub fn incoming(&self) -> Option<Incoming> {
+        self.incoming.lock().expect("incoming lock poisoned").take()
+    }
+
     pub async fn ping(&self) -> Result<(), ClientError> {
         let response = self.rpc.call(WireMessage::Ping, self.timeout).await?;
 
@@ -49,6 +124,7 @@ impl Client {
         }
     }
 
+    /// Say hello, switching to the frame compression agreed if accepted.
     pub async fn hello(&self, hello: HelloRequest) -> Result<HelloResponse, ClientError> {
         let response = self
             .rpc
@@ -56,7 +132,12 @@ impl Client {
             .await?;
 
         match response.payload {
-            WireMessage::HelloResponse(resp) => Ok(resp),
+            WireMessage::HelloResponse(resp) => {
+                if let HelloResponse::Accepted { compression, .. } = &resp {
+                    self.rpc.set_compression(*compression);
+                }
+                Ok(resp)
+            }
             WireMessage::Error(err) => Err(ClientError::ErrorResponse(err)),
             _ => Err(ClientError::UnexpectedResponse),
         }
@@ -77,4 +158,93 @@ impl Client {
             _ => Err(ClientError::UnexpectedResponse),
         }
     }
+
+    /// Upload a batch too large for one frame by streaming it in chunks, on
+    /// the terms agreed in the hello. Batches that fit in a single chunk are
+    /// uploaded whole.
+    pub async fn batch_upload_chunked(
+        &self,
+        request: BatchUploadRequest,
+        limits: ChunkLimits,
+    ) -> Result<BatchUploadResponse, ClientError> {
+        let chunks = split_batch(&request, limits.chunk_bytes)?;
+        let size: usize = chunks.iter().map(|chunk| chunk.data.len()).sum();
+        if size as u64 > limits.max_batch_bytes {
+            return Err(ClientError::BatchTooLarge(size));
+        }
+        if chunks.len() == 1 {
+            return self.batch_upload(request).await;
+        }
+
+        // Only the last chunk is answered; the others are sent in order
+        // ahead of it.
+        let mut chunks = chunks;
+        let last = chunks.pop().expect("a batch encodes to at least one chunk");
+        for chunk in chunks {
+            self.rpc.send(WireMessage::BatchUploadChunk(chunk)).await?;
+        }
+        let response = self
+            .rpc
+            .call(WireMessage::BatchUploadChunk(last), self.timeout)
+            .await?;
+
+        match response.payload {
+            WireMessage::BatchUploadResponse(resp) => Ok(resp),
+            WireMessage::Error(err) => Err(ClientError::ErrorResponse(err)),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn dispatcher_status(
+        &self,
+        status: DispatcherStatus,
+    ) -> Result<DispatcherStatusResponse, ClientError> {
+        let response = self
+            .rpc
+            .call(WireMessage::DispatcherStatusRequest(status), self.timeout)
+            .await?;
+
+        match response.payload {
+            WireMessage::DispatcherStatusResponse(resp) => Ok(resp),
+            WireMessage::Error(err) => Err(ClientError::ErrorResponse(err)),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Fetch pending commands, acknowledging those already passed on.
+    pub async fn poll_commands(
+        &self,
+        poll: CommandPoll,
+    ) -> Result<CommandPollResponse, ClientError> {
+        let response = self
+            .rpc
+            .call(WireMessage::CommandPollRequest(poll), self.timeout)
+            .await?;
+
+        match response.payload {
+            WireMessage::CommandPollResponse(resp) => Ok(resp),
+            WireMessage::Error(err) => Err(ClientError::ErrorResponse(err)),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Report devices that stopped reporting, or started again.
+    pub async fn device_disconnection(
+        &self,
+        request: DeviceDisconnectionRequest,
+    ) -> Result<DeviceDisconnectionResponse, ClientError> {
+        let response = self
+            .rpc
+            .call(
+                WireMessage::DeviceDisconnectionRequest(request),
+                self.timeout,
+            )
+            .await?;
+
+        match response.payload {
+            WireMessage::DeviceDisconnectionResponse(resp) => Ok(resp),
+            WireMessage::Error(err) => Err(ClientError::ErrorResponse(err)),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
 }
@@ -0,0 +1,35 @@
+use std::future::Future;
+
+use crate::CorrelationId;
+
+tokio::task_local! {
+    static CORRELATION_ID: CorrelationId;
+}
+
+/// Run `future` as part of the work identified by `id`: the calls, notices
+/// and replies it sends carry `id`, so the other end can log under it too.
+///
+/// Servers handle each request carrying a correlation ID inside its scope,
+/// so anything logged or sent while handling it is tied to the same work.
+pub async fn correlated<F: Future>(id: CorrelationId, future: F) -> F::Output {
+    CORRELATION_ID.scope(id, future).await
+}
+
+/// The correlation ID of the work the current task is part of, if any.
+pub fn correlation_id() -> Option<CorrelationId> {
+    CORRELATION_ID.try_with(|id| *id).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{correlated, correlation_id};
+    use crate::CorrelationId;
+
+    #[tokio::test]
+    async fn ids_are_scoped_to_the_future() {
+        let id = CorrelationId::new();
+
+        assert_eq!(correlated(id, async { correlation_id() }).await, Some(id));
+        assert_eq!(correlation_id(), None);
+    }
+}
@@ -1,10 +1,29 @@
+use std::time::Duration;
+
+use ersha_core::Compression;
 use thiserror::Error;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-use crate::Envelope;
+use crate::{Envelope, MAX_CHUNK_BYTES};
 
 pub const MAX_FRAME_BYTES: u32 = 2_000_000; // 2 MB
 
+/// The top byte of a frame's length prefix says how its body is compressed.
+const CODEC_SHIFT: u32 = 24;
+const LEN_MASK: u32 = (1 << CODEC_SHIFT) - 1;
+
+const UNCOMPRESSED: u8 = 0;
+const LZ4: u8 = 1;
+const ZSTD: u8 = 2;
+
+/// Frames smaller than this are sent uncompressed, as compression would
+/// save little or nothing.
+const MIN_COMPRESSED_BYTES: usize = 256;
+
+/// Memory set aside for a frame body before any of it arrives. Larger
+/// bodies grow their buffer as they are read.
+const INITIAL_READ_BYTES: usize = 64 * 1024;
+
 #[derive(Debug, Error)]
 pub enum FrameError {
     #[error("postcard error: {0}")]
@@ -13,53 +32,210 @@ pub enum FrameError {
     FrameTooLarge,
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("unknown compression {0}")]
+    UnknownCompression(u8),
+    #[error("failed to decompress frame: {0}")]
+    Decompress(String),
+    #[error("frame not received in time")]
+    Timeout,
+}
+
+/// Bounds on reading frames from a peer, so it can't tie up memory or the
+/// reader with frames it never finishes sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// Largest frame accepted, compressed or not. Capped at
+    /// [`MAX_FRAME_BYTES`].
+    pub max_frame_bytes: u32,
+    /// Time allowed for the rest of a frame to arrive once its length has.
+    /// Waiting for the next frame to start is never timed out.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_bytes: MAX_FRAME_BYTES,
+            timeout: None,
+        }
+    }
+}
+
+impl ReadLimits {
+    fn max_frame_bytes(&self) -> u32 {
+        self.max_frame_bytes.min(MAX_FRAME_BYTES)
+    }
+
+    /// Largest batch chunk whose frame stays within the limits.
+    pub(crate) fn max_chunk_bytes(&self) -> u32 {
+        let overhead = MAX_FRAME_BYTES - MAX_CHUNK_BYTES;
+        self.max_frame_bytes().saturating_sub(overhead).max(1)
+    }
+}
+
+/// Number of bytes `value` occupies once postcard-encoded, excluding the
+/// 4-byte frame length prefix.
+pub fn encoded_len<T>(value: &T) -> Result<usize, FrameError>
+where
+    T: serde::Serialize + ?Sized,
+{
+    Ok(postcard::to_stdvec(value)?.len())
+}
+
+pub(crate) fn codec_flag(compression: Option<Compression>) -> u8 {
+    match compression {
+        None => UNCOMPRESSED,
+        Some(Compression::Lz4) => LZ4,
+        Some(Compression::Zstd) => ZSTD,
+    }
+}
+
+pub(crate) fn codec_from_flag(flag: u8) -> Option<Compression> {
+    match flag {
+        LZ4 => Some(Compression::Lz4),
+        ZSTD => Some(Compression::Zstd),
+        _ => None,
+    }
+}
+
+/// Compress an encoded frame body with `compression`.
+pub fn compress(compression: Compression, bytes: &[u8]) -> Result<Vec<u8>, FrameError> {
+    match compression {
+        Compression::Lz4 => Ok(lz4_flex::block::compress_prepend_size(bytes)),
+        Compression::Zstd => Ok(zstd::bulk::compress(bytes, 0)?),
+    }
+}
+
+/// Decompress a frame body marked with `flag`, refusing bodies that would
+/// expand past `limit` bytes.
+fn decompress(flag: u8, bytes: Vec<u8>, limit: usize) -> Result<Vec<u8>, FrameError> {
+    match flag {
+        UNCOMPRESSED => Ok(bytes),
+        LZ4 => {
+            let size = bytes
+                .first_chunk::<4>()
+                .map(|size| u32::from_le_bytes(*size) as usize);
+            if size.is_none_or(|size| size > limit) {
+                return Err(FrameError::FrameTooLarge);
+            }
+            lz4_flex::block::decompress_size_prepended(&bytes)
+                .map_err(|e| FrameError::Decompress(e.to_string()))
+        }
+        ZSTD => {
+            zstd::bulk::decompress(&bytes, limit).map_err(|e| FrameError::Decompress(e.to_string()))
+        }
+        flag => Err(FrameError::UnknownCompression(flag)),
+    }
 }
 
 pub async fn write_frame<W>(w: &mut W, msg: &Envelope) -> Result<(), FrameError>
 where
     W: AsyncWriteExt + Unpin,
 {
-    let bytes = postcard::to_stdvec(msg)?;
-    let len = bytes.len() as u32;
+    write_compressed_frame(w, msg, None).await
+}
 
-    if len > MAX_FRAME_BYTES {
+/// Write `msg`, compressed with `compression` when that makes it smaller.
+pub async fn write_compressed_frame<W>(
+    w: &mut W,
+    msg: &Envelope,
+    compression: Option<Compression>,
+) -> Result<(), FrameError>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    let bytes = postcard::to_stdvec(msg)?;
+    if bytes.len() > MAX_FRAME_BYTES as usize {
         return Err(FrameError::FrameTooLarge);
     }
 
-    w.write_u32(len).await?;
+    let (flag, bytes) = match compression {
+        Some(codec) if bytes.len() >= MIN_COMPRESSED_BYTES => {
+            let compressed = compress(codec, &bytes)?;
+            if compressed.len() < bytes.len() {
+                (codec_flag(Some(codec)), compressed)
+            } else {
+                (UNCOMPRESSED, bytes)
+            }
+        }
+        _ => (UNCOMPRESSED, bytes),
+    };
+
+    w.write_u32(u32::from(flag) << CODEC_SHIFT | bytes.len() as u32)
+        .await?;
     w.write_all(&bytes).await?;
     w.flush().await?;
 
     Ok(())
 }
 
+/// Read a frame, decompressing it if the sender compressed it.
 pub async fn read_frame<R>(r: &mut R) -> Result<Envelope, FrameError>
 where
     R: AsyncReadExt + Unpin,
 {
-    let len = r.read_u32().await?;
-    if len > MAX_FRAME_BYTES {
+    read_frame_with(r, &ReadLimits::default()).await
+}
+
+/// Read a frame within `limits`.
+pub async fn read_frame_with<R>(r: &mut R, limits: &ReadLimits) -> Result<Envelope, FrameError>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let header = r.read_u32().await?;
+    let flag = (header >> CODEC_SHIFT) as u8;
+    let len = header & LEN_MASK;
+    let max = limits.max_frame_bytes();
+    if len > max {
         return Err(FrameError::FrameTooLarge);
     }
 
-    let mut buf = vec![0u8; len as usize];
-    r.read_exact(&mut buf).await?;
-    let msg = postcard::from_bytes(&buf)?;
+    let body = read_body(r, len);
+    let buf = match limits.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, body)
+            .await
+            .map_err(|_| FrameError::Timeout)??,
+        None => body.await?,
+    };
+    let msg = postcard::from_bytes(&decompress(flag, buf, max as usize)?)?;
 
     Ok(msg)
 }
 
+/// Read a body of `len` bytes, allocating only as much as has arrived, so a
+/// length alone can't make the reader reserve the whole of it.
+async fn read_body<R>(r: &mut R, len: u32) -> std::io::Result<Vec<u8>>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let mut buf = Vec::with_capacity((len as usize).min(INITIAL_READ_BYTES));
+    r.take(u64::from(len)).read_to_end(&mut buf).await?;
+    if buf.len() < len as usize {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{MessageId, WireError, WireErrorCode, WireMessage};
-    use ersha_core::{DispatcherId, H3Cell, HelloRequest, HelloResponse};
+    use crate::{CorrelationId, MessageId, WireError, WireErrorCode, WireMessage};
+    use ersha_core::{
+        ChunkLimits, CommandId, CommandKind, CommandPollResponse, Compression, DeviceCommand,
+        DeviceId, DispatcherId, DispatcherStatus, H3Cell, HelloRejectionReason, HelloRequest,
+        HelloResponse, LinkQuality,
+    };
+    use futures_util::FutureExt;
+    use proptest::prelude::*;
     use tokio::io::duplex;
 
     fn create_envelope(payload: WireMessage) -> Envelope {
         Envelope {
             msg_id: MessageId::new(),
             reply_to: None,
+            deadline_ms: None,
+            correlation_id: None,
             payload,
         }
     }
@@ -92,6 +268,10 @@ mod tests {
         let request = HelloRequest {
             dispatcher_id: DispatcherId(ulid::Ulid::new()),
             location: H3Cell(0x8a2a1072b59ffff),
+            credentials: None,
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
         };
         let original = create_envelope(WireMessage::HelloRequest(request.clone()));
 
@@ -101,11 +281,61 @@ mod tests {
         assert_eq!(read, original);
     }
 
+    #[tokio::test]
+    async fn test_roundtrip_dispatcher_status() {
+        let (mut writer, mut reader) = duplex(1024);
+        let status = DispatcherStatus {
+            dispatcher_id: DispatcherId(ulid::Ulid::new()),
+            pending_readings: 120,
+            pending_statuses: 4,
+            link: LinkQuality {
+                rtt_ms: Some(85),
+                failed_uploads: 1,
+            },
+            uptime_seconds: 3600,
+            timestamp: jiff::Timestamp::from_second(1_700_000_000).unwrap(),
+        };
+        let original = create_envelope(WireMessage::DispatcherStatusRequest(status));
+
+        write_frame(&mut writer, &original).await.unwrap();
+        let read = read_frame(&mut reader).await.unwrap();
+
+        assert_eq!(read, original);
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_command_poll_response() {
+        let (mut writer, mut reader) = duplex(1024);
+        let response = CommandPollResponse::Accepted {
+            commands: Box::new([DeviceCommand {
+                id: CommandId(ulid::Ulid::new()),
+                device_id: DeviceId(ulid::Ulid::new()),
+                kind: CommandKind::Configure {
+                    key: "sample_rate".into(),
+                    value: "10".into(),
+                },
+                expires_at: jiff::Timestamp::from_second(1_700_000_000).unwrap(),
+            }]),
+        };
+        let original = create_envelope(WireMessage::CommandPollResponse(response));
+
+        write_frame(&mut writer, &original).await.unwrap();
+        let read = read_frame(&mut reader).await.unwrap();
+
+        assert_eq!(read, original);
+    }
+
     #[tokio::test]
     async fn test_roundtrip_hello_response() {
         let (mut writer, mut reader) = duplex(1024);
-        let response = HelloResponse {
+        let response = HelloResponse::Accepted {
             dispatcher_id: DispatcherId(ulid::Ulid::new()),
+            proof: Some([7; 32]),
+            chunking: Some(ChunkLimits {
+                chunk_bytes: 1_000_000,
+                max_batch_bytes: 64_000_000,
+            }),
+            compression: Some(Compression::Zstd),
         };
         let original = create_envelope(WireMessage::HelloResponse(response.clone()));
 
@@ -115,6 +345,21 @@ mod tests {
         assert_eq!(read, original);
     }
 
+    #[tokio::test]
+    async fn test_roundtrip_hello_rejected() {
+        let (mut writer, mut reader) = duplex(1024);
+        let response = HelloResponse::Rejected {
+            dispatcher_id: DispatcherId(ulid::Ulid::new()),
+            reason: HelloRejectionReason::Suspended,
+        };
+        let original = create_envelope(WireMessage::HelloResponse(response));
+
+        write_frame(&mut writer, &original).await.unwrap();
+        let read = read_frame(&mut reader).await.unwrap();
+
+        assert_eq!(read, original);
+    }
+
     #[tokio::test]
     async fn test_roundtrip_error() {
         let (mut writer, mut reader) = duplex(1024);
@@ -137,6 +382,8 @@ mod tests {
         let original = Envelope {
             msg_id: MessageId::new(),
             reply_to: Some(reply_to),
+            deadline_ms: None,
+            correlation_id: None,
             payload: WireMessage::Ping,
         };
 
@@ -198,12 +445,63 @@ mod tests {
         assert_eq!(read3, frame3);
     }
 
+    #[tokio::test]
+    async fn test_roundtrip_compressed() {
+        let large = create_envelope(WireMessage::Error(WireError {
+            code: WireErrorCode::Internal,
+            message: "sensor offline; ".repeat(100),
+        }));
+        let small = create_envelope(WireMessage::Ping);
+        let raw_len = encoded_len(&large).unwrap();
+
+        for codec in [Compression::Lz4, Compression::Zstd] {
+            let (mut writer, mut reader) = duplex(8192);
+            write_compressed_frame(&mut writer, &large, Some(codec))
+                .await
+                .unwrap();
+            write_compressed_frame(&mut writer, &small, Some(codec))
+                .await
+                .unwrap();
+
+            // Peek at the header of the large frame before decoding it.
+            let header = reader.read_u32().await.unwrap();
+            assert_eq!((header >> CODEC_SHIFT) as u8, codec_flag(Some(codec)));
+            assert!(((header & LEN_MASK) as usize) < raw_len);
+            let mut body = vec![0u8; (header & LEN_MASK) as usize];
+            reader.read_exact(&mut body).await.unwrap();
+            let body = decompress(codec_flag(Some(codec)), body, raw_len).unwrap();
+            assert_eq!(postcard::from_bytes::<Envelope>(&body).unwrap(), large);
+
+            // Small frames aren't worth compressing.
+            let header = reader.read_u32().await.unwrap();
+            assert_eq!((header >> CODEC_SHIFT) as u8, UNCOMPRESSED);
+        }
+
+        let (mut writer, mut reader) = duplex(8192);
+        write_compressed_frame(&mut writer, &large, Some(Compression::Zstd))
+            .await
+            .unwrap();
+        assert_eq!(read_frame(&mut reader).await.unwrap(), large);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_compression_read() {
+        let (mut writer, mut reader) = duplex(1024);
+        writer.write_u32(9 << CODEC_SHIFT | 4).await.unwrap();
+        writer.write_all(&[0; 4]).await.unwrap();
+
+        let result = read_frame(&mut reader).await;
+        assert!(matches!(result, Err(FrameError::UnknownCompression(9))));
+    }
+
     #[tokio::test]
     async fn test_various_error_codes() {
         let error_codes = vec![
             WireErrorCode::BadRequest,
             WireErrorCode::Unsupported,
             WireErrorCode::Internal,
+            WireErrorCode::RateLimited,
+            WireErrorCode::QuotaExceeded,
         ];
 
         for code in error_codes {
@@ -220,4 +518,163 @@ mod tests {
             assert_eq!(read, original);
         }
     }
+
+    #[tokio::test]
+    async fn test_frame_over_configured_limit_read() {
+        let limits = ReadLimits {
+            max_frame_bytes: 64,
+            timeout: None,
+        };
+        let large = create_envelope(WireMessage::Error(WireError {
+            code: WireErrorCode::Internal,
+            message: "x".repeat(64),
+        }));
+        let (mut writer, mut reader) = duplex(1024);
+        write_frame(&mut writer, &large).await.unwrap();
+        let result = read_frame_with(&mut reader, &limits).await;
+        assert!(matches!(result, Err(FrameError::FrameTooLarge)));
+
+        // Compressed frames are held to the limit once decompressed too.
+        let (mut writer, mut reader) = duplex(8192);
+        let large = create_envelope(WireMessage::Error(WireError {
+            code: WireErrorCode::Internal,
+            message: "x".repeat(4096),
+        }));
+        write_compressed_frame(&mut writer, &large, Some(Compression::Zstd))
+            .await
+            .unwrap();
+        let result = read_frame_with(&mut reader, &limits).await;
+        assert!(matches!(result, Err(FrameError::Decompress(_))));
+    }
+
+    #[tokio::test]
+    async fn test_frame_not_finished_in_time() {
+        let limits = ReadLimits {
+            max_frame_bytes: MAX_FRAME_BYTES,
+            timeout: Some(Duration::from_millis(50)),
+        };
+        let (mut writer, mut reader) = duplex(1024);
+        writer.write_u32(16).await.unwrap();
+        writer.write_all(&[0; 8]).await.unwrap();
+
+        let result = read_frame_with(&mut reader, &limits).await;
+        assert!(matches!(result, Err(FrameError::Timeout)));
+    }
+
+    /// Read a frame from `bytes`, which never waits as they are all there.
+    fn read_bytes(mut bytes: &[u8]) -> Result<Envelope, FrameError> {
+        read_frame(&mut bytes)
+            .now_or_never()
+            .expect("reading from memory never waits")
+    }
+
+    fn encode(envelope: &Envelope, compression: Option<Compression>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_compressed_frame(&mut bytes, envelope, compression)
+            .now_or_never()
+            .expect("writing to memory never waits")
+            .unwrap();
+        bytes
+    }
+
+    fn error_code() -> impl Strategy<Value = WireErrorCode> {
+        prop_oneof![
+            Just(WireErrorCode::BadRequest),
+            Just(WireErrorCode::Unsupported),
+            Just(WireErrorCode::Internal),
+            Just(WireErrorCode::RateLimited),
+            Just(WireErrorCode::QuotaExceeded),
+        ]
+    }
+
+    fn envelope() -> impl Strategy<Value = Envelope> {
+        let id = any::<u128>().prop_map(|id| MessageId(ulid::Ulid(id)));
+        let payload = prop_oneof![
+            Just(WireMessage::Ping),
+            Just(WireMessage::Pong),
+            Just(WireMessage::GoingAway),
+            id.clone().prop_map(WireMessage::Cancel),
+            (error_code(), ".{0,2000}")
+                .prop_map(|(code, message)| { WireMessage::Error(WireError { code, message }) }),
+        ];
+        (
+            id.clone(),
+            proptest::option::of(id),
+            any::<Option<u64>>(),
+            proptest::option::of(any::<u128>().prop_map(|id| CorrelationId(ulid::Ulid(id)))),
+            payload,
+        )
+            .prop_map(|(msg_id, reply_to, deadline_ms, correlation_id, payload)| {
+                Envelope {
+                    msg_id,
+                    reply_to,
+                    deadline_ms,
+                    correlation_id,
+                    payload,
+                }
+            })
+    }
+
+    fn compression() -> impl Strategy<Value = Option<Compression>> {
+        prop_oneof![
+            Just(None),
+            Just(Some(Compression::Lz4)),
+            Just(Some(Compression::Zstd)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_frames_roundtrip(envelope in envelope(), compression in compression()) {
+            let bytes = encode(&envelope, compression);
+            prop_assert_eq!(read_bytes(&bytes).unwrap(), envelope);
+        }
+
+        #[test]
+        fn prop_truncated_frames_fail(
+            envelope in envelope(),
+            compression in compression(),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let bytes = encode(&envelope, compression);
+            let cut = cut.index(bytes.len());
+            prop_assert!(read_bytes(&bytes[..cut]).is_err());
+        }
+
+        #[test]
+        fn prop_bit_flips_never_panic(
+            envelope in envelope(),
+            compression in compression(),
+            flips in prop::collection::vec(any::<prop::sample::Index>(), 1..8),
+        ) {
+            let mut bytes = encode(&envelope, compression);
+            for flip in flips {
+                let bit = flip.index(bytes.len() * 8);
+                bytes[bit / 8] ^= 1 << (bit % 8);
+            }
+            let _ = read_bytes(&bytes);
+        }
+
+        #[test]
+        fn prop_lengths_near_the_limit_need_their_whole_body(
+            flag in 0u8..3,
+            below in 0u32..64,
+            body in prop::collection::vec(any::<u8>(), 0..1024),
+        ) {
+            let len = MAX_FRAME_BYTES - below;
+            let mut bytes = (u32::from(flag) << CODEC_SHIFT | len).to_be_bytes().to_vec();
+            bytes.extend(body);
+            prop_assert!(read_bytes(&bytes).is_err());
+
+            let mut bytes = (u32::from(flag) << CODEC_SHIFT | (len + 64)).to_be_bytes().to_vec();
+            bytes.extend([0; 8]);
+            prop_assert!(matches!(read_bytes(&bytes), Err(FrameError::FrameTooLarge)));
+        }
+
+        #[test]
+        fn prop_arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..4096)) {
+            let _ = read_bytes(&bytes);
+            let _ = postcard::from_bytes::<Envelope>(&bytes);
+        }
+    }
 }
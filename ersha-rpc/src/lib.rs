@@ -1,12 +1,33 @@
+pub mod auth;
+pub mod capture;
 mod message;
 pub use message::*;
 mod frame;
 pub use frame::*;
+mod chunk;
+pub use chunk::*;
+mod correlation;
+pub use correlation::{correlated, correlation_id};
+mod queue;
+pub use queue::{Overflow, WriteQueue};
+mod metrics;
+pub use metrics::{Outcome, RpcMetrics};
 mod rpc;
 pub use rpc::*;
 mod client;
 pub use client::*;
+mod reconnect;
+pub use reconnect::*;
+mod router;
+pub use router::*;
+pub mod middleware;
 mod server;
 pub use server::*;
+mod limit;
+pub use limit::*;
+mod push;
+pub use push::{Dispatchers, PushError};
+pub mod tls;
+pub mod ws;
 
 pub use tokio_util::sync::CancellationToken;
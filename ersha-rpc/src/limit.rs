@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// A sustained rate with a burst allowance on top.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quota {
+    /// Units replenished per second
+    pub per_second: u32,
+    /// Units that may be spent at once after a quiet period
+    pub burst: u32,
+}
+
+impl Quota {
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self { per_second, burst }
+    }
+
+    /// Bucket size. A single unit always fits, even with a zero burst.
+    fn capacity(&self) -> f64 {
+        self.burst.max(1) as f64
+    }
+}
+
+/// Token bucket enforcing a [`Quota`].
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    quota: Quota,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota,
+            tokens: quota.capacity(),
+            updated: Instant::now(),
+        }
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Spend `cost` units, or return how long to wait until they are available.
+    ///
+    /// A cost above the burst allowance is charged as the full burst, so an
+    /// oversized request passes once the bucket has filled up.
+    pub fn try_take(&mut self, cost: u32) -> Result<(), Duration> {
+        self.try_take_at(cost, Instant::now())
+    }
+
+    fn try_take_at(&mut self, cost: u32, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        self.tokens =
+            (self.tokens + elapsed * self.quota.per_second as f64).min(self.quota.capacity());
+
+        let cost = (cost as f64).min(self.quota.capacity());
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            return Ok(());
+        }
+
+        if self.quota.per_second == 0 {
+            return Err(Duration::MAX);
+        }
+        let missing = cost - self.tokens;
+        Err(Duration::from_secs_f64(
+            missing / self.quota.per_second as f64,
+        ))
+    }
+}
+
+/// Limits applied to every connection accepted by a [`Server`](crate::Server).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+    /// Requests of any kind
+    pub requests: Option<Quota>,
+    /// Readings carried by batch uploads
+    pub readings: Option<Quota>,
+    /// Bytes of batches streamed in chunks
+    pub chunk_bytes: Option<Quota>,
+}
+
+/// [`RateLimits`] that can be changed while a [`Server`](crate::Server) runs.
+///
+/// Open connections pick up new limits on their next request.
+#[derive(Debug, Clone, Default)]
+pub struct SharedRateLimits(Arc<RwLock<RateLimits>>);
+
+impl SharedRateLimits {
+    pub fn new(limits: RateLimits) -> Self {
+        Self(Arc::new(RwLock::new(limits)))
+    }
+
+    pub fn get(&self) -> RateLimits {
+        *self.0.read().expect("rate limits lock poisoned")
+    }
+
+    pub fn set(&self, limits: RateLimits) {
+        *self.0.write().expect("rate limits lock poisoned") = limits;
+    }
+}
+
+/// Bounds on the connections a [`Server`](crate::Server) holds open, so a
+/// fleet reconnecting at once can't run it out of file descriptors.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionLimits {
+    /// Connections open at once, tunneled ones included. At the limit the
+    /// server stops accepting, and new connections wait in the listen
+    /// backlog until one closes.
+    pub max_connections: Option<usize>,
+    /// Connections open at once from one IP address. Those past it are
+    /// closed as soon as they are accepted.
+    pub max_per_ip: Option<usize>,
+    /// Pace at which connections are accepted
+    pub accept_rate: Option<Quota>,
+    /// Time a connection has to complete its TLS handshake and have a hello
+    /// accepted. Those that take longer are closed, so idle sockets can't
+    /// hold the places real dispatchers need.
+    pub hello_timeout: Option<Duration>,
+}
+
+/// Connections open from each IP address, bounded by
+/// [`ConnectionLimits::max_per_ip`]. Tunneled connections whose address
+/// isn't known count together under the unspecified address.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PeerCounts {
+    max: Option<usize>,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl PeerCounts {
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            open: Arc::default(),
+        }
+    }
+
+    /// Count a connection from `ip`, unless that many are open already.
+    pub(crate) fn open(&self, ip: IpAddr) -> Option<PeerGuard> {
+        let mut open = self.open.lock().expect("peer counts lock poisoned");
+        let count = open.get(&ip).copied().unwrap_or(0);
+        if self.max.is_some_and(|max| count >= max) {
+            return None;
+        }
+        open.insert(ip, count + 1);
+
+        Some(PeerGuard {
+            open: self.open.clone(),
+            ip,
+        })
+    }
+}
+
+/// Uncounts a connection from [`PeerCounts`] when it ends.
+#[derive(Debug)]
+pub(crate) struct PeerGuard {
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    ip: IpAddr,
+}
+
+impl Drop for PeerGuard {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().expect("peer counts lock poisoned");
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Per-connection state for [`RateLimits`].
+pub(crate) struct ConnectionLimiter {
+    shared: SharedRateLimits,
+    limits: RateLimits,
+    requests: Option<TokenBucket>,
+    readings: Option<TokenBucket>,
+    chunk_bytes: Option<TokenBucket>,
+}
+
+impl ConnectionLimiter {
+    pub(crate) fn new(shared: SharedRateLimits) -> Self {
+        let limits = shared.get();
+        Self {
+            shared,
+            limits,
+            requests: limits.requests.map(TokenBucket::new),
+            readings: limits.readings.map(TokenBucket::new),
+            chunk_bytes: limits.chunk_bytes.map(TokenBucket::new),
+        }
+    }
+
+    /// Charge one request carrying `readings` readings.
+    pub(crate) fn check(&mut self, readings: usize) -> Result<(), Duration> {
+        self.refresh();
+
+        if let Some(bucket) = &mut self.requests {
+            bucket.try_take(1)?;
+        }
+        if let Some(bucket) = self.readings.as_mut().filter(|_| readings > 0) {
+            bucket.try_take(u32::try_from(readings).unwrap_or(u32::MAX))?;
+        }
+
+        Ok(())
+    }
+
+    /// Charge one chunk of a streamed batch carrying `bytes` bytes.
+    pub(crate) fn check_chunk(&mut self, bytes: usize) -> Result<(), Duration> {
+        self.check(0)?;
+
+        if let Some(bucket) = &mut self.chunk_bytes {
+            bucket.try_take(u32::try_from(bytes).unwrap_or(u32::MAX))?;
+        }
+
+        Ok(())
+    }
+
+    /// Start over with full buckets for any quota that changed.
+    fn refresh(&mut self) {
+        let limits = self.shared.get();
+        if limits == self.limits {
+            return;
+        }
+
+        if limits.requests != self.limits.requests {
+            self.requests = limits.requests.map(TokenBucket::new);
+        }
+        if limits.readings != self.limits.readings {
+            self.readings = limits.readings.map(TokenBucket::new);
+        }
+        if limits.chunk_bytes != self.limits.chunk_bytes {
+            self.chunk_bytes = limits.chunk_bytes.map(TokenBucket::new);
+        }
+        self.limits = limits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{ConnectionLimiter, PeerCounts, Quota, RateLimits, SharedRateLimits, TokenBucket};
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Quota::new(2, 4));
+        bucket.updated = start;
+
+        for _ in 0..4 {
+            assert!(bucket.try_take_at(1, start).is_ok());
+        }
+        let wait = bucket.try_take_at(1, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.try_take_at(1, later).is_ok());
+        assert!(bucket.try_take_at(1, later).is_err());
+    }
+
+    #[test]
+    fn oversized_cost_needs_a_full_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Quota::new(10, 100));
+        bucket.updated = start;
+
+        assert!(bucket.try_take_at(500, start).is_ok());
+        assert_eq!(
+            bucket.try_take_at(500, start).unwrap_err(),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn connections_pick_up_changed_limits() {
+        let shared = SharedRateLimits::new(RateLimits {
+            requests: Some(Quota::new(0, 1)),
+            ..RateLimits::default()
+        });
+        let mut limiter = ConnectionLimiter::new(shared.clone());
+
+        assert!(limiter.check(0).is_ok());
+        assert!(limiter.check(0).is_err());
+
+        shared.set(RateLimits {
+            requests: Some(Quota::new(0, 2)),
+            ..RateLimits::default()
+        });
+        assert!(limiter.check(0).is_ok());
+        assert!(limiter.check(0).is_ok());
+        assert!(limiter.check(0).is_err());
+
+        shared.set(RateLimits::default());
+        assert!(limiter.check(500).is_ok());
+    }
+
+    #[test]
+    fn chunks_are_charged_by_size() {
+        let shared = SharedRateLimits::new(RateLimits {
+            chunk_bytes: Some(Quota::new(0, 1000)),
+            ..RateLimits::default()
+        });
+        let mut limiter = ConnectionLimiter::new(shared);
+
+        assert!(limiter.check_chunk(600).is_ok());
+        assert!(limiter.check_chunk(600).is_err());
+        assert!(limiter.check_chunk(400).is_ok());
+    }
+
+    #[test]
+    fn connections_per_address_are_bounded() {
+        let peers = PeerCounts::new(Some(2));
+        let ip = "10.0.0.7".parse().unwrap();
+
+        let first = peers.open(ip).unwrap();
+        let _second = peers.open(ip).unwrap();
+        assert!(peers.open(ip).is_none());
+        assert!(peers.open("10.0.0.8".parse().unwrap()).is_some());
+
+        drop(first);
+        assert!(peers.open(ip).is_some());
+    }
+}
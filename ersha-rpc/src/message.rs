@@ -1,7 +1,13 @@
-use ersha_core::{BatchUploadRequest, BatchUploadResponse, HelloRequest, HelloResponse};
+use ersha_core::{
+    BatchUploadRequest, BatchUploadResponse, CommandDispatchRequest, CommandDispatchResponse,
+    CommandPoll, CommandPollResponse, DeviceDisconnectionRequest, DeviceDisconnectionResponse,
+    DispatcherStatus, DispatcherStatusResponse, HelloRequest, HelloResponse,
+};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
+use crate::BatchUploadChunk;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MessageId(pub Ulid);
 
@@ -17,10 +23,40 @@ impl Default for MessageId {
     }
 }
 
+/// Ties together what both ends log about one piece of work, such as the
+/// upload of a batch, across the calls and replies it takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CorrelationId(pub Ulid);
+
+impl CorrelationId {
+    pub fn new() -> Self {
+        Self(Ulid::new())
+    }
+}
+
+impl Default for CorrelationId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Envelope {
     pub msg_id: MessageId,
     pub reply_to: Option<MessageId>,
+    /// Milliseconds from sending after which the sender no longer waits for
+    /// a reply. Relative, so the two ends' clocks needn't agree.
+    pub deadline_ms: Option<u64>,
+    /// The work the message is part of, see [`correlated`].
+    ///
+    /// [`correlated`]: crate::correlated
+    pub correlation_id: Option<CorrelationId>,
     pub payload: WireMessage,
 }
 
@@ -32,7 +68,25 @@ pub enum WireMessage {
     HelloResponse(HelloResponse),
     BatchUploadRequest(BatchUploadRequest),
     BatchUploadResponse(BatchUploadResponse),
+    DispatcherStatusRequest(DispatcherStatus),
+    DispatcherStatusResponse(DispatcherStatusResponse),
+    CommandPollRequest(CommandPoll),
+    CommandPollResponse(CommandPollResponse),
     Error(WireError),
+    /// Part of a batch streamed in chunks; the last is answered with a
+    /// `BatchUploadResponse`.
+    BatchUploadChunk(BatchUploadChunk),
+    /// Pushed by the server to a client that accepts pushes.
+    CommandDispatchRequest(CommandDispatchRequest),
+    CommandDispatchResponse(CommandDispatchResponse),
+    /// The sender gave up on the call it sent as this message, so it needs
+    /// no reply.
+    Cancel(MessageId),
+    /// The sender is shutting down: calls already made are still answered,
+    /// but new ones should go over a new connection.
+    GoingAway,
+    DeviceDisconnectionRequest(DeviceDisconnectionRequest),
+    DeviceDisconnectionResponse(DeviceDisconnectionResponse),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -46,4 +100,14 @@ pub enum WireErrorCode {
     BadRequest,
     Unsupported,
     Internal,
+    /// The connection exceeded its rate limit; retry later
+    RateLimited,
+    /// The dispatcher uploaded more readings this hour than its quota allows;
+    /// retry next hour
+    QuotaExceeded,
+    /// The request needs an accepted hello on the connection first
+    Unauthenticated,
+    /// The request speaks for a dispatcher other than the one whose hello
+    /// was accepted on the connection
+    Forbidden,
 }
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use crate::{RpcError, WireErrorCode, WireMessage};
+
+/// Receives measurements of RPC traffic, for the application to export
+/// however it exports metrics.
+///
+/// Calls are measured by [`RpcTcp`] and [`Client`], requests and
+/// connections by [`Server`]. Every method does nothing unless implemented.
+///
+/// [`RpcTcp`]: crate::RpcTcp
+/// [`Client`]: crate::Client
+/// [`Server`]: crate::Server
+pub trait RpcMetrics: Send + Sync {
+    /// A connection was accepted.
+    fn connection_opened(&self) {}
+
+    /// A connection accepted earlier was closed.
+    fn connection_closed(&self) {}
+
+    /// A connection was closed as soon as it was accepted, its address
+    /// having as many open as [`ConnectionLimits`] allow.
+    ///
+    /// [`ConnectionLimits`]: crate::ConnectionLimits
+    fn connection_refused(&self) {}
+
+    /// A call of `message`, such as `HelloRequest`, ended with `outcome`
+    /// `elapsed` after it was made.
+    fn call_finished(&self, message: &'static str, outcome: &Outcome, elapsed: Duration) {
+        let _ = (message, outcome, elapsed);
+    }
+
+    /// A request of `message` from the other end was answered, or given up
+    /// on, with `outcome` `elapsed` after it was read.
+    fn request_handled(&self, message: &'static str, outcome: &Outcome, elapsed: Duration) {
+        let _ = (message, outcome, elapsed);
+    }
+}
+
+/// Measures nothing.
+impl RpcMetrics for () {}
+
+/// How a call or request ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Answered with anything but an error
+    Ok,
+    /// Answered with an error
+    Error(WireErrorCode),
+    /// Not answered before its deadline
+    Timeout,
+    /// Given up on by its caller
+    Cancelled,
+    /// Lost with its connection
+    Failed,
+}
+
+impl Outcome {
+    /// The outcome of a call or request answered with `reply`.
+    pub fn of_reply(reply: &WireMessage) -> Self {
+        match reply {
+            WireMessage::Error(error) => Self::Error(error.code.clone()),
+            _ => Self::Ok,
+        }
+    }
+
+    /// The outcome of a call that failed with `error`.
+    pub fn of_error(error: &RpcError) -> Self {
+        match error {
+            RpcError::Timeout(_) => Self::Timeout,
+            RpcError::Closed | RpcError::QueueFull | RpcError::ChannelClosed(_) => Self::Failed,
+        }
+    }
+
+    /// Short name to label the outcome with, such as `ok` or `timeout`.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Error(_) => "error",
+            Self::Timeout => "timeout",
+            Self::Cancelled => "cancelled",
+            Self::Failed => "failed",
+        }
+    }
+}
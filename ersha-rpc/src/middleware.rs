@@ -0,0 +1,128 @@
+//! Layers wrapping every request handler on a [`Router`].
+//!
+//! A layer sees each request before its handler does, with the [`Session`]
+//! of the connection it arrived on, and either passes it on with
+//! [`Next::run`] or answers it itself:
+//!
+//! ```ignore
+//! let router = Router::new()
+//!     .route(...)
+//!     .layer(require_hello)
+//!     .layer(|call: Call<'_, State>, next: Next<'_, State>| {
+//!         let span = tracing::info_span!("rpc", request = call.name());
+//!         Box::pin(next.run(call).instrument(span))
+//!     });
+//! ```
+//!
+//! Layers added first are outermost. Rate limits are checked before any
+//! layer runs.
+
+use ersha_core::{ChunkLimits, DispatcherId};
+
+use crate::router::{request_dispatcher, request_name};
+use crate::{MessageId, ReplyFuture, Router, RpcTcp, WireError, WireErrorCode, WireMessage};
+
+pub(crate) type Layer<S> =
+    Box<dyn for<'a> Fn(Call<'a, S>, Next<'a, S>) -> ReplyFuture + Send + Sync>;
+
+/// What the server knows of the other end of a connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Session {
+    /// The dispatcher whose hello was accepted on the connection, if any.
+    pub dispatcher_id: Option<DispatcherId>,
+    /// Chunk sizes agreed in the accepted hello, if the client streams
+    /// batches.
+    pub chunking: Option<ChunkLimits>,
+}
+
+impl Session {
+    /// Whether a hello was accepted on the connection.
+    pub fn is_authenticated(&self) -> bool {
+        self.dispatcher_id.is_some()
+    }
+}
+
+/// A request on its way to its handler.
+pub struct Call<'a, S> {
+    pub msg_id: MessageId,
+    pub payload: WireMessage,
+    pub session: &'a Session,
+    pub rpc: &'a RpcTcp,
+    pub state: &'a S,
+}
+
+impl<S> Call<'_, S> {
+    /// Name of the request, such as `HelloRequest`.
+    pub fn name(&self) -> &'static str {
+        request_name(&self.payload).unwrap_or("unknown")
+    }
+}
+
+/// The layers after the current one, and the handlers behind them.
+pub struct Next<'a, S> {
+    layers: &'a [Layer<S>],
+    router: &'a Router<S>,
+}
+
+impl<'a, S: Send + Sync + 'static> Next<'a, S> {
+    pub(crate) fn new(layers: &'a [Layer<S>], router: &'a Router<S>) -> Self {
+        Self { layers, router }
+    }
+
+    /// Pass `call` on to the next layer, or to its handler after the last.
+    pub fn run(self, call: Call<'a, S>) -> ReplyFuture {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer(call, Next::new(layers, self.router)),
+            None => self
+                .router
+                .route_request(call.payload, call.msg_id, call.rpc, call.state),
+        }
+    }
+}
+
+/// Answer anything but hellos and pings with [`WireErrorCode::Unauthenticated`]
+/// until a hello is accepted on the connection, and requests speaking for
+/// any dispatcher but the one whose hello was accepted with
+/// [`WireErrorCode::Forbidden`].
+pub fn require_hello<S: Send + Sync + 'static>(
+    call: Call<'_, S>,
+    next: Next<'_, S>,
+) -> ReplyFuture {
+    if matches!(
+        call.payload,
+        WireMessage::HelloRequest(_) | WireMessage::Ping
+    ) {
+        return next.run(call);
+    }
+
+    let error = match (
+        call.session.dispatcher_id,
+        request_dispatcher(&call.payload),
+    ) {
+        (None, _) => {
+            tracing::warn!("rejecting {} on a connection without hello", call.name());
+            WireError {
+                code: WireErrorCode::Unauthenticated,
+                message: format!("{} requires an accepted hello", call.name()),
+            }
+        }
+        (Some(authenticated), Some(named)) if named != authenticated => {
+            tracing::warn!(
+                ?authenticated,
+                ?named,
+                "rejecting {} for another dispatcher",
+                call.name()
+            );
+            WireError {
+                code: WireErrorCode::Forbidden,
+                message: format!(
+                    "{} names a dispatcher other than the one authenticated",
+                    call.name()
+                ),
+            }
+        }
+        _ => return next.run(call),
+    };
+
+    Box::pin(async move { WireMessage::Error(error) })
+}
@@ -0,0 +1,147 @@
+//! Requests and notices the server pushes to connected dispatchers.
+//!
+//! A dispatcher that says hello with `accepts_push` is tracked by its id
+//! while its connection lasts, and can be sent requests and notices through
+//! [`Dispatchers`]. On the client, they arrive on [`Client::incoming`].
+//!
+//! [`Client::incoming`]: crate::Client::incoming
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use ersha_core::{CommandDispatchRequest, CommandDispatchResponse, DispatcherId};
+use thiserror::Error;
+
+use crate::{Caller, RpcError, WireError, WireMessage};
+
+#[derive(Debug, Error)]
+pub enum PushError {
+    #[error("dispatcher {0:?} is not connected")]
+    NotConnected(DispatcherId),
+    #[error("rpc error: {0}")]
+    Rpc(#[from] RpcError),
+    #[error("unexpected response type")]
+    UnexpectedResponse,
+    #[error("error response: {0:?}")]
+    ErrorResponse(WireError),
+}
+
+/// Identifies one connection, telling a dispatcher's current connection from
+/// one it replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ConnectionId(u64);
+
+impl ConnectionId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub(crate) fn get(self) -> u64 {
+        self.0
+    }
+}
+
+/// The dispatchers connected to a server that accept pushed requests.
+///
+/// A dispatcher that reconnects replaces its earlier connection.
+#[derive(Clone, Default)]
+pub struct Dispatchers {
+    connections: Arc<DashMap<DispatcherId, (ConnectionId, Caller)>>,
+}
+
+impl Dispatchers {
+    pub fn is_connected(&self, dispatcher_id: DispatcherId) -> bool {
+        self.connections.contains_key(&dispatcher_id)
+    }
+
+    /// Ids of the dispatchers currently connected.
+    pub fn connected(&self) -> Vec<DispatcherId> {
+        self.connections.iter().map(|entry| *entry.key()).collect()
+    }
+
+    /// Send `payload` to the dispatcher and wait up to `timeout` for its
+    /// answer.
+    pub async fn push(
+        &self,
+        dispatcher_id: DispatcherId,
+        payload: WireMessage,
+        timeout: Duration,
+    ) -> Result<WireMessage, PushError> {
+        // Don't hold the map entry across the call.
+        let caller = self
+            .connections
+            .get(&dispatcher_id)
+            .map(|entry| entry.1.clone())
+            .ok_or(PushError::NotConnected(dispatcher_id))?;
+
+        match caller.call(payload, timeout).await?.payload {
+            WireMessage::Error(err) => Err(PushError::ErrorResponse(err)),
+            payload => Ok(payload),
+        }
+    }
+
+    /// Send `payload` to the dispatcher without waiting for an answer, once
+    /// there is room for it in the connection's write queue.
+    pub async fn send_to(
+        &self,
+        dispatcher_id: DispatcherId,
+        payload: WireMessage,
+    ) -> Result<(), PushError> {
+        let caller = self
+            .connections
+            .get(&dispatcher_id)
+            .map(|entry| entry.1.clone())
+            .ok_or(PushError::NotConnected(dispatcher_id))?;
+
+        caller.send(payload).await?;
+        Ok(())
+    }
+
+    /// Send `payload` to every connected dispatcher without waiting for
+    /// answers, returning how many it was queued for. Dispatchers whose
+    /// write queue is full are skipped rather than holding up the rest.
+    pub fn broadcast(&self, payload: &WireMessage) -> usize {
+        let mut sent = 0;
+        for entry in self.connections.iter() {
+            match entry.value().1.try_send(payload.clone()) {
+                Ok(_) => sent += 1,
+                Err(e) => {
+                    tracing::warn!(dispatcher_id = ?entry.key(), "failed to broadcast: {e}")
+                }
+            }
+        }
+        sent
+    }
+
+    /// Hand the dispatcher commands, returning those it passed on.
+    pub async fn dispatch_commands(
+        &self,
+        dispatcher_id: DispatcherId,
+        request: CommandDispatchRequest,
+        timeout: Duration,
+    ) -> Result<CommandDispatchResponse, PushError> {
+        let payload = WireMessage::CommandDispatchRequest(request);
+        match self.push(dispatcher_id, payload, timeout).await? {
+            WireMessage::CommandDispatchResponse(resp) => Ok(resp),
+            _ => Err(PushError::UnexpectedResponse),
+        }
+    }
+
+    pub(crate) fn register(
+        &self,
+        dispatcher_id: DispatcherId,
+        connection: ConnectionId,
+        caller: Caller,
+    ) {
+        self.connections.insert(dispatcher_id, (connection, caller));
+    }
+
+    /// Forget whichever dispatcher `connection` belonged to, unless it has
+    /// since reconnected.
+    pub(crate) fn unregister(&self, connection: ConnectionId) {
+        self.connections.retain(|_, (id, _)| *id != connection);
+    }
+}
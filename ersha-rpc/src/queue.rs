@@ -0,0 +1,260 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{Envelope, RpcError, WireMessage};
+
+/// What a connection does with a message sent while its write queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// Wait until the peer has read enough to make room
+    #[default]
+    Block,
+    /// Make room by dropping the oldest queued ping, pong or cancellation,
+    /// waiting as `Block` does if there is none
+    DropOldest,
+    /// Fail the send with [`RpcError::QueueFull`]
+    Error,
+}
+
+/// Bound on the messages waiting to be written to a connection, so a peer
+/// that reads slowly can't make them pile up without limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteQueue {
+    /// Messages held at most. A single message always fits.
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl WriteQueue {
+    /// A queue of `capacity` messages that blocks senders when full.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            overflow: Overflow::Block,
+        }
+    }
+}
+
+/// Messages waiting for the writer task of a connection.
+pub(crate) struct Outbox {
+    limits: WriteQueue,
+    state: Mutex<OutboxState>,
+    /// Messages waiting across every outbox sharing it.
+    queued: Arc<AtomicUsize>,
+    readable: Notify,
+    writable: Notify,
+}
+
+#[derive(Default)]
+struct OutboxState {
+    messages: VecDeque<Envelope>,
+    closed: bool,
+}
+
+impl Outbox {
+    pub(crate) fn new(limits: WriteQueue, queued: Arc<AtomicUsize>) -> Self {
+        Self {
+            limits,
+            state: Mutex::default(),
+            queued,
+            readable: Notify::new(),
+            writable: Notify::new(),
+        }
+    }
+
+    /// Queue `envelope` to be written, as the overflow policy allows.
+    pub(crate) async fn push(&self, envelope: Envelope) -> Result<(), RpcError> {
+        loop {
+            // Registered before checking, so room made meanwhile isn't missed.
+            let writable = self.writable.notified();
+            {
+                let mut state = self.state.lock().expect("outbox lock poisoned");
+                if state.closed {
+                    return Err(RpcError::Closed);
+                }
+                if state.messages.len() < self.limits.capacity.max(1) {
+                    state.messages.push_back(envelope);
+                    self.queued.fetch_add(1, Ordering::Relaxed);
+                    self.readable.notify_one();
+                    return Ok(());
+                }
+
+                match self.limits.overflow {
+                    Overflow::Block => {}
+                    Overflow::Error => return Err(RpcError::QueueFull),
+                    Overflow::DropOldest => {
+                        let droppable = state
+                            .messages
+                            .iter()
+                            .position(|queued| !is_critical(queued));
+                        if let Some(index) = droppable {
+                            let dropped = state.messages.remove(index);
+                            tracing::warn!("write queue full, dropped {dropped:?}");
+                            state.messages.push_back(envelope);
+                            self.readable.notify_one();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            writable.await;
+        }
+    }
+
+    /// Queue `envelope` if there is room right away.
+    pub(crate) fn try_push(&self, envelope: Envelope) -> Result<(), RpcError> {
+        let mut state = self.state.lock().expect("outbox lock poisoned");
+        if state.closed {
+            return Err(RpcError::Closed);
+        }
+        if state.messages.len() >= self.limits.capacity.max(1) {
+            return Err(RpcError::QueueFull);
+        }
+        state.messages.push_back(envelope);
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.readable.notify_one();
+        Ok(())
+    }
+
+    /// The next message to write, or `None` once closed and drained.
+    pub(crate) async fn pop(&self) -> Option<Envelope> {
+        loop {
+            let readable = self.readable.notified();
+            {
+                let mut state = self.state.lock().expect("outbox lock poisoned");
+                if let Some(envelope) = state.messages.pop_front() {
+                    self.queued.fetch_sub(1, Ordering::Relaxed);
+                    self.writable.notify_one();
+                    return Some(envelope);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+
+    /// Refuse further messages. Those already queued can still be popped.
+    pub(crate) fn close(&self) {
+        self.state.lock().expect("outbox lock poisoned").closed = true;
+        self.readable.notify_one();
+        self.writable.notify_waiters();
+    }
+
+    /// Close, dropping whatever is still queued.
+    pub(crate) fn discard(&self) {
+        let mut state = self.state.lock().expect("outbox lock poisoned");
+        state.closed = true;
+        self.queued
+            .fetch_sub(state.messages.len(), Ordering::Relaxed);
+        state.messages.clear();
+        drop(state);
+        self.writable.notify_waiters();
+    }
+
+    /// Messages waiting to be written.
+    pub(crate) fn len(&self) -> usize {
+        self.state
+            .lock()
+            .expect("outbox lock poisoned")
+            .messages
+            .len()
+    }
+}
+
+/// Whether losing `envelope` would lose data or leave a call unanswered.
+/// Keepalives are only there to show the connection is alive, which a full
+/// queue already does, and cancellations only save the other end work.
+fn is_critical(envelope: &Envelope) -> bool {
+    !matches!(
+        envelope.payload,
+        WireMessage::Ping | WireMessage::Pong | WireMessage::Cancel(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::MessageId;
+
+    fn envelope(payload: WireMessage) -> Envelope {
+        Envelope {
+            msg_id: MessageId::new(),
+            reply_to: None,
+            deadline_ms: None,
+            correlation_id: None,
+            payload,
+        }
+    }
+
+    fn outbox(capacity: usize, overflow: Overflow) -> Outbox {
+        let limits = WriteQueue { capacity, overflow };
+        Outbox::new(limits, Arc::new(AtomicUsize::new(0)))
+    }
+
+    #[tokio::test]
+    async fn full_queue_blocks_until_read() {
+        let outbox = Arc::new(outbox(1, Overflow::Block));
+        outbox.push(envelope(WireMessage::Ping)).await.unwrap();
+
+        let blocked = tokio::spawn({
+            let outbox = outbox.clone();
+            async move { outbox.push(envelope(WireMessage::Pong)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!blocked.is_finished());
+
+        assert_eq!(outbox.pop().await.unwrap().payload, WireMessage::Ping);
+        blocked.await.unwrap().unwrap();
+        assert_eq!(outbox.pop().await.unwrap().payload, WireMessage::Pong);
+        assert_eq!(outbox.queued.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn full_queue_refuses_or_drops_as_configured() {
+        let refusing = outbox(1, Overflow::Error);
+        refusing.push(envelope(WireMessage::Ping)).await.unwrap();
+        assert!(matches!(
+            refusing.push(envelope(WireMessage::Pong)).await,
+            Err(RpcError::QueueFull)
+        ));
+
+        let dropping = outbox(2, Overflow::DropOldest);
+        let error = WireMessage::Error(crate::WireError {
+            code: crate::WireErrorCode::Internal,
+            message: "kept".to_string(),
+        });
+        dropping.push(envelope(error.clone())).await.unwrap();
+        dropping.push(envelope(WireMessage::Ping)).await.unwrap();
+        dropping.push(envelope(WireMessage::Pong)).await.unwrap();
+        assert_eq!(dropping.len(), 2);
+        assert_eq!(dropping.pop().await.unwrap().payload, error);
+        assert_eq!(dropping.pop().await.unwrap().payload, WireMessage::Pong);
+    }
+
+    #[tokio::test]
+    async fn closing_wakes_blocked_senders() {
+        let outbox = Arc::new(outbox(1, Overflow::Block));
+        outbox.push(envelope(WireMessage::Ping)).await.unwrap();
+
+        let blocked = tokio::spawn({
+            let outbox = outbox.clone();
+            async move { outbox.push(envelope(WireMessage::Pong)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        outbox.close();
+        assert!(matches!(blocked.await.unwrap(), Err(RpcError::Closed)));
+
+        // What was queued before closing is still written.
+        assert_eq!(outbox.pop().await.unwrap().payload, WireMessage::Ping);
+        assert!(outbox.pop().await.is_none());
+    }
+}
@@ -0,0 +1,383 @@
+//! A client that survives dropped connections.
+//!
+//! [`ReconnectingClient`] opens a connection on first use and again whenever
+//! one is lost, backing off exponentially between failed attempts. Each new
+//! connection says hello before carrying calls, and calls that are safe to
+//! repeat are retried over the new connection.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use ersha_core::{
+    BatchUploadRequest, BatchUploadResponse, CommandPoll, CommandPollResponse, DispatcherStatus,
+    DispatcherStatusResponse, HelloRejectionReason, HelloRequest, HelloResponse,
+};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, watch};
+
+use crate::{Client, ClientError, Keepalive};
+
+pub type ConnectFn =
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = io::Result<Client>> + Send>> + Send + Sync>;
+
+pub type HelloFn = Box<dyn Fn() -> HelloRequest + Send + Sync>;
+
+/// Delay between failed connection attempts, doubling from `initial` up to
+/// `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// Delay before the next attempt after `failures` consecutive failures.
+    pub fn delay(&self, failures: u32) -> Duration {
+        match failures {
+            0 => Duration::ZERO,
+            n => self
+                .initial
+                .saturating_mul(2u32.saturating_pow(n - 1))
+                .min(self.max),
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Where a [`ReconnectingClient`] stands with its server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// No connection has been attempted yet.
+    Idle,
+    /// Connecting and saying hello; `attempt` counts from 1 since the last
+    /// connection was up.
+    Connecting { attempt: u32 },
+    /// Connected, and the hello was accepted.
+    Connected,
+    /// The connection was lost or could not be made. The next attempt waits
+    /// `retry_in`.
+    Disconnected { failures: u32, retry_in: Duration },
+}
+
+/// An RPC client that reconnects, and says hello again, whenever its
+/// connection drops.
+///
+/// Calls fail over to a new connection at most `retries` times. Batch uploads
+/// are safe to repeat since prime reports readings it already stored as
+/// duplicates; status reports and command polls are likewise idempotent.
+pub struct ReconnectingClient {
+    connect: ConnectFn,
+    hello: Option<HelloFn>,
+    keepalive: Option<Keepalive>,
+    backoff: Backoff,
+    retries: usize,
+    client: Mutex<Option<Arc<Client>>>,
+    failures: AtomicU32,
+    state: watch::Sender<ConnectionState>,
+}
+
+impl ReconnectingClient {
+    /// A client connecting with `connect`, e.g. over TLS.
+    pub fn new<F, Fut>(connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<Client>> + Send + 'static,
+    {
+        Self {
+            connect: Box::new(move || Box::pin(connect())),
+            hello: None,
+            keepalive: None,
+            backoff: Backoff::default(),
+            retries: 3,
+            client: Mutex::new(None),
+            failures: AtomicU32::new(0),
+            state: watch::Sender::new(ConnectionState::Idle),
+        }
+    }
+
+    /// A client connecting over plain TCP to `addr`.
+    pub fn tcp(addr: SocketAddr) -> Self {
+        Self::new(move || async move { Ok(Client::new(TcpStream::connect(addr).await?)) })
+    }
+
+    /// Say hello with the request built by `hello` on every new connection.
+    ///
+    /// It is built afresh each time so signed credentials stay current.
+    pub fn with_hello<F>(mut self, hello: F) -> Self
+    where
+        F: Fn() -> HelloRequest + Send + Sync + 'static,
+    {
+        self.hello = Some(Box::new(hello));
+        self
+    }
+
+    /// Keep each connection alive with pings, reconnecting on the next call
+    /// once the server stops answering them.
+    pub fn with_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Retry a call over a new connection up to `retries` times.
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Notifications of connection state changes.
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    pub async fn ping(&self) -> Result<(), ClientError> {
+        self.retrying(|client| async move { client.ping().await })
+            .await
+    }
+
+    pub async fn batch_upload(
+        &self,
+        request: BatchUploadRequest,
+    ) -> Result<BatchUploadResponse, ClientError> {
+        self.retrying(|client| {
+            let request = request.clone();
+            async move { client.batch_upload(request).await }
+        })
+        .await
+    }
+
+    pub async fn dispatcher_status(
+        &self,
+        status: DispatcherStatus,
+    ) -> Result<DispatcherStatusResponse, ClientError> {
+        self.retrying(|client| {
+            let status = status.clone();
+            async move { client.dispatcher_status(status).await }
+        })
+        .await
+    }
+
+    pub async fn poll_commands(
+        &self,
+        poll: CommandPoll,
+    ) -> Result<CommandPollResponse, ClientError> {
+        self.retrying(|client| {
+            let poll = poll.clone();
+            async move { client.poll_commands(poll).await }
+        })
+        .await
+    }
+
+    /// Run `call` over the current connection, reconnecting and running it
+    /// again when the connection turns out to be lost.
+    async fn retrying<T, F, Fut>(&self, call: F) -> Result<T, ClientError>
+    where
+        F: Fn(Arc<Client>) -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let mut retries = 0;
+        loop {
+            let result = match self.client().await {
+                Ok(client) => {
+                    let result = call(client.clone()).await;
+                    if result.as_ref().is_err_and(ClientError::is_disconnect) {
+                        self.disconnected(&client).await;
+                    }
+                    result
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Err(e) if retryable(&e) && retries < self.retries => {
+                    retries += 1;
+                    tracing::debug!(error = %e, retries, "retrying call over a new connection");
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The current connection, opening one if there is none.
+    async fn client(&self) -> Result<Arc<Client>, ClientError> {
+        let mut current = self.client.lock().await;
+        match current.as_ref() {
+            Some(client) if !client.is_closed() => return Ok(client.clone()),
+            Some(_) => {
+                *current = None;
+                tracing::warn!("connection lost");
+            }
+            None => {}
+        }
+
+        let failures = self.failures.load(Ordering::Relaxed);
+        tokio::time::sleep(self.backoff.delay(failures)).await;
+        self.state.send_replace(ConnectionState::Connecting {
+            attempt: failures + 1,
+        });
+
+        match self.open().await {
+            Ok(client) => {
+                let client = Arc::new(client);
+                *current = Some(client.clone());
+                self.failures.store(0, Ordering::Relaxed);
+                self.state.send_replace(ConnectionState::Connected);
+                tracing::info!("connected");
+                Ok(client)
+            }
+            Err(e) => {
+                let failures = failures + 1;
+                self.failures.store(failures, Ordering::Relaxed);
+                let retry_in = self.backoff.delay(failures);
+                self.state
+                    .send_replace(ConnectionState::Disconnected { failures, retry_in });
+                tracing::warn!(error = %e, ?retry_in, "failed to connect");
+                Err(e)
+            }
+        }
+    }
+
+    /// Connect and say hello.
+    async fn open(&self) -> Result<Client, ClientError> {
+        let mut client = (self.connect)().await.map_err(ClientError::Connect)?;
+        if let Some(keepalive) = self.keepalive {
+            client = client.with_keepalive(keepalive);
+        }
+        if let Some(hello) = &self.hello
+            && let HelloResponse::Rejected { reason, .. } = client.hello(hello()).await?
+        {
+            return Err(ClientError::HelloRejected(reason));
+        }
+
+        Ok(client)
+    }
+
+    /// Drop `client` as lost, unless another call already replaced it.
+    async fn disconnected(&self, client: &Arc<Client>) {
+        let mut current = self.client.lock().await;
+        if current
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, client))
+        {
+            *current = None;
+            self.state.send_replace(ConnectionState::Disconnected {
+                failures: 0,
+                retry_in: Duration::ZERO,
+            });
+            tracing::warn!("connection lost");
+        }
+    }
+}
+
+/// Whether a call failing with `error` may succeed if made again.
+fn retryable(error: &ClientError) -> bool {
+    error.is_disconnect()
+        || matches!(
+            error,
+            ClientError::HelloRejected(HelloRejectionReason::Unavailable)
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use ersha_core::{DispatcherId, H3Cell, HelloRequest, HelloResponse};
+    use tokio::net::TcpListener;
+    use ulid::Ulid;
+
+    use super::{Backoff, ConnectionState, ReconnectingClient};
+    use crate::{RpcTcp, WireMessage};
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(5),
+        };
+
+        let delays: Vec<_> = (0..5).map(|n| backoff.delay(n).as_secs()).collect();
+        assert_eq!(delays, [0, 1, 2, 4, 5]);
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(5));
+    }
+
+    /// Accept connections on `listener`, each answering hellos and a single
+    /// ping before it is dropped.
+    fn serve(listener: TcpListener, hellos: Arc<AtomicUsize>) {
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut rpc = RpcTcp::new(stream, 16);
+                while let Some(envelope) = rpc.recv().await {
+                    let reply = match envelope.payload {
+                        WireMessage::HelloRequest(hello) => {
+                            hellos.fetch_add(1, Ordering::Relaxed);
+                            WireMessage::HelloResponse(HelloResponse::Accepted {
+                                dispatcher_id: hello.dispatcher_id,
+                                proof: None,
+                                chunking: None,
+                                compression: None,
+                            })
+                        }
+                        WireMessage::Ping => WireMessage::Pong,
+                        _ => continue,
+                    };
+                    let last = reply == WireMessage::Pong;
+                    rpc.reply(envelope.msg_id, reply).await.unwrap();
+                    if last {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn calls_resume_over_a_new_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hellos = Arc::new(AtomicUsize::new(0));
+        serve(listener, hellos.clone());
+
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let client = ReconnectingClient::tcp(addr)
+            .with_hello(move || HelloRequest {
+                dispatcher_id,
+                location: H3Cell(0x8a2a1072b59ffff),
+                credentials: None,
+                max_chunk_bytes: None,
+                compression: Box::new([]),
+                accepts_push: false,
+            })
+            .with_backoff(Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+            });
+        let state = client.state();
+        assert_eq!(*state.borrow(), ConnectionState::Idle);
+
+        for hellos_expected in 1..=3 {
+            client.ping().await.unwrap();
+            assert_eq!(*state.borrow(), ConnectionState::Connected);
+            assert_eq!(hellos.load(Ordering::Relaxed), hellos_expected);
+        }
+    }
+}
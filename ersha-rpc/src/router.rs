@@ -0,0 +1,360 @@
+//! Typed dispatch of requests to the handlers registered for them.
+//!
+//! Each kind of request is a [`Request`], naming the response it is answered
+//! with. Handlers are registered per request type on a [`Router`]:
+//!
+//! ```ignore
+//! let router = Router::new()
+//!     .route(|hello: HelloRequest, _msg_id, _rpc: &RpcTcp, state: &State| async { ... })
+//!     .route(|batch: BatchUploadRequest, _msg_id, _rpc: &RpcTcp, state: &State| async { ... });
+//! ```
+//!
+//! A handler returns its response, or a `Result` whose error is converted
+//! into a [`WireError`] and sent in place of the response. Requests without
+//! a handler are answered with [`WireErrorCode::Unsupported`].
+
+use std::future::Future;
+use std::pin::Pin;
+
+use ersha_core::{
+    BatchUploadRequest, BatchUploadResponse, CommandPoll, CommandPollResponse,
+    DeviceDisconnectionRequest, DeviceDisconnectionResponse, DispatcherId, DispatcherStatus,
+    DispatcherStatusResponse, HelloRequest, HelloResponse,
+};
+
+use crate::middleware::{Call, Layer, Next, Session};
+use crate::{MessageId, RpcTcp, WireError, WireErrorCode, WireMessage};
+
+/// The reply a handler, or a layer in front of it, answers a request with.
+pub type ReplyFuture = Pin<Box<dyn Future<Output = WireMessage> + Send>>;
+
+type Handler<S> = Box<dyn Fn(WireMessage, MessageId, &RpcTcp, &S) -> ReplyFuture + Send + Sync>;
+
+/// A handler, and which messages it handles.
+struct Route<S> {
+    matches: fn(&WireMessage) -> bool,
+    handler: Handler<S>,
+}
+
+type DisconnectFn<S> =
+    Box<dyn Fn(Option<DispatcherId>, &S) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// A request a client sends, and the response it is answered with.
+pub trait Request: Sized + Send + 'static {
+    type Response: Send + 'static;
+
+    /// Whether `message` carries this request.
+    fn matches(message: &WireMessage) -> bool;
+
+    /// The request `message` carries, if it is this one.
+    fn from_wire(message: WireMessage) -> Option<Self>;
+
+    fn into_wire(response: Self::Response) -> WireMessage;
+}
+
+/// A [`WireMessage::Ping`], answered with a [`WireMessage::Pong`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ping;
+
+impl Request for Ping {
+    type Response = ();
+
+    fn matches(message: &WireMessage) -> bool {
+        matches!(message, WireMessage::Ping)
+    }
+
+    fn from_wire(message: WireMessage) -> Option<Self> {
+        match message {
+            WireMessage::Ping => Some(Ping),
+            _ => None,
+        }
+    }
+
+    fn into_wire((): ()) -> WireMessage {
+        WireMessage::Pong
+    }
+}
+
+impl Request for HelloRequest {
+    type Response = HelloResponse;
+
+    fn matches(message: &WireMessage) -> bool {
+        matches!(message, WireMessage::HelloRequest(_))
+    }
+
+    fn from_wire(message: WireMessage) -> Option<Self> {
+        match message {
+            WireMessage::HelloRequest(hello) => Some(hello),
+            _ => None,
+        }
+    }
+
+    fn into_wire(response: HelloResponse) -> WireMessage {
+        WireMessage::HelloResponse(response)
+    }
+}
+
+impl Request for BatchUploadRequest {
+    type Response = BatchUploadResponse;
+
+    fn matches(message: &WireMessage) -> bool {
+        matches!(message, WireMessage::BatchUploadRequest(_))
+    }
+
+    fn from_wire(message: WireMessage) -> Option<Self> {
+        match message {
+            WireMessage::BatchUploadRequest(request) => Some(request),
+            _ => None,
+        }
+    }
+
+    fn into_wire(response: BatchUploadResponse) -> WireMessage {
+        WireMessage::BatchUploadResponse(response)
+    }
+}
+
+impl Request for DispatcherStatus {
+    type Response = DispatcherStatusResponse;
+
+    fn matches(message: &WireMessage) -> bool {
+        matches!(message, WireMessage::DispatcherStatusRequest(_))
+    }
+
+    fn from_wire(message: WireMessage) -> Option<Self> {
+        match message {
+            WireMessage::DispatcherStatusRequest(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    fn into_wire(response: DispatcherStatusResponse) -> WireMessage {
+        WireMessage::DispatcherStatusResponse(response)
+    }
+}
+
+impl Request for CommandPoll {
+    type Response = CommandPollResponse;
+
+    fn matches(message: &WireMessage) -> bool {
+        matches!(message, WireMessage::CommandPollRequest(_))
+    }
+
+    fn from_wire(message: WireMessage) -> Option<Self> {
+        match message {
+            WireMessage::CommandPollRequest(poll) => Some(poll),
+            _ => None,
+        }
+    }
+
+    fn into_wire(response: CommandPollResponse) -> WireMessage {
+        WireMessage::CommandPollResponse(response)
+    }
+}
+
+impl Request for DeviceDisconnectionRequest {
+    type Response = DeviceDisconnectionResponse;
+
+    fn matches(message: &WireMessage) -> bool {
+        matches!(message, WireMessage::DeviceDisconnectionRequest(_))
+    }
+
+    fn from_wire(message: WireMessage) -> Option<Self> {
+        match message {
+            WireMessage::DeviceDisconnectionRequest(request) => Some(request),
+            _ => None,
+        }
+    }
+
+    fn into_wire(response: DeviceDisconnectionResponse) -> WireMessage {
+        WireMessage::DeviceDisconnectionResponse(response)
+    }
+}
+
+/// What a handler for requests answered with `R` may return.
+pub trait IntoReply<R> {
+    fn into_reply(self) -> Result<R, WireError>;
+}
+
+impl<R> IntoReply<R> for R {
+    fn into_reply(self) -> Result<R, WireError> {
+        Ok(self)
+    }
+}
+
+impl<R, E: Into<WireError>> IntoReply<R> for Result<R, E> {
+    fn into_reply(self) -> Result<R, WireError> {
+        self.map_err(Into::into)
+    }
+}
+
+/// Handlers for the requests a [`Server`] answers, sharing its state `S`.
+///
+/// [`Server`]: crate::Server
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+    layers: Vec<Layer<S>>,
+    on_disconnect: Option<DisconnectFn<S>>,
+}
+
+impl<S: Send + Sync + 'static> Router<S> {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            layers: Vec::new(),
+            on_disconnect: None,
+        }
+    }
+
+    /// Handle requests of type `Req`, replacing any handler registered for
+    /// them before.
+    pub fn route<Req, F, Fut>(mut self, handler: F) -> Self
+    where
+        Req: Request,
+        F: Fn(Req, MessageId, &RpcTcp, &S) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoReply<Req::Response>,
+    {
+        // Later routes are tried first.
+        self.routes.insert(
+            0,
+            Route {
+                matches: Req::matches,
+                handler: Box::new(move |message, msg_id, rpc, state| {
+                    let request = Req::from_wire(message).expect("route matches its request");
+                    let reply = handler(request, msg_id, rpc, state);
+                    Box::pin(async move {
+                        match reply.await.into_reply() {
+                            Ok(response) => Req::into_wire(response),
+                            Err(error) => WireMessage::Error(error),
+                        }
+                    })
+                }),
+            },
+        );
+        self
+    }
+
+    /// Wrap every handler in `layer`, inside the layers added before it.
+    ///
+    /// See [`middleware`](crate::middleware).
+    pub fn layer<F>(mut self, layer: F) -> Self
+    where
+        F: for<'a> Fn(Call<'a, S>, Next<'a, S>) -> ReplyFuture + Send + Sync + 'static,
+    {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Run `handler` when a connection closes, with the dispatcher whose
+    /// hello was accepted on it, if any.
+    pub fn on_disconnect<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Option<DispatcherId>, &S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_disconnect = Some(Box::new(move |dispatcher_id, state| {
+            Box::pin(handler(dispatcher_id, state))
+        }));
+        self
+    }
+
+    /// The reply to `message`, or `None` if it needs none.
+    pub(crate) fn handle(
+        &self,
+        message: WireMessage,
+        msg_id: MessageId,
+        rpc: &RpcTcp,
+        session: &Session,
+        state: &S,
+    ) -> Option<ReplyFuture> {
+        if request_name(&message).is_none() {
+            tracing::debug!("received unexpected message on server: {message:?}");
+            return None;
+        }
+
+        let call = Call {
+            msg_id,
+            payload: message,
+            session,
+            rpc,
+            state,
+        };
+        Some(Next::new(&self.layers, self).run(call))
+    }
+
+    /// The reply to request `message` from the handler registered for it.
+    pub(crate) fn route_request(
+        &self,
+        message: WireMessage,
+        msg_id: MessageId,
+        rpc: &RpcTcp,
+        state: &S,
+    ) -> ReplyFuture {
+        if let Some(route) = self.routes.iter().find(|route| (route.matches)(&message)) {
+            return (route.handler)(message, msg_id, rpc, state);
+        }
+
+        match message {
+            // Pings are answered whether or not anything else is done.
+            WireMessage::Ping => Box::pin(async { WireMessage::Pong }),
+            message => {
+                let name = request_name(&message).unwrap_or("unknown");
+                tracing::warn!("received {name} but no handler registered");
+                let error = WireError {
+                    code: WireErrorCode::Unsupported,
+                    message: format!("{name} is not supported"),
+                };
+                Box::pin(async move { WireMessage::Error(error) })
+            }
+        }
+    }
+
+    pub(crate) async fn disconnected(&self, dispatcher_id: Option<DispatcherId>, state: &S) {
+        if let Some(handler) = &self.on_disconnect {
+            handler(dispatcher_id, state).await;
+        }
+    }
+}
+
+impl<S: Send + Sync + 'static> Default for Router<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The dispatcher a request speaks for, if it names one.
+pub(crate) fn request_dispatcher(message: &WireMessage) -> Option<DispatcherId> {
+    match message {
+        WireMessage::HelloRequest(hello) => Some(hello.dispatcher_id),
+        WireMessage::BatchUploadRequest(batch) => Some(batch.dispatcher_id),
+        WireMessage::DispatcherStatusRequest(status) => Some(status.dispatcher_id),
+        WireMessage::CommandPollRequest(poll) => Some(poll.dispatcher_id),
+        WireMessage::DeviceDisconnectionRequest(request) => Some(request.dispatcher_id),
+        _ => None,
+    }
+}
+
+/// Name of the request `message` carries, or `None` if it is a response,
+/// error or notice, which a server never answers.
+pub(crate) fn request_name(message: &WireMessage) -> Option<&'static str> {
+    match message {
+        WireMessage::Ping => Some("Ping"),
+        WireMessage::HelloRequest(_) => Some("HelloRequest"),
+        WireMessage::BatchUploadRequest(_) => Some("BatchUploadRequest"),
+        WireMessage::DispatcherStatusRequest(_) => Some("DispatcherStatusRequest"),
+        WireMessage::CommandPollRequest(_) => Some("CommandPollRequest"),
+        WireMessage::BatchUploadChunk(_) => Some("BatchUploadChunk"),
+        WireMessage::CommandDispatchRequest(_) => Some("CommandDispatchRequest"),
+        WireMessage::DeviceDisconnectionRequest(_) => Some("DeviceDisconnectionRequest"),
+        WireMessage::Pong
+        | WireMessage::HelloResponse(_)
+        | WireMessage::BatchUploadResponse(_)
+        | WireMessage::DispatcherStatusResponse(_)
+        | WireMessage::CommandPollResponse(_)
+        | WireMessage::CommandDispatchResponse(_)
+        | WireMessage::DeviceDisconnectionResponse(_)
+        | WireMessage::Error(_)
+        | WireMessage::Cancel(_)
+        | WireMessage::GoingAway => None,
+    }
+}
@@ -1,58 +1,181 @@
 use dashmap::DashMap;
-use std::{sync::Arc, time::Duration};
+use ersha_core::Compression;
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 use thiserror::Error;
 use tokio::{
-    io::{BufReader, BufWriter},
-    net::TcpStream,
+    io::{AsyncRead, AsyncWrite, BufReader, BufWriter},
     sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::queue::Outbox;
+use crate::router::request_name;
+use crate::tls::PeerCertificate;
+use crate::{
+    Envelope, MessageId, Outcome, ReadLimits, RpcMetrics, WireMessage, WriteQueue, codec_flag,
+    codec_from_flag, correlation_id, read_frame_with, write_compressed_frame,
 };
 
-use crate::{Envelope, MessageId, WireMessage, read_frame, write_frame};
+type Pending = Arc<DashMap<MessageId, oneshot::Sender<Envelope>>>;
+
+/// How often a connection checks that the other end is still there, and how
+/// long it waits for an answer before giving the connection up as dead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum RpcError {
-    #[error("send error: {0}")]
-    SendError(#[from] mpsc::error::SendError<Envelope>),
+    #[error("connection closed")]
+    Closed,
+    #[error("write queue full")]
+    QueueFull,
     #[error("response channel closed: {0}")]
     ChannelClosed(#[from] oneshot::error::RecvError),
     #[error("timeout: {0}")]
     Timeout(#[from] tokio::time::error::Elapsed),
 }
 
+/// A connection carrying requests in both directions.
+///
+/// Calls are multiplexed: any number may be in flight at once, and replies
+/// are matched to their calls by `reply_to` as they arrive, in any order.
+/// Messages that aren't replies to a pending call are handed to [`recv`].
+///
+/// [`recv`]: RpcTcp::recv
 pub struct RpcTcp {
-    tx: mpsc::Sender<Envelope>,
+    tx: Arc<Outbox>,
     rx: mpsc::Receiver<Envelope>,
-    pending: Arc<DashMap<MessageId, oneshot::Sender<Envelope>>>,
+    pending: Pending,
+    peer: Option<PeerCertificate>,
+    compression: Arc<AtomicU8>,
+    read_limits: Arc<Mutex<ReadLimits>>,
+    answer_pings: Arc<AtomicBool>,
+    notify_cancel: bool,
+    metrics: Arc<dyn RpcMetrics>,
+    closed: CancellationToken,
+    going_away: CancellationToken,
 }
 
 impl RpcTcp {
-    pub fn new(stream: TcpStream, buffer: usize) -> Self {
-        let (reader, writer) = stream.into_split();
+    /// Exchange messages over `stream`, typically a TCP or TLS connection,
+    /// queueing up to `buffer` messages to write.
+    pub fn new<S>(stream: S, buffer: usize) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::with_write_queue(stream, WriteQueue::new(buffer))
+    }
+
+    /// Exchange messages over `stream`, bounding those waiting to be written
+    /// by `queue`.
+    pub fn with_write_queue<S>(stream: S, queue: WriteQueue) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::open(
+            stream,
+            queue,
+            ReadLimits::default(),
+            Arc::new(AtomicUsize::new(0)),
+        )
+    }
+
+    /// As [`with_write_queue`], counting queued messages in `queued` along
+    /// with those of other connections.
+    ///
+    /// [`with_write_queue`]: RpcTcp::with_write_queue
+    pub(crate) fn open<S>(
+        stream: S,
+        queue: WriteQueue,
+        read_limits: ReadLimits,
+        queued: Arc<AtomicUsize>,
+    ) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
         let mut reader = BufReader::new(reader);
         let mut writer = BufWriter::new(writer);
 
-        let (tx_out, mut rx_out) = mpsc::channel::<Envelope>(buffer);
-        let (tx_in, rx_in) = mpsc::channel::<Envelope>(buffer);
+        let tx_out = Arc::new(Outbox::new(queue, queued));
+        let (tx_in, rx_in) = mpsc::channel::<Envelope>(queue.capacity.max(1));
 
-        let pending: Arc<DashMap<MessageId, oneshot::Sender<Envelope>>> = Arc::new(DashMap::new());
+        let pending: Pending = Arc::new(DashMap::new());
+        let compression = Arc::new(AtomicU8::new(codec_flag(None)));
+        let read_limits = Arc::new(Mutex::new(read_limits));
+        let answer_pings = Arc::new(AtomicBool::new(false));
+        let closed = CancellationToken::new();
+        let going_away = CancellationToken::new();
 
+        let compression_clone = compression.clone();
+        let closed_clone = closed.clone();
+        let rx_out = tx_out.clone();
         tokio::spawn(async move {
-            while let Some(msg) = rx_out.recv().await {
-                if let Err(e) = write_frame(&mut writer, &msg).await {
+            let mut draining = false;
+            loop {
+                let msg = tokio::select! {
+                    // Messages already queued are still written.
+                    _ = closed_clone.cancelled(), if !draining => {
+                        draining = true;
+                        rx_out.close();
+                        continue;
+                    }
+                    msg = rx_out.pop() => match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    },
+                };
+                let codec = codec_from_flag(compression_clone.load(Ordering::Relaxed));
+                if let Err(e) = write_compressed_frame(&mut writer, &msg, codec).await {
                     tracing::error!("writer error: {:?}", e);
+                    closed_clone.cancel();
                     break;
                 }
                 tracing::info!("wrote message: {msg:?}");
             }
+            rx_out.discard();
         });
 
+        // Demultiplex incoming messages: replies go to the call waiting on
+        // them, everything else to `recv`.
         let pending_clone = pending.clone();
+        let answer_pings_clone = answer_pings.clone();
+        let closed_clone = closed.clone();
+        let tx_pong = tx_out.clone();
+        let read_limits_clone = read_limits.clone();
+        let going_away_clone = going_away.clone();
         tokio::spawn(async move {
             loop {
-                let msg = match read_frame(&mut reader).await {
+                let limits = *read_limits_clone.lock().expect("read limits lock poisoned");
+                let result = tokio::select! {
+                    _ = closed_clone.cancelled() => break,
+                    result = read_frame_with(&mut reader, &limits) => result,
+                };
+                let msg = match result {
                     Ok(m) => m,
                     Err(e) => {
                         tracing::error!("reader error: {:?}", e);
+                        closed_clone.cancel();
                         break;
                     }
                 };
@@ -67,28 +190,169 @@ impl RpcTcp {
                     tracing::warn!("no waiter found for reply");
                 }
 
+                if msg.payload == WireMessage::Ping && answer_pings_clone.load(Ordering::Relaxed) {
+                    let pong = Envelope {
+                        msg_id: MessageId::new(),
+                        reply_to: Some(msg.msg_id),
+                        deadline_ms: None,
+                        correlation_id: None,
+                        payload: WireMessage::Pong,
+                    };
+                    let _ = tx_pong.push(pong).await;
+                    continue;
+                }
+
+                if msg.payload == WireMessage::GoingAway {
+                    tracing::info!("other end is going away");
+                    going_away_clone.cancel();
+                    continue;
+                }
+
                 if tx_in.send(msg).await.is_err() {
                     break;
                 }
             }
+            // Fail calls still waiting rather than leave them to time out.
+            pending_clone.clear();
         });
 
         Self {
             tx: tx_out,
             rx: rx_in,
             pending,
+            peer: None,
+            compression,
+            read_limits,
+            answer_pings,
+            notify_cancel: false,
+            metrics: Arc::new(()),
+            closed,
+            going_away,
         }
     }
 
+    /// Record the certificate the other end authenticated with.
+    pub fn with_peer_certificate(mut self, peer: PeerCertificate) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
+    /// Answer pings from the other end with a pong directly, rather than
+    /// handing them to [`recv`]. For clients that never call `recv`, so a
+    /// server's keepalives still get answered.
+    ///
+    /// [`recv`]: RpcTcp::recv
+    pub fn answering_pings(self) -> Self {
+        self.answer_pings.store(true, Ordering::Relaxed);
+        self
+    }
+
+    /// Send a [`WireMessage::Cancel`] for each call given up on before its
+    /// reply arrived, so the other end can stop working on it.
+    pub fn notifying_cancellations(mut self) -> Self {
+        self.notify_cancel = true;
+        self
+    }
+
+    /// Bound the frames read from the other end by `limits`, from the next
+    /// frame on.
+    pub fn with_read_limits(self, limits: ReadLimits) -> Self {
+        *self.read_limits.lock().expect("read limits lock poisoned") = limits;
+        self
+    }
+
+    /// Report the calls made on this connection to `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn RpcMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Ping the other end every `keepalive.interval`, closing the connection
+    /// if it doesn't answer within `keepalive.timeout`.
+    ///
+    /// Without this, a connection whose peer vanished, e.g. behind a NAT
+    /// that dropped its mapping, can look open for hours.
+    pub fn keepalive(&self, keepalive: Keepalive) {
+        // Keepalives need no cancelling: a connection that doesn't answer
+        // them is closed anyway.
+        let caller = Caller {
+            notify_cancel: false,
+            ..self.caller()
+        };
+        let closed = self.closed.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = closed.cancelled() => return,
+                    _ = tokio::time::sleep(keepalive.interval) => {}
+                }
+                if let Err(e) = caller.call(WireMessage::Ping, keepalive.timeout).await {
+                    tracing::warn!("closing connection that stopped answering keepalives: {e}");
+                    closed.cancel();
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Close the connection, as happens when it is dropped. Calls waiting on
+    /// it fail, [`recv`] returns `None` and no more messages are sent.
+    ///
+    /// [`recv`]: RpcTcp::recv
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    /// Whether the connection was closed, by either end, by an error or
+    /// because the other end stopped answering keepalives.
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_cancelled()
+    }
+
+    /// Wait until the connection is closed.
+    pub async fn closed(&self) {
+        self.closed.cancelled().await
+    }
+
+    /// Whether the other end said it is going away, so new calls should go
+    /// over a new connection once those in flight are answered.
+    pub fn is_going_away(&self) -> bool {
+        self.going_away.is_cancelled()
+    }
+
+    /// Wait until the other end says it is going away.
+    pub async fn going_away(&self) {
+        self.going_away.cancelled().await
+    }
+
+    /// Compress outgoing frames with `compression` from now on, as agreed in
+    /// the hello. Incoming frames are decompressed whatever was agreed.
+    pub fn set_compression(&self, compression: Option<Compression>) {
+        self.compression
+            .store(codec_flag(compression), Ordering::Relaxed);
+    }
+
+    /// Messages waiting to be written, as the other end reads them.
+    pub fn queued(&self) -> usize {
+        self.tx.len()
+    }
+
+    /// The certificate the other end presented in the TLS handshake, if any.
+    pub fn peer_certificate(&self) -> Option<&PeerCertificate> {
+        self.peer.as_ref()
+    }
+
     pub async fn send(&self, payload: WireMessage) -> Result<MessageId, RpcError> {
         let msg_id = MessageId::new();
         let env = Envelope {
             msg_id,
             reply_to: None,
+            deadline_ms: None,
+            correlation_id: correlation_id(),
             payload,
         };
 
-        self.tx.send(env).await?;
+        self.tx.push(env).await?;
 
         Ok(msg_id)
     }
@@ -102,31 +366,72 @@ impl RpcTcp {
         payload: WireMessage,
         timeout: Duration,
     ) -> Result<Envelope, RpcError> {
-        let msg_id = MessageId::new();
-        let (tx_wait, rx_wait) = oneshot::channel();
+        self.call_until(payload, Instant::now() + timeout).await
+    }
 
-        self.pending.insert(msg_id, tx_wait);
+    /// Make a call that gives up at `deadline`. The other end is told the
+    /// deadline and skips the call if it only gets to it later.
+    pub async fn call_until(
+        &self,
+        payload: WireMessage,
+        deadline: Instant,
+    ) -> Result<Envelope, RpcError> {
+        self.caller().call_until(payload, deadline).await
+    }
 
-        let env = Envelope {
-            msg_id,
-            reply_to: None,
-            payload,
-        };
+    /// A handle for making calls on this connection from other tasks.
+    pub fn caller(&self) -> Caller {
+        Caller {
+            tx: self.tx.clone(),
+            pending: self.pending.clone(),
+            notify_cancel: self.notify_cancel,
+            metrics: self.metrics.clone(),
+        }
+    }
 
-        if let Err(e) = self.tx.send(env).await {
-            self.pending.remove(&msg_id);
-            return Err(RpcError::SendError(e));
+    /// Take the messages the other end sends unprompted, to handle them
+    /// apart from this connection. [`recv`] returns `None` from then on.
+    ///
+    /// [`recv`]: RpcTcp::recv
+    pub fn incoming(&mut self) -> Incoming {
+        let (_, closed) = mpsc::channel(1);
+        Incoming {
+            rx: std::mem::replace(&mut self.rx, closed),
+            replier: self.replier(),
         }
+    }
 
-        match tokio::time::timeout(timeout, rx_wait).await {
-            Ok(Ok(resp)) => Ok(resp),
-            Ok(Err(closed)) => Err(RpcError::ChannelClosed(closed)),
-            Err(elapsed) => {
-                self.pending.remove(&msg_id);
-                Err(RpcError::Timeout(elapsed))
-            }
+    pub async fn reply(
+        &self,
+        request_msg_id: MessageId,
+        payload: WireMessage,
+    ) -> Result<MessageId, RpcError> {
+        self.replier().reply(request_msg_id, payload).await
+    }
+
+    /// A handle for replying on this connection from other tasks, so
+    /// requests can be answered as they complete rather than in order.
+    pub fn replier(&self) -> Replier {
+        Replier {
+            tx: self.tx.clone(),
+            compression: self.compression.clone(),
         }
     }
+}
+
+/// Sends replies on an [`RpcTcp`] connection.
+#[derive(Clone)]
+pub struct Replier {
+    tx: Arc<Outbox>,
+    compression: Arc<AtomicU8>,
+}
+
+impl Replier {
+    /// See [`RpcTcp::set_compression`].
+    pub fn set_compression(&self, compression: Option<Compression>) {
+        self.compression
+            .store(codec_flag(compression), Ordering::Relaxed);
+    }
 
     pub async fn reply(
         &self,
@@ -137,11 +442,182 @@ impl RpcTcp {
         let env = Envelope {
             msg_id,
             reply_to: Some(request_msg_id),
+            deadline_ms: None,
+            correlation_id: correlation_id(),
             payload,
         };
 
-        self.tx.send(env).await?;
+        self.tx.push(env).await?;
+
+        Ok(msg_id)
+    }
+}
+
+impl Drop for RpcTcp {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Makes calls on an [`RpcTcp`] connection.
+#[derive(Clone)]
+pub struct Caller {
+    tx: Arc<Outbox>,
+    pending: Pending,
+    notify_cancel: bool,
+    metrics: Arc<dyn RpcMetrics>,
+}
 
+impl Caller {
+    /// Send `payload` without waiting for a reply, as a notice.
+    pub async fn send(&self, payload: WireMessage) -> Result<MessageId, RpcError> {
+        let msg_id = MessageId::new();
+        self.tx.push(Self::notice(msg_id, payload)).await?;
         Ok(msg_id)
     }
+
+    /// As [`send`], but failing rather than waiting if the write queue is
+    /// full.
+    ///
+    /// [`send`]: Caller::send
+    pub fn try_send(&self, payload: WireMessage) -> Result<MessageId, RpcError> {
+        let msg_id = MessageId::new();
+        self.tx.try_push(Self::notice(msg_id, payload))?;
+        Ok(msg_id)
+    }
+
+    fn notice(msg_id: MessageId, payload: WireMessage) -> Envelope {
+        Envelope {
+            msg_id,
+            reply_to: None,
+            deadline_ms: None,
+            correlation_id: correlation_id(),
+            payload,
+        }
+    }
+
+    pub async fn call(
+        &self,
+        payload: WireMessage,
+        timeout: Duration,
+    ) -> Result<Envelope, RpcError> {
+        self.call_until(payload, Instant::now() + timeout).await
+    }
+
+    /// See [`RpcTcp::call_until`].
+    ///
+    /// A call abandoned before its reply arrives, by timing out or by its
+    /// future being dropped, frees its pending slot and, if the connection
+    /// is [notifying cancellations], tells the other end with a
+    /// [`WireMessage::Cancel`].
+    ///
+    /// [notifying cancellations]: RpcTcp::notifying_cancellations
+    pub async fn call_until(
+        &self,
+        payload: WireMessage,
+        deadline: Instant,
+    ) -> Result<Envelope, RpcError> {
+        let msg_id = MessageId::new();
+        let message = request_name(&payload).unwrap_or("unknown");
+        let span = tracing::debug_span!("rpc_call", ?msg_id, message);
+
+        let (tx_wait, rx_wait) = oneshot::channel();
+        self.pending.insert(msg_id, tx_wait);
+        let mut call = PendingCall {
+            caller: self,
+            msg_id,
+            message,
+            started: Instant::now(),
+            sent: false,
+            outcome: Outcome::Cancelled,
+        };
+
+        let result = async {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let env = Envelope {
+                msg_id,
+                reply_to: None,
+                deadline_ms: Some(remaining.as_millis().try_into().unwrap_or(u64::MAX)),
+                correlation_id: correlation_id(),
+                payload,
+            };
+
+            self.tx.push(env).await?;
+            call.sent = true;
+
+            match tokio::time::timeout_at(deadline, rx_wait).await {
+                Ok(Ok(resp)) => Ok(resp),
+                Ok(Err(closed)) => Err(RpcError::ChannelClosed(closed)),
+                Err(elapsed) => Err(RpcError::Timeout(elapsed)),
+            }
+        }
+        .instrument(span)
+        .await;
+
+        call.outcome = match &result {
+            Ok(resp) => Outcome::of_reply(&resp.payload),
+            Err(e) => Outcome::of_error(e),
+        };
+        result
+    }
+}
+
+/// A call in flight, measured and, unless answered, given up on when dropped.
+struct PendingCall<'a> {
+    caller: &'a Caller,
+    msg_id: MessageId,
+    message: &'static str,
+    started: Instant,
+    /// Whether the call was written to the queue, so the other end may
+    /// be working on it.
+    sent: bool,
+    outcome: Outcome,
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        let caller = self.caller;
+        caller
+            .metrics
+            .call_finished(self.message, &self.outcome, self.started.elapsed());
+
+        // Replies take their call out of `pending`, so there is nothing to
+        // cancel for a call that was answered or whose connection closed.
+        if caller.pending.remove(&self.msg_id).is_none() {
+            return;
+        }
+        if self.sent && caller.notify_cancel {
+            let cancel = Envelope {
+                msg_id: MessageId::new(),
+                reply_to: None,
+                deadline_ms: None,
+                correlation_id: correlation_id(),
+                payload: WireMessage::Cancel(self.msg_id),
+            };
+            // Best effort: the call is over either way.
+            let _ = caller.tx.try_push(cancel);
+        }
+    }
+}
+
+/// Messages the other end of an [`RpcTcp`] connection sent unprompted, with
+/// the means to answer them.
+pub struct Incoming {
+    rx: mpsc::Receiver<Envelope>,
+    replier: Replier,
+}
+
+impl Incoming {
+    /// The next message, or `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<Envelope> {
+        self.rx.recv().await
+    }
+
+    pub async fn reply(
+        &self,
+        request_msg_id: MessageId,
+        payload: WireMessage,
+    ) -> Result<MessageId, RpcError> {
+        self.replier.reply(request_msg_id, payload).await
+    }
 }
@@ -1,179 +1,1853 @@
-use std::future::Future;
-use std::pin::Pin;
-use std::sync::Arc;
+use dashmap::DashMap;
+use std::future;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::Instrument;
 
-use crate::{MessageId, RpcTcp, WireMessage};
-use ersha_core::{BatchUploadRequest, BatchUploadResponse, HelloRequest, HelloResponse};
+use crate::capture::CaptureWriter;
+use crate::chunk::{ChunkAssembler, DEFAULT_MAX_BATCH_BYTES, negotiate};
+use crate::limit::{ConnectionLimiter, PeerCounts, PeerGuard, TokenBucket};
+use crate::middleware::Session;
+use crate::push::{ConnectionId, Dispatchers};
+use crate::router::request_name;
+use crate::tls::{PeerCertificate, TlsAcceptor, rustls};
+use crate::{
+    ConnectionLimits, Keepalive, MessageId, Outcome, RateLimits, ReadLimits, Router, RpcMetrics,
+    RpcTcp, SharedRateLimits, WireError, WireErrorCode, WireMessage, WriteQueue, correlated,
+};
+use ersha_core::{Compression, HelloResponse};
 
-pub type HandlerFn<Req, Res, S> = Box<
-    dyn Fn(Req, MessageId, &RpcTcp, &S) -> Pin<Box<dyn Future<Output = Res> + Send>> + Send + Sync,
->;
+/// Requests handled at once on each connection unless configured otherwise.
+const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+
+/// How long a server shutting down waits for its connections unless
+/// configured otherwise.
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause after failing to accept a connection, typically for want of file
+/// descriptors, before trying again.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
 
 pub struct Server<S> {
     listener: TcpListener,
-    buffer_size: usize,
+    write_queue: WriteQueue,
+    read_limits: ReadLimits,
+    max_in_flight: usize,
+    max_batch_bytes: u64,
+    compression: Arc<[Compression]>,
+    rate_limits: SharedRateLimits,
+    connection_limits: ConnectionLimits,
+    tls: Option<TlsAcceptor>,
+    keepalive: Option<Keepalive>,
+    drain_timeout: Duration,
+    dispatchers: Dispatchers,
     state: Arc<S>,
-    handlers: ServerHandlers<S>,
+    router: Router<S>,
+    connections: Arc<AtomicUsize>,
+    queued: Arc<AtomicUsize>,
+    tunneled: Option<(mpsc::Sender<TunneledRpc>, mpsc::Receiver<TunneledRpc>)>,
+    metrics: Arc<dyn RpcMetrics>,
+    capture: Option<CaptureWriter>,
+}
+
+/// How each connection is handled, as configured on the [`Server`].
+struct ConnectionSettings {
+    rate_limits: SharedRateLimits,
+    max_in_flight: usize,
+    max_batch_bytes: u64,
+    compression: Arc<[Compression]>,
+    /// Largest chunk a client may stream batches in, to fit the frames read.
+    max_chunk_bytes: u32,
+    keepalive: Option<Keepalive>,
+    /// When the connection is closed unless a hello has been accepted.
+    hello_deadline: Option<Instant>,
+    dispatchers: Dispatchers,
+    metrics: Arc<dyn RpcMetrics>,
+    capture: Option<CaptureWriter>,
+    /// Cancelled when the server starts shutting down.
+    draining: CancellationToken,
+    /// Connections and the requests they are handling, waited for on
+    /// shutdown.
+    tasks: TaskTracker,
+}
+
+/// Decrements the open connection count when a connection ends, giving up
+/// the place it held under the [`ConnectionLimits`].
+struct ConnectionGuard {
+    connections: Arc<AtomicUsize>,
+    _slot: Option<OwnedSemaphorePermit>,
+    _peer: Option<PeerGuard>,
+}
+
+impl ConnectionGuard {
+    fn open(
+        connections: &Arc<AtomicUsize>,
+        slot: Option<OwnedSemaphorePermit>,
+        peer: Option<PeerGuard>,
+    ) -> Self {
+        connections.fetch_add(1, Ordering::Relaxed);
+        Self {
+            connections: connections.clone(),
+            _slot: slot,
+            _peer: peer,
+        }
+    }
 }
 
-struct ServerHandlers<S> {
-    on_ping: Option<HandlerFn<(), (), S>>,
-    on_hello: Option<HandlerFn<HelloRequest, HelloResponse, S>>,
-    on_batch_upload: Option<HandlerFn<BatchUploadRequest, BatchUploadResponse, S>>,
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl<S: Send + Sync + 'static> Server<S> {
     pub fn new(listener: TcpListener, state: S) -> Self {
         Self {
             listener,
-            buffer_size: 1024,
+            write_queue: WriteQueue::new(1024),
+            read_limits: ReadLimits::default(),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            compression: Arc::new([Compression::Zstd, Compression::Lz4]),
+            rate_limits: SharedRateLimits::default(),
+            connection_limits: ConnectionLimits::default(),
+            tls: None,
+            keepalive: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            dispatchers: Dispatchers::default(),
             state: Arc::new(state),
-            handlers: ServerHandlers {
-                on_hello: None,
-                on_ping: None,
-                on_batch_upload: None,
-            },
+            router: Router::new(),
+            connections: Arc::new(AtomicUsize::new(0)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            tunneled: None,
+            metrics: Arc::new(()),
+            capture: None,
         }
     }
 
+    /// Number of currently open client connections, kept up to date while serving.
+    pub fn connections(&self) -> Arc<AtomicUsize> {
+        self.connections.clone()
+    }
+
+    /// Number of messages waiting to be written across all connections,
+    /// kept up to date while serving.
+    pub fn queued(&self) -> Arc<AtomicUsize> {
+        self.queued.clone()
+    }
+
+    /// Queue up to `buffer_size` messages to write on each connection.
     pub fn with_buffer(mut self, buffer_size: usize) -> Self {
-        self.buffer_size = buffer_size;
+        self.write_queue.capacity = buffer_size;
         self
     }
 
-    pub fn on_hello<F, Fut>(mut self, handler: F) -> Self
-    where
-        F: Fn(HelloRequest, MessageId, &RpcTcp, &S) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = HelloResponse> + Send + 'static,
-    {
-        self.handlers.on_hello = Some(Box::new(move |hello, msg_id, rpc, state| {
-            Box::pin(handler(hello, msg_id, rpc, state))
-        }));
+    /// Bound the messages waiting to be written on each connection, and
+    /// choose what happens when a client reads too slowly to keep up.
+    pub fn with_write_queue(mut self, write_queue: WriteQueue) -> Self {
+        self.write_queue = write_queue;
         self
     }
 
-    pub fn on_ping<F, Fut>(mut self, handler: F) -> Self
-    where
-        F: Fn(MessageId, &RpcTcp, &S) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = ()> + Send + 'static,
-    {
-        self.handlers.on_ping = Some(Box::new(move |_, msg_id, rpc, state| {
-            Box::pin(handler(msg_id, rpc, state))
-        }));
+    /// Bound the frames read from each client, dropping connections that
+    /// send frames too large or too slowly.
+    pub fn with_read_limits(mut self, read_limits: ReadLimits) -> Self {
+        self.read_limits = read_limits;
         self
     }
 
-    pub fn on_batch_upload<F, Fut>(mut self, handler: F) -> Self
-    where
-        F: Fn(BatchUploadRequest, MessageId, &RpcTcp, &S) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = BatchUploadResponse> + Send + 'static,
-    {
-        self.handlers.on_batch_upload = Some(Box::new(move |request, msg_id, rpc, state| {
-            Box::pin(handler(request, msg_id, rpc, state))
-        }));
+    /// Handle at most `max_in_flight` requests at once on each connection.
+    ///
+    /// Clients may pipeline more; they are read once earlier ones complete.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    /// Reassemble batches of up to `max_batch_bytes` streamed in chunks.
+    /// Zero turns chunked uploads off.
+    pub fn with_max_batch_bytes(mut self, max_batch_bytes: u64) -> Self {
+        self.max_batch_bytes = max_batch_bytes;
         self
     }
 
+    /// Compress frames with one of `codecs`, the first a client offers in
+    /// its hello. Empty turns compression off. Both zstd and LZ4 are
+    /// accepted by default.
+    pub fn with_compression(mut self, codecs: &[Compression]) -> Self {
+        self.compression = codecs.into();
+        self
+    }
+
+    /// Limit how fast each connection may send requests and readings.
+    ///
+    /// Requests over the limit are answered with [`WireErrorCode::RateLimited`]
+    /// without reaching their handler.
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = SharedRateLimits::new(rate_limits);
+        self
+    }
+
+    /// Bound the connections held open and pace accepting new ones.
+    pub fn with_connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.connection_limits = limits;
+        self
+    }
+
+    /// Accept connections over TLS only.
+    ///
+    /// When `config` verifies client certificates, the one a client
+    /// authenticated with is available to handlers through
+    /// [`RpcTcp::peer_certificate`].
+    pub fn with_tls(mut self, config: Arc<rustls::ServerConfig>) -> Self {
+        self.tls = Some(TlsAcceptor::from(config));
+        self
+    }
+
+    /// Ping each client periodically, dropping connections that stop
+    /// answering.
+    pub fn with_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// On shutdown, wait up to `drain_timeout` for connections to finish the
+    /// requests they are handling.
+    ///
+    /// Clients are told the server is going away, and connections are
+    /// served until the client hangs up so calls already on their way are
+    /// answered too.
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Handle for pushing requests to connected dispatchers that accept
+    /// them.
+    pub fn dispatchers(&self) -> Dispatchers {
+        self.dispatchers.clone()
+    }
+
+    /// Handle for changing the rate limits while serving.
+    pub fn rate_limits(&self) -> SharedRateLimits {
+        self.rate_limits.clone()
+    }
+
+    /// Report connections, the requests handled on them and the calls
+    /// pushed over them to `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn RpcMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Record every envelope received, as it arrives, to `capture`.
+    pub fn with_capture(mut self, capture: CaptureWriter) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Answer requests with the handlers registered on `router`.
+    pub fn with_router(mut self, router: Router<S>) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Wrap an accepted stream, completing the TLS handshake first, by
+    /// `deadline` if any, if the server requires it.
+    async fn open(
+        tls: Option<TlsAcceptor>,
+        stream: TcpStream,
+        write_queue: WriteQueue,
+        read_limits: ReadLimits,
+        queued: Arc<AtomicUsize>,
+        deadline: Option<Instant>,
+    ) -> std::io::Result<RpcTcp> {
+        let Some(acceptor) = tls else {
+            return Ok(RpcTcp::open(stream, write_queue, read_limits, queued));
+        };
+
+        let handshake = acceptor.accept(stream);
+        let stream = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, handshake)
+                .await
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??,
+            None => handshake.await?,
+        };
+        let peer = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .map(|certificate| PeerCertificate::new(certificate.clone().into_owned()));
+
+        let rpc = RpcTcp::open(stream, write_queue, read_limits, queued);
+        Ok(match peer {
+            Some(peer) => rpc.with_peer_certificate(peer),
+            None => rpc,
+        })
+    }
+
     async fn handle_connection(
-        handlers: Arc<ServerHandlers<S>>,
+        router: Arc<Router<S>>,
         state: Arc<S>,
-        stream: TcpStream,
-        buffer_size: usize,
+        rpc: RpcTcp,
+        connection: ConnectionId,
+        settings: ConnectionSettings,
     ) {
-        let mut rpc = RpcTcp::new(stream, buffer_size);
+        let ConnectionSettings {
+            rate_limits,
+            max_in_flight,
+            max_batch_bytes,
+            compression,
+            max_chunk_bytes,
+            keepalive,
+            mut hello_deadline,
+            dispatchers,
+            metrics,
+            capture,
+            draining,
+            tasks,
+        } = settings;
+        let mut rpc = rpc.with_metrics(metrics.clone());
+        if let Some(keepalive) = keepalive {
+            rpc.keepalive(keepalive);
+        }
+        metrics.connection_opened();
+        let session = Arc::new(Mutex::new(Session::default()));
+        let mut limiter = ConnectionLimiter::new(rate_limits);
+        let in_flight = Arc::new(Semaphore::new(max_in_flight.max(1)));
+        // Requests being handled, to abandon when their caller cancels them.
+        let running: Arc<DashMap<MessageId, CancellationToken>> = Arc::new(DashMap::new());
+        let mut chunks = ChunkAssembler::new(max_batch_bytes);
+        let mut going_away = false;
 
         loop {
-            let envelope = match rpc.recv().await {
-                Some(env) => env,
-                None => {
-                    tracing::debug!("connection closed");
+            let envelope = tokio::select! {
+                envelope = rpc.recv() => match envelope {
+                    Some(env) => env,
+                    None => {
+                        tracing::debug!("connection closed");
+                        break;
+                    }
+                },
+                _ = sleep_until(hello_deadline) => {
+                    if session.lock().expect("session lock poisoned").is_authenticated() {
+                        hello_deadline = None;
+                        continue;
+                    }
+                    tracing::warn!("closing connection without an accepted hello");
                     break;
                 }
+                // Keep serving, as the client may have calls on their way.
+                _ = draining.cancelled(), if !going_away => {
+                    going_away = true;
+                    tracing::debug!("telling client the server is going away");
+                    if let Err(e) = rpc.send(WireMessage::GoingAway).await {
+                        tracing::error!("failed to send GoingAway: {:?}", e);
+                    }
+                    continue;
+                }
             };
 
+            let captured = capture
+                .as_ref()
+                .map(|capture| capture.record(connection.get(), &envelope));
+            if let Some(Err(e)) = captured {
+                tracing::warn!("failed to capture envelope: {e}");
+            }
+
+            let read_at = Instant::now();
             let msg_id = envelope.msg_id;
-            let payload = envelope.payload;
+            let correlation_id = envelope.correlation_id;
+            let mut payload = envelope.payload;
+            let deadline = envelope
+                .deadline_ms
+                .and_then(|ms| Instant::now().checked_add(Duration::from_millis(ms)));
 
-            match payload {
-                WireMessage::Ping => {
-                    if let Some(handler) = &handlers.on_ping {
-                        handler((), msg_id, &rpc, &state).await;
-                    }
-                    if let Err(e) = rpc.reply(msg_id, WireMessage::Pong).await {
-                        tracing::error!("failed to send Pong reply: {:?}", e);
-                    }
+            if let WireMessage::Cancel(cancelled) = payload {
+                if let Some(token) = running.get(&cancelled) {
+                    tracing::debug!(msg_id = ?cancelled, "request cancelled by client");
+                    token.cancel();
                 }
-                WireMessage::HelloRequest(hello) => {
-                    if let Some(handler) = &handlers.on_hello {
-                        let response = handler(hello, msg_id, &rpc, &state).await;
-                        if let Err(e) = rpc
-                            .reply(msg_id, WireMessage::HelloResponse(response))
-                            .await
-                        {
-                            tracing::error!("failed to send HelloResponse reply: {:?}", e);
+                continue;
+            }
+
+            // A streamed batch is handled as a whole once its last chunk is
+            // in. Chunks are only buffered for a client whose hello agreed
+            // to them, and are charged as they arrive.
+            if let WireMessage::BatchUploadChunk(chunk) = payload {
+                let last = chunk.last;
+                let agreed = session.lock().expect("session lock poisoned").chunking;
+                let pushed = match agreed {
+                    None => Err(WireError {
+                        code: WireErrorCode::Unauthenticated,
+                        message: "BatchUploadChunk requires a hello agreeing to chunks".to_owned(),
+                    }),
+                    Some(_) => match limiter.check_chunk(chunk.data.len()) {
+                        Err(retry_after) => Err(WireError {
+                            code: WireErrorCode::RateLimited,
+                            message: format!("rate limit exceeded, retry in {retry_after:?}"),
+                        }),
+                        Ok(()) => chunks.push(chunk).map_err(|e| WireError {
+                            code: WireErrorCode::BadRequest,
+                            message: e.to_string(),
+                        }),
+                    },
+                };
+                match pushed {
+                    Ok(Some(request)) => payload = WireMessage::BatchUploadRequest(request),
+                    Ok(None) => continue,
+                    Err(error) => {
+                        tracing::warn!("dropping streamed batch: {}", error.message);
+                        // Only the last chunk is awaited by the client.
+                        if last && let Err(e) = rpc.reply(msg_id, WireMessage::Error(error)).await {
+                            tracing::error!("failed to send Error reply: {:?}", e);
                         }
-                    } else {
-                        tracing::warn!("received HelloRequest but no handler registered");
+                        continue;
                     }
                 }
-                WireMessage::BatchUploadRequest(request) => {
-                    if let Some(handler) = &handlers.on_batch_upload {
-                        let response = handler(request, msg_id, &rpc, &state).await;
-                        if let Err(e) = rpc
-                            .reply(msg_id, WireMessage::BatchUploadResponse(response))
-                            .await
-                        {
-                            tracing::error!("failed to send BatchUploadResponse reply: {:?}", e);
+            }
+
+            // Replies and errors from the client are not charged.
+            let readings = match &payload {
+                WireMessage::BatchUploadRequest(request) => Some(request.readings.len()),
+                WireMessage::Ping
+                | WireMessage::HelloRequest(_)
+                | WireMessage::DispatcherStatusRequest(_)
+                | WireMessage::CommandPollRequest(_)
+                | WireMessage::DeviceDisconnectionRequest(_) => Some(0),
+                _ => None,
+            };
+            let message = request_name(&payload).unwrap_or("unknown");
+            if let Some(Err(retry_after)) = readings.map(|readings| limiter.check(readings)) {
+                tracing::warn!(?retry_after, ?readings, "rate limit exceeded");
+                let error = WireError {
+                    code: WireErrorCode::RateLimited,
+                    message: format!("rate limit exceeded, retry in {retry_after:?}"),
+                };
+                metrics.request_handled(
+                    message,
+                    &Outcome::Error(WireErrorCode::RateLimited),
+                    read_at.elapsed(),
+                );
+                if let Err(e) = rpc.reply(msg_id, WireMessage::Error(error)).await {
+                    tracing::error!("failed to send Error reply: {:?}", e);
+                }
+                continue;
+            }
+
+            // Terms the server sets in an accepted hello, whatever the
+            // handler answered.
+            let hello = match &payload {
+                WireMessage::HelloRequest(hello) => Some((
+                    negotiate(
+                        hello
+                            .max_chunk_bytes
+                            .map(|bytes| bytes.min(max_chunk_bytes)),
+                        max_batch_bytes,
+                    ),
+                    hello
+                        .compression
+                        .iter()
+                        .find(|codec| compression.contains(codec))
+                        .copied(),
+                    hello.accepts_push,
+                )),
+                _ => None,
+            };
+
+            // Requests are answered as they complete, so a slow one doesn't
+            // hold up those pipelined behind it. Waiting for a free slot
+            // stops reading from a client that has too many in flight.
+            let permit = in_flight
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("in-flight semaphore is never closed");
+
+            // The client stopped waiting while the request was queued.
+            if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                tracing::debug!(?msg_id, message, "skipping request past its deadline");
+                metrics.request_handled(message, &Outcome::Timeout, read_at.elapsed());
+                continue;
+            }
+
+            let current = session.lock().expect("session lock poisoned").clone();
+            let span = tracing::info_span!(
+                "rpc_request",
+                ?msg_id,
+                message,
+                dispatcher_id = ?current.dispatcher_id,
+                correlation_id = correlation_id.map(tracing::field::display),
+            );
+            let routed = span.in_scope(|| router.handle(payload, msg_id, &rpc, &current, &state));
+            let Some(mut reply) = routed else {
+                continue;
+            };
+            if let Some((offered, codec, accepts_push)) = hello {
+                let replier = rpc.replier();
+                let push = accepts_push.then(|| (dispatchers.clone(), rpc.caller()));
+                let session = session.clone();
+                reply = Box::pin(async move {
+                    let mut reply = reply.await;
+                    if let WireMessage::HelloResponse(HelloResponse::Accepted {
+                        dispatcher_id,
+                        chunking,
+                        compression,
+                        ..
+                    }) = &mut reply
+                    {
+                        *chunking = offered;
+                        *compression = codec;
+                        replier.set_compression(codec);
+                        let mut session = session.lock().expect("session lock poisoned");
+                        session.dispatcher_id = Some(*dispatcher_id);
+                        session.chunking = offered;
+                        drop(session);
+                        if let Some((dispatchers, caller)) = push {
+                            dispatchers.register(*dispatcher_id, connection, caller);
                         }
-                    } else {
-                        tracing::warn!("received BatchUploadRequest but no handler registered");
                     }
-                }
-                WireMessage::Pong => {
-                    tracing::debug!("received Pong (unexpected on server)");
-                }
-                WireMessage::HelloResponse(res) => {
-                    tracing::debug!("received HelloResponse (unexpected on server): {res:?}");
-                }
-                WireMessage::BatchUploadResponse(res) => {
-                    tracing::debug!("received BatchUploadResponse (unexpected on server): {res:?}");
-                }
-                WireMessage::Error(err) => {
-                    tracing::warn!("received error: {:?}", err);
-                }
+                    reply
+                });
             }
+
+            let cancelled = CancellationToken::new();
+            running.insert(msg_id, cancelled.clone());
+            let running = running.clone();
+            let replier = rpc.replier();
+            let metrics = metrics.clone();
+            let handled = async move {
+                let expired = sleep_until(deadline);
+                // Nobody waits for the reply to an abandoned request.
+                let outcome = tokio::select! {
+                    reply = reply => {
+                        let outcome = Outcome::of_reply(&reply);
+                        if let Err(e) = replier.reply(msg_id, reply).await {
+                            tracing::error!("failed to send reply: {:?}", e);
+                        }
+                        outcome
+                    }
+                    _ = cancelled.cancelled() => Outcome::Cancelled,
+                    _ = expired => {
+                        tracing::debug!("abandoning request past its deadline");
+                        Outcome::Timeout
+                    }
+                };
+                metrics.request_handled(message, &outcome, read_at.elapsed());
+                running.remove(&msg_id);
+                drop(permit);
+            };
+            // Replies, and whatever the handler sends while at it, carry
+            // the client's correlation ID back.
+            let handled = handled.instrument(span);
+            match correlation_id {
+                Some(id) => tasks.spawn(correlated(id, handled)),
+                None => tasks.spawn(handled),
+            };
+        }
+
+        dispatchers.unregister(connection);
+        let dispatcher_id = session.lock().expect("session lock poisoned").dispatcher_id;
+        router.disconnected(dispatcher_id, &state).await;
+        metrics.connection_closed();
+    }
+
+    /// Handle for handing the server connections accepted elsewhere, such as
+    /// RPC tunneled over a WebSocket.
+    pub fn tunnel(&mut self) -> Tunnel {
+        let tx = self
+            .tunneled
+            .get_or_insert_with(|| mpsc::channel(16))
+            .0
+            .clone();
+        Tunnel {
+            tx,
+            write_queue: self.write_queue,
+            read_limits: self.read_limits,
+            queued: self.queued.clone(),
+        }
+    }
+
+    fn settings(&self, draining: &CancellationToken, tasks: &TaskTracker) -> ConnectionSettings {
+        let hello_deadline = self
+            .connection_limits
+            .hello_timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        ConnectionSettings {
+            rate_limits: self.rate_limits.clone(),
+            max_in_flight: self.max_in_flight,
+            max_batch_bytes: self.max_batch_bytes,
+            compression: self.compression.clone(),
+            max_chunk_bytes: self.read_limits.max_chunk_bytes(),
+            keepalive: self.keepalive,
+            hello_deadline,
+            dispatchers: self.dispatchers.clone(),
+            metrics: self.metrics.clone(),
+            capture: self.capture.clone(),
+            draining: draining.clone(),
+            tasks: tasks.clone(),
         }
     }
 
-    pub async fn serve(self, cancel: CancellationToken) {
-        let handlers = Arc::new(self.handlers);
-        let state = self.state;
+    pub async fn serve(mut self, cancel: CancellationToken) {
+        let router = Arc::new(std::mem::take(&mut self.router));
+        let state = self.state.clone();
+        // Only the receiver is kept, so tunneling stops with the server.
+        let mut tunneled = self.tunneled.take().map(|(_, rx)| rx);
+        let tasks = TaskTracker::new();
+        let limits = self.connection_limits;
+        let slots = limits
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max.max(1))));
+        let peers = PeerCounts::new(limits.max_per_ip);
+        let mut pacing = limits.accept_rate.map(TokenBucket::new);
 
         loop {
+            // Only take connections once there is room for them, leaving them
+            // in the backlog rather than opening them to be dropped.
+            let slot = tokio::select! {
+                _ = cancel.cancelled() => break,
+                slot = reserve(slots.as_ref()) => slot,
+            };
+
             tokio::select! {
-                _ = cancel.cancelled() => {
-                    tracing::info!("server shutdown requested");
-                    break;
+                _ = cancel.cancelled() => break,
+                Some(TunneledRpc { rpc, peer }) = next_tunneled(&mut tunneled) => {
+                    let ip = peer.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+                    let Some(peer_guard) = peers.open(ip) else {
+                        tracing::warn!(?peer, "refusing tunneled connection, too many open from this address");
+                        self.metrics.connection_refused();
+                        continue;
+                    };
+                    tracing::debug!(?peer, "accepted tunneled connection");
+                    let router = router.clone();
+                    let state = state.clone();
+                    let settings = self.settings(&cancel, &tasks);
+                    let guard = ConnectionGuard::open(&self.connections, slot, Some(peer_guard));
+                    let connection = ConnectionId::next();
+                    let span = tracing::info_span!("rpc_connection", ?connection, peer = "tunnel", tunnel_peer = ?peer);
+                    tasks.spawn(
+                        async move {
+                            Self::handle_connection(router, state, rpc, connection, settings).await;
+                            drop(guard);
+                        }
+                        .instrument(span),
+                    );
                 }
-                result = self.listener.accept() => {
+                result = async {
+                    pace(pacing.as_mut()).await;
+                    self.listener.accept().await
+                } => {
                     match result {
                         Ok((stream, addr)) => {
+                            let Some(peer) = peers.open(addr.ip()) else {
+                                tracing::warn!(peer = %addr, "refusing connection, too many open from this address");
+                                self.metrics.connection_refused();
+                                continue;
+                            };
                             tracing::debug!("accepted connection from {:?}", addr);
-                            let handlers = handlers.clone();
+                            let router = router.clone();
                             let state = state.clone();
-                            let buffer_size = self.buffer_size;
-                            tokio::spawn(async move {
-                                Self::handle_connection(handlers, state, stream, buffer_size).await;
-                            });
+                            let write_queue = self.write_queue;
+                            let read_limits = self.read_limits;
+                            let queued = self.queued.clone();
+                            let settings = self.settings(&cancel, &tasks);
+                            let deadline = settings.hello_deadline;
+                            let tls = self.tls.clone();
+                            let guard = ConnectionGuard::open(&self.connections, slot, Some(peer));
+                            let connection = ConnectionId::next();
+                            let span = tracing::info_span!("rpc_connection", ?connection, peer = %addr);
+                            tasks.spawn(
+                                async move {
+                                    match Self::open(tls, stream, write_queue, read_limits, queued, deadline).await {
+                                        Ok(rpc) => {
+                                            Self::handle_connection(router, state, rpc, connection, settings)
+                                                .await
+                                        }
+                                        Err(e) => {
+                                            tracing::warn!("TLS handshake with {:?} failed: {:?}", addr, e)
+                                        }
+                                    }
+                                    drop(guard);
+                                }
+                                .instrument(span),
+                            );
                         }
                         Err(e) => {
                             tracing::error!("error accepting connection: {:?}", e);
+                            // Out of file descriptors, accepting again at once
+                            // would only spin.
+                            tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
                         }
                     }
                 }
             }
         }
+
+        tracing::info!("server shutdown requested, draining connections");
+
+        // No new connections, accepted or tunneled, while draining.
+        drop(self.listener);
+        drop(tunneled);
+        tasks.close();
+        if tokio::time::timeout(self.drain_timeout, tasks.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                connections = self.connections.load(Ordering::Relaxed),
+                "gave up waiting for connections to drain"
+            );
+        }
+    }
+}
+
+/// A place for one more connection, waiting until one closes if `slots`
+/// are all taken.
+async fn reserve(slots: Option<&Arc<Semaphore>>) -> Option<OwnedSemaphorePermit> {
+    let slots = slots?;
+    Some(
+        slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("connection slots are never closed"),
+    )
+}
+
+/// Wait for `pacing` to allow accepting another connection.
+async fn pace(pacing: Option<&mut TokenBucket>) {
+    let Some(bucket) = pacing else {
+        return;
+    };
+    while let Err(wait) = bucket.try_take(1) {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// The next tunneled connection, waiting forever if there can be none.
+async fn next_tunneled(tunneled: &mut Option<mpsc::Receiver<TunneledRpc>>) -> Option<TunneledRpc> {
+    match tunneled {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait until `deadline`, or forever without one.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+/// A connection accepted elsewhere, with the address it came from if known.
+struct TunneledRpc {
+    rpc: RpcTcp,
+    peer: Option<IpAddr>,
+}
+
+/// Hands a [`Server`] connections it didn't accept itself.
+///
+/// They are served like any other, except that TLS, if any, is up to
+/// whatever accepted them.
+#[derive(Clone)]
+pub struct Tunnel {
+    tx: mpsc::Sender<TunneledRpc>,
+    write_queue: WriteQueue,
+    read_limits: ReadLimits,
+    queued: Arc<AtomicUsize>,
+}
+
+impl Tunnel {
+    /// Serve RPC over `stream`, opened from `peer` if known, returning once
+    /// the server has taken it.
+    ///
+    /// Connections are bounded per address like those accepted directly;
+    /// those from an unknown `peer` count together.
+    pub async fn connect<T>(&self, stream: T, peer: Option<IpAddr>)
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let rpc = RpcTcp::open(
+            stream,
+            self.write_queue,
+            self.read_limits,
+            self.queued.clone(),
+        );
+        if self.tx.send(TunneledRpc { rpc, peer }).await.is_err() {
+            tracing::debug!("dropping tunneled connection, server stopped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::sync::CancellationToken;
+
+    use std::path::{Path, PathBuf};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use ersha_core::{
+        BatchId, BatchUploadRequest, BatchUploadResponse, ChunkLimits, CommandDispatchRequest,
+        CommandDispatchResponse, CommandId, CommandKind, CommandPoll, CommandPollResponse,
+        Compression, DeviceCommand, DeviceId, DispatcherId, DispatcherStatus,
+        DispatcherStatusResponse, H3Cell, HelloRejectionReason, HelloRequest, HelloResponse,
+        LinkQuality,
+    };
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use ulid::Ulid;
+
+    use super::Server;
+    use crate::capture::{CaptureReader, CaptureWriter};
+    use crate::middleware::{Call, Next, require_hello};
+    use crate::tls::{self, TlsConnector, dispatcher_name};
+    use crate::{
+        Client, ClientError, ConnectionLimits, CorrelationId, Keepalive, Outcome, PushError, Quota,
+        RateLimits, Router, RpcError, RpcMetrics, RpcTcp, WireError, WireErrorCode, WireMessage,
+        correlated, correlation_id,
+    };
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ()).with_rate_limits(RateLimits {
+            requests: Some(Quota::new(0, 2)),
+            ..RateLimits::default()
+        });
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        client.ping().await.unwrap();
+        client.ping().await.unwrap();
+        match client.ping().await {
+            Err(ClientError::ErrorResponse(error)) => {
+                assert_eq!(error.code, WireErrorCode::RateLimited)
+            }
+            other => panic!("expected a rate limit error, got {other:?}"),
+        }
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn connections_past_the_limit_wait_for_room() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ()).with_connection_limits(ConnectionLimits {
+            max_connections: Some(1),
+            ..Default::default()
+        });
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let first = Client::new(TcpStream::connect(addr).await.unwrap());
+        first.ping().await.unwrap();
+
+        // Connected, but left in the backlog until the first one closes.
+        let second = Client::new(TcpStream::connect(addr).await.unwrap());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), second.ping())
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(2), second.ping())
+            .await
+            .expect("second connection should be accepted")
+            .unwrap();
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn connections_past_the_address_limit_are_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ()).with_connection_limits(ConnectionLimits {
+            max_per_ip: Some(1),
+            accept_rate: Some(Quota::new(100, 10)),
+            ..Default::default()
+        });
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let first = Client::new(TcpStream::connect(addr).await.unwrap());
+        first.ping().await.unwrap();
+
+        let second = Client::new(TcpStream::connect(addr).await.unwrap());
+        let refused = tokio::time::timeout(Duration::from_secs(2), second.ping())
+            .await
+            .expect("server should close the connection");
+        assert!(refused.unwrap_err().is_disconnect());
+        first.ping().await.unwrap();
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn tunneled_connections_are_bounded_by_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut server = Server::new(listener, ()).with_connection_limits(ConnectionLimits {
+            max_per_ip: Some(1),
+            ..Default::default()
+        });
+        let tunnel = server.tunnel();
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let (client_io, server_io) = tokio::io::duplex(1024);
+            tunnel.connect(server_io, None).await;
+            clients.push(Client::new(client_io));
+        }
+
+        clients[0].ping().await.unwrap();
+        let refused = tokio::time::timeout(Duration::from_secs(2), clients[1].ping())
+            .await
+            .expect("server should close the connection");
+        assert!(refused.unwrap_err().is_disconnect());
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn connections_without_a_hello_are_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new().route(
+            |hello: HelloRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                HelloResponse::Accepted {
+                    dispatcher_id: hello.dispatcher_id,
+                    proof: None,
+                    chunking: None,
+                    compression: None,
+                }
+            },
+        );
+        let server = Server::new(listener, ())
+            .with_connection_limits(ConnectionLimits {
+                hello_timeout: Some(Duration::from_millis(200)),
+                ..Default::default()
+            })
+            .with_router(router);
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let idle = Client::new(TcpStream::connect(addr).await.unwrap());
+        let greeted = Client::new(TcpStream::connect(addr).await.unwrap());
+        greeted
+            .hello(HelloRequest {
+                dispatcher_id: DispatcherId(Ulid::new()),
+                location: H3Cell(0x8a2a1072b59ffff),
+                credentials: None,
+                max_chunk_bytes: None,
+                compression: Box::new([]),
+                accepts_push: false,
+            })
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        greeted.ping().await.unwrap();
+        let closed = tokio::time::timeout(Duration::from_secs(2), idle.ping())
+            .await
+            .expect("server should close the connection");
+        assert!(closed.unwrap_err().is_disconnect());
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn received_envelopes_are_captured() {
+        let path = std::env::temp_dir().join(format!("ersha-rpc-capture-{}.bin", Ulid::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ()).with_capture(CaptureWriter::open(&path).unwrap());
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        let WireMessage::DispatcherStatusRequest(report) = status(3) else {
+            unreachable!()
+        };
+        client.ping().await.unwrap();
+        // Unanswered without a router, but captured all the same.
+        client.dispatcher_status(report.clone()).await.unwrap_err();
+        cancel.cancel();
+
+        let captured: Vec<_> = CaptureReader::open(&path)
+            .unwrap()
+            .map(|captured| captured.unwrap().envelope.payload)
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            captured,
+            [
+                WireMessage::Ping,
+                WireMessage::DispatcherStatusRequest(report)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests_are_answered_as_they_complete() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Each status report takes `pending_readings` milliseconds to handle.
+        let server = Server::new(listener, ()).with_router(Router::new().route(
+            |status: DispatcherStatus, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                tokio::time::sleep(Duration::from_millis(status.pending_readings)).await;
+                DispatcherStatusResponse::Accepted
+            },
+        ));
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let client = &Client::new(TcpStream::connect(addr).await.unwrap());
+        let answered = &Mutex::new(Vec::new());
+        let report = |millis| async move {
+            let status = DispatcherStatus {
+                dispatcher_id: DispatcherId(Ulid::new()),
+                pending_readings: millis,
+                pending_statuses: 0,
+                link: LinkQuality {
+                    rtt_ms: None,
+                    failed_uploads: 0,
+                },
+                uptime_seconds: 0,
+                timestamp: jiff::Timestamp::now(),
+            };
+            client.dispatcher_status(status).await.unwrap();
+            answered.lock().unwrap().push(millis);
+        };
+
+        tokio::join!(report(300), report(0), report(100));
+        assert_eq!(*answered.lock().unwrap(), [0, 100, 300]);
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn correlation_ids_carry_through_handling_and_back() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let seen = Arc::new(Mutex::new(None));
+        let router = Router::new().route({
+            let seen = seen.clone();
+            move |_status: DispatcherStatus, _msg_id, _rpc: &RpcTcp, _state: &()| {
+                let seen = seen.clone();
+                async move {
+                    *seen.lock().unwrap() = correlation_id();
+                    DispatcherStatusResponse::Accepted
+                }
+            }
+        });
+        let server = Server::new(listener, ()).with_router(router);
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let rpc = RpcTcp::new(TcpStream::connect(addr).await.unwrap(), 16);
+        let id = CorrelationId::new();
+        let reply = correlated(id, rpc.call(status(0), Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(reply.correlation_id, Some(id));
+        assert_eq!(*seen.lock().unwrap(), Some(id));
+
+        let reply = rpc.call(status(0), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(reply.correlation_id, None);
+
+        cancel.cancel();
+    }
+
+    fn status(pending_readings: u64) -> WireMessage {
+        WireMessage::DispatcherStatusRequest(DispatcherStatus {
+            dispatcher_id: DispatcherId(Ulid::new()),
+            pending_readings,
+            pending_statuses: 0,
+            link: LinkQuality {
+                rtt_ms: None,
+                failed_uploads: 0,
+            },
+            uptime_seconds: 0,
+            timestamp: jiff::Timestamp::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn requests_past_their_deadline_are_skipped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let started = Arc::new(AtomicUsize::new(0));
+        let router = Router::new().route({
+            let started = started.clone();
+            move |_status: DispatcherStatus, _msg_id, _rpc: &RpcTcp, _state: &()| {
+                started.fetch_add(1, Ordering::Relaxed);
+                async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    DispatcherStatusResponse::Accepted
+                }
+            }
+        });
+        let server = Server::new(listener, ())
+            .with_router(router)
+            .with_max_in_flight(1);
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        // The second call waits behind the first and expires meanwhile.
+        let rpc = RpcTcp::new(TcpStream::connect(addr).await.unwrap(), 16);
+        let (first, second) = tokio::join!(
+            rpc.call(status(0), Duration::from_secs(5)),
+            rpc.call(status(0), Duration::from_millis(100)),
+        );
+        first.unwrap();
+        assert!(matches!(second, Err(RpcError::Timeout(_))));
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(started.load(Ordering::Relaxed), 1);
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_calls_in_flight() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ())
+            .with_drain_timeout(Duration::from_secs(5))
+            .with_router(Router::new().route(
+                |status: DispatcherStatus, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                    tokio::time::sleep(Duration::from_millis(status.pending_readings)).await;
+                    DispatcherStatusResponse::Accepted
+                },
+            ));
+        let cancel = CancellationToken::new();
+        let served = tokio::spawn(server.serve(cancel.clone()));
+
+        let rpc = RpcTcp::new(TcpStream::connect(addr).await.unwrap(), 16);
+        let shutdown = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+            rpc.going_away().await;
+            // Calls made before the client heard are still answered.
+            rpc.call(status(0), Duration::from_secs(5)).await.unwrap();
+        };
+        let (reply, ()) = tokio::join!(rpc.call(status(300), Duration::from_secs(5)), shutdown);
+        reply.unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+
+        // The server stops once the client hangs up.
+        drop(rpc);
+        tokio::time::timeout(Duration::from_secs(1), served)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn draining_is_bounded() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ()).with_drain_timeout(Duration::from_millis(100));
+        let cancel = CancellationToken::new();
+        let served = tokio::spawn(server.serve(cancel.clone()));
+
+        // A client that never hangs up.
+        let rpc = RpcTcp::new(TcpStream::connect(addr).await.unwrap(), 16);
+        rpc.call(WireMessage::Ping, Duration::from_secs(5))
+            .await
+            .unwrap();
+        cancel.cancel();
+        tokio::time::timeout(Duration::from_secs(1), served)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(rpc.is_going_away());
+    }
+
+    #[tokio::test]
+    async fn cancelled_calls_stop_their_handlers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let router = Router::new().route({
+            let finished = finished.clone();
+            move |_status: DispatcherStatus, _msg_id, _rpc: &RpcTcp, _state: &()| {
+                let finished = finished.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    finished.fetch_add(1, Ordering::Relaxed);
+                    DispatcherStatusResponse::Accepted
+                }
+            }
+        });
+        let server = Server::new(listener, ()).with_router(router);
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let rpc =
+            RpcTcp::new(TcpStream::connect(addr).await.unwrap(), 16).notifying_cancellations();
+        // Given up on well before its deadline.
+        let call = rpc.call(status(0), Duration::from_secs(5));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), call)
+                .await
+                .is_err()
+        );
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(finished.load(Ordering::Relaxed), 0);
+        // The connection is still usable.
+        rpc.call(WireMessage::Ping, Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        cancel.cancel();
+    }
+
+    /// Records what it is told, as `side message outcome`.
+    #[derive(Default)]
+    struct Recorded(Mutex<Vec<String>>);
+
+    impl RpcMetrics for Recorded {
+        fn connection_opened(&self) {
+            self.0.lock().unwrap().push("opened".to_string());
+        }
+
+        fn connection_closed(&self) {
+            self.0.lock().unwrap().push("closed".to_string());
+        }
+
+        fn call_finished(&self, message: &'static str, outcome: &Outcome, _elapsed: Duration) {
+            let call = format!("call {message} {}", outcome.label());
+            self.0.lock().unwrap().push(call);
+        }
+
+        fn request_handled(&self, message: &'static str, outcome: &Outcome, _elapsed: Duration) {
+            let request = format!("request {message} {}", outcome.label());
+            self.0.lock().unwrap().push(request);
+        }
+    }
+
+    #[tokio::test]
+    async fn calls_and_requests_are_measured() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_metrics = Arc::new(Recorded::default());
+        let server = Server::new(listener, ())
+            .with_rate_limits(RateLimits {
+                requests: Some(Quota::new(0, 1)),
+                ..RateLimits::default()
+            })
+            .with_metrics(server_metrics.clone());
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let client_metrics = Arc::new(Recorded::default());
+        let client = Client::new(TcpStream::connect(addr).await.unwrap())
+            .with_metrics(client_metrics.clone());
+        client.ping().await.unwrap();
+        assert!(client.ping().await.is_err());
+        drop(client);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            *client_metrics.0.lock().unwrap(),
+            ["call Ping ok", "call Ping error"]
+        );
+        assert_eq!(
+            *server_metrics.0.lock().unwrap(),
+            ["opened", "request Ping ok", "request Ping error", "closed"]
+        );
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn requests_are_routed_to_their_handlers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (disconnected_tx, mut disconnected) = tokio::sync::mpsc::unbounded_channel();
+        let router = Router::new()
+            .route(
+                |hello: HelloRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                    HelloResponse::Accepted {
+                        dispatcher_id: hello.dispatcher_id,
+                        proof: None,
+                        chunking: None,
+                        compression: None,
+                    }
+                },
+            )
+            .route(
+                |_status: DispatcherStatus, _msg_id, _rpc: &RpcTcp, _state: &()| async {
+                    Err::<DispatcherStatusResponse, _>(WireError {
+                        code: WireErrorCode::BadRequest,
+                        message: "no status today".to_string(),
+                    })
+                },
+            )
+            .on_disconnect(move |dispatcher_id, _state: &()| {
+                let _ = disconnected_tx.send(dispatcher_id);
+                async {}
+            });
+        let server = Server::new(listener, ()).with_router(router);
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let hello = HelloRequest {
+            dispatcher_id,
+            location: H3Cell(0x8a2a1072b59ffff),
+            credentials: None,
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
+        };
+        client.hello(hello).await.unwrap();
+
+        // Unrouted pings are still answered.
+        client.ping().await.unwrap();
+
+        let status = DispatcherStatus {
+            dispatcher_id,
+            pending_readings: 0,
+            pending_statuses: 0,
+            link: LinkQuality {
+                rtt_ms: None,
+                failed_uploads: 0,
+            },
+            uptime_seconds: 0,
+            timestamp: jiff::Timestamp::now(),
+        };
+        match client.dispatcher_status(status).await {
+            Err(ClientError::ErrorResponse(error)) => {
+                assert_eq!(error.code, WireErrorCode::BadRequest)
+            }
+            other => panic!("expected the handler's error, got {other:?}"),
+        }
+
+        let poll = CommandPoll {
+            dispatcher_id,
+            acks: Box::new([]),
+        };
+        match client.poll_commands(poll).await {
+            Err(ClientError::ErrorResponse(error)) => {
+                assert_eq!(error.code, WireErrorCode::Unsupported)
+            }
+            other => panic!("expected an unsupported error, got {other:?}"),
+        }
+
+        drop(client);
+        let disconnected = tokio::time::timeout(Duration::from_secs(5), disconnected.recv())
+            .await
+            .unwrap();
+        assert_eq!(disconnected, Some(Some(dispatcher_id)));
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn layers_see_each_request_with_its_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        let router = Router::new()
+            .route(
+                |hello: HelloRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                    HelloResponse::Accepted {
+                        dispatcher_id: hello.dispatcher_id,
+                        proof: None,
+                        chunking: None,
+                        compression: None,
+                    }
+                },
+            )
+            .route(
+                |_poll: CommandPoll, _msg_id, _rpc: &RpcTcp, _state: &()| async {
+                    CommandPollResponse::Accepted {
+                        commands: Box::new([]),
+                    }
+                },
+            )
+            .layer(move |call: Call<'_, ()>, next: Next<'_, ()>| {
+                let entry = (call.name(), call.session.dispatcher_id);
+                seen_clone.lock().unwrap().push(entry);
+                next.run(call)
+            })
+            .layer(require_hello);
+        let server = Server::new(listener, ()).with_router(router);
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let poll = CommandPoll {
+            dispatcher_id,
+            acks: Box::new([]),
+        };
+        match client.poll_commands(poll.clone()).await {
+            Err(ClientError::ErrorResponse(error)) => {
+                assert_eq!(error.code, WireErrorCode::Unauthenticated)
+            }
+            other => panic!("expected an unauthenticated error, got {other:?}"),
+        }
+        client.ping().await.unwrap();
+
+        let hello = HelloRequest {
+            dispatcher_id,
+            location: H3Cell(0x8a2a1072b59ffff),
+            credentials: None,
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
+        };
+        client.hello(hello).await.unwrap();
+        client.poll_commands(poll).await.unwrap();
+
+        let other = CommandPoll {
+            dispatcher_id: DispatcherId(Ulid::new()),
+            acks: Box::new([]),
+        };
+        match client.poll_commands(other).await {
+            Err(ClientError::ErrorResponse(error)) => {
+                assert_eq!(error.code, WireErrorCode::Forbidden)
+            }
+            other => panic!("expected a forbidden error, got {other:?}"),
+        }
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                ("CommandPollRequest", None),
+                ("Ping", None),
+                ("HelloRequest", None),
+                ("CommandPollRequest", Some(dispatcher_id)),
+                ("CommandPollRequest", Some(dispatcher_id)),
+            ]
+        );
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn large_batches_are_streamed_in_agreed_chunks() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new()
+            .route(
+                |hello: HelloRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                    HelloResponse::Accepted {
+                        dispatcher_id: hello.dispatcher_id,
+                        proof: None,
+                        chunking: None,
+                        compression: None,
+                    }
+                },
+            )
+            .route(
+                |request: BatchUploadRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                    BatchUploadResponse::Accepted {
+                        id: request.id,
+                        readings: Box::new([]),
+                        statuses: Box::new([]),
+                    }
+                },
+            );
+        let server = Server::new(listener, ())
+            .with_max_batch_bytes(1024)
+            .with_router(router);
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let batch = BatchUploadRequest {
+            id: BatchId(Ulid::new()),
+            dispatcher_id,
+            readings: Box::new([]),
+            statuses: Box::new([]),
+            timestamp: jiff::Timestamp::now(),
+        };
+
+        // Nothing is buffered before a hello agrees to chunks.
+        let unagreed = ChunkLimits {
+            chunk_bytes: 16,
+            max_batch_bytes: 1024,
+        };
+        match client.batch_upload_chunked(batch.clone(), unagreed).await {
+            Err(ClientError::ErrorResponse(error)) => {
+                assert_eq!(error.code, WireErrorCode::Unauthenticated)
+            }
+            other => panic!("expected an unauthenticated error, got {other:?}"),
+        }
+
+        let hello = HelloRequest {
+            dispatcher_id,
+            location: H3Cell(0x8a2a1072b59ffff),
+            credentials: None,
+            max_chunk_bytes: Some(16),
+            compression: Box::new([]),
+            accepts_push: false,
+        };
+        let limits = match client.hello(hello).await.unwrap() {
+            HelloResponse::Accepted {
+                chunking: Some(limits),
+                ..
+            } => limits,
+            other => panic!("expected chunking terms, got {other:?}"),
+        };
+        assert_eq!(
+            limits,
+            ChunkLimits {
+                chunk_bytes: 16,
+                max_batch_bytes: 1024,
+            }
+        );
+
+        match client.batch_upload_chunked(batch.clone(), limits).await {
+            Ok(BatchUploadResponse::Accepted { id, .. }) => assert_eq!(id, batch.id),
+            other => panic!("expected the batch to be accepted, got {other:?}"),
+        }
+
+        let too_small = ChunkLimits {
+            max_batch_bytes: 8,
+            ..limits
+        };
+        assert!(matches!(
+            client.batch_upload_chunked(batch, too_small).await,
+            Err(ClientError::BatchTooLarge(_))
+        ));
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn compression_is_agreed_at_hello() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ())
+            .with_compression(&[Compression::Lz4])
+            .with_router(Router::new().route(
+                |hello: HelloRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                    HelloResponse::Accepted {
+                        dispatcher_id: hello.dispatcher_id,
+                        proof: None,
+                        chunking: None,
+                        compression: None,
+                    }
+                },
+            ));
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let hello = |compression: Box<[Compression]>| HelloRequest {
+            dispatcher_id: DispatcherId(Ulid::new()),
+            location: H3Cell(0x8a2a1072b59ffff),
+            credentials: None,
+            max_chunk_bytes: None,
+            compression,
+            accepts_push: false,
+        };
+        let offers = [
+            (
+                vec![Compression::Zstd, Compression::Lz4],
+                Some(Compression::Lz4),
+            ),
+            (vec![Compression::Zstd], None),
+            (vec![], None),
+        ];
+        for (offered, agreed) in offers {
+            let client = Client::new(TcpStream::connect(addr).await.unwrap());
+            match client.hello(hello(offered.into())).await.unwrap() {
+                HelloResponse::Accepted { compression, .. } => assert_eq!(compression, agreed),
+                other => panic!("expected hello to be accepted, got {other:?}"),
+            }
+            // Calls keep working whatever was agreed.
+            client.ping().await.unwrap();
+        }
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn unresponsive_connections_are_closed() {
+        let keepalive = Keepalive {
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_millis(50),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ()).with_keepalive(keepalive);
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        // Clients answer the server's pings, so stay connected.
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!client.is_closed());
+        client.ping().await.unwrap();
+
+        // A peer that never answers is dropped by the server.
+        let silent = RpcTcp::new(TcpStream::connect(addr).await.unwrap(), 16);
+        tokio::time::timeout(Duration::from_secs(2), silent.closed())
+            .await
+            .expect("server should close the connection");
+
+        // And by a client whose server stopped answering.
+        let hung = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hung_addr = hung.local_addr().unwrap();
+        let accepted = tokio::spawn(async move { hung.accept().await.unwrap() });
+        let client =
+            Client::new(TcpStream::connect(hung_addr).await.unwrap()).with_keepalive(keepalive);
+        let _held = accepted.await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(client.is_closed());
+        assert!(client.ping().await.unwrap_err().is_disconnect());
+
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn commands_are_pushed_to_connected_dispatchers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ()).with_router(Router::new().route(
+            |hello: HelloRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                HelloResponse::Accepted {
+                    dispatcher_id: hello.dispatcher_id,
+                    proof: None,
+                    chunking: None,
+                    compression: None,
+                }
+            },
+        ));
+        let dispatchers = server.dispatchers();
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let hello = |dispatcher_id, accepts_push| HelloRequest {
+            dispatcher_id,
+            location: H3Cell(0x8a2a1072b59ffff),
+            credentials: None,
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push,
+        };
+
+        // Dispatchers that don't accept pushes aren't tracked.
+        let polling = DispatcherId(Ulid::new());
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        client.hello(hello(polling, false)).await.unwrap();
+        assert!(!dispatchers.is_connected(polling));
+
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        let mut incoming = client.incoming().unwrap();
+        assert!(client.incoming().is_none());
+        tokio::spawn(async move {
+            while let Some(envelope) = incoming.recv().await {
+                if let WireMessage::CommandDispatchRequest(request) = envelope.payload {
+                    let acks = request.commands.iter().map(|command| command.id).collect();
+                    let response = CommandDispatchResponse { acks };
+                    incoming
+                        .reply(
+                            envelope.msg_id,
+                            WireMessage::CommandDispatchResponse(response),
+                        )
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+        client.hello(hello(dispatcher_id, true)).await.unwrap();
+        assert_eq!(dispatchers.connected(), [dispatcher_id]);
+
+        let command = DeviceCommand {
+            id: CommandId(Ulid::new()),
+            device_id: DeviceId(Ulid::new()),
+            kind: CommandKind::Reboot,
+            expires_at: jiff::Timestamp::now(),
+        };
+        let request = CommandDispatchRequest {
+            commands: Box::new([command.clone()]),
+        };
+        let response = dispatchers
+            .dispatch_commands(dispatcher_id, request.clone(), Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(*response.acks, [command.id]);
+        assert!(matches!(
+            dispatchers
+                .dispatch_commands(polling, request, Duration::from_secs(1))
+                .await,
+            Err(PushError::NotConnected(_))
+        ));
+
+        // Notices are delivered without waiting for an answer.
+        let (tx, mut notices) = tokio::sync::mpsc::unbounded_channel();
+        let listening = DispatcherId(Ulid::new());
+        let notified = Client::new(TcpStream::connect(addr).await.unwrap());
+        let mut notified_incoming = notified.incoming().unwrap();
+        tokio::spawn(async move {
+            while let Some(envelope) = notified_incoming.recv().await {
+                tx.send(envelope.payload).unwrap();
+            }
+        });
+        notified.hello(hello(listening, true)).await.unwrap();
+        let notice = || {
+            WireMessage::Error(WireError {
+                code: WireErrorCode::Internal,
+                message: "maintenance at noon".to_string(),
+            })
+        };
+        dispatchers.send_to(listening, notice()).await.unwrap();
+        assert_eq!(notices.recv().await, Some(notice()));
+        assert!(matches!(
+            dispatchers.send_to(polling, notice()).await,
+            Err(PushError::NotConnected(_))
+        ));
+        assert_eq!(dispatchers.broadcast(&notice()), 2);
+        assert_eq!(notices.recv().await, Some(notice()));
+        drop(notified);
+
+        // Closed connections stop being tracked.
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while dispatchers.is_connected(dispatcher_id) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        cancel.cancel();
+    }
+
+    /// Write a certificate and key for `names`, issued by `ca`, into `dir`.
+    fn issue(
+        dir: &Path,
+        file: &str,
+        names: &[&str],
+        (ca, ca_key): (&Certificate, &KeyPair),
+    ) -> (PathBuf, PathBuf) {
+        let key = KeyPair::generate().unwrap();
+        let names: Vec<_> = names.iter().map(|name| name.to_string()).collect();
+        let certificate = CertificateParams::new(names)
+            .unwrap()
+            .signed_by(&key, ca, ca_key)
+            .unwrap();
+
+        let cert_path = dir.join(format!("{file}.pem"));
+        let key_path = dir.join(format!("{file}-key.pem"));
+        std::fs::write(&cert_path, certificate.pem()).unwrap();
+        std::fs::write(&key_path, key.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[tokio::test]
+    async fn client_certificates_reach_the_hello_handler() {
+        let dir = std::env::temp_dir().join(format!("ersha-rpc-tls-{}", Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let ca_path = dir.join("ca.pem");
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+        let issuer = (&ca, &ca_key);
+
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let (server_cert, server_key) = issue(&dir, "server", &["localhost"], issuer);
+        let (client_cert, client_key) =
+            issue(&dir, "client", &[&dispatcher_name(dispatcher_id)], issuer);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(listener, ())
+            .with_tls(tls::server_config(&server_cert, &server_key, Some(&ca_path)).unwrap())
+            .with_router(Router::new().route(
+                |hello: HelloRequest, _msg_id, rpc: &RpcTcp, _state: &()| {
+                    let identified = rpc
+                        .peer_certificate()
+                        .is_some_and(|peer| peer.identifies(hello.dispatcher_id));
+                    async move {
+                        if identified {
+                            HelloResponse::Accepted {
+                                dispatcher_id: hello.dispatcher_id,
+                                proof: None,
+                                chunking: None,
+                                compression: None,
+                            }
+                        } else {
+                            HelloResponse::Rejected {
+                                dispatcher_id: hello.dispatcher_id,
+                                reason: HelloRejectionReason::CertificateMismatch,
+                            }
+                        }
+                    }
+                },
+            ));
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        let connector = TlsConnector::from(
+            tls::client_config(&ca_path, Some((&client_cert, &client_key))).unwrap(),
+        );
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = connector
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        let client = Client::new(stream);
+
+        let hello = |dispatcher_id| HelloRequest {
+            dispatcher_id,
+            location: H3Cell(0x8a2a1072b59ffff),
+            credentials: None,
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
+        };
+        assert!(matches!(
+            client.hello(hello(dispatcher_id)).await.unwrap(),
+            HelloResponse::Accepted { .. }
+        ));
+        assert!(matches!(
+            client
+                .hello(hello(DispatcherId(Ulid::new())))
+                .await
+                .unwrap(),
+            HelloResponse::Rejected {
+                reason: HelloRejectionReason::CertificateMismatch,
+                ..
+            }
+        ));
+
+        cancel.cancel();
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
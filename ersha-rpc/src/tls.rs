@@ -0,0 +1,168 @@
+//! TLS for RPC connections, with dispatchers optionally authenticated by
+//! client certificates.
+//!
+//! A dispatcher's certificate is bound to its [`DispatcherId`] through a DNS
+//! subject alternative name, see [`dispatcher_name`].
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ersha_core::DispatcherId;
+use thiserror::Error;
+use tokio_rustls::rustls::{
+    ClientConfig, RootCertStore, ServerConfig,
+    client::verify_server_name,
+    crypto::{CryptoProvider, ring},
+    pki_types::{
+        CertificateDer, PrivateKeyDer, ServerName,
+        pem::{self, PemObject},
+    },
+    server::{ParsedCertificate, VerifierBuilderError, WebPkiClientVerifier},
+};
+
+pub use tokio_rustls::{TlsAcceptor, TlsConnector, rustls};
+
+/// Domain under which dispatcher certificates are named.
+const DISPATCHER_DOMAIN: &str = "dispatcher.ersha";
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("failed to read {path}: {source}")]
+    Pem { path: PathBuf, source: pem::Error },
+    #[error("no certificates in {0}")]
+    NoCertificates(PathBuf),
+    #[error("invalid client CA: {0}")]
+    ClientCa(#[from] VerifierBuilderError),
+    #[error("invalid TLS configuration: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+/// The DNS name a dispatcher's client certificate is issued for:
+/// `<ulid>.dispatcher.ersha`, with the ULID in lowercase.
+pub fn dispatcher_name(dispatcher_id: DispatcherId) -> String {
+    format!(
+        "{}.{DISPATCHER_DOMAIN}",
+        dispatcher_id.0.to_string().to_lowercase()
+    )
+}
+
+/// The end-entity certificate a client authenticated with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCertificate(CertificateDer<'static>);
+
+impl PeerCertificate {
+    pub fn new(certificate: CertificateDer<'static>) -> Self {
+        Self(certificate)
+    }
+
+    /// Whether the certificate was issued to `dispatcher_id`.
+    pub fn identifies(&self, dispatcher_id: DispatcherId) -> bool {
+        let Ok(name) = ServerName::try_from(dispatcher_name(dispatcher_id)) else {
+            return false;
+        };
+
+        ParsedCertificate::try_from(&self.0)
+            .and_then(|certificate| verify_server_name(&certificate, &name))
+            .is_ok()
+    }
+}
+
+/// Load every certificate in a PEM file.
+pub fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|source| TlsError::Pem {
+            path: path.to_owned(),
+            source,
+        })?;
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_owned()));
+    }
+
+    Ok(certificates)
+}
+
+/// Load the first private key in a PEM file.
+pub fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    PrivateKeyDer::from_pem_file(path).map_err(|source| TlsError::Pem {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn roots(ca: &Path) -> Result<Arc<RootCertStore>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(ca)? {
+        roots.add(certificate)?;
+    }
+
+    Ok(Arc::new(roots))
+}
+
+/// Server configuration presenting `cert` and `key`.
+///
+/// With a `client_ca`, clients must present a certificate it issued.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        Some(ca) => builder.with_client_cert_verifier(
+            WebPkiClientVerifier::builder_with_provider(roots(ca)?, provider()).build()?,
+        ),
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder.with_single_cert(load_certificates(cert)?, load_private_key(key)?)?;
+
+    Ok(Arc::new(config))
+}
+
+/// Client configuration trusting servers issued by `ca`, presenting the
+/// `(cert, key)` identity when given.
+pub fn client_config(
+    ca: &Path,
+    identity: Option<(&Path, &Path)>,
+) -> Result<Arc<ClientConfig>, TlsError> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots(ca)?);
+    let config = match identity {
+        Some((cert, key)) => {
+            builder.with_client_auth_cert(load_certificates(cert)?, load_private_key(key)?)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::DispatcherId;
+    use rcgen::{CertificateParams, KeyPair};
+    use ulid::Ulid;
+
+    use super::{PeerCertificate, dispatcher_name};
+
+    #[test]
+    fn certificates_identify_the_dispatcher_they_name() {
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let key = KeyPair::generate().unwrap();
+        let certificate = CertificateParams::new(vec![dispatcher_name(dispatcher_id)])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let peer = PeerCertificate::new(certificate.der().clone());
+
+        assert!(peer.identifies(dispatcher_id));
+        assert!(!peer.identifies(DispatcherId(Ulid::new())));
+    }
+}
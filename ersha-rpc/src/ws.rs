@@ -0,0 +1,276 @@
+//! RPC tunneled over a WebSocket, for networks that only let HTTP(S) out.
+//!
+//! Frames are written exactly as over TCP, carried in binary messages. A
+//! frame may span messages and a message may hold several frames.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use bytes::Bytes;
+use futures_util::{Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::error::UrlError;
+use tokio_tungstenite::tungstenite::http::Uri;
+use tokio_tungstenite::tungstenite::http::uri::InvalidUri;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+pub use tokio_tungstenite::tungstenite::Error as WsError;
+
+/// What a WebSocket message means to a tunnel.
+pub enum Tunneled {
+    /// Bytes of the tunneled stream
+    Data(Bytes),
+    /// A control message, handled by the WebSocket library
+    Control,
+    /// The other end is closing the WebSocket
+    Close,
+    /// Text, which a tunnel never sends
+    Text,
+}
+
+/// A message of the WebSocket library in use.
+pub trait WsMessage: Sized {
+    fn binary(data: Bytes) -> Self;
+
+    fn into_tunneled(self) -> Tunneled;
+}
+
+impl WsMessage for Message {
+    fn binary(data: Bytes) -> Self {
+        Message::Binary(data)
+    }
+
+    fn into_tunneled(self) -> Tunneled {
+        match self {
+            Message::Binary(data) => Tunneled::Data(data),
+            Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => Tunneled::Control,
+            Message::Close(_) => Tunneled::Close,
+            Message::Text(_) => Tunneled::Text,
+        }
+    }
+}
+
+/// A WebSocket read and written as the byte stream it tunnels, to hand to
+/// [`RpcTcp`] or [`Client`] like a TCP connection.
+///
+/// [`RpcTcp`]: crate::RpcTcp
+/// [`Client`]: crate::Client
+pub struct WsStream<S> {
+    socket: S,
+    /// Data of the last message not read yet.
+    unread: Bytes,
+}
+
+impl<S> WsStream<S> {
+    pub fn new(socket: S) -> Self {
+        Self {
+            socket,
+            unread: Bytes::new(),
+        }
+    }
+}
+
+/// Where to connect for a `ws://` or `wss://` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    /// Whether the WebSocket runs over TLS, for `wss://`.
+    pub secure: bool,
+}
+
+impl Endpoint {
+    pub fn parse(url: &str) -> Result<Self, WsError> {
+        let uri: Uri = url
+            .parse()
+            .map_err(|e: InvalidUri| WsError::HttpFormat(e.into()))?;
+        let secure = match uri.scheme_str() {
+            Some("ws") => false,
+            Some("wss") => true,
+            _ => return Err(UrlError::UnsupportedUrlScheme.into()),
+        };
+        let host = uri.host().ok_or(UrlError::NoHostName)?;
+
+        Ok(Self {
+            // IPv6 literals are bracketed in URLs only.
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_owned(),
+            port: uri.port_u16().unwrap_or(if secure { 443 } else { 80 }),
+            secure,
+        })
+    }
+}
+
+/// Open a WebSocket to `url` over `stream`, an open TCP or, for `wss://`,
+/// TLS connection to its host.
+pub async fn connect<S>(url: &str, stream: S) -> Result<WsStream<WebSocketStream<S>>, WsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (socket, _response) = tokio_tungstenite::client_async(url, stream).await?;
+    Ok(WsStream::new(socket))
+}
+
+/// Accept a WebSocket opened over `stream`.
+pub async fn accept<S>(stream: S) -> Result<WsStream<WebSocketStream<S>>, WsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let socket = tokio_tungstenite::accept_async(stream).await?;
+    Ok(WsStream::new(socket))
+}
+
+impl<S, M, E> AsyncRead for WsStream<S>
+where
+    S: Stream<Item = Result<M, E>> + Unpin,
+    M: WsMessage,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if !self.unread.is_empty() {
+                let n = self.unread.len().min(buf.remaining());
+                buf.put_slice(&self.unread.split_to(n));
+                return Poll::Ready(Ok(()));
+            }
+
+            // Filling nothing signals the end of the stream.
+            let Some(message) = ready!(Pin::new(&mut self.socket).poll_next(cx)) else {
+                return Poll::Ready(Ok(()));
+            };
+            match message.map_err(io::Error::other)?.into_tunneled() {
+                Tunneled::Data(data) => self.unread = data,
+                Tunneled::Control => {}
+                Tunneled::Close => return Poll::Ready(Ok(())),
+                Tunneled::Text => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "text message on an RPC tunnel",
+                    )));
+                }
+            }
+        }
+    }
+}
+
+impl<S, M, E> AsyncWrite for WsStream<S>
+where
+    S: Stream<Item = Result<M, E>> + Sink<M, Error = E> + Unpin,
+    M: WsMessage,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut socket = Pin::new(&mut self.socket);
+        ready!(socket.as_mut().poll_ready(cx)).map_err(io::Error::other)?;
+        socket
+            .start_send(M::binary(Bytes::copy_from_slice(buf)))
+            .map_err(io::Error::other)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket)
+            .poll_flush(cx)
+            .map_err(io::Error::other)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Err(error) = ready!(Pin::new(&mut self.socket).poll_close(cx)) else {
+            return Poll::Ready(Ok(()));
+        };
+        let error = error.into();
+        // Already closed by the other end.
+        if is_closed(error.as_ref()) {
+            return Poll::Ready(Ok(()));
+        }
+        Poll::Ready(Err(io::Error::other(error)))
+    }
+}
+
+fn is_closed(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<WsError>(),
+        Some(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ersha_core::{DispatcherId, H3Cell, HelloRequest, HelloResponse};
+    use tokio_util::sync::CancellationToken;
+    use ulid::Ulid;
+
+    use super::*;
+    use crate::{Client, Router, RpcTcp, Server};
+
+    #[test]
+    fn endpoints_default_to_the_scheme_port() {
+        let endpoint = Endpoint::parse("wss://prime.example.com/rpc").unwrap();
+        assert_eq!(
+            endpoint,
+            Endpoint {
+                host: "prime.example.com".to_owned(),
+                port: 443,
+                secure: true,
+            }
+        );
+        assert_eq!(Endpoint::parse("ws://[::1]:8080/rpc").unwrap().port, 8080);
+        assert_eq!(Endpoint::parse("ws://[::1]:8080/rpc").unwrap().host, "::1");
+        assert!(Endpoint::parse("http://prime.example.com/rpc").is_err());
+    }
+
+    #[tokio::test]
+    async fn rpc_is_served_through_a_tunnel() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let router = Router::new().route(
+            |hello: HelloRequest, _msg_id, _rpc: &RpcTcp, _state: &()| async move {
+                HelloResponse::Accepted {
+                    dispatcher_id: hello.dispatcher_id,
+                    proof: None,
+                    chunking: None,
+                    compression: None,
+                }
+            },
+        );
+        let mut server = Server::new(listener, ()).with_router(router);
+        let tunnel = server.tunnel();
+        let cancel = CancellationToken::new();
+        tokio::spawn(server.serve(cancel.clone()));
+
+        // A small pipe, so frames are split across messages.
+        let (client_io, server_io) = tokio::io::duplex(64);
+        tokio::spawn(async move {
+            tunnel.connect(accept(server_io).await.unwrap(), None).await;
+        });
+        let client = Client::new(connect("ws://prime/rpc", client_io).await.unwrap());
+
+        client.ping().await.unwrap();
+        let dispatcher_id = DispatcherId(Ulid::new());
+        let hello = HelloRequest {
+            dispatcher_id,
+            location: H3Cell(0x8a2a1072b59ffff),
+            credentials: None,
+            max_chunk_bytes: None,
+            compression: Box::new([]),
+            accepts_push: false,
+        };
+        assert!(matches!(
+            client.hello(hello).await.unwrap(),
+            HelloResponse::Accepted { dispatcher_id: id, .. } if id == dispatcher_id
+        ));
+
+        cancel.cancel();
+    }
+}